@@ -0,0 +1,177 @@
+//! Collections and editorial curation
+//!
+//! Moderators group apps into collections such as "Essential dev tools"
+//! and keep an editorial order of the apps they recommend, apart from the
+//! featured list. Both are kept in the VFS once changed; until then the
+//! store shows the ones it ships with. Recommendations favor apps that
+//! share a category or a collection with the installed ones.
+//!
+//! ```json
+//! {"collections": [{"id": "dev-essentials", "title": "Essential dev tools",
+//!   "description": "Everything to write and ship code", "apps": ["code-studio", "text-editor"]}],
+//!  "editorial": ["hairr-messenger", "text-editor"]}
+//! ```
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use filesystem::VirtualFileSystem;
+use serde::{Deserialize, Serialize};
+
+use crate::AppListing;
+
+/// Collections and editorial order
+pub const CURATION_FILE: &str = "/var/lib/app-store/curation.json";
+
+/// Name `curate` uses for the editorial order
+pub const EDITORIAL: &str = "editorial";
+
+/// Weight of an installed app in the same category over one sharing a
+/// collection
+const CATEGORY_WEIGHT: u32 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Collection {
+    pub id: String,
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    /// App IDs, in the order shown
+    pub apps: Vec<String>,
+}
+
+impl Collection {
+    pub fn new(id: &str, title: &str, description: &str, apps: &[&str]) -> Self {
+        Collection {
+            id: id.to_string(),
+            title: title.to_string(),
+            description: description.to_string(),
+            apps: apps.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Curation {
+    pub collections: Vec<Collection>,
+    /// App IDs, most recommended first
+    #[serde(default)]
+    pub editorial: Vec<String>,
+}
+
+impl Curation {
+    /// Curation in `vfs`; `None` when nothing was curated there yet
+    pub fn load(vfs: &VirtualFileSystem) -> Result<Option<Self>, String> {
+        let path = Path::new(CURATION_FILE);
+        if !vfs.exists(path) {
+            return Ok(None);
+        }
+        serde_json::from_slice(&vfs.read_file(path)?).map(Some).map_err(|e| format!("Invalid curation: {}", e))
+    }
+
+    pub fn save(&self, vfs: &VirtualFileSystem) -> Result<(), String> {
+        vfs.write_file(Path::new(CURATION_FILE), &serde_json::to_vec_pretty(self).unwrap()).map_err(String::from)
+    }
+
+    pub fn collection(&self, id: &str) -> Option<&Collection> {
+        self.collections.iter().find(|c| c.id == id)
+    }
+
+    /// Put an app in a collection, or in the editorial order, at
+    /// `position` or last. An app already there is moved.
+    pub fn add(&mut self, list: &str, app: &str, position: Option<usize>) -> Result<(), String> {
+        let apps = self.list_mut(list)?;
+        apps.retain(|a| a != app);
+        let position = position.unwrap_or(apps.len()).min(apps.len());
+        apps.insert(position, app.to_string());
+        Ok(())
+    }
+
+    /// Whether the app was there to take out
+    pub fn remove(&mut self, list: &str, app: &str) -> Result<bool, String> {
+        let apps = self.list_mut(list)?;
+        let before = apps.len();
+        apps.retain(|a| a != app);
+        Ok(apps.len() < before)
+    }
+
+    fn list_mut(&mut self, list: &str) -> Result<&mut Vec<String>, String> {
+        if list == EDITORIAL {
+            return Ok(&mut self.editorial);
+        }
+        self.collections
+            .iter_mut()
+            .find(|c| c.id == list)
+            .map(|c| &mut c.apps)
+            .ok_or_else(|| format!("Collection not found: {}", list))
+    }
+
+    /// How well each app that is not installed fits the installed ones:
+    /// every installed app in its category and every collection it shares
+    /// with an installed app add to its score. Apps scoring nothing are
+    /// left out.
+    pub fn affinity(&self, apps: &[&AppListing]) -> HashMap<String, u32> {
+        let mut categories = HashMap::new();
+        for app in apps.iter().filter(|a| a.installed) {
+            *categories.entry(app.category).or_insert(0) += 1;
+        }
+        let installed: Vec<&str> = apps.iter().filter(|a| a.installed).map(|a| a.id.as_str()).collect();
+
+        let mut scores = HashMap::new();
+        for app in apps.iter().filter(|a| !a.installed) {
+            let shared = self
+                .collections
+                .iter()
+                .filter(|c| c.apps.contains(&app.id))
+                .map(|c| c.apps.iter().filter(|a| installed.contains(&a.as_str())).count() as u32)
+                .sum::<u32>();
+            let score = CATEGORY_WEIGHT * categories.get(&app.category).copied().unwrap_or(0) + shared;
+            if score > 0 {
+                scores.insert(app.id.clone(), score);
+            }
+        }
+        scores
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppCategory;
+
+    fn app(id: &str, category: AppCategory, installed: bool) -> AppListing {
+        let mut app = AppListing::new(id.to_string(), id.to_string(), "hairr OS Foundation".to_string(), category);
+        app.installed = installed;
+        app
+    }
+
+    #[test]
+    fn test_curation() {
+        let vfs = VirtualFileSystem::new();
+        assert_eq!(Curation::load(&vfs).unwrap(), None);
+        let mut curation = Curation {
+            collections: vec![Collection::new("dev-essentials", "Essential dev tools", "", &["code-studio"])],
+            editorial: vec!["messenger".to_string(), "editor".to_string()],
+        };
+        curation.add("dev-essentials", "editor", Some(0)).unwrap();
+        curation.add(EDITORIAL, "editor", Some(0)).unwrap();
+        assert_eq!(curation.collection("dev-essentials").unwrap().apps, vec!["editor", "code-studio"]);
+        assert_eq!(curation.editorial, vec!["editor", "messenger"]);
+        assert!(curation.remove(EDITORIAL, "messenger").unwrap());
+        assert!(!curation.remove(EDITORIAL, "messenger").unwrap());
+        assert!(curation.add("games", "editor", None).is_err());
+        curation.save(&vfs).unwrap();
+        assert_eq!(Curation::load(&vfs).unwrap(), Some(curation.clone()));
+
+        let apps = [
+            app("editor", AppCategory::Productivity, true),
+            app("code-studio", AppCategory::Development, false),
+            app("notes", AppCategory::Productivity, false),
+            app("player", AppCategory::Entertainment, false),
+        ];
+        let scores = curation.affinity(&apps.iter().collect::<Vec<_>>());
+        assert_eq!(scores.len(), 2);
+        assert_eq!(scores["notes"], 2);
+        assert_eq!(scores["code-studio"], 1);
+    }
+}
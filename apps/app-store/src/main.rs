@@ -1,10 +1,57 @@
 //! hairr OS App Store
 //! 
 //! First-party graphical application store for discovering and managing
-//! applications on hairr OS.
+//! applications on hairr OS. Installs, removals and updates are carried
+//! out by the package manager over IPC, and whether an app is installed
+//! comes from its package database. The Updates tab lists what
+//! `pkg check-updates` found with each version's changelog, and updates
+//! apps one at a time or all at once, skipping those the user deferred.
+//! Priced apps are bought with the user's decentralized identity and only
+//! installed with a valid entitlement. Developers submit signed bundles,
+//! which are validated and kept as drafts until published. Their
+//! screenshots are stored with thumbnails and only read when shown.
+//! Moderators curate collections and an editorial order, and the store
+//! recommends apps close to the installed ones.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use filesystem::VirtualFileSystem;
+use i18n::{Locale, Localizer};
+use ipc::{ChannelId, IPCManager, Message};
+use keystore::Keystore;
+use serde::Deserialize;
+use system_utils::cli::{Cli, CliError, Command};
+use system_utils::time::Deadline;
+use theme::{Mode, ThemeManager};
+use users::{SessionToken, User, UserService};
+
+mod curation;
+mod media;
+mod publishing;
+mod purchases;
+mod reviews;
+mod updates;
+
+use curation::{Collection, Curation};
+use media::{MediaStore, THUMBNAIL_WIDTHS};
+use publishing::{AppBundle, Publisher};
+use purchases::{EntitlementStore, Licensor, PaymentProcessor, PurchaseHistory};
+use reviews::{ReviewStore, ReviewSort, ReviewSync};
+use updates::DeferredUpdates;
+
+/// Members of this group, and root, moderate reviews
+pub const MODERATORS_GROUP: &str = "store-moderators";
+
+/// Reviews shown per page
+const REVIEWS_PER_PAGE: usize = 10;
+
+/// Apps `recommended` lists
+const RECOMMENDATIONS: usize = 5;
 
 /// Application category
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -32,6 +79,21 @@ impl AppCategory {
             AppCategory::System => "System",
         }
     }
+
+    /// Category by name, in any case
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "productivity" => Some(AppCategory::Productivity),
+            "development" => Some(AppCategory::Development),
+            "graphics" => Some(AppCategory::Graphics),
+            "entertainment" => Some(AppCategory::Entertainment),
+            "utilities" => Some(AppCategory::Utilities),
+            "education" => Some(AppCategory::Education),
+            "communication" => Some(AppCategory::Communication),
+            "system" => Some(AppCategory::System),
+            _ => None,
+        }
+    }
 }
 
 /// Application rating
@@ -59,7 +121,10 @@ pub struct AppListing {
     pub size_mb: u32,
     pub rating: Option<Rating>,
     pub price: f32,
+    /// VFS paths, read only when shown
     pub screenshots: Vec<String>,
+    /// What the app may access, as `pkg info` lists it
+    pub capabilities: Vec<String>,
     pub installed: bool,
 }
 
@@ -76,6 +141,7 @@ impl AppListing {
             rating: None,
             price: 0.0,
             screenshots: Vec::new(),
+            capabilities: Vec::new(),
             installed: false,
         }
     }
@@ -85,11 +151,102 @@ impl AppListing {
     }
 }
 
+/// Newer version of an installed package, as listed by `pkg check-updates`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct AppUpdate {
+    pub id: String,
+    pub name: String,
+    pub installed: String,
+    pub available: String,
+    /// Newest first
+    #[serde(default)]
+    pub changelog: Vec<ChangelogEntry>,
+}
+
+/// What changed in one version
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ChangelogEntry {
+    pub version: String,
+    pub notes: String,
+}
+
+/// Runs `pkg check-updates` and returns its output
+pub type UpdateSource = Arc<dyn Fn() -> Result<Vec<u8>, String> + Send + Sync>;
+
+/// Step of a package manager transaction
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum PackageEvent {
+    Downloading { id: String, version: String },
+    Installing { id: String, version: String },
+    Removing { id: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct InstalledPackage {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+}
+
+/// Package manager reply to a request
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum PackageResponse {
+    Installed { packages: Vec<InstalledPackage> },
+    Ok,
+    Error { message: String },
+}
+
+/// Sends a request to the package manager and returns its reply, handing
+/// every message that arrives before it to the callback
+pub type PackageTransport = Arc<dyn Fn(Message, &dyn Fn(&Message)) -> Result<Message, String> + Send + Sync>;
+
+/// Transport over the channels the package manager serves; gives up when
+/// no reply arrives within `timeout`
+pub fn ipc_transport(ipc: Arc<IPCManager>, requests: ChannelId, replies: ChannelId, timeout: Duration) -> PackageTransport {
+    Arc::new(move |request: Message, on_event: &dyn Fn(&Message)| {
+        let Message::Request { id, .. } = &request else {
+            return Err("Only requests can be sent to the package manager".to_string());
+        };
+        let id = *id;
+        ipc.send_message(requests, request)?;
+        let deadline = Deadline::after(timeout);
+        loop {
+            match ipc.receive_message(replies)? {
+                Some(Message::Response { id: reply, data }) if reply == id => return Ok(Message::Response { id, data }),
+                Some(error @ Message::Error { .. }) => return Ok(error),
+                Some(event) => on_event(&event),
+                None if deadline.expired() => return Err("The package manager did not respond".to_string()),
+                None => thread::sleep(Duration::from_millis(5)),
+            }
+        }
+    })
+}
+
+/// Asks the user a yes/no question
+pub type Prompt = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+/// Prompt answered on stdin; anything but "y" or "yes" declines
+fn stdin_prompt() -> Prompt {
+    Arc::new(|question: &str| {
+        print!("{} ", question);
+        io::stdout().flush().unwrap();
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer).is_ok() && matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+    })
+}
+
 /// App Store
 pub struct AppStore {
     apps: HashMap<String, AppListing>,
     featured_apps: Vec<String>,
+    curation: Curation,
     categories: HashMap<AppCategory, Vec<String>>,
+    updates: Vec<AppUpdate>,
+    deferred: DeferredUpdates,
+    reviews: ReviewStore,
+    entitlements: EntitlementStore,
 }
 
 impl AppStore {
@@ -97,7 +254,12 @@ impl AppStore {
         let mut store = AppStore {
             apps: HashMap::new(),
             featured_apps: Vec::new(),
+            curation: Curation::default(),
             categories: HashMap::new(),
+            updates: Vec::new(),
+            deferred: DeferredUpdates::default(),
+            reviews: ReviewStore::default(),
+            entitlements: EntitlementStore::default(),
         };
 
         store.populate_default_apps();
@@ -138,6 +300,7 @@ impl AppStore {
         code_editor.description = "Professional IDE for multiple programming languages".to_string();
         code_editor.size_mb = 150;
         code_editor.rating = Some(Rating::new(4.8, 5400));
+        code_editor.price = 29.99;
         self.add_app(code_editor);
 
         // Add communication apps
@@ -150,6 +313,10 @@ impl AppStore {
         messenger.description = "Secure, decentralized messaging with end-to-end encryption".to_string();
         messenger.size_mb = 45;
         messenger.rating = Some(Rating::new(4.6, 3200));
+        messenger.capabilities = vec![
+            "read and write the network host relay.hairr-os.org".to_string(),
+            "read files in /home/contacts".to_string(),
+        ];
         self.add_app(messenger);
 
         // Add entertainment apps
@@ -162,6 +329,7 @@ impl AppStore {
         media_player.description = "Play all your favorite audio and video formats".to_string();
         media_player.size_mb = 80;
         media_player.rating = Some(Rating::new(4.4, 1800));
+        media_player.capabilities = vec!["write the audio device".to_string(), "read files in /home/media".to_string()];
         self.add_app(media_player);
 
         // Add system apps
@@ -182,6 +350,34 @@ impl AppStore {
             "hairr-messenger".to_string(),
             "chrysalis".to_string(),
         ];
+
+        // Set collections and editors' picks
+        self.curation.collections = vec![
+            Collection::new(
+                "dev-essentials",
+                "Essential dev tools",
+                "Everything to write and ship code",
+                &["code-studio", "text-editor", "file-manager"],
+            ),
+            Collection::new(
+                "stay-in-touch",
+                "Stay in touch",
+                "Talk to friends without giving up your data",
+                &["hairr-messenger"],
+            ),
+            Collection::new(
+                "unwind",
+                "Unwind",
+                "Music, films and more",
+                &["media-player"],
+            ),
+        ];
+        self.curation.editorial = vec![
+            "hairr-messenger".to_string(),
+            "text-editor".to_string(),
+            "media-player".to_string(),
+            "file-manager".to_string(),
+        ];
     }
 
     /// Add an app to the store
@@ -193,10 +389,19 @@ impl AppStore {
         
         self.categories
             .entry(category)
-            .or_insert_with(Vec::new)
+            .or_default()
             .push(app_id);
     }
 
+    /// Take an app out of the store; it stays featured should it come back
+    pub fn remove_app(&mut self, id: &str) -> Option<AppListing> {
+        let app = self.apps.remove(id)?;
+        if let Some(ids) = self.categories.get_mut(&app.category) {
+            ids.retain(|i| i != id);
+        }
+        Some(app)
+    }
+
     /// Get an app by ID
     pub fn get_app(&self, id: &str) -> Option<&AppListing> {
         self.apps.get(id)
@@ -237,6 +442,75 @@ impl AppStore {
         self.apps.values().collect()
     }
 
+    /// Get curated collections
+    pub fn get_collections(&self) -> &[Collection] {
+        &self.curation.collections
+    }
+
+    /// Get the apps of a collection, in its order
+    pub fn get_collection(&self, id: &str) -> Option<Vec<&AppListing>> {
+        let collection = self.curation.collection(id)?;
+        Some(collection.apps.iter().filter_map(|id| self.apps.get(id)).collect())
+    }
+
+    /// Get editors' picks, in editorial order
+    pub fn get_editorial(&self) -> Vec<&AppListing> {
+        self.curation
+            .editorial
+            .iter()
+            .filter_map(|id| self.apps.get(id))
+            .collect()
+    }
+
+    /// Get up to `limit` apps that are not installed, closest to the
+    /// installed ones first and better rated apps first among equals
+    pub fn get_recommended(&self, limit: usize) -> Vec<&AppListing> {
+        let scores = self.curation.affinity(&self.get_all());
+        let mut apps: Vec<&AppListing> = scores.keys().filter_map(|id| self.apps.get(id)).collect();
+        let stars = |app: &AppListing| self.rating(app).map_or(0.0, |r| r.stars);
+        apps.sort_by(|a, b| {
+            scores[&b.id]
+                .cmp(&scores[&a.id])
+                .then(stars(b).total_cmp(&stars(a)))
+                .then(a.id.cmp(&b.id))
+        });
+        apps.truncate(limit);
+        apps
+    }
+
+    /// Replace the update list with the JSON `pkg check-updates` printed
+    pub fn set_updates_from_json(&mut self, data: &[u8]) -> Result<(), String> {
+        self.updates = serde_json::from_slice(data).map_err(|e| format!("Invalid update list: {}", e))?;
+        Ok(())
+    }
+
+    /// Updates found by the last check
+    pub fn get_updates(&self) -> &[AppUpdate] {
+        &self.updates
+    }
+
+    /// Update found for an app by the last check
+    pub fn get_update(&self, id: &str) -> Option<&AppUpdate> {
+        self.updates.iter().find(|u| u.id == id)
+    }
+
+    /// Whether `update-all` leaves the update out
+    pub fn is_deferred(&self, update: &AppUpdate) -> bool {
+        self.deferred.is_deferred(update)
+    }
+
+    /// Seed rating combined with the visible user reviews
+    pub fn rating(&self, app: &AppListing) -> Option<Rating> {
+        self.reviews.rating(&app.id, app.rating)
+    }
+
+    /// Take the installed state of every app from the package database
+    pub fn set_installed(&mut self, ids: &HashSet<String>) {
+        for app in self.apps.values_mut() {
+            app.installed = ids.contains(&app.id);
+        }
+    }
+
     /// Mark an app as installed
     pub fn mark_installed(&mut self, id: &str) -> Result<(), String> {
         if let Some(app) = self.apps.get_mut(id) {
@@ -264,22 +538,191 @@ impl Default for AppStore {
     }
 }
 
+/// What the app store CLI accepts
+fn commands() -> Cli {
+    Cli::new()
+        .command(Command::new("featured", "Show featured apps"))
+        .command(Command::new("categories", "List all categories"))
+        .command(Command::new("category", "Show apps in a category").arg("category_name"))
+        .command(Command::new("collections", "List curated collections"))
+        .command(Command::new("collection", "Show the apps in a collection").arg("collection_id"))
+        .command(Command::new("picks", "Show editors' picks"))
+        .command(Command::new("recommended", "Show apps like the ones you installed"))
+        .command(Command::new("search", "Search for apps").rest("query"))
+        .command(Command::new("info", "Show detailed app information").arg("app_id"))
+        .command(Command::new("all", "List all available apps"))
+        .command(Command::new("buy", "Buy a priced app").arg("app_id"))
+        .command(Command::new("restore-purchases", "Restore apps bought on other devices"))
+        .command(Command::new("install", "Review an app's permissions and install it").arg("app_id"))
+        .command(Command::new("reviews", "Read an app's reviews, newest, oldest, highest or lowest first").arg("app_id").optional("sort").optional("page"))
+        .command(Command::new("review", "Rate and review an app").arg("app_id").arg("stars").optional_rest("text"))
+        .command(Command::new("flag", "Report an abusive review").arg("review_id"))
+        .command(Command::new("moderate", "Review reported reviews, or hide or restore one").optional("review_id").optional("verdict"))
+        .command(Command::new("screenshots", "Show an app's screenshot thumbnails").arg("app_id").optional("width"))
+        .command(Command::new("submit", "Submit a signed app bundle as a draft").arg("bundle_path"))
+        .command(Command::new("submissions", "List submitted apps and their state"))
+        .command(Command::new("publish", "Publish an app's draft").arg("app_id"))
+        .command(Command::new("unpublish", "Take an app out of the store").arg("app_id"))
+        .command(Command::new("register-developer", "Register a developer's signing key").arg("key_id").rest("developer"))
+        .command(
+            Command::new("curate", "Add an app to or remove it from a collection or the editors' picks")
+                .arg("list")
+                .arg("action")
+                .arg("app_id")
+                .optional("position"),
+        )
+        .command(Command::new("uninstall", "Remove an installed app").arg("app_id"))
+        .command(Command::new("update", "Update an installed app").arg("app_id"))
+        .command(Command::new("updates", "Check for updates to installed apps"))
+        .command(Command::new("changelog", "Show what changed in an app's update").arg("app_id"))
+        .command(Command::new("update-all", "Install every update that is not deferred"))
+        .command(Command::new("defer", "Leave an update out of update-all").arg("app_id"))
+        .command(Command::new("locale", "Show or change the display language").optional("name"))
+        .command(Command::new("mode", "Show or change the color mode").optional("mode"))
+        .command(Command::new("help", "Show this help message"))
+        .command(Command::new("exit", "Exit the app store").alias("quit"))
+}
+
 /// CLI interface for the App Store
 pub struct AppStoreCLI {
     store: AppStore,
+    i18n: Arc<Localizer>,
+    theme: Arc<ThemeManager>,
+    update_source: Option<UpdateSource>,
+    packages: Option<PackageTransport>,
+    next_request: u64,
+    prompt: Prompt,
+    /// Keeps the reviews, deferred updates, entitlements and curation
+    vfs: Option<Arc<VirtualFileSystem>>,
+    /// Screenshots of published apps
+    media: Option<MediaStore>,
+    /// Signed-in user reviews are written as
+    session: Option<(Arc<UserService>, SessionToken)>,
+    review_sync: Option<ReviewSync>,
+    /// DID apps are bought with
+    identity: Option<String>,
+    licensing: Option<(Licensor, PaymentProcessor)>,
+    purchase_history: Option<PurchaseHistory>,
+    publisher: Option<Publisher>,
+    commands: Cli,
 }
 
 impl AppStoreCLI {
     pub fn new() -> Self {
+        Self::with_localizer(Arc::new(Localizer::new()))
+    }
+
+    /// CLI rendering its messages through `i18n`
+    pub fn with_localizer(i18n: Arc<Localizer>) -> Self {
         AppStoreCLI {
             store: AppStore::new(),
+            i18n,
+            theme: Arc::new(ThemeManager::new()),
+            update_source: None,
+            packages: None,
+            next_request: 0,
+            prompt: stdin_prompt(),
+            vfs: None,
+            media: None,
+            session: None,
+            review_sync: None,
+            identity: None,
+            licensing: None,
+            purchase_history: None,
+            publisher: None,
+            commands: commands(),
         }
     }
 
+    /// Keep reviews, deferred updates, entitlements and curation in `vfs`, loading
+    /// those already there
+    pub fn attach_storage(&mut self, vfs: Arc<VirtualFileSystem>) -> Result<(), String> {
+        self.store.reviews = ReviewStore::load(&vfs)?;
+        self.store.deferred = DeferredUpdates::load(&vfs)?;
+        self.store.entitlements = EntitlementStore::load(&vfs)?;
+        if let Some(curation) = Curation::load(&vfs)? {
+            self.store.curation = curation;
+        }
+        self.media = Some(MediaStore::new(Arc::clone(&vfs)));
+        self.vfs = Some(vfs);
+        Ok(())
+    }
+
+    /// Write reviews and flags as the user of `token`
+    pub fn set_session(&mut self, users: Arc<UserService>, token: SessionToken) {
+        self.session = Some((users, token));
+    }
+
+    /// Send new and changed reviews on to a remote catalog
+    pub fn set_review_sync(&mut self, sync: ReviewSync) {
+        self.review_sync = Some(sync);
+    }
+
+    /// Buy apps as `did`
+    pub fn set_identity(&mut self, did: String) {
+        self.identity = Some(did);
+    }
+
+    /// Charge for apps through `payments` and issue their entitlements with
+    /// `licensor`
+    pub fn set_licensing(&mut self, licensor: Licensor, payments: PaymentProcessor) {
+        self.licensing = Some((licensor, payments));
+    }
+
+    /// Where purchases made on other devices are restored from
+    pub fn set_purchase_history(&mut self, history: PurchaseHistory) {
+        self.purchase_history = Some(history);
+    }
+
+    /// Accept developer submissions, checking signatures against keys in
+    /// `keystore`. Submissions already in storage are loaded and the
+    /// published ones listed.
+    pub fn attach_publishing(&mut self, keystore: Arc<Keystore>) -> Result<(), String> {
+        let publisher = match &self.vfs {
+            Some(vfs) => Publisher::load(keystore, vfs)?,
+            None => Publisher::new(keystore),
+        };
+        for listing in publisher.listings() {
+            self.list_app(listing);
+        }
+        self.publisher = Some(publisher);
+        Ok(())
+    }
+
+    /// Carry out installs, removals and updates through the package manager
+    pub fn set_package_service(&mut self, transport: PackageTransport) {
+        self.packages = Some(transport);
+    }
+
+    /// How the user confirms an app's permissions before it is installed
+    pub fn set_prompt(&mut self, prompt: Prompt) {
+        self.prompt = prompt;
+    }
+
+    /// Where the Updates tab gets its list from
+    pub fn set_update_source(&mut self, source: UpdateSource) {
+        self.update_source = Some(source);
+    }
+
+    /// Style headings with the desktop theme, following its dark/light mode
+    pub fn attach_theme(&mut self, theme: Arc<ThemeManager>) {
+        self.theme = theme;
+    }
+
+    /// A heading in the theme's accent color
+    fn heading(&self, text: &str) -> String {
+        format!("{}{}\x1b[0m", self.theme.appearance().palette().accent.ansi_foreground(), text)
+    }
+
     pub fn run(&mut self) {
         println!("hairr OS App Store v0.1.0");
-        println!("Discover and install applications for hairr OS");
-        println!("Type 'help' for available commands\n");
+        println!("{}", self.i18n.tr("Discover and install applications for hairr OS"));
+        println!("{}\n", self.i18n.tr("Type 'help' for available commands"));
+        if self.packages.is_some() {
+            if let Err(e) = self.sync_installed() {
+                println!("{}", self.i18n.tr_args("Error: {error}", &[("error", &e)]));
+            }
+        }
 
         loop {
             print!("store> ");
@@ -301,24 +744,35 @@ impl AppStoreCLI {
                         break;
                     }
                 }
-                Err(e) => println!("Error: {}", e),
+                Err(e) => println!("{}", self.i18n.tr_args("Error: {error}", &[("error", &e)])),
             }
         }
     }
 
     fn handle_command(&mut self, input: &str) -> Result<bool, String> {
-        let parts: Vec<&str> = input.split_whitespace().collect();
-        if parts.is_empty() {
-            return Ok(false);
-        }
+        let parsed = match self.commands.parse(input) {
+            Ok(parsed) => parsed,
+            Err(CliError::Empty) => return Ok(false),
+            Err(CliError::Unknown(command)) => {
+                println!("{}", self.i18n.tr_args("Unknown command: {command}", &[("command", &command)]));
+                println!("{}", self.i18n.tr("Type 'help' for available commands"));
+                return Ok(false);
+            }
+            Err(CliError::Usage(usage)) => {
+                println!("{}", self.i18n.tr_args("Usage: {usage}", &[("usage", &usage)]));
+                return Ok(false);
+            }
+            Err(e) => return Err(e.to_string()),
+        };
+        let arg = |name: &str| parsed.get(name).unwrap_or_default();
 
-        match parts[0] {
+        match parsed.command() {
             "help" => {
                 self.show_help();
                 Ok(false)
             }
-            "exit" | "quit" => {
-                println!("Goodbye!");
+            "exit" => {
+                println!("{}", self.i18n.tr("Goodbye!"));
                 Ok(true)
             }
             "featured" => {
@@ -329,58 +783,227 @@ impl AppStoreCLI {
                 self.show_categories();
                 Ok(false)
             }
-            "category" => {
-                if parts.len() < 2 {
-                    println!("Usage: category <category_name>");
-                } else {
-                    self.show_category(parts[1]);
+            "collections" => {
+                self.show_collections();
+                Ok(false)
+            }
+            "collection" => {
+                self.show_collection(arg("collection_id"))?;
+                Ok(false)
+            }
+            "picks" => {
+                let picks = self.store.get_editorial();
+                self.show_list(&self.i18n.tr("Editors' Picks:"), &picks);
+                Ok(false)
+            }
+            "recommended" => {
+                let recommended = self.store.get_recommended(RECOMMENDATIONS);
+                self.show_list(&self.i18n.tr("Recommended for You:"), &recommended);
+                if recommended.is_empty() {
+                    println!("{}\n", self.i18n.tr("Install a few apps to get recommendations"));
+                }
+                Ok(false)
+            }
+            "curate" => {
+                let (list, action, id) = (arg("list"), arg("action"), arg("app_id"));
+                if action != "add" && action != "remove" {
+                    println!("{}", self.i18n.tr_args("Usage: {usage}", &[("usage", parsed.usage())]));
+                    return Ok(false);
+                }
+                let position = parsed.value::<usize>("position")?;
+                let user = self.signed_in_user()?;
+                if !self.is_moderator(&user) {
+                    return Err("Only moderators can curate the store".to_string());
                 }
+                if action == "add" {
+                    self.store.get_app(id).ok_or_else(|| format!("App not found: {}", id))?;
+                    self.store.curation.add(list, id, position.map(|p| p.saturating_sub(1)))?;
+                } else if !self.store.curation.remove(list, id)? {
+                    return Err(format!("{} is not in {}", id, list));
+                }
+                if let Some(vfs) = &self.vfs {
+                    self.store.curation.save(vfs)?;
+                }
+                println!("{}", self.i18n.tr_args("{list} updated", &[("list", list)]));
+                Ok(false)
+            }
+            "category" => {
+                self.show_category(arg("category_name"));
                 Ok(false)
             }
             "search" => {
-                if parts.len() < 2 {
-                    println!("Usage: search <query>");
+                self.search_apps(arg("query"));
+                Ok(false)
+            }
+            "info" => {
+                self.show_app_info(arg("app_id"));
+                Ok(false)
+            }
+            "all" => {
+                self.show_all_apps();
+                Ok(false)
+            }
+            "install" => {
+                self.install_app(arg("app_id"))?;
+                Ok(false)
+            }
+            "buy" => {
+                self.buy_app(arg("app_id"))?;
+                Ok(false)
+            }
+            "restore-purchases" => {
+                self.restore_purchases()?;
+                Ok(false)
+            }
+            "submit" => {
+                let path = arg("bundle_path");
+                let vfs = self.vfs.clone().ok_or("No storage to read bundles from")?;
+                let bundle = AppBundle::from_json(&vfs.read_file(Path::new(path))?)?;
+                let (id, version) = (bundle.manifest.id.clone(), bundle.manifest.version.clone());
+                self.publisher_mut()?.submit(bundle)?;
+                self.save_submissions()?;
+                println!("{}", self.i18n.tr_args("{app} {version} saved as a draft", &[("app", &id), ("version", &version)]));
+                Ok(false)
+            }
+            "screenshots" => {
+                let width = parsed.value("width")?.unwrap_or(THUMBNAIL_WIDTHS[0]);
+                self.show_screenshots(arg("app_id"), width)?;
+                Ok(false)
+            }
+            "submissions" => {
+                self.show_submissions()?;
+                Ok(false)
+            }
+            "publish" | "unpublish" => {
+                let id = arg("app_id");
+                if parsed.command() == "publish" {
+                    let bundle = self.publisher_mut()?.publish(id)?.clone();
+                    if let Some(media) = &self.media {
+                        media.store(id, &bundle.screenshots)?;
+                    }
+                    self.list_app(bundle.listing());
+                    println!("{}", self.i18n.tr_args("{app} published", &[("app", id)]));
                 } else {
-                    let query = parts[1..].join(" ");
-                    self.search_apps(&query);
+                    self.publisher_mut()?.unpublish(id)?;
+                    self.store.remove_app(id);
+                    if let Some(media) = &self.media {
+                        media.remove(id);
+                    }
+                    println!("{}", self.i18n.tr_args("{app} unpublished", &[("app", id)]));
                 }
+                self.save_submissions()?;
                 Ok(false)
             }
-            "info" => {
-                if parts.len() < 2 {
-                    println!("Usage: info <app_id>");
+            "register-developer" => {
+                let user = self.signed_in_user()?;
+                if !self.is_moderator(&user) {
+                    return Err("Only moderators can register developers".to_string());
+                }
+                let developer = arg("developer");
+                self.publisher_mut()?.register_developer(developer, arg("key_id"))?;
+                self.save_submissions()?;
+                println!("{}", self.i18n.tr_args("{developer} registered", &[("developer", developer)]));
+                Ok(false)
+            }
+            "review" => {
+                let (id, stars) = (arg("app_id"), parsed.parse::<u8>("stars")?);
+                self.store.get_app(id).ok_or_else(|| format!("App not found: {}", id))?;
+                let user = self.signed_in_user()?;
+                self.store.reviews.submit(id, &user.name, stars, arg("text"), now())?;
+                self.save_reviews()?;
+                println!("{}", self.i18n.tr("Thanks for your review"));
+                Ok(false)
+            }
+            "reviews" => {
+                let sort = parsed.get("sort").map_or(Ok(ReviewSort::Newest), ReviewSort::parse)?;
+                let page = parsed.value("page")?.unwrap_or(1);
+                self.show_reviews(arg("app_id"), sort, page)?;
+                Ok(false)
+            }
+            "flag" => {
+                let review = parsed.parse::<u64>("review_id")?;
+                let user = self.signed_in_user()?;
+                self.store.reviews.flag(review, &user.name)?;
+                self.save_reviews()?;
+                println!("{}", self.i18n.tr("Review reported"));
+                Ok(false)
+            }
+            "moderate" => {
+                let user = self.signed_in_user()?;
+                if !self.is_moderator(&user) {
+                    return Err("Only moderators can moderate reviews".to_string());
+                }
+                match (parsed.value::<u64>("review_id")?, parsed.get("verdict")) {
+                    (None, None) => self.show_flagged(),
+                    (Some(review), Some(verdict @ ("hide" | "restore"))) => {
+                        self.store.reviews.moderate(review, verdict == "hide")?;
+                        self.save_reviews()?;
+                        println!("{}", self.i18n.tr("Review updated"));
+                    }
+                    _ => println!("{}", self.i18n.tr_args("Usage: {usage}", &[("usage", parsed.usage())])),
+                }
+                Ok(false)
+            }
+            "uninstall" | "update" => {
+                let id = arg("app_id");
+                self.store.get_app(id).ok_or_else(|| format!("App not found: {}", id))?;
+                self.transaction(serde_json::json!({"op": parsed.command(), "id": id}))?;
+                if parsed.command() == "update" {
+                    self.updated(id)?;
+                    println!("{}", self.i18n.tr("Updated"));
                 } else {
-                    self.show_app_info(parts[1]);
+                    println!("{}", self.i18n.tr("Uninstalled"));
                 }
                 Ok(false)
             }
-            "all" => {
-                self.show_all_apps();
+            "updates" => {
+                if self.check_updates()? {
+                    self.show_updates();
+                }
                 Ok(false)
             }
-            _ => {
-                println!("Unknown command: {}", parts[0]);
-                println!("Type 'help' for available commands");
+            "changelog" => {
+                self.show_changelog(arg("app_id"))?;
                 Ok(false)
             }
+            "update-all" => {
+                self.update_all()?;
+                Ok(false)
+            }
+            "defer" => {
+                let id = arg("app_id");
+                let update = self.store.get_update(id).ok_or_else(|| format!("No update for {}", id))?.clone();
+                self.store.deferred.defer(&update);
+                self.save_deferred()?;
+                println!("{}", self.i18n.tr_args("{app} {version} deferred", &[("app", &update.name), ("version", &update.available)]));
+                Ok(false)
+            }
+            "locale" => {
+                match parsed.get("name") {
+                    Some(name) => self.i18n.set_locale(Locale::parse(name)?),
+                    None => println!("{}", self.i18n.locale()),
+                }
+                Ok(false)
+            }
+            "mode" => {
+                match parsed.get("mode") {
+                    Some(mode) => self.theme.set_mode(Mode::parse(mode)?),
+                    None => println!("{}", self.theme.mode()),
+                }
+                Ok(false)
+            }
+            command => unreachable!("{} has no handler", command),
         }
     }
 
     fn show_help(&self) {
-        println!("Available commands:");
-        println!("  featured             - Show featured apps");
-        println!("  categories           - List all categories");
-        println!("  category <name>      - Show apps in a category");
-        println!("  search <query>       - Search for apps");
-        println!("  info <app_id>        - Show detailed app information");
-        println!("  all                  - List all available apps");
-        println!("  help                 - Show this help message");
-        println!("  exit/quit            - Exit the app store");
+        println!("{}", self.i18n.tr("Available commands:"));
+        print!("{}", self.commands.help(|about| self.i18n.tr(about)));
     }
 
     fn show_featured(&self) {
         let featured = self.store.get_featured();
-        println!("\n🌟 Featured Apps:");
+        println!("\n{}", self.heading(&self.i18n.tr("🌟 Featured Apps:")));
         println!("{:-<80}", "");
         
         for app in featured {
@@ -389,8 +1012,128 @@ impl AppStoreCLI {
         println!();
     }
 
+    /// A titled list of app summaries
+    fn show_list(&self, title: &str, apps: &[&AppListing]) {
+        println!("\n{}", self.heading(title));
+        println!("{:-<80}", "");
+        for app in apps {
+            self.print_app_summary(app);
+        }
+        println!();
+    }
+
+    fn show_collections(&self) {
+        println!("\n{}", self.heading(&self.i18n.tr("Collections:")));
+        println!("{:-<80}", "");
+        for collection in self.store.get_collections() {
+            let count = self.i18n.trn("{count} app", "{count} apps", collection.apps.len() as u64, &[]);
+            println!("  {} - {} ({})", collection.id, collection.title, count);
+            if !collection.description.is_empty() {
+                println!("      {}", collection.description);
+            }
+        }
+        println!();
+    }
+
+    fn show_collection(&self, id: &str) -> Result<(), String> {
+        let apps = self.store.get_collection(id).ok_or_else(|| format!("Collection not found: {}", id))?;
+        let collection = self.store.curation.collection(id).unwrap();
+        self.show_list(&format!("{}:", collection.title), &apps);
+        Ok(())
+    }
+
+    fn show_updates(&self) {
+        let updates = self.store.get_updates();
+        println!("\n{}", self.heading(&self.i18n.tr("Updates:")));
+        println!("{:-<80}", "");
+        if updates.is_empty() {
+            println!("  {}", self.i18n.tr("All apps are up to date"));
+        }
+        for update in updates {
+            let deferred = if self.store.is_deferred(update) { self.i18n.tr(" [DEFERRED]") } else { String::new() };
+            println!("  {} - {} {} -> {}{}", update.id, update.name, update.installed, update.available, deferred);
+        }
+        println!();
+    }
+
+    /// Replace the update list from the update source; false when there
+    /// is none
+    fn check_updates(&mut self) -> Result<bool, String> {
+        let Some(source) = &self.update_source else {
+            println!("{}", self.i18n.tr("Update checks are not available"));
+            return Ok(false);
+        };
+        self.store.set_updates_from_json(&source()?)?;
+        Ok(true)
+    }
+
+    fn show_changelog(&self, app_id: &str) -> Result<(), String> {
+        let update = self.store.get_update(app_id).ok_or_else(|| format!("No update for {}", app_id))?;
+        let title = self.i18n.tr_args("What's new in {app} {version}:", &[("app", &update.name), ("version", &update.available)]);
+        println!("\n{}", self.heading(&title));
+        println!("{:-<80}", "");
+        if update.changelog.is_empty() {
+            println!("  {}", self.i18n.tr("No changelog published"));
+        }
+        for entry in &update.changelog {
+            println!("  {}", entry.version);
+            for line in entry.notes.lines() {
+                println!("    {}", line);
+            }
+        }
+        println!();
+        Ok(())
+    }
+
+    /// Update every app with an update that is not deferred, going on past
+    /// failures
+    fn update_all(&mut self) -> Result<(), String> {
+        if !self.check_updates()? {
+            return Ok(());
+        }
+        let pending: Vec<AppUpdate> =
+            self.store.get_updates().iter().filter(|u| !self.store.is_deferred(u)).cloned().collect();
+        if pending.is_empty() {
+            println!("{}", self.i18n.tr("Nothing to update"));
+            return Ok(());
+        }
+        let mut failed = 0;
+        for update in &pending {
+            match self.transaction(serde_json::json!({"op": "update", "id": update.id})) {
+                Ok(()) => {
+                    self.updated(&update.id)?;
+                    println!("{}", self.i18n.tr_args("Updated {app} to {version}", &[("app", &update.name), ("version", &update.available)]));
+                }
+                Err(e) => {
+                    failed += 1;
+                    println!("{}", self.i18n.tr_args("{app} not updated: {error}", &[("app", &update.name), ("error", &e)]));
+                }
+            }
+        }
+        if failed > 0 {
+            return Err(self.i18n.trn("{count} update failed", "{count} updates failed", failed, &[]));
+        }
+        Ok(())
+    }
+
+    /// Drop an app from the update list along with its deferral
+    fn updated(&mut self, id: &str) -> Result<(), String> {
+        self.store.updates.retain(|u| u.id != id);
+        if self.store.deferred.remove(id) {
+            self.save_deferred()?;
+        }
+        Ok(())
+    }
+
+    fn save_deferred(&self) -> Result<(), String> {
+        match &self.vfs {
+            Some(vfs) => self.store.deferred.save(vfs),
+            None => Ok(()),
+        }
+    }
+
     fn show_categories(&self) {
-        println!("\nAvailable Categories:");
+        println!("\n{}", self.heading(&self.i18n.tr("Available Categories:")));
         println!("  - Productivity");
         println!("  - Development");
         println!("  - Graphics");
@@ -399,28 +1142,18 @@ impl AppStoreCLI {
         println!("  - Education");
         println!("  - Communication");
         println!("  - System");
-        println!("\nUse 'category <name>' to view apps in a category");
+        println!("\n{}", self.i18n.tr("Use 'category <name>' to view apps in a category"));
         println!();
     }
 
     fn show_category(&self, category_name: &str) {
-        let category = match category_name.to_lowercase().as_str() {
-            "productivity" => AppCategory::Productivity,
-            "development" => AppCategory::Development,
-            "graphics" => AppCategory::Graphics,
-            "entertainment" => AppCategory::Entertainment,
-            "utilities" => AppCategory::Utilities,
-            "education" => AppCategory::Education,
-            "communication" => AppCategory::Communication,
-            "system" => AppCategory::System,
-            _ => {
-                println!("Unknown category: {}", category_name);
-                return;
-            }
+        let Some(category) = AppCategory::parse(category_name) else {
+            println!("{}", self.i18n.tr_args("Unknown category: {category}", &[("category", category_name)]));
+            return;
         };
 
         let apps = self.store.get_by_category(category);
-        println!("\n{} Apps:", category.as_str());
+        println!("\n{}", self.heading(&self.i18n.tr_args("{category} Apps:", &[("category", category.as_str())])));
         println!("{:-<80}", "");
         
         for app in apps {
@@ -433,11 +1166,11 @@ impl AppStoreCLI {
         let results = self.store.search(query);
         
         if results.is_empty() {
-            println!("No apps found matching '{}'", query);
+            println!("{}", self.i18n.tr_args("No apps found matching '{query}'", &[("query", query)]));
             return;
         }
 
-        println!("\nSearch Results for '{}':", query);
+        println!("\n{}", self.heading(&self.i18n.tr_args("Search Results for '{query}':", &[("query", query)])));
         println!("{:-<80}", "");
         
         for app in results {
@@ -455,25 +1188,316 @@ impl AppStoreCLI {
             println!("Category:     {}", app.category.as_str());
             println!("Version:      {}", app.version);
             println!("Size:         {} MB", app.size_mb);
-            println!("Price:        {}", if app.is_free() { "Free".to_string() } else { format!("${:.2}", app.price) });
+            println!("Price:        {}", self.price_label(app));
             
-            if let Some(rating) = app.rating {
+            if let Some(rating) = self.store.rating(app) {
                 println!("Rating:       ⭐ {:.1}/5.0 ({} reviews)", rating.stars, rating.count);
             }
             
             println!("Installed:    {}", if app.installed { "Yes" } else { "No" });
-            println!("\nDescription:");
+            println!("\n{}", self.i18n.tr("Description:"));
             println!("{}", app.description);
+            self.show_permissions(app);
+            if !app.screenshots.is_empty() {
+                let count = self.i18n.trn("{count} screenshot", "{count} screenshots", app.screenshots.len() as u64, &[]);
+                println!("\n{}", self.i18n.tr_args("{count}; 'screenshots {app}' shows them", &[("count", &count), ("app", &app.id)]));
+            }
             println!("{}", "=".repeat(80));
             println!();
         } else {
-            println!("App not found: {}", app_id);
+            println!("{}", self.i18n.tr_args("App not found: {app}", &[("app", app_id)]));
+        }
+    }
+
+    /// User of the current session
+    fn signed_in_user(&self) -> Result<User, String> {
+        let (users, token) = self.session.as_ref().ok_or("Sign in to review apps")?;
+        users.session_user(token).ok_or_else(|| "Your session has ended".to_string())
+    }
+
+    fn is_moderator(&self, user: &User) -> bool {
+        let Some((users, _)) = &self.session else {
+            return false;
+        };
+        user.uid == 0
+            || users
+                .group(MODERATORS_GROUP)
+                .is_some_and(|g| g.gid == user.primary_group || g.members.contains(&user.uid))
+    }
+
+    /// Store the reviews and pass changes on to the remote catalog; a
+    /// failed sync is retried with the next change
+    fn save_reviews(&mut self) -> Result<(), String> {
+        if let Some(vfs) = &self.vfs {
+            self.store.reviews.save(vfs)?;
+        }
+        if let Some(sync) = &self.review_sync {
+            match self.store.reviews.sync(sync) {
+                Ok(_) => {
+                    if let Some(vfs) = &self.vfs {
+                        self.store.reviews.save(vfs)?;
+                    }
+                }
+                Err(e) => println!("{}", self.i18n.tr_args("Reviews not synced: {error}", &[("error", &e)])),
+            }
+        }
+        Ok(())
+    }
+
+    fn show_reviews(&self, app_id: &str, sort: ReviewSort, page: usize) -> Result<(), String> {
+        let app = self.store.get_app(app_id).ok_or_else(|| format!("App not found: {}", app_id))?;
+        println!("\n{}", self.heading(&self.i18n.tr_args("Reviews of {app}:", &[("app", &app.name)])));
+        println!("{:-<80}", "");
+        let reviews = self.store.reviews.page(app_id, sort, page, REVIEWS_PER_PAGE);
+        if reviews.is_empty() {
+            println!("  {}", self.i18n.tr("No reviews"));
+        }
+        for review in reviews {
+            println!("  #{} {} {}", review.id, "⭐".repeat(review.stars as usize), review.user);
+            if !review.text.is_empty() {
+                println!("    {}", review.text);
+            }
+        }
+        println!();
+        Ok(())
+    }
+
+    fn show_flagged(&self) {
+        println!("\n{}", self.heading(&self.i18n.tr("Reported reviews:")));
+        println!("{:-<80}", "");
+        for review in self.store.reviews.flagged() {
+            let status = if review.hidden { self.i18n.tr("hidden") } else { self.i18n.tr("visible") };
+            println!(
+                "  #{} {} {} ({}, {})",
+                review.id,
+                review.app,
+                review.user,
+                self.i18n.trn("{count} report", "{count} reports", review.flagged_by.len() as u64, &[]),
+                status
+            );
+            println!("    {}", review.text);
+        }
+        println!();
+    }
+
+    /// Everything the app will be able to access
+    fn show_permissions(&self, app: &AppListing) {
+        println!("\n{}", self.i18n.tr("Permissions:"));
+        if app.capabilities.is_empty() {
+            println!("  {}", self.i18n.tr("No access beyond its own files"));
+        }
+        for capability in &app.capabilities {
+            println!("  - {}", capability);
+        }
+    }
+
+    /// Send a request to the package manager, showing its progress as it
+    /// arrives
+    fn request(&mut self, request: serde_json::Value) -> Result<PackageResponse, String> {
+        let transport = self.packages.clone().ok_or("The package manager is not available")?;
+        self.next_request += 1;
+        let message = Message::Request {
+            id: self.next_request,
+            data: serde_json::to_vec(&request).unwrap(),
+        };
+        let reply = transport(message, &|message| self.show_event(message))?;
+        let response = match reply {
+            Message::Response { data, .. } => {
+                serde_json::from_slice(&data).map_err(|e| format!("Invalid package manager response: {}", e))?
+            }
+            Message::Error { message, .. } => return Err(message),
+            other => return Err(format!("Unexpected package manager reply: {:?}", other)),
+        };
+        match response {
+            PackageResponse::Error { message } => Err(message),
+            response => Ok(response),
+        }
+    }
+
+    /// Run an install, removal or update, then refresh what is installed
+    fn transaction(&mut self, request: serde_json::Value) -> Result<(), String> {
+        let result = self.request(request).map(|_| ());
+        self.sync_installed()?;
+        result
+    }
+
+    /// Mark exactly the apps the package database lists as installed
+    fn sync_installed(&mut self) -> Result<(), String> {
+        let PackageResponse::Installed { packages } = self.request(serde_json::json!({"op": "list"}))? else {
+            return Err("Unexpected package manager response".to_string());
+        };
+        self.store.set_installed(&packages.into_iter().map(|p| p.id).collect());
+        Ok(())
+    }
+
+    fn show_event(&self, message: &Message) {
+        let Message::Text(text) = message else {
+            return;
+        };
+        match serde_json::from_str(text) {
+            Ok(PackageEvent::Downloading { id, version }) => {
+                println!("{}", self.i18n.tr_args("Downloading {app} {version}...", &[("app", &id), ("version", &version)]))
+            }
+            Ok(PackageEvent::Installing { id, version }) => {
+                println!("{}", self.i18n.tr_args("Installing {app} {version}...", &[("app", &id), ("version", &version)]))
+            }
+            Ok(PackageEvent::Removing { id }) => println!("{}", self.i18n.tr_args("Removing {app}...", &[("app", &id)])),
+            Err(_) => {}
+        }
+    }
+
+    /// Install an app once the user has accepted its permissions
+    fn install_app(&mut self, id: &str) -> Result<(), String> {
+        if self.packages.is_none() {
+            println!("{}", self.i18n.tr("Installing is not available"));
+            return Ok(());
+        }
+        let app = self.store.get_app(id).ok_or_else(|| format!("App not found: {}", id))?;
+        if app.installed {
+            println!("{}", self.i18n.tr_args("{app} is already installed", &[("app", &app.name)]));
+            return Ok(());
+        }
+        if !app.is_free() && !self.owns(id)? {
+            return Err(self.i18n.tr_args("Buy {app} before installing it", &[("app", &app.name)]));
+        }
+        if !app.capabilities.is_empty() {
+            println!("{}", self.heading(&self.i18n.tr_args("{app} will be able to:", &[("app", &app.name)])));
+            for capability in &app.capabilities {
+                println!("  - {}", capability);
+            }
+            if !(self.prompt)(&self.i18n.tr("Install? [y/N]")) {
+                println!("{}", self.i18n.tr("Installation cancelled"));
+                return Ok(());
+            }
+        }
+        self.transaction(serde_json::json!({"op": "install", "id": id}))?;
+        println!("{}", self.i18n.tr("Installed"));
+        Ok(())
+    }
+
+    /// Identity purchases are made and checked with
+    fn buyer(&self) -> Result<&str, String> {
+        self.identity.as_deref().ok_or_else(|| "Set up a decentralized identity to buy apps".to_string())
+    }
+
+    /// Whether the buyer holds a valid entitlement to an app
+    fn owns(&self, app_id: &str) -> Result<bool, String> {
+        let (licensor, _) = self.licensing.as_ref().ok_or("Purchases are not available")?;
+        Ok(self.store.entitlements.find(licensor, self.buyer()?, app_id).is_some())
+    }
+
+    /// Charge for an app once the user confirms, and keep its entitlement
+    fn buy_app(&mut self, id: &str) -> Result<(), String> {
+        let app = self.store.get_app(id).ok_or_else(|| format!("App not found: {}", id))?.clone();
+        if app.is_free() {
+            println!("{}", self.i18n.tr_args("{app} is free", &[("app", &app.name)]));
+            return Ok(());
+        }
+        if self.owns(id)? {
+            println!("{}", self.i18n.tr_args("You already own {app}", &[("app", &app.name)]));
+            return Ok(());
+        }
+        let question = self.i18n.tr_args("Buy {app} for {price}? [y/N]", &[("app", &app.name), ("price", &self.price_label(&app))]);
+        if !(self.prompt)(&question) {
+            println!("{}", self.i18n.tr("Purchase cancelled"));
+            return Ok(());
+        }
+        let buyer = self.buyer()?.to_string();
+        let (licensor, payments) = self.licensing.as_ref().ok_or("Purchases are not available")?;
+        let receipt = payments(&buyer, id, app.price)?;
+        let token = licensor.issue(&buyer, id, app.price, &receipt)?;
+        self.store.entitlements.add(&buyer, token);
+        self.save_entitlements()?;
+        println!("{}", self.i18n.tr_args("Purchased {app}", &[("app", &app.name)]));
+        Ok(())
+    }
+
+    /// Fetch the buyer's entitlements from the remote catalog and keep
+    /// those that verify
+    fn restore_purchases(&mut self) -> Result<(), String> {
+        let buyer = self.buyer()?.to_string();
+        let history = self.purchase_history.clone().ok_or("Restoring purchases is not available")?;
+        let (licensor, _) = self.licensing.as_ref().ok_or("Purchases are not available")?;
+        let restored = self.store.entitlements.restore(licensor, &buyer, history(&buyer)?);
+        self.save_entitlements()?;
+        println!("{}", self.i18n.trn("Restored {count} purchase", "Restored {count} purchases", restored.len() as u64, &[]));
+        for entitlement in restored {
+            let name = self.store.get_app(&entitlement.app).map_or(entitlement.app.clone(), |a| a.name.clone());
+            println!("  {} ({})", name, entitlement.purchased_at);
+        }
+        Ok(())
+    }
+
+    fn publisher_mut(&mut self) -> Result<&mut Publisher, String> {
+        self.publisher.as_mut().ok_or_else(|| "Publishing is not available".to_string())
+    }
+
+    fn save_submissions(&self) -> Result<(), String> {
+        match (&self.vfs, &self.publisher) {
+            (Some(vfs), Some(publisher)) => publisher.save(vfs),
+            _ => Ok(()),
+        }
+    }
+
+    /// List a published app, replacing the listing of an earlier version
+    fn list_app(&mut self, mut listing: AppListing) {
+        if let Some(old) = self.store.remove_app(&listing.id) {
+            listing.installed = old.installed;
+        }
+        self.store.add_app(listing);
+    }
+
+    /// Thumbnails of an app's screenshots at one of the thumbnail widths,
+    /// read from storage the first time
+    fn show_screenshots(&self, app_id: &str, width: u32) -> Result<(), String> {
+        let app = self.store.get_app(app_id).ok_or_else(|| format!("App not found: {}", app_id))?;
+        if !THUMBNAIL_WIDTHS.contains(&width) {
+            return Err(format!("Thumbnails are {:?} pixels wide", THUMBNAIL_WIDTHS));
+        }
+        println!("\n{}", self.heading(&self.i18n.tr_args("Screenshots of {app}:", &[("app", &app.name)])));
+        println!("{:-<80}", "");
+        if app.screenshots.is_empty() {
+            println!("  {}", self.i18n.tr("No screenshots"));
+        }
+        let media = self.media.as_ref().ok_or("No storage to read screenshots from")?;
+        for index in 0..app.screenshots.len() {
+            let path = media::thumbnail_path(&app.id, index, width);
+            let thumbnail = media.load(&path)?;
+            println!("  {} ({}x{})", path.display(), thumbnail.width, thumbnail.height);
+        }
+        println!();
+        Ok(())
+    }
+
+    fn show_submissions(&self) -> Result<(), String> {
+        let publisher = self.publisher.as_ref().ok_or("Publishing is not available")?;
+        println!("\n{}", self.heading(&self.i18n.tr("Submissions:")));
+        println!("{:-<80}", "");
+        for (id, submissions) in publisher.submissions() {
+            let mut states = Vec::new();
+            if let Some(published) = &submissions.published {
+                states.push(self.i18n.tr_args("{version} published", &[("version", &published.manifest.version)]));
+            }
+            if let Some(draft) = &submissions.draft {
+                states.push(self.i18n.tr_args("{version} draft", &[("version", &draft.manifest.version)]));
+            }
+            println!("  {} - {}", id, states.join(", "));
+        }
+        println!();
+        Ok(())
+    }
+
+    fn save_entitlements(&self) -> Result<(), String> {
+        match &self.vfs {
+            Some(vfs) => self.store.entitlements.save(vfs),
+            None => Ok(()),
         }
     }
 
     fn show_all_apps(&self) {
         let apps = self.store.get_all();
-        println!("\nAll Available Apps ({} total):", apps.len());
+        let total = self.i18n.trn("{count} app", "{count} apps", apps.len() as u64, &[]);
+        println!("\n{}", self.heading(&self.i18n.tr_args("All Available Apps ({total}):", &[("total", &total)])));
         println!("{:-<80}", "");
         
         for app in apps {
@@ -482,9 +1506,18 @@ impl AppStoreCLI {
         println!();
     }
 
+    /// Price in the user's number format, or "Free"
+    fn price_label(&self, app: &AppListing) -> String {
+        if app.is_free() {
+            self.i18n.tr("Free")
+        } else {
+            format!("${}", self.i18n.format_number(app.price as f64, 2))
+        }
+    }
+
     fn print_app_summary(&self, app: &AppListing) {
-        let price = if app.is_free() { "Free".to_string() } else { format!("${:.2}", app.price) };
-        let rating = if let Some(r) = app.rating {
+        let price = self.price_label(app);
+        let rating = if let Some(r) = self.store.rating(app) {
             format!("⭐ {:.1}", r.stars)
         } else {
             "N/A".to_string()
@@ -498,6 +1531,18 @@ impl AppStoreCLI {
     }
 }
 
+impl Default for AppStoreCLI {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+/// Seconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
 fn main() {
     let mut cli = AppStoreCLI::new();
     cli.run();
@@ -543,4 +1588,370 @@ mod tests {
         let app = store.get_app(app_id).unwrap();
         assert!(app.installed);
     }
+
+    #[test]
+    fn test_updates_tab() {
+        let mut cli = AppStoreCLI::new();
+        assert!(cli.handle_command("updates").is_ok());
+        assert!(cli.store.get_updates().is_empty());
+
+        let listed = Arc::new(std::sync::Mutex::new(
+            br#"[{"id":"web-browser","name":"Web Browser","installed":"2.1.5","available":"2.2.0"}]"#.to_vec(),
+        ));
+        let source = Arc::clone(&listed);
+        cli.set_update_source(Arc::new(move || Ok(source.lock().unwrap().clone())));
+        assert!(cli.handle_command("updates").is_ok());
+        assert_eq!(cli.store.get_updates()[0].available, "2.2.0");
+
+        *listed.lock().unwrap() = b"[]".to_vec();
+        assert!(cli.handle_command("updates").is_ok());
+        assert!(cli.store.get_updates().is_empty());
+        *listed.lock().unwrap() = b"not json".to_vec();
+        assert!(cli.handle_command("updates").is_err());
+    }
+
+    /// Package manager keeping its installed set in `installed`
+    fn fake_package_manager(installed: Arc<std::sync::Mutex<HashSet<String>>>) -> PackageTransport {
+        Arc::new(move |request: Message, on_event: &dyn Fn(&Message)| {
+            let Message::Request { id, data } = request else {
+                return Err("expected a request".to_string());
+            };
+            let request: serde_json::Value = serde_json::from_slice(&data).unwrap();
+            let app = request["id"].as_str().unwrap_or_default().to_string();
+            let mut installed = installed.lock().unwrap();
+            let response = match request["op"].as_str().unwrap() {
+                "list" => serde_json::json!({"result": "installed", "packages": installed.iter()
+                    .map(|id| serde_json::json!({"id": id, "name": id, "version": "1.0.0"})).collect::<Vec<_>>()}),
+                "install" => {
+                    on_event(&Message::Text(format!(r#"{{"event":"installing","id":"{}","version":"1.0.0"}}"#, app)));
+                    installed.insert(app);
+                    serde_json::json!({"result": "ok"})
+                }
+                "uninstall" if installed.remove(&app) => serde_json::json!({"result": "ok"}),
+                "update" if installed.contains(&app) => serde_json::json!({"result": "ok"}),
+                _ => serde_json::json!({"result": "error", "message": "Package not installed"}),
+            };
+            Ok(Message::Response { id, data: serde_json::to_vec(&response).unwrap() })
+        })
+    }
+
+    #[test]
+    fn test_install_asks_for_permissions() {
+        let mut cli = AppStoreCLI::new();
+        let installed = Arc::new(std::sync::Mutex::new(HashSet::new()));
+        assert!(cli.handle_command("install media-player").is_ok());
+        cli.set_package_service(fake_package_manager(Arc::clone(&installed)));
+        let asked = Arc::new(std::sync::Mutex::new(0));
+        let answer = Arc::new(std::sync::Mutex::new(false));
+        let (count, given) = (Arc::clone(&asked), Arc::clone(&answer));
+        cli.set_prompt(Arc::new(move |_: &str| {
+            *count.lock().unwrap() += 1;
+            *given.lock().unwrap()
+        }));
+
+        assert!(cli.handle_command("install media-player").is_ok());
+        assert!(installed.lock().unwrap().is_empty());
+        assert!(!cli.store.get_app("media-player").unwrap().installed);
+        *answer.lock().unwrap() = true;
+        assert!(cli.handle_command("install media-player").is_ok());
+        assert!(installed.lock().unwrap().contains("media-player"));
+        assert!(cli.store.get_app("media-player").unwrap().installed);
+
+        // Nothing to confirm for apps that need no access
+        assert!(cli.handle_command("install text-editor").is_ok());
+        assert_eq!(*asked.lock().unwrap(), 2);
+        assert!(cli.handle_command("install missing").is_err());
+    }
+
+    #[test]
+    fn test_installed_state_follows_package_database() {
+        let mut cli = AppStoreCLI::new();
+        let installed = Arc::new(std::sync::Mutex::new(HashSet::from(["file-manager".to_string()])));
+        cli.store.mark_installed("text-editor").unwrap();
+        cli.set_package_service(fake_package_manager(Arc::clone(&installed)));
+        cli.sync_installed().unwrap();
+        assert!(cli.store.get_app("file-manager").unwrap().installed);
+        assert!(!cli.store.get_app("text-editor").unwrap().installed);
+
+        assert!(cli.handle_command("uninstall file-manager").is_ok());
+        assert!(!cli.store.get_app("file-manager").unwrap().installed);
+        assert_eq!(cli.handle_command("uninstall file-manager"), Err("Package not installed".to_string()));
+        // Installed behind the store's back
+        installed.lock().unwrap().insert("chrysalis".to_string());
+        assert!(cli.handle_command("update file-manager").is_err());
+        assert!(cli.store.get_app("chrysalis").unwrap().installed);
+    }
+
+    #[test]
+    fn test_update_all_skips_deferred() {
+        let mut cli = AppStoreCLI::new();
+        let vfs = Arc::new(VirtualFileSystem::new());
+        cli.attach_storage(Arc::clone(&vfs)).unwrap();
+        let installed = Arc::new(std::sync::Mutex::new(HashSet::from(["text-editor".to_string(), "code-studio".to_string()])));
+        cli.set_package_service(fake_package_manager(Arc::clone(&installed)));
+        let listed = Arc::new(std::sync::Mutex::new(
+            br#"[{"id":"text-editor","name":"Text Editor","installed":"1.0.0","available":"1.2.0",
+                  "changelog":[{"version":"1.2.0","notes":"Split view"},{"version":"1.1.0","notes":"Faster search"}]},
+                 {"id":"code-studio","name":"Code Studio","installed":"1.0.0","available":"1.1.0"},
+                 {"id":"web-browser","name":"Web Browser","installed":"2.1.5","available":"2.2.0"}]"#
+                .to_vec(),
+        ));
+        let source = Arc::clone(&listed);
+        cli.set_update_source(Arc::new(move || Ok(source.lock().unwrap().clone())));
+        assert!(cli.handle_command("updates").is_ok());
+        assert_eq!(cli.store.get_update("text-editor").unwrap().changelog[1].notes, "Faster search");
+        assert!(cli.handle_command("changelog text-editor").is_ok());
+        assert!(cli.handle_command("changelog file-manager").is_err());
+
+        assert!(cli.handle_command("defer code-studio").is_ok());
+        assert!(DeferredUpdates::load(&vfs).unwrap().is_deferred(cli.store.get_update("code-studio").unwrap()));
+        // The browser is not installed, so its update fails without stopping the rest
+        assert_eq!(cli.handle_command("update-all"), Err("1 update failed".to_string()));
+        assert!(cli.store.get_update("text-editor").is_none());
+        assert!(cli.store.get_update("code-studio").is_some());
+
+        assert!(cli.handle_command("update code-studio").is_ok());
+        assert!(cli.store.get_update("code-studio").is_none());
+        assert_eq!(DeferredUpdates::load(&vfs).unwrap(), DeferredUpdates::default());
+    }
+
+    #[test]
+    fn test_priced_apps_need_entitlement() {
+        let keystore = Arc::new(keystore::Keystore::new());
+        let key = keystore::KeyId::from("store-key");
+        keystore.generate_key(key.clone(), keystore::KeyType::Ed25519, vec![keystore::KeyUsage::Sign, keystore::KeyUsage::Verify], false).unwrap();
+        keystore.create_identity("did:hairr:store".to_string(), &key).unwrap();
+        let licensor = || Licensor::new(Arc::clone(&keystore), "did:hairr:store".to_string(), key.clone());
+        let charges = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let record = Arc::clone(&charges);
+        let payments: PaymentProcessor = Arc::new(move |did: &str, app: &str, price: f32| {
+            record.lock().unwrap().push((did.to_string(), app.to_string(), price));
+            Ok(format!("rcpt-{}", app))
+        });
+
+        let vfs = Arc::new(VirtualFileSystem::new());
+        let mut cli = AppStoreCLI::new();
+        cli.attach_storage(Arc::clone(&vfs)).unwrap();
+        cli.set_package_service(fake_package_manager(Arc::new(std::sync::Mutex::new(HashSet::new()))));
+        cli.set_prompt(Arc::new(|_: &str| true));
+        assert_eq!(cli.handle_command("install code-studio"), Err("Purchases are not available".to_string()));
+        cli.set_licensing(licensor(), Arc::clone(&payments));
+        assert!(cli.handle_command("buy code-studio").unwrap_err().contains("decentralized identity"));
+        cli.set_identity("did:hairr:alice".to_string());
+        assert_eq!(cli.handle_command("install code-studio"), Err("Buy Code Studio before installing it".to_string()));
+        cli.handle_command("buy code-studio").unwrap();
+        cli.handle_command("buy code-studio").unwrap();
+        assert_eq!(charges.lock().unwrap().len(), 1);
+        cli.handle_command("install code-studio").unwrap();
+        assert!(cli.store.get_app("code-studio").unwrap().installed);
+
+        // A second device starts without the entitlement and restores it
+        assert!(EntitlementStore::load(&vfs).unwrap().find(&licensor(), "did:hairr:alice", "code-studio").is_some());
+        let mut device = AppStoreCLI::new();
+        device.set_package_service(fake_package_manager(Arc::new(std::sync::Mutex::new(HashSet::new()))));
+        device.set_licensing(licensor(), payments);
+        device.set_identity("did:hairr:alice".to_string());
+        assert!(device.handle_command("install code-studio").is_err());
+        assert!(device.handle_command("restore-purchases").is_err());
+        let remote = licensor();
+        device.set_purchase_history(Arc::new(move |did: &str| Ok(vec![remote.issue(did, "code-studio", 29.99, "rcpt-code-studio")?])));
+        device.handle_command("restore-purchases").unwrap();
+        device.handle_command("install code-studio").unwrap();
+        assert_eq!(charges.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_ipc_transport() {
+        let ipc = Arc::new(IPCManager::new());
+        let (requests, replies) = (ipc.create_channel(), ipc.create_channel());
+        let server = Arc::clone(&ipc);
+        let handle = thread::spawn(move || loop {
+            if let Some(Message::Request { id, .. }) = server.receive_message(requests).unwrap() {
+                server.send_message(replies, Message::Response { id: id + 100, data: Vec::new() }).unwrap();
+                server.send_message(replies, Message::Text(r#"{"event":"removing","id":"chrysalis"}"#.to_string())).unwrap();
+                server.send_message(replies, Message::Response { id, data: br#"{"result":"ok"}"#.to_vec() }).unwrap();
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        });
+        let transport = ipc_transport(Arc::clone(&ipc), requests, replies, Duration::from_secs(5));
+        let events = std::sync::Mutex::new(0);
+        let reply = transport(Message::Request { id: 7, data: Vec::new() }, &|_| *events.lock().unwrap() += 1).unwrap();
+        handle.join().unwrap();
+        assert!(matches!(reply, Message::Response { id: 7, .. }));
+        assert_eq!(*events.lock().unwrap(), 2);
+
+        let silent = ipc_transport(ipc, requests, replies, Duration::from_millis(20));
+        assert!(silent(Message::Request { id: 8, data: Vec::new() }, &|_| {}).is_err());
+    }
+
+    #[test]
+    fn test_review_commands() {
+        let capabilities = Arc::new(capability::CapabilityManager::new());
+        let users = Arc::new(UserService::new(Arc::new(keystore::Keystore::new()), capabilities).unwrap());
+        for name in ["alice", "bob", "carol", "dave"] {
+            users.create_user(name, "secret").unwrap();
+        }
+        users.create_group(MODERATORS_GROUP).unwrap();
+        users.add_to_group("dave", MODERATORS_GROUP).unwrap();
+        let vfs = Arc::new(VirtualFileSystem::new());
+        let mut cli = AppStoreCLI::new();
+        cli.attach_storage(Arc::clone(&vfs)).unwrap();
+        let synced = Arc::new(std::sync::Mutex::new(0));
+        let count = Arc::clone(&synced);
+        cli.set_review_sync(Arc::new(move |_: &reviews::Review| {
+            *count.lock().unwrap() += 1;
+            Ok(())
+        }));
+
+        assert_eq!(cli.handle_command("review code-studio 5 Great"), Err("Sign in to review apps".to_string()));
+        cli.set_session(Arc::clone(&users), users.login("alice", "secret").unwrap());
+        assert!(cli.handle_command("review code-studio 9").is_err());
+        cli.handle_command("review code-studio 1 Spam spam spam").unwrap();
+        let seeded = cli.store.get_app("code-studio").unwrap().clone();
+        assert_eq!(cli.store.rating(&seeded).unwrap().count, 5401);
+        assert!(cli.handle_command("moderate").is_err());
+
+        for name in ["bob", "carol", "dave"] {
+            cli.set_session(Arc::clone(&users), users.login(name, "secret").unwrap());
+            cli.handle_command("flag 1").unwrap();
+        }
+        assert_eq!(cli.store.rating(&seeded).unwrap().count, 5400);
+        assert!(cli.handle_command("moderate").is_ok());
+        cli.handle_command("moderate 1 restore").unwrap();
+        assert_eq!(cli.store.rating(&seeded).unwrap().count, 5401);
+        assert!(cli.handle_command("reviews code-studio lowest 1").is_ok());
+        assert!(cli.handle_command("reviews code-studio loudest").is_err());
+        assert_eq!(*synced.lock().unwrap(), 5);
+
+        let mut restarted = AppStoreCLI::new();
+        restarted.attach_storage(vfs).unwrap();
+        assert_eq!(restarted.store.reviews, cli.store.reviews);
+    }
+
+    #[test]
+    fn test_curation_commands() {
+        let users = Arc::new(UserService::new(Arc::new(keystore::Keystore::new()), Arc::new(capability::CapabilityManager::new())).unwrap());
+        users.create_user("alice", "secret").unwrap();
+        users.create_user("dave", "secret").unwrap();
+        users.create_group(MODERATORS_GROUP).unwrap();
+        users.add_to_group("dave", MODERATORS_GROUP).unwrap();
+        let vfs = Arc::new(VirtualFileSystem::new());
+        let mut cli = AppStoreCLI::new();
+        cli.attach_storage(Arc::clone(&vfs)).unwrap();
+
+        let picks: Vec<&str> = cli.store.get_editorial().iter().map(|a| a.id.as_str()).collect();
+        assert_eq!(picks, vec!["hairr-messenger", "text-editor", "media-player", "file-manager"]);
+        assert_eq!(cli.store.get_collection("dev-essentials").unwrap()[0].id, "code-studio");
+        assert!(cli.store.get_collection("games").is_none());
+        assert!(cli.handle_command("collection games").is_err());
+        assert!(cli.store.get_recommended(RECOMMENDATIONS).is_empty());
+
+        cli.store.mark_installed("text-editor").unwrap();
+        let recommended: Vec<&str> = cli.store.get_recommended(RECOMMENDATIONS).iter().map(|a| a.id.as_str()).collect();
+        assert_eq!(recommended, vec!["code-studio", "file-manager"]);
+        assert!(cli.handle_command("recommended").is_ok());
+
+        cli.set_session(Arc::clone(&users), users.login("alice", "secret").unwrap());
+        assert!(cli.handle_command("curate editorial add chrysalis 1").is_err());
+        cli.set_session(Arc::clone(&users), users.login("dave", "secret").unwrap());
+        cli.handle_command("curate editorial add chrysalis 1").unwrap();
+        cli.handle_command("curate dev-essentials remove file-manager").unwrap();
+        assert!(cli.handle_command("curate dev-essentials remove file-manager").is_err());
+        assert!(cli.handle_command("curate dev-essentials add missing-app").is_err());
+        assert_eq!(cli.store.get_editorial()[0].id, "chrysalis");
+
+        let mut restarted = AppStoreCLI::new();
+        restarted.attach_storage(vfs).unwrap();
+        assert_eq!(restarted.store.curation, cli.store.curation);
+        assert_eq!(restarted.store.get_featured()[0].id, "code-studio");
+    }
+
+    #[test]
+    fn test_publishing_commands() {
+        let keystore = Arc::new(keystore::Keystore::new());
+        let key = keystore::KeyId::from("devtools");
+        keystore.generate_key(key.clone(), keystore::KeyType::Ed25519, vec![keystore::KeyUsage::Sign, keystore::KeyUsage::Verify], false).unwrap();
+        let users = Arc::new(UserService::new(Arc::clone(&keystore), Arc::new(capability::CapabilityManager::new())).unwrap());
+        users.create_user("alice", "secret").unwrap();
+        users.create_user("dave", "secret").unwrap();
+        users.create_group(MODERATORS_GROUP).unwrap();
+        users.add_to_group("dave", MODERATORS_GROUP).unwrap();
+        let vfs = Arc::new(VirtualFileSystem::new());
+        let mut cli = AppStoreCLI::new();
+        cli.attach_storage(Arc::clone(&vfs)).unwrap();
+        assert!(cli.handle_command("submissions").is_err());
+        cli.attach_publishing(Arc::clone(&keystore)).unwrap();
+
+        let manifest: publishing::AppManifest = serde_json::from_value(serde_json::json!({
+            "id": "code-studio", "name": "Code Studio", "version": "2.0.0", "developer": "DevTools Inc",
+            "description": "Professional IDE", "category": "development", "price": 29.99,
+        }))
+        .unwrap();
+        let archive = vec![7; 100];
+        let screenshots = vec![media::Image { width: 640, height: 360, pixels: [40, 80, 120, 255].repeat(640 * 360) }.to_qoi()];
+        let signature = keystore.sign(&key, &AppBundle::signed_data(&manifest, &archive, &screenshots)).unwrap();
+        let bundle = AppBundle { manifest, archive, screenshots, signature };
+        vfs.write_file(Path::new("/home/alice/code-studio.bundle"), &serde_json::to_vec(&bundle).unwrap()).unwrap();
+
+        cli.set_session(Arc::clone(&users), users.login("alice", "secret").unwrap());
+        assert!(cli.handle_command("register-developer devtools DevTools Inc").is_err());
+        assert!(cli.handle_command("submit /home/alice/code-studio.bundle").unwrap_err().contains("not a registered developer"));
+        cli.set_session(Arc::clone(&users), users.login("dave", "secret").unwrap());
+        cli.handle_command("register-developer devtools DevTools Inc").unwrap();
+        cli.handle_command("submit /home/alice/code-studio.bundle").unwrap();
+        assert_eq!(cli.store.get_app("code-studio").unwrap().version, "1.0.0");
+        cli.handle_command("publish code-studio").unwrap();
+        assert_eq!(cli.store.get_app("code-studio").unwrap().version, "2.0.0");
+        assert_eq!(cli.store.get_by_category(AppCategory::Development).len(), 1);
+        assert!(cli.store.get_featured().iter().any(|a| a.id == "code-studio"));
+        assert_eq!(cli.store.get_app("code-studio").unwrap().screenshots.len(), 1);
+        assert_eq!(cli.media.as_ref().unwrap().cached(), 0);
+        cli.handle_command("screenshots code-studio 480").unwrap();
+        assert_eq!(cli.media.as_ref().unwrap().cached(), 1);
+        assert!(cli.handle_command("screenshots code-studio 100").is_err());
+
+        let mut restarted = AppStoreCLI::new();
+        restarted.attach_storage(Arc::clone(&vfs)).unwrap();
+        restarted.attach_publishing(Arc::clone(&keystore)).unwrap();
+        assert_eq!(restarted.store.get_app("code-studio").unwrap().version, "2.0.0");
+        restarted.handle_command("screenshots code-studio").unwrap();
+        restarted.handle_command("unpublish code-studio").unwrap();
+        assert!(restarted.store.get_app("code-studio").is_none());
+        assert!(!vfs.exists(&media::screenshot_path("code-studio", 0)));
+        assert!(restarted.handle_command("submissions").is_ok());
+    }
+
+    #[test]
+    fn test_localized_price() {
+        let i18n = Arc::new(Localizer::new());
+        let mut catalog = i18n::Catalog::new(Locale::parse("de").unwrap());
+        catalog.insert("Free", i18n::Translation::Text("Kostenlos".to_string()));
+        i18n.add_catalog(catalog);
+        let mut cli = AppStoreCLI::with_localizer(Arc::clone(&i18n));
+
+        let mut app = AppListing::new("suite".to_string(), "Suite".to_string(), "hairr".to_string(), AppCategory::Productivity);
+        assert_eq!(cli.price_label(&app), "Free");
+        app.price = 1234.5;
+        assert_eq!(cli.price_label(&app), "$1,234.50");
+        assert!(cli.handle_command("locale de-DE").is_ok());
+        assert_eq!(cli.price_label(&app), "$1.234,50");
+        app.price = 0.0;
+        assert_eq!(cli.price_label(&app), "Kostenlos");
+    }
+
+    #[test]
+    fn test_headings_follow_mode() {
+        let theme = Arc::new(ThemeManager::new());
+        let mut cli = AppStoreCLI::new();
+        cli.attach_theme(Arc::clone(&theme));
+        let light = theme.appearance().theme.light.accent;
+        assert_eq!(cli.heading("Apps"), format!("{}Apps\x1b[0m", light.ansi_foreground()));
+
+        assert!(cli.handle_command("mode dark").is_ok());
+        assert_eq!(theme.mode(), Mode::Dark);
+        let dark = theme.appearance().theme.dark.accent;
+        assert!(cli.heading("Apps").starts_with(&dark.ansi_foreground()));
+        assert!(cli.handle_command("mode dim").is_err());
+    }
 }
@@ -0,0 +1,382 @@
+//! Store media
+//!
+//! Screenshots come with a submission as QOI or binary PPM images. Each is
+//! checked for its format and dimensions, then kept in the VFS as QOI
+//! next to thumbnails scaled down to fixed widths when the app is
+//! published. Listings only carry the paths; the image data is read the
+//! first time something shows it and cached from then on.
+//!
+//! ```text
+//! /var/lib/app-store/media/viewer/0.qoi
+//! /var/lib/app-store/media/viewer/0-160.qoi
+//! /var/lib/app-store/media/viewer/0-480.qoi
+//! ```
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use filesystem::VirtualFileSystem;
+
+
+/// Screenshots and thumbnails of every published app
+pub const MEDIA_DIR: &str = "/var/lib/app-store/media";
+
+/// Widths thumbnails are made at
+pub const THUMBNAIL_WIDTHS: &[u32] = &[160, 480];
+
+/// Smallest and largest screenshot, in pixels
+pub const MIN_DIMENSIONS: (u32, u32) = (320, 240);
+pub const MAX_DIMENSIONS: (u32, u32) = (3840, 2160);
+
+/// Most screenshots one app may have
+pub const MAX_SCREENSHOTS: usize = 8;
+
+/// Largest screenshot file
+pub const MAX_MEDIA_BYTES: usize = 16 * 1024 * 1024;
+
+const QOI_MAGIC: &[u8; 4] = b"qoif";
+const QOI_END: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
+/// An RGBA image, rows top to bottom
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    /// Decode a QOI or binary PPM file
+    pub fn decode(data: &[u8]) -> Result<Self, String> {
+        if data.starts_with(QOI_MAGIC) {
+            decode_qoi(data)
+        } else if data.starts_with(b"P6") {
+            decode_ppm(data)
+        } else {
+            Err("Unsupported image format; use QOI or binary PPM".to_string())
+        }
+    }
+
+    pub fn to_qoi(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(22 + self.pixels.len() / 4);
+        out.extend_from_slice(QOI_MAGIC);
+        out.extend_from_slice(&self.width.to_be_bytes());
+        out.extend_from_slice(&self.height.to_be_bytes());
+        out.extend_from_slice(&[4, 0]);
+
+        let mut index = [[0u8; 4]; 64];
+        let mut prev = [0, 0, 0, 255];
+        let mut run = 0u8;
+        for chunk in self.pixels.chunks_exact(4) {
+            let px: [u8; 4] = chunk.try_into().unwrap();
+            if px == prev {
+                run += 1;
+                if run == 62 {
+                    out.push(0xc0 | (run - 1));
+                    run = 0;
+                }
+                continue;
+            }
+            if run > 0 {
+                out.push(0xc0 | (run - 1));
+                run = 0;
+            }
+            let hash = qoi_hash(px);
+            if index[hash] == px {
+                out.push(hash as u8);
+            } else {
+                index[hash] = px;
+                if px[3] == prev[3] {
+                    let dr = px[0].wrapping_sub(prev[0]) as i8;
+                    let dg = px[1].wrapping_sub(prev[1]) as i8;
+                    let db = px[2].wrapping_sub(prev[2]) as i8;
+                    let (dr_dg, db_dg) = (dr.wrapping_sub(dg), db.wrapping_sub(dg));
+                    if (-2..=1).contains(&dr) && (-2..=1).contains(&dg) && (-2..=1).contains(&db) {
+                        out.push(0x40 | ((dr + 2) as u8) << 4 | ((dg + 2) as u8) << 2 | (db + 2) as u8);
+                    } else if (-32..=31).contains(&dg) && (-8..=7).contains(&dr_dg) && (-8..=7).contains(&db_dg) {
+                        out.push(0x80 | (dg + 32) as u8);
+                        out.push(((dr_dg + 8) as u8) << 4 | (db_dg + 8) as u8);
+                    } else {
+                        out.extend_from_slice(&[0xfe, px[0], px[1], px[2]]);
+                    }
+                } else {
+                    out.push(0xff);
+                    out.extend_from_slice(&px);
+                }
+            }
+            prev = px;
+        }
+        if run > 0 {
+            out.push(0xc0 | (run - 1));
+        }
+        out.extend_from_slice(&QOI_END);
+        out
+    }
+
+    /// Scaled down to `width`, keeping the aspect ratio; images already
+    /// that narrow are kept as they are
+    pub fn thumbnail(&self, width: u32) -> Image {
+        if width >= self.width {
+            return self.clone();
+        }
+        let height = ((self.height as u64 * width as u64 / self.width as u64) as u32).max(1);
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+        for y in 0..height {
+            let (y0, y1) = span(y, height, self.height);
+            for x in 0..width {
+                let (x0, x1) = span(x, width, self.width);
+                let mut sum = [0u64; 4];
+                for sy in y0..y1 {
+                    for sx in x0..x1 {
+                        let start = (sy as usize * self.width as usize + sx as usize) * 4;
+                        for (total, value) in sum.iter_mut().zip(&self.pixels[start..start + 4]) {
+                            *total += *value as u64;
+                        }
+                    }
+                }
+                let count = ((y1 - y0) * (x1 - x0)) as u64;
+                pixels.extend(sum.iter().map(|total| (total / count) as u8));
+            }
+        }
+        Image { width, height, pixels }
+    }
+}
+
+/// Source rows or columns averaged into target `i` of `target`
+fn span(i: u32, target: u32, source: u32) -> (u32, u32) {
+    let start = (i as u64 * source as u64 / target as u64) as u32;
+    let end = ((i as u64 + 1) * source as u64 / target as u64) as u32;
+    (start, end.max(start + 1))
+}
+
+fn qoi_hash(px: [u8; 4]) -> usize {
+    (px[0] as usize * 3 + px[1] as usize * 5 + px[2] as usize * 7 + px[3] as usize * 11) % 64
+}
+
+/// Pixel count of a `width` by `height` image, if it is within limits
+fn pixel_count(width: u32, height: u32) -> Result<usize, String> {
+    if width == 0 || height == 0 || width > MAX_DIMENSIONS.0 * 2 || height > MAX_DIMENSIONS.1 * 2 {
+        return Err(format!("Unreasonable image size {}x{}", width, height));
+    }
+    Ok(width as usize * height as usize)
+}
+
+fn decode_qoi(data: &[u8]) -> Result<Image, String> {
+    let header = data.get(..14).ok_or("Truncated QOI header")?;
+    let width = u32::from_be_bytes(header[4..8].try_into().unwrap());
+    let height = u32::from_be_bytes(header[8..12].try_into().unwrap());
+    let count = pixel_count(width, height)?;
+
+    let mut pixels = Vec::with_capacity(count * 4);
+    let mut index = [[0u8; 4]; 64];
+    let mut px = [0, 0, 0, 255];
+    let mut pos = 14;
+    let byte = |pos: usize| data.get(pos).copied().ok_or_else(|| "Truncated QOI data".to_string());
+    while pixels.len() < count * 4 {
+        let op = byte(pos)?;
+        pos += 1;
+        let mut run = 1;
+        match op {
+            0xfe => {
+                px[..3].copy_from_slice(data.get(pos..pos + 3).ok_or("Truncated QOI data")?);
+                pos += 3;
+            }
+            0xff => {
+                px.copy_from_slice(data.get(pos..pos + 4).ok_or("Truncated QOI data")?);
+                pos += 4;
+            }
+            _ => match op >> 6 {
+                0 => px = index[op as usize],
+                1 => {
+                    px[0] = px[0].wrapping_add((op >> 4) & 3).wrapping_sub(2);
+                    px[1] = px[1].wrapping_add((op >> 2) & 3).wrapping_sub(2);
+                    px[2] = px[2].wrapping_add(op & 3).wrapping_sub(2);
+                }
+                2 => {
+                    let second = byte(pos)?;
+                    pos += 1;
+                    let dg = (op & 0x3f).wrapping_sub(32);
+                    px[0] = px[0].wrapping_add(dg).wrapping_add(second >> 4).wrapping_sub(8);
+                    px[1] = px[1].wrapping_add(dg);
+                    px[2] = px[2].wrapping_add(dg).wrapping_add(second & 0x0f).wrapping_sub(8);
+                }
+                _ => run = (op & 0x3f) as usize + 1,
+            },
+        }
+        index[qoi_hash(px)] = px;
+        for _ in 0..run.min(count - pixels.len() / 4) {
+            pixels.extend_from_slice(&px);
+        }
+    }
+    if data.get(pos..pos + 8) != Some(&QOI_END[..]) {
+        return Err("QOI data does not end where it should".to_string());
+    }
+    Ok(Image { width, height, pixels })
+}
+
+fn decode_ppm(data: &[u8]) -> Result<Image, String> {
+    // Magic, width, height and maximum value, separated by whitespace and
+    // comments, then a single whitespace byte before the pixels
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    while fields.len() < 4 {
+        while data.get(pos).is_some_and(|b| b.is_ascii_whitespace()) {
+            pos += 1;
+        }
+        if data.get(pos) == Some(&b'#') {
+            while data.get(pos).is_some_and(|b| *b != b'\n') {
+                pos += 1;
+            }
+            continue;
+        }
+        let start = pos;
+        while data.get(pos).is_some_and(|b| !b.is_ascii_whitespace()) {
+            pos += 1;
+        }
+        if start == pos {
+            return Err("Truncated PPM header".to_string());
+        }
+        fields.push(std::str::from_utf8(&data[start..pos]).unwrap_or_default());
+    }
+    let number = |field: &str| field.parse::<u32>().map_err(|_| format!("Invalid PPM header field {:?}", field));
+    let (width, height) = (number(fields[1])?, number(fields[2])?);
+    if number(fields[3])? != 255 {
+        return Err("Only 8-bit PPM images are supported".to_string());
+    }
+    let count = pixel_count(width, height)?;
+    let body = data.get(pos + 1..pos + 1 + count * 3).ok_or("Truncated PPM data")?;
+    let pixels = body.chunks_exact(3).flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255]).collect();
+    Ok(Image { width, height, pixels })
+}
+
+/// Decode a submitted screenshot and check it is usable
+pub fn validate(data: &[u8]) -> Result<Image, String> {
+    if data.len() > MAX_MEDIA_BYTES {
+        return Err(format!("Screenshot is larger than {} MB", MAX_MEDIA_BYTES / (1024 * 1024)));
+    }
+    let image = Image::decode(data)?;
+    let (min, max) = (MIN_DIMENSIONS, MAX_DIMENSIONS);
+    if image.width < min.0 || image.height < min.1 || image.width > max.0 || image.height > max.1 {
+        return Err(format!(
+            "Screenshot is {}x{}, not between {}x{} and {}x{}",
+            image.width, image.height, min.0, min.1, max.0, max.1
+        ));
+    }
+    Ok(image)
+}
+
+pub fn screenshot_path(app: &str, index: usize) -> PathBuf {
+    Path::new(MEDIA_DIR).join(app).join(format!("{}.qoi", index))
+}
+
+pub fn thumbnail_path(app: &str, index: usize, width: u32) -> PathBuf {
+    Path::new(MEDIA_DIR).join(app).join(format!("{}-{}.qoi", index, width))
+}
+
+/// Screenshots in the VFS, read when first shown
+pub struct MediaStore {
+    vfs: Arc<VirtualFileSystem>,
+    cache: Mutex<HashMap<PathBuf, Arc<Image>>>,
+}
+
+impl MediaStore {
+    pub fn new(vfs: Arc<VirtualFileSystem>) -> Self {
+        MediaStore {
+            vfs,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Keep an app's screenshots with their thumbnails, replacing those
+    /// it had; returns their paths
+    pub fn store(&self, app: &str, screenshots: &[Vec<u8>]) -> Result<Vec<String>, String> {
+        self.remove(app);
+        let mut paths = Vec::new();
+        for (index, data) in screenshots.iter().enumerate() {
+            let image = validate(data)?;
+            let path = screenshot_path(app, index);
+            self.vfs.write_file(&path, &image.to_qoi())?;
+            for width in THUMBNAIL_WIDTHS {
+                self.vfs.write_file(&thumbnail_path(app, index, *width), &image.thumbnail(*width).to_qoi())?;
+            }
+            paths.push(path.to_string_lossy().into_owned());
+        }
+        Ok(paths)
+    }
+
+    /// Delete an app's screenshots
+    pub fn remove(&self, app: &str) {
+        let dir = Path::new(MEDIA_DIR).join(app);
+        for path in self.vfs.list_directory(&dir).unwrap_or_default() {
+            let _ = self.vfs.delete(&path);
+        }
+        self.cache.lock().unwrap().retain(|path, _| !path.starts_with(&dir));
+    }
+
+    /// Image at `path`, from the cache once it has been read
+    pub fn load(&self, path: &Path) -> Result<Arc<Image>, String> {
+        if let Some(image) = self.cache.lock().unwrap().get(path) {
+            return Ok(Arc::clone(image));
+        }
+        let image = Arc::new(Image::decode(&self.vfs.read_file(path)?)?);
+        self.cache.lock().unwrap().insert(path.to_path_buf(), Arc::clone(&image));
+        Ok(image)
+    }
+
+    /// Images read so far
+    #[cfg(test)]
+    pub fn cached(&self) -> usize {
+        self.cache.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: u32, height: u32) -> Image {
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).flat_map(move |x| [(x % 256) as u8, (y % 256) as u8, ((x + y) / 8 % 256) as u8, 255]))
+            .collect();
+        Image { width, height, pixels }
+    }
+
+    #[test]
+    fn test_qoi_roundtrip_and_thumbnails() {
+        let image = gradient(640, 360);
+        let encoded = image.to_qoi();
+        assert!(encoded.len() < image.pixels.len() / 2);
+        assert_eq!(Image::decode(&encoded).unwrap(), image);
+        let mut translucent = gradient(3, 70);
+        translucent.pixels[7] = 10;
+        assert_eq!(Image::decode(&translucent.to_qoi()).unwrap(), translucent);
+
+        let thumbnail = image.thumbnail(160);
+        assert_eq!((thumbnail.width, thumbnail.height), (160, 90));
+        assert_eq!(&thumbnail.pixels[..4], &[1, 1, 0, 255]);
+
+        let mut ppm = b"P6\n# screenshot\n320 240\n255\n".to_vec();
+        ppm.extend(std::iter::repeat_n([10, 20, 30], 320 * 240).flatten());
+        assert_eq!(&validate(&ppm).unwrap().pixels[..4], &[10, 20, 30, 255]);
+        assert!(validate(&gradient(100, 100).to_qoi()).unwrap_err().contains("not between"));
+        assert!(validate(b"\x89PNG\r\n").unwrap_err().contains("Unsupported"));
+        assert!(validate(&encoded[..encoded.len() - 9]).is_err());
+    }
+
+    #[test]
+    fn test_media_store_loads_lazily() {
+        let store = MediaStore::new(Arc::new(VirtualFileSystem::new()));
+        let paths = store.store("viewer", &[gradient(640, 360).to_qoi(), gradient(800, 600).to_qoi()]).unwrap();
+        assert_eq!(paths[1], "/var/lib/app-store/media/viewer/1.qoi");
+        assert_eq!(store.cached(), 0);
+        let thumbnail = store.load(&thumbnail_path("viewer", 1, 480)).unwrap();
+        assert_eq!((thumbnail.width, thumbnail.height), (480, 360));
+        store.load(&thumbnail_path("viewer", 1, 480)).unwrap();
+        assert_eq!(store.cached(), 1);
+
+        store.store("viewer", &[gradient(640, 360).to_qoi()]).unwrap();
+        assert_eq!(store.cached(), 0);
+        assert!(store.load(&screenshot_path("viewer", 1)).is_err());
+    }
+}
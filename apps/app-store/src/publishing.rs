@@ -0,0 +1,448 @@
+//! Publishing
+//!
+//! Developers register the keystore key they sign bundles with, then submit
+//! app bundles: a manifest, the package archive and a signature over both.
+//! A submission is checked before it is accepted: the manifest must be
+//! complete, the bundle within the size limits, the capabilities something
+//! an app may reasonably ask for, and the signature made with the
+//! developer's registered key. Accepted submissions are drafts until the
+//! developer publishes them, which lists the app in the store; unpublishing
+//! takes it out again. A new version may be drafted while an older one
+//! stays published. Screenshots travel in the bundle, under the same
+//! signature, and are checked like the rest.
+//!
+//! ```json
+//! {"manifest": {"id": "viewer", "name": "Viewer", "version": "1.0.0", "developer": "DevTools Inc",
+//!               "description": "Image viewer", "category": "graphics", "price": 0.0,
+//!               "capabilities": [{"resource": {"Device": "gpu"}, "permission": "Read"}]},
+//!  "archive": [123, 34, 101], "screenshots": [[113, 111, 105, 102]], "signature": [123, 34, 105]}
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use capability::{Permission, Resource};
+use filesystem::VirtualFileSystem;
+use keystore::{KeyId, Keystore};
+use serde::{Deserialize, Serialize};
+use system_utils::string;
+
+use crate::media::{self, MAX_SCREENSHOTS};
+use crate::{AppCategory, AppListing};
+
+/// Drafts and published versions of every submitted app
+pub const SUBMISSIONS_FILE: &str = "/var/lib/app-store/submissions.json";
+
+/// Largest package archive accepted
+pub const MAX_BUNDLE_BYTES: usize = 512 * 1024 * 1024;
+
+/// Longest description, in characters
+pub const MAX_DESCRIPTION_LEN: usize = 4000;
+
+/// Most capabilities one app may ask for
+pub const MAX_CAPABILITIES: usize = 16;
+
+/// Paths no app may be granted
+const PROTECTED_PATHS: &[&str] = &["/etc", "/var/lib", "/apps", "/boot", "/dev"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RequestedCapability {
+    pub resource: Resource,
+    pub permission: Permission,
+}
+
+impl RequestedCapability {
+    /// As the store lists it
+    pub fn describe(&self) -> String {
+        let access = match self.permission {
+            Permission::Read => "read",
+            Permission::Write => "write",
+            Permission::Execute => "run",
+            Permission::ReadWrite => "read and write",
+            Permission::Full => "fully control",
+        };
+        match &self.resource {
+            Resource::File(path) => format!("{} files in {}", access, path),
+            Resource::Device(device) => format!("{} the {} device", access, device),
+            Resource::Network(host) => format!("{} the network host {}", access, host),
+            Resource::IPC(service) => format!("{} the {} service", access, service),
+            Resource::Memory(bytes) => format!("{} {} bytes of memory", access, bytes),
+        }
+    }
+
+    /// Why an app may not ask for this, if it may not
+    fn problem(&self) -> Option<String> {
+        match &self.resource {
+            Resource::File(path) => {
+                let path = Path::new(path);
+                if !path.is_absolute() || path.components().any(|c| c.as_os_str() == "..") {
+                    Some(format!("File capability {} is not a plain absolute path", path.display()))
+                } else if path == Path::new("/") || PROTECTED_PATHS.iter().any(|p| path.starts_with(p)) {
+                    Some(format!("Apps cannot be granted {}", path.display()))
+                } else if self.permission == Permission::Full {
+                    Some(format!("Apps cannot fully control {}", path.display()))
+                } else {
+                    None
+                }
+            }
+            Resource::Network(host) if host.is_empty() || host.contains('/') => {
+                Some(format!("Invalid network host {:?}", host))
+            }
+            Resource::IPC(service) if self.permission == Permission::Full => {
+                Some(format!("Apps cannot fully control the {} service", service))
+            }
+            Resource::Memory(_) => Some("Apps cannot ask for memory".to_string()),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AppManifest {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub developer: String,
+    pub description: String,
+    pub category: String,
+    #[serde(default)]
+    pub price: f32,
+    #[serde(default)]
+    pub capabilities: Vec<RequestedCapability>,
+}
+
+/// Package IDs are kebab-case ASCII: `text-editor`
+fn valid_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+impl AppManifest {
+    /// What is missing or malformed
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !valid_id(&self.id) {
+            match string::to_kebab_case(&self.id) {
+                suggestion if valid_id(&suggestion) => {
+                    problems.push(format!("Invalid app ID {:?}, try {:?}", self.id, suggestion))
+                }
+                _ => problems.push(format!("Invalid app ID {:?}", self.id)),
+            }
+        }
+        for (field, value) in [("name", &self.name), ("developer", &self.developer), ("description", &self.description)] {
+            if value.trim().is_empty() {
+                problems.push(format!("Manifest has no {}", field));
+            }
+        }
+        if parse_version(&self.version).is_none() {
+            problems.push(format!("Invalid version {:?}", self.version));
+        }
+        if AppCategory::parse(&self.category).is_none() {
+            problems.push(format!("Unknown category {:?}", self.category));
+        }
+        if !(self.price >= 0.0 && self.price.is_finite()) {
+            problems.push(format!("Invalid price {}", self.price));
+        }
+        if self.description.chars().count() > MAX_DESCRIPTION_LEN {
+            problems.push(format!("Description is longer than {} characters", MAX_DESCRIPTION_LEN));
+        }
+        if self.capabilities.len() > MAX_CAPABILITIES {
+            problems.push(format!("More than {} capabilities", MAX_CAPABILITIES));
+        }
+        for (i, capability) in self.capabilities.iter().enumerate() {
+            if self.capabilities[..i].iter().any(|c| c.resource == capability.resource) {
+                problems.push(format!("{:?} is asked for twice", capability.resource));
+            }
+        }
+        problems.extend(self.capabilities.iter().filter_map(|c| c.problem()));
+        problems
+    }
+
+}
+
+/// `major.minor.patch`
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let mut parts = version.split('.').map(|p| p.parse::<u64>().ok());
+    let parsed = (parts.next()??, parts.next()??, parts.next()??);
+    parts.next().is_none().then_some(parsed)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AppBundle {
+    pub manifest: AppManifest,
+    pub archive: Vec<u8>,
+    /// QOI or binary PPM images
+    #[serde(default)]
+    pub screenshots: Vec<Vec<u8>>,
+    /// Over the manifest's JSON followed by the archive and screenshots
+    pub signature: Vec<u8>,
+}
+
+impl AppBundle {
+    pub fn from_json(data: &[u8]) -> Result<Self, String> {
+        serde_json::from_slice(data).map_err(|e| format!("Invalid app bundle: {}", e))
+    }
+
+    /// Bytes the developer signs
+    pub fn signed_data(manifest: &AppManifest, archive: &[u8], screenshots: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = serde_json::to_vec(manifest).unwrap();
+        data.extend_from_slice(archive);
+        for screenshot in screenshots {
+            data.extend_from_slice(screenshot);
+        }
+        data
+    }
+
+    /// Store listing of the app, its screenshots where the media store
+    /// keeps them once published
+    pub fn listing(&self) -> AppListing {
+        let manifest = &self.manifest;
+        let category = AppCategory::parse(&manifest.category).unwrap();
+        let mut app = AppListing::new(manifest.id.clone(), manifest.name.clone(), manifest.developer.clone(), category);
+        app.description = manifest.description.clone();
+        app.version = manifest.version.clone();
+        app.size_mb = self.archive.len().div_ceil(1024 * 1024) as u32;
+        app.price = manifest.price;
+        app.screenshots = (0..self.screenshots.len())
+            .map(|i| media::screenshot_path(&manifest.id, i).to_string_lossy().into_owned())
+            .collect();
+        app.capabilities = manifest.capabilities.iter().map(|c| c.describe()).collect();
+        app
+    }
+}
+
+/// Every version of an app the store holds
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AppSubmissions {
+    pub draft: Option<AppBundle>,
+    pub published: Option<AppBundle>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct SubmissionDatabase {
+    /// Registered signing key, by developer
+    developers: BTreeMap<String, String>,
+    /// By app ID
+    apps: BTreeMap<String, AppSubmissions>,
+}
+
+/// Accepts, checks and publishes developers' submissions
+pub struct Publisher {
+    keystore: Arc<Keystore>,
+    database: SubmissionDatabase,
+}
+
+impl Publisher {
+    pub fn new(keystore: Arc<Keystore>) -> Self {
+        Publisher {
+            keystore,
+            database: SubmissionDatabase::default(),
+        }
+    }
+
+    pub fn load(keystore: Arc<Keystore>, vfs: &VirtualFileSystem) -> Result<Self, String> {
+        let path = Path::new(SUBMISSIONS_FILE);
+        if !vfs.exists(path) {
+            return Ok(Publisher::new(keystore));
+        }
+        let database =
+            serde_json::from_slice(&vfs.read_file(path)?).map_err(|e| format!("Invalid submission database: {}", e))?;
+        Ok(Publisher { keystore, database })
+    }
+
+    pub fn save(&self, vfs: &VirtualFileSystem) -> Result<(), String> {
+        vfs.write_file(Path::new(SUBMISSIONS_FILE), &serde_json::to_vec_pretty(&self.database).unwrap()).map_err(String::from)
+    }
+
+    /// Accept bundles from `developer` signed with `key`
+    pub fn register_developer(&mut self, developer: &str, key: &str) -> Result<(), String> {
+        if self.keystore.get_key(&KeyId::from(key)).is_none() {
+            return Err("Key not found".to_string());
+        }
+        self.database.developers.insert(developer.to_string(), key.to_string());
+        Ok(())
+    }
+
+    /// Everything wrong with a bundle
+    pub fn validate(&self, bundle: &AppBundle) -> Vec<String> {
+        let manifest = &bundle.manifest;
+        let mut problems = manifest.problems();
+        if bundle.archive.is_empty() {
+            problems.push("Bundle has no archive".to_string());
+        }
+        if bundle.archive.len() > MAX_BUNDLE_BYTES {
+            problems.push(format!("Archive is larger than {} MB", MAX_BUNDLE_BYTES / (1024 * 1024)));
+        }
+        if bundle.screenshots.len() > MAX_SCREENSHOTS {
+            problems.push(format!("More than {} screenshots", MAX_SCREENSHOTS));
+        }
+        for (i, screenshot) in bundle.screenshots.iter().enumerate() {
+            if let Err(e) = media::validate(screenshot) {
+                problems.push(format!("Screenshot {}: {}", i + 1, e));
+            }
+        }
+        match self.database.developers.get(&manifest.developer) {
+            None => problems.push(format!("{} is not a registered developer", manifest.developer)),
+            Some(key) => {
+                let data = AppBundle::signed_data(manifest, &bundle.archive, &bundle.screenshots);
+                if self.keystore.verify(&KeyId::from(key.as_str()), &data, &bundle.signature) != Ok(true) {
+                    problems.push(format!("Signature was not made with the key of {}", manifest.developer));
+                }
+            }
+        }
+        if let Some(existing) = self.database.apps.get(&manifest.id) {
+            let other = existing.published.as_ref().or(existing.draft.as_ref()).map(|b| &b.manifest);
+            if let Some(other) = other.filter(|m| m.developer != manifest.developer) {
+                problems.push(format!("{} belongs to {}", manifest.id, other.developer));
+            }
+            let published = existing.published.as_ref().and_then(|b| parse_version(&b.manifest.version));
+            if published.is_some() && parse_version(&manifest.version) <= published {
+                problems.push(format!("Version {} is not newer than the published one", manifest.version));
+            }
+        }
+        problems
+    }
+
+    /// Keep a bundle that passes validation as the app's draft, replacing
+    /// any earlier draft
+    pub fn submit(&mut self, bundle: AppBundle) -> Result<(), String> {
+        let problems = self.validate(&bundle);
+        if !problems.is_empty() {
+            return Err(problems.join("; "));
+        }
+        let id = bundle.manifest.id.clone();
+        self.database.apps.entry(id).or_default().draft = Some(bundle);
+        Ok(())
+    }
+
+    /// Make an app's draft its published version
+    pub fn publish(&mut self, app_id: &str) -> Result<&AppBundle, String> {
+        let submissions = self.database.apps.get_mut(app_id).ok_or_else(|| format!("No submission of {}", app_id))?;
+        let draft = submissions.draft.take().ok_or_else(|| format!("{} has no draft", app_id))?;
+        Ok(submissions.published.insert(draft))
+    }
+
+    /// Take an app out of the store, keeping its published version as the
+    /// draft unless a newer one is drafted
+    pub fn unpublish(&mut self, app_id: &str) -> Result<(), String> {
+        let submissions = self.database.apps.get_mut(app_id).ok_or_else(|| format!("No submission of {}", app_id))?;
+        let published = submissions.published.take().ok_or_else(|| format!("{} is not published", app_id))?;
+        submissions.draft.get_or_insert(published);
+        Ok(())
+    }
+
+    pub fn submissions(&self) -> impl Iterator<Item = (&String, &AppSubmissions)> {
+        self.database.apps.iter()
+    }
+
+    /// Published versions, as the store lists them
+    pub fn listings(&self) -> Vec<AppListing> {
+        self.database
+            .apps
+            .values()
+            .filter_map(|s| s.published.as_ref())
+            .map(AppBundle::listing)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keystore::{KeyType, KeyUsage};
+
+    fn signed_bundle(keystore: &Keystore, key: &KeyId, manifest: AppManifest) -> AppBundle {
+        let archive = b"{\"entries\":[]}".to_vec();
+        let signature = keystore.sign(key, &AppBundle::signed_data(&manifest, &archive, &[])).unwrap();
+        AppBundle {
+            manifest,
+            archive,
+            screenshots: Vec::new(),
+            signature,
+        }
+    }
+
+    fn manifest(id: &str, version: &str) -> AppManifest {
+        serde_json::from_value(serde_json::json!({
+            "id": id, "name": "Viewer", "version": version, "developer": "DevTools Inc",
+            "description": "Image viewer", "category": "graphics",
+            "capabilities": [{"resource": {"Device": "gpu"}, "permission": "Read"}],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_submission_validation() {
+        let keystore = Arc::new(Keystore::new());
+        let key = KeyId::from("devtools");
+        keystore.generate_key(key.clone(), KeyType::Ed25519, vec![KeyUsage::Sign, KeyUsage::Verify], false).unwrap();
+        let mut publisher = Publisher::new(Arc::clone(&keystore));
+        let bundle = signed_bundle(&keystore, &key, manifest("viewer", "1.0.0"));
+        assert!(publisher.submit(bundle.clone()).unwrap_err().contains("not a registered developer"));
+        publisher.register_developer("DevTools Inc", "devtools").unwrap();
+
+        let mut broken = manifest("Viewer!", "1.0");
+        broken.description = String::new();
+        broken.capabilities.push(RequestedCapability {
+            resource: Resource::File("/etc/passwd".to_string()),
+            permission: Permission::Read,
+        });
+        broken.capabilities.push(broken.capabilities[0].clone());
+        let problems = publisher.validate(&signed_bundle(&keystore, &key, broken));
+        assert_eq!(problems.len(), 5, "{:?}", problems);
+        assert_eq!(problems[0], r#"Invalid app ID "Viewer!", try "viewer""#);
+        let mut tampered = bundle.clone();
+        tampered.manifest.price = 0.5;
+        assert_eq!(publisher.validate(&tampered), vec!["Signature was not made with the key of DevTools Inc".to_string()]);
+        tampered.screenshots.push(b"GIF89a".to_vec());
+        assert!(publisher.validate(&tampered)[0].starts_with("Screenshot 1: Unsupported image format"));
+
+        publisher.submit(bundle.clone()).unwrap();
+        assert!(publisher.listings().is_empty());
+        assert_eq!(publisher.publish("viewer").unwrap().manifest.version, "1.0.0");
+        assert!(publisher.publish("viewer").is_err());
+        assert_eq!(publisher.listings()[0].capabilities, vec!["read the gpu device".to_string()]);
+        assert!(publisher.submit(bundle).unwrap_err().contains("not newer"));
+        publisher.submit(signed_bundle(&keystore, &key, manifest("viewer", "1.1.0"))).unwrap();
+        assert_eq!(publisher.listings()[0].version, "1.0.0");
+
+        let vfs = VirtualFileSystem::new();
+        publisher.unpublish("viewer").unwrap();
+        publisher.save(&vfs).unwrap();
+        let loaded = Publisher::load(keystore, &vfs).unwrap();
+        assert!(loaded.listings().is_empty());
+        let (_, viewer) = loaded.submissions().next().unwrap();
+        assert_eq!(viewer.draft.as_ref().unwrap().manifest.version, "1.1.0");
+    }
+
+    #[test]
+    fn test_bundle_resigned_without_developer_key() {
+        let keystore = Arc::new(Keystore::new());
+        let key = KeyId::from("devtools");
+        let other = KeyId::from("mallory");
+        for id in [&key, &other] {
+            keystore.generate_key(id.clone(), KeyType::Ed25519, vec![KeyUsage::Sign, KeyUsage::Verify], false).unwrap();
+        }
+        let mut publisher = Publisher::new(Arc::clone(&keystore));
+        publisher.register_developer("DevTools Inc", "devtools").unwrap();
+        publisher.register_developer("Mallory", "mallory").unwrap();
+
+        // A keystore holding a different key under the developer's key name
+        let forger = Keystore::new();
+        forger.generate_key(key.clone(), KeyType::Ed25519, vec![KeyUsage::Sign, KeyUsage::Verify], false).unwrap();
+        let mut tampered = signed_bundle(&keystore, &key, manifest("viewer", "1.0.0"));
+        tampered.archive = b"{\"entries\":[{\"path\":\"payload\"}]}".to_vec();
+        let data = AppBundle::signed_data(&tampered.manifest, &tampered.archive, &tampered.screenshots);
+        for signature in [data.clone(), keystore.sign(&other, &data).unwrap(), forger.sign(&key, &data).unwrap()] {
+            let forged = AppBundle { signature, ..tampered.clone() };
+            assert_eq!(
+                publisher.submit(forged).unwrap_err(),
+                "Signature was not made with the key of DevTools Inc"
+            );
+        }
+        assert!(publisher.submissions().next().is_none());
+
+        tampered.signature = keystore.sign(&key, &data).unwrap();
+        publisher.submit(tampered).unwrap();
+    }
+}
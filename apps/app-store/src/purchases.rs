@@ -0,0 +1,238 @@
+//! Purchases and licenses
+//!
+//! Buying a priced app gets the buyer an entitlement: a verifiable
+//! credential the store's identity issues to the buyer's DID, naming the
+//! app, the price paid and the payment receipt, and handed around as a
+//! signed JWT. Installing a priced app needs an entitlement that verifies
+//! against the store's key and belongs to the installing identity.
+//! Entitlements are kept in the VFS by owner, and restoring purchases on
+//! another device only takes the identity: the remote catalog returns the
+//! tokens it holds for it and those that verify are kept.
+//!
+//! ```json
+//! {"entitlements": {"did:hairr:alice": ["eyJhbGciOiJFZERTQSIs..."]}}
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use filesystem::VirtualFileSystem;
+use keystore::{KeyId, Keystore, VerifiableCredential};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+
+/// Entitlements of every identity
+pub const ENTITLEMENTS_FILE: &str = "/var/lib/app-store/entitlements.json";
+
+/// Credential type of an entitlement
+pub const ENTITLEMENT_TYPE: &str = "AppEntitlement";
+
+/// Charges a DID for an app at a price, returning the payment's receipt
+pub type PaymentProcessor = Arc<dyn Fn(&str, &str, f32) -> Result<String, String> + Send + Sync>;
+
+/// Entitlement tokens the remote catalog holds for a DID
+pub type PurchaseHistory = Arc<dyn Fn(&str) -> Result<Vec<String>, String> + Send + Sync>;
+
+/// A verified entitlement
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entitlement {
+    pub app: String,
+    /// DID of the buyer
+    pub owner: String,
+    pub price: f64,
+    pub receipt: String,
+    /// RFC 3339 timestamp
+    pub purchased_at: String,
+}
+
+/// Issues and verifies entitlements with the store's identity
+pub struct Licensor {
+    keystore: Arc<Keystore>,
+    issuer: String,
+    key: KeyId,
+}
+
+impl Licensor {
+    /// Sign as `issuer` with `key`, one of its verification methods
+    pub fn new(keystore: Arc<Keystore>, issuer: String, key: KeyId) -> Self {
+        Licensor { keystore, issuer, key }
+    }
+
+    pub fn issue(&self, owner: &str, app: &str, price: f32, receipt: &str) -> Result<String, String> {
+        let credential = VerifiableCredential::new(self.issuer.clone(), owner.to_string(), ENTITLEMENT_TYPE)
+            .with_claim("app", json!(app))
+            .with_claim("price", json!(price))
+            .with_claim("receipt", json!(receipt));
+        self.keystore.credential_to_jwt(&credential, &self.key)
+    }
+
+    pub fn verify(&self, token: &str) -> Result<Entitlement, String> {
+        // The keystore refuses tokens whose credential names an issuer
+        // other than the signer, so this is the verified issuer
+        let credential = self.keystore.verify_jwt_credential(token)?;
+        if credential.issuer != self.issuer {
+            return Err("Entitlement was not issued by this store".to_string());
+        }
+        if !credential.types.iter().any(|t| t == ENTITLEMENT_TYPE) {
+            return Err("Not an app entitlement".to_string());
+        }
+        let claim = |name: &str| credential.credential_subject.get(name);
+        Ok(Entitlement {
+            app: claim("app").and_then(|v| v.as_str()).ok_or("Entitlement names no app")?.to_string(),
+            owner: credential.subject_id().ok_or("Entitlement has no owner")?.to_string(),
+            price: claim("price").and_then(|v| v.as_f64()).unwrap_or_default(),
+            receipt: claim("receipt").and_then(|v| v.as_str()).unwrap_or_default().to_string(),
+            purchased_at: credential.issuance_date.clone(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntitlementStore {
+    /// Tokens by owner DID
+    entitlements: BTreeMap<String, Vec<String>>,
+}
+
+impl EntitlementStore {
+    pub fn load(vfs: &VirtualFileSystem) -> Result<Self, String> {
+        let path = Path::new(ENTITLEMENTS_FILE);
+        if !vfs.exists(path) {
+            return Ok(EntitlementStore::default());
+        }
+        serde_json::from_slice(&vfs.read_file(path)?).map_err(|e| format!("Invalid entitlement database: {}", e))
+    }
+
+    pub fn save(&self, vfs: &VirtualFileSystem) -> Result<(), String> {
+        vfs.write_file(Path::new(ENTITLEMENTS_FILE), &serde_json::to_vec_pretty(self).unwrap()).map_err(String::from)
+    }
+
+    /// Keep a token for `owner`; false if it was already kept
+    pub fn add(&mut self, owner: &str, token: String) -> bool {
+        let tokens = self.entitlements.entry(owner.to_string()).or_default();
+        if tokens.contains(&token) {
+            return false;
+        }
+        tokens.push(token);
+        true
+    }
+
+    /// Entitlements of `owner` that still verify
+    pub fn owned(&self, licensor: &Licensor, owner: &str) -> Vec<Entitlement> {
+        self.entitlements
+            .get(owner)
+            .into_iter()
+            .flatten()
+            .filter_map(|token| licensor.verify(token).ok())
+            .filter(|e| e.owner == owner)
+            .collect()
+    }
+
+    pub fn find(&self, licensor: &Licensor, owner: &str, app: &str) -> Option<Entitlement> {
+        self.owned(licensor, owner).into_iter().find(|e| e.app == app)
+    }
+
+    /// Keep the tokens of `owner` among `tokens` that verify, returning the
+    /// entitlements not kept before
+    pub fn restore(&mut self, licensor: &Licensor, owner: &str, tokens: Vec<String>) -> Vec<Entitlement> {
+        let mut restored = Vec::new();
+        for token in tokens {
+            let Ok(entitlement) = licensor.verify(&token) else {
+                continue;
+            };
+            if entitlement.owner == owner && self.add(owner, token) {
+                restored.push(entitlement);
+            }
+        }
+        restored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keystore::{KeyType, KeyUsage};
+    use system_utils::encoding::base64url_encode;
+
+    fn licensor() -> Licensor {
+        let keystore = Arc::new(Keystore::new());
+        for (did, key) in [("did:hairr:store", "store-key"), ("did:hairr:mallory", "mallory-key")] {
+            keystore.generate_key(KeyId::from(key), KeyType::Ed25519, vec![KeyUsage::Sign, KeyUsage::Verify], false).unwrap();
+            keystore.create_identity(did.to_string(), &KeyId::from(key)).unwrap();
+        }
+        Licensor::new(keystore, "did:hairr:store".to_string(), KeyId::from("store-key"))
+    }
+
+    #[test]
+    fn test_entitlements() {
+        let licensor = licensor();
+        let token = licensor.issue("did:hairr:alice", "code-studio", 29.99, "rcpt-1").unwrap();
+        let entitlement = licensor.verify(&token).unwrap();
+        assert_eq!((entitlement.app.as_str(), entitlement.receipt.as_str()), ("code-studio", "rcpt-1"));
+
+        let forged = Licensor::new(Arc::clone(&licensor.keystore), "did:hairr:mallory".to_string(), KeyId::from("mallory-key"))
+            .issue("did:hairr:alice", "media-player", 0.0, "none")
+            .unwrap();
+        assert!(licensor.verify(&forged).unwrap_err().contains("not issued by this store"));
+        let mut parts: Vec<&str> = token.split('.').collect();
+        parts[2] = "AAAA";
+        assert!(licensor.verify(&parts.join(".")).is_err());
+
+        let vfs = VirtualFileSystem::new();
+        let mut store = EntitlementStore::default();
+        let bob = licensor.issue("did:hairr:bob", "code-studio", 29.99, "rcpt-2").unwrap();
+        let restored = store.restore(&licensor, "did:hairr:alice", vec![token.clone(), forged, bob, token.clone()]);
+        assert_eq!(restored, vec![entitlement]);
+        store.save(&vfs).unwrap();
+        let loaded = EntitlementStore::load(&vfs).unwrap();
+        assert!(loaded.find(&licensor, "did:hairr:alice", "code-studio").is_some());
+        assert!(loaded.find(&licensor, "did:hairr:alice", "media-player").is_none());
+        assert!(loaded.owned(&licensor, "did:hairr:bob").is_empty());
+    }
+
+    #[test]
+    fn test_entitlement_signed_by_another_identity() {
+        let licensor = licensor();
+        // Mallory signs as herself but claims the store issued the credential
+        let credential = VerifiableCredential::new("did:hairr:store".to_string(), "did:hairr:alice".to_string(), ENTITLEMENT_TYPE)
+            .with_claim("app", json!("code-studio"));
+        let header = json!({"alg": "EdDSA", "typ": "JWT", "kid": "did:hairr:mallory#mallory-key"});
+        let claims = json!({"iss": "did:hairr:mallory", "sub": "did:hairr:alice", "vc": credential});
+        let input = format!("{}.{}", base64url_encode(header.to_string().as_bytes()), base64url_encode(claims.to_string().as_bytes()));
+        let signature = licensor.keystore.sign(&KeyId::from("mallory-key"), input.as_bytes()).unwrap();
+        let forged = format!("{}.{}", input, base64url_encode(&signature));
+
+        assert!(licensor.verify(&forged).is_err());
+        let mut store = EntitlementStore::default();
+        assert!(store.restore(&licensor, "did:hairr:alice", vec![forged]).is_empty());
+        assert!(store.find(&licensor, "did:hairr:alice", "code-studio").is_none());
+    }
+
+    #[test]
+    fn test_entitlement_minted_without_store_key() {
+        let licensor = licensor();
+        // A token that claims the store's key, in every field, but was not
+        // signed with it
+        let credential = VerifiableCredential::new("did:hairr:store".to_string(), "did:hairr:alice".to_string(), ENTITLEMENT_TYPE)
+            .with_claim("app", json!("code-studio"))
+            .with_claim("price", json!(29.99))
+            .with_claim("receipt", json!("rcpt-free"));
+        let header = json!({"alg": "EdDSA", "typ": "JWT", "kid": "did:hairr:store#store-key"});
+        let claims = json!({"iss": "did:hairr:store", "sub": "did:hairr:alice", "vc": credential});
+        let input = format!("{}.{}", base64url_encode(header.to_string().as_bytes()), base64url_encode(claims.to_string().as_bytes()));
+        let unkeyed = input.as_bytes().to_vec();
+        let mallory = licensor.keystore.sign(&KeyId::from("mallory-key"), input.as_bytes()).unwrap();
+
+        for signature in [unkeyed, mallory] {
+            let minted = format!("{}.{}", input, base64url_encode(&signature));
+            assert!(licensor.verify(&minted).is_err());
+            let mut store = EntitlementStore::default();
+            assert!(store.restore(&licensor, "did:hairr:alice", vec![minted.clone()]).is_empty());
+            store.add("did:hairr:alice", minted);
+            assert!(store.find(&licensor, "did:hairr:alice", "code-studio").is_none());
+        }
+        let signed = licensor.keystore.sign(&KeyId::from("store-key"), input.as_bytes()).unwrap();
+        assert!(licensor.verify(&format!("{}.{}", input, base64url_encode(&signed))).is_ok());
+    }
+}
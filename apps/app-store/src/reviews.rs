@@ -0,0 +1,246 @@
+//! User reviews
+//!
+//! Signed-in users rate an app from one to five stars with an optional
+//! text; a second review of the same app replaces the first. Ratings shown
+//! in the store combine the seed rating with the visible reviews. Any user
+//! can flag a review as abusive, and one flagged by enough people is hidden
+//! until a moderator looks at it. Reviews are kept in the VFS and each is
+//! marked once the sync hook has sent it on, so a remote catalog can catch
+//! up later.
+//!
+//! ```json
+//! {"next_id": 2, "reviews": [{"id": 1, "app": "code-studio", "user": "alice", "stars": 5,
+//!   "text": "Fast", "submitted_at": 1700000000, "flagged_by": [], "hidden": false, "synced": false}]}
+//! ```
+
+use std::path::Path;
+use std::sync::Arc;
+
+use filesystem::VirtualFileSystem;
+use serde::{Deserialize, Serialize};
+
+use crate::Rating;
+
+/// Reviews of every app
+pub const REVIEWS_FILE: &str = "/var/lib/app-store/reviews.json";
+
+/// Flags from distinct users that hide a review
+pub const FLAG_THRESHOLD: usize = 3;
+
+/// Longest review text, in characters
+pub const MAX_TEXT_LEN: usize = 2000;
+
+/// Sends a review to the remote catalog
+pub type ReviewSync = Arc<dyn Fn(&Review) -> Result<(), String> + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Review {
+    pub id: u64,
+    pub app: String,
+    /// Name of the user who wrote it
+    pub user: String,
+    pub stars: u8,
+    pub text: String,
+    /// Seconds since the Unix epoch
+    pub submitted_at: u64,
+    /// Users who reported it as abusive
+    pub flagged_by: Vec<String>,
+    pub hidden: bool,
+    /// Sent to the remote catalog since it last changed
+    pub synced: bool,
+}
+
+/// Order of a review listing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReviewSort {
+    Newest,
+    Oldest,
+    Highest,
+    Lowest,
+}
+
+impl ReviewSort {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "newest" => Ok(ReviewSort::Newest),
+            "oldest" => Ok(ReviewSort::Oldest),
+            "highest" => Ok(ReviewSort::Highest),
+            "lowest" => Ok(ReviewSort::Lowest),
+            _ => Err(format!("Unknown sort order: {}", name)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReviewStore {
+    next_id: u64,
+    reviews: Vec<Review>,
+}
+
+impl ReviewStore {
+    pub fn load(vfs: &VirtualFileSystem) -> Result<Self, String> {
+        let path = Path::new(REVIEWS_FILE);
+        if !vfs.exists(path) {
+            return Ok(ReviewStore::default());
+        }
+        serde_json::from_slice(&vfs.read_file(path)?).map_err(|e| format!("Invalid review database: {}", e))
+    }
+
+    pub fn save(&self, vfs: &VirtualFileSystem) -> Result<(), String> {
+        vfs.write_file(Path::new(REVIEWS_FILE), &serde_json::to_vec_pretty(self).unwrap()).map_err(String::from)
+    }
+
+    /// Add `user`'s review of `app`, replacing any earlier one; returns
+    /// its ID
+    pub fn submit(&mut self, app: &str, user: &str, stars: u8, text: &str, now: u64) -> Result<u64, String> {
+        if !(1..=5).contains(&stars) {
+            return Err("Ratings are from 1 to 5 stars".to_string());
+        }
+        if text.chars().count() > MAX_TEXT_LEN {
+            return Err(format!("Reviews are limited to {} characters", MAX_TEXT_LEN));
+        }
+        self.reviews.retain(|r| r.app != app || r.user != user);
+        self.next_id += 1;
+        self.reviews.push(Review {
+            id: self.next_id,
+            app: app.to_string(),
+            user: user.to_string(),
+            stars,
+            text: text.to_string(),
+            submitted_at: now,
+            flagged_by: Vec::new(),
+            hidden: false,
+            synced: false,
+        });
+        Ok(self.next_id)
+    }
+
+    fn get_mut(&mut self, id: u64) -> Result<&mut Review, String> {
+        self.reviews
+            .iter_mut()
+            .find(|r| r.id == id)
+            .ok_or_else(|| format!("Review {} not found", id))
+    }
+
+    /// `seed` combined with the visible reviews of `app`
+    pub fn rating(&self, app: &str, seed: Option<Rating>) -> Option<Rating> {
+        let (mut total, mut count) = seed.map_or((0.0, 0), |r| (r.stars * r.count as f32, r.count));
+        for review in self.visible(app) {
+            total += review.stars as f32;
+            count += 1;
+        }
+        (count > 0).then(|| Rating::new(total / count as f32, count))
+    }
+
+    fn visible(&self, app: &str) -> Vec<&Review> {
+        self.reviews.iter().filter(|r| r.app == app && !r.hidden).collect()
+    }
+
+    /// One page of the visible reviews of `app`, counting pages from 1
+    pub fn page(&self, app: &str, sort: ReviewSort, page: usize, per_page: usize) -> Vec<&Review> {
+        let mut reviews = self.visible(app);
+        match sort {
+            ReviewSort::Newest => reviews.sort_by_key(|r| std::cmp::Reverse((r.submitted_at, r.id))),
+            ReviewSort::Oldest => reviews.sort_by_key(|r| (r.submitted_at, r.id)),
+            ReviewSort::Highest => reviews.sort_by_key(|r| std::cmp::Reverse((r.stars, r.submitted_at, r.id))),
+            ReviewSort::Lowest => reviews.sort_by_key(|r| (r.stars, std::cmp::Reverse((r.submitted_at, r.id)))),
+        }
+        reviews.into_iter().skip(page.saturating_sub(1) * per_page).take(per_page).collect()
+    }
+
+    /// Report a review as abusive; it is hidden once `FLAG_THRESHOLD`
+    /// users have. Returns whether it is now hidden.
+    pub fn flag(&mut self, id: u64, user: &str) -> Result<bool, String> {
+        let review = self.get_mut(id)?;
+        if review.user == user {
+            return Err("You cannot flag your own review".to_string());
+        }
+        if !review.flagged_by.iter().any(|u| u == user) {
+            review.flagged_by.push(user.to_string());
+        }
+        if review.flagged_by.len() >= FLAG_THRESHOLD {
+            review.hidden = true;
+        }
+        review.synced = false;
+        Ok(review.hidden)
+    }
+
+    /// Flagged reviews awaiting a moderator, most flagged first
+    pub fn flagged(&self) -> Vec<&Review> {
+        let mut flagged: Vec<&Review> = self.reviews.iter().filter(|r| !r.flagged_by.is_empty()).collect();
+        flagged.sort_by_key(|r| std::cmp::Reverse(r.flagged_by.len()));
+        flagged
+    }
+
+    /// A moderator's verdict: hide the review, or restore it and clear
+    /// its flags
+    pub fn moderate(&mut self, id: u64, hide: bool) -> Result<(), String> {
+        let review = self.get_mut(id)?;
+        review.hidden = hide;
+        if !hide {
+            review.flagged_by.clear();
+        }
+        review.synced = false;
+        Ok(())
+    }
+
+    /// Send every review changed since the last sync through `sync`,
+    /// stopping at the first failure; returns how many were sent
+    pub fn sync(&mut self, sync: &ReviewSync) -> Result<usize, String> {
+        let mut sent = 0;
+        for review in self.reviews.iter_mut().filter(|r| !r.synced) {
+            sync(review)?;
+            review.synced = true;
+            sent += 1;
+        }
+        Ok(sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_reviews_and_moderation() {
+        let vfs = VirtualFileSystem::new();
+        let mut store = ReviewStore::default();
+        assert!(store.submit("viewer", "alice", 6, "", 10).is_err());
+        let first = store.submit("viewer", "alice", 2, "Crashes", 10).unwrap();
+        store.submit("viewer", "bob", 5, "Great", 20).unwrap();
+        store.submit("viewer", "carol", 4, "", 30).unwrap();
+        let replaced = store.submit("viewer", "alice", 3, "Better now", 40).unwrap();
+        assert!(store.flag(first, "bob").unwrap_err().contains("not found"));
+
+        let rating = store.rating("viewer", None).unwrap();
+        assert_eq!((rating.stars, rating.count), (4.0, 3));
+        assert_eq!(store.rating("viewer", Some(Rating::new(1.0, 1))).unwrap().stars, 13.0 / 4.0);
+        assert!(store.rating("editor", None).is_none());
+        let newest: Vec<&str> = store.page("viewer", ReviewSort::Newest, 1, 2).iter().map(|r| r.user.as_str()).collect();
+        assert_eq!(newest, vec!["alice", "carol"]);
+        assert_eq!(store.page("viewer", ReviewSort::Highest, 2, 2)[0].user, "alice");
+        assert!(store.page("viewer", ReviewSort::Oldest, 3, 2).is_empty());
+
+        assert!(store.flag(replaced, "alice").is_err());
+        assert!(!store.flag(replaced, "bob").unwrap());
+        assert!(!store.flag(replaced, "bob").unwrap());
+        store.flag(replaced, "carol").unwrap();
+        assert!(store.flag(replaced, "dave").unwrap());
+        assert_eq!(store.page("viewer", ReviewSort::Newest, 1, 10).len(), 2);
+        assert_eq!(store.flagged()[0].id, replaced);
+        store.moderate(replaced, false).unwrap();
+        assert!(store.flagged().is_empty());
+
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let record = Arc::clone(&sent);
+        let sync: ReviewSync = Arc::new(move |review: &Review| {
+            record.lock().unwrap().push(review.id);
+            Ok(())
+        });
+        assert_eq!(store.sync(&sync).unwrap(), 3);
+        assert_eq!(store.sync(&sync).unwrap(), 0);
+        store.save(&vfs).unwrap();
+        assert_eq!(ReviewStore::load(&vfs).unwrap(), store);
+    }
+}
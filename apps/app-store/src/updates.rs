@@ -0,0 +1,86 @@
+//! Deferred updates
+//!
+//! Deferring an update keeps `update-all` from applying it. A deferral is
+//! for one version only: once the catalog offers a newer one, the app is
+//! updated with the rest again. Updating the app by hand drops it. The
+//! deferrals are kept in the VFS.
+//!
+//! ```json
+//! {"apps": {"web-browser": "2.2.0"}}
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use filesystem::VirtualFileSystem;
+use serde::{Deserialize, Serialize};
+
+use crate::AppUpdate;
+
+/// Deferred update of every app
+pub const DEFERRED_FILE: &str = "/var/lib/app-store/deferred.json";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeferredUpdates {
+    /// Version deferred, by app ID
+    apps: BTreeMap<String, String>,
+}
+
+impl DeferredUpdates {
+    pub fn load(vfs: &VirtualFileSystem) -> Result<Self, String> {
+        let path = Path::new(DEFERRED_FILE);
+        if !vfs.exists(path) {
+            return Ok(DeferredUpdates::default());
+        }
+        serde_json::from_slice(&vfs.read_file(path)?).map_err(|e| format!("Invalid deferred update list: {}", e))
+    }
+
+    pub fn save(&self, vfs: &VirtualFileSystem) -> Result<(), String> {
+        vfs.write_file(Path::new(DEFERRED_FILE), &serde_json::to_vec_pretty(self).unwrap()).map_err(String::from)
+    }
+
+    pub fn defer(&mut self, update: &AppUpdate) {
+        self.apps.insert(update.id.clone(), update.available.clone());
+    }
+
+    /// Whether there was a deferral to drop
+    pub fn remove(&mut self, id: &str) -> bool {
+        self.apps.remove(id).is_some()
+    }
+
+    pub fn is_deferred(&self, update: &AppUpdate) -> bool {
+        self.apps.get(&update.id) == Some(&update.available)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(id: &str, available: &str) -> AppUpdate {
+        AppUpdate {
+            id: id.to_string(),
+            name: id.to_string(),
+            installed: "1.0.0".to_string(),
+            available: available.to_string(),
+            changelog: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_deferral_lasts_one_version() {
+        let vfs = VirtualFileSystem::new();
+        let mut deferred = DeferredUpdates::default();
+        deferred.defer(&update("web-browser", "2.2.0"));
+        deferred.save(&vfs).unwrap();
+        let loaded = DeferredUpdates::load(&vfs).unwrap();
+        assert_eq!(loaded, deferred);
+        assert!(loaded.is_deferred(&update("web-browser", "2.2.0")));
+        assert!(!loaded.is_deferred(&update("web-browser", "2.3.0")));
+        assert!(!loaded.is_deferred(&update("text-editor", "2.2.0")));
+
+        assert!(deferred.remove("web-browser"));
+        assert!(!deferred.remove("web-browser"));
+        assert!(!deferred.is_deferred(&update("web-browser", "2.2.0")));
+    }
+}
@@ -0,0 +1,104 @@
+//! Command console
+//!
+//! The command-line host running on the slave end of a terminal's PTY. It
+//! prints a prompt, reads canonical lines, and runs builtins or registered
+//! programs, writing their output back to the terminal.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ipc::PtySlave;
+
+/// Shown before each command
+pub const PROMPT: &str = "$ ";
+
+/// Command-line program; gets its arguments, returns its output
+pub type Program = Arc<dyn Fn(&[String]) -> Result<String, String> + Send + Sync>;
+
+const BUILTINS: [&str; 4] = ["clear", "echo", "exit", "help"];
+
+/// Runs commands typed into a terminal
+pub struct Console {
+    slave: PtySlave,
+    programs: HashMap<String, Program>,
+    /// Input read but not yet terminated by a newline
+    pending: String,
+    exited: bool,
+}
+
+impl Console {
+    /// Attach to a PTY and show the first prompt
+    pub fn new(slave: PtySlave) -> Result<Self, String> {
+        slave.write(PROMPT.as_bytes())?;
+        Ok(Console {
+            slave,
+            programs: HashMap::new(),
+            pending: String::new(),
+            exited: false,
+        })
+    }
+
+    /// Make `program` runnable as `name`
+    pub fn register(&mut self, name: &str, program: Program) {
+        self.programs.insert(name.to_string(), program);
+    }
+
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    /// Run every complete command that has arrived
+    pub fn poll(&mut self) -> Result<(), String> {
+        if self.exited {
+            return Ok(());
+        }
+        if self.slave.take_interrupt() {
+            self.pending.clear();
+            self.slave.write(PROMPT.as_bytes())?;
+        }
+        let input = self.slave.read()?;
+        self.pending.push_str(&String::from_utf8_lossy(&input));
+        while let Some(end) = self.pending.find('\n') {
+            let line: String = self.pending.drain(..=end).collect();
+            self.run(line.trim())?;
+            if self.exited {
+                return Ok(());
+            }
+            self.slave.write(PROMPT.as_bytes())?;
+        }
+        if self.slave.take_eof() {
+            self.exited = true;
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, line: &str) -> Result<(), String> {
+        let args: Vec<String> = line.split_whitespace().map(str::to_string).collect();
+        let Some(name) = args.first() else {
+            return Ok(());
+        };
+        let output = match name.as_str() {
+            "exit" => {
+                self.exited = true;
+                return Ok(());
+            }
+            "clear" => "\x1b[2J".to_string(),
+            "echo" => format!("{}\n", args[1..].join(" ")),
+            "help" => {
+                let mut names: Vec<&str> = BUILTINS.to_vec();
+                names.extend(self.programs.keys().map(String::as_str));
+                names.sort();
+                format!("Commands: {}\n", names.join(" "))
+            }
+            _ => match self.programs.get(name) {
+                Some(program) => match program(&args[1..]) {
+                    Ok(output) if output.is_empty() || output.ends_with('\n') => output,
+                    Ok(output) => format!("{}\n", output),
+                    Err(e) => format!("{}: {}\n", name, e),
+                },
+                None => format!("{}: command not found\n", name),
+            },
+        };
+        self.slave.write(output.replace('\n', "\r\n").as_bytes()).map_err(String::from)
+    }
+}
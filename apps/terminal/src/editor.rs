@@ -0,0 +1,227 @@
+//! Line editing
+//!
+//! While the program in the terminal reads canonical input the terminal
+//! edits the line locally, with cursor movement and history, and only
+//! sends it to the PTY once the user presses enter.
+
+/// Commands kept for recall with the up and down keys
+pub const MAX_HISTORY: usize = 500;
+
+/// Keyboard input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Home,
+    End,
+    Up,
+    Down,
+    Enter,
+    /// Ctrl-C
+    Interrupt,
+    /// Ctrl-D
+    EndOfFile,
+}
+
+impl Key {
+    /// Bytes sent to a program reading raw input
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Key::Char(c) => c.to_string().into_bytes(),
+            Key::Backspace => vec![0x7f],
+            Key::Delete => b"\x1b[3~".to_vec(),
+            Key::Left => b"\x1b[D".to_vec(),
+            Key::Right => b"\x1b[C".to_vec(),
+            Key::Home => b"\x1b[H".to_vec(),
+            Key::End => b"\x1b[F".to_vec(),
+            Key::Up => b"\x1b[A".to_vec(),
+            Key::Down => b"\x1b[B".to_vec(),
+            Key::Enter => b"\r".to_vec(),
+            Key::Interrupt => vec![0x03],
+            Key::EndOfFile => vec![0x04],
+        }
+    }
+}
+
+/// Editable input line with history
+pub struct LineEditor {
+    buffer: Vec<char>,
+    cursor: usize,
+    history: Vec<String>,
+    /// Index into `history` while browsing it
+    browsing: Option<usize>,
+    /// Line being typed before browsing started
+    draft: Vec<char>,
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        LineEditor {
+            buffer: Vec::new(),
+            cursor: 0,
+            history: Vec::new(),
+            browsing: None,
+            draft: Vec::new(),
+        }
+    }
+
+    /// Apply a key, returning the finished line when it is enter
+    pub fn handle(&mut self, key: Key) -> Option<String> {
+        match key {
+            Key::Char(c) => {
+                self.buffer.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Key::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.buffer.remove(self.cursor);
+            }
+            Key::Delete if self.cursor < self.buffer.len() => {
+                self.buffer.remove(self.cursor);
+            }
+            Key::Left => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right => self.cursor = (self.cursor + 1).min(self.buffer.len()),
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.buffer.len(),
+            Key::Up => self.recall_older(),
+            Key::Down => self.recall_newer(),
+            Key::Enter => return Some(self.submit()),
+            _ => {}
+        }
+        None
+    }
+
+    fn recall_older(&mut self) {
+        let index = match self.browsing {
+            Some(0) => return,
+            Some(index) => index - 1,
+            None if self.history.is_empty() => return,
+            None => {
+                self.draft = self.buffer.clone();
+                self.history.len() - 1
+            }
+        };
+        self.browsing = Some(index);
+        self.set_buffer(self.history[index].chars().collect());
+    }
+
+    fn recall_newer(&mut self) {
+        let Some(index) = self.browsing else {
+            return;
+        };
+        if index + 1 < self.history.len() {
+            self.browsing = Some(index + 1);
+            self.set_buffer(self.history[index + 1].chars().collect());
+        } else {
+            self.browsing = None;
+            let draft = std::mem::take(&mut self.draft);
+            self.set_buffer(draft);
+        }
+    }
+
+    fn set_buffer(&mut self, buffer: Vec<char>) {
+        self.cursor = buffer.len();
+        self.buffer = buffer;
+    }
+
+    fn submit(&mut self) -> String {
+        let line: String = self.buffer.iter().collect();
+        self.clear();
+        if !line.trim().is_empty() && self.history.last() != Some(&line) {
+            self.history.push(line.clone());
+            if self.history.len() > MAX_HISTORY {
+                self.history.remove(0);
+            }
+        }
+        line
+    }
+
+    /// Drop the line being edited
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+        self.cursor = 0;
+        self.browsing = None;
+        self.draft.clear();
+    }
+
+    pub fn text(&self) -> String {
+        self.buffer.iter().collect()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+}
+
+impl Default for LineEditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_text(editor: &mut LineEditor, text: &str) {
+        for c in text.chars() {
+            editor.handle(Key::Char(c));
+        }
+    }
+
+    #[test]
+    fn test_cursor_editing() {
+        let mut editor = LineEditor::new();
+        type_text(&mut editor, "ech hllo");
+        editor.handle(Key::Home);
+        for _ in 0..3 {
+            editor.handle(Key::Right);
+        }
+        editor.handle(Key::Char('o'));
+        editor.handle(Key::End);
+        for _ in 0..3 {
+            editor.handle(Key::Left);
+        }
+        editor.handle(Key::Char('e'));
+        editor.handle(Key::Backspace);
+        editor.handle(Key::Backspace);
+        editor.handle(Key::Char('e'));
+        editor.handle(Key::Delete);
+        editor.handle(Key::Char('h'));
+        assert_eq!(editor.text(), "echo ehlo");
+        assert_eq!(editor.cursor(), 7);
+        assert_eq!(editor.handle(Key::Enter), Some("echo ehlo".to_string()));
+        assert!(editor.is_empty());
+    }
+
+    #[test]
+    fn test_history_recall() {
+        let mut editor = LineEditor::new();
+        for line in ["ls", "ls", "help", "   "] {
+            type_text(&mut editor, line);
+            editor.handle(Key::Enter);
+        }
+        assert_eq!(editor.history(), ["ls", "help"]);
+
+        type_text(&mut editor, "ec");
+        editor.handle(Key::Up);
+        assert_eq!(editor.text(), "help");
+        editor.handle(Key::Up);
+        editor.handle(Key::Up);
+        assert_eq!(editor.text(), "ls");
+        editor.handle(Key::Down);
+        editor.handle(Key::Down);
+        assert_eq!(editor.text(), "ec");
+    }
+}
@@ -0,0 +1,246 @@
+//! hairr OS Terminal
+//!
+//! Terminal emulator running command-line programs on a PTY. Keys either go
+//! to the local line editor or, when the program has switched the PTY out
+//! of canonical mode, straight to the program. Output is interpreted into
+//! a scrollback buffer that the desktop shell renders inside a window,
+//! in the colors of the desktop theme.
+
+use std::sync::Arc;
+
+use ipc::{open_pty, IPCManager, PtyMaster, WindowSize};
+use theme::{Mode, Palette, Theme};
+
+pub mod console;
+pub mod editor;
+pub mod scrollback;
+
+pub use console::{Console, Program, PROMPT};
+pub use editor::{Key, LineEditor};
+pub use scrollback::{Scrollback, DEFAULT_SCROLLBACK_LINES};
+
+/// Pixel size of one character cell
+pub const CELL_WIDTH: u32 = 8;
+pub const CELL_HEIGHT: u32 = 16;
+
+/// Character grid that fits in a window of the given pixel size
+pub fn size_for_pixels(width: u32, height: u32) -> WindowSize {
+    WindowSize::new(
+        (height / CELL_HEIGHT).clamp(1, u16::MAX as u32) as u16,
+        (width / CELL_WIDTH).clamp(1, u16::MAX as u32) as u16,
+    )
+}
+
+/// A terminal session
+pub struct Terminal {
+    master: PtyMaster,
+    console: Console,
+    editor: LineEditor,
+    scrollback: Scrollback,
+    palette: Palette,
+}
+
+impl Terminal {
+    pub fn new(ipc: &Arc<IPCManager>, size: WindowSize) -> Result<Self, String> {
+        let (master, slave) = open_pty(ipc, size);
+        let mut terminal = Terminal {
+            master,
+            console: Console::new(slave)?,
+            editor: LineEditor::new(),
+            scrollback: Scrollback::new(size.cols as usize, DEFAULT_SCROLLBACK_LINES),
+            palette: *Theme::default().palette(Mode::Light),
+        };
+        terminal.pump()?;
+        Ok(terminal)
+    }
+
+    /// Make a program runnable from the console
+    pub fn register(&mut self, name: &str, program: Program) {
+        self.console.register(name, program);
+    }
+
+    /// Handle a key press
+    pub fn press(&mut self, key: Key) -> Result<(), String> {
+        if self.has_exited() {
+            return Err("Terminal session has ended".to_string());
+        }
+        self.scrollback.scroll_to_bottom();
+        if self.master.termios().canonical {
+            match key {
+                Key::Interrupt => {
+                    self.editor.clear();
+                    self.master.write(&key.to_bytes())?;
+                }
+                Key::EndOfFile if self.editor.is_empty() => self.master.write(&key.to_bytes())?,
+                _ => {
+                    if let Some(line) = self.editor.handle(key) {
+                        self.master.write(format!("{}\r", line).as_bytes())?;
+                    }
+                }
+            }
+        } else {
+            self.master.write(&key.to_bytes())?;
+        }
+        self.pump()
+    }
+
+    /// Type text, treating newlines as enter
+    pub fn type_text(&mut self, text: &str) -> Result<(), String> {
+        for c in text.chars() {
+            let key = match c {
+                '\n' | '\r' => Key::Enter,
+                c => Key::Char(c),
+            };
+            self.press(key)?;
+        }
+        Ok(())
+    }
+
+    /// Let the console run and collect its output
+    pub fn pump(&mut self) -> Result<(), String> {
+        self.collect_output()?;
+        self.console.poll()?;
+        self.collect_output()?;
+        if self.console.has_exited() {
+            self.master.close();
+        }
+        Ok(())
+    }
+
+    fn collect_output(&mut self) -> Result<(), String> {
+        let output = self.master.read()?;
+        self.scrollback.feed(&String::from_utf8_lossy(&output));
+        Ok(())
+    }
+
+    pub fn has_exited(&self) -> bool {
+        self.console.has_exited()
+    }
+
+    pub fn size(&self) -> WindowSize {
+        self.master.size()
+    }
+
+    /// Follow a change of the window size
+    pub fn resize(&mut self, size: WindowSize) {
+        self.master.resize(size);
+        self.scrollback.set_cols(size.cols as usize);
+    }
+
+    pub fn scroll_up(&mut self, lines: usize) {
+        self.scrollback.scroll_up(lines);
+    }
+
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.scrollback.scroll_down(lines);
+    }
+
+    pub fn history(&self) -> &[String] {
+        self.editor.history()
+    }
+
+    /// Screen contents, with the line being edited after the prompt
+    pub fn render(&self) -> Vec<String> {
+        let mut lines = self.scrollback.view(self.size().rows as usize);
+        if self.scrollback.at_bottom() {
+            if let Some(last) = lines.last_mut() {
+                last.push_str(&self.editor.text());
+            }
+        }
+        lines
+    }
+
+    /// Follow a change of theme or of dark/light mode
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// Screen contents in the palette's colors, for an ANSI console
+    pub fn render_ansi(&self) -> String {
+        format!(
+            "{}{}{}\x1b[K\x1b[0m",
+            self.palette.window.ansi_background(),
+            self.palette.text.ansi_foreground(),
+            self.render().join("\x1b[K\n")
+        )
+    }
+
+    /// End the session
+    pub fn close(&self) {
+        self.master.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terminal() -> Terminal {
+        Terminal::new(&Arc::new(IPCManager::new()), WindowSize::new(5, 40)).unwrap()
+    }
+
+    #[test]
+    fn test_run_commands() {
+        let mut terminal = terminal();
+        terminal.register(
+            "upper",
+            Arc::new(|args: &[String]| match args {
+                [] => Err("missing argument".to_string()),
+                args => Ok(args.join(" ").to_uppercase()),
+            }),
+        );
+        terminal.type_text("echo  hi there\nupper abc\nupper\nnope\nup").unwrap();
+        assert_eq!(
+            terminal.render(),
+            vec![
+                "$ upper",
+                "upper: missing argument",
+                "$ nope",
+                "nope: command not found",
+                "$ up",
+            ]
+        );
+        terminal.scroll_up(4);
+        assert_eq!(terminal.render()[0], "$ echo  hi there");
+        terminal.press(Key::Char('p')).unwrap();
+        assert_eq!(terminal.render().last().unwrap(), "$ upp");
+        assert_eq!(terminal.history().len(), 4);
+    }
+
+    #[test]
+    fn test_interrupt_and_exit() {
+        let mut terminal = terminal();
+        terminal.type_text("half typed").unwrap();
+        terminal.press(Key::Interrupt).unwrap();
+        assert_eq!(terminal.render(), vec!["$ ^C", "$ "]);
+
+        terminal.type_text("clear\n").unwrap();
+        assert_eq!(terminal.render(), vec!["$ "]);
+        terminal.press(Key::EndOfFile).unwrap();
+        assert!(terminal.has_exited());
+        assert!(terminal.press(Key::Enter).is_err());
+    }
+
+    #[test]
+    fn test_dark_mode_colors() {
+        let mut terminal = terminal();
+        let dark = *Theme::default().palette(Mode::Dark);
+        terminal.set_palette(dark);
+        let screen = terminal.render_ansi();
+        assert!(screen.starts_with(&format!("{}{}$ ", dark.window.ansi_background(), dark.text.ansi_foreground())));
+        assert!(screen.ends_with("\x1b[0m"));
+    }
+
+    #[test]
+    fn test_window_size() {
+        assert_eq!(size_for_pixels(800, 600), WindowSize::new(37, 100));
+        let mut terminal = terminal();
+        terminal.resize(WindowSize::new(10, 8));
+        terminal.type_text("echo abcdefghij\n").unwrap();
+        assert_eq!(terminal.render(), vec!["$ echo a", "bcdefghi", "j", "abcdefgh", "ij", "$ "]);
+    }
+}
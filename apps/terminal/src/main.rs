@@ -0,0 +1,44 @@
+//! hairr OS Terminal
+//!
+//! Standalone terminal session driven from standard input. Pass `--dark`
+//! for the dark variant of the default theme.
+
+use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+
+use ipc::{IPCManager, WindowSize};
+use terminal::Terminal;
+use theme::{Mode, Theme};
+
+fn main() {
+    let ipc = Arc::new(IPCManager::new());
+    let mut terminal = match Terminal::new(&ipc, WindowSize::new(24, 80)) {
+        Ok(terminal) => terminal,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return;
+        }
+    };
+    if std::env::args().any(|arg| arg == "--dark") {
+        terminal.set_palette(*Theme::default().palette(Mode::Dark));
+    }
+    terminal.register("uname", Arc::new(|_: &[String]| Ok("hairr OS 0.1.0".to_string())));
+
+    let stdin = io::stdin();
+    loop {
+        print!("\x1b[2J\x1b[H{}", terminal.render_ansi());
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 || terminal.has_exited() {
+            break;
+        }
+        if let Err(e) = terminal.type_text(&line) {
+            eprintln!("Error: {}", e);
+            break;
+        }
+        if terminal.has_exited() {
+            break;
+        }
+    }
+    println!();
+}
@@ -0,0 +1,197 @@
+//! Scrollback buffer
+//!
+//! Interprets terminal output into lines of text. Handles carriage
+//! returns, backspaces, tabs, wrapping at the terminal width and the few
+//! escape sequences programs use to clear the screen or erase a line.
+
+use std::collections::VecDeque;
+
+/// Lines kept once they scroll off the top
+pub const DEFAULT_SCROLLBACK_LINES: usize = 1000;
+
+const TAB_WIDTH: usize = 8;
+
+enum Escape {
+    None,
+    Started,
+    /// Control sequence introducer seen, collecting parameters
+    Csi(String),
+}
+
+/// Terminal output history
+pub struct Scrollback {
+    lines: VecDeque<String>,
+    current: Vec<char>,
+    column: usize,
+    cols: usize,
+    capacity: usize,
+    /// Lines scrolled back from the bottom
+    offset: usize,
+    escape: Escape,
+}
+
+impl Scrollback {
+    pub fn new(cols: usize, capacity: usize) -> Self {
+        Scrollback {
+            lines: VecDeque::new(),
+            current: Vec::new(),
+            column: 0,
+            cols: cols.max(1),
+            capacity: capacity.max(1),
+            offset: 0,
+            escape: Escape::None,
+        }
+    }
+
+    /// Interpret a chunk of output
+    pub fn feed(&mut self, text: &str) {
+        for c in text.chars() {
+            match std::mem::replace(&mut self.escape, Escape::None) {
+                Escape::Started if c == '[' => self.escape = Escape::Csi(String::new()),
+                Escape::Started => {}
+                Escape::Csi(params) if ('@'..='~').contains(&c) => self.control(&params, c),
+                Escape::Csi(mut params) => {
+                    params.push(c);
+                    self.escape = Escape::Csi(params);
+                }
+                Escape::None => self.put(c),
+            }
+        }
+    }
+
+    fn put(&mut self, c: char) {
+        match c {
+            '\x1b' => self.escape = Escape::Started,
+            '\n' => self.newline(),
+            '\r' => self.column = 0,
+            '\x08' => self.column = self.column.saturating_sub(1),
+            '\t' => self.column = ((self.column / TAB_WIDTH + 1) * TAB_WIDTH).min(self.cols),
+            c if c.is_control() => {}
+            c => self.write_char(c),
+        }
+    }
+
+    fn write_char(&mut self, c: char) {
+        if self.column >= self.cols {
+            self.newline();
+        }
+        if self.column < self.current.len() {
+            self.current[self.column] = c;
+        } else {
+            self.current.resize(self.column, ' ');
+            self.current.push(c);
+        }
+        self.column += 1;
+    }
+
+    fn newline(&mut self) {
+        self.lines.push_back(self.current.drain(..).collect());
+        self.column = 0;
+        while self.lines.len() > self.capacity {
+            self.lines.pop_front();
+        }
+        self.offset = self.offset.min(self.lines.len());
+    }
+
+    fn control(&mut self, params: &str, command: char) {
+        let count = params.parse::<usize>().unwrap_or(1).max(1);
+        match command {
+            'J' if params == "2" || params == "3" => self.clear(),
+            'K' => self.current.truncate(self.column),
+            'C' => self.column += count,
+            'D' => self.column = self.column.saturating_sub(count),
+            _ => {}
+        }
+    }
+
+    /// Forget everything, as after a clear screen sequence
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.current.clear();
+        self.column = 0;
+        self.offset = 0;
+    }
+
+    /// Completed lines still held
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty() && self.current.is_empty()
+    }
+
+    /// The unterminated line the cursor is on
+    pub fn current_line(&self) -> String {
+        self.current.iter().collect()
+    }
+
+    /// Width used for wrapping future output
+    pub fn set_cols(&mut self, cols: usize) {
+        self.cols = cols.max(1);
+    }
+
+    pub fn scroll_up(&mut self, lines: usize) {
+        self.offset = (self.offset + lines).min(self.lines.len());
+    }
+
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.offset = self.offset.saturating_sub(lines);
+    }
+
+    pub fn scroll_to_bottom(&mut self) {
+        self.offset = 0;
+    }
+
+    pub fn at_bottom(&self) -> bool {
+        self.offset == 0
+    }
+
+    /// The `rows` lines on screen; at the bottom the last one is the current line
+    pub fn view(&self, rows: usize) -> Vec<String> {
+        let mut all: Vec<String> = self.lines.iter().cloned().collect();
+        all.push(self.current_line());
+        let end = all.len() - self.offset;
+        let start = end.saturating_sub(rows);
+        all[start..end].to_vec()
+    }
+}
+
+impl Default for Scrollback {
+    fn default() -> Self {
+        Self::new(80, DEFAULT_SCROLLBACK_LINES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_handling() {
+        let mut scrollback = Scrollback::new(10, 100);
+        scrollback.feed("hello\r\nworld\rW\tx\r\n");
+        scrollback.feed("abc\x08\x08Z\x1b[K");
+        assert_eq!(scrollback.view(10), vec!["hello", "World   x", "aZ"]);
+
+        scrollback.feed("\r\n0123456789ab");
+        assert_eq!(scrollback.view(2), vec!["0123456789", "ab"]);
+        scrollback.feed("\x1b[2J");
+        assert!(scrollback.is_empty());
+    }
+
+    #[test]
+    fn test_scrolling_and_capacity() {
+        let mut scrollback = Scrollback::new(80, 3);
+        for i in 0..5 {
+            scrollback.feed(&format!("line {}\n", i));
+        }
+        assert_eq!(scrollback.len(), 3);
+        assert_eq!(scrollback.view(2), vec!["line 4", ""]);
+        scrollback.scroll_up(10);
+        assert_eq!(scrollback.view(2), vec!["line 2"]);
+        scrollback.scroll_down(1);
+        assert_eq!(scrollback.view(2), vec!["line 2", "line 3"]);
+        assert!(!scrollback.at_bottom());
+    }
+}
@@ -0,0 +1,269 @@
+//! Guest agent protocol
+//!
+//! An agent inside each guest runs commands, starts apps and reports what
+//! is installed on Chrysalis' behalf. It is reached over two IPC channels:
+//! requests go to the guest on one, and responses come back on the other
+//! together with guest events, which are JSON text messages and can arrive
+//! at any time, including while a request waits for its response.
+//!
+//! ```json
+//! {"op": "launch", "target": "/usr/bin/gimp"}
+//! {"event": "booted"}
+//! {"result": "launched", "pid": 412}
+//! ```
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use ipc::service::handle_json;
+use ipc::{ChannelId, IPCManager, Message};
+use serde::{Deserialize, Serialize};
+use system_utils::time::Deadline;
+
+use crate::android::{ApkInfo, DataDir};
+use crate::VirtualMachine;
+
+/// How long Chrysalis waits for the agent to respond
+pub const AGENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Requests sent to the agent
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum AgentRequest {
+    /// Run a command and wait for it to exit
+    Exec {
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+    /// Start an executable, or an Android package, without waiting for it
+    Launch { target: String },
+    ListApps,
+    SetClipboard { mime_type: String, data: String },
+    /// Install an APK, reading its manifest
+    InstallPackage { apk: Vec<u8> },
+    /// Mount an installed app's data directory and grant it permissions
+    ConfigurePackage { package: String, data: DataDir, granted: Vec<String> },
+    UninstallPackage { package: String },
+    /// Bring vCPUs online or take them offline until `count` are online
+    SetCpus { count: usize },
+    /// Hand `mb` of memory back to the host
+    InflateBalloon { mb: usize },
+    /// Take back `mb` of memory handed back before
+    DeflateBalloon { mb: usize },
+    /// Bring `mb` of newly plugged memory online
+    HotplugMemory { mb: usize },
+}
+
+/// How a command run in the guest ended
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandOutput {
+    pub code: i32,
+    #[serde(default)]
+    pub stdout: String,
+    #[serde(default)]
+    pub stderr: String,
+}
+
+/// App installed in the guest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstalledApp {
+    /// Package name, or path of the executable
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub version: String,
+}
+
+/// Responses sent by the agent
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum AgentResponse {
+    Exited(CommandOutput),
+    Launched { pid: u64 },
+    Apps { apps: Vec<InstalledApp> },
+    Installed(ApkInfo),
+    Ok,
+    Error { message: String },
+}
+
+/// Events the guest reports on its own
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum GuestEvent {
+    Booted,
+    AppExited { pid: u64, code: i32 },
+    ClipboardChanged { mime_type: String, data: String },
+    ShuttingDown,
+}
+
+/// A VM's agent channels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AgentChannels {
+    /// Host to guest
+    pub requests: ChannelId,
+    /// Guest to host: responses and events
+    pub replies: ChannelId,
+}
+
+/// Connects the agent inside a guest to its channels once the VM runs
+pub type AgentConnector = Arc<dyn Fn(&VirtualMachine, AgentChannels) -> Result<(), String> + Send + Sync>;
+
+/// Host end of a VM's agent connection
+pub struct GuestAgent {
+    ipc: Arc<IPCManager>,
+    channels: AgentChannels,
+    /// Held while a request waits, so responses go to whoever asked
+    next_id: Mutex<u64>,
+    /// Events that arrived while requests waited
+    events: Mutex<Vec<GuestEvent>>,
+    timeout: Duration,
+}
+
+impl GuestAgent {
+    /// Open a fresh pair of channels for an agent
+    pub fn open(ipc: Arc<IPCManager>, timeout: Duration) -> Self {
+        let channels = AgentChannels {
+            requests: ipc.create_channel(),
+            replies: ipc.create_channel(),
+        };
+        GuestAgent {
+            ipc,
+            channels,
+            next_id: Mutex::new(1),
+            events: Mutex::new(Vec::new()),
+            timeout,
+        }
+    }
+
+    pub fn channels(&self) -> AgentChannels {
+        self.channels
+    }
+
+    /// Send a request and wait for its response; an error the agent
+    /// responds with is returned as `Err`
+    pub fn request(&self, request: &AgentRequest) -> Result<AgentResponse, String> {
+        let mut next_id = self.next_id.lock().unwrap();
+        let id = *next_id;
+        *next_id += 1;
+        self.ipc.send_message(
+            self.channels.requests,
+            Message::Request {
+                id,
+                data: serde_json::to_vec(request).unwrap(),
+            },
+        )?;
+        let deadline = Deadline::after(self.timeout);
+        loop {
+            match self.ipc.receive_message(self.channels.replies)? {
+                Some(Message::Response { id: reply, data }) if reply == id => {
+                    return match serde_json::from_slice(&data) {
+                        Ok(AgentResponse::Error { message }) => Err(message),
+                        Ok(response) => Ok(response),
+                        Err(e) => Err(format!("Invalid response from the guest agent: {}", e)),
+                    }
+                }
+                Some(Message::Error { message, .. }) => return Err(message),
+                Some(message) => self.collect(message),
+                None if deadline.expired() => return Err("The guest agent did not respond".to_string()),
+                None => thread::sleep(Duration::from_millis(1)),
+            }
+        }
+    }
+
+    /// Events received since last asked, oldest first
+    pub fn events(&self) -> Result<Vec<GuestEvent>, String> {
+        // Not racing a request for the reply channel
+        let _guard = self.next_id.lock().unwrap();
+        while let Some(message) = self.ipc.receive_message(self.channels.replies)? {
+            self.collect(message);
+        }
+        Ok(std::mem::take(&mut *self.events.lock().unwrap()))
+    }
+
+    /// Close both channels; the agent sees them gone
+    pub fn close(&self) {
+        self.ipc.close_channel(self.channels.requests);
+        self.ipc.close_channel(self.channels.replies);
+    }
+
+    /// Keep an event; stray responses and anything unreadable are dropped
+    fn collect(&self, message: Message) {
+        if let Message::Text(text) = message {
+            if let Ok(event) = serde_json::from_str(&text) {
+                self.events.lock().unwrap().push(event);
+            }
+        }
+    }
+}
+
+/// Answer one request on the guest side with `handler`
+pub fn handle_message(message: &Message, handler: &mut dyn FnMut(AgentRequest) -> AgentResponse) -> Option<Message> {
+    handle_json("agent", message, handler)
+}
+
+/// Answer the requests waiting on `channels` on the guest side, returning
+/// how many there were
+pub fn serve(ipc: &IPCManager, channels: AgentChannels, handler: &mut dyn FnMut(AgentRequest) -> AgentResponse) -> Result<usize, String> {
+    let mut handled = 0;
+    while let Some(message) = ipc.receive_message(channels.requests)? {
+        if let Some(reply) = handle_message(&message, handler) {
+            ipc.send_message(channels.replies, reply)?;
+        }
+        handled += 1;
+    }
+    Ok(handled)
+}
+
+/// Report an event from the guest side
+pub fn send_event(ipc: &IPCManager, channels: AgentChannels, event: &GuestEvent) -> Result<(), String> {
+    ipc.send_message(channels.replies, Message::Text(serde_json::to_string(event).unwrap())).map_err(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ipc::ERROR_BAD_REQUEST;
+
+    #[test]
+    fn test_request_with_events() {
+        let ipc = Arc::new(IPCManager::new());
+        let agent = GuestAgent::open(Arc::clone(&ipc), AGENT_TIMEOUT);
+        let channels = agent.channels();
+        send_event(&ipc, channels, &GuestEvent::Booted).unwrap();
+
+        let guest = Arc::clone(&ipc);
+        let server = thread::spawn(move || {
+            let mut handler = |request| match request {
+                AgentRequest::Exec { command, args } => AgentResponse::Exited(CommandOutput {
+                    code: 0,
+                    stdout: format!("{} {}", command, args.join(" ")),
+                    stderr: String::new(),
+                }),
+                _ => AgentResponse::Error { message: "Not supported".to_string() },
+            };
+            let mut handled = 0;
+            while handled < 2 {
+                handled += serve(&guest, channels, &mut handler).unwrap();
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+        let exec = AgentRequest::Exec { command: "echo".to_string(), args: vec!["hi".to_string()] };
+        match agent.request(&exec).unwrap() {
+            AgentResponse::Exited(output) => assert_eq!(output.stdout, "echo hi"),
+            other => panic!("unexpected response {:?}", other),
+        }
+        assert_eq!(agent.request(&AgentRequest::ListApps).unwrap_err(), "Not supported");
+        server.join().unwrap();
+
+        send_event(&ipc, channels, &GuestEvent::ShuttingDown).unwrap();
+        assert_eq!(agent.events().unwrap(), vec![GuestEvent::Booted, GuestEvent::ShuttingDown]);
+        assert!(agent.events().unwrap().is_empty());
+
+        let bad = Message::Request { id: 9, data: b"{\"op\": \"reboot\"}".to_vec() };
+        assert!(matches!(handle_message(&bad, &mut |_| AgentResponse::Ok), Some(Message::Error { code: ERROR_BAD_REQUEST, .. })));
+        agent.close();
+        assert!(agent.request(&AgentRequest::ListApps).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+//! Android packages
+//!
+//! APKs are installed by the agent in the Android VM, which reads the
+//! package name and requested permissions from the manifest. Chrysalis then
+//! gives the app a data directory of its own on the VM disk and grants the
+//! Android permissions that map to hairr capabilities; the rest are denied
+//! in the guest. Uninstalling an app wipes its data directory.
+//!
+//! ```json
+//! {"op": "configure_package", "package": "org.fdroid.fdroid",
+//!  "data": {"path": "/data/data/org.fdroid.fdroid", "offset": 10737418240, "size": 268435456},
+//!  "granted": ["android.permission.INTERNET"]}
+//! ```
+
+use capability::{Permission, Resource};
+use serde::{Deserialize, Serialize};
+
+use crate::snapshot::BLOCK_SIZE;
+use crate::VmId;
+
+/// Room each app has for its data
+pub const APP_DATA_SIZE: u64 = 256 << 20;
+
+/// Where apps see their data directory
+pub const DATA_ROOT: &str = "/data/data";
+
+/// Files apps share with the rest of the system
+pub const SHARED_STORAGE: &str = "/home/shared";
+
+/// Capability an Android permission needs on the host, if any
+type Needs = Option<(ResourceKind, &'static str, Permission)>;
+
+/// Android permissions apps are granted
+const PERMISSIONS: &[(&str, Needs)] = &[
+    ("android.permission.INTERNET", Some((ResourceKind::Network, "*", Permission::ReadWrite))),
+    ("android.permission.ACCESS_NETWORK_STATE", None),
+    ("android.permission.WAKE_LOCK", None),
+    ("android.permission.RECEIVE_BOOT_COMPLETED", None),
+    ("android.permission.VIBRATE", Some((ResourceKind::Device, "vibrator", Permission::Write))),
+    ("android.permission.CAMERA", Some((ResourceKind::Device, "camera", Permission::Read))),
+    ("android.permission.RECORD_AUDIO", Some((ResourceKind::Device, "microphone", Permission::Read))),
+    ("android.permission.ACCESS_FINE_LOCATION", Some((ResourceKind::Device, "location", Permission::Read))),
+    ("android.permission.ACCESS_COARSE_LOCATION", Some((ResourceKind::Device, "location", Permission::Read))),
+    ("android.permission.BLUETOOTH_CONNECT", Some((ResourceKind::Device, "bluetooth", Permission::ReadWrite))),
+    ("android.permission.READ_CONTACTS", Some((ResourceKind::Ipc, "contacts", Permission::Read))),
+    ("android.permission.WRITE_CONTACTS", Some((ResourceKind::Ipc, "contacts", Permission::ReadWrite))),
+    ("android.permission.POST_NOTIFICATIONS", Some((ResourceKind::Ipc, "notifications", Permission::Write))),
+    ("android.permission.READ_EXTERNAL_STORAGE", Some((ResourceKind::File, SHARED_STORAGE, Permission::Read))),
+    ("android.permission.WRITE_EXTERNAL_STORAGE", Some((ResourceKind::File, SHARED_STORAGE, Permission::ReadWrite))),
+];
+
+#[derive(Debug, Clone, Copy)]
+enum ResourceKind {
+    File,
+    Network,
+    Device,
+    Ipc,
+}
+
+/// What the agent read from an APK it installed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApkInfo {
+    pub package: String,
+    pub label: String,
+    pub version: String,
+    /// Android permissions the manifest asks for
+    #[serde(default)]
+    pub permissions: Vec<String>,
+}
+
+/// An app's share of the VM disk, mounted at `path` in the guest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DataDir {
+    pub path: String,
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// Android app installed through Chrysalis
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AndroidApp {
+    pub info: ApkInfo,
+    pub vm_id: VmId,
+    pub data: DataDir,
+    /// Permissions granted in the guest
+    pub granted: Vec<String>,
+    /// What the granted permissions need on the host
+    pub capabilities: Vec<(Resource, Permission)>,
+}
+
+/// Split the permissions an app asks for into those granted, with the
+/// capabilities they need, and those denied
+pub fn map_permissions(permissions: &[String]) -> (Vec<String>, Vec<(Resource, Permission)>, Vec<String>) {
+    let (mut granted, mut capabilities, mut denied) = (Vec::new(), Vec::new(), Vec::new());
+    for permission in permissions {
+        match PERMISSIONS.iter().find(|(name, _)| name == permission) {
+            Some((_, needs)) => {
+                granted.push(permission.clone());
+                if let Some((kind, name, access)) = needs {
+                    let name = name.to_string();
+                    let resource = match kind {
+                        ResourceKind::File => Resource::File(name),
+                        ResourceKind::Network => Resource::Network(name),
+                        ResourceKind::Device => Resource::Device(name),
+                        ResourceKind::Ipc => Resource::IPC(name),
+                    };
+                    if !capabilities.contains(&(resource.clone(), *access)) {
+                        capabilities.push((resource, *access));
+                    }
+                }
+            }
+            None => denied.push(permission.clone()),
+        }
+    }
+    (granted, capabilities, denied)
+}
+
+/// Find room for another app's data in the upper half of a disk of
+/// `capacity` bytes, where the lower half holds the Android system
+pub fn allocate_data(package: &str, capacity: u64, taken: &[&DataDir]) -> Result<DataDir, String> {
+    let start = (capacity / 2).div_ceil(BLOCK_SIZE as u64) * BLOCK_SIZE as u64;
+    let mut offset = start;
+    while offset + APP_DATA_SIZE <= capacity {
+        if !taken.iter().any(|d| d.offset == offset) {
+            return Ok(DataDir {
+                path: format!("{}/{}", DATA_ROOT, package),
+                offset,
+                size: APP_DATA_SIZE,
+            });
+        }
+        offset += APP_DATA_SIZE;
+    }
+    Err(format!("No room on the VM disk for the data of {}", package))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permission_mapping() {
+        let asked: Vec<String> = ["android.permission.INTERNET", "android.permission.WAKE_LOCK", "android.permission.READ_SMS", "android.permission.ACCESS_FINE_LOCATION", "android.permission.ACCESS_COARSE_LOCATION"]
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        let (granted, capabilities, denied) = map_permissions(&asked);
+        assert_eq!(granted.len(), 4);
+        assert_eq!(denied, vec!["android.permission.READ_SMS"]);
+        assert_eq!(
+            capabilities,
+            vec![(Resource::Network("*".to_string()), Permission::ReadWrite), (Resource::Device("location".to_string()), Permission::Read)]
+        );
+    }
+
+    #[test]
+    fn test_data_allocation() {
+        let capacity = 4 * APP_DATA_SIZE;
+        let first = allocate_data("a", capacity, &[]).unwrap();
+        assert_eq!((first.path.as_str(), first.offset), ("/data/data/a", 2 * APP_DATA_SIZE));
+        let second = allocate_data("b", capacity, &[&first]).unwrap();
+        assert_eq!(second.offset, 3 * APP_DATA_SIZE);
+        assert!(allocate_data("c", capacity, &[&first, &second]).is_err());
+        assert_eq!(allocate_data("c", capacity, &[&second]).unwrap().offset, first.offset);
+    }
+}
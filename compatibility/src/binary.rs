@@ -0,0 +1,363 @@
+//! Foreign binary detection
+//!
+//! What a file is comes from its contents, never its name: ELF headers
+//! give the word size, machine and OS ABI of executables and libraries,
+//! ZIP archives are APKs only when they hold an `AndroidManifest.xml`, and
+//! scripts name their interpreter on a `#!` line. Linux packages and
+//! AppImages are recognised by their own magic numbers.
+
+use crate::GuestOS;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const AR_MAGIC: &[u8] = b"!<arch>\n";
+const RPM_MAGIC: &[u8] = b"\xed\xab\xee\xdb";
+/// At offset 8 of an AppImage's ELF header
+const APPIMAGE_MAGIC: &[u8] = b"AI\x02";
+
+const ZIP_END_OF_DIRECTORY: u32 = 0x0605_4b50;
+const ZIP_DIRECTORY_ENTRY: u32 = 0x0201_4b50;
+
+/// Program header type naming the dynamic linker
+const PT_INTERP: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryKind {
+    Elf,
+    AppImage,
+    Apk,
+    DebPackage,
+    RpmPackage,
+    Script,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfClass {
+    Elf32,
+    Elf64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfType {
+    Relocatable,
+    Executable,
+    SharedObject,
+    Core,
+    Other(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Machine {
+    X86,
+    X86_64,
+    Arm,
+    Aarch64,
+    RiscV,
+    Other(u16),
+}
+
+impl Machine {
+    /// Machine of the host
+    pub fn host() -> Self {
+        match std::env::consts::ARCH {
+            "x86" => Machine::X86,
+            "x86_64" => Machine::X86_64,
+            "arm" => Machine::Arm,
+            "aarch64" => Machine::Aarch64,
+            "riscv64" => Machine::RiscV,
+            _ => Machine::Other(0),
+        }
+    }
+
+    /// Whether VMs on a host of this machine run code built for `other`
+    pub fn runs(&self, other: Machine) -> bool {
+        *self == other || matches!((self, other), (Machine::X86_64, Machine::X86) | (Machine::Aarch64, Machine::Arm))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OsAbi {
+    SystemV,
+    Linux,
+    FreeBsd,
+    Other(u8),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElfInfo {
+    pub class: ElfClass,
+    pub big_endian: bool,
+    pub elf_type: ElfType,
+    pub machine: Machine,
+    pub os_abi: OsAbi,
+}
+
+/// What a foreign file is and where it runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BinaryInfo {
+    pub kind: BinaryKind,
+    /// `None` for binaries no guest runs, such as FreeBSD ones
+    pub guest_os: Option<GuestOS>,
+    /// For ELF files and AppImages
+    pub elf: Option<ElfInfo>,
+    /// Dynamic linker of an ELF file, or the program a script names
+    pub interpreter: Option<String>,
+    /// What a script passes its interpreter before itself
+    pub interpreter_args: Option<String>,
+}
+
+impl BinaryInfo {
+    fn new(kind: BinaryKind, guest_os: Option<GuestOS>) -> Self {
+        BinaryInfo {
+            kind,
+            guest_os,
+            elf: None,
+            interpreter: None,
+            interpreter_args: None,
+        }
+    }
+
+    /// Whether a VM on this host can run it
+    pub fn runs_on_host(&self) -> bool {
+        self.guest_os.is_some() && self.elf.as_ref().is_none_or(|elf| Machine::host().runs(elf.machine))
+    }
+}
+
+/// What `data` is, if it needs a guest OS or is one Chrysalis knows of
+pub fn inspect(data: &[u8]) -> Option<BinaryInfo> {
+    if data.starts_with(ELF_MAGIC) {
+        return inspect_elf(data);
+    }
+    if data.starts_with(ZIP_MAGIC) {
+        return zip_names(data)?
+            .iter()
+            .any(|name| name == "AndroidManifest.xml")
+            .then(|| BinaryInfo::new(BinaryKind::Apk, Some(GuestOS::Android)));
+    }
+    if data.starts_with(AR_MAGIC) && data[AR_MAGIC.len()..].starts_with(b"debian-binary") {
+        return Some(BinaryInfo::new(BinaryKind::DebPackage, Some(GuestOS::Linux)));
+    }
+    if data.starts_with(RPM_MAGIC) {
+        return Some(BinaryInfo::new(BinaryKind::RpmPackage, Some(GuestOS::Linux)));
+    }
+    if let Some(line) = data.strip_prefix(b"#!") {
+        let line = &line[..line.iter().position(|b| *b == b'\n').unwrap_or(line.len())];
+        let line = String::from_utf8_lossy(line);
+        let (interpreter, args) = match line.trim().split_once(char::is_whitespace) {
+            Some((interpreter, args)) => (interpreter.to_string(), Some(args.trim().to_string())),
+            None => (line.trim().to_string(), None),
+        };
+        if interpreter.is_empty() {
+            return None;
+        }
+        let mut info = BinaryInfo::new(BinaryKind::Script, Some(GuestOS::Linux));
+        info.interpreter = Some(interpreter);
+        info.interpreter_args = args;
+        return Some(info);
+    }
+    None
+}
+
+fn inspect_elf(data: &[u8]) -> Option<BinaryInfo> {
+    let class = match *data.get(4)? {
+        1 => ElfClass::Elf32,
+        2 => ElfClass::Elf64,
+        _ => return None,
+    };
+    let big_endian = match *data.get(5)? {
+        1 => false,
+        2 => true,
+        _ => return None,
+    };
+    let reader = Reader { data, big_endian };
+    let os_abi = match *data.get(7)? {
+        0 => OsAbi::SystemV,
+        3 => OsAbi::Linux,
+        9 => OsAbi::FreeBsd,
+        other => OsAbi::Other(other),
+    };
+    let elf_type = match reader.u16(16)? {
+        1 => ElfType::Relocatable,
+        2 => ElfType::Executable,
+        3 => ElfType::SharedObject,
+        4 => ElfType::Core,
+        other => ElfType::Other(other),
+    };
+    let machine = match reader.u16(18)? {
+        3 => Machine::X86,
+        62 => Machine::X86_64,
+        40 => Machine::Arm,
+        183 => Machine::Aarch64,
+        243 => Machine::RiscV,
+        other => Machine::Other(other),
+    };
+
+    let interpreter = reader.interpreter(class);
+    let kind = if data.get(8..11) == Some(APPIMAGE_MAGIC) { BinaryKind::AppImage } else { BinaryKind::Elf };
+    // Android executables are plain System V ELF files run by Bionic's linker
+    let guest_os = match os_abi {
+        _ if interpreter.as_deref().is_some_and(|i| i.starts_with("/system/bin/linker")) => Some(GuestOS::Android),
+        OsAbi::SystemV | OsAbi::Linux => Some(GuestOS::Linux),
+        _ => None,
+    };
+    let mut info = BinaryInfo::new(kind, guest_os);
+    info.elf = Some(ElfInfo {
+        class,
+        big_endian,
+        elf_type,
+        machine,
+        os_abi,
+    });
+    info.interpreter = interpreter;
+    Some(info)
+}
+
+/// Names of the files in a ZIP archive, from its central directory
+fn zip_names(data: &[u8]) -> Option<Vec<String>> {
+    let reader = Reader { data, big_endian: false };
+    // The end record is the last 22 bytes, unless an archive comment follows
+    let end = (0..=data.len().checked_sub(22)?).rev().find(|at| reader.u32(*at) == Some(ZIP_END_OF_DIRECTORY))?;
+    let count = reader.u16(end + 10)? as usize;
+    let mut at = reader.u32(end + 16)? as usize;
+    let mut names = Vec::with_capacity(count);
+    for _ in 0..count {
+        if reader.u32(at)? != ZIP_DIRECTORY_ENTRY {
+            return None;
+        }
+        let name_len = reader.u16(at + 28)? as usize;
+        let extra_len = reader.u16(at + 30)? as usize;
+        let comment_len = reader.u16(at + 32)? as usize;
+        names.push(String::from_utf8_lossy(data.get(at + 46..at + 46 + name_len)?).into_owned());
+        at += 46 + name_len + extra_len + comment_len;
+    }
+    Some(names)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&self, at: usize) -> Option<[u8; N]> {
+        let mut bytes: [u8; N] = self.data.get(at..at.checked_add(N)?)?.try_into().ok()?;
+        if self.big_endian {
+            bytes.reverse();
+        }
+        Some(bytes)
+    }
+
+    fn u16(&self, at: usize) -> Option<u16> {
+        self.bytes(at).map(u16::from_le_bytes)
+    }
+
+    fn u32(&self, at: usize) -> Option<u32> {
+        self.bytes(at).map(u32::from_le_bytes)
+    }
+
+    fn u64(&self, at: usize) -> Option<u64> {
+        self.bytes(at).map(u64::from_le_bytes)
+    }
+
+    /// Path in the `PT_INTERP` program header, if there is one
+    fn interpreter(&self, class: ElfClass) -> Option<String> {
+        let (table, entry_size, count) = match class {
+            ElfClass::Elf32 => (self.u32(28)? as usize, self.u16(42)? as usize, self.u16(44)? as usize),
+            ElfClass::Elf64 => (self.u64(32)? as usize, self.u16(54)? as usize, self.u16(56)? as usize),
+        };
+        for i in 0..count {
+            let header = table.checked_add(i.checked_mul(entry_size)?)?;
+            if self.u32(header)? != PT_INTERP {
+                continue;
+            }
+            let (offset, size) = match class {
+                ElfClass::Elf32 => (self.u32(header + 4)? as usize, self.u32(header + 16)? as usize),
+                ElfClass::Elf64 => (self.u64(header + 8)? as usize, self.u64(header + 32)? as usize),
+            };
+            let path = self.data.get(offset..offset.checked_add(size)?)?;
+            let path = &path[..path.iter().position(|b| *b == 0).unwrap_or(path.len())];
+            return Some(String::from_utf8_lossy(path).into_owned());
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// 64-bit little-endian ELF executable for `machine`, with a
+    /// `PT_INTERP` header naming `interpreter`
+    pub(crate) fn elf(machine: u16, os_abi: u8, interpreter: &str) -> Vec<u8> {
+        let mut data = vec![0u8; 64 + 56];
+        data[..4].copy_from_slice(ELF_MAGIC);
+        data[4..8].copy_from_slice(&[2, 1, 1, os_abi]);
+        data[16..18].copy_from_slice(&2u16.to_le_bytes());
+        data[18..20].copy_from_slice(&machine.to_le_bytes());
+        data[32..40].copy_from_slice(&64u64.to_le_bytes());
+        data[54..56].copy_from_slice(&56u16.to_le_bytes());
+        data[56..58].copy_from_slice(&1u16.to_le_bytes());
+        data[64..68].copy_from_slice(&PT_INTERP.to_le_bytes());
+        data[72..80].copy_from_slice(&120u64.to_le_bytes());
+        data[96..104].copy_from_slice(&(interpreter.len() as u64 + 1).to_le_bytes());
+        data.extend_from_slice(interpreter.as_bytes());
+        data.push(0);
+        data
+    }
+
+    /// ZIP archive of empty, stored files
+    pub(crate) fn zip(names: &[&str]) -> Vec<u8> {
+        let (mut data, mut directory) = (Vec::new(), Vec::new());
+        for name in names {
+            let offset = data.len() as u32;
+            data.extend_from_slice(ZIP_MAGIC);
+            data.extend_from_slice(&[0; 22]);
+            data.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            data.extend_from_slice(&[0; 2]);
+            data.extend_from_slice(name.as_bytes());
+
+            directory.extend_from_slice(&ZIP_DIRECTORY_ENTRY.to_le_bytes());
+            directory.extend_from_slice(&[0; 24]);
+            directory.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            directory.extend_from_slice(&[0; 12]);
+            directory.extend_from_slice(&offset.to_le_bytes());
+            directory.extend_from_slice(name.as_bytes());
+        }
+        let start = data.len() as u32;
+        data.extend_from_slice(&directory);
+        data.extend_from_slice(&ZIP_END_OF_DIRECTORY.to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&(names.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(names.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        data.extend_from_slice(&start.to_le_bytes());
+        data.extend_from_slice(&[0; 2]);
+        data
+    }
+
+    #[test]
+    fn test_inspect() {
+        let gimp = inspect(&elf(62, 0, "/lib64/ld-linux-x86-64.so.2")).unwrap();
+        assert_eq!((gimp.kind, gimp.guest_os), (BinaryKind::Elf, Some(GuestOS::Linux)));
+        let elf_info = gimp.elf.unwrap();
+        assert_eq!((elf_info.class, elf_info.machine, elf_info.elf_type), (ElfClass::Elf64, Machine::X86_64, ElfType::Executable));
+        assert_eq!(gimp.interpreter.as_deref(), Some("/lib64/ld-linux-x86-64.so.2"));
+
+        let toybox = inspect(&elf(183, 0, "/system/bin/linker64")).unwrap();
+        assert_eq!((toybox.guest_os, toybox.elf.unwrap().machine), (Some(GuestOS::Android), Machine::Aarch64));
+        assert_eq!(inspect(&elf(62, 9, "/libexec/ld-elf.so.1")).unwrap().guest_os, None);
+        let mut appimage = elf(62, 0, "/lib64/ld-linux-x86-64.so.2");
+        appimage[8..11].copy_from_slice(APPIMAGE_MAGIC);
+        assert_eq!(inspect(&appimage).unwrap().kind, BinaryKind::AppImage);
+
+        assert_eq!(inspect(&zip(&["classes.dex", "AndroidManifest.xml"])).unwrap().kind, BinaryKind::Apk);
+        assert_eq!(inspect(&zip(&["META-INF/MANIFEST.MF"])), None);
+        assert_eq!(inspect(b"!<arch>\ndebian-binary   ").unwrap().kind, BinaryKind::DebPackage);
+
+        let script = inspect(b"#!/usr/bin/env python3\nprint()\n").unwrap();
+        assert_eq!(script.interpreter.as_deref(), Some("/usr/bin/env"));
+        assert_eq!(script.interpreter_args.as_deref(), Some("python3"));
+        assert_eq!(inspect(b"plain text"), None);
+        assert_eq!(inspect(b"\x7fELF\x02"), None);
+    }
+}
@@ -0,0 +1,327 @@
+//! Containers
+//!
+//! Besides full VMs, Chrysalis runs OCI images as containers: the image's
+//! root filesystem is built in the VFS and its command runs as a hairr
+//! process sandboxed to that tree. Each container is a group of processes
+//! with limits on the memory they may use and how many there may be. The
+//! container exits with its first process, taking the others with it.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use filesystem::VirtualFileSystem;
+use kernel::{Kernel, NetworkPolicy, Priority, ProcessId, SandboxProfile};
+
+use crate::oci::{self, ImageLayout};
+
+/// Where container root filesystems are built
+pub const CONTAINER_DIR: &str = "/var/lib/chrysalis/containers";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ContainerId(u64);
+
+impl fmt::Display for ContainerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:012x}", self.0)
+    }
+}
+
+/// Limits of a container's processes taken together; `None` is unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ContainerLimits {
+    pub memory_mb: Option<usize>,
+    pub pids: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunOptions {
+    pub name: Option<String>,
+    /// Replaces the image's command; its entrypoint stays
+    pub command: Vec<String>,
+    /// `KEY=value`, added to the image's environment
+    pub env: Vec<String>,
+    pub limits: ContainerLimits,
+    pub network: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerState {
+    Running,
+    Exited,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Container {
+    pub id: ContainerId,
+    pub name: String,
+    pub image: String,
+    pub command: Vec<String>,
+    pub env: Vec<String>,
+    pub working_dir: String,
+    pub rootfs: PathBuf,
+    pub state: ContainerState,
+    pub limits: ContainerLimits,
+    pub network: bool,
+    /// Running processes, the first one started first
+    pub processes: Vec<ProcessId>,
+    /// Bytes charged to the container
+    pub memory_used: u64,
+    /// Seconds since the Unix epoch
+    pub created_at: u64,
+}
+
+/// Containers, run as sandboxed processes of `kernel`
+pub struct ContainerRuntime {
+    vfs: Arc<VirtualFileSystem>,
+    kernel: Arc<Kernel>,
+    containers: Arc<Mutex<BTreeMap<ContainerId, Container>>>,
+    next_id: Mutex<u64>,
+}
+
+impl ContainerRuntime {
+    /// The kernel needs a capability manager attached to spawn processes
+    pub fn new(vfs: Arc<VirtualFileSystem>, kernel: Arc<Kernel>) -> Self {
+        let containers: Arc<Mutex<BTreeMap<ContainerId, Container>>> = Arc::new(Mutex::new(BTreeMap::new()));
+        let tracked = Arc::clone(&containers);
+        let weak = Arc::downgrade(&kernel);
+        kernel.on_process_terminated(Arc::new(move |pid: ProcessId| {
+            let mut containers = tracked.lock().unwrap();
+            let Some(container) = containers.values_mut().find(|c| c.processes.contains(&pid)) else {
+                return;
+            };
+            let first = container.processes[0] == pid;
+            container.processes.retain(|p| *p != pid);
+            if !first {
+                return;
+            }
+            container.state = ContainerState::Exited;
+            container.memory_used = 0;
+            let rest = std::mem::take(&mut container.processes);
+            drop(containers);
+            if let Some(kernel) = weak.upgrade() {
+                for pid in rest {
+                    let _ = kernel.terminate_process(pid);
+                }
+            }
+        }));
+        ContainerRuntime {
+            vfs,
+            kernel,
+            containers,
+            next_id: Mutex::new(1),
+        }
+    }
+
+    /// Run an image from the OCI layout at `layout`: `reference` names it,
+    /// or the first image there is used
+    pub fn run(&self, layout: &Path, reference: Option<&str>, options: RunOptions) -> Result<ContainerId, String> {
+        let layout = ImageLayout::new(&self.vfs, layout);
+        let image = layout.image(reference)?;
+        let defaults = &image.config.config;
+        let mut command = defaults.entrypoint.clone();
+        command.extend(if options.command.is_empty() { defaults.cmd.clone() } else { options.command.clone() });
+        if command.is_empty() {
+            return Err(format!("Image {} has no command to run", image.reference));
+        }
+
+        let id = {
+            let mut next_id = self.next_id.lock().unwrap();
+            let id = ContainerId(*next_id);
+            *next_id += 1;
+            id
+        };
+        let name = options.name.clone().unwrap_or_else(|| format!("container-{}", id.0));
+        if self.containers.lock().unwrap().values().any(|c| c.name == name) {
+            return Err(format!("A container is already named {}", name));
+        }
+        let rootfs = Path::new(CONTAINER_DIR).join(id.to_string()).join("rootfs");
+        if let Err(e) = layout.unpack(&image, &rootfs) {
+            let _ = oci::remove_tree(&self.vfs, rootfs.parent().unwrap());
+            return Err(e);
+        }
+
+        let mut env = defaults.env.clone();
+        for variable in &options.env {
+            let key = variable.split('=').next().unwrap_or_default();
+            env.retain(|e| e.split('=').next() != Some(key));
+            env.push(variable.clone());
+        }
+        let mut container = Container {
+            id,
+            name,
+            image: image.reference.clone(),
+            command,
+            env,
+            working_dir: if defaults.working_dir.is_empty() { "/".to_string() } else { defaults.working_dir.clone() },
+            rootfs,
+            state: ContainerState::Running,
+            limits: options.limits,
+            network: options.network,
+            processes: Vec::new(),
+            memory_used: 0,
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        };
+        let pid = match self.spawn(&container, &container.command) {
+            Ok(pid) => pid,
+            Err(e) => {
+                let _ = oci::remove_tree(&self.vfs, container.rootfs.parent().unwrap());
+                return Err(e);
+            }
+        };
+        container.processes.push(pid);
+        self.containers.lock().unwrap().insert(id, container);
+        Ok(id)
+    }
+
+    /// Start another process in a running container
+    pub fn exec(&self, id: ContainerId, command: &[String]) -> Result<ProcessId, String> {
+        let container = self.get(id).ok_or("Container not found")?;
+        if container.state != ContainerState::Running {
+            return Err(format!("Container {} is not running", container.name));
+        }
+        if container.limits.pids.is_some_and(|max| container.processes.len() >= max) {
+            return Err(format!("Container {} has as many processes as it may", container.name));
+        }
+        let pid = self.spawn(&container, command)?;
+        self.containers.lock().unwrap().get_mut(&id).ok_or("Container not found")?.processes.push(pid);
+        Ok(pid)
+    }
+
+    /// Charge memory a container's processes allocate
+    pub fn charge_memory(&self, id: ContainerId, bytes: u64) -> Result<(), String> {
+        let mut containers = self.containers.lock().unwrap();
+        let container = containers.get_mut(&id).ok_or("Container not found")?;
+        if container.state != ContainerState::Running {
+            return Err(format!("Container {} is not running", container.name));
+        }
+        if let Some(limit) = container.limits.memory_mb {
+            if container.memory_used + bytes > (limit as u64) << 20 {
+                return Err(format!("Container {} is out of memory", container.name));
+            }
+        }
+        container.memory_used += bytes;
+        Ok(())
+    }
+
+    pub fn release_memory(&self, id: ContainerId, bytes: u64) -> Result<(), String> {
+        let mut containers = self.containers.lock().unwrap();
+        let container = containers.get_mut(&id).ok_or("Container not found")?;
+        container.memory_used = container.memory_used.saturating_sub(bytes);
+        Ok(())
+    }
+
+    /// Running containers, or all of them; oldest first
+    pub fn ps(&self, all: bool) -> Vec<Container> {
+        let containers = self.containers.lock().unwrap();
+        containers.values().filter(|c| all || c.state == ContainerState::Running).cloned().collect()
+    }
+
+    pub fn get(&self, id: ContainerId) -> Option<Container> {
+        self.containers.lock().unwrap().get(&id).cloned()
+    }
+
+    /// Container by name or ID
+    pub fn find(&self, name: &str) -> Option<Container> {
+        let containers = self.containers.lock().unwrap();
+        containers.values().find(|c| c.name == name || c.id.to_string() == name).cloned()
+    }
+
+    /// Terminate every process of a container
+    pub fn stop(&self, id: ContainerId) -> Result<(), String> {
+        let container = self.get(id).ok_or("Container not found")?;
+        if container.state != ContainerState::Running {
+            return Err(format!("Container {} is not running", container.name));
+        }
+        // The rest go with the first
+        self.kernel.terminate_process(container.processes[0]).map_err(String::from)
+    }
+
+    /// Delete a stopped container and its root filesystem
+    pub fn remove(&self, id: ContainerId) -> Result<(), String> {
+        let mut containers = self.containers.lock().unwrap();
+        let container = containers.get(&id).ok_or("Container not found")?;
+        if container.state != ContainerState::Exited {
+            return Err(format!("Container {} must be stopped first", container.name));
+        }
+        let dir = container.rootfs.parent().unwrap().to_path_buf();
+        containers.remove(&id);
+        drop(containers);
+        oci::remove_tree(&self.vfs, &dir)
+    }
+
+    /// A process confined to the container's root filesystem
+    fn spawn(&self, container: &Container, command: &[String]) -> Result<ProcessId, String> {
+        let mut profile = SandboxProfile::new(container.rootfs.to_string_lossy().into_owned());
+        profile.network = if container.network { NetworkPolicy::Any } else { NetworkPolicy::Deny };
+        self.kernel.spawn_sandboxed(command.join(" "), Priority::Normal, profile).map_err(String::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oci::tests::{add_blob, tar};
+    use crate::oci::{EntryKind, ImageIndex, REF_NAME};
+    use capability::CapabilityManager;
+    use kernel::ProcessState;
+
+    fn alpine_layout(vfs: &VirtualFileSystem, dir: &Path) {
+        let layer = tar(&[("bin/sh", EntryKind::File, b"\x7fELF"), ("etc/os-release", EntryKind::File, b"ID=alpine")]);
+        let layer = add_blob(vfs, dir, "application/vnd.oci.image.layer.v1.tar", &layer);
+        let config = br#"{"os": "linux", "config": {"Env": ["PATH=/bin", "HOME=/root"], "Cmd": ["/bin/sh"]}}"#;
+        let config = add_blob(vfs, dir, "application/vnd.oci.image.config.v1+json", config);
+        let manifest = serde_json::json!({"schemaVersion": 2, "config": config, "layers": [layer]});
+        let mut manifest = add_blob(vfs, dir, "application/vnd.oci.image.manifest.v1+json", manifest.to_string().as_bytes());
+        manifest.annotations.insert(REF_NAME.to_string(), "alpine:3.20".to_string());
+        let index = ImageIndex { schema_version: 2, manifests: vec![manifest] };
+        vfs.write_file(&dir.join("index.json"), &serde_json::to_vec(&index).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_run_exec_stop() {
+        let vfs = Arc::new(VirtualFileSystem::new());
+        let kernel = Arc::new(Kernel::new());
+        kernel.attach_capabilities(Arc::new(CapabilityManager::new()));
+        let layout = Path::new("/home/user/images/alpine");
+        alpine_layout(&vfs, layout);
+        let runtime = ContainerRuntime::new(Arc::clone(&vfs), Arc::clone(&kernel));
+        assert!(runtime.run(layout, Some("debian:12"), RunOptions::default()).is_err());
+
+        let options = RunOptions {
+            name: Some("web".to_string()),
+            env: vec!["HOME=/srv".to_string()],
+            limits: ContainerLimits { memory_mb: Some(1), pids: Some(2) },
+            ..RunOptions::default()
+        };
+        let id = runtime.run(layout, Some("alpine:3.20"), options).unwrap();
+        let web = runtime.find("web").unwrap();
+        assert_eq!(web.command, vec!["/bin/sh"]);
+        assert_eq!(web.env, vec!["PATH=/bin", "HOME=/srv"]);
+        assert_eq!(vfs.read_file(&web.rootfs.join("etc/os-release")).unwrap(), b"ID=alpine");
+        assert_eq!(kernel.resolve_path(web.processes[0], "/bin/sh").unwrap(), web.rootfs.join("bin/sh"));
+        assert!(runtime.run(layout, None, RunOptions { name: Some("web".to_string()), ..RunOptions::default() }).is_err());
+
+        let worker = runtime.exec(id, &["/bin/sh".to_string(), "-c".to_string(), "sleep 60".to_string()]).unwrap();
+        assert!(runtime.exec(id, &["/bin/sh".to_string()]).is_err());
+        runtime.charge_memory(id, 900 << 10).unwrap();
+        assert!(runtime.charge_memory(id, 200 << 10).is_err());
+        runtime.release_memory(id, 100 << 10).unwrap();
+        runtime.charge_memory(id, 200 << 10).unwrap();
+
+        kernel.terminate_process(worker).unwrap();
+        assert_eq!(runtime.get(id).unwrap().processes.len(), 1);
+        assert!(runtime.remove(id).is_err());
+        let init = web.processes[0];
+        runtime.exec(id, &["/bin/sh".to_string()]).unwrap();
+        runtime.stop(id).unwrap();
+        assert!(runtime.ps(false).is_empty());
+        assert_eq!(runtime.ps(true)[0].state, ContainerState::Exited);
+        assert_eq!(kernel.get_process(init).unwrap().state, ProcessState::Terminated);
+        assert!(kernel.list_processes().iter().all(|p| p.state == ProcessState::Terminated));
+        runtime.remove(id).unwrap();
+        assert!(!vfs.exists(&web.rootfs));
+    }
+}
@@ -0,0 +1,197 @@
+//! Disk images
+//!
+//! Besides its own disk, a VM can have disk images from the VFS attached.
+//! Images are sparse: only the blocks written are stored. An overlay names
+//! a base image and holds just the blocks written through it, reading the
+//! rest from the base, so several VMs can share one base image. Once an
+//! overlay is made of an image the image is read-only, since changing it
+//! would change every overlay.
+//!
+//! ```json
+//! {"base": "/var/lib/chrysalis/disks/debian.img", "read_only": false,
+//!  "data": {"capacity": 21474836480, "blocks": {"0": [235, 99, 144]}}}
+//! ```
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use filesystem::VirtualFileSystem;
+use serde::{Deserialize, Serialize};
+
+use crate::snapshot::{SparseImage, BLOCK_SIZE};
+
+/// Where disk images are kept by default
+pub const DISK_DIR: &str = "/var/lib/chrysalis/disks";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiskImage {
+    /// Image the blocks not written here are read from
+    #[serde(default)]
+    pub base: Option<PathBuf>,
+    /// Set once an overlay is made of the image
+    #[serde(default)]
+    pub read_only: bool,
+    pub data: SparseImage,
+}
+
+/// Disk images in the VFS
+pub struct DiskImages {
+    vfs: Arc<VirtualFileSystem>,
+}
+
+impl DiskImages {
+    pub fn new(vfs: Arc<VirtualFileSystem>) -> Self {
+        DiskImages { vfs }
+    }
+
+    /// The VFS the images are kept in
+    pub(crate) fn vfs(&self) -> &VirtualFileSystem {
+        &self.vfs
+    }
+
+    /// Create an empty image of `capacity` bytes
+    pub fn create(&self, path: &Path, capacity: u64) -> Result<(), String> {
+        if self.vfs.exists(path) {
+            return Err(format!("{} already exists", path.display()));
+        }
+        self.save(
+            path,
+            &DiskImage {
+                base: None,
+                read_only: false,
+                data: SparseImage::new(capacity),
+            },
+        )
+    }
+
+    /// Create an overlay of `base`, which becomes read-only
+    pub fn create_overlay(&self, path: &Path, base: &Path) -> Result<(), String> {
+        if self.vfs.exists(path) {
+            return Err(format!("{} already exists", path.display()));
+        }
+        let mut image = self.load(base)?;
+        let overlay = DiskImage {
+            base: Some(base.to_path_buf()),
+            read_only: false,
+            data: SparseImage::new(image.data.capacity()),
+        };
+        if !image.read_only {
+            image.read_only = true;
+            self.save(base, &image)?;
+        }
+        self.save(path, &overlay)
+    }
+
+    pub fn load(&self, path: &Path) -> Result<DiskImage, String> {
+        if !self.vfs.exists(path) {
+            return Err(format!("Disk image not found: {}", path.display()));
+        }
+        serde_json::from_slice(&self.vfs.read_file(path)?).map_err(|e| format!("Invalid disk image {}: {}", path.display(), e))
+    }
+
+    /// Grow or shrink an image that is not read-only
+    pub fn resize(&self, path: &Path, capacity: u64) -> Result<(), String> {
+        let mut image = self.writable(path)?;
+        image.data.resize(capacity)?;
+        self.save(path, &image)
+    }
+
+    pub fn read(&self, path: &Path, offset: u64, len: usize) -> Result<Vec<u8>, String> {
+        let chain = self.chain(path)?;
+        chain[0].data.check_range(offset, len)?;
+        Ok((offset..offset + len as u64)
+            .map(|position| {
+                let block = position / BLOCK_SIZE as u64;
+                chain
+                    .iter()
+                    .find_map(|image| image.data.block(block))
+                    .map_or(0, |data| data[(position % BLOCK_SIZE as u64) as usize])
+            })
+            .collect())
+    }
+
+    /// Write to an image that is not read-only, copying the blocks it
+    /// touches up from the base first
+    pub fn write(&self, path: &Path, offset: u64, data: &[u8]) -> Result<(), String> {
+        let mut image = self.writable(path)?;
+        image.data.check_range(offset, data.len())?;
+        if data.is_empty() {
+            return Ok(());
+        }
+        if let Some(base) = &image.base {
+            let chain = self.chain(base)?;
+            let (first, last) = (offset / BLOCK_SIZE as u64, (offset + data.len() as u64 - 1) / BLOCK_SIZE as u64);
+            for block in first..=last {
+                if image.data.block(block).is_some() {
+                    continue;
+                }
+                if let Some(below) = chain.iter().find_map(|i| i.data.block(block)) {
+                    image.data.set_block(block, below.clone());
+                }
+            }
+        }
+        image.data.write(offset, data)?;
+        self.save(path, &image)
+    }
+
+    /// Delete an image no overlay is made of
+    pub fn delete(&self, path: &Path) -> Result<(), String> {
+        if self.load(path)?.read_only {
+            return Err(format!("{} is the base of other images", path.display()));
+        }
+        self.vfs.delete(path).map_err(String::from)
+    }
+
+    fn writable(&self, path: &Path) -> Result<DiskImage, String> {
+        let image = self.load(path)?;
+        if image.read_only {
+            return Err(format!("{} is the base of other images and cannot change", path.display()));
+        }
+        Ok(image)
+    }
+
+    /// The image and the bases below it
+    fn chain(&self, path: &Path) -> Result<Vec<DiskImage>, String> {
+        let mut chain = vec![self.load(path)?];
+        while let Some(base) = chain.last().unwrap().base.clone() {
+            chain.push(self.load(&base)?);
+        }
+        Ok(chain)
+    }
+
+    fn save(&self, path: &Path, image: &DiskImage) -> Result<(), String> {
+        self.vfs.write_file(path, &serde_json::to_vec(image).unwrap()).map_err(String::from)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overlay_copy_on_write() {
+        let images = DiskImages::new(Arc::new(VirtualFileSystem::new()));
+        let base = Path::new("/var/lib/chrysalis/disks/debian.img");
+        let overlay = Path::new("/var/lib/chrysalis/disks/dev.img");
+        images.create(base, 4 * BLOCK_SIZE as u64).unwrap();
+        images.write(base, 10, b"debian").unwrap();
+        assert!(images.create(base, 1).is_err());
+
+        images.create_overlay(overlay, base).unwrap();
+        assert!(images.write(base, 0, b"x").is_err());
+        assert!(images.resize(base, BLOCK_SIZE as u64).is_err());
+        assert_eq!(images.read(overlay, 10, 6).unwrap(), b"debian");
+        images.write(overlay, 12, b"DI").unwrap();
+        assert_eq!(images.read(overlay, 8, 8).unwrap(), b"\0\0deDIan");
+        assert_eq!(images.read(base, 10, 6).unwrap(), b"debian");
+        assert_eq!(images.load(overlay).unwrap().data.allocated(), BLOCK_SIZE as u64);
+
+        images.resize(overlay, 8 * BLOCK_SIZE as u64).unwrap();
+        assert_eq!(images.read(overlay, 7 * BLOCK_SIZE as u64, 4).unwrap(), vec![0; 4]);
+        assert!(images.resize(overlay, 0).is_err());
+        assert!(images.delete(base).is_err());
+        images.delete(overlay).unwrap();
+        assert!(images.read(overlay, 0, 1).is_err());
+    }
+}
@@ -1,11 +1,53 @@
 //! Chrysalis Compatibility Suite
 //! 
 //! Provides virtualization-based compatibility for running Linux and Android
-//! applications on hairr OS with strong isolation and security.
+//! applications on hairr OS with strong isolation and security. VMs can
+//! be snapshotted and rolled back, have disk images from the VFS attached,
+//! and be networked through NAT, a bridge or a host-only network. Their
+//! resource use is tracked, and a running VM's vCPUs and memory can be
+//! changed without restarting it, with its guest told first and the host
+//! keeping enough memory for itself. Apps are launched, commands run and the
+//! clipboard shared through an agent inside each guest, which also
+//! installs APKs in the Android runtime. Foreign binaries are recognised
+//! by their contents and opened in the guest they need. OCI images can also run as
+//! containers, without a VM, and VMs can be cloned from templates.
 
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use filesystem::VirtualFileSystem;
+use ipc::IPCManager;
+use memory_manager::MemoryManager;
+use net_stack::firewall::Protocol;
+use net_stack::{Ipv4Config, VirtualNic};
+
+pub mod agent;
+pub mod android;
+pub mod binary;
+pub mod container;
+pub mod disk;
+pub mod network;
+pub mod oci;
+pub mod snapshot;
+pub mod stats;
+pub mod template;
+
+pub use agent::{AgentChannels, AgentConnector, AgentRequest, AgentResponse, CommandOutput, GuestAgent, GuestEvent, InstalledApp};
+pub use android::{AndroidApp, ApkInfo, DataDir};
+pub use binary::{BinaryInfo, BinaryKind, ElfInfo, Machine};
+pub use container::{Container, ContainerId, ContainerLimits, ContainerRuntime, ContainerState, RunOptions};
+pub use disk::{DiskImage, DiskImages};
+pub use network::{NetworkMode, PortForward, VmNetwork};
+pub use snapshot::{Snapshot, SnapshotInfo, SparseImage};
+pub use stats::{VmEvent, VmEventListener, VmResource, VmStats};
+pub use template::{ConfigOverrides, VmTemplate};
+
+use agent::AGENT_TIMEOUT;
+use disk::DISK_DIR;
+use snapshot::BLOCK_SIZE;
+use stats::{VmUsage, HOST_RESERVE_MB, MAX_CPU_CORES};
 
 /// Virtual machine identifier
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -41,6 +83,7 @@ pub struct VmConfig {
     pub cpu_cores: usize,
     pub disk_size_gb: usize,
     pub network_enabled: bool,
+    pub network_mode: NetworkMode,
     pub gpu_passthrough: bool,
 }
 
@@ -51,6 +94,7 @@ impl Default for VmConfig {
             cpu_cores: 2,
             disk_size_gb: 20,
             network_enabled: true,
+            network_mode: NetworkMode::Nat,
             gpu_passthrough: false,
         }
     }
@@ -64,6 +108,19 @@ pub struct VirtualMachine {
     pub guest_os: GuestOS,
     pub state: VmState,
     pub config: VmConfig,
+    pub disk: SparseImage,
+    /// Guest RAM; cleared when the VM stops. `config.memory_mb` of it
+    /// may be used while running.
+    pub memory: SparseImage,
+    /// Most memory a running VM can be given, set when it starts
+    pub max_memory_mb: usize,
+    pub usage: VmUsage,
+    /// Disk images attached besides its own disk
+    pub attached_disks: Vec<PathBuf>,
+    /// Template the VM was made from
+    pub template: Option<String>,
+    /// Overlay of the template's image made for the VM, deleted with it
+    pub clone_disk: Option<PathBuf>,
 }
 
 impl VirtualMachine {
@@ -73,6 +130,13 @@ impl VirtualMachine {
             name,
             guest_os,
             state: VmState::Stopped,
+            disk: SparseImage::new((config.disk_size_gb as u64) << 30),
+            memory: SparseImage::new((config.memory_mb as u64) << 20),
+            max_memory_mb: config.memory_mb,
+            usage: VmUsage::default(),
+            attached_disks: Vec::new(),
+            template: None,
+            clone_disk: None,
             config,
         }
     }
@@ -91,6 +155,19 @@ pub struct GuestApplication {
 pub struct Chrysalis {
     vms: Arc<Mutex<HashMap<VmId, VirtualMachine>>>,
     applications: Arc<Mutex<HashMap<String, GuestApplication>>>,
+    android_apps: Arc<Mutex<Vec<AndroidApp>>>,
+    templates: Arc<Mutex<Vec<VmTemplate>>>,
+    /// Oldest first
+    snapshots: Arc<Mutex<HashMap<VmId, Vec<Snapshot>>>>,
+    images: Option<DiskImages>,
+    network: Option<VmNetwork>,
+    containers: Option<ContainerRuntime>,
+    host_memory: Option<Arc<MemoryManager>>,
+    listener: Option<VmEventListener>,
+    ipc: Option<Arc<IPCManager>>,
+    connector: Option<AgentConnector>,
+    /// Of running VMs
+    agents: Arc<Mutex<HashMap<VmId, Arc<GuestAgent>>>>,
     next_vm_id: Arc<Mutex<u64>>,
     installed: bool,
 }
@@ -100,6 +177,17 @@ impl Chrysalis {
         Chrysalis {
             vms: Arc::new(Mutex::new(HashMap::new())),
             applications: Arc::new(Mutex::new(HashMap::new())),
+            android_apps: Arc::new(Mutex::new(Vec::new())),
+            templates: Arc::new(Mutex::new(Vec::new())),
+            snapshots: Arc::new(Mutex::new(HashMap::new())),
+            images: None,
+            network: None,
+            containers: None,
+            host_memory: None,
+            listener: None,
+            ipc: None,
+            connector: None,
+            agents: Arc::new(Mutex::new(HashMap::new())),
             next_vm_id: Arc::new(Mutex::new(1)),
             installed: false,
         }
@@ -127,6 +215,241 @@ impl Chrysalis {
         self.installed
     }
 
+    /// Keep disk images in `vfs`
+    pub fn attach_storage(&mut self, vfs: Arc<VirtualFileSystem>) {
+        self.images = Some(DiskImages::new(vfs));
+    }
+
+    /// Disk images, for creating and reading them
+    pub fn disk_images(&self) -> Result<&DiskImages, String> {
+        self.images.as_ref().ok_or_else(|| "No storage for disk images".to_string())
+    }
+
+    /// Attach a disk image to a stopped VM. A writable image is attached to
+    /// one VM at a time; base images are shared through overlays instead.
+    pub fn attach_disk(&self, vm_id: VmId, path: &Path) -> Result<(), String> {
+        if self.disk_images()?.load(path)?.read_only {
+            return Err(format!("{} is a base image; attach an overlay of it", path.display()));
+        }
+        let mut vms = self.vms.lock().unwrap();
+        if let Some(other) = vms.values().find(|vm| vm.attached_disks.iter().any(|d| d == path)) {
+            return Err(format!("{} is attached to VM '{}'", path.display(), other.name));
+        }
+        let vm = vms.get_mut(&vm_id).ok_or("VM not found")?;
+
+        if vm.state != VmState::Stopped {
+            return Err("VM must be stopped to attach disks".to_string());
+        }
+
+        vm.attached_disks.push(path.to_path_buf());
+        Ok(())
+    }
+
+    /// Detach a disk image from a stopped VM
+    pub fn detach_disk(&self, vm_id: VmId, path: &Path) -> Result<(), String> {
+        let mut vms = self.vms.lock().unwrap();
+        let vm = vms.get_mut(&vm_id).ok_or("VM not found")?;
+
+        if vm.state != VmState::Stopped {
+            return Err("VM must be stopped to detach disks".to_string());
+        }
+        let before = vm.attached_disks.len();
+        vm.attached_disks.retain(|d| d != path);
+        if vm.attached_disks.len() == before {
+            return Err(format!("{} is not attached to VM '{}'", path.display(), vm.name));
+        }
+        Ok(())
+    }
+
+    /// Resize a disk image unless a running VM has it attached
+    pub fn resize_disk(&self, path: &Path, size_gb: usize) -> Result<(), String> {
+        self.check_disk_idle(path)?;
+        self.disk_images()?.resize(path, (size_gb as u64) << 30)
+    }
+
+    /// Delete a disk image no VM has attached
+    pub fn delete_disk(&self, path: &Path) -> Result<(), String> {
+        let vms = self.vms.lock().unwrap();
+        if let Some(vm) = vms.values().find(|vm| vm.attached_disks.iter().any(|d| d == path)) {
+            return Err(format!("{} is attached to VM '{}'", path.display(), vm.name));
+        }
+        drop(vms);
+        self.disk_images()?.delete(path)
+    }
+
+    /// Route VM traffic through `network`
+    pub fn attach_network(&mut self, network: VmNetwork) {
+        self.network = Some(network);
+    }
+
+    /// VM networking, for moving traffic between guests and the host
+    pub fn network(&self) -> Result<&VmNetwork, String> {
+        self.network.as_ref().ok_or_else(|| "VM networking is not set up".to_string())
+    }
+
+    /// Connect a VM in the network mode of its configuration, returning
+    /// the guest's NIC and the address it should use
+    pub fn connect_network(&self, vm_id: VmId) -> Result<(VirtualNic, Option<Ipv4Config>), String> {
+        let vm = self.get_vm(vm_id).ok_or("VM not found")?;
+        if !vm.config.network_enabled {
+            return Err(format!("Networking is disabled for VM '{}'", vm.name));
+        }
+        self.network()?.connect(vm_id, vm.config.network_mode)
+    }
+
+    /// Forward a host port to a port of a NAT VM
+    pub fn add_port_forward(&self, vm_id: VmId, forward: PortForward) -> Result<(), String> {
+        self.get_vm(vm_id).ok_or("VM not found")?;
+        self.network()?.add_forward(vm_id, forward)
+    }
+
+    pub fn remove_port_forward(&self, vm_id: VmId, protocol: Protocol, host_port: u16) -> Result<(), String> {
+        self.network()?.remove_forward(vm_id, protocol, host_port)
+    }
+
+    /// List a VM's port forwards
+    pub fn port_forwards(&self, vm_id: VmId) -> Vec<PortForward> {
+        self.network.as_ref().map_or_else(Vec::new, |n| n.forwards(vm_id))
+    }
+
+    /// Only give VMs memory `host` can spare
+    pub fn attach_host_memory(&mut self, host: Arc<MemoryManager>) {
+        self.host_memory = Some(host);
+    }
+
+    /// Whether the host can give VMs `extra_mb` more than the running ones
+    /// have, keeping `HOST_RESERVE_MB` for itself
+    fn check_host_memory(&self, vms: &HashMap<VmId, VirtualMachine>, extra_mb: usize) -> Result<(), String> {
+        let Some(host) = &self.host_memory else {
+            return Ok(());
+        };
+        let committed: usize = vms.values().filter(|vm| vm.state != VmState::Stopped).map(|vm| vm.config.memory_mb).sum();
+        let spare = (host.stats().free_memory >> 20).saturating_sub(HOST_RESERVE_MB + committed);
+        if extra_mb > spare {
+            return Err(format!("The host can spare {} MB of memory, not {} MB", spare, extra_mb));
+        }
+        Ok(())
+    }
+
+    /// Send a request to a running VM's agent, if it has one
+    fn tell_guest(&self, vm_id: VmId, request: &AgentRequest) -> Result<(), String> {
+        let agent = self.agents.lock().unwrap().get(&vm_id).cloned();
+        match agent {
+            Some(agent) => agent.request(request).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    /// Run containers with `runtime`
+    pub fn attach_containers(&mut self, runtime: ContainerRuntime) {
+        self.containers = Some(runtime);
+    }
+
+    /// Container runtime, for running and stopping containers
+    pub fn containers(&self) -> Result<&ContainerRuntime, String> {
+        self.containers.as_ref().ok_or_else(|| "No container runtime attached".to_string())
+    }
+
+    /// Give VMs guest agents over `ipc`; `connector` is called as each VM
+    /// starts running to connect its agent
+    pub fn attach_agents(&mut self, ipc: Arc<IPCManager>, connector: AgentConnector) {
+        self.ipc = Some(ipc);
+        self.connector = Some(connector);
+    }
+
+    /// Channels of a running VM's agent
+    pub fn agent_channels(&self, vm_id: VmId) -> Option<AgentChannels> {
+        self.agents.lock().unwrap().get(&vm_id).map(|a| a.channels())
+    }
+
+    fn connect_agent(&self, vm_id: VmId) -> Result<(), String> {
+        let (Some(ipc), Some(connector)) = (&self.ipc, &self.connector) else {
+            return Ok(());
+        };
+        if self.agents.lock().unwrap().contains_key(&vm_id) {
+            return Ok(());
+        }
+        let vm = self.get_vm(vm_id).ok_or("VM not found")?;
+        let agent = GuestAgent::open(Arc::clone(ipc), AGENT_TIMEOUT);
+        if let Err(e) = connector(&vm, agent.channels()) {
+            agent.close();
+            return Err(format!("Could not connect the agent of VM '{}': {}", vm.name, e));
+        }
+        self.agents.lock().unwrap().insert(vm_id, Arc::new(agent));
+        Ok(())
+    }
+
+    fn disconnect_agent(&self, vm_id: VmId) {
+        if let Some(agent) = self.agents.lock().unwrap().remove(&vm_id) {
+            agent.close();
+        }
+    }
+
+    /// Agent of a running VM
+    fn agent(&self, vm_id: VmId) -> Result<Arc<GuestAgent>, String> {
+        let vm = self.get_vm(vm_id).ok_or("VM not found")?;
+        if vm.state != VmState::Running {
+            return Err("VM is not running".to_string());
+        }
+        self.agents.lock().unwrap().get(&vm_id).cloned().ok_or_else(|| format!("VM '{}' has no guest agent", vm.name))
+    }
+
+    /// Run a command in a VM and wait for it to exit
+    pub fn exec_in_guest(&self, vm_id: VmId, command: &str, args: &[&str]) -> Result<CommandOutput, String> {
+        let request = AgentRequest::Exec {
+            command: command.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+        };
+        match self.agent(vm_id)?.request(&request)? {
+            AgentResponse::Exited(output) => Ok(output),
+            other => Err(format!("Unexpected response from the guest agent: {:?}", other)),
+        }
+    }
+
+    /// List the apps installed in a VM
+    pub fn guest_apps(&self, vm_id: VmId) -> Result<Vec<InstalledApp>, String> {
+        match self.agent(vm_id)?.request(&AgentRequest::ListApps)? {
+            AgentResponse::Apps { apps } => Ok(apps),
+            other => Err(format!("Unexpected response from the guest agent: {:?}", other)),
+        }
+    }
+
+    /// Put data on a VM's clipboard
+    pub fn push_clipboard(&self, vm_id: VmId, mime_type: &str, data: &str) -> Result<(), String> {
+        let request = AgentRequest::SetClipboard {
+            mime_type: mime_type.to_string(),
+            data: data.to_string(),
+        };
+        self.agent(vm_id)?.request(&request).map(|_| ())
+    }
+
+    /// Events a VM's guest reported since last asked. Apps that exited are
+    /// no longer listed, and a guest shutting down stops its VM.
+    pub fn guest_events(&self, vm_id: VmId) -> Result<Vec<GuestEvent>, String> {
+        let Some(agent) = self.agents.lock().unwrap().get(&vm_id).cloned() else {
+            return Ok(Vec::new());
+        };
+        let events = agent.events()?;
+        for event in &events {
+            match event {
+                GuestEvent::AppExited { pid, .. } => {
+                    self.applications.lock().unwrap().retain(|_, app| app.vm_id != vm_id || app.process_id != *pid);
+                }
+                GuestEvent::ShuttingDown => self.stop_vm(vm_id)?,
+                _ => {}
+            }
+        }
+        Ok(events)
+    }
+
+    fn check_disk_idle(&self, path: &Path) -> Result<(), String> {
+        let vms = self.vms.lock().unwrap();
+        match vms.values().find(|vm| vm.state != VmState::Stopped && vm.attached_disks.iter().any(|d| d == path)) {
+            Some(vm) => Err(format!("{} is in use by VM '{}'", path.display(), vm.name)),
+            None => Ok(()),
+        }
+    }
+
     /// Create a new virtual machine
     pub fn create_vm(
         &self,
@@ -148,6 +471,69 @@ impl Chrysalis {
         Ok(vm_id)
     }
 
+    /// Offer a template; its base image must exist
+    pub fn add_template(&self, template: VmTemplate) -> Result<(), String> {
+        self.disk_images()?.load(&template.base_image)?;
+        let mut templates = self.templates.lock().unwrap();
+        if templates.iter().any(|t| t.id == template.id) {
+            return Err(format!("Template {} already exists", template.id));
+        }
+        templates.push(template);
+        Ok(())
+    }
+
+    pub fn list_templates(&self) -> Vec<VmTemplate> {
+        self.templates.lock().unwrap().clone()
+    }
+
+    /// Stop offering a template; VMs made from it keep working
+    pub fn remove_template(&self, id: &str) -> Result<(), String> {
+        let mut templates = self.templates.lock().unwrap();
+        let before = templates.len();
+        templates.retain(|t| t.id != id);
+        if templates.len() == before {
+            return Err(format!("Template not found: {}", id));
+        }
+        Ok(())
+    }
+
+    /// Create a VM from a template, booting an overlay of its base image
+    pub fn create_vm_from_template(&self, template_id: &str, name: String, overrides: ConfigOverrides) -> Result<VmId, String> {
+        let template = self
+            .list_templates()
+            .into_iter()
+            .find(|t| t.id == template_id)
+            .ok_or_else(|| format!("Template not found: {}", template_id))?;
+        let images = self.disk_images()?;
+        let config = overrides.apply(&template.config);
+        let base_size = images.load(&template.base_image)?.data.capacity();
+        let disk_size = (config.disk_size_gb as u64) << 30;
+        if overrides.disk_size_gb.is_some() && disk_size < base_size {
+            return Err(format!("Template {} needs a disk of at least {} GB", template.id, base_size.div_ceil(1 << 30)));
+        }
+
+        let vm_id = self.create_vm(name, template.guest_os, config)?;
+        let path = Path::new(DISK_DIR).join(format!("{}-{}.img", template.id, vm_id.0));
+        let cloned = images.create_overlay(&path, &template.base_image).and_then(|_| {
+            if disk_size > base_size {
+                images.resize(&path, disk_size)?;
+            }
+            Ok(())
+        });
+        if let Err(e) = cloned {
+            let _ = images.delete(&path);
+            self.vms.lock().unwrap().remove(&vm_id);
+            return Err(e);
+        }
+
+        let mut vms = self.vms.lock().unwrap();
+        let vm = vms.get_mut(&vm_id).ok_or("VM not found")?;
+        vm.template = Some(template.id);
+        vm.attached_disks.push(path.clone());
+        vm.clone_disk = Some(path);
+        Ok(vm_id)
+    }
+
     /// Start a virtual machine
     pub fn start_vm(&self, vm_id: VmId) -> Result<(), String> {
         let mut vms = self.vms.lock().unwrap();
@@ -156,14 +542,24 @@ impl Chrysalis {
         if vm.state != VmState::Stopped {
             return Err("VM is not in stopped state".to_string());
         }
+        let memory_mb = vm.config.memory_mb;
+        self.check_host_memory(&vms, memory_mb)?;
+        let vm = vms.get_mut(&vm_id).ok_or("VM not found")?;
 
         vm.state = VmState::Starting;
         println!("Starting VM '{}'...", vm.name);
+        vm.memory = SparseImage::new((vm.config.memory_mb as u64) << 20);
+        vm.max_memory_mb = vm.config.memory_mb;
         
         // Simulate VM startup
         vm.state = VmState::Running;
         println!("VM '{}' is now running", vm.name);
+        drop(vms);
 
+        if let Err(e) = self.connect_agent(vm_id) {
+            self.stop_vm(vm_id)?;
+            return Err(e);
+        }
         Ok(())
     }
 
@@ -180,8 +576,12 @@ impl Chrysalis {
         println!("Stopping VM '{}'...", vm.name);
         
         vm.state = VmState::Stopped;
+        vm.memory = SparseImage::new(vm.memory.capacity());
         println!("VM '{}' stopped", vm.name);
+        drop(vms);
 
+        self.disconnect_agent(vm_id);
+        self.applications.lock().unwrap().retain(|_, app| app.vm_id != vm_id);
         Ok(())
     }
 
@@ -208,7 +608,9 @@ impl Chrysalis {
         }
 
         vm.state = VmState::Running;
-        Ok(())
+        drop(vms);
+        // A VM restored from a snapshot resumes without an agent connected
+        self.connect_agent(vm_id)
     }
 
     /// Delete a virtual machine
@@ -220,7 +622,265 @@ impl Chrysalis {
             return Err("VM must be stopped before deletion".to_string());
         }
 
-        vms.remove(&vm_id);
+        let vm = vms.remove(&vm_id).unwrap();
+        drop(vms);
+        if let (Some(path), Some(images)) = (&vm.clone_disk, &self.images) {
+            // Kept when other images were made from it
+            let _ = images.delete(path);
+        }
+        self.snapshots.lock().unwrap().remove(&vm_id);
+        self.android_apps.lock().unwrap().retain(|app| app.vm_id != vm_id);
+        if let Some(network) = &self.network {
+            // Not every VM was connected
+            let _ = network.disconnect(vm_id);
+        }
+        Ok(())
+    }
+
+    /// Write to a VM's disk
+    pub fn write_disk(&self, vm_id: VmId, offset: u64, data: &[u8]) -> Result<(), String> {
+        let mut vms = self.vms.lock().unwrap();
+        let vm = vms.get_mut(&vm_id).ok_or("VM not found")?;
+        vm.disk.write(offset, data)?;
+        vm.usage.disk_written += data.len() as u64;
+        let event = vm.usage.check(vm_id, VmResource::Disk, vm.disk.allocated(), vm.disk.capacity());
+        drop(vms);
+        self.notify(event);
+        Ok(())
+    }
+
+    /// Read from a VM's disk
+    pub fn read_disk(&self, vm_id: VmId, offset: u64, len: usize) -> Result<Vec<u8>, String> {
+        let mut vms = self.vms.lock().unwrap();
+        let vm = vms.get_mut(&vm_id).ok_or("VM not found")?;
+        let data = vm.disk.read(offset, len)?;
+        vm.usage.disk_read += len as u64;
+        Ok(data)
+    }
+
+    /// Write to the memory of a running VM, within the memory it has
+    pub fn write_memory(&self, vm_id: VmId, offset: u64, data: &[u8]) -> Result<(), String> {
+        let mut vms = self.vms.lock().unwrap();
+        let vm = vms.get_mut(&vm_id).ok_or("VM not found")?;
+
+        if vm.state != VmState::Running {
+            return Err("VM is not running".to_string());
+        }
+        vm.memory.check_range(offset, data.len())?;
+        let block = BLOCK_SIZE as u64;
+        let touched = if data.is_empty() { 0..0 } else { offset / block..(offset + data.len() as u64 - 1) / block + 1 };
+        let new_blocks = touched.filter(|b| vm.memory.block(*b).is_none()).count() as u64;
+        let limit = (vm.config.memory_mb as u64) << 20;
+        if vm.memory.allocated() + new_blocks * block > limit {
+            return Err(format!("VM '{}' is out of memory", vm.name));
+        }
+
+        vm.memory.write(offset, data)?;
+        let event = vm.usage.check(vm_id, VmResource::Memory, vm.memory.allocated(), limit);
+        drop(vms);
+        self.notify(event);
+        Ok(())
+    }
+
+    /// Add time a VM's vCPUs ran
+    pub fn charge_cpu(&self, vm_id: VmId, time: Duration) -> Result<(), String> {
+        let mut vms = self.vms.lock().unwrap();
+        let vm = vms.get_mut(&vm_id).ok_or("VM not found")?;
+        vm.usage.cpu_time += time;
+        Ok(())
+    }
+
+    /// Report what a VM uses
+    pub fn vm_stats(&self, vm_id: VmId) -> Option<VmStats> {
+        let vm = self.get_vm(vm_id)?;
+        let (network_received, network_sent) = self.network.as_ref().and_then(|n| n.traffic(vm_id)).unwrap_or((0, 0));
+        Some(VmStats {
+            cpu_time: vm.usage.cpu_time,
+            cpu_cores: vm.config.cpu_cores,
+            memory_used: vm.memory.allocated(),
+            memory_limit: (vm.config.memory_mb as u64) << 20,
+            disk_used: vm.disk.allocated(),
+            disk_capacity: vm.disk.capacity(),
+            disk_read: vm.usage.disk_read,
+            disk_written: vm.usage.disk_written,
+            network_received,
+            network_sent,
+        })
+    }
+
+    /// Change a VM's vCPUs; a running VM gets or loses them at once,
+    /// once its guest has brought them online or taken them offline
+    pub fn set_cpu_cores(&self, vm_id: VmId, cpu_cores: usize) -> Result<(), String> {
+        if !(1..=MAX_CPU_CORES).contains(&cpu_cores) {
+            return Err(format!("A VM has 1 to {} vCPUs", MAX_CPU_CORES));
+        }
+        let vm = self.get_vm(vm_id).ok_or("VM not found")?;
+        if vm.state == VmState::Running && cpu_cores != vm.config.cpu_cores {
+            self.tell_guest(vm_id, &AgentRequest::SetCpus { count: cpu_cores })?;
+        }
+        let mut vms = self.vms.lock().unwrap();
+        let vm = vms.get_mut(&vm_id).ok_or("VM not found")?;
+        vm.config.cpu_cores = cpu_cores;
+        Ok(())
+    }
+
+    /// Plug more memory into a running VM, raising how far it can be
+    /// ballooned up
+    pub fn hotplug_memory(&self, vm_id: VmId, mb: usize) -> Result<(), String> {
+        let vms = self.vms.lock().unwrap();
+        let vm = vms.get(&vm_id).ok_or("VM not found")?;
+        if vm.state != VmState::Running {
+            return Err("VM is not running".to_string());
+        }
+        if mb == 0 {
+            return Ok(());
+        }
+        self.check_host_memory(&vms, mb)?;
+        drop(vms);
+        self.tell_guest(vm_id, &AgentRequest::HotplugMemory { mb })?;
+
+        let mut vms = self.vms.lock().unwrap();
+        let vm = vms.get_mut(&vm_id).ok_or("VM not found")?;
+        vm.max_memory_mb += mb;
+        vm.config.memory_mb += mb;
+        vm.memory.resize((vm.max_memory_mb as u64) << 20)
+    }
+
+    /// Change a VM's memory. A running or paused VM is ballooned: it can
+    /// give back memory it does not use and take it again, up to what it
+    /// started with or was hotplugged.
+    pub fn set_memory(&self, vm_id: VmId, memory_mb: usize) -> Result<(), String> {
+        if memory_mb == 0 {
+            return Err("A VM needs some memory".to_string());
+        }
+        let mut vms = self.vms.lock().unwrap();
+        let vm = vms.get_mut(&vm_id).ok_or("VM not found")?;
+        let limit = (memory_mb as u64) << 20;
+
+        if vm.state == VmState::Stopped {
+            vm.config.memory_mb = memory_mb;
+            vm.memory = SparseImage::new(limit);
+            vm.max_memory_mb = memory_mb;
+            return Ok(());
+        }
+        if memory_mb > vm.max_memory_mb {
+            return Err(format!("VM '{}' has {} MB plugged in and cannot get more without hotplug", vm.name, vm.max_memory_mb));
+        }
+        if vm.memory.allocated() > limit {
+            return Err(format!("VM '{}' uses more than {} MB", vm.name, memory_mb));
+        }
+        let (current, running) = (vm.config.memory_mb, vm.state == VmState::Running);
+        if memory_mb > current {
+            self.check_host_memory(&vms, memory_mb - current)?;
+        }
+        drop(vms);
+
+        // The guest gives up memory or takes it back before the limit moves
+        if running && memory_mb < current {
+            self.tell_guest(vm_id, &AgentRequest::InflateBalloon { mb: current - memory_mb })?;
+        } else if running && memory_mb > current {
+            self.tell_guest(vm_id, &AgentRequest::DeflateBalloon { mb: memory_mb - current })?;
+        }
+        let mut vms = self.vms.lock().unwrap();
+        let vm = vms.get_mut(&vm_id).ok_or("VM not found")?;
+        vm.config.memory_mb = memory_mb;
+        let event = vm.usage.check(vm_id, VmResource::Memory, vm.memory.allocated(), limit);
+        drop(vms);
+        self.notify(event);
+        Ok(())
+    }
+
+    /// Hear about VMs nearing their limits
+    pub fn set_event_listener(&mut self, listener: VmEventListener) {
+        self.listener = Some(listener);
+    }
+
+    fn notify(&self, event: Option<VmEvent>) {
+        if let (Some(listener), Some(event)) = (&self.listener, event) {
+            listener(&event);
+        }
+    }
+
+    /// Read from a VM's memory
+    pub fn read_memory(&self, vm_id: VmId, offset: u64, len: usize) -> Result<Vec<u8>, String> {
+        let vms = self.vms.lock().unwrap();
+        vms.get(&vm_id).ok_or("VM not found")?.memory.read(offset, len)
+    }
+
+    /// Snapshot a stopped or paused VM under a name of its own; the memory
+    /// of a paused VM is kept too
+    pub fn snapshot_vm(&self, vm_id: VmId, name: &str) -> Result<(), String> {
+        let vms = self.vms.lock().unwrap();
+        let vm = vms.get(&vm_id).ok_or("VM not found")?;
+
+        let memory = match vm.state {
+            VmState::Stopped => None,
+            VmState::Paused => Some(vm.memory.clone()),
+            _ => return Err("Pause or stop the VM before taking a snapshot".to_string()),
+        };
+        let mut snapshots = self.snapshots.lock().unwrap();
+        let taken = snapshots.entry(vm_id).or_default();
+        if taken.iter().any(|s| s.name == name) {
+            return Err(format!("Snapshot '{}' already exists", name));
+        }
+
+        taken.push(Snapshot {
+            name: name.to_string(),
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            config: vm.config.clone(),
+            disk: vm.disk.clone(),
+            memory,
+        });
+        Ok(())
+    }
+
+    /// Roll a stopped or paused VM back to a snapshot. It is paused
+    /// afterwards if the snapshot has memory, and stopped otherwise.
+    pub fn restore_snapshot(&self, vm_id: VmId, name: &str) -> Result<(), String> {
+        let mut vms = self.vms.lock().unwrap();
+        let vm = vms.get_mut(&vm_id).ok_or("VM not found")?;
+
+        if vm.state != VmState::Stopped && vm.state != VmState::Paused {
+            return Err("Pause or stop the VM before restoring a snapshot".to_string());
+        }
+        let snapshots = self.snapshots.lock().unwrap();
+        let snapshot = snapshots
+            .get(&vm_id)
+            .and_then(|taken| taken.iter().find(|s| s.name == name))
+            .ok_or_else(|| format!("Snapshot '{}' not found", name))?;
+
+        vm.config = snapshot.config.clone();
+        vm.disk = snapshot.disk.clone();
+        match &snapshot.memory {
+            Some(memory) => {
+                vm.memory = memory.clone();
+                vm.max_memory_mb = (memory.capacity() >> 20) as usize;
+                vm.state = VmState::Paused;
+            }
+            None => {
+                vm.memory = SparseImage::new((vm.config.memory_mb as u64) << 20);
+                vm.state = VmState::Stopped;
+            }
+        }
+        println!("VM '{}' restored to snapshot '{}'", vm.name, name);
+        Ok(())
+    }
+
+    /// List a VM's snapshots, oldest first
+    pub fn list_snapshots(&self, vm_id: VmId) -> Vec<SnapshotInfo> {
+        let snapshots = self.snapshots.lock().unwrap();
+        snapshots.get(&vm_id).map_or_else(Vec::new, |taken| taken.iter().map(Snapshot::info).collect())
+    }
+
+    /// Delete a snapshot
+    pub fn delete_snapshot(&self, vm_id: VmId, name: &str) -> Result<(), String> {
+        let mut snapshots = self.snapshots.lock().unwrap();
+        let taken = snapshots.get_mut(&vm_id).ok_or_else(|| format!("Snapshot '{}' not found", name))?;
+        let before = taken.len();
+        taken.retain(|s| s.name != name);
+        if taken.len() == before {
+            return Err(format!("Snapshot '{}' not found", name));
+        }
         Ok(())
     }
 
@@ -235,100 +895,197 @@ impl Chrysalis {
     }
 
     /// Launch a Linux application
-    pub fn launch_linux_app(&self, executable_path: PathBuf) -> Result<(), String> {
+    pub fn launch_linux_app(&self, executable_path: PathBuf) -> Result<GuestApplication, String> {
         if !self.installed {
             return Err("Chrysalis not installed".to_string());
         }
 
-        // Find or create a Linux VM
-        let vms = self.vms.lock().unwrap();
-        let linux_vm = vms.values().find(|vm| vm.guest_os == GuestOS::Linux && vm.state == VmState::Running);
+        let vm_id = self.runtime_vm(GuestOS::Linux)?;
+        let name = executable_path.file_name().map_or_else(String::new, |n| n.to_string_lossy().into_owned());
+        self.launch_in_guest(vm_id, name, executable_path)
+    }
 
-        if linux_vm.is_none() {
-            drop(vms);
-            println!("No running Linux VM found. Creating one...");
-            let vm_id = self.create_vm("Linux Container".to_string(), GuestOS::Linux, VmConfig::default())?;
-            self.start_vm(vm_id)?;
+    /// Launch an Android application
+    pub fn launch_android_app(&self, package_name: &str) -> Result<GuestApplication, String> {
+        if !self.installed {
+            return Err("Chrysalis not installed".to_string());
         }
 
-        println!("Launching Linux application: {:?}", executable_path);
-        Ok(())
+        let vm_id = self.runtime_vm(GuestOS::Android)?;
+        self.launch_in_guest(vm_id, package_name.to_string(), PathBuf::from(package_name))
     }
 
-    /// Launch an Android application
-    pub fn launch_android_app(&self, package_name: &str) -> Result<(), String> {
+    /// A running VM for the guest OS, created and started if there is none
+    fn runtime_vm(&self, guest_os: GuestOS) -> Result<VmId, String> {
+        let vms = self.vms.lock().unwrap();
+        let running = vms.values().find(|vm| vm.guest_os == guest_os && vm.state == VmState::Running).map(|vm| vm.id);
+        drop(vms);
+        if let Some(vm_id) = running {
+            return Ok(vm_id);
+        }
+
+        let name = match guest_os {
+            GuestOS::Linux => "Linux Container",
+            GuestOS::Android => "Android Runtime",
+        };
+        println!("No running {:?} VM found. Creating one...", guest_os);
+        let vm_id = self.create_vm(name.to_string(), guest_os, VmConfig::default())?;
+        self.start_vm(vm_id)?;
+        Ok(vm_id)
+    }
+
+    /// Install an APK in the Android runtime. An app installed before
+    /// keeps its data directory.
+    pub fn install_apk(&self, apk: &[u8]) -> Result<AndroidApp, String> {
         if !self.installed {
             return Err("Chrysalis not installed".to_string());
         }
+        let vm_id = self.runtime_vm(GuestOS::Android)?;
+        let agent = self.agent(vm_id)?;
+        let info = match agent.request(&AgentRequest::InstallPackage { apk: apk.to_vec() })? {
+            AgentResponse::Installed(info) => info,
+            other => return Err(format!("Unexpected response from the guest agent: {:?}", other)),
+        };
 
-        // Find or create an Android VM
-        let vms = self.vms.lock().unwrap();
-        let android_vm = vms.values().find(|vm| vm.guest_os == GuestOS::Android && vm.state == VmState::Running);
+        let capacity = self.get_vm(vm_id).ok_or("VM not found")?.disk.capacity();
+        let apps = self.android_apps.lock().unwrap();
+        let data = match apps.iter().find(|a| a.vm_id == vm_id && a.info.package == info.package) {
+            Some(app) => app.data.clone(),
+            None => {
+                let taken: Vec<&DataDir> = apps.iter().filter(|a| a.vm_id == vm_id).map(|a| &a.data).collect();
+                android::allocate_data(&info.package, capacity, &taken)?
+            }
+        };
+        drop(apps);
+        let (granted, capabilities, denied) = android::map_permissions(&info.permissions);
+        if !denied.is_empty() {
+            println!("Denied {} permissions hairr OS has no equivalent for: {}", info.package, denied.join(", "));
+        }
 
-        if android_vm.is_none() {
-            drop(vms);
-            println!("No running Android VM found. Creating one...");
-            let vm_id = self.create_vm("Android Runtime".to_string(), GuestOS::Android, VmConfig::default())?;
-            self.start_vm(vm_id)?;
+        let configure = AgentRequest::ConfigurePackage {
+            package: info.package.clone(),
+            data: data.clone(),
+            granted: granted.clone(),
+        };
+        if let Err(e) = agent.request(&configure) {
+            let _ = agent.request(&AgentRequest::UninstallPackage { package: info.package.clone() });
+            return Err(e);
         }
+        let app = AndroidApp {
+            info,
+            vm_id,
+            data,
+            granted,
+            capabilities,
+        };
+        let mut apps = self.android_apps.lock().unwrap();
+        apps.retain(|a| a.vm_id != vm_id || a.info.package != app.info.package);
+        apps.push(app.clone());
+        Ok(app)
+    }
 
-        println!("Launching Android app: {}", package_name);
+    /// Uninstall an app installed with `install_apk`, wiping its data
+    pub fn uninstall_apk(&self, package: &str) -> Result<(), String> {
+        let app = self.android_app(package).ok_or_else(|| format!("{} is not installed", package))?;
+        self.agent(app.vm_id)?.request(&AgentRequest::UninstallPackage { package: package.to_string() })?;
+
+        let mut vms = self.vms.lock().unwrap();
+        if let Some(vm) = vms.get_mut(&app.vm_id) {
+            vm.disk.discard(app.data.offset, app.data.size)?;
+        }
+        drop(vms);
+        self.android_apps.lock().unwrap().retain(|a| a.vm_id != app.vm_id || a.info.package != package);
+        self.applications.lock().unwrap().retain(|_, a| a.vm_id != app.vm_id || a.name != package);
         Ok(())
     }
 
+    /// List the apps installed in the Android runtime, as its agent reports
+    pub fn list_apks(&self) -> Result<Vec<InstalledApp>, String> {
+        if !self.installed {
+            return Err("Chrysalis not installed".to_string());
+        }
+        self.guest_apps(self.runtime_vm(GuestOS::Android)?)
+    }
+
+    /// An app installed with `install_apk`
+    pub fn android_app(&self, package: &str) -> Option<AndroidApp> {
+        self.android_apps.lock().unwrap().iter().find(|a| a.info.package == package).cloned()
+    }
+
+    /// Have a VM's agent start an app, and list it until it exits
+    fn launch_in_guest(&self, vm_id: VmId, name: String, executable_path: PathBuf) -> Result<GuestApplication, String> {
+        let request = AgentRequest::Launch {
+            target: executable_path.to_string_lossy().into_owned(),
+        };
+        let process_id = match self.agent(vm_id)?.request(&request)? {
+            AgentResponse::Launched { pid } => pid,
+            other => return Err(format!("Unexpected response from the guest agent: {:?}", other)),
+        };
+        let app = GuestApplication {
+            name,
+            executable_path,
+            vm_id,
+            process_id,
+        };
+        self.applications.lock().unwrap().insert(app.name.clone(), app.clone());
+        Ok(app)
+    }
+
+    /// List applications launched in guest VMs
+    pub fn list_applications(&self) -> Vec<GuestApplication> {
+        self.applications.lock().unwrap().values().cloned().collect()
+    }
+
     /// Check if Docker daemon can be run
     pub fn supports_docker(&self) -> bool {
         self.installed
     }
 
-    /// Start Docker daemon in a Linux container
+    /// Make Docker images runnable; they run as containers without a VM
     pub fn start_docker(&self) -> Result<(), String> {
         if !self.installed {
             return Err("Chrysalis not installed".to_string());
         }
 
-        println!("Starting Docker daemon in Linux container...");
+        self.containers()?;
         println!("Docker is now available on hairr OS!");
         Ok(())
     }
 
-    /// Detect and handle foreign binaries
-    pub fn detect_foreign_binary(&self, path: &PathBuf) -> Option<GuestOS> {
-        let extension = path.extension()?.to_str()?;
-        
-        match extension {
-            "deb" | "rpm" | "AppImage" => Some(GuestOS::Linux),
-            "apk" => Some(GuestOS::Android),
-            _ => {
-                // Check ELF header for Linux binaries
-                if path.to_str()?.contains("elf") {
-                    Some(GuestOS::Linux)
-                } else {
-                    None
-                }
-            }
-        }
+    /// Detect foreign binaries from their contents in storage
+    pub fn detect_foreign_binary(&self, path: &Path) -> Option<BinaryInfo> {
+        let data = self.disk_images().ok()?.vfs().read_file(path).ok()?;
+        binary::inspect(&data)
     }
 
     /// Auto-install prompt for foreign binaries
-    pub fn prompt_install_for_binary(&self, path: &PathBuf) -> Result<(), String> {
-        if let Some(guest_os) = self.detect_foreign_binary(path) {
-            if !self.installed {
-                println!("This file requires Chrysalis compatibility suite.");
-                println!("Would you like to install Chrysalis? (yes/no)");
-                println!("Run: pkg install chrysalis");
-                return Err("Chrysalis not installed".to_string());
-            }
+    pub fn prompt_install_for_binary(&self, path: &Path) -> Result<(), String> {
+        let info = self.detect_foreign_binary(path).ok_or("Unknown binary format")?;
+        if !self.installed {
+            println!("This file requires Chrysalis compatibility suite.");
+            println!("Would you like to install Chrysalis? (yes/no)");
+            println!("Run: pkg install chrysalis");
+            return Err("Chrysalis not installed".to_string());
+        }
+        if !info.runs_on_host() {
+            return match &info.elf {
+                Some(elf) if info.guest_os.is_some() => Err(format!("{} is built for {:?}, not this machine", path.display(), elf.machine)),
+                _ => Err(format!("{} is not for Linux or Android", path.display())),
+            };
+        }
 
-            match guest_os {
-                GuestOS::Linux => self.launch_linux_app(path.clone()),
-                GuestOS::Android => {
-                    let package_name = path.file_name().unwrap().to_str().unwrap();
-                    self.launch_android_app(package_name)
-                }
+        match info.kind {
+            BinaryKind::Apk => {
+                let apk = self.disk_images()?.vfs().read_file(path)?;
+                let app = self.install_apk(&apk)?;
+                self.launch_android_app(&app.info.package).map(|_| ())
+            }
+            _ if info.guest_os == Some(GuestOS::Android) => {
+                let vm_id = self.runtime_vm(GuestOS::Android)?;
+                let name = path.file_name().map_or_else(String::new, |n| n.to_string_lossy().into_owned());
+                self.launch_in_guest(vm_id, name, path.to_path_buf()).map(|_| ())
             }
-        } else {
-            Err("Unknown binary format".to_string())
+            _ => self.launch_linux_app(path.to_path_buf()).map(|_| ()),
         }
     }
 }
@@ -386,6 +1143,372 @@ mod tests {
         assert!(chrysalis.stop_vm(vm_id).is_ok());
     }
 
+    #[test]
+    fn test_snapshot_rollback() {
+        let mut chrysalis = Chrysalis::new();
+        chrysalis.install().unwrap();
+        let vm_id = chrysalis.create_vm("Test VM".to_string(), GuestOS::Linux, VmConfig::default()).unwrap();
+        chrysalis.write_disk(vm_id, 0, b"clean").unwrap();
+        chrysalis.snapshot_vm(vm_id, "before-install").unwrap();
+        assert!(chrysalis.snapshot_vm(vm_id, "before-install").is_err());
+
+        chrysalis.start_vm(vm_id).unwrap();
+        assert!(chrysalis.snapshot_vm(vm_id, "running").is_err());
+        chrysalis.write_memory(vm_id, 64, b"state").unwrap();
+        chrysalis.pause_vm(vm_id).unwrap();
+        chrysalis.snapshot_vm(vm_id, "paused").unwrap();
+        chrysalis.write_disk(vm_id, 0, b"broke").unwrap();
+        assert!(chrysalis.restore_snapshot(vm_id, "missing").is_err());
+
+        chrysalis.restore_snapshot(vm_id, "before-install").unwrap();
+        assert_eq!(chrysalis.get_vm(vm_id).unwrap().state, VmState::Stopped);
+        assert_eq!(chrysalis.read_disk(vm_id, 0, 5).unwrap(), b"clean");
+        assert_eq!(chrysalis.read_memory(vm_id, 64, 5).unwrap(), vec![0; 5]);
+
+        chrysalis.restore_snapshot(vm_id, "paused").unwrap();
+        assert_eq!(chrysalis.get_vm(vm_id).unwrap().state, VmState::Paused);
+        assert_eq!(chrysalis.read_memory(vm_id, 64, 5).unwrap(), b"state");
+        chrysalis.resume_vm(vm_id).unwrap();
+
+        let snapshots = chrysalis.list_snapshots(vm_id);
+        assert_eq!(snapshots.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(), vec!["before-install", "paused"]);
+        assert!(!snapshots[0].has_memory && snapshots[1].has_memory);
+        chrysalis.delete_snapshot(vm_id, "paused").unwrap();
+        assert!(chrysalis.delete_snapshot(vm_id, "paused").is_err());
+        assert_eq!(chrysalis.list_snapshots(vm_id).len(), 1);
+    }
+
+    #[test]
+    fn test_shared_base_image() {
+        let mut chrysalis = Chrysalis::new();
+        chrysalis.install().unwrap();
+        let first = chrysalis.create_vm("First".to_string(), GuestOS::Linux, VmConfig::default()).unwrap();
+        let second = chrysalis.create_vm("Second".to_string(), GuestOS::Linux, VmConfig::default()).unwrap();
+        let (base, a, b) = (Path::new("/disks/debian.img"), Path::new("/disks/a.img"), Path::new("/disks/b.img"));
+        assert!(chrysalis.attach_disk(first, base).is_err());
+
+        chrysalis.attach_storage(Arc::new(VirtualFileSystem::new()));
+        let images = chrysalis.disk_images().unwrap();
+        images.create(base, 1 << 30).unwrap();
+        images.write(base, 0, b"debian").unwrap();
+        images.create_overlay(a, base).unwrap();
+        images.create_overlay(b, base).unwrap();
+        assert!(chrysalis.attach_disk(first, base).is_err());
+        chrysalis.attach_disk(first, a).unwrap();
+        assert!(chrysalis.attach_disk(second, a).is_err());
+        chrysalis.attach_disk(second, b).unwrap();
+
+        images.write(a, 0, b"ubuntu").unwrap();
+        assert_eq!(images.read(b, 0, 6).unwrap(), b"debian");
+        chrysalis.start_vm(first).unwrap();
+        assert!(chrysalis.resize_disk(a, 2).is_err());
+        assert!(chrysalis.detach_disk(first, a).is_err());
+        chrysalis.stop_vm(first).unwrap();
+        chrysalis.resize_disk(a, 2).unwrap();
+        assert_eq!(images.load(a).unwrap().data.capacity(), 2 << 30);
+        assert!(chrysalis.delete_disk(a).is_err());
+        chrysalis.detach_disk(first, a).unwrap();
+        chrysalis.delete_disk(a).unwrap();
+        assert!(chrysalis.get_vm(first).unwrap().attached_disks.is_empty());
+    }
+
+    #[test]
+    fn test_create_vm_from_template() {
+        let mut chrysalis = Chrysalis::new();
+        chrysalis.install().unwrap();
+        chrysalis.attach_storage(Arc::new(VirtualFileSystem::new()));
+        let base = PathBuf::from("/var/lib/chrysalis/disks/debian-12.img");
+        let images = chrysalis.disk_images().unwrap();
+        images.create(&base, 4 << 30).unwrap();
+        images.write(&base, 0, b"debian").unwrap();
+        let config = VmConfig { memory_mb: 1024, disk_size_gb: 4, ..VmConfig::default() };
+        chrysalis.add_template(VmTemplate::new("debian-12", "Debian 12", GuestOS::Linux, config.clone(), base.clone())).unwrap();
+        assert!(chrysalis.add_template(VmTemplate::new("debian-12", "Debian 12", GuestOS::Linux, config, base.clone())).is_err());
+
+        let overrides = ConfigOverrides { memory_mb: Some(4096), disk_size_gb: Some(8), ..ConfigOverrides::default() };
+        let dev = chrysalis.create_vm_from_template("debian-12", "Dev".to_string(), overrides).unwrap();
+        let plain = chrysalis.create_vm_from_template("debian-12", "Plain".to_string(), ConfigOverrides::default()).unwrap();
+        let small = ConfigOverrides { disk_size_gb: Some(1), ..ConfigOverrides::default() };
+        assert!(chrysalis.create_vm_from_template("debian-12", "Small".to_string(), small).is_err());
+        assert!(chrysalis.create_vm_from_template("arch", "Arch".to_string(), ConfigOverrides::default()).is_err());
+        assert_eq!(chrysalis.list_vms().len(), 2);
+
+        let vm = chrysalis.get_vm(dev).unwrap();
+        assert_eq!((vm.config.memory_mb, vm.config.cpu_cores), (4096, 2));
+        assert_eq!(vm.template.as_deref(), Some("debian-12"));
+        let disk = vm.clone_disk.clone().unwrap();
+        assert_eq!(vm.attached_disks, vec![disk.clone()]);
+        assert_eq!(images.load(&disk).unwrap().data.capacity(), 8 << 30);
+        images.write(&disk, 0, b"ubuntu").unwrap();
+        let plain_disk = chrysalis.get_vm(plain).unwrap().clone_disk.unwrap();
+        assert_eq!(images.read(&plain_disk, 0, 6).unwrap(), b"debian");
+        assert!(images.load(&base).unwrap().read_only);
+
+        chrysalis.remove_template("debian-12").unwrap();
+        chrysalis.start_vm(dev).unwrap();
+        chrysalis.stop_vm(dev).unwrap();
+        chrysalis.delete_vm(dev).unwrap();
+        assert!(images.load(&disk).is_err());
+    }
+
+    #[test]
+    fn test_vm_port_forwards() {
+        use capability::{CapabilityManager, Permission, Resource};
+        use net_stack::Firewall;
+
+        let mut chrysalis = Chrysalis::new();
+        chrysalis.install().unwrap();
+        let offline = VmConfig { network_enabled: false, ..VmConfig::default() };
+        let isolated = chrysalis.create_vm("Offline".to_string(), GuestOS::Linux, offline).unwrap();
+        let vm_id = chrysalis.create_vm("Web".to_string(), GuestOS::Linux, VmConfig::default()).unwrap();
+        assert!(chrysalis.connect_network(vm_id).is_err());
+
+        let capabilities = Arc::new(CapabilityManager::new());
+        let token = capabilities.grant(Resource::Network(net_stack::firewall::FIREWALL_RESOURCE.to_string()), Permission::ReadWrite);
+        let firewall = Arc::new(Firewall::new(capabilities));
+        chrysalis.attach_network(VmNetwork::new(Arc::clone(&firewall), token, "eth0", std::net::Ipv4Addr::new(203, 0, 113, 5)));
+        assert!(chrysalis.connect_network(isolated).is_err());
+        let (_nic, config) = chrysalis.connect_network(vm_id).unwrap();
+        assert!(config.is_some());
+
+        let forward = PortForward { protocol: Protocol::Tcp, host_port: 8080, guest_port: 80 };
+        chrysalis.add_port_forward(vm_id, forward).unwrap();
+        assert_eq!(chrysalis.port_forwards(vm_id), vec![forward]);
+        assert_eq!(firewall.rules().len(), 1);
+        chrysalis.delete_vm(vm_id).unwrap();
+        assert!(firewall.rules().is_empty());
+        assert!(chrysalis.port_forwards(vm_id).is_empty());
+    }
+
+    #[test]
+    fn test_stats_and_ballooning() {
+        let mut chrysalis = Chrysalis::new();
+        chrysalis.install().unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&events);
+        chrysalis.set_event_listener(Arc::new(move |event: &VmEvent| seen.lock().unwrap().push(event.clone())));
+        let config = VmConfig { memory_mb: 1, ..VmConfig::default() };
+        let vm_id = chrysalis.create_vm("Test VM".to_string(), GuestOS::Linux, config).unwrap();
+        chrysalis.start_vm(vm_id).unwrap();
+
+        chrysalis.write_disk(vm_id, 0, &[1; 100]).unwrap();
+        chrysalis.read_disk(vm_id, 0, 40).unwrap();
+        chrysalis.write_memory(vm_id, 0, &[1; 200 * 1024]).unwrap();
+        chrysalis.charge_cpu(vm_id, Duration::from_millis(250)).unwrap();
+        let stats = chrysalis.vm_stats(vm_id).unwrap();
+        assert_eq!((stats.disk_written, stats.disk_read), (100, 40));
+        assert_eq!(stats.memory_used, 200 * 1024);
+        assert_eq!(stats.cpu_time, Duration::from_millis(250));
+        assert!(events.lock().unwrap().is_empty());
+
+        chrysalis.set_cpu_cores(vm_id, 4).unwrap();
+        assert!(chrysalis.set_cpu_cores(vm_id, 0).is_err());
+        assert_eq!(chrysalis.vm_stats(vm_id).unwrap().cpu_cores, 4);
+        assert!(chrysalis.set_memory(vm_id, 2).is_err());
+
+        let config = VmConfig { memory_mb: 1, ..VmConfig::default() };
+        let small = chrysalis.create_vm("Small".to_string(), GuestOS::Linux, config).unwrap();
+        chrysalis.start_vm(small).unwrap();
+        chrysalis.write_memory(small, 0, &[1; 950 * 1024]).unwrap();
+        assert!(chrysalis.write_memory(small, 1000 * 1024, &[1; 100 * 1024]).is_err());
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![VmEvent::NearLimit { vm: small, resource: VmResource::Memory, used: 952 * 1024, limit: 1 << 20 }]
+        );
+        chrysalis.stop_vm(small).unwrap();
+        chrysalis.set_memory(small, 4).unwrap();
+        chrysalis.start_vm(small).unwrap();
+        chrysalis.write_memory(small, 0, &[1; 2 << 20]).unwrap();
+        assert!(chrysalis.set_memory(small, 1).is_err());
+        chrysalis.set_memory(small, 2).unwrap();
+        assert_eq!(events.lock().unwrap().len(), 2);
+        assert_eq!(chrysalis.vm_stats(small).unwrap().memory_limit, 2 << 20);
+    }
+
+    #[test]
+    fn test_hotplug_tells_guest() {
+        let ipc = Arc::new(IPCManager::new());
+        let mut chrysalis = Chrysalis::new();
+        chrysalis.install().unwrap();
+        chrysalis.attach_host_memory(Arc::new(MemoryManager::new(3072)));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let (seen, guest) = (Arc::clone(&requests), Arc::clone(&ipc));
+        chrysalis.attach_agents(
+            Arc::clone(&ipc),
+            Arc::new(move |_: &VirtualMachine, channels: AgentChannels| {
+                let (seen, mut ballooned) = (Arc::clone(&seen), 0);
+                serve_agent(Arc::clone(&guest), channels, move |request| {
+                    seen.lock().unwrap().push(request.clone());
+                    match request {
+                        // The guest needs 512 MB of the 2048 MB it has
+                        AgentRequest::InflateBalloon { mb } if ballooned + mb > 1536 => AgentResponse::Error { message: "Memory in use".to_string() },
+                        AgentRequest::InflateBalloon { mb } => {
+                            ballooned += mb;
+                            AgentResponse::Ok
+                        }
+                        AgentRequest::DeflateBalloon { mb } => {
+                            ballooned -= mb;
+                            AgentResponse::Ok
+                        }
+                        _ => AgentResponse::Ok,
+                    }
+                });
+                Ok(())
+            }),
+        );
+        let config = VmConfig { memory_mb: 1024, ..VmConfig::default() };
+        let vm = chrysalis.create_vm("Build".to_string(), GuestOS::Linux, config).unwrap();
+        chrysalis.start_vm(vm).unwrap();
+
+        chrysalis.set_cpu_cores(vm, 8).unwrap();
+        chrysalis.hotplug_memory(vm, 1024).unwrap();
+        // 3072 MB less the host's reserve and the 2048 MB already given
+        assert!(chrysalis.hotplug_memory(vm, 1024).is_err());
+        chrysalis.set_memory(vm, 1024).unwrap();
+        assert!(chrysalis.set_memory(vm, 256).is_err());
+        chrysalis.set_memory(vm, 2048).unwrap();
+        assert_eq!(chrysalis.vm_stats(vm).unwrap().memory_limit, 2048 << 20);
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![
+                AgentRequest::SetCpus { count: 8 },
+                AgentRequest::HotplugMemory { mb: 1024 },
+                AgentRequest::InflateBalloon { mb: 1024 },
+                AgentRequest::InflateBalloon { mb: 768 },
+                AgentRequest::DeflateBalloon { mb: 1024 },
+            ]
+        );
+
+        let config = VmConfig { memory_mb: 1024, ..VmConfig::default() };
+        let other = chrysalis.create_vm("Other".to_string(), GuestOS::Linux, config).unwrap();
+        assert!(chrysalis.start_vm(other).is_err());
+        chrysalis.set_memory(vm, 1024).unwrap();
+        chrysalis.start_vm(other).unwrap();
+        chrysalis.stop_vm(vm).unwrap();
+        assert!(chrysalis.hotplug_memory(vm, 1).is_err());
+    }
+
+    /// Answer a VM's agent requests from a thread until its channels close
+    fn serve_agent(ipc: Arc<IPCManager>, channels: AgentChannels, mut handler: impl FnMut(AgentRequest) -> AgentResponse + Send + 'static) {
+        std::thread::spawn(move || {
+            while agent::serve(&ipc, channels, &mut handler).is_ok() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        });
+    }
+
+    #[test]
+    fn test_launch_through_guest_agent() {
+        let ipc = Arc::new(IPCManager::new());
+        let mut chrysalis = Chrysalis::new();
+        chrysalis.install().unwrap();
+        let guest = Arc::clone(&ipc);
+        chrysalis.attach_agents(
+            Arc::clone(&ipc),
+            Arc::new(move |vm: &VirtualMachine, channels: AgentChannels| {
+                let os = vm.guest_os;
+                agent::send_event(&guest, channels, &GuestEvent::Booted)?;
+                let mut next_pid = 100;
+                serve_agent(Arc::clone(&guest), channels, move |request| match request {
+                    AgentRequest::Launch { .. } => {
+                        next_pid += 1;
+                        AgentResponse::Launched { pid: next_pid }
+                    }
+                    AgentRequest::ListApps if os == GuestOS::Android => AgentResponse::Apps {
+                        apps: vec![InstalledApp { id: "org.fdroid.fdroid".to_string(), name: "F-Droid".to_string(), version: "1.19".to_string() }],
+                    },
+                    AgentRequest::Exec { command, args } => AgentResponse::Exited(CommandOutput { code: 0, stdout: format!("{} {}", command, args.join(" ")), stderr: String::new() }),
+                    _ => AgentResponse::Ok,
+                });
+                Ok(())
+            }),
+        );
+
+        let app = chrysalis.launch_linux_app(PathBuf::from("/usr/bin/gimp")).unwrap();
+        assert_eq!((app.name.as_str(), app.process_id), ("gimp", 101));
+        let vm = app.vm_id;
+        assert_eq!(chrysalis.launch_linux_app(PathBuf::from("/usr/bin/vlc")).unwrap().vm_id, vm);
+        assert_eq!(chrysalis.exec_in_guest(vm, "uname", &["-s"]).unwrap().stdout, "uname -s");
+        chrysalis.push_clipboard(vm, "text/plain", "hello").unwrap();
+        assert_eq!(chrysalis.guest_events(vm).unwrap(), vec![GuestEvent::Booted]);
+
+        let channels = chrysalis.agent_channels(vm).unwrap();
+        agent::send_event(&ipc, channels, &GuestEvent::AppExited { pid: 101, code: 0 }).unwrap();
+        chrysalis.guest_events(vm).unwrap();
+        let running: Vec<String> = chrysalis.list_applications().into_iter().map(|a| a.name).collect();
+        assert_eq!(running, vec!["vlc"]);
+
+        let android = chrysalis.launch_android_app("org.fdroid.fdroid").unwrap();
+        assert_eq!(chrysalis.guest_apps(android.vm_id).unwrap()[0].name, "F-Droid");
+
+        agent::send_event(&ipc, channels, &GuestEvent::ShuttingDown).unwrap();
+        chrysalis.guest_events(vm).unwrap();
+        assert_eq!(chrysalis.get_vm(vm).unwrap().state, VmState::Stopped);
+        assert_eq!(chrysalis.agent_channels(vm), None);
+        assert!(chrysalis.exec_in_guest(vm, "true", &[]).is_err());
+        assert_eq!(chrysalis.list_applications().len(), 1);
+    }
+
+    #[test]
+    fn test_apk_lifecycle() {
+        let ipc = Arc::new(IPCManager::new());
+        let mut chrysalis = Chrysalis::new();
+        chrysalis.install().unwrap();
+        let guest = Arc::clone(&ipc);
+        chrysalis.attach_agents(
+            Arc::clone(&ipc),
+            Arc::new(move |_: &VirtualMachine, channels: AgentChannels| {
+                // The test APKs are their manifests
+                let mut installed: Vec<ApkInfo> = Vec::new();
+                serve_agent(Arc::clone(&guest), channels, move |request| match request {
+                    AgentRequest::InstallPackage { apk } => {
+                        let info: ApkInfo = serde_json::from_slice(&apk).unwrap();
+                        installed.retain(|i| i.package != info.package);
+                        installed.push(info.clone());
+                        AgentResponse::Installed(info)
+                    }
+                    AgentRequest::UninstallPackage { package } => {
+                        installed.retain(|i| i.package != package);
+                        AgentResponse::Ok
+                    }
+                    AgentRequest::ListApps => AgentResponse::Apps {
+                        apps: installed.iter().map(|i| InstalledApp { id: i.package.clone(), name: i.label.clone(), version: i.version.clone() }).collect(),
+                    },
+                    _ => AgentResponse::Ok,
+                });
+                Ok(())
+            }),
+        );
+        let apk = |package: &str, version: &str, permissions: &[&str]| {
+            serde_json::to_vec(&ApkInfo {
+                package: package.to_string(),
+                label: package.to_string(),
+                version: version.to_string(),
+                permissions: permissions.iter().map(|p| format!("android.permission.{}", p)).collect(),
+            })
+            .unwrap()
+        };
+
+        let fdroid = chrysalis.install_apk(&apk("org.fdroid.fdroid", "1.19", &["INTERNET", "READ_SMS"])).unwrap();
+        assert_eq!(fdroid.data.path, "/data/data/org.fdroid.fdroid");
+        assert_eq!(fdroid.granted, vec!["android.permission.INTERNET"]);
+        assert_eq!(fdroid.capabilities, vec![(capability::Resource::Network("*".to_string()), capability::Permission::ReadWrite)]);
+        chrysalis.write_disk(fdroid.vm_id, fdroid.data.offset, b"repos").unwrap();
+
+        let upgraded = chrysalis.install_apk(&apk("org.fdroid.fdroid", "1.20", &["INTERNET"])).unwrap();
+        assert_eq!(upgraded.data, fdroid.data);
+        let camera = chrysalis.install_apk(&apk("net.sourceforge.opencamera", "1.53", &["CAMERA"])).unwrap();
+        assert_ne!(camera.data.offset, fdroid.data.offset);
+        let listed: Vec<String> = chrysalis.list_apks().unwrap().into_iter().map(|a| a.version).collect();
+        assert_eq!(listed, vec!["1.20", "1.53"]);
+
+        chrysalis.uninstall_apk("org.fdroid.fdroid").unwrap();
+        assert_eq!(chrysalis.read_disk(fdroid.vm_id, fdroid.data.offset, 5).unwrap(), vec![0; 5]);
+        assert_eq!(chrysalis.list_apks().unwrap().len(), 1);
+        assert!(chrysalis.android_app("org.fdroid.fdroid").is_none());
+        assert!(chrysalis.uninstall_apk("org.fdroid.fdroid").is_err());
+    }
+
     #[test]
     fn test_docker_support() {
         let mut chrysalis = Chrysalis::new();
@@ -397,12 +1520,30 @@ mod tests {
 
     #[test]
     fn test_foreign_binary_detection() {
-        let chrysalis = Chrysalis::new();
-        
-        let linux_binary = PathBuf::from("/usr/bin/test.deb");
-        assert_eq!(chrysalis.detect_foreign_binary(&linux_binary), Some(GuestOS::Linux));
-        
-        let android_binary = PathBuf::from("/apps/test.apk");
-        assert_eq!(chrysalis.detect_foreign_binary(&android_binary), Some(GuestOS::Android));
+        let mut chrysalis = Chrysalis::new();
+        let vfs = Arc::new(VirtualFileSystem::new());
+        chrysalis.attach_storage(vfs.clone());
+
+        // Names don't matter, contents do
+        let linux_binary = PathBuf::from("/home/user/Downloads/tool.apk");
+        vfs.write_file(&linux_binary, &binary::tests::elf(62, 3, "/lib64/ld-linux-x86-64.so.2")).unwrap();
+        let info = chrysalis.detect_foreign_binary(&linux_binary).unwrap();
+        assert_eq!((info.kind, info.guest_os), (BinaryKind::Elf, Some(GuestOS::Linux)));
+
+        let android_binary = PathBuf::from("/home/user/Downloads/app.zip");
+        vfs.write_file(&android_binary, &binary::tests::zip(&["AndroidManifest.xml", "classes.dex"])).unwrap();
+        assert_eq!(chrysalis.detect_foreign_binary(&android_binary).unwrap().guest_os, Some(GuestOS::Android));
+
+        let archive = PathBuf::from("/home/user/Downloads/photos.apk");
+        vfs.write_file(&archive, &binary::tests::zip(&["photo.jpg"])).unwrap();
+        assert_eq!(chrysalis.detect_foreign_binary(&archive), None);
+        assert_eq!(chrysalis.prompt_install_for_binary(&archive), Err("Unknown binary format".to_string()));
+
+        chrysalis.install().unwrap();
+        // 64-bit PowerPC, unless that is what runs the tests
+        let machine = if Machine::host().runs(Machine::Other(21)) { 62 } else { 21 };
+        let foreign = PathBuf::from("/home/user/Downloads/other-arch");
+        vfs.write_file(&foreign, &binary::tests::elf(machine, 0, "/lib/ld.so.1")).unwrap();
+        assert!(chrysalis.prompt_install_for_binary(&foreign).unwrap_err().contains("not this machine"));
     }
 }
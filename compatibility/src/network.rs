@@ -0,0 +1,542 @@
+//! VM networking
+//!
+//! Each VM is connected through a veth pair: the guest gets one end as its
+//! NIC and Chrysalis keeps the other, answering ARP on it for every address
+//! but the guest's own so all of the guest's traffic comes to the host.
+//!
+//! - NAT guests share the host's address. Outgoing TCP and UDP get a host
+//!   port, and replies to it go back to the guest that sent them. Port
+//!   forwards route a host port to a guest port; each installs a firewall
+//!   rule admitting the port, and other unsolicited traffic goes no
+//!   further.
+//! - Host-only guests reach the host and each other, and nothing else.
+//! - Bridged guests' packets leave as they are, and the guest is reached
+//!   at the address it sends from.
+//!
+//! Traffic to and from a guest is also checked against the firewall under
+//! the VM's interface name, `veth<vm id>`.
+
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+
+use capability::CapabilityToken;
+use hal::NetworkDevice;
+use net_stack::ethernet::{ETHERTYPE_ARP, ETHERTYPE_IPV4};
+use net_stack::firewall::{Action, Direction, PacketInfo, Protocol};
+use net_stack::ipv4::{pseudo_header_checksum, PROTOCOL_TCP, PROTOCOL_UDP};
+use net_stack::{ArpOperation, ArpPacket, EthernetFrame, Firewall, FirewallRule, Ipv4Config, Ipv4Packet, MacAddress, RuleId, VirtualNic};
+
+use crate::VmId;
+
+/// Network NAT guests are numbered in, with its gateway and first guest
+pub const NAT_NETWORK: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 0);
+pub const NAT_GATEWAY: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 2);
+const NAT_FIRST_GUEST: u8 = 15;
+
+/// Network shared by the host and host-only guests
+pub const HOST_ONLY_NETWORK: Ipv4Addr = Ipv4Addr::new(192, 168, 56, 0);
+pub const HOST_ONLY_HOST: Ipv4Addr = Ipv4Addr::new(192, 168, 56, 1);
+const HOST_ONLY_FIRST_GUEST: u8 = 101;
+
+const NETMASK: Ipv4Addr = Ipv4Addr::new(255, 255, 255, 0);
+
+/// Host ports given to outgoing NAT connections
+const DYNAMIC_PORTS: std::ops::RangeInclusive<u16> = 49152..=65535;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NetworkMode {
+    #[default]
+    Nat,
+    Bridged,
+    HostOnly,
+}
+
+/// Host port routed to a port of a NAT guest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortForward {
+    pub protocol: Protocol,
+    pub host_port: u16,
+    pub guest_port: u16,
+}
+
+/// Host end of a VM's veth pair
+struct Attachment {
+    mode: NetworkMode,
+    interface: String,
+    nic: VirtualNic,
+    guest_mac: MacAddress,
+    /// Assigned, or learned from a bridged guest's traffic
+    address: Option<Ipv4Addr>,
+    forwards: Vec<(PortForward, RuleId)>,
+    /// Bytes delivered to the guest
+    received: u64,
+    /// Bytes the guest sent
+    sent: u64,
+}
+
+/// Guest endpoint behind a host port
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Mapping {
+    vm: VmId,
+    guest: (Ipv4Addr, u16),
+}
+
+#[derive(Default)]
+struct NetworkState {
+    attachments: HashMap<VmId, Attachment>,
+    /// Outgoing NAT connections by protocol and host port
+    connections: HashMap<(Protocol, u16), Mapping>,
+    next_port: u16,
+}
+
+/// Routes traffic between VMs and the host
+pub struct VmNetwork {
+    firewall: Arc<Firewall>,
+    /// Write access to the firewall, for port forward rules
+    token: CapabilityToken,
+    /// Interface inbound traffic arrives on
+    uplink: String,
+    host_address: Ipv4Addr,
+    state: Mutex<NetworkState>,
+}
+
+impl VmNetwork {
+    pub fn new(firewall: Arc<Firewall>, token: CapabilityToken, uplink: &str, host_address: Ipv4Addr) -> Self {
+        VmNetwork {
+            firewall,
+            token,
+            uplink: uplink.to_string(),
+            host_address,
+            state: Mutex::new(NetworkState {
+                next_port: *DYNAMIC_PORTS.start(),
+                ..NetworkState::default()
+            }),
+        }
+    }
+
+    /// Create a VM's veth pair and return the guest end, with the address
+    /// the guest should use; bridged guests get theirs from the LAN
+    pub fn connect(&self, vm: VmId, mode: NetworkMode) -> Result<(VirtualNic, Option<Ipv4Config>), String> {
+        let mut state = self.state.lock().unwrap();
+        if state.attachments.contains_key(&vm) {
+            return Err("VM is already connected".to_string());
+        }
+        let taken: Vec<Ipv4Addr> = state.attachments.values().filter_map(|a| a.address).collect();
+        let free = |network: Ipv4Addr, first: u8| {
+            let [a, b, c, _] = network.octets();
+            (first..255).map(|d| Ipv4Addr::new(a, b, c, d)).find(|ip| !taken.contains(ip))
+        };
+        let config = match mode {
+            NetworkMode::Nat => {
+                let address = free(NAT_NETWORK, NAT_FIRST_GUEST).ok_or("No NAT addresses left")?;
+                Some(Ipv4Config::new(address, NETMASK, Some(NAT_GATEWAY)))
+            }
+            NetworkMode::HostOnly => {
+                let address = free(HOST_ONLY_NETWORK, HOST_ONLY_FIRST_GUEST).ok_or("No host-only addresses left")?;
+                Some(Ipv4Config::new(address, NETMASK, Some(HOST_ONLY_HOST)))
+            }
+            NetworkMode::Bridged => None,
+        };
+
+        let id = vm.0;
+        let guest_mac = [0x52, 0x54, 0x00, 0x00, (id >> 8) as u8, id as u8];
+        let (host, guest) = VirtualNic::pair([0x52, 0x54, 0x00, 0x01, (id >> 8) as u8, id as u8], guest_mac);
+        state.attachments.insert(
+            vm,
+            Attachment {
+                mode,
+                interface: format!("veth{}", id),
+                nic: host,
+                guest_mac,
+                address: config.map(|c| c.address),
+                forwards: Vec::new(),
+                received: 0,
+                sent: 0,
+            },
+        );
+        Ok((guest, config))
+    }
+
+    /// Remove a VM's veth pair, port forwards and connections
+    pub fn disconnect(&self, vm: VmId) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        let attachment = state.attachments.remove(&vm).ok_or("VM is not connected")?;
+        for (_, rule) in attachment.forwards {
+            self.firewall.remove_rule(self.token, rule)?;
+        }
+        state.connections.retain(|_, m| m.vm != vm);
+        Ok(())
+    }
+
+    pub fn mode(&self, vm: VmId) -> Option<NetworkMode> {
+        self.state.lock().unwrap().attachments.get(&vm).map(|a| a.mode)
+    }
+
+    /// Route a host port to a NAT guest and let the firewall admit it
+    pub fn add_forward(&self, vm: VmId, forward: PortForward) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        let in_use = state.attachments.values().flat_map(|a| &a.forwards).any(|(f, _)| f.protocol == forward.protocol && f.host_port == forward.host_port);
+        if in_use || state.connections.contains_key(&(forward.protocol, forward.host_port)) {
+            return Err(format!("Host port {} is already in use", forward.host_port));
+        }
+        let attachment = state.attachments.get_mut(&vm).ok_or("VM is not connected")?;
+        if attachment.mode != NetworkMode::Nat {
+            return Err("Port forwarding needs a VM in NAT mode".to_string());
+        }
+        let rule = FirewallRule::allow()
+            .with_direction(Direction::Inbound)
+            .with_interface(&self.uplink)
+            .with_protocol(forward.protocol)
+            .with_local_port(forward.host_port);
+        let rule = self.firewall.add_rule(self.token, rule)?;
+        attachment.forwards.push((forward, rule));
+        Ok(())
+    }
+
+    pub fn remove_forward(&self, vm: VmId, protocol: Protocol, host_port: u16) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        let attachment = state.attachments.get_mut(&vm).ok_or("VM is not connected")?;
+        let index = attachment
+            .forwards
+            .iter()
+            .position(|(f, _)| f.protocol == protocol && f.host_port == host_port)
+            .ok_or_else(|| format!("Host port {} is not forwarded", host_port))?;
+        let (_, rule) = attachment.forwards.remove(index);
+        self.firewall.remove_rule(self.token, rule)
+    }
+
+    /// Bytes delivered to a guest and sent by it
+    pub fn traffic(&self, vm: VmId) -> Option<(u64, u64)> {
+        self.state.lock().unwrap().attachments.get(&vm).map(|a| (a.received, a.sent))
+    }
+
+    pub fn forwards(&self, vm: VmId) -> Vec<PortForward> {
+        let state = self.state.lock().unwrap();
+        state.attachments.get(&vm).map_or_else(Vec::new, |a| a.forwards.iter().map(|(f, _)| *f).collect())
+    }
+
+    /// Handle every frame the guest sent: answer its ARP requests, pass
+    /// packets for other guests on, and return those leaving for the host
+    /// or beyond, translated for NAT guests
+    pub fn poll(&self, vm: VmId) -> Result<Vec<Ipv4Packet>, String> {
+        let mut state = self.state.lock().unwrap();
+        let mut outgoing = Vec::new();
+        loop {
+            let attachment = state.attachments.get_mut(&vm).ok_or("VM is not connected")?;
+            let Some(bytes) = attachment.nic.receive_packet() else {
+                break;
+            };
+            attachment.sent += bytes.len() as u64;
+            // Malformed frames are dropped
+            let Ok(frame) = EthernetFrame::parse(&bytes) else {
+                continue;
+            };
+            match frame.ethertype {
+                ETHERTYPE_ARP => {
+                    let Ok(request) = ArpPacket::parse(&frame.payload) else {
+                        continue;
+                    };
+                    if request.operation == ArpOperation::Request && Some(request.target_ip) != attachment.address {
+                        let reply = ArpPacket::reply_to(&request, attachment.nic.mac_address());
+                        let frame = EthernetFrame::new(attachment.guest_mac, attachment.nic.mac_address(), ETHERTYPE_ARP, reply.to_bytes());
+                        attachment.nic.send_packet(&frame.to_bytes())?;
+                    }
+                }
+                ETHERTYPE_IPV4 => {
+                    let Ok(packet) = Ipv4Packet::parse(&frame.payload) else {
+                        continue;
+                    };
+                    if let Some(packet) = self.outbound(&mut state, vm, packet)? {
+                        outgoing.push(packet);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(outgoing)
+    }
+
+    /// Deliver a packet that reached the host to the guest it is for;
+    /// false when it is for none or the firewall refuses it
+    pub fn inbound(&self, packet: Ipv4Packet) -> Result<bool, String> {
+        let mut state = self.state.lock().unwrap();
+        let mut packet = packet;
+        let ports = ports(&packet);
+        let protocol = Protocol::from_number(packet.protocol);
+
+        let vm = if packet.dst == self.host_address {
+            let (Some(protocol), Some((src_port, dst_port))) = (protocol, ports) else {
+                return Ok(false);
+            };
+            let forward = state.attachments.iter().find_map(|(vm, a)| {
+                let (forward, _) = a.forwards.iter().find(|(f, _)| f.protocol == protocol && f.host_port == dst_port)?;
+                Some(Mapping {
+                    vm: *vm,
+                    guest: (a.address?, forward.guest_port),
+                })
+            });
+            let mapping = match forward {
+                Some(mapping) => {
+                    let info = PacketInfo {
+                        direction: Direction::Inbound,
+                        interface: self.uplink.clone(),
+                        protocol: Some(protocol),
+                        remote: packet.src,
+                        local_port: Some(dst_port),
+                        remote_port: Some(src_port),
+                        owner: None,
+                        len: packet.payload.len(),
+                    };
+                    if self.firewall.check(&info) != Action::Allow {
+                        return Ok(false);
+                    }
+                    mapping
+                }
+                // Replies on connections the guest opened
+                None => match state.connections.get(&(protocol, dst_port)) {
+                    Some(mapping) => *mapping,
+                    None => return Ok(false),
+                },
+            };
+            rewrite(&mut packet, None, Some(mapping.guest));
+            mapping.vm
+        } else {
+            let Some(vm) = state.attachments.iter().find(|(_, a)| a.address == Some(packet.dst)).map(|(vm, _)| *vm) else {
+                return Ok(false);
+            };
+            vm
+        };
+
+        let attachment = state.attachments.get_mut(&vm).unwrap();
+        if !self.permits(attachment, Direction::Inbound, &packet) {
+            return Ok(false);
+        }
+        deliver(attachment, &packet)?;
+        Ok(true)
+    }
+
+    fn outbound(&self, state: &mut NetworkState, vm: VmId, mut packet: Ipv4Packet) -> Result<Option<Ipv4Packet>, String> {
+        let attachment = state.attachments.get_mut(&vm).unwrap();
+        if !self.permits(attachment, Direction::Outbound, &packet) {
+            return Ok(None);
+        }
+        match attachment.mode {
+            NetworkMode::Bridged => {
+                attachment.address = Some(packet.src);
+                Ok(Some(packet))
+            }
+            NetworkMode::HostOnly => {
+                if packet.dst == HOST_ONLY_HOST {
+                    return Ok(Some(packet));
+                }
+                let guest = state.attachments.values_mut().find(|a| a.mode == NetworkMode::HostOnly && a.address == Some(packet.dst));
+                if let Some(guest) = guest {
+                    deliver(guest, &packet)?;
+                }
+                Ok(None)
+            }
+            NetworkMode::Nat => {
+                let (Some(protocol), Some((src_port, _))) = (Protocol::from_number(packet.protocol), ports(&packet)) else {
+                    return Ok(None);
+                };
+                let guest = (packet.src, src_port);
+                let existing = state.connections.iter().find(|(k, m)| k.0 == protocol && m.vm == vm && m.guest == guest).map(|(k, _)| k.1);
+                let host_port = match existing {
+                    Some(port) => port,
+                    None => {
+                        let port = self.free_port(state, protocol).ok_or("No host ports left for NAT")?;
+                        state.connections.insert((protocol, port), Mapping { vm, guest });
+                        port
+                    }
+                };
+                rewrite(&mut packet, Some((self.host_address, host_port)), None);
+                Ok(Some(packet))
+            }
+        }
+    }
+
+    /// Next dynamic host port neither a connection nor a forward uses
+    fn free_port(&self, state: &mut NetworkState, protocol: Protocol) -> Option<u16> {
+        let span = DYNAMIC_PORTS.len();
+        for _ in 0..span {
+            let port = state.next_port;
+            state.next_port = if port == *DYNAMIC_PORTS.end() { *DYNAMIC_PORTS.start() } else { port + 1 };
+            let forwarded = state.attachments.values().flat_map(|a| &a.forwards).any(|(f, _)| f.protocol == protocol && f.host_port == port);
+            if !forwarded && !state.connections.contains_key(&(protocol, port)) {
+                return Some(port);
+            }
+        }
+        None
+    }
+
+    /// Whether the firewall lets a guest's packet through its interface
+    fn permits(&self, attachment: &Attachment, direction: Direction, packet: &Ipv4Packet) -> bool {
+        let ports = ports(packet);
+        let (remote, ports) = match direction {
+            Direction::Outbound => (packet.dst, ports),
+            Direction::Inbound => (packet.src, ports.map(|(src, dst)| (dst, src))),
+        };
+        let info = PacketInfo {
+            direction,
+            interface: attachment.interface.clone(),
+            protocol: Protocol::from_number(packet.protocol),
+            remote,
+            local_port: ports.map(|p| p.0),
+            remote_port: ports.map(|p| p.1),
+            owner: None,
+            len: packet.payload.len(),
+        };
+        self.firewall.check(&info) == Action::Allow
+    }
+}
+
+/// Source and destination ports of a TCP or UDP packet
+fn ports(packet: &Ipv4Packet) -> Option<(u16, u16)> {
+    let header_len = match packet.protocol {
+        PROTOCOL_TCP => 20,
+        PROTOCOL_UDP => 8,
+        _ => return None,
+    };
+    let payload = &packet.payload;
+    if payload.len() < header_len {
+        return None;
+    }
+    Some((u16::from_be_bytes([payload[0], payload[1]]), u16::from_be_bytes([payload[2], payload[3]])))
+}
+
+/// Replace a TCP or UDP packet's source or destination and fix its checksum
+fn rewrite(packet: &mut Ipv4Packet, src: Option<(Ipv4Addr, u16)>, dst: Option<(Ipv4Addr, u16)>) {
+    if let Some((address, port)) = src {
+        packet.src = address;
+        packet.payload[0..2].copy_from_slice(&port.to_be_bytes());
+    }
+    if let Some((address, port)) = dst {
+        packet.dst = address;
+        packet.payload[2..4].copy_from_slice(&port.to_be_bytes());
+    }
+    let offset = if packet.protocol == PROTOCOL_TCP { 16 } else { 6 };
+    // A zero UDP checksum means the sender did not compute one
+    if packet.protocol == PROTOCOL_UDP && packet.payload[offset..offset + 2] == [0, 0] {
+        return;
+    }
+    packet.payload[offset..offset + 2].copy_from_slice(&[0, 0]);
+    let sum = match pseudo_header_checksum(packet.src, packet.dst, packet.protocol, &packet.payload) {
+        0 if packet.protocol == PROTOCOL_UDP => 0xffff,
+        sum => sum,
+    };
+    packet.payload[offset..offset + 2].copy_from_slice(&sum.to_be_bytes());
+}
+
+fn deliver(attachment: &mut Attachment, packet: &Ipv4Packet) -> Result<(), String> {
+    let frame = EthernetFrame::new(attachment.guest_mac, attachment.nic.mac_address(), ETHERTYPE_IPV4, packet.to_bytes()).to_bytes();
+    attachment.nic.send_packet(&frame)?;
+    attachment.received += frame.len() as u64;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use capability::{CapabilityManager, Permission, Resource};
+    use net_stack::firewall::FIREWALL_RESOURCE;
+    use net_stack::{NetStack, TcpSegment, UdpDatagram};
+
+    const HOST: Ipv4Addr = Ipv4Addr::new(203, 0, 113, 5);
+    const REMOTE: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 7);
+
+    fn network() -> (VmNetwork, Arc<Firewall>) {
+        let capabilities = Arc::new(CapabilityManager::new());
+        let token = capabilities.grant(Resource::Network(FIREWALL_RESOURCE.to_string()), Permission::ReadWrite);
+        let firewall = Arc::new(Firewall::new(capabilities));
+        firewall.add_rule(token, FirewallRule::allow().with_direction(Direction::Outbound)).unwrap();
+        firewall.add_rule(token, FirewallRule::allow().with_interface("veth1")).unwrap();
+        firewall.set_default_action(token, Action::Deny).unwrap();
+        (VmNetwork::new(Arc::clone(&firewall), token, "eth0", HOST), firewall)
+    }
+
+    fn tcp_to(dst: Ipv4Addr, port: u16) -> Ipv4Packet {
+        let segment = TcpSegment {
+            src_port: 40000,
+            dst_port: port,
+            seq: 1,
+            ack: 0,
+            flags: net_stack::tcp::FLAG_SYN,
+            window: 1024,
+            payload: Vec::new(),
+        };
+        Ipv4Packet::new(REMOTE, dst, PROTOCOL_TCP, segment.to_bytes(REMOTE, dst))
+    }
+
+    #[test]
+    fn test_nat_and_port_forwards() {
+        let (network, firewall) = network();
+        let vm = VmId::new(1);
+        let (nic, config) = network.connect(vm, NetworkMode::Nat).unwrap();
+        assert_eq!(config.unwrap().address, Ipv4Addr::new(10, 0, 2, 15));
+        let guest = NetStack::new(Box::new(nic));
+        guest.configure(config.unwrap());
+        let socket = guest.bind_udp(5353).unwrap();
+        guest.send_to(socket, REMOTE, 53, b"query").unwrap();
+
+        // The guest resolves its gateway first
+        assert!(network.poll(vm).unwrap().is_empty());
+        guest.poll();
+        let sent = network.poll(vm).unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].src, HOST);
+        let datagram = UdpDatagram::parse(&sent[0].payload, HOST, REMOTE).unwrap();
+        assert_eq!((datagram.src_port, datagram.payload.as_slice()), (49152, &b"query"[..]));
+
+        let reply = UdpDatagram::new(53, 49152, b"answer".to_vec());
+        assert!(network.inbound(Ipv4Packet::new(REMOTE, HOST, PROTOCOL_UDP, reply.to_bytes(REMOTE, HOST))).unwrap());
+        guest.poll();
+        let received = guest.recv_from(socket).unwrap().unwrap();
+        assert_eq!((received.addr, received.port, received.data.as_slice()), (REMOTE, 53, &b"answer"[..]));
+
+        assert!(!network.inbound(tcp_to(HOST, 8080)).unwrap());
+        let forward = PortForward {
+            protocol: Protocol::Tcp,
+            host_port: 8080,
+            guest_port: 80,
+        };
+        network.add_forward(vm, forward).unwrap();
+        assert!(network.add_forward(vm, forward).is_err());
+        assert_eq!(firewall.rules().len(), 3);
+        assert!(network.inbound(tcp_to(HOST, 8080)).unwrap());
+        network.remove_forward(vm, Protocol::Tcp, 8080).unwrap();
+        assert!(!network.inbound(tcp_to(HOST, 8080)).unwrap());
+        assert_eq!(network.forwards(vm), Vec::new());
+
+        network.add_forward(vm, forward).unwrap();
+        network.disconnect(vm).unwrap();
+        assert_eq!(firewall.rules().len(), 2);
+    }
+
+    #[test]
+    fn test_host_only_and_bridged() {
+        let (network, _) = network();
+        let (_nic, config) = network.connect(VmId::new(2), NetworkMode::HostOnly).unwrap();
+        assert_eq!(config.unwrap().address, Ipv4Addr::new(192, 168, 56, 101));
+        let (nic, config) = network.connect(VmId::new(1), NetworkMode::Bridged).unwrap();
+        assert!(config.is_none());
+        assert!(network.connect(VmId::new(1), NetworkMode::Nat).is_err());
+        let forward = PortForward {
+            protocol: Protocol::Udp,
+            host_port: 53,
+            guest_port: 53,
+        };
+        assert!(network.add_forward(VmId::new(1), forward).is_err());
+
+        let bridged = NetStack::new(Box::new(nic));
+        let lan = Ipv4Addr::new(192, 168, 1, 40);
+        bridged.configure(Ipv4Config::new(lan, NETMASK, None));
+        bridged.ping(Ipv4Addr::new(192, 168, 1, 1), 1, 1, b"hi").unwrap();
+        network.poll(VmId::new(1)).unwrap();
+        bridged.poll();
+        let sent = network.poll(VmId::new(1)).unwrap();
+        assert_eq!((sent[0].src, sent[0].dst), (lan, Ipv4Addr::new(192, 168, 1, 1)));
+        assert!(network.inbound(tcp_to(lan, 22)).unwrap());
+        assert!(!network.inbound(tcp_to(Ipv4Addr::new(192, 168, 56, 101), 22)).unwrap());
+    }
+}
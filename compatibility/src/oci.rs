@@ -0,0 +1,388 @@
+//! OCI images
+//!
+//! Container images are read from an OCI image layout in the VFS: an
+//! `index.json` naming manifests, and blobs stored by SHA-256 digest, which
+//! is checked on every read. A manifest lists the image config and the
+//! layers, uncompressed tar archives applied in order to build the root
+//! filesystem. Whiteout entries delete what lower layers put there. The VFS
+//! has no links or device nodes, so those entries are skipped.
+//!
+//! ```json
+//! {"schemaVersion": 2, "mediaType": "application/vnd.oci.image.manifest.v1+json",
+//!  "config": {"mediaType": "application/vnd.oci.image.config.v1+json", "digest": "sha256:44136fa3...", "size": 2},
+//!  "layers": [{"mediaType": "application/vnd.oci.image.layer.v1.tar", "digest": "sha256:5f70bf18...", "size": 2048}]}
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+use filesystem::{FilePermissions, VirtualFileSystem};
+use serde::{Deserialize, Serialize};
+use system_utils::{encoding, hash};
+
+/// Annotation naming a manifest in an image index
+pub const REF_NAME: &str = "org.opencontainers.image.ref.name";
+
+/// Layer media types that can be applied
+const TAR_LAYERS: &[&str] = &["application/vnd.oci.image.layer.v1.tar", "application/vnd.docker.image.rootfs.diff.tar"];
+
+/// Prefix of a whiteout entry
+const WHITEOUT: &str = ".wh.";
+
+/// Whiteout hiding everything lower layers put in its directory
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+const TAR_BLOCK: usize = 512;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Descriptor {
+    pub media_type: String,
+    /// `sha256:` and the hex digest of the blob
+    pub digest: String,
+    pub size: u64,
+    #[serde(default)]
+    pub annotations: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageIndex {
+    pub schema_version: u32,
+    pub manifests: Vec<Descriptor>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageManifest {
+    pub schema_version: u32,
+    #[serde(default)]
+    pub media_type: Option<String>,
+    pub config: Descriptor,
+    pub layers: Vec<Descriptor>,
+}
+
+/// How the image runs by default
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RunConfig {
+    #[serde(default)]
+    pub env: Vec<String>,
+    #[serde(default)]
+    pub entrypoint: Vec<String>,
+    #[serde(default)]
+    pub cmd: Vec<String>,
+    #[serde(default)]
+    pub working_dir: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageConfig {
+    #[serde(default)]
+    pub architecture: String,
+    #[serde(default)]
+    pub os: String,
+    #[serde(default)]
+    pub config: RunConfig,
+}
+
+/// Image read from a layout, with its layers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    /// Name it was found under, or its manifest digest
+    pub reference: String,
+    pub manifest: ImageManifest,
+    pub config: ImageConfig,
+}
+
+/// OCI image layout in the VFS
+pub struct ImageLayout<'a> {
+    vfs: &'a VirtualFileSystem,
+    dir: PathBuf,
+}
+
+impl<'a> ImageLayout<'a> {
+    pub fn new(vfs: &'a VirtualFileSystem, dir: &Path) -> Self {
+        ImageLayout { vfs, dir: dir.to_path_buf() }
+    }
+
+    /// The image named `reference`, or the first one in the index
+    pub fn image(&self, reference: Option<&str>) -> Result<Image, String> {
+        let index: ImageIndex = serde_json::from_slice(&self.read(&self.dir.join("index.json"))?)
+            .map_err(|e| format!("Invalid image index: {}", e))?;
+        let descriptor = match reference {
+            Some(name) => index.manifests.iter().find(|m| m.annotations.get(REF_NAME).map(String::as_str) == Some(name)),
+            None => index.manifests.first(),
+        }
+        .ok_or_else(|| format!("Image not found: {}", reference.unwrap_or("(any)")))?;
+
+        let manifest: ImageManifest =
+            serde_json::from_slice(&self.blob(descriptor)?).map_err(|e| format!("Invalid image manifest: {}", e))?;
+        if manifest.schema_version != 2 {
+            return Err(format!("Unsupported manifest schema version {}", manifest.schema_version));
+        }
+        let config = serde_json::from_slice(&self.blob(&manifest.config)?).map_err(|e| format!("Invalid image config: {}", e))?;
+        Ok(Image {
+            reference: reference.map_or_else(|| descriptor.digest.clone(), str::to_string),
+            manifest,
+            config,
+        })
+    }
+
+    /// Content of a blob, once its size and digest match
+    pub fn blob(&self, descriptor: &Descriptor) -> Result<Vec<u8>, String> {
+        let hex = descriptor
+            .digest
+            .strip_prefix("sha256:")
+            .filter(|h| h.len() == 64 && h.chars().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(|| format!("Unsupported digest {}", descriptor.digest))?;
+        let data = self.read(&self.dir.join("blobs/sha256").join(hex))?;
+        if data.len() as u64 != descriptor.size || sha256_hex(&data) != hex {
+            return Err(format!("Blob {} does not match its digest", descriptor.digest));
+        }
+        Ok(data)
+    }
+
+    /// Build an image's root filesystem at `root`, applying its layers in
+    /// order
+    pub fn unpack(&self, image: &Image, root: &Path) -> Result<(), String> {
+        create_dirs(self.vfs, root)?;
+        for layer in &image.manifest.layers {
+            if !TAR_LAYERS.contains(&layer.media_type.as_str()) {
+                return Err(format!("Unsupported layer type {}", layer.media_type));
+            }
+            apply_layer(self.vfs, root, &read_tar(&self.blob(layer)?)?)?;
+        }
+        Ok(())
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>, String> {
+        if !self.vfs.exists(path) {
+            return Err(format!("Not found in the image layout: {}", path.display()));
+        }
+        self.vfs.read_file(path).map_err(String::from)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    /// Links and device nodes
+    Other,
+}
+
+/// One entry of a layer's tar archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TarEntry {
+    pub path: String,
+    pub kind: EntryKind,
+    pub mode: u32,
+    pub content: Vec<u8>,
+}
+
+/// Read a ustar archive, with GNU long names
+pub fn read_tar(data: &[u8]) -> Result<Vec<TarEntry>, String> {
+    let mut entries = Vec::new();
+    let mut long_name = None;
+    let mut offset = 0;
+    while offset + TAR_BLOCK <= data.len() {
+        let header = &data[offset..offset + TAR_BLOCK];
+        if header.iter().all(|b| *b == 0) {
+            break;
+        }
+        let size = octal(&header[124..136])? as usize;
+        let start = offset + TAR_BLOCK;
+        let content = data.get(start..start + size).ok_or("Truncated layer archive")?.to_vec();
+        offset = start + size.div_ceil(TAR_BLOCK) * TAR_BLOCK;
+
+        let mut path = text(&header[0..100]);
+        if &header[257..262] == b"ustar" && header[345] != 0 {
+            path = format!("{}/{}", text(&header[345..500]), path);
+        }
+        let kind = match header[156] {
+            b'L' => {
+                long_name = Some(text(&content));
+                continue;
+            }
+            // PAX headers only add attributes kept nowhere here
+            b'x' | b'g' => continue,
+            b'0' | 0 | b'7' => EntryKind::File,
+            b'5' => EntryKind::Directory,
+            _ => EntryKind::Other,
+        };
+        entries.push(TarEntry {
+            path: long_name.take().unwrap_or(path),
+            kind,
+            mode: octal(&header[100..108])? as u32,
+            content,
+        });
+    }
+    Ok(entries)
+}
+
+/// Apply a layer's entries on top of the tree at `root`
+pub fn apply_layer(vfs: &VirtualFileSystem, root: &Path, entries: &[TarEntry]) -> Result<(), String> {
+    for entry in entries {
+        let relative = clean_path(&entry.path)?;
+        let Some(name) = relative.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let path = root.join(&relative);
+        let parent = path.parent().unwrap().to_path_buf();
+
+        if name == OPAQUE_WHITEOUT {
+            if vfs.exists(&parent) {
+                for child in vfs.list_directory(&parent)? {
+                    remove_tree(vfs, &child)?;
+                }
+            }
+            continue;
+        }
+        if let Some(hidden) = name.strip_prefix(WHITEOUT) {
+            remove_tree(vfs, &parent.join(hidden))?;
+            continue;
+        }
+
+        match entry.kind {
+            EntryKind::Other => continue,
+            EntryKind::Directory => {
+                if vfs.exists(&path) && !vfs.metadata(&path)?.is_directory() {
+                    vfs.delete(&path)?;
+                }
+                create_dirs(vfs, &path)?;
+            }
+            EntryKind::File => {
+                if vfs.exists(&path) && vfs.metadata(&path)?.is_directory() {
+                    remove_tree(vfs, &path)?;
+                }
+                create_dirs(vfs, &parent)?;
+                vfs.write_file(&path, &entry.content)?;
+            }
+        }
+        vfs.set_permissions(&path, FilePermissions::new(entry.mode & 0o777))?;
+    }
+    Ok(())
+}
+
+/// Delete a path and everything below it
+pub fn remove_tree(vfs: &VirtualFileSystem, path: &Path) -> Result<(), String> {
+    if !vfs.exists(path) {
+        return Ok(());
+    }
+    let mut paths: Vec<PathBuf> = vfs.snapshot().entries.into_keys().filter(|p| p.starts_with(path)).collect();
+    paths.sort_by_key(|p| std::cmp::Reverse(p.components().count()));
+    for path in paths {
+        vfs.delete(&path)?;
+    }
+    Ok(())
+}
+
+fn create_dirs(vfs: &VirtualFileSystem, path: &Path) -> Result<(), String> {
+    for ancestor in path.ancestors().collect::<Vec<_>>().into_iter().rev() {
+        if !vfs.exists(ancestor) {
+            vfs.create_directory(ancestor)?;
+        }
+    }
+    Ok(())
+}
+
+/// An archive path relative to the root; nothing may climb out of it
+fn clean_path(path: &str) -> Result<PathBuf, String> {
+    let mut clean = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(name) => clean.push(name),
+            Component::RootDir | Component::CurDir => {}
+            _ => return Err(format!("Layer entry leaves the root filesystem: {}", path)),
+        }
+    }
+    Ok(clean)
+}
+
+fn text(field: &[u8]) -> String {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+fn octal(field: &[u8]) -> Result<u64, String> {
+    let digits = text(field);
+    let digits = digits.trim_matches(|c: char| c == ' ' || c == '\0');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(digits, 8).map_err(|_| format!("Invalid number in layer archive: {:?}", digits))
+}
+
+/// Hex SHA-256 of `data`, as OCI digests use
+pub fn sha256_hex(data: &[u8]) -> String {
+    encoding::hex_encode(&hash::sha256(data))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Tar archive of `(path, kind, content)` entries
+    pub(crate) fn tar(entries: &[(&str, EntryKind, &[u8])]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        for (path, kind, content) in entries {
+            let mut header = [0u8; TAR_BLOCK];
+            header[..path.len()].copy_from_slice(path.as_bytes());
+            header[100..107].copy_from_slice(b"0000755");
+            header[124..135].copy_from_slice(format!("{:011o}", content.len()).as_bytes());
+            header[156] = if *kind == EntryKind::Directory { b'5' } else { b'0' };
+            header[257..262].copy_from_slice(b"ustar");
+            archive.extend_from_slice(&header);
+            archive.extend_from_slice(content);
+            archive.resize(archive.len().div_ceil(TAR_BLOCK) * TAR_BLOCK, 0);
+        }
+        archive.extend_from_slice(&[0; 2 * TAR_BLOCK]);
+        archive
+    }
+
+    /// Write a blob into a layout and describe it
+    pub(crate) fn add_blob(vfs: &VirtualFileSystem, dir: &Path, media_type: &str, data: &[u8]) -> Descriptor {
+        let hex = sha256_hex(data);
+        vfs.write_file(&dir.join("blobs/sha256").join(&hex), data).unwrap();
+        Descriptor {
+            media_type: media_type.to_string(),
+            digest: format!("sha256:{}", hex),
+            size: data.len() as u64,
+            annotations: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(sha256_hex(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(sha256_hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+
+    #[test]
+    fn test_layers_with_whiteouts() {
+        let vfs = VirtualFileSystem::new();
+        let root = Path::new("/rootfs");
+        let base = tar(&[
+            ("etc/", EntryKind::Directory, b""),
+            ("etc/motd", EntryKind::File, b"hello"),
+            ("var/cache/apt/pkgcache.bin", EntryKind::File, b"cache"),
+            ("tmp/a", EntryKind::File, b"a"),
+        ]);
+        let upper = tar(&[
+            ("./etc/.wh.motd", EntryKind::File, b""),
+            ("var/cache/.wh..wh..opq", EntryKind::File, b""),
+            ("tmp", EntryKind::File, b"now a file"),
+        ]);
+        apply_layer(&vfs, root, &read_tar(&base).unwrap()).unwrap();
+        assert_eq!(vfs.read_file(&root.join("etc/motd")).unwrap(), b"hello");
+        apply_layer(&vfs, root, &read_tar(&upper).unwrap()).unwrap();
+        assert!(!vfs.exists(&root.join("etc/motd")));
+        assert!(vfs.exists(&root.join("var/cache")));
+        assert!(!vfs.exists(&root.join("var/cache/apt")));
+        assert_eq!(vfs.read_file(&root.join("tmp")).unwrap(), b"now a file");
+
+        let escape = tar(&[("../etc/passwd", EntryKind::File, b"x")]);
+        assert!(apply_layer(&vfs, root, &read_tar(&escape).unwrap()).is_err());
+    }
+}
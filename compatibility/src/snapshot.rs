@@ -0,0 +1,155 @@
+//! VM snapshots
+//!
+//! A snapshot records a VM's configuration and disk image, and for a
+//! paused VM its memory too, so the VM can be rolled back after a bad
+//! install. Restoring one from a stopped VM leaves it stopped; restoring
+//! one with memory leaves the VM paused where the snapshot was taken.
+//! Disk and memory are sparse: only blocks the guest wrote are kept.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::VmConfig;
+
+/// Size of a disk or memory block
+pub const BLOCK_SIZE: usize = 4096;
+
+/// Disk or memory contents; blocks never written read as zeros
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SparseImage {
+    capacity: u64,
+    /// By block number
+    blocks: BTreeMap<u64, Vec<u8>>,
+}
+
+impl SparseImage {
+    pub fn new(capacity: u64) -> Self {
+        SparseImage {
+            capacity,
+            blocks: BTreeMap::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    /// Bytes taken by the blocks written so far
+    pub fn allocated(&self) -> u64 {
+        (self.blocks.len() * BLOCK_SIZE) as u64
+    }
+
+    pub fn write(&mut self, offset: u64, data: &[u8]) -> Result<(), String> {
+        self.check_range(offset, data.len())?;
+        for (i, byte) in data.iter().enumerate() {
+            let position = offset + i as u64;
+            let block = self.blocks.entry(position / BLOCK_SIZE as u64).or_insert_with(|| vec![0; BLOCK_SIZE]);
+            block[(position % BLOCK_SIZE as u64) as usize] = *byte;
+        }
+        Ok(())
+    }
+
+    pub fn read(&self, offset: u64, len: usize) -> Result<Vec<u8>, String> {
+        self.check_range(offset, len)?;
+        Ok((offset..offset + len as u64)
+            .map(|position| {
+                self.blocks
+                    .get(&(position / BLOCK_SIZE as u64))
+                    .map_or(0, |block| block[(position % BLOCK_SIZE as u64) as usize])
+            })
+            .collect())
+    }
+
+    /// Grow or shrink the image; it cannot shrink past a written block
+    pub fn resize(&mut self, capacity: u64) -> Result<(), String> {
+        if let Some(last) = self.blocks.keys().next_back() {
+            if (last + 1) * BLOCK_SIZE as u64 > capacity {
+                return Err("Data was written past the new size".to_string());
+            }
+        }
+        self.capacity = capacity;
+        Ok(())
+    }
+
+    /// Zero a range, freeing the blocks it covers whole
+    pub(crate) fn discard(&mut self, offset: u64, len: u64) -> Result<(), String> {
+        self.check_range(offset, len as usize)?;
+        let (size, end) = (BLOCK_SIZE as u64, offset + len);
+        let touched: Vec<u64> = self.blocks.range(offset / size..end.div_ceil(size)).map(|(b, _)| *b).collect();
+        for number in touched {
+            let (from, to) = (offset.max(number * size), end.min((number + 1) * size));
+            if to - from == size {
+                self.blocks.remove(&number);
+            } else if let Some(block) = self.blocks.get_mut(&number) {
+                block[(from - number * size) as usize..(to - number * size) as usize].fill(0);
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn block(&self, number: u64) -> Option<&Vec<u8>> {
+        self.blocks.get(&number)
+    }
+
+    pub(crate) fn set_block(&mut self, number: u64, data: Vec<u8>) {
+        self.blocks.insert(number, data);
+    }
+
+    pub(crate) fn check_range(&self, offset: u64, len: usize) -> Result<(), String> {
+        match offset.checked_add(len as u64) {
+            Some(end) if end <= self.capacity => Ok(()),
+            _ => Err(format!("{} bytes at {} are past the end of the image", len, offset)),
+        }
+    }
+}
+
+/// Saved state of a VM
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub name: String,
+    /// Seconds since the Unix epoch
+    pub created_at: u64,
+    pub config: VmConfig,
+    pub disk: SparseImage,
+    /// Taken only from paused VMs
+    pub memory: Option<SparseImage>,
+}
+
+/// Snapshot as listed, without its images
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotInfo {
+    pub name: String,
+    pub created_at: u64,
+    /// Bytes of disk and memory it keeps
+    pub size: u64,
+    pub has_memory: bool,
+}
+
+impl Snapshot {
+    pub fn info(&self) -> SnapshotInfo {
+        let memory = self.memory.as_ref().map_or(0, SparseImage::allocated);
+        SnapshotInfo {
+            name: self.name.clone(),
+            created_at: self.created_at,
+            size: self.disk.allocated() + memory,
+            has_memory: self.memory.is_some(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sparse_image() {
+        let mut image = SparseImage::new(3 * BLOCK_SIZE as u64);
+        assert_eq!(image.read(100, 4).unwrap(), vec![0; 4]);
+        image.write(BLOCK_SIZE as u64 - 2, b"boot").unwrap();
+        assert_eq!(image.read(BLOCK_SIZE as u64 - 3, 6).unwrap(), b"\0boot\0");
+        assert_eq!(image.allocated(), 2 * BLOCK_SIZE as u64);
+        assert!(image.write(3 * BLOCK_SIZE as u64 - 1, b"xy").is_err());
+        assert!(image.read(u64::MAX, 2).is_err());
+    }
+}
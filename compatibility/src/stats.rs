@@ -0,0 +1,95 @@
+//! VM resource usage
+//!
+//! Chrysalis counts what each VM uses: CPU time its vCPUs report, memory
+//! the guest has touched, and bytes moved on its disk and network. When
+//! memory or disk use crosses `NEAR_LIMIT` of what the VM has, listeners
+//! hear about it once, and again only after use has dropped back below.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::VmId;
+
+/// Share of a limit that counts as approaching it
+pub const NEAR_LIMIT: f64 = 0.9;
+
+/// Most vCPUs a VM can have
+pub const MAX_CPU_CORES: usize = 64;
+
+/// Memory the host keeps for itself however much VMs ask for
+pub const HOST_RESERVE_MB: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VmResource {
+    Memory,
+    Disk,
+}
+
+/// Snapshot of a VM's usage; sizes in bytes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VmStats {
+    pub cpu_time: Duration,
+    pub cpu_cores: usize,
+    pub memory_used: u64,
+    pub memory_limit: u64,
+    pub disk_used: u64,
+    pub disk_capacity: u64,
+    pub disk_read: u64,
+    pub disk_written: u64,
+    pub network_received: u64,
+    pub network_sent: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmEvent {
+    NearLimit { vm: VmId, resource: VmResource, used: u64, limit: u64 },
+}
+
+/// Told about VM events as they happen
+pub type VmEventListener = Arc<dyn Fn(&VmEvent) + Send + Sync>;
+
+/// Counters kept with a VM
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VmUsage {
+    pub cpu_time: Duration,
+    pub disk_read: u64,
+    pub disk_written: u64,
+    /// Resources over `NEAR_LIMIT`, already reported
+    near_limit: Vec<VmResource>,
+}
+
+impl VmUsage {
+    /// Note current use of a resource; returns the event to send when it
+    /// has just crossed `NEAR_LIMIT`
+    pub fn check(&mut self, vm: VmId, resource: VmResource, used: u64, limit: u64) -> Option<VmEvent> {
+        let near = limit > 0 && used as f64 >= limit as f64 * NEAR_LIMIT;
+        let reported = self.near_limit.contains(&resource);
+        if !near {
+            self.near_limit.retain(|r| *r != resource);
+            return None;
+        }
+        if reported {
+            return None;
+        }
+        self.near_limit.push(resource);
+        Some(VmEvent::NearLimit { vm, resource, used, limit })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_near_limit_reported_once() {
+        let vm = VmId::new(1);
+        let mut usage = VmUsage::default();
+        assert_eq!(usage.check(vm, VmResource::Memory, 80, 100), None);
+        assert!(usage.check(vm, VmResource::Memory, 95, 100).is_some());
+        assert_eq!(usage.check(vm, VmResource::Memory, 99, 100), None);
+        assert!(usage.check(vm, VmResource::Disk, 9, 10).is_some());
+        assert_eq!(usage.check(vm, VmResource::Memory, 50, 100), None);
+        assert!(usage.check(vm, VmResource::Memory, 90, 100).is_some());
+        assert_eq!(usage.check(vm, VmResource::Memory, 0, 0), None);
+    }
+}
@@ -0,0 +1,59 @@
+//! VM templates
+//!
+//! A template pairs a guest OS and configuration with a base disk image
+//! that already has the OS installed. A VM made from one boots an overlay
+//! of that image, so it is ready as soon as the overlay exists instead of
+//! after an install; only the settings given as overrides differ from the
+//! template's.
+
+use std::path::PathBuf;
+
+use crate::network::NetworkMode;
+use crate::{GuestOS, VmConfig};
+
+#[derive(Debug, Clone)]
+pub struct VmTemplate {
+    pub id: String,
+    pub name: String,
+    pub guest_os: GuestOS,
+    pub config: VmConfig,
+    /// Disk image with the OS installed; read-only once a VM is made
+    pub base_image: PathBuf,
+}
+
+impl VmTemplate {
+    pub fn new(id: &str, name: &str, guest_os: GuestOS, config: VmConfig, base_image: PathBuf) -> Self {
+        VmTemplate {
+            id: id.to_string(),
+            name: name.to_string(),
+            guest_os,
+            config,
+            base_image,
+        }
+    }
+}
+
+/// Settings to change from a template's; `None` keeps the template's
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConfigOverrides {
+    pub memory_mb: Option<usize>,
+    pub cpu_cores: Option<usize>,
+    /// Only grows the disk past the base image
+    pub disk_size_gb: Option<usize>,
+    pub network_enabled: Option<bool>,
+    pub network_mode: Option<NetworkMode>,
+    pub gpu_passthrough: Option<bool>,
+}
+
+impl ConfigOverrides {
+    pub fn apply(&self, config: &VmConfig) -> VmConfig {
+        VmConfig {
+            memory_mb: self.memory_mb.unwrap_or(config.memory_mb),
+            cpu_cores: self.cpu_cores.unwrap_or(config.cpu_cores),
+            disk_size_gb: self.disk_size_gb.unwrap_or(config.disk_size_gb),
+            network_enabled: self.network_enabled.unwrap_or(config.network_enabled),
+            network_mode: self.network_mode.unwrap_or(config.network_mode),
+            gpu_passthrough: self.gpu_passthrough.unwrap_or(config.gpu_passthrough),
+        }
+    }
+}
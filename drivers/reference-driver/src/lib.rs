@@ -6,6 +6,8 @@
 pub mod display {
     use std::sync::Mutex;
 
+    use hal::{Device, DeviceInfo, DeviceType, DisplayDevice};
+
     /// Reference display device
     pub struct ReferenceDisplay {
         width: u32,
@@ -74,6 +76,59 @@ pub mod display {
             
             Ok(())
         }
+
+        /// Copy of what is currently on screen
+        pub fn framebuffer(&self) -> Vec<u8> {
+            self.framebuffer.lock().unwrap().clone()
+        }
+    }
+
+    impl Device for ReferenceDisplay {
+        fn info(&self) -> DeviceInfo {
+            DeviceInfo {
+                device_type: DeviceType::Display,
+                vendor: "hairr OS".to_string(),
+                model: "Reference Display".to_string(),
+                version: "0.1.0".to_string(),
+            }
+        }
+
+        fn init(&mut self) -> Result<(), String> {
+            ReferenceDisplay::init(self)
+        }
+
+        fn shutdown(&mut self) -> Result<(), String> {
+            self.initialized = false;
+            Ok(())
+        }
+
+        fn read(&self, offset: usize, buffer: &mut [u8]) -> Result<usize, String> {
+            let fb = self.framebuffer.lock().unwrap();
+            let available = fb.len().saturating_sub(offset).min(buffer.len());
+            buffer[..available].copy_from_slice(&fb[offset..offset + available]);
+            Ok(available)
+        }
+
+        fn write(&mut self, offset: usize, data: &[u8]) -> Result<usize, String> {
+            let mut fb = self.framebuffer.lock().unwrap();
+            let written = fb.len().saturating_sub(offset).min(data.len());
+            fb[offset..offset + written].copy_from_slice(&data[..written]);
+            Ok(written)
+        }
+    }
+
+    impl DisplayDevice for ReferenceDisplay {
+        fn resolution(&self) -> (u32, u32) {
+            ReferenceDisplay::resolution(self)
+        }
+
+        fn set_resolution(&mut self, width: u32, height: u32) -> Result<(), String> {
+            ReferenceDisplay::set_resolution(self, width, height)
+        }
+
+        fn update_framebuffer(&mut self, buffer: &[u8]) -> Result<(), String> {
+            ReferenceDisplay::update_framebuffer(self, buffer)
+        }
     }
 }
 
@@ -143,6 +198,8 @@ pub mod network {
     use std::collections::VecDeque;
     use std::sync::Mutex;
 
+    use hal::{Device, DeviceInfo, DeviceType, NetworkDevice};
+
     /// Reference network device
     pub struct ReferenceNetwork {
         mac_address: [u8; 6],
@@ -196,13 +253,128 @@ pub mod network {
             self.tx_queue.lock().unwrap().len()
         }
     }
+
+    /// Traffic counters for a virtual link
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct LinkStats {
+        pub tx_packets: u64,
+        pub tx_bytes: u64,
+        pub rx_packets: u64,
+        pub rx_bytes: u64,
+    }
+
+    fn network_info(model: &str) -> DeviceInfo {
+        DeviceInfo {
+            device_type: DeviceType::Network,
+            vendor: "hairr OS".to_string(),
+            model: model.to_string(),
+            version: "1.0".to_string(),
+        }
+    }
+
+    /// Loopback device: every frame sent is received by the same device
+    pub struct LoopbackDevice {
+        queue: Mutex<VecDeque<Vec<u8>>>,
+        stats: Mutex<LinkStats>,
+        up: bool,
+    }
+
+    impl LoopbackDevice {
+        /// Hardware address reported by the loopback device
+        pub const MAC_ADDRESS: [u8; 6] = [0; 6];
+
+        pub fn new() -> Self {
+            LoopbackDevice {
+                queue: Mutex::new(VecDeque::new()),
+                stats: Mutex::new(LinkStats::default()),
+                up: false,
+            }
+        }
+
+        pub fn stats(&self) -> LinkStats {
+            *self.stats.lock().unwrap()
+        }
+    }
+
+    impl Default for LoopbackDevice {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl Device for LoopbackDevice {
+        fn info(&self) -> DeviceInfo {
+            network_info("Loopback")
+        }
+
+        fn init(&mut self) -> Result<(), String> {
+            self.up = true;
+            Ok(())
+        }
+
+        fn shutdown(&mut self) -> Result<(), String> {
+            self.up = false;
+            self.queue.lock().unwrap().clear();
+            Ok(())
+        }
+
+        fn read(&self, _offset: usize, buffer: &mut [u8]) -> Result<usize, String> {
+            read_frame(self.receive_packet(), buffer)
+        }
+
+        fn write(&mut self, _offset: usize, data: &[u8]) -> Result<usize, String> {
+            self.send_packet(data)?;
+            Ok(data.len())
+        }
+    }
+
+    impl NetworkDevice for LoopbackDevice {
+        fn mac_address(&self) -> [u8; 6] {
+            Self::MAC_ADDRESS
+        }
+
+        fn send_packet(&mut self, packet: &[u8]) -> Result<(), String> {
+            if !self.up {
+                return Err("Network device not initialized".to_string());
+            }
+            let mut stats = self.stats.lock().unwrap();
+            stats.tx_packets += 1;
+            stats.tx_bytes += packet.len() as u64;
+            self.queue.lock().unwrap().push_back(packet.to_vec());
+            Ok(())
+        }
+
+        fn receive_packet(&self) -> Option<Vec<u8>> {
+            if !self.up {
+                return None;
+            }
+            let frame = self.queue.lock().unwrap().pop_front()?;
+            let mut stats = self.stats.lock().unwrap();
+            stats.rx_packets += 1;
+            stats.rx_bytes += frame.len() as u64;
+            Some(frame)
+        }
+    }
+
+    fn read_frame(frame: Option<Vec<u8>>, buffer: &mut [u8]) -> Result<usize, String> {
+        match frame {
+            Some(frame) => {
+                let len = frame.len().min(buffer.len());
+                buffer[..len].copy_from_slice(&frame[..len]);
+                Ok(len)
+            }
+            None => Ok(0),
+        }
+    }
 }
 
 /// Storage driver implementation
 pub mod storage {
     use std::sync::Mutex;
 
-    const BLOCK_SIZE: usize = 512;
+    use hal::{Device, DeviceInfo, DeviceType, StorageDevice};
+
+    pub const BLOCK_SIZE: usize = 512;
 
     /// Reference storage device
     pub struct ReferenceStorage {
@@ -276,6 +448,292 @@ pub mod storage {
             Ok(())
         }
     }
+
+    impl Device for ReferenceStorage {
+        fn info(&self) -> DeviceInfo {
+            DeviceInfo {
+                device_type: DeviceType::Storage,
+                vendor: "hairr OS".to_string(),
+                model: "Reference Storage".to_string(),
+                version: "0.1.0".to_string(),
+            }
+        }
+
+        fn init(&mut self) -> Result<(), String> {
+            ReferenceStorage::init(self)
+        }
+
+        fn shutdown(&mut self) -> Result<(), String> {
+            self.flush()?;
+            self.initialized = false;
+            Ok(())
+        }
+
+        fn read(&self, offset: usize, buffer: &mut [u8]) -> Result<usize, String> {
+            let mut block = [0u8; BLOCK_SIZE];
+            let mut done = 0;
+            while done < buffer.len() {
+                let position = offset + done;
+                if position as u64 >= self.capacity {
+                    break;
+                }
+                ReferenceStorage::read_block(self, (position / BLOCK_SIZE) as u64, &mut block)?;
+                let start = position % BLOCK_SIZE;
+                let len = (BLOCK_SIZE - start).min(buffer.len() - done);
+                buffer[done..done + len].copy_from_slice(&block[start..start + len]);
+                done += len;
+            }
+            Ok(done)
+        }
+
+        fn write(&mut self, offset: usize, data: &[u8]) -> Result<usize, String> {
+            let mut block = [0u8; BLOCK_SIZE];
+            let mut done = 0;
+            while done < data.len() {
+                let position = offset + done;
+                if position as u64 >= self.capacity {
+                    break;
+                }
+                let index = (position / BLOCK_SIZE) as u64;
+                ReferenceStorage::read_block(self, index, &mut block)?;
+                let start = position % BLOCK_SIZE;
+                let len = (BLOCK_SIZE - start).min(data.len() - done);
+                block[start..start + len].copy_from_slice(&data[done..done + len]);
+                ReferenceStorage::write_block(self, index, &block)?;
+                done += len;
+            }
+            Ok(done)
+        }
+    }
+
+    impl StorageDevice for ReferenceStorage {
+        fn capacity(&self) -> u64 {
+            self.capacity
+        }
+
+        fn read_block(&self, block: u64, buffer: &mut [u8]) -> Result<(), String> {
+            ReferenceStorage::read_block(self, block, buffer)
+        }
+
+        fn write_block(&mut self, block: u64, data: &[u8]) -> Result<(), String> {
+            ReferenceStorage::write_block(self, block, data)
+        }
+
+        fn block_size(&self) -> usize {
+            BLOCK_SIZE
+        }
+    }
+}
+
+/// Audio driver implementation
+pub mod audio {
+    use std::collections::VecDeque;
+
+    use hal::{AudioDevice, AudioFormat, Device, DeviceInfo, DeviceType};
+
+    /// Reference audio output with a fixed-size hardware buffer
+    pub struct ReferenceAudio {
+        name: String,
+        format: AudioFormat,
+        capacity_frames: usize,
+        buffer: VecDeque<i16>,
+        underruns: u64,
+        initialized: bool,
+    }
+
+    impl ReferenceAudio {
+        pub fn new(name: &str, format: AudioFormat, capacity_frames: usize) -> Self {
+            ReferenceAudio {
+                name: name.to_string(),
+                format,
+                capacity_frames,
+                buffer: VecDeque::new(),
+                underruns: 0,
+                initialized: false,
+            }
+        }
+
+        /// Simulate the hardware playing `ms` of audio; missing frames are
+        /// played as silence and counted as an underrun
+        pub fn play(&mut self, ms: u64) -> Vec<i16> {
+            let wanted = self.format.frames_in(ms) * self.format.channels as usize;
+            let available = wanted.min(self.buffer.len());
+            let mut played: Vec<i16> = self.buffer.drain(..available).collect();
+            if available < wanted {
+                self.underruns += 1;
+                played.resize(wanted, 0);
+            }
+            played
+        }
+
+        /// Times the hardware ran out of frames
+        pub fn underruns(&self) -> u64 {
+            self.underruns
+        }
+    }
+
+    impl Device for ReferenceAudio {
+        fn info(&self) -> DeviceInfo {
+            DeviceInfo {
+                device_type: DeviceType::Audio,
+                vendor: "hairr OS".to_string(),
+                model: self.name.clone(),
+                version: "0.1.0".to_string(),
+            }
+        }
+
+        fn init(&mut self) -> Result<(), String> {
+            self.initialized = true;
+            Ok(())
+        }
+
+        fn shutdown(&mut self) -> Result<(), String> {
+            self.initialized = false;
+            self.buffer.clear();
+            Ok(())
+        }
+
+        fn read(&self, _offset: usize, _buffer: &mut [u8]) -> Result<usize, String> {
+            Err("Audio output cannot be read".to_string())
+        }
+
+        fn write(&mut self, _offset: usize, data: &[u8]) -> Result<usize, String> {
+            let samples: Vec<i16> = data
+                .chunks_exact(2)
+                .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+                .collect();
+            let frames = self.write_frames(&samples)?;
+            Ok(frames * self.format.channels as usize * 2)
+        }
+    }
+
+    impl AudioDevice for ReferenceAudio {
+        fn format(&self) -> AudioFormat {
+            self.format
+        }
+
+        fn queued_frames(&self) -> usize {
+            self.buffer.len() / self.format.channels as usize
+        }
+
+        fn write_frames(&mut self, samples: &[i16]) -> Result<usize, String> {
+            if !self.initialized {
+                return Err("Audio device not initialized".to_string());
+            }
+            let channels = self.format.channels as usize;
+            let free = self.capacity_frames.saturating_sub(self.queued_frames());
+            let frames = (samples.len() / channels).min(free);
+            self.buffer.extend(&samples[..frames * channels]);
+            Ok(frames)
+        }
+    }
+}
+
+/// Printer driver implementation
+pub mod printer {
+    use hal::{Device, DeviceInfo, DeviceType, PrinterDevice, PrinterState};
+
+    /// Reference printer with a paper tray
+    pub struct ReferencePrinter {
+        name: String,
+        pages_per_minute: u32,
+        color: bool,
+        paper: u32,
+        online: bool,
+        printed: Vec<Vec<u8>>,
+        initialized: bool,
+    }
+
+    impl ReferencePrinter {
+        pub fn new(name: &str, pages_per_minute: u32, color: bool) -> Self {
+            ReferencePrinter {
+                name: name.to_string(),
+                pages_per_minute,
+                color,
+                paper: 0,
+                online: true,
+                printed: Vec::new(),
+                initialized: false,
+            }
+        }
+
+        /// Add sheets to the paper tray
+        pub fn load_paper(&mut self, sheets: u32) {
+            self.paper += sheets;
+        }
+
+        /// Simulate the printer being switched on or off
+        pub fn set_online(&mut self, online: bool) {
+            self.online = online;
+        }
+
+        /// Pages printed so far
+        pub fn printed_pages(&self) -> &[Vec<u8>] {
+            &self.printed
+        }
+    }
+
+    impl Device for ReferencePrinter {
+        fn info(&self) -> DeviceInfo {
+            DeviceInfo {
+                device_type: DeviceType::Printer,
+                vendor: "hairr OS".to_string(),
+                model: self.name.clone(),
+                version: "0.1.0".to_string(),
+            }
+        }
+
+        fn init(&mut self) -> Result<(), String> {
+            self.initialized = true;
+            Ok(())
+        }
+
+        fn shutdown(&mut self) -> Result<(), String> {
+            self.initialized = false;
+            Ok(())
+        }
+
+        fn read(&self, _offset: usize, _buffer: &mut [u8]) -> Result<usize, String> {
+            Err("Printer cannot be read".to_string())
+        }
+
+        fn write(&mut self, _offset: usize, data: &[u8]) -> Result<usize, String> {
+            self.print_page(data)?;
+            Ok(data.len())
+        }
+    }
+
+    impl PrinterDevice for ReferencePrinter {
+        fn printer_state(&self) -> PrinterState {
+            if !self.initialized || !self.online {
+                PrinterState::Offline
+            } else if self.paper == 0 {
+                PrinterState::OutOfPaper
+            } else {
+                PrinterState::Ready
+            }
+        }
+
+        fn pages_per_minute(&self) -> u32 {
+            self.pages_per_minute
+        }
+
+        fn supports_color(&self) -> bool {
+            self.color
+        }
+
+        fn print_page(&mut self, page: &[u8]) -> Result<(), String> {
+            match self.printer_state() {
+                PrinterState::Offline => Err("Printer is offline".to_string()),
+                PrinterState::OutOfPaper => Err("Printer is out of paper".to_string()),
+                PrinterState::Ready => {
+                    self.paper -= 1;
+                    self.printed.push(page.to_vec());
+                    Ok(())
+                }
+            }
+        }
+    }
 }
 
 /// GPU/AI Accelerator driver implementation
@@ -393,6 +851,63 @@ mod tests {
         assert_eq!(network.get_tx_queue_size(), 1);
     }
 
+    #[test]
+    fn test_audio_buffer() {
+        use hal::{AudioDevice, AudioFormat, Device};
+
+        let mut speaker = audio::ReferenceAudio::new("Speaker", AudioFormat::new(1000, 2), 8);
+        assert!(speaker.write_frames(&[1, 1]).is_err());
+        speaker.init().unwrap();
+        assert_eq!(speaker.write_frames(&[5; 20]).unwrap(), 8);
+        assert_eq!(speaker.queued_frames(), 8);
+
+        assert_eq!(speaker.play(5), vec![5; 10]);
+        assert_eq!(speaker.play(5), vec![5, 5, 5, 5, 5, 5, 0, 0, 0, 0]);
+        assert_eq!(speaker.underruns(), 1);
+    }
+
+    #[test]
+    fn test_printer_paper_tray() {
+        use hal::{Device, PrinterDevice, PrinterState};
+
+        let mut printer = printer::ReferencePrinter::new("laser", 20, false);
+        assert_eq!(printer.printer_state(), PrinterState::Offline);
+        printer.init().unwrap();
+        assert!(printer.print_page(b"page").is_err());
+        printer.load_paper(1);
+        printer.print_page(b"page").unwrap();
+        assert_eq!(printer.printer_state(), PrinterState::OutOfPaper);
+        assert_eq!(printer.printed_pages().len(), 1);
+    }
+
+    #[test]
+    fn test_loopback_device() {
+        use hal::{Device, NetworkDevice};
+
+        let mut lo = network::LoopbackDevice::new();
+        assert!(lo.send_packet(b"early").is_err());
+        lo.init().unwrap();
+        lo.send_packet(b"frame").unwrap();
+        assert_eq!(lo.receive_packet().unwrap(), b"frame");
+        assert!(lo.receive_packet().is_none());
+        assert_eq!(lo.stats().rx_packets, 1);
+    }
+
+    #[test]
+    fn test_ping_over_loopback() {
+        use hal::Device;
+        use net_stack::{Ipv4Config, NetStack};
+        use std::net::Ipv4Addr;
+
+        let mut lo = network::LoopbackDevice::new();
+        lo.init().unwrap();
+        let stack = NetStack::new(Box::new(lo));
+        stack.configure(Ipv4Config::new(Ipv4Addr::LOCALHOST, Ipv4Addr::new(255, 0, 0, 0), None));
+        stack.ping(Ipv4Addr::LOCALHOST, 1, 1, b"self").unwrap();
+        while stack.poll() > 0 {}
+        assert_eq!(stack.echo_replies().len(), 1);
+    }
+
     #[test]
     fn test_storage_driver() {
         let mut storage = storage::ReferenceStorage::new(10);
@@ -406,6 +921,25 @@ mod tests {
         assert_eq!(read_buffer, data);
     }
 
+    #[test]
+    fn test_storage_byte_access() {
+        use hal::{Device, StorageDevice};
+
+        let mut storage = storage::ReferenceStorage::new(1);
+        Device::init(&mut storage).unwrap();
+        let data: Vec<u8> = (0..700).map(|i| i as u8).collect();
+        assert_eq!(Device::write(&mut storage, 300, &data).unwrap(), 700);
+
+        let mut buffer = vec![0u8; 700];
+        assert_eq!(Device::read(&storage, 300, &mut buffer).unwrap(), 700);
+        assert_eq!(buffer, data);
+        let mut block = vec![0u8; storage.block_size()];
+        StorageDevice::read_block(&storage, 1, &mut block).unwrap();
+        assert_eq!(block[0], data[212]);
+        // Reads stop at the end of the device
+        assert_eq!(Device::read(&storage, 1024 * 1024 - 10, &mut buffer).unwrap(), 10);
+    }
+
     #[test]
     fn test_ai_accelerator() {
         let mut accelerator = accelerator::ReferenceAccelerator::new(128, 8192);
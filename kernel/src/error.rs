@@ -0,0 +1,60 @@
+//! Kernel errors
+//!
+//! Messages are the ones the kernel has always reported, so converting to
+//! a `String` with `?` or `to_string` reads the same as before.
+
+use core::fmt;
+
+use sync::prelude::*;
+#[cfg(feature = "std")]
+use system_utils::error::ErrorCode;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KernelError {
+    ProcessNotFound,
+    /// Spawning needs a capability manager to issue the grants
+    NoCapabilityManager,
+    /// A sandboxed process named a path outside its root
+    PathOutsideSandbox(String),
+    /// The process was reaped; its id may since name another one
+    StaleProcess,
+    /// Terminated processes stay terminated until reaped
+    ProcessTerminated,
+}
+
+impl fmt::Display for KernelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KernelError::ProcessNotFound => write!(f, "Process not found"),
+            KernelError::NoCapabilityManager => write!(f, "No capability manager attached"),
+            KernelError::PathOutsideSandbox(path) => write!(f, "Path leaves the sandbox: {}", path),
+            KernelError::StaleProcess => write!(f, "Stale process id"),
+            KernelError::ProcessTerminated => write!(f, "Process has terminated"),
+        }
+    }
+}
+
+impl core::error::Error for KernelError {}
+
+#[cfg(feature = "std")]
+impl ErrorCode for KernelError {
+    fn code(&self) -> u32 {
+        match self {
+            KernelError::ProcessNotFound => 101,
+            KernelError::NoCapabilityManager => 102,
+            KernelError::PathOutsideSandbox(_) => 103,
+            KernelError::StaleProcess => 104,
+            KernelError::ProcessTerminated => 105,
+        }
+    }
+
+    fn component(&self) -> &'static str {
+        "kernel"
+    }
+}
+
+impl From<KernelError> for String {
+    fn from(error: KernelError) -> Self {
+        error.to_string()
+    }
+}
@@ -5,18 +5,60 @@
 //! - Memory management
 //! - IPC facilitation
 //! - Capability-based security enforcement
+//! - Sandboxed app processes
+//!
+//! Operations fail with a `KernelError`, whose code identifies the failure.
+//!
+//! The simulator builds the kernel with the default `std` feature. Without
+//! it the kernel is `no_std` and needs only an allocator; paths, the wall
+//! clock and system information are std-only.
 
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+#![cfg_attr(not(feature = "std"), no_std)]
 
-/// Process identifier
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg(feature = "std")]
+use std::path::PathBuf;
+
+use capability::{CapabilityManager, CapabilityToken, Permission, Resource};
+use metrics::{Counter, Gauge, MetricsRegistry};
+use serde::{Deserialize, Serialize};
+use sync::prelude::*;
+use sync::{ids, Arc, IdAllocator, Mutex, ShardedMap};
+#[cfg(feature = "std")]
+use system_utils::sysinfo::SystemMonitor;
+#[cfg(feature = "std")]
+use system_utils::time::current_time_ms;
+
+pub mod error;
+pub mod sandbox;
+
+pub use error::KernelError;
+pub use sandbox::{NetworkPolicy, SandboxProfile};
+
+/// Process identifier; generational, so the id of a reaped process is
+/// told apart from the one that reuses its slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ProcessId(u64);
 
 impl ProcessId {
+    /// The kernel and the services built into the system image; never
+    /// handed out to a created process
+    pub const KERNEL: ProcessId = ProcessId(0);
+
     pub fn new(id: u64) -> Self {
         ProcessId(id)
     }
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+
+    pub fn index(&self) -> u32 {
+        ids::index(self.0)
+    }
+
+    pub fn generation(&self) -> u32 {
+        ids::generation(self.0)
+    }
 }
 
 /// Thread identifier
@@ -39,7 +81,7 @@ pub enum ProcessState {
 }
 
 /// Process priority levels
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum Priority {
     RealTime,
     High,
@@ -55,6 +97,10 @@ pub struct Process {
     pub state: ProcessState,
     pub priority: Priority,
     pub parent: Option<ProcessId>,
+    /// Granted when the process was spawned and revoked when it terminates
+    pub capabilities: Vec<CapabilityToken>,
+    /// What a sandboxed process may reach
+    pub sandbox: Option<SandboxProfile>,
 }
 
 impl Process {
@@ -65,71 +111,210 @@ impl Process {
             state: ProcessState::Ready,
             priority,
             parent: None,
+            capabilities: Vec::new(),
+            sandbox: None,
         }
     }
 }
 
+/// Callback invoked after a process terminates
+pub type TerminationHook = Arc<dyn Fn(ProcessId) + Send + Sync>;
+
+struct KernelMetrics {
+    created: Counter,
+    terminated: Counter,
+    processes: Gauge,
+}
+
 /// The microkernel itself
 pub struct Kernel {
-    processes: Arc<Mutex<HashMap<ProcessId, Process>>>,
-    next_process_id: Arc<Mutex<u64>>,
+    /// Sharded by process id, since every running process goes through it
+    processes: ShardedMap<ProcessId, Process>,
+    process_ids: IdAllocator,
+    termination_hooks: Arc<Mutex<Vec<TerminationHook>>>,
+    metrics: Arc<Mutex<Option<KernelMetrics>>>,
+    capabilities: Arc<Mutex<Option<Arc<CapabilityManager>>>>,
+    /// Milliseconds since the Unix epoch when the kernel came up; zero
+    /// without std, which has no wall clock
+    boot_time_ms: u64,
 }
 
 impl Kernel {
     pub fn new() -> Self {
         Kernel {
-            processes: Arc::new(Mutex::new(HashMap::new())),
-            next_process_id: Arc::new(Mutex::new(1)),
+            processes: ShardedMap::new(),
+            process_ids: IdAllocator::new(),
+            termination_hooks: Arc::new(Mutex::new(Vec::new())),
+            metrics: Arc::new(Mutex::new(None)),
+            capabilities: Arc::new(Mutex::new(None)),
+            #[cfg(feature = "std")]
+            boot_time_ms: current_time_ms(),
+            #[cfg(not(feature = "std"))]
+            boot_time_ms: 0,
         }
     }
 
+    pub fn boot_time_ms(&self) -> u64 {
+        self.boot_time_ms
+    }
+
+    /// Report when the system booted to `monitor`, so uptime counts from it
+    #[cfg(feature = "std")]
+    pub fn attach_sysinfo(&self, monitor: &SystemMonitor) {
+        monitor.set_boot_time(self.boot_time_ms);
+    }
+
+    /// Issue the capabilities of spawned processes from `manager`
+    pub fn attach_capabilities(&self, manager: Arc<CapabilityManager>) {
+        *self.capabilities.lock().unwrap() = Some(manager);
+    }
+
+    /// Report process lifecycle metrics to `registry`
+    pub fn attach_metrics(&self, registry: &MetricsRegistry) -> Result<(), String> {
+        let metrics = KernelMetrics {
+            created: registry.counter("kernel_processes_created_total", "Processes created")?,
+            terminated: registry.counter("kernel_processes_terminated_total", "Processes terminated")?,
+            processes: registry.gauge("kernel_processes", "Processes in the process table")?,
+        };
+        metrics.processes.set(self.process_count() as f64);
+        *self.metrics.lock().unwrap() = Some(metrics);
+        Ok(())
+    }
+
     /// Create a new process
     pub fn create_process(&self, name: String, priority: Priority) -> ProcessId {
-        let mut next_id = self.next_process_id.lock().unwrap();
-        let process_id = ProcessId(*next_id);
-        *next_id += 1;
+        let process_id = ProcessId(self.process_ids.allocate());
+        self.processes.insert(process_id, Process::new(process_id, name, priority));
+        let count = self.processes.len();
 
-        let process = Process::new(process_id, name, priority);
-        self.processes.lock().unwrap().insert(process_id, process);
-        
+        if let Some(metrics) = self.metrics.lock().unwrap().as_ref() {
+            metrics.created.inc();
+            metrics.processes.set(count as f64);
+        }
         process_id
     }
 
+    /// Create a process holding exactly `grants`, which are revoked when it
+    /// terminates
+    pub fn spawn(&self, name: String, priority: Priority, grants: &[(Resource, Permission)]) -> Result<ProcessId, KernelError> {
+        let manager = self.capabilities.lock().unwrap().clone().ok_or(KernelError::NoCapabilityManager)?;
+        let process_id = self.create_process(name, priority);
+        let tokens = grants
+            .iter()
+            .map(|(resource, permission)| manager.grant(resource.clone(), *permission))
+            .collect();
+        self.processes.with_mut(&process_id, |process| process.capabilities = tokens);
+        Ok(process_id)
+    }
+
+    /// Create a process confined to `profile`, holding the capabilities it
+    /// lists
+    pub fn spawn_sandboxed(&self, name: String, priority: Priority, profile: SandboxProfile) -> Result<ProcessId, KernelError> {
+        let process_id = self.spawn(name, priority, &profile.grants())?;
+        self.processes.with_mut(&process_id, |process| process.sandbox = Some(profile));
+        Ok(process_id)
+    }
+
+    /// Where a path a process names really is; unsandboxed processes see
+    /// the whole tree
+    #[cfg(feature = "std")]
+    pub fn resolve_path(&self, id: ProcessId, path: &str) -> Result<PathBuf, KernelError> {
+        self.processes
+            .with(&id, |process| match &process.sandbox {
+                Some(profile) => profile.resolve(path),
+                None => Ok(PathBuf::from(path)),
+            })
+            .ok_or_else(|| self.missing(id))?
+    }
+
     /// Get process information
     pub fn get_process(&self, id: ProcessId) -> Option<Process> {
-        self.processes.lock().unwrap().get(&id).cloned()
+        self.processes.get(&id)
     }
 
     /// Terminate a process
-    pub fn terminate_process(&self, id: ProcessId) -> Result<(), String> {
-        let mut processes = self.processes.lock().unwrap();
-        if let Some(process) = processes.get_mut(&id) {
-            process.state = ProcessState::Terminated;
-            Ok(())
-        } else {
-            Err("Process not found".to_string())
+    pub fn terminate_process(&self, id: ProcessId) -> Result<(), KernelError> {
+        let capabilities = self
+            .processes
+            .with_mut(&id, |process| {
+                if process.state == ProcessState::Terminated {
+                    return None;
+                }
+                process.state = ProcessState::Terminated;
+                Some(core::mem::take(&mut process.capabilities))
+            })
+            .ok_or_else(|| self.missing(id))?;
+        // Terminating twice is harmless: hooks and metrics saw the first
+        let Some(capabilities) = capabilities else {
+            return Ok(());
+        };
+        if let Some(manager) = self.capabilities.lock().unwrap().as_ref() {
+            for token in capabilities {
+                manager.revoke(token);
+            }
+        }
+        if let Some(metrics) = self.metrics.lock().unwrap().as_ref() {
+            metrics.terminated.inc();
+        }
+
+        // Hooks may call back into the kernel, so run them without the lock
+        let hooks = self.termination_hooks.lock().unwrap().clone();
+        for hook in hooks {
+            hook(id);
+        }
+        Ok(())
+    }
+
+    /// Register a callback to run whenever a process terminates
+    pub fn on_process_terminated(&self, hook: TerminationHook) {
+        self.termination_hooks.lock().unwrap().push(hook);
+    }
+
+    /// Update process state; a terminated process can only be reaped
+    pub fn update_process_state(&self, id: ProcessId, state: ProcessState) -> Result<(), KernelError> {
+        self.processes
+            .with_mut(&id, |process| {
+                if process.state == ProcessState::Terminated {
+                    return Err(KernelError::ProcessTerminated);
+                }
+                process.state = state;
+                Ok(())
+            })
+            .ok_or_else(|| self.missing(id))?
+    }
+
+    /// Remove a terminated process from the process table, freeing its id
+    /// for reuse; `None` if there is no such process or it is still running
+    pub fn reap(&self, id: ProcessId) -> Option<Process> {
+        let terminated = self.processes.with(&id, |process| process.state == ProcessState::Terminated)?;
+        if !terminated {
+            return None;
         }
+        let process = self.processes.remove(&id)?;
+        self.process_ids.release(id.value());
+        if let Some(metrics) = self.metrics.lock().unwrap().as_ref() {
+            metrics.processes.set(self.processes.len() as f64);
+        }
+        Some(process)
     }
 
-    /// Update process state
-    pub fn update_process_state(&self, id: ProcessId, state: ProcessState) -> Result<(), String> {
-        let mut processes = self.processes.lock().unwrap();
-        if let Some(process) = processes.get_mut(&id) {
-            process.state = state;
-            Ok(())
+    /// Why `id` is not in the process table
+    fn missing(&self, id: ProcessId) -> KernelError {
+        if self.process_ids.is_stale(id.value()) {
+            KernelError::StaleProcess
         } else {
-            Err("Process not found".to_string())
+            KernelError::ProcessNotFound
         }
     }
 
     /// List all processes
     pub fn list_processes(&self) -> Vec<Process> {
-        self.processes.lock().unwrap().values().cloned().collect()
+        self.processes.values()
     }
 
     /// Get process count
     pub fn process_count(&self) -> usize {
-        self.processes.lock().unwrap().len()
+        self.processes.len()
     }
 }
 
@@ -161,6 +346,115 @@ mod tests {
         assert!(kernel.terminate_process(pid).is_ok());
         let process = kernel.get_process(pid).unwrap();
         assert_eq!(process.state, ProcessState::Terminated);
+
+        // Nothing brings a terminated process back
+        let error = kernel.update_process_state(pid, ProcessState::Running).unwrap_err();
+        assert_eq!(error, KernelError::ProcessTerminated);
+        assert_eq!(kernel.get_process(pid).unwrap().state, ProcessState::Terminated);
+    }
+
+    #[test]
+    fn test_termination_hooks() {
+        let kernel = Kernel::new();
+        let pid = kernel.create_process("test_process".to_string(), Priority::Normal);
+
+        let terminated = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&terminated);
+        kernel.on_process_terminated(Arc::new(move |id| seen.lock().unwrap().push(id)));
+
+        kernel.terminate_process(pid).unwrap();
+        // A second termination does not run the hooks again
+        kernel.terminate_process(pid).unwrap();
+        let error = kernel.terminate_process(ProcessId::new(99)).unwrap_err();
+        assert_eq!(error, KernelError::ProcessNotFound);
+        assert_eq!(error.to_string(), "Process not found");
+        let report = system_utils::error::SystemError::from(error);
+        assert_eq!((report.code, report.component.as_str()), (101, "kernel"));
+        assert_eq!(*terminated.lock().unwrap(), vec![pid]);
+    }
+
+    #[test]
+    fn test_spawn_grants_capabilities() {
+        let kernel = Kernel::new();
+        let grants = [(Resource::Network("api.example.org".to_string()), Permission::ReadWrite)];
+        assert!(kernel.spawn("viewer".to_string(), Priority::Normal, &grants).is_err());
+
+        let manager = Arc::new(CapabilityManager::new());
+        kernel.attach_capabilities(Arc::clone(&manager));
+        let pid = kernel.spawn("viewer".to_string(), Priority::Normal, &grants).unwrap();
+        let tokens = kernel.get_process(pid).unwrap().capabilities;
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(manager.validate(tokens[0]).unwrap().resource, grants[0].0);
+
+        kernel.terminate_process(pid).unwrap();
+        assert!(manager.validate(tokens[0]).is_none());
+        assert!(kernel.get_process(pid).unwrap().capabilities.is_empty());
+    }
+
+    #[test]
+    fn test_sandboxed_processes() {
+        let kernel = Kernel::new();
+        let manager = Arc::new(CapabilityManager::new());
+        kernel.attach_capabilities(Arc::clone(&manager));
+        let mut profile = SandboxProfile::new("/apps/viewer".to_string());
+        profile.files.push(("/home/media".to_string(), Permission::Read));
+        profile.services.push("notifications".to_string());
+        profile.network = NetworkPolicy::Hosts(vec!["api.example.org".to_string()]);
+        let pid = kernel.spawn_sandboxed("viewer".to_string(), Priority::Normal, profile).unwrap();
+
+        let process = kernel.get_process(pid).unwrap();
+        assert_eq!(process.capabilities.len(), 4);
+        let sandbox = process.sandbox.unwrap();
+        assert!(sandbox.allows_service("notifications") && !sandbox.allows_service("users"));
+        assert!(sandbox.allows_host("api.example.org") && !sandbox.allows_host("tracker.example.com"));
+        assert_eq!(kernel.resolve_path(pid, "/data/state.json").unwrap(), PathBuf::from("/apps/viewer/data/state.json"));
+        assert_eq!(kernel.resolve_path(pid, "/home/media/song.ogg").unwrap(), PathBuf::from("/home/media/song.ogg"));
+        assert!(kernel.resolve_path(pid, "/data/../../etc/passwd").is_err());
+
+        let plain = kernel.create_process("shell".to_string(), Priority::Normal);
+        assert_eq!(kernel.resolve_path(plain, "/etc/passwd").unwrap(), PathBuf::from("/etc/passwd"));
+    }
+
+    #[test]
+    fn test_lifecycle_metrics() {
+        let kernel = Kernel::new();
+        kernel.create_process("early".to_string(), Priority::Low);
+        let registry = MetricsRegistry::new();
+        kernel.attach_metrics(&registry).unwrap();
+
+        let pid = kernel.create_process("late".to_string(), Priority::Normal);
+        kernel.terminate_process(pid).unwrap();
+        kernel.terminate_process(pid).unwrap();
+        assert_eq!(registry.counter("kernel_processes_created_total", "").unwrap().get(), 1);
+        assert_eq!(registry.counter("kernel_processes_terminated_total", "").unwrap().get(), 1);
+        assert_eq!(registry.gauge("kernel_processes", "").unwrap().get(), 2.0);
+    }
+
+    #[test]
+    fn test_boot_time() {
+        let kernel = Kernel::new();
+        let monitor = SystemMonitor::new();
+        monitor.set_boot_time(0);
+        kernel.attach_sysinfo(&monitor);
+        assert_eq!(monitor.boot_time_ms(), kernel.boot_time_ms());
+        assert!(monitor.uptime_ms() < 60_000);
+    }
+
+    #[test]
+    fn test_reaped_ids_are_stale() {
+        let kernel = Kernel::new();
+        let pid = kernel.create_process("old".to_string(), Priority::Normal);
+        assert!(kernel.reap(pid).is_none());
+        kernel.terminate_process(pid).unwrap();
+        assert_eq!(kernel.reap(pid).unwrap().name, "old");
+
+        // The slot is reused, but the old id does not name the new process
+        let reused = kernel.create_process("new".to_string(), Priority::Normal);
+        assert_eq!((reused.index(), reused.generation()), (pid.index(), 1));
+        assert!(kernel.get_process(pid).is_none());
+        assert_eq!(kernel.terminate_process(pid), Err(KernelError::StaleProcess));
+        assert_eq!(kernel.update_process_state(ProcessId::new(99), ProcessState::Ready), Err(KernelError::ProcessNotFound));
+        assert_eq!(kernel.get_process(reused).unwrap().state, ProcessState::Ready);
     }
 
     #[test]
@@ -0,0 +1,104 @@
+//! Sandbox profiles
+//!
+//! A sandboxed process sees one filesystem subtree as its root, reaches
+//! other paths and devices only where its profile lists them, may talk to
+//! the listed services over IPC, and reaches the network as its policy
+//! allows. Profiles are written by whoever installs the app and handed to
+//! the kernel when it is launched.
+//!
+//! ```json
+//! {"root": "/apps/viewer", "files": [["/home/media", "Read"]], "devices": [["gpu", "Read"]],
+//!  "services": ["notifications"], "network": {"hosts": ["api.example.org"]}}
+//! ```
+
+#[cfg(feature = "std")]
+use std::path::{Component, Path, PathBuf};
+
+use capability::{Permission, Resource};
+use serde::{Deserialize, Serialize};
+use sync::prelude::*;
+
+#[cfg(feature = "std")]
+use crate::KernelError;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NetworkPolicy {
+    Deny,
+    Hosts(Vec<String>),
+    Any,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SandboxProfile {
+    /// Subtree the process sees as `/`
+    pub root: String,
+    /// Paths outside the root, seen at their own location
+    #[serde(default)]
+    pub files: Vec<(String, Permission)>,
+    #[serde(default)]
+    pub devices: Vec<(String, Permission)>,
+    /// Services it may send requests to
+    #[serde(default)]
+    pub services: Vec<String>,
+    pub network: NetworkPolicy,
+}
+
+impl SandboxProfile {
+    /// Profile confined to `root` with nothing else
+    pub fn new(root: String) -> Self {
+        SandboxProfile {
+            root,
+            files: Vec::new(),
+            devices: Vec::new(),
+            services: Vec::new(),
+            network: NetworkPolicy::Deny,
+        }
+    }
+
+    /// Capabilities a process with this profile holds
+    pub fn grants(&self) -> Vec<(Resource, Permission)> {
+        let mut grants = vec![(Resource::File(self.root.clone()), Permission::ReadWrite)];
+        grants.extend(self.files.iter().map(|(path, p)| (Resource::File(path.clone()), *p)));
+        grants.extend(self.devices.iter().map(|(device, p)| (Resource::Device(device.clone()), *p)));
+        grants.extend(self.services.iter().map(|s| (Resource::IPC(s.clone()), Permission::ReadWrite)));
+        match &self.network {
+            NetworkPolicy::Deny => {}
+            NetworkPolicy::Hosts(hosts) => {
+                grants.extend(hosts.iter().map(|h| (Resource::Network(h.clone()), Permission::ReadWrite)))
+            }
+            NetworkPolicy::Any => grants.push((Resource::Network("*".to_string()), Permission::ReadWrite)),
+        }
+        grants
+    }
+
+    /// Where a path the process names really is: inside a listed path it
+    /// stays as it is, anything else is under the root
+    #[cfg(feature = "std")]
+    pub fn resolve(&self, path: &str) -> Result<PathBuf, KernelError> {
+        let mut inner = PathBuf::from("/");
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(name) => inner.push(name),
+                Component::RootDir | Component::CurDir => {}
+                _ => return Err(KernelError::PathOutsideSandbox(path.to_string())),
+            }
+        }
+        if self.files.iter().any(|(listed, _)| inner.starts_with(listed)) {
+            return Ok(inner);
+        }
+        Ok(Path::new(&self.root).join(inner.strip_prefix("/").unwrap()))
+    }
+
+    pub fn allows_service(&self, service: &str) -> bool {
+        self.services.iter().any(|s| s == service)
+    }
+
+    pub fn allows_host(&self, host: &str) -> bool {
+        match &self.network {
+            NetworkPolicy::Deny => false,
+            NetworkPolicy::Hosts(hosts) => hosts.iter().any(|h| h == host),
+            NetworkPolicy::Any => true,
+        }
+    }
+}
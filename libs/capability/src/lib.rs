@@ -3,12 +3,17 @@
 //! This module provides the foundation for capability-based access control,
 //! ensuring that components can only access resources they have explicit
 //! permission to use.
+//!
+//! Builds as `no_std` when the default `std` feature is turned off.
 
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use serde::{Deserialize, Serialize};
+use sync::prelude::*;
+use sync::{Arc, HashMap, Mutex};
 
 /// Represents a unique capability token
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct CapabilityToken(u64);
 
 impl CapabilityToken {
@@ -18,7 +23,7 @@ impl CapabilityToken {
 }
 
 /// Types of resources that can be protected by capabilities
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Resource {
     File(String),
     Network(String),
@@ -28,7 +33,7 @@ pub enum Resource {
 }
 
 /// Permission levels for capabilities
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Permission {
     Read,
     Write,
@@ -37,18 +42,56 @@ pub enum Permission {
     Full,
 }
 
+impl Permission {
+    /// Whether holding this permission allows an operation needing `required`
+    pub fn allows(self, required: Permission) -> bool {
+        match (self, required) {
+            (Permission::Full, _) => true,
+            (Permission::ReadWrite, Permission::Read) => true,
+            (Permission::ReadWrite, Permission::Write) => true,
+            (p1, p2) => p1 == p2,
+        }
+    }
+}
+
 /// A capability grants specific permissions to a resource
 #[derive(Debug, Clone)]
 pub struct Capability {
     pub token: CapabilityToken,
     pub resource: Resource,
     pub permission: Permission,
+    /// User the capability was issued to; `None` for bearer capabilities
+    pub owner: Option<u32>,
+}
+
+/// A permission check that failed, for auditing
+#[derive(Debug, Clone)]
+pub struct AccessDenied {
+    pub token: CapabilityToken,
+    /// User who presented the token, or its owner if no user was given
+    pub uid: Option<u32>,
+    pub required: Permission,
+    /// What the token grants access to; `None` if it is not a valid token
+    pub resource: Option<Resource>,
+}
+
+/// Callback run for every failed permission check
+pub type DenialListener = Arc<dyn Fn(&AccessDenied) + Send + Sync>;
+
+/// Resolves numeric user ids to accounts
+pub trait UserDirectory: Send + Sync {
+    fn user_name(&self, uid: u32) -> Option<String>;
+
+    /// Groups `uid` belongs to, including its primary group
+    fn groups(&self, uid: u32) -> Vec<u32>;
 }
 
 /// The capability manager tracks and validates capabilities
 pub struct CapabilityManager {
     capabilities: Arc<Mutex<HashMap<CapabilityToken, Capability>>>,
     next_token_id: Arc<Mutex<u64>>,
+    directory: Arc<Mutex<Option<Arc<dyn UserDirectory>>>>,
+    denial_listeners: Arc<Mutex<Vec<DenialListener>>>,
 }
 
 impl CapabilityManager {
@@ -56,11 +99,22 @@ impl CapabilityManager {
         CapabilityManager {
             capabilities: Arc::new(Mutex::new(HashMap::new())),
             next_token_id: Arc::new(Mutex::new(1)),
+            directory: Arc::new(Mutex::new(None)),
+            denial_listeners: Arc::new(Mutex::new(Vec::new())),
         }
     }
 
     /// Grant a new capability for a resource
     pub fn grant(&self, resource: Resource, permission: Permission) -> CapabilityToken {
+        self.issue(resource, permission, None)
+    }
+
+    /// Grant a capability that only `uid` may exercise
+    pub fn grant_to(&self, uid: u32, resource: Resource, permission: Permission) -> CapabilityToken {
+        self.issue(resource, permission, Some(uid))
+    }
+
+    fn issue(&self, resource: Resource, permission: Permission, owner: Option<u32>) -> CapabilityToken {
         let mut next_id = self.next_token_id.lock().unwrap();
         let token = CapabilityToken(*next_id);
         *next_id += 1;
@@ -69,6 +123,7 @@ impl CapabilityManager {
             token,
             resource,
             permission,
+            owner,
         };
 
         self.capabilities.lock().unwrap().insert(token, capability);
@@ -80,6 +135,74 @@ impl CapabilityManager {
         self.capabilities.lock().unwrap().remove(&token).is_some()
     }
 
+    /// Revoke every capability issued to `uid`; returns how many were revoked
+    pub fn revoke_owned_by(&self, uid: u32) -> usize {
+        let mut capabilities = self.capabilities.lock().unwrap();
+        let before = capabilities.len();
+        capabilities.retain(|_, cap| cap.owner != Some(uid));
+        before - capabilities.len()
+    }
+
+    /// Resolve capability owners through `directory`
+    pub fn set_user_directory(&self, directory: Arc<dyn UserDirectory>) {
+        *self.directory.lock().unwrap() = Some(directory);
+    }
+
+    /// Name of the user a capability was issued to
+    pub fn owner_name(&self, token: CapabilityToken) -> Option<String> {
+        self.user_name(self.validate(token)?.owner?)
+    }
+
+    /// Name of `uid` in the user directory
+    pub fn user_name(&self, uid: u32) -> Option<String> {
+        let directory = self.directory.lock().unwrap().clone()?;
+        directory.user_name(uid)
+    }
+
+    /// Run `listener` for each permission check that fails from now on
+    pub fn on_denied(&self, listener: DenialListener) {
+        self.denial_listeners.lock().unwrap().push(listener);
+    }
+
+    fn denied(&self, token: CapabilityToken, uid: Option<u32>, required: Permission, capability: Option<Capability>) {
+        let listeners = self.denial_listeners.lock().unwrap().clone();
+        if listeners.is_empty() {
+            return;
+        }
+        let denial = AccessDenied {
+            token,
+            uid: uid.or(capability.as_ref().and_then(|cap| cap.owner)),
+            required,
+            resource: capability.map(|cap| cap.resource),
+        };
+        for listener in listeners {
+            listener(&denial);
+        }
+    }
+
+    /// Check a token presented by `uid`. Owned capabilities only work for
+    /// their owner, and stop working once the owner no longer resolves in
+    /// the user directory.
+    pub fn check_user_permission(&self, token: CapabilityToken, uid: u32, required: Permission) -> bool {
+        let capability = self.validate(token);
+        let allowed = capability.as_ref().is_some_and(|cap| {
+            let owned_by_uid = match cap.owner {
+                Some(owner) => owner == uid && self.user_name_resolves(owner),
+                None => true,
+            };
+            owned_by_uid && cap.permission.allows(required)
+        });
+        if !allowed {
+            self.denied(token, Some(uid), required, capability);
+        }
+        allowed
+    }
+
+    fn user_name_resolves(&self, uid: u32) -> bool {
+        let directory = self.directory.lock().unwrap().clone();
+        directory.is_none_or(|d| d.user_name(uid).is_some())
+    }
+
     /// Check if a capability is valid
     pub fn validate(&self, token: CapabilityToken) -> Option<Capability> {
         self.capabilities.lock().unwrap().get(&token).cloned()
@@ -87,19 +210,16 @@ impl CapabilityManager {
 
     /// Check if a token has permission for a specific operation
     pub fn check_permission(&self, token: CapabilityToken, required: Permission) -> bool {
-        if let Some(cap) = self.validate(token) {
-            match (cap.permission, required) {
-                (Permission::Full, _) => true,
-                (Permission::ReadWrite, Permission::Read) => true,
-                (Permission::ReadWrite, Permission::Write) => true,
-                (p1, p2) => p1 == p2,
-            }
-        } else {
-            false
+        let capability = self.validate(token);
+        let allowed = capability.as_ref().is_some_and(|cap| cap.permission.allows(required));
+        if !allowed {
+            self.denied(token, None, required, capability);
         }
+        allowed
     }
 }
 
+
 impl Default for CapabilityManager {
     fn default() -> Self {
         Self::new()
@@ -138,4 +258,55 @@ mod tests {
         assert!(manager.check_permission(token, Permission::Write));
         assert!(!manager.check_permission(token, Permission::Execute));
     }
+
+    struct Directory;
+
+    impl UserDirectory for Directory {
+        fn user_name(&self, uid: u32) -> Option<String> {
+            (uid == 1000).then(|| "alice".to_string())
+        }
+
+        fn groups(&self, _uid: u32) -> Vec<u32> {
+            Vec::new()
+        }
+    }
+
+    #[test]
+    fn test_user_owned_capabilities() {
+        let manager = CapabilityManager::new();
+        manager.set_user_directory(Arc::new(Directory));
+        let alice = manager.grant_to(1000, Resource::File("/home/alice".to_string()), Permission::Read);
+        let ghost = manager.grant_to(1001, Resource::File("/home/ghost".to_string()), Permission::Read);
+
+        assert_eq!(manager.owner_name(alice), Some("alice".to_string()));
+        assert!(manager.check_user_permission(alice, 1000, Permission::Read));
+        assert!(!manager.check_user_permission(alice, 1001, Permission::Read));
+        // Owner is not a known user
+        assert!(!manager.check_user_permission(ghost, 1001, Permission::Read));
+
+        assert_eq!(manager.revoke_owned_by(1000), 1);
+        assert!(manager.validate(alice).is_none());
+    }
+
+    #[test]
+    fn test_denial_listener() {
+        let manager = CapabilityManager::new();
+        let denials = Arc::new(Mutex::new(Vec::new()));
+        let heard = Arc::clone(&denials);
+        manager.on_denied(Arc::new(move |denial: &AccessDenied| heard.lock().unwrap().push(denial.clone())));
+        let token = manager.grant_to(1000, Resource::Device("camera".to_string()), Permission::Read);
+
+        assert!(manager.check_user_permission(token, 1000, Permission::Read));
+        assert!(!manager.check_user_permission(token, 1001, Permission::Read));
+        assert!(!manager.check_permission(token, Permission::Write));
+        assert!(!manager.check_permission(CapabilityToken::new(99), Permission::Read));
+
+        let denials = denials.lock().unwrap();
+        let summary: Vec<(Option<u32>, Permission, bool)> =
+            denials.iter().map(|d| (d.uid, d.required, d.resource.is_some())).collect();
+        assert_eq!(
+            summary,
+            vec![(Some(1001), Permission::Read, true), (Some(1000), Permission::Write, true), (None, Permission::Read, false)]
+        );
+    }
 }
@@ -0,0 +1,478 @@
+//! Full-disk encryption for storage devices
+//!
+//! An `EncryptedVolume` sits between a filesystem and a `StorageDevice`
+//! and is a `StorageDevice` itself, encrypting every block on its way to
+//! the device. Each volume has its own random data key, stored only
+//! wrapped: once in a key slot for every way of unlocking it, as with
+//! LUKS. A passphrase slot derives its wrapping key from the passphrase
+//! through a keystore master key, so the passphrase is useless without the
+//! keystore; a hardware slot derives it from a hardware-backed key alone,
+//! subject to that key's policy. Slots and the cipher mode live in a
+//! header in the first blocks of the device.
+//!
+//! Like the TLS record layer, the ciphers are sized for the simulation and
+//! are not real-world secure.
+
+use std::sync::{Arc, Mutex};
+
+use hal::{Device, DeviceInfo, StorageDevice};
+use keystore::exchange::random_bytes;
+use keystore::{kdf, KeyId, Keystore};
+use serde::{Deserialize, Serialize};
+use tls::crypto::{apply_keystream, tag, TAG_LEN};
+
+mod cipher;
+
+/// Blocks reserved for the header at the start of the device
+pub const HEADER_BLOCKS: u64 = 8;
+
+const MAGIC: &str = "hairr-fde-1";
+const DATA_KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+/// Content type of key slot check values
+const SLOT_CHECK: u8 = 0x5c;
+
+/// How blocks are encrypted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CipherMode {
+    /// Length-preserving and tweaked by block number, as AES-XTS: blocks
+    /// keep their size, but tampering goes unnoticed
+    Xts,
+    /// Authenticated, as AES-GCM: altered or moved blocks fail to read, and
+    /// each block gives up 16 bytes to its nonce and tag
+    Gcm,
+}
+
+/// A way of unlocking a volume
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Credential {
+    Passphrase { master_key: KeyId, passphrase: String },
+    /// A hardware-backed keystore key with `DeriveKey` usage
+    Hardware(KeyId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum SlotKind {
+    Passphrase,
+    Hardware,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KeySlot {
+    kind: SlotKind,
+    key_id: String,
+    salt: Vec<u8>,
+    iterations: u32,
+    wrapped_key: Vec<u8>,
+    /// Tag over the data key, telling a right unwrap from a wrong one
+    check: [u8; TAG_LEN],
+}
+
+impl KeySlot {
+    fn matches(&self, credential: &Credential) -> bool {
+        match credential {
+            Credential::Passphrase { master_key, .. } => self.kind == SlotKind::Passphrase && self.key_id == master_key.as_str(),
+            Credential::Hardware(key) => self.kind == SlotKind::Hardware && self.key_id == key.as_str(),
+        }
+    }
+
+    fn unwrap_key(&self, keystore: &Keystore, volume_id: &[u8], credential: &Credential) -> Result<Option<Vec<u8>>, String> {
+        let wrapping_key = wrapping_key(keystore, volume_id, credential, &self.salt, self.iterations)?;
+        let mut data_key = self.wrapped_key.clone();
+        apply_keystream(&wrapping_key, 0, &mut data_key);
+        Ok((tag(&wrapping_key, 0, SLOT_CHECK, &data_key) == self.check).then_some(data_key))
+    }
+}
+
+fn wrapping_key(keystore: &Keystore, volume_id: &[u8], credential: &Credential, salt: &[u8], iterations: u32) -> Result<Vec<u8>, String> {
+    match credential {
+        Credential::Passphrase { master_key, passphrase } => {
+            keystore.derive_key(master_key, passphrase.as_bytes(), salt, iterations, DATA_KEY_LEN)
+        }
+        Credential::Hardware(key) => {
+            if !keystore.get_key(key).ok_or("Key not found")?.hardware_backed {
+                return Err("Key is not hardware-backed".to_string());
+            }
+            keystore.derive_key(key, volume_id, salt, iterations, DATA_KEY_LEN)
+        }
+    }
+}
+
+fn wrap_key(keystore: &Keystore, volume_id: &[u8], credential: &Credential, data_key: &[u8]) -> Result<KeySlot, String> {
+    let (kind, key_id, iterations) = match credential {
+        Credential::Passphrase { master_key, .. } => (SlotKind::Passphrase, master_key, kdf::DEFAULT_ITERATIONS),
+        Credential::Hardware(key) => (SlotKind::Hardware, key, 1),
+    };
+    let salt = random_bytes(SALT_LEN);
+    let wrapping_key = wrapping_key(keystore, volume_id, credential, &salt, iterations)?;
+    let mut wrapped_key = data_key.to_vec();
+    apply_keystream(&wrapping_key, 0, &mut wrapped_key);
+    Ok(KeySlot {
+        kind,
+        key_id: key_id.as_str().to_string(),
+        salt,
+        iterations,
+        wrapped_key,
+        check: tag(&wrapping_key, 0, SLOT_CHECK, data_key),
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Header {
+    magic: String,
+    mode: CipherMode,
+    volume_id: Vec<u8>,
+    slots: Vec<KeySlot>,
+}
+
+impl Header {
+    fn load(device: &dyn StorageDevice) -> Result<Self, String> {
+        let len = u32::from_be_bytes(device.read_bytes(0, 4)?[..4].try_into().unwrap()) as u64;
+        if len == 0 || len > header_capacity(device) {
+            return Err("No encrypted volume on this device".to_string());
+        }
+        let header: Header = serde_json::from_slice(&device.read_bytes(4, len as usize)?)
+            .map_err(|_| "No encrypted volume on this device".to_string())?;
+        if header.magic != MAGIC {
+            return Err("No encrypted volume on this device".to_string());
+        }
+        Ok(header)
+    }
+
+    fn save(&self, device: &mut dyn StorageDevice) -> Result<(), String> {
+        let json = serde_json::to_vec(self).map_err(|e| e.to_string())?;
+        if json.len() as u64 > header_capacity(device) {
+            return Err("Too many key slots".to_string());
+        }
+        let mut data = (json.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(&json);
+        device.write_bytes(0, &data)
+    }
+}
+
+fn header_capacity(device: &dyn StorageDevice) -> u64 {
+    HEADER_BLOCKS * device.block_size() as u64 - 4
+}
+
+/// Device an encrypted volume sits on, shared with its driver
+pub type SharedStorage = Arc<Mutex<dyn StorageDevice>>;
+
+/// An unlocked volume; dropping it forgets the data key
+pub struct EncryptedVolume {
+    device: SharedStorage,
+    keystore: Arc<Keystore>,
+    header: Header,
+    data_key: Vec<u8>,
+}
+
+impl EncryptedVolume {
+    /// Start a new volume on `device`, unlocked by `credential`. XTS leaves
+    /// blocks already on the device in place; GCM seals every block as
+    /// zeros, so that no read goes unauthenticated.
+    pub fn format(device: SharedStorage, keystore: Arc<Keystore>, mode: CipherMode, credential: &Credential) -> Result<Self, String> {
+        let volume_id = random_bytes(SALT_LEN);
+        let data_key = random_bytes(DATA_KEY_LEN);
+        let header = Header {
+            magic: MAGIC.to_string(),
+            mode,
+            slots: vec![wrap_key(&keystore, &volume_id, credential, &data_key)?],
+            volume_id,
+        };
+        {
+            let mut device = device.lock().unwrap();
+            let blocks = device.capacity() / device.block_size() as u64;
+            if blocks <= HEADER_BLOCKS || (mode == CipherMode::Gcm && device.block_size() <= 2 * cipher::TRAILER_LEN) {
+                return Err("Device too small for an encrypted volume".to_string());
+            }
+            header.save(&mut *device)?;
+        }
+        let mut volume = EncryptedVolume { device, keystore, header, data_key };
+        if mode == CipherMode::Gcm {
+            let zeros = vec![0; volume.block_size()];
+            for block in 0..volume.blocks() {
+                volume.write_block(block, &zeros)?;
+            }
+        }
+        Ok(volume)
+    }
+
+    /// Unlock the volume on `device`, as when mounting it
+    pub fn unlock(device: SharedStorage, keystore: Arc<Keystore>, credential: &Credential) -> Result<Self, String> {
+        let header = Header::load(&*device.lock().unwrap())?;
+        let data_key = Self::try_unlock(&header, &keystore, credential)?.ok_or("Wrong passphrase or key")?;
+        Ok(EncryptedVolume { device, keystore, header, data_key })
+    }
+
+    fn try_unlock(header: &Header, keystore: &Keystore, credential: &Credential) -> Result<Option<Vec<u8>>, String> {
+        for slot in header.slots.iter().filter(|slot| slot.matches(credential)) {
+            if let Some(data_key) = slot.unwrap_key(keystore, &header.volume_id, credential)? {
+                return Ok(Some(data_key));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn mode(&self) -> CipherMode {
+        self.header.mode
+    }
+
+    /// Let `credential` unlock the volume too
+    pub fn add_credential(&mut self, credential: &Credential) -> Result<(), String> {
+        if Self::try_unlock(&self.header, &self.keystore, credential)?.is_some() {
+            return Err("Credential already unlocks this volume".to_string());
+        }
+        let slot = wrap_key(&self.keystore, &self.header.volume_id, credential, &self.data_key)?;
+        self.header.slots.push(slot);
+        self.save_header()
+    }
+
+    /// Stop `credential` from unlocking the volume; the last one stays
+    pub fn remove_credential(&mut self, credential: &Credential) -> Result<(), String> {
+        let mut index = None;
+        for (i, slot) in self.header.slots.iter().enumerate().filter(|(_, slot)| slot.matches(credential)) {
+            if slot.unwrap_key(&self.keystore, &self.header.volume_id, credential)?.is_some() {
+                index = Some(i);
+                break;
+            }
+        }
+        let index = index.ok_or("Credential does not unlock this volume")?;
+        if self.header.slots.len() == 1 {
+            return Err("Cannot remove the last credential".to_string());
+        }
+        self.header.slots.remove(index);
+        self.save_header()
+    }
+
+    /// Re-encrypt every block under a new data key, unlocked afterwards by
+    /// `credentials` alone. Each must unlock the volume now; slots for any
+    /// others are dropped. Not crash-safe: an interrupted re-key leaves
+    /// blocks under both keys.
+    pub fn rekey(&mut self, credentials: &[Credential]) -> Result<(), String> {
+        if credentials.is_empty() {
+            return Err("Re-keying needs at least one credential".to_string());
+        }
+        for credential in credentials {
+            if Self::try_unlock(&self.header, &self.keystore, credential)?.is_none() {
+                return Err("Credential does not unlock this volume".to_string());
+            }
+        }
+        let data_key = random_bytes(DATA_KEY_LEN);
+        let slots = credentials
+            .iter()
+            .map(|credential| wrap_key(&self.keystore, &self.header.volume_id, credential, &data_key))
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let mut buffer = vec![0; self.block_size()];
+        for block in 0..self.blocks() {
+            self.read_block(block, &mut buffer)?;
+            let stored = self.seal(&data_key, block, &buffer);
+            self.device.lock().unwrap().write_block(HEADER_BLOCKS + block, &stored)?;
+        }
+        self.data_key = data_key;
+        self.header.slots = slots;
+        self.save_header()
+    }
+
+    fn save_header(&self) -> Result<(), String> {
+        self.header.save(&mut *self.device.lock().unwrap())
+    }
+
+    fn blocks(&self) -> u64 {
+        let device = self.device.lock().unwrap();
+        device.capacity() / device.block_size() as u64 - HEADER_BLOCKS
+    }
+
+    fn device_block_size(&self) -> usize {
+        self.device.lock().unwrap().block_size()
+    }
+
+    fn seal(&self, key: &[u8], block: u64, data: &[u8]) -> Vec<u8> {
+        match self.header.mode {
+            CipherMode::Xts => {
+                let mut stored = data.to_vec();
+                cipher::xts_encrypt(key, block, &mut stored);
+                stored
+            }
+            CipherMode::Gcm => cipher::gcm_seal(key, block, data),
+        }
+    }
+}
+
+impl Device for EncryptedVolume {
+    fn info(&self) -> DeviceInfo {
+        let mut info = self.device.lock().unwrap().info();
+        info.model = format!("{} (encrypted)", info.model);
+        info
+    }
+
+    fn init(&mut self) -> Result<(), String> {
+        self.device.lock().unwrap().init()
+    }
+
+    fn shutdown(&mut self) -> Result<(), String> {
+        self.device.lock().unwrap().shutdown()
+    }
+
+    fn read(&self, offset: usize, buffer: &mut [u8]) -> Result<usize, String> {
+        let len = buffer.len().min(self.capacity().saturating_sub(offset as u64) as usize);
+        buffer[..len].copy_from_slice(&self.read_bytes(offset as u64, len)?);
+        Ok(len)
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<usize, String> {
+        let len = data.len().min(self.capacity().saturating_sub(offset as u64) as usize);
+        self.write_bytes(offset as u64, &data[..len])?;
+        Ok(len)
+    }
+}
+
+impl StorageDevice for EncryptedVolume {
+    fn capacity(&self) -> u64 {
+        self.blocks() * self.block_size() as u64
+    }
+
+    fn block_size(&self) -> usize {
+        match self.header.mode {
+            CipherMode::Xts => self.device_block_size(),
+            CipherMode::Gcm => self.device_block_size() - cipher::TRAILER_LEN,
+        }
+    }
+
+    fn read_block(&self, block: u64, buffer: &mut [u8]) -> Result<(), String> {
+        if block >= self.blocks() {
+            return Err("Block out of range".to_string());
+        }
+        let mut stored = vec![0; self.device_block_size()];
+        self.device.lock().unwrap().read_block(HEADER_BLOCKS + block, &mut stored)?;
+        let data = match self.header.mode {
+            CipherMode::Xts => {
+                cipher::xts_decrypt(&self.data_key, block, &mut stored);
+                stored
+            }
+            CipherMode::Gcm => cipher::gcm_open(&self.data_key, block, &stored)?,
+        };
+        let len = buffer.len().min(data.len());
+        buffer[..len].copy_from_slice(&data[..len]);
+        Ok(())
+    }
+
+    fn write_block(&mut self, block: u64, data: &[u8]) -> Result<(), String> {
+        if block >= self.blocks() {
+            return Err("Block out of range".to_string());
+        }
+        // Partial writes fill the rest of the block with zeros, as on the
+        // devices underneath
+        let mut plain = vec![0; self.block_size()];
+        let len = data.len().min(plain.len());
+        plain[..len].copy_from_slice(&data[..len]);
+        let stored = self.seal(&self.data_key, block, &plain);
+        self.device.lock().unwrap().write_block(HEADER_BLOCKS + block, &stored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keystore::{AuthorizationPolicy, KeyType, KeyUsage};
+    use reference_driver::storage::ReferenceStorage;
+
+    fn setup() -> (SharedStorage, Arc<Keystore>) {
+        let mut device = ReferenceStorage::new(1);
+        device.init().unwrap();
+        let keystore = Arc::new(Keystore::new());
+        keystore
+            .generate_key("master".into(), KeyType::AES256, vec![KeyUsage::DeriveKey], false)
+            .unwrap();
+        keystore
+            .generate_key("tpm".into(), KeyType::AES256, vec![KeyUsage::DeriveKey], true)
+            .unwrap();
+        (Arc::new(Mutex::new(device)), keystore)
+    }
+
+    fn passphrase(passphrase: &str) -> Credential {
+        Credential::Passphrase {
+            master_key: "master".into(),
+            passphrase: passphrase.to_string(),
+        }
+    }
+
+    fn raw_contains(device: &SharedStorage, needle: &[u8]) -> bool {
+        let device = device.lock().unwrap();
+        let raw = device.read_bytes(0, device.capacity() as usize).unwrap();
+        raw.windows(needle.len()).any(|window| window == needle)
+    }
+
+    #[test]
+    fn test_unlock_with_passphrase() {
+        let (device, keystore) = setup();
+        let mut volume = EncryptedVolume::format(Arc::clone(&device), Arc::clone(&keystore), CipherMode::Xts, &passphrase("hunter2")).unwrap();
+        assert_eq!(volume.block_size(), 512);
+        assert_eq!(volume.capacity(), 1024 * 1024 - HEADER_BLOCKS * 512);
+        volume.write_bytes(1000, b"quarterly figures").unwrap();
+        drop(volume);
+        assert!(!raw_contains(&device, b"quarterly"));
+
+        let error = EncryptedVolume::unlock(Arc::clone(&device), Arc::clone(&keystore), &passphrase("hunter3")).err();
+        assert_eq!(error.as_deref(), Some("Wrong passphrase or key"));
+        // The passphrase alone is not enough without the keystore's master key
+        let other = Arc::new(Keystore::new());
+        other.generate_key("master".into(), KeyType::AES256, vec![KeyUsage::DeriveKey], false).unwrap();
+        assert!(EncryptedVolume::unlock(Arc::clone(&device), other, &passphrase("hunter2")).is_err());
+
+        let volume = EncryptedVolume::unlock(Arc::clone(&device), keystore, &passphrase("hunter2")).unwrap();
+        assert_eq!(volume.mode(), CipherMode::Xts);
+        assert_eq!(volume.read_bytes(1000, 17).unwrap(), b"quarterly figures");
+    }
+
+    #[test]
+    fn test_authenticated_blocks() {
+        let (device, keystore) = setup();
+        let mut volume = EncryptedVolume::format(Arc::clone(&device), keystore, CipherMode::Gcm, &passphrase("pw")).unwrap();
+        assert_eq!(volume.block_size(), 512 - 16);
+        volume.write_block(0, b"balance: 100").unwrap();
+        volume.write_block(1, b"balance: 999").unwrap();
+        let mut buffer = [0; 12];
+        volume.read_block(5, &mut buffer).unwrap();
+        assert_eq!(buffer, [0; 12]);
+
+        // Swapping stored blocks or flipping a bit is caught
+        let first = device.lock().unwrap().read_bytes(HEADER_BLOCKS * 512, 512).unwrap();
+        device.lock().unwrap().write_bytes((HEADER_BLOCKS + 1) * 512, &first).unwrap();
+        assert_eq!(volume.read_block(1, &mut buffer).unwrap_err(), "Block 1 failed authentication");
+        device.lock().unwrap().write_bytes(HEADER_BLOCKS * 512 + 3, &[first[3] ^ 1]).unwrap();
+        assert!(volume.read_block(0, &mut buffer).is_err());
+        assert!(volume.read_block(volume.capacity() / 496, &mut buffer).is_err());
+
+        // Wiping a block does not turn it back into a readable empty one
+        device.lock().unwrap().write_bytes((HEADER_BLOCKS + 5) * 512, &[0; 512]).unwrap();
+        assert_eq!(volume.read_block(5, &mut buffer).unwrap_err(), "Block 5 failed authentication");
+    }
+
+    #[test]
+    fn test_hardware_unlock_and_rekey() {
+        let (device, keystore) = setup();
+        let hardware = Credential::Hardware("tpm".into());
+        let mut volume = EncryptedVolume::format(Arc::clone(&device), Arc::clone(&keystore), CipherMode::Xts, &passphrase("pw")).unwrap();
+        volume.write_bytes(0, b"tax return").unwrap();
+        volume.add_credential(&hardware).unwrap();
+        assert!(volume.add_credential(&hardware).is_err());
+        assert!(volume.add_credential(&Credential::Hardware("master".into())).is_err());
+        let before = device.lock().unwrap().read_bytes(HEADER_BLOCKS * 512, 512).unwrap();
+
+        // The hardware key prompts the user before it unlocks anything
+        keystore
+            .set_policy(&"tpm".into(), AuthorizationPolicy::unrestricted().with_high_value(KeyUsage::DeriveKey))
+            .unwrap();
+        assert!(EncryptedVolume::unlock(Arc::clone(&device), Arc::clone(&keystore), &hardware).is_err());
+        keystore.set_authenticator(Arc::new(|_| true));
+        let mut volume = EncryptedVolume::unlock(Arc::clone(&device), Arc::clone(&keystore), &hardware).unwrap();
+
+        volume.rekey(std::slice::from_ref(&hardware)).unwrap();
+        assert_ne!(device.lock().unwrap().read_bytes(HEADER_BLOCKS * 512, 512).unwrap(), before);
+        assert_eq!(volume.read_bytes(0, 10).unwrap(), b"tax return");
+        assert_eq!(volume.remove_credential(&hardware).unwrap_err(), "Cannot remove the last credential");
+        assert!(EncryptedVolume::unlock(Arc::clone(&device), Arc::clone(&keystore), &passphrase("pw")).is_err());
+        let volume = EncryptedVolume::unlock(device, keystore, &hardware).unwrap();
+        assert_eq!(volume.read_bytes(0, 10).unwrap(), b"tax return");
+    }
+}
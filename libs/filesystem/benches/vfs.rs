@@ -0,0 +1,174 @@
+//! VFS workloads, to guide the persistent filesystem and caching work
+//!
+//! Small-file create/delete storms, large sequential writes, deep directory
+//! trees and many threads sharing one filesystem. Every workload checks what
+//! it wrote, so a run doubles as a stress test, and the run fails if any
+//! workload is slower than its floor in `SCENARIOS`.
+//!
+//! Two workloads are quadratic today: deleting scans the parent's children,
+//! and every write checksums the whole file again.
+//! Run with `cargo bench -p filesystem`.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use filesystem::{OpenOptions, ProcessId, VirtualFileSystem};
+
+const STORM_FILES: usize = 10_000;
+const SMALL_FILE: &[u8] = &[0x5a; 128];
+const LARGE_FILE_BYTES: usize = 8 << 20;
+const CHUNK_BYTES: usize = 64 << 10;
+const TREE_DEPTH: usize = 256;
+const THREADS: usize = 16;
+const FILES_PER_THREAD: usize = 500;
+
+struct Scenario {
+    name: &'static str,
+    unit: &'static str,
+    /// Slowest acceptable rate, about a quarter of what a release build
+    /// reached when it was set; raise it along with the rate
+    floor: f64,
+    run: fn() -> f64,
+}
+
+const SCENARIOS: &[Scenario] = &[
+    Scenario { name: "small-file storm", unit: "files/s", floor: 700.0, run: small_file_storm },
+    Scenario { name: "sequential write", unit: "MiB/s", floor: 1.0, run: sequential_write },
+    Scenario { name: "deep tree", unit: "ops/s", floor: 20_000.0, run: deep_tree },
+    Scenario { name: "concurrent access", unit: "files/s", floor: 80_000.0, run: concurrent_access },
+];
+
+fn write_file(vfs: &VirtualFileSystem, path: &Path, content: &[u8]) {
+    let handle = vfs.open(ProcessId::KERNEL, path, OpenOptions::write_only()).unwrap();
+    assert_eq!(vfs.write(ProcessId::KERNEL, handle, content).unwrap(), content.len());
+    vfs.close(ProcessId::KERNEL, handle).unwrap();
+}
+
+/// Files created, written and deleted again per second, all in one
+/// directory
+fn small_file_storm() -> f64 {
+    let vfs = VirtualFileSystem::new();
+    let storm = Path::new("/storm");
+    vfs.create_directory(storm).unwrap();
+
+    let started = Instant::now();
+    let paths: Vec<PathBuf> = (0..STORM_FILES).map(|i| storm.join(format!("file{}", i))).collect();
+    for path in &paths {
+        vfs.create_file(path).unwrap();
+        write_file(&vfs, path, SMALL_FILE);
+    }
+    assert_eq!(vfs.list_directory(storm).unwrap().len(), STORM_FILES);
+    for path in &paths {
+        vfs.delete(path).unwrap();
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+
+    assert!(vfs.list_directory(storm).unwrap().is_empty());
+    STORM_FILES as f64 / elapsed
+}
+
+/// Throughput of one file written from start to end in fixed chunks
+fn sequential_write() -> f64 {
+    let vfs = VirtualFileSystem::new();
+    let path = Path::new("/large.bin");
+    let chunk: Vec<u8> = (0..CHUNK_BYTES).map(|i| i as u8).collect();
+
+    let started = Instant::now();
+    let handle = vfs.open(ProcessId::KERNEL, path, OpenOptions::write_only()).unwrap();
+    for _ in 0..LARGE_FILE_BYTES / CHUNK_BYTES {
+        vfs.write(ProcessId::KERNEL, handle, &chunk).unwrap();
+    }
+    vfs.close(ProcessId::KERNEL, handle).unwrap();
+    let elapsed = started.elapsed().as_secs_f64();
+
+    let content = vfs.read_file(path).unwrap();
+    assert_eq!(content.len(), LARGE_FILE_BYTES);
+    assert!(content.chunks(CHUNK_BYTES).all(|c| c == chunk.as_slice()));
+    (LARGE_FILE_BYTES >> 20) as f64 / elapsed
+}
+
+/// Directory creations, file creations and listings per second down one
+/// chain of nested directories
+fn deep_tree() -> f64 {
+    let vfs = VirtualFileSystem::new();
+    let mut levels = Vec::with_capacity(TREE_DEPTH);
+    let mut path = PathBuf::from("/");
+    for depth in 0..TREE_DEPTH {
+        path = path.join(format!("level{}", depth));
+        levels.push(path.clone());
+    }
+
+    let started = Instant::now();
+    for level in &levels {
+        vfs.create_directory(level).unwrap();
+        vfs.create_file(&level.join("leaf")).unwrap();
+    }
+    for (depth, level) in levels.iter().enumerate() {
+        let expected = if depth + 1 == TREE_DEPTH { 1 } else { 2 };
+        assert_eq!(vfs.list_directory(level).unwrap().len(), expected);
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+
+    assert!(vfs.metadata(&levels[TREE_DEPTH - 1].join("leaf")).unwrap().is_file());
+    (TREE_DEPTH * 3) as f64 / elapsed
+}
+
+/// Files per second through a full lifecycle with `THREADS` threads on one
+/// filesystem, each also reading a file they all share
+fn concurrent_access() -> f64 {
+    let vfs = Arc::new(VirtualFileSystem::new());
+    let shared = Path::new("/shared");
+    vfs.create_file(shared).unwrap();
+    write_file(&vfs, shared, SMALL_FILE);
+
+    let started = Instant::now();
+    let workers: Vec<_> = (0..THREADS)
+        .map(|thread| {
+            let vfs = Arc::clone(&vfs);
+            thread::spawn(move || {
+                let home = PathBuf::from(format!("/thread{}", thread));
+                vfs.create_directory(&home).unwrap();
+                let mut buffer = vec![0; 64];
+                for i in 0..FILES_PER_THREAD {
+                    let path = home.join(format!("file{}", i));
+                    let content = format!("thread {} file {}", thread, i);
+                    vfs.create_file(&path).unwrap();
+                    write_file(&vfs, &path, content.as_bytes());
+
+                    let handle = vfs.open(ProcessId::KERNEL, &path, OpenOptions::read_only()).unwrap();
+                    let read = vfs.read(ProcessId::KERNEL, handle, &mut buffer).unwrap();
+                    assert_eq!(&buffer[..read], content.as_bytes());
+                    vfs.close(ProcessId::KERNEL, handle).unwrap();
+                    assert_eq!(vfs.read_file(shared).unwrap(), SMALL_FILE);
+                    vfs.delete(&path).unwrap();
+                }
+                vfs.delete(&home).unwrap();
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+
+    assert_eq!(vfs.list_directory(Path::new("/")).unwrap(), vec![shared.to_path_buf()]);
+    (THREADS * FILES_PER_THREAD) as f64 / elapsed
+}
+
+fn main() {
+    println!("{:<20} {:>14} {:>14} {:>8}", "scenario", "rate", "floor", "unit");
+    let mut regressions = Vec::new();
+    for scenario in SCENARIOS {
+        let rate = (scenario.run)();
+        println!("{:<20} {:>14.1} {:>14.1} {:>8}", scenario.name, rate, scenario.floor, scenario.unit);
+        if rate < scenario.floor {
+            regressions.push(scenario.name);
+        }
+    }
+    if !regressions.is_empty() {
+        eprintln!("Below the floor: {}", regressions.join(", "));
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,90 @@
+//! Filesystem errors
+
+use std::fmt;
+use std::path::PathBuf;
+
+use system_utils::error::ErrorCode;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    DirectoryNotFound,
+    AlreadyExists,
+    DirectoryExists,
+    ParentNotFound,
+    ParentNotDirectory,
+    NotADirectory,
+    NotAFile,
+    DirectoryNotEmpty,
+    /// A snapshot entry would replace a file with a directory or the reverse
+    TypeMismatch(PathBuf),
+    InvalidHandle,
+    NotOpenForReading,
+    NotOpenForWriting,
+    PermissionDenied,
+    /// The path is served by a mounted provider
+    ReadOnly,
+    AlreadyMounted,
+    /// The handle was closed; its slot may since belong to another file
+    StaleHandle,
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsError::NotFound => write!(f, "File not found"),
+            FsError::DirectoryNotFound => write!(f, "Directory not found"),
+            FsError::AlreadyExists => write!(f, "File already exists"),
+            FsError::DirectoryExists => write!(f, "Directory already exists"),
+            FsError::ParentNotFound => write!(f, "Parent directory does not exist"),
+            FsError::ParentNotDirectory => write!(f, "Parent is not a directory"),
+            FsError::NotADirectory => write!(f, "Not a directory"),
+            FsError::NotAFile => write!(f, "Not a regular file"),
+            FsError::DirectoryNotEmpty => write!(f, "Directory not empty"),
+            FsError::TypeMismatch(path) => write!(f, "{} exists with a different type", path.display()),
+            FsError::InvalidHandle => write!(f, "Invalid file handle"),
+            FsError::NotOpenForReading => write!(f, "File not opened for reading"),
+            FsError::NotOpenForWriting => write!(f, "File not opened for writing"),
+            FsError::PermissionDenied => write!(f, "Permission denied"),
+            FsError::ReadOnly => write!(f, "Read-only filesystem"),
+            FsError::AlreadyMounted => write!(f, "Already a mount point"),
+            FsError::StaleHandle => write!(f, "Stale file handle"),
+        }
+    }
+}
+
+impl std::error::Error for FsError {}
+
+impl ErrorCode for FsError {
+    fn code(&self) -> u32 {
+        match self {
+            FsError::NotFound => 301,
+            FsError::DirectoryNotFound => 302,
+            FsError::AlreadyExists => 303,
+            FsError::DirectoryExists => 304,
+            FsError::ParentNotFound => 305,
+            FsError::ParentNotDirectory => 306,
+            FsError::NotADirectory => 307,
+            FsError::NotAFile => 308,
+            FsError::DirectoryNotEmpty => 309,
+            FsError::TypeMismatch(_) => 310,
+            FsError::InvalidHandle => 311,
+            FsError::NotOpenForReading => 312,
+            FsError::NotOpenForWriting => 313,
+            FsError::PermissionDenied => 314,
+            FsError::ReadOnly => 315,
+            FsError::AlreadyMounted => 316,
+            FsError::StaleHandle => 317,
+        }
+    }
+
+    fn component(&self) -> &'static str {
+        "filesystem"
+    }
+}
+
+impl From<FsError> for String {
+    fn from(error: FsError) -> Self {
+        error.to_string()
+    }
+}
@@ -1,12 +1,37 @@
 //! Filesystem Abstraction for hairr OS
 //! 
 //! Provides a virtual filesystem layer that supports multiple filesystem types
-//! and allows for easy integration of new filesystems.
-
-use std::collections::HashMap;
+//! and allows for easy integration of new filesystems. Operations fail with
+//! an `FsError`, whose code identifies the failure.
+//!
+//! Besides the in-memory tree, synthetic trees can be mounted from a
+//! `FileSystemProvider`, whose files are computed when they are read.
+//!
+//! Open files are tracked per process: a handle is only good in the
+//! process it was opened or passed to, so knowing another process's
+//! handle value gets nothing. Code running as part of the system itself
+//! opens files as `ProcessId::KERNEL`.
+
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
+use capability::UserDirectory;
+use sync::{ids, IdAllocator};
+use system_utils::hash;
+
+pub mod error;
+pub mod provider;
+
+pub use error::FsError;
+pub use kernel::ProcessId;
+pub use provider::FileSystemProvider;
+
+use provider::provided_metadata;
+
+/// User id that bypasses permission checks
+pub const ROOT_UID: u32 = 0;
+
 /// File type
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FileType {
@@ -81,6 +106,9 @@ pub struct FileMetadata {
     pub accessed_at: u64,
     pub owner_id: u32,
     pub group_id: u32,
+    /// CRC-32 of the content, so copies can be compared without reading
+    /// them and corruption noticed
+    pub checksum: u32,
 }
 
 impl FileMetadata {
@@ -104,6 +132,7 @@ impl FileMetadata {
             accessed_at: now,
             owner_id: 0,
             group_id: 0,
+            checksum: 0,
         }
     }
 
@@ -114,9 +143,75 @@ impl FileMetadata {
     pub fn is_directory(&self) -> bool {
         self.file_type == FileType::Directory
     }
+
+    /// Whether a user in `groups` may access the file
+    pub fn allows(&self, uid: u32, groups: &[u32], access: Access) -> bool {
+        if uid == ROOT_UID {
+            return true;
+        }
+        let p = &self.permissions;
+        let (read, write, execute) = if uid == self.owner_id {
+            (p.owner_read, p.owner_write, p.owner_execute)
+        } else if groups.contains(&self.group_id) {
+            (p.group_read, p.group_write, p.group_execute)
+        } else {
+            (p.other_read, p.other_write, p.other_execute)
+        };
+        match access {
+            Access::Read => read,
+            Access::Write => write,
+            Access::Execute => execute,
+        }
+    }
+}
+
+/// Kind of access checked against file permissions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+/// Change to the filesystem tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VfsEvent {
+    Created { path: PathBuf, file_type: FileType },
+    Modified { path: PathBuf },
+    Deleted { path: PathBuf },
+}
+
+impl VfsEvent {
+    pub fn path(&self) -> &Path {
+        match self {
+            VfsEvent::Created { path, .. } | VfsEvent::Modified { path } | VfsEvent::Deleted { path } => path,
+        }
+    }
+}
+
+/// Point-in-time copy of one file or directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotEntry {
+    pub path: PathBuf,
+    pub file_type: FileType,
+    pub permissions: FilePermissions,
+    pub owner_id: u32,
+    pub group_id: u32,
+    pub modified_at: u64,
+    pub content: Vec<u8>,
+}
+
+/// Consistent copy of the whole tree, parents ordered before children
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub entries: BTreeMap<PathBuf, SnapshotEntry>,
 }
 
-/// File handle
+/// Callback run after the tree changes, with no filesystem locks held
+pub type VfsWatcher = Arc<dyn Fn(&VfsEvent) + Send + Sync>;
+
+/// File handle; generational, so a closed handle is rejected rather than
+/// reaching a file opened later
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct FileHandle(u64);
 
@@ -124,6 +219,18 @@ impl FileHandle {
     pub fn new(id: u64) -> Self {
         FileHandle(id)
     }
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+
+    pub fn index(&self) -> u32 {
+        ids::index(self.0)
+    }
+
+    pub fn generation(&self) -> u32 {
+        ids::generation(self.0)
+    }
 }
 
 /// File open options
@@ -171,16 +278,14 @@ impl OpenOptions {
 /// In-memory file node
 #[derive(Debug, Clone)]
 struct FileNode {
-    path: PathBuf,
     metadata: FileMetadata,
     content: Vec<u8>,
     children: Vec<PathBuf>,
 }
 
 impl FileNode {
-    fn new(path: PathBuf, file_type: FileType) -> Self {
+    fn new(file_type: FileType) -> Self {
         FileNode {
-            path,
             metadata: FileMetadata::new(file_type),
             content: Vec::new(),
             children: Vec::new(),
@@ -188,173 +293,325 @@ impl FileNode {
     }
 }
 
-/// Open file descriptor
-#[derive(Debug, Clone)]
+/// Open file description; a handle duplicated or inherited from another
+/// shares it, and with it the file position
+#[derive(Debug)]
 struct OpenFile {
-    handle: FileHandle,
     path: PathBuf,
     options: OpenOptions,
     position: usize,
+    /// Content read from a provider when the file was opened
+    provided: Option<Vec<u8>>,
+}
+
+type SharedOpenFile = Arc<Mutex<OpenFile>>;
+
+/// Provider answering for everything under `path`
+struct Mount {
+    path: PathBuf,
+    provider: Arc<dyn FileSystemProvider>,
 }
 
 /// Virtual Filesystem
 pub struct VirtualFileSystem {
-    root: PathBuf,
     nodes: Arc<Mutex<HashMap<PathBuf, FileNode>>>,
-    open_files: Arc<Mutex<HashMap<FileHandle, OpenFile>>>,
-    next_handle: Arc<Mutex<u64>>,
+    open_files: Arc<Mutex<HashMap<(ProcessId, FileHandle), SharedOpenFile>>>,
+    handles: IdAllocator,
+    directory: Arc<Mutex<Option<Arc<dyn UserDirectory>>>>,
+    watchers: Arc<Mutex<Vec<VfsWatcher>>>,
+    mounts: Arc<Mutex<Vec<Mount>>>,
 }
 
 impl VirtualFileSystem {
     pub fn new() -> Self {
-        let mut fs = VirtualFileSystem {
-            root: PathBuf::from("/"),
+        let fs = VirtualFileSystem {
             nodes: Arc::new(Mutex::new(HashMap::new())),
             open_files: Arc::new(Mutex::new(HashMap::new())),
-            next_handle: Arc::new(Mutex::new(1)),
+            handles: IdAllocator::new(),
+            directory: Arc::new(Mutex::new(None)),
+            watchers: Arc::new(Mutex::new(Vec::new())),
+            mounts: Arc::new(Mutex::new(Vec::new())),
         };
 
         // Create root directory
-        let root = FileNode::new(PathBuf::from("/"), FileType::Directory);
+        let root = FileNode::new(FileType::Directory);
         fs.nodes.lock().unwrap().insert(PathBuf::from("/"), root);
 
         fs
     }
 
+    /// Serve everything under `path` from `provider`, creating the mount
+    /// point if needed. Files already under it are hidden until unmounted.
+    pub fn mount(&self, path: &Path, provider: Arc<dyn FileSystemProvider>) -> Result<(), FsError> {
+        if self.mounts.lock().unwrap().iter().any(|m| m.path == path) {
+            return Err(FsError::AlreadyMounted);
+        }
+        match self.metadata(path) {
+            Ok(metadata) if !metadata.is_directory() => return Err(FsError::NotADirectory),
+            Ok(_) => {}
+            Err(_) => self.create_directory(path)?,
+        }
+        self.mounts.lock().unwrap().push(Mount {
+            path: path.to_path_buf(),
+            provider,
+        });
+        Ok(())
+    }
+
+    /// Stop serving `path` from a provider; returns whether it was mounted
+    pub fn unmount(&self, path: &Path) -> bool {
+        let mut mounts = self.mounts.lock().unwrap();
+        let before = mounts.len();
+        mounts.retain(|m| m.path != path);
+        mounts.len() != before
+    }
+
+    /// Provider answering for `path`, and the path relative to its mount
+    /// point
+    fn mounted(&self, path: &Path) -> Option<(Arc<dyn FileSystemProvider>, PathBuf)> {
+        let mounts = self.mounts.lock().unwrap();
+        mounts
+            .iter()
+            .filter_map(|m| Some((m, path.strip_prefix(&m.path).ok()?)))
+            .max_by_key(|(m, _)| m.path.components().count())
+            .map(|(m, inner)| (Arc::clone(&m.provider), inner.to_path_buf()))
+    }
+
     /// Create a new file
-    pub fn create_file(&self, path: &Path) -> Result<(), String> {
+    pub fn create_file(&self, path: &Path) -> Result<(), FsError> {
+        if self.mounted(path).is_some() {
+            return Err(FsError::ReadOnly);
+        }
         let mut nodes = self.nodes.lock().unwrap();
         
         if nodes.contains_key(path) {
-            return Err("File already exists".to_string());
+            return Err(FsError::AlreadyExists);
         }
 
         // Check if parent directory exists
         if let Some(parent) = path.parent() {
             if !nodes.contains_key(parent) {
-                return Err("Parent directory does not exist".to_string());
+                return Err(FsError::ParentNotFound);
             }
 
             // Add to parent's children
             if let Some(parent_node) = nodes.get_mut(parent) {
                 if !parent_node.metadata.is_directory() {
-                    return Err("Parent is not a directory".to_string());
+                    return Err(FsError::ParentNotDirectory);
                 }
                 parent_node.children.push(path.to_path_buf());
             }
         }
 
-        let node = FileNode::new(path.to_path_buf(), FileType::Regular);
+        let node = FileNode::new(FileType::Regular);
         nodes.insert(path.to_path_buf(), node);
+        drop(nodes);
 
+        self.notify(VfsEvent::Created {
+            path: path.to_path_buf(),
+            file_type: FileType::Regular,
+        });
         Ok(())
     }
 
     /// Create a new directory
-    pub fn create_directory(&self, path: &Path) -> Result<(), String> {
+    pub fn create_directory(&self, path: &Path) -> Result<(), FsError> {
+        if self.mounted(path).is_some() {
+            return Err(FsError::ReadOnly);
+        }
         let mut nodes = self.nodes.lock().unwrap();
         
         if nodes.contains_key(path) {
-            return Err("Directory already exists".to_string());
+            return Err(FsError::DirectoryExists);
         }
 
         // Check if parent directory exists
         if let Some(parent) = path.parent() {
             if !nodes.contains_key(parent) {
-                return Err("Parent directory does not exist".to_string());
+                return Err(FsError::ParentNotFound);
             }
 
             // Add to parent's children
             if let Some(parent_node) = nodes.get_mut(parent) {
                 if !parent_node.metadata.is_directory() {
-                    return Err("Parent is not a directory".to_string());
+                    return Err(FsError::ParentNotDirectory);
                 }
                 parent_node.children.push(path.to_path_buf());
             }
         }
 
-        let node = FileNode::new(path.to_path_buf(), FileType::Directory);
+        let node = FileNode::new(FileType::Directory);
         nodes.insert(path.to_path_buf(), node);
+        drop(nodes);
 
+        self.notify(VfsEvent::Created {
+            path: path.to_path_buf(),
+            file_type: FileType::Directory,
+        });
         Ok(())
     }
 
-    /// Open a file
-    pub fn open(&self, path: &Path, options: OpenOptions) -> Result<FileHandle, String> {
+    /// Open a file in `process`
+    pub fn open(&self, process: ProcessId, path: &Path, options: OpenOptions) -> Result<FileHandle, FsError> {
+        if let Some((provider, inner)) = self.mounted(path) {
+            if options.write || options.append || options.truncate {
+                return Err(FsError::ReadOnly);
+            }
+            let content = provider.read(&inner)?;
+            return Ok(self.insert_open_file(process, path, options, Some(content)));
+        }
+
         let nodes = self.nodes.lock().unwrap();
         
-        if !nodes.contains_key(path) {
-            if options.create {
+        match nodes.get(path) {
+            Some(node) if node.metadata.is_directory() && (options.write || options.append) => {
+                return Err(FsError::NotAFile);
+            }
+            // Reads and writes lock open files before nodes, so let go of
+            // the nodes before registering the handle
+            Some(_) => drop(nodes),
+            None if options.create => {
                 drop(nodes);
                 self.create_file(path)?;
-            } else {
-                return Err("File not found".to_string());
             }
+            None => return Err(FsError::NotFound),
         }
 
-        let mut next_handle = self.next_handle.lock().unwrap();
-        let handle = FileHandle(*next_handle);
-        *next_handle += 1;
+        Ok(self.insert_open_file(process, path, options, None))
+    }
 
+    fn insert_open_file(&self, process: ProcessId, path: &Path, options: OpenOptions, provided: Option<Vec<u8>>) -> FileHandle {
         let open_file = OpenFile {
-            handle,
             path: path.to_path_buf(),
             options,
             position: 0,
+            provided,
         };
+        self.insert_handle(process, Arc::new(Mutex::new(open_file)))
+    }
 
-        self.open_files.lock().unwrap().insert(handle, open_file);
+    fn insert_handle(&self, process: ProcessId, open_file: SharedOpenFile) -> FileHandle {
+        let handle = FileHandle(self.handles.allocate());
+        self.open_files.lock().unwrap().insert((process, handle), open_file);
+        handle
+    }
 
-        Ok(handle)
+    /// Open a file in `process` on behalf of `uid`, enforcing permissions
+    pub fn open_as(&self, process: ProcessId, uid: u32, path: &Path, options: OpenOptions) -> Result<FileHandle, FsError> {
+        if self.exists(path) {
+            if options.read {
+                self.check_access(path, uid, Access::Read)?;
+            }
+            if options.write || options.append {
+                self.check_access(path, uid, Access::Write)?;
+            }
+        } else if options.create {
+            let parent = path.parent().ok_or(FsError::NotFound)?;
+            self.check_access(parent, uid, Access::Write)?;
+        }
+        self.open(process, path, options)
     }
 
     /// Close a file
-    pub fn close(&self, handle: FileHandle) -> Result<(), String> {
-        self.open_files.lock().unwrap().remove(&handle)
-            .ok_or("Invalid file handle".to_string())?;
+    pub fn close(&self, process: ProcessId, handle: FileHandle) -> Result<(), FsError> {
+        self.open_files.lock().unwrap().remove(&(process, handle))
+            .ok_or_else(|| self.invalid(handle))?;
+        self.handles.release(handle.value());
         Ok(())
     }
 
+    /// Another handle in `process` to the same open file
+    pub fn duplicate(&self, process: ProcessId, handle: FileHandle) -> Result<FileHandle, FsError> {
+        self.inherit(process, handle, process)
+    }
+
+    /// Hand `child` its own handle to an open file of `parent`. Nothing is
+    /// inherited implicitly; the parent passes each handle it means to.
+    pub fn inherit(&self, parent: ProcessId, handle: FileHandle, child: ProcessId) -> Result<FileHandle, FsError> {
+        let open_file = self.open_file(parent, handle)?;
+        Ok(self.insert_handle(child, open_file))
+    }
+
+    /// Handles open in `process`
+    pub fn open_handles(&self, process: ProcessId) -> Vec<FileHandle> {
+        let mut handles: Vec<FileHandle> = self.open_files.lock().unwrap()
+            .keys()
+            .filter(|(owner, _)| *owner == process)
+            .map(|(_, handle)| *handle)
+            .collect();
+        handles.sort_by_key(|handle| handle.value());
+        handles
+    }
+
+    /// Close every handle `process` holds, as when it exits; returns how
+    /// many there were
+    pub fn close_process(&self, process: ProcessId) -> usize {
+        let handles = self.open_handles(process);
+        for handle in &handles {
+            let _ = self.close(process, *handle);
+        }
+        handles.len()
+    }
+
+    /// The open file behind `handle`, if `process` holds it. A handle open
+    /// in another process is as invalid here as one never handed out.
+    fn open_file(&self, process: ProcessId, handle: FileHandle) -> Result<SharedOpenFile, FsError> {
+        self.open_files.lock().unwrap()
+            .get(&(process, handle))
+            .map(Arc::clone)
+            .ok_or_else(|| self.invalid(handle))
+    }
+
+    /// Why `handle` names no open file
+    fn invalid(&self, handle: FileHandle) -> FsError {
+        if self.handles.is_stale(handle.value()) {
+            FsError::StaleHandle
+        } else {
+            FsError::InvalidHandle
+        }
+    }
+
     /// Read from a file
-    pub fn read(&self, handle: FileHandle, buffer: &mut [u8]) -> Result<usize, String> {
-        let mut open_files = self.open_files.lock().unwrap();
-        let open_file = open_files.get_mut(&handle)
-            .ok_or("Invalid file handle")?;
+    pub fn read(&self, process: ProcessId, handle: FileHandle, buffer: &mut [u8]) -> Result<usize, FsError> {
+        let shared = self.open_file(process, handle)?;
+        let mut guard = shared.lock().unwrap();
+        let open_file = &mut *guard;
 
         if !open_file.options.read {
-            return Err("File not opened for reading".to_string());
+            return Err(FsError::NotOpenForReading);
         }
 
         let nodes = self.nodes.lock().unwrap();
-        let node = nodes.get(&open_file.path)
-            .ok_or("File not found")?;
+        let content = match &open_file.provided {
+            Some(content) => content,
+            None => &nodes.get(&open_file.path).ok_or(FsError::NotFound)?.content,
+        };
 
-        let available = node.content.len().saturating_sub(open_file.position);
+        let position = open_file.position;
+        let available = content.len().saturating_sub(position);
         let to_read = available.min(buffer.len());
 
         if to_read > 0 {
-            buffer[..to_read].copy_from_slice(
-                &node.content[open_file.position..open_file.position + to_read]
-            );
-            open_file.position += to_read;
+            buffer[..to_read].copy_from_slice(&content[position..position + to_read]);
         }
+        open_file.position += to_read;
 
         Ok(to_read)
     }
 
     /// Write to a file
-    pub fn write(&self, handle: FileHandle, data: &[u8]) -> Result<usize, String> {
-        let mut open_files = self.open_files.lock().unwrap();
-        let open_file = open_files.get_mut(&handle)
-            .ok_or("Invalid file handle")?;
+    pub fn write(&self, process: ProcessId, handle: FileHandle, data: &[u8]) -> Result<usize, FsError> {
+        let shared = self.open_file(process, handle)?;
+        let mut guard = shared.lock().unwrap();
+        let open_file = &mut *guard;
 
         if !open_file.options.write {
-            return Err("File not opened for writing".to_string());
+            return Err(FsError::NotOpenForWriting);
         }
 
         let mut nodes = self.nodes.lock().unwrap();
         let node = nodes.get_mut(&open_file.path)
-            .ok_or("File not found")?;
+            .ok_or(FsError::NotFound)?;
 
         if open_file.options.truncate && open_file.position == 0 {
             node.content.clear();
@@ -375,41 +632,55 @@ impl VirtualFileSystem {
         }
 
         node.metadata.size = node.content.len() as u64;
+        node.metadata.checksum = hash::crc32(&node.content);
         node.metadata.modified_at = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
             .as_secs();
 
+        let path = open_file.path.clone();
+        drop(nodes);
+        drop(guard);
+        self.notify(VfsEvent::Modified { path });
         Ok(data.len())
     }
 
     /// Get file metadata
-    pub fn metadata(&self, path: &Path) -> Result<FileMetadata, String> {
+    pub fn metadata(&self, path: &Path) -> Result<FileMetadata, FsError> {
+        if let Some((provider, inner)) = self.mounted(path) {
+            return provider.file_type(&inner).map(provided_metadata).ok_or(FsError::NotFound);
+        }
         let nodes = self.nodes.lock().unwrap();
-        let node = nodes.get(path).ok_or("File not found")?;
+        let node = nodes.get(path).ok_or(FsError::NotFound)?;
         Ok(node.metadata.clone())
     }
 
     /// List directory contents
-    pub fn list_directory(&self, path: &Path) -> Result<Vec<PathBuf>, String> {
+    pub fn list_directory(&self, path: &Path) -> Result<Vec<PathBuf>, FsError> {
+        if let Some((provider, inner)) = self.mounted(path) {
+            return Ok(provider.list(&inner)?.into_iter().map(|name| path.join(name)).collect());
+        }
         let nodes = self.nodes.lock().unwrap();
-        let node = nodes.get(path).ok_or("Directory not found")?;
+        let node = nodes.get(path).ok_or(FsError::DirectoryNotFound)?;
 
         if !node.metadata.is_directory() {
-            return Err("Not a directory".to_string());
+            return Err(FsError::NotADirectory);
         }
 
         Ok(node.children.clone())
     }
 
     /// Delete a file or empty directory
-    pub fn delete(&self, path: &Path) -> Result<(), String> {
+    pub fn delete(&self, path: &Path) -> Result<(), FsError> {
+        if self.mounted(path).is_some() {
+            return Err(FsError::ReadOnly);
+        }
         let mut nodes = self.nodes.lock().unwrap();
         
-        let node = nodes.get(path).ok_or("File not found")?;
+        let node = nodes.get(path).ok_or(FsError::NotFound)?;
         
         if node.metadata.is_directory() && !node.children.is_empty() {
-            return Err("Directory not empty".to_string());
+            return Err(FsError::DirectoryNotEmpty);
         }
 
         // Remove from parent's children list
@@ -420,11 +691,158 @@ impl VirtualFileSystem {
         }
 
         nodes.remove(path);
+        drop(nodes);
+
+        self.notify(VfsEvent::Deleted {
+            path: path.to_path_buf(),
+        });
         Ok(())
     }
 
+    /// Read the whole content of a file
+    pub fn read_file(&self, path: &Path) -> Result<Vec<u8>, FsError> {
+        if let Some((provider, inner)) = self.mounted(path) {
+            return provider.read(&inner);
+        }
+        let nodes = self.nodes.lock().unwrap();
+        let node = nodes.get(path).ok_or(FsError::NotFound)?;
+        if !node.metadata.is_file() {
+            return Err(FsError::NotAFile);
+        }
+        Ok(node.content.clone())
+    }
+
+    /// Replace the whole content of a file, creating it and any missing
+    /// parent directories
+    pub fn write_file(&self, path: &Path, data: &[u8]) -> Result<(), FsError> {
+        for ancestor in path.ancestors().skip(1).collect::<Vec<_>>().into_iter().rev() {
+            if !self.exists(ancestor) {
+                self.create_directory(ancestor)?;
+            }
+        }
+        let options = OpenOptions {
+            truncate: true,
+            ..OpenOptions::write_only()
+        };
+        let handle = self.open(ProcessId::KERNEL, path, options)?;
+        let result = self.write(ProcessId::KERNEL, handle, data);
+        self.close(ProcessId::KERNEL, handle)?;
+        result.map(|_| ())
+    }
+
+    /// Copy the whole tree under a single lock
+    pub fn snapshot(&self) -> Snapshot {
+        let nodes = self.nodes.lock().unwrap();
+        let entries = nodes
+            .iter()
+            .map(|(path, node)| {
+                let entry = SnapshotEntry {
+                    path: path.clone(),
+                    file_type: node.metadata.file_type,
+                    permissions: node.metadata.permissions,
+                    owner_id: node.metadata.owner_id,
+                    group_id: node.metadata.group_id,
+                    modified_at: node.metadata.modified_at,
+                    content: node.content.clone(),
+                };
+                (path.clone(), entry)
+            })
+            .collect();
+        Snapshot { entries }
+    }
+
+    /// Put a snapshotted file or directory back, creating it if needed.
+    /// The parent directory must already exist.
+    pub fn restore_entry(&self, entry: &SnapshotEntry) -> Result<(), FsError> {
+        let existing = self.metadata(&entry.path).ok();
+        match existing {
+            Some(metadata) if metadata.file_type != entry.file_type => {
+                return Err(FsError::TypeMismatch(entry.path.clone()));
+            }
+            Some(_) => {}
+            None if entry.file_type == FileType::Directory => self.create_directory(&entry.path)?,
+            None => self.create_file(&entry.path)?,
+        }
+
+        let mut nodes = self.nodes.lock().unwrap();
+        let node = nodes.get_mut(&entry.path).ok_or(FsError::NotFound)?;
+        node.metadata.permissions = entry.permissions;
+        node.metadata.owner_id = entry.owner_id;
+        node.metadata.group_id = entry.group_id;
+        node.metadata.modified_at = entry.modified_at;
+        if entry.file_type != FileType::Directory {
+            node.content = entry.content.clone();
+            node.metadata.size = node.content.len() as u64;
+            node.metadata.checksum = hash::crc32(&node.content);
+        }
+        drop(nodes);
+
+        if existing.is_some() {
+            self.notify(VfsEvent::Modified {
+                path: entry.path.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Subscribe to changes anywhere in the tree
+    pub fn watch(&self, watcher: VfsWatcher) {
+        self.watchers.lock().unwrap().push(watcher);
+    }
+
+    fn notify(&self, event: VfsEvent) {
+        let watchers = self.watchers.lock().unwrap().clone();
+        for watcher in watchers {
+            watcher(&event);
+        }
+    }
+
+    /// Resolve user groups through `directory` for permission checks
+    pub fn set_user_directory(&self, directory: Arc<dyn UserDirectory>) {
+        *self.directory.lock().unwrap() = Some(directory);
+    }
+
+    /// Change the owning user and group of a path
+    pub fn set_owner(&self, path: &Path, uid: u32, gid: u32) -> Result<(), FsError> {
+        if self.mounted(path).is_some() {
+            return Err(FsError::ReadOnly);
+        }
+        let mut nodes = self.nodes.lock().unwrap();
+        let node = nodes.get_mut(path).ok_or(FsError::NotFound)?;
+        node.metadata.owner_id = uid;
+        node.metadata.group_id = gid;
+        Ok(())
+    }
+
+    /// Change the permission bits of a path
+    pub fn set_permissions(&self, path: &Path, permissions: FilePermissions) -> Result<(), FsError> {
+        if self.mounted(path).is_some() {
+            return Err(FsError::ReadOnly);
+        }
+        let mut nodes = self.nodes.lock().unwrap();
+        let node = nodes.get_mut(path).ok_or(FsError::NotFound)?;
+        node.metadata.permissions = permissions;
+        Ok(())
+    }
+
+    /// Check whether `uid` may access a path. Without a user directory
+    /// only owner and other permissions apply.
+    pub fn check_access(&self, path: &Path, uid: u32, access: Access) -> Result<(), FsError> {
+        let metadata = self.metadata(path)?;
+        let directory = self.directory.lock().unwrap().clone();
+        let groups = directory.map(|d| d.groups(uid)).unwrap_or_default();
+        if metadata.allows(uid, &groups, access) {
+            Ok(())
+        } else {
+            Err(FsError::PermissionDenied)
+        }
+    }
+
     /// Check if a path exists
     pub fn exists(&self, path: &Path) -> bool {
+        if let Some((provider, inner)) = self.mounted(path) {
+            return provider.file_type(&inner).is_some();
+        }
         self.nodes.lock().unwrap().contains_key(path)
     }
 
@@ -483,21 +901,103 @@ mod tests {
         let fs = VirtualFileSystem::new();
         fs.create_file(Path::new("/test.txt")).unwrap();
         
-        let handle = fs.open(Path::new("/test.txt"), OpenOptions::read_write()).unwrap();
+        let handle = fs.open(ProcessId::KERNEL, Path::new("/test.txt"), OpenOptions::read_write()).unwrap();
         
         let data = b"Hello, hairr OS!";
-        let written = fs.write(handle, data).unwrap();
+        let written = fs.write(ProcessId::KERNEL, handle, data).unwrap();
         assert_eq!(written, data.len());
         
-        fs.close(handle).unwrap();
+        fs.close(ProcessId::KERNEL, handle).unwrap();
         
-        let handle = fs.open(Path::new("/test.txt"), OpenOptions::read_only()).unwrap();
+        let handle = fs.open(ProcessId::KERNEL, Path::new("/test.txt"), OpenOptions::read_only()).unwrap();
         let mut buffer = vec![0u8; data.len()];
-        let read = fs.read(handle, &mut buffer).unwrap();
+        let read = fs.read(ProcessId::KERNEL, handle, &mut buffer).unwrap();
         assert_eq!(read, data.len());
         assert_eq!(&buffer, data);
         
-        fs.close(handle).unwrap();
+        fs.close(ProcessId::KERNEL, handle).unwrap();
+        assert_eq!(fs.metadata(Path::new("/test.txt")).unwrap().checksum, hash::crc32(data));
+    }
+
+    #[test]
+    fn test_write_whole_file() {
+        let fs = VirtualFileSystem::new();
+        fs.write_file(Path::new("/etc/app/config"), b"first version").unwrap();
+        assert!(fs.metadata(Path::new("/etc/app")).unwrap().is_directory());
+        fs.write_file(Path::new("/etc/app/config"), b"second").unwrap();
+        assert_eq!(fs.read_file(Path::new("/etc/app/config")).unwrap(), b"second");
+        assert!(fs.open_handles(ProcessId::KERNEL).is_empty());
+    }
+
+    #[test]
+    fn test_concurrent_open_and_write() {
+        let fs = Arc::new(VirtualFileSystem::new());
+        fs.create_file(Path::new("/shared")).unwrap();
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let fs = Arc::clone(&fs);
+                std::thread::spawn(move || {
+                    // Opening used to hold the nodes while a write held the
+                    // open files and waited for the nodes
+                    for _ in 0..500 {
+                        let handle = fs.open(ProcessId::KERNEL, Path::new("/shared"), OpenOptions::write_only()).unwrap();
+                        fs.write(ProcessId::KERNEL, handle, b"x").unwrap();
+                        fs.close(ProcessId::KERNEL, handle).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert_eq!(fs.metadata(Path::new("/shared")).unwrap().size, 1);
+    }
+
+    #[test]
+    fn test_closed_handles_are_stale() {
+        let fs = VirtualFileSystem::new();
+        fs.create_file(Path::new("/old.txt")).unwrap();
+        fs.create_file(Path::new("/new.txt")).unwrap();
+        let closed = fs.open(ProcessId::KERNEL, Path::new("/old.txt"), OpenOptions::read_write()).unwrap();
+        fs.close(ProcessId::KERNEL, closed).unwrap();
+        let reopened = fs.open(ProcessId::KERNEL, Path::new("/new.txt"), OpenOptions::read_write()).unwrap();
+        assert_eq!((reopened.index(), reopened.generation()), (closed.index(), 1));
+
+        // A write through the old handle must not land in the new file
+        assert_eq!(fs.write(ProcessId::KERNEL, closed, b"late"), Err(FsError::StaleHandle));
+        assert_eq!(fs.close(ProcessId::KERNEL, closed), Err(FsError::StaleHandle));
+        assert_eq!(fs.read(ProcessId::KERNEL, FileHandle::new(99), &mut [0; 4]), Err(FsError::InvalidHandle));
+        assert_eq!(fs.metadata(Path::new("/new.txt")).unwrap().size, 0);
+    }
+
+    #[test]
+    fn test_handles_belong_to_their_process() {
+        let fs = VirtualFileSystem::new();
+        let (parent, child, other) = (ProcessId::new(1), ProcessId::new(2), ProcessId::new(3));
+        fs.create_file(Path::new("/secret")).unwrap();
+        let handle = fs.open(parent, Path::new("/secret"), OpenOptions::read_write()).unwrap();
+        fs.write(parent, handle, b"abcdef").unwrap();
+
+        // Knowing the value is not enough to use or close it
+        assert_eq!(fs.read(other, handle, &mut [0; 4]), Err(FsError::InvalidHandle));
+        assert_eq!(fs.write(other, handle, b"x"), Err(FsError::InvalidHandle));
+        assert_eq!(fs.close(other, handle), Err(FsError::InvalidHandle));
+        assert_eq!(fs.inherit(other, handle, child), Err(FsError::InvalidHandle));
+
+        // Duplicated and inherited handles share the position
+        let duplicate = fs.duplicate(parent, handle).unwrap();
+        let inherited = fs.inherit(parent, handle, child).unwrap();
+        assert_eq!(fs.open_handles(parent), vec![handle, duplicate]);
+        assert_eq!(fs.open_handles(child), vec![inherited]);
+        assert_eq!(fs.read(child, inherited, &mut [0; 4]), Ok(0));
+        fs.write(parent, duplicate, b"gh").unwrap();
+
+        fs.close(parent, handle).unwrap();
+        assert_eq!(fs.close_process(parent), 1);
+        assert!(fs.open_handles(parent).is_empty());
+        fs.write(child, inherited, b"ij").unwrap();
+        fs.close(child, inherited).unwrap();
+        assert_eq!(fs.read_file(Path::new("/secret")).unwrap(), b"abcdefghij");
     }
 
     #[test]
@@ -520,6 +1020,89 @@ mod tests {
         assert!(!fs.exists(Path::new("/test.txt")));
     }
 
+    struct Directory;
+
+    impl UserDirectory for Directory {
+        fn user_name(&self, uid: u32) -> Option<String> {
+            Some(format!("user{}", uid))
+        }
+
+        fn groups(&self, uid: u32) -> Vec<u32> {
+            if uid == 1001 { vec![1001, 100] } else { vec![uid] }
+        }
+    }
+
+    #[test]
+    fn test_permission_checks() {
+        let fs = VirtualFileSystem::new();
+        let path = Path::new("/notes.txt");
+        fs.create_file(path).unwrap();
+        fs.set_owner(path, 1000, 100).unwrap();
+        fs.set_permissions(path, FilePermissions::new(0o640)).unwrap();
+
+        assert!(fs.open_as(ProcessId::KERNEL, 1000, path, OpenOptions::read_write()).is_ok());
+        assert_eq!(fs.open_as(ProcessId::KERNEL, 1001, path, OpenOptions::read_only()), Err(FsError::PermissionDenied));
+        assert!(fs.open_as(ProcessId::KERNEL, ROOT_UID, path, OpenOptions::read_write()).is_ok());
+
+        // Group membership comes from the user directory
+        fs.set_user_directory(Arc::new(Directory));
+        assert!(fs.open_as(ProcessId::KERNEL, 1001, path, OpenOptions::read_only()).is_ok());
+        assert!(fs.open_as(ProcessId::KERNEL, 1001, path, OpenOptions::read_write()).is_err());
+        assert!(fs.open_as(ProcessId::KERNEL, 1002, path, OpenOptions::read_only()).is_err());
+
+        // Creating needs write access to the parent directory
+        assert!(fs.open_as(ProcessId::KERNEL, 1000, Path::new("/new.txt"), OpenOptions::write_only()).is_err());
+    }
+
+    #[test]
+    fn test_change_notifications() {
+        let fs = VirtualFileSystem::new();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&events);
+        fs.watch(Arc::new(move |event: &VfsEvent| seen.lock().unwrap().push(event.clone())));
+
+        let path = Path::new("/log.txt");
+        let handle = fs.open(ProcessId::KERNEL, path, OpenOptions::write_only()).unwrap();
+        fs.write(ProcessId::KERNEL, handle, b"hello").unwrap();
+        fs.close(ProcessId::KERNEL, handle).unwrap();
+        assert_eq!(fs.read_file(path).unwrap(), b"hello");
+        fs.delete(path).unwrap();
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                VfsEvent::Created {
+                    path: path.to_path_buf(),
+                    file_type: FileType::Regular
+                },
+                VfsEvent::Modified { path: path.to_path_buf() },
+                VfsEvent::Deleted { path: path.to_path_buf() },
+            ]
+        );
+        assert_eq!(fs.read_file(Path::new("/")), Err(FsError::NotAFile));
+        assert_eq!(fs.open(ProcessId::KERNEL, Path::new("/"), OpenOptions::write_only()), Err(FsError::NotAFile));
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let fs = VirtualFileSystem::new();
+        fs.create_directory(Path::new("/etc")).unwrap();
+        let handle = fs.open(ProcessId::KERNEL, Path::new("/etc/hosts"), OpenOptions::write_only()).unwrap();
+        fs.write(ProcessId::KERNEL, handle, b"127.0.0.1 localhost").unwrap();
+        fs.set_owner(Path::new("/etc/hosts"), 1000, 1000).unwrap();
+        let snapshot = fs.snapshot();
+        assert_eq!(snapshot.entries.len(), 3);
+
+        let restored = VirtualFileSystem::new();
+        for entry in snapshot.entries.values().filter(|e| e.path != Path::new("/")) {
+            restored.restore_entry(entry).unwrap();
+        }
+        assert_eq!(restored.read_file(Path::new("/etc/hosts")).unwrap(), b"127.0.0.1 localhost");
+        assert_eq!(restored.metadata(Path::new("/etc/hosts")).unwrap().owner_id, 1000);
+        let hosts = Path::new("/etc/hosts");
+        assert_eq!(restored.snapshot().entries[hosts], snapshot.entries[hosts]);
+    }
+
     #[test]
     fn test_filesystem_stats() {
         let fs = VirtualFileSystem::new();
@@ -530,4 +1113,65 @@ mod tests {
         assert_eq!(stats.total_files, 1);
         assert_eq!(stats.total_directories, 2); // root + /dir
     }
+
+    /// Counts how often `counter` is read
+    struct Counter(Mutex<u32>);
+
+    impl FileSystemProvider for Counter {
+        fn file_type(&self, path: &Path) -> Option<FileType> {
+            match path.to_str()? {
+                "" => Some(FileType::Directory),
+                "count" => Some(FileType::Regular),
+                _ => None,
+            }
+        }
+
+        fn list(&self, path: &Path) -> Result<Vec<String>, FsError> {
+            match self.file_type(path) {
+                Some(FileType::Directory) => Ok(vec!["count".to_string()]),
+                Some(_) => Err(FsError::NotADirectory),
+                None => Err(FsError::DirectoryNotFound),
+            }
+        }
+
+        fn read(&self, path: &Path) -> Result<Vec<u8>, FsError> {
+            if self.file_type(path) != Some(FileType::Regular) {
+                return Err(FsError::NotFound);
+            }
+            let mut count = self.0.lock().unwrap();
+            *count += 1;
+            Ok(count.to_string().into_bytes())
+        }
+    }
+
+    #[test]
+    fn test_mounted_provider() {
+        let fs = VirtualFileSystem::new();
+        fs.mount(Path::new("/stats"), Arc::new(Counter(Mutex::new(0)))).unwrap();
+        let count = Path::new("/stats/count");
+
+        assert_eq!(fs.list_directory(Path::new("/")).unwrap(), vec![PathBuf::from("/stats")]);
+        assert_eq!(fs.list_directory(Path::new("/stats")).unwrap(), vec![count.to_path_buf()]);
+        assert_eq!(fs.read_file(count).unwrap(), b"1");
+        assert_eq!(fs.read_file(count).unwrap(), b"2");
+
+        let handle = fs.open(ProcessId::KERNEL, count, OpenOptions::read_only()).unwrap();
+        let mut buffer = [0; 8];
+        assert_eq!(fs.read(ProcessId::KERNEL, handle, &mut buffer).unwrap(), 1);
+        assert_eq!(&buffer[..1], b"3");
+        fs.close(ProcessId::KERNEL, handle).unwrap();
+
+        assert!(fs.metadata(count).unwrap().is_file());
+        assert!(fs.check_access(count, 1000, Access::Read).is_ok());
+        assert_eq!(fs.check_access(count, 1000, Access::Write), Err(FsError::PermissionDenied));
+        assert_eq!(fs.open(ProcessId::KERNEL, count, OpenOptions::write_only()), Err(FsError::ReadOnly));
+        assert_eq!(fs.create_file(Path::new("/stats/other")), Err(FsError::ReadOnly));
+        assert_eq!(fs.delete(count), Err(FsError::ReadOnly));
+        assert!(!fs.exists(Path::new("/stats/other")));
+        assert_eq!(fs.mount(Path::new("/stats"), Arc::new(Counter(Mutex::new(0)))), Err(FsError::AlreadyMounted));
+
+        assert!(fs.unmount(Path::new("/stats")));
+        assert!(!fs.exists(count));
+        assert!(fs.metadata(Path::new("/stats")).unwrap().is_directory());
+    }
 }
@@ -0,0 +1,36 @@
+//! Synthetic filesystems
+//!
+//! A `FileSystemProvider` serves a tree whose content is computed when it
+//! is read, such as live kernel and service state. Mounted with
+//! `VirtualFileSystem::mount`, it answers for every path under the mount
+//! point. Those paths are read-only: they cannot be written, created,
+//! deleted or have their ownership changed.
+
+use std::path::Path;
+
+use crate::{FileMetadata, FilePermissions, FileType, FsError};
+
+/// Source of a mounted tree. Paths are relative to the mount point, and the
+/// empty path is the mount point itself, which must be a directory.
+pub trait FileSystemProvider: Send + Sync {
+    /// What is at `path`, if anything
+    fn file_type(&self, path: &Path) -> Option<FileType>;
+
+    /// Names of the entries of the directory at `path`
+    fn list(&self, path: &Path) -> Result<Vec<String>, FsError>;
+
+    /// Current content of the file at `path`
+    fn read(&self, path: &Path) -> Result<Vec<u8>, FsError>;
+}
+
+/// Metadata of a provided entry: readable by everyone, writable by no one
+pub(crate) fn provided_metadata(file_type: FileType) -> FileMetadata {
+    let mode = match file_type {
+        FileType::Directory => 0o555,
+        _ => 0o444,
+    };
+    FileMetadata {
+        permissions: FilePermissions::new(mode),
+        ..FileMetadata::new(file_type)
+    }
+}
@@ -19,6 +19,7 @@ pub enum DeviceType {
     Network,
     Storage,
     Audio,
+    Printer,
     GPU,
     Sensor,
     Custom(String),
@@ -98,18 +99,132 @@ pub trait NetworkDevice: Device {
 pub trait StorageDevice: Device {
     /// Get storage capacity in bytes
     fn capacity(&self) -> u64;
+
+    /// Size of a block in bytes
+    fn block_size(&self) -> usize {
+        512
+    }
     
     /// Read a block from storage
     fn read_block(&self, block: u64, buffer: &mut [u8]) -> Result<(), String>;
     
     /// Write a block to storage
     fn write_block(&mut self, block: u64, data: &[u8]) -> Result<(), String>;
+
+    /// Read `len` bytes starting at byte `offset`
+    fn read_bytes(&self, offset: u64, len: usize) -> Result<Vec<u8>, String> {
+        let block_size = self.block_size() as u64;
+        let mut block = vec![0u8; block_size as usize];
+        let mut data = Vec::with_capacity(len);
+        while data.len() < len {
+            let position = offset + data.len() as u64;
+            self.read_block(position / block_size, &mut block)?;
+            let start = (position % block_size) as usize;
+            let take = (block.len() - start).min(len - data.len());
+            data.extend_from_slice(&block[start..start + take]);
+        }
+        Ok(data)
+    }
+
+    /// Write `data` starting at byte `offset`, preserving the rest of
+    /// partially covered blocks
+    fn write_bytes(&mut self, offset: u64, data: &[u8]) -> Result<(), String> {
+        let block_size = self.block_size() as u64;
+        let mut block = vec![0u8; block_size as usize];
+        let mut done = 0;
+        while done < data.len() {
+            let position = offset + done as u64;
+            let index = position / block_size;
+            let start = (position % block_size) as usize;
+            let take = (block.len() - start).min(data.len() - done);
+            if take < block.len() {
+                self.read_block(index, &mut block)?;
+            }
+            block[start..start + take].copy_from_slice(&data[done..done + take]);
+            self.write_block(index, &block)?;
+            done += take;
+        }
+        Ok(())
+    }
+}
+
+/// PCM stream format; samples are interleaved signed 16-bit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl AudioFormat {
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        AudioFormat { sample_rate, channels }
+    }
+
+    /// Number of frames covering `ms` milliseconds
+    pub fn frames_in(&self, ms: u64) -> usize {
+        (self.sample_rate as u64 * ms / 1000) as usize
+    }
+}
+
+/// Trait for audio output devices
+pub trait AudioDevice: Device {
+    /// Format the device plays
+    fn format(&self) -> AudioFormat;
+
+    /// Frames queued in the hardware buffer and not yet played
+    fn queued_frames(&self) -> usize;
+
+    /// Queue interleaved samples for playback, returning the frames accepted
+    fn write_frames(&mut self, samples: &[i16]) -> Result<usize, String>;
+}
+
+/// Condition reported by a printer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrinterState {
+    Ready,
+    OutOfPaper,
+    Offline,
+}
+
+/// Trait for printers
+pub trait PrinterDevice: Device {
+    /// Current condition of the printer
+    fn printer_state(&self) -> PrinterState;
+
+    /// Rated print speed
+    fn pages_per_minute(&self) -> u32;
+
+    /// Whether pages can be printed in color
+    fn supports_color(&self) -> bool;
+
+    /// Print one rendered page
+    fn print_page(&mut self, page: &[u8]) -> Result<(), String>;
+}
+
+/// Device power state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerState {
+    Active,
+    Suspended,
+}
+
+/// Trait for devices that can be suspended and resumed
+pub trait PowerManagedDevice: Device {
+    /// Current power state
+    fn power_state(&self) -> PowerState;
+
+    /// Save device state and enter a low-power state
+    fn suspend(&mut self) -> Result<(), String>;
+
+    /// Restore the device after a suspend
+    fn resume(&mut self) -> Result<(), String>;
 }
 
 /// Reference implementation of a basic device
 pub struct ReferenceDevice {
     info: DeviceInfo,
     initialized: bool,
+    suspended: bool,
 }
 
 impl ReferenceDevice {
@@ -122,6 +237,7 @@ impl ReferenceDevice {
                 version: "0.1.0".to_string(),
             },
             initialized: false,
+            suspended: false,
         }
     }
 }
@@ -145,6 +261,9 @@ impl Device for ReferenceDevice {
         if !self.initialized {
             return Err("Device not initialized".to_string());
         }
+        if self.suspended {
+            return Err("Device suspended".to_string());
+        }
         Ok(buffer.len())
     }
 
@@ -152,10 +271,36 @@ impl Device for ReferenceDevice {
         if !self.initialized {
             return Err("Device not initialized".to_string());
         }
+        if self.suspended {
+            return Err("Device suspended".to_string());
+        }
         Ok(data.len())
     }
 }
 
+impl PowerManagedDevice for ReferenceDevice {
+    fn power_state(&self) -> PowerState {
+        if self.suspended {
+            PowerState::Suspended
+        } else {
+            PowerState::Active
+        }
+    }
+
+    fn suspend(&mut self) -> Result<(), String> {
+        if !self.initialized {
+            return Err("Device not initialized".to_string());
+        }
+        self.suspended = true;
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<(), String> {
+        self.suspended = false;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -171,4 +316,18 @@ mod tests {
         
         assert!(device.shutdown().is_ok());
     }
+
+    #[test]
+    fn test_reference_device_power() {
+        let mut device = ReferenceDevice::new(DeviceType::Storage);
+        assert!(device.suspend().is_err());
+        device.init().unwrap();
+
+        device.suspend().unwrap();
+        assert_eq!(device.power_state(), PowerState::Suspended);
+        assert!(device.read(0, &mut [0u8; 4]).is_err());
+        device.resume().unwrap();
+        assert_eq!(device.power_state(), PowerState::Active);
+        assert!(device.read(0, &mut [0u8; 4]).is_ok());
+    }
 }
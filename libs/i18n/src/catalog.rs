@@ -0,0 +1,104 @@
+//! Message catalogs
+//!
+//! A catalog maps English source strings to their translation in one
+//! locale. Entries with plural forms map each plural category to a
+//! translation. Catalogs are JSON files:
+//!
+//! ```json
+//! {"locale": "de", "messages": {"Cancel": "Abbrechen",
+//!   "{count} file": {"one": "{count} Datei", "other": "{count} Dateien"}}}
+//! ```
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::locale::Locale;
+use crate::plural::PluralCategory;
+
+/// Translation of one source string
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Translation {
+    Text(String),
+    Plural(HashMap<PluralCategory, String>),
+}
+
+#[derive(Deserialize)]
+struct CatalogFile {
+    locale: String,
+    messages: HashMap<String, Translation>,
+}
+
+/// Translations for one locale
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Catalog {
+    pub locale: Locale,
+    messages: HashMap<String, Translation>,
+}
+
+impl Catalog {
+    pub fn new(locale: Locale) -> Self {
+        Catalog {
+            locale,
+            messages: HashMap::new(),
+        }
+    }
+
+    pub fn from_json(data: &[u8]) -> Result<Self, String> {
+        let file: CatalogFile = serde_json::from_slice(data).map_err(|e| format!("Invalid message catalog: {}", e))?;
+        Ok(Catalog {
+            locale: Locale::parse(&file.locale)?,
+            messages: file.messages,
+        })
+    }
+
+    pub fn insert(&mut self, source: &str, translation: Translation) {
+        self.messages.insert(source.to_string(), translation);
+    }
+
+    /// Take over every entry of `other`, replacing existing ones
+    pub fn merge(&mut self, other: Catalog) {
+        self.messages.extend(other.messages);
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Translation of `source`, picking the plural form for `category`
+    pub fn lookup(&self, source: &str, category: PluralCategory) -> Option<&str> {
+        match self.messages.get(source)? {
+            Translation::Text(text) => Some(text),
+            Translation::Plural(forms) => forms
+                .get(&category)
+                .or_else(|| forms.get(&PluralCategory::Other))
+                .map(String::as_str),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_lookup() {
+        let catalog = Catalog::from_json(
+            r#"{"locale":"ru","messages":{"Cancel":"Отмена",
+                "{count} file":{"one":"{count} файл","few":"{count} файла","many":"{count} файлов"}}}"#
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(catalog.locale.to_string(), "ru");
+        assert_eq!(catalog.lookup("Cancel", PluralCategory::Other), Some("Отмена"));
+        assert_eq!(catalog.lookup("{count} file", PluralCategory::Few), Some("{count} файла"));
+        // No "other" form to fall back on
+        assert_eq!(catalog.lookup("{count} file", PluralCategory::Other), None);
+        assert!(Catalog::from_json(br#"{"locale":"??","messages":{}}"#).is_err());
+    }
+}
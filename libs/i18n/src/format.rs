@@ -0,0 +1,118 @@
+//! Number and date formatting
+
+use crate::locale::Locale;
+
+/// Separators used when writing numbers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumberSymbols {
+    pub decimal: char,
+    pub group: char,
+}
+
+impl NumberSymbols {
+    pub fn for_locale(locale: &Locale) -> Self {
+        let (decimal, group) = match (locale.language.as_str(), locale.region.as_deref()) {
+            ("de", Some("CH")) => ('.', '\''),
+            ("de" | "es" | "it" | "pt" | "nl" | "id" | "tr" | "da", _) => (',', '.'),
+            ("fr" | "ru" | "uk" | "pl" | "cs" | "sk" | "sv" | "fi" | "nb", _) => (',', '\u{a0}'),
+            _ => ('.', ','),
+        };
+        NumberSymbols { decimal, group }
+    }
+}
+
+fn group_digits(digits: &str, group: char) -> String {
+    let mut grouped = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(group);
+        }
+        grouped.push(c);
+    }
+    grouped
+}
+
+/// Write an integer with grouped thousands
+pub fn format_integer(locale: &Locale, value: i64) -> String {
+    let symbols = NumberSymbols::for_locale(locale);
+    let sign = if value < 0 { "-" } else { "" };
+    format!("{}{}", sign, group_digits(&value.unsigned_abs().to_string(), symbols.group))
+}
+
+/// Write a number rounded to `decimals` places
+pub fn format_number(locale: &Locale, value: f64, decimals: usize) -> String {
+    let symbols = NumberSymbols::for_locale(locale);
+    let text = format!("{:.*}", decimals, value.abs());
+    let (whole, fraction) = text.split_once('.').unwrap_or((&text, ""));
+    let mut formatted = String::new();
+    if value < 0.0 && text.chars().any(|c| c.is_ascii_digit() && c != '0') {
+        formatted.push('-');
+    }
+    formatted.push_str(&group_digits(whole, symbols.group));
+    if !fraction.is_empty() {
+        formatted.push(symbols.decimal);
+        formatted.push_str(fraction);
+    }
+    formatted
+}
+
+/// Write a calendar date in the locale's short form
+pub fn format_date(locale: &Locale, year: i64, month: u32, day: u32) -> String {
+    match (locale.language.as_str(), locale.region.as_deref()) {
+        ("en", Some("US") | None) => format!("{}/{}/{}", month, day, year),
+        ("ja" | "zh", _) => format!("{}/{:02}/{:02}", year, month, day),
+        ("ko", _) => format!("{}. {}. {}.", year, month, day),
+        ("sv" | "lt", _) | ("en", Some("CA")) => format!("{}-{:02}-{:02}", year, month, day),
+        ("de" | "ru" | "uk" | "pl" | "cs" | "sk" | "fi" | "nb" | "tr", _) => {
+            format!("{:02}.{:02}.{}", day, month, year)
+        }
+        ("nl", _) => format!("{:02}-{:02}-{}", day, month, year),
+        _ => format!("{:02}/{:02}/{}", day, month, year),
+    }
+}
+
+/// Write a time of day on the locale's 12 or 24 hour clock
+pub fn format_time(locale: &Locale, hour: u32, minute: u32) -> String {
+    match (locale.language.as_str(), locale.region.as_deref()) {
+        ("en", Some("US" | "CA" | "AU") | None) => {
+            let suffix = if hour < 12 { "AM" } else { "PM" };
+            let hour = match hour % 12 {
+                0 => 12,
+                h => h,
+            };
+            format!("{}:{:02} {}", hour, minute, suffix)
+        }
+        _ => format!("{:02}:{:02}", hour, minute),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locale(name: &str) -> Locale {
+        Locale::parse(name).unwrap()
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(format_integer(&locale("en-US"), 1234567), "1,234,567");
+        assert_eq!(format_integer(&locale("de-DE"), -1234), "-1.234");
+        assert_eq!(format_integer(&locale("fr"), 999), "999");
+        assert_eq!(format_number(&locale("fr-FR"), 12345.678, 2), "12\u{a0}345,68");
+        assert_eq!(format_number(&locale("de-CH"), 1000.5, 1), "1'000.5");
+        assert_eq!(format_number(&locale("en"), -0.001, 2), "0.00");
+        assert_eq!(format_number(&locale("en"), 42.0, 0), "42");
+    }
+
+    #[test]
+    fn test_dates_and_times() {
+        assert_eq!(format_date(&locale("en-US"), 2024, 3, 9), "3/9/2024");
+        assert_eq!(format_date(&locale("en-GB"), 2024, 3, 9), "09/03/2024");
+        assert_eq!(format_date(&locale("de-AT"), 2024, 3, 9), "09.03.2024");
+        assert_eq!(format_date(&locale("ja"), 2024, 3, 9), "2024/03/09");
+        assert_eq!(format_time(&locale("en-US"), 0, 5), "12:05 AM");
+        assert_eq!(format_time(&locale("en-US"), 13, 30), "1:30 PM");
+        assert_eq!(format_time(&locale("fr-FR"), 13, 30), "13:30");
+    }
+}
@@ -0,0 +1,235 @@
+//! Internationalization for hairr OS
+//!
+//! Source strings are written in English and double as catalog keys, so a
+//! string without a translation is shown as written. Catalogs are loaded
+//! from JSON files in the VFS; lookups walk from the exact locale to its
+//! language before giving up. Programs subscribe to locale changes to
+//! redraw in the new language.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use filesystem::VirtualFileSystem;
+
+pub mod catalog;
+pub mod format;
+pub mod locale;
+pub mod plural;
+
+pub use catalog::{Catalog, Translation};
+pub use locale::Locale;
+pub use plural::{plural_category, PluralCategory};
+
+/// Directory the system message catalogs are installed in
+pub const LOCALE_DIR: &str = "/usr/share/locale";
+
+/// Locale used until the user picks one
+pub const DEFAULT_LOCALE: &str = "en-US";
+
+/// Callback run after the locale changes
+pub type LocaleListener = Arc<dyn Fn(&Locale) + Send + Sync>;
+
+/// Replace `{name}` placeholders with their values
+pub fn substitute(text: &str, args: &[(&str, &str)]) -> String {
+    let mut result = text.to_string();
+    for (name, value) in args {
+        result = result.replace(&format!("{{{}}}", name), value);
+    }
+    result
+}
+
+/// Translates and formats text for the current locale
+pub struct Localizer {
+    locale: Arc<Mutex<Locale>>,
+    catalogs: Arc<Mutex<HashMap<Locale, Catalog>>>,
+    listeners: Arc<Mutex<Vec<LocaleListener>>>,
+}
+
+impl Localizer {
+    pub fn new() -> Self {
+        Localizer {
+            locale: Arc::new(Mutex::new(Locale::parse(DEFAULT_LOCALE).unwrap())),
+            catalogs: Arc::new(Mutex::new(HashMap::new())),
+            listeners: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn locale(&self) -> Locale {
+        self.locale.lock().unwrap().clone()
+    }
+
+    /// Switch language, notifying listeners if it changed
+    pub fn set_locale(&self, locale: Locale) {
+        {
+            let mut current = self.locale.lock().unwrap();
+            if *current == locale {
+                return;
+            }
+            *current = locale.clone();
+        }
+        let listeners: Vec<LocaleListener> = self.listeners.lock().unwrap().clone();
+        for listener in listeners {
+            listener(&locale);
+        }
+    }
+
+    /// Run `listener` after every locale change
+    pub fn on_change(&self, listener: LocaleListener) {
+        self.listeners.lock().unwrap().push(listener);
+    }
+
+    /// Add translations, merging with any catalog already held for the locale
+    pub fn add_catalog(&self, catalog: Catalog) {
+        let mut catalogs = self.catalogs.lock().unwrap();
+        match catalogs.get_mut(&catalog.locale) {
+            Some(existing) => existing.merge(catalog),
+            None => {
+                catalogs.insert(catalog.locale.clone(), catalog);
+            }
+        }
+    }
+
+    /// Load one catalog file, returning its locale
+    pub fn load_catalog(&self, vfs: &VirtualFileSystem, path: &Path) -> Result<Locale, String> {
+        let catalog = Catalog::from_json(&vfs.read_file(path)?)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let locale = catalog.locale.clone();
+        self.add_catalog(catalog);
+        Ok(locale)
+    }
+
+    /// Load every `.json` catalog in a directory, returning how many were read
+    pub fn load_directory(&self, vfs: &VirtualFileSystem, dir: &Path) -> Result<usize, String> {
+        let mut loaded = 0;
+        for path in vfs.list_directory(dir)? {
+            if path.extension().is_some_and(|ext| ext == "json") {
+                self.load_catalog(vfs, &path)?;
+                loaded += 1;
+            }
+        }
+        Ok(loaded)
+    }
+
+    /// Locales with a catalog, sorted
+    pub fn available_locales(&self) -> Vec<Locale> {
+        let mut locales: Vec<Locale> = self.catalogs.lock().unwrap().keys().cloned().collect();
+        locales.sort_by_key(|locale| locale.to_string());
+        locales
+    }
+
+    fn lookup(&self, source: &str, category: PluralCategory) -> Option<String> {
+        let catalogs = self.catalogs.lock().unwrap();
+        self.locale()
+            .fallbacks()
+            .iter()
+            .find_map(|locale| catalogs.get(locale)?.lookup(source, category).map(str::to_string))
+    }
+
+    /// Translate a source string
+    pub fn tr(&self, source: &str) -> String {
+        self.tr_args(source, &[])
+    }
+
+    /// Translate a source string and fill in its placeholders
+    pub fn tr_args(&self, source: &str, args: &[(&str, &str)]) -> String {
+        let text = self.lookup(source, PluralCategory::Other).unwrap_or_else(|| source.to_string());
+        substitute(&text, args)
+    }
+
+    /// Translate a count-dependent string; `{count}` becomes the formatted count
+    pub fn trn(&self, singular: &str, plural: &str, count: u64, args: &[(&str, &str)]) -> String {
+        let locale = self.locale();
+        let category = plural_category(&locale.language, count);
+        let text = self.lookup(singular, category).unwrap_or_else(|| {
+            let english = if count == 1 { singular } else { plural };
+            english.to_string()
+        });
+        let count = format::format_integer(&locale, count.min(i64::MAX as u64) as i64);
+        let mut args = args.to_vec();
+        args.push(("count", &count));
+        substitute(&text, &args)
+    }
+
+    pub fn format_integer(&self, value: i64) -> String {
+        format::format_integer(&self.locale(), value)
+    }
+
+    pub fn format_number(&self, value: f64, decimals: usize) -> String {
+        format::format_number(&self.locale(), value, decimals)
+    }
+
+    pub fn format_date(&self, year: i64, month: u32, day: u32) -> String {
+        format::format_date(&self.locale(), year, month, day)
+    }
+
+    pub fn format_time(&self, hour: u32, minute: u32) -> String {
+        format::format_time(&self.locale(), hour, minute)
+    }
+}
+
+impl Default for Localizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use filesystem::{OpenOptions, ProcessId};
+
+    fn install(vfs: &VirtualFileSystem, name: &str, data: &str) {
+        let path = Path::new(LOCALE_DIR).join(name);
+        let handle = vfs.open(ProcessId::KERNEL, &path, OpenOptions::write_only()).unwrap();
+        vfs.write(ProcessId::KERNEL, handle, data.as_bytes()).unwrap();
+        vfs.close(ProcessId::KERNEL, handle).unwrap();
+    }
+
+    fn localizer_with_catalogs() -> Localizer {
+        let vfs = VirtualFileSystem::new();
+        for dir in ["/usr", "/usr/share", LOCALE_DIR] {
+            vfs.create_directory(Path::new(dir)).unwrap();
+        }
+        install(
+            &vfs,
+            "de.json",
+            r#"{"locale":"de","messages":{"Cancel":"Abbrechen","Hello, {name}":"Hallo, {name}",
+                "{count} package":{"one":"{count} Paket","other":"{count} Pakete"}}}"#,
+        );
+        install(&vfs, "de-AT.json", r#"{"locale":"de-AT","messages":{"Cancel":"Abbrechen!"}}"#);
+        install(&vfs, "README", "not a catalog");
+        let localizer = Localizer::new();
+        assert_eq!(localizer.load_directory(&vfs, Path::new(LOCALE_DIR)).unwrap(), 2);
+        localizer
+    }
+
+    #[test]
+    fn test_translation_fallbacks() {
+        let localizer = localizer_with_catalogs();
+        assert_eq!(localizer.tr("Cancel"), "Cancel");
+        assert_eq!(localizer.trn("{count} package", "{count} packages", 1200, &[]), "1,200 packages");
+
+        localizer.set_locale(Locale::parse("de-AT").unwrap());
+        assert_eq!(localizer.tr("Cancel"), "Abbrechen!");
+        assert_eq!(localizer.tr_args("Hello, {name}", &[("name", "Ana")]), "Hallo, Ana");
+        assert_eq!(localizer.trn("{count} package", "{count} packages", 1, &[]), "1 Paket");
+        assert_eq!(localizer.trn("{count} package", "{count} packages", 1200, &[]), "1.200 Pakete");
+        assert_eq!(localizer.tr("Untranslated"), "Untranslated");
+        assert_eq!(localizer.format_date(2024, 12, 31), "31.12.2024");
+        let names: Vec<String> = localizer.available_locales().iter().map(|l| l.to_string()).collect();
+        assert_eq!(names, vec!["de", "de-AT"]);
+    }
+
+    #[test]
+    fn test_locale_change_event() {
+        let localizer = Localizer::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        localizer.on_change(Arc::new(move |locale| log.lock().unwrap().push(locale.to_string())));
+        localizer.set_locale(Locale::parse("fr-FR").unwrap());
+        localizer.set_locale(Locale::parse("fr_FR").unwrap());
+        localizer.set_locale(Locale::parse("en-US").unwrap());
+        assert_eq!(*seen.lock().unwrap(), vec!["fr-FR", "en-US"]);
+    }
+}
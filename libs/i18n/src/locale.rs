@@ -0,0 +1,84 @@
+//! Locale identifiers
+
+use std::fmt;
+
+/// Language with an optional region, written `en-US`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Locale {
+    /// Lowercase ISO 639 code
+    pub language: String,
+    /// Uppercase ISO 3166 code or UN M.49 number
+    pub region: Option<String>,
+}
+
+impl Locale {
+    pub fn new(language: &str, region: Option<&str>) -> Self {
+        Locale {
+            language: language.to_lowercase(),
+            region: region.map(str::to_uppercase),
+        }
+    }
+
+    /// Parse `en-US`, `pt_BR` or a POSIX name such as `de_DE.UTF-8`
+    pub fn parse(name: &str) -> Result<Self, String> {
+        let base = name.split(['.', '@']).next().unwrap_or_default();
+        let mut parts = base.split(['-', '_']);
+        let language = parts.next().unwrap_or_default();
+        if !(2..=3).contains(&language.len()) || !language.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err(format!("Invalid locale {:?}", name));
+        }
+        let region = parts.next();
+        if let Some(region) = region {
+            let letters = region.len() == 2 && region.chars().all(|c| c.is_ascii_alphabetic());
+            let digits = region.len() == 3 && region.chars().all(|c| c.is_ascii_digit());
+            if !letters && !digits {
+                return Err(format!("Invalid region in locale {:?}", name));
+            }
+        }
+        if parts.next().is_some() {
+            return Err(format!("Invalid locale {:?}", name));
+        }
+        Ok(Locale::new(language, region))
+    }
+
+    /// Locales to look messages up in, most specific first
+    pub fn fallbacks(&self) -> Vec<Locale> {
+        let mut chain = vec![self.clone()];
+        if self.region.is_some() {
+            chain.push(Locale::new(&self.language, None));
+        }
+        chain
+    }
+}
+
+impl fmt::Display for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.region {
+            Some(region) => write!(f, "{}-{}", self.language, region),
+            None => write!(f, "{}", self.language),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_forms() {
+        assert_eq!(Locale::parse("en-US").unwrap(), Locale::new("en", Some("US")));
+        assert_eq!(Locale::parse("pt_br").unwrap().to_string(), "pt-BR");
+        assert_eq!(Locale::parse("de_DE.UTF-8").unwrap().to_string(), "de-DE");
+        assert_eq!(Locale::parse("es-419").unwrap().region.as_deref(), Some("419"));
+        assert_eq!(Locale::parse("fr").unwrap().region, None);
+        for bad in ["", "e", "english", "en-USA", "en-US-x"] {
+            assert!(Locale::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_fallbacks() {
+        let chain: Vec<String> = Locale::parse("pt-BR").unwrap().fallbacks().iter().map(|l| l.to_string()).collect();
+        assert_eq!(chain, vec!["pt-BR", "pt"]);
+    }
+}
@@ -0,0 +1,79 @@
+//! Plural rules
+//!
+//! Integer plural categories following the CLDR rules for the languages
+//! hairr OS ships translations for. Unknown languages use the English rule.
+
+use serde::{Deserialize, Serialize};
+
+/// CLDR plural category
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PluralCategory {
+    Zero,
+    One,
+    Two,
+    Few,
+    Many,
+    Other,
+}
+
+/// Category of `n` in `language`
+pub fn plural_category(language: &str, n: u64) -> PluralCategory {
+    let (last, last_two) = (n % 10, n % 100);
+    let few = (2..=4).contains(&last) && !(12..=14).contains(&last_two);
+    match language {
+        "ja" | "zh" | "ko" | "vi" | "th" | "id" | "ms" => PluralCategory::Other,
+        "fr" | "pt" if n <= 1 => PluralCategory::One,
+        "fr" | "pt" => PluralCategory::Other,
+        "ru" | "uk" | "be" | "sr" | "hr" | "bs" => {
+            if last == 1 && last_two != 11 {
+                PluralCategory::One
+            } else if few {
+                PluralCategory::Few
+            } else {
+                PluralCategory::Many
+            }
+        }
+        "pl" => match n {
+            1 => PluralCategory::One,
+            _ if few => PluralCategory::Few,
+            _ => PluralCategory::Many,
+        },
+        "cs" | "sk" => match n {
+            1 => PluralCategory::One,
+            2..=4 => PluralCategory::Few,
+            _ => PluralCategory::Other,
+        },
+        "ar" => match (n, last_two) {
+            (0, _) => PluralCategory::Zero,
+            (1, _) => PluralCategory::One,
+            (2, _) => PluralCategory::Two,
+            (_, 3..=10) => PluralCategory::Few,
+            (_, 11..=99) => PluralCategory::Many,
+            _ => PluralCategory::Other,
+        },
+        _ if n == 1 => PluralCategory::One,
+        _ => PluralCategory::Other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_language_rules() {
+        use PluralCategory::*;
+
+        let categories = |language: &str, numbers: &[u64]| -> Vec<PluralCategory> {
+            numbers.iter().map(|n| plural_category(language, *n)).collect()
+        };
+        assert_eq!(categories("en", &[0, 1, 2]), vec![Other, One, Other]);
+        assert_eq!(categories("fr", &[0, 1, 2]), vec![One, One, Other]);
+        assert_eq!(categories("ru", &[1, 3, 5, 11, 21, 22, 112]), vec![One, Few, Many, Many, One, Few, Many]);
+        assert_eq!(categories("pl", &[1, 2, 5, 21, 24]), vec![One, Few, Many, Many, Few]);
+        assert_eq!(categories("cs", &[1, 3, 5]), vec![One, Few, Other]);
+        assert_eq!(categories("ar", &[0, 1, 2, 3, 11, 100]), vec![Zero, One, Two, Few, Many, Other]);
+        assert_eq!(categories("ja", &[1]), vec![Other]);
+    }
+}
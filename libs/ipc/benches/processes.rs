@@ -0,0 +1,54 @@
+//! Kernel and IPC throughput with many simulated processes running at once
+//!
+//! Each thread plays one process at a time: it is created, opens a channel,
+//! exchanges messages over it and terminates, which closes the channel.
+//! Run with `cargo bench -p ipc`.
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use ipc::{IPCManager, Message, OrphanPolicy};
+use kernel::{Kernel, Priority};
+
+const PROCESSES: u64 = 4_096;
+const MESSAGES: u64 = 32;
+
+/// Messages per second across `threads` threads sharing `PROCESSES`
+/// process lifetimes
+fn throughput(threads: u64) -> f64 {
+    let kernel = Arc::new(Kernel::new());
+    let ipc = Arc::new(IPCManager::new());
+    ipc.attach_kernel(&kernel);
+
+    let started = Instant::now();
+    let workers: Vec<_> = (0..threads)
+        .map(|_| {
+            let kernel = Arc::clone(&kernel);
+            let ipc = Arc::clone(&ipc);
+            thread::spawn(move || {
+                for _ in 0..PROCESSES / threads {
+                    let process = kernel.create_process("worker".to_string(), Priority::Normal);
+                    let channel = ipc.create_owned_channel(process, OrphanPolicy::Close);
+                    for i in 0..MESSAGES {
+                        ipc.send_message(channel, Message::Request { id: i, data: Vec::new() }).unwrap();
+                        ipc.receive_message(channel).unwrap();
+                    }
+                    kernel.terminate_process(process).unwrap();
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    assert!(ipc.channels_of(kernel::ProcessId::new(1)).is_empty());
+    (PROCESSES / threads * threads * MESSAGES) as f64 / started.elapsed().as_secs_f64()
+}
+
+fn main() {
+    println!("{:>8} {:>16}", "threads", "messages/s");
+    for threads in [1, 2, 4, 8, 16, 64] {
+        println!("{:>8} {:>16.0}", threads, throughput(threads));
+    }
+}
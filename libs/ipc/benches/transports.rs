@@ -0,0 +1,145 @@
+//! Throughput and latency of IPC transports for small and large payloads
+//!
+//! Compares the three transports `ipc` provides: a plain channel, a
+//! bounded channel, which pushes back on the sender when full, and a
+//! `SharedRing` of preallocated slots that the sender copies into and the
+//! receiver reads in place. Throughput has a sender and a receiver thread;
+//! latency is one send and receive on a single thread, so it measures the
+//! transport rather than the scheduler.
+//! Run with `cargo bench -p ipc --bench transports`.
+
+use std::hint::black_box;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ipc::{ChannelId, IPCManager, IpcError, Message, SharedRing};
+
+const QUEUE_CAPACITY: usize = 256;
+const LATENCY_SAMPLES: usize = 10_000;
+
+/// Payload size and how many messages to send for throughput
+const PAYLOADS: &[(usize, usize)] = &[(64, 200_000), (64 << 10, 2_000)];
+
+trait Transport: Send + Sync + 'static {
+    /// Queue `payload`; false if the transport is full
+    fn send(&self, payload: &[u8]) -> bool;
+    /// Hand the next payload to `read`; false if there was none
+    fn receive(&self, read: &mut dyn FnMut(&[u8])) -> bool;
+}
+
+struct Channel {
+    ipc: IPCManager,
+    channel: ChannelId,
+}
+
+impl Transport for Channel {
+    fn send(&self, payload: &[u8]) -> bool {
+        match self.ipc.send_message(self.channel, Message::Binary(payload.to_vec())) {
+            Ok(()) => true,
+            Err(IpcError::ChannelFull) => false,
+            Err(e) => panic!("send failed: {}", e),
+        }
+    }
+
+    fn receive(&self, read: &mut dyn FnMut(&[u8])) -> bool {
+        match self.ipc.receive_message(self.channel).unwrap() {
+            Some(Message::Binary(data)) => {
+                read(&data);
+                true
+            }
+            Some(other) => panic!("unexpected message: {:?}", other),
+            None => false,
+        }
+    }
+}
+
+impl Transport for SharedRing {
+    fn send(&self, payload: &[u8]) -> bool {
+        match SharedRing::send(self, payload) {
+            Ok(()) => true,
+            Err(IpcError::ChannelFull) => false,
+            Err(e) => panic!("send failed: {}", e),
+        }
+    }
+
+    fn receive(&self, read: &mut dyn FnMut(&[u8])) -> bool {
+        SharedRing::receive(self, read)
+    }
+}
+
+fn transports(payload: usize) -> Vec<(&'static str, Arc<dyn Transport>)> {
+    let ipc = IPCManager::new();
+    let channel = ipc.create_channel();
+    let bounded = IPCManager::new();
+    let bounded_channel = bounded.create_bounded_channel(QUEUE_CAPACITY);
+    vec![
+        ("channel", Arc::new(Channel { ipc, channel })),
+        ("bounded queue", Arc::new(Channel { ipc: bounded, channel: bounded_channel })),
+        ("shared memory", Arc::new(SharedRing::new(QUEUE_CAPACITY, payload))),
+    ]
+}
+
+/// Look at the payload the way a receiver would, so reading it is not
+/// optimised away
+fn consume(data: &[u8]) {
+    black_box(data.first().copied().unwrap_or(0) ^ data.last().copied().unwrap_or(0));
+}
+
+/// Messages per second from a sender thread to a receiver thread
+fn throughput(transport: &Arc<dyn Transport>, payload: &[u8], messages: usize) -> f64 {
+    let started = Instant::now();
+    let receiver = {
+        let transport = Arc::clone(transport);
+        thread::spawn(move || {
+            let mut received = 0;
+            while received < messages {
+                if transport.receive(&mut consume) {
+                    received += 1;
+                } else {
+                    thread::yield_now();
+                }
+            }
+        })
+    };
+    for _ in 0..messages {
+        while !transport.send(payload) {
+            thread::yield_now();
+        }
+    }
+    receiver.join().unwrap();
+    messages as f64 / started.elapsed().as_secs_f64()
+}
+
+/// Median and 99th percentile time to send a message and receive it
+fn latency(transport: &Arc<dyn Transport>, payload: &[u8]) -> (Duration, Duration) {
+    let mut samples: Vec<Duration> = (0..LATENCY_SAMPLES)
+        .map(|_| {
+            let started = Instant::now();
+            assert!(transport.send(payload));
+            assert!(transport.receive(&mut consume));
+            started.elapsed()
+        })
+        .collect();
+    samples.sort();
+    (samples[LATENCY_SAMPLES / 2], samples[LATENCY_SAMPLES * 99 / 100])
+}
+
+fn main() {
+    println!("{:<14} {:>8} {:>14} {:>10} {:>10}", "transport", "payload", "messages/s", "p50 us", "p99 us");
+    for &(size, messages) in PAYLOADS {
+        let payload: Vec<u8> = (0..size).map(|i| i as u8).collect();
+        for (name, transport) in transports(size) {
+            let rate = throughput(&transport, &payload, messages);
+            let (p50, p99) = latency(&transport, &payload);
+            println!(
+                "{:<14} {:>8} {:>14.0} {:>10.2} {:>10.2}",
+                name,
+                size,
+                rate,
+                p50.as_secs_f64() * 1e6,
+                p99.as_secs_f64() * 1e6
+            );
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! IPC errors
+
+use core::fmt;
+
+use sync::prelude::*;
+#[cfg(feature = "std")]
+use system_utils::error::ErrorCode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpcError {
+    ChannelNotFound,
+    /// Only one process can be at the other end
+    PeerAlreadyConnected,
+    /// The terminal was hung up or one of its channels is gone
+    PtyClosed,
+    /// The channel was closed; its id may since name another one
+    StaleChannel,
+    /// A bounded channel or ring has no room until the receiver catches up
+    ChannelFull,
+    /// The payload is larger than a ring slot
+    MessageTooLarge,
+}
+
+impl fmt::Display for IpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpcError::ChannelNotFound => write!(f, "Channel not found"),
+            IpcError::PeerAlreadyConnected => write!(f, "Channel already has a peer"),
+            IpcError::PtyClosed => write!(f, "PTY closed"),
+            IpcError::StaleChannel => write!(f, "Stale channel id"),
+            IpcError::ChannelFull => write!(f, "Channel is full"),
+            IpcError::MessageTooLarge => write!(f, "Message too large"),
+        }
+    }
+}
+
+impl core::error::Error for IpcError {}
+
+#[cfg(feature = "std")]
+impl ErrorCode for IpcError {
+    fn code(&self) -> u32 {
+        match self {
+            IpcError::ChannelNotFound => 201,
+            IpcError::PeerAlreadyConnected => 202,
+            IpcError::PtyClosed => 203,
+            IpcError::StaleChannel => 204,
+            IpcError::ChannelFull => 205,
+            IpcError::MessageTooLarge => 206,
+        }
+    }
+
+    fn component(&self) -> &'static str {
+        "ipc"
+    }
+}
+
+impl From<IpcError> for String {
+    fn from(error: IpcError) -> Self {
+        error.to_string()
+    }
+}
@@ -2,18 +2,57 @@
 //! 
 //! Provides high-performance, capability-aware IPC mechanisms for communication
 //! between userspace processes and services.
+//!
+//! A channel may be owned by the process that created it and connected to
+//! a peer process. When either terminates, the other end is told through
+//! `PeerClosed`; an owner's channels close with it unless they pass to the
+//! peer.
+//!
+//! Operations fail with an `IpcError`, whose code identifies the failure.
+//!
+//! Builds as `no_std`, alongside the kernel, when the default `std` feature
+//! is turned off.
 
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+#![cfg_attr(not(feature = "std"), no_std)]
 
-/// Unique identifier for IPC channels
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+use kernel::{Kernel, ProcessId};
+use metrics::{Counter, Gauge, MetricsRegistry};
+use sync::prelude::*;
+use sync::{ids, Arc, IdAllocator, Mutex, ShardedMap, VecDeque};
+
+pub mod error;
+pub mod pty;
+pub mod ring;
+#[cfg(feature = "std")]
+pub mod service;
+
+pub use error::IpcError;
+pub use pty::{open_pty, PtyMaster, PtySlave, Termios, WindowSize};
+pub use ring::SharedRing;
+#[cfg(feature = "std")]
+pub use service::{JsonService, ERROR_BAD_REQUEST};
+
+/// Unique identifier for IPC channels, generational so that a closed
+/// channel's id never reaches the channel that reuses its slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct ChannelId(u64);
 
 impl ChannelId {
     pub fn new(id: u64) -> Self {
         ChannelId(id)
     }
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+
+    pub fn index(&self) -> u32 {
+        ids::index(self.0)
+    }
+
+    pub fn generation(&self) -> u32 {
+        ids::generation(self.0)
+    }
 }
 
 /// Message types that can be sent through IPC
@@ -26,18 +65,50 @@ pub enum Message {
     Error { code: u32, message: String },
 }
 
+/// What happens to a channel when its owner terminates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OrphanPolicy {
+    #[default]
+    Close,
+    /// The peer becomes the owner; without one the channel closes
+    TransferToPeer,
+}
+
+/// The process at one end of a channel is gone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerClosed {
+    pub channel: ChannelId,
+    /// Process at the end that is left
+    pub process: ProcessId,
+    /// Whether the channel is still open, owned by `process` if it was the
+    /// peer
+    pub open: bool,
+}
+
+/// Callback told when a channel loses one of its ends
+pub type PeerClosedListener = Arc<dyn Fn(&PeerClosed) + Send + Sync>;
+
 /// Represents an IPC channel endpoint
 #[derive(Debug)]
 pub struct Channel {
     id: ChannelId,
-    messages: Arc<Mutex<Vec<Message>>>,
+    messages: Arc<Mutex<VecDeque<Message>>>,
+    owner: Option<ProcessId>,
+    peer: Option<ProcessId>,
+    orphan_policy: OrphanPolicy,
+    /// Most messages that can wait at once; unbounded if `None`
+    capacity: Option<usize>,
 }
 
 impl Channel {
     pub fn new(id: ChannelId) -> Self {
         Channel {
             id,
-            messages: Arc::new(Mutex::new(Vec::new())),
+            messages: Arc::new(Mutex::new(VecDeque::new())),
+            owner: None,
+            peer: None,
+            orphan_policy: OrphanPolicy::default(),
+            capacity: None,
         }
     }
 
@@ -45,76 +116,270 @@ impl Channel {
         self.id
     }
 
-    /// Send a message through this channel
-    pub fn send(&self, message: Message) -> Result<(), String> {
-        self.messages.lock().unwrap().push(message);
+    /// Process that created the channel, if a process did
+    pub fn owner(&self) -> Option<ProcessId> {
+        self.owner
+    }
+
+    /// Process connected to the other end
+    pub fn peer(&self) -> Option<ProcessId> {
+        self.peer
+    }
+
+    /// Send a message through this channel; a bounded channel refuses it
+    /// while full
+    pub fn send(&self, message: Message) -> Result<(), IpcError> {
+        let mut messages = self.messages.lock().unwrap();
+        if self.capacity.is_some_and(|capacity| messages.len() >= capacity) {
+            return Err(IpcError::ChannelFull);
+        }
+        messages.push_back(message);
         Ok(())
     }
 
-    /// Receive the next message from this channel
+    /// Receive the oldest pending message from this channel
     pub fn receive(&self) -> Option<Message> {
-        self.messages.lock().unwrap().pop()
+        self.messages.lock().unwrap().pop_front()
     }
 
     /// Check if there are pending messages
     pub fn has_messages(&self) -> bool {
         !self.messages.lock().unwrap().is_empty()
     }
+
+    /// Number of messages waiting to be received
+    pub fn pending(&self) -> usize {
+        self.messages.lock().unwrap().len()
+    }
+}
+
+struct IpcMetrics {
+    sent: Counter,
+    received: Counter,
+    failed: Counter,
+    channels: Gauge,
 }
 
 /// The IPC manager handles channel creation and routing
 pub struct IPCManager {
-    channels: Arc<Mutex<HashMap<ChannelId, Channel>>>,
-    next_channel_id: Arc<Mutex<u64>>,
+    /// Sharded by channel id so traffic on different channels does not
+    /// contend
+    channels: ShardedMap<ChannelId, Channel>,
+    channel_ids: IdAllocator,
+    metrics: Arc<Mutex<Option<IpcMetrics>>>,
+    peer_closed_listeners: Arc<Mutex<Vec<PeerClosedListener>>>,
 }
 
 impl IPCManager {
     pub fn new() -> Self {
         IPCManager {
-            channels: Arc::new(Mutex::new(HashMap::new())),
-            next_channel_id: Arc::new(Mutex::new(1)),
+            channels: ShardedMap::new(),
+            channel_ids: IdAllocator::new(),
+            metrics: Arc::new(Mutex::new(None)),
+            peer_closed_listeners: Arc::new(Mutex::new(Vec::new())),
         }
     }
 
+    /// Release a process's channels when the kernel terminates it
+    pub fn attach_kernel(self: &Arc<Self>, kernel: &Kernel) {
+        let manager = Arc::downgrade(self);
+        kernel.on_process_terminated(Arc::new(move |id| {
+            if let Some(manager) = manager.upgrade() {
+                manager.release_process(id);
+            }
+        }));
+    }
+
+    /// Report message traffic and channel counts to `registry`
+    pub fn attach_metrics(&self, registry: &MetricsRegistry) -> Result<(), String> {
+        let metrics = IpcMetrics {
+            sent: registry.counter("ipc_messages_sent_total", "Messages delivered to a channel")?,
+            received: registry.counter("ipc_messages_received_total", "Messages taken off a channel")?,
+            failed: registry.counter("ipc_send_failures_total", "Sends to a missing channel")?,
+            channels: registry.gauge("ipc_channels", "Open channels")?,
+        };
+        metrics.channels.set(self.channels.len() as f64);
+        *self.metrics.lock().unwrap() = Some(metrics);
+        Ok(())
+    }
+
+    fn update_channel_gauge(&self) {
+        if let Some(metrics) = self.metrics.lock().unwrap().as_ref() {
+            metrics.channels.set(self.channels.len() as f64);
+        }
+    }
+
+    fn insert_channel(&self, channel: impl FnOnce(ChannelId) -> Channel) -> ChannelId {
+        let channel_id = ChannelId(self.channel_ids.allocate());
+        self.channels.insert(channel_id, channel(channel_id));
+        self.update_channel_gauge();
+        channel_id
+    }
+
     /// Create a new IPC channel
     pub fn create_channel(&self) -> ChannelId {
-        let mut next_id = self.next_channel_id.lock().unwrap();
-        let channel_id = ChannelId(*next_id);
-        *next_id += 1;
+        self.insert_channel(Channel::new)
+    }
 
-        let channel = Channel::new(channel_id);
-        self.channels.lock().unwrap().insert(channel_id, channel);
-        channel_id
+    /// Create a channel holding at most `capacity` messages, so a sender
+    /// that gets ahead of the receiver is pushed back instead of queueing
+    /// without limit
+    pub fn create_bounded_channel(&self, capacity: usize) -> ChannelId {
+        self.insert_channel(|id| Channel {
+            messages: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity: Some(capacity),
+            ..Channel::new(id)
+        })
+    }
+
+    /// Create a channel owned by `owner`, which is cleaned up according to
+    /// `orphan_policy` when the owner terminates
+    pub fn create_owned_channel(&self, owner: ProcessId, orphan_policy: OrphanPolicy) -> ChannelId {
+        self.insert_channel(|id| Channel {
+            owner: Some(owner),
+            orphan_policy,
+            ..Channel::new(id)
+        })
+    }
+
+    /// Connect `peer` to the other end of a channel
+    pub fn connect(&self, id: ChannelId, peer: ProcessId) -> Result<(), IpcError> {
+        self.channels
+            .with_mut(&id, |channel| match channel.peer {
+                Some(_) => Err(IpcError::PeerAlreadyConnected),
+                None => {
+                    channel.peer = Some(peer);
+                    Ok(())
+                }
+            })
+            .ok_or_else(|| self.missing(id))?
+    }
+
+    /// Every open channel
+    pub fn list_channels(&self) -> Vec<ChannelId> {
+        let mut ids = self.channels.filter_map(|c| Some(c.id));
+        ids.sort_by_key(|id| id.0);
+        ids
+    }
+
+    /// Channels a process owns or is the peer of
+    pub fn channels_of(&self, process: ProcessId) -> Vec<ChannelId> {
+        let mut ids = self
+            .channels
+            .filter_map(|c| (c.owner == Some(process) || c.peer == Some(process)).then_some(c.id));
+        ids.sort_by_key(|id| id.0);
+        ids
+    }
+
+    /// Register a callback to run whenever a channel loses one of its ends
+    pub fn on_peer_closed(&self, listener: PeerClosedListener) {
+        self.peer_closed_listeners.lock().unwrap().push(listener);
+    }
+
+    fn notify_peer_closed(&self, events: Vec<PeerClosed>) {
+        // Listeners may use the manager, so run them without any lock held
+        let listeners = self.peer_closed_listeners.lock().unwrap().clone();
+        for event in &events {
+            for listener in &listeners {
+                listener(event);
+            }
+        }
     }
 
     /// Get a reference to a channel
     pub fn get_channel(&self, id: ChannelId) -> Option<Channel> {
-        self.channels.lock().unwrap().get(&id).map(|c| Channel {
+        self.channels.with(&id, |c| Channel {
             id: c.id,
             messages: Arc::clone(&c.messages),
+            owner: c.owner,
+            peer: c.peer,
+            orphan_policy: c.orphan_policy,
+            capacity: c.capacity,
         })
     }
 
-    /// Close a channel
+    /// Close a channel, telling its peer
     pub fn close_channel(&self, id: ChannelId) -> bool {
-        self.channels.lock().unwrap().remove(&id).is_some()
+        let removed = self.channels.remove(&id);
+        self.update_channel_gauge();
+        let Some(channel) = removed else {
+            return false;
+        };
+        self.channel_ids.release(id.value());
+        if let Some(peer) = channel.peer {
+            self.notify_peer_closed(vec![PeerClosed { channel: id, process: peer, open: false }]);
+        }
+        true
+    }
+
+    /// Clean up after a terminated process. Channels it owns close, or pass
+    /// to their peer if their policy says so; channels it was the peer of
+    /// stay open for their owner. The other end is told either way.
+    pub fn release_process(&self, process: ProcessId) {
+        let mut events = Vec::new();
+        let mut closed = Vec::new();
+        self.channels.retain(|_, channel| {
+            if channel.owner == Some(process) {
+                match (channel.orphan_policy, channel.peer.take()) {
+                    // A process connected to its own channel leaves nobody
+                    (OrphanPolicy::TransferToPeer, Some(peer)) if peer != process => {
+                        channel.owner = Some(peer);
+                        events.push(PeerClosed { channel: channel.id, process: peer, open: true });
+                    }
+                    (_, peer) => {
+                        let peer = peer.filter(|&peer| peer != process);
+                        events.extend(peer.map(|peer| PeerClosed { channel: channel.id, process: peer, open: false }));
+                        closed.push(channel.id);
+                        return false;
+                    }
+                }
+            } else if channel.peer == Some(process) {
+                channel.peer = None;
+                events.extend(channel.owner.map(|owner| PeerClosed { channel: channel.id, process: owner, open: true }));
+            }
+            true
+        });
+        for id in closed {
+            self.channel_ids.release(id.value());
+        }
+        self.update_channel_gauge();
+        events.sort_by_key(|e| e.channel.0);
+        self.notify_peer_closed(events);
     }
 
     /// Send a message to a specific channel
-    pub fn send_message(&self, channel_id: ChannelId, message: Message) -> Result<(), String> {
-        if let Some(channel) = self.get_channel(channel_id) {
-            channel.send(message)
-        } else {
-            Err("Channel not found".to_string())
+    pub fn send_message(&self, channel_id: ChannelId, message: Message) -> Result<(), IpcError> {
+        let result = match self.get_channel(channel_id) {
+            Some(channel) => channel.send(message),
+            None => Err(self.missing(channel_id)),
+        };
+        if let Some(metrics) = self.metrics.lock().unwrap().as_ref() {
+            match result {
+                Ok(()) => metrics.sent.inc(),
+                Err(_) => metrics.failed.inc(),
+            }
         }
+        result
     }
 
     /// Receive a message from a specific channel
-    pub fn receive_message(&self, channel_id: ChannelId) -> Result<Option<Message>, String> {
-        if let Some(channel) = self.get_channel(channel_id) {
-            Ok(channel.receive())
+    pub fn receive_message(&self, channel_id: ChannelId) -> Result<Option<Message>, IpcError> {
+        let channel = self.get_channel(channel_id).ok_or_else(|| self.missing(channel_id))?;
+        let message = channel.receive();
+        if message.is_some() {
+            if let Some(metrics) = self.metrics.lock().unwrap().as_ref() {
+                metrics.received.inc();
+            }
+        }
+        Ok(message)
+    }
+
+    /// Why `id` names no open channel
+    fn missing(&self, id: ChannelId) -> IpcError {
+        if self.channel_ids.is_stale(id.value()) {
+            IpcError::StaleChannel
         } else {
-            Err("Channel not found".to_string())
+            IpcError::ChannelNotFound
         }
     }
 }
@@ -134,6 +399,7 @@ mod tests {
         let manager = IPCManager::new();
         let channel_id = manager.create_channel();
         assert!(manager.get_channel(channel_id).is_some());
+        assert_eq!(manager.list_channels(), vec![channel_id]);
     }
 
     #[test]
@@ -148,6 +414,84 @@ mod tests {
         assert!(received.is_some());
     }
 
+    #[test]
+    fn test_messages_arrive_in_order() {
+        let manager = IPCManager::new();
+        let channel_id = manager.create_channel();
+        for text in ["first", "second"] {
+            manager.send_message(channel_id, Message::Text(text.to_string())).unwrap();
+        }
+        let Some(Message::Text(text)) = manager.receive_message(channel_id).unwrap() else {
+            panic!("expected a text message");
+        };
+        assert_eq!(text, "first");
+    }
+
+    #[test]
+    fn test_bounded_channel() {
+        let manager = IPCManager::new();
+        let channel_id = manager.create_bounded_channel(1);
+        manager.send_message(channel_id, Message::Text("first".to_string())).unwrap();
+        let full = manager.send_message(channel_id, Message::Text("second".to_string()));
+        assert_eq!(full, Err(IpcError::ChannelFull));
+        manager.receive_message(channel_id).unwrap().unwrap();
+        manager.send_message(channel_id, Message::Text("second".to_string())).unwrap();
+    }
+
+    #[test]
+    fn test_traffic_metrics() {
+        let manager = IPCManager::new();
+        let registry = MetricsRegistry::new();
+        manager.attach_metrics(&registry).unwrap();
+
+        let channel_id = manager.create_channel();
+        manager.send_message(channel_id, Message::Text("ping".to_string())).unwrap();
+        manager.receive_message(channel_id).unwrap();
+        manager.receive_message(channel_id).unwrap();
+        manager.close_channel(channel_id);
+        assert_eq!(manager.send_message(channel_id, Message::Text("lost".to_string())), Err(IpcError::StaleChannel));
+
+        assert_eq!(registry.counter("ipc_messages_sent_total", "").unwrap().get(), 1);
+        assert_eq!(registry.counter("ipc_messages_received_total", "").unwrap().get(), 1);
+        assert_eq!(registry.counter("ipc_send_failures_total", "").unwrap().get(), 1);
+        assert_eq!(registry.gauge("ipc_channels", "").unwrap().get(), 0.0);
+    }
+
+    #[test]
+    fn test_process_termination_cleans_up_channels() {
+        let kernel = Kernel::new();
+        let manager = Arc::new(IPCManager::new());
+        manager.attach_kernel(&kernel);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&events);
+        manager.on_peer_closed(Arc::new(move |event| seen.lock().unwrap().push(*event)));
+
+        let client = kernel.create_process("client".to_string(), kernel::Priority::Normal);
+        let server = kernel.create_process("server".to_string(), kernel::Priority::Normal);
+        let private = manager.create_owned_channel(client, OrphanPolicy::Close);
+        let session = manager.create_owned_channel(client, OrphanPolicy::TransferToPeer);
+        let callback = manager.create_owned_channel(server, OrphanPolicy::Close);
+        manager.connect(private, server).unwrap();
+        manager.connect(session, server).unwrap();
+        manager.connect(callback, client).unwrap();
+        assert_eq!(manager.connect(callback, server), Err(IpcError::PeerAlreadyConnected));
+        assert_eq!(manager.channels_of(client), vec![private, session, callback]);
+
+        kernel.terminate_process(client).unwrap();
+        assert!(manager.get_channel(private).is_none());
+        assert_eq!(manager.get_channel(session).unwrap().owner(), Some(server));
+        assert_eq!(manager.get_channel(callback).unwrap().peer(), None);
+        assert!(manager.channels_of(client).is_empty());
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                PeerClosed { channel: private, process: server, open: false },
+                PeerClosed { channel: session, process: server, open: true },
+                PeerClosed { channel: callback, process: server, open: true },
+            ]
+        );
+    }
+
     #[test]
     fn test_channel_close() {
         let manager = IPCManager::new();
@@ -156,4 +500,25 @@ mod tests {
         assert!(manager.close_channel(channel_id));
         assert!(manager.get_channel(channel_id).is_none());
     }
+
+    #[test]
+    fn test_closed_channel_ids_are_stale() {
+        let manager = IPCManager::new();
+        let closed = manager.create_channel();
+        manager.close_channel(closed);
+        let reused = manager.create_channel();
+        assert_eq!((reused.index(), reused.generation()), (closed.index(), 1));
+
+        // Traffic for the old channel does not land on the new one
+        assert_eq!(manager.send_message(closed, Message::Text("late".to_string())), Err(IpcError::StaleChannel));
+        assert_eq!(manager.receive_message(closed).unwrap_err(), IpcError::StaleChannel);
+        assert_eq!(manager.connect(ChannelId::new(99), ProcessId::new(1)), Err(IpcError::ChannelNotFound));
+        assert!(manager.receive_message(reused).unwrap().is_none());
+
+        // Channels closed with their owner are recycled too
+        let owner = ProcessId::new(7);
+        let owned = manager.create_owned_channel(owner, OrphanPolicy::Close);
+        manager.release_process(owner);
+        assert_eq!(manager.send_message(owned, Message::Text("late".to_string())), Err(IpcError::StaleChannel));
+    }
 }
@@ -0,0 +1,342 @@
+//! Pseudo-terminals
+//!
+//! A PTY pairs a master end, held by a terminal emulator, with a slave end
+//! used by the program running inside it. Both directions are IPC channels
+//! carrying `Message::Binary` chunks. Bytes written to the master pass
+//! through the line discipline, which echoes input and, in canonical mode,
+//! only hands complete lines to the slave.
+
+use sync::prelude::*;
+use sync::{Arc, Mutex};
+
+use crate::{ChannelId, IPCManager, IpcError, Message};
+
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
+const INTERRUPT: u8 = 0x03;
+const END_OF_FILE: u8 = 0x04;
+const KILL_LINE: u8 = 0x15;
+
+/// Line discipline settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Termios {
+    /// Copy input back to the master
+    pub echo: bool,
+    /// Buffer input until a newline and handle erase, kill and end of file
+    pub canonical: bool,
+}
+
+impl Default for Termios {
+    fn default() -> Self {
+        Termios {
+            echo: true,
+            canonical: true,
+        }
+    }
+}
+
+impl Termios {
+    /// Settings for programs that handle every keystroke themselves
+    pub fn raw() -> Self {
+        Termios {
+            echo: false,
+            canonical: false,
+        }
+    }
+}
+
+/// Terminal dimensions in character cells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowSize {
+    pub rows: u16,
+    pub cols: u16,
+}
+
+impl WindowSize {
+    pub fn new(rows: u16, cols: u16) -> Self {
+        WindowSize { rows, cols }
+    }
+}
+
+struct PtyState {
+    termios: Termios,
+    size: WindowSize,
+    /// Canonical input not yet terminated by a newline
+    line: Vec<u8>,
+    interrupted: bool,
+    eof: bool,
+    closed: bool,
+}
+
+struct PtyShared {
+    ipc: Arc<IPCManager>,
+    /// Master to slave
+    input: ChannelId,
+    /// Slave to master
+    output: ChannelId,
+    state: Mutex<PtyState>,
+}
+
+impl PtyShared {
+    fn send(&self, channel: ChannelId, bytes: Vec<u8>) -> Result<(), IpcError> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        self.ipc
+            .send_message(channel, Message::Binary(bytes))
+            .map_err(|_| IpcError::PtyClosed)
+    }
+
+    fn drain(&self, channel: ChannelId) -> Result<Vec<u8>, IpcError> {
+        let mut bytes = Vec::new();
+        while let Some(message) = self.ipc.receive_message(channel).map_err(|_| IpcError::PtyClosed)? {
+            match message {
+                Message::Binary(chunk) => bytes.extend(chunk),
+                Message::Text(text) => bytes.extend(text.into_bytes()),
+                _ => {}
+            }
+        }
+        Ok(bytes)
+    }
+
+    fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !state.closed {
+            state.closed = true;
+            self.ipc.close_channel(self.input);
+            self.ipc.close_channel(self.output);
+        }
+    }
+}
+
+/// Terminal emulator's end of a PTY
+pub struct PtyMaster {
+    shared: Arc<PtyShared>,
+}
+
+/// Program's end of a PTY
+pub struct PtySlave {
+    shared: Arc<PtyShared>,
+}
+
+/// Open a connected master/slave pair
+pub fn open_pty(ipc: &Arc<IPCManager>, size: WindowSize) -> (PtyMaster, PtySlave) {
+    let shared = Arc::new(PtyShared {
+        ipc: Arc::clone(ipc),
+        input: ipc.create_channel(),
+        output: ipc.create_channel(),
+        state: Mutex::new(PtyState {
+            termios: Termios::default(),
+            size,
+            line: Vec::new(),
+            interrupted: false,
+            eof: false,
+            closed: false,
+        }),
+    });
+    (
+        PtyMaster {
+            shared: Arc::clone(&shared),
+        },
+        PtySlave { shared },
+    )
+}
+
+impl PtyMaster {
+    /// Feed keyboard input through the line discipline
+    pub fn write(&self, bytes: &[u8]) -> Result<(), IpcError> {
+        let (input, echo) = {
+            let mut state = self.shared.state.lock().unwrap();
+            if state.closed {
+                return Err(IpcError::PtyClosed);
+            }
+            discipline(&mut state, bytes)
+        };
+        self.shared.send(self.shared.output, echo)?;
+        self.shared.send(self.shared.input, input)
+    }
+
+    /// Take everything the slave and the echo have written so far
+    pub fn read(&self) -> Result<Vec<u8>, IpcError> {
+        self.shared.drain(self.shared.output)
+    }
+
+    pub fn termios(&self) -> Termios {
+        self.shared.state.lock().unwrap().termios
+    }
+
+    pub fn size(&self) -> WindowSize {
+        self.shared.state.lock().unwrap().size
+    }
+
+    /// Record new dimensions after the terminal window changed size
+    pub fn resize(&self, size: WindowSize) {
+        self.shared.state.lock().unwrap().size = size;
+    }
+
+    /// Hang up, closing both channels
+    pub fn close(&self) {
+        self.shared.close();
+    }
+}
+
+impl PtySlave {
+    /// Take the input the line discipline has released so far
+    pub fn read(&self) -> Result<Vec<u8>, IpcError> {
+        self.shared.drain(self.shared.input)
+    }
+
+    /// Write program output to the terminal
+    pub fn write(&self, bytes: &[u8]) -> Result<(), IpcError> {
+        self.shared.send(self.shared.output, bytes.to_vec())
+    }
+
+    pub fn termios(&self) -> Termios {
+        self.shared.state.lock().unwrap().termios
+    }
+
+    /// Change line discipline settings; leaving canonical mode releases any buffered line
+    pub fn set_termios(&self, termios: Termios) -> Result<(), IpcError> {
+        let pending = {
+            let mut state = self.shared.state.lock().unwrap();
+            state.termios = termios;
+            if termios.canonical {
+                Vec::new()
+            } else {
+                core::mem::take(&mut state.line)
+            }
+        };
+        self.shared.send(self.shared.input, pending)
+    }
+
+    pub fn size(&self) -> WindowSize {
+        self.shared.state.lock().unwrap().size
+    }
+
+    /// Whether an interrupt was typed since the last call
+    pub fn take_interrupt(&self) -> bool {
+        core::mem::take(&mut self.shared.state.lock().unwrap().interrupted)
+    }
+
+    /// Whether end of file was typed on an empty line since the last call
+    pub fn take_eof(&self) -> bool {
+        core::mem::take(&mut self.shared.state.lock().unwrap().eof)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.state.lock().unwrap().closed
+    }
+
+    /// Close the PTY from the program's side
+    pub fn close(&self) {
+        self.shared.close();
+    }
+}
+
+/// Process master input, returning bytes for the slave and bytes to echo
+fn discipline(state: &mut PtyState, bytes: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let mut input = Vec::new();
+    let mut echo = Vec::new();
+    if !state.termios.canonical {
+        input.extend_from_slice(bytes);
+        if state.termios.echo {
+            echo.extend_from_slice(bytes);
+        }
+        return (input, echo);
+    }
+
+    for &byte in bytes {
+        match byte {
+            b'\r' | b'\n' => {
+                state.line.push(b'\n');
+                input.append(&mut state.line);
+                echo.extend_from_slice(b"\r\n");
+            }
+            BACKSPACE | DELETE => {
+                // Erase a whole character, not just its last UTF-8 byte
+                while let Some(last) = state.line.pop() {
+                    if last & 0xc0 != 0x80 {
+                        echo.extend_from_slice(b"\x08 \x08");
+                        break;
+                    }
+                }
+            }
+            KILL_LINE => {
+                let chars = String::from_utf8_lossy(&state.line).chars().count();
+                state.line.clear();
+                for _ in 0..chars {
+                    echo.extend_from_slice(b"\x08 \x08");
+                }
+            }
+            INTERRUPT => {
+                state.line.clear();
+                state.interrupted = true;
+                echo.extend_from_slice(b"^C\r\n");
+            }
+            END_OF_FILE => {
+                if state.line.is_empty() {
+                    state.eof = true;
+                } else {
+                    input.append(&mut state.line);
+                }
+            }
+            _ => {
+                state.line.push(byte);
+                echo.push(byte);
+            }
+        }
+    }
+    if !state.termios.echo {
+        echo.clear();
+    }
+    (input, echo)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair() -> (PtyMaster, PtySlave) {
+        open_pty(&Arc::new(IPCManager::new()), WindowSize::new(24, 80))
+    }
+
+    #[test]
+    fn test_canonical_line_editing() {
+        let (master, slave) = pair();
+        master.write(b"lx").unwrap();
+        assert!(slave.read().unwrap().is_empty());
+        master.write("\x7fs \u{e9}\x7f-la\r".as_bytes()).unwrap();
+        assert_eq!(slave.read().unwrap(), b"ls -la\n");
+        assert_eq!(master.read().unwrap(), "lx\x08 \x08s \u{e9}\x08 \x08-la\r\n".as_bytes());
+
+        master.write(b"oops\x15\x03").unwrap();
+        assert!(slave.take_interrupt());
+        assert!(!slave.take_interrupt());
+        master.write(b"\x04").unwrap();
+        assert!(slave.take_eof());
+        assert!(slave.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_raw_mode_and_output() {
+        let (master, slave) = pair();
+        master.write(b"ab").unwrap();
+        slave.set_termios(Termios::raw()).unwrap();
+        master.write(b"\x1b[A").unwrap();
+        assert_eq!(slave.read().unwrap(), b"ab\x1b[A");
+
+        slave.write(b"hello\r\n").unwrap();
+        assert_eq!(master.read().unwrap(), b"abhello\r\n");
+        master.resize(WindowSize::new(40, 120));
+        assert_eq!(slave.size(), WindowSize::new(40, 120));
+    }
+
+    #[test]
+    fn test_hangup() {
+        let (master, slave) = pair();
+        master.close();
+        assert!(slave.is_closed());
+        assert!(slave.write(b"lost").is_err());
+        assert!(master.write(b"x").is_err());
+    }
+}
@@ -0,0 +1,96 @@
+//! Shared-memory rings
+//!
+//! A ring is a fixed set of preallocated slots shared by one sender and
+//! one receiver. The sender copies a payload into the next free slot and
+//! the receiver reads it in place, so nothing is allocated per message
+//! and a full ring pushes back on the sender instead of growing. Unlike a
+//! channel, a ring carries raw bytes only, each payload at most the slot
+//! size it was created with.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use sync::prelude::*;
+use sync::Mutex;
+
+use crate::IpcError;
+
+/// Single-sender, single-receiver ring of fixed-size slots. Payload `n`
+/// lives in slot `n % slots` until it is received.
+#[derive(Debug)]
+pub struct SharedRing {
+    slots: Box<[Mutex<Vec<u8>>]>,
+    slot_size: usize,
+    sent: AtomicUsize,
+    received: AtomicUsize,
+}
+
+impl SharedRing {
+    pub fn new(slots: usize, slot_size: usize) -> Self {
+        SharedRing {
+            slots: (0..slots).map(|_| Mutex::new(Vec::with_capacity(slot_size))).collect(),
+            slot_size,
+            sent: AtomicUsize::new(0),
+            received: AtomicUsize::new(0),
+        }
+    }
+
+    /// Largest payload a slot holds
+    pub fn slot_size(&self) -> usize {
+        self.slot_size
+    }
+
+    /// Payloads sent and not yet received
+    pub fn pending(&self) -> usize {
+        self.sent.load(Ordering::Acquire) - self.received.load(Ordering::Acquire)
+    }
+
+    /// Copy `payload` into the next free slot
+    pub fn send(&self, payload: &[u8]) -> Result<(), IpcError> {
+        if payload.len() > self.slot_size {
+            return Err(IpcError::MessageTooLarge);
+        }
+        let sent = self.sent.load(Ordering::Acquire);
+        if sent - self.received.load(Ordering::Acquire) == self.slots.len() {
+            return Err(IpcError::ChannelFull);
+        }
+        let mut slot = self.slots[sent % self.slots.len()].lock().unwrap();
+        slot.clear();
+        slot.extend_from_slice(payload);
+        drop(slot);
+        self.sent.store(sent + 1, Ordering::Release);
+        Ok(())
+    }
+
+    /// Hand the oldest payload to `read` in place, freeing its slot
+    /// afterwards; false if there was none
+    pub fn receive(&self, read: impl FnOnce(&[u8])) -> bool {
+        let received = self.received.load(Ordering::Acquire);
+        if received == self.sent.load(Ordering::Acquire) {
+            return false;
+        }
+        read(&self.slots[received % self.slots.len()].lock().unwrap());
+        self.received.store(received + 1, Ordering::Release);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_pushes_back_when_full() {
+        let ring = SharedRing::new(2, 4);
+        ring.send(b"one").unwrap();
+        ring.send(b"two").unwrap();
+        assert_eq!(ring.send(b"six"), Err(IpcError::ChannelFull));
+        assert_eq!(ring.send(b"large"), Err(IpcError::MessageTooLarge));
+
+        let mut received = Vec::new();
+        assert!(ring.receive(|data| received.push(data.to_vec())));
+        ring.send(b"six").unwrap();
+        while ring.receive(|data| received.push(data.to_vec())) {}
+        assert_eq!(received, vec![b"one".to_vec(), b"two".to_vec(), b"six".to_vec()]);
+        assert_eq!(ring.pending(), 0);
+    }
+}
@@ -0,0 +1,99 @@
+//! JSON request/response services
+//!
+//! A service answers a `Message::Request` whose data is a JSON-encoded
+//! request with a `Message::Response` under the same id, carrying the
+//! JSON-encoded response. A request that does not parse gets a
+//! `Message::Error` with `ERROR_BAD_REQUEST`; other kinds of message are
+//! not requests and get no reply.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::Message;
+
+/// Error code for requests that fail to parse
+pub const ERROR_BAD_REQUEST: u32 = 400;
+
+/// Decode the request in `message`, answer it with `handler` and encode
+/// the reply; `kind` names the request in parse errors, as in "Invalid
+/// time request"
+pub fn handle_json<Req, Resp>(kind: &str, message: &Message, handler: impl FnOnce(Req) -> Resp) -> Option<Message>
+where
+    Req: DeserializeOwned,
+    Resp: Serialize,
+{
+    let (id, data) = match message {
+        Message::Request { id, data } => (*id, data),
+        _ => return None,
+    };
+    let request = match serde_json::from_slice(data) {
+        Ok(request) => request,
+        Err(e) => {
+            return Some(Message::Error {
+                code: ERROR_BAD_REQUEST,
+                message: format!("Invalid {} request: {}", kind, e),
+            })
+        }
+    };
+    Some(Message::Response {
+        id,
+        data: serde_json::to_vec(&handler(request)).unwrap(),
+    })
+}
+
+/// A service whose requests and responses travel as JSON
+pub trait JsonService {
+    type Request: DeserializeOwned;
+    type Response: Serialize;
+
+    /// What a request is called in parse errors
+    const KIND: &'static str;
+
+    fn handle_request(&self, request: Self::Request) -> Self::Response;
+
+    /// Answer an IPC request message; other message kinds are ignored
+    fn handle_message(&self, message: &Message) -> Option<Message> {
+        handle_json(Self::KIND, message, |request| self.handle_request(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    enum EchoRequest {
+        Say(String),
+    }
+
+    struct Echo;
+
+    impl JsonService for Echo {
+        type Request = EchoRequest;
+        type Response = String;
+        const KIND: &'static str = "echo";
+
+        fn handle_request(&self, request: EchoRequest) -> String {
+            let EchoRequest::Say(text) = request;
+            text
+        }
+    }
+
+    #[test]
+    fn test_json_service() {
+        let request = Message::Request { id: 7, data: br#"{"Say":"hi"}"#.to_vec() };
+        let Some(Message::Response { id, data }) = Echo.handle_message(&request) else {
+            panic!("expected a response");
+        };
+        assert_eq!((id, data), (7, br#""hi""#.to_vec()));
+
+        let bad = Message::Request { id: 8, data: b"nope".to_vec() };
+        let Some(Message::Error { code, message }) = Echo.handle_message(&bad) else {
+            panic!("expected an error");
+        };
+        assert_eq!(code, ERROR_BAD_REQUEST);
+        assert!(message.starts_with("Invalid echo request"));
+        assert!(Echo.handle_message(&Message::Text("hi".to_string())).is_none());
+    }
+}
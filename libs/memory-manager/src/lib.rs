@@ -2,9 +2,17 @@
 //! 
 //! Provides memory allocation, paging, and virtual memory management
 //! for the hairr OS microkernel.
+//!
+//! Builds as `no_std` when the default `std` feature is turned off;
+//! reporting to system information needs std.
 
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use metrics::{Counter, Gauge, MetricsRegistry};
+use sync::prelude::*;
+use sync::{Arc, HashMap, Mutex};
+#[cfg(feature = "std")]
+use system_utils::sysinfo::{MemoryUsage, SystemMonitor};
 
 /// Memory page size (4KB)
 pub const PAGE_SIZE: usize = 4096;
@@ -81,6 +89,12 @@ pub struct VirtualMapping {
 pub struct ProcessId(pub u64);
 
 /// Memory manager
+struct AllocatorMetrics {
+    allocations: Counter,
+    failures: Counter,
+    used_bytes: Gauge,
+}
+
 pub struct MemoryManager {
     // Physical memory tracking
     total_memory: usize,
@@ -93,6 +107,8 @@ pub struct MemoryManager {
     // Page allocation tracking
     free_pages: Arc<Mutex<Vec<Address>>>,
     used_pages: Arc<Mutex<HashMap<Address, ProcessId>>>,
+
+    metrics: Arc<Mutex<Option<AllocatorMetrics>>>,
 }
 
 impl MemoryManager {
@@ -113,11 +129,54 @@ impl MemoryManager {
             virtual_mappings: Arc::new(Mutex::new(HashMap::new())),
             free_pages: Arc::new(Mutex::new(free_pages)),
             used_pages: Arc::new(Mutex::new(HashMap::new())),
+            metrics: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Report allocation activity and usage to `registry`
+    pub fn attach_metrics(&self, registry: &MetricsRegistry) -> Result<(), String> {
+        let metrics = AllocatorMetrics {
+            allocations: registry.counter("memory_allocations_total", "Successful allocations")?,
+            failures: registry.counter("memory_allocation_failures_total", "Rejected allocations")?,
+            used_bytes: registry.gauge("memory_used_bytes", "Physical memory allocated to processes")?,
+        };
+        *self.metrics.lock().unwrap() = Some(metrics);
+        self.update_usage_gauge();
+        Ok(())
+    }
+
+    /// Report physical memory totals to `monitor`
+    #[cfg(feature = "std")]
+    pub fn attach_sysinfo(&self, monitor: &SystemMonitor) {
+        let total = self.total_memory;
+        let free = Arc::clone(&self.free_memory);
+        monitor.attach_memory(move || MemoryUsage {
+            total: total as u64,
+            used: (total - *free.lock().unwrap()) as u64,
+        });
+    }
+
+    fn update_usage_gauge(&self) {
+        if let Some(metrics) = self.metrics.lock().unwrap().as_ref() {
+            let free = *self.free_memory.lock().unwrap();
+            metrics.used_bytes.set((self.total_memory - free) as f64);
         }
     }
 
     /// Allocate memory for a process
     pub fn allocate(&self, process_id: ProcessId, size: usize) -> Result<MemoryRegion, String> {
+        let result = self.allocate_pages(process_id, size);
+        if let Some(metrics) = self.metrics.lock().unwrap().as_ref() {
+            match result {
+                Ok(_) => metrics.allocations.inc(),
+                Err(_) => metrics.failures.inc(),
+            }
+        }
+        self.update_usage_gauge();
+        result
+    }
+
+    fn allocate_pages(&self, process_id: ProcessId, size: usize) -> Result<MemoryRegion, String> {
         if size == 0 {
             return Err("Cannot allocate zero bytes".to_string());
         }
@@ -130,18 +189,16 @@ impl MemoryManager {
             return Err("Out of memory".to_string());
         }
 
+        // Regions are freed by address range, so their pages must be
+        // consecutive. Free pages are kept sorted to find a run of them.
         let mut free_pages = self.free_pages.lock().unwrap();
-        if free_pages.len() < num_pages {
-            return Err("Out of memory pages".to_string());
-        }
-
-        // Allocate consecutive pages
-        let start_addr = free_pages.remove(0);
+        let first = free_pages
+            .windows(num_pages)
+            .position(|run| run[num_pages - 1] - run[0] == (num_pages - 1) * PAGE_SIZE)
+            .ok_or("Out of memory pages")?;
+        let start_addr = free_pages[first];
         let mut used_pages = self.used_pages.lock().unwrap();
-        used_pages.insert(start_addr, process_id);
-
-        for _ in 1..num_pages {
-            let page_addr = free_pages.remove(0);
+        for page_addr in free_pages.drain(first..first + num_pages) {
             used_pages.insert(page_addr, process_id);
         }
 
@@ -151,7 +208,7 @@ impl MemoryManager {
         
         // Track allocation
         let mut allocated = self.allocated_regions.lock().unwrap();
-        allocated.entry(process_id).or_insert_with(Vec::new).push(region);
+        allocated.entry(process_id).or_default().push(region);
 
         Ok(region)
     }
@@ -174,10 +231,14 @@ impl MemoryManager {
         for i in 0..num_pages {
             let page_addr = region.start + (i * PAGE_SIZE);
             used_pages.remove(&page_addr);
-            free_pages.push(page_addr);
+            release_page(&mut free_pages, page_addr);
         }
 
         *self.free_memory.lock().unwrap() += region.size;
+        drop(used_pages);
+        drop(free_pages);
+        drop(allocated);
+        self.update_usage_gauge();
 
         Ok(())
     }
@@ -196,7 +257,7 @@ impl MemoryManager {
             for i in 0..num_pages {
                 let page_addr = region.start + (i * PAGE_SIZE);
                 used_pages.remove(&page_addr);
-                free_pages.push(page_addr);
+                release_page(&mut free_pages, page_addr);
             }
             total_freed += region.size;
         }
@@ -205,6 +266,10 @@ impl MemoryManager {
 
         // Also remove virtual mappings
         self.virtual_mappings.lock().unwrap().remove(&process_id);
+        drop(used_pages);
+        drop(free_pages);
+        drop(allocated);
+        self.update_usage_gauge();
 
         Ok(())
     }
@@ -226,7 +291,7 @@ impl MemoryManager {
         };
 
         let mut mappings = self.virtual_mappings.lock().unwrap();
-        mappings.entry(process_id).or_insert_with(Vec::new).push(mapping);
+        mappings.entry(process_id).or_default().push(mapping);
 
         Ok(())
     }
@@ -282,6 +347,37 @@ impl MemoryManager {
     pub fn list_processes(&self) -> Vec<ProcessId> {
         self.allocated_regions.lock().unwrap().keys().copied().collect()
     }
+
+    /// How the free pages are broken into runs of consecutive pages
+    pub fn fragmentation(&self) -> FragmentationReport {
+        let free_pages = self.free_pages.lock().unwrap();
+        let mut report = FragmentationReport {
+            free_pages: free_pages.len(),
+            largest_free_run: 0,
+            runs_by_order: Vec::new(),
+        };
+        let mut start = 0;
+        for end in 1..=free_pages.len() {
+            if end < free_pages.len() && free_pages[end] == free_pages[end - 1] + PAGE_SIZE {
+                continue;
+            }
+            let run = end - start;
+            report.largest_free_run = report.largest_free_run.max(run);
+            let order = run.ilog2() as usize;
+            if report.runs_by_order.len() <= order {
+                report.runs_by_order.resize(order + 1, 0);
+            }
+            report.runs_by_order[order] += 1;
+            start = end;
+        }
+        report
+    }
+}
+
+/// Return a page to the sorted free list
+fn release_page(free_pages: &mut Vec<Address>, page_addr: Address) {
+    let index = free_pages.binary_search(&page_addr).unwrap_or_else(|index| index);
+    free_pages.insert(index, page_addr);
 }
 
 /// Memory statistics
@@ -301,6 +397,29 @@ impl MemoryStats {
     }
 }
 
+/// How free memory is broken up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FragmentationReport {
+    pub free_pages: usize,
+    /// Pages in the longest run of free pages, the largest region that can
+    /// still be allocated
+    pub largest_free_run: usize,
+    /// Free runs by size class: entry `k` counts runs of `2^k` up to
+    /// `2^(k+1) - 1` pages, the orders a buddy allocator keeps lists for
+    pub runs_by_order: Vec<usize>,
+}
+
+impl FragmentationReport {
+    /// Share of free pages outside the largest run; 0 when free memory is
+    /// one run
+    pub fn fragmentation(&self) -> f32 {
+        if self.free_pages == 0 {
+            return 0.0;
+        }
+        1.0 - self.largest_free_run as f32 / self.free_pages as f32
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -366,6 +485,56 @@ mod tests {
         assert_eq!(stats.used_memory, 0);
     }
 
+    #[test]
+    fn test_regions_are_contiguous() {
+        let manager = MemoryManager::new(1);
+        let process_id = ProcessId(1);
+
+        let first = manager.allocate(process_id, PAGE_SIZE).unwrap();
+        manager.allocate(process_id, PAGE_SIZE).unwrap();
+        manager.free(process_id, first).unwrap();
+
+        // The freed page is too small, so the region starts after the one in use
+        let region = manager.allocate(process_id, 2 * PAGE_SIZE).unwrap();
+        assert_eq!(region.start, 2 * PAGE_SIZE);
+        manager.free(process_id, region).unwrap();
+        assert_eq!(manager.allocate(process_id, PAGE_SIZE).unwrap().start, 0);
+    }
+
+    #[test]
+    fn test_fragmentation_report() {
+        let manager = MemoryManager::new(1);
+        let process_id = ProcessId(1);
+        assert_eq!(manager.fragmentation().runs_by_order, vec![0, 0, 0, 0, 0, 0, 0, 0, 1]);
+
+        let regions: Vec<_> = (0..6).map(|_| manager.allocate(process_id, PAGE_SIZE).unwrap()).collect();
+        manager.free(process_id, regions[1]).unwrap();
+        manager.free(process_id, regions[3]).unwrap();
+        manager.free(process_id, regions[4]).unwrap();
+
+        // Free runs of 1 and 2 pages, then the 250 never allocated
+        let report = manager.fragmentation();
+        assert_eq!((report.free_pages, report.largest_free_run), (253, 250));
+        assert_eq!(report.runs_by_order, vec![1, 1, 0, 0, 0, 0, 0, 1]);
+        assert!(report.fragmentation() > 0.01 && report.fragmentation() < 0.02);
+    }
+
+    #[test]
+    fn test_allocation_metrics() {
+        let manager = MemoryManager::new(1);
+        let registry = MetricsRegistry::new();
+        manager.attach_metrics(&registry).unwrap();
+
+        let region = manager.allocate(ProcessId(1), 8192).unwrap();
+        assert!(manager.allocate(ProcessId(1), 4 * 1024 * 1024).is_err());
+        let used = registry.gauge("memory_used_bytes", "").unwrap();
+        assert_eq!(used.get(), 8192.0);
+        manager.free(ProcessId(1), region).unwrap();
+        assert_eq!(used.get(), 0.0);
+        assert_eq!(registry.counter("memory_allocations_total", "").unwrap().get(), 1);
+        assert_eq!(registry.counter("memory_allocation_failures_total", "").unwrap().get(), 1);
+    }
+
     #[test]
     fn test_virtual_mapping() {
         let manager = MemoryManager::new(16);
@@ -393,5 +562,10 @@ mod tests {
         assert_eq!(stats.total_memory, 16 * 1024 * 1024);
         assert_eq!(stats.free_memory, stats.total_memory);
         assert!(stats.usage_percent() < 0.01);
+
+        let monitor = SystemMonitor::new();
+        manager.attach_sysinfo(&monitor);
+        manager.allocate(ProcessId(1), 2 * PAGE_SIZE).unwrap();
+        assert_eq!(monitor.memory(), Some(MemoryUsage { total: 16 * 1024 * 1024, used: 2 * PAGE_SIZE as u64 }));
     }
 }
@@ -0,0 +1,285 @@
+//! Allocation stress testing for the memory manager
+//!
+//! Replays a trace of allocations, frees and process exits against a
+//! `MemoryManager`, timing every allocation and watching how free memory
+//! breaks up. A trace is text, one operation per line:
+//!
+//! ```text
+//! # comments and blank lines are skipped
+//! alloc <name> <process> <bytes>
+//! free <name>
+//! exit <process>
+//! ```
+//!
+//! Names identify allocations within the trace; freeing one whose
+//! allocation failed is skipped, as its owner would have. Seeded synthetic
+//! traces mix many small allocations with fewer large ones, which is what
+//! fragments a first-fit page allocator.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use memory_manager::{FragmentationReport, MemoryManager, MemoryRegion, ProcessId, PAGE_SIZE};
+use syscall_fuzz::Rng;
+
+/// Operations between fragmentation samples, since a sample walks every
+/// free page
+const SAMPLE_EVERY: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceOp {
+    Alloc { name: String, process: u64, size: usize },
+    Free { name: String },
+    /// The process exits, releasing everything it holds
+    Exit { process: u64 },
+}
+
+impl fmt::Display for TraceOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceOp::Alloc { name, process, size } => write!(f, "alloc {} {} {}", name, process, size),
+            TraceOp::Free { name } => write!(f, "free {}", name),
+            TraceOp::Exit { process } => write!(f, "exit {}", process),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Trace {
+    pub ops: Vec<TraceOp>,
+}
+
+impl Trace {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut ops = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let number_at = |index: usize| {
+                fields[index]
+                    .parse()
+                    .map_err(|_| format!("line {}: {} is not a number", number + 1, fields[index]))
+            };
+            let op = match fields.as_slice() {
+                ["alloc", name, _, _] => TraceOp::Alloc {
+                    name: name.to_string(),
+                    process: number_at(2)?,
+                    size: number_at(3)? as usize,
+                },
+                ["free", name] => TraceOp::Free { name: name.to_string() },
+                ["exit", _] => TraceOp::Exit { process: number_at(1)? },
+                _ => return Err(format!("line {}: cannot parse {:?}", number + 1, line)),
+            };
+            ops.push(op);
+        }
+        Ok(Trace { ops })
+    }
+
+    /// `length` operations across eight processes, the same for the same
+    /// `seed`
+    pub fn synthetic(seed: u64, length: usize) -> Self {
+        let mut rng = Rng::new(seed);
+        let mut live: Vec<(String, u64)> = Vec::new();
+        let mut ops = Vec::with_capacity(length);
+        for step in 0..length {
+            let roll = rng.below(100);
+            let op = if roll < 2 {
+                let process = 1 + rng.below(8) as u64;
+                live.retain(|(_, owner)| *owner != process);
+                TraceOp::Exit { process }
+            } else if roll < 45 && !live.is_empty() {
+                let (name, _) = live.swap_remove(rng.below(live.len()));
+                TraceOp::Free { name }
+            } else {
+                let pages = match rng.below(100) {
+                    0..=74 => 1 + rng.below(4),
+                    75..=94 => 8 + rng.below(57),
+                    _ => 128 + rng.below(385),
+                };
+                let (name, process) = (format!("a{}", step), 1 + rng.below(8) as u64);
+                live.push((name.clone(), process));
+                TraceOp::Alloc { name, process, size: pages * PAGE_SIZE - rng.below(PAGE_SIZE) }
+            };
+            ops.push(op);
+        }
+        Trace { ops }
+    }
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for op in &self.ops {
+            writeln!(f, "{}", op)?;
+        }
+        Ok(())
+    }
+}
+
+/// Distribution of allocation times, successful or not
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Latency {
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl Latency {
+    fn from_samples(mut samples: Vec<Duration>) -> Self {
+        if samples.is_empty() {
+            return Latency::default();
+        }
+        samples.sort();
+        let at = |percent: usize| samples[(samples.len() - 1) * percent / 100];
+        Latency {
+            p50: at(50),
+            p90: at(90),
+            p99: at(99),
+            max: at(100),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub allocations: usize,
+    /// Rejected because too little memory was free
+    pub out_of_memory: usize,
+    /// Rejected with enough memory free, but in no run long enough
+    pub fragmented: usize,
+    pub latency: Latency,
+    /// Free memory when it was most broken up
+    pub worst: FragmentationReport,
+    /// Free memory after the last operation
+    pub end: FragmentationReport,
+}
+
+/// Replay `trace` on a fresh manager with `memory_mb` of memory
+pub fn replay(trace: &Trace, memory_mb: usize) -> Report {
+    let manager = MemoryManager::new(memory_mb);
+    let mut regions: HashMap<&str, (ProcessId, MemoryRegion)> = HashMap::new();
+    let mut samples = Vec::new();
+    let (mut out_of_memory, mut fragmented) = (0, 0);
+    let mut worst = manager.fragmentation();
+
+    for (step, op) in trace.ops.iter().enumerate() {
+        match op {
+            TraceOp::Alloc { name, process, size } => {
+                let free = manager.stats().free_memory;
+                let started = Instant::now();
+                let result = manager.allocate(ProcessId(*process), *size);
+                samples.push(started.elapsed());
+                match result {
+                    Ok(region) => {
+                        regions.insert(name, (ProcessId(*process), region));
+                    }
+                    Err(_) if free >= *size => fragmented += 1,
+                    Err(_) => out_of_memory += 1,
+                }
+            }
+            TraceOp::Free { name } => {
+                if let Some((process, region)) = regions.remove(name.as_str()) {
+                    // Fails only if the process already exited
+                    let _ = manager.free(process, region);
+                }
+            }
+            TraceOp::Exit { process } => {
+                // Fails only when the process held no memory
+                let _ = manager.free_all(ProcessId(*process));
+                regions.retain(|_, (owner, _)| owner.0 != *process);
+            }
+        }
+        if step % SAMPLE_EVERY == SAMPLE_EVERY - 1 {
+            let sample = manager.fragmentation();
+            if sample.fragmentation() > worst.fragmentation() {
+                worst = sample;
+            }
+        }
+    }
+
+    Report {
+        allocations: samples.len(),
+        out_of_memory,
+        fragmented,
+        latency: Latency::from_samples(samples),
+        worst,
+        end: manager.fragmentation(),
+    }
+}
+
+fn micros(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1e6
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let failed = self.out_of_memory + self.fragmented;
+        writeln!(
+            f,
+            "allocations       {} ({} out of memory, {} fragmented)",
+            self.allocations - failed,
+            self.out_of_memory,
+            self.fragmented
+        )?;
+        writeln!(
+            f,
+            "latency           p50 {:.1}us  p90 {:.1}us  p99 {:.1}us  max {:.1}us",
+            micros(self.latency.p50),
+            micros(self.latency.p90),
+            micros(self.latency.p99),
+            micros(self.latency.max)
+        )?;
+        writeln!(f, "free pages        {}", self.end.free_pages)?;
+        writeln!(f, "largest free run  {} pages", self.end.largest_free_run)?;
+        writeln!(
+            f,
+            "fragmentation     {:.1}% (worst {:.1}%)",
+            self.end.fragmentation() * 100.0,
+            self.worst.fragmentation() * 100.0
+        )?;
+        writeln!(f, "free runs by order")?;
+        for (order, runs) in self.end.runs_by_order.iter().enumerate() {
+            let pages = format!("{}-{}", 1usize << order, (2usize << order) - 1);
+            writeln!(f, "  {:>2}  {:>12} pages  {:>8}", order, pages, runs)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_round_trip() {
+        let trace = Trace::synthetic(3, 500);
+        assert_eq!(Trace::parse(&trace.to_string()).unwrap(), trace);
+        assert_eq!(trace, Trace::synthetic(3, 500));
+        assert!(Trace::parse("alloc a 1").is_err());
+        assert_eq!(Trace::parse("free a\nexit x").unwrap_err(), "line 2: x is not a number");
+    }
+
+    #[test]
+    fn test_replay_counts_fragmented_failures() {
+        // Every other page of a 1 MB manager stays allocated
+        let mut text = String::new();
+        for page in 0..256 {
+            text.push_str(&format!("alloc p{} 1 4096\n", page));
+        }
+        for page in (0..256).step_by(2) {
+            text.push_str(&format!("free p{}\n", page));
+        }
+        text.push_str("alloc big 2 8192\nalloc huge 2 2000000\nfree big\n");
+
+        let report = replay(&Trace::parse(&text).unwrap(), 1);
+        assert_eq!((report.allocations, report.fragmented, report.out_of_memory), (258, 1, 1));
+        assert_eq!((report.end.free_pages, report.end.largest_free_run), (128, 1));
+        assert_eq!(report.end.runs_by_order, vec![128]);
+        assert!(report.latency.p50 <= report.latency.max);
+        assert!(report.to_string().contains("1 fragmented"));
+    }
+}
@@ -0,0 +1,56 @@
+//! Replay an allocation trace and report latency and fragmentation
+//!
+//! `memory-stress [TRACE] [--memory <MB>] [--seed <N>] [--ops <N>] [--emit]`
+//!
+//! Without a trace file, replays a synthetic trace of `--ops` operations
+//! from `--seed`. `--emit` prints the trace instead, to save and edit.
+
+use std::fs;
+
+use memory_stress::{replay, Trace};
+
+const DEFAULT_MEMORY_MB: usize = 64;
+const DEFAULT_OPS: usize = 20_000;
+const VALUE_FLAGS: &[&str] = &["--memory", "--seed", "--ops"];
+
+fn value(args: &[String], flag: &str) -> Result<Option<usize>, String> {
+    match args.iter().position(|arg| arg == flag) {
+        Some(index) => args
+            .get(index + 1)
+            .and_then(|value| value.parse().ok())
+            .map(Some)
+            .ok_or_else(|| format!("{} needs a number", flag)),
+        None => Ok(None),
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let memory_mb = value(args, "--memory")?.unwrap_or(DEFAULT_MEMORY_MB);
+    let seed = value(args, "--seed")?.unwrap_or(1) as u64;
+    let ops = value(args, "--ops")?.unwrap_or(DEFAULT_OPS);
+    let file = args
+        .iter()
+        .enumerate()
+        .find(|(index, arg)| !arg.starts_with("--") && (*index == 0 || !VALUE_FLAGS.contains(&args[index - 1].as_str())))
+        .map(|(_, arg)| arg);
+
+    let trace = match file {
+        Some(path) => Trace::parse(&fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?)?,
+        None => Trace::synthetic(seed, ops),
+    };
+    if args.iter().any(|arg| arg == "--emit") {
+        print!("{}", trace);
+        return Ok(());
+    }
+    println!("{} operations on {} MB", trace.ops.len(), memory_mb);
+    print!("{}", replay(&trace, memory_mb));
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("Error: {}", e);
+        std::process::exit(2);
+    }
+}
@@ -0,0 +1,270 @@
+//! Metrics client API for hairr OS
+//!
+//! Components register counters, gauges and histograms in a shared
+//! `MetricsRegistry` and update them through cheap cloneable handles. The
+//! metrics service samples the registry periodically for aggregation and
+//! export.
+//!
+//! Without the default `std` feature the crate is `no_std`, so the kernel
+//! can report metrics on bare metal.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use serde::{Deserialize, Serialize};
+use sync::prelude::*;
+use sync::{Arc, BTreeMap, Mutex};
+
+/// Default histogram buckets, suited to latencies in milliseconds
+pub const DEFAULT_BUCKETS: &[f64] = &[1.0, 5.0, 10.0, 50.0, 100.0, 500.0, 1000.0];
+
+/// Kind of a registered metric
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+/// Histogram state at sampling time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistogramSnapshot {
+    /// Upper bounds, ascending
+    pub buckets: Vec<f64>,
+    /// Observations per bucket, not cumulative; one extra for +Inf
+    pub counts: Vec<u64>,
+    pub sum: f64,
+    pub count: u64,
+}
+
+/// Value of a metric at sampling time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MetricValue {
+    Counter { value: u64 },
+    Gauge { value: f64 },
+    Histogram { histogram: HistogramSnapshot },
+}
+
+impl MetricValue {
+    pub fn kind(&self) -> MetricKind {
+        match self {
+            MetricValue::Counter { .. } => MetricKind::Counter,
+            MetricValue::Gauge { .. } => MetricKind::Gauge,
+            MetricValue::Histogram { .. } => MetricKind::Histogram,
+        }
+    }
+
+    /// Single number for time series: counters and gauges as-is,
+    /// histograms by their observation count
+    pub fn scalar(&self) -> f64 {
+        match self {
+            MetricValue::Counter { value } => *value as f64,
+            MetricValue::Gauge { value } => *value,
+            MetricValue::Histogram { histogram } => histogram.count as f64,
+        }
+    }
+}
+
+/// One metric as sampled from the registry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetricSample {
+    pub name: String,
+    pub help: String,
+    pub value: MetricValue,
+}
+
+/// Monotonically increasing count
+#[derive(Debug, Clone, Default)]
+pub struct Counter(Arc<Mutex<u64>>);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        *self.0.lock().unwrap() += n;
+    }
+
+    pub fn get(&self) -> u64 {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// Value that can go up and down
+#[derive(Debug, Clone, Default)]
+pub struct Gauge(Arc<Mutex<f64>>);
+
+impl Gauge {
+    pub fn set(&self, value: f64) {
+        *self.0.lock().unwrap() = value;
+    }
+
+    pub fn add(&self, delta: f64) {
+        *self.0.lock().unwrap() += delta;
+    }
+
+    pub fn get(&self) -> f64 {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// Distribution of observed values over fixed buckets
+#[derive(Debug, Clone)]
+pub struct Histogram(Arc<Mutex<HistogramSnapshot>>);
+
+impl Histogram {
+    fn new(buckets: &[f64]) -> Self {
+        Histogram(Arc::new(Mutex::new(HistogramSnapshot {
+            buckets: buckets.to_vec(),
+            counts: vec![0; buckets.len() + 1],
+            sum: 0.0,
+            count: 0,
+        })))
+    }
+
+    pub fn observe(&self, value: f64) {
+        let mut state = self.0.lock().unwrap();
+        let bucket = state.buckets.iter().position(|&b| value <= b).unwrap_or(state.buckets.len());
+        state.counts[bucket] += 1;
+        state.sum += value;
+        state.count += 1;
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Metric {
+    Counter(Counter),
+    Gauge(Gauge),
+    Histogram(Histogram),
+}
+
+#[derive(Debug, Clone)]
+struct Registered {
+    help: String,
+    metric: Metric,
+}
+
+/// Shared set of named metrics; clones refer to the same registry
+#[derive(Debug, Clone, Default)]
+pub struct MetricsRegistry {
+    metrics: Arc<Mutex<BTreeMap<String, Registered>>>,
+}
+
+impl MetricsRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counter named `name`, registering it on first use
+    pub fn counter(&self, name: &str, help: &str) -> Result<Counter, String> {
+        match self.register(name, help, || Metric::Counter(Counter::default()))? {
+            Metric::Counter(counter) => Ok(counter),
+            _ => Err(format!("Metric {} is not a counter", name)),
+        }
+    }
+
+    /// Gauge named `name`, registering it on first use
+    pub fn gauge(&self, name: &str, help: &str) -> Result<Gauge, String> {
+        match self.register(name, help, || Metric::Gauge(Gauge::default()))? {
+            Metric::Gauge(gauge) => Ok(gauge),
+            _ => Err(format!("Metric {} is not a gauge", name)),
+        }
+    }
+
+    /// Histogram named `name`, registering it with `buckets` on first use
+    pub fn histogram(&self, name: &str, help: &str, buckets: &[f64]) -> Result<Histogram, String> {
+        if buckets.is_empty() || buckets.windows(2).any(|w| w[0] >= w[1]) {
+            return Err("Histogram buckets must be non-empty and ascending".to_string());
+        }
+        match self.register(name, help, || Metric::Histogram(Histogram::new(buckets)))? {
+            Metric::Histogram(histogram) => Ok(histogram),
+            _ => Err(format!("Metric {} is not a histogram", name)),
+        }
+    }
+
+    fn register(&self, name: &str, help: &str, create: impl FnOnce() -> Metric) -> Result<Metric, String> {
+        if !valid_name(name) {
+            return Err(format!("Invalid metric name: {}", name));
+        }
+        let mut metrics = self.metrics.lock().unwrap();
+        let registered = metrics.entry(name.to_string()).or_insert_with(|| Registered {
+            help: help.to_string(),
+            metric: create(),
+        });
+        Ok(registered.metric.clone())
+    }
+
+    /// Current value of every metric, ordered by name
+    pub fn sample(&self) -> Vec<MetricSample> {
+        self.metrics
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, registered)| MetricSample {
+                name: name.clone(),
+                help: registered.help.clone(),
+                value: match &registered.metric {
+                    Metric::Counter(counter) => MetricValue::Counter { value: counter.get() },
+                    Metric::Gauge(gauge) => MetricValue::Gauge { value: gauge.get() },
+                    Metric::Histogram(histogram) => MetricValue::Histogram {
+                        histogram: histogram.snapshot(),
+                    },
+                },
+            })
+            .collect()
+    }
+}
+
+/// Names follow the Prometheus rules: `[a-zA-Z_:][a-zA-Z0-9_:]*`
+pub fn valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == ':' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handles_share_state() {
+        let registry = MetricsRegistry::new();
+        let requests = registry.counter("requests_total", "Requests served").unwrap();
+        requests.inc();
+        registry.counter("requests_total", "ignored").unwrap().add(2);
+        assert_eq!(requests.get(), 3);
+
+        let temperature = registry.gauge("temperature", "Degrees").unwrap();
+        temperature.set(40.0);
+        temperature.add(-2.5);
+        assert!(registry.gauge("requests_total", "").is_err());
+        assert!(registry.counter("9lives", "").is_err());
+
+        let samples = registry.sample();
+        assert_eq!(samples[0].name, "requests_total");
+        assert_eq!(samples[0].help, "Requests served");
+        assert_eq!(samples[1].value, MetricValue::Gauge { value: 37.5 });
+    }
+
+    #[test]
+    fn test_histogram_buckets() {
+        let registry = MetricsRegistry::new();
+        assert!(registry.histogram("bad", "", &[5.0, 1.0]).is_err());
+        let latency = registry.histogram("latency_ms", "Latency", &[1.0, 10.0]).unwrap();
+        for value in [0.5, 1.0, 7.0, 100.0] {
+            latency.observe(value);
+        }
+        let snapshot = latency.snapshot();
+        assert_eq!(snapshot.counts, vec![2, 1, 1]);
+        assert_eq!((snapshot.count, snapshot.sum), (4, 108.5));
+    }
+}
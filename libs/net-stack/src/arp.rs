@@ -0,0 +1,136 @@
+//! Address Resolution Protocol for IPv4 over Ethernet
+
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+
+use crate::ethernet::{MacAddress, ETHERTYPE_IPV4};
+
+const PACKET_LEN: usize = 28;
+
+/// ARP operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArpOperation {
+    Request,
+    Reply,
+}
+
+/// ARP packet (Ethernet/IPv4 only)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArpPacket {
+    pub operation: ArpOperation,
+    pub sender_mac: MacAddress,
+    pub sender_ip: Ipv4Addr,
+    pub target_mac: MacAddress,
+    pub target_ip: Ipv4Addr,
+}
+
+impl ArpPacket {
+    pub fn request(sender_mac: MacAddress, sender_ip: Ipv4Addr, target_ip: Ipv4Addr) -> Self {
+        ArpPacket {
+            operation: ArpOperation::Request,
+            sender_mac,
+            sender_ip,
+            target_mac: [0; 6],
+            target_ip,
+        }
+    }
+
+    /// Reply answering `request` on behalf of `mac`
+    pub fn reply_to(request: &ArpPacket, mac: MacAddress) -> Self {
+        ArpPacket {
+            operation: ArpOperation::Reply,
+            sender_mac: mac,
+            sender_ip: request.target_ip,
+            target_mac: request.sender_mac,
+            target_ip: request.sender_ip,
+        }
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < PACKET_LEN {
+            return Err("ARP packet too short".to_string());
+        }
+        let htype = u16::from_be_bytes([bytes[0], bytes[1]]);
+        let ptype = u16::from_be_bytes([bytes[2], bytes[3]]);
+        if htype != 1 || ptype != ETHERTYPE_IPV4 || bytes[4] != 6 || bytes[5] != 4 {
+            return Err("Unsupported ARP hardware or protocol type".to_string());
+        }
+        let operation = match u16::from_be_bytes([bytes[6], bytes[7]]) {
+            1 => ArpOperation::Request,
+            2 => ArpOperation::Reply,
+            _ => return Err("Unknown ARP operation".to_string()),
+        };
+
+        let mut sender_mac = [0u8; 6];
+        let mut target_mac = [0u8; 6];
+        sender_mac.copy_from_slice(&bytes[8..14]);
+        target_mac.copy_from_slice(&bytes[18..24]);
+        Ok(ArpPacket {
+            operation,
+            sender_mac,
+            sender_ip: Ipv4Addr::new(bytes[14], bytes[15], bytes[16], bytes[17]),
+            target_mac,
+            target_ip: Ipv4Addr::new(bytes[24], bytes[25], bytes[26], bytes[27]),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(PACKET_LEN);
+        bytes.extend_from_slice(&1u16.to_be_bytes());
+        bytes.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        bytes.push(6);
+        bytes.push(4);
+        let op: u16 = match self.operation {
+            ArpOperation::Request => 1,
+            ArpOperation::Reply => 2,
+        };
+        bytes.extend_from_slice(&op.to_be_bytes());
+        bytes.extend_from_slice(&self.sender_mac);
+        bytes.extend_from_slice(&self.sender_ip.octets());
+        bytes.extend_from_slice(&self.target_mac);
+        bytes.extend_from_slice(&self.target_ip.octets());
+        bytes
+    }
+}
+
+/// IPv4 to MAC address mappings learned from the link
+#[derive(Debug, Default)]
+pub struct ArpCache {
+    entries: HashMap<Ipv4Addr, MacAddress>,
+}
+
+impl ArpCache {
+    pub fn new() -> Self {
+        ArpCache {
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, ip: Ipv4Addr, mac: MacAddress) {
+        self.entries.insert(ip, mac);
+    }
+
+    pub fn lookup(&self, ip: Ipv4Addr) -> Option<MacAddress> {
+        self.entries.get(&ip).copied()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_reply_round_trip() {
+        let request = ArpPacket::request([2, 0, 0, 0, 0, 1], Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2));
+        let parsed = ArpPacket::parse(&request.to_bytes()).unwrap();
+        assert_eq!(parsed, request);
+
+        let reply = ArpPacket::reply_to(&parsed, [2, 0, 0, 0, 0, 2]);
+        assert_eq!(reply.target_mac, [2, 0, 0, 0, 0, 1]);
+        assert_eq!(reply.sender_ip, Ipv4Addr::new(10, 0, 0, 2));
+    }
+}
@@ -0,0 +1,70 @@
+//! Ethernet II framing
+
+/// Hardware (MAC) address
+pub type MacAddress = [u8; 6];
+
+/// Destination address that reaches every station on the link
+pub const BROADCAST_MAC: MacAddress = [0xff; 6];
+
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+
+const HEADER_LEN: usize = 14;
+
+/// Ethernet II frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EthernetFrame {
+    pub dst: MacAddress,
+    pub src: MacAddress,
+    pub ethertype: u16,
+    pub payload: Vec<u8>,
+}
+
+impl EthernetFrame {
+    pub fn new(dst: MacAddress, src: MacAddress, ethertype: u16, payload: Vec<u8>) -> Self {
+        EthernetFrame {
+            dst,
+            src,
+            ethertype,
+            payload,
+        }
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < HEADER_LEN {
+            return Err("Ethernet frame too short".to_string());
+        }
+        let mut dst = [0u8; 6];
+        let mut src = [0u8; 6];
+        dst.copy_from_slice(&bytes[0..6]);
+        src.copy_from_slice(&bytes[6..12]);
+        Ok(EthernetFrame {
+            dst,
+            src,
+            ethertype: u16::from_be_bytes([bytes[12], bytes[13]]),
+            payload: bytes[HEADER_LEN..].to_vec(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.payload.len());
+        bytes.extend_from_slice(&self.dst);
+        bytes.extend_from_slice(&self.src);
+        bytes.extend_from_slice(&self.ethertype.to_be_bytes());
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_round_trip() {
+        let frame = EthernetFrame::new(BROADCAST_MAC, [2, 0, 0, 0, 0, 1], ETHERTYPE_ARP, vec![1, 2, 3]);
+        let parsed = EthernetFrame::parse(&frame.to_bytes()).unwrap();
+        assert_eq!(parsed, frame);
+        assert!(EthernetFrame::parse(&[0u8; 10]).is_err());
+    }
+}
@@ -0,0 +1,491 @@
+//! Packet filter
+//!
+//! Rules match packets by direction, interface, protocol, remote address,
+//! ports and owning process; the first matching rule decides, falling back
+//! to the default action. Changing rules requires a capability for the
+//! `Network("firewall")` resource, and processes open listening ports by
+//! presenting a `Network("listen:<port>")` capability, which installs an
+//! allow rule for the lifetime of the socket.
+
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use capability::{CapabilityManager, CapabilityToken, Permission, Resource};
+use filesystem::{OpenOptions, VirtualFileSystem};
+use kernel::ProcessId;
+use serde::{Deserialize, Serialize};
+
+use crate::ipv4::{PROTOCOL_ICMP, PROTOCOL_TCP, PROTOCOL_UDP};
+
+/// Network resource guarding rule changes
+pub const FIREWALL_RESOURCE: &str = "firewall";
+
+/// Network resource allowing a process to listen on any port
+pub const LISTEN_ANY_RESOURCE: &str = "listen:*";
+
+/// Network resource allowing a process to listen on `port`
+pub fn listen_resource(port: u16) -> String {
+    format!("listen:{}", port)
+}
+
+/// Traffic direction relative to the local host
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// Transport protocols understood by the filter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Protocol {
+    Icmp,
+    Udp,
+    Tcp,
+}
+
+impl Protocol {
+    pub fn from_number(protocol: u8) -> Option<Self> {
+        match protocol {
+            PROTOCOL_ICMP => Some(Protocol::Icmp),
+            PROTOCOL_UDP => Some(Protocol::Udp),
+            PROTOCOL_TCP => Some(Protocol::Tcp),
+            _ => None,
+        }
+    }
+}
+
+/// Verdict for a packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Action {
+    Allow,
+    Deny,
+}
+
+/// Firewall rule identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RuleId(u64);
+
+impl RuleId {
+    pub fn new(id: u64) -> Self {
+        RuleId(id)
+    }
+}
+
+/// Packet attributes a rule is matched against
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketInfo {
+    pub direction: Direction,
+    pub interface: String,
+    pub protocol: Option<Protocol>,
+    pub remote: Ipv4Addr,
+    pub local_port: Option<u16>,
+    pub remote_port: Option<u16>,
+    pub owner: Option<ProcessId>,
+    pub len: usize,
+}
+
+/// A filter rule; unset fields match anything
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FirewallRule {
+    pub action: Action,
+    pub direction: Option<Direction>,
+    pub interface: Option<String>,
+    pub protocol: Option<Protocol>,
+    /// Remote network as address and prefix length
+    pub remote: Option<(Ipv4Addr, u8)>,
+    pub local_port: Option<u16>,
+    pub remote_port: Option<u16>,
+    pub owner: Option<ProcessId>,
+}
+
+impl FirewallRule {
+    pub fn new(action: Action) -> Self {
+        FirewallRule {
+            action,
+            direction: None,
+            interface: None,
+            protocol: None,
+            remote: None,
+            local_port: None,
+            remote_port: None,
+            owner: None,
+        }
+    }
+
+    pub fn allow() -> Self {
+        Self::new(Action::Allow)
+    }
+
+    pub fn deny() -> Self {
+        Self::new(Action::Deny)
+    }
+
+    pub fn with_direction(mut self, direction: Direction) -> Self {
+        self.direction = Some(direction);
+        self
+    }
+
+    pub fn with_interface(mut self, interface: &str) -> Self {
+        self.interface = Some(interface.to_string());
+        self
+    }
+
+    pub fn with_protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = Some(protocol);
+        self
+    }
+
+    pub fn with_remote(mut self, network: Ipv4Addr, prefix_len: u8) -> Self {
+        self.remote = Some((network, prefix_len.min(32)));
+        self
+    }
+
+    pub fn with_local_port(mut self, port: u16) -> Self {
+        self.local_port = Some(port);
+        self
+    }
+
+    pub fn with_remote_port(mut self, port: u16) -> Self {
+        self.remote_port = Some(port);
+        self
+    }
+
+    pub fn with_owner(mut self, owner: ProcessId) -> Self {
+        self.owner = Some(owner);
+        self
+    }
+
+    pub fn matches(&self, packet: &PacketInfo) -> bool {
+        let in_network = |(network, prefix_len): (Ipv4Addr, u8)| {
+            let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+            u32::from(packet.remote) & mask == u32::from(network) & mask
+        };
+        self.direction.is_none_or(|d| d == packet.direction)
+            && self.interface.as_ref().is_none_or(|i| *i == packet.interface)
+            && self.protocol.is_none_or(|p| Some(p) == packet.protocol)
+            && self.remote.is_none_or(in_network)
+            && self.local_port.is_none_or(|p| Some(p) == packet.local_port)
+            && self.remote_port.is_none_or(|p| Some(p) == packet.remote_port)
+            && self.owner.is_none_or(|o| Some(o) == packet.owner)
+    }
+}
+
+/// Traffic matched by a rule
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RuleCounters {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+struct Entry {
+    id: RuleId,
+    rule: FirewallRule,
+    counters: RuleCounters,
+    /// Installed by a listen grant rather than an administrator
+    granted: bool,
+}
+
+/// Rule set as stored on disk
+#[derive(Serialize, Deserialize)]
+struct PersistedRules {
+    default_action: Action,
+    rules: Vec<FirewallRule>,
+}
+
+/// Callback run for every packet the filter denies
+pub type DenialListener = Arc<dyn Fn(&PacketInfo) + Send + Sync>;
+
+/// Capability-aware packet filter shared by network stacks
+pub struct Firewall {
+    capabilities: Arc<CapabilityManager>,
+    rules: Arc<Mutex<Vec<Entry>>>,
+    default_action: Arc<Mutex<Action>>,
+    default_counters: Arc<Mutex<RuleCounters>>,
+    next_rule_id: Arc<Mutex<u64>>,
+    denial_listeners: Arc<Mutex<Vec<DenialListener>>>,
+}
+
+impl Firewall {
+    /// Create a firewall that allows all traffic until rules are added
+    pub fn new(capabilities: Arc<CapabilityManager>) -> Self {
+        Firewall {
+            capabilities,
+            rules: Arc::new(Mutex::new(Vec::new())),
+            default_action: Arc::new(Mutex::new(Action::Allow)),
+            default_counters: Arc::new(Mutex::new(RuleCounters::default())),
+            next_rule_id: Arc::new(Mutex::new(1)),
+            denial_listeners: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Check that `token` grants `required` on one of the network `resources`
+    fn authorize(&self, token: CapabilityToken, resources: &[&str], required: Permission) -> Result<(), String> {
+        let capability = self.capabilities.validate(token).ok_or("Invalid capability")?;
+        let covered = matches!(&capability.resource, Resource::Network(name) if resources.contains(&name.as_str()));
+        if !covered || !self.capabilities.check_permission(token, required) {
+            return Err("Permission denied".to_string());
+        }
+        Ok(())
+    }
+
+    fn insert(&self, rule: FirewallRule, granted: bool) -> RuleId {
+        let id = {
+            let mut next_id = self.next_rule_id.lock().unwrap();
+            let id = RuleId(*next_id);
+            *next_id += 1;
+            id
+        };
+        self.rules.lock().unwrap().push(Entry {
+            id,
+            rule,
+            counters: RuleCounters::default(),
+            granted,
+        });
+        id
+    }
+
+    /// Append a rule; requires write access to the firewall resource
+    pub fn add_rule(&self, token: CapabilityToken, rule: FirewallRule) -> Result<RuleId, String> {
+        self.authorize(token, &[FIREWALL_RESOURCE], Permission::Write)?;
+        Ok(self.insert(rule, false))
+    }
+
+    pub fn remove_rule(&self, token: CapabilityToken, id: RuleId) -> Result<(), String> {
+        self.authorize(token, &[FIREWALL_RESOURCE], Permission::Write)?;
+        let mut rules = self.rules.lock().unwrap();
+        let index = rules.iter().position(|e| e.id == id).ok_or("Rule not found")?;
+        rules.remove(index);
+        Ok(())
+    }
+
+    /// Action for packets no rule matches
+    pub fn set_default_action(&self, token: CapabilityToken, action: Action) -> Result<(), String> {
+        self.authorize(token, &[FIREWALL_RESOURCE], Permission::Write)?;
+        *self.default_action.lock().unwrap() = action;
+        Ok(())
+    }
+
+    pub fn default_action(&self) -> Action {
+        *self.default_action.lock().unwrap()
+    }
+
+    /// Allow inbound traffic to a port owned by `owner`, if `token` grants
+    /// write access to the matching listen resource
+    pub fn grant_listen(
+        &self,
+        token: CapabilityToken,
+        owner: ProcessId,
+        protocol: Protocol,
+        port: u16,
+    ) -> Result<RuleId, String> {
+        let resource = listen_resource(port);
+        self.authorize(token, &[&resource, LISTEN_ANY_RESOURCE], Permission::Write)?;
+        let rule = FirewallRule::allow()
+            .with_direction(Direction::Inbound)
+            .with_protocol(protocol)
+            .with_local_port(port)
+            .with_owner(owner);
+        Ok(self.insert(rule, true))
+    }
+
+    /// Drop a rule installed by `grant_listen`
+    pub fn release_grant(&self, id: RuleId) {
+        self.rules.lock().unwrap().retain(|e| !(e.id == id && e.granted));
+    }
+
+    /// Rules in evaluation order
+    pub fn rules(&self) -> Vec<(RuleId, FirewallRule)> {
+        self.rules.lock().unwrap().iter().map(|e| (e.id, e.rule.clone())).collect()
+    }
+
+    pub fn counters(&self, id: RuleId) -> Option<RuleCounters> {
+        self.rules.lock().unwrap().iter().find(|e| e.id == id).map(|e| e.counters)
+    }
+
+    /// Traffic that fell through to the default action
+    pub fn default_counters(&self) -> RuleCounters {
+        *self.default_counters.lock().unwrap()
+    }
+
+    /// Run `listener` for each packet denied from now on
+    pub fn on_denied(&self, listener: DenialListener) {
+        self.denial_listeners.lock().unwrap().push(listener);
+    }
+
+    /// Decide a packet's fate, updating the counters of the deciding rule
+    pub fn check(&self, packet: &PacketInfo) -> Action {
+        let action = self.decide(packet);
+        if action == Action::Deny {
+            let listeners = self.denial_listeners.lock().unwrap().clone();
+            for listener in listeners {
+                listener(packet);
+            }
+        }
+        action
+    }
+
+    fn decide(&self, packet: &PacketInfo) -> Action {
+        let mut rules = self.rules.lock().unwrap();
+        let (action, counters) = match rules.iter_mut().find(|e| e.rule.matches(packet)) {
+            Some(entry) => (entry.rule.action, &mut entry.counters),
+            None => {
+                let mut counters = self.default_counters.lock().unwrap();
+                counters.packets += 1;
+                counters.bytes += packet.len as u64;
+                return self.default_action();
+            }
+        };
+        counters.packets += 1;
+        counters.bytes += packet.len as u64;
+        action
+    }
+
+    /// Write the administrator rules and default action to `path`
+    pub fn save(&self, vfs: &VirtualFileSystem, path: &Path) -> Result<(), String> {
+        let persisted = PersistedRules {
+            default_action: self.default_action(),
+            rules: self
+                .rules
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|e| !e.granted)
+                .map(|e| e.rule.clone())
+                .collect(),
+        };
+        let data = serde_json::to_vec_pretty(&persisted).map_err(|e| e.to_string())?;
+        vfs.write_file(path, &data).map_err(String::from)
+    }
+
+    /// Replace the administrator rules with those saved at `path`; listen
+    /// grants are kept. Returns the number of rules loaded.
+    pub fn load(&self, token: CapabilityToken, vfs: &VirtualFileSystem, path: &Path) -> Result<usize, String> {
+        self.authorize(token, &[FIREWALL_RESOURCE], Permission::Write)?;
+        let size = vfs.metadata(path)?.size as usize;
+        let handle = vfs.open(ProcessId::KERNEL, path, OpenOptions::read_only())?;
+        let mut data = vec![0u8; size];
+        let read = vfs.read(ProcessId::KERNEL, handle, &mut data);
+        vfs.close(ProcessId::KERNEL, handle)?;
+        read?;
+        let persisted: PersistedRules =
+            serde_json::from_slice(&data).map_err(|e| format!("Invalid firewall rules: {}", e))?;
+
+        self.rules.lock().unwrap().retain(|e| e.granted);
+        let count = persisted.rules.len();
+        for rule in persisted.rules {
+            self.insert(rule, false);
+        }
+        *self.default_action.lock().unwrap() = persisted.default_action;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn admin() -> (Firewall, CapabilityToken) {
+        let capabilities = Arc::new(CapabilityManager::new());
+        let token = capabilities.grant(Resource::Network(FIREWALL_RESOURCE.to_string()), Permission::ReadWrite);
+        (Firewall::new(capabilities), token)
+    }
+
+    fn inbound_tcp(remote: Ipv4Addr, local_port: u16) -> PacketInfo {
+        PacketInfo {
+            direction: Direction::Inbound,
+            interface: "eth0".to_string(),
+            protocol: Some(Protocol::Tcp),
+            remote,
+            local_port: Some(local_port),
+            remote_port: Some(40000),
+            owner: None,
+            len: 60,
+        }
+    }
+
+    #[test]
+    fn test_rule_changes_require_capability() {
+        let (firewall, token) = admin();
+        let other = firewall
+            .capabilities
+            .grant(Resource::Network("dns".to_string()), Permission::Full);
+        let read_only = firewall
+            .capabilities
+            .grant(Resource::Network(FIREWALL_RESOURCE.to_string()), Permission::Read);
+
+        assert!(firewall.add_rule(other, FirewallRule::deny()).is_err());
+        assert!(firewall.add_rule(read_only, FirewallRule::deny()).is_err());
+        assert!(firewall.set_default_action(CapabilityToken::new(999), Action::Deny).is_err());
+        let id = firewall.add_rule(token, FirewallRule::deny()).unwrap();
+        assert_eq!(firewall.rules().len(), 1);
+        firewall.remove_rule(token, id).unwrap();
+        assert!(firewall.rules().is_empty());
+    }
+
+    #[test]
+    fn test_first_match_and_counters() {
+        let (firewall, token) = admin();
+        let ssh = firewall
+            .add_rule(token, FirewallRule::allow().with_local_port(22).with_remote(Ipv4Addr::new(10, 0, 0, 0), 8))
+            .unwrap();
+        let inbound = firewall
+            .add_rule(token, FirewallRule::deny().with_direction(Direction::Inbound))
+            .unwrap();
+        let denied = Arc::new(Mutex::new(Vec::new()));
+        let heard = Arc::clone(&denied);
+        firewall.on_denied(Arc::new(move |packet: &PacketInfo| heard.lock().unwrap().push(packet.local_port)));
+
+        assert_eq!(firewall.check(&inbound_tcp(Ipv4Addr::new(10, 1, 2, 3), 22)), Action::Allow);
+        assert_eq!(firewall.check(&inbound_tcp(Ipv4Addr::new(192, 168, 0, 1), 22)), Action::Deny);
+        assert_eq!(firewall.check(&inbound_tcp(Ipv4Addr::new(10, 1, 2, 3), 80)), Action::Deny);
+        let mut outbound = inbound_tcp(Ipv4Addr::new(10, 1, 2, 3), 80);
+        outbound.direction = Direction::Outbound;
+        assert_eq!(firewall.check(&outbound), Action::Allow);
+
+        assert_eq!(firewall.counters(ssh), Some(RuleCounters { packets: 1, bytes: 60 }));
+        assert_eq!(firewall.counters(inbound).unwrap().packets, 2);
+        assert_eq!(firewall.default_counters().packets, 1);
+        assert_eq!(*denied.lock().unwrap(), vec![Some(22), Some(80)]);
+    }
+
+    #[test]
+    fn test_listen_grants() {
+        let (firewall, _) = admin();
+        let owner = ProcessId::new(7);
+        let web = firewall
+            .capabilities
+            .grant(Resource::Network(listen_resource(80)), Permission::Write);
+        assert!(firewall.grant_listen(web, owner, Protocol::Tcp, 443).is_err());
+
+        let id = firewall.grant_listen(web, owner, Protocol::Tcp, 80).unwrap();
+        let mut packet = inbound_tcp(Ipv4Addr::new(1, 2, 3, 4), 80);
+        packet.owner = Some(owner);
+        firewall.check(&packet);
+        assert_eq!(firewall.counters(id).unwrap().packets, 1);
+
+        firewall.release_grant(id);
+        assert!(firewall.rules().is_empty());
+    }
+
+    #[test]
+    fn test_rules_persist() {
+        let (firewall, token) = admin();
+        let vfs = VirtualFileSystem::new();
+        let path = Path::new("/etc/firewall/rules.json");
+        firewall
+            .add_rule(token, FirewallRule::allow().with_protocol(Protocol::Icmp).with_interface("eth0"))
+            .unwrap();
+        firewall.set_default_action(token, Action::Deny).unwrap();
+        let listener = firewall
+            .capabilities
+            .grant(Resource::Network(LISTEN_ANY_RESOURCE.to_string()), Permission::Full);
+        firewall.grant_listen(listener, ProcessId::new(1), Protocol::Udp, 53).unwrap();
+        firewall.save(&vfs, path).unwrap();
+
+        let (restored, token) = admin();
+        assert_eq!(restored.load(token, &vfs, path).unwrap(), 1);
+        assert_eq!(restored.default_action(), Action::Deny);
+        assert_eq!(restored.rules()[0].1, firewall.rules()[0].1);
+    }
+}
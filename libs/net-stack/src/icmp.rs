@@ -0,0 +1,82 @@
+//! ICMP echo (ping)
+
+use crate::ipv4::checksum;
+
+const ECHO_REPLY: u8 = 0;
+const ECHO_REQUEST: u8 = 8;
+const HEADER_LEN: usize = 8;
+
+/// ICMP echo request or reply
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IcmpEcho {
+    pub is_request: bool,
+    pub identifier: u16,
+    pub sequence: u16,
+    pub data: Vec<u8>,
+}
+
+impl IcmpEcho {
+    pub fn request(identifier: u16, sequence: u16, data: Vec<u8>) -> Self {
+        IcmpEcho {
+            is_request: true,
+            identifier,
+            sequence,
+            data,
+        }
+    }
+
+    /// Reply echoing this request's identifier, sequence, and data
+    pub fn reply(&self) -> Self {
+        IcmpEcho {
+            is_request: false,
+            ..self.clone()
+        }
+    }
+
+    /// Parse an echo message; other ICMP types yield `Ok(None)`
+    pub fn parse(bytes: &[u8]) -> Result<Option<Self>, String> {
+        if bytes.len() < HEADER_LEN {
+            return Err("ICMP message too short".to_string());
+        }
+        if checksum(bytes) != 0 {
+            return Err("ICMP checksum mismatch".to_string());
+        }
+        let is_request = match bytes[0] {
+            ECHO_REQUEST => true,
+            ECHO_REPLY => false,
+            _ => return Ok(None),
+        };
+        Ok(Some(IcmpEcho {
+            is_request,
+            identifier: u16::from_be_bytes([bytes[4], bytes[5]]),
+            sequence: u16::from_be_bytes([bytes[6], bytes[7]]),
+            data: bytes[HEADER_LEN..].to_vec(),
+        }))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.data.len());
+        bytes.push(if self.is_request { ECHO_REQUEST } else { ECHO_REPLY });
+        bytes.push(0);
+        bytes.extend_from_slice(&[0, 0]);
+        bytes.extend_from_slice(&self.identifier.to_be_bytes());
+        bytes.extend_from_slice(&self.sequence.to_be_bytes());
+        bytes.extend_from_slice(&self.data);
+        let sum = checksum(&bytes);
+        bytes[2..4].copy_from_slice(&sum.to_be_bytes());
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_echo_round_trip() {
+        let request = IcmpEcho::request(7, 1, b"ping".to_vec());
+        let parsed = IcmpEcho::parse(&request.to_bytes()).unwrap().unwrap();
+        assert_eq!(parsed, request);
+        assert!(!parsed.reply().is_request);
+    }
+}
@@ -0,0 +1,186 @@
+//! IPv4 packets and the Internet checksum
+
+use std::net::Ipv4Addr;
+
+pub const PROTOCOL_ICMP: u8 = 1;
+pub const PROTOCOL_TCP: u8 = 6;
+pub const PROTOCOL_UDP: u8 = 17;
+
+/// TTL given to locally originated packets
+pub const DEFAULT_TTL: u8 = 64;
+
+const HEADER_LEN: usize = 20;
+
+/// One's-complement sum used by IPv4, ICMP, UDP and TCP
+pub fn checksum(data: &[u8]) -> u16 {
+    finish_checksum(sum_words(data, 0))
+}
+
+/// Checksum over the IPv4 pseudo-header followed by `segment`
+pub fn pseudo_header_checksum(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, segment: &[u8]) -> u16 {
+    let mut pseudo = Vec::with_capacity(12);
+    pseudo.extend_from_slice(&src.octets());
+    pseudo.extend_from_slice(&dst.octets());
+    pseudo.push(0);
+    pseudo.push(protocol);
+    pseudo.extend_from_slice(&(segment.len() as u16).to_be_bytes());
+    finish_checksum(sum_words(segment, sum_words(&pseudo, 0)))
+}
+
+fn sum_words(data: &[u8], mut sum: u32) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    for word in &mut chunks {
+        sum += u16::from_be_bytes([word[0], word[1]]) as u32;
+    }
+    if let [last] = chunks.remainder() {
+        sum += (*last as u32) << 8;
+    }
+    sum
+}
+
+fn finish_checksum(mut sum: u32) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// IPv4 packet without options; fragments are not supported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ipv4Packet {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub protocol: u8,
+    pub ttl: u8,
+    pub identification: u16,
+    pub payload: Vec<u8>,
+}
+
+impl Ipv4Packet {
+    pub fn new(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, payload: Vec<u8>) -> Self {
+        Ipv4Packet {
+            src,
+            dst,
+            protocol,
+            ttl: DEFAULT_TTL,
+            identification: 0,
+            payload,
+        }
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < HEADER_LEN {
+            return Err("IPv4 packet too short".to_string());
+        }
+        if bytes[0] >> 4 != 4 {
+            return Err("Not an IPv4 packet".to_string());
+        }
+        let header_len = ((bytes[0] & 0x0f) as usize) * 4;
+        let total_len = u16::from_be_bytes([bytes[2], bytes[3]]) as usize;
+        if header_len < HEADER_LEN || total_len < header_len || total_len > bytes.len() {
+            return Err("Malformed IPv4 header".to_string());
+        }
+        if checksum(&bytes[..header_len]) != 0 {
+            return Err("IPv4 header checksum mismatch".to_string());
+        }
+        let flags_fragment = u16::from_be_bytes([bytes[6], bytes[7]]);
+        if flags_fragment & 0x3fff != 0 {
+            return Err("Fragmented IPv4 packets are not supported".to_string());
+        }
+
+        Ok(Ipv4Packet {
+            src: Ipv4Addr::new(bytes[12], bytes[13], bytes[14], bytes[15]),
+            dst: Ipv4Addr::new(bytes[16], bytes[17], bytes[18], bytes[19]),
+            protocol: bytes[9],
+            ttl: bytes[8],
+            identification: u16::from_be_bytes([bytes[4], bytes[5]]),
+            payload: bytes[header_len..total_len].to_vec(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let total_len = (HEADER_LEN + self.payload.len()) as u16;
+        let mut bytes = Vec::with_capacity(total_len as usize);
+        bytes.push(0x45);
+        bytes.push(0);
+        bytes.extend_from_slice(&total_len.to_be_bytes());
+        bytes.extend_from_slice(&self.identification.to_be_bytes());
+        // Don't Fragment
+        bytes.extend_from_slice(&0x4000u16.to_be_bytes());
+        bytes.push(self.ttl);
+        bytes.push(self.protocol);
+        bytes.extend_from_slice(&[0, 0]);
+        bytes.extend_from_slice(&self.src.octets());
+        bytes.extend_from_slice(&self.dst.octets());
+
+        let sum = checksum(&bytes);
+        bytes[10..12].copy_from_slice(&sum.to_be_bytes());
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+}
+
+/// Address configuration of an interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Config {
+    pub address: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub gateway: Option<Ipv4Addr>,
+}
+
+impl Ipv4Config {
+    pub fn new(address: Ipv4Addr, netmask: Ipv4Addr, gateway: Option<Ipv4Addr>) -> Self {
+        Ipv4Config {
+            address,
+            netmask,
+            gateway,
+        }
+    }
+
+    /// Whether `ip` is on the directly attached subnet
+    pub fn is_local(&self, ip: Ipv4Addr) -> bool {
+        let mask = u32::from(self.netmask);
+        u32::from(ip) & mask == u32::from(self.address) & mask
+    }
+
+    /// Directed broadcast address of the subnet
+    pub fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.address) | !u32::from(self.netmask))
+    }
+
+    /// First hop towards `dst`
+    pub fn next_hop(&self, dst: Ipv4Addr) -> Result<Ipv4Addr, String> {
+        if self.is_local(dst) {
+            Ok(dst)
+        } else {
+            self.gateway.ok_or_else(|| "No route to host".to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packet_round_trip_and_checksum() {
+        let packet = Ipv4Packet::new(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2), PROTOCOL_UDP, vec![9; 5]);
+        let mut bytes = packet.to_bytes();
+        assert_eq!(Ipv4Packet::parse(&bytes).unwrap(), packet);
+
+        bytes[8] = 1;
+        assert!(Ipv4Packet::parse(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_routing() {
+        let config = Ipv4Config::new(
+            Ipv4Addr::new(192, 168, 1, 10),
+            Ipv4Addr::new(255, 255, 255, 0),
+            Some(Ipv4Addr::new(192, 168, 1, 1)),
+        );
+        assert_eq!(config.next_hop(Ipv4Addr::new(192, 168, 1, 20)).unwrap(), Ipv4Addr::new(192, 168, 1, 20));
+        assert_eq!(config.next_hop(Ipv4Addr::new(8, 8, 8, 8)).unwrap(), Ipv4Addr::new(192, 168, 1, 1));
+        assert_eq!(config.broadcast(), Ipv4Addr::new(192, 168, 1, 255));
+    }
+}
@@ -0,0 +1,804 @@
+//! Network Protocol Stack for hairr OS
+//!
+//! Implements Ethernet framing, ARP, IPv4, ICMP, UDP, and TCP over any HAL
+//! `NetworkDevice`, with a socket-style API (bind/connect/send/recv) that
+//! services can also drive over IPC.
+//!
+//! The stack is polled: `poll` drains frames from the device and advances
+//! every protocol state machine. An attached `Firewall` filters traffic in
+//! both directions; sockets opened with the `_as` variants record their
+//! owning process so rules can match on it.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+
+use capability::CapabilityToken;
+use hal::NetworkDevice;
+use kernel::ProcessId;
+
+pub mod arp;
+pub mod ethernet;
+pub mod firewall;
+pub mod icmp;
+pub mod ipv4;
+pub mod link;
+pub mod service;
+pub mod socket;
+pub mod tcp;
+pub mod udp;
+
+pub use arp::{ArpCache, ArpOperation, ArpPacket};
+pub use ethernet::{EthernetFrame, MacAddress, BROADCAST_MAC};
+pub use firewall::{Firewall, FirewallRule, RuleCounters, RuleId};
+pub use icmp::IcmpEcho;
+pub use ipv4::{Ipv4Config, Ipv4Packet};
+pub use link::VirtualNic;
+pub use service::{SocketRequest, SocketResponse};
+pub use socket::{Datagram, SocketId};
+pub use tcp::{TcpConnection, TcpSegment, TcpState};
+pub use udp::UdpDatagram;
+
+use ethernet::{ETHERTYPE_ARP, ETHERTYPE_IPV4};
+use firewall::{Action, Direction, PacketInfo, Protocol};
+use ipv4::{PROTOCOL_ICMP, PROTOCOL_TCP, PROTOCOL_UDP};
+use socket::{Socket, SocketTable};
+use tcp::{FLAG_ACK, FLAG_RST, FLAG_SYN};
+
+/// Decides whether a process may open a connection to an address
+pub type ConnectCheck = Arc<dyn Fn(ProcessId, Ipv4Addr) -> Result<(), String> + Send + Sync>;
+
+/// Network stack bound to one interface
+pub struct NetStack {
+    device: Arc<Mutex<Box<dyn NetworkDevice>>>,
+    mac: MacAddress,
+    config: Arc<Mutex<Option<Ipv4Config>>>,
+    arp_cache: Arc<Mutex<ArpCache>>,
+    /// Packets waiting for their next hop to be resolved
+    pending: Arc<Mutex<Vec<(Ipv4Addr, Ipv4Packet)>>>,
+    sockets: Arc<Mutex<SocketTable>>,
+    echo_replies: Arc<Mutex<VecDeque<(Ipv4Addr, IcmpEcho)>>>,
+    next_ip_id: Arc<Mutex<u16>>,
+    firewall: Arc<Mutex<Option<AttachedFirewall>>>,
+    owners: Arc<Mutex<HashMap<SocketId, SocketOwner>>>,
+    connect_check: Arc<Mutex<Option<ConnectCheck>>>,
+}
+
+/// Filter applied to an interface, with the name rules see it under
+#[derive(Clone)]
+struct AttachedFirewall {
+    firewall: Arc<Firewall>,
+    interface: String,
+}
+
+/// Process owning a socket, and the listen grant it holds
+#[derive(Debug, Clone, Copy)]
+struct SocketOwner {
+    process: ProcessId,
+    grant: Option<RuleId>,
+}
+
+impl NetStack {
+    pub fn new(device: Box<dyn NetworkDevice>) -> Self {
+        let mac = device.mac_address();
+        NetStack {
+            device: Arc::new(Mutex::new(device)),
+            mac,
+            config: Arc::new(Mutex::new(None)),
+            arp_cache: Arc::new(Mutex::new(ArpCache::new())),
+            pending: Arc::new(Mutex::new(Vec::new())),
+            sockets: Arc::new(Mutex::new(SocketTable::new())),
+            echo_replies: Arc::new(Mutex::new(VecDeque::new())),
+            next_ip_id: Arc::new(Mutex::new(1)),
+            firewall: Arc::new(Mutex::new(None)),
+            owners: Arc::new(Mutex::new(HashMap::new())),
+            connect_check: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn mac_address(&self) -> MacAddress {
+        self.mac
+    }
+
+    /// Assign an address to the interface
+    pub fn configure(&self, config: Ipv4Config) {
+        *self.config.lock().unwrap() = Some(config);
+        self.arp_cache.lock().unwrap().clear();
+    }
+
+    /// Remove the interface address
+    pub fn deconfigure(&self) {
+        *self.config.lock().unwrap() = None;
+        self.arp_cache.lock().unwrap().clear();
+        self.pending.lock().unwrap().clear();
+    }
+
+    pub fn config(&self) -> Option<Ipv4Config> {
+        *self.config.lock().unwrap()
+    }
+
+    /// Filter this interface's traffic through `firewall` under `interface`
+    pub fn attach_firewall(&self, firewall: Arc<Firewall>, interface: &str) {
+        *self.firewall.lock().unwrap() = Some(AttachedFirewall {
+            firewall,
+            interface: interface.to_string(),
+        });
+    }
+
+    pub fn detach_firewall(&self) {
+        *self.firewall.lock().unwrap() = None;
+    }
+
+    /// Run `check` before every connection opened on behalf of a process
+    pub fn attach_connect_check(&self, check: ConnectCheck) {
+        *self.connect_check.lock().unwrap() = Some(check);
+    }
+
+    /// Whether the firewall lets `packet` through. Traffic on an existing
+    /// TCP stream was admitted with its opening segment, so only a stream's
+    /// outgoing SYN is checked against the rules.
+    fn permits(&self, direction: Direction, packet: &Ipv4Packet) -> bool {
+        let Some(AttachedFirewall { firewall, interface }) = self.firewall.lock().unwrap().clone() else {
+            return true;
+        };
+        let remote = match direction {
+            Direction::Inbound => packet.src,
+            Direction::Outbound => packet.dst,
+        };
+        let protocol = Protocol::from_number(packet.protocol);
+        let ports = match protocol {
+            Some(Protocol::Udp | Protocol::Tcp) if packet.payload.len() >= 4 => {
+                let src = u16::from_be_bytes([packet.payload[0], packet.payload[1]]);
+                let dst = u16::from_be_bytes([packet.payload[2], packet.payload[3]]);
+                match direction {
+                    Direction::Inbound => Some((dst, src)),
+                    Direction::Outbound => Some((src, dst)),
+                }
+            }
+            _ => None,
+        };
+
+        let mut owner = None;
+        if let Some((local_port, remote_port)) = ports {
+            let socket = self
+                .sockets
+                .lock()
+                .unwrap()
+                .socket_for(packet.protocol, local_port, (remote, remote_port));
+            if let Some((id, state)) = socket {
+                let opening = direction == Direction::Outbound && state == Some(TcpState::SynSent);
+                if state.is_some() && !opening {
+                    return true;
+                }
+                owner = self.owners.lock().unwrap().get(&id).map(|o| o.process);
+            }
+        }
+
+        let info = PacketInfo {
+            direction,
+            interface,
+            protocol,
+            remote,
+            local_port: ports.map(|p| p.0),
+            remote_port: ports.map(|p| p.1),
+            owner,
+            len: packet.payload.len(),
+        };
+        firewall.check(&info) == Action::Allow
+    }
+
+    /// Process every frame waiting on the device; returns the number handled
+    pub fn poll(&self) -> usize {
+        let mut handled = 0;
+        loop {
+            let frame = self.device.lock().unwrap().receive_packet();
+            match frame {
+                Some(bytes) => {
+                    // Malformed frames are dropped
+                    let _ = self.handle_frame(&bytes);
+                    handled += 1;
+                }
+                None => break,
+            }
+        }
+        self.reap();
+        handled
+    }
+
+    /// Drop finished streams and the ownership records of removed sockets
+    fn reap(&self) {
+        let mut table = self.sockets.lock().unwrap();
+        table.reap();
+        self.owners.lock().unwrap().retain(|id, _| table.sockets.contains_key(id));
+    }
+
+    fn transmit(&self, dst: MacAddress, ethertype: u16, payload: Vec<u8>) -> Result<(), String> {
+        let frame = EthernetFrame::new(dst, self.mac, ethertype, payload);
+        self.device.lock().unwrap().send_packet(&frame.to_bytes())
+    }
+
+    /// Send an IPv4 packet, resolving the next hop with ARP if needed
+    fn send_ip(&self, dst: Ipv4Addr, protocol: u8, payload: Vec<u8>) -> Result<(), String> {
+        let config = self.config();
+        let src = config.map_or(Ipv4Addr::UNSPECIFIED, |c| c.address);
+        let mut packet = Ipv4Packet::new(src, dst, protocol, payload);
+        {
+            let mut next_id = self.next_ip_id.lock().unwrap();
+            packet.identification = *next_id;
+            *next_id = next_id.wrapping_add(1);
+        }
+        if !self.permits(Direction::Outbound, &packet) {
+            return Err("Blocked by firewall".to_string());
+        }
+
+        if dst == Ipv4Addr::BROADCAST || config.is_some_and(|c| dst == c.broadcast()) {
+            return self.transmit(BROADCAST_MAC, ETHERTYPE_IPV4, packet.to_bytes());
+        }
+
+        let config = config.ok_or("Interface has no address")?;
+        let hop = config.next_hop(dst)?;
+        let mac = self.arp_cache.lock().unwrap().lookup(hop);
+        match mac {
+            Some(mac) => self.transmit(mac, ETHERTYPE_IPV4, packet.to_bytes()),
+            None => {
+                self.pending.lock().unwrap().push((hop, packet));
+                let request = ArpPacket::request(self.mac, config.address, hop);
+                self.transmit(BROADCAST_MAC, ETHERTYPE_ARP, request.to_bytes())
+            }
+        }
+    }
+
+    fn handle_frame(&self, bytes: &[u8]) -> Result<(), String> {
+        let frame = EthernetFrame::parse(bytes)?;
+        if frame.dst != self.mac && frame.dst != BROADCAST_MAC {
+            return Ok(());
+        }
+        match frame.ethertype {
+            ETHERTYPE_ARP => self.handle_arp(&frame.payload),
+            ETHERTYPE_IPV4 => self.handle_ipv4(&frame.payload),
+            _ => Ok(()),
+        }
+    }
+
+    fn handle_arp(&self, bytes: &[u8]) -> Result<(), String> {
+        let packet = ArpPacket::parse(bytes)?;
+        self.arp_cache.lock().unwrap().insert(packet.sender_ip, packet.sender_mac);
+
+        // Flush packets that were waiting on this neighbor
+        let ready: Vec<Ipv4Packet> = {
+            let mut pending = self.pending.lock().unwrap();
+            let (ready, waiting) = pending.drain(..).partition(|(hop, _)| *hop == packet.sender_ip);
+            *pending = waiting;
+            ready.into_iter().map(|(_, p)| p).collect()
+        };
+        for ip_packet in ready {
+            self.transmit(packet.sender_mac, ETHERTYPE_IPV4, ip_packet.to_bytes())?;
+        }
+
+        let ours = self.config().is_some_and(|c| c.address == packet.target_ip);
+        if packet.operation == ArpOperation::Request && ours {
+            let reply = ArpPacket::reply_to(&packet, self.mac);
+            self.transmit(packet.sender_mac, ETHERTYPE_ARP, reply.to_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn handle_ipv4(&self, bytes: &[u8]) -> Result<(), String> {
+        let packet = Ipv4Packet::parse(bytes)?;
+        let accepted = match self.config() {
+            // Without an address (e.g. during DHCP) accept everything on the link
+            None => true,
+            Some(config) => {
+                packet.dst == config.address || packet.dst == Ipv4Addr::BROADCAST || packet.dst == config.broadcast()
+            }
+        };
+        if !accepted || !self.permits(Direction::Inbound, &packet) {
+            return Ok(());
+        }
+
+        match packet.protocol {
+            PROTOCOL_ICMP => self.handle_icmp(&packet),
+            PROTOCOL_UDP => {
+                let datagram = UdpDatagram::parse(&packet.payload, packet.src, packet.dst)?;
+                if let Some(received) = self.sockets.lock().unwrap().udp_socket_for(datagram.dst_port) {
+                    received.push_back(Datagram {
+                        addr: packet.src,
+                        port: datagram.src_port,
+                        data: datagram.payload,
+                    });
+                }
+                Ok(())
+            }
+            PROTOCOL_TCP => {
+                let segment = TcpSegment::parse(&packet.payload, packet.src, packet.dst)?;
+                self.handle_tcp(packet.src, packet.dst, &segment)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn handle_icmp(&self, packet: &Ipv4Packet) -> Result<(), String> {
+        let echo = match IcmpEcho::parse(&packet.payload)? {
+            Some(echo) => echo,
+            None => return Ok(()),
+        };
+        if echo.is_request {
+            self.send_ip(packet.src, PROTOCOL_ICMP, echo.reply().to_bytes())
+        } else {
+            self.echo_replies.lock().unwrap().push_back((packet.src, echo));
+            Ok(())
+        }
+    }
+
+    fn handle_tcp(&self, src: Ipv4Addr, dst: Ipv4Addr, segment: &TcpSegment) -> Result<(), String> {
+        let replies = {
+            let mut table = self.sockets.lock().unwrap();
+            if let Some(connection) = table.stream_for(segment.dst_port, (src, segment.src_port)) {
+                connection.on_segment(segment)
+            } else if let Some(listener) = table
+                .listener_for(segment.dst_port)
+                .filter(|_| segment.has(FLAG_SYN) && !segment.has(FLAG_ACK))
+            {
+                let iss = table.next_iss();
+                let (connection, syn_ack) =
+                    TcpConnection::accept((dst, segment.dst_port), (src, segment.src_port), segment, iss);
+                let id = table.insert(Socket::Stream {
+                    connection,
+                    closed: false,
+                });
+                if let Some(Socket::Listener { backlog, .. }) = table.sockets.get_mut(&listener) {
+                    backlog.push_back(id);
+                }
+                // Accepted streams belong to the listener's owner
+                let mut owners = self.owners.lock().unwrap();
+                if let Some(owner) = owners.get(&listener).copied() {
+                    owners.insert(id, SocketOwner { grant: None, ..owner });
+                }
+                vec![syn_ack]
+            } else if !segment.has(FLAG_RST) {
+                vec![TcpSegment::reset_for(segment)]
+            } else {
+                Vec::new()
+            }
+        };
+
+        for reply in replies {
+            self.send_ip(src, PROTOCOL_TCP, reply.to_bytes(dst, src))?;
+        }
+        Ok(())
+    }
+
+    fn send_segment(&self, connection_remote: Ipv4Addr, local: Ipv4Addr, segment: TcpSegment) -> Result<(), String> {
+        self.send_ip(connection_remote, PROTOCOL_TCP, segment.to_bytes(local, connection_remote))
+    }
+
+    /// Bind a UDP socket; port 0 picks an ephemeral port. While a
+    /// firewall is attached a fixed port needs a capability, so only
+    /// `bind_udp_as` can bind one.
+    pub fn bind_udp(&self, port: u16) -> Result<SocketId, String> {
+        if port != 0 {
+            self.require_unfiltered()?;
+        }
+        self.open_udp(port)
+    }
+
+    /// Refuse while a firewall decides who may open ports
+    fn require_unfiltered(&self) -> Result<(), String> {
+        if self.firewall.lock().unwrap().is_some() {
+            return Err("Opening a port needs a listen capability".to_string());
+        }
+        Ok(())
+    }
+
+    fn open_udp(&self, port: u16) -> Result<SocketId, String> {
+        let mut table = self.sockets.lock().unwrap();
+        let port = if port == 0 {
+            table.ephemeral_port(SocketTable::udp_port_in_use)?
+        } else if table.udp_port_in_use(port) {
+            return Err("Port already in use".to_string());
+        } else {
+            port
+        };
+        Ok(table.insert(Socket::Udp {
+            port,
+            received: VecDeque::new(),
+        }))
+    }
+
+    /// Local port a UDP socket or TCP listener/stream is bound to
+    pub fn local_port(&self, socket: SocketId) -> Option<u16> {
+        match self.sockets.lock().unwrap().sockets.get(&socket)? {
+            Socket::Udp { port, .. } | Socket::Listener { port, .. } => Some(*port),
+            Socket::Stream { connection, .. } => Some(connection.local.1),
+        }
+    }
+
+    /// Send a datagram from a UDP socket
+    pub fn send_to(&self, socket: SocketId, addr: Ipv4Addr, port: u16, data: &[u8]) -> Result<usize, String> {
+        let src_port = match self.sockets.lock().unwrap().sockets.get(&socket) {
+            Some(Socket::Udp { port, .. }) => *port,
+            Some(_) => return Err("Not a UDP socket".to_string()),
+            None => return Err("Socket not found".to_string()),
+        };
+        let src = self.config().map_or(Ipv4Addr::UNSPECIFIED, |c| c.address);
+        let datagram = UdpDatagram::new(src_port, port, data.to_vec());
+        self.send_ip(addr, PROTOCOL_UDP, datagram.to_bytes(src, addr))?;
+        Ok(data.len())
+    }
+
+    /// Take the next datagram received on a UDP socket
+    pub fn recv_from(&self, socket: SocketId) -> Result<Option<Datagram>, String> {
+        match self.sockets.lock().unwrap().sockets.get_mut(&socket) {
+            Some(Socket::Udp { received, .. }) => Ok(received.pop_front()),
+            Some(_) => Err("Not a UDP socket".to_string()),
+            None => Err("Socket not found".to_string()),
+        }
+    }
+
+    /// Listen for TCP connections on `port`; while a firewall is attached
+    /// only `listen_as` can
+    pub fn listen(&self, port: u16) -> Result<SocketId, String> {
+        self.require_unfiltered()?;
+        self.open_listener(port)
+    }
+
+    fn open_listener(&self, port: u16) -> Result<SocketId, String> {
+        let mut table = self.sockets.lock().unwrap();
+        if table.tcp_port_in_use(port) {
+            return Err("Port already in use".to_string());
+        }
+        Ok(table.insert(Socket::Listener {
+            port,
+            backlog: VecDeque::new(),
+        }))
+    }
+
+    /// Take the next established connection from a listener
+    pub fn accept(&self, listener: SocketId) -> Result<Option<SocketId>, String> {
+        let mut table = self.sockets.lock().unwrap();
+        let backlog: Vec<SocketId> = match table.sockets.get(&listener) {
+            Some(Socket::Listener { backlog, .. }) => backlog.iter().copied().collect(),
+            Some(_) => return Err("Not a listening socket".to_string()),
+            None => return Err("Socket not found".to_string()),
+        };
+
+        let ready = backlog.into_iter().find(|id| {
+            matches!(
+                table.sockets.get(id),
+                Some(Socket::Stream { connection, .. }) if connection.state() != TcpState::SynReceived
+            )
+        });
+        if let (Some(id), Some(Socket::Listener { backlog, .. })) = (ready, table.sockets.get_mut(&listener)) {
+            backlog.retain(|b| *b != id);
+        }
+        Ok(ready)
+    }
+
+    /// Open a TCP connection; completes as `poll` processes the handshake
+    pub fn connect(&self, addr: Ipv4Addr, port: u16) -> Result<SocketId, String> {
+        self.open_connection(None, addr, port)
+    }
+
+    fn open_connection(&self, owner: Option<ProcessId>, addr: Ipv4Addr, port: u16) -> Result<SocketId, String> {
+        let config = self.config().ok_or("Interface has no address")?;
+        let (id, syn) = {
+            let mut table = self.sockets.lock().unwrap();
+            let local_port = table.ephemeral_port(SocketTable::tcp_port_in_use)?;
+            let iss = table.next_iss();
+            let (connection, syn) = TcpConnection::connect((config.address, local_port), (addr, port), iss);
+            let id = table.insert(Socket::Stream {
+                connection,
+                closed: false,
+            });
+            (id, syn)
+        };
+        if let Some(owner) = owner {
+            self.set_owner(id, owner, None);
+        }
+        if let Err(e) = self.send_segment(addr, config.address, syn) {
+            self.sockets.lock().unwrap().sockets.remove(&id);
+            self.owners.lock().unwrap().remove(&id);
+            return Err(e);
+        }
+        Ok(id)
+    }
+
+    /// Send data on a connected TCP socket
+    pub fn send(&self, socket: SocketId, data: &[u8]) -> Result<usize, String> {
+        let (remote, local, segment) = {
+            let mut table = self.sockets.lock().unwrap();
+            match table.sockets.get_mut(&socket) {
+                Some(Socket::Stream { connection, closed: false }) => {
+                    let segment = connection.send(data)?;
+                    (connection.remote.0, connection.local.0, segment)
+                }
+                Some(_) => return Err("Not a connected TCP socket".to_string()),
+                None => return Err("Socket not found".to_string()),
+            }
+        };
+        self.send_segment(remote, local, segment)?;
+        Ok(data.len())
+    }
+
+    /// Read up to `max` bytes received on a TCP socket
+    pub fn recv(&self, socket: SocketId, max: usize) -> Result<Vec<u8>, String> {
+        match self.sockets.lock().unwrap().sockets.get_mut(&socket) {
+            Some(Socket::Stream { connection, .. }) => Ok(connection.read(max)),
+            Some(_) => Err("Not a TCP stream".to_string()),
+            None => Err("Socket not found".to_string()),
+        }
+    }
+
+    /// State of a TCP stream
+    pub fn tcp_state(&self, socket: SocketId) -> Option<TcpState> {
+        match self.sockets.lock().unwrap().sockets.get(&socket)? {
+            Socket::Stream { connection, .. } => Some(connection.state()),
+            _ => None,
+        }
+    }
+
+    /// Close a socket; TCP streams finish their shutdown handshake in the background
+    pub fn close(&self, socket: SocketId) -> Result<(), String> {
+        let fin = {
+            let mut table = self.sockets.lock().unwrap();
+            match table.sockets.get_mut(&socket) {
+                Some(Socket::Stream { connection, closed }) => {
+                    *closed = true;
+                    connection
+                        .close()
+                        .map(|fin| (connection.remote.0, connection.local.0, fin))
+                }
+                Some(_) => {
+                    table.sockets.remove(&socket);
+                    None
+                }
+                None => return Err("Socket not found".to_string()),
+            }
+        };
+        if let Some((remote, local, fin)) = fin {
+            self.send_segment(remote, local, fin)?;
+        }
+        let grant = self.owners.lock().unwrap().get(&socket).and_then(|o| o.grant);
+        if let (Some(grant), Some(attached)) = (grant, self.firewall.lock().unwrap().as_ref()) {
+            attached.firewall.release_grant(grant);
+        }
+        self.reap();
+        Ok(())
+    }
+
+    /// Acquire a listen grant for `port` when a firewall is attached
+    fn grant_listen(
+        &self,
+        owner: ProcessId,
+        token: CapabilityToken,
+        protocol: Protocol,
+        port: u16,
+    ) -> Result<Option<RuleId>, String> {
+        match self.firewall.lock().unwrap().as_ref() {
+            Some(attached) => attached.firewall.grant_listen(token, owner, protocol, port).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn set_owner(&self, socket: SocketId, process: ProcessId, grant: Option<RuleId>) {
+        self.owners.lock().unwrap().insert(socket, SocketOwner { process, grant });
+    }
+
+    /// Process owning a socket opened with one of the `_as` calls
+    pub fn socket_owner(&self, socket: SocketId) -> Option<ProcessId> {
+        self.owners.lock().unwrap().get(&socket).map(|o| o.process)
+    }
+
+    /// Bind a UDP socket on behalf of `owner`. Binding a fixed port needs a
+    /// listen capability for it when a firewall is attached.
+    pub fn bind_udp_as(&self, owner: ProcessId, port: u16, token: CapabilityToken) -> Result<SocketId, String> {
+        let socket = self.open_udp(port)?;
+        let grant = if port == 0 {
+            Ok(None)
+        } else {
+            self.grant_listen(owner, token, Protocol::Udp, port)
+        };
+        match grant {
+            Ok(grant) => {
+                self.set_owner(socket, owner, grant);
+                Ok(socket)
+            }
+            Err(e) => {
+                self.close(socket)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Listen for TCP connections on behalf of `owner`, which needs a listen
+    /// capability for `port` when a firewall is attached
+    pub fn listen_as(&self, owner: ProcessId, port: u16, token: CapabilityToken) -> Result<SocketId, String> {
+        let socket = self.open_listener(port)?;
+        match self.grant_listen(owner, token, Protocol::Tcp, port) {
+            Ok(grant) => {
+                self.set_owner(socket, owner, grant);
+                Ok(socket)
+            }
+            Err(e) => {
+                self.close(socket)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Open a TCP connection on behalf of `owner`, if the attached connect
+    /// check lets it reach `addr`
+    pub fn connect_as(&self, owner: ProcessId, addr: Ipv4Addr, port: u16) -> Result<SocketId, String> {
+        let check = self.connect_check.lock().unwrap().clone();
+        if let Some(check) = check {
+            check(owner, addr)?;
+        }
+        self.open_connection(Some(owner), addr, port)
+    }
+
+    /// Send an ICMP echo request
+    pub fn ping(&self, addr: Ipv4Addr, identifier: u16, sequence: u16, data: &[u8]) -> Result<(), String> {
+        let request = IcmpEcho::request(identifier, sequence, data.to_vec());
+        self.send_ip(addr, PROTOCOL_ICMP, request.to_bytes())
+    }
+
+    /// Drain received echo replies
+    pub fn echo_replies(&self) -> Vec<(Ipv4Addr, IcmpEcho)> {
+        self.echo_replies.lock().unwrap().drain(..).collect()
+    }
+
+    /// Whether a neighbor's hardware address is known
+    pub fn arp_lookup(&self, ip: Ipv4Addr) -> Option<MacAddress> {
+        self.arp_cache.lock().unwrap().lookup(ip)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use capability::{CapabilityManager, Permission, Resource};
+
+    pub(crate) fn connected_pair() -> (NetStack, NetStack) {
+        let (a, b) = VirtualNic::pair([2, 0, 0, 0, 0, 1], [2, 0, 0, 0, 0, 2]);
+        let netmask = Ipv4Addr::new(255, 255, 255, 0);
+        let client = NetStack::new(Box::new(a));
+        let server = NetStack::new(Box::new(b));
+        client.configure(Ipv4Config::new(Ipv4Addr::new(10, 0, 0, 1), netmask, None));
+        server.configure(Ipv4Config::new(Ipv4Addr::new(10, 0, 0, 2), netmask, None));
+        (client, server)
+    }
+
+    pub(crate) fn pump(a: &NetStack, b: &NetStack) {
+        while a.poll() + b.poll() > 0 {}
+    }
+
+    #[test]
+    fn test_arp_resolution_and_ping() {
+        let (client, server) = connected_pair();
+        client.ping(Ipv4Addr::new(10, 0, 0, 2), 1, 1, b"hi").unwrap();
+        pump(&client, &server);
+
+        assert_eq!(client.arp_lookup(Ipv4Addr::new(10, 0, 0, 2)), Some([2, 0, 0, 0, 0, 2]));
+        let replies = client.echo_replies();
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].0, Ipv4Addr::new(10, 0, 0, 2));
+        assert_eq!(replies[0].1.data, b"hi");
+    }
+
+    #[test]
+    fn test_udp_exchange() {
+        let (client, server) = connected_pair();
+        let listener = server.bind_udp(53).unwrap();
+        let socket = client.bind_udp(0).unwrap();
+        assert!(server.bind_udp(53).is_err());
+
+        client.send_to(socket, Ipv4Addr::new(10, 0, 0, 2), 53, b"query").unwrap();
+        pump(&client, &server);
+        let datagram = server.recv_from(listener).unwrap().unwrap();
+        assert_eq!(datagram.data, b"query");
+        assert_eq!(datagram.port, client.local_port(socket).unwrap());
+
+        server.send_to(listener, datagram.addr, datagram.port, b"answer").unwrap();
+        pump(&client, &server);
+        assert_eq!(client.recv_from(socket).unwrap().unwrap().data, b"answer");
+    }
+
+    #[test]
+    fn test_tcp_connect_send_recv_close() {
+        let (client, server) = connected_pair();
+        let listener = server.listen(80).unwrap();
+        let stream = client.connect(Ipv4Addr::new(10, 0, 0, 2), 80).unwrap();
+        pump(&client, &server);
+        assert_eq!(client.tcp_state(stream), Some(TcpState::Established));
+
+        let accepted = server.accept(listener).unwrap().unwrap();
+        client.send(stream, b"GET /").unwrap();
+        pump(&client, &server);
+        assert_eq!(server.recv(accepted, 64).unwrap(), b"GET /");
+
+        server.send(accepted, b"200 OK").unwrap();
+        pump(&client, &server);
+        assert_eq!(client.recv(stream, 64).unwrap(), b"200 OK");
+
+        client.close(stream).unwrap();
+        pump(&client, &server);
+        assert_eq!(server.tcp_state(accepted), Some(TcpState::CloseWait));
+        server.close(accepted).unwrap();
+        pump(&client, &server);
+        assert!(server.tcp_state(accepted).is_none());
+        assert!(client.tcp_state(stream).is_none());
+    }
+
+    #[test]
+    fn test_firewall_filters_by_port_and_owner() {
+        let (client, server) = connected_pair();
+        let capabilities = Arc::new(CapabilityManager::new());
+        let admin = capabilities.grant(Resource::Network(firewall::FIREWALL_RESOURCE.to_string()), Permission::Write);
+        let firewall = Arc::new(Firewall::new(Arc::clone(&capabilities)));
+        firewall.set_default_action(admin, Action::Deny).unwrap();
+        server.attach_firewall(Arc::clone(&firewall), "eth0");
+        let allow_out = firewall
+            .add_rule(admin, FirewallRule::allow().with_direction(Direction::Outbound).with_interface("eth0"))
+            .unwrap();
+
+        // Listening needs a capability for the port
+        let web = ProcessId::new(42);
+        let token = capabilities.grant(Resource::Network(firewall::listen_resource(80)), Permission::Write);
+        assert!(server.listen_as(web, 8080, token).is_err());
+        let listener = server.listen_as(web, 80, token).unwrap();
+        assert!(server.bind_udp_as(web, 53, token).is_err());
+        assert!(server.listen(81).is_err());
+        assert!(server.bind_udp(53).is_err());
+
+        let stream = client.connect(Ipv4Addr::new(10, 0, 0, 2), 80).unwrap();
+        pump(&client, &server);
+        assert_eq!(client.tcp_state(stream), Some(TcpState::Established));
+        let accepted = server.accept(listener).unwrap().unwrap();
+        assert_eq!(server.socket_owner(accepted), Some(web));
+        client.send(stream, b"hello").unwrap();
+        pump(&client, &server);
+        assert_eq!(server.recv(accepted, 16).unwrap(), b"hello");
+
+        // Ports without a grant stay closed, and the drops are counted
+        client.ping(Ipv4Addr::new(10, 0, 0, 2), 1, 1, b"x").unwrap();
+        let blocked = client.connect(Ipv4Addr::new(10, 0, 0, 2), 22).unwrap();
+        pump(&client, &server);
+        assert!(client.echo_replies().is_empty());
+        assert_eq!(client.tcp_state(blocked), Some(TcpState::SynSent));
+        assert_eq!(firewall.default_counters().packets, 2);
+
+        let socket = server.bind_udp(0).unwrap();
+        server.send_to(socket, Ipv4Addr::new(10, 0, 0, 1), 9, b"out").unwrap();
+        assert_eq!(firewall.counters(allow_out).unwrap().packets, 1);
+        firewall.remove_rule(admin, allow_out).unwrap();
+        assert!(server.send_to(socket, Ipv4Addr::new(10, 0, 0, 1), 9, b"out").is_err());
+
+        server.close(listener).unwrap();
+        assert!(firewall.rules().is_empty());
+    }
+
+    #[test]
+    fn test_connect_check() {
+        let (client, server) = connected_pair();
+        let server_addr = Ipv4Addr::new(10, 0, 0, 2);
+        client.attach_connect_check(Arc::new(move |process, addr| {
+            if process == ProcessId::new(7) && addr == server_addr {
+                Err("Blocked".to_string())
+            } else {
+                Ok(())
+            }
+        }));
+        assert_eq!(client.connect_as(ProcessId::new(7), server_addr, 80).unwrap_err(), "Blocked");
+        assert!(client.connect_as(ProcessId::new(8), server_addr, 80).is_ok());
+        pump(&client, &server);
+    }
+
+    #[test]
+    fn test_connection_refused() {
+        let (client, server) = connected_pair();
+        let stream = client.connect(Ipv4Addr::new(10, 0, 0, 2), 8080).unwrap();
+        pump(&client, &server);
+        assert_eq!(client.tcp_state(stream), Some(TcpState::Closed));
+        assert!(client.send(stream, b"x").is_err());
+    }
+}
@@ -0,0 +1,189 @@
+//! Socket API over IPC
+//!
+//! Services send `SocketRequest`s as JSON in `Message::Request` and get a
+//! `SocketResponse` back in a `Message::Response` with the same id.
+//! Requests act on behalf of the process that owns the request channel:
+//! opening a port takes its listen capability, and connections go through
+//! the stack's connect check for it.
+
+use std::net::Ipv4Addr;
+
+use capability::CapabilityToken;
+use ipc::service::handle_json;
+use ipc::{ChannelId, IPCManager, Message};
+use kernel::ProcessId;
+use serde::{Deserialize, Serialize};
+
+use crate::{Datagram, NetStack, SocketId, TcpState};
+
+/// Socket operation requested by a client service
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum SocketRequest {
+    BindUdp { port: u16, token: CapabilityToken },
+    SendTo { socket: SocketId, addr: Ipv4Addr, port: u16, data: Vec<u8> },
+    RecvFrom { socket: SocketId },
+    Listen { port: u16, token: CapabilityToken },
+    Accept { socket: SocketId },
+    Connect { addr: Ipv4Addr, port: u16 },
+    Send { socket: SocketId, data: Vec<u8> },
+    Recv { socket: SocketId, max: usize },
+    State { socket: SocketId },
+    Close { socket: SocketId },
+}
+
+/// Result of a socket operation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum SocketResponse {
+    Socket { socket: SocketId },
+    Sent { len: usize },
+    Datagram { datagram: Option<Datagram> },
+    Accepted { socket: Option<SocketId> },
+    Data { data: Vec<u8> },
+    State { state: Option<TcpState> },
+    Ok,
+    Error { message: String },
+}
+
+impl NetStack {
+    /// Execute one socket request from `caller`
+    pub fn handle_request(&self, caller: ProcessId, request: SocketRequest) -> SocketResponse {
+        let result = match request {
+            SocketRequest::BindUdp { port, token } => {
+                self.bind_udp_as(caller, port, token).map(|socket| SocketResponse::Socket { socket })
+            }
+            SocketRequest::SendTo {
+                socket,
+                addr,
+                port,
+                data,
+            } => self.send_to(socket, addr, port, &data).map(|len| SocketResponse::Sent { len }),
+            SocketRequest::RecvFrom { socket } => {
+                self.recv_from(socket).map(|datagram| SocketResponse::Datagram { datagram })
+            }
+            SocketRequest::Listen { port, token } => {
+                self.listen_as(caller, port, token).map(|socket| SocketResponse::Socket { socket })
+            }
+            SocketRequest::Accept { socket } => {
+                self.accept(socket).map(|socket| SocketResponse::Accepted { socket })
+            }
+            SocketRequest::Connect { addr, port } => {
+                self.connect_as(caller, addr, port).map(|socket| SocketResponse::Socket { socket })
+            }
+            SocketRequest::Send { socket, data } => self.send(socket, &data).map(|len| SocketResponse::Sent { len }),
+            SocketRequest::Recv { socket, max } => self.recv(socket, max).map(|data| SocketResponse::Data { data }),
+            SocketRequest::State { socket } => Ok(SocketResponse::State {
+                state: self.tcp_state(socket),
+            }),
+            SocketRequest::Close { socket } => self.close(socket).map(|_| SocketResponse::Ok),
+        };
+        result.unwrap_or_else(|message| SocketResponse::Error { message })
+    }
+
+    /// Answer an IPC request message from `caller`; other message kinds
+    /// are ignored
+    pub fn handle_message(&self, caller: ProcessId, message: &Message) -> Option<Message> {
+        handle_json("socket", message, |request| self.handle_request(caller, request))
+    }
+
+    /// Answer every pending request on `requests`, replying on `replies`;
+    /// the process owning `requests` is the caller
+    pub fn serve(&self, ipc: &IPCManager, requests: ChannelId, replies: ChannelId) -> Result<usize, String> {
+        let caller = ipc
+            .get_channel(requests)
+            .and_then(|channel| channel.owner())
+            .ok_or("Request channel has no owning process")?;
+        let mut served = 0;
+        while let Some(message) = ipc.receive_message(requests)? {
+            if let Some(reply) = self.handle_message(caller, &message) {
+                ipc.send_message(replies, reply)?;
+                served += 1;
+            }
+        }
+        Ok(served)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firewall::{listen_resource, Firewall};
+    use crate::tests::{connected_pair, pump};
+    use capability::{CapabilityManager, Permission, Resource};
+    use std::sync::Arc;
+    use ipc::ERROR_BAD_REQUEST;
+
+    const CALLER: ProcessId = ProcessId::KERNEL;
+
+    fn call(stack: &NetStack, id: u64, request: &SocketRequest) -> SocketResponse {
+        let message = Message::Request {
+            id,
+            data: serde_json::to_vec(request).unwrap(),
+        };
+        match stack.handle_message(CALLER, &message) {
+            Some(Message::Response { id: reply_id, data }) => {
+                assert_eq!(reply_id, id);
+                serde_json::from_slice(&data).unwrap()
+            }
+            other => panic!("unexpected reply: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_udp_over_ipc() {
+        let (client, server) = connected_pair();
+        let token = CapabilityToken::new(0);
+        let SocketResponse::Socket { socket: listener } = call(&server, 1, &SocketRequest::BindUdp { port: 7, token }) else {
+            panic!("bind failed");
+        };
+        let SocketResponse::Socket { socket } = call(&client, 2, &SocketRequest::BindUdp { port: 0, token }) else {
+            panic!("bind failed");
+        };
+
+        let send = SocketRequest::SendTo {
+            socket,
+            addr: Ipv4Addr::new(10, 0, 0, 2),
+            port: 7,
+            data: b"echo".to_vec(),
+        };
+        assert_eq!(call(&client, 3, &send), SocketResponse::Sent { len: 4 });
+        pump(&client, &server);
+
+        match call(&server, 4, &SocketRequest::RecvFrom { socket: listener }) {
+            SocketResponse::Datagram { datagram: Some(d) } => assert_eq!(d.data, b"echo"),
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_errors_are_reported() {
+        let (client, _server) = connected_pair();
+        let response = call(&client, 1, &SocketRequest::Recv { socket: SocketId::new(99), max: 10 });
+        assert!(matches!(response, SocketResponse::Error { .. }));
+
+        let bad = Message::Request { id: 2, data: b"{}".to_vec() };
+        assert!(matches!(client.handle_message(CALLER, &bad), Some(Message::Error { code: ERROR_BAD_REQUEST, .. })));
+        assert!(client.handle_message(CALLER, &Message::Text("hi".to_string())).is_none());
+    }
+
+    #[test]
+    fn test_ports_need_capability_over_ipc() {
+        let (_client, server) = connected_pair();
+        let capabilities = Arc::new(CapabilityManager::new());
+        server.attach_firewall(Arc::new(Firewall::new(Arc::clone(&capabilities))), "eth0");
+
+        // The firewall allows traffic by default, but not unauthorized ports
+        let stranger = capabilities.grant(Resource::Network("dns".to_string()), Permission::Full);
+        let listen = SocketRequest::Listen { port: 80, token: stranger };
+        assert!(matches!(call(&server, 1, &listen), SocketResponse::Error { .. }));
+        let bind = SocketRequest::BindUdp { port: 53, token: stranger };
+        assert!(matches!(call(&server, 2, &bind), SocketResponse::Error { .. }));
+
+        let token = capabilities.grant(Resource::Network(listen_resource(80)), Permission::Write);
+        let SocketResponse::Socket { socket } = call(&server, 3, &SocketRequest::Listen { port: 80, token }) else {
+            panic!("listen failed");
+        };
+        assert_eq!(server.socket_owner(socket), Some(CALLER));
+    }
+}
@@ -0,0 +1,172 @@
+//! Socket table
+
+use std::collections::{HashMap, VecDeque};
+use std::net::Ipv4Addr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ipv4::{PROTOCOL_TCP, PROTOCOL_UDP};
+use crate::tcp::{TcpConnection, TcpState};
+
+/// First port handed out for ephemeral bindings
+pub const EPHEMERAL_PORT_START: u16 = 49152;
+
+/// Socket identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct SocketId(u64);
+
+impl SocketId {
+    pub fn new(id: u64) -> Self {
+        SocketId(id)
+    }
+}
+
+/// Datagram received on a UDP socket
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Datagram {
+    pub addr: Ipv4Addr,
+    pub port: u16,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub(crate) enum Socket {
+    Udp {
+        port: u16,
+        received: VecDeque<Datagram>,
+    },
+    Listener {
+        port: u16,
+        backlog: VecDeque<SocketId>,
+    },
+    Stream {
+        connection: TcpConnection,
+        /// Closed by the application; reaped once the connection finishes
+        closed: bool,
+    },
+}
+
+#[derive(Debug)]
+pub(crate) struct SocketTable {
+    pub(crate) sockets: HashMap<SocketId, Socket>,
+    next_id: u64,
+    next_port: u16,
+    next_iss: u32,
+}
+
+impl SocketTable {
+    pub(crate) fn new() -> Self {
+        SocketTable {
+            sockets: HashMap::new(),
+            next_id: 1,
+            next_port: EPHEMERAL_PORT_START,
+            next_iss: 1000,
+        }
+    }
+
+    pub(crate) fn insert(&mut self, socket: Socket) -> SocketId {
+        let id = SocketId(self.next_id);
+        self.next_id += 1;
+        self.sockets.insert(id, socket);
+        id
+    }
+
+    /// Initial sequence number for a new connection
+    pub(crate) fn next_iss(&mut self) -> u32 {
+        let iss = self.next_iss;
+        self.next_iss = self.next_iss.wrapping_add(64000);
+        iss
+    }
+
+    pub(crate) fn udp_port_in_use(&self, port: u16) -> bool {
+        self.sockets
+            .values()
+            .any(|s| matches!(s, Socket::Udp { port: p, .. } if *p == port))
+    }
+
+    pub(crate) fn tcp_port_in_use(&self, port: u16) -> bool {
+        self.sockets.values().any(|s| match s {
+            Socket::Listener { port: p, .. } => *p == port,
+            Socket::Stream { connection, .. } => connection.local.1 == port,
+            Socket::Udp { .. } => false,
+        })
+    }
+
+    /// Allocate an unused ephemeral port
+    pub(crate) fn ephemeral_port(&mut self, in_use: impl Fn(&Self, u16) -> bool) -> Result<u16, String> {
+        for _ in EPHEMERAL_PORT_START..=u16::MAX {
+            let port = self.next_port;
+            self.next_port = if port == u16::MAX { EPHEMERAL_PORT_START } else { port + 1 };
+            if !in_use(self, port) {
+                return Ok(port);
+            }
+        }
+        Err("No ephemeral ports available".to_string())
+    }
+
+    pub(crate) fn udp_socket_for(&mut self, port: u16) -> Option<&mut VecDeque<Datagram>> {
+        self.sockets.values_mut().find_map(|s| match s {
+            Socket::Udp { port: p, received } if *p == port => Some(received),
+            _ => None,
+        })
+    }
+
+    pub(crate) fn stream_for(&mut self, local_port: u16, remote: (Ipv4Addr, u16)) -> Option<&mut TcpConnection> {
+        self.sockets.values_mut().find_map(|s| match s {
+            Socket::Stream { connection, .. }
+                if connection.local.1 == local_port
+                    && connection.remote == remote
+                    && connection.state() != TcpState::Closed =>
+            {
+                Some(connection)
+            }
+            _ => None,
+        })
+    }
+
+    pub(crate) fn listener_for(&mut self, port: u16) -> Option<SocketId> {
+        self.sockets.iter().find_map(|(id, s)| match s {
+            Socket::Listener { port: p, .. } if *p == port => Some(*id),
+            _ => None,
+        })
+    }
+
+    /// Socket handling traffic on `local_port` from `remote`, with the
+    /// state of the connection if it is a TCP stream
+    pub(crate) fn socket_for(
+        &self,
+        protocol: u8,
+        local_port: u16,
+        remote: (Ipv4Addr, u16),
+    ) -> Option<(SocketId, Option<TcpState>)> {
+        let find = |matches: &dyn Fn(&Socket) -> bool| self.sockets.iter().find(|(_, s)| matches(s)).map(|(id, _)| *id);
+        match protocol {
+            PROTOCOL_UDP => find(&|s| matches!(s, Socket::Udp { port, .. } if *port == local_port)).map(|id| (id, None)),
+            PROTOCOL_TCP => self
+                .sockets
+                .iter()
+                .find_map(|(id, s)| match s {
+                    Socket::Stream { connection, .. }
+                        if connection.local.1 == local_port
+                            && connection.remote == remote
+                            && connection.state() != TcpState::Closed =>
+                    {
+                        Some((*id, Some(connection.state())))
+                    }
+                    _ => None,
+                })
+                .or_else(|| find(&|s| matches!(s, Socket::Listener { port, .. } if *port == local_port)).map(|id| (id, None))),
+            _ => None,
+        }
+    }
+
+    /// Drop application-closed streams whose connection has finished
+    pub(crate) fn reap(&mut self) {
+        self.sockets.retain(|_, s| match s {
+            Socket::Stream { connection, closed } => {
+                !(*closed && matches!(connection.state(), TcpState::Closed | TcpState::TimeWait))
+            }
+            _ => true,
+        });
+    }
+}
@@ -0,0 +1,390 @@
+//! TCP segments and the connection state machine
+//!
+//! Implements the RFC 793 states for connection setup, in-order data
+//! transfer, and both active and passive close. Segments arriving out of
+//! order are dropped and re-acknowledged; retransmission is left to the
+//! peer, which suits the reliable simulated links the stack runs on.
+
+use std::collections::VecDeque;
+use std::net::Ipv4Addr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ipv4::{pseudo_header_checksum, PROTOCOL_TCP};
+
+pub const FLAG_FIN: u8 = 0x01;
+pub const FLAG_SYN: u8 = 0x02;
+pub const FLAG_RST: u8 = 0x04;
+pub const FLAG_PSH: u8 = 0x08;
+pub const FLAG_ACK: u8 = 0x10;
+
+/// Receive window advertised to peers
+pub const RECEIVE_WINDOW: u16 = 65535;
+
+const HEADER_LEN: usize = 20;
+
+/// TCP segment without options
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TcpSegment {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u8,
+    pub window: u16,
+    pub payload: Vec<u8>,
+}
+
+impl TcpSegment {
+    pub fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// Sequence space the segment occupies (SYN and FIN count as one each)
+    pub fn seq_len(&self) -> u32 {
+        let mut len = self.payload.len() as u32;
+        if self.has(FLAG_SYN) {
+            len += 1;
+        }
+        if self.has(FLAG_FIN) {
+            len += 1;
+        }
+        len
+    }
+
+    /// Reset answering a segment that matches no connection
+    pub fn reset_for(segment: &TcpSegment) -> Self {
+        let (seq, ack, flags) = if segment.has(FLAG_ACK) {
+            (segment.ack, 0, FLAG_RST)
+        } else {
+            (0, segment.seq.wrapping_add(segment.seq_len()), FLAG_RST | FLAG_ACK)
+        };
+        TcpSegment {
+            src_port: segment.dst_port,
+            dst_port: segment.src_port,
+            seq,
+            ack,
+            flags,
+            window: 0,
+            payload: Vec::new(),
+        }
+    }
+
+    pub fn parse(bytes: &[u8], src: Ipv4Addr, dst: Ipv4Addr) -> Result<Self, String> {
+        if bytes.len() < HEADER_LEN {
+            return Err("TCP segment too short".to_string());
+        }
+        if pseudo_header_checksum(src, dst, PROTOCOL_TCP, bytes) != 0 {
+            return Err("TCP checksum mismatch".to_string());
+        }
+        let data_offset = ((bytes[12] >> 4) as usize) * 4;
+        if data_offset < HEADER_LEN || data_offset > bytes.len() {
+            return Err("Malformed TCP header".to_string());
+        }
+        Ok(TcpSegment {
+            src_port: u16::from_be_bytes([bytes[0], bytes[1]]),
+            dst_port: u16::from_be_bytes([bytes[2], bytes[3]]),
+            seq: u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+            ack: u32::from_be_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+            flags: bytes[13],
+            window: u16::from_be_bytes([bytes[14], bytes[15]]),
+            payload: bytes[data_offset..].to_vec(),
+        })
+    }
+
+    pub fn to_bytes(&self, src: Ipv4Addr, dst: Ipv4Addr) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.payload.len());
+        bytes.extend_from_slice(&self.src_port.to_be_bytes());
+        bytes.extend_from_slice(&self.dst_port.to_be_bytes());
+        bytes.extend_from_slice(&self.seq.to_be_bytes());
+        bytes.extend_from_slice(&self.ack.to_be_bytes());
+        bytes.push(((HEADER_LEN / 4) as u8) << 4);
+        bytes.push(self.flags);
+        bytes.extend_from_slice(&self.window.to_be_bytes());
+        bytes.extend_from_slice(&[0, 0, 0, 0]);
+        bytes.extend_from_slice(&self.payload);
+        let sum = pseudo_header_checksum(src, dst, PROTOCOL_TCP, &bytes);
+        bytes[16..18].copy_from_slice(&sum.to_be_bytes());
+        bytes
+    }
+}
+
+/// Connection state (RFC 793)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TcpState {
+    Closed,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+/// Endpoint address
+pub type Endpoint = (Ipv4Addr, u16);
+
+/// One TCP connection
+#[derive(Debug)]
+pub struct TcpConnection {
+    pub local: Endpoint,
+    pub remote: Endpoint,
+    state: TcpState,
+    snd_una: u32,
+    snd_nxt: u32,
+    rcv_nxt: u32,
+    remote_window: u16,
+    recv_buffer: VecDeque<u8>,
+}
+
+impl TcpConnection {
+    /// Active open: returns the connection and its SYN
+    pub fn connect(local: Endpoint, remote: Endpoint, iss: u32) -> (Self, TcpSegment) {
+        let mut connection = Self::with_state(local, remote, iss, TcpState::SynSent);
+        let syn = connection.segment(FLAG_SYN, Vec::new());
+        connection.snd_nxt = iss.wrapping_add(1);
+        (connection, syn)
+    }
+
+    /// Passive open in response to `syn`: returns the connection and its SYN-ACK
+    pub fn accept(local: Endpoint, remote: Endpoint, syn: &TcpSegment, iss: u32) -> (Self, TcpSegment) {
+        let mut connection = Self::with_state(local, remote, iss, TcpState::SynReceived);
+        connection.rcv_nxt = syn.seq.wrapping_add(1);
+        connection.remote_window = syn.window;
+        let syn_ack = connection.segment(FLAG_SYN | FLAG_ACK, Vec::new());
+        connection.snd_nxt = iss.wrapping_add(1);
+        (connection, syn_ack)
+    }
+
+    fn with_state(local: Endpoint, remote: Endpoint, iss: u32, state: TcpState) -> Self {
+        TcpConnection {
+            local,
+            remote,
+            state,
+            snd_una: iss,
+            snd_nxt: iss,
+            rcv_nxt: 0,
+            remote_window: 0,
+            recv_buffer: VecDeque::new(),
+        }
+    }
+
+    pub fn state(&self) -> TcpState {
+        self.state
+    }
+
+    /// Bytes sent but not yet acknowledged
+    pub fn unacknowledged(&self) -> u32 {
+        self.snd_nxt.wrapping_sub(self.snd_una)
+    }
+
+    fn segment(&self, flags: u8, payload: Vec<u8>) -> TcpSegment {
+        TcpSegment {
+            src_port: self.local.1,
+            dst_port: self.remote.1,
+            seq: self.snd_nxt,
+            ack: if flags & FLAG_ACK != 0 { self.rcv_nxt } else { 0 },
+            flags,
+            window: RECEIVE_WINDOW,
+            payload,
+        }
+    }
+
+    /// Queue application data; returns the segment carrying it
+    pub fn send(&mut self, data: &[u8]) -> Result<TcpSegment, String> {
+        if !matches!(self.state, TcpState::Established | TcpState::CloseWait) {
+            return Err("Connection is not established".to_string());
+        }
+        let in_flight = self.unacknowledged() as usize;
+        if in_flight + data.len() > self.remote_window as usize {
+            return Err("Peer receive window is full".to_string());
+        }
+        let segment = self.segment(FLAG_ACK | FLAG_PSH, data.to_vec());
+        self.snd_nxt = self.snd_nxt.wrapping_add(data.len() as u32);
+        Ok(segment)
+    }
+
+    /// Take up to `max` received bytes
+    pub fn read(&mut self, max: usize) -> Vec<u8> {
+        let count = max.min(self.recv_buffer.len());
+        self.recv_buffer.drain(..count).collect()
+    }
+
+    pub fn available(&self) -> usize {
+        self.recv_buffer.len()
+    }
+
+    /// Begin closing; returns the FIN to send, if any
+    pub fn close(&mut self) -> Option<TcpSegment> {
+        let next = match self.state {
+            TcpState::Established | TcpState::SynReceived => TcpState::FinWait1,
+            TcpState::CloseWait => TcpState::LastAck,
+            _ => {
+                self.state = TcpState::Closed;
+                return None;
+            }
+        };
+        let fin = self.segment(FLAG_FIN | FLAG_ACK, Vec::new());
+        self.snd_nxt = self.snd_nxt.wrapping_add(1);
+        self.state = next;
+        Some(fin)
+    }
+
+    /// Abort the connection; returns the reset to send
+    pub fn abort(&mut self) -> Option<TcpSegment> {
+        let synchronized = !matches!(self.state, TcpState::Closed | TcpState::SynSent | TcpState::TimeWait);
+        self.state = TcpState::Closed;
+        synchronized.then(|| self.segment(FLAG_RST, Vec::new()))
+    }
+
+    fn acceptable_ack(&self, ack: u32) -> bool {
+        ack.wrapping_sub(self.snd_una) <= self.snd_nxt.wrapping_sub(self.snd_una)
+    }
+
+    /// Process an incoming segment; returns segments to send in response
+    pub fn on_segment(&mut self, segment: &TcpSegment) -> Vec<TcpSegment> {
+        if segment.has(FLAG_RST) {
+            self.state = TcpState::Closed;
+            return Vec::new();
+        }
+
+        match self.state {
+            TcpState::Closed => return Vec::new(),
+            TcpState::SynSent => {
+                if segment.has(FLAG_SYN) && segment.has(FLAG_ACK) && segment.ack == self.snd_nxt {
+                    self.rcv_nxt = segment.seq.wrapping_add(1);
+                    self.snd_una = segment.ack;
+                    self.remote_window = segment.window;
+                    self.state = TcpState::Established;
+                    return vec![self.segment(FLAG_ACK, Vec::new())];
+                }
+                return Vec::new();
+            }
+            TcpState::SynReceived => {
+                if !segment.has(FLAG_ACK) || segment.ack != self.snd_nxt {
+                    return Vec::new();
+                }
+                self.state = TcpState::Established;
+            }
+            _ => {}
+        }
+
+        let mut need_ack = false;
+
+        if segment.has(FLAG_ACK) && self.acceptable_ack(segment.ack) {
+            self.snd_una = segment.ack;
+            self.remote_window = segment.window;
+            if self.snd_una == self.snd_nxt {
+                self.state = match self.state {
+                    TcpState::FinWait1 => TcpState::FinWait2,
+                    TcpState::Closing => TcpState::TimeWait,
+                    TcpState::LastAck => TcpState::Closed,
+                    state => state,
+                };
+            }
+        }
+
+        if segment.seq != self.rcv_nxt {
+            // Out of order or duplicate: re-acknowledge what we have
+            if segment.seq_len() > 0 {
+                return vec![self.segment(FLAG_ACK, Vec::new())];
+            }
+            return Vec::new();
+        }
+
+        if !segment.payload.is_empty()
+            && matches!(self.state, TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2)
+        {
+            self.recv_buffer.extend(&segment.payload);
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(segment.payload.len() as u32);
+            need_ack = true;
+        }
+
+        if segment.has(FLAG_FIN) {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            need_ack = true;
+            self.state = match self.state {
+                TcpState::Established => TcpState::CloseWait,
+                TcpState::FinWait1 => TcpState::Closing,
+                TcpState::FinWait2 => TcpState::TimeWait,
+                state => state,
+            };
+        }
+
+        if need_ack {
+            vec![self.segment(FLAG_ACK, Vec::new())]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoints() -> (Endpoint, Endpoint) {
+        ((Ipv4Addr::new(10, 0, 0, 1), 40000), (Ipv4Addr::new(10, 0, 0, 2), 80))
+    }
+
+    fn deliver(to: &mut TcpConnection, segments: Vec<TcpSegment>) -> Vec<TcpSegment> {
+        segments.iter().flat_map(|s| to.on_segment(s)).collect()
+    }
+
+    #[test]
+    fn test_segment_round_trip() {
+        let (client, server) = endpoints();
+        let (_, syn) = TcpConnection::connect(client, server, 1000);
+        let bytes = syn.to_bytes(client.0, server.0);
+        assert_eq!(TcpSegment::parse(&bytes, client.0, server.0).unwrap(), syn);
+    }
+
+    #[test]
+    fn test_handshake_transfer_and_close() {
+        let (client_ep, server_ep) = endpoints();
+        let (mut client, syn) = TcpConnection::connect(client_ep, server_ep, 1000);
+        let (mut server, syn_ack) = TcpConnection::accept(server_ep, client_ep, &syn, 5000);
+
+        let ack = deliver(&mut client, vec![syn_ack]);
+        assert_eq!(client.state(), TcpState::Established);
+        deliver(&mut server, ack);
+        assert_eq!(server.state(), TcpState::Established);
+
+        let data = client.send(b"hello").unwrap();
+        let ack = deliver(&mut server, vec![data]);
+        assert_eq!(server.read(16), b"hello");
+        deliver(&mut client, ack);
+        assert_eq!(client.unacknowledged(), 0);
+
+        // Active close from the client
+        let fin = client.close().unwrap();
+        let ack = deliver(&mut server, vec![fin]);
+        assert_eq!(server.state(), TcpState::CloseWait);
+        deliver(&mut client, ack);
+        assert_eq!(client.state(), TcpState::FinWait2);
+
+        let fin = server.close().unwrap();
+        let ack = deliver(&mut client, vec![fin]);
+        assert_eq!(client.state(), TcpState::TimeWait);
+        deliver(&mut server, ack);
+        assert_eq!(server.state(), TcpState::Closed);
+    }
+
+    #[test]
+    fn test_out_of_order_data_is_dropped() {
+        let (client_ep, server_ep) = endpoints();
+        let (mut client, syn) = TcpConnection::connect(client_ep, server_ep, 1000);
+        let (mut server, syn_ack) = TcpConnection::accept(server_ep, client_ep, &syn, 5000);
+        let ack = deliver(&mut client, vec![syn_ack]);
+        deliver(&mut server, ack);
+
+        let first = client.send(b"one").unwrap();
+        let second = client.send(b"two").unwrap();
+        let dup_ack = deliver(&mut server, vec![second]);
+        assert_eq!(dup_ack[0].ack, first.seq);
+        assert_eq!(server.available(), 0);
+    }
+}
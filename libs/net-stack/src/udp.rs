@@ -0,0 +1,80 @@
+//! UDP datagrams
+
+use std::net::Ipv4Addr;
+
+use crate::ipv4::{pseudo_header_checksum, PROTOCOL_UDP};
+
+const HEADER_LEN: usize = 8;
+
+/// UDP datagram
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UdpDatagram {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub payload: Vec<u8>,
+}
+
+impl UdpDatagram {
+    pub fn new(src_port: u16, dst_port: u16, payload: Vec<u8>) -> Self {
+        UdpDatagram {
+            src_port,
+            dst_port,
+            payload,
+        }
+    }
+
+    /// Parse a datagram carried between `src` and `dst`
+    pub fn parse(bytes: &[u8], src: Ipv4Addr, dst: Ipv4Addr) -> Result<Self, String> {
+        if bytes.len() < HEADER_LEN {
+            return Err("UDP datagram too short".to_string());
+        }
+        let length = u16::from_be_bytes([bytes[4], bytes[5]]) as usize;
+        if length < HEADER_LEN || length > bytes.len() {
+            return Err("Malformed UDP length".to_string());
+        }
+        let bytes = &bytes[..length];
+        // A zero checksum means the sender did not compute one
+        let sent = u16::from_be_bytes([bytes[6], bytes[7]]);
+        if sent != 0 && pseudo_header_checksum(src, dst, PROTOCOL_UDP, bytes) != 0 {
+            return Err("UDP checksum mismatch".to_string());
+        }
+        Ok(UdpDatagram {
+            src_port: u16::from_be_bytes([bytes[0], bytes[1]]),
+            dst_port: u16::from_be_bytes([bytes[2], bytes[3]]),
+            payload: bytes[HEADER_LEN..].to_vec(),
+        })
+    }
+
+    pub fn to_bytes(&self, src: Ipv4Addr, dst: Ipv4Addr) -> Vec<u8> {
+        let length = (HEADER_LEN + self.payload.len()) as u16;
+        let mut bytes = Vec::with_capacity(length as usize);
+        bytes.extend_from_slice(&self.src_port.to_be_bytes());
+        bytes.extend_from_slice(&self.dst_port.to_be_bytes());
+        bytes.extend_from_slice(&length.to_be_bytes());
+        bytes.extend_from_slice(&[0, 0]);
+        bytes.extend_from_slice(&self.payload);
+
+        let sum = match pseudo_header_checksum(src, dst, PROTOCOL_UDP, &bytes) {
+            0 => 0xffff,
+            sum => sum,
+        };
+        bytes[6..8].copy_from_slice(&sum.to_be_bytes());
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_datagram_round_trip() {
+        let (src, dst) = (Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2));
+        let datagram = UdpDatagram::new(5000, 53, b"query".to_vec());
+        let bytes = datagram.to_bytes(src, dst);
+        assert_eq!(UdpDatagram::parse(&bytes, src, dst).unwrap(), datagram);
+
+        // The pseudo-header binds the checksum to the addresses
+        assert!(UdpDatagram::parse(&bytes, src, Ipv4Addr::new(10, 0, 0, 3)).is_err());
+    }
+}
@@ -0,0 +1,66 @@
+//! `/ipc`: channels
+//!
+//! ```text
+//! /ipc/channels  one line per open channel: id, owner, peer and the
+//!                number of messages waiting
+//! ```
+
+use std::path::Path;
+use std::sync::Arc;
+
+use filesystem::{FileSystemProvider, FileType, FsError};
+use ipc::IPCManager;
+use kernel::ProcessId;
+
+use crate::{parts, Node};
+
+pub struct IpcFs {
+    ipc: Arc<IPCManager>,
+}
+
+impl IpcFs {
+    pub fn new(ipc: Arc<IPCManager>) -> Self {
+        IpcFs { ipc }
+    }
+
+    fn node(&self, path: &Path) -> Option<Node> {
+        match parts(path)[..] {
+            [] => Some(Node::Directory(vec!["channels".to_string()])),
+            ["channels"] => Some(Node::File(self.channels())),
+            _ => None,
+        }
+    }
+
+    fn channels(&self) -> String {
+        let process = |id: Option<ProcessId>| id.map_or("-".to_string(), |id| id.value().to_string());
+        let mut table = String::from("ID\tOWNER\tPEER\tPENDING\n");
+        for id in self.ipc.list_channels() {
+            // Closed since it was listed
+            let Some(channel) = self.ipc.get_channel(id) else {
+                continue;
+            };
+            table.push_str(&format!(
+                "{}\t{}\t{}\t{}\n",
+                id.value(),
+                process(channel.owner()),
+                process(channel.peer()),
+                channel.pending()
+            ));
+        }
+        table
+    }
+}
+
+impl FileSystemProvider for IpcFs {
+    fn file_type(&self, path: &Path) -> Option<FileType> {
+        self.node(path).map(|node| node.file_type())
+    }
+
+    fn list(&self, path: &Path) -> Result<Vec<String>, FsError> {
+        self.node(path).ok_or(FsError::DirectoryNotFound)?.entries()
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>, FsError> {
+        self.node(path).ok_or(FsError::NotFound)?.content()
+    }
+}
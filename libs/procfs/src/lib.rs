@@ -0,0 +1,135 @@
+//! System object namespace for hairr OS
+//!
+//! Exposes live kernel and service state as read-only files, rendered
+//! each time they are read:
+//!
+//! - `/proc/<pid>/status` and `/proc/meminfo`, from the kernel and the
+//!   memory manager
+//! - `/sys/devices/<id>`, from the device manager
+//! - `/ipc/channels`, from the IPC manager
+//!
+//! Each tree is a `FileSystemProvider`; `mount_all` mounts the three of
+//! them into a VFS.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use device_manager::DeviceManager;
+use filesystem::{FileType, FsError, VirtualFileSystem};
+use ipc::IPCManager;
+use kernel::Kernel;
+use memory_manager::MemoryManager;
+
+pub mod channels;
+pub mod proc;
+pub mod sys;
+
+pub use channels::IpcFs;
+pub use proc::ProcFs;
+pub use sys::SysFs;
+
+/// An entry of a synthetic tree
+enum Node {
+    Directory(Vec<String>),
+    File(String),
+}
+
+impl Node {
+    fn file_type(&self) -> FileType {
+        match self {
+            Node::Directory(_) => FileType::Directory,
+            Node::File(_) => FileType::Regular,
+        }
+    }
+
+    fn entries(self) -> Result<Vec<String>, FsError> {
+        match self {
+            Node::Directory(entries) => Ok(entries),
+            Node::File(_) => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn content(self) -> Result<Vec<u8>, FsError> {
+        match self {
+            Node::Directory(_) => Err(FsError::NotAFile),
+            Node::File(content) => Ok(content.into_bytes()),
+        }
+    }
+}
+
+/// Components of a path relative to a mount point
+fn parts(path: &Path) -> Vec<&str> {
+    path.iter().filter_map(|part| part.to_str()).collect()
+}
+
+/// One `Key:<tab>value` line, as in Linux status files
+fn field(name: &str, value: impl std::fmt::Display) -> String {
+    format!("{}:\t{}\n", name, value)
+}
+
+/// Mount `/proc`, `/sys` and `/ipc` into `vfs`
+pub fn mount_all(
+    vfs: &VirtualFileSystem,
+    kernel: Arc<Kernel>,
+    memory: Arc<MemoryManager>,
+    devices: Arc<DeviceManager>,
+    ipc: Arc<IPCManager>,
+) -> Result<(), FsError> {
+    vfs.mount(Path::new("/proc"), Arc::new(ProcFs::new(kernel, memory)))?;
+    vfs.mount(Path::new("/sys"), Arc::new(SysFs::new(devices)))?;
+    vfs.mount(Path::new("/ipc"), Arc::new(IpcFs::new(ipc)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use device_manager::DeviceStatus;
+    use filesystem::OpenOptions;
+    use kernel::{Priority, ProcessId};
+    use std::path::PathBuf;
+
+    fn read(vfs: &VirtualFileSystem, path: &str) -> String {
+        String::from_utf8(vfs.read_file(Path::new(path)).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_system_namespace() {
+        let kernel = Arc::new(Kernel::new());
+        let memory = Arc::new(MemoryManager::new(1));
+        let devices = Arc::new(DeviceManager::new());
+        let ipc = Arc::new(IPCManager::new());
+        let vfs = VirtualFileSystem::new();
+        mount_all(&vfs, Arc::clone(&kernel), Arc::clone(&memory), Arc::clone(&devices), Arc::clone(&ipc)).unwrap();
+
+        let init = kernel.create_process("init".to_string(), Priority::High);
+        memory.allocate(memory_manager::ProcessId(init.value()), 8192).unwrap();
+        let status = read(&vfs, "/proc/1/status");
+        assert!(status.starts_with("Name:\tinit\nState:\tReady\nPid:\t1\n"));
+        assert!(status.contains("Memory:\t8192 bytes\n"));
+
+        // Files are rendered on every read
+        kernel.update_process_state(init, kernel::ProcessState::Running).unwrap();
+        assert!(read(&vfs, "/proc/1/status").contains("State:\tRunning\n"));
+        assert!(read(&vfs, "/proc/meminfo").starts_with("MemTotal:\t1024 kB\nMemFree:\t1016 kB\n"));
+        assert_eq!(
+            vfs.list_directory(Path::new("/proc")).unwrap(),
+            vec![PathBuf::from("/proc/meminfo"), PathBuf::from("/proc/1")]
+        );
+        assert_eq!(vfs.read_file(Path::new("/proc/2/status")), Err(FsError::NotFound));
+
+        let display = devices.register_device("Display0".to_string(), "display".to_string(), "reference".to_string());
+        devices.update_status(display, DeviceStatus::Ready).unwrap();
+        assert_eq!(
+            read(&vfs, "/sys/devices/1"),
+            "Name:\tDisplay0\nType:\tdisplay\nStatus:\tReady\nDriver:\treference\n"
+        );
+        assert_eq!(vfs.list_directory(Path::new("/sys/devices")).unwrap(), vec![PathBuf::from("/sys/devices/1")]);
+
+        let channel = ipc.create_owned_channel(init, ipc::OrphanPolicy::Close);
+        ipc.send_message(channel, ipc::Message::Text("hello".to_string())).unwrap();
+        assert_eq!(read(&vfs, "/ipc/channels"), "ID\tOWNER\tPEER\tPENDING\n1\t1\t-\t1\n");
+
+        assert!(vfs.metadata(Path::new("/proc/1")).unwrap().is_directory());
+        assert_eq!(vfs.open(ProcessId::KERNEL, Path::new("/proc/meminfo"), OpenOptions::write_only()), Err(FsError::ReadOnly));
+    }
+}
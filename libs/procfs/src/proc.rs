@@ -0,0 +1,91 @@
+//! `/proc`: processes and memory
+//!
+//! ```text
+//! /proc/meminfo       physical memory and page totals
+//! /proc/<pid>/status  name, state, parent and resources of a process
+//! ```
+
+use std::path::Path;
+use std::sync::Arc;
+
+use filesystem::{FileSystemProvider, FileType, FsError};
+use kernel::{Kernel, Process, ProcessId};
+use memory_manager::MemoryManager;
+
+use crate::{field, parts, Node};
+
+pub struct ProcFs {
+    kernel: Arc<Kernel>,
+    memory: Arc<MemoryManager>,
+}
+
+impl ProcFs {
+    pub fn new(kernel: Arc<Kernel>, memory: Arc<MemoryManager>) -> Self {
+        ProcFs { kernel, memory }
+    }
+
+    fn node(&self, path: &Path) -> Option<Node> {
+        match parts(path)[..] {
+            [] => {
+                let mut ids: Vec<u64> = self.kernel.list_processes().iter().map(|p| p.id.value()).collect();
+                ids.sort();
+                let mut entries = vec!["meminfo".to_string()];
+                entries.extend(ids.iter().map(u64::to_string));
+                Some(Node::Directory(entries))
+            }
+            ["meminfo"] => Some(Node::File(self.meminfo())),
+            [pid] => {
+                self.process(pid)?;
+                Some(Node::Directory(vec!["status".to_string()]))
+            }
+            [pid, "status"] => Some(Node::File(self.status(&self.process(pid)?))),
+            _ => None,
+        }
+    }
+
+    fn process(&self, pid: &str) -> Option<Process> {
+        self.kernel.get_process(ProcessId::new(pid.parse().ok()?))
+    }
+
+    fn meminfo(&self) -> String {
+        let stats = self.memory.stats();
+        [
+            field("MemTotal", format!("{} kB", stats.total_memory / 1024)),
+            field("MemFree", format!("{} kB", stats.free_memory / 1024)),
+            field("MemUsed", format!("{} kB", stats.used_memory / 1024)),
+            field("PagesTotal", stats.total_pages),
+            field("PagesFree", stats.free_pages),
+            field("PagesUsed", stats.used_pages),
+        ]
+        .concat()
+    }
+
+    fn status(&self, process: &Process) -> String {
+        let memory = self.memory.process_memory(memory_manager::ProcessId(process.id.value()));
+        [
+            field("Name", &process.name),
+            field("State", format!("{:?}", process.state)),
+            field("Pid", process.id.value()),
+            field("PPid", process.parent.map_or(0, |parent| parent.value())),
+            field("Priority", format!("{:?}", process.priority)),
+            field("Capabilities", process.capabilities.len()),
+            field("Sandbox", process.sandbox.as_ref().map_or("none", |profile| &profile.root)),
+            field("Memory", format!("{} bytes", memory)),
+        ]
+        .concat()
+    }
+}
+
+impl FileSystemProvider for ProcFs {
+    fn file_type(&self, path: &Path) -> Option<FileType> {
+        self.node(path).map(|node| node.file_type())
+    }
+
+    fn list(&self, path: &Path) -> Result<Vec<String>, FsError> {
+        self.node(path).ok_or(FsError::DirectoryNotFound)?.entries()
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>, FsError> {
+        self.node(path).ok_or(FsError::NotFound)?.content()
+    }
+}
@@ -0,0 +1,63 @@
+//! `/sys`: devices
+//!
+//! ```text
+//! /sys/devices/<id>  name, type, status and driver of a device
+//! ```
+
+use std::path::Path;
+use std::sync::Arc;
+
+use device_manager::{DeviceId, DeviceManager, ManagedDevice};
+use filesystem::{FileSystemProvider, FileType, FsError};
+
+use crate::{field, parts, Node};
+
+pub struct SysFs {
+    devices: Arc<DeviceManager>,
+}
+
+impl SysFs {
+    pub fn new(devices: Arc<DeviceManager>) -> Self {
+        SysFs { devices }
+    }
+
+    fn node(&self, path: &Path) -> Option<Node> {
+        match parts(path)[..] {
+            [] => Some(Node::Directory(vec!["devices".to_string()])),
+            ["devices"] => {
+                let mut ids: Vec<DeviceId> = self.devices.list_devices().iter().map(|d| d.id).collect();
+                ids.sort();
+                Some(Node::Directory(ids.iter().map(|id| id.value().to_string()).collect()))
+            }
+            ["devices", id] => {
+                let device = self.devices.get_device(DeviceId::new(id.parse().ok()?))?;
+                Some(Node::File(describe(&device)))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn describe(device: &ManagedDevice) -> String {
+    [
+        field("Name", &device.name),
+        field("Type", &device.device_type),
+        field("Status", format!("{:?}", device.status)),
+        field("Driver", &device.driver_name),
+    ]
+    .concat()
+}
+
+impl FileSystemProvider for SysFs {
+    fn file_type(&self, path: &Path) -> Option<FileType> {
+        self.node(path).map(|node| node.file_type())
+    }
+
+    fn list(&self, path: &Path) -> Result<Vec<String>, FsError> {
+        self.node(path).ok_or(FsError::DirectoryNotFound)?.entries()
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>, FsError> {
+        self.node(path).ok_or(FsError::NotFound)?.content()
+    }
+}
@@ -0,0 +1,72 @@
+//! Throughput of a single locked map against `ShardedMap` as more threads
+//! hit it at once
+//!
+//! Run with `cargo bench -p sync`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use sync::ShardedMap;
+
+const OPERATIONS: u64 = 200_000;
+
+trait Map: Send + Sync + 'static {
+    fn insert(&self, key: u64, value: u64);
+    fn get(&self, key: u64) -> Option<u64>;
+}
+
+impl Map for Mutex<HashMap<u64, u64>> {
+    fn insert(&self, key: u64, value: u64) {
+        self.lock().unwrap().insert(key, value);
+    }
+
+    fn get(&self, key: u64) -> Option<u64> {
+        self.lock().unwrap().get(&key).copied()
+    }
+}
+
+impl Map for ShardedMap<u64, u64> {
+    fn insert(&self, key: u64, value: u64) {
+        ShardedMap::insert(self, key, value);
+    }
+
+    fn get(&self, key: u64) -> Option<u64> {
+        ShardedMap::get(self, &key)
+    }
+}
+
+/// Operations per second with `threads` threads each doing a share of
+/// `OPERATIONS`, three lookups to every insert
+fn throughput(map: Arc<dyn Map>, threads: u64) -> f64 {
+    let started = Instant::now();
+    let workers: Vec<_> = (0..threads)
+        .map(|thread| {
+            let map = Arc::clone(&map);
+            thread::spawn(move || {
+                let base = thread * OPERATIONS;
+                for i in 0..OPERATIONS / threads {
+                    if i % 4 == 0 {
+                        map.insert(base + i, i);
+                    } else {
+                        map.get(base + i - i % 4);
+                    }
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    OPERATIONS as f64 / started.elapsed().as_secs_f64()
+}
+
+fn main() {
+    println!("{:>8} {:>16} {:>16}", "threads", "mutex ops/s", "sharded ops/s");
+    for threads in [1, 2, 4, 8, 16, 64] {
+        let locked = throughput(Arc::new(Mutex::new(HashMap::new())), threads);
+        let sharded = throughput(Arc::new(ShardedMap::new()), threads);
+        println!("{:>8} {:>16.0} {:>16.0}", threads, locked, sharded);
+    }
+}
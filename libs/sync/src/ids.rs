@@ -0,0 +1,151 @@
+//! Generational identifiers
+//!
+//! An id is a slot index in its low 32 bits and the slot's generation in
+//! its high 32 bits. Releasing an id frees its slot, and the next id for
+//! that slot has the next generation, so a handle kept past the release
+//! is recognisably stale rather than naming whatever took the slot. The
+//! first id of every slot has generation 0, so fresh ids are just 1, 2, 3
+//! and so on; 0 is never handed out.
+//!
+//! Within one allocator no id is ever handed out twice: freed slots are
+//! reused oldest first, and a slot whose generation would wrap is retired.
+
+use crate::prelude::*;
+use crate::{Mutex, VecDeque};
+
+const INDEX_BITS: u32 = 32;
+const INDEX_MASK: u64 = (1 << INDEX_BITS) - 1;
+
+/// Slot index of `id`
+pub fn index(id: u64) -> u32 {
+    (id & INDEX_MASK) as u32
+}
+
+/// How many times the slot of `id` had been released before `id` was
+/// handed out
+pub fn generation(id: u64) -> u32 {
+    (id >> INDEX_BITS) as u32
+}
+
+fn compose(index: u32, generation: u32) -> u64 {
+    ((generation as u64) << INDEX_BITS) | index as u64
+}
+
+/// What an allocator knows about an id
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdState {
+    /// Handed out and not yet released
+    Live,
+    /// Handed out and released since
+    Stale,
+    /// Never handed out
+    Unknown,
+}
+
+struct Slots {
+    /// Generation of the last id handed out for each slot, indexed by slot
+    /// index; slot 0 is never used
+    generations: Vec<u32>,
+    live: Vec<bool>,
+    /// Released slots, oldest first
+    free: VecDeque<u32>,
+}
+
+/// Hands out generational ids and recycles their slots
+pub struct IdAllocator {
+    slots: Mutex<Slots>,
+}
+
+impl IdAllocator {
+    pub fn new() -> Self {
+        IdAllocator {
+            slots: Mutex::new(Slots {
+                generations: vec![0],
+                live: vec![false],
+                free: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub fn allocate(&self) -> u64 {
+        let mut slots = self.slots.lock().unwrap();
+        let index = match slots.free.pop_front() {
+            Some(index) => {
+                slots.generations[index as usize] += 1;
+                index
+            }
+            None => {
+                slots.generations.push(0);
+                slots.live.push(false);
+                (slots.generations.len() - 1) as u32
+            }
+        };
+        slots.live[index as usize] = true;
+        compose(index, slots.generations[index as usize])
+    }
+
+    /// Free the slot of a live `id`; false if it was not live
+    pub fn release(&self, id: u64) -> bool {
+        let mut slots = self.slots.lock().unwrap();
+        let slot = index(id) as usize;
+        if slot >= slots.live.len() || !slots.live[slot] || slots.generations[slot] != generation(id) {
+            return false;
+        }
+        slots.live[slot] = false;
+        // A slot that has used up its generations is never reused
+        if slots.generations[slot] < u32::MAX {
+            slots.free.push_back(slot as u32);
+        }
+        true
+    }
+
+    pub fn state(&self, id: u64) -> IdState {
+        let slots = self.slots.lock().unwrap();
+        let slot = index(id) as usize;
+        if slot == 0 || slot >= slots.generations.len() {
+            return IdState::Unknown;
+        }
+        let current = slots.generations[slot];
+        match generation(id) {
+            g if g == current && slots.live[slot] => IdState::Live,
+            g if g <= current => IdState::Stale,
+            _ => IdState::Unknown,
+        }
+    }
+
+    pub fn is_stale(&self, id: u64) -> bool {
+        self.state(id) == IdState::Stale
+    }
+}
+
+impl Default for IdAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generational_ids() {
+        let ids = IdAllocator::new();
+        let (first, second) = (ids.allocate(), ids.allocate());
+        assert_eq!((first, second), (1, 2));
+        assert_eq!(ids.state(first), IdState::Live);
+        assert_eq!(ids.state(7), IdState::Unknown);
+
+        assert!(ids.release(first));
+        assert!(!ids.release(first));
+        assert!(ids.is_stale(first));
+        // The oldest free slot is reused, with the next generation
+        assert!(ids.release(second));
+        let reused = ids.allocate();
+        assert_eq!((index(reused), generation(reused)), (1, 1));
+        assert!(ids.is_stale(first) && ids.state(reused) == IdState::Live);
+        assert_eq!(index(ids.allocate()), 2);
+        assert_eq!(index(ids.allocate()), 3);
+        assert_eq!(ids.state(compose(1, 5)), IdState::Unknown);
+    }
+}
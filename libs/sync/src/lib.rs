@@ -0,0 +1,85 @@
+//! Locks and collections for crates that also build without std
+//!
+//! The kernel and the libraries it is built from take `Mutex` and
+//! `HashMap` from here. With the default `std` feature they are the std
+//! types; without it they are a spin lock and hashbrown's map, so the same
+//! code runs on bare metal with nothing but an allocator. A spin lock is
+//! never poisoned, but its `lock` still returns a `Result` so callers read
+//! the same either way.
+//!
+//! Maps on hot paths, shared by every simulated process, are a
+//! `ShardedMap` so that unrelated lookups do not queue on one lock.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub use alloc::collections::{BTreeMap, VecDeque};
+pub use alloc::sync::Arc;
+
+#[cfg(feature = "std")]
+pub use std::collections::HashMap;
+#[cfg(feature = "std")]
+pub use std::sync::{Mutex, MutexGuard};
+
+#[cfg(not(feature = "std"))]
+pub use hashbrown::HashMap;
+#[cfg(not(feature = "std"))]
+pub use spin_lock::{Mutex, MutexGuard};
+
+pub mod ids;
+pub mod sharded;
+
+pub use ids::{IdAllocator, IdState};
+pub use sharded::ShardedMap;
+
+/// The `alloc` types std has in its prelude
+pub mod prelude {
+    pub use alloc::borrow::ToOwned;
+    pub use alloc::boxed::Box;
+    pub use alloc::format;
+    pub use alloc::string::{String, ToString};
+    pub use alloc::vec;
+    pub use alloc::vec::Vec;
+}
+
+#[cfg(not(feature = "std"))]
+mod spin_lock {
+    use core::convert::Infallible;
+
+    pub use spin::MutexGuard;
+
+    #[derive(Debug, Default)]
+    pub struct Mutex<T: ?Sized>(spin::Mutex<T>);
+
+    impl<T> Mutex<T> {
+        pub const fn new(value: T) -> Self {
+            Mutex(spin::Mutex::new(value))
+        }
+    }
+
+    impl<T: ?Sized> Mutex<T> {
+        /// Spin until the lock is free; never fails
+        pub fn lock(&self) -> Result<MutexGuard<'_, T>, Infallible> {
+            Ok(self.0.lock())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::prelude::*;
+    use super::*;
+
+    #[test]
+    fn test_shared_map() {
+        let map = Arc::new(Mutex::new(HashMap::new()));
+        let other = Arc::clone(&map);
+        other.lock().unwrap().insert(1, "one".to_string());
+
+        let map = map.lock().unwrap();
+        assert_eq!(map.get(&1), Some(&"one".to_string()));
+        assert_eq!(map.len(), 1);
+        assert_eq!(vec![format!("{}", 1)], Vec::from(["1".to_string()]));
+    }
+}
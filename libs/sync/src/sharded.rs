@@ -0,0 +1,192 @@
+//! Sharded concurrent map
+//!
+//! `ShardedMap` splits its entries over several independently locked
+//! `HashMap`s, picked by the hash of the key, so threads working on
+//! different keys rarely wait for each other. Each operation holds one
+//! shard lock at most; the closures passed in run under it and must not
+//! touch the same map again.
+
+use core::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::prelude::*;
+use crate::{HashMap, Mutex};
+
+/// Picks shards. Keys are usually sequential ids, which a multiplicative
+/// hash spreads evenly at a fraction of the cost of the map's own hasher.
+#[derive(Default)]
+struct ShardHasher(u64);
+
+impl Hasher for ShardHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_u64(byte as u64);
+        }
+    }
+
+    fn write_u64(&mut self, word: u64) {
+        self.0 = (self.0.rotate_left(5) ^ word).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Shards used by `ShardedMap::new`
+pub const DEFAULT_SHARDS: usize = 16;
+
+pub struct ShardedMap<K, V> {
+    shards: Box<[Mutex<HashMap<K, V>>]>,
+    hasher: BuildHasherDefault<ShardHasher>,
+    len: AtomicUsize,
+}
+
+impl<K: Hash + Eq, V> ShardedMap<K, V> {
+    pub fn new() -> Self {
+        Self::with_shards(DEFAULT_SHARDS)
+    }
+
+    /// Map spread over `shards` locks; more shards mean less contention
+    /// and slower whole-map operations
+    pub fn with_shards(shards: usize) -> Self {
+        assert!(shards > 0, "a sharded map needs at least one shard");
+        ShardedMap {
+            shards: (0..shards).map(|_| Mutex::new(HashMap::new())).collect(),
+            hasher: BuildHasherDefault::default(),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    fn shard(&self, key: &K) -> &Mutex<HashMap<K, V>> {
+        let index = self.hasher.hash_one(key) as usize % self.shards.len();
+        &self.shards[index]
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        let previous = self.shard(&key).lock().unwrap().insert(key, value);
+        if previous.is_none() {
+            self.len.fetch_add(1, Ordering::Relaxed);
+        }
+        previous
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let removed = self.shard(key).lock().unwrap().remove(key);
+        if removed.is_some() {
+            self.len.fetch_sub(1, Ordering::Relaxed);
+        }
+        removed
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.shard(key).lock().unwrap().contains_key(key)
+    }
+
+    /// Run `f` on the value for `key`, if there is one
+    pub fn with<R>(&self, key: &K, f: impl FnOnce(&V) -> R) -> Option<R> {
+        self.shard(key).lock().unwrap().get(key).map(f)
+    }
+
+    /// Run `f` on the value for `key` with write access
+    pub fn with_mut<R>(&self, key: &K, f: impl FnOnce(&mut V) -> R) -> Option<R> {
+        self.shard(key).lock().unwrap().get_mut(key).map(f)
+    }
+
+    pub fn get(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.with(key, V::clone)
+    }
+
+    /// Number of entries; concurrent inserts and removals may or may not be
+    /// counted yet
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Copies of every value, one shard at a time
+    pub fn values(&self) -> Vec<V>
+    where
+        V: Clone,
+    {
+        self.filter_map(|value| Some(value.clone()))
+    }
+
+    /// Collect what `f` returns for each value, one shard at a time
+    pub fn filter_map<R>(&self, mut f: impl FnMut(&V) -> Option<R>) -> Vec<R> {
+        let mut results = Vec::new();
+        for shard in self.shards.iter() {
+            results.extend(shard.lock().unwrap().values().filter_map(&mut f));
+        }
+        results
+    }
+
+    /// Keep only the entries `f` returns true for, one shard at a time
+    pub fn retain(&self, mut f: impl FnMut(&K, &mut V) -> bool) {
+        for shard in self.shards.iter() {
+            let mut shard = shard.lock().unwrap();
+            let before = shard.len();
+            shard.retain(|key, value| f(key, value));
+            self.len.fetch_sub(before - shard.len(), Ordering::Relaxed);
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> Default for ShardedMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Arc;
+
+    #[test]
+    fn test_sharded_map() {
+        let map = ShardedMap::with_shards(4);
+        for id in 0..100 {
+            assert_eq!(map.insert(id, id * 2), None);
+        }
+        assert_eq!(map.insert(7, 0), Some(14));
+        assert_eq!(map.len(), 100);
+        assert_eq!(map.get(&7), Some(0));
+        assert_eq!(map.with_mut(&8, |value| core::mem::replace(value, 1)), Some(16));
+        assert_eq!(map.with(&200, |value| *value), None);
+
+        map.retain(|id, _| id % 2 == 0);
+        assert_eq!(map.len(), 50);
+        assert_eq!(map.remove(&8), Some(1));
+        assert!(!map.contains_key(&8));
+        assert_eq!(map.values().len(), 49);
+        let mut large = map.filter_map(|value| (*value >= 190).then_some(*value));
+        large.sort();
+        assert_eq!(large, vec![192, 196]);
+    }
+
+    #[test]
+    fn test_concurrent_inserts() {
+        let map = Arc::new(ShardedMap::new());
+        let threads: Vec<_> = (0..8)
+            .map(|thread| {
+                let map = Arc::clone(&map);
+                std::thread::spawn(move || {
+                    for i in 0..1000 {
+                        map.insert(thread * 1000 + i, i);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(map.len(), 8000);
+    }
+}
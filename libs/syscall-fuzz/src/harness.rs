@@ -0,0 +1,338 @@
+//! The system under test and the model it is checked against
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use filesystem::{OpenOptions, VirtualFileSystem};
+use ipc::{ChannelId, IPCManager, Message, OrphanPolicy};
+use kernel::{Kernel, Priority, ProcessId, ProcessState};
+use memory_manager::{MemoryManager, MemoryRegion, PAGE_SIZE};
+
+use crate::ops::Op;
+use crate::Rng;
+
+/// Where operations create files; everything under it is modelled
+const ROOT: &str = "/tmp";
+
+/// Kernel, VFS, memory manager and IPC manager wired together as at boot
+pub struct System {
+    pub kernel: Arc<Kernel>,
+    pub vfs: VirtualFileSystem,
+    pub memory: Arc<MemoryManager>,
+    pub ipc: Arc<IPCManager>,
+}
+
+impl System {
+    /// `memory_mb` of physical memory; a small amount runs out quickly
+    pub fn new(memory_mb: usize) -> Self {
+        let kernel = Arc::new(Kernel::new());
+        let memory = Arc::new(MemoryManager::new(memory_mb));
+        let ipc = Arc::new(IPCManager::new());
+        ipc.attach_kernel(&kernel);
+        let released = Arc::clone(&memory);
+        kernel.on_process_terminated(Arc::new(move |id| {
+            // Fails only when the process held no memory
+            let _ = released.free_all(memory_manager::ProcessId(id.value()));
+        }));
+        System {
+            kernel,
+            vfs: VirtualFileSystem::new(),
+            memory,
+            ipc,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ChannelModel {
+    owner: ProcessId,
+    peer: Option<ProcessId>,
+    policy: OrphanPolicy,
+    pending: usize,
+}
+
+/// What the system should hold after the operations so far
+#[derive(Default)]
+pub(crate) struct Model {
+    pub(crate) live: Vec<ProcessId>,
+    pub(crate) dead: Vec<ProcessId>,
+    pub(crate) regions: HashMap<ProcessId, Vec<MemoryRegion>>,
+    files: BTreeMap<PathBuf, Vec<u8>>,
+    directories: BTreeSet<PathBuf>,
+    pub(crate) channels: BTreeMap<ChannelId, ChannelModel>,
+    pub(crate) channels_created: usize,
+}
+
+impl Model {
+    pub(crate) fn processes(&self) -> Vec<ProcessId> {
+        self.live.iter().chain(&self.dead).copied().collect()
+    }
+
+    fn is_directory(&self, path: &Path) -> bool {
+        path == Path::new(ROOT) || self.directories.contains(path)
+    }
+
+    fn has_children(&self, path: &Path) -> bool {
+        let child = |p: &PathBuf| p.parent() == Some(path);
+        self.files.keys().any(child) || self.directories.iter().any(child)
+    }
+
+    fn terminate(&mut self, id: ProcessId) {
+        if let Some(index) = self.live.iter().position(|&p| p == id) {
+            self.dead.push(self.live.remove(index));
+        }
+        self.regions.remove(&id);
+        self.channels.retain(|_, channel| {
+            if channel.owner == id {
+                match (channel.policy, channel.peer.take()) {
+                    (OrphanPolicy::TransferToPeer, Some(peer)) if peer != id => channel.owner = peer,
+                    _ => return false,
+                }
+            } else if channel.peer == Some(id) {
+                channel.peer = None;
+            }
+            true
+        });
+    }
+}
+
+/// Issues operations against a `System` and checks it against the model
+pub struct Harness {
+    system: System,
+    model: Model,
+    rng: Rng,
+}
+
+impl Harness {
+    pub fn new(seed: u64) -> Self {
+        let system = System::new(1);
+        system.vfs.create_directory(Path::new(ROOT)).unwrap();
+        Harness {
+            system,
+            model: Model::default(),
+            rng: Rng::new(seed),
+        }
+    }
+
+    pub fn system(&self) -> &System {
+        &self.system
+    }
+
+    pub fn next_op(&mut self) -> Op {
+        Op::generate(&mut self.rng, &self.model)
+    }
+
+    /// Perform `op`, failing if it succeeded or failed when the model says
+    /// it should not have
+    pub fn apply(&mut self, op: &Op) -> Result<(), String> {
+        let System { kernel, vfs, memory, ipc } = &self.system;
+        let model = &mut self.model;
+        match op {
+            Op::Spawn => {
+                let id = kernel.create_process("fuzz".to_string(), Priority::Normal);
+                model.live.push(id);
+            }
+            Op::Terminate(id) => {
+                kernel.terminate_process(*id).map_err(|e| e.to_string())?;
+                model.terminate(*id);
+            }
+            Op::Allocate { process, size } => {
+                match memory.allocate(memory_manager::ProcessId(process.value()), *size) {
+                    Ok(region) => model.regions.entry(*process).or_default().push(region),
+                    Err(e) if e.starts_with("Out of memory") => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            Op::Free { process, region } => {
+                memory.free(memory_manager::ProcessId(process.value()), *region)?;
+                let regions = model.regions.get_mut(process).unwrap();
+                regions.retain(|r| r != region);
+            }
+            Op::CreateDirectory(path) => {
+                let allowed = model.is_directory(path.parent().unwrap())
+                    && !model.is_directory(path)
+                    && !model.files.contains_key(path);
+                expect(allowed, vfs.create_directory(path))?;
+                if allowed {
+                    model.directories.insert(path.clone());
+                }
+            }
+            Op::Write { path, data, append } => {
+                let allowed = model.is_directory(path.parent().unwrap()) && !model.is_directory(path);
+                let options = OpenOptions {
+                    truncate: !append,
+                    append: *append,
+                    ..OpenOptions::write_only()
+                };
+                let written = vfs.open(ProcessId::KERNEL, path, options).and_then(|handle| {
+                    let written = vfs.write(ProcessId::KERNEL, handle, data);
+                    vfs.close(ProcessId::KERNEL, handle)?;
+                    written
+                });
+                expect(allowed, written)?;
+                if allowed {
+                    let content = model.files.entry(path.clone()).or_default();
+                    if !append {
+                        content.clear();
+                    }
+                    content.extend_from_slice(data);
+                }
+            }
+            Op::Delete(path) => {
+                let allowed = model.files.contains_key(path) || (model.directories.contains(path) && !model.has_children(path));
+                expect(allowed, vfs.delete(path))?;
+                if allowed {
+                    model.files.remove(path);
+                    model.directories.remove(path);
+                }
+            }
+            Op::CreateChannel { owner, policy } => {
+                let id = ipc.create_owned_channel(*owner, *policy);
+                model.channels_created += 1;
+                model.channels.insert(id, ChannelModel { owner: *owner, peer: None, policy: *policy, pending: 0 });
+            }
+            Op::Connect { channel, peer } => {
+                let model_channel = model.channels.get_mut(channel);
+                let allowed = model_channel.as_ref().is_some_and(|c| c.peer.is_none());
+                expect(allowed, ipc.connect(*channel, *peer))?;
+                if let Some(c) = model_channel.filter(|_| allowed) {
+                    c.peer = Some(*peer);
+                }
+            }
+            Op::Send { channel, data } => {
+                let model_channel = model.channels.get_mut(channel);
+                expect(model_channel.is_some(), ipc.send_message(*channel, Message::Binary(data.clone())))?;
+                if let Some(c) = model_channel {
+                    c.pending += 1;
+                }
+            }
+            Op::Receive(channel) => {
+                let model_channel = model.channels.get_mut(channel);
+                let received = ipc.receive_message(*channel);
+                expect(model_channel.is_some(), received.clone())?;
+                if let Some(c) = model_channel {
+                    if received.unwrap().is_some() != (c.pending > 0) {
+                        return Err(format!("Receive disagrees with {} pending messages", c.pending));
+                    }
+                    c.pending = c.pending.saturating_sub(1);
+                }
+            }
+            Op::Close(channel) => {
+                let open = model.channels.remove(channel).is_some();
+                if ipc.close_channel(*channel) != open {
+                    return Err(format!("Closing reported open = {}", !open));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the invariants that must hold between any two operations
+    pub fn check(&self) -> Result<(), String> {
+        self.check_processes()?;
+        self.check_memory()?;
+        self.check_channels()?;
+        self.check_files()
+    }
+
+    fn check_processes(&self) -> Result<(), String> {
+        let kernel = &self.system.kernel;
+        if kernel.process_count() != self.model.live.len() + self.model.dead.len() {
+            return Err(format!("Kernel has {} processes", kernel.process_count()));
+        }
+        for id in &self.model.dead {
+            let process = kernel.get_process(*id).ok_or("Terminated process vanished")?;
+            if process.state != ProcessState::Terminated || !process.capabilities.is_empty() {
+                return Err(format!("Process {} still running or holding capabilities", id.value()));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_memory(&self) -> Result<(), String> {
+        let memory = &self.system.memory;
+        let stats = memory.stats();
+        if stats.used_pages + stats.free_pages != stats.total_pages {
+            return Err(format!("{} used and {} free of {} pages", stats.used_pages, stats.free_pages, stats.total_pages));
+        }
+        if stats.used_pages * PAGE_SIZE != stats.used_memory {
+            return Err(format!("{} pages in use for {} bytes", stats.used_pages, stats.used_memory));
+        }
+        let mut owned = 0;
+        for id in self.model.processes() {
+            let expected: usize = self.model.regions.get(&id).map_or(0, |regions| regions.iter().map(|r| r.size).sum());
+            let held = memory.process_memory(memory_manager::ProcessId(id.value()));
+            if held != expected {
+                return Err(format!("Process {} holds {} bytes, expected {}", id.value(), held, expected));
+            }
+            owned += held;
+        }
+        if owned != stats.used_memory {
+            return Err(format!("{} bytes in use but processes own {}", stats.used_memory, owned));
+        }
+        Ok(())
+    }
+
+    fn check_channels(&self) -> Result<(), String> {
+        let ipc = &self.system.ipc;
+        let open: Vec<ChannelId> = self.model.channels.keys().copied().collect();
+        if ipc.list_channels() != open {
+            return Err(format!("Open channels {:?}, expected {:?}", ipc.list_channels(), open));
+        }
+        for (id, expected) in &self.model.channels {
+            let channel = ipc.get_channel(*id).ok_or("Listed channel is gone")?;
+            for end in [channel.owner(), channel.peer()].into_iter().flatten() {
+                if self.model.dead.contains(&end) {
+                    return Err(format!("Channel {} orphaned by process {}", id.value(), end.value()));
+                }
+            }
+            if channel.owner() != Some(expected.owner) || channel.peer() != expected.peer || channel.pending() != expected.pending {
+                return Err(format!("Channel {} is {:?}, expected {:?}", id.value(), channel, expected));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_files(&self) -> Result<(), String> {
+        let vfs = &self.system.vfs;
+        for (path, content) in &self.model.files {
+            if vfs.read_file(path).as_ref() != Ok(content) {
+                return Err(format!("{} does not hold what was written", path.display()));
+            }
+        }
+
+        // Every node is reachable from its parent exactly once
+        let mut found = Vec::new();
+        let mut pending = vec![PathBuf::from("/")];
+        while let Some(directory) = pending.pop() {
+            for child in vfs.list_directory(&directory).map_err(|e| format!("{}: {}", directory.display(), e))? {
+                if vfs.metadata(&child).map_err(|e| format!("{}: {}", child.display(), e))?.is_directory() {
+                    pending.push(child.clone());
+                }
+                found.push(child);
+            }
+        }
+        let stats = vfs.stats();
+        if found.len() + 1 != stats.total_files + stats.total_directories {
+            return Err(format!("{} nodes reachable of {}", found.len() + 1, stats.total_files + stats.total_directories));
+        }
+        let mut modelled: Vec<PathBuf> = found.into_iter().filter(|p| p.starts_with(ROOT) && p != Path::new(ROOT)).collect();
+        modelled.sort();
+        let mut expected: Vec<PathBuf> = self.model.files.keys().chain(&self.model.directories).cloned().collect();
+        expected.sort();
+        if modelled != expected {
+            return Err(format!("Tree holds {:?}, expected {:?}", modelled, expected));
+        }
+        Ok(())
+    }
+}
+
+/// Compare the outcome of an operation with whether it should succeed
+fn expect<T, E: std::fmt::Display>(allowed: bool, result: Result<T, E>) -> Result<(), String> {
+    match (allowed, result) {
+        (true, Err(e)) => Err(format!("Failed: {}", e)),
+        (false, Ok(_)) => Err("Succeeded but should have failed".to_string()),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,109 @@
+//! Randomized system call testing for hairr OS
+//!
+//! Drives seeded random sequences of operations across the kernel, VFS,
+//! memory manager and IPC manager, the way running processes would: they
+//! spawn and exit, allocate and free memory, write and delete files, and
+//! open channels and message each other. After every step the harness
+//! checks that the components agree with each other and with a model of
+//! what should exist, such as no pages held without an owner and no
+//! channel left to a process that has terminated.
+//!
+//! A failure names the seed and step, and `run` with the same seed replays
+//! the same sequence.
+
+use std::fmt;
+
+pub mod harness;
+pub mod ops;
+
+pub use harness::{Harness, System};
+pub use ops::Op;
+
+/// Deterministic xorshift64* generator, so any run can be replayed
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // A zero state would stay zero
+        Rng(seed ^ 0x9e37_79b9_7f4a_7c15)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Uniform in `0..n`; `n` must not be zero
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// True `percent` times in a hundred
+    pub fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+    pub fn pick<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        if items.is_empty() {
+            None
+        } else {
+            Some(&items[self.below(items.len())])
+        }
+    }
+}
+
+/// An operation left the system inconsistent
+#[derive(Debug, Clone)]
+pub struct Failure {
+    pub seed: u64,
+    pub step: usize,
+    pub op: Op,
+    pub problem: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "seed {}, step {}: after {:?}: {}", self.seed, self.step, self.op, self.problem)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Run `steps` random operations from `seed`, checking invariants after
+/// each one
+pub fn run(seed: u64, steps: usize) -> Result<(), Failure> {
+    let mut harness = Harness::new(seed);
+    for step in 0..steps {
+        let op = harness.next_op();
+        harness
+            .apply(&op)
+            .and_then(|()| harness.check())
+            .map_err(|problem| Failure { seed, step, op, problem })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rng_is_deterministic() {
+        let (mut a, mut b) = (Rng::new(7), Rng::new(7));
+        let first: Vec<u64> = (0..4).map(|_| a.next_u64()).collect();
+        assert_eq!(first, (0..4).map(|_| b.next_u64()).collect::<Vec<_>>());
+        assert_ne!(first[0], Rng::new(8).next_u64());
+        assert!((0..100).all(|_| a.below(3) < 3));
+    }
+
+    #[test]
+    fn test_random_operations() {
+        for seed in 0..64 {
+            if let Err(failure) = run(seed, 400) {
+                panic!("{}", failure);
+            }
+        }
+    }
+}
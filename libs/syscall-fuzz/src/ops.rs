@@ -0,0 +1,86 @@
+//! Operations the harness issues
+//!
+//! Calls are made by live processes, since a terminated process can no
+//! longer make any, but their arguments may be stale: a channel that was
+//! closed, a path that was deleted or a process that already exited.
+
+use std::path::PathBuf;
+
+use ipc::{ChannelId, OrphanPolicy};
+use kernel::ProcessId;
+use memory_manager::MemoryRegion;
+
+use crate::harness::Model;
+use crate::Rng;
+
+/// Directories operations create files in, beyond `/tmp` itself
+const DIRECTORIES: &[&str] = &["/tmp/a", "/tmp/b", "/tmp/a/c"];
+/// `/tmp/a/c` may end up a file or a directory
+const FILE_NAMES: &[&str] = &["x", "y", "c"];
+
+#[derive(Debug, Clone)]
+pub enum Op {
+    Spawn,
+    Terminate(ProcessId),
+    Allocate { process: ProcessId, size: usize },
+    Free { process: ProcessId, region: MemoryRegion },
+    CreateDirectory(PathBuf),
+    /// Replace the content of a file, or append to it
+    Write { path: PathBuf, data: Vec<u8>, append: bool },
+    Delete(PathBuf),
+    CreateChannel { owner: ProcessId, policy: OrphanPolicy },
+    Connect { channel: ChannelId, peer: ProcessId },
+    Send { channel: ChannelId, data: Vec<u8> },
+    Receive(ChannelId),
+    Close(ChannelId),
+}
+
+impl Op {
+    pub(crate) fn generate(rng: &mut Rng, model: &Model) -> Op {
+        let Some(&caller) = rng.pick(&model.live) else {
+            return Op::Spawn;
+        };
+        match rng.below(13) {
+            0 => Op::Spawn,
+            // Either the caller exits or it kills someone, possibly someone
+            // already gone
+            1 => Op::Terminate(*rng.pick(&model.processes()).unwrap_or(&caller)),
+            2 | 3 => Op::Allocate { process: caller, size: 1 + rng.below(4 * memory_manager::PAGE_SIZE) },
+            4 => match model.regions.get(&caller).and_then(|regions| rng.pick(regions)) {
+                Some(&region) => Op::Free { process: caller, region },
+                None => Op::Allocate { process: caller, size: 1 },
+            },
+            5 => Op::CreateDirectory(PathBuf::from(*rng.pick(DIRECTORIES).unwrap())),
+            6 | 7 => Op::Write { path: file_path(rng), data: data(rng), append: rng.chance(50) },
+            8 if rng.chance(70) => Op::Delete(file_path(rng)),
+            8 => Op::Delete(PathBuf::from(*rng.pick(DIRECTORIES).unwrap())),
+            9 => Op::CreateChannel {
+                owner: caller,
+                policy: if rng.chance(50) { OrphanPolicy::Close } else { OrphanPolicy::TransferToPeer },
+            },
+            10 => Op::Connect { channel: channel(rng, model), peer: caller },
+            11 if rng.chance(60) => Op::Send { channel: channel(rng, model), data: data(rng) },
+            11 => Op::Receive(channel(rng, model)),
+            _ => Op::Close(channel(rng, model)),
+        }
+    }
+}
+
+fn file_path(rng: &mut Rng) -> PathBuf {
+    let directory = if rng.chance(30) { "/tmp" } else { rng.pick(DIRECTORIES).unwrap() };
+    PathBuf::from(directory).join(rng.pick(FILE_NAMES).unwrap())
+}
+
+fn data(rng: &mut Rng) -> Vec<u8> {
+    (0..rng.below(64)).map(|_| rng.next_u64() as u8).collect()
+}
+
+/// An open channel most of the time, otherwise any id handed out so far
+/// or one never handed out
+fn channel(rng: &mut Rng, model: &Model) -> ChannelId {
+    let open: Vec<ChannelId> = model.channels.keys().copied().collect();
+    match rng.pick(&open) {
+        Some(&id) if rng.chance(80) => id,
+        _ => ChannelId::new(1 + rng.below(model.channels_created + 1) as u64),
+    }
+}
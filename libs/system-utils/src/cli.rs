@@ -0,0 +1,473 @@
+//! Command-line parsing for interactive tools
+//!
+//! A `Cli` is a list of `Command`s, each with positional arguments,
+//! `--flags`, `--options value` and subcommands. Input is split into words
+//! like a shell does, so quoted strings keep their spaces. Parsing checks
+//! argument counts and gives back the command's canonical name and its
+//! values, which are converted to other types when read; help and usage
+//! lines are generated from the same definitions.
+//!
+//! ```ignore
+//! let cli = Cli::new()
+//!     .command(Command::new("install", "Install a package").arg("package").optional_rest("req").flag("locked"))
+//!     .command(Command::new("exit", "Exit").alias("quit"));
+//! let parsed = cli.parse("install viewer ^1.2 --locked")?;
+//! assert_eq!((parsed.command(), parsed.get("req"), parsed.flag("locked")), ("install", Some("^1.2"), true));
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+/// Width of the usage column in help
+const HELP_WIDTH: usize = 24;
+
+/// Split input into words. Single quotes keep everything literally,
+/// double quotes allow `\"` and `\\`, and a backslash outside quotes
+/// escapes the next character.
+pub fn split(input: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("Unterminated single quote".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err("Unterminated double quote".to_string()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("Unterminated double quote".to_string()),
+                    }
+                }
+            }
+            '\\' => word.get_or_insert_with(String::new).push(chars.next().ok_or("Nothing to escape at end of input")?),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArgKind {
+    Required,
+    Optional,
+    /// All remaining words, joined by spaces
+    Rest { required: bool },
+}
+
+#[derive(Debug, Clone)]
+struct Arg {
+    name: String,
+    kind: ArgKind,
+}
+
+#[derive(Debug, Clone)]
+struct Flag {
+    name: String,
+    /// Name of the value an option takes; `None` for plain flags
+    value: Option<String>,
+}
+
+/// A command and what it accepts
+#[derive(Debug, Clone)]
+pub struct Command {
+    name: String,
+    aliases: Vec<String>,
+    about: String,
+    args: Vec<Arg>,
+    flags: Vec<Flag>,
+    subcommands: Vec<Command>,
+}
+
+impl Command {
+    pub fn new(name: &str, about: &str) -> Self {
+        Command {
+            name: name.to_string(),
+            aliases: Vec::new(),
+            about: about.to_string(),
+            args: Vec::new(),
+            flags: Vec::new(),
+            subcommands: Vec::new(),
+        }
+    }
+
+    /// Another name for the command; `Parsed::invoked_as` tells which
+    /// was typed
+    pub fn alias(mut self, alias: &str) -> Self {
+        self.aliases.push(alias.to_string());
+        self
+    }
+
+    fn push_arg(mut self, name: &str, kind: ArgKind) -> Self {
+        assert!(
+            !self.args.iter().any(|a| matches!(a.kind, ArgKind::Rest { .. })),
+            "{}: no arguments can follow {}",
+            self.name,
+            name
+        );
+        self.args.push(Arg { name: name.to_string(), kind });
+        self
+    }
+
+    pub fn arg(self, name: &str) -> Self {
+        self.push_arg(name, ArgKind::Required)
+    }
+
+    pub fn optional(self, name: &str) -> Self {
+        self.push_arg(name, ArgKind::Optional)
+    }
+
+    /// One or more remaining words, as one value. Once it has started,
+    /// `--words` that are not the command's flags belong to it.
+    pub fn rest(self, name: &str) -> Self {
+        self.push_arg(name, ArgKind::Rest { required: true })
+    }
+
+    /// Any remaining words, as one value if there are some
+    pub fn optional_rest(self, name: &str) -> Self {
+        self.push_arg(name, ArgKind::Rest { required: false })
+    }
+
+    /// `--name`, on or off
+    pub fn flag(mut self, name: &str) -> Self {
+        self.flags.push(Flag { name: name.to_string(), value: None });
+        self
+    }
+
+    /// `--name value` or `--name=value`
+    pub fn option(mut self, name: &str, value: &str) -> Self {
+        self.flags.push(Flag {
+            name: name.to_string(),
+            value: Some(value.to_string()),
+        });
+        self
+    }
+
+    /// A command under this one, such as `new` in `workspace new`. Words
+    /// that name no subcommand are this command's own arguments.
+    pub fn subcommand(mut self, command: Command) -> Self {
+        self.subcommands.push(command);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn about(&self) -> &str {
+        &self.about
+    }
+
+    fn matches(&self, word: &str) -> bool {
+        self.name == word || self.aliases.iter().any(|a| a == word)
+    }
+
+    /// The command's own usage, such as `install <package> [req...] [--locked]`
+    pub fn usage(&self) -> String {
+        self.usage_as(&std::iter::once(self.name.as_str()).chain(self.aliases.iter().map(|a| a.as_str())).collect::<Vec<_>>().join("/"))
+    }
+
+    fn usage_as(&self, name: &str) -> String {
+        let mut usage = name.to_string();
+        for arg in &self.args {
+            usage += &match arg.kind {
+                ArgKind::Required => format!(" <{}>", arg.name),
+                ArgKind::Optional => format!(" [{}]", arg.name),
+                ArgKind::Rest { required: true } => format!(" <{}...>", arg.name),
+                ArgKind::Rest { required: false } => format!(" [{}...]", arg.name),
+            };
+        }
+        for flag in &self.flags {
+            usage += &match &flag.value {
+                Some(value) => format!(" [--{} <{}>]", flag.name, value),
+                None => format!(" [--{}]", flag.name),
+            };
+        }
+        usage
+    }
+
+    /// Usage lines of the command and its subcommands, with what they do
+    fn help_lines(&self, prefix: &str, lines: &mut Vec<(String, String)>) {
+        let name = format!("{}{}", prefix, self.usage().split(' ').next().unwrap_or_default());
+        lines.push((self.usage_as(&name), self.about.clone()));
+        for subcommand in &self.subcommands {
+            subcommand.help_lines(&format!("{} ", self.name), lines);
+        }
+    }
+}
+
+/// Why input did not parse
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CliError {
+    /// Blank input
+    Empty,
+    Unknown(String),
+    /// Wrong arguments; holds the usage of the command meant
+    Usage(String),
+    /// Unbalanced quotes and the like
+    Syntax(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Empty => write!(f, "No command given"),
+            CliError::Unknown(command) => write!(f, "Unknown command: {}", command),
+            CliError::Usage(usage) => write!(f, "Usage: {}", usage),
+            CliError::Syntax(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// A parsed command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Parsed {
+    command: String,
+    invoked_as: String,
+    usage: String,
+    values: HashMap<String, String>,
+    flags: HashSet<String>,
+}
+
+impl Parsed {
+    /// Canonical name, with subcommands after a space: `workspace new`
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// The name or alias typed for the last command word
+    pub fn invoked_as(&self) -> &str {
+        &self.invoked_as
+    }
+
+    /// Usage of the command, for reporting bad values
+    pub fn usage(&self) -> &str {
+        &self.usage
+    }
+
+    /// An argument or option value
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(|v| v.as_str())
+    }
+
+    /// An argument or option converted to `T`, if given
+    pub fn value<T: FromStr>(&self, name: &str) -> Result<Option<T>, String> {
+        self.get(name)
+            .map(|value| value.parse().map_err(|_| format!("Invalid {}: {}", name.replace('_', " "), value)))
+            .transpose()
+    }
+
+    /// An argument converted to `T`, with the usage as the error when it
+    /// is missing
+    pub fn parse<T: FromStr>(&self, name: &str) -> Result<T, String> {
+        self.value(name)?.ok_or_else(|| format!("Usage: {}", self.usage))
+    }
+
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.contains(name)
+    }
+}
+
+/// The commands a tool accepts
+#[derive(Debug, Clone, Default)]
+pub struct Cli {
+    commands: Vec<Command>,
+}
+
+impl Cli {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn command(mut self, command: Command) -> Self {
+        self.commands.push(command);
+        self
+    }
+
+    pub fn commands(&self) -> &[Command] {
+        &self.commands
+    }
+
+    /// Usage of a top-level command
+    pub fn usage(&self, name: &str) -> Option<String> {
+        self.commands.iter().find(|c| c.matches(name)).map(|c| c.usage())
+    }
+
+    pub fn parse(&self, input: &str) -> Result<Parsed, CliError> {
+        let words = split(input).map_err(CliError::Syntax)?;
+        let first = words.first().ok_or(CliError::Empty)?;
+        let mut command = self.commands.iter().find(|c| c.matches(first)).ok_or_else(|| CliError::Unknown(first.clone()))?;
+        let mut path = command.name.clone();
+        let mut invoked_as = first.clone();
+        let mut rest = &words[1..];
+        while let Some(sub) = rest.first().and_then(|word| command.subcommands.iter().find(|c| c.matches(word))) {
+            invoked_as = rest[0].clone();
+            path = format!("{} {}", path, sub.name);
+            command = sub;
+            rest = &rest[1..];
+        }
+
+        let usage = command.usage_as(&path);
+        let bad = || CliError::Usage(usage.clone());
+        // Trailing text such as a command to run may have its own options
+        let rest_from = command.args.iter().position(|a| matches!(a.kind, ArgKind::Rest { .. }));
+        let mut values = HashMap::new();
+        let mut flags = HashSet::new();
+        let mut positional = Vec::new();
+        let mut words = rest.iter();
+        let mut options_done = false;
+        while let Some(word) = words.next() {
+            let Some(option) = word.strip_prefix("--").filter(|_| !options_done) else {
+                positional.push(word.clone());
+                continue;
+            };
+            if option.is_empty() {
+                options_done = true;
+                continue;
+            }
+            let (name, inline) = match option.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (option, None),
+            };
+            let Some(flag) = command.flags.iter().find(|f| f.name == name) else {
+                match rest_from {
+                    Some(start) if positional.len() > start => {
+                        positional.push(word.clone());
+                        continue;
+                    }
+                    _ => return Err(bad()),
+                }
+            };
+            match (&flag.value, inline) {
+                (None, None) => {
+                    flags.insert(flag.name.clone());
+                }
+                (Some(_), Some(value)) => {
+                    values.insert(flag.name.clone(), value);
+                }
+                (Some(_), None) => {
+                    values.insert(flag.name.clone(), words.next().ok_or_else(bad)?.clone());
+                }
+                (None, Some(_)) => return Err(bad()),
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        for arg in &command.args {
+            match arg.kind {
+                ArgKind::Rest { required } => {
+                    let words: Vec<String> = positional.by_ref().collect();
+                    if words.is_empty() && required {
+                        return Err(bad());
+                    }
+                    if !words.is_empty() {
+                        values.insert(arg.name.clone(), words.join(" "));
+                    }
+                }
+                kind => match positional.next() {
+                    Some(word) => {
+                        values.insert(arg.name.clone(), word);
+                    }
+                    None if kind == ArgKind::Required => return Err(bad()),
+                    None => {}
+                },
+            }
+        }
+        if positional.next().is_some() {
+            return Err(bad());
+        }
+
+        Ok(Parsed {
+            command: path,
+            invoked_as,
+            usage,
+            values,
+            flags,
+        })
+    }
+
+    /// One line per command: its usage and what it does, run through
+    /// `translate`
+    pub fn help(&self, translate: impl Fn(&str) -> String) -> String {
+        let mut lines = Vec::new();
+        for command in &self.commands {
+            command.help_lines("", &mut lines);
+        }
+        lines
+            .iter()
+            .map(|(usage, about)| format!("  {:<width$} - {}\n", usage, translate(about), width = HELP_WIDTH))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cli() -> Cli {
+        Cli::new()
+            .command(Command::new("install", "Install a package").arg("package").optional_rest("req").flag("locked"))
+            .command(Command::new("screenshots", "Show screenshots").arg("app_id").option("width", "pixels"))
+            .command(
+                Command::new("workspace", "Switch to a workspace")
+                    .arg("id")
+                    .subcommand(Command::new("new", "Create a workspace").optional_rest("name")),
+            )
+            .command(Command::new("exit", "Exit").alias("quit"))
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split(r#"create "My  Window" it\'s 'a "b"'"#).unwrap(), vec!["create", "My  Window", "it's", r#"a "b""#]);
+        assert_eq!(split(r#"x "" y"#).unwrap(), vec!["x", "", "y"]);
+        assert!(split("say \"hello").is_err());
+    }
+
+    #[test]
+    fn test_parse_and_help() {
+        let cli = cli();
+        let parsed = cli.parse("install viewer >=1.0, <2.0 --locked").unwrap();
+        assert_eq!((parsed.command(), parsed.get("package"), parsed.get("req")), ("install", Some("viewer"), Some(">=1.0, <2.0")));
+        assert!(parsed.flag("locked"));
+
+        let parsed = cli.parse("screenshots editor --width=320").unwrap();
+        assert_eq!(parsed.value::<u32>("width"), Ok(Some(320)));
+        assert_eq!(cli.parse("screenshots editor --width wide").unwrap().value::<u32>("width"), Err("Invalid width: wide".to_string()));
+        assert_eq!(cli.parse("screenshots").unwrap_err(), CliError::Usage("screenshots <app_id> [--width <pixels>]".to_string()));
+
+        let parsed = cli.parse("workspace new Side Project").unwrap();
+        assert_eq!((parsed.command(), parsed.get("name")), ("workspace new", Some("Side Project")));
+        assert_eq!(cli.parse("workspace 3").unwrap().parse::<u64>("id"), Ok(3));
+        assert_eq!(cli.parse("quit").unwrap().invoked_as(), "quit");
+        assert_eq!(cli.parse("install a --force").unwrap_err(), CliError::Usage(cli.usage("install").unwrap()));
+        assert_eq!(cli.parse("install a b --force").unwrap().get("req"), Some("b --force"));
+        assert_eq!(cli.parse("frobnicate"), Err(CliError::Unknown("frobnicate".to_string())));
+        assert_eq!(cli.parse("  "), Err(CliError::Empty));
+
+        let help = cli.help(|about| about.to_uppercase());
+        assert!(help.contains("  install <package> [req...] [--locked] - INSTALL A PACKAGE\n"));
+        assert!(help.contains("  workspace new [name...]  - CREATE A WORKSPACE\n"));
+        assert!(help.contains("  exit/quit                - EXIT\n"));
+    }
+}
@@ -0,0 +1,273 @@
+//! In-process event bus
+//!
+//! Components publish events on typed topics and others subscribe to them
+//! without knowing about each other. A subscriber is either called right
+//! away on the publishing thread, or given a bounded queue it drains at its
+//! own pace; a full queue drops new events rather than blocking the
+//! publisher. Weak subscriptions hold their target by `Weak` and end when
+//! it is dropped, as do queues whose receiver is dropped.
+//!
+//! ```ignore
+//! const DEVICE_ADDED: Topic<DeviceInfo> = Topic::new("device.added");
+//! bus.subscribe(&DEVICE_ADDED, |device| println!("{} plugged in", device.name));
+//! bus.publish(&DEVICE_ADDED, info);
+//! ```
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+/// A named stream of events of type `T`. Topics with the same name but
+/// different event types are unrelated.
+pub struct Topic<T> {
+    name: &'static str,
+    event: PhantomData<fn(T)>,
+}
+
+impl<T> Topic<T> {
+    pub const fn new(name: &'static str) -> Self {
+        Topic { name, event: PhantomData }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+/// Subscription identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+type Key = (&'static str, TypeId);
+
+enum Subscriber<T> {
+    Sync(Box<dyn Fn(&T) + Send + Sync>),
+    /// Returns false once its target is gone
+    Weak(Box<dyn Fn(&T) -> bool + Send + Sync>),
+    Buffered { sender: SyncSender<T>, dropped: Arc<AtomicU64> },
+}
+
+impl<T: Clone> Subscriber<T> {
+    /// Hand over an event; false if the subscription has ended
+    fn deliver(&self, event: &T) -> bool {
+        match self {
+            Subscriber::Sync(handler) => {
+                handler(event);
+                true
+            }
+            Subscriber::Weak(handler) => handler(event),
+            Subscriber::Buffered { sender, dropped } => match sender.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    dropped.fetch_add(1, Ordering::Relaxed);
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            },
+        }
+    }
+}
+
+type Subscribers = Vec<(SubscriptionId, Arc<dyn Any + Send + Sync>)>;
+
+/// Publish/subscribe hub shared by cloning
+#[derive(Clone, Default)]
+pub struct EventBus {
+    topics: Arc<Mutex<HashMap<Key, Subscribers>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn add<T: Send + 'static>(&self, topic: &Topic<T>, subscriber: Subscriber<T>) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let mut topics = self.topics.lock().unwrap();
+        topics.entry((topic.name, TypeId::of::<T>())).or_default().push((id, Arc::new(subscriber)));
+        id
+    }
+
+    /// Call `handler` on the publishing thread for each event
+    pub fn subscribe<T, F>(&self, topic: &Topic<T>, handler: F) -> SubscriptionId
+    where
+        T: Send + 'static,
+        F: Fn(&T) + Send + Sync + 'static,
+    {
+        self.add(topic, Subscriber::Sync(Box::new(handler)))
+    }
+
+    /// Call `handler` with `target` for each event, for as long as
+    /// something else keeps `target` alive
+    pub fn subscribe_weak<T, S, F>(&self, topic: &Topic<T>, target: &Arc<S>, handler: F) -> SubscriptionId
+    where
+        T: Send + 'static,
+        S: Send + Sync + 'static,
+        F: Fn(&S, &T) + Send + Sync + 'static,
+    {
+        let target = Arc::downgrade(target);
+        let weak = move |event: &T| match Weak::upgrade(&target) {
+            Some(target) => {
+                handler(&target, event);
+                true
+            }
+            None => false,
+        };
+        self.add(topic, Subscriber::Weak(Box::new(weak)))
+    }
+
+    /// Queue up to `capacity` events for the receiver to take later
+    pub fn subscribe_buffered<T>(&self, topic: &Topic<T>, capacity: usize) -> EventReceiver<T>
+    where
+        T: Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let id = self.add(
+            topic,
+            Subscriber::Buffered {
+                sender,
+                dropped: dropped.clone(),
+            },
+        );
+        EventReceiver { id, receiver, dropped }
+    }
+
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        let mut topics = self.topics.lock().unwrap();
+        let mut found = false;
+        for subscribers in topics.values_mut() {
+            let before = subscribers.len();
+            subscribers.retain(|(sid, _)| *sid != id);
+            found |= subscribers.len() != before;
+        }
+        topics.retain(|_, subscribers| !subscribers.is_empty());
+        found
+    }
+
+    /// Deliver an event to every subscriber of the topic, returning how
+    /// many took it. Handlers run without the bus locked, so they may
+    /// publish or subscribe themselves.
+    pub fn publish<T>(&self, topic: &Topic<T>, event: T) -> usize
+    where
+        T: Clone + Send + 'static,
+    {
+        let key = (topic.name, TypeId::of::<T>());
+        let subscribers = match self.topics.lock().unwrap().get(&key) {
+            Some(subscribers) => subscribers.clone(),
+            None => return 0,
+        };
+
+        let mut ended = Vec::new();
+        let mut delivered = 0;
+        for (id, subscriber) in &subscribers {
+            let Some(subscriber) = subscriber.downcast_ref::<Subscriber<T>>() else {
+                continue;
+            };
+            if subscriber.deliver(&event) {
+                delivered += 1;
+            } else {
+                ended.push(*id);
+            }
+        }
+        for id in ended {
+            self.unsubscribe(id);
+        }
+        delivered
+    }
+
+    pub fn subscriber_count<T: 'static>(&self, topic: &Topic<T>) -> usize {
+        self.topics.lock().unwrap().get(&(topic.name, TypeId::of::<T>())).map_or(0, |s| s.len())
+    }
+}
+
+/// Events queued for a buffered subscription. Dropping it ends the
+/// subscription at the next publish.
+pub struct EventReceiver<T> {
+    id: SubscriptionId,
+    receiver: Receiver<T>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl<T> EventReceiver<T> {
+    pub fn id(&self) -> SubscriptionId {
+        self.id
+    }
+
+    /// Wait for the next event; `None` once unsubscribed and drained
+    pub fn recv(&self) -> Option<T> {
+        self.receiver.recv().ok()
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<T> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+
+    pub fn try_recv(&self) -> Option<T> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Queued events, without waiting
+    pub fn drain(&self) -> Vec<T> {
+        self.receiver.try_iter().collect()
+    }
+
+    /// Events lost because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VOLUME: Topic<u32> = Topic::new("audio.volume");
+    const MUTED: Topic<bool> = Topic::new("audio.volume");
+
+    #[test]
+    fn test_sync_and_weak_subscribers() {
+        let bus = EventBus::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        let id = bus.subscribe(&VOLUME, move |level| log.lock().unwrap().push(*level));
+
+        let widget = Arc::new(Mutex::new(0));
+        bus.subscribe_weak(&VOLUME, &widget, |shown, level| *shown.lock().unwrap() = *level);
+        assert_eq!(bus.publish(&VOLUME, 40), 2);
+        assert_eq!(*widget.lock().unwrap(), 40);
+        // Same name, other type: a different topic
+        assert_eq!(bus.publish(&MUTED, true), 0);
+
+        drop(widget);
+        assert_eq!(bus.publish(&VOLUME, 55), 1);
+        assert_eq!(bus.subscriber_count(&VOLUME), 1);
+        assert!(bus.unsubscribe(id));
+        assert_eq!(bus.publish(&VOLUME, 70), 0);
+        assert_eq!(*seen.lock().unwrap(), vec![40, 55]);
+    }
+
+    #[test]
+    fn test_buffered_subscriber() {
+        let bus = EventBus::new();
+        let receiver = bus.subscribe_buffered(&VOLUME, 2);
+        for level in [10, 20, 30] {
+            bus.publish(&VOLUME, level);
+        }
+        assert_eq!(receiver.drain(), vec![10, 20]);
+        assert_eq!(receiver.dropped(), 1);
+
+        let publisher = bus.clone();
+        std::thread::spawn(move || publisher.publish(&VOLUME, 90)).join().unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(1)), Some(90));
+
+        drop(receiver);
+        assert_eq!(bus.publish(&VOLUME, 100), 0);
+        assert_eq!(bus.subscriber_count(&VOLUME), 0);
+    }
+}
@@ -1,13 +1,33 @@
 //! System Utilities for hairr OS
 //! 
 //! Provides common utility functions and helpers for system operations,
-//! including logging, timing, error handling, and system information.
+//! including logging, timing, tracing, job scheduling, an in-process event
+//! bus, command-line parsing, error handling, checksums, encodings and
+//! system information.
 
 use std::time::{SystemTime, UNIX_EPOCH};
 
+pub mod cli;
+pub mod events;
+pub mod scheduler;
+pub mod tracing;
+
 /// System time utilities
 pub mod time {
     use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, OnceLock};
+    use std::time::{Duration, Instant};
+
+    /// Units `parse_duration` accepts, longest suffix first
+    const DURATION_UNITS: &[(&str, u64)] = &[
+        ("ms", 1),
+        ("s", 1000),
+        ("m", 60 * 1000),
+        ("h", 60 * 60 * 1000),
+        ("d", 24 * 60 * 60 * 1000),
+        ("w", 7 * 24 * 60 * 60 * 1000),
+    ];
 
     /// Get current system time in milliseconds since Unix epoch
     pub fn current_time_ms() -> u64 {
@@ -52,6 +72,268 @@ pub mod time {
             format!("{}ms", ms)
         }
     }
+
+    /// Parse a duration such as `1h30m`, `90s`, `2d` or `250ms`
+    pub fn parse_duration(text: &str) -> Result<Duration, String> {
+        let text = text.trim();
+        if text == "0" {
+            return Ok(Duration::ZERO);
+        }
+        if text.is_empty() {
+            return Err("Empty duration".to_string());
+        }
+
+        let mut rest = text;
+        let mut total: u64 = 0;
+        while !rest.is_empty() {
+            let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+            let value: u64 = rest[..digits].parse().map_err(|_| format!("Invalid duration: {}", text))?;
+            rest = &rest[digits..];
+            let unit_len = rest.find(|c: char| c.is_ascii_digit()).unwrap_or(rest.len());
+            let (_, scale) = DURATION_UNITS
+                .iter()
+                .find(|(unit, _)| *unit == &rest[..unit_len])
+                .ok_or_else(|| format!("Invalid duration unit in {}", text))?;
+            total = value
+                .checked_mul(*scale)
+                .and_then(|ms| total.checked_add(ms))
+                .ok_or_else(|| format!("Duration too long: {}", text))?;
+            rest = &rest[unit_len..];
+        }
+        Ok(Duration::from_millis(total))
+    }
+
+    /// Year, month and day of a day counted from the Unix epoch
+    pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        (year, month, day)
+    }
+
+    /// Days from the Unix epoch to a date
+    pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+        let year = if month <= 2 { year - 1 } else { year };
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let mp = (month as i64 + 9) % 12;
+        let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era * 146_097 + doe - 719_468
+    }
+
+    /// RFC 3339 timestamp in UTC, such as `2024-03-15T10:07:00Z`, with
+    /// milliseconds only when there are some
+    pub fn format_rfc3339(ms: u64) -> String {
+        let (days, day_ms) = ((ms / 86_400_000) as i64, ms % 86_400_000);
+        let (year, month, day) = civil_from_days(days);
+        let seconds = day_ms / 1000;
+        let time = format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", year, month, day, seconds / 3600, seconds / 60 % 60, seconds % 60);
+        match day_ms % 1000 {
+            0 => format!("{}Z", time),
+            millis => format!("{}.{:03}Z", time, millis),
+        }
+    }
+
+    /// Milliseconds since the Unix epoch of an RFC 3339 timestamp; digits
+    /// past milliseconds are dropped
+    pub fn parse_rfc3339(text: &str) -> Result<u64, String> {
+        let invalid = || format!("Invalid RFC 3339 timestamp: {}", text);
+        let bytes = text.as_bytes();
+        if bytes.len() < 20 || bytes[4] != b'-' || bytes[7] != b'-' || !matches!(bytes[10], b'T' | b't' | b' ') || bytes[13] != b':' || bytes[16] != b':' {
+            return Err(invalid());
+        }
+        let number = |range: std::ops::Range<usize>| -> Result<i64, String> {
+            let digits = text.get(range).ok_or_else(invalid)?;
+            if !digits.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            digits.parse().map_err(|_| invalid())
+        };
+        let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+        let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+            return Err(invalid());
+        }
+
+        let mut rest = &text[19..];
+        let mut millis = 0;
+        if let Some(fraction) = rest.strip_prefix('.') {
+            let digits = fraction.find(|c: char| !c.is_ascii_digit()).unwrap_or(fraction.len());
+            if digits == 0 {
+                return Err(invalid());
+            }
+            let padded = format!("{:0<3}", &fraction[..digits.min(3)]);
+            millis = padded.parse::<i64>().map_err(|_| invalid())?;
+            rest = &fraction[digits..];
+        }
+        let offset_minutes = match rest {
+            "Z" | "z" => 0,
+            _ if rest.len() == 6 && rest.as_bytes()[3] == b':' => {
+                let sign = match rest.as_bytes()[0] {
+                    b'+' => 1,
+                    b'-' => -1,
+                    _ => return Err(invalid()),
+                };
+                let hours: i64 = rest[1..3].parse().map_err(|_| invalid())?;
+                let minutes: i64 = rest[4..6].parse().map_err(|_| invalid())?;
+                sign * (hours * 60 + minutes)
+            }
+            _ => return Err(invalid()),
+        };
+
+        let days = days_from_civil(year, month as u32, day as u32);
+        let seconds = days * 86_400 + hour * 3600 + minute * 60 + second - offset_minutes * 60;
+        u64::try_from(seconds * 1000 + millis).map_err(|_| format!("Timestamp before 1970: {}", text))
+    }
+
+    /// Where components that schedule, wait or expire things read the time
+    pub trait Clock: std::fmt::Debug + Send + Sync {
+        /// Milliseconds since the Unix epoch
+        fn now_ms(&self) -> u64;
+
+        /// Time since a fixed point; never goes backwards
+        fn monotonic(&self) -> Duration;
+    }
+
+    /// The host's clocks
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct SystemClock;
+
+    impl Clock for SystemClock {
+        fn now_ms(&self) -> u64 {
+            current_time_ms()
+        }
+
+        fn monotonic(&self) -> Duration {
+            static ORIGIN: OnceLock<Instant> = OnceLock::new();
+            ORIGIN.get_or_init(Instant::now).elapsed()
+        }
+    }
+
+    /// The host's clocks, as a shared `Clock`
+    pub fn system_clock() -> Arc<dyn Clock> {
+        Arc::new(SystemClock)
+    }
+
+    /// Virtual time that only moves when advanced, so deadlines, expiry and
+    /// schedules behave the same on every run
+    #[derive(Debug, Default)]
+    pub struct SimulatedClock {
+        start_ms: u64,
+        elapsed_ns: AtomicU64,
+    }
+
+    impl SimulatedClock {
+        /// A clock reading `start_ms` milliseconds since the Unix epoch
+        pub fn new(start_ms: u64) -> Self {
+            SimulatedClock {
+                start_ms,
+                elapsed_ns: AtomicU64::new(0),
+            }
+        }
+
+        pub fn advance(&self, by: Duration) {
+            self.elapsed_ns.fetch_add(by.as_nanos() as u64, Ordering::SeqCst);
+        }
+
+        /// Time advanced since the clock was created
+        pub fn elapsed(&self) -> Duration {
+            Duration::from_nanos(self.elapsed_ns.load(Ordering::SeqCst))
+        }
+    }
+
+    impl Clock for SimulatedClock {
+        fn now_ms(&self) -> u64 {
+            self.start_ms + self.elapsed().as_millis() as u64
+        }
+
+        fn monotonic(&self) -> Duration {
+            self.elapsed()
+        }
+    }
+
+    /// Measures elapsed time on a clock's monotonic time
+    #[derive(Debug, Clone)]
+    pub struct Stopwatch {
+        started: Duration,
+        clock: Arc<dyn Clock>,
+    }
+
+    impl Stopwatch {
+        pub fn start() -> Self {
+            Stopwatch::start_on(&system_clock())
+        }
+
+        pub fn start_on(clock: &Arc<dyn Clock>) -> Self {
+            Stopwatch {
+                started: clock.monotonic(),
+                clock: Arc::clone(clock),
+            }
+        }
+
+        pub fn elapsed(&self) -> Duration {
+            self.clock.monotonic().saturating_sub(self.started)
+        }
+
+        pub fn elapsed_ms(&self) -> u64 {
+            self.elapsed().as_millis() as u64
+        }
+
+        /// Time since the start or the last lap, starting a new lap
+        pub fn lap(&mut self) -> Duration {
+            let now = self.clock.monotonic();
+            let lap = now.saturating_sub(self.started);
+            self.started = now;
+            lap
+        }
+    }
+
+    /// A point in a clock's monotonic time after which waiting stops
+    #[derive(Debug, Clone)]
+    pub struct Deadline {
+        at: Duration,
+        clock: Arc<dyn Clock>,
+    }
+
+    impl Deadline {
+        /// `timeout` from now
+        pub fn after(timeout: Duration) -> Self {
+            Deadline::after_on(&system_clock(), timeout)
+        }
+
+        /// `timeout` from now on `clock`
+        pub fn after_on(clock: &Arc<dyn Clock>, timeout: Duration) -> Self {
+            Deadline {
+                at: clock.monotonic() + timeout,
+                clock: Arc::clone(clock),
+            }
+        }
+
+        pub fn expired(&self) -> bool {
+            self.clock.monotonic() >= self.at
+        }
+
+        /// Time left, zero once expired
+        pub fn remaining(&self) -> Duration {
+            self.at.saturating_sub(self.clock.monotonic())
+        }
+
+        /// `Err(message)` once expired, for ending wait loops with `?`
+        pub fn check(&self, message: &str) -> Result<(), String> {
+            if self.expired() {
+                Err(message.to_string())
+            } else {
+                Ok(())
+            }
+        }
+    }
 }
 
 /// Memory utilities
@@ -110,55 +392,191 @@ pub mod memory {
     }
 }
 
-/// String utilities
+/// String utilities. Text is measured in grapheme clusters (what a reader
+/// sees as one character) and terminal columns, so cutting or padding
+/// never splits a character and wide CJK text or emoji line up.
 pub mod string {
-    /// Truncate string to specified length with ellipsis
-    pub fn truncate(s: &str, max_len: usize) -> String {
-        if s.len() <= max_len {
-            s.to_string()
-        } else if max_len <= 3 {
-            "...".to_string()
-        } else {
-            format!("{}...", &s[..max_len - 3])
+    /// Characters that join the one before them into a cluster
+    fn extends(ch: char) -> bool {
+        matches!(
+            ch as u32,
+            0x0300..=0x036F // combining diacritics
+                | 0x0483..=0x0489
+                | 0x0591..=0x05BD
+                | 0x0610..=0x061A
+                | 0x064B..=0x065F
+                | 0x0E31 | 0x0E34..=0x0E3A | 0x0E47..=0x0E4E
+                | 0x1AB0..=0x1AFF
+                | 0x1DC0..=0x1DFF
+                | 0x200C..=0x200D // zero-width (non-)joiner
+                | 0x20D0..=0x20FF
+                | 0xFE00..=0xFE0F // variation selectors
+                | 0xFE20..=0xFE2F
+                | 0x1F3FB..=0x1F3FF // skin tones
+                | 0xE0020..=0xE007F // emoji tag sequences
+        )
+    }
+
+    fn is_regional_indicator(ch: char) -> bool {
+        matches!(ch as u32, 0x1F1E6..=0x1F1FF)
+    }
+
+    /// Split into grapheme clusters: a character with the marks, joiners
+    /// and modifiers that follow it, a flag's two regional indicators, or
+    /// CR LF
+    pub fn graphemes(s: &str) -> Vec<&str> {
+        let mut clusters = Vec::new();
+        let mut start = 0;
+        let mut prev: Option<char> = None;
+        let mut indicators = 0;
+        for (i, ch) in s.char_indices() {
+            let joined = match prev {
+                None => true,
+                Some('\u{200D}') => true,
+                Some('\r') => ch == '\n',
+                Some(p) if is_regional_indicator(p) && is_regional_indicator(ch) => indicators % 2 == 1,
+                Some(_) => extends(ch),
+            };
+            if !joined {
+                clusters.push(&s[start..i]);
+                start = i;
+            }
+            indicators = if is_regional_indicator(ch) { indicators + 1 } else { 0 };
+            prev = Some(ch);
+        }
+        if start < s.len() {
+            clusters.push(&s[start..]);
         }
+        clusters
     }
 
-    /// Pad string to specified length
-    pub fn pad_left(s: &str, width: usize, ch: char) -> String {
-        if s.len() >= width {
-            s.to_string()
-        } else {
-            format!("{}{}", ch.to_string().repeat(width - s.len()), s)
+    /// Terminal columns a character takes: none for controls and marks,
+    /// two for wide East Asian characters and emoji
+    pub fn char_width(ch: char) -> usize {
+        if ch.is_control() || extends(ch) {
+            return 0;
+        }
+        match ch as u32 {
+            0x1100..=0x115F
+            | 0x2E80..=0x303E
+            | 0x3041..=0x33FF
+            | 0x3400..=0x4DBF
+            | 0x4E00..=0x9FFF
+            | 0xA000..=0xA4CF
+            | 0xAC00..=0xD7A3
+            | 0xF900..=0xFAFF
+            | 0xFE30..=0xFE4F
+            | 0xFF00..=0xFF60
+            | 0xFFE0..=0xFFE6
+            | 0x1F1E6..=0x1F1FF
+            | 0x1F300..=0x1F64F
+            | 0x1F680..=0x1F6FF
+            | 0x1F900..=0x1F9FF
+            | 0x20000..=0x3FFFD => 2,
+            _ => 1,
+        }
+    }
+
+    /// Terminal columns a cluster takes, that of its first character
+    fn cluster_width(cluster: &str) -> usize {
+        cluster.chars().next().map_or(0, char_width)
+    }
+
+    /// Terminal columns the string takes
+    pub fn width(s: &str) -> usize {
+        graphemes(s).into_iter().map(cluster_width).sum()
+    }
+
+    /// Shorten to at most `max_width` columns, ending in "..." when cut
+    pub fn truncate(s: &str, max_width: usize) -> String {
+        if width(s) <= max_width {
+            return s.to_string();
         }
+        if max_width <= 3 {
+            return "...".to_string();
+        }
+        let mut result = String::new();
+        let mut used = 0;
+        for cluster in graphemes(s) {
+            let w = cluster_width(cluster);
+            if used + w > max_width - 3 {
+                break;
+            }
+            result.push_str(cluster);
+            used += w;
+        }
+        result + "..."
+    }
+
+    /// `ch` repeated to fill the columns `s` leaves of `width`
+    fn fill(s: &str, width: usize, ch: char) -> String {
+        let columns = width.saturating_sub(self::width(s));
+        ch.to_string().repeat(columns / char_width(ch).max(1))
     }
 
-    /// Pad string to specified length on the right
+    /// Pad string to specified width on the left
+    pub fn pad_left(s: &str, width: usize, ch: char) -> String {
+        format!("{}{}", fill(s, width, ch), s)
+    }
+
+    /// Pad string to specified width on the right
     pub fn pad_right(s: &str, width: usize, ch: char) -> String {
-        if s.len() >= width {
-            s.to_string()
-        } else {
-            format!("{}{}", s, ch.to_string().repeat(width - s.len()))
+        format!("{}{}", s, fill(s, width, ch))
+    }
+
+    /// Words of an identifier, split at punctuation, spaces and case
+    /// changes: `HTTPServer_v2` gives `HTTP`, `Server`, `v2`
+    fn words(s: &str) -> Vec<String> {
+        let chars: Vec<char> = s.chars().collect();
+        let mut words = Vec::new();
+        let mut word = String::new();
+        for (i, &ch) in chars.iter().enumerate() {
+            if !ch.is_alphanumeric() {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+                continue;
+            }
+            if ch.is_uppercase() && !word.is_empty() {
+                let prev = chars[i - 1];
+                let next_lower = chars.get(i + 1).is_some_and(|c| c.is_lowercase());
+                if prev.is_lowercase() || prev.is_numeric() || (prev.is_uppercase() && next_lower) {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            word.push(ch);
+        }
+        if !word.is_empty() {
+            words.push(word);
         }
+        words
     }
 
     /// Convert string to snake_case
     pub fn to_snake_case(s: &str) -> String {
-        let mut result = String::new();
-        let mut prev_lowercase = false;
+        words(s).iter().map(|w| w.to_lowercase()).collect::<Vec<_>>().join("_")
+    }
 
-        for (i, ch) in s.chars().enumerate() {
-            if ch.is_uppercase() {
-                if i > 0 && prev_lowercase {
-                    result.push('_');
-                }
-                result.push(ch.to_lowercase().next().unwrap());
-                prev_lowercase = false;
-            } else {
-                result.push(ch);
-                prev_lowercase = ch.is_lowercase();
+    /// Convert string to kebab-case, the form of package IDs
+    pub fn to_kebab_case(s: &str) -> String {
+        words(s).iter().map(|w| w.to_lowercase()).collect::<Vec<_>>().join("-")
+    }
+
+    /// Convert string to camelCase
+    pub fn to_camel_case(s: &str) -> String {
+        let mut result = String::new();
+        for (i, word) in words(s).iter().enumerate() {
+            let lower = word.to_lowercase();
+            if i == 0 {
+                result.push_str(&lower);
+                continue;
+            }
+            let mut chars = lower.chars();
+            if let Some(first) = chars.next() {
+                result.extend(first.to_uppercase());
+                result.push_str(chars.as_str());
             }
         }
-
         result
     }
 }
@@ -197,6 +615,23 @@ pub mod error {
 
     impl std::error::Error for SystemError {}
 
+    /// A crate's error enum, with a code that stays the same across
+    /// releases so callers and logs can tell errors apart without matching
+    /// on messages. Codes are unique across the system: the kernel uses
+    /// 1xx, IPC 2xx and the filesystem 3xx.
+    pub trait ErrorCode: std::error::Error {
+        fn code(&self) -> u32;
+
+        /// Component reported in `SystemError`, such as "kernel"
+        fn component(&self) -> &'static str;
+    }
+
+    impl<E: ErrorCode> From<E> for SystemError {
+        fn from(error: E) -> Self {
+            SystemError::new(error.code(), error.to_string(), error.component().to_string())
+        }
+    }
+
     /// Result type for system operations
     pub type SystemResult<T> = Result<T, SystemError>;
 }
@@ -205,6 +640,7 @@ pub mod error {
 pub mod logging {
     use std::sync::Mutex;
     use std::collections::VecDeque;
+    use std::fmt;
 
     /// Log level
     #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -248,6 +684,12 @@ pub mod logging {
         }
     }
 
+    impl fmt::Display for LogEntry {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{} [{}] [{}] {}", crate::time::format_rfc3339(self.timestamp), self.level.as_str(), self.component, self.message)
+        }
+    }
+
     /// Simple in-memory logger
     pub struct Logger {
         entries: Mutex<VecDeque<LogEntry>>,
@@ -276,10 +718,9 @@ pub mod logging {
                 entries.pop_front();
             }
 
-            entries.push_back(entry.clone());
-
             // Also print to stdout
-            println!("[{}] [{}] {}", level.as_str(), component, message);
+            println!("{}", entry);
+            entries.push_back(entry);
         }
 
         pub fn debug(&self, component: &str, message: &str) {
@@ -320,6 +761,23 @@ pub mod logging {
 
 /// System information utilities
 pub mod sysinfo {
+    use std::sync::{Arc, Mutex, OnceLock};
+
+    /// Seconds the 1, 5 and 15 minute load averages decay over
+    const LOAD_WINDOWS: [f64; 3] = [60.0, 300.0, 900.0];
+
+    /// Hostname until the configuration provides one
+    pub const DEFAULT_HOSTNAME: &str = "hairr-system";
+
+    type Source<T> = Arc<dyn Fn() -> T + Send + Sync>;
+
+    /// Physical memory, in bytes
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct MemoryUsage {
+        pub total: u64,
+        pub used: u64,
+    }
+
     /// System information
     #[derive(Debug, Clone)]
     pub struct SystemInfo {
@@ -328,44 +786,323 @@ pub mod sysinfo {
         pub architecture: String,
         pub cpu_count: usize,
         pub hostname: String,
+        pub uptime_ms: u64,
+        pub load_average: (f32, f32, f32),
+        /// `None` until a memory manager reports it
+        pub memory: Option<MemoryUsage>,
     }
 
     impl SystemInfo {
+        /// Snapshot from the global monitor
+        pub fn new() -> Self {
+            monitor().info()
+        }
+    }
+
+    impl Default for SystemInfo {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    struct Load {
+        averages: [f64; 3],
+        sampled_at: u64,
+    }
+
+    struct Sources {
+        boot_time_ms: u64,
+        run_queue: Option<Source<usize>>,
+        memory: Option<Source<MemoryUsage>>,
+        hostname: Option<Source<String>>,
+    }
+
+    /// Where system figures come from. The kernel reports when it booted,
+    /// the scheduler how many tasks are runnable and the memory manager
+    /// its totals; whatever is not attached falls back to what the process
+    /// itself knows.
+    #[derive(Clone)]
+    pub struct SystemMonitor {
+        sources: Arc<Mutex<Sources>>,
+        load: Arc<Mutex<Load>>,
+    }
+
+    impl SystemMonitor {
         pub fn new() -> Self {
+            let now = crate::time::current_time_ms();
+            SystemMonitor {
+                sources: Arc::new(Mutex::new(Sources {
+                    boot_time_ms: now,
+                    run_queue: None,
+                    memory: None,
+                    hostname: None,
+                })),
+                load: Arc::new(Mutex::new(Load {
+                    averages: [0.0; 3],
+                    sampled_at: now,
+                })),
+            }
+        }
+
+        /// When the system booted, in milliseconds since the Unix epoch
+        pub fn set_boot_time(&self, boot_time_ms: u64) {
+            self.sources.lock().unwrap().boot_time_ms = boot_time_ms;
+        }
+
+        /// Count of tasks running or waiting to run, which load is made of
+        pub fn attach_run_queue(&self, source: impl Fn() -> usize + Send + Sync + 'static) {
+            self.sources.lock().unwrap().run_queue = Some(Arc::new(source));
+        }
+
+        pub fn attach_memory(&self, source: impl Fn() -> MemoryUsage + Send + Sync + 'static) {
+            self.sources.lock().unwrap().memory = Some(Arc::new(source));
+        }
+
+        pub fn attach_hostname(&self, source: impl Fn() -> String + Send + Sync + 'static) {
+            self.sources.lock().unwrap().hostname = Some(Arc::new(source));
+        }
+
+        pub fn boot_time_ms(&self) -> u64 {
+            self.sources.lock().unwrap().boot_time_ms
+        }
+
+        pub fn uptime_ms(&self) -> u64 {
+            crate::time::current_time_ms().saturating_sub(self.boot_time_ms())
+        }
+
+        /// Run-queue length averaged over 1, 5 and 15 minutes
+        pub fn load_average(&self) -> (f32, f32, f32) {
+            let [one, five, fifteen] = self.sample_load(crate::time::current_time_ms());
+            (one as f32, five as f32, fifteen as f32)
+        }
+
+        /// Fold the current run-queue length into the averages. It is taken
+        /// to have held since the last sample, so sampling often, as a
+        /// scheduler tick does, tracks bursts more closely.
+        pub fn sample_load(&self, now_ms: u64) -> [f64; 3] {
+            // Sources may lock their own state; call them without ours held
+            let run_queue = self.sources.lock().unwrap().run_queue.clone();
+            let runnable = run_queue.map_or(0, |source| source()) as f64;
+            let mut load = self.load.lock().unwrap();
+            let elapsed = now_ms.saturating_sub(load.sampled_at) as f64 / 1000.0;
+            for (average, window) in load.averages.iter_mut().zip(LOAD_WINDOWS) {
+                let decay = (-elapsed / window).exp();
+                *average = *average * decay + runnable * (1.0 - decay);
+            }
+            load.sampled_at = load.sampled_at.max(now_ms);
+            load.averages
+        }
+
+        pub fn memory(&self) -> Option<MemoryUsage> {
+            let memory = self.sources.lock().unwrap().memory.clone();
+            memory.map(|source| source())
+        }
+
+        pub fn hostname(&self) -> String {
+            let hostname = self.sources.lock().unwrap().hostname.clone();
+            hostname.map_or_else(|| DEFAULT_HOSTNAME.to_string(), |source| source())
+        }
+
+        pub fn info(&self) -> SystemInfo {
             SystemInfo {
                 os_name: "hairr OS".to_string(),
                 os_version: "0.1.0".to_string(),
                 architecture: std::env::consts::ARCH.to_string(),
                 cpu_count: num_cpus::get(),
-                hostname: "hairr-system".to_string(),
+                hostname: self.hostname(),
+                uptime_ms: self.uptime_ms(),
+                load_average: self.load_average(),
+                memory: self.memory(),
             }
         }
     }
 
-    impl Default for SystemInfo {
+    impl Default for SystemMonitor {
         fn default() -> Self {
             Self::new()
         }
     }
 
+    /// The monitor components report to, created on first use
+    pub fn monitor() -> &'static SystemMonitor {
+        static MONITOR: OnceLock<SystemMonitor> = OnceLock::new();
+        MONITOR.get_or_init(SystemMonitor::new)
+    }
+
     /// Get system uptime in milliseconds
     pub fn uptime_ms() -> u64 {
-        // Simplified - in a real OS, this would read from the kernel
-        crate::time::current_time_ms()
+        monitor().uptime_ms()
     }
 
     /// Get load average
     pub fn load_average() -> (f32, f32, f32) {
-        // Simplified - in a real OS, this would read from the scheduler
-        (0.5, 0.7, 0.9)
+        monitor().load_average()
     }
 }
 
 /// Hash utilities
+///
+/// `hash_bytes` and `hash_value` are only for in-process tables: they
+/// differ between builds. Anything stored or sent uses `crc32` to catch
+/// accidental corruption or `sha256` where content must not be forgeable.
 pub mod hash {
     use std::collections::hash_map::DefaultHasher;
     use std::hash::{Hash, Hasher};
 
+    /// Reflected CRC-32 polynomial, as used by zlib, PNG and Ethernet
+    const CRC32_POLYNOMIAL: u32 = 0xedb8_8320;
+
+    const CRC32_TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 != 0 { (crc >> 1) ^ CRC32_POLYNOMIAL } else { crc >> 1 };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+
+    const SHA256_K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5, 0xd807aa98, 0x12835b01,
+        0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc,
+        0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147,
+        0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+        0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116, 0x1e376c08,
+        0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3, 0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208,
+        0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+    ];
+
+    const SHA256_INIT: [u32; 8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+
+    /// CRC-32 of data
+    pub fn crc32(data: &[u8]) -> u32 {
+        crc32_update(0, data)
+    }
+
+    /// CRC-32 of data following whatever gave `crc`
+    pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+        let mut crc = !crc;
+        for byte in data {
+            crc = CRC32_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+        }
+        !crc
+    }
+
+    /// SHA-256 of data
+    pub fn sha256(data: &[u8]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        hasher.finish()
+    }
+
+    /// SHA-256 of data given in pieces
+    #[derive(Clone)]
+    pub struct Sha256 {
+        state: [u32; 8],
+        block: [u8; 64],
+        filled: usize,
+        length: u64,
+    }
+
+    impl Sha256 {
+        pub fn new() -> Self {
+            Sha256 {
+                state: SHA256_INIT,
+                block: [0; 64],
+                filled: 0,
+                length: 0,
+            }
+        }
+
+        pub fn update(&mut self, mut data: &[u8]) {
+            self.length += data.len() as u64;
+            while !data.is_empty() {
+                let take = (64 - self.filled).min(data.len());
+                self.block[self.filled..self.filled + take].copy_from_slice(&data[..take]);
+                self.filled += take;
+                data = &data[take..];
+                if self.filled == 64 {
+                    self.compress();
+                    self.filled = 0;
+                }
+            }
+        }
+
+        pub fn finish(mut self) -> [u8; 32] {
+            let bits = self.length * 8;
+            self.update(&[0x80]);
+            while self.filled != 56 {
+                self.update(&[0]);
+            }
+            self.update(&bits.to_be_bytes());
+
+            let mut digest = [0u8; 32];
+            for (out, word) in digest.chunks_mut(4).zip(self.state) {
+                out.copy_from_slice(&word.to_be_bytes());
+            }
+            digest
+        }
+
+        fn compress(&mut self) {
+            let mut w = [0u32; 64];
+            for (i, word) in self.block.chunks(4).enumerate() {
+                w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+            }
+            for i in 16..64 {
+                let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+                let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+                w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+            }
+            let mut v = self.state;
+            for i in 0..64 {
+                let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+                let choice = (v[4] & v[5]) ^ (!v[4] & v[6]);
+                let t1 = v[7].wrapping_add(s1).wrapping_add(choice).wrapping_add(SHA256_K[i]).wrapping_add(w[i]);
+                let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+                let majority = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+                let t2 = s0.wrapping_add(majority);
+                v = [t1.wrapping_add(t2), v[0], v[1], v[2], v[3].wrapping_add(t1), v[4], v[5], v[6]];
+            }
+            for (state, value) in self.state.iter_mut().zip(v) {
+                *state = state.wrapping_add(value);
+            }
+        }
+    }
+
+    impl Default for Sha256 {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    /// HMAC-SHA256 of data under key (RFC 2104)
+    pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+        let mut block = [0u8; 64];
+        if key.len() > block.len() {
+            block[..32].copy_from_slice(&sha256(key));
+        } else {
+            block[..key.len()].copy_from_slice(key);
+        }
+        let mut inner = Sha256::new();
+        inner.update(&block.map(|b| b ^ 0x36));
+        inner.update(data);
+        let mut outer = Sha256::new();
+        outer.update(&block.map(|b| b ^ 0x5c));
+        outer.update(&inner.finish());
+        outer.finish()
+    }
+
+    /// Compare two byte strings in time that depends only on their lengths
+    pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+    }
+
     /// Calculate simple hash of data
     pub fn hash_bytes(data: &[u8]) -> u64 {
         let mut hasher = DefaultHasher::new();
@@ -381,6 +1118,87 @@ pub mod hash {
     }
 }
 
+/// Text encodings of binary data
+pub mod encoding {
+    const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    const BASE64URL: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+    /// Lowercase hex
+    pub fn hex_encode(data: &[u8]) -> String {
+        data.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Hex in either case
+    pub fn hex_decode(text: &str) -> Result<Vec<u8>, String> {
+        if !text.len().is_multiple_of(2) {
+            return Err("Hex data has an odd number of digits".to_string());
+        }
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(text.get(i..i + 2).ok_or("Invalid hex data")?, 16).map_err(|_| "Invalid hex data".to_string()))
+            .collect()
+    }
+
+    /// Standard base64, padded
+    pub fn base64_encode(data: &[u8]) -> String {
+        encode(data, BASE64, true)
+    }
+
+    /// Standard base64; padding is optional and whitespace is skipped
+    pub fn base64_decode(text: &str) -> Result<Vec<u8>, String> {
+        decode(text, BASE64)
+    }
+
+    /// URL-safe base64 without padding, as used in JWTs
+    pub fn base64url_encode(data: &[u8]) -> String {
+        encode(data, BASE64URL, false)
+    }
+
+    /// URL-safe base64; padding is optional
+    pub fn base64url_decode(text: &str) -> Result<Vec<u8>, String> {
+        decode(text, BASE64URL)
+    }
+
+    fn encode(data: &[u8], alphabet: &[u8; 64], pad: bool) -> String {
+        let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+        for chunk in data.chunks(3) {
+            let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+            let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    out.push(alphabet[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+                } else if pad {
+                    out.push('=');
+                }
+            }
+        }
+        out
+    }
+
+    fn decode(text: &str, alphabet: &[u8; 64]) -> Result<Vec<u8>, String> {
+        let digits: Vec<u8> = text.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+        let padding = digits.iter().rev().take_while(|b| **b == b'=').count();
+        let digits = &digits[..digits.len() - padding];
+        if padding > 2 || (padding > 0 && !(digits.len() + padding).is_multiple_of(4)) || digits.len() % 4 == 1 {
+            return Err("Invalid base64 length".to_string());
+        }
+
+        let mut out = Vec::with_capacity(digits.len() * 3 / 4);
+        let (mut buffer, mut bits) = (0u32, 0);
+        for digit in digits {
+            let value = alphabet.iter().position(|c| c == digit).ok_or("Invalid base64 data")? as u32;
+            buffer = (buffer << 6) | value;
+            bits += 6;
+            if bits >= 8 {
+                bits -= 8;
+                out.push((buffer >> bits) as u8);
+                buffer &= (1 << bits) - 1;
+            }
+        }
+        Ok(out)
+    }
+}
+
 /// UUID generation
 pub mod uuid {
     use std::time::{SystemTime, UNIX_EPOCH};
@@ -414,6 +1232,63 @@ mod tests {
         assert_eq!(time::format_duration(65000), "1m 5s");
     }
 
+    #[test]
+    fn test_duration_parsing() {
+        use std::time::Duration;
+        assert_eq!(time::parse_duration("1h30m").unwrap(), Duration::from_secs(5400));
+        assert_eq!(time::parse_duration("2d").unwrap(), Duration::from_secs(2 * 86_400));
+        assert_eq!(time::parse_duration("1s250ms").unwrap(), Duration::from_millis(1250));
+        assert_eq!(time::parse_duration("0").unwrap(), Duration::ZERO);
+        assert!(time::parse_duration("90").is_err());
+        assert!(time::parse_duration("5 minutes").is_err());
+        assert!(time::parse_duration("h").is_err());
+    }
+
+    #[test]
+    fn test_civil_dates() {
+        assert_eq!(time::days_from_civil(1970, 1, 1), 0);
+        assert_eq!(time::civil_from_days(19_723), (2024, 1, 1));
+        for days in [-1, 0, 59, 60, 11_016, 19_782, 100_000] {
+            let (y, m, d) = time::civil_from_days(days);
+            assert_eq!(time::days_from_civil(y, m, d), days);
+        }
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        assert_eq!(time::format_rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(time::format_rfc3339(1_709_164_800_123), "2024-02-29T00:00:00.123Z");
+        assert_eq!(time::parse_rfc3339("2024-02-29T00:00:00.123Z").unwrap(), 1_709_164_800_123);
+        assert_eq!(time::parse_rfc3339("2024-02-29T02:30:00+02:30").unwrap(), 1_709_164_800_000);
+        assert_eq!(time::parse_rfc3339("2024-02-29t00:00:00.1234z").unwrap(), 1_709_164_800_123);
+        assert!(time::parse_rfc3339("2024-02-29").is_err());
+        assert!(time::parse_rfc3339("2024-13-01T00:00:00Z").is_err());
+
+        let deadline = time::Deadline::after(std::time::Duration::from_secs(60));
+        assert!(!deadline.expired() && deadline.check("timed out").is_ok());
+        assert!(time::Deadline::after(std::time::Duration::ZERO).check("timed out").is_err());
+    }
+
+    #[test]
+    fn test_simulated_clock() {
+        use std::time::Duration;
+        use time::Clock;
+
+        let simulated = std::sync::Arc::new(time::SimulatedClock::new(1_709_164_800_000));
+        let clock: std::sync::Arc<dyn Clock> = simulated.clone();
+        let deadline = time::Deadline::after_on(&clock, Duration::from_secs(5));
+        let mut stopwatch = time::Stopwatch::start_on(&clock);
+
+        simulated.advance(Duration::from_millis(4_999));
+        assert!(!deadline.expired());
+        assert_eq!(deadline.remaining(), Duration::from_millis(1));
+        assert_eq!(stopwatch.lap(), Duration::from_millis(4_999));
+        simulated.advance(Duration::from_millis(1));
+        assert!(deadline.check("timed out").is_err());
+        assert_eq!(stopwatch.elapsed_ms(), 1);
+        assert_eq!(clock.now_ms(), 1_709_164_805_000);
+    }
+
     #[test]
     fn test_memory_formatting() {
         assert_eq!(memory::format_bytes(0), "0 B");
@@ -438,18 +1313,55 @@ mod tests {
     fn test_string_truncate() {
         assert_eq!(string::truncate("hello world", 5), "he...");
         assert_eq!(string::truncate("hello", 10), "hello");
+        assert_eq!(string::truncate("héllo wörld", 7), "héll...");
+        assert_eq!(string::truncate("日本語のテキスト", 9), "日本語...");
+        assert_eq!(string::truncate("cafe\u{301} au lait", 7), "cafe\u{301}...");
     }
 
     #[test]
     fn test_string_padding() {
         assert_eq!(string::pad_left("42", 5, '0'), "00042");
         assert_eq!(string::pad_right("42", 5, '0'), "42000");
+        assert_eq!(string::pad_right("日本", 6, ' '), "日本  ");
+        assert_eq!(string::pad_left("né", 3, ' '), " né");
     }
 
     #[test]
-    fn test_snake_case() {
+    fn test_graphemes_and_width() {
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        let flags = "\u{1F1EB}\u{1F1F7}\u{1F1E9}\u{1F1EA}";
+        assert_eq!(string::graphemes(&format!("e\u{301}{}a\r\n", family)), vec!["e\u{301}", family, "a", "\r\n"]);
+        assert_eq!(string::graphemes(flags).len(), 2);
+        assert_eq!(string::width(&format!("ab{}日", family)), 6);
+    }
+
+    #[test]
+    fn test_case_conversions() {
         assert_eq!(string::to_snake_case("HelloWorld"), "hello_world");
         assert_eq!(string::to_snake_case("myTestValue"), "my_test_value");
+        assert_eq!(string::to_kebab_case("Text Editor"), "text-editor");
+        assert_eq!(string::to_kebab_case("HTTPServer_v2"), "http-server-v2");
+        assert_eq!(string::to_camel_case("web-browser"), "webBrowser");
+        assert_eq!(string::to_camel_case(&string::to_kebab_case("myTestValue")), "myTestValue");
+    }
+
+    #[test]
+    fn test_system_monitor() {
+        let monitor = sysinfo::SystemMonitor::new();
+        assert_eq!(monitor.hostname(), "hairr-system");
+        assert_eq!(monitor.memory(), None);
+        monitor.attach_hostname(|| "studio".to_string());
+        monitor.set_boot_time(time::current_time_ms() - 90_000);
+        assert!(monitor.info().uptime_ms >= 90_000);
+        assert_eq!(monitor.info().hostname, "studio");
+
+        monitor.attach_run_queue(|| 2);
+        let start = time::current_time_ms();
+        let [one, five, fifteen] = monitor.sample_load(start + 60_000);
+        assert!((one - 2.0 * (1.0 - (-1.0f64).exp())).abs() < 1e-3);
+        assert!(one > five && five > fifteen);
+        let [one, ..] = monitor.sample_load(start + 3_600_000);
+        assert!((one - 2.0).abs() < 1e-6);
     }
 
     #[test]
@@ -470,6 +1382,47 @@ mod tests {
         assert_eq!(hash1, hash2);
     }
 
+    #[test]
+    fn test_hmac_sha256() {
+        // RFC 4231 test case 2
+        let mac = hash::hmac_sha256(b"Jefe", b"what do ya want for nothing?");
+        assert_eq!(encoding::hex_encode(&mac), "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+        // Keys longer than a block are hashed first (test case 6)
+        let mac = hash::hmac_sha256(&[0xaa; 131], b"Test Using Larger Than Block-Size Key - Hash Key First");
+        assert_eq!(encoding::hex_encode(&mac), "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54");
+        assert!(hash::constant_time_eq(&mac, &mac.clone()));
+        assert!(!hash::constant_time_eq(&mac, &mac[..31]));
+    }
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(hash::crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(hash::crc32_update(hash::crc32(b"12345"), b"6789"), 0xcbf4_3926);
+        assert_eq!(encoding::hex_encode(&hash::sha256(b"abc")), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+
+        // Pieces straddling block boundaries give the same digest
+        let data: Vec<u8> = (0..200u8).collect();
+        let mut hasher = hash::Sha256::new();
+        for piece in data.chunks(63) {
+            hasher.update(piece);
+        }
+        assert_eq!(hasher.finish(), hash::sha256(&data));
+    }
+
+    #[test]
+    fn test_encodings() {
+        assert_eq!(encoding::hex_decode("00ffA0").unwrap(), vec![0x00, 0xff, 0xa0]);
+        assert!(encoding::hex_decode("abc").is_err());
+        assert_eq!(encoding::base64_encode(b"hairr"), "aGFpcnI=");
+        assert_eq!(encoding::base64_encode(b"ha"), "aGE=");
+        assert_eq!(encoding::base64_decode("aGFpcnI=").unwrap(), b"hairr");
+        assert_eq!(encoding::base64_decode("aGFp\ncnI").unwrap(), b"hairr");
+        assert!(encoding::base64_decode("a===").is_err());
+        assert_eq!(encoding::base64url_encode(&[0xfb, 0xff]), "-_8");
+        assert_eq!(encoding::base64url_decode("-_8").unwrap(), vec![0xfb, 0xff]);
+        assert!(encoding::base64url_decode("+/8").is_err());
+    }
+
     #[test]
     fn test_uuid_generation() {
         let uuid1 = uuid::generate();
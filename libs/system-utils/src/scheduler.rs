@@ -0,0 +1,481 @@
+//! Job scheduler
+//!
+//! Jobs run on a pool of worker threads, either every fixed interval, at
+//! the times a cron expression matches (in UTC), or once after a delay. A
+//! job that is still running when it comes due again skips that run. Each
+//! job's last and next run can be inspected, and jobs can be cancelled.
+//!
+//! Cron expressions have five fields, `minute hour day-of-month month
+//! day-of-week`, each `*`, a number, a range `a-b`, a list `a,b` or a step
+//! `*/n` or `a-b/n`; Sunday is 0 or 7. `@hourly`, `@daily`, `@weekly`,
+//! `@monthly` and `@yearly` stand for the usual expressions.
+//!
+//! A scheduler reads the time from a `Clock`. `JobScheduler::simulated`
+//! starts no threads: jobs run on the caller's thread when `run_due` finds
+//! them due on the clock, so advancing a `SimulatedClock` and then calling
+//! `run_due` runs exactly the jobs due by then, in job order.
+
+use std::collections::BTreeMap;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::time::{civil_from_days, system_clock, Clock};
+
+/// How far ahead a cron expression is searched for its next match
+const CRON_HORIZON_DAYS: i64 = 366 * 5;
+
+/// Work a job does; an error is kept as its last result
+pub type Job = Arc<dyn Fn() -> Result<(), String> + Send + Sync>;
+
+/// Scheduled job identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct JobId(u64);
+
+/// Parsed cron expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronExpr {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    /// Day of month and day of week were both restricted, so either
+    /// matching is enough
+    either_day: bool,
+}
+
+impl CronExpr {
+    pub fn parse(expr: &str) -> Result<Self, String> {
+        let expr = match expr.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("Cron expression needs 5 fields, got {}", fields.len()));
+        }
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        // Sunday is both 0 and 7
+        weekdays[0] |= weekdays[7];
+        weekdays.truncate(7);
+        Ok(CronExpr {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            either_day: fields[2] != "*" && fields[4] != "*",
+        })
+    }
+
+    /// First matching time after `after_ms`, in milliseconds since the
+    /// Unix epoch
+    pub fn next_after(&self, after_ms: u64) -> Option<u64> {
+        let start_minute = (after_ms / 60_000 + 1) as i64;
+        let first_day = start_minute.div_euclid(1440);
+        for day in first_day..first_day + CRON_HORIZON_DAYS {
+            let (_, month, day_of_month) = civil_from_days(day);
+            let weekday = (day + 4).rem_euclid(7) as usize;
+            let day_matches = if self.either_day {
+                self.days[day_of_month as usize] || self.weekdays[weekday]
+            } else {
+                self.days[day_of_month as usize] && self.weekdays[weekday]
+            };
+            if !self.months[month as usize] || !day_matches {
+                continue;
+            }
+            let from = if day == first_day { start_minute.rem_euclid(1440) } else { 0 };
+            if let Some(minute) = (from..1440).find(|m| self.hours[(m / 60) as usize] && self.minutes[(m % 60) as usize]) {
+                return Some(((day * 1440 + minute) * 60_000) as u64);
+            }
+        }
+        None
+    }
+}
+
+/// Values a cron field allows, indexed by value
+fn parse_field(field: &str, min: u32, max: u32) -> Result<Vec<bool>, String> {
+    let mut allowed = vec![false; max as usize + 1];
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| format!("Invalid cron step: {}", part))?),
+            None => (part, 1),
+        };
+        let (low, high) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((low, high)) => (parse_value(low, part)?, parse_value(high, part)?),
+                // `a/n` runs from a to the end of the range
+                None if part.contains('/') => (parse_value(range, part)?, max),
+                None => (parse_value(range, part)?, parse_value(range, part)?),
+            },
+        };
+        if step == 0 || low < min || high > max || low > high {
+            return Err(format!("Cron field out of range: {}", part));
+        }
+        for value in (low..=high).step_by(step as usize) {
+            allowed[value as usize] = true;
+        }
+    }
+    Ok(allowed)
+}
+
+fn parse_value(value: &str, part: &str) -> Result<u32, String> {
+    value.parse().map_err(|_| format!("Invalid cron field: {}", part))
+}
+
+/// When a job runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    Every(Duration),
+    Cron(CronExpr),
+    /// Once, this long after being scheduled
+    After(Duration),
+}
+
+impl Schedule {
+    fn next_after(&self, after_ms: u64) -> Option<u64> {
+        match self {
+            Schedule::Every(interval) | Schedule::After(interval) => Some(after_ms + interval.as_millis() as u64),
+            Schedule::Cron(expr) => expr.next_after(after_ms),
+        }
+    }
+}
+
+/// A job's state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobInfo {
+    pub id: JobId,
+    pub name: String,
+    pub schedule: Schedule,
+    /// Milliseconds since the Unix epoch; `None` once a job will not run
+    /// again
+    pub next_run: Option<u64>,
+    pub last_run: Option<u64>,
+    pub last_result: Option<Result<(), String>>,
+    pub runs: u64,
+    pub running: bool,
+}
+
+struct Entry {
+    info: JobInfo,
+    job: Job,
+}
+
+struct State {
+    jobs: BTreeMap<JobId, Entry>,
+    next_id: u64,
+    shutdown: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    wake: Condvar,
+    clock: Arc<dyn Clock>,
+}
+
+/// Runs jobs on a pool of worker threads
+pub struct JobScheduler {
+    shared: Arc<Shared>,
+    threads: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl JobScheduler {
+    pub fn new(workers: usize) -> Self {
+        JobScheduler::with_clock(workers, system_clock())
+    }
+
+    /// Dispatch jobs on worker threads by the time on `clock`
+    pub fn with_clock(workers: usize, clock: Arc<dyn Clock>) -> Self {
+        let shared = JobScheduler::shared(clock);
+        let (sender, receiver) = mpsc::channel::<JobId>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let mut threads: Vec<JoinHandle<()>> = (0..workers.max(1))
+            .map(|_| {
+                let (shared, receiver) = (shared.clone(), receiver.clone());
+                std::thread::spawn(move || work(&shared, &receiver))
+            })
+            .collect();
+        let dispatcher = shared.clone();
+        threads.push(std::thread::spawn(move || dispatch(&dispatcher, sender)));
+        JobScheduler {
+            shared,
+            threads: Mutex::new(threads),
+        }
+    }
+
+    /// Run jobs only from `run_due`, by the time on `clock`
+    pub fn simulated(clock: Arc<dyn Clock>) -> Self {
+        JobScheduler {
+            shared: JobScheduler::shared(clock),
+            threads: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn shared(clock: Arc<dyn Clock>) -> Arc<Shared> {
+        Arc::new(Shared {
+            state: Mutex::new(State {
+                jobs: BTreeMap::new(),
+                next_id: 1,
+                shutdown: false,
+            }),
+            wake: Condvar::new(),
+            clock,
+        })
+    }
+
+    /// Add a job; its first run is one interval, delay or cron match from now
+    pub fn schedule(&self, name: &str, schedule: Schedule, job: Job) -> JobId {
+        let next_run = schedule.next_after(self.shared.clock.now_ms());
+        let mut state = self.shared.state.lock().unwrap();
+        let id = JobId(state.next_id);
+        state.next_id += 1;
+        state.jobs.insert(
+            id,
+            Entry {
+                info: JobInfo {
+                    id,
+                    name: name.to_string(),
+                    schedule,
+                    next_run,
+                    last_run: None,
+                    last_result: None,
+                    runs: 0,
+                    running: false,
+                },
+                job,
+            },
+        );
+        drop(state);
+        self.shared.wake.notify_all();
+        id
+    }
+
+    pub fn schedule_every(&self, name: &str, interval: Duration, job: Job) -> JobId {
+        self.schedule(name, Schedule::Every(interval), job)
+    }
+
+    pub fn schedule_cron(&self, name: &str, expr: &str, job: Job) -> Result<JobId, String> {
+        Ok(self.schedule(name, Schedule::Cron(CronExpr::parse(expr)?), job))
+    }
+
+    /// Run a job once after `delay`
+    pub fn run_after(&self, name: &str, delay: Duration, job: Job) -> JobId {
+        self.schedule(name, Schedule::After(delay), job)
+    }
+
+    /// Remove a job; a run already started finishes
+    pub fn cancel(&self, id: JobId) -> bool {
+        self.shared.state.lock().unwrap().jobs.remove(&id).is_some()
+    }
+
+    pub fn job(&self, id: JobId) -> Option<JobInfo> {
+        self.shared.state.lock().unwrap().jobs.get(&id).map(|e| e.info.clone())
+    }
+
+    /// All jobs, including one-shot jobs that have run
+    pub fn jobs(&self) -> Vec<JobInfo> {
+        self.shared.state.lock().unwrap().jobs.values().map(|e| e.info.clone()).collect()
+    }
+
+    /// Run the jobs due now on this thread, returning how many ran
+    pub fn run_due(&self) -> usize {
+        let due = {
+            let mut state = self.shared.state.lock().unwrap();
+            let now = self.shared.clock.now_ms();
+            take_due(&mut state, now)
+        };
+        for id in &due {
+            run_job(&self.shared, *id);
+        }
+        due.len()
+    }
+
+    /// Stop dispatching and wait for running jobs to finish
+    pub fn shutdown(&self) {
+        self.shared.state.lock().unwrap().shutdown = true;
+        self.shared.wake.notify_all();
+        for thread in self.threads.lock().unwrap().drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Default for JobScheduler {
+    fn default() -> Self {
+        JobScheduler::new(4)
+    }
+}
+
+impl Drop for JobScheduler {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Hand due jobs to the workers, sleeping until the next one is due
+fn dispatch(shared: &Shared, sender: Sender<JobId>) {
+    let mut state = shared.state.lock().unwrap();
+    while !state.shutdown {
+        let now = shared.clock.now_ms();
+        for id in take_due(&mut state, now) {
+            let _ = sender.send(id);
+        }
+        let wait = state.jobs.values().filter_map(|e| e.info.next_run).min().map_or(Duration::from_secs(60), |at| {
+            Duration::from_millis(at.saturating_sub(now))
+        });
+        state = shared.wake.wait_timeout(state, wait).unwrap().0;
+    }
+    // Dropping the sender stops the workers
+}
+
+/// Move the schedule of every job due at `now` on, returning those not
+/// still running from an earlier run and marking them running
+fn take_due(state: &mut State, now: u64) -> Vec<JobId> {
+    let mut due = Vec::new();
+    for entry in state.jobs.values_mut() {
+        let Some(next_run) = entry.info.next_run.filter(|at| *at <= now) else {
+            continue;
+        };
+        entry.info.next_run = match entry.info.schedule {
+            Schedule::After(_) => None,
+            // Missed runs are skipped rather than run back to back
+            _ => entry.info.schedule.next_after(next_run.max(now)),
+        };
+        if !entry.info.running {
+            entry.info.running = true;
+            due.push(entry.info.id);
+        }
+    }
+    due
+}
+
+fn work(shared: &Shared, receiver: &Mutex<Receiver<JobId>>) {
+    loop {
+        let Ok(id) = receiver.lock().unwrap().recv() else {
+            return;
+        };
+        run_job(shared, id);
+    }
+}
+
+fn run_job(shared: &Shared, id: JobId) {
+    let job = match shared.state.lock().unwrap().jobs.get(&id) {
+        Some(entry) => entry.job.clone(),
+        None => return,
+    };
+    let started = shared.clock.now_ms();
+    let result = job();
+    if let Some(entry) = shared.state.lock().unwrap().jobs.get_mut(&id) {
+        entry.info.running = false;
+        entry.info.last_run = Some(started);
+        entry.info.last_result = Some(result);
+        entry.info.runs += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::SimulatedClock;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_cron_next_run() {
+        // 2024-03-15 10:07 UTC, a Friday
+        let now = 1_710_497_220_000;
+        let minute = 60_000;
+        assert_eq!(CronExpr::parse("*/15 * * * *").unwrap().next_after(now), Some(now + 8 * minute));
+        assert_eq!(CronExpr::parse("@daily").unwrap().next_after(now), Some(now + (13 * 60 + 53) * minute));
+        // Next Monday at 09:30
+        let monday = CronExpr::parse("30 9 * * 1").unwrap().next_after(now).unwrap();
+        assert_eq!(monday, now + ((2 * 24 + 23) * 60 + 23) * minute);
+        // Day of month or day of week: the 20th or any Sunday, so the 17th
+        let either = CronExpr::parse("0 0 20 * 7").unwrap().next_after(now).unwrap();
+        assert_eq!(either, now + (24 * 60 + 13 * 60 + 53) * minute);
+        assert_eq!(CronExpr::parse("0 0 30 2 *").unwrap().next_after(now), None);
+        assert!(CronExpr::parse("60 * * * *").is_err());
+        assert!(CronExpr::parse("* * * *").is_err());
+    }
+
+    #[test]
+    fn test_jobs_run_and_cancel() {
+        let scheduler = JobScheduler::new(2);
+        let (ticks, once) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let counter = ticks.clone();
+        let every = scheduler.schedule_every(
+            "tick",
+            Duration::from_millis(10),
+            Arc::new(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }),
+        );
+        let counter = once.clone();
+        let delayed = scheduler.run_after(
+            "once",
+            Duration::from_millis(20),
+            Arc::new(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Err("disk full".to_string())
+            }),
+        );
+        assert_eq!(scheduler.job(delayed).unwrap().last_run, None);
+
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(ticks.load(Ordering::SeqCst) >= 3);
+        let info = scheduler.job(delayed).unwrap();
+        assert_eq!((info.runs, info.next_run, info.last_result), (1, None, Some(Err("disk full".to_string()))));
+        assert_eq!(once.load(Ordering::SeqCst), 1);
+        assert!(scheduler.job(every).unwrap().next_run.is_some());
+
+        assert!(scheduler.cancel(every));
+        assert!(!scheduler.cancel(every));
+        std::thread::sleep(Duration::from_millis(20));
+        let stopped = ticks.load(Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(ticks.load(Ordering::SeqCst), stopped);
+        scheduler.shutdown();
+    }
+
+    #[test]
+    fn test_simulated_time() {
+        // 2024-03-15 10:07 UTC
+        let now = 1_710_497_220_000;
+        let clock = Arc::new(SimulatedClock::new(now));
+        let scheduler = JobScheduler::simulated(clock.clone());
+        let runs = Arc::new(Mutex::new(Vec::new()));
+        let job = |name: &'static str| -> Job {
+            let runs = runs.clone();
+            Arc::new(move || {
+                runs.lock().unwrap().push(name);
+                Ok(())
+            })
+        };
+        let tick = scheduler.schedule_every("tick", Duration::from_secs(60), job("tick"));
+        scheduler.run_after("once", Duration::from_secs(90), job("once"));
+        scheduler.schedule_cron("quarter", "*/15 * * * *", job("quarter")).unwrap();
+
+        assert_eq!(scheduler.run_due(), 0);
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(scheduler.run_due(), 0);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(scheduler.run_due(), 1);
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(scheduler.run_due(), 1);
+        assert_eq!(*runs.lock().unwrap(), vec!["tick", "once"]);
+
+        // Missed ticks are skipped, and jobs due together run in job order
+        clock.advance(Duration::from_secs(7 * 60));
+        assert_eq!(scheduler.run_due(), 2);
+        assert_eq!(runs.lock().unwrap()[2..], ["tick", "quarter"]);
+        let info = scheduler.job(tick).unwrap();
+        assert_eq!((info.runs, info.last_run, info.next_run), (2, Some(now + 510_000), Some(now + 570_000)));
+    }
+}
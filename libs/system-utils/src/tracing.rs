@@ -0,0 +1,431 @@
+//! Tracing spans
+//!
+//! `span("vfs.write")` starts a span that ends when its guard is dropped.
+//! Spans started while another is open on the same thread are its
+//! children. Finished spans are kept by a `Tracer`, summed up per name,
+//! written as folded stacks for flamegraph tools, and exported to or
+//! imported from the Chrome trace format:
+//!
+//! ```json
+//! {"traceEvents": [{"name": "vfs.write", "cat": "vfs", "ph": "X", "ts": 1700000000000000,
+//!   "dur": 120, "pid": 1, "tid": 3, "args": {"id": 7, "parent": 5}}]}
+//! ```
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+/// Finished spans a tracer keeps by default
+pub const DEFAULT_MAX_SPANS: usize = 10_000;
+
+static NEXT_SPAN_ID: AtomicU64 = AtomicU64::new(1);
+static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static THREAD_ID: u64 = NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed);
+    /// Spans open on this thread, innermost last
+    static OPEN_SPANS: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Small number identifying the current thread in traces
+pub fn thread_id() -> u64 {
+    THREAD_ID.with(|id| *id)
+}
+
+/// A finished span
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpanRecord {
+    pub id: u64,
+    pub parent: Option<u64>,
+    pub name: String,
+    pub thread: u64,
+    /// Microseconds since the Unix epoch
+    pub start_us: u64,
+    pub duration_us: u64,
+}
+
+impl SpanRecord {
+    fn end_us(&self) -> u64 {
+        self.start_us + self.duration_us
+    }
+}
+
+/// Time spent in spans of one name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpanStats {
+    pub name: String,
+    pub count: u64,
+    pub total_us: u64,
+    /// Time not spent in child spans
+    pub self_us: u64,
+    pub min_us: u64,
+    pub max_us: u64,
+}
+
+impl SpanStats {
+    pub fn mean_us(&self) -> u64 {
+        self.total_us / self.count.max(1)
+    }
+}
+
+/// Collects finished spans, dropping the oldest past its limit
+#[derive(Clone)]
+pub struct Tracer {
+    spans: Arc<Mutex<VecDeque<SpanRecord>>>,
+    max_spans: usize,
+}
+
+impl Tracer {
+    pub fn new(max_spans: usize) -> Self {
+        Tracer {
+            spans: Arc::new(Mutex::new(VecDeque::new())),
+            max_spans,
+        }
+    }
+
+    /// Start a span, a child of the innermost one open on this thread
+    pub fn span(&self, name: &str) -> SpanGuard {
+        let id = NEXT_SPAN_ID.fetch_add(1, Ordering::Relaxed);
+        let parent = OPEN_SPANS.with(|open| {
+            let mut open = open.borrow_mut();
+            let parent = open.last().copied();
+            open.push(id);
+            parent
+        });
+        SpanGuard {
+            tracer: self.clone(),
+            id,
+            parent,
+            name: name.to_string(),
+            start_us: crate::time::current_time_us(),
+            started: Instant::now(),
+        }
+    }
+
+    fn record(&self, span: SpanRecord) {
+        let mut spans = self.spans.lock().unwrap();
+        if spans.len() >= self.max_spans {
+            spans.pop_front();
+        }
+        spans.push_back(span);
+    }
+
+    /// Finished spans, oldest first
+    pub fn spans(&self) -> Vec<SpanRecord> {
+        self.spans.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.spans.lock().unwrap().clear();
+    }
+
+    /// Add spans, such as ones imported from a trace
+    pub fn extend(&self, spans: Vec<SpanRecord>) {
+        for span in spans {
+            self.record(span);
+        }
+    }
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Tracer::new(DEFAULT_MAX_SPANS)
+    }
+}
+
+/// The process-wide tracer `span` records to
+pub fn tracer() -> &'static Tracer {
+    static TRACER: OnceLock<Tracer> = OnceLock::new();
+    TRACER.get_or_init(Tracer::default)
+}
+
+/// Start a span on the process-wide tracer
+pub fn span(name: &str) -> SpanGuard {
+    tracer().span(name)
+}
+
+/// An open span; dropping it ends the span
+pub struct SpanGuard {
+    tracer: Tracer,
+    id: u64,
+    parent: Option<u64>,
+    name: String,
+    start_us: u64,
+    started: Instant,
+}
+
+impl SpanGuard {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        // Guards normally drop innermost first, but one moved elsewhere
+        // may not; only this span leaves the stack either way
+        OPEN_SPANS.with(|open| {
+            let mut open = open.borrow_mut();
+            if let Some(at) = open.iter().rposition(|id| *id == self.id) {
+                open.remove(at);
+            }
+        });
+        self.tracer.record(SpanRecord {
+            id: self.id,
+            parent: self.parent,
+            name: std::mem::take(&mut self.name),
+            thread: thread_id(),
+            start_us: self.start_us,
+            duration_us: self.started.elapsed().as_micros() as u64,
+        });
+    }
+}
+
+/// Per-name statistics, most total time first
+pub fn stats(spans: &[SpanRecord]) -> Vec<SpanStats> {
+    let child_time = child_time(spans);
+    let mut by_name: HashMap<&str, SpanStats> = HashMap::new();
+    for span in spans {
+        let own = span.duration_us.saturating_sub(child_time.get(&span.id).copied().unwrap_or(0));
+        let stats = by_name.entry(&span.name).or_insert_with(|| SpanStats {
+            name: span.name.clone(),
+            count: 0,
+            total_us: 0,
+            self_us: 0,
+            min_us: u64::MAX,
+            max_us: 0,
+        });
+        stats.count += 1;
+        stats.total_us += span.duration_us;
+        stats.self_us += own;
+        stats.min_us = stats.min_us.min(span.duration_us);
+        stats.max_us = stats.max_us.max(span.duration_us);
+    }
+    let mut stats: Vec<SpanStats> = by_name.into_values().collect();
+    stats.sort_by(|a, b| b.total_us.cmp(&a.total_us).then_with(|| a.name.cmp(&b.name)));
+    stats
+}
+
+/// Time each span spent in its children
+fn child_time(spans: &[SpanRecord]) -> HashMap<u64, u64> {
+    let mut time = HashMap::new();
+    for span in spans {
+        if let Some(parent) = span.parent {
+            *time.entry(parent).or_insert(0) += span.duration_us;
+        }
+    }
+    time
+}
+
+/// Folded stacks, one `outer;inner self_us` line per distinct stack, as
+/// flamegraph tools read them
+pub fn folded(spans: &[SpanRecord]) -> String {
+    let by_id: HashMap<u64, &SpanRecord> = spans.iter().map(|s| (s.id, s)).collect();
+    let child_time = child_time(spans);
+    let mut stacks: BTreeMap<String, u64> = BTreeMap::new();
+    for span in spans {
+        let mut names = vec![span.name.as_str()];
+        let mut parent = span.parent;
+        while let Some(outer) = parent.and_then(|id| by_id.get(&id)) {
+            names.push(&outer.name);
+            parent = outer.parent;
+        }
+        names.reverse();
+        let own = span.duration_us.saturating_sub(child_time.get(&span.id).copied().unwrap_or(0));
+        *stacks.entry(names.join(";")).or_insert(0) += own;
+    }
+    stacks.iter().map(|(stack, us)| format!("{} {}\n", stack, us)).collect()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ChromeTrace {
+    #[serde(rename = "traceEvents")]
+    trace_events: Vec<TraceEvent>,
+    #[serde(rename = "displayTimeUnit", default, skip_serializing_if = "Option::is_none")]
+    display_time_unit: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TraceEvent {
+    name: String,
+    #[serde(default)]
+    cat: String,
+    ph: String,
+    ts: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dur: Option<f64>,
+    #[serde(default)]
+    pid: u64,
+    #[serde(default)]
+    tid: u64,
+    #[serde(default)]
+    args: TraceArgs,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct TraceArgs {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parent: Option<u64>,
+}
+
+/// Spans as Chrome trace JSON, for chrome://tracing or Perfetto
+pub fn to_chrome_trace(spans: &[SpanRecord]) -> String {
+    let pid = std::process::id() as u64;
+    let trace = ChromeTrace {
+        trace_events: spans
+            .iter()
+            .map(|span| TraceEvent {
+                name: span.name.clone(),
+                cat: span.name.split('.').next().unwrap_or_default().to_string(),
+                ph: "X".to_string(),
+                ts: span.start_us as f64,
+                dur: Some(span.duration_us as f64),
+                pid,
+                tid: span.thread,
+                args: TraceArgs {
+                    id: Some(span.id),
+                    parent: span.parent,
+                },
+            })
+            .collect(),
+        display_time_unit: Some("ms".to_string()),
+    };
+    serde_json::to_string(&trace).unwrap()
+}
+
+/// Spans from Chrome trace JSON. Complete (`X`) events and `B`/`E` pairs
+/// become spans; parents missing from the event arguments are found from
+/// how spans on each thread nest.
+pub fn from_chrome_trace(json: &str) -> Result<Vec<SpanRecord>, String> {
+    // Traces may also be a bare array of events
+    let events = match serde_json::from_str::<ChromeTrace>(json) {
+        Ok(trace) => trace.trace_events,
+        Err(_) => serde_json::from_str::<Vec<TraceEvent>>(json).map_err(|e| format!("Invalid trace: {}", e))?,
+    };
+
+    let mut spans = Vec::new();
+    let mut inferred = Vec::new();
+    let mut begun: HashMap<(u64, u64), Vec<TraceEvent>> = HashMap::new();
+    let mut push = |event: TraceEvent, duration_us: u64, spans: &mut Vec<SpanRecord>| {
+        let id = event.args.id.unwrap_or_else(|| NEXT_SPAN_ID.fetch_add(1, Ordering::Relaxed));
+        if event.args.id.is_none() {
+            inferred.push(spans.len());
+        }
+        spans.push(SpanRecord {
+            id,
+            parent: event.args.parent,
+            name: event.name,
+            thread: event.tid,
+            start_us: event.ts as u64,
+            duration_us,
+        });
+    };
+    for event in events {
+        match event.ph.as_str() {
+            "X" => {
+                let duration_us = event.dur.ok_or_else(|| format!("Complete event {} has no duration", event.name))? as u64;
+                push(event, duration_us, &mut spans);
+            }
+            "B" => begun.entry((event.pid, event.tid)).or_default().push(event),
+            "E" => {
+                let begin = begun
+                    .get_mut(&(event.pid, event.tid))
+                    .and_then(|open| open.pop())
+                    .ok_or_else(|| format!("End event at {} with no begin event", event.ts))?;
+                let duration_us = (event.ts - begin.ts).max(0.0) as u64;
+                push(begin, duration_us, &mut spans);
+            }
+            // Instant, counter and metadata events have no duration
+            _ => {}
+        }
+    }
+    if let Some(open) = begun.values().flatten().next() {
+        return Err(format!("Begin event {} has no end event", open.name));
+    }
+
+    // Outer spans start no later and end no earlier than their children
+    let mut order: Vec<usize> = (0..spans.len()).collect();
+    order.sort_by(|a, b| {
+        let (a, b) = (&spans[*a], &spans[*b]);
+        (a.thread, a.start_us, std::cmp::Reverse(a.duration_us)).cmp(&(b.thread, b.start_us, std::cmp::Reverse(b.duration_us)))
+    });
+    let mut open: Vec<usize> = Vec::new();
+    for at in order {
+        while let Some(&outer) = open.last() {
+            let (outer, span) = (&spans[outer], &spans[at]);
+            if outer.thread == span.thread && span.end_us() <= outer.end_us() {
+                break;
+            }
+            open.pop();
+        }
+        if inferred.contains(&at) && spans[at].parent.is_none() {
+            spans[at].parent = open.last().map(|outer| spans[*outer].id);
+        }
+        open.push(at);
+    }
+    Ok(spans)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nested_spans() {
+        let tracer = Tracer::new(10);
+        {
+            let outer = tracer.span("vfs.write");
+            let _inner = tracer.span("block.flush");
+            assert_eq!(tracer.spans().len(), 0);
+            drop(outer);
+        }
+        let spans = tracer.spans();
+        assert_eq!(spans.len(), 2);
+        let (outer, inner) = (&spans[0], &spans[1]);
+        assert_eq!((outer.name.as_str(), inner.name.as_str()), ("vfs.write", "block.flush"));
+        assert_eq!((outer.parent, inner.parent), (None, Some(outer.id)));
+        assert_eq!(outer.thread, thread_id());
+
+        let worker = tracer.clone();
+        std::thread::spawn(move || drop(worker.span("net.send"))).join().unwrap();
+        let send = tracer.spans().pop().unwrap();
+        assert_eq!(send.parent, None);
+        assert_ne!(send.thread, outer.thread);
+
+        let stats = stats(&[
+            SpanRecord { id: 1, parent: None, name: "vfs.write".to_string(), thread: 1, start_us: 0, duration_us: 100 },
+            SpanRecord { id: 2, parent: Some(1), name: "block.flush".to_string(), thread: 1, start_us: 10, duration_us: 60 },
+            SpanRecord { id: 3, parent: None, name: "vfs.write".to_string(), thread: 1, start_us: 200, duration_us: 20 },
+        ]);
+        assert_eq!(stats[0], SpanStats { name: "vfs.write".to_string(), count: 2, total_us: 120, self_us: 60, min_us: 20, max_us: 100 });
+        assert_eq!(stats[1].self_us, 60);
+    }
+
+    #[test]
+    fn test_chrome_trace_round_trip() {
+        let spans = vec![
+            SpanRecord { id: 1, parent: None, name: "vfs.write".to_string(), thread: 1, start_us: 1000, duration_us: 100 },
+            SpanRecord { id: 2, parent: Some(1), name: "block.flush".to_string(), thread: 1, start_us: 1010, duration_us: 60 },
+        ];
+        assert_eq!(from_chrome_trace(&to_chrome_trace(&spans)).unwrap(), spans);
+        assert_eq!(folded(&spans), "vfs.write 40\nvfs.write;block.flush 60\n");
+
+        // Traces from other tools nest by time instead of arguments
+        let json = r#"[{"name": "frame", "ph": "B", "ts": 0, "pid": 1, "tid": 7},
+            {"name": "layout", "ph": "X", "ts": 5, "dur": 20, "pid": 1, "tid": 7},
+            {"name": "paint", "ph": "X", "ts": 30, "dur": 10, "pid": 1, "tid": 7},
+            {"name": "frame", "ph": "E", "ts": 50, "pid": 1, "tid": 7},
+            {"name": "vsync", "ph": "i", "ts": 50, "pid": 1, "tid": 7}]"#;
+        let spans = from_chrome_trace(json).unwrap();
+        let frame = spans.iter().find(|s| s.name == "frame").unwrap();
+        assert_eq!((frame.duration_us, frame.parent), (50, None));
+        assert!(spans.iter().filter(|s| s.name != "frame").all(|s| s.parent == Some(frame.id)));
+        assert!(from_chrome_trace(r#"[{"name": "frame", "ph": "B", "ts": 0}]"#).is_err());
+    }
+}
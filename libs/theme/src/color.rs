@@ -0,0 +1,87 @@
+//! Colors
+//!
+//! Theme files write colors as `#rrggbb` or `#rrggbbaa` hex strings.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// An RGBA color
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Color(pub [u8; 4]);
+
+impl Color {
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Color([r, g, b, 0xff])
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid color: {}", text);
+        let hex = text.strip_prefix('#').ok_or_else(invalid)?;
+        if !(hex.len() == 6 || hex.len() == 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let mut rgba = [0xff; 4];
+        for (i, channel) in rgba.iter_mut().enumerate().take(hex.len() / 2) {
+            *channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap();
+        }
+        Ok(Color(rgba))
+    }
+
+    pub fn rgba(&self) -> [u8; 4] {
+        self.0
+    }
+
+    /// Escape sequence setting a 24-bit terminal foreground color
+    pub fn ansi_foreground(&self) -> String {
+        format!("\x1b[38;2;{};{};{}m", self.0[0], self.0[1], self.0[2])
+    }
+
+    /// Escape sequence setting a 24-bit terminal background color
+    pub fn ansi_background(&self) -> String {
+        format!("\x1b[48;2;{};{};{}m", self.0[0], self.0[1], self.0[2])
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [r, g, b, a] = self.0;
+        write!(f, "#{:02x}{:02x}{:02x}", r, g, b)?;
+        if a != 0xff {
+            write!(f, "{:02x}", a)?;
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        Color::parse(&text)
+    }
+}
+
+impl From<Color> for String {
+    fn from(color: Color) -> Self {
+        color.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format() {
+        assert_eq!(Color::parse("#3a3f4b").unwrap(), Color::rgb(0x3a, 0x3f, 0x4b));
+        assert_eq!(Color::parse("#00000080").unwrap().rgba(), [0, 0, 0, 0x80]);
+        assert_eq!(Color::parse("#FFFFFF").unwrap().to_string(), "#ffffff");
+        assert_eq!(Color([1, 2, 3, 4]).to_string(), "#01020304");
+        assert!(Color::parse("3a3f4b").is_err());
+        assert!(Color::parse("#3a3f4").is_err());
+        assert!(Color::parse("#gg0000").is_err());
+        assert_eq!(Color::rgb(1, 2, 3).ansi_background(), "\x1b[48;2;1;2;3m");
+    }
+}
@@ -0,0 +1,359 @@
+//! Theming for hairr OS
+//!
+//! A theme names a light and a dark palette along with fonts, border sizes
+//! and a wallpaper. Themes are JSON files in the VFS; the active theme and
+//! the dark/light mode are chosen independently, so every theme works in
+//! both modes. Programs subscribe to appearance changes to repaint.
+//!
+//! ```json
+//! {"name": "Nord", "light": {...}, "dark": {...}, "border_width": 2,
+//!  "fonts": {"ui": "Inter", "monospace": "Iosevka", "size": 11},
+//!  "wallpaper": "/usr/share/wallpapers/nord.png"}
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use filesystem::VirtualFileSystem;
+use serde::{Deserialize, Serialize};
+
+pub mod color;
+
+pub use color::Color;
+
+/// Directory the system themes are installed in
+pub const THEME_DIR: &str = "/usr/share/themes";
+
+/// Theme every system starts with
+pub const DEFAULT_THEME: &str = "Default";
+
+/// Widest window border a theme may ask for
+pub const MAX_BORDER_WIDTH: u32 = 16;
+
+/// Dark or light variant of a theme
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    #[default]
+    Light,
+    Dark,
+}
+
+impl Mode {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text.to_ascii_lowercase().as_str() {
+            "light" => Ok(Mode::Light),
+            "dark" => Ok(Mode::Dark),
+            _ => Err(format!("Unknown mode: {}", text)),
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Mode::Light => "light",
+            Mode::Dark => "dark",
+        })
+    }
+}
+
+/// Colors of one mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Palette {
+    /// Behind all windows when there is no wallpaper
+    pub desktop: Color,
+    pub window: Color,
+    pub title_bar: Color,
+    pub text: Color,
+    pub accent: Color,
+    pub border: Color,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fonts {
+    pub ui: String,
+    pub monospace: String,
+    /// Point size of body text
+    pub size: u32,
+}
+
+impl Default for Fonts {
+    fn default() -> Self {
+        Fonts {
+            ui: "Sans".to_string(),
+            monospace: "Monospace".to_string(),
+            size: 11,
+        }
+    }
+}
+
+/// A named look for the desktop
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Theme {
+    pub name: String,
+    pub light: Palette,
+    pub dark: Palette,
+    #[serde(default)]
+    pub fonts: Fonts,
+    #[serde(default = "default_border_width")]
+    pub border_width: u32,
+    /// Image path in the VFS
+    #[serde(default)]
+    pub wallpaper: Option<String>,
+}
+
+fn default_border_width() -> u32 {
+    1
+}
+
+impl Theme {
+    pub fn from_json(data: &[u8]) -> Result<Self, String> {
+        let theme: Theme = serde_json::from_slice(data).map_err(|e| format!("Invalid theme: {}", e))?;
+        if theme.name.is_empty() {
+            return Err("Invalid theme: missing name".to_string());
+        }
+        if theme.border_width > MAX_BORDER_WIDTH {
+            return Err(format!("Invalid theme: borders are at most {} pixels", MAX_BORDER_WIDTH));
+        }
+        if theme.fonts.size == 0 {
+            return Err("Invalid theme: font size must be positive".to_string());
+        }
+        Ok(theme)
+    }
+
+    pub fn to_json(&self) -> Vec<u8> {
+        serde_json::to_vec_pretty(self).unwrap()
+    }
+
+    pub fn palette(&self, mode: Mode) -> &Palette {
+        match mode {
+            Mode::Light => &self.light,
+            Mode::Dark => &self.dark,
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            name: DEFAULT_THEME.to_string(),
+            light: Palette {
+                desktop: Color::rgb(0x20, 0x24, 0x2c),
+                window: Color::rgb(0xf5, 0xf5, 0xf5),
+                title_bar: Color::rgb(0x3a, 0x3f, 0x4b),
+                text: Color::rgb(0x1d, 0x1f, 0x23),
+                accent: Color::rgb(0x2f, 0x6f, 0xdf),
+                border: Color::rgb(0x3a, 0x3f, 0x4b),
+            },
+            dark: Palette {
+                desktop: Color::rgb(0x10, 0x12, 0x16),
+                window: Color::rgb(0x24, 0x27, 0x2e),
+                title_bar: Color::rgb(0x15, 0x17, 0x1c),
+                text: Color::rgb(0xe6, 0xe6, 0xe6),
+                accent: Color::rgb(0x5b, 0x9b, 0xff),
+                border: Color::rgb(0x15, 0x17, 0x1c),
+            },
+            fonts: Fonts::default(),
+            border_width: default_border_width(),
+            wallpaper: None,
+        }
+    }
+}
+
+/// The active theme in the active mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Appearance {
+    pub theme: Theme,
+    pub mode: Mode,
+}
+
+impl Appearance {
+    pub fn palette(&self) -> &Palette {
+        self.theme.palette(self.mode)
+    }
+}
+
+/// Callback run after the appearance changes
+pub type ThemeListener = Arc<dyn Fn(&Appearance) + Send + Sync>;
+
+/// Installed themes and the user's choice among them
+pub struct ThemeManager {
+    themes: Arc<Mutex<BTreeMap<String, Theme>>>,
+    current: Arc<Mutex<Appearance>>,
+    listeners: Arc<Mutex<Vec<ThemeListener>>>,
+}
+
+impl ThemeManager {
+    pub fn new() -> Self {
+        let theme = Theme::default();
+        let mut themes = BTreeMap::new();
+        themes.insert(theme.name.clone(), theme.clone());
+        ThemeManager {
+            themes: Arc::new(Mutex::new(themes)),
+            current: Arc::new(Mutex::new(Appearance {
+                theme,
+                mode: Mode::default(),
+            })),
+            listeners: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn appearance(&self) -> Appearance {
+        self.current.lock().unwrap().clone()
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.current.lock().unwrap().mode
+    }
+
+    /// Install a theme; replacing the active theme repaints with the new one
+    pub fn add_theme(&self, theme: Theme) {
+        self.themes.lock().unwrap().insert(theme.name.clone(), theme.clone());
+        let active = self.current.lock().unwrap().theme.name == theme.name;
+        if active {
+            self.update(|current| current.theme = theme);
+        }
+    }
+
+    /// Load one theme file, returning the theme's name
+    pub fn load_theme(&self, vfs: &VirtualFileSystem, path: &Path) -> Result<String, String> {
+        let theme = Theme::from_json(&vfs.read_file(path)?).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let name = theme.name.clone();
+        self.add_theme(theme);
+        Ok(name)
+    }
+
+    /// Load every `.json` theme in a directory, returning how many were read
+    pub fn load_directory(&self, vfs: &VirtualFileSystem, dir: &Path) -> Result<usize, String> {
+        let mut loaded = 0;
+        for path in vfs.list_directory(dir)? {
+            if path.extension().is_some_and(|ext| ext == "json") {
+                self.load_theme(vfs, &path)?;
+                loaded += 1;
+            }
+        }
+        Ok(loaded)
+    }
+
+    /// Names of the installed themes, sorted
+    pub fn themes(&self) -> Vec<String> {
+        self.themes.lock().unwrap().keys().cloned().collect()
+    }
+
+    pub fn theme(&self, name: &str) -> Option<Theme> {
+        self.themes.lock().unwrap().get(name).cloned()
+    }
+
+    /// Switch to an installed theme, keeping the mode
+    pub fn set_theme(&self, name: &str) -> Result<(), String> {
+        let theme = self.theme(name).ok_or_else(|| format!("Unknown theme: {}", name))?;
+        self.update(|current| current.theme = theme);
+        Ok(())
+    }
+
+    pub fn set_mode(&self, mode: Mode) {
+        self.update(|current| current.mode = mode);
+    }
+
+    /// Run `listener` after every change of theme or mode
+    pub fn on_change(&self, listener: ThemeListener) {
+        self.listeners.lock().unwrap().push(listener);
+    }
+
+    fn update(&self, change: impl FnOnce(&mut Appearance)) {
+        let appearance = {
+            let mut current = self.current.lock().unwrap();
+            let before = current.clone();
+            change(&mut current);
+            if *current == before {
+                return;
+            }
+            current.clone()
+        };
+        let listeners: Vec<ThemeListener> = self.listeners.lock().unwrap().clone();
+        for listener in listeners {
+            listener(&appearance);
+        }
+    }
+}
+
+impl Default for ThemeManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use filesystem::{OpenOptions, ProcessId};
+
+    fn install(vfs: &VirtualFileSystem, name: &str, data: &[u8]) {
+        let path = Path::new(THEME_DIR).join(name);
+        let handle = vfs.open(ProcessId::KERNEL, &path, OpenOptions::write_only()).unwrap();
+        vfs.write(ProcessId::KERNEL, handle, data).unwrap();
+        vfs.close(ProcessId::KERNEL, handle).unwrap();
+    }
+
+    #[test]
+    fn test_load_themes() {
+        let vfs = VirtualFileSystem::new();
+        for dir in ["/usr", "/usr/share", THEME_DIR] {
+            vfs.create_directory(Path::new(dir)).unwrap();
+        }
+        let nord = Theme {
+            name: "Nord".to_string(),
+            border_width: 2,
+            wallpaper: Some("/usr/share/wallpapers/nord.png".to_string()),
+            ..Theme::default()
+        };
+        install(&vfs, "nord.json", &nord.to_json());
+        install(
+            &vfs,
+            "mono.json",
+            br##"{"name":"Mono","light":{"desktop":"#ffffff","window":"#ffffff","title_bar":"#000000",
+                "text":"#000000","accent":"#000000","border":"#000000"},"dark":{"desktop":"#000000",
+                "window":"#000000","title_bar":"#ffffff","text":"#ffffff","accent":"#ffffff","border":"#ffffff"}}"##,
+        );
+        install(&vfs, "README", b"not a theme");
+
+        let manager = ThemeManager::new();
+        assert_eq!(manager.load_directory(&vfs, Path::new(THEME_DIR)).unwrap(), 2);
+        assert_eq!(manager.themes(), vec!["Default", "Mono", "Nord"]);
+        assert_eq!(manager.theme("Nord").unwrap(), nord);
+        let mono = manager.theme("Mono").unwrap();
+        assert_eq!((mono.border_width, mono.fonts.size), (1, 11));
+
+        install(&vfs, "wide.json", &Theme { border_width: 40, ..Theme::default() }.to_json());
+        assert!(manager.load_theme(&vfs, &Path::new(THEME_DIR).join("wide.json")).is_err());
+    }
+
+    #[test]
+    fn test_change_events() {
+        let manager = ThemeManager::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        manager.on_change(Arc::new(move |appearance| {
+            log.lock().unwrap().push((appearance.theme.name.clone(), appearance.mode, appearance.palette().window))
+        }));
+
+        manager.set_mode(Mode::Dark);
+        manager.set_mode(Mode::Dark);
+        assert!(manager.set_theme("Missing").is_err());
+        let mut restyled = Theme::default();
+        restyled.dark.window = Color::rgb(0, 0, 0);
+        manager.add_theme(restyled);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                (DEFAULT_THEME.to_string(), Mode::Dark, Theme::default().dark.window),
+                (DEFAULT_THEME.to_string(), Mode::Dark, Color::rgb(0, 0, 0)),
+            ]
+        );
+        assert_eq!(Mode::parse("Light").unwrap(), Mode::Light);
+        assert!(Mode::parse("dim").is_err());
+    }
+}
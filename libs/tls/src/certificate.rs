@@ -0,0 +1,193 @@
+//! Certificates and trust anchors
+//!
+//! Certificates bind a subject name to a key-agreement public key and are
+//! signed through the keystore by the issuer's signing key. Clients accept
+//! a certificate when its issuer is a trust anchor whose verification key
+//! is held in their keystore.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use keystore::{KeyId, Keystore};
+use serde::{Deserialize, Serialize};
+
+/// Seconds since the Unix epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Server certificate
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Certificate {
+    pub subject: String,
+    pub issuer: String,
+    /// Key-agreement public key of the subject
+    pub public_key: Vec<u8>,
+    pub not_before: u64,
+    pub not_after: u64,
+    #[serde(default)]
+    pub signature: Vec<u8>,
+}
+
+impl Certificate {
+    /// Issue a certificate for `public_key`, valid for `validity` seconds,
+    /// signed by the issuer's keystore key
+    pub fn issue(
+        keystore: &Keystore,
+        issuer_key: &KeyId,
+        issuer: &str,
+        subject: &str,
+        public_key: Vec<u8>,
+        validity: u64,
+    ) -> Result<Self, String> {
+        let not_before = now();
+        let mut certificate = Certificate {
+            subject: subject.to_string(),
+            issuer: issuer.to_string(),
+            public_key,
+            not_before,
+            not_after: not_before.saturating_add(validity),
+            signature: Vec::new(),
+        };
+        certificate.signature = keystore.sign(issuer_key, &certificate.signed_bytes()?)?;
+        Ok(certificate)
+    }
+
+    /// Bytes covered by the issuer signature
+    fn signed_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut unsigned = self.clone();
+        unsigned.signature.clear();
+        serde_json::to_vec(&unsigned).map_err(|e| e.to_string())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        serde_json::from_slice(bytes).map_err(|e| format!("Invalid certificate: {}", e))
+    }
+
+    /// Whether the certificate names `host`; `*.` matches one label
+    pub fn matches_name(&self, host: &str) -> bool {
+        match self.subject.strip_prefix("*.") {
+            Some(domain) => host
+                .split_once('.')
+                .is_some_and(|(label, rest)| !label.is_empty() && rest == domain),
+            None => self.subject == host,
+        }
+    }
+
+    pub fn is_valid_at(&self, time: u64) -> bool {
+        self.not_before <= time && time <= self.not_after
+    }
+}
+
+/// Issuers a client trusts, keyed by issuer name
+pub struct TrustStore {
+    keystore: Arc<Keystore>,
+    anchors: HashMap<String, KeyId>,
+}
+
+impl TrustStore {
+    pub fn new(keystore: Arc<Keystore>) -> Self {
+        TrustStore {
+            keystore,
+            anchors: HashMap::new(),
+        }
+    }
+
+    /// Trust certificates from `issuer`, verified with keystore key `key_id`
+    pub fn add_anchor(&mut self, issuer: &str, key_id: KeyId) {
+        self.anchors.insert(issuer.to_string(), key_id);
+    }
+
+    /// Check that `certificate` is trusted for `host` at `time`
+    pub fn verify(&self, certificate: &Certificate, host: &str, time: u64) -> Result<(), String> {
+        let key_id = self
+            .anchors
+            .get(&certificate.issuer)
+            .ok_or_else(|| format!("Untrusted issuer: {}", certificate.issuer))?;
+        if !self
+            .keystore
+            .verify(key_id, &certificate.signed_bytes()?, &certificate.signature)?
+        {
+            return Err("Invalid certificate signature".to_string());
+        }
+        if !certificate.matches_name(host) {
+            return Err(format!("Certificate is not valid for {}", host));
+        }
+        if !certificate.is_valid_at(time) {
+            return Err("Certificate has expired or is not yet valid".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keystore::{KeyType, KeyUsage};
+
+    fn authority() -> (Arc<Keystore>, KeyId) {
+        let keystore = Arc::new(Keystore::new());
+        let ca = keystore
+            .generate_key(KeyId::from("ca"), KeyType::Ed25519, vec![KeyUsage::Sign, KeyUsage::Verify], true)
+            .unwrap();
+        (keystore, ca)
+    }
+
+    #[test]
+    fn test_trust_verification() {
+        let (keystore, ca) = authority();
+        let certificate =
+            Certificate::issue(&keystore, &ca, "Hairr Root CA", "*.hairr-os.org", vec![1; 8], 3600).unwrap();
+        let mut trust = TrustStore::new(Arc::clone(&keystore));
+        assert!(trust.verify(&certificate, "packages.hairr-os.org", now()).is_err());
+
+        trust.add_anchor("Hairr Root CA", ca);
+        assert!(trust.verify(&certificate, "packages.hairr-os.org", now()).is_ok());
+        assert!(trust.verify(&certificate, "hairr-os.org", now()).is_err());
+        assert!(trust.verify(&certificate, "a.b.hairr-os.org", now()).is_err());
+        assert!(trust.verify(&certificate, "apps.hairr-os.org", now() + 7200).is_err());
+
+        let mut forged = certificate.clone();
+        forged.public_key = vec![2; 8];
+        assert!(trust.verify(&forged, "packages.hairr-os.org", now()).is_err());
+    }
+
+    #[test]
+    fn test_certificate_forged_without_issuer_key() {
+        let (keystore, ca) = authority();
+        let mut trust = TrustStore::new(Arc::clone(&keystore));
+        trust.add_anchor("Hairr Root CA", ca.clone());
+
+        // Another authority with a key of the same name, claiming the same issuer
+        let (forger, forged_ca) = authority();
+        let issued =
+            Certificate::issue(&forger, &forged_ca, "Hairr Root CA", "*.hairr-os.org", vec![2; 8], 3600).unwrap();
+        let unsigned = Certificate { signature: issued.signed_bytes().unwrap(), ..issued.clone() };
+        for forged in [issued.clone(), unsigned] {
+            let forged = Certificate::from_bytes(&forged.to_bytes()).unwrap();
+            assert_eq!(
+                trust.verify(&forged, "packages.hairr-os.org", now()),
+                Err("Invalid certificate signature".to_string())
+            );
+        }
+
+        let signature = keystore.sign(&ca, &issued.signed_bytes().unwrap()).unwrap();
+        assert!(trust.verify(&Certificate { signature, ..issued }, "packages.hairr-os.org", now()).is_ok());
+    }
+
+    #[test]
+    fn test_certificate_encoding() {
+        let (keystore, ca) = authority();
+        let certificate = Certificate::issue(&keystore, &ca, "CA", "example.org", vec![7; 8], 60).unwrap();
+        assert_eq!(Certificate::from_bytes(&certificate.to_bytes()).unwrap(), certificate);
+        assert!(Certificate::from_bytes(b"not a certificate").is_err());
+    }
+}
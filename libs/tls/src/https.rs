@@ -0,0 +1,320 @@
+//! HTTPS Fetching
+//!
+//! Just enough HTTP/1.1 to fetch resources over a TLS session: URL parsing,
+//! request and response encoding, and a polled GET client. Responses are
+//! delimited by `Content-Length`.
+
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+
+use kernel::ProcessId;
+use net_stack::{NetStack, SocketId};
+
+use crate::{TlsClientConfig, TlsSession};
+
+pub const HTTPS_PORT: u16 = 443;
+
+/// Header names and values in arrival order
+pub type Headers = Vec<(String, String)>;
+
+/// Message split into start line, headers and body
+struct RawMessage {
+    start: String,
+    headers: Headers,
+    body: Vec<u8>,
+}
+
+/// Parsed `https://` URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let rest = url.strip_prefix("https://").ok_or("Only https:// URLs are supported")?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| "Invalid port")?),
+            None => (authority, HTTPS_PORT),
+        };
+        if host.is_empty() {
+            return Err("Missing host".to_string());
+        }
+        Ok(Url {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+fn find_header_end(bytes: &[u8]) -> Option<usize> {
+    bytes.windows(4).position(|w| w == b"\r\n\r\n")
+}
+
+fn parse_head(head: &str) -> Result<(&str, Headers), String> {
+    let mut lines = head.split("\r\n");
+    let start = lines.next().ok_or("Empty message")?;
+    let headers = lines
+        .map(|line| {
+            line.split_once(':')
+                .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+                .ok_or_else(|| format!("Malformed header: {}", line))
+        })
+        .collect::<Result<_, _>>()?;
+    Ok((start, headers))
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+/// Split a complete message into head and body; `None` until all of the
+/// body announced by Content-Length has arrived
+fn split_message(bytes: &[u8]) -> Result<Option<RawMessage>, String> {
+    let Some(end) = find_header_end(bytes) else {
+        return Ok(None);
+    };
+    let head = std::str::from_utf8(&bytes[..end]).map_err(|_| "Header is not UTF-8")?;
+    let (start, headers) = parse_head(head)?;
+    let length = match header(&headers, "Content-Length") {
+        Some(value) => value.parse::<usize>().map_err(|_| "Invalid Content-Length")?,
+        None => 0,
+    };
+    let body = &bytes[end + 4..];
+    if body.len() < length {
+        return Ok(None);
+    }
+    Ok(Some(RawMessage {
+        start: start.to_string(),
+        headers,
+        body: body[..length].to_vec(),
+    }))
+}
+
+/// HTTP request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    pub host: String,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    pub fn get(url: &Url) -> Self {
+        HttpRequest {
+            method: "GET".to_string(),
+            path: url.path.clone(),
+            host: url.host.clone(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.method,
+            self.path,
+            self.host,
+            self.body.len()
+        )
+        .into_bytes();
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+
+    /// Parse a request; `Ok(None)` if more bytes are needed
+    pub fn parse(bytes: &[u8]) -> Result<Option<Self>, String> {
+        let Some(RawMessage { start, headers, body }) = split_message(bytes)? else {
+            return Ok(None);
+        };
+        let mut parts = start.split(' ');
+        let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+            return Err("Malformed request line".to_string());
+        };
+        Ok(Some(HttpRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            host: header(&headers, "Host").unwrap_or_default().to_string(),
+            body,
+        }))
+    }
+}
+
+/// HTTP response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn new(status: u16, body: Vec<u8>) -> Self {
+        HttpResponse {
+            status,
+            headers: Vec::new(),
+            body,
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        header(&self.headers, name)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let reason = match self.status {
+            200 => "OK",
+            404 => "Not Found",
+            _ => "Status",
+        };
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason);
+        for (name, value) in &self.headers {
+            if !name.eq_ignore_ascii_case("Content-Length") {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        head.push_str(&format!("Content-Length: {}\r\n\r\n", self.body.len()));
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+
+    /// Parse a response; `Ok(None)` if more bytes are needed
+    pub fn parse(bytes: &[u8]) -> Result<Option<Self>, String> {
+        let Some(RawMessage { start, headers, body }) = split_message(bytes)? else {
+            return Ok(None);
+        };
+        let status = start
+            .split(' ')
+            .nth(1)
+            .and_then(|s| s.parse().ok())
+            .ok_or("Malformed status line")?;
+        Ok(Some(HttpResponse { status, headers, body }))
+    }
+}
+
+/// A GET request in flight; poll it alongside the network stack
+pub struct HttpsFetch {
+    session: TlsSession,
+    request: Option<Vec<u8>>,
+    received: Vec<u8>,
+}
+
+impl HttpsFetch {
+    /// Connect to `addr` and request `url`, authenticating the server as
+    /// the URL's host
+    pub fn start(config: &TlsClientConfig, stack: Arc<NetStack>, addr: Ipv4Addr, url: &Url) -> Result<Self, String> {
+        let socket = stack.connect(addr, url.port)?;
+        Ok(Self::over(config, stack, socket, url))
+    }
+
+    /// `start` on behalf of `owner`, subject to the stack's connect check
+    pub fn start_as(
+        config: &TlsClientConfig,
+        stack: Arc<NetStack>,
+        owner: ProcessId,
+        addr: Ipv4Addr,
+        url: &Url,
+    ) -> Result<Self, String> {
+        let socket = stack.connect_as(owner, addr, url.port)?;
+        Ok(Self::over(config, stack, socket, url))
+    }
+
+    fn over(config: &TlsClientConfig, stack: Arc<NetStack>, socket: SocketId, url: &Url) -> Self {
+        HttpsFetch {
+            session: TlsSession::client(config, stack, socket, &url.host),
+            request: Some(HttpRequest::get(url).to_bytes()),
+            received: Vec::new(),
+        }
+    }
+
+    /// Advance the fetch; returns the response once it is complete
+    pub fn poll(&mut self) -> Result<Option<HttpResponse>, String> {
+        self.session.poll()?;
+        if self.session.is_established() {
+            if let Some(request) = self.request.take() {
+                self.session.send(&request)?;
+            }
+        }
+        self.received.extend(self.session.recv(usize::MAX));
+        let response = HttpResponse::parse(&self.received)?;
+        if response.is_some() {
+            self.session.close()?;
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{pump, setup, SERVER_ADDR, SERVER_NAME};
+    use crate::TlsSession;
+
+    #[test]
+    fn test_url_and_message_parsing() {
+        let url = Url::parse("https://packages.hairr-os.org:8443/index.json").unwrap();
+        assert_eq!((url.host.as_str(), url.port, url.path.as_str()), ("packages.hairr-os.org", 8443, "/index.json"));
+        assert_eq!(Url::parse("https://example.org").unwrap().path, "/");
+        assert!(Url::parse("http://example.org/").is_err());
+
+        let request = HttpRequest::get(&url).to_bytes();
+        assert_eq!(HttpRequest::parse(&request[..10]).unwrap(), None);
+        assert_eq!(HttpRequest::parse(&request).unwrap().unwrap().path, "/index.json");
+
+        let response = HttpResponse::new(200, b"{}".to_vec()).to_bytes();
+        assert_eq!(HttpResponse::parse(&response[..response.len() - 1]).unwrap(), None);
+        let parsed = HttpResponse::parse(&response).unwrap().unwrap();
+        assert_eq!((parsed.status, parsed.body.as_slice()), (200, b"{}".as_slice()));
+    }
+
+    #[test]
+    fn test_https_fetch() {
+        let (client, server, server_config, client_config) = setup(SERVER_NAME);
+        let listener = server.listen(HTTPS_PORT).unwrap();
+        let url = Url::parse("https://packages.hairr-os.org/index.json").unwrap();
+        let mut fetch = HttpsFetch::start(&client_config, Arc::clone(&client), SERVER_ADDR, &url).unwrap();
+
+        let mut session = None;
+        let mut request = Vec::new();
+        let mut response = None;
+        for _ in 0..16 {
+            response = fetch.poll().unwrap();
+            if response.is_some() {
+                break;
+            }
+            pump(&client, &server);
+            if session.is_none() {
+                session = server
+                    .accept(listener)
+                    .unwrap()
+                    .map(|socket| TlsSession::server(&server_config, Arc::clone(&server), socket));
+            }
+            if let Some(session) = session.as_mut() {
+                session.poll().unwrap();
+                request.extend(session.recv(1024));
+                if let Some(parsed) = HttpRequest::parse(&request).unwrap() {
+                    assert_eq!((parsed.host.as_str(), parsed.path.as_str()), (SERVER_NAME, "/index.json"));
+                    session.send(&HttpResponse::new(200, b"[\"core\"]".to_vec()).to_bytes()).unwrap();
+                    request.clear();
+                }
+            }
+            pump(&client, &server);
+        }
+
+        let response = response.expect("fetch did not complete");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"[\"core\"]");
+    }
+}
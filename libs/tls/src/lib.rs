@@ -0,0 +1,600 @@
+//! TLS for hairr OS Services
+//!
+//! A minimal TLS-style secure channel over net-stack TCP sockets. The
+//! client sends an ephemeral key share; the server answers with its own
+//! share and a certificate for a keystore key-agreement key. Session keys
+//! mix the ephemeral-ephemeral secret with the ephemeral-static secret, so
+//! only the holder of the certified key can complete the handshake, and
+//! both sides confirm the transcript with Finished messages before any
+//! application data flows.
+//!
+//! Sessions are polled like the stack underneath them.
+
+use std::sync::Arc;
+
+use keystore::exchange;
+use keystore::{KeyId, Keystore};
+use net_stack::{NetStack, SocketId, TcpState};
+use serde::{Deserialize, Serialize};
+
+pub mod certificate;
+pub mod crypto;
+pub mod https;
+
+pub use certificate::{Certificate, TrustStore};
+pub use https::{HttpRequest, HttpResponse, HttpsFetch, Url};
+
+/// Record content types
+pub const CONTENT_ALERT: u8 = 21;
+pub const CONTENT_HANDSHAKE: u8 = 22;
+pub const CONTENT_APPLICATION_DATA: u8 = 23;
+
+/// Largest record payload
+pub const MAX_RECORD_LEN: usize = 16384;
+
+const RECORD_HEADER_LEN: usize = 3;
+const KEY_LEN: usize = 16;
+const READ_CHUNK: usize = 4096;
+
+/// Alert payloads
+const ALERT_CLOSE_NOTIFY: u8 = 0;
+const ALERT_HANDSHAKE_FAILURE: u8 = 40;
+
+/// Handshake message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Handshake {
+    ClientHello {
+        server_name: String,
+        random: Vec<u8>,
+        key_share: Vec<u8>,
+    },
+    ServerHello {
+        random: Vec<u8>,
+        key_share: Vec<u8>,
+        certificate: Certificate,
+    },
+    Finished {
+        verify_data: Vec<u8>,
+    },
+}
+
+/// Progress of a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    /// Client waiting for the TCP connection to open
+    Connecting,
+    AwaitingClientHello,
+    AwaitingServerHello,
+    AwaitingServerFinished,
+    AwaitingClientFinished,
+    Established,
+    /// Closed by either side
+    Closed,
+    Failed,
+}
+
+/// Server identity loaded from the keystore
+#[derive(Clone)]
+pub struct TlsServerConfig {
+    keystore: Arc<Keystore>,
+    key_id: KeyId,
+    certificate: Certificate,
+}
+
+impl TlsServerConfig {
+    /// Use key `key_id` and the certificate stored alongside it
+    pub fn from_keystore(keystore: Arc<Keystore>, key_id: KeyId) -> Result<Self, String> {
+        let encoded = keystore.certificate(&key_id).ok_or("No certificate stored for key")?;
+        let certificate = Certificate::from_bytes(&encoded)?;
+        if certificate.public_key != keystore.public_key(&key_id)? {
+            return Err("Certificate does not match key".to_string());
+        }
+        Ok(TlsServerConfig {
+            keystore,
+            key_id,
+            certificate,
+        })
+    }
+}
+
+/// Client trust configuration
+pub struct TlsClientConfig {
+    trust: Arc<TrustStore>,
+}
+
+impl TlsClientConfig {
+    pub fn new(trust: Arc<TrustStore>) -> Self {
+        TlsClientConfig { trust }
+    }
+}
+
+enum Role {
+    Client {
+        trust: Arc<TrustStore>,
+        server_name: String,
+        ephemeral: u64,
+    },
+    Server(TlsServerConfig),
+}
+
+struct TrafficKeys {
+    write_key: Vec<u8>,
+    write_mac: Vec<u8>,
+    read_key: Vec<u8>,
+    read_mac: Vec<u8>,
+    client_finished: Vec<u8>,
+    server_finished: Vec<u8>,
+}
+
+impl TrafficKeys {
+    fn derive(secret: &[u8], is_client: bool) -> Self {
+        let client_key = crypto::expand(secret, "client write key", KEY_LEN);
+        let client_mac = crypto::expand(secret, "client write mac", KEY_LEN);
+        let server_key = crypto::expand(secret, "server write key", KEY_LEN);
+        let server_mac = crypto::expand(secret, "server write mac", KEY_LEN);
+        let (write_key, write_mac, read_key, read_mac) = if is_client {
+            (client_key, client_mac, server_key, server_mac)
+        } else {
+            (server_key, server_mac, client_key, client_mac)
+        };
+        TrafficKeys {
+            write_key,
+            write_mac,
+            read_key,
+            read_mac,
+            client_finished: crypto::expand(secret, "client finished", KEY_LEN),
+            server_finished: crypto::expand(secret, "server finished", KEY_LEN),
+        }
+    }
+}
+
+/// Session secret from both key agreements and the hello transcript
+fn session_secret(ephemeral_secret: &[u8], static_secret: &[u8], transcript: &[u8]) -> Vec<u8> {
+    let digest = crypto::keyed_hash(b"transcript", &[transcript]).to_be_bytes();
+    let mut input = ephemeral_secret.to_vec();
+    input.extend_from_slice(static_secret);
+    input.extend_from_slice(&digest);
+    crypto::expand(&input, "session secret", 32)
+}
+
+fn verify_data(finished_key: &[u8], transcript: &[u8]) -> Vec<u8> {
+    crypto::tag(finished_key, 0, CONTENT_HANDSHAKE, transcript).to_vec()
+}
+
+/// A secure channel over one TCP socket
+pub struct TlsSession {
+    stack: Arc<NetStack>,
+    socket: SocketId,
+    role: Role,
+    state: SessionState,
+    keys: Option<TrafficKeys>,
+    transcript: Vec<u8>,
+    write_seq: u64,
+    read_seq: u64,
+    /// Raw bytes read from the socket that do not yet form a record
+    inbound: Vec<u8>,
+    plaintext: Vec<u8>,
+    peer_certificate: Option<Certificate>,
+    error: Option<String>,
+}
+
+impl TlsSession {
+    fn new(stack: Arc<NetStack>, socket: SocketId, role: Role, state: SessionState) -> Self {
+        TlsSession {
+            stack,
+            socket,
+            role,
+            state,
+            keys: None,
+            transcript: Vec::new(),
+            write_seq: 0,
+            read_seq: 0,
+            inbound: Vec::new(),
+            plaintext: Vec::new(),
+            peer_certificate: None,
+            error: None,
+        }
+    }
+
+    /// Start a client session on `socket`, a TCP stream to `server_name`;
+    /// the handshake begins once the connection is established
+    pub fn client(config: &TlsClientConfig, stack: Arc<NetStack>, socket: SocketId, server_name: &str) -> Self {
+        let role = Role::Client {
+            trust: Arc::clone(&config.trust),
+            server_name: server_name.to_string(),
+            ephemeral: exchange::generate_private(),
+        };
+        TlsSession::new(stack, socket, role, SessionState::Connecting)
+    }
+
+    /// Start a server session on an accepted TCP stream
+    pub fn server(config: &TlsServerConfig, stack: Arc<NetStack>, socket: SocketId) -> Self {
+        TlsSession::new(stack, socket, Role::Server(config.clone()), SessionState::AwaitingClientHello)
+    }
+
+    pub fn state(&self) -> SessionState {
+        self.state
+    }
+
+    pub fn is_established(&self) -> bool {
+        self.state == SessionState::Established
+    }
+
+    /// Why the handshake failed, if it did
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// Certificate presented by the server, once verified
+    pub fn peer_certificate(&self) -> Option<&Certificate> {
+        self.peer_certificate.as_ref()
+    }
+
+    pub fn socket(&self) -> SocketId {
+        self.socket
+    }
+
+    /// Advance the handshake and decrypt received records
+    pub fn poll(&mut self) -> Result<(), String> {
+        if matches!(self.state, SessionState::Failed | SessionState::Closed) {
+            return Ok(());
+        }
+        if self.state == SessionState::Connecting {
+            match self.stack.tcp_state(self.socket) {
+                Some(TcpState::Established) => self.send_client_hello()?,
+                Some(TcpState::SynSent) => return Ok(()),
+                _ => return self.fail("Connection failed".to_string(), false),
+            }
+        }
+
+        loop {
+            let data = self.stack.recv(self.socket, READ_CHUNK)?;
+            if data.is_empty() {
+                break;
+            }
+            self.inbound.extend_from_slice(&data);
+        }
+
+        while self.inbound.len() >= RECORD_HEADER_LEN {
+            let len = u16::from_be_bytes([self.inbound[1], self.inbound[2]]) as usize;
+            if self.inbound.len() < RECORD_HEADER_LEN + len {
+                break;
+            }
+            let content_type = self.inbound[0];
+            let payload = self.inbound[RECORD_HEADER_LEN..RECORD_HEADER_LEN + len].to_vec();
+            self.inbound.drain(..RECORD_HEADER_LEN + len);
+            if let Err(e) = self.handle_record(content_type, payload) {
+                return self.fail(e, true);
+            }
+            if matches!(self.state, SessionState::Failed | SessionState::Closed) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn fail(&mut self, error: String, alert: bool) -> Result<(), String> {
+        if alert {
+            // Best effort; the connection is being torn down anyway
+            let _ = self.write_record(CONTENT_ALERT, &[ALERT_HANDSHAKE_FAILURE]);
+        }
+        self.state = SessionState::Failed;
+        self.error = Some(error.clone());
+        let _ = self.stack.close(self.socket);
+        Err(error)
+    }
+
+    fn write_record(&self, content_type: u8, payload: &[u8]) -> Result<(), String> {
+        let mut record = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+        record.push(content_type);
+        record.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        record.extend_from_slice(payload);
+        self.stack.send(self.socket, &record).map(|_| ())
+    }
+
+    fn send_handshake(&mut self, message: &Handshake) -> Result<(), String> {
+        let bytes = serde_json::to_vec(message).map_err(|e| e.to_string())?;
+        self.transcript.extend_from_slice(&bytes);
+        self.write_record(CONTENT_HANDSHAKE, &bytes)
+    }
+
+    fn send_client_hello(&mut self) -> Result<(), String> {
+        let Role::Client {
+            server_name, ephemeral, ..
+        } = &self.role
+        else {
+            return Err("Only clients send a ClientHello".to_string());
+        };
+        let hello = Handshake::ClientHello {
+            server_name: server_name.clone(),
+            random: exchange::random_bytes(32),
+            key_share: exchange::public_key(*ephemeral),
+        };
+        self.send_handshake(&hello)?;
+        self.state = SessionState::AwaitingServerHello;
+        Ok(())
+    }
+
+    fn handle_record(&mut self, content_type: u8, payload: Vec<u8>) -> Result<(), String> {
+        match content_type {
+            CONTENT_HANDSHAKE => {
+                let message: Handshake =
+                    serde_json::from_slice(&payload).map_err(|e| format!("Malformed handshake: {}", e))?;
+                self.handle_handshake(message, &payload)
+            }
+            CONTENT_APPLICATION_DATA => {
+                if self.state != SessionState::Established {
+                    return Err("Application data before handshake completed".to_string());
+                }
+                let data = self.open(CONTENT_APPLICATION_DATA, payload)?;
+                self.plaintext.extend_from_slice(&data);
+                Ok(())
+            }
+            CONTENT_ALERT => {
+                let alert = if self.state == SessionState::Established {
+                    self.open(CONTENT_ALERT, payload)?
+                } else {
+                    payload
+                };
+                match alert.first() {
+                    Some(&ALERT_CLOSE_NOTIFY) => {
+                        self.state = SessionState::Closed;
+                        Ok(())
+                    }
+                    _ => Err("Peer aborted the handshake".to_string()),
+                }
+            }
+            other => Err(format!("Unexpected record type {}", other)),
+        }
+    }
+
+    fn handle_handshake(&mut self, message: Handshake, raw: &[u8]) -> Result<(), String> {
+        match (self.state, message) {
+            (SessionState::AwaitingClientHello, Handshake::ClientHello { key_share, .. }) => {
+                self.transcript.extend_from_slice(raw);
+                let Role::Server(config) = &self.role else {
+                    return Err("Unexpected ClientHello".to_string());
+                };
+                let config = config.clone();
+                let ephemeral = exchange::generate_private();
+                let ephemeral_secret = exchange::shared_secret(ephemeral, &key_share)?;
+                let static_secret = config.keystore.derive_shared_secret(&config.key_id, &key_share)?;
+
+                self.send_handshake(&Handshake::ServerHello {
+                    random: exchange::random_bytes(32),
+                    key_share: exchange::public_key(ephemeral),
+                    certificate: config.certificate.clone(),
+                })?;
+                let keys = TrafficKeys::derive(&session_secret(&ephemeral_secret, &static_secret, &self.transcript), false);
+                let finished = verify_data(&keys.server_finished, &self.transcript);
+                self.keys = Some(keys);
+                self.send_handshake(&Handshake::Finished { verify_data: finished })?;
+                self.state = SessionState::AwaitingClientFinished;
+                Ok(())
+            }
+            (
+                SessionState::AwaitingServerHello,
+                Handshake::ServerHello {
+                    key_share, certificate, ..
+                },
+            ) => {
+                let Role::Client {
+                    trust,
+                    server_name,
+                    ephemeral,
+                } = &self.role
+                else {
+                    return Err("Unexpected ServerHello".to_string());
+                };
+                trust.verify(&certificate, server_name, certificate::now())?;
+                let ephemeral_secret = exchange::shared_secret(*ephemeral, &key_share)?;
+                let static_secret = exchange::shared_secret(*ephemeral, &certificate.public_key)?;
+
+                self.transcript.extend_from_slice(raw);
+                let secret = session_secret(&ephemeral_secret, &static_secret, &self.transcript);
+                self.keys = Some(TrafficKeys::derive(&secret, true));
+                self.peer_certificate = Some(certificate);
+                self.state = SessionState::AwaitingServerFinished;
+                Ok(())
+            }
+            (SessionState::AwaitingServerFinished, Handshake::Finished { verify_data: received }) => {
+                let keys = self.keys.as_ref().ok_or("Missing session keys")?;
+                if received != verify_data(&keys.server_finished, &self.transcript) {
+                    return Err("Server Finished did not verify".to_string());
+                }
+                self.transcript.extend_from_slice(raw);
+                let finished = verify_data(&keys.client_finished, &self.transcript);
+                self.send_handshake(&Handshake::Finished { verify_data: finished })?;
+                self.state = SessionState::Established;
+                Ok(())
+            }
+            (SessionState::AwaitingClientFinished, Handshake::Finished { verify_data: received }) => {
+                let keys = self.keys.as_ref().ok_or("Missing session keys")?;
+                if received != verify_data(&keys.client_finished, &self.transcript) {
+                    return Err("Client Finished did not verify".to_string());
+                }
+                self.transcript.extend_from_slice(raw);
+                self.state = SessionState::Established;
+                Ok(())
+            }
+            _ => Err("Unexpected handshake message".to_string()),
+        }
+    }
+
+    /// Encrypt and authenticate a record payload
+    fn seal(&mut self, content_type: u8, data: &[u8]) -> Result<Vec<u8>, String> {
+        let keys = self.keys.as_ref().ok_or("Missing session keys")?;
+        let mut sealed = data.to_vec();
+        crypto::apply_keystream(&keys.write_key, self.write_seq, &mut sealed);
+        let tag = crypto::tag(&keys.write_mac, self.write_seq, content_type, &sealed);
+        sealed.extend_from_slice(&tag);
+        self.write_seq += 1;
+        Ok(sealed)
+    }
+
+    /// Authenticate and decrypt a record payload
+    fn open(&mut self, content_type: u8, mut payload: Vec<u8>) -> Result<Vec<u8>, String> {
+        let keys = self.keys.as_ref().ok_or("Missing session keys")?;
+        if payload.len() < crypto::TAG_LEN {
+            return Err("Record too short".to_string());
+        }
+        let received = payload.split_off(payload.len() - crypto::TAG_LEN);
+        if received != crypto::tag(&keys.read_mac, self.read_seq, content_type, &payload) {
+            return Err("Record authentication failed".to_string());
+        }
+        crypto::apply_keystream(&keys.read_key, self.read_seq, &mut payload);
+        self.read_seq += 1;
+        Ok(payload)
+    }
+
+    /// Send application data once the handshake has completed
+    pub fn send(&mut self, data: &[u8]) -> Result<usize, String> {
+        if self.state != SessionState::Established {
+            return Err("TLS session is not established".to_string());
+        }
+        for chunk in data.chunks(MAX_RECORD_LEN - crypto::TAG_LEN) {
+            let sealed = self.seal(CONTENT_APPLICATION_DATA, chunk)?;
+            self.write_record(CONTENT_APPLICATION_DATA, &sealed)?;
+        }
+        Ok(data.len())
+    }
+
+    /// Take up to `max` bytes of decrypted application data
+    pub fn recv(&mut self, max: usize) -> Vec<u8> {
+        let len = max.min(self.plaintext.len());
+        self.plaintext.drain(..len).collect()
+    }
+
+    /// Send close_notify and close the underlying socket
+    pub fn close(&mut self) -> Result<(), String> {
+        if self.state == SessionState::Established {
+            let sealed = self.seal(CONTENT_ALERT, &[ALERT_CLOSE_NOTIFY])?;
+            self.write_record(CONTENT_ALERT, &sealed)?;
+        }
+        self.state = SessionState::Closed;
+        self.stack.close(self.socket)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use keystore::{KeyType, KeyUsage};
+    use net_stack::{Ipv4Config, VirtualNic};
+    use std::net::Ipv4Addr;
+
+    pub(crate) const SERVER_NAME: &str = "packages.hairr-os.org";
+    pub(crate) const SERVER_ADDR: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+
+    /// Client and server stacks, a keystore holding a CA and a server key
+    /// certified for `subject`, and the client config trusting the CA
+    pub(crate) fn setup(subject: &str) -> (Arc<NetStack>, Arc<NetStack>, TlsServerConfig, TlsClientConfig) {
+        let (a, b) = VirtualNic::pair([2, 0, 0, 0, 0, 1], [2, 0, 0, 0, 0, 2]);
+        let netmask = Ipv4Addr::new(255, 255, 255, 0);
+        let client = Arc::new(NetStack::new(Box::new(a)));
+        let server = Arc::new(NetStack::new(Box::new(b)));
+        client.configure(Ipv4Config::new(Ipv4Addr::new(10, 0, 0, 1), netmask, None));
+        server.configure(Ipv4Config::new(SERVER_ADDR, netmask, None));
+
+        let keystore = Arc::new(Keystore::new());
+        let ca = keystore
+            .generate_key(KeyId::from("ca"), KeyType::Ed25519, vec![KeyUsage::Sign, KeyUsage::Verify], true)
+            .unwrap();
+        let server_key = keystore
+            .generate_key(KeyId::from("web"), KeyType::ECC256, vec![KeyUsage::DeriveKey], true)
+            .unwrap();
+        let public_key = keystore.public_key(&server_key).unwrap();
+        let certificate = Certificate::issue(&keystore, &ca, "Hairr Root CA", subject, public_key, 3600).unwrap();
+        keystore.store_certificate(&server_key, certificate.to_bytes()).unwrap();
+
+        let mut trust = TrustStore::new(Arc::clone(&keystore));
+        trust.add_anchor("Hairr Root CA", ca);
+        let server_config = TlsServerConfig::from_keystore(keystore, server_key).unwrap();
+        (client, server, server_config, TlsClientConfig::new(Arc::new(trust)))
+    }
+
+    /// Open a TCP connection and wrap both ends in sessions
+    pub(crate) fn open(
+        client: &Arc<NetStack>,
+        server: &Arc<NetStack>,
+        server_config: &TlsServerConfig,
+        client_config: &TlsClientConfig,
+        server_name: &str,
+    ) -> (TlsSession, TlsSession) {
+        let listener = server.listen(443).unwrap();
+        let stream = client.connect(SERVER_ADDR, 443).unwrap();
+        pump(client, server);
+        let accepted = server.accept(listener).unwrap().unwrap();
+        server.close(listener).unwrap();
+        (
+            TlsSession::client(client_config, Arc::clone(client), stream, server_name),
+            TlsSession::server(server_config, Arc::clone(server), accepted),
+        )
+    }
+
+    pub(crate) fn pump(a: &NetStack, b: &NetStack) {
+        while a.poll() + b.poll() > 0 {}
+    }
+
+    /// Alternate polling both sessions until neither makes progress
+    pub(crate) fn drive(client: &Arc<NetStack>, server: &Arc<NetStack>, sessions: [&mut TlsSession; 2]) {
+        let [a, b] = sessions;
+        for _ in 0..8 {
+            let _ = a.poll();
+            pump(client, server);
+            let _ = b.poll();
+            pump(client, server);
+        }
+    }
+
+    #[test]
+    fn test_handshake_and_data() {
+        let (client, server, server_config, client_config) = setup(SERVER_NAME);
+        let (mut tls_client, mut tls_server) = open(&client, &server, &server_config, &client_config, SERVER_NAME);
+        assert!(tls_client.send(b"early").is_err());
+
+        drive(&client, &server, [&mut tls_client, &mut tls_server]);
+        assert!(tls_client.is_established());
+        assert!(tls_server.is_established());
+        assert_eq!(tls_client.peer_certificate().unwrap().subject, SERVER_NAME);
+
+        tls_client.send(b"GET /index").unwrap();
+        pump(&client, &server);
+        tls_server.poll().unwrap();
+        assert_eq!(tls_server.recv(64), b"GET /index");
+
+        let large = vec![0x5a; MAX_RECORD_LEN + 100];
+        tls_server.send(&large).unwrap();
+        pump(&client, &server);
+        tls_client.poll().unwrap();
+        assert_eq!(tls_client.recv(usize::MAX), large);
+
+        tls_client.close().unwrap();
+        pump(&client, &server);
+        tls_server.poll().unwrap();
+        assert_eq!(tls_server.state(), SessionState::Closed);
+    }
+
+    #[test]
+    fn test_rejects_wrong_server_name() {
+        let (client, server, server_config, client_config) = setup("apps.hairr-os.org");
+        let (mut tls_client, mut tls_server) = open(&client, &server, &server_config, &client_config, SERVER_NAME);
+        drive(&client, &server, [&mut tls_client, &mut tls_server]);
+
+        assert_eq!(tls_client.state(), SessionState::Failed);
+        assert!(tls_client.error().unwrap().contains("not valid for"));
+        assert_eq!(tls_server.state(), SessionState::Failed);
+    }
+
+    #[test]
+    fn test_rejects_untrusted_issuer() {
+        let (client, server, server_config, _) = setup(SERVER_NAME);
+        let untrusted = TlsClientConfig::new(Arc::new(TrustStore::new(Arc::new(Keystore::new()))));
+        let (mut tls_client, mut tls_server) = open(&client, &server, &server_config, &untrusted, SERVER_NAME);
+        drive(&client, &server, [&mut tls_client, &mut tls_server]);
+
+        assert!(!tls_client.is_established());
+        assert!(tls_client.error().unwrap().contains("Untrusted issuer"));
+    }
+}
@@ -0,0 +1,143 @@
+//! Delta updates
+//!
+//! A delta rebuilds a package archive from the archive of an older
+//! version: a list of byte ranges to copy from the old archive and new
+//! bytes to insert between them. Repositories publish deltas next to the
+//! full archives, and the package manager keeps the archive of every
+//! installed package so it has something to apply them to. The delta names
+//! the digests of both archives; when the installed archive is not the
+//! one it was made from, or the result does not match, the full archive is
+//! downloaded instead.
+//!
+//! ```json
+//! {"base_digest": "9f2c...", "target_digest": "41ab...",
+//!  "ops": [{"op": "copy", "offset": 0, "len": 4096}, {"op": "insert", "data": [1, 2, 3]}]}
+//! ```
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::index::archive_digest;
+
+/// Archives of installed packages, by digest
+pub const ARCHIVE_CACHE_DIR: &str = "/var/cache/pkg/archives";
+
+/// Shortest run of old bytes worth a copy
+const BLOCK: usize = 16;
+
+pub fn cache_path(digest: &str) -> PathBuf {
+    Path::new(ARCHIVE_CACHE_DIR).join(format!("{}.pkg", digest))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum DeltaOp {
+    /// Bytes from the old archive
+    Copy { offset: usize, len: usize },
+    Insert { data: Vec<u8> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackageDelta {
+    pub base_digest: String,
+    pub target_digest: String,
+    pub ops: Vec<DeltaOp>,
+}
+
+impl PackageDelta {
+    /// Delta turning `base` into `target`
+    pub fn generate(base: &[u8], target: &[u8]) -> Self {
+        let mut blocks: HashMap<&[u8], usize> = HashMap::new();
+        for offset in (0..base.len().saturating_sub(BLOCK - 1)).step_by(BLOCK) {
+            blocks.entry(&base[offset..offset + BLOCK]).or_insert(offset);
+        }
+
+        let mut ops = Vec::new();
+        let mut literal = Vec::new();
+        let mut pos = 0;
+        while pos < target.len() {
+            let found = target.get(pos..pos + BLOCK).and_then(|block| blocks.get(block));
+            let Some(&offset) = found else {
+                literal.push(target[pos]);
+                pos += 1;
+                continue;
+            };
+            let len = base[offset..].iter().zip(&target[pos..]).take_while(|(a, b)| a == b).count();
+            if !literal.is_empty() {
+                ops.push(DeltaOp::Insert {
+                    data: std::mem::take(&mut literal),
+                });
+            }
+            ops.push(DeltaOp::Copy { offset, len });
+            pos += len;
+        }
+        if !literal.is_empty() {
+            ops.push(DeltaOp::Insert { data: literal });
+        }
+        PackageDelta {
+            base_digest: archive_digest(base),
+            target_digest: archive_digest(target),
+            ops,
+        }
+    }
+
+    pub fn from_json(data: &[u8]) -> Result<Self, String> {
+        serde_json::from_slice(data).map_err(|e| format!("Invalid package delta: {}", e))
+    }
+
+    pub fn to_json(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+
+    /// Rebuild the new archive, checking both ends against their digests
+    pub fn apply(&self, base: &[u8]) -> Result<Vec<u8>, String> {
+        if archive_digest(base) != self.base_digest {
+            return Err("Delta was made from a different archive".to_string());
+        }
+        let mut target = Vec::new();
+        for op in &self.ops {
+            match op {
+                DeltaOp::Copy { offset, len } => {
+                    let range = base.get(*offset..offset.saturating_add(*len)).ok_or("Delta copies past the archive")?;
+                    target.extend_from_slice(range);
+                }
+                DeltaOp::Insert { data } => target.extend_from_slice(data),
+            }
+        }
+        if archive_digest(&target) != self.target_digest {
+            return Err("Delta result does not match its digest".to_string());
+        }
+        Ok(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delta_roundtrip() {
+        let base: Vec<u8> = (0..4000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut target = base.clone();
+        target[1000] ^= 0xff;
+        target.splice(2500..2600, b"patched".iter().copied());
+        target.extend_from_slice(b"tail");
+
+        let delta = PackageDelta::generate(&base, &target);
+        assert!(delta.to_json().len() < target.len() / 2);
+        let parsed = PackageDelta::from_json(&delta.to_json()).unwrap();
+        assert_eq!(parsed.apply(&base).unwrap(), target);
+        assert_eq!(PackageDelta::generate(b"", b"new").apply(b"").unwrap(), b"new");
+
+        let mut other = base.clone();
+        other[0] ^= 1;
+        assert!(delta.apply(&other).unwrap_err().contains("different archive"));
+        let mut corrupt = delta.clone();
+        corrupt.ops.push(DeltaOp::Insert { data: vec![0] });
+        assert!(corrupt.apply(&base).unwrap_err().contains("does not match"));
+        corrupt.ops = vec![DeltaOp::Copy { offset: 3990, len: 100 }];
+        assert!(corrupt.apply(&base).is_err());
+    }
+}
@@ -0,0 +1,204 @@
+//! Lifecycle hooks
+//!
+//! A package manifest may name commands to run after the package is
+//! installed and before it is removed. Each hook runs as its own kernel
+//! process holding only the capabilities the manifest declares for it, and
+//! has a time limit. A hook may only ask for access the package's own
+//! capabilities, the ones shown before install, already give. When the limit passes the process is terminated and
+//! its capabilities revoked, so a hook that keeps running can no longer
+//! touch anything. Whatever the hook prints goes to the package manager's
+//! log.
+//!
+//! ```json
+//! "hooks": [{"stage": "post_install", "command": "bin/setup", "timeout_ms": 5000,
+//!            "capabilities": [{"resource": {"File": "/apps/viewer"}, "permission": "ReadWrite"}]}]
+//! ```
+
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use capability::{CapabilityManager, CapabilityToken};
+use kernel::{Kernel, Priority, ProcessId};
+use serde::{Deserialize, Serialize};
+
+use crate::permissions::CapabilityGrant;
+use crate::{Package, PackageId};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookStage {
+    PostInstall,
+    /// Failing stops the removal
+    PreRemove,
+}
+
+impl fmt::Display for HookStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HookStage::PostInstall => f.write_str("post-install"),
+            HookStage::PreRemove => f.write_str("pre-remove"),
+        }
+    }
+}
+
+fn default_timeout_ms() -> u64 {
+    30_000
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hook {
+    pub stage: HookStage,
+    /// Executable, relative to the install root
+    pub command: PathBuf,
+    /// Granted to the hook process while it runs
+    #[serde(default)]
+    pub capabilities: Vec<CapabilityGrant>,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+impl Hook {
+    /// Refuse capabilities beyond those the package was approved for
+    pub fn check_capabilities(&self, approved: &[CapabilityGrant]) -> Result<(), String> {
+        match self.capabilities.iter().find(|c| !c.is_covered_by(approved)) {
+            Some(capability) => Err(format!(
+                "{} hook {} asks to {}, which the package was not granted",
+                self.stage,
+                self.command.display(),
+                capability
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A hook about to run in its process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HookInvocation {
+    pub package: PackageId,
+    pub stage: HookStage,
+    pub process: ProcessId,
+    /// Absolute path of the executable
+    pub command: PathBuf,
+    pub capabilities: Vec<CapabilityToken>,
+}
+
+/// Runs a hook's command inside its process, returning what it printed
+pub type HookExecutor = Arc<dyn Fn(&HookInvocation) -> Result<String, String> + Send + Sync>;
+
+/// Starts hook processes with their declared capabilities
+pub struct HookRunner {
+    kernel: Arc<Kernel>,
+    capabilities: Arc<CapabilityManager>,
+    executor: HookExecutor,
+}
+
+impl HookRunner {
+    pub fn new(kernel: Arc<Kernel>, capabilities: Arc<CapabilityManager>, executor: HookExecutor) -> Self {
+        HookRunner {
+            kernel,
+            capabilities,
+            executor,
+        }
+    }
+
+    /// Run a package's hook from its install root and return its output.
+    /// The process and its capabilities are gone when this returns.
+    pub fn run(&self, package: &Package, root: &Path, hook: &Hook) -> Result<String, String> {
+        if !hook.command.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(format!("Invalid hook command {}", hook.command.display()));
+        }
+        hook.check_capabilities(&package.capabilities)?;
+        let process = self.kernel.create_process(format!("{}-{}", package.id, hook.stage), Priority::Low);
+        let tokens: Vec<CapabilityToken> = hook
+            .capabilities
+            .iter()
+            .map(|c| self.capabilities.grant(c.resource.clone(), c.permission))
+            .collect();
+        let invocation = HookInvocation {
+            package: package.id.clone(),
+            stage: hook.stage,
+            process,
+            command: root.join(&hook.command),
+            capabilities: tokens.clone(),
+        };
+
+        let (sender, receiver) = mpsc::channel();
+        let executor = Arc::clone(&self.executor);
+        thread::spawn(move || {
+            let _ = sender.send(executor(&invocation));
+        });
+        let result = receiver
+            .recv_timeout(Duration::from_millis(hook.timeout_ms))
+            .unwrap_or_else(|_| Err(format!("timed out after {} ms", hook.timeout_ms)));
+
+        for token in tokens {
+            self.capabilities.revoke(token);
+        }
+        let _ = self.kernel.terminate_process(process);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use capability::Permission;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_hook_sandbox() {
+        let kernel = Arc::new(Kernel::new());
+        let capabilities = Arc::new(CapabilityManager::new());
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let record = Arc::clone(&seen);
+        let manager = Arc::clone(&capabilities);
+        let runner = HookRunner::new(
+            Arc::clone(&kernel),
+            Arc::clone(&capabilities),
+            Arc::new(move |invocation: &HookInvocation| {
+                record.lock().unwrap().push(invocation.clone());
+                if invocation.command.ends_with("hang") {
+                    thread::sleep(Duration::from_millis(200));
+                }
+                let writable = invocation.capabilities.iter().any(|t| manager.check_permission(*t, Permission::Write));
+                Ok(format!("writable: {}", writable))
+            }),
+        );
+        let hook: Hook = serde_json::from_str(
+            r#"{"stage":"post_install","command":"bin/setup",
+                "capabilities":[{"resource":{"File":"/apps/viewer"},"permission":"ReadWrite"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(hook.timeout_ms, 30_000);
+        let mut viewer = Package::new(PackageId::from("viewer"), "Viewer".to_string(), crate::Version::new(1, 0, 0), String::new());
+        assert!(runner.run(&viewer, Path::new("/apps/viewer"), &hook).unwrap_err().contains("not granted"));
+        assert!(seen.lock().unwrap().is_empty());
+        viewer.capabilities = hook.capabilities.clone();
+        assert_eq!(runner.run(&viewer, Path::new("/apps/viewer"), &hook).unwrap(), "writable: true");
+
+        let invocation = seen.lock().unwrap()[0].clone();
+        assert_eq!(invocation.command, Path::new("/apps/viewer/bin/setup"));
+        assert_eq!(kernel.get_process(invocation.process).unwrap().name, "viewer-post-install");
+        assert_eq!(kernel.get_process(invocation.process).unwrap().state, kernel::ProcessState::Terminated);
+        assert!(capabilities.validate(invocation.capabilities[0]).is_none());
+
+        let hanging = Hook {
+            stage: HookStage::PreRemove,
+            command: PathBuf::from("hang"),
+            capabilities: Vec::new(),
+            timeout_ms: 20,
+        };
+        assert_eq!(runner.run(&viewer, Path::new("/apps/viewer"), &hanging), Err("timed out after 20 ms".to_string()));
+        let escaping = Hook {
+            command: PathBuf::from("../other/bin"),
+            ..hanging
+        };
+        assert!(runner.run(&viewer, Path::new("/apps/viewer"), &escaping).is_err());
+        assert_eq!(seen.lock().unwrap().len(), 2);
+    }
+}
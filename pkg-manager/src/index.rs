@@ -0,0 +1,274 @@
+//! Repository indices
+//!
+//! A repository publishes a signed JSON index at `<url>/index.json` with a
+//! manifest for every package version it offers, including the digest of
+//! the package archive. Refreshing fetches each index, checks it against
+//! the repository's signing key and caches it in the VFS, so the package
+//! list survives restarts and works offline.
+//!
+//! Each index names when it was generated and when it expires. An expired
+//! index is stale: the cached copy is still used but the user is told to
+//! refresh, while a freshly fetched index that has already expired, or is
+//! older than the cached one, is refused so a mirror cannot hold back
+//! updates by replaying old indices.
+//!
+//! ```json
+//! {"index": {"generated_at": 1767225600, "expires_at": 1767830400, "packages": [
+//!    {"id": "web-browser", "name": "Web Browser", "version": "2.1.5",
+//!     "description": "Modern web browser", "size": 48000000,
+//!     "dependencies": {"libssl": "^3"}, "digest": "9f2c..."}]},
+//!  "signature": [...]}
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use filesystem::VirtualFileSystem;
+use keystore::{KeyId, Keystore};
+use serde::{Deserialize, Serialize};
+use system_utils::{encoding, hash};
+
+use crate::hooks::Hook;
+use crate::permissions::CapabilityGrant;
+use crate::semver::VersionReq;
+use crate::{Dependency, Package, PackageId, Version};
+
+/// Configured repositories
+pub const REPOSITORIES_FILE: &str = "/etc/pkg/repositories.json";
+
+/// Directory holding the last good index of each repository
+pub const INDEX_CACHE_DIR: &str = "/var/cache/pkg/indices";
+
+/// Downloads the resource at a URL
+pub type Fetcher = Arc<dyn Fn(&str) -> Result<Vec<u8>, String> + Send + Sync>;
+
+/// Seconds since the Unix epoch
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// SHA-256 of a package archive
+pub fn archive_digest(data: &[u8]) -> String {
+    encoding::hex_encode(&hash::sha256(data))
+}
+
+/// One package version as listed in an index
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackageManifest {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub author: String,
+    /// Installed size in bytes
+    #[serde(default)]
+    pub size: u64,
+    /// Version requirement of each dependency, by package ID
+    #[serde(default)]
+    pub dependencies: BTreeMap<String, String>,
+    /// Hex-encoded `archive_digest` of the package archive; package files
+    /// carry their archive and leave it out
+    #[serde(default)]
+    pub digest: String,
+    #[serde(default)]
+    pub hooks: Vec<Hook>,
+    /// What the app may access once launched
+    #[serde(default)]
+    pub capabilities: Vec<CapabilityGrant>,
+    /// What changed in this version
+    #[serde(default)]
+    pub changelog: String,
+    /// Versions the repository has a delta from, published at
+    /// `<url>/deltas/<id>-<from>-<version>.delta`
+    #[serde(default)]
+    pub deltas: Vec<String>,
+}
+
+impl PackageManifest {
+    pub fn to_package(&self) -> Result<Package, String> {
+        let invalid = |e: String| format!("{} {}: {}", self.id, self.version, e);
+        let mut package = Package::new(
+            PackageId::from(self.id.as_str()),
+            self.name.clone(),
+            Version::parse(&self.version).map_err(invalid)?,
+            self.description.clone(),
+        );
+        package.author = self.author.clone();
+        package.size = self.size;
+        package.digest = self.digest.clone();
+        package.hooks = self.hooks.clone();
+        for capability in &self.capabilities {
+            capability.validate().map_err(invalid)?;
+        }
+        for hook in &self.hooks {
+            hook.check_capabilities(&self.capabilities).map_err(invalid)?;
+        }
+        package.capabilities = self.capabilities.clone();
+        package.changelog = self.changelog.clone();
+        for from in &self.deltas {
+            package.deltas.insert(Version::parse(from).map_err(invalid)?, String::new());
+        }
+        for (id, req) in &self.dependencies {
+            let req = VersionReq::parse(req).map_err(invalid)?;
+            package.dependencies.push(Dependency::new(PackageId::from(id.as_str()), req));
+        }
+        Ok(package)
+    }
+}
+
+/// Everything a repository offers
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepositoryIndex {
+    /// Seconds since the Unix epoch
+    pub generated_at: u64,
+    /// Past this time the index may be missing security updates
+    pub expires_at: u64,
+    pub packages: Vec<PackageManifest>,
+}
+
+impl RepositoryIndex {
+    pub fn is_stale(&self, now: u64) -> bool {
+        now >= self.expires_at
+    }
+
+    pub fn packages(&self) -> Result<Vec<Package>, String> {
+        self.packages.iter().map(PackageManifest::to_package).collect()
+    }
+
+    /// Bytes the repository signs
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+}
+
+/// Index with the repository's signature
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedIndex {
+    pub index: RepositoryIndex,
+    pub signature: Vec<u8>,
+}
+
+impl SignedIndex {
+    /// Check the signature against a keystore verification key
+    pub fn verify(&self, keystore: &Keystore, key_id: &KeyId) -> Result<(), String> {
+        if keystore.verify(key_id, &self.index.signed_bytes(), &self.signature)? {
+            Ok(())
+        } else {
+            Err("Invalid index signature".to_string())
+        }
+    }
+
+    pub fn from_json(data: &[u8]) -> Result<Self, String> {
+        serde_json::from_slice(data).map_err(|e| format!("Invalid repository index: {}", e))
+    }
+
+    pub fn to_json(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+}
+
+/// A repository the user added
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepositoryConfig {
+    pub name: String,
+    /// Base URL; the index is at `<url>/index.json`
+    pub url: String,
+    /// Keystore key the index must be signed with
+    pub key: String,
+    /// Higher wins when repositories publish the same version
+    #[serde(default)]
+    pub priority: i32,
+    /// Base URLs serving the same files, tried in order when the primary
+    /// URL fails
+    #[serde(default)]
+    pub mirrors: Vec<String>,
+}
+
+impl RepositoryConfig {
+    pub fn index_url(&self) -> String {
+        format!("{}/index.json", self.url.trim_end_matches('/'))
+    }
+
+    pub fn cache_path(&self) -> PathBuf {
+        Path::new(INDEX_CACHE_DIR).join(format!("{}.json", self.name))
+    }
+
+    /// `url` and the same file on each mirror, or `None` when `url` is not
+    /// under this repository
+    pub fn mirror_urls(&self, url: &str) -> Option<Vec<String>> {
+        let base = self.url.trim_end_matches('/');
+        let path = url.strip_prefix(base).filter(|path| path.starts_with('/'))?;
+        let mirrors = self.mirrors.iter().map(|mirror| format!("{}{}", mirror.trim_end_matches('/'), path));
+        Some(std::iter::once(url.to_string()).chain(mirrors).collect())
+    }
+}
+
+pub fn load_configs(vfs: &VirtualFileSystem) -> Result<Vec<RepositoryConfig>, String> {
+    let path = Path::new(REPOSITORIES_FILE);
+    if !vfs.exists(path) {
+        return Ok(Vec::new());
+    }
+    serde_json::from_slice(&vfs.read_file(path)?).map_err(|e| format!("Invalid repository list: {}", e))
+}
+
+pub fn save_configs(vfs: &VirtualFileSystem, configs: &[RepositoryConfig]) -> Result<(), String> {
+    vfs.write_file(Path::new(REPOSITORIES_FILE), &serde_json::to_vec_pretty(configs).unwrap()).map_err(String::from)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keystore::{KeyType, KeyUsage};
+
+    #[test]
+    fn test_signed_index() {
+        let keystore = Keystore::new();
+        let key = keystore
+            .generate_key(KeyId::from("repo"), KeyType::ECC256, vec![KeyUsage::Sign, KeyUsage::Verify], false)
+            .unwrap();
+        let data = br#"{"generated_at":100,"expires_at":200,"packages":[{"id":"viewer","name":"Viewer",
+            "version":"1.2.0","dependencies":{"codec":">=2, <3"},"digest":"ab"}]}"#;
+        let index: RepositoryIndex = serde_json::from_slice(data).unwrap();
+        let packages = index.packages().unwrap();
+        assert_eq!(packages[0].version, Version::new(1, 2, 0));
+        assert_eq!(packages[0].dependencies[0].to_string(), "codec >=2, <3");
+        assert!(!index.is_stale(199) && index.is_stale(200));
+
+        let signature = keystore.sign(&key, &index.signed_bytes()).unwrap();
+        let signed = SignedIndex { index, signature };
+        let parsed = SignedIndex::from_json(&signed.to_json()).unwrap();
+        assert!(parsed.verify(&keystore, &key).is_ok());
+        let mut tampered = parsed.clone();
+        tampered.index.packages[0].digest = archive_digest(b"something else");
+        assert!(tampered.verify(&keystore, &key).is_err());
+        tampered.index.packages[0].version = "1.two".to_string();
+        assert!(tampered.index.packages().is_err());
+    }
+
+    #[test]
+    fn test_index_signed_without_repository_key() {
+        let keystore = Keystore::new();
+        let key = keystore
+            .generate_key(KeyId::from("repo"), KeyType::ECC256, vec![KeyUsage::Sign, KeyUsage::Verify], false)
+            .unwrap();
+        let index = RepositoryIndex { generated_at: 100, expires_at: 200, packages: Vec::new() };
+
+        // A keystore holding a different key under the same name
+        let forger = Keystore::new();
+        forger
+            .generate_key(key.clone(), KeyType::ECC256, vec![KeyUsage::Sign, KeyUsage::Verify], false)
+            .unwrap();
+        for signature in [index.signed_bytes(), forger.sign(&key, &index.signed_bytes()).unwrap()] {
+            let forged = SignedIndex::from_json(&SignedIndex { index: index.clone(), signature }.to_json()).unwrap();
+            assert_eq!(forged.verify(&keystore, &key), Err("Invalid index signature".to_string()));
+        }
+
+        let signature = keystore.sign(&key, &index.signed_bytes()).unwrap();
+        assert!(SignedIndex { index, signature }.verify(&keystore, &key).is_ok());
+    }
+}
@@ -0,0 +1,104 @@
+//! Local package files
+//!
+//! A `.hpkg` file bundles a package's manifest with its archive, so a
+//! developer can install or inspect a package before publishing it to a
+//! repository. Its dependencies still come from the repositories.
+//!
+//! ```json
+//! {"manifest": {"id": "viewer", "name": "Viewer", "version": "0.3.0",
+//!               "dependencies": {"codec": "^1"}},
+//!  "archive": {"entries": [{"path": "bin/viewer", "kind": "file", "mode": 493}]}}
+//! ```
+
+use std::path::{Component, Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::index::{archive_digest, PackageManifest};
+use crate::payload::PackageArchive;
+use crate::permissions::CapabilityGrant;
+use crate::Package;
+
+/// Extension of package files
+pub const PACKAGE_FILE_EXTENSION: &str = "hpkg";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackageFile {
+    pub manifest: PackageManifest,
+    pub archive: PackageArchive,
+}
+
+impl PackageFile {
+    pub fn from_json(data: &[u8]) -> Result<Self, String> {
+        let file: PackageFile = serde_json::from_slice(data).map_err(|e| format!("Invalid package file: {}", e))?;
+        file.archive.validate()?;
+        file.manifest.to_package()?;
+        Ok(file)
+    }
+
+    /// The package, its digest taken from the bundled archive
+    pub fn package(&self) -> Package {
+        let mut package = self.manifest.to_package().unwrap();
+        package.digest = archive_digest(&serde_json::to_vec(&self.archive).unwrap());
+        package
+    }
+
+    /// Capabilities the package's hooks ask for
+    pub fn capabilities(&self) -> Vec<&CapabilityGrant> {
+        self.manifest.hooks.iter().flat_map(|h| &h.capabilities).collect()
+    }
+}
+
+/// Whether a command-line argument names a package file rather than a
+/// package ID
+pub fn is_package_file(arg: &str) -> bool {
+    Path::new(arg).extension().is_some_and(|e| e == PACKAGE_FILE_EXTENSION)
+}
+
+/// VFS path of a package file argument; relative paths start at the root
+pub fn resolve_path(arg: &str) -> PathBuf {
+    let mut path = PathBuf::from("/");
+    for component in Path::new(arg).components() {
+        match component {
+            Component::Normal(name) => path.push(name),
+            Component::ParentDir => {
+                path.pop();
+            }
+            _ => {}
+        }
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Version;
+
+    #[test]
+    fn test_package_file() {
+        let data = br#"{"manifest":{"id":"viewer","name":"Viewer","version":"0.3.0","dependencies":{"codec":"^1"},
+            "capabilities":[{"resource":{"Device":"gpu"},"permission":"ReadWrite"}],
+            "hooks":[{"stage":"post_install","command":"bin/setup","capabilities":[{"resource":{"Device":"gpu"},"permission":"Read"}]}]},
+            "archive":{"entries":[{"path":"bin/viewer","kind":"file","mode":493}]}}"#;
+        let file = PackageFile::from_json(data).unwrap();
+        let package = file.package();
+        assert_eq!(package.version, Version::new(0, 3, 0));
+        assert!(package.verify_archive(&serde_json::to_vec(&file.archive).unwrap()).is_ok());
+        assert_eq!(file.capabilities().len(), 1);
+
+        let escaping = br#"{"manifest":{"id":"x","name":"X","version":"1.0.0"},"archive":{"entries":[{"path":"../x","kind":"file"}]}}"#;
+        assert!(PackageFile::from_json(escaping).is_err());
+        // Hooks get nothing beyond what the package itself is granted
+        let overreaching = br#"{"manifest":{"id":"x","name":"X","version":"1.0.0",
+            "hooks":[{"stage":"post_install","command":"bin/setup","capabilities":[{"resource":{"Device":"gpu"},"permission":"Read"}]}]},
+            "archive":{"entries":[]}}"#;
+        let error = PackageFile::from_json(overreaching).unwrap_err();
+        assert_eq!(error, "x 1.0.0: post-install hook bin/setup asks to read the gpu device, which the package was not granted");
+        assert!(PackageFile::from_json(br#"{"manifest":{"id":"x","name":"X","version":"1"},"archive":{"entries":[]}}"#).is_err());
+
+        assert!(is_package_file("./viewer.hpkg") && !is_package_file("viewer"));
+        assert_eq!(resolve_path("./build/../viewer.hpkg"), Path::new("/viewer.hpkg"));
+        assert_eq!(resolve_path("/home/dev/viewer.hpkg"), Path::new("/home/dev/viewer.hpkg"));
+    }
+}
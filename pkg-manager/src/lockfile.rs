@@ -0,0 +1,123 @@
+//! Lockfile
+//!
+//! The lockfile records the exact version and archive digest of every
+//! installed package. `install --locked` resolves against it and refuses
+//! any package it does not list, or lists at another version or digest,
+//! so a repository that moved on cannot change what gets installed.
+//! Exporting copies the lockfile elsewhere; importing one installs the
+//! package set it describes.
+//!
+//! ```json
+//! {"packages": {"codec": {"version": "1.4.0", "digest": "9f2c..."}}}
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use filesystem::VirtualFileSystem;
+use serde::{Deserialize, Serialize};
+
+use crate::{Package, PackageId, Version};
+
+/// Lockfile of the installed packages
+pub const LOCKFILE: &str = "/var/lib/pkg/lock.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockedPackage {
+    pub version: String,
+    /// Archive digest; empty for packages without an archive
+    #[serde(default)]
+    pub digest: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lockfile {
+    /// By package ID
+    pub packages: BTreeMap<String, LockedPackage>,
+}
+
+impl Lockfile {
+    pub fn from_json(data: &[u8]) -> Result<Self, String> {
+        let lockfile: Lockfile = serde_json::from_slice(data).map_err(|e| format!("Invalid lockfile: {}", e))?;
+        for (id, locked) in &lockfile.packages {
+            Version::parse(&locked.version).map_err(|e| format!("Invalid lockfile: {}: {}", id, e))?;
+        }
+        Ok(lockfile)
+    }
+
+    pub fn to_json(&self) -> Vec<u8> {
+        serde_json::to_vec_pretty(self).unwrap()
+    }
+
+    pub fn load(vfs: &VirtualFileSystem) -> Result<Self, String> {
+        let path = Path::new(LOCKFILE);
+        if !vfs.exists(path) {
+            return Ok(Lockfile::default());
+        }
+        Lockfile::from_json(&vfs.read_file(path)?)
+    }
+
+    pub fn save(&self, vfs: &VirtualFileSystem) -> Result<(), String> {
+        vfs.write_file(Path::new(LOCKFILE), &self.to_json()).map_err(String::from)
+    }
+
+    /// Locked packages with their versions
+    pub fn versions(&self) -> Vec<(PackageId, Version)> {
+        self.packages
+            .iter()
+            .map(|(id, locked)| (PackageId::from(id.as_str()), Version::parse(&locked.version).unwrap()))
+            .collect()
+    }
+
+    pub fn record(&mut self, package: &Package) {
+        let locked = LockedPackage {
+            version: package.version.to_string(),
+            digest: package.digest.clone(),
+        };
+        self.packages.insert(package.id.to_string(), locked);
+    }
+
+    pub fn remove(&mut self, id: &PackageId) {
+        self.packages.remove(&id.to_string());
+    }
+
+    /// Refuse a package that differs from what the lockfile records
+    pub fn check(&self, package: &Package) -> Result<(), String> {
+        let Some(locked) = self.packages.get(&package.id.to_string()) else {
+            return Err(format!("{} is not in the lockfile", package.id));
+        };
+        if locked.version != package.version.to_string() {
+            return Err(format!("{} is locked at {}, not {}", package.id, locked.version, package.version));
+        }
+        if locked.digest != package.digest {
+            return Err(format!("{} {} does not match the digest in the lockfile", package.id, package.version));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lockfile_check() {
+        let vfs = VirtualFileSystem::new();
+        let mut package = Package::new(PackageId::from("codec"), "Codec".to_string(), Version::new(1, 4, 0), String::new());
+        package.digest = "ab".to_string();
+        let mut lockfile = Lockfile::default();
+        assert!(lockfile.check(&package).unwrap_err().contains("not in the lockfile"));
+        lockfile.record(&package);
+        lockfile.save(&vfs).unwrap();
+        let loaded = Lockfile::load(&vfs).unwrap();
+        assert_eq!(loaded, lockfile);
+        assert!(loaded.check(&package).is_ok());
+        assert_eq!(loaded.versions(), vec![(PackageId::from("codec"), Version::new(1, 4, 0))]);
+
+        package.digest = "cd".to_string();
+        assert!(loaded.check(&package).unwrap_err().contains("digest"));
+        package.version = Version::new(1, 5, 0);
+        assert_eq!(loaded.check(&package), Err("codec is locked at 1.4.0, not 1.5.0".to_string()));
+        assert!(Lockfile::from_json(br#"{"packages":{"codec":{"version":"one"}}}"#).is_err());
+    }
+}
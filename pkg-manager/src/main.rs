@@ -3,8 +3,48 @@
 //! Native package management system for installing, updating, and managing
 //! applications and system components on hairr OS.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use capability::{Permission, Resource};
+use filesystem::VirtualFileSystem;
+use i18n::{Locale, Localizer};
+use kernel::SandboxProfile;
+use keystore::{KeyId, Keystore};
+use system_utils::cli::{Cli, CliError, Command};
+use system_utils::logging::{LogEntry, Logger};
+use system_utils::string;
+use system_utils::time::format_rfc3339;
+
+mod delta;
+mod hooks;
+mod index;
+mod local;
+mod lockfile;
+mod payload;
+mod permissions;
+mod pins;
+mod resolver;
+mod sandbox;
+mod semver;
+mod service;
+mod updates;
+
+use delta::PackageDelta;
+use hooks::{Hook, HookRunner, HookStage};
+use index::{archive_digest, Fetcher, RepositoryConfig, RepositoryIndex, SignedIndex};
+use local::PackageFile;
+use lockfile::Lockfile;
+use payload::{install_root, remove_paths, FileDatabase, PackageArchive, TreeBackup};
+use permissions::{CapabilityDatabase, CapabilityGrant};
+use pins::{Pin, PinDatabase};
+use resolver::Resolver;
+use semver::VersionReq;
+use service::{EventListener, PackageEvent};
+use updates::UpdateInfo;
 
 /// Package identifier
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -16,6 +56,12 @@ impl PackageId {
     }
 }
 
+impl fmt::Display for PackageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
 impl From<String> for PackageId {
     fn from(id: String) -> Self {
         PackageId(id)
@@ -29,7 +75,7 @@ impl From<&str> for PackageId {
 }
 
 /// Package version
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Version {
     pub major: u32,
     pub minor: u32,
@@ -61,6 +107,25 @@ impl std::fmt::Display for Version {
     }
 }
 
+/// A package another package needs, with the versions it works with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    pub id: PackageId,
+    pub req: VersionReq,
+}
+
+impl Dependency {
+    pub fn new(id: PackageId, req: VersionReq) -> Self {
+        Dependency { id, req }
+    }
+}
+
+impl fmt::Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.id, self.req)
+    }
+}
+
 /// Package metadata
 #[derive(Debug, Clone)]
 pub struct Package {
@@ -69,9 +134,23 @@ pub struct Package {
     pub version: Version,
     pub description: String,
     pub author: String,
-    pub dependencies: Vec<PackageId>,
+    pub dependencies: Vec<Dependency>,
     pub installed: bool,
     pub size: u64,
+    /// Hex digest of the package archive; empty for built-in packages
+    pub digest: String,
+    /// Where the archive is downloaded from; built-in packages have none
+    pub archive_url: Option<String>,
+    pub hooks: Vec<Hook>,
+    /// What the app may access once launched
+    pub capabilities: Vec<CapabilityGrant>,
+    /// What changed in this version
+    pub changelog: String,
+    /// Where to download a delta to this version, by the version it
+    /// starts from
+    pub deltas: BTreeMap<Version, String>,
+    /// Name of the repository publishing it
+    pub repository: String,
 }
 
 impl Package {
@@ -85,35 +164,110 @@ impl Package {
             dependencies: Vec::new(),
             installed: false,
             size: 0,
+            digest: String::new(),
+            archive_url: None,
+            hooks: Vec::new(),
+            capabilities: Vec::new(),
+            changelog: String::new(),
+            deltas: BTreeMap::new(),
+            repository: String::new(),
+        }
+    }
+
+    /// Check a downloaded archive against the digest from the index
+    pub fn verify_archive(&self, data: &[u8]) -> Result<(), String> {
+        if archive_digest(data) == self.digest {
+            Ok(())
+        } else {
+            Err(format!("Archive of {} {} does not match its digest", self.id, self.version))
         }
     }
 }
 
 /// Package repository
 pub struct Repository {
+    name: String,
     url: String,
-    packages: HashMap<PackageId, Package>,
+    /// Every published version of each package
+    packages: HashMap<PackageId, BTreeMap<Version, Package>>,
+    /// Index the packages came from; `None` for built-in repositories
+    index: Option<RepositoryIndex>,
+    /// Higher wins when repositories publish the same version
+    priority: i32,
 }
 
 impl Repository {
     pub fn new(url: String) -> Self {
         Repository {
+            name: url.clone(),
             url,
             packages: HashMap::new(),
+            index: None,
+            priority: 0,
+        }
+    }
+
+    /// Repository holding the packages of a verified index
+    pub fn from_index(config: &RepositoryConfig, index: RepositoryIndex) -> Result<Self, String> {
+        let mut repository = Repository::new(config.url.clone());
+        repository.name = config.name.clone();
+        repository.priority = config.priority;
+        let base = config.url.trim_end_matches('/');
+        for mut package in index.packages()? {
+            package.archive_url = Some(format!("{}/packages/{}-{}.pkg", base, package.id, package.version));
+            for (from, url) in package.deltas.iter_mut() {
+                *url = format!("{}/deltas/{}-{}-{}.delta", base, package.id, from, package.version);
+            }
+            repository.add_package(package);
         }
+        repository.index = Some(index);
+        Ok(repository)
     }
 
-    pub fn add_package(&mut self, package: Package) {
-        self.packages.insert(package.id.clone(), package);
+    pub fn name(&self) -> &str {
+        &self.name
     }
 
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn index(&self) -> Option<&RepositoryIndex> {
+        self.index.as_ref()
+    }
+
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// Publish a package version, replacing the same version if present
+    pub fn add_package(&mut self, mut package: Package) {
+        package.repository = self.name.clone();
+        self.packages
+            .entry(package.id.clone())
+            .or_default()
+            .insert(package.version.clone(), package);
+    }
+
+    /// Every published version of a package, oldest first
+    pub fn versions(&self, id: &PackageId) -> impl Iterator<Item = &Package> {
+        self.packages.get(id).into_iter().flat_map(|versions| versions.values())
+    }
+
+    /// Newest version of a package
     pub fn find_package(&self, id: &PackageId) -> Option<&Package> {
-        self.packages.get(id)
+        self.packages.get(id).and_then(|versions| versions.values().next_back())
+    }
+
+    /// Every version of every package
+    pub fn packages(&self) -> impl Iterator<Item = &Package> {
+        self.packages.values().flat_map(|versions| versions.values())
     }
 
     pub fn search(&self, query: &str) -> Vec<&Package> {
         self.packages
             .values()
+            .filter_map(|versions| versions.values().next_back())
             .filter(|p| p.name.contains(query) || p.description.contains(query))
             .collect()
     }
@@ -121,8 +275,30 @@ impl Repository {
 
 /// Package manager
 pub struct PackageManager {
+    /// The built-in repository, then the configured ones that have an index
     repositories: Vec<Repository>,
     installed_packages: HashMap<PackageId, Package>,
+    /// Repositories the user added, in the order they were added
+    sources: Vec<RepositoryConfig>,
+    /// Keeps the repository list and cached indices
+    vfs: Option<Arc<VirtualFileSystem>>,
+    /// Holds the keys repository indices are signed with
+    keystore: Arc<Keystore>,
+    fetcher: Option<Fetcher>,
+    /// Package owning each extracted path
+    files: FileDatabase,
+    /// Exact versions `install --locked` and `import` hold to
+    lock: Lockfile,
+    /// Versions the user keeps packages to
+    pins: PinDatabase,
+    /// What each installed app is granted at launch
+    capabilities: CapabilityDatabase,
+    /// Runs package hooks; without one hooks are skipped
+    hook_runner: Option<HookRunner>,
+    /// Hook output and failures
+    log: Arc<Logger>,
+    /// Told about each step of a transaction
+    listeners: Vec<EventListener>,
 }
 
 impl PackageManager {
@@ -130,6 +306,17 @@ impl PackageManager {
         let mut manager = PackageManager {
             repositories: Vec::new(),
             installed_packages: HashMap::new(),
+            sources: Vec::new(),
+            vfs: None,
+            keystore: Arc::new(Keystore::new()),
+            fetcher: None,
+            files: FileDatabase::default(),
+            lock: Lockfile::default(),
+            pins: PinDatabase::default(),
+            capabilities: CapabilityDatabase::default(),
+            hook_runner: None,
+            log: Arc::new(Logger::default()),
+            listeners: Vec::new(),
         };
 
         // Initialize with default repository
@@ -168,39 +355,484 @@ impl PackageManager {
         manager
     }
 
-    /// Install a package
+    /// Keep repositories in `vfs`, loading the configured ones and their
+    /// cached indices. A cached index that no longer verifies is left out
+    /// until the next refresh.
+    pub fn attach_storage(&mut self, vfs: Arc<VirtualFileSystem>) -> Result<(), String> {
+        self.sources = index::load_configs(&vfs)?;
+        self.files = FileDatabase::load(&vfs)?;
+        self.lock = Lockfile::load(&vfs)?;
+        self.pins = PinDatabase::load(&vfs)?;
+        self.capabilities = CapabilityDatabase::load(&vfs)?;
+        self.repositories.retain(|r| r.index.is_none());
+        for config in &self.sources {
+            let path = config.cache_path();
+            if !vfs.exists(&path) {
+                continue;
+            }
+            let cached = vfs.read_file(&path).map_err(String::from).and_then(|data| SignedIndex::from_json(&data));
+            if let Ok(signed) = cached {
+                if signed.verify(&self.keystore, &KeyId::from(config.key.as_str())).is_ok() {
+                    self.repositories.push(Repository::from_index(config, signed.index)?);
+                }
+            }
+        }
+        self.vfs = Some(vfs);
+        Ok(())
+    }
+
+    /// Run package hooks through `runner`
+    pub fn set_hook_runner(&mut self, runner: HookRunner) {
+        self.hook_runner = Some(runner);
+    }
+
+    pub fn log(&self) -> Vec<LogEntry> {
+        self.log.get_entries()
+    }
+
+    /// Run a package's hooks for `stage`, logging their output
+    fn run_hooks(&self, package: &Package, stage: HookStage) -> Result<(), String> {
+        for hook in package.hooks.iter().filter(|h| h.stage == stage) {
+            let Some(runner) = &self.hook_runner else {
+                self.log.warning("pkg", &format!("{} {} hook skipped: hooks are disabled", package.id, stage));
+                continue;
+            };
+            match runner.run(package, &install_root(&package.id), hook) {
+                Ok(output) => {
+                    for line in output.lines() {
+                        self.log.info("pkg", &format!("{} {}: {}", package.id, stage, line));
+                    }
+                }
+                Err(e) => {
+                    let message = format!("{} {} hook failed: {}", package.id, stage, e);
+                    self.log.error("pkg", &message);
+                    return Err(message);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check repository indices with keys from `keystore`
+    pub fn set_keystore(&mut self, keystore: Arc<Keystore>) {
+        self.keystore = keystore;
+    }
+
+    /// Download repository indices through `fetcher`
+    pub fn set_fetcher(&mut self, fetcher: Fetcher) {
+        self.fetcher = Some(fetcher);
+    }
+
+    pub fn sources(&self) -> &[RepositoryConfig] {
+        &self.sources
+    }
+
+    /// Add a repository whose index is signed with the keystore key `key`;
+    /// its packages become available after the next refresh
+    pub fn add_repository(&mut self, name: &str, url: &str, key: &str) -> Result<(), String> {
+        if name.is_empty() || name.contains('/') {
+            return Err("Invalid repository name".to_string());
+        }
+        if self.sources.iter().any(|s| s.name == name) {
+            return Err(format!("Repository {} already exists", name));
+        }
+        if !url.starts_with("https://") {
+            return Err("Only https:// repositories are supported".to_string());
+        }
+        if self.keystore.get_key(&KeyId::from(key)).is_none() {
+            return Err(format!("Key {} not found", key));
+        }
+        self.sources.push(RepositoryConfig {
+            name: name.to_string(),
+            url: url.to_string(),
+            key: key.to_string(),
+            priority: 0,
+            mirrors: Vec::new(),
+        });
+        self.save_sources()
+    }
+
+    /// Change which repository wins when several publish the same version
+    pub fn set_priority(&mut self, name: &str, priority: i32) -> Result<(), String> {
+        self.source_mut(name)?.priority = priority;
+        for repository in self.repositories.iter_mut().filter(|r| r.index.is_some() && r.name == name) {
+            repository.priority = priority;
+        }
+        self.save_sources()
+    }
+
+    /// Add a URL serving the same files as a repository, tried when the
+    /// repository's own URL fails
+    pub fn add_mirror(&mut self, name: &str, url: &str) -> Result<(), String> {
+        if !url.starts_with("https://") {
+            return Err("Only https:// mirrors are supported".to_string());
+        }
+        let source = self.source_mut(name)?;
+        if source.url == url || source.mirrors.iter().any(|m| m == url) {
+            return Err(format!("{} is already a mirror of {}", url, name));
+        }
+        source.mirrors.push(url.to_string());
+        self.save_sources()
+    }
+
+    pub fn remove_mirror(&mut self, name: &str, url: &str) -> Result<(), String> {
+        let source = self.source_mut(name)?;
+        let position = source
+            .mirrors
+            .iter()
+            .position(|m| m == url)
+            .ok_or_else(|| format!("{} is not a mirror of {}", url, name))?;
+        source.mirrors.remove(position);
+        self.save_sources()
+    }
+
+    fn source_mut(&mut self, name: &str) -> Result<&mut RepositoryConfig, String> {
+        self.sources
+            .iter_mut()
+            .find(|s| s.name == name)
+            .ok_or_else(|| format!("Repository {} not found", name))
+    }
+
+    /// Remove a repository and its cached index; installed packages stay
+    pub fn remove_repository(&mut self, name: &str) -> Result<(), String> {
+        let position = self
+            .sources
+            .iter()
+            .position(|s| s.name == name)
+            .ok_or_else(|| format!("Repository {} not found", name))?;
+        let config = self.sources.remove(position);
+        self.repositories.retain(|r| r.index.is_none() || r.name != name);
+        if let Some(vfs) = &self.vfs {
+            if vfs.exists(&config.cache_path()) {
+                vfs.delete(&config.cache_path())?;
+            }
+        }
+        self.save_sources()
+    }
+
+    fn save_sources(&self) -> Result<(), String> {
+        match &self.vfs {
+            Some(vfs) => index::save_configs(vfs, &self.sources),
+            None => Ok(()),
+        }
+    }
+
+    /// Fetch every configured index, reporting each repository's package
+    /// count or why its index was refused
+    pub fn refresh(&mut self, now: u64) -> Vec<(String, Result<usize, String>)> {
+        let names: Vec<String> = self.sources.iter().map(|s| s.name.clone()).collect();
+        names
+            .into_iter()
+            .map(|name| {
+                let result = self.refresh_repository(&name, now);
+                (name, result)
+            })
+            .collect()
+    }
+
+    /// Fetch and verify one repository's index, then cache it; on failure
+    /// the previous index stays in use
+    pub fn refresh_repository(&mut self, name: &str, now: u64) -> Result<usize, String> {
+        let config = self
+            .sources
+            .iter()
+            .find(|s| s.name == name)
+            .cloned()
+            .ok_or_else(|| format!("Repository {} not found", name))?;
+        let fetcher = self.fetcher.clone().ok_or("No network access to fetch repository indices")?;
+        let data = self.fetch(&fetcher, &config.index_url())?;
+        let signed = SignedIndex::from_json(&data)?;
+        signed.verify(&self.keystore, &KeyId::from(config.key.as_str()))?;
+        if signed.index.is_stale(now) {
+            return Err(format!("Index of {} expired at {}", name, format_rfc3339(signed.index.expires_at * 1000)));
+        }
+        let position = self.repositories.iter().position(|r| r.index.is_some() && r.name == name);
+        if let Some(cached) = position.and_then(|p| self.repositories[p].index()) {
+            if signed.index.generated_at < cached.generated_at {
+                return Err(format!("Index of {} is older than the cached one", name));
+            }
+        }
+        let repository = Repository::from_index(&config, signed.index.clone())?;
+        let count = repository.packages().count();
+        if let Some(vfs) = &self.vfs {
+            vfs.write_file(&config.cache_path(), &signed.to_json())?;
+        }
+        // Keep the configured order among fetched repositories
+        self.repositories.retain(|r| r.index.is_none() || r.name != name);
+        let rank = |name: &str| self.sources.iter().position(|s| s.name == name);
+        let at = self
+            .repositories
+            .iter()
+            .position(|r| r.index.is_some() && rank(&r.name) > rank(&config.name))
+            .unwrap_or(self.repositories.len());
+        self.repositories.insert(at, repository);
+        Ok(count)
+    }
+
+    /// Configured repositories whose index has expired or was never fetched
+    pub fn stale_repositories(&self, now: u64) -> Vec<String> {
+        self.sources
+            .iter()
+            .filter(|source| {
+                let repository = self.repositories.iter().find(|r| r.index.is_some() && r.name == source.name);
+                repository.and_then(Repository::index).is_none_or(|index| index.is_stale(now))
+            })
+            .map(|source| source.name.clone())
+            .collect()
+    }
+
+    /// Install the newest version of a package
     pub fn install(&mut self, package_id: &PackageId) -> Result<(), String> {
-        // Check if already installed
+        self.install_matching(package_id, &VersionReq::any())
+    }
+
+    /// Install the newest version matching `req` along with the
+    /// dependencies it needs
+    pub fn install_matching(&mut self, package_id: &PackageId, req: &VersionReq) -> Result<(), String> {
         if self.installed_packages.contains_key(package_id) {
             return Err("Package already installed".to_string());
         }
+        let plan = self
+            .resolver(&self.installed_packages)
+            .resolve(package_id, req)
+            .map_err(|conflict| conflict.to_string())?;
+        self.install_all(plan)
+    }
+
+    /// Install a package file from the VFS along with the dependencies it
+    /// needs from the repositories, replacing any installed version of it
+    pub fn install_file(&mut self, path: &Path) -> Result<PackageId, String> {
+        let file = self.inspect(path)?;
+        let local = file.package();
+        let mut others = self.installed_packages.clone();
+        others.remove(&local.id);
+        let plan = {
+            let mut resolver = self.resolver(&others);
+            resolver.replace(&local);
+            resolver.resolve(&local.id, &VersionReq::any()).map_err(|conflict| conflict.to_string())?
+        };
+        let mut packages = Vec::new();
+        for package in plan {
+            let archive = if package.id == local.id { Some(file.archive.clone()) } else { self.download(&package)? };
+            packages.push((package, archive));
+        }
+        self.place_all(packages)?;
+        Ok(local.id)
+    }
+
+    /// Read a package file from the VFS without installing it
+    pub fn inspect(&self, path: &Path) -> Result<PackageFile, String> {
+        let vfs = self.vfs.as_ref().ok_or("No storage to read package files from")?;
+        PackageFile::from_json(&vfs.read_file(path)?)
+    }
+
+    /// Install a package and whatever it needs at exactly the versions and
+    /// digests in the lockfile, refusing anything else
+    pub fn install_locked(&mut self, package_id: &PackageId, req: &VersionReq) -> Result<(), String> {
+        if self.installed_packages.contains_key(package_id) {
+            return Err("Package already installed".to_string());
+        }
+        let plan = self.locked_plan(&[(package_id.clone(), req.clone())], &self.installed_packages)?;
+        self.install_all(plan)
+    }
+
+    /// Resolve with every locked package pinned to its locked version
+    fn locked_plan(
+        &self,
+        requests: &[(PackageId, VersionReq)],
+        keep: &HashMap<PackageId, Package>,
+    ) -> Result<Vec<Package>, String> {
+        let mut resolver = self.resolver(keep);
+        // An installed version may be what the lockfile asks for
+        for package in self.installed_packages.values() {
+            resolver.offer(package);
+        }
+        for (id, version) in self.lock.versions() {
+            resolver.pin(&id, &version);
+        }
+        let plan = resolver.resolve_all(requests).map_err(|conflict| conflict.to_string())?;
+        for package in &plan {
+            self.lock.check(package)?;
+        }
+        Ok(plan)
+    }
+
+    /// Download and verify every archive, then extract the packages in
+    /// order. A failure leaves the packages before it installed.
+    fn install_all(&mut self, packages: Vec<Package>) -> Result<(), String> {
+        let archives = packages.iter().map(|p| self.download(p)).collect::<Result<Vec<_>, _>>()?;
+        self.place_all(packages.into_iter().zip(archives).collect())
+    }
+
+    /// Extract packages whose archives are at hand, in order
+    fn place_all(&mut self, packages: Vec<(Package, Option<PackageArchive>)>) -> Result<(), String> {
+        let result = packages
+            .into_iter()
+            .try_for_each(|(package, archive)| self.place(package, archive));
+        if let Some(vfs) = &self.vfs {
+            self.files.save(vfs)?;
+            self.lock.save(vfs)?;
+            self.capabilities.save(vfs)?;
+            if result.is_ok() {
+                self.prune_archive_cache(vfs);
+            }
+        }
+        result
+    }
 
-        // Find package in repositories
-        let package = self
-            .find_package_in_repos(package_id)
-            .ok_or("Package not found in any repository")?
-            .clone();
+    /// Install a plan as a whole: if any package fails, the packages, their
+    /// files, capabilities and the lockfile are left as they were
+    fn install_transaction(&mut self, plan: Vec<Package>) -> Result<(), String> {
+        let installed = self.installed_packages.clone();
+        let files = self.files.clone();
+        let lock = self.lock.clone();
+        let capabilities = self.capabilities.clone();
+        let roots: Vec<PathBuf> = plan.iter().map(|p| install_root(&p.id)).collect();
+        let backup = self.vfs.as_ref().map(|vfs| TreeBackup::take(vfs, &roots));
+        let result = self.install_all(plan);
+        if result.is_err() {
+            self.installed_packages = installed;
+            self.files = files;
+            self.lock = lock;
+            self.capabilities = capabilities;
+            if let (Some(vfs), Some(backup)) = (&self.vfs, &backup) {
+                backup.restore(vfs)?;
+                self.files.save(vfs)?;
+                self.lock.save(vfs)?;
+                self.capabilities.save(vfs)?;
+            }
+        }
+        result
+    }
 
-        // Install dependencies first
-        for dep_id in &package.dependencies {
-            if !self.installed_packages.contains_key(dep_id) {
-                self.install(dep_id)?;
+    fn download(&self, package: &Package) -> Result<Option<PackageArchive>, String> {
+        let Some(url) = &package.archive_url else {
+            return Ok(None);
+        };
+        let fetcher = self.fetcher.as_ref().ok_or_else(|| format!("No network access to download {}", package.id))?;
+        self.emit(PackageEvent::Downloading {
+            id: package.id.to_string(),
+            version: package.version.to_string(),
+        });
+        let data = match self.fetch_delta(package, fetcher) {
+            Some(Ok(data)) => {
+                self.log.info("pkg", &format!("{} {} rebuilt from a delta", package.id, package.version));
+                data
+            }
+            Some(Err(e)) => {
+                self.log.warning("pkg", &format!("{} {}: {}, downloading the full archive", package.id, package.version, e));
+                self.fetch(fetcher, url)?
             }
+            None => self.fetch(fetcher, url)?,
+        };
+        package.verify_archive(&data)?;
+        let archive = PackageArchive::from_json(&data)?;
+        // Keep the archive as the base for the next delta
+        if let Some(vfs) = &self.vfs {
+            vfs.write_file(&delta::cache_path(&package.digest), &data)?;
         }
+        Ok(Some(archive))
+    }
 
-        // Install the package
-        let mut installed_package = package;
-        installed_package.installed = true;
-        self.installed_packages.insert(package_id.clone(), installed_package);
+    /// Rebuild an archive from the installed version's with a published
+    /// delta; `None` when there is no delta from the installed version
+    fn fetch_delta(&self, package: &Package, fetcher: &Fetcher) -> Option<Result<Vec<u8>, String>> {
+        let installed = self.installed_packages.get(&package.id)?;
+        let url = package.deltas.get(&installed.version)?;
+        let vfs = self.vfs.as_ref()?;
+        let Ok(base) = vfs.read_file(&delta::cache_path(&installed.digest)) else {
+            return Some(Err("the installed archive is not cached".to_string()));
+        };
+        Some(self.fetch(fetcher, url).and_then(|data| PackageDelta::from_json(&data)).and_then(|delta| delta.apply(&base)))
+    }
 
+    /// Download `url`, falling back to the mirrors of the repository it
+    /// belongs to; the last mirror's error is returned
+    fn fetch(&self, fetcher: &Fetcher, url: &str) -> Result<Vec<u8>, String> {
+        let urls = self.sources.iter().find_map(|s| s.mirror_urls(url)).unwrap_or_else(|| vec![url.to_string()]);
+        let mut error = String::new();
+        for (attempt, url) in urls.iter().enumerate() {
+            match fetcher(url) {
+                Ok(data) => return Ok(data),
+                Err(e) if attempt + 1 < urls.len() => self.log.warning("pkg", &format!("{}: {}, trying a mirror", url, e)),
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+
+    /// Drop cached archives no installed package came from
+    fn prune_archive_cache(&self, vfs: &VirtualFileSystem) {
+        let Ok(cached) = vfs.list_directory(Path::new(delta::ARCHIVE_CACHE_DIR)) else {
+            return;
+        };
+        for path in cached {
+            let in_use = self.installed_packages.values().any(|p| delta::cache_path(&p.digest) == path);
+            if !in_use {
+                let _ = vfs.delete(&path);
+            }
+        }
+    }
+
+    /// Write a delta from one archive in the VFS to another, returning the
+    /// sizes of the delta and of the full archive
+    pub fn make_delta(&self, base: &Path, target: &Path, output: &Path) -> Result<(usize, usize), String> {
+        let vfs = self.vfs.as_ref().ok_or("No storage to read archives from")?;
+        let target = vfs.read_file(target)?;
+        let delta = PackageDelta::generate(&vfs.read_file(base)?, &target).to_json();
+        vfs.write_file(output, &delta)?;
+        Ok((delta.len(), target.len()))
+    }
+
+    /// Extract a package's files, replacing those of the version it updates
+    fn place(&mut self, mut package: Package, archive: Option<PackageArchive>) -> Result<(), String> {
+        self.emit(PackageEvent::Installing {
+            id: package.id.to_string(),
+            version: package.version.to_string(),
+        });
+        if let Some(archive) = archive {
+            let vfs = self.vfs.clone().ok_or_else(|| format!("No storage to install {} into", package.id))?;
+            let paths = archive.extract(&vfs, &install_root(&package.id))?;
+            let replaced: Vec<PathBuf> =
+                self.files.release(&package.id).into_iter().filter(|p| !paths.contains(p)).collect();
+            remove_paths(&vfs, &replaced);
+            self.files.claim(&package.id, &paths);
+        }
+        package.installed = true;
+        self.lock.record(&package);
+        self.capabilities.record(&package);
+        self.installed_packages.insert(package.id.clone(), package.clone());
+        // The files are in place either way; a failure is only logged
+        let _ = self.run_hooks(&package, HookStage::PostInstall);
         Ok(())
     }
 
+    /// Capabilities an installed app is launched with, as recorded when it
+    /// was installed; `None` for apps no package installed
+    pub fn app_capabilities(&self, package_id: &PackageId) -> Option<Vec<(Resource, Permission)>> {
+        self.capabilities.grants(package_id)
+    }
+
+    /// Sandbox an installed app is launched in, built from its capabilities
+    pub fn app_sandbox(&self, package_id: &PackageId) -> Option<SandboxProfile> {
+        self.capabilities.sandbox(package_id)
+    }
+
+    /// Paths a package installed
+    pub fn files_of(&self, package_id: &PackageId) -> Vec<PathBuf> {
+        self.files.paths_of(package_id)
+    }
+
+    /// Package that installed a path
+    pub fn owner_of(&self, path: &Path) -> Option<PackageId> {
+        self.files.owner(path)
+    }
+
     /// Uninstall a package
     pub fn uninstall(&mut self, package_id: &PackageId) -> Result<(), String> {
-        if !self.installed_packages.contains_key(package_id) {
+        let Some(package) = self.installed_packages.get(package_id) else {
             return Err("Package not installed".to_string());
-        }
+        };
 
         // Check for dependent packages
         let dependents = self.find_dependents(package_id);
@@ -211,27 +843,168 @@ impl PackageManager {
             ));
         }
 
+        self.run_hooks(package, HookStage::PreRemove)?;
+        self.emit(PackageEvent::Removing {
+            id: package_id.to_string(),
+        });
         self.installed_packages.remove(package_id);
+        self.lock.remove(package_id);
+        self.capabilities.remove(package_id);
+        let paths = self.files.release(package_id);
+        if let Some(vfs) = &self.vfs {
+            remove_paths(vfs, &paths);
+            self.files.save(vfs)?;
+            self.lock.save(vfs)?;
+            self.capabilities.save(vfs)?;
+            self.prune_archive_cache(vfs);
+        }
         Ok(())
     }
 
-    /// Update a package
+    /// Update a package to the newest version its dependents still work
+    /// with, installing any new dependencies
     pub fn update(&mut self, package_id: &PackageId) -> Result<(), String> {
+        let mut others = self.installed_packages.clone();
+        if others.remove(package_id).is_none() {
+            return Err("Package not installed".to_string());
+        }
+        if self.pins.get(package_id) == Some(&Pin::Hold) {
+            return Err(format!("{} is held", package_id));
+        }
+        let plan = self
+            .resolver(&others)
+            .resolve(package_id, &VersionReq::any())
+            .map_err(|conflict| conflict.to_string())?;
+        self.install_all(plan)
+    }
+
+    /// Resolver over every repository that keeps to the pins and holds
+    fn resolver<'a>(&'a self, installed: &'a HashMap<PackageId, Package>) -> Resolver<'a> {
+        let mut resolver = Resolver::new(&self.repositories, installed);
+        for (id, req) in self.pins.requirements(&self.installed_packages) {
+            resolver.constrain(&id, req);
+        }
+        resolver
+    }
+
+    fn upgrade_plan(&self) -> Result<Vec<Package>, String> {
+        let pins = self.pins.requirements(&self.installed_packages);
+        updates::upgrade_plan(&self.repositories, &self.installed_packages, &pins).map_err(|c| c.to_string())
+    }
+
+    /// Installed packages that `upgrade` would move to a newer version
+    pub fn check_updates(&self) -> Result<Vec<UpdateInfo>, String> {
+        Ok(updates::updates_in(&self.repositories, &self.upgrade_plan()?, &self.installed_packages))
+    }
+
+    /// Newer versions `upgrade` leaves out because of a pin or hold, each
+    /// with the pin responsible
+    pub fn pinned_updates(&self) -> Result<Vec<(UpdateInfo, String)>, String> {
+        let plan = self.upgrade_plan()?;
+        let mut skipped = Vec::new();
+        for (id, pin) in self.pins.iter() {
+            let (Some(current), Some(newest)) = (self.installed_packages.get(&id), self.find_package_in_repos(&id)) else {
+                continue;
+            };
+            let target = plan.iter().find(|p| p.id == id).map_or(&current.version, |p| &p.version);
+            if newest.version > *target {
+                let update = UpdateInfo::new(&self.repositories, current, newest);
+                skipped.push((update, pin.to_string()));
+            }
+        }
+        Ok(skipped)
+    }
+
+    /// Keep a package within `req` from now on
+    pub fn pin(&mut self, package_id: &PackageId, req: &VersionReq) -> Result<(), String> {
+        if let Some(installed) = self.installed_packages.get(package_id) {
+            if !req.matches(&installed.version) {
+                return Err(format!("{} {} is installed, which {} does not allow", package_id, installed.version, req));
+            }
+        }
+        self.pins.set(package_id, Pin::Version(req.to_string()));
+        self.save_pins()
+    }
+
+    /// Keep an installed package at its version from now on
+    pub fn hold(&mut self, package_id: &PackageId) -> Result<(), String> {
         if !self.installed_packages.contains_key(package_id) {
             return Err("Package not installed".to_string());
         }
+        self.pins.set(package_id, Pin::Hold);
+        self.save_pins()
+    }
+
+    /// Remove a package's pin or hold
+    pub fn unpin(&mut self, package_id: &PackageId) -> Result<(), String> {
+        if !self.pins.remove(package_id) {
+            return Err(format!("{} is not pinned", package_id));
+        }
+        self.save_pins()
+    }
+
+    fn save_pins(&self) -> Result<(), String> {
+        match &self.vfs {
+            Some(vfs) => self.pins.save(vfs),
+            None => Ok(()),
+        }
+    }
 
-        // Find latest version in repositories
-        let latest = self
-            .find_package_in_repos(package_id)
-            .ok_or("Package not found in any repository")?
-            .clone();
+    /// Upgrade every package with a newer version. Either the whole plan
+    /// is installed or the packages and their files are left as they were.
+    pub fn upgrade(&mut self) -> Result<Vec<UpdateInfo>, String> {
+        let plan = self.upgrade_plan()?;
+        let upgraded = updates::updates_in(&self.repositories, &plan, &self.installed_packages);
+        self.install_transaction(plan)?;
+        Ok(upgraded)
+    }
 
-        let mut updated_package = latest;
-        updated_package.installed = true;
-        self.installed_packages.insert(package_id.clone(), updated_package);
+    /// Write the lockfile to `path` in the VFS, returning how many
+    /// packages it locks
+    pub fn export(&self, path: &Path) -> Result<usize, String> {
+        let vfs = self.vfs.as_ref().ok_or("No storage to export to")?;
+        vfs.write_file(path, &self.lock.to_json())?;
+        Ok(self.lock.packages.len())
+    }
 
-        Ok(())
+    /// Install the package set of an exported lockfile, moving installed
+    /// packages to the locked versions, and return how many packages
+    /// changed. Packages the lockfile does not mention stay installed.
+    pub fn import(&mut self, path: &Path) -> Result<usize, String> {
+        let vfs = self.vfs.clone().ok_or("No storage to import from")?;
+        let imported = Lockfile::from_json(&vfs.read_file(path)?)?;
+        let mut keep = self.installed_packages.clone();
+        let mut requests = Vec::new();
+        for (id, _) in imported.versions() {
+            keep.remove(&id);
+            requests.push((id, VersionReq::any()));
+        }
+
+        let previous = std::mem::replace(&mut self.lock, imported);
+        let plan = self.locked_plan(&requests, &keep).map(|plan| {
+            plan.into_iter()
+                .filter(|p| self.installed_packages.get(&p.id).is_none_or(|current| current.version != p.version))
+                .collect::<Vec<_>>()
+        });
+        let plan = match plan {
+            Ok(plan) => plan,
+            Err(e) => {
+                self.lock = previous;
+                return Err(e);
+            }
+        };
+        // Packages kept from before stay locked too
+        for package in keep.values() {
+            self.lock.record(package);
+        }
+        let count = plan.len();
+        if let Err(e) = self.install_transaction(plan) {
+            self.lock = previous;
+            self.lock.save(&vfs)?;
+            return Err(e);
+        }
+        self.lock.save(&vfs)?;
+        Ok(count)
     }
 
     /// List installed packages
@@ -256,19 +1029,21 @@ impl PackageManager {
             .or_else(|| self.find_package_in_repos(package_id).cloned())
     }
 
+    /// Newest version in any repository, from the one the resolver would
+    /// pick on a tie
     fn find_package_in_repos(&self, package_id: &PackageId) -> Option<&Package> {
-        for repo in &self.repositories {
-            if let Some(package) = repo.find_package(package_id) {
-                return Some(package);
-            }
-        }
-        None
+        self.repositories
+            .iter()
+            .rev()
+            .filter_map(|repo| Some((repo.find_package(package_id)?, repo.priority)))
+            .max_by_key(|(package, priority)| (&package.version, *priority))
+            .map(|(package, _)| package)
     }
 
     fn find_dependents(&self, package_id: &PackageId) -> Vec<PackageId> {
         self.installed_packages
             .values()
-            .filter(|p| p.dependencies.contains(package_id))
+            .filter(|p| p.dependencies.iter().any(|d| &d.id == package_id))
             .map(|p| p.id.clone())
             .collect()
     }
@@ -283,18 +1058,27 @@ impl Default for PackageManager {
 /// CLI for package manager
 pub struct CLI {
     manager: PackageManager,
+    i18n: Arc<Localizer>,
+    commands: Cli,
 }
 
 impl CLI {
     pub fn new() -> Self {
+        Self::with_localizer(Arc::new(Localizer::new()))
+    }
+
+    /// CLI rendering its messages through `i18n`
+    pub fn with_localizer(i18n: Arc<Localizer>) -> Self {
         CLI {
             manager: PackageManager::new(),
+            i18n,
+            commands: commands(),
         }
     }
 
     pub fn run(&mut self) {
         println!("hairr Package Manager v0.1.0");
-        println!("Type 'help' for available commands\n");
+        println!("{}\n", self.i18n.tr("Type 'help' for available commands"));
 
         loop {
             print!("pkg> ");
@@ -316,164 +1100,417 @@ impl CLI {
                         break;
                     }
                 }
-                Err(e) => println!("Error: {}", e),
+                Err(e) => println!("{}", self.i18n.tr_args("Error: {error}", &[("error", &e)])),
             }
         }
     }
 
     fn handle_command(&mut self, input: &str) -> Result<bool, String> {
-        let parts: Vec<&str> = input.split_whitespace().collect();
-        if parts.is_empty() {
-            return Ok(false);
-        }
+        let parsed = match self.commands.parse(input) {
+            Ok(parsed) => parsed,
+            Err(CliError::Empty) => return Ok(false),
+            Err(CliError::Unknown(command)) => {
+                println!("{}", self.i18n.tr_args("Unknown command: {command}", &[("command", &command)]));
+                println!("{}", self.i18n.tr("Type 'help' for available commands"));
+                return Ok(false);
+            }
+            Err(CliError::Usage(usage)) => {
+                println!("{}", self.i18n.tr_args("Usage: {usage}", &[("usage", &usage)]));
+                return Ok(false);
+            }
+            Err(e) => return Err(e.to_string()),
+        };
+        let arg = |name: &str| parsed.get(name).unwrap_or_default();
 
-        match parts[0] {
+        match parsed.command() {
             "help" => {
                 self.show_help();
                 Ok(false)
             }
-            "exit" | "quit" => {
-                println!("Goodbye!");
+            "exit" => {
+                println!("{}", self.i18n.tr("Goodbye!"));
                 Ok(true)
             }
             "install" => {
-                if parts.len() < 2 {
-                    println!("Usage: install <package_id>");
+                if local::is_package_file(arg("package")) {
+                    let id = self.manager.install_file(&local::resolve_path(arg("package")))?;
+                    println!("{}", self.i18n.tr_args("Installed {package} from file", &[("package", &id.to_string())]));
                 } else {
-                    let package_id = PackageId::from(parts[1]);
-                    match self.manager.install(&package_id) {
-                        Ok(_) => println!("Package installed successfully"),
-                        Err(e) => println!("Error: {}", e),
+                    let package_id = PackageId::from(arg("package"));
+                    let req = match parsed.get("req") {
+                        None => VersionReq::any(),
+                        Some(req) => VersionReq::parse(req)?,
+                    };
+                    self.warn_stale();
+                    let result = if parsed.flag("locked") {
+                        self.manager.install_locked(&package_id, &req)
+                    } else {
+                        self.manager.install_matching(&package_id, &req)
+                    };
+                    match result {
+                        Ok(_) => println!("{}", self.i18n.tr("Package installed successfully")),
+                        Err(e) => println!("{}", self.i18n.tr_args("Error: {error}", &[("error", &e)])),
                     }
                 }
                 Ok(false)
             }
-            "uninstall" | "remove" => {
-                if parts.len() < 2 {
-                    println!("Usage: uninstall <package_id>");
-                } else {
-                    let package_id = PackageId::from(parts[1]);
-                    match self.manager.uninstall(&package_id) {
-                        Ok(_) => println!("Package uninstalled successfully"),
-                        Err(e) => println!("Error: {}", e),
-                    }
+            "uninstall" => {
+                match self.manager.uninstall(&PackageId::from(arg("package"))) {
+                    Ok(_) => println!("{}", self.i18n.tr("Package uninstalled successfully")),
+                    Err(e) => println!("{}", self.i18n.tr_args("Error: {error}", &[("error", &e)])),
                 }
                 Ok(false)
             }
             "update" => {
-                if parts.len() < 2 {
-                    println!("Usage: update <package_id>");
-                } else {
-                    let package_id = PackageId::from(parts[1]);
-                    match self.manager.update(&package_id) {
-                        Ok(_) => println!("Package updated successfully"),
-                        Err(e) => println!("Error: {}", e),
-                    }
+                match self.manager.update(&PackageId::from(arg("package"))) {
+                    Ok(_) => println!("{}", self.i18n.tr("Package updated successfully")),
+                    Err(e) => println!("{}", self.i18n.tr_args("Error: {error}", &[("error", &e)])),
+                }
+                Ok(false)
+            }
+            "upgrade" => {
+                self.warn_stale();
+                let skipped = self.manager.pinned_updates()?;
+                let upgraded = self.manager.upgrade()?;
+                if upgraded.is_empty() {
+                    println!("{}", self.i18n.tr("All packages are up to date"));
+                }
+                for update in &upgraded {
+                    println!("{} {} -> {}", update.id, update.installed, update.available);
+                }
+                for (update, pin) in &skipped {
+                    println!(
+                        "{}",
+                        self.i18n.tr_args(
+                            "Skipped {package} {available}: {pin}",
+                            &[("package", &update.id), ("available", &update.available), ("pin", pin)]
+                        )
+                    );
+                }
+                Ok(false)
+            }
+            "pin" => {
+                self.manager.pin(&PackageId::from(arg("package")), &VersionReq::parse(arg("req"))?)?;
+                Ok(false)
+            }
+            "hold" => {
+                self.manager.hold(&PackageId::from(arg("package")))?;
+                Ok(false)
+            }
+            "unpin" => {
+                self.manager.unpin(&PackageId::from(arg("package")))?;
+                Ok(false)
+            }
+            "pins" => {
+                for (id, pin) in self.manager.pins.iter() {
+                    println!("{:<20} {}", id, pin);
                 }
                 Ok(false)
             }
+            "check-updates" => {
+                // For the app store, so JSON only
+                println!("{}", serde_json::to_string(&self.manager.check_updates()?).unwrap());
+                Ok(false)
+            }
+            "sandbox" => {
+                let profile = self.manager.app_sandbox(&PackageId::from(arg("package"))).ok_or("Package not installed")?;
+                println!("{}", serde_json::to_string_pretty(&profile).unwrap());
+                Ok(false)
+            }
+            "export" => {
+                let count = self.manager.export(Path::new(arg("path")))?;
+                println!("{}", self.i18n.trn("{count} package locked", "{count} packages locked", count as u64, &[]));
+                Ok(false)
+            }
+            "import" => {
+                self.warn_stale();
+                let count = self.manager.import(Path::new(arg("path")))?;
+                println!("{}", self.i18n.trn("{count} package changed", "{count} packages changed", count as u64, &[]));
+                Ok(false)
+            }
+            "inspect" => {
+                self.show_package_file(&self.manager.inspect(&local::resolve_path(arg("file")))?);
+                Ok(false)
+            }
+            "make-delta" => {
+                let (delta, full) = self.manager.make_delta(Path::new(arg("base")), Path::new(arg("new")), Path::new(arg("output")))?;
+                println!("{} / {} bytes", delta, full);
+                Ok(false)
+            }
             "list" => {
                 self.list_packages();
                 Ok(false)
             }
-            "search" => {
-                if parts.len() < 2 {
-                    println!("Usage: search <query>");
-                } else {
-                    let query = parts[1..].join(" ");
-                    self.search_packages(&query);
+            "files" => {
+                for path in self.manager.files_of(&PackageId::from(arg("package"))) {
+                    println!("{}", path.display());
                 }
                 Ok(false)
             }
-            "info" => {
-                if parts.len() < 2 {
-                    println!("Usage: info <package_id>");
-                } else {
-                    let package_id = PackageId::from(parts[1]);
-                    self.show_info(&package_id);
+            "owner" => {
+                match self.manager.owner_of(Path::new(arg("path"))) {
+                    Some(id) => println!("{}", id),
+                    None => println!("{}", self.i18n.tr("No package owns this path")),
                 }
                 Ok(false)
             }
-            _ => {
-                println!("Unknown command: {}", parts[0]);
-                println!("Type 'help' for available commands");
+            "repos" => {
+                self.list_repositories();
+                Ok(false)
+            }
+            "add-repo" => {
+                self.manager.add_repository(arg("name"), arg("url"), arg("key_id"))?;
+                let count = self.manager.refresh_repository(arg("name"), index::now())?;
+                println!("{}", self.i18n.trn("{count} package available", "{count} packages available", count as u64, &[]));
+                Ok(false)
+            }
+            "remove-repo" => {
+                self.manager.remove_repository(arg("name"))?;
+                println!("{}", self.i18n.tr("Repository removed"));
+                Ok(false)
+            }
+            "priority" => {
+                self.manager.set_priority(arg("repo"), parsed.parse::<i32>("number")?)?;
+                println!("{}", self.i18n.tr("Priority set"));
+                Ok(false)
+            }
+            "add-mirror" => {
+                self.manager.add_mirror(arg("repo"), arg("url"))?;
+                println!("{}", self.i18n.tr("Mirror added"));
                 Ok(false)
             }
+            "remove-mirror" => {
+                self.manager.remove_mirror(arg("repo"), arg("url"))?;
+                println!("{}", self.i18n.tr("Mirror removed"));
+                Ok(false)
+            }
+            "refresh" => {
+                for (name, result) in self.manager.refresh(index::now()) {
+                    match result {
+                        Ok(count) => println!(
+                            "{}: {}",
+                            name,
+                            self.i18n.trn("{count} package available", "{count} packages available", count as u64, &[])
+                        ),
+                        Err(e) => println!("{}: {}", name, self.i18n.tr_args("Error: {error}", &[("error", &e)])),
+                    }
+                }
+                Ok(false)
+            }
+            "locale" => {
+                match parsed.get("name") {
+                    Some(name) => self.i18n.set_locale(Locale::parse(name)?),
+                    None => println!("{}", self.i18n.locale()),
+                }
+                Ok(false)
+            }
+            "search" => {
+                self.search_packages(arg("query"));
+                Ok(false)
+            }
+            "info" => {
+                self.show_info(&PackageId::from(arg("package")));
+                Ok(false)
+            }
+            command => unreachable!("{} has no handler", command),
         }
     }
 
     fn show_help(&self) {
-        println!("Available commands:");
-        println!("  install <package>    - Install a package");
-        println!("  uninstall <package>  - Uninstall a package");
-        println!("  update <package>     - Update a package");
-        println!("  list                 - List installed packages");
-        println!("  search <query>       - Search for packages");
-        println!("  info <package>       - Show package information");
-        println!("  help                 - Show this help message");
-        println!("  exit/quit            - Exit the package manager");
+        println!("{}", self.i18n.tr("Available commands:"));
+        print!("{}", self.commands.help(|about| self.i18n.tr(about)));
     }
 
     fn list_packages(&self) {
         let packages = self.manager.list_installed();
         if packages.is_empty() {
-            println!("No packages installed");
+            println!("{}", self.i18n.tr("No packages installed"));
             return;
         }
 
-        println!("\nInstalled Packages:");
+        println!("\n{}", self.installed_summary());
         println!("{:<20} {:<10} {:<50}", "Name", "Version", "Description");
         println!("{:-<80}", "");
         
         for package in packages {
             println!(
-                "{:<20} {:<10} {:<50}",
-                package.name,
+                "{} {:<10} {}",
+                column(&package.name, 20),
                 package.version,
-                package.description
+                column(&package.description, 50)
             );
         }
         println!();
     }
 
+    fn list_repositories(&self) {
+        for repository in self.manager.repositories.iter().filter(|r| r.index().is_none()) {
+            println!("{:<20} {}", self.i18n.tr("built-in"), repository.url());
+        }
+        let stale = self.manager.stale_repositories(index::now());
+        for source in self.manager.sources() {
+            let status = if stale.contains(&source.name) {
+                self.i18n.tr("stale, run 'refresh'")
+            } else {
+                self.i18n.tr("up to date")
+            };
+            println!("{:<20} {} ({})", source.name, source.url, status);
+            if source.priority != 0 {
+                println!("{:<20} {}", "", self.i18n.tr_args("priority {priority}", &[("priority", &source.priority.to_string())]));
+            }
+            for mirror in &source.mirrors {
+                println!("{:<20} {}", "", self.i18n.tr_args("mirror {url}", &[("url", mirror)]));
+            }
+        }
+    }
+
+    /// Point out repositories whose packages may be out of date
+    fn warn_stale(&self) {
+        for name in self.manager.stale_repositories(index::now()) {
+            println!(
+                "{}",
+                self.i18n.tr_args("Warning: the index of {name} is out of date, run 'refresh'", &[("name", &name)])
+            );
+        }
+    }
+
+    /// Heading of the installed package list
+    fn installed_summary(&self) -> String {
+        let count = self.manager.list_installed().len() as u64;
+        self.i18n.trn("{count} package installed:", "{count} packages installed:", count, &[])
+    }
+
     fn search_packages(&self, query: &str) {
         let results = self.manager.search(query);
         if results.is_empty() {
-            println!("No packages found matching '{}'", query);
+            println!("{}", self.i18n.tr_args("No packages found matching '{query}'", &[("query", query)]));
             return;
         }
 
-        println!("\nSearch Results for '{}':", query);
+        println!("\n{}", self.i18n.tr_args("Search Results for '{query}':", &[("query", query)]));
         println!("{:<20} {:<10} {:<50}", "Name", "Version", "Description");
         println!("{:-<80}", "");
         
         for package in results {
             let installed = if package.installed { "[installed]" } else { "" };
             println!(
-                "{:<20} {:<10} {:<40} {}",
-                package.name,
+                "{} {:<10} {} {}",
+                column(&package.name, 20),
                 package.version,
-                package.description,
+                column(&package.description, 40),
                 installed
             );
         }
         println!();
     }
 
+    fn show_package_file(&self, file: &PackageFile) {
+        let package = file.package();
+        println!("\n{}", self.i18n.tr("Package File:"));
+        println!("  ID:          {}", package.id);
+        println!("  Name:        {}", package.name);
+        println!("  Version:     {}", package.version);
+        println!("  Description: {}", package.description);
+        println!("  Author:      {}", package.author);
+        println!("  Digest:      {}", package.digest);
+        if !package.dependencies.is_empty() {
+            let dependencies: Vec<String> = package.dependencies.iter().map(Dependency::to_string).collect();
+            println!("  Dependencies: {}", dependencies.join(", "));
+        }
+        for hook in &package.hooks {
+            println!("  Hook:        {} {}", hook.stage, hook.command.display());
+        }
+        for capability in file.capabilities() {
+            println!("  Capability:  {:?} {:?}", capability.resource, capability.permission);
+        }
+        self.show_permissions(&package);
+        println!("\n{}", self.i18n.tr("Files:"));
+        for entry in &file.archive.entries {
+            println!("  {:o} {}", entry.mode, entry.path.display());
+        }
+        println!();
+    }
+
     fn show_info(&self, package_id: &PackageId) {
         if let Some(package) = self.manager.info(package_id) {
-            println!("\nPackage Information:");
+            println!("\n{}", self.i18n.tr("Package Information:"));
             println!("  Name:        {}", package.name);
             println!("  Version:     {}", package.version);
             println!("  Description: {}", package.description);
             println!("  Installed:   {}", package.installed);
+            if let Some(candidate) = self.manager.find_package_in_repos(package_id) {
+                println!("  Repository:  {} ({})", candidate.repository, candidate.version);
+            }
             if !package.dependencies.is_empty() {
-                println!("  Dependencies: {:?}", package.dependencies);
+                let dependencies: Vec<String> = package.dependencies.iter().map(Dependency::to_string).collect();
+                println!("  Dependencies: {}", dependencies.join(", "));
             }
+            self.show_permissions(&package);
             println!();
         } else {
-            println!("Package not found");
+            println!("{}", self.i18n.tr("Package not found"));
+        }
+    }
+
+    /// What the app will be able to access once installed
+    fn show_permissions(&self, package: &Package) {
+        if package.capabilities.is_empty() {
+            return;
         }
+        println!("  {}", self.i18n.tr("Permissions:"));
+        for capability in &package.capabilities {
+            println!("    - {}", capability);
+        }
+    }
+}
+
+/// Text cut or padded to fill `width` terminal columns
+fn column(text: &str, width: usize) -> String {
+    string::pad_right(&string::truncate(text, width), width, ' ')
+}
+
+/// What the package manager CLI accepts
+fn commands() -> Cli {
+    Cli::new()
+        .command(
+            Command::new("install", "Install a package or package file, optionally a version such as ^1.2")
+                .arg("package")
+                .optional_rest("req")
+                .flag("locked"),
+        )
+        .command(Command::new("inspect", "Show what a package file contains without installing it").arg("file"))
+        .command(Command::new("uninstall", "Uninstall a package").alias("remove").arg("package"))
+        .command(Command::new("update", "Update a package").arg("package"))
+        .command(Command::new("upgrade", "Upgrade every package with a newer version"))
+        .command(Command::new("check-updates", "List available updates as JSON"))
+        .command(Command::new("sandbox", "Show the sandbox an app is launched in").arg("package"))
+        .command(Command::new("pin", "Keep a package within a version requirement").arg("package").rest("req"))
+        .command(Command::new("hold", "Keep a package at its installed version").arg("package"))
+        .command(Command::new("unpin", "Remove a pin or hold").alias("unhold").arg("package"))
+        .command(Command::new("pins", "List pinned and held packages"))
+        .command(Command::new("export", "Save the lockfile to reproduce this package set").arg("path"))
+        .command(Command::new("import", "Install the package set of an exported lockfile").arg("path"))
+        .command(Command::new("make-delta", "Write a delta between two package archives").arg("base").arg("new").arg("output"))
+        .command(Command::new("list", "List installed packages"))
+        .command(Command::new("files", "List the files a package installed").arg("package"))
+        .command(Command::new("owner", "Show which package installed a file").arg("path"))
+        .command(Command::new("repos", "List repositories"))
+        .command(Command::new("add-repo", "Add a repository signed with a keystore key").arg("name").arg("url").arg("key_id"))
+        .command(Command::new("remove-repo", "Remove a repository").arg("name"))
+        .command(Command::new("priority", "Prefer a repository when versions tie; higher wins").arg("repo").arg("number"))
+        .command(Command::new("add-mirror", "Add a mirror tried when a repository fails").arg("repo").arg("url"))
+        .command(Command::new("remove-mirror", "Remove a repository mirror").arg("repo").arg("url"))
+        .command(Command::new("refresh", "Download the repository indices"))
+        .command(Command::new("search", "Search for packages").rest("query"))
+        .command(Command::new("info", "Show package information").arg("package"))
+        .command(Command::new("locale", "Show or change the display language").optional("name"))
+        .command(Command::new("help", "Show this help message"))
+        .command(Command::new("exit", "Exit the package manager").alias("quit"))
+}
+
+impl Default for CLI {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -518,4 +1555,612 @@ mod tests {
         assert!(manager.install(&package_id).is_ok());
         assert!(manager.install(&package_id).is_err());
     }
+
+    #[test]
+    fn test_install_and_update_with_requirements() {
+        let mut manager = PackageManager::new();
+        let mut repo = Repository::new("https://mirror.example.org".to_string());
+        let codec = |version| Package::new(PackageId::from("codec"), "Codec".to_string(), version, String::new());
+        repo.add_package(codec(Version::new(1, 4, 0)));
+        repo.add_package(codec(Version::new(2, 0, 0)));
+        let mut player = Package::new(PackageId::from("player"), "Player".to_string(), Version::new(1, 0, 0), String::new());
+        player.dependencies.push(Dependency::new(PackageId::from("codec"), VersionReq::parse("^1.2").unwrap()));
+        repo.add_package(player);
+        manager.repositories.push(repo);
+
+        let codec_id = PackageId::from("codec");
+        manager.install(&PackageId::from("player")).unwrap();
+        assert_eq!(manager.installed_packages[&codec_id].version, Version::new(1, 4, 0));
+        // The player keeps the codec on 1.x
+        manager.update(&codec_id).unwrap();
+        assert_eq!(manager.installed_packages[&codec_id].version, Version::new(1, 4, 0));
+        assert!(manager.uninstall(&codec_id).is_err());
+
+        let mut fresh = PackageManager::new();
+        fresh.repositories = std::mem::take(&mut manager.repositories);
+        fresh.install_matching(&codec_id, &VersionReq::parse(">=2").unwrap()).unwrap();
+        let error = fresh.install(&PackageId::from("player")).unwrap_err();
+        assert!(error.contains("player 1.0.0 requires codec ^1.2"));
+        assert!(error.contains("installed: 2.0.0"));
+    }
+
+    #[test]
+    fn test_repository_sync() {
+        let vfs = Arc::new(VirtualFileSystem::new());
+        let keystore = Arc::new(Keystore::new());
+        let key = KeyId::from("extra-signing");
+        keystore
+            .generate_key(key.clone(), keystore::KeyType::ECC256, vec![keystore::KeyUsage::Sign, keystore::KeyUsage::Verify], false)
+            .unwrap();
+        let archive = br#"{"entries":[]}"#;
+        let served = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let publish = |generated_at: u64, expires_at: u64, version: &str| {
+            let index: RepositoryIndex = serde_json::from_value(serde_json::json!({
+                "generated_at": generated_at,
+                "expires_at": expires_at,
+                "packages": [{"id": "codec", "name": "Codec", "version": version, "digest": archive_digest(archive)}],
+            }))
+            .unwrap();
+            let signature = keystore.sign(&key, &index.signed_bytes()).unwrap();
+            *served.lock().unwrap() = SignedIndex { index, signature }.to_json();
+        };
+
+        let mut manager = PackageManager::new();
+        manager.set_keystore(Arc::clone(&keystore));
+        manager.attach_storage(Arc::clone(&vfs)).unwrap();
+        let source = Arc::clone(&served);
+        let fetcher: Fetcher = Arc::new(move |url: &str| match url {
+            "https://extra.example.org/index.json" => Ok(source.lock().unwrap().clone()),
+            "https://extra.example.org/packages/codec-2.0.0.pkg" => Ok(archive.to_vec()),
+            _ => Err(format!("Unexpected fetch of {}", url)),
+        });
+        manager.set_fetcher(Arc::clone(&fetcher));
+        assert!(manager.add_repository("extra", "http://extra.example.org", "extra-signing").is_err());
+        assert!(manager.add_repository("extra", "https://extra.example.org", "missing").is_err());
+        manager.add_repository("extra", "https://extra.example.org/", "extra-signing").unwrap();
+        assert_eq!(manager.stale_repositories(500), vec!["extra"]);
+
+        publish(100, 1000, "2.0.0");
+        assert_eq!(manager.refresh(500), vec![("extra".to_string(), Ok(1))]);
+        assert!(manager.stale_repositories(500).is_empty());
+        assert_eq!(manager.stale_repositories(1000), vec!["extra"]);
+        manager.install(&PackageId::from("codec")).unwrap();
+        let codec = &manager.installed_packages[&PackageId::from("codec")];
+        assert!(codec.verify_archive(archive).is_ok());
+        assert!(codec.verify_archive(b"tampered").is_err());
+
+        // Replayed, expired and forged indices are refused
+        publish(50, 2000, "1.0.0");
+        assert!(manager.refresh_repository("extra", 500).unwrap_err().contains("older"));
+        publish(200, 400, "3.0.0");
+        assert!(manager.refresh_repository("extra", 500).unwrap_err().contains("expired"));
+        publish(300, 2000, "3.0.0");
+        let mut forged = SignedIndex::from_json(&served.lock().unwrap()).unwrap();
+        forged.index.packages[0].digest = archive_digest(b"malware");
+        *served.lock().unwrap() = forged.to_json();
+        assert_eq!(manager.refresh_repository("extra", 500), Err("Invalid index signature".to_string()));
+        assert_eq!(manager.info(&PackageId::from("codec")).unwrap().version, Version::new(2, 0, 0));
+
+        // The cached index is used offline after a restart
+        let mut restarted = PackageManager::new();
+        restarted.set_keystore(Arc::clone(&keystore));
+        restarted.attach_storage(Arc::clone(&vfs)).unwrap();
+        restarted.set_fetcher(fetcher);
+        assert_eq!(restarted.sources().len(), 1);
+        assert!(restarted.install(&PackageId::from("codec")).is_ok());
+
+        restarted.remove_repository("extra").unwrap();
+        assert!(!vfs.exists(&std::path::Path::new(index::INDEX_CACHE_DIR).join("extra.json")));
+        let mut fresh = PackageManager::new();
+        fresh.attach_storage(Arc::clone(&vfs)).unwrap();
+        assert!(fresh.sources().is_empty());
+        assert!(fresh.install(&PackageId::from("codec")).is_err());
+    }
+
+    #[test]
+    fn test_payload_installation() {
+        let vfs = Arc::new(VirtualFileSystem::new());
+        let served = Arc::new(std::sync::Mutex::new(HashMap::new()));
+        let mut repo = Repository::new("https://apps.example.org".to_string());
+        let mut publish = |id: &str, version: Version, files: serde_json::Value, deps: &[&str]| {
+            let data = serde_json::to_vec(&serde_json::json!({ "entries": files })).unwrap();
+            let url = format!("https://apps.example.org/{}-{}.pkg", id, version);
+            let mut package = Package::new(PackageId::from(id), id.to_string(), version, String::new());
+            package.digest = archive_digest(&data);
+            package.archive_url = Some(url.clone());
+            package.dependencies = deps.iter().map(|d| Dependency::new(PackageId::from(*d), VersionReq::any())).collect();
+            served.lock().unwrap().insert(url, data);
+            repo.add_package(package);
+        };
+        publish("codec", Version::new(1, 0, 0), serde_json::json!([{"path": "lib/codec.so", "kind": "file", "content": [1]}]), &[]);
+        publish(
+            "viewer",
+            Version::new(1, 0, 0),
+            serde_json::json!([
+                {"path": "bin", "kind": "directory", "mode": 0o755},
+                {"path": "bin/viewer", "kind": "file", "mode": 0o755, "content": [2]},
+                {"path": "share/help.txt", "kind": "file", "content": [3]},
+            ]),
+            &["codec"],
+        );
+        publish(
+            "viewer",
+            Version::new(1, 1, 0),
+            serde_json::json!([{"path": "bin/viewer", "kind": "file", "mode": 0o755, "content": [4]}]),
+            &["codec"],
+        );
+
+        let mut manager = PackageManager::new();
+        manager.attach_storage(Arc::clone(&vfs)).unwrap();
+        manager.repositories.push(repo);
+        let source = Arc::clone(&served);
+        manager.set_fetcher(Arc::new(move |url: &str| source.lock().unwrap().get(url).cloned().ok_or("Not found".to_string())));
+
+        let viewer = PackageId::from("viewer");
+        manager.install_matching(&viewer, &VersionReq::parse("=1.0.0").unwrap()).unwrap();
+        assert_eq!(vfs.read_file(Path::new("/apps/viewer/bin/viewer")).unwrap(), vec![2]);
+        assert_eq!(vfs.read_file(Path::new("/apps/codec/lib/codec.so")).unwrap(), vec![1]);
+        assert_eq!(manager.owner_of(Path::new("/apps/viewer/share/help.txt")), Some(viewer.clone()));
+        assert_eq!(manager.files_of(&viewer).len(), 5);
+
+        // Updating drops the files the new version no longer ships
+        manager.update(&viewer).unwrap();
+        assert_eq!(vfs.read_file(Path::new("/apps/viewer/bin/viewer")).unwrap(), vec![4]);
+        assert!(!vfs.exists(Path::new("/apps/viewer/share")));
+
+        // The database survives a restart; uninstalling keeps the user's files
+        let mut restarted = PackageManager::new();
+        restarted.attach_storage(Arc::clone(&vfs)).unwrap();
+        restarted.installed_packages = std::mem::take(&mut manager.installed_packages);
+        assert_eq!(restarted.files_of(&viewer), manager.files_of(&viewer));
+        vfs.write_file(Path::new("/apps/viewer/notes.txt"), b"mine").unwrap();
+        restarted.uninstall(&viewer).unwrap();
+        assert!(!vfs.exists(Path::new("/apps/viewer/bin")));
+        assert!(vfs.exists(Path::new("/apps/viewer/notes.txt")));
+        assert!(restarted.files_of(&viewer).is_empty());
+
+        // A corrupted download installs nothing
+        served.lock().unwrap().insert("https://apps.example.org/viewer-1.1.0.pkg".to_string(), b"{}".to_vec());
+        assert!(manager.install(&viewer).unwrap_err().contains("does not match its digest"));
+        assert!(!vfs.exists(Path::new("/apps/viewer/bin")));
+    }
+
+    #[test]
+    fn test_upgrade_is_transactional() {
+        let vfs = Arc::new(VirtualFileSystem::new());
+        let served = Arc::new(std::sync::Mutex::new(HashMap::new()));
+        let publish = |id: &str, version: Version, files: serde_json::Value, deps: &[&str]| {
+            let data = serde_json::to_vec(&serde_json::json!({ "entries": files })).unwrap();
+            let url = format!("https://apps.example.org/{}-{}.pkg", id, version);
+            let mut package = Package::new(PackageId::from(id), id.to_string(), version, String::new());
+            package.digest = archive_digest(&data);
+            package.archive_url = Some(url.clone());
+            package.dependencies = deps.iter().map(|d| Dependency::new(PackageId::from(*d), VersionReq::any())).collect();
+            served.lock().unwrap().insert(url, data);
+            package
+        };
+        let codec_file = |byte: u8| serde_json::json!([{"path": "codec.so", "kind": "file", "content": [byte]}]);
+        let mut repo = Repository::new("https://apps.example.org".to_string());
+        repo.add_package(publish("codec", Version::new(1, 0, 0), codec_file(1), &[]));
+        repo.add_package(publish("viewer", Version::new(1, 0, 0), serde_json::json!([]), &["codec"]));
+
+        let mut manager = PackageManager::new();
+        manager.attach_storage(Arc::clone(&vfs)).unwrap();
+        let source = Arc::clone(&served);
+        manager.set_fetcher(Arc::new(move |url: &str| source.lock().unwrap().get(url).cloned().ok_or("Not found".to_string())));
+        manager.repositories.push(repo);
+        manager.install(&PackageId::from("viewer")).unwrap();
+        assert!(manager.check_updates().unwrap().is_empty());
+
+        // The new viewer cannot extract over a file the user keeps in its directory
+        manager.repositories[1].add_package(publish("codec", Version::new(1, 1, 0), codec_file(2), &[]));
+        let viewer = publish("viewer", Version::new(1, 1, 0), serde_json::json!([{"path": "data/inner", "kind": "file"}]), &["codec"]);
+        manager.repositories[1].add_package(viewer);
+        vfs.write_file(Path::new("/apps/viewer/data"), b"mine").unwrap();
+        let updates = manager.check_updates().unwrap();
+        let listed: Vec<(&str, &str)> = updates.iter().map(|u| (u.id.as_str(), u.available.as_str())).collect();
+        assert_eq!(listed, vec![("codec", "1.1.0"), ("viewer", "1.1.0")]);
+
+        assert!(manager.upgrade().is_err());
+        assert_eq!(vfs.read_file(Path::new("/apps/codec/codec.so")).unwrap(), vec![1]);
+        assert_eq!(manager.installed_packages[&PackageId::from("codec")].version, Version::new(1, 0, 0));
+        assert_eq!(FileDatabase::load(&vfs).unwrap().paths_of(&PackageId::from("codec")), manager.files_of(&PackageId::from("codec")));
+        assert_eq!(vfs.read_file(Path::new("/apps/viewer/data")).unwrap(), b"mine");
+
+        vfs.delete(Path::new("/apps/viewer/data")).unwrap();
+        assert_eq!(manager.upgrade().unwrap(), updates);
+        assert_eq!(vfs.read_file(Path::new("/apps/codec/codec.so")).unwrap(), vec![2]);
+        assert!(manager.check_updates().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_lockfile_reproduction() {
+        let served = Arc::new(std::sync::Mutex::new(HashMap::new()));
+        let publish = |id: &str, version: Version, byte: u8, deps: &[&str]| {
+            let files = serde_json::json!([{"path": "data", "kind": "file", "content": [byte]}]);
+            let data = serde_json::to_vec(&serde_json::json!({ "entries": files })).unwrap();
+            let url = format!("https://apps.example.org/{}-{}.pkg", id, version);
+            let mut package = Package::new(PackageId::from(id), id.to_string(), version, String::new());
+            package.digest = archive_digest(&data);
+            package.archive_url = Some(url.clone());
+            package.dependencies = deps.iter().map(|d| Dependency::new(PackageId::from(*d), VersionReq::any())).collect();
+            served.lock().unwrap().insert(url, data);
+            package
+        };
+        let machine = |vfs: &Arc<VirtualFileSystem>, packages: &[Package]| {
+            let mut repo = Repository::new("https://apps.example.org".to_string());
+            for package in packages {
+                repo.add_package(package.clone());
+            }
+            let mut manager = PackageManager::new();
+            manager.attach_storage(Arc::clone(vfs)).unwrap();
+            manager.repositories.push(repo);
+            let source = Arc::clone(&served);
+            manager.set_fetcher(Arc::new(move |url: &str| source.lock().unwrap().get(url).cloned().ok_or("Not found".to_string())));
+            manager
+        };
+        let mut published = vec![
+            publish("codec", Version::new(1, 0, 0), 1, &[]),
+            publish("codec", Version::new(1, 1, 0), 2, &[]),
+            publish("viewer", Version::new(1, 0, 0), 3, &["codec"]),
+        ];
+
+        let first = Arc::new(VirtualFileSystem::new());
+        let mut manager = machine(&first, &published);
+        manager.install(&PackageId::from("viewer")).unwrap();
+        assert_eq!(manager.export(Path::new("/home/lock.json")).unwrap(), 2);
+        assert_eq!(Lockfile::load(&first).unwrap().packages["codec"].version, "1.1.0");
+
+        // A newer codec appears, but the import reproduces the exported set
+        published.push(publish("codec", Version::new(1, 2, 0), 4, &[]));
+        published.push(publish("player", Version::new(1, 0, 0), 5, &["codec"]));
+        let exported = first.read_file(Path::new("/home/lock.json")).unwrap();
+        let second = Arc::new(VirtualFileSystem::new());
+        second.write_file(Path::new("/home/lock.json"), &exported).unwrap();
+        let mut other = machine(&second, &published);
+        assert_eq!(other.import(Path::new("/home/lock.json")).unwrap(), 2);
+        assert_eq!(other.installed_packages[&PackageId::from("codec")].version, Version::new(1, 1, 0));
+        assert_eq!(second.read_file(Path::new("/apps/codec/data")).unwrap(), vec![2]);
+        let error = other.install_locked(&PackageId::from("player"), &VersionReq::any()).unwrap_err();
+        assert_eq!(error, "player is not in the lockfile");
+        assert!(!other.installed_packages.contains_key(&PackageId::from("player")));
+
+        // A republished archive is drift too
+        published[1] = publish("codec", Version::new(1, 1, 0), 9, &[]);
+        let third = Arc::new(VirtualFileSystem::new());
+        third.write_file(Path::new("/home/lock.json"), &exported).unwrap();
+        let mut drifted = machine(&third, &published);
+        assert!(drifted.import(Path::new("/home/lock.json")).unwrap_err().contains("does not match the digest"));
+        assert!(drifted.list_installed().is_empty());
+        assert!(!third.exists(Path::new("/apps/codec")));
+    }
+
+    #[test]
+    fn test_package_hooks() {
+        let kernel = Arc::new(kernel::Kernel::new());
+        let capabilities = Arc::new(capability::CapabilityManager::new());
+        let refuse_removal = Arc::new(std::sync::Mutex::new(true));
+        let refuse = Arc::clone(&refuse_removal);
+        let runner = HookRunner::new(
+            Arc::clone(&kernel),
+            capabilities,
+            Arc::new(move |invocation: &hooks::HookInvocation| match invocation.stage {
+                HookStage::PostInstall => Ok(format!("granted {}\ndone", invocation.capabilities.len())),
+                HookStage::PreRemove if *refuse.lock().unwrap() => Err("still in use".to_string()),
+                HookStage::PreRemove => Ok(String::new()),
+            }),
+        );
+        let mut viewer = Package::new(PackageId::from("viewer"), "Viewer".to_string(), Version::new(1, 0, 0), String::new());
+        viewer.hooks = serde_json::from_str(
+            r#"[{"stage":"post_install","command":"bin/setup","capabilities":[{"resource":{"File":"/apps/viewer"},"permission":"Write"}]},
+                {"stage":"pre_remove","command":"bin/teardown"}]"#,
+        )
+        .unwrap();
+        viewer.capabilities = viewer.hooks[0].capabilities.clone();
+        let mut repo = Repository::new("https://apps.example.org".to_string());
+        repo.add_package(viewer);
+
+        let mut manager = PackageManager::new();
+        manager.repositories.push(repo);
+        manager.set_hook_runner(runner);
+        let id = PackageId::from("viewer");
+        manager.install(&id).unwrap();
+        let messages: Vec<String> = manager.log().into_iter().map(|e| e.message).collect();
+        assert_eq!(messages, vec!["viewer post-install: granted 1", "viewer post-install: done"]);
+        assert!(kernel.list_processes().iter().all(|p| p.state == kernel::ProcessState::Terminated));
+
+        assert_eq!(manager.uninstall(&id), Err("viewer pre-remove hook failed: still in use".to_string()));
+        assert!(manager.installed_packages.contains_key(&id));
+        *refuse_removal.lock().unwrap() = false;
+        manager.uninstall(&id).unwrap();
+        assert_eq!(kernel.process_count(), 3);
+
+        // Without a runner nothing runs, and the log says so
+        let mut unsandboxed = PackageManager::new();
+        unsandboxed.repositories = std::mem::take(&mut manager.repositories);
+        unsandboxed.install(&id).unwrap();
+        assert_eq!(unsandboxed.log()[0].message, "viewer post-install hook skipped: hooks are disabled");
+    }
+
+    #[test]
+    fn test_local_package_file() {
+        let vfs = Arc::new(VirtualFileSystem::new());
+        let codec_archive = br#"{"entries":[{"path":"codec.so","kind":"file","content":[1]}]}"#;
+        let mut codec = Package::new(PackageId::from("codec"), "Codec".to_string(), Version::new(1, 2, 0), String::new());
+        codec.digest = archive_digest(codec_archive);
+        codec.archive_url = Some("https://apps.example.org/codec-1.2.0.pkg".to_string());
+        let mut published_viewer = Package::new(PackageId::from("viewer"), "Viewer".to_string(), Version::new(0, 3, 0), String::new());
+        published_viewer.archive_url = Some("https://apps.example.org/viewer-0.3.0.pkg".to_string());
+        let mut repo = Repository::new("https://apps.example.org".to_string());
+        repo.add_package(codec);
+        repo.add_package(published_viewer);
+
+        let mut cli = CLI::new();
+        cli.manager.attach_storage(Arc::clone(&vfs)).unwrap();
+        cli.manager.repositories.push(repo);
+        cli.manager.set_fetcher(Arc::new(move |url: &str| match url {
+            "https://apps.example.org/codec-1.2.0.pkg" => Ok(codec_archive.to_vec()),
+            _ => Err(format!("Unexpected fetch of {}", url)),
+        }));
+        let file = br#"{"manifest":{"id":"viewer","name":"Viewer","version":"0.3.0","dependencies":{"codec":"^1.1"}},
+            "archive":{"entries":[{"path":"bin/viewer","kind":"file","mode":493,"content":[7]}]}}"#;
+        vfs.write_file(Path::new("/home/dev/viewer.hpkg"), file).unwrap();
+
+        let inspected = cli.manager.inspect(Path::new("/home/dev/viewer.hpkg")).unwrap();
+        assert_eq!(inspected.archive.entries.len(), 1);
+        assert!(cli.handle_command("inspect home/dev/viewer.hpkg").is_ok());
+        assert!(cli.manager.list_installed().is_empty());
+
+        // The bundled archive wins over the published one of the same version
+        assert!(cli.handle_command("install ./home/dev/viewer.hpkg").is_ok());
+        assert_eq!(vfs.read_file(Path::new("/apps/viewer/bin/viewer")).unwrap(), vec![7]);
+        assert_eq!(vfs.read_file(Path::new("/apps/codec/codec.so")).unwrap(), vec![1]);
+        let lock = Lockfile::load(&vfs).unwrap();
+        assert_eq!(lock.packages["viewer"].digest, inspected.package().digest);
+
+        vfs.write_file(Path::new("/home/dev/broken.hpkg"), b"{}").unwrap();
+        assert!(cli.handle_command("install /home/dev/broken.hpkg").unwrap_err().contains("Invalid package file"));
+        assert!(cli.handle_command("inspect /home/dev/missing.hpkg").is_err());
+    }
+
+    #[test]
+    fn test_pins_and_holds() {
+        let vfs = Arc::new(VirtualFileSystem::new());
+        let repository = || {
+            let mut repo = Repository::new("https://apps.example.org".to_string());
+            for (id, version) in [("codec", "1.0.0"), ("codec", "1.4.0"), ("codec", "2.0.0"), ("browser", "1.0.0"), ("browser", "1.1.0")] {
+                repo.add_package(Package::new(PackageId::from(id), id.to_string(), Version::parse(version).unwrap(), String::new()));
+            }
+            repo
+        };
+        let mut manager = PackageManager::new();
+        manager.attach_storage(Arc::clone(&vfs)).unwrap();
+        manager.repositories.push(repository());
+        let (codec, browser) = (PackageId::from("codec"), PackageId::from("browser"));
+        manager.install_matching(&codec, &VersionReq::parse("=1.0").unwrap()).unwrap();
+        manager.install_matching(&browser, &VersionReq::parse("=1.0").unwrap()).unwrap();
+
+        assert!(manager.pin(&codec, &VersionReq::parse("^3").unwrap()).unwrap_err().contains("does not allow"));
+        manager.pin(&codec, &VersionReq::parse("^1").unwrap()).unwrap();
+        manager.hold(&browser).unwrap();
+        assert!(manager.hold(&PackageId::from("viewer")).is_err());
+        assert_eq!(manager.update(&browser), Err("browser is held".to_string()));
+
+        // Pins survive a restart
+        let mut manager = PackageManager::new();
+        manager.attach_storage(Arc::clone(&vfs)).unwrap();
+        manager.repositories.push(repository());
+        manager.install_matching(&codec, &VersionReq::parse("=1.0").unwrap()).unwrap();
+        manager.install_matching(&browser, &VersionReq::parse("=1.0").unwrap()).unwrap();
+        let skipped: Vec<(String, String, String)> =
+            manager.pinned_updates().unwrap().into_iter().map(|(u, pin)| (u.id, u.available, pin)).collect();
+        assert_eq!(
+            skipped,
+            vec![
+                ("browser".to_string(), "1.1.0".to_string(), "held".to_string()),
+                ("codec".to_string(), "2.0.0".to_string(), "pinned to ^1".to_string()),
+            ]
+        );
+        let upgraded = manager.upgrade().unwrap();
+        assert_eq!(upgraded.len(), 1);
+        assert_eq!(manager.installed_packages[&codec].version, Version::new(1, 4, 0));
+        assert_eq!(manager.installed_packages[&browser].version, Version::new(1, 0, 0));
+
+        manager.unpin(&codec).unwrap();
+        assert!(manager.unpin(&codec).is_err());
+        manager.unpin(&browser).unwrap();
+        assert_eq!(manager.upgrade().unwrap().len(), 2);
+        assert!(manager.pinned_updates().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_delta_updates() {
+        let config = RepositoryConfig {
+            name: "extra".to_string(),
+            url: "https://apps.example.org/".to_string(),
+            key: "extra-signing".to_string(),
+            priority: 0,
+            mirrors: Vec::new(),
+        };
+        let content: Vec<u8> = (0..2000u32).map(|i| (i % 97) as u8).collect();
+        let old = serde_json::to_vec(&serde_json::json!({"entries": [{"path": "codec.so", "kind": "file", "content": content}]})).unwrap();
+        let mut patched = content.clone();
+        patched[1500] = 255;
+        let new = serde_json::to_vec(&serde_json::json!({"entries": [{"path": "codec.so", "kind": "file", "content": patched}]})).unwrap();
+        let index: RepositoryIndex = serde_json::from_value(serde_json::json!({
+            "generated_at": 0,
+            "expires_at": u64::MAX,
+            "packages": [
+                {"id": "codec", "name": "Codec", "version": "1.0.0", "digest": archive_digest(&old)},
+                {"id": "codec", "name": "Codec", "version": "1.1.0", "digest": archive_digest(&new), "deltas": ["1.0.0"]},
+            ],
+        }))
+        .unwrap();
+
+        let delta = PackageDelta::generate(&old, &new).to_json();
+        assert!(delta.len() < new.len() / 4);
+        let fetched = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let setup = |vfs: &Arc<VirtualFileSystem>| {
+            let mut manager = PackageManager::new();
+            manager.attach_storage(Arc::clone(vfs)).unwrap();
+            manager.repositories.push(Repository::from_index(&config, index.clone()).unwrap());
+            let (old, new, delta, fetched) = (old.clone(), new.clone(), delta.clone(), Arc::clone(&fetched));
+            manager.set_fetcher(Arc::new(move |url: &str| {
+                fetched.lock().unwrap().push(url.to_string());
+                match url {
+                    "https://apps.example.org/packages/codec-1.0.0.pkg" => Ok(old.clone()),
+                    "https://apps.example.org/packages/codec-1.1.0.pkg" => Ok(new.clone()),
+                    "https://apps.example.org/deltas/codec-1.0.0-1.1.0.delta" => Ok(delta.clone()),
+                    _ => Err(format!("Unexpected fetch of {}", url)),
+                }
+            }));
+            manager.install_matching(&PackageId::from("codec"), &VersionReq::parse("=1.0").unwrap()).unwrap();
+            manager
+        };
+
+        let vfs = Arc::new(VirtualFileSystem::new());
+        let mut manager = setup(&vfs);
+        fetched.lock().unwrap().clear();
+        manager.update(&PackageId::from("codec")).unwrap();
+        assert_eq!(*fetched.lock().unwrap(), vec!["https://apps.example.org/deltas/codec-1.0.0-1.1.0.delta"]);
+        assert_eq!(vfs.read_file(Path::new("/apps/codec/codec.so")).unwrap()[1500], 255);
+        assert!(manager.log().iter().any(|e| e.message == "codec 1.1.0 rebuilt from a delta"));
+        // Only the installed archive stays cached
+        let cached = vfs.list_directory(Path::new(delta::ARCHIVE_CACHE_DIR)).unwrap();
+        assert_eq!(cached, vec![delta::cache_path(&archive_digest(&new))]);
+
+        // A base that changed since it was installed means a full download
+        let vfs = Arc::new(VirtualFileSystem::new());
+        let mut manager = setup(&vfs);
+        vfs.write_file(&delta::cache_path(&archive_digest(&old)), b"changed").unwrap();
+        fetched.lock().unwrap().clear();
+        manager.update(&PackageId::from("codec")).unwrap();
+        assert_eq!(
+            *fetched.lock().unwrap(),
+            vec!["https://apps.example.org/deltas/codec-1.0.0-1.1.0.delta", "https://apps.example.org/packages/codec-1.1.0.pkg"]
+        );
+        assert_eq!(vfs.read_file(Path::new("/apps/codec/codec.so")).unwrap()[1500], 255);
+        assert!(manager.log().iter().any(|e| e.message.contains("downloading the full archive")));
+
+        vfs.write_file(Path::new("/srv/old.pkg"), &old).unwrap();
+        vfs.write_file(Path::new("/srv/new.pkg"), &new).unwrap();
+        let (size, full) = manager.make_delta(Path::new("/srv/old.pkg"), Path::new("/srv/new.pkg"), Path::new("/srv/out.delta")).unwrap();
+        assert_eq!((size, full), (delta.len(), new.len()));
+    }
+
+    #[test]
+    fn test_repository_priorities_and_mirrors() {
+        let codec = PackageId::from("codec");
+        let main_archive = br#"{"entries":[]}"#.to_vec();
+        let extra_archive = br#"{"entries":[{"path":"codec.so","kind":"file"}]}"#.to_vec();
+        let vfs = Arc::new(VirtualFileSystem::new());
+        let mut manager = PackageManager::new();
+        manager.attach_storage(Arc::clone(&vfs)).unwrap();
+        for (name, archive) in [("main", &main_archive), ("extra", &extra_archive)] {
+            let config = RepositoryConfig {
+                name: name.to_string(),
+                url: format!("https://{}.example.org", name),
+                key: "signing".to_string(),
+                priority: 0,
+                mirrors: Vec::new(),
+            };
+            let index: RepositoryIndex = serde_json::from_value(serde_json::json!({
+                "generated_at": 0,
+                "expires_at": u64::MAX,
+                "packages": [{"id": "codec", "name": "Codec", "version": "1.0.0", "digest": archive_digest(archive)}],
+            }))
+            .unwrap();
+            manager.repositories.push(Repository::from_index(&config, index).unwrap());
+            manager.sources.push(config);
+        }
+        assert_eq!(manager.find_package_in_repos(&codec).unwrap().repository, "main");
+        manager.set_priority("extra", 5).unwrap();
+        assert_eq!(manager.find_package_in_repos(&codec).unwrap().repository, "extra");
+        assert!(manager.set_priority("missing", 1).is_err());
+
+        let archive = extra_archive.clone();
+        manager.set_fetcher(Arc::new(move |url: &str| match url {
+            "https://mirror.example.net/packages/codec-1.0.0.pkg" => Ok(archive.clone()),
+            _ => Err("Connection refused".to_string()),
+        }));
+        assert_eq!(manager.install(&codec), Err("Connection refused".to_string()));
+        assert!(manager.add_mirror("extra", "http://mirror.example.net").is_err());
+        manager.add_mirror("extra", "https://mirror.example.net/").unwrap();
+        assert!(manager.add_mirror("extra", "https://mirror.example.net/").is_err());
+        manager.install(&codec).unwrap();
+        assert_eq!(manager.installed_packages[&codec].digest, archive_digest(&extra_archive));
+        let log = manager.log();
+        assert!(log.iter().any(|e| e.message == "https://extra.example.org/packages/codec-1.0.0.pkg: Connection refused, trying a mirror"));
+
+        manager.remove_mirror("extra", "https://mirror.example.net/").unwrap();
+        assert!(manager.remove_mirror("extra", "https://mirror.example.net/").is_err());
+        let saved = index::load_configs(&vfs).unwrap();
+        assert_eq!((saved[1].priority, saved[1].mirrors.len()), (5, 0));
+    }
+
+    #[test]
+    fn test_app_capabilities_recorded() {
+        let vfs = Arc::new(VirtualFileSystem::new());
+        let mut manager = PackageManager::new();
+        manager.attach_storage(Arc::clone(&vfs)).unwrap();
+        let manifest: index::PackageManifest = serde_json::from_value(serde_json::json!({
+            "id": "camera", "name": "Camera", "version": "1.0.0",
+            "capabilities": [
+                {"resource": {"Device": "camera"}, "permission": "Read"},
+                {"resource": {"File": "/home/pictures"}, "permission": "Write"},
+            ],
+        }))
+        .unwrap();
+        manager.repositories[0].add_package(manifest.to_package().unwrap());
+        let camera = PackageId::from("camera");
+        assert_eq!(manager.info(&camera).unwrap().capabilities[0].to_string(), "read the camera device");
+
+        manager.install(&camera).unwrap();
+        let granted = vec![
+            (Resource::Device("camera".to_string()), Permission::Read),
+            (Resource::File("/home/pictures".to_string()), Permission::Write),
+        ];
+        assert_eq!(manager.app_capabilities(&camera), Some(granted.clone()));
+        manager.install(&PackageId::from("text-editor")).unwrap();
+        assert_eq!(manager.app_capabilities(&PackageId::from("text-editor")), Some(Vec::new()));
+        let mut restarted = PackageManager::new();
+        restarted.attach_storage(Arc::clone(&vfs)).unwrap();
+        assert_eq!(restarted.app_capabilities(&camera), Some(granted));
+        let sandbox = restarted.app_sandbox(&camera).unwrap();
+        assert_eq!((sandbox.root.as_str(), sandbox.devices.len()), ("/apps/camera", 1));
+        let profiles = || -> sandbox::SandboxDatabase {
+            serde_json::from_slice(&vfs.read_file(Path::new(sandbox::SANDBOX_FILE)).unwrap()).unwrap()
+        };
+        assert_eq!(profiles().profiles["camera"], sandbox);
+
+        manager.uninstall(&camera).unwrap();
+        assert!(manager.app_capabilities(&camera).is_none());
+        assert!(!profiles().profiles.contains_key("camera"));
+
+        let mut memory = manifest.clone();
+        memory.capabilities[0].resource = Resource::Memory(1 << 30);
+        assert!(memory.to_package().is_err());
+    }
+
+    #[test]
+    fn test_localized_messages() {
+        let i18n = Arc::new(Localizer::new());
+        let mut catalog = i18n::Catalog::new(Locale::parse("de").unwrap());
+        let forms = [
+            (i18n::PluralCategory::One, "{count} Paket installiert:".to_string()),
+            (i18n::PluralCategory::Other, "{count} Pakete installiert:".to_string()),
+        ];
+        catalog.insert("{count} package installed:", i18n::Translation::Plural(forms.into_iter().collect()));
+        i18n.add_catalog(catalog);
+
+        let mut cli = CLI::with_localizer(Arc::clone(&i18n));
+        assert_eq!(cli.installed_summary(), format!("{} packages installed:", cli.manager.list_installed().len()));
+        assert!(cli.handle_command("locale de-DE").is_ok());
+        assert_eq!(i18n.locale().to_string(), "de-DE");
+        cli.manager.install(&PackageId::from("text-editor")).unwrap();
+        let count = cli.manager.list_installed().len();
+        let expected = if count == 1 { "1 Paket installiert:".to_string() } else { format!("{} Pakete installiert:", count) };
+        assert_eq!(cli.installed_summary(), expected);
+        assert!(cli.handle_command("locale english").is_err());
+    }
 }
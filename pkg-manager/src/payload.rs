@@ -0,0 +1,267 @@
+//! Package payloads
+//!
+//! A package archive is a JSON file tree: every directory and file the
+//! package installs, relative to its install root `/apps/<id>`, with mode
+//! bits and content. The file database records which package owns each
+//! path it extracted, so uninstalling removes exactly those paths and
+//! leaves anything the user put next to them.
+//!
+//! ```json
+//! {"entries": [{"path": "bin", "kind": "directory", "mode": 493},
+//!              {"path": "bin/viewer", "kind": "file", "mode": 493, "content": [127, 69, 76, 70]}]}
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Component, Path, PathBuf};
+
+use filesystem::{FilePermissions, SnapshotEntry, VirtualFileSystem};
+use serde::{Deserialize, Serialize};
+
+use crate::PackageId;
+
+/// Directory packages are installed under
+pub const APPS_DIR: &str = "/apps";
+
+/// Owner of every path extracted from a package
+pub const FILES_DB: &str = "/var/lib/pkg/files.json";
+
+/// Where a package's files go
+pub fn install_root(id: &PackageId) -> PathBuf {
+    Path::new(APPS_DIR).join(id.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryKind {
+    File,
+    Directory,
+}
+
+fn default_mode() -> u32 {
+    0o644
+}
+
+/// One path in a package archive
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PayloadEntry {
+    /// Relative to the install root
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    #[serde(default = "default_mode")]
+    pub mode: u32,
+    #[serde(default)]
+    pub content: Vec<u8>,
+}
+
+/// File tree a package installs
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackageArchive {
+    /// Parents before their children
+    pub entries: Vec<PayloadEntry>,
+}
+
+impl PackageArchive {
+    pub fn from_json(data: &[u8]) -> Result<Self, String> {
+        let archive: PackageArchive =
+            serde_json::from_slice(data).map_err(|e| format!("Invalid package archive: {}", e))?;
+        archive.validate()?;
+        Ok(archive)
+    }
+
+    /// Nothing may land outside the install root
+    pub fn validate(&self) -> Result<(), String> {
+        for entry in &self.entries {
+            if !entry.path.components().all(|c| matches!(c, Component::Normal(_))) {
+                return Err(format!("Invalid package archive: bad path {}", entry.path.display()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Every path the tree occupies once extracted to `root`, root included
+    pub fn paths(&self, root: &Path) -> Vec<PathBuf> {
+        let mut paths = BTreeSet::new();
+        paths.insert(root.to_path_buf());
+        for entry in &self.entries {
+            let path = root.join(&entry.path);
+            paths.extend(path.ancestors().take_while(|p| *p != root).map(Path::to_path_buf));
+        }
+        paths.into_iter().collect()
+    }
+
+    /// Write the tree below `root`, replacing files already there, and
+    /// return its paths. On failure whatever was created is removed again.
+    pub fn extract(&self, vfs: &VirtualFileSystem, root: &Path) -> Result<Vec<PathBuf>, String> {
+        let mut created = Vec::new();
+        let result = self.extract_into(vfs, root, &mut created);
+        if let Err(e) = result {
+            remove_paths(vfs, &created);
+            return Err(e);
+        }
+        Ok(self.paths(root))
+    }
+
+    fn extract_into(&self, vfs: &VirtualFileSystem, root: &Path, created: &mut Vec<PathBuf>) -> Result<(), String> {
+        for ancestor in root.ancestors().collect::<Vec<_>>().into_iter().rev() {
+            if !vfs.exists(ancestor) {
+                vfs.create_directory(ancestor)?;
+                // Shared parents such as /apps belong to no package
+                if ancestor == root {
+                    created.push(ancestor.to_path_buf());
+                }
+            }
+        }
+        for entry in &self.entries {
+            let path = root.join(&entry.path);
+            for parent in path.ancestors().skip(1).take_while(|p| *p != root).collect::<Vec<_>>().into_iter().rev() {
+                if !vfs.exists(parent) {
+                    vfs.create_directory(parent)?;
+                    created.push(parent.to_path_buf());
+                }
+            }
+            let existed = vfs.exists(&path);
+            match entry.kind {
+                EntryKind::Directory if existed => continue,
+                EntryKind::Directory => vfs.create_directory(&path)?,
+                EntryKind::File => vfs.write_file(&path, &entry.content)?,
+            }
+            vfs.set_permissions(&path, FilePermissions::new(entry.mode))?;
+            if !existed {
+                created.push(path);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Delete paths, children first; directories that still hold files
+/// nobody listed are kept
+pub fn remove_paths(vfs: &VirtualFileSystem, paths: &[PathBuf]) {
+    let mut paths = paths.to_vec();
+    paths.sort_by_key(|p| std::cmp::Reverse(p.components().count()));
+    for path in paths {
+        if vfs.exists(&path) {
+            let _ = vfs.delete(&path);
+        }
+    }
+}
+
+/// Copy of the trees below some install roots, to put back when an
+/// upgrade fails halfway
+pub struct TreeBackup {
+    roots: Vec<PathBuf>,
+    /// Parents before children
+    entries: BTreeMap<PathBuf, SnapshotEntry>,
+}
+
+impl TreeBackup {
+    pub fn take(vfs: &VirtualFileSystem, roots: &[PathBuf]) -> Self {
+        let entries = vfs
+            .snapshot()
+            .entries
+            .into_iter()
+            .filter(|(path, _)| roots.iter().any(|root| path.starts_with(root)))
+            .collect();
+        TreeBackup {
+            roots: roots.to_vec(),
+            entries,
+        }
+    }
+
+    /// Remove what was added below the roots and restore the rest
+    pub fn restore(&self, vfs: &VirtualFileSystem) -> Result<(), String> {
+        let added: Vec<PathBuf> = vfs
+            .snapshot()
+            .entries
+            .into_values()
+            .filter(|entry| self.roots.iter().any(|root| entry.path.starts_with(root)))
+            .filter(|entry| self.entries.get(&entry.path).is_none_or(|saved| saved.file_type != entry.file_type))
+            .map(|entry| entry.path)
+            .collect();
+        remove_paths(vfs, &added);
+        for entry in self.entries.values() {
+            vfs.restore_entry(entry)?;
+        }
+        Ok(())
+    }
+}
+
+/// Which package installed each path
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileDatabase {
+    owners: BTreeMap<PathBuf, String>,
+}
+
+impl FileDatabase {
+    pub fn load(vfs: &VirtualFileSystem) -> Result<Self, String> {
+        let path = Path::new(FILES_DB);
+        if !vfs.exists(path) {
+            return Ok(FileDatabase::default());
+        }
+        serde_json::from_slice(&vfs.read_file(path)?).map_err(|e| format!("Invalid file database: {}", e))
+    }
+
+    pub fn save(&self, vfs: &VirtualFileSystem) -> Result<(), String> {
+        vfs.write_file(Path::new(FILES_DB), &serde_json::to_vec_pretty(self).unwrap()).map_err(String::from)
+    }
+
+    pub fn owner(&self, path: &Path) -> Option<PackageId> {
+        self.owners.get(path).map(|id| PackageId::from(id.as_str()))
+    }
+
+    /// Paths owned by a package, sorted
+    pub fn paths_of(&self, id: &PackageId) -> Vec<PathBuf> {
+        let id = id.to_string();
+        self.owners.iter().filter(|(_, owner)| **owner == id).map(|(path, _)| path.clone()).collect()
+    }
+
+    /// Record `paths` as belonging to `id`
+    pub fn claim(&mut self, id: &PackageId, paths: &[PathBuf]) {
+        for path in paths {
+            self.owners.insert(path.clone(), id.to_string());
+        }
+    }
+
+    /// Forget a package's paths, returning them
+    pub fn release(&mut self, id: &PackageId) -> Vec<PathBuf> {
+        let paths = self.paths_of(id);
+        for path in &paths {
+            self.owners.remove(path);
+        }
+        paths
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_and_remove() {
+        let vfs = VirtualFileSystem::new();
+        let archive = PackageArchive::from_json(
+            br#"{"entries":[{"path":"bin","kind":"directory","mode":493},
+                {"path":"bin/viewer","kind":"file","mode":493,"content":[1,2,3]},
+                {"path":"README","kind":"file","content":[104,105]}]}"#,
+        )
+        .unwrap();
+        let root = install_root(&PackageId::from("viewer"));
+        let created = archive.extract(&vfs, &root).unwrap();
+        assert_eq!(created, vec![root.clone(), root.join("README"), root.join("bin"), root.join("bin/viewer")]);
+        assert_eq!(vfs.read_file(&root.join("bin/viewer")).unwrap(), vec![1, 2, 3]);
+        assert_eq!(vfs.metadata(&root.join("README")).unwrap().permissions.to_mode(), 0o644);
+
+        // A file the user added keeps its directory alive
+        vfs.write_file(&root.join("notes.txt"), b"mine").unwrap();
+        remove_paths(&vfs, &created);
+        assert!(!vfs.exists(&root.join("bin")));
+        assert!(vfs.exists(&root.join("notes.txt")));
+        assert!(vfs.exists(Path::new(APPS_DIR)));
+
+        assert!(PackageArchive::from_json(br#"{"entries":[{"path":"../etc/passwd","kind":"file"}]}"#).is_err());
+        assert!(PackageArchive::from_json(br#"{"entries":[{"path":"/etc/passwd","kind":"file"}]}"#).is_err());
+        let clash = PackageArchive::from_json(br#"{"entries":[{"path":"README/inner","kind":"file"}]}"#).unwrap();
+        vfs.write_file(&root.join("README"), b"file").unwrap();
+        assert!(clash.extract(&vfs, &root).is_err());
+    }
+}
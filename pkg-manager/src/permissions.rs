@@ -0,0 +1,161 @@
+//! App capabilities
+//!
+//! A package manifest lists the files, devices, services and network hosts
+//! its app needs. The list is shown before the package is installed and
+//! recorded once it is; the app is launched in a sandbox built from those
+//! capabilities and nothing else.
+//!
+//! ```json
+//! "capabilities": [{"resource": {"Network": "api.example.org"}, "permission": "ReadWrite"},
+//!                  {"resource": {"Device": "camera"}, "permission": "Read"}]
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use capability::{Permission, Resource};
+use filesystem::VirtualFileSystem;
+use kernel::SandboxProfile;
+use serde::{Deserialize, Serialize};
+
+use crate::sandbox::{self, SandboxDatabase};
+use crate::{Package, PackageId};
+
+/// Capabilities of every installed package
+pub const CAPABILITIES_FILE: &str = "/var/lib/pkg/capabilities.json";
+
+/// Access to one resource
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CapabilityGrant {
+    pub resource: Resource,
+    pub permission: Permission,
+}
+
+impl CapabilityGrant {
+    /// Apps may only ask for files, devices, network hosts and, short of
+    /// full control, services
+    pub fn validate(&self) -> Result<(), String> {
+        match &self.resource {
+            Resource::File(_) | Resource::Device(_) | Resource::Network(_) => Ok(()),
+            Resource::IPC(_) if self.permission != Permission::Full => Ok(()),
+            other => Err(format!("Apps cannot ask for {:?} with {:?}", other, self.permission)),
+        }
+    }
+
+    /// Whether one of the `approved` grants already gives this access
+    pub fn is_covered_by(&self, approved: &[CapabilityGrant]) -> bool {
+        approved
+            .iter()
+            .any(|grant| grant.resource == self.resource && grant.permission.allows(self.permission))
+    }
+}
+
+impl fmt::Display for CapabilityGrant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let access = match self.permission {
+            Permission::Read => "read",
+            Permission::Write => "write",
+            Permission::Execute => "run",
+            Permission::ReadWrite => "read and write",
+            Permission::Full => "fully control",
+        };
+        match &self.resource {
+            Resource::File(path) => write!(f, "{} files in {}", access, path),
+            Resource::Device(device) => write!(f, "{} the {} device", access, device),
+            Resource::Network(host) => write!(f, "{} the network host {}", access, host),
+            Resource::IPC(service) => write!(f, "{} the {} service", access, service),
+            Resource::Memory(bytes) => write!(f, "{} {} bytes of memory", access, bytes),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CapabilityDatabase {
+    /// By package ID
+    packages: BTreeMap<String, Vec<CapabilityGrant>>,
+}
+
+impl CapabilityDatabase {
+    pub fn load(vfs: &VirtualFileSystem) -> Result<Self, String> {
+        let path = Path::new(CAPABILITIES_FILE);
+        if !vfs.exists(path) {
+            return Ok(CapabilityDatabase::default());
+        }
+        serde_json::from_slice(&vfs.read_file(path)?).map_err(|e| format!("Invalid capability database: {}", e))
+    }
+
+    /// Write the capabilities along with the sandbox profiles built from
+    /// them, so the two never disagree
+    pub fn save(&self, vfs: &VirtualFileSystem) -> Result<(), String> {
+        vfs.write_file(Path::new(CAPABILITIES_FILE), &serde_json::to_vec_pretty(self).unwrap())?;
+        self.sandboxes().save(vfs)
+    }
+
+    pub fn record(&mut self, package: &Package) {
+        self.packages.insert(package.id.to_string(), package.capabilities.clone());
+    }
+
+    pub fn remove(&mut self, id: &PackageId) {
+        self.packages.remove(&id.to_string());
+    }
+
+    /// What an installed app is granted at launch; `None` when no package
+    /// installed it
+    pub fn grants(&self, id: &PackageId) -> Option<Vec<(Resource, Permission)>> {
+        let grants = self.packages.get(&id.to_string())?;
+        Some(grants.iter().map(|g| (g.resource.clone(), g.permission)).collect())
+    }
+
+    /// Sandbox an installed app is launched in
+    pub fn sandbox(&self, id: &PackageId) -> Option<SandboxProfile> {
+        Some(sandbox::profile(id, self.packages.get(&id.to_string())?))
+    }
+
+    /// Sandbox profiles of every installed app
+    pub fn sandboxes(&self) -> SandboxDatabase {
+        let profiles = self
+            .packages
+            .iter()
+            .map(|(id, grants)| (id.clone(), sandbox::profile(&PackageId::from(id.as_str()), grants)))
+            .collect();
+        SandboxDatabase { profiles }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Version;
+
+    #[test]
+    fn test_capability_database() {
+        let vfs = VirtualFileSystem::new();
+        let mut package = Package::new(PackageId::from("viewer"), "Viewer".to_string(), Version::new(1, 0, 0), String::new());
+        package.capabilities = vec![CapabilityGrant {
+            resource: Resource::Network("api.example.org".to_string()),
+            permission: Permission::ReadWrite,
+        }];
+        assert_eq!(package.capabilities[0].to_string(), "read and write the network host api.example.org");
+
+        let mut database = CapabilityDatabase::default();
+        database.record(&package);
+        database.save(&vfs).unwrap();
+        let loaded = CapabilityDatabase::load(&vfs).unwrap();
+        assert_eq!(loaded, database);
+        let grants = loaded.grants(&package.id).unwrap();
+        assert_eq!(grants, vec![(Resource::Network("api.example.org".to_string()), Permission::ReadWrite)]);
+        assert!(loaded.grants(&PackageId::from("editor")).is_none());
+
+        let mut ipc = CapabilityGrant {
+            resource: Resource::IPC("session".to_string()),
+            permission: Permission::Full,
+        };
+        assert!(ipc.validate().is_err());
+        ipc.permission = Permission::Write;
+        assert!(ipc.validate().is_ok());
+        assert_eq!(ipc.to_string(), "write the session service");
+        assert_eq!(loaded.sandbox(&package.id).unwrap().root, "/apps/viewer");
+        assert_eq!(loaded.sandboxes().profiles.len(), 1);
+    }
+}
@@ -0,0 +1,128 @@
+//! Pins and holds
+//!
+//! Pinning a package to a version requirement keeps the resolver within
+//! it, and holding a package keeps it at the installed version. Both apply
+//! to every install, update and upgrade until removed, and are kept in
+//! the VFS.
+//!
+//! ```json
+//! {"pins": {"codec": {"version": "~1.4"}, "web-browser": "hold"}}
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::Path;
+
+use filesystem::VirtualFileSystem;
+use serde::{Deserialize, Serialize};
+
+use crate::semver::VersionReq;
+use crate::{Package, PackageId};
+
+/// Pins and holds of every package
+pub const PINS_FILE: &str = "/var/lib/pkg/pins.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pin {
+    /// Versions allowed, as a requirement
+    Version(String),
+    /// The installed version only
+    Hold,
+}
+
+impl fmt::Display for Pin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pin::Version(req) => write!(f, "pinned to {}", req),
+            Pin::Hold => f.write_str("held"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PinDatabase {
+    /// By package ID
+    pins: BTreeMap<String, Pin>,
+}
+
+impl PinDatabase {
+    pub fn load(vfs: &VirtualFileSystem) -> Result<Self, String> {
+        let path = Path::new(PINS_FILE);
+        if !vfs.exists(path) {
+            return Ok(PinDatabase::default());
+        }
+        let pins: PinDatabase =
+            serde_json::from_slice(&vfs.read_file(path)?).map_err(|e| format!("Invalid pin database: {}", e))?;
+        for (id, pin) in &pins.pins {
+            if let Pin::Version(req) = pin {
+                VersionReq::parse(req).map_err(|e| format!("Invalid pin database: {}: {}", id, e))?;
+            }
+        }
+        Ok(pins)
+    }
+
+    pub fn save(&self, vfs: &VirtualFileSystem) -> Result<(), String> {
+        vfs.write_file(Path::new(PINS_FILE), &serde_json::to_vec_pretty(self).unwrap()).map_err(String::from)
+    }
+
+    pub fn get(&self, id: &PackageId) -> Option<&Pin> {
+        self.pins.get(&id.to_string())
+    }
+
+    pub fn set(&mut self, id: &PackageId, pin: Pin) {
+        self.pins.insert(id.to_string(), pin);
+    }
+
+    /// Whether there was a pin to remove
+    pub fn remove(&mut self, id: &PackageId) -> bool {
+        self.pins.remove(&id.to_string()).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (PackageId, &Pin)> {
+        self.pins.iter().map(|(id, pin)| (PackageId::from(id.as_str()), pin))
+    }
+
+    /// What each pin allows; holds on packages that are not installed
+    /// allow anything
+    pub fn requirements(&self, installed: &HashMap<PackageId, Package>) -> HashMap<PackageId, VersionReq> {
+        self.iter()
+            .filter_map(|(id, pin)| {
+                let req = match pin {
+                    Pin::Version(req) => VersionReq::parse(req).unwrap(),
+                    Pin::Hold => VersionReq::parse(&format!("={}", installed.get(&id)?.version)).unwrap(),
+                };
+                Some((id, req))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Version;
+
+    #[test]
+    fn test_pin_database() {
+        let vfs = VirtualFileSystem::new();
+        let mut pins = PinDatabase::default();
+        pins.set(&PackageId::from("codec"), Pin::Version("~1.4".to_string()));
+        pins.set(&PackageId::from("browser"), Pin::Hold);
+        pins.set(&PackageId::from("viewer"), Pin::Hold);
+        pins.save(&vfs).unwrap();
+        let loaded = PinDatabase::load(&vfs).unwrap();
+        assert_eq!(loaded, pins);
+        assert_eq!(loaded.get(&PackageId::from("codec")).unwrap().to_string(), "pinned to ~1.4");
+
+        let mut installed = HashMap::new();
+        let browser = Package::new(PackageId::from("browser"), "Browser".to_string(), Version::new(2, 1, 5), String::new());
+        installed.insert(browser.id.clone(), browser);
+        let requirements = loaded.requirements(&installed);
+        assert_eq!(requirements.len(), 2);
+        assert_eq!(requirements[&PackageId::from("browser")].to_string(), "=2.1.5");
+
+        vfs.write_file(Path::new(PINS_FILE), br#"{"pins":{"codec":{"version":"1.x"}}}"#).unwrap();
+        assert!(PinDatabase::load(&vfs).is_err());
+    }
+}
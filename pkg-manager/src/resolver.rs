@@ -0,0 +1,369 @@
+//! Dependency resolution
+//!
+//! The resolver picks one version of every package an install needs,
+//! drawing on all repositories. It tries the newest version that fits
+//! every requirement seen so far and backtracks when a choice leads to a
+//! conflict further down. Installed packages keep their version, and their
+//! own requirements hold for any package the install would replace. When
+//! no set of versions works, the conflict names the package, who asked for
+//! which versions and what exists.
+
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::semver::VersionReq;
+use crate::{Package, PackageId, Repository, Version};
+
+/// One constraint on a package, with where it came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Requirement {
+    pub req: VersionReq,
+    /// Package and version asking; `None` for the user's own request
+    pub required_by: Option<(PackageId, Version)>,
+}
+
+/// Why resolution failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub package: PackageId,
+    pub requirements: Vec<Requirement>,
+    /// Version already installed, which the install may not change
+    pub installed: Option<Version>,
+    /// Requirement the user pinned the package to
+    pub pinned: Option<VersionReq>,
+    /// Versions in the repositories, newest first
+    pub available: Vec<Version>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.available.is_empty() && self.installed.is_none() {
+            write!(f, "Package {} not found in any repository", self.package)?;
+        } else {
+            write!(f, "No version of {} satisfies every requirement", self.package)?;
+        }
+        for requirement in &self.requirements {
+            match &requirement.required_by {
+                Some((id, version)) => write!(f, "\n  {} {} requires {} {}", id, version, self.package, requirement.req)?,
+                None => write!(f, "\n  requested {} {}", self.package, requirement.req)?,
+            }
+        }
+        if let Some(pinned) = &self.pinned {
+            write!(f, "\n  pinned to {}", pinned)?;
+        }
+        if let Some(installed) = &self.installed {
+            write!(f, "\n  installed: {}", installed)?;
+        }
+        if !self.available.is_empty() {
+            let versions: Vec<String> = self.available.iter().map(Version::to_string).collect();
+            write!(f, "\n  available: {}", versions.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Default)]
+struct State<'a> {
+    selected: HashMap<PackageId, &'a Package>,
+    requirements: HashMap<PackageId, Vec<Requirement>>,
+}
+
+pub struct Resolver<'a> {
+    /// Every version of every package, newest first
+    candidates: HashMap<PackageId, Vec<&'a Package>>,
+    installed: &'a HashMap<PackageId, Package>,
+    pins: HashMap<PackageId, VersionReq>,
+}
+
+impl<'a> Resolver<'a> {
+    /// Where several repositories offer the same version, the one with the
+    /// highest priority wins, then the first
+    pub fn new(repositories: &'a [Repository], installed: &'a HashMap<PackageId, Package>) -> Self {
+        let mut ordered: Vec<&'a Repository> = repositories.iter().collect();
+        ordered.sort_by_key(|r| Reverse(r.priority()));
+        let mut candidates: HashMap<PackageId, Vec<&'a Package>> = HashMap::new();
+        for package in ordered.into_iter().flat_map(Repository::packages) {
+            let versions = candidates.entry(package.id.clone()).or_default();
+            if !versions.iter().any(|p| p.version == package.version) {
+                versions.push(package);
+            }
+        }
+        for versions in candidates.values_mut() {
+            versions.sort_by(|a, b| b.version.cmp(&a.version));
+        }
+        Resolver {
+            candidates,
+            installed,
+            pins: HashMap::new(),
+        }
+    }
+
+    /// Add a version no repository lists, such as the one installed
+    pub fn offer(&mut self, package: &'a Package) {
+        let versions = self.candidates.entry(package.id.clone()).or_default();
+        if !versions.iter().any(|p| p.version == package.version) {
+            versions.push(package);
+            versions.sort_by(|a, b| b.version.cmp(&a.version));
+        }
+    }
+
+    /// Make `package` the only candidate for its ID, such as a local
+    /// package file
+    pub fn replace(&mut self, package: &'a Package) {
+        self.candidates.insert(package.id.clone(), vec![package]);
+    }
+
+    /// Keep a package within a requirement the user pinned it to
+    pub fn constrain(&mut self, id: &PackageId, req: VersionReq) {
+        self.pins.insert(id.clone(), req);
+    }
+
+    /// Allow only `version` of a package
+    pub fn pin(&mut self, id: &PackageId, version: &Version) {
+        if let Some(versions) = self.candidates.get_mut(id) {
+            versions.retain(|p| &p.version == version);
+        }
+    }
+
+    /// Packages to install for `id` at a version matching `req`,
+    /// dependencies before the packages needing them
+    pub fn resolve(&self, id: &PackageId, req: &VersionReq) -> Result<Vec<Package>, Conflict> {
+        self.resolve_all(&[(id.clone(), req.clone())])
+    }
+
+    /// Packages satisfying several requests at once, in the same order
+    pub fn resolve_all(&self, requests: &[(PackageId, VersionReq)]) -> Result<Vec<Package>, Conflict> {
+        let mut state = State::default();
+        for package in self.installed.values() {
+            for dependency in &package.dependencies {
+                state.requirements.entry(dependency.id.clone()).or_default().push(Requirement {
+                    req: dependency.req.clone(),
+                    required_by: Some((package.id.clone(), package.version.clone())),
+                });
+            }
+        }
+        let roots = requests
+            .iter()
+            .map(|(id, req)| {
+                let requirement = Requirement {
+                    req: req.clone(),
+                    required_by: None,
+                };
+                (id.clone(), requirement)
+            })
+            .collect();
+        let mut conflict = None;
+        let Some(state) = self.solve(state, roots, &mut conflict) else {
+            return Err(conflict.unwrap());
+        };
+
+        let mut order = Vec::new();
+        let mut visited = HashSet::new();
+        for (id, _) in requests {
+            self.visit(&state, id, &mut visited, &mut order);
+        }
+        Ok(order)
+    }
+
+    fn versions_of(&self, id: &PackageId) -> Vec<&'a Package> {
+        match self.installed.get(id) {
+            Some(package) => vec![package],
+            None => self.candidates.get(id).cloned().unwrap_or_default(),
+        }
+    }
+
+    /// Satisfy the pending requirements in order, trying each fitting
+    /// version of a package before giving up on the choices above it
+    fn solve(
+        &self,
+        mut state: State<'a>,
+        mut pending: Vec<(PackageId, Requirement)>,
+        conflict: &mut Option<Conflict>,
+    ) -> Option<State<'a>> {
+        while !pending.is_empty() {
+            let (id, requirement) = pending.remove(0);
+            state.requirements.entry(id.clone()).or_default().push(requirement.clone());
+            if let Some(package) = state.selected.get(&id) {
+                if requirement.req.matches(&package.version) {
+                    continue;
+                }
+                self.record(&state, &id, conflict);
+                return None;
+            }
+
+            let requirements = &state.requirements[&id];
+            let pin = self.pins.get(&id);
+            let fitting: Vec<&'a Package> = self
+                .versions_of(&id)
+                .into_iter()
+                .filter(|p| requirements.iter().all(|r| r.req.matches(&p.version)))
+                .filter(|p| pin.is_none_or(|pin| pin.matches(&p.version)))
+                .collect();
+            if fitting.is_empty() {
+                self.record(&state, &id, conflict);
+                return None;
+            }
+            for package in fitting {
+                let mut next = state.clone();
+                next.selected.insert(id.clone(), package);
+                let mut queue = pending.clone();
+                queue.extend(package.dependencies.iter().map(|dependency| {
+                    let requirement = Requirement {
+                        req: dependency.req.clone(),
+                        required_by: Some((id.clone(), package.version.clone())),
+                    };
+                    (dependency.id.clone(), requirement)
+                }));
+                if let Some(done) = self.solve(next, queue, conflict) {
+                    return Some(done);
+                }
+            }
+            return None;
+        }
+        Some(state)
+    }
+
+    /// Keep the conflict involving the most requirements; it explains the
+    /// failure better than the dead ends found while backtracking
+    fn record(&self, state: &State<'a>, id: &PackageId, conflict: &mut Option<Conflict>) {
+        let requirements = state.requirements[id].clone();
+        if conflict.as_ref().is_some_and(|c| c.requirements.len() >= requirements.len()) {
+            return;
+        }
+        *conflict = Some(Conflict {
+            package: id.clone(),
+            requirements,
+            installed: self.installed.get(id).map(|p| p.version.clone()),
+            pinned: self.pins.get(id).cloned(),
+            available: self
+                .candidates
+                .get(id)
+                .into_iter()
+                .flatten()
+                .map(|p| p.version.clone())
+                .collect(),
+        });
+    }
+
+    fn visit(&self, state: &State<'a>, id: &PackageId, visited: &mut HashSet<PackageId>, order: &mut Vec<Package>) {
+        if !visited.insert(id.clone()) || self.installed.contains_key(id) {
+            return;
+        }
+        let package = state.selected[id];
+        for dependency in &package.dependencies {
+            self.visit(state, &dependency.id, visited, order);
+        }
+        order.push(package.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Dependency;
+
+    fn package(id: &str, version: &str, dependencies: &[(&str, &str)]) -> Package {
+        let mut package = Package::new(PackageId::from(id), id.to_string(), Version::parse(version).unwrap(), String::new());
+        package.dependencies = dependencies
+            .iter()
+            .map(|(id, req)| Dependency::new(PackageId::from(*id), VersionReq::parse(req).unwrap()))
+            .collect();
+        package
+    }
+
+    fn repository(packages: Vec<Package>) -> Repository {
+        let mut repository = Repository::new("https://example.org".to_string());
+        for package in packages {
+            repository.add_package(package);
+        }
+        repository
+    }
+
+    fn plan(packages: &[Package]) -> Vec<String> {
+        packages.iter().map(|p| format!("{} {}", p.id, p.version)).collect()
+    }
+
+    #[test]
+    fn test_diamond_picks_shared_version() {
+        let repositories = [
+            repository(vec![
+                package("app", "1.0.0", &[("left", "^1"), ("right", "^1")]),
+                package("left", "1.0.0", &[("base", "^1.2")]),
+                package("right", "1.0.0", &[("base", ">=1.0, <1.3")]),
+                package("base", "1.1.0", &[]),
+                package("base", "1.2.5", &[]),
+            ]),
+            repository(vec![package("base", "1.4.0", &[])]),
+        ];
+        let installed = HashMap::new();
+        let resolver = Resolver::new(&repositories, &installed);
+        let order = resolver.resolve(&PackageId::from("app"), &VersionReq::any()).unwrap();
+        assert_eq!(plan(&order), vec!["base 1.2.5", "left 1.0.0", "right 1.0.0", "app 1.0.0"]);
+    }
+
+    #[test]
+    fn test_diamond_backtracks_to_older_version() {
+        // The newest left needs a base that right cannot use
+        let repositories = [repository(vec![
+            package("app", "1.0.0", &[("left", "*"), ("right", "^1")]),
+            package("left", "2.0.0", &[("base", "^2")]),
+            package("left", "1.5.0", &[("base", "^1")]),
+            package("right", "1.0.0", &[("base", "~1.1")]),
+            package("base", "1.1.3", &[]),
+            package("base", "2.0.0", &[]),
+        ])];
+        let installed = HashMap::new();
+        let resolver = Resolver::new(&repositories, &installed);
+        let order = resolver.resolve(&PackageId::from("app"), &VersionReq::any()).unwrap();
+        assert_eq!(plan(&order), vec!["base 1.1.3", "left 1.5.0", "right 1.0.0", "app 1.0.0"]);
+    }
+
+    #[test]
+    fn test_conflict_diagnostics() {
+        let repositories = [repository(vec![
+            package("app", "1.0.0", &[("left", "^1"), ("right", "^1")]),
+            package("left", "1.0.0", &[("base", "^1")]),
+            package("right", "1.0.0", &[("base", ">=2")]),
+            package("base", "1.3.0", &[]),
+            package("base", "2.1.0", &[]),
+            package("viewer", "1.0.0", &[("codec", "^3")]),
+        ])];
+        let installed = HashMap::new();
+        let resolver = Resolver::new(&repositories, &installed);
+        let conflict = resolver.resolve(&PackageId::from("app"), &VersionReq::any()).unwrap_err();
+        assert_eq!(conflict.package, PackageId::from("base"));
+        assert_eq!(
+            conflict.to_string(),
+            "No version of base satisfies every requirement\n  left 1.0.0 requires base ^1\n  \
+             right 1.0.0 requires base >=2\n  available: 2.1.0, 1.3.0"
+        );
+        let missing = resolver.resolve(&PackageId::from("viewer"), &VersionReq::any()).unwrap_err();
+        assert!(missing.to_string().starts_with("Package codec not found in any repository"));
+    }
+
+    #[test]
+    fn test_installed_versions_stay() {
+        let repositories = [repository(vec![
+            package("player", "1.0.0", &[("codec", "^2")]),
+            package("codec", "1.0.0", &[]),
+            package("codec", "2.0.0", &[]),
+        ])];
+        let mut installed = HashMap::new();
+        installed.insert(PackageId::from("codec"), package("codec", "1.0.0", &[]));
+        let resolver = Resolver::new(&repositories, &installed);
+        let conflict = resolver.resolve(&PackageId::from("player"), &VersionReq::any()).unwrap_err();
+        assert_eq!(conflict.installed, Some(Version::new(1, 0, 0)));
+
+        installed.insert(PackageId::from("codec"), package("codec", "2.0.0", &[]));
+        let resolver = Resolver::new(&repositories, &installed);
+        let order = resolver.resolve(&PackageId::from("player"), &VersionReq::any()).unwrap();
+        assert_eq!(plan(&order), vec!["player 1.0.0"]);
+
+        let none = HashMap::new();
+        let mut pinned = Resolver::new(&repositories, &none);
+        pinned.constrain(&PackageId::from("codec"), VersionReq::parse("^1").unwrap());
+        let conflict = pinned.resolve(&PackageId::from("player"), &VersionReq::any()).unwrap_err();
+        assert!(conflict.to_string().ends_with("player 1.0.0 requires codec ^2\n  pinned to ^1\n  available: 2.0.0, 1.0.0"));
+    }
+}
@@ -0,0 +1,103 @@
+//! Sandbox profiles
+//!
+//! Every installed app gets a sandbox profile built from its capabilities:
+//! its install root as its filesystem, the paths, devices and services it
+//! asked for, and the network hosts it may reach, with `*` meaning any
+//! host. The profiles of all apps are written to one file whenever the
+//! capabilities change, for the session manager to launch apps with.
+//!
+//! ```json
+//! {"profiles": {"viewer": {"root": "/apps/viewer", "files": [], "devices": [["gpu", "Read"]],
+//!                          "services": [], "network": "deny"}}}
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use capability::Resource;
+use filesystem::VirtualFileSystem;
+use kernel::{NetworkPolicy, SandboxProfile};
+use serde::{Deserialize, Serialize};
+
+use crate::payload::install_root;
+use crate::permissions::CapabilityGrant;
+use crate::PackageId;
+
+/// Sandbox profile of every installed app
+pub const SANDBOX_FILE: &str = "/var/lib/pkg/sandbox.json";
+
+/// Host standing for the whole network
+const ANY_HOST: &str = "*";
+
+/// Profile of an app with `grants`
+pub fn profile(id: &PackageId, grants: &[CapabilityGrant]) -> SandboxProfile {
+    let mut profile = SandboxProfile::new(install_root(id).to_string_lossy().into_owned());
+    let mut hosts = Vec::new();
+    for grant in grants {
+        match &grant.resource {
+            Resource::File(path) => profile.files.push((path.clone(), grant.permission)),
+            Resource::Device(device) => profile.devices.push((device.clone(), grant.permission)),
+            Resource::IPC(service) => profile.services.push(service.clone()),
+            Resource::Network(host) => hosts.push(host.clone()),
+            Resource::Memory(_) => {}
+        }
+    }
+    profile.network = if hosts.iter().any(|h| h == ANY_HOST) {
+        NetworkPolicy::Any
+    } else if hosts.is_empty() {
+        NetworkPolicy::Deny
+    } else {
+        NetworkPolicy::Hosts(hosts)
+    };
+    profile
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SandboxDatabase {
+    /// By package ID
+    pub profiles: BTreeMap<String, SandboxProfile>,
+}
+
+impl SandboxDatabase {
+    pub fn save(&self, vfs: &VirtualFileSystem) -> Result<(), String> {
+        vfs.write_file(Path::new(SANDBOX_FILE), &serde_json::to_vec_pretty(self).unwrap()).map_err(String::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use capability::Permission;
+
+    fn grant(resource: Resource, permission: Permission) -> CapabilityGrant {
+        CapabilityGrant { resource, permission }
+    }
+
+    #[test]
+    fn test_profile_from_capabilities() {
+        let viewer = PackageId::from("viewer");
+        let sandbox = profile(
+            &viewer,
+            &[
+                grant(Resource::File("/home/media".to_string()), Permission::Read),
+                grant(Resource::Device("gpu".to_string()), Permission::Read),
+                grant(Resource::IPC("notifications".to_string()), Permission::Write),
+                grant(Resource::Network("api.example.org".to_string()), Permission::ReadWrite),
+            ],
+        );
+        assert_eq!(sandbox.root, "/apps/viewer");
+        assert_eq!(sandbox.files, vec![("/home/media".to_string(), Permission::Read)]);
+        assert!(sandbox.allows_service("notifications"));
+        assert_eq!(sandbox.network, NetworkPolicy::Hosts(vec!["api.example.org".to_string()]));
+        let anywhere = profile(&viewer, &[grant(Resource::Network(ANY_HOST.to_string()), Permission::Read)]);
+        assert_eq!(anywhere.network, NetworkPolicy::Any);
+        assert_eq!(profile(&viewer, &[]).network, NetworkPolicy::Deny);
+
+        let vfs = VirtualFileSystem::new();
+        let mut database = SandboxDatabase::default();
+        database.profiles.insert(viewer.to_string(), sandbox);
+        database.save(&vfs).unwrap();
+        let saved: SandboxDatabase = serde_json::from_slice(&vfs.read_file(Path::new(SANDBOX_FILE)).unwrap()).unwrap();
+        assert_eq!(saved, database);
+    }
+}
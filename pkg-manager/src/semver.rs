@@ -0,0 +1,194 @@
+//! Version requirements
+//!
+//! A requirement is a comma-separated list of comparators that must all
+//! hold, following Cargo's rules: `^1.2` allows any 1.x from 1.2.0 on,
+//! `~1.2` any 1.2.x, `>=2,<3` any 2.x, and a bare version is read as a
+//! caret requirement. Versions in comparators may leave out the minor and
+//! patch numbers.
+
+use std::fmt;
+
+use crate::Version;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Exact,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    Tilde,
+    Caret,
+}
+
+impl Op {
+    fn symbol(&self) -> &'static str {
+        match self {
+            Op::Exact => "=",
+            Op::Greater => ">",
+            Op::GreaterEq => ">=",
+            Op::Less => "<",
+            Op::LessEq => "<=",
+            Op::Tilde => "~",
+            Op::Caret => "^",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Comparator {
+    op: Op,
+    major: u32,
+    minor: Option<u32>,
+    patch: Option<u32>,
+}
+
+impl Comparator {
+    fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let (op, rest) = [">=", "<=", "=", ">", "<", "~", "^"]
+            .iter()
+            .find_map(|symbol| text.strip_prefix(symbol).map(|rest| (*symbol, rest)))
+            .unwrap_or(("^", text));
+        let op = match op {
+            "=" => Op::Exact,
+            ">" => Op::Greater,
+            ">=" => Op::GreaterEq,
+            "<" => Op::Less,
+            "<=" => Op::LessEq,
+            "~" => Op::Tilde,
+            _ => Op::Caret,
+        };
+        let invalid = || format!("Invalid version requirement: {}", text);
+        let mut numbers = rest.trim().split('.').map(|n| n.parse::<u32>().map_err(|_| invalid()));
+        let major = numbers.next().ok_or_else(invalid)??;
+        let minor = numbers.next().transpose()?;
+        let patch = numbers.next().transpose()?;
+        if numbers.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(Comparator { op, major, minor, patch })
+    }
+
+    /// Lowest version the comparator names, missing parts as zero
+    fn floor(&self) -> (u32, u32, u32) {
+        (self.major, self.minor.unwrap_or(0), self.patch.unwrap_or(0))
+    }
+
+    /// First version past the ones a partial version names: `1.2` names
+    /// every 1.2.x, so the bound is 1.3.0
+    fn ceiling(&self) -> (u32, u32, u32) {
+        match (self.minor, self.patch) {
+            (None, _) => (self.major + 1, 0, 0),
+            (Some(minor), None) => (self.major, minor + 1, 0),
+            (Some(minor), Some(patch)) => (self.major, minor, patch + 1),
+        }
+    }
+
+    fn matches(&self, version: &Version) -> bool {
+        let v = (version.major, version.minor, version.patch);
+        match self.op {
+            Op::Exact => self.floor() <= v && v < self.ceiling(),
+            Op::Greater => v >= self.ceiling(),
+            Op::GreaterEq => v >= self.floor(),
+            Op::Less => v < self.floor(),
+            Op::LessEq => v < self.ceiling(),
+            Op::Tilde => {
+                let upper = match self.minor {
+                    None => (self.major + 1, 0, 0),
+                    Some(minor) => (self.major, minor + 1, 0),
+                };
+                self.floor() <= v && v < upper
+            }
+            Op::Caret => {
+                // The leftmost non-zero part may not change
+                let upper = match (self.major, self.minor, self.patch) {
+                    (0, None, _) => (1, 0, 0),
+                    (0, Some(0), None) => (0, 1, 0),
+                    (0, Some(0), Some(patch)) => (0, 0, patch + 1),
+                    (0, Some(minor), _) => (0, minor + 1, 0),
+                    (major, ..) => (major + 1, 0, 0),
+                };
+                self.floor() <= v && v < upper
+            }
+        }
+    }
+}
+
+impl fmt::Display for Comparator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.op.symbol(), self.major)?;
+        if let Some(minor) = self.minor {
+            write!(f, ".{}", minor)?;
+        }
+        if let Some(patch) = self.patch {
+            write!(f, ".{}", patch)?;
+        }
+        Ok(())
+    }
+}
+
+/// Versions a dependency accepts
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VersionReq {
+    /// All must match; none means any version
+    comparators: Vec<Comparator>,
+}
+
+impl VersionReq {
+    /// Any version at all
+    pub fn any() -> Self {
+        VersionReq::default()
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        if text.trim() == "*" {
+            return Ok(VersionReq::any());
+        }
+        let comparators = text.split(',').map(Comparator::parse).collect::<Result<_, _>>()?;
+        Ok(VersionReq { comparators })
+    }
+
+    pub fn matches(&self, version: &Version) -> bool {
+        self.comparators.iter().all(|c| c.matches(version))
+    }
+}
+
+impl fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.comparators.is_empty() {
+            return f.write_str("*");
+        }
+        let parts: Vec<String> = self.comparators.iter().map(Comparator::to_string).collect();
+        f.write_str(&parts.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accepts(req: &str, versions: &[&str]) -> Vec<bool> {
+        let req = VersionReq::parse(req).unwrap();
+        versions.iter().map(|v| req.matches(&Version::parse(v).unwrap())).collect()
+    }
+
+    #[test]
+    fn test_requirement_matching() {
+        let versions = ["0.9.0", "1.2.0", "1.2.7", "1.9.0", "2.0.0", "2.4.1", "3.0.0"];
+        assert_eq!(accepts("^1.2", &versions), [false, true, true, true, false, false, false]);
+        assert_eq!(accepts("1.2.5", &versions), [false, false, true, true, false, false, false]);
+        assert_eq!(accepts("~1.2", &versions), [false, true, true, false, false, false, false]);
+        assert_eq!(accepts(">=2,<3", &versions), [false, false, false, false, true, true, false]);
+        assert_eq!(accepts(">1.2, <=2", &versions), [false, false, false, true, true, true, false]);
+        assert_eq!(accepts("=1.2", &versions), [false, true, true, false, false, false, false]);
+        assert_eq!(accepts("*", &versions), [true; 7]);
+        assert_eq!(accepts("^0.9", &["0.9.3", "0.10.0"]), [true, false]);
+        assert_eq!(accepts("^0.0.3", &["0.0.3", "0.0.4"]), [true, false]);
+
+        assert_eq!(VersionReq::parse(">=2,<3").unwrap().to_string(), ">=2, <3");
+        assert!(VersionReq::parse("^1.x").is_err());
+        assert!(VersionReq::parse("1.2.3.4").is_err());
+        assert!(VersionReq::parse(">=1,").is_err());
+    }
+}
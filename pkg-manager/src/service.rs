@@ -0,0 +1,167 @@
+//! Package service
+//!
+//! Other programs, such as the app store, drive the package manager over
+//! IPC. A request names a transaction; while it runs, progress events are
+//! sent to the reply channel as JSON text messages, followed by the
+//! response. The installed list always comes from the package database.
+//!
+//! ```json
+//! {"op": "install", "id": "viewer"}
+//! {"event": "downloading", "id": "viewer", "version": "1.2.0"}
+//! {"result": "ok"}
+//! ```
+
+use std::sync::Arc;
+
+use ipc::service::handle_json;
+use ipc::{ChannelId, IPCManager, Message};
+use serde::{Deserialize, Serialize};
+
+use crate::{PackageId, PackageManager};
+
+/// Callback receiving transaction progress
+pub type EventListener = Arc<dyn Fn(&PackageEvent) + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum PackageEvent {
+    Downloading { id: String, version: String },
+    Installing { id: String, version: String },
+    Removing { id: String },
+}
+
+/// Requests accepted over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum PackageRequest {
+    List,
+    Install { id: String },
+    Uninstall { id: String },
+    Update { id: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstalledPackage {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+}
+
+/// Responses sent over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum PackageResponse {
+    Installed { packages: Vec<InstalledPackage> },
+    Ok,
+    Error { message: String },
+}
+
+impl PackageManager {
+    pub fn subscribe(&mut self, listener: EventListener) {
+        self.listeners.push(listener);
+    }
+
+    pub(crate) fn emit(&self, event: PackageEvent) {
+        for listener in &self.listeners {
+            listener(&event);
+        }
+    }
+
+    pub fn handle_message(&mut self, message: &Message) -> Option<Message> {
+        handle_json("package", message, |request| self.handle_request(request))
+    }
+
+    pub fn handle_request(&mut self, request: PackageRequest) -> PackageResponse {
+        let result = match request {
+            PackageRequest::List => {
+                let mut packages: Vec<InstalledPackage> = self
+                    .list_installed()
+                    .into_iter()
+                    .map(|p| InstalledPackage {
+                        id: p.id.to_string(),
+                        name: p.name.clone(),
+                        version: p.version.to_string(),
+                    })
+                    .collect();
+                packages.sort_by(|a, b| a.id.cmp(&b.id));
+                Ok(PackageResponse::Installed { packages })
+            }
+            PackageRequest::Install { id } => self.install(&PackageId::from(id)).map(|_| PackageResponse::Ok),
+            PackageRequest::Uninstall { id } => self.uninstall(&PackageId::from(id)).map(|_| PackageResponse::Ok),
+            PackageRequest::Update { id } => self.update(&PackageId::from(id)).map(|_| PackageResponse::Ok),
+        };
+        result.unwrap_or_else(|message| PackageResponse::Error { message })
+    }
+
+    /// Answer every request waiting on `requests`, streaming each
+    /// transaction's events to `replies` ahead of its response; returns how
+    /// many requests were handled
+    pub fn serve(&mut self, ipc: &Arc<IPCManager>, requests: ChannelId, replies: ChannelId) -> Result<usize, String> {
+        let forward = Arc::clone(ipc);
+        self.listeners.push(Arc::new(move |event: &PackageEvent| {
+            let _ = forward.send_message(replies, Message::Text(serde_json::to_string(event).unwrap()));
+        }));
+        let mut handled = 0;
+        let result = loop {
+            let message = match ipc.receive_message(requests) {
+                Ok(Some(message)) => message,
+                Ok(None) => break Ok(handled),
+                Err(e) => break Err(e),
+            };
+            if let Some(reply) = self.handle_message(&message) {
+                if let Err(e) = ipc.send_message(replies, reply) {
+                    break Err(e);
+                }
+            }
+            handled += 1;
+        };
+        self.listeners.pop();
+        result.map_err(String::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ipc::ERROR_BAD_REQUEST;
+
+    #[test]
+    fn test_transactions_over_ipc() {
+        let ipc = Arc::new(IPCManager::new());
+        let (requests, replies) = (ipc.create_channel(), ipc.create_channel());
+        let mut manager = PackageManager::new();
+        let send = |id: u64, request: &str| {
+            ipc.send_message(requests, Message::Request { id, data: request.as_bytes().to_vec() }).unwrap();
+        };
+        send(1, r#"{"op":"install","id":"web-browser"}"#);
+        send(2, r#"{"op":"list"}"#);
+        send(3, r#"{"op":"uninstall","id":"text-editor"}"#);
+        send(4, r#"{"op":"launch"}"#);
+        assert_eq!(manager.serve(&ipc, requests, replies).unwrap(), 4);
+
+        let mut received = Vec::new();
+        while let Some(message) = ipc.receive_message(replies).unwrap() {
+            received.push(message);
+        }
+        let Message::Text(event) = &received[0] else {
+            panic!("expected an event first: {:?}", received);
+        };
+        let event: PackageEvent = serde_json::from_str(event).unwrap();
+        assert_eq!(event, PackageEvent::Installing { id: "web-browser".to_string(), version: "2.1.5".to_string() });
+        let responses: Vec<PackageResponse> = received
+            .iter()
+            .filter_map(|m| match m {
+                Message::Response { data, .. } => Some(serde_json::from_slice(data).unwrap()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(responses[0], PackageResponse::Ok);
+        let PackageResponse::Installed { packages } = &responses[1] else {
+            panic!("expected the installed list: {:?}", responses[1]);
+        };
+        assert_eq!(packages[0].id, "web-browser");
+        assert!(matches!(responses[2], PackageResponse::Error { .. }));
+        assert!(matches!(received.last(), Some(Message::Error { code: ERROR_BAD_REQUEST, .. })));
+        assert!(manager.listeners.is_empty());
+    }
+}
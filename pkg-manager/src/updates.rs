@@ -0,0 +1,163 @@
+//! Upgrades
+//!
+//! An upgrade moves every installed package to the newest version that
+//! still fits what the other installed packages, at their own new
+//! versions, require. Nothing is downgraded, packages no repository
+//! offers keep their version, and new dependencies are installed along
+//! the way. `check-updates` prints the same plan as JSON for the app
+//! store's Updates tab, with the changelog of every version the update
+//! skips over:
+//!
+//! ```json
+//! [{"id": "web-browser", "name": "Web Browser", "installed": "2.1.5", "available": "2.2.0",
+//!   "changelog": [{"version": "2.2.0", "notes": "Tab groups"}, {"version": "2.1.6", "notes": "Security fixes"}]}]
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::resolver::{Conflict, Resolver};
+use crate::semver::VersionReq;
+use crate::{Package, PackageId, Repository, Version};
+
+/// What changed in one version
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangelogEntry {
+    pub version: String,
+    pub notes: String,
+}
+
+/// An installed package with a newer version to move to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateInfo {
+    pub id: String,
+    pub name: String,
+    pub installed: String,
+    pub available: String,
+    /// Versions after the installed one up to the available one, newest
+    /// first
+    #[serde(default)]
+    pub changelog: Vec<ChangelogEntry>,
+}
+
+impl UpdateInfo {
+    pub fn new(repositories: &[Repository], current: &Package, target: &Package) -> Self {
+        UpdateInfo {
+            id: current.id.to_string(),
+            name: current.name.clone(),
+            installed: current.version.to_string(),
+            available: target.version.to_string(),
+            changelog: changelog(repositories, &current.id, &current.version, &target.version),
+        }
+    }
+}
+
+/// Notes of the published versions after `from` up to `to`, newest first;
+/// versions without notes are left out
+pub fn changelog(repositories: &[Repository], id: &PackageId, from: &Version, to: &Version) -> Vec<ChangelogEntry> {
+    let mut notes: BTreeMap<&Version, &str> = BTreeMap::new();
+    for package in repositories.iter().flat_map(|r| r.versions(id)) {
+        if &package.version > from && &package.version <= to && !package.changelog.is_empty() {
+            notes.entry(&package.version).or_insert(&package.changelog);
+        }
+    }
+    notes
+        .into_iter()
+        .rev()
+        .map(|(version, notes)| ChangelogEntry {
+            version: version.to_string(),
+            notes: notes.to_string(),
+        })
+        .collect()
+}
+
+/// Packages to install for a full upgrade within the pins, dependencies
+/// first; versions already installed are left out
+pub fn upgrade_plan(
+    repositories: &[Repository],
+    installed: &HashMap<PackageId, Package>,
+    pins: &HashMap<PackageId, VersionReq>,
+) -> Result<Vec<Package>, Conflict> {
+    // Nothing is locked; every installed version stays a candidate
+    let unlocked = HashMap::new();
+    let mut resolver = Resolver::new(repositories, &unlocked);
+    for (id, req) in pins {
+        resolver.constrain(id, req.clone());
+    }
+    let mut requests = Vec::new();
+    for package in installed.values() {
+        resolver.offer(package);
+        let req = VersionReq::parse(&format!(">={}", package.version)).unwrap();
+        requests.push((package.id.clone(), req));
+    }
+    requests.sort_by_key(|(id, _)| id.to_string());
+    let plan = resolver.resolve_all(&requests)?;
+    Ok(plan
+        .into_iter()
+        .filter(|p| installed.get(&p.id).is_none_or(|current| current.version != p.version))
+        .collect())
+}
+
+/// The upgrades in a plan, leaving out newly added dependencies
+pub fn updates_in(repositories: &[Repository], plan: &[Package], installed: &HashMap<PackageId, Package>) -> Vec<UpdateInfo> {
+    plan.iter()
+        .filter_map(|package| Some(UpdateInfo::new(repositories, installed.get(&package.id)?, package)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Dependency, Version};
+
+    fn package(id: &str, version: &str, dependencies: &[(&str, &str)]) -> Package {
+        let mut package = Package::new(PackageId::from(id), id.to_string(), Version::parse(version).unwrap(), String::new());
+        package.changelog = format!("Changes in {}", version);
+        package.dependencies = dependencies
+            .iter()
+            .map(|(id, req)| Dependency::new(PackageId::from(*id), VersionReq::parse(req).unwrap()))
+            .collect();
+        package
+    }
+
+    #[test]
+    fn test_upgrade_plan() {
+        let mut repository = Repository::new("https://example.org".to_string());
+        for p in [
+            package("player", "1.0.0", &[("codec", "^1")]),
+            package("player", "2.0.0", &[("codec", "^2"), ("fonts", "*")]),
+            package("codec", "1.0.0", &[]),
+            package("codec", "1.5.0", &[]),
+            package("codec", "2.1.0", &[]),
+            package("fonts", "1.0.0", &[]),
+            package("editor", "0.9.0", &[("codec", "^1")]),
+        ] {
+            repository.add_package(p);
+        }
+        let mut installed = HashMap::new();
+        for p in [
+            package("player", "1.0.0", &[("codec", "^1")]),
+            package("codec", "1.0.0", &[]),
+            package("local", "3.0.0", &[]),
+        ] {
+            installed.insert(p.id.clone(), p);
+        }
+        let repositories = [repository];
+        let plan = upgrade_plan(&repositories, &installed, &HashMap::new()).unwrap();
+        let names: Vec<String> = plan.iter().map(|p| format!("{} {}", p.id, p.version)).collect();
+        assert_eq!(names, vec!["codec 2.1.0", "fonts 1.0.0", "player 2.0.0"]);
+        let updates = updates_in(&repositories, &plan, &installed);
+        assert_eq!(updates.len(), 2);
+        assert_eq!((updates[0].installed.as_str(), updates[0].available.as_str()), ("1.0.0", "2.1.0"));
+        let versions: Vec<&str> = updates[0].changelog.iter().map(|c| c.version.as_str()).collect();
+        assert_eq!(versions, vec!["2.1.0", "1.5.0"]);
+        assert_eq!(updates[0].changelog[1].notes, "Changes in 1.5.0");
+
+        // An installed package pinning the codec to 1.x holds back both
+        installed.insert(PackageId::from("editor"), package("editor", "0.9.0", &[("codec", "^1")]));
+        let plan = upgrade_plan(&repositories, &installed, &HashMap::new()).unwrap();
+        let names: Vec<String> = plan.iter().map(|p| format!("{} {}", p.id, p.version)).collect();
+        assert_eq!(names, vec!["codec 1.5.0"]);
+    }
+}
@@ -0,0 +1,471 @@
+//! Accessibility Service
+//!
+//! Applications publish their UI element trees over IPC. The screen reader
+//! tracks keyboard focus within the active application and turns focus
+//! changes, announcements and read-all requests into speech events for a
+//! speech synthesizer. The service also owns the system-wide text scale
+//! and high-contrast settings and tells subscribers when they change.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use ipc::JsonService;
+use serde::{Deserialize, Serialize};
+
+pub mod tree;
+
+pub use tree::{Element, ElementId, ElementState, Role};
+
+/// Allowed range of the text scale factor
+pub const MIN_TEXT_SCALE: f32 = 0.5;
+pub const MAX_TEXT_SCALE: f32 = 3.0;
+
+/// How urgently a speech event should be spoken
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    /// Spoken after whatever is being said
+    Polite,
+    /// Interrupts current speech
+    Assertive,
+}
+
+/// Text handed to the speech synthesizer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpeechEvent {
+    pub text: String,
+    pub priority: Priority,
+}
+
+/// System-wide accessibility settings
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AccessibilitySettings {
+    pub screen_reader: bool,
+    pub high_contrast: bool,
+    /// Factor applied to every font size
+    pub text_scale: f32,
+}
+
+impl Default for AccessibilitySettings {
+    fn default() -> Self {
+        AccessibilitySettings {
+            screen_reader: false,
+            high_contrast: false,
+            text_scale: 1.0,
+        }
+    }
+}
+
+impl AccessibilitySettings {
+    /// Font size after applying the text scale
+    pub fn scaled(&self, size: u32) -> u32 {
+        (size as f32 * self.text_scale).round().max(1.0) as u32
+    }
+}
+
+/// Callback run for every speech event
+pub type SpeechListener = Arc<dyn Fn(&SpeechEvent) + Send + Sync>;
+
+/// Callback run after the settings change
+pub type SettingsListener = Arc<dyn Fn(&AccessibilitySettings) + Send + Sync>;
+
+struct AppTree {
+    root: Element,
+    focus: Option<ElementId>,
+}
+
+/// Accessibility daemon and screen reader
+pub struct AccessibilityService {
+    apps: Arc<Mutex<HashMap<String, AppTree>>>,
+    active_app: Arc<Mutex<Option<String>>>,
+    settings: Arc<Mutex<AccessibilitySettings>>,
+    speech: Arc<Mutex<Vec<SpeechEvent>>>,
+    speech_listeners: Arc<Mutex<Vec<SpeechListener>>>,
+    settings_listeners: Arc<Mutex<Vec<SettingsListener>>>,
+}
+
+impl AccessibilityService {
+    pub fn new() -> Self {
+        AccessibilityService {
+            apps: Arc::new(Mutex::new(HashMap::new())),
+            active_app: Arc::new(Mutex::new(None)),
+            settings: Arc::new(Mutex::new(AccessibilitySettings::default())),
+            speech: Arc::new(Mutex::new(Vec::new())),
+            speech_listeners: Arc::new(Mutex::new(Vec::new())),
+            settings_listeners: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn settings(&self) -> AccessibilitySettings {
+        *self.settings.lock().unwrap()
+    }
+
+    /// Change the settings, notifying subscribers
+    pub fn set_settings(&self, settings: AccessibilitySettings) -> Result<(), String> {
+        if !(MIN_TEXT_SCALE..=MAX_TEXT_SCALE).contains(&settings.text_scale) {
+            return Err(format!(
+                "Text scale must be between {} and {}",
+                MIN_TEXT_SCALE, MAX_TEXT_SCALE
+            ));
+        }
+        let previous = std::mem::replace(&mut *self.settings.lock().unwrap(), settings);
+        if previous == settings {
+            return Ok(());
+        }
+        if settings.screen_reader != previous.screen_reader {
+            let state = if settings.screen_reader { "on" } else { "off" };
+            self.speak_always(&format!("Screen reader {}", state), Priority::Assertive);
+        }
+        let listeners: Vec<SettingsListener> = self.settings_listeners.lock().unwrap().clone();
+        for listener in listeners {
+            listener(&settings);
+        }
+        Ok(())
+    }
+
+    pub fn on_settings_change(&self, listener: SettingsListener) {
+        self.settings_listeners.lock().unwrap().push(listener);
+    }
+
+    /// Deliver speech events to `listener` as they are produced
+    pub fn on_speech(&self, listener: SpeechListener) {
+        self.speech_listeners.lock().unwrap().push(listener);
+    }
+
+    /// Drain the speech events produced so far
+    pub fn take_speech(&self) -> Vec<SpeechEvent> {
+        std::mem::take(&mut *self.speech.lock().unwrap())
+    }
+
+    fn speak(&self, text: &str, priority: Priority) {
+        if self.settings.lock().unwrap().screen_reader {
+            self.speak_always(text, priority);
+        }
+    }
+
+    fn speak_always(&self, text: &str, priority: Priority) {
+        let event = SpeechEvent {
+            text: text.to_string(),
+            priority,
+        };
+        self.speech.lock().unwrap().push(event.clone());
+        let listeners: Vec<SpeechListener> = self.speech_listeners.lock().unwrap().clone();
+        for listener in listeners {
+            listener(&event);
+        }
+    }
+
+    /// Publish or replace an application's element tree
+    pub fn publish_tree(&self, app: &str, root: Element) {
+        let mut apps = self.apps.lock().unwrap();
+        let focus = apps
+            .get(app)
+            .and_then(|tree| tree.focus)
+            .filter(|id| root.focus_order().contains(id));
+        apps.insert(app.to_string(), AppTree { root, focus });
+    }
+
+    /// Replace one subtree; a change to the focused element is read out
+    pub fn update_element(&self, app: &str, element: Element) -> Result<(), String> {
+        let spoken = {
+            let mut apps = self.apps.lock().unwrap();
+            let tree = apps.get_mut(app).ok_or_else(|| format!("No element tree for {}", app))?;
+            let id = element.id;
+            if !tree.root.replace(element) {
+                return Err(format!("Element {} not found", id.value()));
+            }
+            if tree.focus.is_some_and(|focus| !tree.root.focus_order().contains(&focus)) {
+                tree.focus = None;
+            }
+            let active = self.active_app.lock().unwrap().as_deref() == Some(app);
+            match tree.focus {
+                Some(focus) if active && tree.root.find(focus).is_some_and(|e| e.find(id).is_some()) => {
+                    tree.root.find(focus).map(Element::describe)
+                }
+                _ => None,
+            }
+        };
+        if let Some(text) = spoken {
+            self.speak(&text, Priority::Polite);
+        }
+        Ok(())
+    }
+
+    /// Forget an application's tree, as when it exits
+    pub fn remove_app(&self, app: &str) {
+        self.apps.lock().unwrap().remove(app);
+        let mut active = self.active_app.lock().unwrap();
+        if active.as_deref() == Some(app) {
+            *active = None;
+        }
+    }
+
+    /// Make `app` the one keyboard focus and the reader follow
+    pub fn activate_app(&self, app: &str) -> Result<(), String> {
+        let text = {
+            let apps = self.apps.lock().unwrap();
+            let tree = apps.get(app).ok_or_else(|| format!("No element tree for {}", app))?;
+            let mut text = tree.root.describe();
+            if let Some(focused) = tree.focus.and_then(|id| tree.root.find(id)) {
+                text = format!("{}; {}", text, focused.describe());
+            }
+            text
+        };
+        *self.active_app.lock().unwrap() = Some(app.to_string());
+        self.speak(&text, Priority::Assertive);
+        Ok(())
+    }
+
+    pub fn active_app(&self) -> Option<String> {
+        self.active_app.lock().unwrap().clone()
+    }
+
+    /// Focused element of the active application
+    pub fn focused(&self) -> Option<Element> {
+        let app = self.active_app()?;
+        let apps = self.apps.lock().unwrap();
+        let tree = apps.get(&app)?;
+        tree.focus.and_then(|id| tree.root.find(id)).cloned()
+    }
+
+    /// Move focus to an element of the active application
+    pub fn focus(&self, id: ElementId) -> Result<(), String> {
+        self.move_focus(|order, _| order.iter().position(|e| *e == id).ok_or("Element cannot take focus".to_string()))
+    }
+
+    /// Move focus to the next focusable element, wrapping around
+    pub fn focus_next(&self) -> Result<(), String> {
+        self.move_focus(|order, current| {
+            if order.is_empty() {
+                return Err("Nothing to focus".to_string());
+            }
+            Ok(current.map_or(0, |i| (i + 1) % order.len()))
+        })
+    }
+
+    /// Move focus to the previous focusable element, wrapping around
+    pub fn focus_previous(&self) -> Result<(), String> {
+        self.move_focus(|order, current| {
+            if order.is_empty() {
+                return Err("Nothing to focus".to_string());
+            }
+            Ok(current.map_or(order.len() - 1, |i| (i + order.len() - 1) % order.len()))
+        })
+    }
+
+    fn move_focus(
+        &self,
+        pick: impl FnOnce(&[ElementId], Option<usize>) -> Result<usize, String>,
+    ) -> Result<(), String> {
+        let app = self.active_app().ok_or("No active application")?;
+        let text = {
+            let mut apps = self.apps.lock().unwrap();
+            let tree = apps.get_mut(&app).ok_or("No active application")?;
+            let order = tree.root.focus_order();
+            let current = tree.focus.and_then(|id| order.iter().position(|e| *e == id));
+            let index = pick(&order, current)?;
+            tree.focus = Some(order[index]);
+            tree.root.find(order[index]).map(Element::describe).unwrap_or_default()
+        };
+        self.speak(&text, Priority::Assertive);
+        Ok(())
+    }
+
+    /// Speak every visible element of the active application in order
+    pub fn read_all(&self) -> Result<(), String> {
+        let app = self.active_app().ok_or("No active application")?;
+        let lines: Vec<String> = {
+            let apps = self.apps.lock().unwrap();
+            let tree = apps.get(&app).ok_or("No active application")?;
+            tree.root
+                .visible()
+                .into_iter()
+                .filter(|e| !(e.role == Role::Group && e.name.is_empty()))
+                .map(Element::describe)
+                .collect()
+        };
+        for line in lines {
+            self.speak(&line, Priority::Polite);
+        }
+        Ok(())
+    }
+
+    /// Speak a message on behalf of an application or the shell
+    pub fn announce(&self, text: &str, priority: Priority) {
+        self.speak(text, priority);
+    }
+}
+
+impl JsonService for AccessibilityService {
+    type Request = AccessibilityRequest;
+    type Response = AccessibilityResponse;
+    const KIND: &'static str = "accessibility";
+
+    fn handle_request(&self, request: AccessibilityRequest) -> AccessibilityResponse {
+        let result = match request {
+            AccessibilityRequest::PublishTree { app, root } => {
+                self.publish_tree(&app, root);
+                Ok(AccessibilityResponse::Ok)
+            }
+            AccessibilityRequest::UpdateElement { app, element } => {
+                self.update_element(&app, element).map(|_| AccessibilityResponse::Ok)
+            }
+            AccessibilityRequest::RemoveApp { app } => {
+                self.remove_app(&app);
+                Ok(AccessibilityResponse::Ok)
+            }
+            AccessibilityRequest::Focus { id } => self.focus(id).map(|_| AccessibilityResponse::Ok),
+            AccessibilityRequest::Announce { text, priority } => {
+                self.announce(&text, priority);
+                Ok(AccessibilityResponse::Ok)
+            }
+            AccessibilityRequest::GetSettings => Ok(AccessibilityResponse::Settings {
+                settings: self.settings(),
+            }),
+            AccessibilityRequest::SetSettings { settings } => {
+                self.set_settings(settings).map(|_| AccessibilityResponse::Ok)
+            }
+        };
+        result.unwrap_or_else(|message| AccessibilityResponse::Error { message })
+    }
+}
+
+impl Default for AccessibilityService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Request accepted over IPC
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum AccessibilityRequest {
+    PublishTree { app: String, root: Element },
+    UpdateElement { app: String, element: Element },
+    RemoveApp { app: String },
+    Focus { id: ElementId },
+    Announce { text: String, priority: Priority },
+    GetSettings,
+    SetSettings { settings: AccessibilitySettings },
+}
+
+/// Reply sent over IPC
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum AccessibilityResponse {
+    Settings { settings: AccessibilitySettings },
+    Ok,
+    Error { message: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ipc::{Message, ERROR_BAD_REQUEST};
+
+    fn dialog() -> Element {
+        Element::new(1, Role::Window, "Save changes")
+            .with_child(Element::new(2, Role::Label, "Save before closing?"))
+            .with_child(
+                Element::new(3, Role::Group, "")
+                    .with_child(Element::new(4, Role::Button, "Save"))
+                    .with_child(Element::new(5, Role::Button, "Discard")),
+            )
+    }
+
+    fn reader() -> AccessibilityService {
+        let service = AccessibilityService::new();
+        service
+            .set_settings(AccessibilitySettings {
+                screen_reader: true,
+                ..AccessibilitySettings::default()
+            })
+            .unwrap();
+        service.take_speech();
+        service
+    }
+
+    fn spoken(service: &AccessibilityService) -> Vec<String> {
+        service.take_speech().into_iter().map(|e| e.text).collect()
+    }
+
+    #[test]
+    fn test_keyboard_navigation_speech() {
+        let service = reader();
+        service.publish_tree("editor", dialog());
+        service.activate_app("editor").unwrap();
+        service.focus_next().unwrap();
+        service.focus_next().unwrap();
+        service.focus_next().unwrap();
+        service.focus_previous().unwrap();
+        assert_eq!(
+            spoken(&service),
+            vec!["Save changes, window", "Save, button", "Discard, button", "Save, button", "Discard, button"]
+        );
+        assert_eq!(service.focused().unwrap().id, ElementId::new(5));
+        assert!(service.focus(ElementId::new(2)).is_err());
+
+        service.read_all().unwrap();
+        assert_eq!(spoken(&service).len(), 4);
+    }
+
+    #[test]
+    fn test_focused_element_updates_are_read() {
+        let service = reader();
+        let form = Element::new(1, Role::Window, "Settings").with_child(Element::new(2, Role::CheckBox, "Wi-Fi").with_state(
+            ElementState {
+                checked: Some(false),
+                ..ElementState::default()
+            },
+        ));
+        service.publish_tree("settings", form);
+        service.activate_app("settings").unwrap();
+        service.focus(ElementId::new(2)).unwrap();
+        service.take_speech();
+
+        let toggled = Element::new(2, Role::CheckBox, "Wi-Fi").with_state(ElementState {
+            checked: Some(true),
+            ..ElementState::default()
+        });
+        service.update_element("settings", toggled).unwrap();
+        assert_eq!(spoken(&service), vec!["Wi-Fi, check box, checked"]);
+        assert!(service.update_element("settings", Element::new(9, Role::Label, "")).is_err());
+
+        // Nothing is spoken once the reader is turned off
+        service.set_settings(AccessibilitySettings::default()).unwrap();
+        assert_eq!(spoken(&service), vec!["Screen reader off"]);
+        service.announce("Download complete", Priority::Polite);
+        assert!(service.take_speech().is_empty());
+    }
+
+    #[test]
+    fn test_settings_over_ipc() {
+        let service = AccessibilityService::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        service.on_settings_change(Arc::new(move |settings| log.lock().unwrap().push(settings.text_scale)));
+
+        let message = Message::Request {
+            id: 1,
+            data: br#"{"op":"set_settings","settings":{"screen_reader":false,"high_contrast":true,"text_scale":1.5}}"#.to_vec(),
+        };
+        let Some(Message::Response { data, .. }) = service.handle_message(&message) else {
+            panic!("expected a response");
+        };
+        assert_eq!(serde_json::from_slice::<AccessibilityResponse>(&data).unwrap(), AccessibilityResponse::Ok);
+        assert!(service.settings().high_contrast);
+        assert_eq!(service.settings().scaled(16), 24);
+        assert_eq!(*seen.lock().unwrap(), vec![1.5]);
+
+        let too_big = AccessibilitySettings {
+            text_scale: 10.0,
+            ..AccessibilitySettings::default()
+        };
+        assert!(matches!(
+            service.handle_request(AccessibilityRequest::SetSettings { settings: too_big }),
+            AccessibilityResponse::Error { .. }
+        ));
+        let bad = Message::Request { id: 2, data: b"{\"op\":\"focus\"}".to_vec() };
+        assert!(matches!(service.handle_message(&bad), Some(Message::Error { code: ERROR_BAD_REQUEST, .. })));
+    }
+}
@@ -0,0 +1,244 @@
+//! UI element trees
+//!
+//! Applications describe their interface as a tree of elements with a
+//! role, an accessible name and state flags. The screen reader only ever
+//! sees this tree, never the pixels.
+
+use serde::{Deserialize, Serialize};
+
+/// Element identifier, unique within one application's tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ElementId(u64);
+
+impl ElementId {
+    pub fn new(id: u64) -> Self {
+        ElementId(id)
+    }
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+/// What kind of control an element is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Window,
+    Group,
+    Label,
+    Button,
+    CheckBox,
+    TextField,
+    List,
+    ListItem,
+    Menu,
+    MenuItem,
+    Link,
+    Image,
+}
+
+impl Role {
+    /// Spoken name of the role
+    pub fn label(&self) -> &'static str {
+        match self {
+            Role::Window => "window",
+            Role::Group => "group",
+            Role::Label => "text",
+            Role::Button => "button",
+            Role::CheckBox => "check box",
+            Role::TextField => "text field",
+            Role::List => "list",
+            Role::ListItem => "list item",
+            Role::Menu => "menu",
+            Role::MenuItem => "menu item",
+            Role::Link => "link",
+            Role::Image => "image",
+        }
+    }
+
+    /// Whether keyboard focus lands on elements of this role by default
+    pub fn focusable(&self) -> bool {
+        matches!(
+            self,
+            Role::Button | Role::CheckBox | Role::TextField | Role::ListItem | Role::MenuItem | Role::Link
+        )
+    }
+}
+
+/// Dynamic state of an element
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ElementState {
+    #[serde(default)]
+    pub disabled: bool,
+    #[serde(default)]
+    pub checked: Option<bool>,
+    #[serde(default)]
+    pub expanded: Option<bool>,
+    /// Not shown on screen and skipped by the reader
+    #[serde(default)]
+    pub hidden: bool,
+}
+
+/// Node of an application's element tree
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Element {
+    pub id: ElementId,
+    pub role: Role,
+    /// Accessible name, such as a button's caption
+    #[serde(default)]
+    pub name: String,
+    /// Current value of editable controls
+    #[serde(default)]
+    pub value: Option<String>,
+    #[serde(default)]
+    pub state: ElementState,
+    #[serde(default)]
+    pub children: Vec<Element>,
+}
+
+impl Element {
+    pub fn new(id: u64, role: Role, name: &str) -> Self {
+        Element {
+            id: ElementId(id),
+            role,
+            name: name.to_string(),
+            value: None,
+            state: ElementState::default(),
+            children: Vec::new(),
+        }
+    }
+
+    pub fn with_child(mut self, child: Element) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    pub fn with_value(mut self, value: &str) -> Self {
+        self.value = Some(value.to_string());
+        self
+    }
+
+    pub fn with_state(mut self, state: ElementState) -> Self {
+        self.state = state;
+        self
+    }
+
+    pub fn find(&self, id: ElementId) -> Option<&Element> {
+        if self.id == id {
+            return Some(self);
+        }
+        self.children.iter().find_map(|child| child.find(id))
+    }
+
+    pub fn find_mut(&mut self, id: ElementId) -> Option<&mut Element> {
+        if self.id == id {
+            return Some(self);
+        }
+        self.children.iter_mut().find_map(|child| child.find_mut(id))
+    }
+
+    /// Replace the subtree with `element`'s id, returning whether it was found
+    pub fn replace(&mut self, element: Element) -> bool {
+        match self.find_mut(element.id) {
+            Some(slot) => {
+                *slot = element;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Visible elements in reading order, depth first
+    pub fn visible(&self) -> Vec<&Element> {
+        let mut elements = Vec::new();
+        self.collect_visible(&mut elements);
+        elements
+    }
+
+    fn collect_visible<'a>(&'a self, elements: &mut Vec<&'a Element>) {
+        if self.state.hidden {
+            return;
+        }
+        elements.push(self);
+        for child in &self.children {
+            child.collect_visible(elements);
+        }
+    }
+
+    /// Elements keyboard focus can move through, in tab order
+    pub fn focus_order(&self) -> Vec<ElementId> {
+        self.visible()
+            .into_iter()
+            .filter(|e| e.role.focusable() && !e.state.disabled)
+            .map(|e| e.id)
+            .collect()
+    }
+
+    /// Text the screen reader speaks for this element
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if !self.name.is_empty() {
+            parts.push(self.name.clone());
+        }
+        parts.push(self.role.label().to_string());
+        match self.state.checked {
+            Some(true) => parts.push("checked".to_string()),
+            Some(false) => parts.push("not checked".to_string()),
+            None => {}
+        }
+        match self.state.expanded {
+            Some(true) => parts.push("expanded".to_string()),
+            Some(false) => parts.push("collapsed".to_string()),
+            None => {}
+        }
+        if self.state.disabled {
+            parts.push("disabled".to_string());
+        }
+        if let Some(value) = &self.value {
+            parts.push(if value.is_empty() { "blank".to_string() } else { value.clone() });
+        }
+        parts.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn login_form() -> Element {
+        Element::new(1, Role::Window, "Sign in")
+            .with_child(Element::new(2, Role::Label, "Welcome back"))
+            .with_child(Element::new(3, Role::TextField, "Email").with_value(""))
+            .with_child(Element::new(4, Role::CheckBox, "Remember me").with_state(ElementState {
+                checked: Some(true),
+                ..ElementState::default()
+            }))
+            .with_child(Element::new(5, Role::Button, "Help").with_state(ElementState {
+                hidden: true,
+                ..ElementState::default()
+            }))
+            .with_child(Element::new(6, Role::Button, "Sign in").with_state(ElementState {
+                disabled: true,
+                ..ElementState::default()
+            }))
+    }
+
+    #[test]
+    fn test_focus_order_and_descriptions() {
+        let form = login_form();
+        assert_eq!(form.focus_order(), vec![ElementId(3), ElementId(4)]);
+        assert_eq!(form.find(ElementId(3)).unwrap().describe(), "Email, text field, blank");
+        assert_eq!(form.find(ElementId(4)).unwrap().describe(), "Remember me, check box, checked");
+        assert_eq!(form.find(ElementId(6)).unwrap().describe(), "Sign in, button, disabled");
+        assert_eq!(form.visible().len(), 5);
+    }
+
+    #[test]
+    fn test_replace_subtree() {
+        let mut form = login_form();
+        assert!(form.replace(Element::new(3, Role::TextField, "Email").with_value("ana@example.com")));
+        assert_eq!(form.find(ElementId(3)).unwrap().value.as_deref(), Some("ana@example.com"));
+        assert!(!form.replace(Element::new(42, Role::Button, "Ghost")));
+    }
+}
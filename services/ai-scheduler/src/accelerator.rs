@@ -0,0 +1,303 @@
+//! AI accelerator pool
+//!
+//! Tracks the accelerators available to the scheduler, matches tasks to a
+//! device that satisfies their requirements, accounts per-device utilization,
+//! and keeps a wait queue per device for when every suitable device is busy.
+
+use std::collections::{HashMap, VecDeque};
+
+use device_manager::DeviceId;
+
+use crate::ProcessId;
+
+/// Static capabilities of an accelerator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AcceleratorInfo {
+    pub device_id: DeviceId,
+    pub memory_mb: u32,
+    pub compute_units: u32,
+}
+
+impl AcceleratorInfo {
+    pub fn new(device_id: DeviceId, memory_mb: u32, compute_units: u32) -> Self {
+        AcceleratorInfo {
+            device_id,
+            memory_mb,
+            compute_units,
+        }
+    }
+
+    /// Accelerator a scheduler has unless given its devices: one device,
+    /// large enough for any task
+    pub fn default_device() -> Self {
+        AcceleratorInfo::new(DeviceId::new(0), u32::MAX, 1)
+    }
+
+    /// Whether this device can host a task needing `memory_mb`
+    pub fn fits(&self, memory_mb: u32) -> bool {
+        self.memory_mb >= memory_mb
+    }
+}
+
+/// Utilization snapshot of one accelerator
+#[derive(Debug, Clone)]
+pub struct AcceleratorStats {
+    pub info: AcceleratorInfo,
+    pub current_task: Option<ProcessId>,
+    pub queued_tasks: usize,
+    /// Predicted run time of the tasks waiting on this device
+    pub queued_work: u64,
+    pub busy_time: u64,
+    pub total_time: u64,
+    pub tasks_completed: u64,
+}
+
+impl AcceleratorStats {
+    /// Fraction of observed time the device was busy (0.0 - 1.0)
+    pub fn utilization(&self) -> f32 {
+        if self.total_time == 0 {
+            0.0
+        } else {
+            self.busy_time as f32 / self.total_time as f32
+        }
+    }
+}
+
+#[derive(Debug)]
+struct AcceleratorSlot {
+    info: AcceleratorInfo,
+    current_task: Option<ProcessId>,
+    /// Waiting tasks with their predicted run time
+    wait_queue: VecDeque<(ProcessId, u64)>,
+    busy_time: u64,
+    total_time: u64,
+    tasks_completed: u64,
+}
+
+impl AcceleratorSlot {
+    fn queued_work(&self) -> u64 {
+        self.wait_queue.iter().map(|(_, predicted)| predicted).sum()
+    }
+}
+
+/// Pool of accelerators owned by the scheduler
+#[derive(Debug, Default)]
+pub struct AcceleratorPool {
+    slots: HashMap<DeviceId, AcceleratorSlot>,
+}
+
+impl AcceleratorPool {
+    pub fn new() -> Self {
+        AcceleratorPool {
+            slots: HashMap::new(),
+        }
+    }
+
+    /// Add an accelerator to the pool
+    pub fn register(&mut self, info: AcceleratorInfo) -> Result<(), String> {
+        if self.slots.contains_key(&info.device_id) {
+            return Err("Accelerator already registered".to_string());
+        }
+        self.slots.insert(
+            info.device_id,
+            AcceleratorSlot {
+                info,
+                current_task: None,
+                wait_queue: VecDeque::new(),
+                busy_time: 0,
+                total_time: 0,
+                tasks_completed: 0,
+            },
+        );
+        Ok(())
+    }
+
+    /// Remove an idle accelerator, returning any tasks that were waiting on it
+    pub fn unregister(&mut self, device_id: DeviceId) -> Result<Vec<ProcessId>, String> {
+        let slot = self.slots.get(&device_id).ok_or("Accelerator not found")?;
+        if slot.current_task.is_some() {
+            return Err("Accelerator is busy".to_string());
+        }
+        let slot = self.slots.remove(&device_id).unwrap();
+        Ok(slot.wait_queue.into_iter().map(|(id, _)| id).collect())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Whether any registered device could ever host the requirement
+    pub fn can_host(&self, memory_mb: u32) -> bool {
+        self.slots.values().any(|s| s.info.fits(memory_mb))
+    }
+
+    /// Best free device for the requirement: the smallest that fits, so large
+    /// devices stay available for large tasks
+    pub fn find_free(&self, memory_mb: u32) -> Option<DeviceId> {
+        self.slots
+            .values()
+            .filter(|s| s.current_task.is_none() && s.info.fits(memory_mb))
+            .min_by_key(|s| (s.info.memory_mb, s.info.compute_units))
+            .map(|s| s.info.device_id)
+    }
+
+    /// Mark a device as running `task`
+    pub fn assign(&mut self, device_id: DeviceId, task: ProcessId) -> Result<(), String> {
+        let slot = self.slots.get_mut(&device_id).ok_or("Accelerator not found")?;
+        if slot.current_task.is_some() {
+            return Err("Accelerator is busy".to_string());
+        }
+        slot.current_task = Some(task);
+        slot.wait_queue.retain(|(id, _)| *id != task);
+        Ok(())
+    }
+
+    /// Free the device running `task`; returns the device if one was held
+    pub fn release(&mut self, task: ProcessId, completed: bool) -> Option<DeviceId> {
+        let slot = self
+            .slots
+            .values_mut()
+            .find(|s| s.current_task == Some(task))?;
+        slot.current_task = None;
+        if completed {
+            slot.tasks_completed += 1;
+        }
+        Some(slot.info.device_id)
+    }
+
+    /// Park a task on the suitable device with the least predicted backlog
+    pub fn enqueue_waiting(&mut self, task: ProcessId, memory_mb: u32, predicted: u64) -> Option<DeviceId> {
+        let slot = self
+            .slots
+            .values_mut()
+            .filter(|s| s.info.fits(memory_mb))
+            .min_by_key(|s| (s.queued_work(), s.info.device_id))?;
+        slot.wait_queue.push_back((task, predicted));
+        Some(slot.info.device_id)
+    }
+
+    /// First `eligible` task waiting on any free device, removed from its
+    /// wait queue
+    pub fn pop_waiting(&mut self, eligible: impl Fn(ProcessId) -> bool) -> Option<(DeviceId, ProcessId)> {
+        let mut free: Vec<&mut AcceleratorSlot> = self
+            .slots
+            .values_mut()
+            .filter(|s| s.current_task.is_none() && !s.wait_queue.is_empty())
+            .collect();
+        free.sort_by_key(|s| s.info.device_id);
+
+        for slot in free {
+            if let Some(pos) = slot.wait_queue.iter().position(|(id, _)| eligible(*id)) {
+                let (task, _) = slot.wait_queue.remove(pos)?;
+                return Some((slot.info.device_id, task));
+            }
+        }
+        None
+    }
+
+    /// Drop a task from every wait queue
+    pub fn forget(&mut self, task: ProcessId) {
+        for slot in self.slots.values_mut() {
+            slot.wait_queue.retain(|(id, _)| *id != task);
+        }
+    }
+
+    /// Device currently running `task`
+    pub fn device_of(&self, task: ProcessId) -> Option<DeviceId> {
+        self.slots
+            .values()
+            .find(|s| s.current_task == Some(task))
+            .map(|s| s.info.device_id)
+    }
+
+    /// Advance the utilization clock by `elapsed`
+    pub fn tick(&mut self, elapsed: u64) {
+        for slot in self.slots.values_mut() {
+            slot.total_time += elapsed;
+            if slot.current_task.is_some() {
+                slot.busy_time += elapsed;
+            }
+        }
+    }
+
+    pub fn stats(&self) -> Vec<AcceleratorStats> {
+        self.slots
+            .values()
+            .map(|s| AcceleratorStats {
+                info: s.info,
+                current_task: s.current_task,
+                queued_tasks: s.wait_queue.len(),
+                queued_work: s.queued_work(),
+                busy_time: s.busy_time,
+                total_time: s.total_time,
+                tasks_completed: s.tasks_completed,
+            })
+            .collect()
+    }
+
+    pub fn device_stats(&self, device_id: DeviceId) -> Option<AcceleratorStats> {
+        self.stats().into_iter().find(|s| s.info.device_id == device_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool() -> AcceleratorPool {
+        let mut pool = AcceleratorPool::new();
+        pool.register(AcceleratorInfo::new(DeviceId::new(1), 4096, 32)).unwrap();
+        pool.register(AcceleratorInfo::new(DeviceId::new(2), 16384, 128)).unwrap();
+        pool
+    }
+
+    #[test]
+    fn test_best_fit_assignment() {
+        let mut pool = pool();
+        assert_eq!(pool.find_free(2048), Some(DeviceId::new(1)));
+        assert_eq!(pool.find_free(8192), Some(DeviceId::new(2)));
+        assert_eq!(pool.find_free(32768), None);
+
+        pool.assign(DeviceId::new(1), ProcessId::new(10)).unwrap();
+        assert_eq!(pool.find_free(2048), Some(DeviceId::new(2)));
+        assert!(pool.assign(DeviceId::new(1), ProcessId::new(11)).is_err());
+    }
+
+    #[test]
+    fn test_utilization_accounting() {
+        let mut pool = pool();
+        pool.assign(DeviceId::new(1), ProcessId::new(10)).unwrap();
+        pool.tick(30);
+        pool.release(ProcessId::new(10), true);
+        pool.tick(70);
+
+        let stats = pool.device_stats(DeviceId::new(1)).unwrap();
+        assert_eq!(stats.tasks_completed, 1);
+        assert!((stats.utilization() - 0.3).abs() < f32::EPSILON);
+        assert_eq!(pool.device_stats(DeviceId::new(2)).unwrap().utilization(), 0.0);
+    }
+
+    #[test]
+    fn test_wait_queue_drains_on_release() {
+        let mut pool = pool();
+        pool.assign(DeviceId::new(2), ProcessId::new(10)).unwrap();
+        assert_eq!(pool.enqueue_waiting(ProcessId::new(11), 8192, 50), Some(DeviceId::new(2)));
+        assert!(pool.pop_waiting(|_| true).is_none());
+
+        pool.release(ProcessId::new(10), true);
+        assert_eq!(pool.pop_waiting(|_| true), Some((DeviceId::new(2), ProcessId::new(11))));
+    }
+
+    #[test]
+    fn test_waiting_packs_by_predicted_work() {
+        let mut pool = pool();
+        pool.assign(DeviceId::new(1), ProcessId::new(10)).unwrap();
+        pool.assign(DeviceId::new(2), ProcessId::new(20)).unwrap();
+
+        assert_eq!(pool.enqueue_waiting(ProcessId::new(11), 1024, 500), Some(DeviceId::new(1)));
+        assert_eq!(pool.enqueue_waiting(ProcessId::new(12), 1024, 20), Some(DeviceId::new(2)));
+        // Two short tasks on device 2 still total less than the long one on device 1
+        assert_eq!(pool.enqueue_waiting(ProcessId::new(13), 1024, 30), Some(DeviceId::new(2)));
+        assert_eq!(pool.device_stats(DeviceId::new(2)).unwrap().queued_work, 50);
+    }
+}
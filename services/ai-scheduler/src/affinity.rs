@@ -0,0 +1,109 @@
+//! CPU affinity and NUMA placement
+//!
+//! Hard affinity restricts the CPUs a task may run on; NUMA node hints and
+//! the CPU a task last ran on are soft preferences used to pick among
+//! otherwise equal candidates, avoiding cache-thrashing migrations.
+
+/// Set of CPUs, one bit per CPU (up to 64)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CpuSet(u64);
+
+impl CpuSet {
+    /// Every CPU
+    pub fn all() -> Self {
+        CpuSet(u64::MAX)
+    }
+
+    pub fn empty() -> Self {
+        CpuSet(0)
+    }
+
+    pub fn from_cpus(cpus: &[u32]) -> Self {
+        let mut set = Self::empty();
+        for cpu in cpus {
+            set.insert(*cpu);
+        }
+        set
+    }
+
+    pub fn insert(&mut self, cpu: u32) {
+        if cpu < 64 {
+            self.0 |= 1 << cpu;
+        }
+    }
+
+    pub fn remove(&mut self, cpu: u32) {
+        if cpu < 64 {
+            self.0 &= !(1 << cpu);
+        }
+    }
+
+    pub fn contains(&self, cpu: u32) -> bool {
+        cpu < 64 && self.0 & (1 << cpu) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl Default for CpuSet {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+/// Mapping of CPUs to NUMA nodes
+#[derive(Debug, Clone, Default)]
+pub struct CpuTopology {
+    /// NUMA node of each CPU, indexed by CPU number
+    nodes: Vec<u32>,
+}
+
+impl CpuTopology {
+    pub fn new(nodes: Vec<u32>) -> Self {
+        CpuTopology { nodes }
+    }
+
+    /// `cpus_per_node` CPUs on each of `node_count` nodes, numbered contiguously
+    pub fn uniform(node_count: u32, cpus_per_node: u32) -> Self {
+        let nodes = (0..node_count)
+            .flat_map(|node| std::iter::repeat_n(node, cpus_per_node as usize))
+            .collect();
+        CpuTopology { nodes }
+    }
+
+    pub fn cpu_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// NUMA node of a CPU; unknown CPUs are treated as node 0
+    pub fn node_of(&self, cpu: u32) -> u32 {
+        self.nodes.get(cpu as usize).copied().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpu_set() {
+        let mut set = CpuSet::from_cpus(&[0, 3]);
+        assert!(set.contains(3));
+        assert!(!set.contains(1));
+        set.remove(3);
+        set.remove(0);
+        assert!(set.is_empty());
+        assert!(CpuSet::all().contains(63));
+        assert!(!CpuSet::all().contains(64));
+    }
+
+    #[test]
+    fn test_uniform_topology() {
+        let topology = CpuTopology::uniform(2, 4);
+        assert_eq!(topology.cpu_count(), 8);
+        assert_eq!(topology.node_of(3), 0);
+        assert_eq!(topology.node_of(4), 1);
+    }
+}
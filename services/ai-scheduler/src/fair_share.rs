@@ -0,0 +1,216 @@
+//! Fair-share accounting across scheduling groups
+//!
+//! Each group (a user or an app) has a weight. CPU time is charged to the
+//! task's group, and the scheduler favors the group with the lowest
+//! weight-normalized usage so long-run utilization converges to the
+//! configured shares. Groups only come into play once one is configured;
+//! time charged to others is tracked but does not change the order.
+
+use std::collections::HashMap;
+
+/// Weight given to groups that were never configured explicitly
+pub const DEFAULT_WEIGHT: u32 = 1;
+
+/// Scheduling group identifier
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GroupId(String);
+
+impl GroupId {
+    pub fn new(id: String) -> Self {
+        GroupId(id)
+    }
+
+    /// Group that ungrouped tasks are charged to
+    pub fn default_group() -> Self {
+        GroupId("default".to_string())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for GroupId {
+    fn from(id: String) -> Self {
+        GroupId(id)
+    }
+}
+
+impl From<&str> for GroupId {
+    fn from(id: &str) -> Self {
+        GroupId(id.to_string())
+    }
+}
+
+/// Usage report for one group
+#[derive(Debug, Clone)]
+pub struct GroupUsage {
+    pub group: GroupId,
+    pub weight: u32,
+    pub cpu_time: u64,
+    /// Fraction of all accounted CPU time consumed by this group
+    pub share: f64,
+    /// Fraction the group is entitled to by weight
+    pub target_share: f64,
+}
+
+#[derive(Debug, Clone)]
+struct GroupState {
+    weight: u32,
+    cpu_time: u64,
+    /// CPU time normalized by weight, used to pick the most underserved group
+    virtual_time: f64,
+    /// Set with `set_weight` rather than created by a charge
+    configured: bool,
+}
+
+/// Per-group weights and usage
+#[derive(Debug, Default)]
+pub struct FairShare {
+    groups: HashMap<GroupId, GroupState>,
+}
+
+impl FairShare {
+    pub fn new() -> Self {
+        FairShare {
+            groups: HashMap::new(),
+        }
+    }
+
+    /// Whether any group has been configured
+    pub fn is_active(&self) -> bool {
+        self.groups.values().any(|g| g.configured)
+    }
+
+    /// Create or reweight a group
+    pub fn set_weight(&mut self, group: GroupId, weight: u32) -> Result<(), String> {
+        if weight == 0 {
+            return Err("Group weight must be positive".to_string());
+        }
+        match self.groups.get_mut(&group) {
+            Some(state) => {
+                state.weight = weight;
+                state.configured = true;
+            }
+            None => self.insert(group, weight, true),
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, group: GroupId, weight: u32, configured: bool) {
+        // New groups start level with the least-served group so they
+        // cannot claim a backlog of CPU time they never competed for
+        let virtual_time = self.min_virtual_time();
+        self.groups.insert(
+            group,
+            GroupState {
+                weight,
+                cpu_time: 0,
+                virtual_time,
+                configured,
+            },
+        );
+    }
+
+    pub fn remove_group(&mut self, group: &GroupId) -> Result<(), String> {
+        self.groups
+            .remove(group)
+            .map(|_| ())
+            .ok_or_else(|| "Group not found".to_string())
+    }
+
+    pub fn weight(&self, group: &GroupId) -> Option<u32> {
+        self.groups.get(group).map(|g| g.weight)
+    }
+
+    /// Charge CPU time to a group, tracking it with the default weight if
+    /// needed; that alone does not make fair share active
+    pub fn charge(&mut self, group: &GroupId, elapsed: u64) {
+        if !self.groups.contains_key(group) {
+            self.insert(group.clone(), DEFAULT_WEIGHT, false);
+        }
+        let state = self.groups.get_mut(group).unwrap();
+        state.cpu_time += elapsed;
+        state.virtual_time += elapsed as f64 / state.weight as f64;
+    }
+
+    /// Weight-normalized usage; unknown groups rank as least served
+    pub fn virtual_time(&self, group: &GroupId) -> f64 {
+        self.groups
+            .get(group)
+            .map(|g| g.virtual_time)
+            .unwrap_or_else(|| self.min_virtual_time())
+    }
+
+    fn min_virtual_time(&self) -> f64 {
+        self.groups
+            .values()
+            .map(|g| g.virtual_time)
+            .fold(None, |min: Option<f64>, v| Some(min.map_or(v, |m| m.min(v))))
+            .unwrap_or(0.0)
+    }
+
+    pub fn usage(&self) -> Vec<GroupUsage> {
+        let total_time: u64 = self.groups.values().map(|g| g.cpu_time).sum();
+        let total_weight: u32 = self.groups.values().map(|g| g.weight).sum();
+
+        let mut usage: Vec<GroupUsage> = self
+            .groups
+            .iter()
+            .map(|(group, state)| GroupUsage {
+                group: group.clone(),
+                weight: state.weight,
+                cpu_time: state.cpu_time,
+                share: if total_time == 0 {
+                    0.0
+                } else {
+                    state.cpu_time as f64 / total_time as f64
+                },
+                target_share: state.weight as f64 / total_weight as f64,
+            })
+            .collect();
+        usage.sort_by(|a, b| a.group.cmp(&b.group));
+        usage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_shares() {
+        let mut fair = FairShare::new();
+        fair.set_weight(GroupId::from("alice"), 3).unwrap();
+        fair.set_weight(GroupId::from("bob"), 1).unwrap();
+        fair.charge(&GroupId::from("alice"), 30);
+        fair.charge(&GroupId::from("bob"), 10);
+
+        let usage = fair.usage();
+        assert_eq!(usage[0].group, GroupId::from("alice"));
+        assert!((usage[0].share - 0.75).abs() < 1e-9);
+        assert!((usage[0].target_share - 0.75).abs() < 1e-9);
+        assert_eq!(fair.virtual_time(&GroupId::from("alice")), fair.virtual_time(&GroupId::from("bob")));
+    }
+
+    #[test]
+    fn test_late_group_starts_level() {
+        let mut fair = FairShare::new();
+        fair.set_weight(GroupId::from("alice"), 1).unwrap();
+        fair.charge(&GroupId::from("alice"), 100);
+        fair.set_weight(GroupId::from("bob"), 1).unwrap();
+
+        assert_eq!(fair.virtual_time(&GroupId::from("bob")), 100.0);
+        assert!(fair.set_weight(GroupId::from("bob"), 0).is_err());
+    }
+
+    #[test]
+    fn test_charging_does_not_configure() {
+        let mut fair = FairShare::new();
+        fair.charge(&GroupId::default_group(), 10);
+        assert!(!fair.is_active());
+        assert_eq!(fair.usage()[0].cpu_time, 10);
+        fair.set_weight(GroupId::default_group(), 2).unwrap();
+        assert!(fair.is_active());
+    }
+}
@@ -3,21 +3,36 @@
 //! Provides intelligent workload scheduling optimized for AI/ML tasks,
 //! with support for mixed-criticality real-time and batch workloads.
 
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::{Arc, Mutex};
 
-/// Process identifier
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub struct ProcessId(u64);
+use device_manager::DeviceId;
+use kernel::{Kernel, ProcessState};
+use system_utils::sysinfo::SystemMonitor;
 
-impl ProcessId {
-    pub fn new(id: u64) -> Self {
-        ProcessId(id)
-    }
-}
+pub mod accelerator;
+pub mod affinity;
+pub mod fair_share;
+pub mod power;
+pub mod prediction;
+pub mod qos;
+pub mod telemetry;
+
+pub use accelerator::{AcceleratorInfo, AcceleratorPool, AcceleratorStats};
+pub use affinity::{CpuSet, CpuTopology};
+pub use fair_share::{FairShare, GroupId, GroupUsage};
+pub use prediction::ExecutionPredictor;
+pub use power::{PowerConstraints, PowerMode, PowerState, Pressure, ThermalState};
+pub use qos::{QosAccounting, QosBudget, QosClass, QosUsage};
+pub use telemetry::{MetricsSink, SchedulerMetrics, TaskMetrics, TelemetrySink, TelemetrySnapshot};
+
+use telemetry::Telemetry;
+
+/// Tasks are scheduled on behalf of kernel processes
+pub use kernel::ProcessId;
 
 /// Workload types that the scheduler can handle
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum WorkloadType {
     /// Real-time critical workload with deadline guarantees
     RealTime,
@@ -41,6 +56,19 @@ pub enum SchedulingPriority {
     Background = 0,
 }
 
+impl WorkloadType {
+    /// Default time slice (in scheduler time units) for this workload type
+    pub fn default_time_slice(&self) -> u64 {
+        match self {
+            WorkloadType::RealTime => 5,
+            WorkloadType::Interactive => 10,
+            WorkloadType::AIInference => 20,
+            WorkloadType::AITraining => 50,
+            WorkloadType::Batch => 100,
+        }
+    }
+}
+
 /// Task information for scheduling
 #[derive(Debug, Clone)]
 pub struct Task {
@@ -50,6 +78,31 @@ pub struct Task {
     pub cpu_time_used: u64,
     pub deadline: Option<u64>,
     pub ai_accelerator_required: bool,
+    /// Length of the task's quantum
+    pub time_slice: u64,
+    /// Portion of the current quantum already consumed
+    pub slice_used: u64,
+    /// Number of times the task has been preempted
+    pub preemptions: u64,
+    /// Accelerator memory the task needs, in MB
+    pub accelerator_memory_mb: u32,
+    /// Accelerator the task is currently running on
+    pub assigned_accelerator: Option<DeviceId>,
+    /// Fair-share group (user or app) the task's CPU time is charged to
+    pub group: Option<GroupId>,
+    /// Size of the task's input (batch size, tokens, frames), used for
+    /// run-time prediction
+    pub work_units: u64,
+    /// CPUs the task may run on
+    pub allowed_cpus: CpuSet,
+    /// NUMA node the task's memory lives on
+    pub preferred_node: Option<u32>,
+    /// CPU the task was last dispatched on
+    pub last_cpu: Option<u32>,
+    /// Number of dispatches onto a different CPU than the previous one
+    pub migrations: u64,
+    /// Bandwidth class the task's run time is charged to
+    pub qos: QosClass,
 }
 
 impl Task {
@@ -69,6 +122,18 @@ impl Task {
             cpu_time_used: 0,
             deadline: None,
             ai_accelerator_required: matches!(workload_type, WorkloadType::AIInference | WorkloadType::AITraining),
+            time_slice: workload_type.default_time_slice(),
+            slice_used: 0,
+            preemptions: 0,
+            accelerator_memory_mb: 0,
+            assigned_accelerator: None,
+            group: None,
+            work_units: 0,
+            allowed_cpus: CpuSet::all(),
+            preferred_node: None,
+            last_cpu: None,
+            migrations: 0,
+            qos: QosClass::for_workload(workload_type),
         }
     }
 
@@ -76,72 +141,426 @@ impl Task {
         self.deadline = Some(deadline);
         self
     }
+
+    pub fn with_accelerator_memory(mut self, memory_mb: u32) -> Self {
+        self.accelerator_memory_mb = memory_mb;
+        self
+    }
+
+    pub fn with_work_units(mut self, work_units: u64) -> Self {
+        self.work_units = work_units;
+        self
+    }
+
+    pub fn with_qos(mut self, qos: QosClass) -> Self {
+        self.qos = qos;
+        self
+    }
+
+    pub fn with_affinity(mut self, allowed_cpus: CpuSet) -> Self {
+        self.allowed_cpus = allowed_cpus;
+        self
+    }
+
+    pub fn with_preferred_node(mut self, node: u32) -> Self {
+        self.preferred_node = Some(node);
+        self
+    }
+
+    pub fn can_run_on(&self, cpu: u32) -> bool {
+        self.allowed_cpus.contains(cpu)
+    }
+
+    /// Soft-affinity preference for running on `cpu`: 2 for the CPU it last
+    /// ran on, 1 for a CPU on its preferred (or last) NUMA node, else 0
+    pub fn affinity_score(&self, cpu: u32, topology: &CpuTopology) -> u8 {
+        if self.last_cpu == Some(cpu) {
+            return 2;
+        }
+        let home = self.preferred_node.or_else(|| self.last_cpu.map(|c| topology.node_of(c)));
+        if home == Some(topology.node_of(cpu)) {
+            1
+        } else {
+            0
+        }
+    }
+
+    pub fn with_group(mut self, group: GroupId) -> Self {
+        self.group = Some(group);
+        self
+    }
+
+    /// Group charged for this task's CPU time
+    pub fn group_id(&self) -> GroupId {
+        self.group.clone().unwrap_or_else(GroupId::default_group)
+    }
+
+    pub fn with_time_slice(mut self, time_slice: u64) -> Self {
+        self.time_slice = time_slice.max(1);
+        self
+    }
+
+    /// Whether the current quantum has been used up
+    pub fn quantum_expired(&self) -> bool {
+        self.slice_used >= self.time_slice
+    }
+
+    /// Deferrable work that yields the accelerator under power pressure
+    pub fn is_background(&self) -> bool {
+        matches!(self.workload_type, WorkloadType::AITraining | WorkloadType::Batch)
+    }
 }
 
 /// AI-aware scheduler
 pub struct AIScheduler {
     ready_queue: Arc<Mutex<VecDeque<Task>>>,
     tasks: Arc<Mutex<HashMap<ProcessId, Task>>>,
-    ai_accelerator_available: Arc<Mutex<bool>>,
+    accelerators: Arc<Mutex<AcceleratorPool>>,
+    fair_share: Arc<Mutex<FairShare>>,
+    telemetry: Arc<Mutex<Telemetry>>,
+    power: Arc<Mutex<PowerState>>,
+    running: Arc<Mutex<HashSet<ProcessId>>>,
+    predictor: Arc<Mutex<ExecutionPredictor>>,
+    kernel: Arc<Mutex<Option<Arc<Kernel>>>>,
+    topology: Arc<Mutex<CpuTopology>>,
+    qos: Arc<Mutex<QosAccounting>>,
 }
 
 impl AIScheduler {
+    /// Create a scheduler with the default accelerator
     pub fn new() -> Self {
+        Self::with_accelerators(vec![AcceleratorInfo::default_device()])
+    }
+
+    fn empty() -> Self {
         AIScheduler {
             ready_queue: Arc::new(Mutex::new(VecDeque::new())),
             tasks: Arc::new(Mutex::new(HashMap::new())),
-            ai_accelerator_available: Arc::new(Mutex::new(true)),
+            accelerators: Arc::new(Mutex::new(AcceleratorPool::new())),
+            fair_share: Arc::new(Mutex::new(FairShare::new())),
+            telemetry: Arc::new(Mutex::new(Telemetry::default())),
+            power: Arc::new(Mutex::new(PowerState::default())),
+            running: Arc::new(Mutex::new(HashSet::new())),
+            predictor: Arc::new(Mutex::new(ExecutionPredictor::new())),
+            kernel: Arc::new(Mutex::new(None)),
+            topology: Arc::new(Mutex::new(CpuTopology::default())),
+            qos: Arc::new(Mutex::new(QosAccounting::default())),
+        }
+    }
+
+    /// Create a scheduler managing the given accelerators
+    pub fn with_accelerators(accelerators: Vec<AcceleratorInfo>) -> Self {
+        let scheduler = Self::empty();
+        for info in accelerators {
+            let _ = scheduler.register_accelerator(info);
+        }
+        scheduler
+    }
+
+    /// Bind the scheduler to the kernel: dispatch and preemption drive the
+    /// process state, and terminated processes lose their tasks
+    pub fn attach_kernel(self: &Arc<Self>, kernel: Arc<Kernel>) {
+        let scheduler = Arc::downgrade(self);
+        kernel.on_process_terminated(Arc::new(move |id| {
+            if let Some(scheduler) = scheduler.upgrade() {
+                scheduler.remove_task(id);
+            }
+        }));
+        *self.kernel.lock().unwrap() = Some(kernel);
+    }
+
+    /// Report the run queue, dispatched and waiting tasks, to `monitor`,
+    /// which derives the load average from it
+    pub fn attach_sysinfo(&self, monitor: &SystemMonitor) {
+        let ready_queue = Arc::clone(&self.ready_queue);
+        let running = Arc::clone(&self.running);
+        monitor.attach_run_queue(move || ready_queue.lock().unwrap().len() + running.lock().unwrap().len());
+    }
+
+    fn set_process_state(&self, id: ProcessId, state: ProcessState) {
+        let kernel = self.kernel.lock().unwrap().clone();
+        if let Some(kernel) = kernel {
+            let _ = kernel.update_process_state(id, state);
         }
     }
 
-    /// Add a task to the scheduler
-    pub fn add_task(&self, task: Task) {
+    /// Add an accelerator to the pool
+    pub fn register_accelerator(&self, info: AcceleratorInfo) -> Result<(), String> {
+        self.accelerators.lock().unwrap().register(info)
+    }
+
+    /// Remove an idle accelerator; tasks waiting on it return to the ready queue
+    pub fn unregister_accelerator(&self, device_id: DeviceId) -> Result<(), String> {
+        let waiting = self.accelerators.lock().unwrap().unregister(device_id)?;
+        for id in waiting {
+            if let Some(task) = self.get_task(id) {
+                self.enqueue(task);
+            }
+        }
+        Ok(())
+    }
+
+    /// Per-device utilization and queue statistics
+    pub fn accelerator_stats(&self) -> Vec<AcceleratorStats> {
+        self.accelerators.lock().unwrap().stats()
+    }
+
+    /// Advance accelerator utilization accounting by `elapsed`
+    pub fn advance_accelerator_time(&self, elapsed: u64) {
+        self.accelerators.lock().unwrap().tick(elapsed);
+    }
+
+    /// Predicted total run time of a task, learned from completed tasks
+    pub fn predict(&self, task: &Task) -> u64 {
+        self.predictor.lock().unwrap().predict(task)
+    }
+
+    /// Add a task after admission control: with a kernel attached the task's
+    /// process must be alive, and a task with a deadline is
+    /// rejected if, with the predicted remaining run time of every deadline
+    /// task, some deadline could no longer be met starting at `now`
+    pub fn admit_task(&self, task: Task, now: u64) -> Result<(), String> {
+        self.check_process(task.id)?;
+
+        if task.deadline.is_some() {
+            let mut pending: Vec<(u64, u64)> = {
+                let predictor = self.predictor.lock().unwrap();
+                self.tasks
+                    .lock()
+                    .unwrap()
+                    .values()
+                    .chain(std::iter::once(&task))
+                    .filter_map(|t| t.deadline.map(|d| (d, predictor.remaining(t))))
+                    .collect()
+            };
+            pending.sort();
+
+            let mut finish = now;
+            for (deadline, remaining) in pending {
+                finish += remaining;
+                if finish > deadline {
+                    return Err("Task rejected: deadlines not schedulable".to_string());
+                }
+            }
+        }
+        self.add_task(task)
+    }
+
+    /// Add a task to the scheduler; with a kernel attached the task's
+    /// process must be alive
+    pub fn add_task(&self, task: Task) -> Result<(), String> {
+        self.check_process(task.id)?;
         let task_id = task.id;
         self.tasks.lock().unwrap().insert(task_id, task.clone());
-        
-        // Insert into ready queue based on priority
+        self.enqueue(task);
+        Ok(())
+    }
+
+    fn check_process(&self, id: ProcessId) -> Result<(), String> {
+        let kernel = self.kernel.lock().unwrap().clone();
+        if let Some(kernel) = kernel {
+            let process = kernel.get_process(id).ok_or("Process not found")?;
+            if process.state == ProcessState::Terminated {
+                return Err("Process has terminated".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Insert a task at the tail of its priority band in the ready queue
+    fn enqueue(&self, task: Task) {
+        self.telemetry.lock().unwrap().on_enqueue(task.id);
         let mut queue = self.ready_queue.lock().unwrap();
-        
-        // Find the correct position based on priority
         let pos = queue.iter().position(|t| t.priority < task.priority).unwrap_or(queue.len());
         queue.insert(pos, task);
     }
 
     /// Remove a task from the scheduler
     pub fn remove_task(&self, id: ProcessId) -> Option<Task> {
+        self.ready_queue.lock().unwrap().retain(|t| t.id != id);
+        let mut accelerators = self.accelerators.lock().unwrap();
+        accelerators.release(id, false);
+        accelerators.forget(id);
+        drop(accelerators);
+        self.running.lock().unwrap().remove(&id);
+        self.telemetry.lock().unwrap().on_remove(id);
         self.tasks.lock().unwrap().remove(&id)
     }
 
     /// Get the next task to execute
     pub fn next_task(&self) -> Option<Task> {
+        self.dispatch(None)
+    }
+
+    /// Get the next task to execute on `cpu`, honoring hard affinity and
+    /// preferring tasks that last ran on, or belong near, that CPU
+    pub fn next_task_on(&self, cpu: u32) -> Option<Task> {
+        self.dispatch(Some(cpu))
+    }
+
+    /// Describe the machine's CPU-to-NUMA-node layout
+    pub fn set_cpu_topology(&self, topology: CpuTopology) {
+        *self.topology.lock().unwrap() = topology;
+    }
+
+    fn dispatch(&self, cpu: Option<u32>) -> Option<Task> {
+        let constraints = self.power_constraints();
+        let at_capacity = constraints
+            .max_concurrency
+            .is_some_and(|cap| self.running.lock().unwrap().len() >= cap);
+
+        let mut task = self.select_next(&constraints, at_capacity, cpu)?;
+        if let Some(cpu) = cpu {
+            if let Some(stored) = self.tasks.lock().unwrap().get_mut(&task.id) {
+                if stored.last_cpu.is_some_and(|last| last != cpu) {
+                    stored.migrations += 1;
+                }
+                stored.last_cpu = Some(cpu);
+                task.last_cpu = stored.last_cpu;
+                task.migrations = stored.migrations;
+            }
+        }
+        self.running.lock().unwrap().insert(task.id);
+        self.telemetry.lock().unwrap().on_dispatch(task.id);
+        self.set_process_state(task.id, ProcessState::Running);
+        Some(task)
+    }
+
+    fn select_next(&self, constraints: &PowerConstraints, realtime_only: bool, cpu: Option<u32>) -> Option<Task> {
         let mut queue = self.ready_queue.lock().unwrap();
-        
-        // Check for real-time tasks first
-        if let Some(pos) = queue.iter().position(|t| matches!(t.workload_type, WorkloadType::RealTime)) {
-            return Some(queue.remove(pos).unwrap());
+        let mut accelerators = self.accelerators.lock().unwrap();
+        let on_cpu = |t: &Task| cpu.is_none_or(|cpu| t.can_run_on(cpu));
+
+        // Check for real-time tasks first; they are never throttled
+        if let Some(pos) = queue
+            .iter()
+            .position(|t| matches!(t.workload_type, WorkloadType::RealTime) && on_cpu(t))
+        {
+            return queue.remove(pos);
+        }
+
+        let throttled = self.throttled_classes(&queue);
+        let eligible = |t: &Task| on_cpu(t) && !throttled.contains(&t.qos);
+
+        // At the concurrency cap only real-time work may start
+        if realtime_only {
+            return None;
+        }
+
+        let offloaded = |t: &Task| constraints.offload_background && t.is_background();
+
+        // Tasks parked on an accelerator that has since become free, unless a
+        // higher-priority task is ready
+        let top = queue.iter().filter(|t| eligible(t)).map(|t| t.priority).max();
+        if let Some((device_id, id)) = accelerators.pop_waiting(|id| {
+            self.get_task(id)
+                .is_some_and(|t| eligible(&t) && top.is_none_or(|top| t.priority >= top))
+        }) {
+            if let Some(task) = self.get_task(id) {
+                if offloaded(&task) {
+                    return Some(task);
+                }
+                accelerators.assign(device_id, id).ok()?;
+                return Some(self.mark_assigned(task, device_id));
+            }
+        }
+
+        // AI tasks that fit a free accelerator
+        let placement = queue.iter().enumerate().find_map(|(pos, t)| {
+            if t.ai_accelerator_required && !offloaded(t) && eligible(t) {
+                accelerators.find_free(t.accelerator_memory_mb).map(|d| (pos, d))
+            } else {
+                None
+            }
+        });
+        if let Some((pos, device_id)) = placement {
+            let task = queue.remove(pos).unwrap();
+            accelerators.assign(device_id, task.id).ok()?;
+            return Some(self.mark_assigned(task, device_id));
         }
 
-        // Check for AI tasks if accelerator is available
-        let accelerator_available = *self.ai_accelerator_available.lock().unwrap();
-        if accelerator_available {
-            if let Some(pos) = queue.iter().position(|t| t.ai_accelerator_required) {
-                *self.ai_accelerator_available.lock().unwrap() = false;
-                return Some(queue.remove(pos).unwrap());
+        // Otherwise, return highest priority task (or, with fair-share groups
+        // configured, the first task of the least-served group); AI tasks whose
+        // suitable accelerators are all busy wait on a device queue instead
+        while let Some(task) = self.pop_best(&mut queue, &eligible, cpu) {
+            if task.ai_accelerator_required
+                && !offloaded(&task)
+                && accelerators.can_host(task.accelerator_memory_mb)
+            {
+                let predicted = self.predictor.lock().unwrap().remaining(&task);
+                accelerators.enqueue_waiting(task.id, task.accelerator_memory_mb, predicted);
+                continue;
             }
+            return Some(task);
         }
+        None
+    }
+
+    /// Pick among the tasks `cpu` may run, with soft affinity breaking ties
+    /// between equally ranked candidates
+    fn pop_best(&self, queue: &mut VecDeque<Task>, eligible: &dyn Fn(&Task) -> bool, cpu: Option<u32>) -> Option<Task> {
+        let fair_share = self.fair_share.lock().unwrap();
+        let topology = self.topology.lock().unwrap();
+        let fair = fair_share.is_active();
+
+        // (position, priority, virtual time, affinity score)
+        let mut best: Option<(usize, SchedulingPriority, f64, u8)> = None;
+        for (pos, task) in queue.iter().enumerate() {
+            if !eligible(task) {
+                continue;
+            }
+            // The queue is priority ordered: only the first band competes, so
+            // fair share never lets a lower priority through, matching
+            // `preempt_check`
+            if best.is_some_and(|(_, p, _, _)| task.priority < p) {
+                break;
+            }
+            let score = cpu.map_or(0, |cpu| task.affinity_score(cpu, &topology));
+            let virtual_time = if fair { fair_share.virtual_time(&task.group_id()) } else { 0.0 };
+            if best.is_none_or(|(_, _, v, s)| virtual_time < v || (virtual_time == v && score > s)) {
+                best = Some((pos, task.priority, virtual_time, score));
+            }
+        }
+        queue.remove(best?.0)
+    }
 
-        // Otherwise, return highest priority task
-        queue.pop_front()
+    /// QoS classes that have exhausted their bandwidth for this period,
+    /// considering both queued and running tasks as runnable
+    fn throttled_classes(&self, queue: &VecDeque<Task>) -> Vec<QosClass> {
+        let mut runnable: Vec<QosClass> = queue.iter().map(|t| t.qos).collect();
+        let running: Vec<ProcessId> = self.running.lock().unwrap().iter().copied().collect();
+        let tasks = self.tasks.lock().unwrap();
+        runnable.extend(running.iter().filter_map(|id| tasks.get(id)).map(|t| t.qos));
+        drop(tasks);
+        runnable.sort();
+        runnable.dedup();
+        let qos = self.qos.lock().unwrap();
+        QosClass::ALL
+            .into_iter()
+            .filter(|class| qos.is_throttled(*class, &runnable))
+            .collect()
+    }
+
+    fn mark_assigned(&self, mut task: Task, device_id: DeviceId) -> Task {
+        task.assigned_accelerator = Some(device_id);
+        if let Some(stored) = self.tasks.lock().unwrap().get_mut(&task.id) {
+            stored.assigned_accelerator = Some(device_id);
+        }
+        task
     }
 
     /// Mark a task as completed
     pub fn complete_task(&self, id: ProcessId) {
-        if let Some(task) = self.tasks.lock().unwrap().get(&id) {
-            if task.ai_accelerator_required {
-                *self.ai_accelerator_available.lock().unwrap() = true;
-            }
+        if let Some(task) = self.get_task(id) {
+            self.predictor
+                .lock()
+                .unwrap()
+                .observe(task.workload_type, task.work_units, task.cpu_time_used);
         }
+        self.accelerators.lock().unwrap().release(id, true);
+        self.telemetry.lock().unwrap().on_complete(id);
         self.remove_task(id);
+        self.set_process_state(id, ProcessState::Ready);
     }
 
     /// Get task information
@@ -156,14 +575,157 @@ impl AIScheduler {
 
     /// Update task CPU time
     pub fn update_cpu_time(&self, id: ProcessId, time: u64) {
-        if let Some(task) = self.tasks.lock().unwrap().get_mut(&id) {
-            task.cpu_time_used += time;
+        let (group, qos, on_accelerator) = match self.tasks.lock().unwrap().get_mut(&id) {
+            Some(task) => {
+                task.cpu_time_used += time;
+                (task.group_id(), task.qos, task.assigned_accelerator.is_some())
+            }
+            None => return,
+        };
+        self.fair_share.lock().unwrap().charge(&group, time);
+        self.qos.lock().unwrap().charge(qos, time);
+        self.telemetry.lock().unwrap().on_run(id, time, on_accelerator);
+    }
+
+    /// Create a fair-share group or change its weight at runtime
+    pub fn set_group_weight(&self, group: GroupId, weight: u32) -> Result<(), String> {
+        self.fair_share.lock().unwrap().set_weight(group, weight)
+    }
+
+    /// Stop tracking a fair-share group
+    pub fn remove_group(&self, group: &GroupId) -> Result<(), String> {
+        self.fair_share.lock().unwrap().remove_group(group)
+    }
+
+    /// Per-group CPU usage compared with configured shares
+    pub fn group_usage(&self) -> Vec<GroupUsage> {
+        self.fair_share.lock().unwrap().usage()
+    }
+
+    /// Account `elapsed` run time to the running task and report whether it
+    /// should be switched out: either a higher-priority task is ready, its
+    /// quantum has expired and another task of equal or higher priority waits,
+    /// or its QoS class has run out of bandwidth for the period.
+    pub fn preempt_check(&self, current: ProcessId, elapsed: u64) -> bool {
+        let multiplier = self.power_constraints().slice_multiplier;
+        let (priority, expired, group, qos, realtime, on_accelerator) = {
+            let mut tasks = self.tasks.lock().unwrap();
+            let task = match tasks.get_mut(&current) {
+                Some(task) => task,
+                None => return false,
+            };
+            task.cpu_time_used += elapsed;
+            task.slice_used += elapsed;
+            let expired = task.slice_used >= task.time_slice * multiplier;
+            let realtime = task.workload_type == WorkloadType::RealTime;
+            (task.priority, expired, task.group_id(), task.qos, realtime, task.assigned_accelerator.is_some())
+        };
+        self.fair_share.lock().unwrap().charge(&group, elapsed);
+        self.qos.lock().unwrap().charge(qos, elapsed);
+        self.telemetry.lock().unwrap().on_run(current, elapsed, on_accelerator);
+
+        let queue = self.ready_queue.lock().unwrap();
+        if !realtime {
+            let mut runnable: Vec<QosClass> = queue.iter().map(|t| t.qos).collect();
+            runnable.push(qos);
+            if self.qos.lock().unwrap().is_throttled(qos, &runnable) {
+                return true;
+            }
         }
+        queue.iter().any(|t| {
+            t.id != current && (t.priority > priority || (expired && t.priority == priority))
+        })
+    }
+
+    /// Change the bandwidth reservation and ceiling of a QoS class
+    pub fn set_qos_budget(&self, class: QosClass, budget: QosBudget) -> Result<(), String> {
+        self.qos.lock().unwrap().set_budget(class, budget)
+    }
+
+    /// Time a QoS class may still run in the current period
+    pub fn qos_remaining_budget(&self, class: QosClass) -> u64 {
+        self.qos.lock().unwrap().remaining(class)
+    }
+
+    /// Per-class bandwidth usage in the current period
+    pub fn qos_usage(&self) -> Vec<QosUsage> {
+        self.qos.lock().unwrap().usage()
+    }
+
+    /// Preempt the running task: requeue it behind its peers with a fresh
+    /// quantum and return the task that should run next.
+    pub fn preempt(&self, current: ProcessId) -> Option<Task> {
+        self.requeue_task(current).ok()?;
+        self.next_task()
+    }
+
+    /// Return a running task to the ready queue with a fresh quantum
+    pub fn requeue_task(&self, id: ProcessId) -> Result<(), String> {
+        let task = {
+            let mut tasks = self.tasks.lock().unwrap();
+            let task = tasks.get_mut(&id).ok_or("Task not found")?;
+            task.slice_used = 0;
+            task.preemptions += 1;
+            task.assigned_accelerator = None;
+            task.clone()
+        };
+
+        if self.ready_queue.lock().unwrap().iter().any(|t| t.id == id) {
+            return Err("Task is already queued".to_string());
+        }
+
+        // A preempted AI task gives up the accelerator until it is dispatched again
+        self.accelerators.lock().unwrap().release(id, false);
+        self.running.lock().unwrap().remove(&id);
+        self.set_process_state(id, ProcessState::Ready);
+
+        self.enqueue(task);
+        Ok(())
+    }
+
+    /// Set the user's power mode
+    pub fn set_power_mode(&self, mode: PowerMode) {
+        self.power.lock().unwrap().mode = mode;
+    }
+
+    /// Apply a thermal throttle signal from the sensor subsystem
+    pub fn set_thermal_state(&self, thermal: ThermalState) {
+        self.power.lock().unwrap().thermal = thermal;
+    }
+
+    /// Apply a raw temperature reading from the sensor subsystem
+    pub fn report_temperature(&self, celsius: f32) {
+        self.set_thermal_state(ThermalState::from_celsius(celsius));
+    }
+
+    pub fn power_state(&self) -> PowerState {
+        *self.power.lock().unwrap()
+    }
+
+    /// Constraints currently applied because of thermal or battery pressure
+    pub fn power_constraints(&self) -> PowerConstraints {
+        self.power.lock().unwrap().constraints()
+    }
+
+    /// Quantum a task receives under the current power constraints
+    pub fn effective_time_slice(&self, task: &Task) -> u64 {
+        task.time_slice * self.power_constraints().slice_multiplier
+    }
+
+    /// Number of tasks currently dispatched
+    pub fn running_count(&self) -> usize {
+        self.running.lock().unwrap().len()
+    }
+
+    /// Number of tasks waiting in the ready queue
+    pub fn queue_len(&self) -> usize {
+        self.ready_queue.lock().unwrap().len()
     }
 
     /// Check for deadline violations
     pub fn check_deadlines(&self, current_time: u64) -> Vec<ProcessId> {
-        self.tasks
+        let violations: Vec<ProcessId> = self
+            .tasks
             .lock()
             .unwrap()
             .values()
@@ -175,7 +737,52 @@ impl AIScheduler {
                 }
             })
             .map(|t| t.id)
-            .collect()
+            .collect();
+
+        let mut telemetry = self.telemetry.lock().unwrap();
+        for id in &violations {
+            telemetry.on_deadline_miss(*id);
+        }
+        violations
+    }
+
+    /// Advance the scheduler clock: accounts accelerator utilization, moves
+    /// QoS accounting toward the next period, samples the ready-queue depth,
+    /// and publishes telemetry when the interval is due
+    pub fn tick(&self, elapsed: u64) {
+        self.advance_accelerator_time(elapsed);
+        self.qos.lock().unwrap().tick(elapsed);
+        let depth = self.queue_len();
+
+        let due = {
+            let mut telemetry = self.telemetry.lock().unwrap();
+            telemetry.now += elapsed;
+            telemetry.sample_queue_depth(depth);
+            telemetry.due()
+        };
+        if let Some((sink, snapshot)) = due {
+            sink.publish(&snapshot);
+        }
+    }
+
+    /// Statistics for one task
+    pub fn task_metrics(&self, id: ProcessId) -> Option<TaskMetrics> {
+        self.telemetry.lock().unwrap().task(id)
+    }
+
+    /// Scheduler-wide statistics
+    pub fn scheduler_metrics(&self) -> SchedulerMetrics {
+        self.telemetry.lock().unwrap().scheduler()
+    }
+
+    /// Current telemetry for all live tasks
+    pub fn telemetry_snapshot(&self) -> TelemetrySnapshot {
+        self.telemetry.lock().unwrap().snapshot()
+    }
+
+    /// Publish a telemetry snapshot to `sink` every `interval` time units
+    pub fn set_telemetry_sink(&self, sink: Arc<dyn TelemetrySink>, interval: u64) {
+        self.telemetry.lock().unwrap().set_sink(sink, interval);
     }
 }
 
@@ -202,32 +809,456 @@ mod tests {
         let task1 = Task::new(ProcessId::new(1), WorkloadType::Interactive);
         let task2 = Task::new(ProcessId::new(2), WorkloadType::Batch);
         
-        scheduler.add_task(task1);
-        scheduler.add_task(task2);
+        scheduler.add_task(task1).unwrap();
+        scheduler.add_task(task2).unwrap();
         
         let next = scheduler.next_task();
         assert!(next.is_some());
         assert_eq!(next.unwrap().id, ProcessId::new(1)); // Interactive has higher priority
     }
 
+    #[test]
+    fn test_run_queue_in_sysinfo() {
+        let scheduler = AIScheduler::new();
+        let monitor = SystemMonitor::new();
+        scheduler.attach_sysinfo(&monitor);
+        scheduler.add_task(Task::new(ProcessId::new(1), WorkloadType::Batch)).unwrap();
+        scheduler.add_task(Task::new(ProcessId::new(2), WorkloadType::Batch)).unwrap();
+        scheduler.next_task().unwrap();
+        let [one, ..] = monitor.sample_load(system_utils::time::current_time_ms() + 3_600_000);
+        assert!((one - 2.0).abs() < 1e-6);
+    }
+
     #[test]
     fn test_realtime_priority() {
         let scheduler = AIScheduler::new();
         let task1 = Task::new(ProcessId::new(1), WorkloadType::Batch);
         let task2 = Task::new(ProcessId::new(2), WorkloadType::RealTime);
         
-        scheduler.add_task(task1);
-        scheduler.add_task(task2);
+        scheduler.add_task(task1).unwrap();
+        scheduler.add_task(task2).unwrap();
         
         let next = scheduler.next_task();
         assert_eq!(next.unwrap().id, ProcessId::new(2)); // Real-time always first
     }
 
+    #[test]
+    fn test_quantum_expiry_round_robin() {
+        let scheduler = AIScheduler::new();
+        scheduler.add_task(Task::new(ProcessId::new(1), WorkloadType::Batch).with_time_slice(10)).unwrap();
+        scheduler.add_task(Task::new(ProcessId::new(2), WorkloadType::Batch).with_time_slice(10)).unwrap();
+
+        let current = scheduler.next_task().unwrap();
+        assert_eq!(current.id, ProcessId::new(1));
+        assert!(!scheduler.preempt_check(current.id, 5));
+        assert!(scheduler.preempt_check(current.id, 5));
+
+        let next = scheduler.preempt(current.id).unwrap();
+        assert_eq!(next.id, ProcessId::new(2));
+
+        // The preempted task is requeued behind its peer with a fresh quantum
+        let requeued = scheduler.get_task(ProcessId::new(1)).unwrap();
+        assert_eq!(requeued.slice_used, 0);
+        assert_eq!(requeued.preemptions, 1);
+        assert_eq!(requeued.cpu_time_used, 10);
+        assert_eq!(scheduler.queue_len(), 1);
+    }
+
+    #[test]
+    fn test_higher_priority_preempts() {
+        let scheduler = AIScheduler::new();
+        scheduler.add_task(Task::new(ProcessId::new(1), WorkloadType::Batch)).unwrap();
+        let current = scheduler.next_task().unwrap();
+
+        // Nothing else is ready: keep running even past the quantum
+        assert!(!scheduler.preempt_check(current.id, 500));
+
+        scheduler.add_task(Task::new(ProcessId::new(2), WorkloadType::Interactive)).unwrap();
+        assert!(scheduler.preempt_check(current.id, 1));
+        assert_eq!(scheduler.preempt(current.id).unwrap().id, ProcessId::new(2));
+    }
+
+    #[test]
+    fn test_lower_priority_does_not_preempt() {
+        let scheduler = AIScheduler::new();
+        scheduler.add_task(Task::new(ProcessId::new(1), WorkloadType::Interactive).with_time_slice(10)).unwrap();
+        let current = scheduler.next_task().unwrap();
+
+        scheduler.add_task(Task::new(ProcessId::new(2), WorkloadType::Batch)).unwrap();
+        assert!(!scheduler.preempt_check(current.id, 20));
+    }
+
+    #[test]
+    fn test_accelerator_matching() {
+        let scheduler = AIScheduler::with_accelerators(vec![
+            AcceleratorInfo::new(DeviceId::new(1), 4096, 32),
+            AcceleratorInfo::new(DeviceId::new(2), 16384, 128),
+        ]);
+        scheduler.add_task(Task::new(ProcessId::new(1), WorkloadType::AITraining).with_accelerator_memory(8192)).unwrap();
+        scheduler.add_task(Task::new(ProcessId::new(2), WorkloadType::AIInference).with_accelerator_memory(1024)).unwrap();
+
+        let first = scheduler.next_task().unwrap();
+        assert_eq!(first.id, ProcessId::new(2));
+        assert_eq!(first.assigned_accelerator, Some(DeviceId::new(1)));
+
+        let second = scheduler.next_task().unwrap();
+        assert_eq!(second.assigned_accelerator, Some(DeviceId::new(2)));
+        assert_eq!(scheduler.get_task(second.id).unwrap().assigned_accelerator, Some(DeviceId::new(2)));
+    }
+
+    #[test]
+    fn test_default_accelerator() {
+        let scheduler = AIScheduler::new();
+        assert_eq!(scheduler.accelerator_stats().len(), 1);
+        scheduler.add_task(Task::new(ProcessId::new(1), WorkloadType::AITraining).with_accelerator_memory(65536)).unwrap();
+        assert_eq!(scheduler.next_task().unwrap().assigned_accelerator, Some(DeviceId::new(0)));
+        assert!(AIScheduler::with_accelerators(Vec::new()).accelerator_stats().is_empty());
+    }
+
+    #[test]
+    fn test_waiting_task_respects_priority() {
+        let scheduler = AIScheduler::new();
+        scheduler.add_task(Task::new(ProcessId::new(1), WorkloadType::AITraining)).unwrap();
+        scheduler.add_task(Task::new(ProcessId::new(2), WorkloadType::AITraining)).unwrap();
+        assert_eq!(scheduler.next_task().unwrap().id, ProcessId::new(1));
+        assert!(scheduler.next_task().is_none());
+
+        // The device frees up, but interactive work is ready first
+        scheduler.add_task(Task::new(ProcessId::new(3), WorkloadType::Interactive)).unwrap();
+        scheduler.complete_task(ProcessId::new(1));
+        assert_eq!(scheduler.next_task().unwrap().id, ProcessId::new(3));
+        assert_eq!(scheduler.next_task().unwrap().id, ProcessId::new(2));
+    }
+
+    #[test]
+    fn test_accelerator_wait_queue() {
+        let scheduler = AIScheduler::with_accelerators(vec![AcceleratorInfo::new(DeviceId::new(1), 4096, 32)]);
+        scheduler.add_task(Task::new(ProcessId::new(1), WorkloadType::AIInference)).unwrap();
+        scheduler.add_task(Task::new(ProcessId::new(2), WorkloadType::AIInference)).unwrap();
+        scheduler.add_task(Task::new(ProcessId::new(3), WorkloadType::Batch)).unwrap();
+
+        assert_eq!(scheduler.next_task().unwrap().id, ProcessId::new(1));
+        // Task 2 parks on the busy device; the CPU task runs meanwhile
+        assert_eq!(scheduler.next_task().unwrap().id, ProcessId::new(3));
+        assert!(scheduler.next_task().is_none());
+        assert_eq!(scheduler.accelerator_stats()[0].queued_tasks, 1);
+
+        scheduler.advance_accelerator_time(10);
+        scheduler.complete_task(ProcessId::new(1));
+        let next = scheduler.next_task().unwrap();
+        assert_eq!(next.id, ProcessId::new(2));
+        assert_eq!(next.assigned_accelerator, Some(DeviceId::new(1)));
+
+        let stats = &scheduler.accelerator_stats()[0];
+        assert_eq!(stats.tasks_completed, 1);
+        assert_eq!(stats.busy_time, 10);
+    }
+
+    #[test]
+    fn test_fair_share_within_priority() {
+        let scheduler = AIScheduler::new();
+        let (alice, bob) = (GroupId::from("alice"), GroupId::from("bob"));
+
+        // Charged but unconfigured groups leave the queue order alone
+        scheduler.add_task(Task::new(ProcessId::new(1), WorkloadType::Batch).with_group(alice.clone())).unwrap();
+        scheduler.add_task(Task::new(ProcessId::new(2), WorkloadType::Batch).with_group(bob.clone())).unwrap();
+        scheduler.update_cpu_time(ProcessId::new(1), 100);
+        assert_eq!(scheduler.next_task().unwrap().id, ProcessId::new(1));
+        scheduler.requeue_task(ProcessId::new(1)).unwrap();
+
+        // Configured groups reorder a band but never outrank priority
+        scheduler.set_group_weight(alice.clone(), 1).unwrap();
+        scheduler.set_group_weight(bob, 1).unwrap();
+        scheduler.add_task(Task::new(ProcessId::new(3), WorkloadType::Interactive).with_group(alice)).unwrap();
+        let current = scheduler.next_task().unwrap();
+        assert_eq!(current.id, ProcessId::new(3));
+        scheduler.update_cpu_time(ProcessId::new(3), 1000);
+        scheduler.add_task(Task::new(ProcessId::new(4), WorkloadType::Interactive).with_group(GroupId::from("carol"))).unwrap();
+        assert!(scheduler.preempt_check(current.id, current.time_slice));
+        assert_eq!(scheduler.preempt(current.id).unwrap().id, ProcessId::new(4));
+        assert_eq!(scheduler.next_task().unwrap().id, ProcessId::new(3));
+    }
+
+    #[test]
+    fn test_fair_share_convergence() {
+        let scheduler = AIScheduler::new();
+        scheduler.set_group_weight(GroupId::from("alice"), 3).unwrap();
+        scheduler.set_group_weight(GroupId::from("bob"), 1).unwrap();
+
+        // Both users keep a batch job permanently runnable
+        scheduler.add_task(Task::new(ProcessId::new(1), WorkloadType::Batch).with_group(GroupId::from("alice"))).unwrap();
+        scheduler.add_task(Task::new(ProcessId::new(2), WorkloadType::Batch).with_group(GroupId::from("bob"))).unwrap();
+
+        for _ in 0..400 {
+            let task = scheduler.next_task().unwrap();
+            scheduler.update_cpu_time(task.id, 10);
+            scheduler.requeue_task(task.id).unwrap();
+        }
+
+        let usage = scheduler.group_usage();
+        let alice = usage.iter().find(|u| u.group == GroupId::from("alice")).unwrap();
+        assert!((alice.share - 0.75).abs() < 0.01);
+        assert!((alice.target_share - 0.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_group_weight_adjustment() {
+        let scheduler = AIScheduler::new();
+        scheduler.set_group_weight(GroupId::from("app.browser"), 1).unwrap();
+        scheduler.set_group_weight(GroupId::from("app.browser"), 4).unwrap();
+        assert_eq!(scheduler.group_usage()[0].weight, 4);
+        assert!(scheduler.set_group_weight(GroupId::from("app.browser"), 0).is_err());
+        assert!(scheduler.remove_group(&GroupId::from("app.browser")).is_ok());
+        assert!(scheduler.group_usage().is_empty());
+    }
+
+    #[test]
+    fn test_task_metrics() {
+        let scheduler = AIScheduler::new();
+        scheduler.add_task(Task::new(ProcessId::new(1), WorkloadType::Batch).with_deadline(50)).unwrap();
+        scheduler.tick(20);
+
+        let task = scheduler.next_task().unwrap();
+        scheduler.preempt_check(task.id, 30);
+        scheduler.check_deadlines(60);
+        scheduler.check_deadlines(70);
+
+        let metrics = scheduler.task_metrics(task.id).unwrap();
+        assert_eq!(metrics.wait_time, 20);
+        assert_eq!(metrics.run_time, 30);
+        assert_eq!(metrics.dispatch_count, 1);
+        assert_eq!(metrics.deadline_misses, 1);
+
+        scheduler.complete_task(task.id);
+        let totals = scheduler.scheduler_metrics();
+        assert_eq!(totals.completed_tasks, 1);
+        assert_eq!(totals.context_switches, 1);
+    }
+
+    #[test]
+    fn test_telemetry_publication() {
+        struct Collector(Mutex<Vec<TelemetrySnapshot>>);
+        impl TelemetrySink for Collector {
+            fn publish(&self, snapshot: &TelemetrySnapshot) {
+                self.0.lock().unwrap().push(snapshot.clone());
+            }
+        }
+
+        let scheduler = AIScheduler::new();
+        let collector = Arc::new(Collector(Mutex::new(Vec::new())));
+        scheduler.set_telemetry_sink(collector.clone(), 100);
+        scheduler.add_task(Task::new(ProcessId::new(1), WorkloadType::Batch)).unwrap();
+        scheduler.add_task(Task::new(ProcessId::new(2), WorkloadType::Batch)).unwrap();
+
+        for _ in 0..25 {
+            scheduler.tick(10);
+        }
+
+        let published = collector.0.lock().unwrap();
+        assert_eq!(published.len(), 2);
+        assert_eq!(published[0].time, 100);
+        assert_eq!(published[0].tasks.len(), 2);
+        assert_eq!(scheduler.scheduler_metrics().max_queue_depth, 2);
+    }
+
+    #[test]
+    fn test_metrics_sink() {
+        let registry = metrics::MetricsRegistry::new();
+        let scheduler = AIScheduler::new();
+        scheduler.set_telemetry_sink(Arc::new(MetricsSink::new(&registry).unwrap()), 10);
+        scheduler.add_task(Task::new(ProcessId::new(1), WorkloadType::Batch)).unwrap();
+        let task = scheduler.next_task().unwrap();
+        scheduler.complete_task(task.id);
+        scheduler.tick(10);
+        scheduler.tick(10);
+
+        assert_eq!(registry.counter("scheduler_completed_tasks_total", "").unwrap().get(), 1);
+        assert_eq!(registry.counter("scheduler_context_switches_total", "").unwrap().get(), 1);
+        assert_eq!(registry.gauge("scheduler_tasks", "").unwrap().get(), 0.0);
+    }
+
+    #[test]
+    fn test_thermal_pressure_offloads_background_work() {
+        let scheduler = AIScheduler::with_accelerators(vec![AcceleratorInfo::new(DeviceId::new(1), 16384, 128)]);
+        scheduler.report_temperature(90.0);
+        scheduler.add_task(Task::new(ProcessId::new(1), WorkloadType::AITraining)).unwrap();
+        scheduler.add_task(Task::new(ProcessId::new(2), WorkloadType::AIInference)).unwrap();
+
+        // Inference keeps the accelerator; training runs on the CPU
+        let inference = scheduler.next_task().unwrap();
+        assert_eq!(inference.assigned_accelerator, Some(DeviceId::new(1)));
+        let training = scheduler.next_task().unwrap();
+        assert_eq!(training.id, ProcessId::new(1));
+        assert_eq!(training.assigned_accelerator, None);
+    }
+
+    #[test]
+    fn test_battery_saver_stretches_slices_and_caps_concurrency() {
+        let scheduler = AIScheduler::new();
+        scheduler.set_power_mode(PowerMode::BatterySaver);
+        for id in 1..=3 {
+            scheduler.add_task(Task::new(ProcessId::new(id), WorkloadType::Batch).with_time_slice(10)).unwrap();
+        }
+
+        let current = scheduler.next_task().unwrap();
+        assert_eq!(scheduler.effective_time_slice(&current), 20);
+        assert!(!scheduler.preempt_check(current.id, 10));
+        assert!(scheduler.preempt_check(current.id, 10));
+
+        assert!(scheduler.next_task().is_some());
+        assert!(scheduler.next_task().is_none());
+        assert_eq!(scheduler.running_count(), 2);
+
+        // Real-time work is exempt from the cap
+        scheduler.add_task(Task::new(ProcessId::new(4), WorkloadType::RealTime)).unwrap();
+        assert_eq!(scheduler.next_task().unwrap().id, ProcessId::new(4));
+
+        scheduler.set_power_mode(PowerMode::Performance);
+        assert!(scheduler.next_task().is_some());
+    }
+
+    #[test]
+    fn test_prediction_learns_from_completions() {
+        let scheduler = AIScheduler::new();
+        for id in 1..=3 {
+            scheduler.add_task(Task::new(ProcessId::new(id), WorkloadType::AIInference)).unwrap();
+            let task = scheduler.next_task().unwrap();
+            scheduler.update_cpu_time(task.id, 40);
+            scheduler.complete_task(task.id);
+        }
+        assert_eq!(scheduler.predict(&Task::new(ProcessId::new(9), WorkloadType::AIInference)), 40);
+    }
+
+    #[test]
+    fn test_edf_admission_control() {
+        let scheduler = AIScheduler::new();
+        // Default prediction for real-time work is its 5-unit slice
+        assert!(scheduler
+            .admit_task(Task::new(ProcessId::new(1), WorkloadType::RealTime).with_deadline(10), 0)
+            .is_ok());
+        assert!(scheduler
+            .admit_task(Task::new(ProcessId::new(2), WorkloadType::RealTime).with_deadline(10), 0)
+            .is_ok());
+        assert!(scheduler
+            .admit_task(Task::new(ProcessId::new(3), WorkloadType::RealTime).with_deadline(12), 0)
+            .is_err());
+        assert!(scheduler
+            .admit_task(Task::new(ProcessId::new(4), WorkloadType::RealTime).with_deadline(15), 0)
+            .is_ok());
+        assert_eq!(scheduler.list_tasks().len(), 3);
+    }
+
+    #[test]
+    fn test_kernel_process_integration() {
+        let kernel = Arc::new(Kernel::new());
+        let scheduler = Arc::new(AIScheduler::new());
+        scheduler.attach_kernel(Arc::clone(&kernel));
+
+        let pid = kernel.create_process("inference".to_string(), kernel::Priority::High);
+        scheduler.admit_task(Task::new(pid, WorkloadType::AIInference), 0).unwrap();
+        assert!(scheduler
+            .admit_task(Task::new(ProcessId::new(42), WorkloadType::Batch), 0)
+            .is_err());
+
+        let task = scheduler.next_task().unwrap();
+        assert_eq!(kernel.get_process(pid).unwrap().state, ProcessState::Running);
+        scheduler.requeue_task(task.id).unwrap();
+        assert_eq!(kernel.get_process(pid).unwrap().state, ProcessState::Ready);
+
+        kernel.terminate_process(pid).unwrap();
+        assert!(scheduler.get_task(pid).is_none());
+        assert_eq!(scheduler.queue_len(), 0);
+
+        // Tasks of dead processes are refused, and a late completion does not
+        // bring a terminated process back to Ready
+        assert_eq!(scheduler.add_task(Task::new(ProcessId::new(42), WorkloadType::Batch)), Err("Process not found".to_string()));
+        assert_eq!(scheduler.add_task(Task::new(pid, WorkloadType::Batch)), Err("Process has terminated".to_string()));
+        let worker = kernel.create_process("worker".to_string(), kernel::Priority::Normal);
+        scheduler.add_task(Task::new(worker, WorkloadType::Batch)).unwrap();
+        scheduler.next_task().unwrap();
+        kernel.terminate_process(worker).unwrap();
+        scheduler.complete_task(worker);
+        assert_eq!(kernel.get_process(worker).unwrap().state, ProcessState::Terminated);
+    }
+
+    #[test]
+    fn test_hard_affinity() {
+        let scheduler = AIScheduler::new();
+        scheduler.add_task(Task::new(ProcessId::new(1), WorkloadType::Interactive).with_affinity(CpuSet::from_cpus(&[2]))).unwrap();
+        scheduler.add_task(Task::new(ProcessId::new(2), WorkloadType::Batch)).unwrap();
+
+        // CPU 0 may not run the pinned task even though it has higher priority
+        assert_eq!(scheduler.next_task_on(0).unwrap().id, ProcessId::new(2));
+        assert!(scheduler.next_task_on(1).is_none());
+        assert_eq!(scheduler.next_task_on(2).unwrap().id, ProcessId::new(1));
+    }
+
+    #[test]
+    fn test_soft_affinity_avoids_migration() {
+        let scheduler = AIScheduler::new();
+        scheduler.set_cpu_topology(CpuTopology::uniform(2, 2));
+        scheduler.add_task(Task::new(ProcessId::new(1), WorkloadType::Batch)).unwrap();
+        scheduler.add_task(Task::new(ProcessId::new(2), WorkloadType::Batch)).unwrap();
+        scheduler.add_task(Task::new(ProcessId::new(3), WorkloadType::Batch).with_preferred_node(1)).unwrap();
+
+        assert_eq!(scheduler.next_task_on(0).unwrap().id, ProcessId::new(1));
+        assert_eq!(scheduler.next_task_on(1).unwrap().id, ProcessId::new(2));
+        scheduler.requeue_task(ProcessId::new(1)).unwrap();
+        scheduler.requeue_task(ProcessId::new(2)).unwrap();
+
+        // CPU 3 (node 1) prefers the task homed there; CPUs 1 and 0 get
+        // back the tasks that last ran on them
+        assert_eq!(scheduler.next_task_on(3).unwrap().id, ProcessId::new(3));
+        assert_eq!(scheduler.next_task_on(1).unwrap().id, ProcessId::new(2));
+        let task = scheduler.next_task_on(0).unwrap();
+        assert_eq!(task.id, ProcessId::new(1));
+        assert_eq!(task.migrations, 0);
+    }
+
+    #[test]
+    fn test_qos_throttles_training() {
+        let scheduler = AIScheduler::new();
+        scheduler.add_task(Task::new(ProcessId::new(1), WorkloadType::AITraining)).unwrap();
+
+        let training = scheduler.next_task().unwrap();
+        assert!(!scheduler.preempt_check(training.id, 30));
+        assert_eq!(scheduler.qos_remaining_budget(QosClass::BestEffort), 70);
+
+        // An interactive task arrives and owns the rest of the period
+        scheduler.add_task(Task::new(ProcessId::new(2), WorkloadType::Interactive)).unwrap();
+        assert!(scheduler.preempt_check(training.id, 20));
+        assert_eq!(scheduler.preempt(training.id).unwrap().id, ProcessId::new(2));
+
+        // Training is throttled even though the UI task is no longer queued
+        assert!(scheduler.next_task().is_none());
+        scheduler.update_cpu_time(ProcessId::new(2), 50);
+        assert_eq!(scheduler.next_task().unwrap().id, ProcessId::new(1));
+    }
+
+    #[test]
+    fn test_qos_period_ends_while_idle() {
+        let scheduler = AIScheduler::new();
+        scheduler
+            .set_qos_budget(QosClass::BestEffort, QosBudget::new(0.0, 0.4))
+            .unwrap();
+        scheduler.add_task(Task::new(ProcessId::new(1), WorkloadType::Batch)).unwrap();
+        let batch = scheduler.next_task().unwrap();
+        assert!(scheduler.preempt_check(batch.id, 40));
+        scheduler.tick(40);
+        assert!(scheduler.preempt(batch.id).is_none());
+
+        // Nothing runs, but the period still ends and the class is let go
+        scheduler.tick(40);
+        assert!(scheduler.next_task().is_none());
+        scheduler.tick(20);
+        assert_eq!(scheduler.next_task().unwrap().id, batch.id);
+    }
+
     #[test]
     fn test_deadline_checking() {
         let scheduler = AIScheduler::new();
         let task = Task::new(ProcessId::new(1), WorkloadType::RealTime).with_deadline(100);
-        scheduler.add_task(task);
+        scheduler.add_task(task).unwrap();
         
         let violations = scheduler.check_deadlines(150);
         assert_eq!(violations.len(), 1);
@@ -0,0 +1,128 @@
+//! Power- and thermal-aware scheduling constraints
+//!
+//! Combines the user's power mode with thermal signals from the sensor
+//! subsystem into a pressure level, and derives the constraints the
+//! scheduler applies at that level: keeping background work off the
+//! accelerators, stretching time slices, and capping concurrency.
+
+/// User-selected power mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PowerMode {
+    Performance,
+    #[default]
+    Balanced,
+    BatterySaver,
+}
+
+/// Thermal condition reported by the sensor subsystem
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum ThermalState {
+    #[default]
+    Nominal,
+    Elevated,
+    Throttling,
+    Critical,
+}
+
+impl ThermalState {
+    /// Classify a die temperature reading in degrees Celsius
+    pub fn from_celsius(celsius: f32) -> Self {
+        if celsius >= 95.0 {
+            ThermalState::Critical
+        } else if celsius >= 85.0 {
+            ThermalState::Throttling
+        } else if celsius >= 70.0 {
+            ThermalState::Elevated
+        } else {
+            ThermalState::Nominal
+        }
+    }
+}
+
+/// Combined thermal and battery pressure
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Pressure {
+    None,
+    Moderate,
+    High,
+    Severe,
+}
+
+/// Scheduling constraints in effect at a pressure level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerConstraints {
+    /// Run AITraining and Batch work on the CPU rather than an accelerator
+    pub offload_background: bool,
+    /// Factor applied to every task's time slice
+    pub slice_multiplier: u64,
+    /// Maximum number of tasks dispatched at once (real-time tasks exempt)
+    pub max_concurrency: Option<usize>,
+}
+
+impl PowerConstraints {
+    pub fn for_pressure(pressure: Pressure) -> Self {
+        let (offload_background, slice_multiplier, max_concurrency) = match pressure {
+            Pressure::None => (false, 1, None),
+            Pressure::Moderate => (false, 2, None),
+            Pressure::High => (true, 2, Some(2)),
+            Pressure::Severe => (true, 4, Some(1)),
+        };
+        PowerConstraints {
+            offload_background,
+            slice_multiplier,
+            max_concurrency,
+        }
+    }
+}
+
+/// Current power inputs
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PowerState {
+    pub mode: PowerMode,
+    pub thermal: ThermalState,
+}
+
+impl PowerState {
+    pub fn pressure(&self) -> Pressure {
+        let thermal = match self.thermal {
+            ThermalState::Nominal => Pressure::None,
+            ThermalState::Elevated => Pressure::Moderate,
+            ThermalState::Throttling => Pressure::High,
+            ThermalState::Critical => Pressure::Severe,
+        };
+        let battery = match self.mode {
+            PowerMode::Performance | PowerMode::Balanced => Pressure::None,
+            PowerMode::BatterySaver => Pressure::High,
+        };
+        thermal.max(battery)
+    }
+
+    pub fn constraints(&self) -> PowerConstraints {
+        PowerConstraints::for_pressure(self.pressure())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pressure_combines_inputs() {
+        let mut state = PowerState::default();
+        assert_eq!(state.pressure(), Pressure::None);
+
+        state.mode = PowerMode::BatterySaver;
+        assert_eq!(state.pressure(), Pressure::High);
+
+        state.thermal = ThermalState::from_celsius(97.0);
+        assert_eq!(state.pressure(), Pressure::Severe);
+        assert_eq!(state.constraints().max_concurrency, Some(1));
+    }
+
+    #[test]
+    fn test_thermal_classification() {
+        assert_eq!(ThermalState::from_celsius(45.0), ThermalState::Nominal);
+        assert_eq!(ThermalState::from_celsius(72.5), ThermalState::Elevated);
+        assert_eq!(ThermalState::from_celsius(88.0), ThermalState::Throttling);
+    }
+}
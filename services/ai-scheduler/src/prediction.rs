@@ -0,0 +1,119 @@
+//! Workload execution-time prediction
+//!
+//! Learns online from completed tasks. For each workload type it keeps a
+//! least-squares fit of run time against task size and an exponential moving
+//! average; the fit is used once sizes vary, the average otherwise.
+
+use std::collections::HashMap;
+
+use crate::{Task, WorkloadType};
+
+/// Weight of the newest observation in the moving average
+const EWMA_ALPHA: f64 = 0.25;
+
+#[derive(Debug, Clone, Default)]
+struct WorkloadModel {
+    samples: u64,
+    sum_x: f64,
+    sum_y: f64,
+    sum_xx: f64,
+    sum_xy: f64,
+    average: f64,
+}
+
+impl WorkloadModel {
+    fn observe(&mut self, size: u64, run_time: u64) {
+        let (x, y) = (size as f64, run_time as f64);
+        self.average = if self.samples == 0 {
+            y
+        } else {
+            EWMA_ALPHA * y + (1.0 - EWMA_ALPHA) * self.average
+        };
+        self.samples += 1;
+        self.sum_x += x;
+        self.sum_y += y;
+        self.sum_xx += x * x;
+        self.sum_xy += x * y;
+    }
+
+    fn predict(&self, size: u64) -> Option<f64> {
+        if self.samples == 0 {
+            return None;
+        }
+        let n = self.samples as f64;
+        let variance = n * self.sum_xx - self.sum_x * self.sum_x;
+        if self.samples < 2 || variance.abs() < f64::EPSILON {
+            return Some(self.average);
+        }
+        let slope = (n * self.sum_xy - self.sum_x * self.sum_y) / variance;
+        let intercept = (self.sum_y - slope * self.sum_x) / n;
+        Some((intercept + slope * size as f64).max(0.0))
+    }
+}
+
+/// Online run-time model keyed by workload type
+#[derive(Debug, Default)]
+pub struct ExecutionPredictor {
+    models: HashMap<WorkloadType, WorkloadModel>,
+}
+
+impl ExecutionPredictor {
+    pub fn new() -> Self {
+        ExecutionPredictor {
+            models: HashMap::new(),
+        }
+    }
+
+    /// Record the total run time of a finished task
+    pub fn observe(&mut self, workload_type: WorkloadType, size: u64, run_time: u64) {
+        self.models.entry(workload_type).or_default().observe(size, run_time);
+    }
+
+    /// Predicted total run time; falls back to the workload's default time
+    /// slice until the model has seen a task of that type
+    pub fn predict(&self, task: &Task) -> u64 {
+        self.models
+            .get(&task.workload_type)
+            .and_then(|m| m.predict(task.work_units))
+            .map(|t| t.round() as u64)
+            .unwrap_or_else(|| task.workload_type.default_time_slice())
+    }
+
+    /// Predicted run time still outstanding for a task
+    pub fn remaining(&self, task: &Task) -> u64 {
+        self.predict(task).saturating_sub(task.cpu_time_used)
+    }
+
+    /// Number of observations recorded for a workload type
+    pub fn samples(&self, workload_type: WorkloadType) -> u64 {
+        self.models.get(&workload_type).map_or(0, |m| m.samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProcessId;
+
+    #[test]
+    fn test_regression_on_size() {
+        let mut predictor = ExecutionPredictor::new();
+        for size in [10, 20, 30, 40] {
+            predictor.observe(WorkloadType::Batch, size, 5 + 2 * size);
+        }
+        let task = Task::new(ProcessId::new(1), WorkloadType::Batch).with_work_units(100);
+        assert_eq!(predictor.predict(&task), 205);
+    }
+
+    #[test]
+    fn test_moving_average_and_fallback() {
+        let mut predictor = ExecutionPredictor::new();
+        let task = Task::new(ProcessId::new(1), WorkloadType::AIInference);
+        assert_eq!(predictor.predict(&task), WorkloadType::AIInference.default_time_slice());
+
+        predictor.observe(WorkloadType::AIInference, 0, 40);
+        predictor.observe(WorkloadType::AIInference, 0, 80);
+        assert_eq!(predictor.predict(&task), 50);
+        assert_eq!(predictor.samples(WorkloadType::AIInference), 2);
+    }
+}
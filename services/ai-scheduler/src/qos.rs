@@ -0,0 +1,252 @@
+//! QoS classes with CPU bandwidth reservation
+//!
+//! Each class owns a reservation (share of every accounting period it is
+//! guaranteed) and a ceiling (share it may never exceed). A class is
+//! throttled for the rest of the period once it reaches its ceiling, or once
+//! the time left in the period is needed to honor other runnable classes'
+//! unmet reservations, so AI training cannot starve interactive work.
+//! Run time moves the period along as it is charged, and the scheduler
+//! clock covers the time nothing ran, so a throttled class is let go when
+//! the period ends even if no other class runs in the meantime.
+
+use std::collections::HashMap;
+
+use crate::WorkloadType;
+
+/// Default accounting period, in scheduler time units
+pub const DEFAULT_QOS_PERIOD: u64 = 100;
+
+/// Quality-of-service class
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum QosClass {
+    Guaranteed,
+    Burstable,
+    BestEffort,
+}
+
+impl QosClass {
+    pub const ALL: [QosClass; 3] = [QosClass::Guaranteed, QosClass::Burstable, QosClass::BestEffort];
+
+    /// Class a workload type falls into unless overridden
+    pub fn for_workload(workload_type: WorkloadType) -> Self {
+        match workload_type {
+            WorkloadType::RealTime | WorkloadType::Interactive => QosClass::Guaranteed,
+            WorkloadType::AIInference => QosClass::Burstable,
+            WorkloadType::AITraining | WorkloadType::Batch => QosClass::BestEffort,
+        }
+    }
+}
+
+/// Bandwidth limits of a class, as fractions of the period (0.0 - 1.0)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QosBudget {
+    pub reservation: f64,
+    pub ceiling: f64,
+}
+
+impl QosBudget {
+    pub fn new(reservation: f64, ceiling: f64) -> Self {
+        QosBudget { reservation, ceiling }
+    }
+
+    fn default_for(class: QosClass) -> Self {
+        match class {
+            QosClass::Guaranteed => QosBudget::new(0.5, 1.0),
+            QosClass::Burstable => QosBudget::new(0.3, 1.0),
+            QosClass::BestEffort => QosBudget::new(0.0, 1.0),
+        }
+    }
+}
+
+/// Usage of one class in the current period
+#[derive(Debug, Clone)]
+pub struct QosUsage {
+    pub class: QosClass,
+    pub budget: QosBudget,
+    pub used: u64,
+    /// Time the class may still use this period before hitting its ceiling
+    pub remaining: u64,
+}
+
+/// Per-class budgets and accounting
+#[derive(Debug)]
+pub struct QosAccounting {
+    period: u64,
+    elapsed: u64,
+    /// Run time charged since the last tick, which already moved the period
+    unticked: u64,
+    budgets: HashMap<QosClass, QosBudget>,
+    used: HashMap<QosClass, u64>,
+}
+
+impl QosAccounting {
+    pub fn new(period: u64) -> Self {
+        QosAccounting {
+            period: period.max(1),
+            elapsed: 0,
+            unticked: 0,
+            budgets: QosClass::ALL.iter().map(|c| (*c, QosBudget::default_for(*c))).collect(),
+            used: HashMap::new(),
+        }
+    }
+
+    pub fn period(&self) -> u64 {
+        self.period
+    }
+
+    pub fn budget(&self, class: QosClass) -> QosBudget {
+        self.budgets[&class]
+    }
+
+    /// Change a class's budget; reservations may not oversubscribe the period
+    pub fn set_budget(&mut self, class: QosClass, budget: QosBudget) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&budget.reservation) || !(0.0..=1.0).contains(&budget.ceiling) {
+            return Err("Budget shares must be between 0 and 1".to_string());
+        }
+        if budget.ceiling < budget.reservation {
+            return Err("Ceiling must not be below the reservation".to_string());
+        }
+        let reserved: f64 = self
+            .budgets
+            .iter()
+            .filter(|(c, _)| **c != class)
+            .map(|(_, b)| b.reservation)
+            .sum();
+        if reserved + budget.reservation > 1.0 + f64::EPSILON {
+            return Err("Reservations exceed the period".to_string());
+        }
+        self.budgets.insert(class, budget);
+        Ok(())
+    }
+
+    /// Charge run time to a class, rolling over to a new period when due
+    pub fn charge(&mut self, class: QosClass, elapsed: u64) {
+        self.unticked += elapsed;
+        self.advance(Some(class), elapsed);
+    }
+
+    /// Advance the clock by `elapsed`; the part not already charged as run
+    /// time was idle and still counts toward the period
+    pub fn tick(&mut self, elapsed: u64) {
+        let idle = elapsed.saturating_sub(self.unticked);
+        self.unticked = self.unticked.saturating_sub(elapsed);
+        self.advance(None, idle);
+    }
+
+    fn advance(&mut self, class: Option<QosClass>, elapsed: u64) {
+        let mut elapsed = elapsed;
+        while elapsed > 0 {
+            let step = elapsed.min(self.period - self.elapsed);
+            if let Some(class) = class {
+                *self.used.entry(class).or_default() += step;
+            }
+            self.elapsed += step;
+            elapsed -= step;
+            if self.elapsed >= self.period {
+                self.elapsed = 0;
+                self.used.clear();
+            }
+        }
+    }
+
+    fn used(&self, class: QosClass) -> u64 {
+        self.used.get(&class).copied().unwrap_or(0)
+    }
+
+    fn share_of_period(&self, share: f64) -> u64 {
+        (share * self.period as f64).round() as u64
+    }
+
+    /// Time the class may still run this period before hitting its ceiling
+    pub fn remaining(&self, class: QosClass) -> u64 {
+        self.share_of_period(self.budget(class).ceiling).saturating_sub(self.used(class))
+    }
+
+    /// Whether `class` must wait for the next period, given the classes
+    /// that currently have runnable tasks
+    pub fn is_throttled(&self, class: QosClass, runnable: &[QosClass]) -> bool {
+        if self.remaining(class) == 0 {
+            return true;
+        }
+        let owed: u64 = runnable
+            .iter()
+            .filter(|c| **c != class)
+            .map(|c| self.share_of_period(self.budget(*c).reservation).saturating_sub(self.used(*c)))
+            .sum();
+        let left = self.period - self.elapsed;
+        let own_unmet = self.share_of_period(self.budget(class).reservation) > self.used(class);
+        owed > 0 && !own_unmet && left <= owed
+    }
+
+    pub fn usage(&self) -> Vec<QosUsage> {
+        QosClass::ALL
+            .iter()
+            .map(|class| QosUsage {
+                class: *class,
+                budget: self.budget(*class),
+                used: self.used(*class),
+                remaining: self.remaining(*class),
+            })
+            .collect()
+    }
+}
+
+impl Default for QosAccounting {
+    fn default() -> Self {
+        Self::new(DEFAULT_QOS_PERIOD)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ceiling_and_period_rollover() {
+        let mut qos = QosAccounting::new(100);
+        qos.set_budget(QosClass::BestEffort, QosBudget::new(0.0, 0.5)).unwrap();
+        qos.charge(QosClass::BestEffort, 40);
+        assert_eq!(qos.remaining(QosClass::BestEffort), 10);
+        qos.charge(QosClass::BestEffort, 10);
+        assert!(qos.is_throttled(QosClass::BestEffort, &[QosClass::BestEffort]));
+
+        qos.charge(QosClass::Guaranteed, 50);
+        assert_eq!(qos.remaining(QosClass::BestEffort), 50);
+        assert!(!qos.is_throttled(QosClass::BestEffort, &[QosClass::BestEffort]));
+    }
+
+    #[test]
+    fn test_idle_time_ends_period() {
+        let mut qos = QosAccounting::new(100);
+        qos.set_budget(QosClass::BestEffort, QosBudget::new(0.0, 0.3)).unwrap();
+        qos.charge(QosClass::BestEffort, 30);
+        qos.tick(30);
+        assert!(qos.is_throttled(QosClass::BestEffort, &[QosClass::BestEffort]));
+
+        // Charged run time is not counted twice
+        qos.tick(60);
+        assert!(qos.is_throttled(QosClass::BestEffort, &[QosClass::BestEffort]));
+        qos.tick(10);
+        assert_eq!(qos.remaining(QosClass::BestEffort), 30);
+    }
+
+    #[test]
+    fn test_reservation_protected() {
+        let mut qos = QosAccounting::new(100);
+        qos.charge(QosClass::BestEffort, 50);
+
+        // Guaranteed still needs its 50 units this period
+        let runnable = [QosClass::Guaranteed, QosClass::BestEffort];
+        assert!(qos.is_throttled(QosClass::BestEffort, &runnable));
+        assert!(!qos.is_throttled(QosClass::Guaranteed, &runnable));
+        assert!(!qos.is_throttled(QosClass::BestEffort, &[QosClass::BestEffort]));
+    }
+
+    #[test]
+    fn test_budget_validation() {
+        let mut qos = QosAccounting::default();
+        assert!(qos.set_budget(QosClass::Burstable, QosBudget::new(0.6, 0.9)).is_err());
+        assert!(qos.set_budget(QosClass::Burstable, QosBudget::new(0.4, 0.3)).is_err());
+        assert!(qos.set_budget(QosClass::Burstable, QosBudget::new(0.5, 0.9)).is_ok());
+    }
+}
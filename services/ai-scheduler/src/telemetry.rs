@@ -0,0 +1,208 @@
+//! Scheduler telemetry
+//!
+//! Per-task and scheduler-wide statistics, plus periodic publication of
+//! snapshots to a pluggable sink (metrics service, event bus, IPC bridge).
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use metrics::{Counter, Gauge, MetricsRegistry};
+
+use crate::ProcessId;
+
+/// Number of queue-depth samples retained
+pub const QUEUE_DEPTH_HISTORY: usize = 256;
+
+/// Statistics for a single task
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TaskMetrics {
+    /// Total time spent waiting in the ready queue
+    pub wait_time: u64,
+    /// Total time spent running
+    pub run_time: u64,
+    /// Number of times the task was dispatched
+    pub dispatch_count: u64,
+    /// Number of deadline misses observed
+    pub deadline_misses: u64,
+    /// Run time spent on an accelerator
+    pub accelerator_time: u64,
+    enqueued_at: Option<u64>,
+    deadline_flagged: bool,
+}
+
+/// Scheduler-wide statistics
+#[derive(Debug, Clone, Default)]
+pub struct SchedulerMetrics {
+    pub context_switches: u64,
+    pub completed_tasks: u64,
+    pub total_deadline_misses: u64,
+    pub max_queue_depth: usize,
+    /// (time, ready-queue depth) samples, oldest first
+    pub queue_depth_history: VecDeque<(u64, usize)>,
+}
+
+impl SchedulerMetrics {
+    /// Average ready-queue depth over the retained history
+    pub fn average_queue_depth(&self) -> f64 {
+        if self.queue_depth_history.is_empty() {
+            return 0.0;
+        }
+        let total: usize = self.queue_depth_history.iter().map(|(_, depth)| depth).sum();
+        total as f64 / self.queue_depth_history.len() as f64
+    }
+}
+
+/// Point-in-time view published to sinks
+#[derive(Debug, Clone)]
+pub struct TelemetrySnapshot {
+    pub time: u64,
+    pub scheduler: SchedulerMetrics,
+    pub tasks: Vec<(ProcessId, TaskMetrics)>,
+}
+
+/// Receiver of periodic telemetry snapshots
+pub trait TelemetrySink: Send + Sync {
+    fn publish(&self, snapshot: &TelemetrySnapshot);
+}
+
+/// Sink that feeds snapshots into a metrics registry
+pub struct MetricsSink {
+    context_switches: Counter,
+    completed_tasks: Counter,
+    deadline_misses: Counter,
+    queue_depth: Gauge,
+    live_tasks: Gauge,
+    /// Totals already added to the counters
+    published: Mutex<(u64, u64, u64)>,
+}
+
+impl MetricsSink {
+    pub fn new(registry: &MetricsRegistry) -> Result<Self, String> {
+        Ok(MetricsSink {
+            context_switches: registry.counter("scheduler_context_switches_total", "Context switches")?,
+            completed_tasks: registry.counter("scheduler_completed_tasks_total", "Tasks run to completion")?,
+            deadline_misses: registry.counter("scheduler_deadline_misses_total", "Deadlines missed")?,
+            queue_depth: registry.gauge("scheduler_queue_depth", "Ready-queue depth at the last sample")?,
+            live_tasks: registry.gauge("scheduler_tasks", "Tasks known to the scheduler")?,
+            published: Mutex::new((0, 0, 0)),
+        })
+    }
+}
+
+impl TelemetrySink for MetricsSink {
+    fn publish(&self, snapshot: &TelemetrySnapshot) {
+        let scheduler = &snapshot.scheduler;
+        let mut published = self.published.lock().unwrap();
+        self.context_switches.add(scheduler.context_switches.saturating_sub(published.0));
+        self.completed_tasks.add(scheduler.completed_tasks.saturating_sub(published.1));
+        self.deadline_misses.add(scheduler.total_deadline_misses.saturating_sub(published.2));
+        *published = (
+            scheduler.context_switches,
+            scheduler.completed_tasks,
+            scheduler.total_deadline_misses,
+        );
+
+        let depth = scheduler.queue_depth_history.back().map_or(0, |(_, depth)| *depth);
+        self.queue_depth.set(depth as f64);
+        self.live_tasks.set(snapshot.tasks.len() as f64);
+    }
+}
+
+/// Telemetry state owned by the scheduler
+#[derive(Default)]
+pub(crate) struct Telemetry {
+    pub(crate) now: u64,
+    tasks: HashMap<ProcessId, TaskMetrics>,
+    scheduler: SchedulerMetrics,
+    last_dispatched: Option<ProcessId>,
+    sink: Option<(Arc<dyn TelemetrySink>, u64)>,
+    last_publish: u64,
+}
+
+impl Telemetry {
+    pub(crate) fn on_enqueue(&mut self, id: ProcessId) {
+        let now = self.now;
+        self.tasks.entry(id).or_default().enqueued_at = Some(now);
+    }
+
+    pub(crate) fn on_dispatch(&mut self, id: ProcessId) {
+        let now = self.now;
+        let metrics = self.tasks.entry(id).or_default();
+        if let Some(enqueued_at) = metrics.enqueued_at.take() {
+            metrics.wait_time += now.saturating_sub(enqueued_at);
+        }
+        metrics.dispatch_count += 1;
+
+        if self.last_dispatched != Some(id) {
+            self.scheduler.context_switches += 1;
+        }
+        self.last_dispatched = Some(id);
+    }
+
+    pub(crate) fn on_run(&mut self, id: ProcessId, elapsed: u64, on_accelerator: bool) {
+        let metrics = self.tasks.entry(id).or_default();
+        metrics.run_time += elapsed;
+        if on_accelerator {
+            metrics.accelerator_time += elapsed;
+        }
+    }
+
+    pub(crate) fn on_deadline_miss(&mut self, id: ProcessId) {
+        let metrics = self.tasks.entry(id).or_default();
+        if !metrics.deadline_flagged {
+            metrics.deadline_flagged = true;
+            metrics.deadline_misses += 1;
+            self.scheduler.total_deadline_misses += 1;
+        }
+    }
+
+    pub(crate) fn on_complete(&mut self, id: ProcessId) {
+        if self.tasks.remove(&id).is_some() {
+            self.scheduler.completed_tasks += 1;
+        }
+    }
+
+    pub(crate) fn on_remove(&mut self, id: ProcessId) {
+        self.tasks.remove(&id);
+    }
+
+    pub(crate) fn sample_queue_depth(&mut self, depth: usize) {
+        let history = &mut self.scheduler.queue_depth_history;
+        if history.len() >= QUEUE_DEPTH_HISTORY {
+            history.pop_front();
+        }
+        history.push_back((self.now, depth));
+        self.scheduler.max_queue_depth = self.scheduler.max_queue_depth.max(depth);
+    }
+
+    pub(crate) fn task(&self, id: ProcessId) -> Option<TaskMetrics> {
+        self.tasks.get(&id).cloned()
+    }
+
+    pub(crate) fn scheduler(&self) -> SchedulerMetrics {
+        self.scheduler.clone()
+    }
+
+    pub(crate) fn snapshot(&self) -> TelemetrySnapshot {
+        TelemetrySnapshot {
+            time: self.now,
+            scheduler: self.scheduler.clone(),
+            tasks: self.tasks.iter().map(|(id, m)| (*id, m.clone())).collect(),
+        }
+    }
+
+    pub(crate) fn set_sink(&mut self, sink: Arc<dyn TelemetrySink>, interval: u64) {
+        self.sink = Some((sink, interval.max(1)));
+        self.last_publish = self.now;
+    }
+
+    /// Snapshot and sink to publish to, if the publish interval has elapsed
+    pub(crate) fn due(&mut self) -> Option<(Arc<dyn TelemetrySink>, TelemetrySnapshot)> {
+        let (sink, interval) = self.sink.clone()?;
+        if self.now - self.last_publish < interval {
+            return None;
+        }
+        self.last_publish = self.now;
+        Some((sink, self.snapshot()))
+    }
+}
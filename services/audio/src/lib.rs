@@ -0,0 +1,574 @@
+//! Audio Service
+//!
+//! A sound server on top of the HAL `AudioDevice` trait. Applications open
+//! streams and submit samples over IPC or straight into the stream's shared
+//! buffer; every period the server mixes the streams routed to each output
+//! device with their volumes and queues the result on the device. Streams
+//! follow the default output unless routed elsewhere, so switching the
+//! default moves them live.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use hal::{AudioDevice, AudioFormat};
+use ipc::JsonService;
+use serde::{Deserialize, Serialize};
+
+pub mod mix;
+
+/// Sample rate every stream and device runs at
+pub const MIX_SAMPLE_RATE: u32 = 48_000;
+
+/// Channels the mixer produces
+pub const MIX_CHANNELS: u16 = 2;
+
+/// Most audio a stream may have buffered ahead
+pub const MAX_STREAM_BUFFER_MS: u64 = 200;
+
+/// Output device shared between its driver and the server
+pub type SharedAudioDevice = Arc<Mutex<dyn AudioDevice>>;
+
+/// Interleaved samples waiting to be mixed, shared with the producing app
+pub type StreamBuffer = Arc<Mutex<VecDeque<i16>>>;
+
+/// Stream identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct StreamId(u64);
+
+impl StreamId {
+    pub fn new(id: u64) -> Self {
+        StreamId(id)
+    }
+}
+
+/// Per-stream statistics
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StreamStats {
+    pub id: StreamId,
+    pub app: String,
+    /// Device the stream is currently mixed into
+    pub device: Option<String>,
+    pub volume: f32,
+    pub buffered_frames: usize,
+    /// Time until a sample written now is heard
+    pub latency_ms: u64,
+    pub underruns: u64,
+    pub frames_played: u64,
+}
+
+/// Per-device statistics
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceStats {
+    pub name: String,
+    pub default: bool,
+    pub queued_frames: usize,
+    pub latency_ms: u64,
+    /// Periods in which the device had run dry
+    pub underruns: u64,
+    pub frames_written: u64,
+    /// Mixed frames the device had no room for
+    pub frames_dropped: u64,
+}
+
+struct Stream {
+    app: String,
+    channels: u16,
+    volume: f32,
+    /// Explicit route; `None` follows the default output
+    device: Option<String>,
+    buffer: StreamBuffer,
+    started: bool,
+    underruns: u64,
+    frames_played: u64,
+}
+
+struct Output {
+    name: String,
+    device: SharedAudioDevice,
+    underruns: u64,
+    frames_written: u64,
+    frames_dropped: u64,
+}
+
+/// The sound server
+pub struct AudioServer {
+    format: AudioFormat,
+    streams: Arc<Mutex<BTreeMap<StreamId, Stream>>>,
+    outputs: Arc<Mutex<Vec<Output>>>,
+    default_output: Arc<Mutex<Option<String>>>,
+    master_volume: Arc<Mutex<f32>>,
+    next_id: Arc<Mutex<u64>>,
+}
+
+impl AudioServer {
+    pub fn new() -> Self {
+        AudioServer {
+            format: AudioFormat::new(MIX_SAMPLE_RATE, MIX_CHANNELS),
+            streams: Arc::new(Mutex::new(BTreeMap::new())),
+            outputs: Arc::new(Mutex::new(Vec::new())),
+            default_output: Arc::new(Mutex::new(None)),
+            master_volume: Arc::new(Mutex::new(1.0)),
+            next_id: Arc::new(Mutex::new(1)),
+        }
+    }
+
+    pub fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Add an output device; the first one becomes the default
+    pub fn add_device(&self, name: &str, device: SharedAudioDevice) -> Result<(), String> {
+        let format = device.lock().unwrap().format();
+        if format != self.format {
+            return Err(format!(
+                "Device {} plays {} Hz x{}, mixer runs at {} Hz x{}",
+                name, format.sample_rate, format.channels, self.format.sample_rate, self.format.channels
+            ));
+        }
+        let mut outputs = self.outputs.lock().unwrap();
+        if outputs.iter().any(|o| o.name == name) {
+            return Err(format!("Device already registered: {}", name));
+        }
+        outputs.push(Output {
+            name: name.to_string(),
+            device,
+            underruns: 0,
+            frames_written: 0,
+            frames_dropped: 0,
+        });
+        self.default_output.lock().unwrap().get_or_insert_with(|| name.to_string());
+        Ok(())
+    }
+
+    /// Remove a device; streams routed to it fall back to the default
+    pub fn remove_device(&self, name: &str) -> Result<(), String> {
+        let mut outputs = self.outputs.lock().unwrap();
+        let index = outputs
+            .iter()
+            .position(|o| o.name == name)
+            .ok_or_else(|| format!("Unknown device: {}", name))?;
+        outputs.remove(index);
+
+        let mut default = self.default_output.lock().unwrap();
+        if default.as_deref() == Some(name) {
+            *default = outputs.first().map(|o| o.name.clone());
+        }
+        for stream in self.streams.lock().unwrap().values_mut() {
+            if stream.device.as_deref() == Some(name) {
+                stream.device = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// Switch the default output; unrouted streams move with it
+    pub fn set_default_output(&self, name: &str) -> Result<(), String> {
+        self.require_device(name)?;
+        *self.default_output.lock().unwrap() = Some(name.to_string());
+        Ok(())
+    }
+
+    pub fn default_output(&self) -> Option<String> {
+        self.default_output.lock().unwrap().clone()
+    }
+
+    fn require_device(&self, name: &str) -> Result<(), String> {
+        if self.outputs.lock().unwrap().iter().any(|o| o.name == name) {
+            Ok(())
+        } else {
+            Err(format!("Unknown device: {}", name))
+        }
+    }
+
+    /// Open a stream; mono streams are upmixed to the mixer's layout
+    pub fn open_stream(&self, app: &str, channels: u16) -> Result<StreamId, String> {
+        if channels != 1 && channels != self.format.channels {
+            return Err(format!("Unsupported channel count: {}", channels));
+        }
+        let mut next_id = self.next_id.lock().unwrap();
+        let id = StreamId(*next_id);
+        *next_id += 1;
+        self.streams.lock().unwrap().insert(
+            id,
+            Stream {
+                app: app.to_string(),
+                channels,
+                volume: 1.0,
+                device: None,
+                buffer: Arc::new(Mutex::new(VecDeque::new())),
+                started: false,
+                underruns: 0,
+                frames_played: 0,
+            },
+        );
+        Ok(id)
+    }
+
+    pub fn close_stream(&self, id: StreamId) -> Result<(), String> {
+        self.streams
+            .lock()
+            .unwrap()
+            .remove(&id)
+            .map(|_| ())
+            .ok_or_else(|| format!("Unknown stream: {:?}", id))
+    }
+
+    /// Buffer the app can write into without going through IPC. Samples
+    /// must already be in the mixer's channel layout.
+    pub fn shared_buffer(&self, id: StreamId) -> Result<StreamBuffer, String> {
+        let mut streams = self.streams.lock().unwrap();
+        let stream = streams.get_mut(&id).ok_or_else(|| format!("Unknown stream: {:?}", id))?;
+        stream.started = true;
+        Ok(Arc::clone(&stream.buffer))
+    }
+
+    /// Queue samples on a stream, returning the frames accepted
+    pub fn write(&self, id: StreamId, samples: &[i16]) -> Result<usize, String> {
+        let mut streams = self.streams.lock().unwrap();
+        let stream = streams.get_mut(&id).ok_or_else(|| format!("Unknown stream: {:?}", id))?;
+        if !samples.len().is_multiple_of(stream.channels as usize) {
+            return Err("Partial frame".to_string());
+        }
+        let samples = mix::convert_channels(samples, stream.channels, self.format.channels)?;
+        let channels = self.format.channels as usize;
+        let limit = self.format.frames_in(MAX_STREAM_BUFFER_MS);
+
+        let mut buffer = stream.buffer.lock().unwrap();
+        let free = limit.saturating_sub(buffer.len() / channels);
+        let frames = (samples.len() / channels).min(free);
+        buffer.extend(&samples[..frames * channels]);
+        stream.started = true;
+        Ok(frames)
+    }
+
+    /// Set a stream's gain, 0.0 to 1.0
+    pub fn set_volume(&self, id: StreamId, volume: f32) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&volume) {
+            return Err(format!("Volume out of range: {}", volume));
+        }
+        let mut streams = self.streams.lock().unwrap();
+        let stream = streams.get_mut(&id).ok_or_else(|| format!("Unknown stream: {:?}", id))?;
+        stream.volume = volume;
+        Ok(())
+    }
+
+    pub fn set_master_volume(&self, volume: f32) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&volume) {
+            return Err(format!("Volume out of range: {}", volume));
+        }
+        *self.master_volume.lock().unwrap() = volume;
+        Ok(())
+    }
+
+    /// Pin a stream to a device, or `None` to follow the default output
+    pub fn route_stream(&self, id: StreamId, device: Option<&str>) -> Result<(), String> {
+        if let Some(name) = device {
+            self.require_device(name)?;
+        }
+        let mut streams = self.streams.lock().unwrap();
+        let stream = streams.get_mut(&id).ok_or_else(|| format!("Unknown stream: {:?}", id))?;
+        stream.device = device.map(str::to_string);
+        Ok(())
+    }
+
+    /// Mix `elapsed_ms` of audio for every device
+    pub fn tick(&self, elapsed_ms: u64) -> Result<(), String> {
+        let frames = self.format.frames_in(elapsed_ms);
+        let channels = self.format.channels as usize;
+        let master = *self.master_volume.lock().unwrap();
+        let default = self.default_output();
+
+        let mut streams = self.streams.lock().unwrap();
+        let mut outputs = self.outputs.lock().unwrap();
+        for output in outputs.iter_mut() {
+            let mut accumulated = vec![0i32; frames * channels];
+            let mut routed = 0;
+            for stream in streams.values_mut() {
+                if stream.device.as_ref().or(default.as_ref()) != Some(&output.name) {
+                    continue;
+                }
+                routed += 1;
+                let mut buffer = stream.buffer.lock().unwrap();
+                let available = (buffer.len() / channels).min(frames);
+                let samples: Vec<i16> = buffer.drain(..available * channels).collect();
+                mix::mix_into(&mut accumulated, &samples, stream.volume * master);
+                stream.frames_played += available as u64;
+                if available < frames && stream.started {
+                    stream.underruns += 1;
+                }
+            }
+            if routed == 0 {
+                continue;
+            }
+
+            let mut device = output.device.lock().unwrap();
+            if device.queued_frames() == 0 && output.frames_written > 0 {
+                output.underruns += 1;
+            }
+            let written = device.write_frames(&mix::finish(&accumulated))?;
+            output.frames_written += written as u64;
+            output.frames_dropped += (frames - written) as u64;
+        }
+        Ok(())
+    }
+
+    fn frames_to_ms(&self, frames: usize) -> u64 {
+        frames as u64 * 1000 / self.format.sample_rate as u64
+    }
+
+    pub fn device_stats(&self) -> Vec<DeviceStats> {
+        let default = self.default_output();
+        self.outputs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|output| {
+                let queued_frames = output.device.lock().unwrap().queued_frames();
+                DeviceStats {
+                    name: output.name.clone(),
+                    default: default.as_deref() == Some(output.name.as_str()),
+                    queued_frames,
+                    latency_ms: self.frames_to_ms(queued_frames),
+                    underruns: output.underruns,
+                    frames_written: output.frames_written,
+                    frames_dropped: output.frames_dropped,
+                }
+            })
+            .collect()
+    }
+
+    pub fn stream_stats(&self) -> Vec<StreamStats> {
+        let default = self.default_output();
+        let devices = self.device_stats();
+        let channels = self.format.channels as usize;
+        self.streams
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, stream)| {
+                let device = stream.device.clone().or_else(|| default.clone());
+                let device_queued = devices
+                    .iter()
+                    .find(|d| Some(&d.name) == device.as_ref())
+                    .map_or(0, |d| d.queued_frames);
+                let buffered_frames = stream.buffer.lock().unwrap().len() / channels;
+                StreamStats {
+                    id: *id,
+                    app: stream.app.clone(),
+                    device,
+                    volume: stream.volume,
+                    buffered_frames,
+                    latency_ms: self.frames_to_ms(buffered_frames + device_queued),
+                    underruns: stream.underruns,
+                    frames_played: stream.frames_played,
+                }
+            })
+            .collect()
+    }
+}
+
+impl JsonService for AudioServer {
+    type Request = AudioRequest;
+    type Response = AudioResponse;
+    const KIND: &'static str = "audio";
+
+    fn handle_request(&self, request: AudioRequest) -> AudioResponse {
+        let result = match request {
+            AudioRequest::Open { app, channels } => {
+                self.open_stream(&app, channels).map(|stream| AudioResponse::Opened { stream })
+            }
+            AudioRequest::Write { stream, samples } => {
+                self.write(stream, &samples).map(|frames| AudioResponse::Written { frames })
+            }
+            AudioRequest::SetVolume { stream, volume } => self.set_volume(stream, volume).map(|_| AudioResponse::Ok),
+            AudioRequest::Route { stream, device } => {
+                self.route_stream(stream, device.as_deref()).map(|_| AudioResponse::Ok)
+            }
+            AudioRequest::SetOutput { device } => self.set_default_output(&device).map(|_| AudioResponse::Ok),
+            AudioRequest::Close { stream } => self.close_stream(stream).map(|_| AudioResponse::Ok),
+            AudioRequest::Stats => Ok(AudioResponse::Stats {
+                streams: self.stream_stats(),
+                devices: self.device_stats(),
+            }),
+        };
+        result.unwrap_or_else(|message| AudioResponse::Error { message })
+    }
+}
+
+impl Default for AudioServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Requests accepted over IPC
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum AudioRequest {
+    Open { app: String, channels: u16 },
+    Write { stream: StreamId, samples: Vec<i16> },
+    SetVolume { stream: StreamId, volume: f32 },
+    Route { stream: StreamId, device: Option<String> },
+    SetOutput { device: String },
+    Close { stream: StreamId },
+    Stats,
+}
+
+/// Responses sent over IPC
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum AudioResponse {
+    Opened {
+        stream: StreamId,
+    },
+    Written {
+        frames: usize,
+    },
+    Stats {
+        streams: Vec<StreamStats>,
+        devices: Vec<DeviceStats>,
+    },
+    Ok,
+    Error {
+        message: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hal::Device;
+    use ipc::{Message, ERROR_BAD_REQUEST};
+    use reference_driver::audio::ReferenceAudio;
+
+    fn speaker(name: &str) -> Arc<Mutex<ReferenceAudio>> {
+        let mut device = ReferenceAudio::new(name, AudioFormat::new(MIX_SAMPLE_RATE, MIX_CHANNELS), 4800);
+        device.init().unwrap();
+        Arc::new(Mutex::new(device))
+    }
+
+    #[test]
+    fn test_mixes_streams_with_volume() {
+        let server = AudioServer::new();
+        let speakers = speaker("speakers");
+        server.add_device("speakers", speakers.clone()).unwrap();
+
+        let music = server.open_stream("music", 2).unwrap();
+        let chime = server.open_stream("chime", 1).unwrap();
+        server.set_volume(music, 0.5).unwrap();
+        assert!(server.set_volume(music, 1.5).is_err());
+        server.write(music, &[1000; 96]).unwrap();
+        server.write(chime, &[300; 48]).unwrap();
+
+        server.tick(1).unwrap();
+        let played = speakers.lock().unwrap().play(1);
+        assert_eq!(played, vec![800; 96]);
+        assert_eq!(server.device_stats()[0].frames_written, 48);
+    }
+
+    #[test]
+    fn test_live_output_switch_and_routing() {
+        let server = AudioServer::new();
+        let speakers = speaker("speakers");
+        let headset = speaker("headset");
+        server.add_device("speakers", speakers.clone()).unwrap();
+        server.add_device("headset", headset.clone()).unwrap();
+        assert!(server.add_device("headset", speaker("headset")).is_err());
+        let mono = ReferenceAudio::new("mono", AudioFormat::new(MIX_SAMPLE_RATE, 1), 100);
+        assert!(server.add_device("mono", Arc::new(Mutex::new(mono))).is_err());
+
+        let call = server.open_stream("call", 2).unwrap();
+        let music = server.open_stream("music", 2).unwrap();
+        server.route_stream(call, Some("headset")).unwrap();
+        assert!(server.route_stream(call, Some("hdmi")).is_err());
+        server.write(call, &[7; 192]).unwrap();
+        server.write(music, &[9; 192]).unwrap();
+
+        server.tick(1).unwrap();
+        assert_eq!(speakers.lock().unwrap().play(1), vec![9; 96]);
+        assert_eq!(headset.lock().unwrap().play(1), vec![7; 96]);
+
+        // Music follows the default output to the headset mid-stream
+        server.set_default_output("headset").unwrap();
+        server.tick(1).unwrap();
+        assert_eq!(headset.lock().unwrap().play(1), vec![16; 96]);
+        assert_eq!(speakers.lock().unwrap().queued_frames(), 0);
+
+        server.remove_device("headset").unwrap();
+        assert_eq!(server.default_output().as_deref(), Some("speakers"));
+        let stats = server.stream_stats();
+        assert!(stats.iter().all(|s| s.device.as_deref() == Some("speakers")));
+    }
+
+    #[test]
+    fn test_underrun_and_latency_stats() {
+        let server = AudioServer::new();
+        let speakers = speaker("speakers");
+        server.add_device("speakers", speakers.clone()).unwrap();
+        let stream = server.open_stream("game", 2).unwrap();
+
+        // Untouched streams are silent, not starved
+        server.tick(1).unwrap();
+        assert_eq!(server.stream_stats()[0].underruns, 0);
+
+        let shared = server.shared_buffer(stream).unwrap();
+        shared.lock().unwrap().extend(std::iter::repeat_n(1i16, 2 * 480));
+        server.tick(2).unwrap();
+        let stats = &server.stream_stats()[0];
+        assert_eq!(stats.buffered_frames, 384);
+        assert_eq!(stats.latency_ms, (384 + 96 + 48) * 1000 / 48_000);
+
+        speakers.lock().unwrap().play(3);
+        server.tick(10).unwrap();
+        let stats = &server.stream_stats()[0];
+        assert_eq!((stats.underruns, stats.frames_played), (1, 480));
+        assert_eq!(server.device_stats()[0].underruns, 1);
+
+        // Writes beyond the buffer limit are cut short
+        let limit = server.format().frames_in(MAX_STREAM_BUFFER_MS);
+        assert_eq!(server.write(stream, &vec![0; 2 * (limit + 10)]).unwrap(), limit);
+    }
+
+    #[test]
+    fn test_ipc_requests() {
+        let server = AudioServer::new();
+        server.add_device("speakers", speaker("speakers")).unwrap();
+
+        let reply = server
+            .handle_message(&Message::Request {
+                id: 3,
+                data: serde_json::to_vec(&AudioRequest::Open {
+                    app: "player".to_string(),
+                    channels: 2,
+                })
+                .unwrap(),
+            })
+            .unwrap();
+        let stream = match reply {
+            Message::Response { id: 3, data } => match serde_json::from_slice(&data).unwrap() {
+                AudioResponse::Opened { stream } => stream,
+                other => panic!("unexpected response: {:?}", other),
+            },
+            other => panic!("unexpected reply: {:?}", other),
+        };
+
+        let write = AudioRequest::Write {
+            stream,
+            samples: vec![1, 2, 3],
+        };
+        assert!(matches!(server.handle_request(write), AudioResponse::Error { .. }));
+        let set_output = AudioRequest::SetOutput {
+            device: "hdmi".to_string(),
+        };
+        assert!(matches!(server.handle_request(set_output), AudioResponse::Error { .. }));
+        match server.handle_request(AudioRequest::Stats) {
+            AudioResponse::Stats { streams, devices } => {
+                assert_eq!(streams[0].app, "player");
+                assert!(devices[0].default);
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+        let bad = server.handle_message(&Message::Request { id: 4, data: b"[]".to_vec() });
+        assert!(matches!(bad, Some(Message::Error { code: ERROR_BAD_REQUEST, .. })));
+    }
+}
@@ -0,0 +1,54 @@
+//! Software mixing
+
+/// Accumulate `samples` into `out` scaled by `gain`
+pub fn mix_into(out: &mut [i32], samples: &[i16], gain: f32) {
+    for (acc, &sample) in out.iter_mut().zip(samples) {
+        *acc += (sample as f32 * gain).round() as i32;
+    }
+}
+
+/// Clamp an accumulator to 16-bit samples
+pub fn finish(accumulated: &[i32]) -> Vec<i16> {
+    accumulated
+        .iter()
+        .map(|&s| s.clamp(i16::MIN as i32, i16::MAX as i32) as i16)
+        .collect()
+}
+
+/// Convert interleaved samples between mono and multichannel layouts
+pub fn convert_channels(samples: &[i16], from: u16, to: u16) -> Result<Vec<i16>, String> {
+    if from == to {
+        return Ok(samples.to_vec());
+    }
+    let (from, to) = (from as usize, to as usize);
+    if from == 1 {
+        return Ok(samples.iter().flat_map(|&s| std::iter::repeat_n(s, to)).collect());
+    }
+    if to == 1 {
+        return Ok(samples
+            .chunks_exact(from)
+            .map(|frame| (frame.iter().map(|&s| s as i32).sum::<i32>() / from as i32) as i16)
+            .collect());
+    }
+    Err(format!("Cannot convert {} channels to {}", from, to))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mix_saturates() {
+        let mut acc = vec![0i32; 3];
+        mix_into(&mut acc, &[20_000, -20_000, 100], 1.0);
+        mix_into(&mut acc, &[30_000, -30_000, 100], 0.5);
+        assert_eq!(finish(&acc), vec![i16::MAX, i16::MIN, 150]);
+    }
+
+    #[test]
+    fn test_convert_channels() {
+        assert_eq!(convert_channels(&[1, 2], 1, 2).unwrap(), vec![1, 1, 2, 2]);
+        assert_eq!(convert_channels(&[2, 4, 6, 8], 2, 1).unwrap(), vec![3, 7]);
+        assert!(convert_channels(&[0; 6], 2, 3).is_err());
+    }
+}
@@ -0,0 +1,134 @@
+//! Backup archive format
+//!
+//! An archive lists every path in the snapshot it was taken from. Full
+//! archives carry the content of every file; incremental archives only
+//! carry content that changed since their parent generation, so restoring
+//! one walks back along the chain to find unchanged content.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use filesystem::{FilePermissions, FileType, SnapshotEntry};
+use keystore::kdf;
+use serde::{Deserialize, Serialize};
+
+/// Length of a content digest in bytes
+pub const DIGEST_LEN: usize = 32;
+
+/// Hex digest used for file contents and whole archives
+pub fn digest(data: &[u8]) -> String {
+    kdf::derive(data, b"hairr-backup", 1, DIGEST_LEN)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupKind {
+    Full,
+    Incremental,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryKind {
+    File,
+    Directory,
+}
+
+/// One path in an archive
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveEntry {
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    pub mode: u32,
+    pub owner_id: u32,
+    pub group_id: u32,
+    pub modified_at: u64,
+    pub digest: String,
+    /// Absent when unchanged since the parent generation
+    pub content: Option<Vec<u8>>,
+}
+
+impl ArchiveEntry {
+    pub fn from_snapshot(entry: &SnapshotEntry, include_content: bool) -> Self {
+        let kind = if entry.file_type == FileType::Directory {
+            EntryKind::Directory
+        } else {
+            EntryKind::File
+        };
+        ArchiveEntry {
+            path: entry.path.clone(),
+            kind,
+            mode: entry.permissions.to_mode(),
+            owner_id: entry.owner_id,
+            group_id: entry.group_id,
+            modified_at: entry.modified_at,
+            digest: digest(&entry.content),
+            content: include_content.then(|| entry.content.clone()),
+        }
+    }
+
+    /// Rebuild the snapshot entry with content resolved from the chain
+    pub fn to_snapshot(&self, content: Vec<u8>) -> SnapshotEntry {
+        SnapshotEntry {
+            path: self.path.clone(),
+            file_type: match self.kind {
+                EntryKind::File => FileType::Regular,
+                EntryKind::Directory => FileType::Directory,
+            },
+            permissions: FilePermissions::new(self.mode),
+            owner_id: self.owner_id,
+            group_id: self.group_id,
+            modified_at: self.modified_at,
+            content,
+        }
+    }
+}
+
+/// Serialized body of a backup generation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Archive {
+    pub generation: u64,
+    pub kind: BackupKind,
+    pub parent: Option<u64>,
+    pub entries: Vec<ArchiveEntry>,
+}
+
+impl Archive {
+    /// Archive `snapshot`, storing content only where it differs from
+    /// `previous` (path to digest of the parent generation)
+    pub fn build(
+        generation: u64,
+        parent: Option<u64>,
+        snapshot: &BTreeMap<PathBuf, SnapshotEntry>,
+        previous: &BTreeMap<PathBuf, String>,
+    ) -> Self {
+        let entries = snapshot
+            .values()
+            .map(|entry| {
+                let mut archived = ArchiveEntry::from_snapshot(entry, true);
+                if previous.get(&entry.path) == Some(&archived.digest) {
+                    archived.content = None;
+                }
+                archived
+            })
+            .collect();
+        Archive {
+            generation,
+            kind: if parent.is_some() {
+                BackupKind::Incremental
+            } else {
+                BackupKind::Full
+            },
+            parent,
+            entries,
+        }
+    }
+
+    /// Bytes of file content carried by this archive
+    pub fn stored_bytes(&self) -> u64 {
+        self.entries.iter().filter_map(|e| e.content.as_ref()).map(|c| c.len() as u64).sum()
+    }
+}
@@ -0,0 +1,132 @@
+//! Clipboard formats
+//!
+//! Contents are offered in one or more MIME-like formats. A paste lists the
+//! formats the consumer understands, most preferred first, and receives the
+//! first one the entry can provide, either directly or by conversion.
+
+use serde::{Deserialize, Serialize};
+
+pub const TEXT_PLAIN: &str = "text/plain";
+pub const URI_LIST: &str = "text/uri-list";
+
+/// Payload of one representation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ClipData {
+    Text { text: String },
+    Binary { bytes: Vec<u8> },
+    Files { paths: Vec<String> },
+}
+
+/// Clipboard contents in a single format
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClipItem {
+    pub format: String,
+    pub data: ClipData,
+}
+
+impl ClipItem {
+    pub fn text(text: &str) -> Self {
+        ClipItem {
+            format: TEXT_PLAIN.to_string(),
+            data: ClipData::Text { text: text.to_string() },
+        }
+    }
+
+    /// Binary contents such as `image/png`
+    pub fn binary(format: &str, bytes: Vec<u8>) -> Self {
+        ClipItem {
+            format: format.to_string(),
+            data: ClipData::Binary { bytes },
+        }
+    }
+
+    pub fn files(paths: Vec<String>) -> Self {
+        ClipItem {
+            format: URI_LIST.to_string(),
+            data: ClipData::Files { paths },
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        match &self.data {
+            ClipData::Text { text } => text.len(),
+            ClipData::Binary { bytes } => bytes.len(),
+            ClipData::Files { paths } => paths.iter().map(|p| p.len()).sum(),
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if !self.format.contains('/') {
+            return Err(format!("Invalid format {}", self.format));
+        }
+        let consistent = match &self.data {
+            ClipData::Text { .. } => self.format.starts_with("text/") && self.format != URI_LIST,
+            ClipData::Files { .. } => self.format == URI_LIST,
+            ClipData::Binary { .. } => true,
+        };
+        if !consistent {
+            return Err(format!("Data does not match format {}", self.format));
+        }
+        Ok(())
+    }
+}
+
+/// Whether `format` satisfies `pattern`, which may be `type/*` or `*/*`
+pub fn format_matches(pattern: &str, format: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some("*") => true,
+        Some(major) => format.split('/').next() == Some(major),
+        None => pattern == format,
+    }
+}
+
+/// Pick the representation best matching `accepted`, converting file lists
+/// to plain text when that is all the consumer understands
+pub fn negotiate(items: &[ClipItem], accepted: &[String]) -> Option<ClipItem> {
+    for pattern in accepted {
+        if let Some(item) = items.iter().find(|i| format_matches(pattern, &i.format)) {
+            return Some(item.clone());
+        }
+        if format_matches(pattern, TEXT_PLAIN) {
+            let files = items.iter().find_map(|i| match &i.data {
+                ClipData::Files { paths } => Some(paths.join("\n")),
+                _ => None,
+            });
+            if let Some(text) = files {
+                return Some(ClipItem::text(&text));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiation() {
+        let items = vec![
+            ClipItem::binary("image/png", vec![0x89, b'P', b'N', b'G']),
+            ClipItem::text("diagram"),
+        ];
+        let accept = |formats: &[&str]| formats.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(negotiate(&items, &accept(&["image/*"])).unwrap().format, "image/png");
+        assert_eq!(negotiate(&items, &accept(&["text/html", "text/plain"])).unwrap(), items[1]);
+        assert!(negotiate(&items, &accept(&["application/pdf"])).is_none());
+
+        let files = vec![ClipItem::files(vec!["/home/a.txt".to_string(), "/home/b.txt".to_string()])];
+        assert_eq!(
+            negotiate(&files, &accept(&[TEXT_PLAIN])).unwrap(),
+            ClipItem::text("/home/a.txt\n/home/b.txt")
+        );
+        assert!(ClipItem::binary("png", Vec::new()).validate().is_err());
+        let mislabeled = ClipItem {
+            format: "image/png".to_string(),
+            data: ClipData::Text { text: String::new() },
+        };
+        assert!(mislabeled.validate().is_err());
+    }
+}
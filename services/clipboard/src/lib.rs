@@ -0,0 +1,349 @@
+//! Clipboard Service
+//!
+//! Holds the current clipboard entry and a short history of earlier ones.
+//! Every entry records the application that copied it. Pasting a sensitive
+//! entry, or a sensitive format such as a password, requires a clipboard
+//! capability so background apps cannot read secrets off the clipboard.
+
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use capability::{CapabilityManager, CapabilityToken, Permission, Resource};
+use ipc::JsonService;
+use serde::{Deserialize, Serialize};
+
+pub mod format;
+
+pub use format::{format_matches, negotiate, ClipData, ClipItem, TEXT_PLAIN, URI_LIST};
+
+/// Resource guarding sensitive pastes
+pub const SENSITIVE_RESOURCE: &str = "clipboard:sensitive";
+
+/// Entries kept, including the current one
+pub const DEFAULT_HISTORY_LEN: usize = 10;
+
+/// Formats that always require the sensitive capability
+pub const DEFAULT_SENSITIVE_FORMATS: &[&str] = &["application/x-password", "application/x-otp"];
+
+/// Clipboard entry identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct EntryId(u64);
+
+impl EntryId {
+    pub fn new(id: u64) -> Self {
+        EntryId(id)
+    }
+}
+
+/// Something copied to the clipboard
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClipboardEntry {
+    pub id: EntryId,
+    pub source_app: String,
+    pub items: Vec<ClipItem>,
+    /// Marked sensitive by the copying application
+    pub sensitive: bool,
+}
+
+/// History entry as reported to clients, without contents
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntrySummary {
+    pub id: EntryId,
+    pub source_app: String,
+    pub formats: Vec<String>,
+    pub sensitive: bool,
+}
+
+/// Result of a successful paste
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pasted {
+    pub item: ClipItem,
+    pub source_app: String,
+}
+
+/// System clipboard
+pub struct ClipboardService {
+    capabilities: Arc<CapabilityManager>,
+    /// Newest entry first
+    history: Arc<Mutex<VecDeque<ClipboardEntry>>>,
+    history_len: usize,
+    sensitive_formats: Arc<Mutex<HashSet<String>>>,
+    next_id: Arc<Mutex<u64>>,
+}
+
+impl ClipboardService {
+    pub fn new(capabilities: Arc<CapabilityManager>) -> Self {
+        ClipboardService {
+            capabilities,
+            history: Arc::new(Mutex::new(VecDeque::new())),
+            history_len: DEFAULT_HISTORY_LEN,
+            sensitive_formats: Arc::new(Mutex::new(
+                DEFAULT_SENSITIVE_FORMATS.iter().map(|f| f.to_string()).collect(),
+            )),
+            next_id: Arc::new(Mutex::new(1)),
+        }
+    }
+
+    pub fn with_history_len(mut self, len: usize) -> Self {
+        self.history_len = len.max(1);
+        self
+    }
+
+    /// Require the sensitive capability to paste `format`
+    pub fn add_sensitive_format(&self, format: &str) {
+        self.sensitive_formats.lock().unwrap().insert(format.to_string());
+    }
+
+    /// Replace the clipboard contents
+    pub fn copy(&self, source_app: &str, items: Vec<ClipItem>, sensitive: bool) -> Result<EntryId, String> {
+        if items.is_empty() {
+            return Err("Nothing to copy".to_string());
+        }
+        for item in &items {
+            item.validate()?;
+        }
+        let mut next_id = self.next_id.lock().unwrap();
+        let id = EntryId(*next_id);
+        *next_id += 1;
+
+        let mut history = self.history.lock().unwrap();
+        history.push_front(ClipboardEntry {
+            id,
+            source_app: source_app.to_string(),
+            items,
+            sensitive,
+        });
+        history.truncate(self.history_len);
+        Ok(id)
+    }
+
+    /// Paste the current entry in the first of `accepted` formats available
+    pub fn paste(&self, accepted: &[String], token: Option<CapabilityToken>) -> Result<Pasted, String> {
+        let entry = self.current().ok_or("Clipboard is empty")?;
+        self.paste_entry(&entry, accepted, token)
+    }
+
+    /// Paste an entry from the history
+    pub fn paste_from_history(
+        &self,
+        id: EntryId,
+        accepted: &[String],
+        token: Option<CapabilityToken>,
+    ) -> Result<Pasted, String> {
+        let entry = self
+            .history
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|e| e.id == id)
+            .cloned()
+            .ok_or("Clipboard entry not found")?;
+        self.paste_entry(&entry, accepted, token)
+    }
+
+    fn paste_entry(
+        &self,
+        entry: &ClipboardEntry,
+        accepted: &[String],
+        token: Option<CapabilityToken>,
+    ) -> Result<Pasted, String> {
+        let item = negotiate(&entry.items, accepted).ok_or("No acceptable format on the clipboard")?;
+        let sensitive = entry.sensitive || self.sensitive_formats.lock().unwrap().contains(&item.format);
+        if sensitive && !self.may_paste_sensitive(token) {
+            return Err("Permission denied for sensitive clipboard contents".to_string());
+        }
+        Ok(Pasted {
+            item,
+            source_app: entry.source_app.clone(),
+        })
+    }
+
+    fn may_paste_sensitive(&self, token: Option<CapabilityToken>) -> bool {
+        let Some(token) = token else {
+            return false;
+        };
+        match self.capabilities.validate(token) {
+            Some(cap) => {
+                cap.resource == Resource::IPC(SENSITIVE_RESOURCE.to_string())
+                    && self.capabilities.check_permission(token, Permission::Read)
+            }
+            None => false,
+        }
+    }
+
+    pub fn current(&self) -> Option<ClipboardEntry> {
+        self.history.lock().unwrap().front().cloned()
+    }
+
+    /// Formats the current entry offers
+    pub fn formats(&self) -> Vec<String> {
+        self.current()
+            .map(|e| e.items.iter().map(|i| i.format.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Clipboard history, newest first
+    pub fn history(&self) -> Vec<EntrySummary> {
+        self.history
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| EntrySummary {
+                id: e.id,
+                source_app: e.source_app.clone(),
+                formats: e.items.iter().map(|i| i.format.clone()).collect(),
+                sensitive: e.sensitive,
+            })
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.history.lock().unwrap().clear();
+    }
+}
+
+impl JsonService for ClipboardService {
+    type Request = ClipboardRequest;
+    type Response = ClipboardResponse;
+    const KIND: &'static str = "clipboard";
+
+    fn handle_request(&self, request: ClipboardRequest) -> ClipboardResponse {
+        let result = match request {
+            ClipboardRequest::Copy {
+                source_app,
+                items,
+                sensitive,
+            } => self.copy(&source_app, items, sensitive).map(|id| ClipboardResponse::Copied { id }),
+            ClipboardRequest::Paste { accept, entry, token } => match entry {
+                Some(id) => self.paste_from_history(id, &accept, token),
+                None => self.paste(&accept, token),
+            }
+            .map(|pasted| ClipboardResponse::Pasted { pasted }),
+            ClipboardRequest::Formats => Ok(ClipboardResponse::Formats { formats: self.formats() }),
+            ClipboardRequest::History => Ok(ClipboardResponse::History {
+                entries: self.history(),
+            }),
+            ClipboardRequest::Clear => {
+                self.clear();
+                Ok(ClipboardResponse::Ok)
+            }
+        };
+        result.unwrap_or_else(|message| ClipboardResponse::Error { message })
+    }
+}
+
+/// Request accepted over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ClipboardRequest {
+    Copy {
+        source_app: String,
+        items: Vec<ClipItem>,
+        #[serde(default)]
+        sensitive: bool,
+    },
+    Paste {
+        accept: Vec<String>,
+        /// History entry to paste instead of the current one
+        #[serde(default)]
+        entry: Option<EntryId>,
+        #[serde(default)]
+        token: Option<CapabilityToken>,
+    },
+    Formats,
+    History,
+    Clear,
+}
+
+/// Reply sent over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum ClipboardResponse {
+    Copied { id: EntryId },
+    Pasted { pasted: Pasted },
+    Formats { formats: Vec<String> },
+    History { entries: Vec<EntrySummary> },
+    Ok,
+    Error { message: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ipc::{Message, ERROR_BAD_REQUEST};
+
+    fn accept(formats: &[&str]) -> Vec<String> {
+        formats.iter().map(|f| f.to_string()).collect()
+    }
+
+    #[test]
+    fn test_copy_paste_and_history() {
+        let clipboard = ClipboardService::new(Arc::new(CapabilityManager::new())).with_history_len(2);
+        assert!(clipboard.paste(&accept(&[TEXT_PLAIN]), None).is_err());
+
+        let first = clipboard.copy("editor", vec![ClipItem::text("hello")], false).unwrap();
+        clipboard
+            .copy("paint", vec![ClipItem::binary("image/png", vec![1, 2, 3]), ClipItem::text("sketch")], false)
+            .unwrap();
+        assert_eq!(clipboard.formats(), vec!["image/png", TEXT_PLAIN]);
+
+        let pasted = clipboard.paste(&accept(&["image/*"]), None).unwrap();
+        assert_eq!(pasted.source_app, "paint");
+        assert_eq!(pasted.item.data, ClipData::Binary { bytes: vec![1, 2, 3] });
+        let older = clipboard.paste_from_history(first, &accept(&[TEXT_PLAIN]), None).unwrap();
+        assert_eq!(older.item, ClipItem::text("hello"));
+
+        clipboard.copy("files", vec![ClipItem::files(vec!["/a".to_string()])], false).unwrap();
+        let history = clipboard.history();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].source_app, "files");
+        assert!(clipboard.paste_from_history(first, &accept(&[TEXT_PLAIN]), None).is_err());
+    }
+
+    #[test]
+    fn test_sensitive_paste_requires_capability() {
+        let capabilities = Arc::new(CapabilityManager::new());
+        let clipboard = ClipboardService::new(Arc::clone(&capabilities));
+        let allowed = capabilities.grant(Resource::IPC(SENSITIVE_RESOURCE.to_string()), Permission::Read);
+        let unrelated = capabilities.grant(Resource::IPC("clipboard".to_string()), Permission::Full);
+
+        clipboard
+            .copy("vault", vec![ClipItem::binary("application/x-password", b"s3cret".to_vec())], false)
+            .unwrap();
+        let formats = accept(&["application/x-password"]);
+        assert!(clipboard.paste(&formats, None).is_err());
+        assert!(clipboard.paste(&formats, Some(unrelated)).is_err());
+        assert!(clipboard.paste(&formats, Some(allowed)).is_ok());
+
+        // Entries can be marked sensitive whatever their format
+        clipboard.copy("vault", vec![ClipItem::text("s3cret")], true).unwrap();
+        assert!(clipboard.paste(&accept(&[TEXT_PLAIN]), None).is_err());
+        capabilities.revoke(allowed);
+        assert!(clipboard.paste(&accept(&[TEXT_PLAIN]), Some(allowed)).is_err());
+    }
+
+    #[test]
+    fn test_clipboard_over_ipc() {
+        let clipboard = ClipboardService::new(Arc::new(CapabilityManager::new()));
+        let copy = Message::Request {
+            id: 1,
+            data: br#"{"op":"copy","source_app":"editor","items":[{"format":"text/plain","data":{"kind":"text","text":"hi"}}]}"#
+                .to_vec(),
+        };
+        let Some(Message::Response { data, .. }) = clipboard.handle_message(&copy) else {
+            panic!("expected a response");
+        };
+        assert!(matches!(serde_json::from_slice(&data).unwrap(), ClipboardResponse::Copied { .. }));
+
+        match clipboard.handle_request(ClipboardRequest::Paste {
+            accept: accept(&["text/*"]),
+            entry: None,
+            token: None,
+        }) {
+            ClipboardResponse::Pasted { pasted } => assert_eq!(pasted.item, ClipItem::text("hi")),
+            other => panic!("unexpected response: {:?}", other),
+        }
+        let bad = Message::Request { id: 2, data: b"{\"op\":\"cut\"}".to_vec() };
+        assert!(matches!(clipboard.handle_message(&bad), Some(Message::Error { code: ERROR_BAD_REQUEST, .. })));
+    }
+}
@@ -0,0 +1,71 @@
+//! Captured images
+//!
+//! Screenshots and recorded frames are plain RGBA images, rows top to
+//! bottom, with the same pixel layout as surfaces and the frame buffer.
+
+use crate::surface::{Rect, BYTES_PER_PIXEL};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    /// Fully transparent image
+    pub fn new(width: u32, height: u32) -> Self {
+        Image {
+            width,
+            height,
+            pixels: vec![0; width as usize * height as usize * BYTES_PER_PIXEL],
+        }
+    }
+
+    pub fn from_pixels(width: u32, height: u32, pixels: Vec<u8>) -> Result<Self, String> {
+        let expected = width as usize * height as usize * BYTES_PER_PIXEL;
+        if pixels.len() != expected {
+            return Err(format!("Expected {} bytes of pixels", expected));
+        }
+        Ok(Image { width, height, pixels })
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Option<[u8; 4]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let start = (y as usize * self.width as usize + x as usize) * BYTES_PER_PIXEL;
+        self.pixels[start..start + BYTES_PER_PIXEL].try_into().ok()
+    }
+
+    /// Copy `source` with its top-left corner at `(x, y)`, clipped to this image
+    pub fn blit(&mut self, source: &Image, x: i32, y: i32) {
+        let bounds = Rect::new(0, 0, self.width, self.height);
+        let Some(area) = Rect::new(x, y, source.width, source.height).intersect(&bounds) else {
+            return;
+        };
+        let row = area.width as usize * BYTES_PER_PIXEL;
+        for dy in area.y..area.y + area.height as i32 {
+            let src = ((dy - y) as usize * source.width as usize + (area.x - x) as usize) * BYTES_PER_PIXEL;
+            let dst = (dy as usize * self.width as usize + area.x as usize) * BYTES_PER_PIXEL;
+            self.pixels[dst..dst + row].copy_from_slice(&source.pixels[src..src + row]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blit_clips() {
+        let mut canvas = Image::new(4, 4);
+        let red = Image::from_pixels(2, 2, [255, 0, 0, 255].repeat(4)).unwrap();
+        canvas.blit(&red, 3, -1);
+        assert_eq!(canvas.pixel(3, 0), Some([255, 0, 0, 255]));
+        assert_eq!(canvas.pixel(2, 0), Some([0, 0, 0, 0]));
+        assert_eq!(canvas.pixel(3, 1), Some([0, 0, 0, 0]));
+        canvas.blit(&red, 10, 10);
+        assert!(Image::from_pixels(2, 2, vec![0; 3]).is_err());
+    }
+}
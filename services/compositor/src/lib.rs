@@ -0,0 +1,377 @@
+//! Software Compositor
+//!
+//! Applications draw into shared surfaces; the shell describes where each
+//! surface sits on screen and in what stacking order. On every vsync the
+//! compositor repaints only the damaged parts of its back buffer, blending
+//! surfaces bottom to top over the background, and pushes the frame to the
+//! display driver. Time advances through `tick`. The last frame can be
+//! captured as an image for screenshots and screen recording.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use hal::DisplayDevice;
+
+pub mod image;
+pub mod surface;
+
+pub use image::Image;
+pub use surface::{Rect, Surface, SurfaceId, BYTES_PER_PIXEL};
+
+/// Time between two vertical blanks, about 60 Hz
+pub const VSYNC_INTERVAL_MS: u64 = 16;
+
+/// Colour shown where no surface covers the screen
+pub const DEFAULT_BACKGROUND: [u8; 4] = [0x20, 0x24, 0x2c, 0xff];
+
+/// Above this many damaged areas a frame repaints their bounding box
+pub const MAX_DAMAGE_RECTS: usize = 32;
+
+/// Display the composited frames are shown on
+pub type SharedDisplay = Arc<Mutex<dyn DisplayDevice>>;
+
+/// Surface IDs are unique across compositors so surfaces can move between displays
+static NEXT_SURFACE_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Placement of a surface on screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layer {
+    pub surface: SurfaceId,
+    pub x: i32,
+    pub y: i32,
+}
+
+impl Layer {
+    pub fn new(surface: SurfaceId, x: i32, y: i32) -> Self {
+        Layer { surface, x, y }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Placed {
+    surface: SurfaceId,
+    rect: Rect,
+}
+
+/// Composites window surfaces into display frames
+pub struct Compositor {
+    display: SharedDisplay,
+    width: u32,
+    height: u32,
+    surfaces: Arc<Mutex<HashMap<SurfaceId, Surface>>>,
+    /// Visible surfaces, bottom first
+    scene: Arc<Mutex<Vec<Placed>>>,
+    frame: Arc<Mutex<Vec<u8>>>,
+    /// Screen areas to repaint on the next vsync
+    damage: Arc<Mutex<Vec<Rect>>>,
+    background: Arc<Mutex<[u8; 4]>>,
+    since_vsync_ms: Arc<Mutex<u64>>,
+    frames: Arc<Mutex<u64>>,
+}
+
+impl Compositor {
+    /// Composite for `display` at its current resolution
+    pub fn new(display: SharedDisplay) -> Self {
+        let (width, height) = display.lock().unwrap().resolution();
+        Compositor {
+            display,
+            width,
+            height,
+            surfaces: Arc::new(Mutex::new(HashMap::new())),
+            scene: Arc::new(Mutex::new(Vec::new())),
+            frame: Arc::new(Mutex::new(vec![0; width as usize * height as usize * BYTES_PER_PIXEL])),
+            damage: Arc::new(Mutex::new(vec![Rect::new(0, 0, width, height)])),
+            background: Arc::new(Mutex::new(DEFAULT_BACKGROUND)),
+            since_vsync_ms: Arc::new(Mutex::new(0)),
+            frames: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    pub fn resolution(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn screen(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
+    }
+
+    /// Allocate a surface for an application to draw into
+    pub fn create_surface(&self, width: u32, height: u32) -> Surface {
+        let id = SurfaceId::new(NEXT_SURFACE_ID.fetch_add(1, Ordering::Relaxed));
+        let surface = Surface::new(id, width, height);
+        self.surfaces.lock().unwrap().insert(id, surface.clone());
+        surface
+    }
+
+    /// Take over a surface created by another compositor
+    pub fn adopt_surface(&self, surface: Surface) {
+        // Drawing damage is relative to the old display; placing it repaints it whole
+        surface.take_damage();
+        self.surfaces.lock().unwrap().insert(surface.id(), surface);
+    }
+
+    pub fn surface(&self, id: SurfaceId) -> Option<Surface> {
+        self.surfaces.lock().unwrap().get(&id).cloned()
+    }
+
+    /// Free a surface, taking it off screen
+    pub fn destroy_surface(&self, id: SurfaceId) -> Result<(), String> {
+        self.surfaces.lock().unwrap().remove(&id).ok_or("Surface not found")?;
+        let mut scene = self.scene.lock().unwrap();
+        let mut damage = self.damage.lock().unwrap();
+        for placed in scene.iter().filter(|p| p.surface == id) {
+            damage.push(placed.rect);
+        }
+        scene.retain(|p| p.surface != id);
+        Ok(())
+    }
+
+    pub fn set_background(&self, color: [u8; 4]) {
+        *self.background.lock().unwrap() = color;
+        self.damage.lock().unwrap().push(self.screen());
+    }
+
+    /// Replace the visible layers, bottom first, damaging whatever moved
+    pub fn set_scene(&self, layers: &[Layer]) -> Result<(), String> {
+        let scene: Vec<Placed> = {
+            let surfaces = self.surfaces.lock().unwrap();
+            layers
+                .iter()
+                .map(|layer| {
+                    let surface = surfaces.get(&layer.surface).ok_or("Surface not found")?;
+                    let (width, height) = surface.size();
+                    Ok(Placed {
+                        surface: layer.surface,
+                        rect: Rect::new(layer.x, layer.y, width, height),
+                    })
+                })
+                .collect::<Result<_, String>>()?
+        };
+        let old = std::mem::replace(&mut *self.scene.lock().unwrap(), scene.clone());
+        let mut damage = self.damage.lock().unwrap();
+        // A layer counts as unchanged only if it keeps its place in the stack
+        for (i, placed) in old.iter().enumerate() {
+            if scene.get(i) != Some(placed) {
+                damage.push(placed.rect);
+            }
+        }
+        for (i, placed) in scene.iter().enumerate() {
+            if old.get(i) != Some(placed) {
+                damage.push(placed.rect);
+            }
+        }
+        Ok(())
+    }
+
+    /// Collect scene changes and surface drawing into clipped screen areas
+    fn collect_damage(&self) -> Vec<Rect> {
+        let mut scene = self.scene.lock().unwrap();
+        let mut damage = std::mem::take(&mut *self.damage.lock().unwrap());
+        let surfaces = self.surfaces.lock().unwrap();
+        for surface in surfaces.values() {
+            let drawn = surface.take_damage();
+            let Some(placed) = scene.iter_mut().find(|p| p.surface == surface.id()) else {
+                continue;
+            };
+            // The application resized its surface since the scene was set
+            let (width, height) = surface.size();
+            if (width, height) != (placed.rect.width, placed.rect.height) {
+                damage.push(placed.rect);
+                placed.rect = Rect::new(placed.rect.x, placed.rect.y, width, height);
+                damage.push(placed.rect);
+            }
+            damage.extend(drawn.iter().map(|r| r.offset(placed.rect.x, placed.rect.y)));
+        }
+        let screen = self.screen();
+        let mut damage: Vec<Rect> = damage.iter().filter_map(|r| r.intersect(&screen)).collect();
+        if damage.len() > MAX_DAMAGE_RECTS {
+            let bounds = damage.iter().skip(1).fold(damage[0], |acc, r| acc.union(r));
+            damage = vec![bounds];
+        }
+        damage
+    }
+
+    fn repaint(&self, area: Rect, frame: &mut [u8]) {
+        let background = *self.background.lock().unwrap();
+        let scene = self.scene.lock().unwrap().clone();
+        let surfaces = self.surfaces.lock().unwrap();
+        let stride = self.width as usize * BYTES_PER_PIXEL;
+        for y in area.y..area.y + area.height as i32 {
+            let row_start = y as usize * stride;
+            let span = &mut frame[row_start + area.x as usize * BYTES_PER_PIXEL..][..area.width as usize * BYTES_PER_PIXEL];
+            for pixel in span.chunks_exact_mut(BYTES_PER_PIXEL) {
+                pixel.copy_from_slice(&background);
+            }
+            let line = Rect::new(area.x, y, area.width, 1);
+            for placed in &scene {
+                let (Some(part), Some(surface)) = (placed.rect.intersect(&line), surfaces.get(&placed.surface)) else {
+                    continue;
+                };
+                let sx = (part.x - placed.rect.x) as u32;
+                let sy = (y - placed.rect.y) as u32;
+                surface.with_row(sx, sy, part.width, |src| {
+                    let dst = &mut frame[row_start + part.x as usize * BYTES_PER_PIXEL..][..src.len()];
+                    for (d, s) in dst.chunks_exact_mut(BYTES_PER_PIXEL).zip(src.chunks_exact(BYTES_PER_PIXEL)) {
+                        blend(d, s);
+                    }
+                });
+            }
+        }
+    }
+
+    /// Repaint damaged areas and present the frame, returning what was repainted
+    pub fn compose(&self) -> Result<Vec<Rect>, String> {
+        let damage = self.collect_damage();
+        if damage.is_empty() {
+            return Ok(damage);
+        }
+        let mut frame = self.frame.lock().unwrap();
+        for area in &damage {
+            self.repaint(*area, &mut frame);
+        }
+        self.display.lock().unwrap().update_framebuffer(&frame)?;
+        *self.frames.lock().unwrap() += 1;
+        Ok(damage)
+    }
+
+    /// Advance time, composing once per elapsed vsync interval
+    pub fn tick(&self, elapsed_ms: u64) -> Result<bool, String> {
+        {
+            let mut since = self.since_vsync_ms.lock().unwrap();
+            *since += elapsed_ms;
+            if *since < VSYNC_INTERVAL_MS {
+                return Ok(false);
+            }
+            // Missed vblanks are dropped rather than replayed
+            *since %= VSYNC_INTERVAL_MS;
+        }
+        Ok(!self.compose()?.is_empty())
+    }
+
+    /// Frames pushed to the display so far
+    pub fn frames_presented(&self) -> u64 {
+        *self.frames.lock().unwrap()
+    }
+
+    /// Copy of the last composed frame
+    pub fn capture_screen(&self) -> Image {
+        Image {
+            width: self.width,
+            height: self.height,
+            pixels: self.frame.lock().unwrap().clone(),
+        }
+    }
+
+    /// Colour of one pixel of the last composed frame
+    pub fn pixel(&self, x: u32, y: u32) -> Option<[u8; 4]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let start = (y as usize * self.width as usize + x as usize) * BYTES_PER_PIXEL;
+        self.frame.lock().unwrap()[start..start + BYTES_PER_PIXEL].try_into().ok()
+    }
+}
+
+/// Source-over blend of one pixel onto an opaque destination
+fn blend(dst: &mut [u8], src: &[u8]) {
+    match src[3] {
+        0 => {}
+        255 => dst.copy_from_slice(src),
+        alpha => {
+            let alpha = alpha as u32;
+            for i in 0..3 {
+                dst[i] = ((src[i] as u32 * alpha + dst[i] as u32 * (255 - alpha) + 127) / 255) as u8;
+            }
+            dst[3] = 255;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reference_driver::display::ReferenceDisplay;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    fn setup() -> (Arc<Mutex<ReferenceDisplay>>, Compositor) {
+        let mut display = ReferenceDisplay::new(64, 48);
+        display.init().unwrap();
+        let display = Arc::new(Mutex::new(display));
+        let compositor = Compositor::new(display.clone());
+        (display, compositor)
+    }
+
+    #[test]
+    fn test_stacking_and_blending() {
+        let (display, compositor) = setup();
+        let back = compositor.create_surface(20, 20);
+        back.fill(Rect::new(0, 0, 20, 20), RED);
+        let front = compositor.create_surface(20, 20);
+        front.fill(Rect::new(0, 0, 20, 20), BLUE);
+        front.fill(Rect::new(0, 0, 10, 20), [255, 255, 255, 128]);
+        compositor
+            .set_scene(&[Layer::new(back.id(), 0, 0), Layer::new(front.id(), 10, 10)])
+            .unwrap();
+        assert!(compositor.compose().is_ok());
+
+        assert_eq!(compositor.pixel(5, 5), Some(RED));
+        assert_eq!(compositor.pixel(25, 25), Some(BLUE));
+        assert_eq!(compositor.pixel(15, 15), Some([255, 128, 128, 255]));
+        assert_eq!(compositor.pixel(40, 40), Some(DEFAULT_BACKGROUND));
+        let framebuffer = display.lock().unwrap().framebuffer();
+        let at = |x: usize, y: usize| framebuffer[(y * 64 + x) * 4..][..4].to_vec();
+        assert_eq!(at(25, 25), BLUE.to_vec());
+
+        // Restacking brings the red surface to the front
+        compositor
+            .set_scene(&[Layer::new(front.id(), 10, 10), Layer::new(back.id(), 0, 0)])
+            .unwrap();
+        compositor.compose().unwrap();
+        assert_eq!(compositor.pixel(15, 15), Some(RED));
+
+        let screen = compositor.capture_screen();
+        assert_eq!((screen.width, screen.height), (64, 48));
+        assert_eq!(screen.pixel(15, 15), Some(RED));
+        assert_eq!(screen.pixel(25, 25), Some(BLUE));
+        // A window's own contents include the parts covered by others
+        assert_eq!(front.snapshot().pixel(5, 5), Some([255, 255, 255, 128]));
+    }
+
+    #[test]
+    fn test_damage_tracking() {
+        let (_, compositor) = setup();
+        let window = compositor.create_surface(8, 8);
+        compositor.set_scene(&[Layer::new(window.id(), 60, 4)]).unwrap();
+        assert_eq!(compositor.compose().unwrap(), vec![Rect::new(0, 0, 64, 48), Rect::new(60, 4, 4, 8)]);
+        assert!(compositor.compose().unwrap().is_empty());
+
+        window.fill(Rect::new(1, 1, 2, 2), RED);
+        assert_eq!(compositor.compose().unwrap(), vec![Rect::new(61, 5, 2, 2)]);
+        assert_eq!(compositor.pixel(61, 5), Some(RED));
+
+        compositor.set_scene(&[Layer::new(window.id(), 0, 0)]).unwrap();
+        assert_eq!(compositor.compose().unwrap(), vec![Rect::new(60, 4, 4, 8), Rect::new(0, 0, 8, 8)]);
+        assert_eq!(compositor.pixel(61, 5), Some(DEFAULT_BACKGROUND));
+
+        compositor.destroy_surface(window.id()).unwrap();
+        assert_eq!(compositor.compose().unwrap(), vec![Rect::new(0, 0, 8, 8)]);
+        assert!(compositor.set_scene(&[Layer::new(window.id(), 0, 0)]).is_err());
+    }
+
+    #[test]
+    fn test_vsync() {
+        let (_, compositor) = setup();
+        assert!(!compositor.tick(10).unwrap());
+        assert!(compositor.tick(10).unwrap());
+        assert_eq!(compositor.frames_presented(), 1);
+        // Nothing changed, so nothing is presented
+        assert!(!compositor.tick(VSYNC_INTERVAL_MS).unwrap());
+        let surface = compositor.create_surface(2, 2);
+        compositor.set_scene(&[Layer::new(surface.id(), 0, 0)]).unwrap();
+        assert!(compositor.tick(VSYNC_INTERVAL_MS * 3).unwrap());
+        assert_eq!(compositor.frames_presented(), 2);
+    }
+}
@@ -0,0 +1,230 @@
+//! Window surfaces
+//!
+//! A surface is an RGBA pixel buffer shared between the application that
+//! draws into it and the compositor that reads it. Every drawing call
+//! records the area it touched so the compositor repaints only that.
+
+use std::sync::{Arc, Mutex};
+
+use crate::image::Image;
+
+/// Size of one RGBA pixel
+pub const BYTES_PER_PIXEL: usize = 4;
+
+/// Axis-aligned rectangle in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, width: u32, height: u32) -> Self {
+        Rect { x, y, width, height }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    fn right(&self) -> i64 {
+        self.x as i64 + self.width as i64
+    }
+
+    fn bottom(&self) -> i64 {
+        self.y as i64 + self.height as i64
+    }
+
+    /// Overlapping area, if any
+    pub fn intersect(&self, other: &Rect) -> Option<Rect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+        if right <= x as i64 || bottom <= y as i64 {
+            return None;
+        }
+        Some(Rect::new(x, y, (right - x as i64) as u32, (bottom - y as i64) as u32))
+    }
+
+    /// Smallest rectangle covering both
+    pub fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = self.right().max(other.right());
+        let bottom = self.bottom().max(other.bottom());
+        Rect::new(x, y, (right - x as i64) as u32, (bottom - y as i64) as u32)
+    }
+
+    pub fn offset(&self, dx: i32, dy: i32) -> Rect {
+        Rect::new(self.x + dx, self.y + dy, self.width, self.height)
+    }
+}
+
+/// Surface identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SurfaceId(u64);
+
+impl SurfaceId {
+    pub fn new(id: u64) -> Self {
+        SurfaceId(id)
+    }
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+struct Buffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+    /// Areas drawn since the compositor last looked
+    damage: Vec<Rect>,
+}
+
+impl Buffer {
+    fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
+    }
+
+    fn offset(&self, x: i32, y: i32) -> usize {
+        (y as usize * self.width as usize + x as usize) * BYTES_PER_PIXEL
+    }
+}
+
+/// Pixel buffer shared by an application and the compositor
+#[derive(Clone)]
+pub struct Surface {
+    id: SurfaceId,
+    buffer: Arc<Mutex<Buffer>>,
+}
+
+impl Surface {
+    /// Fully transparent surface
+    pub fn new(id: SurfaceId, width: u32, height: u32) -> Self {
+        Surface {
+            id,
+            buffer: Arc::new(Mutex::new(Buffer {
+                width,
+                height,
+                pixels: vec![0; width as usize * height as usize * BYTES_PER_PIXEL],
+                damage: Vec::new(),
+            })),
+        }
+    }
+
+    pub fn id(&self) -> SurfaceId {
+        self.id
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        let buffer = self.buffer.lock().unwrap();
+        (buffer.width, buffer.height)
+    }
+
+    /// Paint a rectangle, clipped to the surface
+    pub fn fill(&self, rect: Rect, color: [u8; 4]) {
+        let mut buffer = self.buffer.lock().unwrap();
+        let Some(rect) = rect.intersect(&buffer.bounds()) else {
+            return;
+        };
+        for y in rect.y..rect.y + rect.height as i32 {
+            let start = buffer.offset(rect.x, y);
+            let end = start + rect.width as usize * BYTES_PER_PIXEL;
+            for pixel in buffer.pixels[start..end].chunks_exact_mut(BYTES_PER_PIXEL) {
+                pixel.copy_from_slice(&color);
+            }
+        }
+        buffer.damage.push(rect);
+    }
+
+    /// Copy RGBA rows into a rectangle that lies inside the surface
+    pub fn write(&self, rect: Rect, pixels: &[u8]) -> Result<(), String> {
+        let mut buffer = self.buffer.lock().unwrap();
+        if rect.intersect(&buffer.bounds()) != Some(rect) {
+            return Err("Rectangle lies outside the surface".to_string());
+        }
+        let row = rect.width as usize * BYTES_PER_PIXEL;
+        if pixels.len() != row * rect.height as usize {
+            return Err(format!("Expected {} bytes of pixels", row * rect.height as usize));
+        }
+        for (i, src) in pixels.chunks_exact(row).enumerate() {
+            let start = buffer.offset(rect.x, rect.y + i as i32);
+            buffer.pixels[start..start + row].copy_from_slice(src);
+        }
+        buffer.damage.push(rect);
+        Ok(())
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Option<[u8; 4]> {
+        let buffer = self.buffer.lock().unwrap();
+        if x >= buffer.width || y >= buffer.height {
+            return None;
+        }
+        let start = buffer.offset(x as i32, y as i32);
+        buffer.pixels[start..start + BYTES_PER_PIXEL].try_into().ok()
+    }
+
+    /// Reallocate at a new size; the contents are cleared
+    pub fn resize(&self, width: u32, height: u32) {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.width = width;
+        buffer.height = height;
+        buffer.pixels = vec![0; width as usize * height as usize * BYTES_PER_PIXEL];
+        buffer.damage = vec![buffer.bounds()];
+    }
+
+    /// Copy of the surface contents
+    pub fn snapshot(&self) -> Image {
+        let buffer = self.buffer.lock().unwrap();
+        Image {
+            width: buffer.width,
+            height: buffer.height,
+            pixels: buffer.pixels.clone(),
+        }
+    }
+
+    pub(crate) fn take_damage(&self) -> Vec<Rect> {
+        std::mem::take(&mut self.buffer.lock().unwrap().damage)
+    }
+
+    /// Row `y` of the surface from column `x`, `width` pixels long
+    pub(crate) fn with_row<R>(&self, x: u32, y: u32, width: u32, f: impl FnOnce(&[u8]) -> R) -> R {
+        let buffer = self.buffer.lock().unwrap();
+        let start = buffer.offset(x as i32, y as i32);
+        f(&buffer.pixels[start..start + width as usize * BYTES_PER_PIXEL])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rect_geometry() {
+        let a = Rect::new(0, 0, 10, 10);
+        let b = Rect::new(5, -5, 10, 10);
+        assert_eq!(a.intersect(&b), Some(Rect::new(5, 0, 5, 5)));
+        assert_eq!(a.union(&b), Rect::new(0, -5, 15, 15));
+        assert_eq!(a.intersect(&Rect::new(10, 0, 5, 5)), None);
+        assert!(Rect::new(3, 3, 0, 4).is_empty());
+    }
+
+    #[test]
+    fn test_drawing_records_damage() {
+        let surface = Surface::new(SurfaceId::new(1), 4, 4);
+        surface.fill(Rect::new(2, 2, 10, 10), [255, 0, 0, 255]);
+        assert_eq!(surface.pixel(3, 3), Some([255, 0, 0, 255]));
+        assert_eq!(surface.pixel(1, 1), Some([0, 0, 0, 0]));
+        assert_eq!(surface.take_damage(), vec![Rect::new(2, 2, 2, 2)]);
+        assert!(surface.take_damage().is_empty());
+
+        assert!(surface.write(Rect::new(0, 0, 1, 2), &[9; 8]).is_ok());
+        assert_eq!(surface.pixel(0, 1), Some([9, 9, 9, 9]));
+        assert!(surface.write(Rect::new(3, 3, 2, 1), &[0; 8]).is_err());
+        assert!(surface.write(Rect::new(0, 0, 2, 1), &[0; 4]).is_err());
+    }
+}
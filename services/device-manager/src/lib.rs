@@ -2,17 +2,29 @@
 //! 
 //! Manages hardware devices and driver registration in hairr OS.
 
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use sync::{ids, IdAllocator, ShardedMap};
 
-/// Device identifier
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Device identifier; generational, so a handle to an unplugged device
+/// does not reach the device registered in its place
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct DeviceId(u64);
 
 impl DeviceId {
     pub fn new(id: u64) -> Self {
         DeviceId(id)
     }
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+
+    pub fn index(&self) -> u32 {
+        ids::index(self.0)
+    }
+
+    pub fn generation(&self) -> u32 {
+        ids::generation(self.0)
+    }
 }
 
 /// Device status
@@ -49,15 +61,15 @@ impl ManagedDevice {
 
 /// Device Manager handles device registration and lifecycle
 pub struct DeviceManager {
-    devices: Arc<Mutex<HashMap<DeviceId, ManagedDevice>>>,
-    next_device_id: Arc<Mutex<u64>>,
+    devices: ShardedMap<DeviceId, ManagedDevice>,
+    device_ids: IdAllocator,
 }
 
 impl DeviceManager {
     pub fn new() -> Self {
         DeviceManager {
-            devices: Arc::new(Mutex::new(HashMap::new())),
-            next_device_id: Arc::new(Mutex::new(1)),
+            devices: ShardedMap::new(),
+            device_ids: IdAllocator::new(),
         }
     }
 
@@ -68,55 +80,52 @@ impl DeviceManager {
         device_type: String,
         driver_name: String,
     ) -> DeviceId {
-        let mut next_id = self.next_device_id.lock().unwrap();
-        let device_id = DeviceId(*next_id);
-        *next_id += 1;
-
+        let device_id = DeviceId(self.device_ids.allocate());
         let device = ManagedDevice::new(device_id, name, device_type, driver_name);
-        self.devices.lock().unwrap().insert(device_id, device);
+        self.devices.insert(device_id, device);
         
         device_id
     }
 
     /// Unregister a device
     pub fn unregister_device(&self, id: DeviceId) -> Result<(), String> {
-        if self.devices.lock().unwrap().remove(&id).is_some() {
+        if self.devices.remove(&id).is_some() {
+            self.device_ids.release(id.value());
             Ok(())
         } else {
-            Err("Device not found".to_string())
+            Err(self.missing(id))
         }
     }
 
     /// Get device information
     pub fn get_device(&self, id: DeviceId) -> Option<ManagedDevice> {
-        self.devices.lock().unwrap().get(&id).cloned()
+        self.devices.get(&id)
     }
 
     /// Update device status
     pub fn update_status(&self, id: DeviceId, status: DeviceStatus) -> Result<(), String> {
-        let mut devices = self.devices.lock().unwrap();
-        if let Some(device) = devices.get_mut(&id) {
-            device.status = status;
-            Ok(())
-        } else {
-            Err("Device not found".to_string())
-        }
+        self.devices
+            .with_mut(&id, |device| device.status = status)
+            .ok_or_else(|| self.missing(id))
     }
 
     /// List all devices
     pub fn list_devices(&self) -> Vec<ManagedDevice> {
-        self.devices.lock().unwrap().values().cloned().collect()
+        self.devices.values()
     }
 
     /// Find devices by type
     pub fn find_by_type(&self, device_type: &str) -> Vec<ManagedDevice> {
         self.devices
-            .lock()
-            .unwrap()
-            .values()
-            .filter(|d| d.device_type == device_type)
-            .cloned()
-            .collect()
+            .filter_map(|d| (d.device_type == device_type).then(|| d.clone()))
+    }
+
+    fn missing(&self, id: DeviceId) -> String {
+        if self.device_ids.is_stale(id.value()) {
+            "Stale device handle".to_string()
+        } else {
+            "Device not found".to_string()
+        }
     }
 }
 
@@ -168,4 +177,18 @@ mod tests {
         let displays = manager.find_by_type("display");
         assert_eq!(displays.len(), 2);
     }
+
+    #[test]
+    fn test_unplugged_device_handles_are_stale() {
+        let manager = DeviceManager::new();
+        let unplugged = manager.register_device("Usb0".to_string(), "storage".to_string(), "usb".to_string());
+        manager.unregister_device(unplugged).unwrap();
+        let replacement = manager.register_device("Usb1".to_string(), "storage".to_string(), "usb".to_string());
+        assert_eq!((replacement.index(), replacement.generation()), (unplugged.index(), 1));
+
+        assert!(manager.get_device(unplugged).is_none());
+        assert_eq!(manager.update_status(unplugged, DeviceStatus::Offline).unwrap_err(), "Stale device handle");
+        assert_eq!(manager.unregister_device(DeviceId::new(9)).unwrap_err(), "Device not found");
+        assert_eq!(manager.get_device(replacement).unwrap().status, DeviceStatus::Uninitialized);
+    }
 }
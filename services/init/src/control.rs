@@ -0,0 +1,87 @@
+//! Control API
+//!
+//! JSON requests to start, stop and inspect services over IPC.
+
+use ipc::JsonService;
+use serde::{Deserialize, Serialize};
+
+use crate::{ServiceManager, ServiceStatus};
+
+/// Request accepted over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ControlRequest {
+    List,
+    Status { name: String },
+    Start { name: String },
+    Stop { name: String },
+    Restart { name: String },
+}
+
+/// Reply sent over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum ControlResponse {
+    Services { services: Vec<ServiceStatus> },
+    Service { status: ServiceStatus },
+    Ok,
+    Error { message: String },
+}
+
+impl JsonService for ServiceManager {
+    type Request = ControlRequest;
+    type Response = ControlResponse;
+    const KIND: &'static str = "service control";
+
+    fn handle_request(&self, request: ControlRequest) -> ControlResponse {
+        let result = match request {
+            ControlRequest::List => Ok(ControlResponse::Services { services: self.list() }),
+            ControlRequest::Status { name } => self
+                .status(&name)
+                .map(|status| ControlResponse::Service { status })
+                .ok_or_else(|| format!("Unknown service {}", name)),
+            ControlRequest::Start { name } => self.start(&name).map(|_| ControlResponse::Ok),
+            ControlRequest::Stop { name } => self.stop(&name).map(|_| ControlResponse::Ok),
+            ControlRequest::Restart { name } => self.restart(&name).map(|_| ControlResponse::Ok),
+        };
+        result.unwrap_or_else(|message| ControlResponse::Error { message })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ServiceState, ServiceUnit};
+    use capability::CapabilityManager;
+    use ipc::{Message, ERROR_BAD_REQUEST};
+    use kernel::Kernel;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_control_over_ipc() {
+        let manager = ServiceManager::new(Arc::new(Kernel::new()), Arc::new(CapabilityManager::new()));
+        manager.add_unit(ServiceUnit::new("keystore")).unwrap();
+
+        let start = Message::Request {
+            id: 3,
+            data: br#"{"op":"start","name":"keystore"}"#.to_vec(),
+        };
+        let Some(Message::Response { id, data }) = manager.handle_message(&start) else {
+            panic!("expected a response");
+        };
+        assert_eq!(id, 3);
+        assert_eq!(serde_json::from_slice::<ControlResponse>(&data).unwrap(), ControlResponse::Ok);
+
+        match manager.handle_request(ControlRequest::Status { name: "keystore".to_string() }) {
+            ControlResponse::Service { status } => assert_eq!(status.state, ServiceState::Running),
+            other => panic!("unexpected response: {:?}", other),
+        }
+        manager.handle_request(ControlRequest::Stop { name: "keystore".to_string() });
+        assert_eq!(manager.status("keystore").unwrap().state, ServiceState::Stopped);
+
+        let missing = manager.handle_request(ControlRequest::Start { name: "nope".to_string() });
+        assert!(matches!(missing, ControlResponse::Error { .. }));
+        let bad = Message::Request { id: 4, data: b"not json".to_vec() };
+        assert!(matches!(manager.handle_message(&bad), Some(Message::Error { code: ERROR_BAD_REQUEST, .. })));
+    }
+}
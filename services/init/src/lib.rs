@@ -0,0 +1,463 @@
+//! Init and Service Supervision
+//!
+//! Brings system services up in dependency order, each as its own kernel
+//! process holding only the capabilities its unit declares. Crashed or
+//! unhealthy services are restarted with exponential backoff according to
+//! their restart policy. Time advances through `tick`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use capability::{CapabilityManager, CapabilityToken};
+use kernel::{Kernel, ProcessId};
+use serde::{Deserialize, Serialize};
+
+pub mod control;
+pub mod unit;
+
+pub use control::{ControlRequest, ControlResponse};
+pub use unit::{HealthCheck, RequiredCapability, RestartPolicy, ServiceUnit};
+
+/// Delay before the first restart, in ticks
+pub const INITIAL_BACKOFF: u64 = 1;
+
+/// Longest delay between restarts, in ticks
+pub const MAX_BACKOFF: u64 = 64;
+
+/// Liveness probe for a service; returns false when unhealthy
+pub type HealthProbe = Arc<dyn Fn() -> bool + Send + Sync>;
+
+/// Lifecycle state of a service
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceState {
+    Inactive,
+    Running,
+    /// Waiting out the backoff delay before the next restart
+    Restarting { remaining: u64 },
+    Stopped,
+    /// Crashed and not restarted
+    Failed,
+}
+
+/// Service status reported over the control API
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceStatus {
+    pub name: String,
+    pub state: ServiceState,
+    pub process: Option<ProcessId>,
+    pub restarts: u32,
+    /// Ticks since the current process started
+    pub uptime: u64,
+}
+
+struct Service {
+    unit: ServiceUnit,
+    state: ServiceState,
+    process: Option<ProcessId>,
+    tokens: Vec<CapabilityToken>,
+    restarts: u32,
+    /// Restarts since the service last ran for a full backoff period
+    consecutive_failures: u32,
+    uptime: u64,
+    since_probe: u64,
+    failed_probes: u32,
+}
+
+impl Service {
+    fn status(&self) -> ServiceStatus {
+        ServiceStatus {
+            name: self.unit.name.clone(),
+            state: self.state,
+            process: self.process,
+            restarts: self.restarts,
+            uptime: self.uptime,
+        }
+    }
+}
+
+/// Supervises system services
+pub struct ServiceManager {
+    kernel: Arc<Kernel>,
+    capabilities: Arc<CapabilityManager>,
+    services: Arc<Mutex<HashMap<String, Service>>>,
+    probes: Arc<Mutex<HashMap<String, HealthProbe>>>,
+    /// Processes the kernel reported as terminated since the last tick
+    exited: Arc<Mutex<Vec<ProcessId>>>,
+}
+
+impl ServiceManager {
+    pub fn new(kernel: Arc<Kernel>, capabilities: Arc<CapabilityManager>) -> Self {
+        let exited = Arc::new(Mutex::new(Vec::new()));
+        let hook_exited = Arc::clone(&exited);
+        kernel.on_process_terminated(Arc::new(move |pid| hook_exited.lock().unwrap().push(pid)));
+        ServiceManager {
+            kernel,
+            capabilities,
+            services: Arc::new(Mutex::new(HashMap::new())),
+            probes: Arc::new(Mutex::new(HashMap::new())),
+            exited,
+        }
+    }
+
+    /// Register a service unit
+    pub fn add_unit(&self, unit: ServiceUnit) -> Result<(), String> {
+        let mut services = self.services.lock().unwrap();
+        if services.contains_key(&unit.name) {
+            return Err(format!("Service {} already exists", unit.name));
+        }
+        services.insert(
+            unit.name.clone(),
+            Service {
+                unit,
+                state: ServiceState::Inactive,
+                process: None,
+                tokens: Vec::new(),
+                restarts: 0,
+                consecutive_failures: 0,
+                uptime: 0,
+                since_probe: 0,
+                failed_probes: 0,
+            },
+        );
+        Ok(())
+    }
+
+    /// Install the probe used by a unit's health check
+    pub fn set_health_probe(&self, name: &str, probe: HealthProbe) {
+        self.probes.lock().unwrap().insert(name.to_string(), probe);
+    }
+
+    /// Start every service in dependency order; returns the order used
+    pub fn start_all(&self) -> Result<Vec<String>, String> {
+        let order = {
+            let services = self.services.lock().unwrap();
+            let units = services.iter().map(|(name, s)| (name.clone(), s.unit.clone())).collect();
+            unit::startup_order(&units)?
+        };
+        for name in &order {
+            self.start(name)?;
+        }
+        Ok(order)
+    }
+
+    /// Start a service, starting its dependencies first
+    pub fn start(&self, name: &str) -> Result<(), String> {
+        self.start_inner(name, &mut Vec::new())
+    }
+
+    fn start_inner(&self, name: &str, visiting: &mut Vec<String>) -> Result<(), String> {
+        if visiting.iter().any(|v| v == name) {
+            return Err("Dependency cycle between services".to_string());
+        }
+        let dependencies = {
+            let services = self.services.lock().unwrap();
+            let service = services.get(name).ok_or_else(|| format!("Unknown service {}", name))?;
+            if service.state == ServiceState::Running {
+                return Ok(());
+            }
+            service.unit.dependencies.clone()
+        };
+
+        visiting.push(name.to_string());
+        for dependency in &dependencies {
+            self.start_inner(dependency, visiting)
+                .map_err(|e| format!("Cannot start {}: {}", name, e))?;
+        }
+        visiting.pop();
+
+        let mut services = self.services.lock().unwrap();
+        let service = services.get_mut(name).ok_or_else(|| format!("Unknown service {}", name))?;
+        self.launch(service);
+        Ok(())
+    }
+
+    /// Create the service's process and grant its capabilities
+    fn launch(&self, service: &mut Service) {
+        let unit = &service.unit;
+        service.tokens = unit
+            .capabilities
+            .iter()
+            .map(|c| self.capabilities.grant(c.resource.clone(), c.permission))
+            .collect();
+        service.process = Some(self.kernel.create_process(unit.name.clone(), unit.priority));
+        service.state = ServiceState::Running;
+        service.uptime = 0;
+        service.since_probe = 0;
+        service.failed_probes = 0;
+    }
+
+    /// Revoke capabilities and terminate the process, if still alive
+    fn teardown(&self, service: &mut Service) -> Option<ProcessId> {
+        for token in service.tokens.drain(..) {
+            self.capabilities.revoke(token);
+        }
+        service.process.take()
+    }
+
+    /// Stop a service and everything that depends on it
+    pub fn stop(&self, name: &str) -> Result<(), String> {
+        let dependents: Vec<String> = {
+            let services = self.services.lock().unwrap();
+            if !services.contains_key(name) {
+                return Err(format!("Unknown service {}", name));
+            }
+            services
+                .values()
+                .filter(|s| s.unit.dependencies.iter().any(|d| d == name))
+                .filter(|s| matches!(s.state, ServiceState::Running | ServiceState::Restarting { .. }))
+                .map(|s| s.unit.name.clone())
+                .collect()
+        };
+        for dependent in dependents {
+            self.stop(&dependent)?;
+        }
+
+        let process = {
+            let mut services = self.services.lock().unwrap();
+            let service = services.get_mut(name).ok_or_else(|| format!("Unknown service {}", name))?;
+            service.state = ServiceState::Stopped;
+            self.teardown(service)
+        };
+        // Terminate without holding the lock: the kernel runs our hook
+        if let Some(pid) = process {
+            self.kernel.terminate_process(pid)?;
+        }
+        Ok(())
+    }
+
+    /// Stop and start a service again
+    pub fn restart(&self, name: &str) -> Result<(), String> {
+        self.stop(name)?;
+        self.start(name)
+    }
+
+    pub fn status(&self, name: &str) -> Option<ServiceStatus> {
+        self.services.lock().unwrap().get(name).map(|s| s.status())
+    }
+
+    pub fn list(&self) -> Vec<ServiceStatus> {
+        let mut statuses: Vec<ServiceStatus> = self.services.lock().unwrap().values().map(|s| s.status()).collect();
+        statuses.sort_by(|a, b| a.name.cmp(&b.name));
+        statuses
+    }
+
+    /// Capability tokens currently held by a service
+    pub fn capabilities_of(&self, name: &str) -> Vec<CapabilityToken> {
+        self.services
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|s| s.tokens.clone())
+            .unwrap_or_default()
+    }
+
+    /// Advance time: restart services whose backoff has elapsed, run health
+    /// checks, and schedule restarts for services that crashed
+    pub fn tick(&self, elapsed: u64) {
+        let mut services = self.services.lock().unwrap();
+        for service in services.values_mut() {
+            match service.state {
+                ServiceState::Running => {
+                    service.uptime += elapsed;
+                    if service.uptime >= MAX_BACKOFF {
+                        service.consecutive_failures = 0;
+                    }
+                }
+                ServiceState::Restarting { remaining } if remaining <= elapsed => {
+                    service.restarts += 1;
+                    self.launch(service);
+                }
+                ServiceState::Restarting { remaining } => {
+                    service.state = ServiceState::Restarting {
+                        remaining: remaining - elapsed,
+                    };
+                }
+                _ => {}
+            }
+        }
+        drop(services);
+
+        self.run_health_checks(elapsed);
+        self.reap_exited();
+    }
+
+    /// Handle processes that exited without being stopped, then reap every
+    /// terminated process so the kernel can reuse its slot
+    fn reap_exited(&self) {
+        let exited: Vec<ProcessId> = self.exited.lock().unwrap().drain(..).collect();
+        if exited.is_empty() {
+            return;
+        }
+        let mut services = self.services.lock().unwrap();
+        for service in services.values_mut() {
+            if service.state != ServiceState::Running || !service.process.is_some_and(|p| exited.contains(&p)) {
+                continue;
+            }
+            self.teardown(service);
+
+            let unit = &service.unit;
+            let allowed = unit.restart != RestartPolicy::Never && unit.restart_limit.is_none_or(|l| service.restarts < l);
+            service.state = if allowed {
+                let backoff = INITIAL_BACKOFF
+                    .saturating_mul(1 << service.consecutive_failures.min(16))
+                    .min(MAX_BACKOFF);
+                service.consecutive_failures += 1;
+                ServiceState::Restarting { remaining: backoff }
+            } else {
+                ServiceState::Failed
+            };
+        }
+        drop(services);
+
+        for pid in exited {
+            self.kernel.reap(pid);
+        }
+    }
+
+    /// Probe services whose check interval has passed, terminating those
+    /// that keep failing so they go through the crash path
+    fn run_health_checks(&self, elapsed: u64) {
+        let due: Vec<(String, HealthCheck)> = {
+            let mut services = self.services.lock().unwrap();
+            services
+                .values_mut()
+                .filter(|s| s.state == ServiceState::Running)
+                .filter_map(|s| {
+                    let check = s.unit.health_check?;
+                    s.since_probe += elapsed;
+                    (s.since_probe >= check.interval).then(|| {
+                        s.since_probe = 0;
+                        (s.unit.name.clone(), check)
+                    })
+                })
+                .collect()
+        };
+
+        for (name, check) in due {
+            let probe = self.probes.lock().unwrap().get(&name).cloned();
+            // Probes may call into other services, so run them unlocked
+            let healthy = probe.is_none_or(|probe| probe());
+            let unhealthy_process = {
+                let mut services = self.services.lock().unwrap();
+                let Some(service) = services.get_mut(&name) else {
+                    continue;
+                };
+                service.failed_probes = if healthy { 0 } else { service.failed_probes + 1 };
+                (service.failed_probes >= check.failure_threshold)
+                    .then_some(service.process)
+                    .flatten()
+            };
+            if let Some(pid) = unhealthy_process {
+                let _ = self.kernel.terminate_process(pid);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use capability::{Permission, Resource};
+    use kernel::ProcessState;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    fn manager() -> (ServiceManager, Arc<Kernel>, Arc<CapabilityManager>) {
+        let kernel = Arc::new(Kernel::new());
+        let capabilities = Arc::new(CapabilityManager::new());
+        (
+            ServiceManager::new(Arc::clone(&kernel), Arc::clone(&capabilities)),
+            kernel,
+            capabilities,
+        )
+    }
+
+    #[test]
+    fn test_ordered_startup_with_capabilities() {
+        let (manager, kernel, capabilities) = manager();
+        manager
+            .add_unit(ServiceUnit::new("net-config").with_dependency("device-manager"))
+            .unwrap();
+        manager
+            .add_unit(ServiceUnit::new("device-manager").with_capability(Resource::Device("*".to_string()), Permission::Full))
+            .unwrap();
+        assert!(manager.add_unit(ServiceUnit::new("net-config")).is_err());
+
+        assert_eq!(manager.start_all().unwrap(), vec!["device-manager", "net-config"]);
+        let status = manager.status("net-config").unwrap();
+        assert_eq!(status.state, ServiceState::Running);
+        assert_eq!(kernel.get_process(status.process.unwrap()).unwrap().name, "net-config");
+
+        let tokens = manager.capabilities_of("device-manager");
+        assert_eq!(tokens.len(), 1);
+        assert!(capabilities.check_permission(tokens[0], Permission::Write));
+
+        // Stopping a dependency stops its dependents and revokes capabilities
+        manager.stop("device-manager").unwrap();
+        assert_eq!(manager.status("net-config").unwrap().state, ServiceState::Stopped);
+        assert!(capabilities.validate(tokens[0]).is_none());
+        assert_eq!(kernel.get_process(status.process.unwrap()).unwrap().state, ProcessState::Terminated);
+
+        // Starting a dependent brings its dependencies back
+        manager.start("net-config").unwrap();
+        assert_eq!(manager.status("device-manager").unwrap().state, ServiceState::Running);
+    }
+
+    #[test]
+    fn test_crash_restart_with_backoff() {
+        let (manager, kernel, _) = manager();
+        manager
+            .add_unit(ServiceUnit::new("keystore").with_restart(RestartPolicy::OnFailure, Some(2)))
+            .unwrap();
+        manager.start("keystore").unwrap();
+
+        let crash = |manager: &ServiceManager| {
+            let pid = manager.status("keystore").unwrap().process.unwrap();
+            kernel.terminate_process(pid).unwrap();
+            manager.tick(0);
+            // Init reaps what exited
+            assert!(kernel.get_process(pid).is_none());
+        };
+
+        crash(&manager);
+        assert_eq!(manager.status("keystore").unwrap().state, ServiceState::Restarting { remaining: 1 });
+        manager.tick(1);
+        assert_eq!(manager.status("keystore").unwrap().state, ServiceState::Running);
+
+        // The second crash waits twice as long
+        crash(&manager);
+        assert_eq!(manager.status("keystore").unwrap().state, ServiceState::Restarting { remaining: 2 });
+        manager.tick(2);
+        assert_eq!(manager.status("keystore").unwrap().restarts, 2);
+
+        // The restart limit is exhausted
+        crash(&manager);
+        assert_eq!(manager.status("keystore").unwrap().state, ServiceState::Failed);
+    }
+
+    #[test]
+    fn test_failed_health_checks_restart_service() {
+        let (manager, _, _) = manager();
+        manager
+            .add_unit(ServiceUnit::new("ai-scheduler").with_health_check(2, 2))
+            .unwrap();
+        let healthy = Arc::new(AtomicBool::new(true));
+        let probe_state = Arc::clone(&healthy);
+        manager.set_health_probe("ai-scheduler", Arc::new(move || probe_state.load(Ordering::SeqCst)));
+        manager.start("ai-scheduler").unwrap();
+        let first = manager.status("ai-scheduler").unwrap().process;
+
+        manager.tick(2);
+        healthy.store(false, Ordering::SeqCst);
+        manager.tick(2);
+        assert_eq!(manager.status("ai-scheduler").unwrap().state, ServiceState::Running);
+        manager.tick(2);
+        assert!(matches!(manager.status("ai-scheduler").unwrap().state, ServiceState::Restarting { .. }));
+
+        healthy.store(true, Ordering::SeqCst);
+        manager.tick(1);
+        let status = manager.status("ai-scheduler").unwrap();
+        assert_eq!(status.state, ServiceState::Running);
+        assert_ne!(status.process, first);
+    }
+}
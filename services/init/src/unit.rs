@@ -0,0 +1,198 @@
+//! Service units
+//!
+//! A unit declares what a service needs before it can run: the services it
+//! depends on, the capabilities it must be granted, how it is restarted
+//! after a crash, and how its health is checked. Units are plain JSON.
+
+use std::collections::{HashMap, VecDeque};
+
+use capability::{Permission, Resource};
+use kernel::Priority;
+use serde::{Deserialize, Serialize};
+
+/// When a stopped service is brought back up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestartPolicy {
+    Never,
+    /// Restart after crashes and failed health checks
+    OnFailure,
+    /// Restart whenever the process exits without being stopped
+    Always,
+}
+
+/// A capability granted to the service while it runs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequiredCapability {
+    pub resource: Resource,
+    pub permission: Permission,
+}
+
+/// Periodic liveness probe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthCheck {
+    /// Ticks between probes
+    pub interval: u64,
+    /// Consecutive failed probes before the service is restarted
+    pub failure_threshold: u32,
+}
+
+fn default_priority() -> Priority {
+    Priority::Normal
+}
+
+/// Declarative description of a service
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceUnit {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub dependencies: Vec<String>,
+    #[serde(default = "default_priority")]
+    pub priority: Priority,
+    pub restart: RestartPolicy,
+    /// Restarts allowed before the service is marked failed; `None` is unlimited
+    #[serde(default)]
+    pub restart_limit: Option<u32>,
+    #[serde(default)]
+    pub capabilities: Vec<RequiredCapability>,
+    #[serde(default)]
+    pub health_check: Option<HealthCheck>,
+}
+
+impl ServiceUnit {
+    pub fn new(name: &str) -> Self {
+        ServiceUnit {
+            name: name.to_string(),
+            description: String::new(),
+            dependencies: Vec::new(),
+            priority: Priority::Normal,
+            restart: RestartPolicy::OnFailure,
+            restart_limit: None,
+            capabilities: Vec::new(),
+            health_check: None,
+        }
+    }
+
+    pub fn with_dependency(mut self, name: &str) -> Self {
+        self.dependencies.push(name.to_string());
+        self
+    }
+
+    pub fn with_restart(mut self, restart: RestartPolicy, limit: Option<u32>) -> Self {
+        self.restart = restart;
+        self.restart_limit = limit;
+        self
+    }
+
+    pub fn with_capability(mut self, resource: Resource, permission: Permission) -> Self {
+        self.capabilities.push(RequiredCapability { resource, permission });
+        self
+    }
+
+    pub fn with_health_check(mut self, interval: u64, failure_threshold: u32) -> Self {
+        self.health_check = Some(HealthCheck {
+            interval: interval.max(1),
+            failure_threshold: failure_threshold.max(1),
+        });
+        self
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| format!("Invalid service unit: {}", e))
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|e| e.to_string())
+    }
+}
+
+/// Order `units` so every service comes after its dependencies
+pub fn startup_order(units: &HashMap<String, ServiceUnit>) -> Result<Vec<String>, String> {
+    let mut pending: HashMap<&str, usize> = HashMap::new();
+    let mut dependents: HashMap<&str, Vec<&str>> = HashMap::new();
+    for unit in units.values() {
+        for dependency in &unit.dependencies {
+            if !units.contains_key(dependency) {
+                return Err(format!("{} depends on unknown service {}", unit.name, dependency));
+            }
+            dependents.entry(dependency.as_str()).or_default().push(&unit.name);
+        }
+        pending.insert(&unit.name, unit.dependencies.len());
+    }
+
+    let mut ready: Vec<&str> = pending.iter().filter(|(_, n)| **n == 0).map(|(name, _)| *name).collect();
+    ready.sort_unstable();
+    let mut ready: VecDeque<&str> = ready.into();
+    let mut order = Vec::with_capacity(units.len());
+    while let Some(name) = ready.pop_front() {
+        order.push(name.to_string());
+        let mut unlocked = Vec::new();
+        for dependent in dependents.get(name).into_iter().flatten() {
+            let count = pending.get_mut(dependent).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                unlocked.push(*dependent);
+            }
+        }
+        unlocked.sort_unstable();
+        ready.extend(unlocked);
+    }
+
+    if order.len() != units.len() {
+        return Err("Dependency cycle between services".to_string());
+    }
+    Ok(order)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn units(list: Vec<ServiceUnit>) -> HashMap<String, ServiceUnit> {
+        list.into_iter().map(|u| (u.name.clone(), u)).collect()
+    }
+
+    #[test]
+    fn test_startup_order() {
+        let order = startup_order(&units(vec![
+            ServiceUnit::new("net-config").with_dependency("device-manager"),
+            ServiceUnit::new("app-store").with_dependency("net-config").with_dependency("keystore"),
+            ServiceUnit::new("keystore"),
+            ServiceUnit::new("device-manager"),
+        ]))
+        .unwrap();
+        assert_eq!(order, vec!["device-manager", "keystore", "net-config", "app-store"]);
+    }
+
+    #[test]
+    fn test_invalid_dependencies() {
+        let missing = units(vec![ServiceUnit::new("a").with_dependency("b")]);
+        assert!(startup_order(&missing).unwrap_err().contains("unknown service"));
+
+        let cycle = units(vec![
+            ServiceUnit::new("a").with_dependency("b"),
+            ServiceUnit::new("b").with_dependency("a"),
+        ]);
+        assert!(startup_order(&cycle).unwrap_err().contains("cycle"));
+    }
+
+    #[test]
+    fn test_unit_from_json() {
+        let unit = ServiceUnit::from_json(
+            r#"{
+                "name": "keystore",
+                "restart": "on_failure",
+                "restart_limit": 3,
+                "capabilities": [{"resource": {"Device": "tpm0"}, "permission": "ReadWrite"}],
+                "health_check": {"interval": 5, "failure_threshold": 2}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(unit.priority, Priority::Normal);
+        assert_eq!(unit.capabilities[0].resource, Resource::Device("tpm0".to_string()));
+        assert_eq!(ServiceUnit::from_json(&unit.to_json().unwrap()).unwrap(), unit);
+        assert!(ServiceUnit::from_json("{}").is_err());
+    }
+}
@@ -0,0 +1,559 @@
+//! DID Documents and Verifiable Credentials
+//!
+//! Builds W3C DID Documents from keystore identities and issues/verifies
+//! verifiable credentials signed with stored keys, in both JSON-LD (embedded
+//! proof) and JWT (compact JWS) forms.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+use system_utils::encoding::{base64url_decode, base64url_encode};
+use system_utils::time::format_rfc3339;
+
+use crate::{KeyId, KeyType, Keystore};
+
+/// Default JSON-LD contexts
+pub const DID_CONTEXT: &str = "https://www.w3.org/ns/did/v1";
+pub const CREDENTIALS_CONTEXT: &str = "https://www.w3.org/2018/credentials/v1";
+
+/// Verification method entry in a DID Document
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VerificationMethod {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub method_type: String,
+    pub controller: String,
+    #[serde(rename = "publicKeyJwk")]
+    pub public_key_jwk: Value,
+}
+
+impl VerificationMethod {
+    /// Key identifier referenced by this method (the DID URL fragment)
+    pub fn key_id(&self) -> Option<KeyId> {
+        self.id.split_once('#').map(|(_, fragment)| KeyId::from(fragment))
+    }
+}
+
+/// Service endpoint advertised in a DID Document
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServiceEndpoint {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub service_type: String,
+    #[serde(rename = "serviceEndpoint")]
+    pub endpoint: String,
+}
+
+impl ServiceEndpoint {
+    pub fn new(id: String, service_type: String, endpoint: String) -> Self {
+        ServiceEndpoint {
+            id,
+            service_type,
+            endpoint,
+        }
+    }
+}
+
+/// W3C DID Document
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DidDocument {
+    #[serde(rename = "@context")]
+    pub context: Vec<String>,
+    pub id: String,
+    pub controller: String,
+    #[serde(rename = "verificationMethod", default)]
+    pub verification_methods: Vec<VerificationMethod>,
+    #[serde(default)]
+    pub authentication: Vec<String>,
+    #[serde(rename = "assertionMethod", default)]
+    pub assertion_method: Vec<String>,
+    #[serde(rename = "service", default)]
+    pub services: Vec<ServiceEndpoint>,
+}
+
+impl DidDocument {
+    /// Find a verification method by its full DID URL
+    pub fn find_method(&self, id: &str) -> Option<&VerificationMethod> {
+        self.verification_methods.iter().find(|m| m.id == id)
+    }
+
+    /// Serialize to JSON-LD
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|e| e.to_string())
+    }
+
+    /// Parse from JSON-LD
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| format!("Invalid DID document: {}", e))
+    }
+}
+
+/// Linked-data proof attached to a credential
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Proof {
+    #[serde(rename = "type")]
+    pub proof_type: String,
+    pub created: String,
+    #[serde(rename = "verificationMethod")]
+    pub verification_method: String,
+    #[serde(rename = "proofPurpose")]
+    pub proof_purpose: String,
+    pub jws: String,
+}
+
+/// W3C Verifiable Credential
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VerifiableCredential {
+    #[serde(rename = "@context")]
+    pub context: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "type")]
+    pub types: Vec<String>,
+    pub issuer: String,
+    #[serde(rename = "issuanceDate")]
+    pub issuance_date: String,
+    #[serde(rename = "expirationDate", skip_serializing_if = "Option::is_none")]
+    pub expiration_date: Option<String>,
+    #[serde(rename = "credentialSubject")]
+    pub credential_subject: Map<String, Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proof: Option<Proof>,
+}
+
+impl VerifiableCredential {
+    /// Create an unsigned credential about `subject_id`
+    pub fn new(issuer: String, subject_id: String, credential_type: &str) -> Self {
+        let mut subject = Map::new();
+        subject.insert("id".to_string(), Value::String(subject_id));
+
+        VerifiableCredential {
+            context: vec![CREDENTIALS_CONTEXT.to_string()],
+            id: None,
+            types: vec!["VerifiableCredential".to_string(), credential_type.to_string()],
+            issuer,
+            issuance_date: format_rfc3339(now_secs() * 1000),
+            expiration_date: None,
+            credential_subject: subject,
+            proof: None,
+        }
+    }
+
+    /// Add a claim about the subject
+    pub fn with_claim(mut self, name: &str, value: Value) -> Self {
+        self.credential_subject.insert(name.to_string(), value);
+        self
+    }
+
+    /// Subject DID, if present
+    pub fn subject_id(&self) -> Option<&str> {
+        self.credential_subject.get("id").and_then(|v| v.as_str())
+    }
+
+    /// Serialize to JSON-LD
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|e| e.to_string())
+    }
+
+    /// Parse from JSON-LD
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| format!("Invalid credential: {}", e))
+    }
+
+    /// Bytes covered by the proof: the credential without its proof
+    fn signing_payload(&self) -> Result<Vec<u8>, String> {
+        let mut unsigned = self.clone();
+        unsigned.proof = None;
+        serde_json::to_vec(&unsigned).map_err(|e| e.to_string())
+    }
+}
+
+impl Keystore {
+    /// Build the DID Document for a stored identity
+    pub fn did_document(&self, did: &str) -> Result<DidDocument, String> {
+        let identity = self.get_identity(did).ok_or("Identity not found")?;
+        let keys = self.keys.lock().unwrap();
+
+        let mut verification_methods = Vec::new();
+        for method_id in &identity.verification_methods {
+            let key_id = method_id
+                .split_once('#')
+                .map(|(_, fragment)| KeyId::from(fragment))
+                .ok_or("Malformed verification method")?;
+            let key = keys.get(&key_id).ok_or("Key not found")?;
+
+            verification_methods.push(VerificationMethod {
+                id: method_id.clone(),
+                method_type: "JsonWebKey2020".to_string(),
+                controller: did.to_string(),
+                public_key_jwk: public_jwk(key.key_type, &identity.public_key),
+            });
+        }
+
+        Ok(DidDocument {
+            context: vec![DID_CONTEXT.to_string()],
+            id: did.to_string(),
+            controller: did.to_string(),
+            authentication: identity.verification_methods.clone(),
+            assertion_method: identity.verification_methods.clone(),
+            verification_methods,
+            services: self.services.lock().unwrap().get(did).cloned().unwrap_or_default(),
+        })
+    }
+
+    /// Advertise a service endpoint in an identity's DID Document
+    pub fn add_service(&self, did: &str, service: ServiceEndpoint) -> Result<(), String> {
+        if self.get_identity(did).is_none() {
+            return Err("Identity not found".to_string());
+        }
+
+        let mut services = self.services.lock().unwrap();
+        let entries = services.entry(did.to_string()).or_default();
+        if entries.iter().any(|s| s.id == service.id) {
+            return Err("Service already registered".to_string());
+        }
+        entries.push(service);
+        Ok(())
+    }
+
+    /// Remove a service endpoint from an identity's DID Document
+    pub fn remove_service(&self, did: &str, service_id: &str) -> Result<(), String> {
+        let mut services = self.services.lock().unwrap();
+        let entries = services.get_mut(did).ok_or("Service not found")?;
+        let before = entries.len();
+        entries.retain(|s| s.id != service_id);
+        if entries.len() == before {
+            return Err("Service not found".to_string());
+        }
+        Ok(())
+    }
+
+    /// Sign a credential as `issuer_did` using one of its verification keys
+    pub fn issue_credential(
+        &self,
+        mut credential: VerifiableCredential,
+        key_id: &KeyId,
+    ) -> Result<VerifiableCredential, String> {
+        let method = self.issuer_method(&credential.issuer, key_id)?;
+        credential.proof = None;
+
+        let header = json!({ "alg": "EdDSA", "b64": false, "crit": ["b64"] });
+        let header = base64url_encode(header.to_string().as_bytes());
+        let signature = self.sign(key_id, &detached_input(&header, &credential.signing_payload()?))?;
+
+        credential.proof = Some(Proof {
+            proof_type: "JsonWebSignature2020".to_string(),
+            created: format_rfc3339(now_secs() * 1000),
+            verification_method: method,
+            proof_purpose: "assertionMethod".to_string(),
+            jws: format!("{}..{}", header, base64url_encode(&signature)),
+        });
+
+        Ok(credential)
+    }
+
+    /// Verify a credential's embedded proof against the issuer's DID Document
+    pub fn verify_credential(&self, credential: &VerifiableCredential) -> Result<bool, String> {
+        let proof = credential.proof.as_ref().ok_or("Credential has no proof")?;
+        let key_id = self.resolve_method(&credential.issuer, &proof.verification_method)?;
+
+        let (header, signature) = proof
+            .jws
+            .split_once("..")
+            .ok_or("Malformed detached JWS")?;
+        let signature = base64url_decode(signature)?;
+
+        self.verify(&key_id, &detached_input(header, &credential.signing_payload()?), &signature)
+    }
+
+    /// Encode a credential as a signed JWT (compact JWS)
+    pub fn credential_to_jwt(
+        &self,
+        credential: &VerifiableCredential,
+        key_id: &KeyId,
+    ) -> Result<String, String> {
+        let method = self.issuer_method(&credential.issuer, key_id)?;
+
+        let mut vc = credential.clone();
+        vc.proof = None;
+        let mut claims = json!({
+            "iss": vc.issuer,
+            "nbf": now_secs(),
+            "vc": vc,
+        });
+        if let Some(subject) = credential.subject_id() {
+            claims["sub"] = Value::String(subject.to_string());
+        }
+        if let Some(id) = &credential.id {
+            claims["jti"] = Value::String(id.clone());
+        }
+
+        let header = json!({ "alg": "EdDSA", "typ": "JWT", "kid": method });
+        let signing_input = format!(
+            "{}.{}",
+            base64url_encode(header.to_string().as_bytes()),
+            base64url_encode(claims.to_string().as_bytes())
+        );
+        let signature = self.sign(key_id, signing_input.as_bytes())?;
+
+        Ok(format!("{}.{}", signing_input, base64url_encode(&signature)))
+    }
+
+    /// Verify a JWT credential and return the embedded credential, whose
+    /// issuer is then the DID that signed the token
+    pub fn verify_jwt_credential(&self, jwt: &str) -> Result<VerifiableCredential, String> {
+        let parts: Vec<&str> = jwt.split('.').collect();
+        if parts.len() != 3 {
+            return Err("Malformed JWT".to_string());
+        }
+
+        let header: Value = serde_json::from_slice(&base64url_decode(parts[0])?)
+            .map_err(|e| format!("Invalid JWT header: {}", e))?;
+        let claims: Value = serde_json::from_slice(&base64url_decode(parts[1])?)
+            .map_err(|e| format!("Invalid JWT claims: {}", e))?;
+
+        let issuer = claims["iss"].as_str().ok_or("JWT missing issuer")?;
+        let kid = header["kid"].as_str().ok_or("JWT missing key id")?;
+        let key_id = self.resolve_method(issuer, kid)?;
+
+        let signing_input = format!("{}.{}", parts[0], parts[1]);
+        let signature = base64url_decode(parts[2])?;
+        if !self.verify(&key_id, signing_input.as_bytes(), &signature)? {
+            return Err("Invalid JWT signature".to_string());
+        }
+
+        // The signature only vouches for `iss`; the embedded credential
+        // must not claim a different issuer or subject
+        let credential: VerifiableCredential =
+            serde_json::from_value(claims["vc"].clone()).map_err(|e| format!("Invalid credential: {}", e))?;
+        if credential.issuer != issuer {
+            return Err("Credential issuer does not match JWT issuer".to_string());
+        }
+        if let Some(subject) = claims.get("sub") {
+            if subject.as_str() != credential.subject_id() {
+                return Err("Credential subject does not match JWT subject".to_string());
+            }
+        }
+        Ok(credential)
+    }
+
+    /// DID URL of `key_id` if it is a verification method of `issuer`
+    fn issuer_method(&self, issuer: &str, key_id: &KeyId) -> Result<String, String> {
+        let identity = self.get_identity(issuer).ok_or("Issuer identity not found")?;
+        let method = format!("{}#{}", issuer, key_id.0);
+        if !identity.verification_methods.contains(&method) {
+            return Err("Key is not a verification method of the issuer".to_string());
+        }
+        Ok(method)
+    }
+
+    /// Resolve a verification method of `issuer` to a stored key
+    fn resolve_method(&self, issuer: &str, method_id: &str) -> Result<KeyId, String> {
+        let document = self.did_document(issuer)?;
+        if !document.assertion_method.iter().any(|m| m == method_id) {
+            return Err("Verification method not authorized for assertions".to_string());
+        }
+        document
+            .find_method(method_id)
+            .and_then(|m| m.key_id())
+            .ok_or_else(|| "Verification method not found".to_string())
+    }
+}
+
+fn detached_input(header: &str, payload: &[u8]) -> Vec<u8> {
+    let mut input = Vec::with_capacity(header.len() + 1 + payload.len());
+    input.extend_from_slice(header.as_bytes());
+    input.push(b'.');
+    input.extend_from_slice(payload);
+    input
+}
+
+fn public_jwk(key_type: KeyType, public_key: &[u8]) -> Value {
+    let (kty, crv) = match key_type {
+        KeyType::Ed25519 => ("OKP", "Ed25519"),
+        KeyType::ECC256 => ("EC", "P-256"),
+        KeyType::ECC384 => ("EC", "P-384"),
+        KeyType::RSA2048 | KeyType::RSA4096 => ("RSA", ""),
+        KeyType::AES256 => ("oct", ""),
+    };
+
+    let mut jwk = json!({ "kty": kty, "x": base64url_encode(public_key) });
+    if !crv.is_empty() {
+        jwk["crv"] = Value::String(crv.to_string());
+    }
+    jwk
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KeyUsage;
+
+    fn issuer_keystore() -> (Keystore, KeyId) {
+        let keystore = Keystore::new();
+        let key_id = KeyId::from("issuer-key");
+        keystore
+            .import_key(
+                key_id.clone(),
+                KeyType::Ed25519,
+                vec![KeyUsage::Sign, KeyUsage::Verify],
+                vec![7u8; 32],
+            )
+            .unwrap();
+        keystore
+            .create_identity("did:hairr:issuer".to_string(), &key_id)
+            .unwrap();
+        (keystore, key_id)
+    }
+
+    #[test]
+    fn test_did_document_generation() {
+        let (keystore, _) = issuer_keystore();
+        keystore
+            .add_service(
+                "did:hairr:issuer",
+                ServiceEndpoint::new(
+                    "did:hairr:issuer#inbox".to_string(),
+                    "DIDCommMessaging".to_string(),
+                    "hairr://messenger/inbox".to_string(),
+                ),
+            )
+            .unwrap();
+
+        let document = keystore.did_document("did:hairr:issuer").unwrap();
+        assert_eq!(document.verification_methods.len(), 1);
+        assert_eq!(document.verification_methods[0].id, "did:hairr:issuer#issuer-key");
+        assert_eq!(document.services.len(), 1);
+
+        let parsed = DidDocument::from_json(&document.to_json().unwrap()).unwrap();
+        assert_eq!(parsed, document);
+    }
+
+    #[test]
+    fn test_credential_issue_and_verify() {
+        let (keystore, key_id) = issuer_keystore();
+        let credential = VerifiableCredential::new(
+            "did:hairr:issuer".to_string(),
+            "did:hairr:user123".to_string(),
+            "DeveloperCredential",
+        )
+        .with_claim("publisher", json!("DevTools Inc"));
+
+        let signed = keystore.issue_credential(credential, &key_id).unwrap();
+        assert!(keystore.verify_credential(&signed).unwrap());
+
+        let json = signed.to_json().unwrap();
+        assert!(json.contains("\"@context\""));
+        let mut tampered = VerifiableCredential::from_json(&json).unwrap();
+        tampered.credential_subject.insert("publisher".to_string(), json!("Mallory"));
+        assert!(!keystore.verify_credential(&tampered).unwrap());
+    }
+
+    #[test]
+    fn test_credential_jwt_roundtrip() {
+        let (keystore, key_id) = issuer_keystore();
+        let credential = VerifiableCredential::new(
+            "did:hairr:issuer".to_string(),
+            "did:hairr:user123".to_string(),
+            "AgeCredential",
+        )
+        .with_claim("over18", json!(true));
+
+        let jwt = keystore.credential_to_jwt(&credential, &key_id).unwrap();
+        assert_eq!(jwt.split('.').count(), 3);
+
+        let decoded = keystore.verify_jwt_credential(&jwt).unwrap();
+        assert_eq!(decoded.subject_id(), Some("did:hairr:user123"));
+        assert_eq!(decoded.credential_subject["over18"], json!(true));
+    }
+
+    #[test]
+    fn test_jwt_issuer_must_match_credential() {
+        let (keystore, issuer) = issuer_keystore();
+        let mallory = KeyId::from("mallory-key");
+        keystore
+            .generate_key(mallory.clone(), KeyType::Ed25519, vec![KeyUsage::Sign, KeyUsage::Verify], false)
+            .unwrap();
+        keystore.create_identity("did:hairr:mallory".to_string(), &mallory).unwrap();
+
+        // Signed by mallory, but the credential claims the issuer wrote it
+        let credential = VerifiableCredential::new(
+            "did:hairr:issuer".to_string(),
+            "did:hairr:user123".to_string(),
+            "AgeCredential",
+        );
+        let sign = |claims: Value| {
+            let header = json!({ "alg": "EdDSA", "typ": "JWT", "kid": "did:hairr:mallory#mallory-key" });
+            let input = format!(
+                "{}.{}",
+                base64url_encode(header.to_string().as_bytes()),
+                base64url_encode(claims.to_string().as_bytes())
+            );
+            let signature = keystore.sign(&mallory, input.as_bytes()).unwrap();
+            format!("{}.{}", input, base64url_encode(&signature))
+        };
+
+        let forged = sign(json!({ "iss": "did:hairr:mallory", "vc": credential }));
+        assert_eq!(
+            keystore.verify_jwt_credential(&forged).unwrap_err(),
+            "Credential issuer does not match JWT issuer"
+        );
+
+        let mut own = credential.clone();
+        own.issuer = "did:hairr:mallory".to_string();
+        let swapped = sign(json!({ "iss": "did:hairr:mallory", "sub": "did:hairr:someone-else", "vc": own }));
+        assert!(keystore.verify_jwt_credential(&swapped).is_err());
+        let honest = sign(json!({ "iss": "did:hairr:mallory", "sub": "did:hairr:user123", "vc": own }));
+        assert!(keystore.verify_jwt_credential(&honest).is_ok());
+
+        // Naming the issuer's key gets nowhere without holding it: the same
+        // bytes pass only when the issuer's key signed them
+        let header = json!({ "alg": "EdDSA", "typ": "JWT", "kid": "did:hairr:issuer#issuer-key" });
+        let claims = json!({ "iss": "did:hairr:issuer", "sub": "did:hairr:user123", "vc": credential });
+        let input = format!(
+            "{}.{}",
+            base64url_encode(header.to_string().as_bytes()),
+            base64url_encode(claims.to_string().as_bytes())
+        );
+        let token = |signature: &[u8]| format!("{}.{}", input, base64url_encode(signature));
+        for forged in [keystore.sign(&mallory, input.as_bytes()).unwrap(), input.as_bytes().to_vec()] {
+            assert_eq!(keystore.verify_jwt_credential(&token(&forged)).unwrap_err(), "Invalid JWT signature");
+        }
+        assert!(keystore.verify_jwt_credential(&token(&keystore.sign(&issuer, input.as_bytes()).unwrap())).is_ok());
+    }
+
+    #[test]
+    fn test_issue_with_foreign_key_fails() {
+        let (keystore, _) = issuer_keystore();
+        let other = KeyId::from("other-key");
+        keystore
+            .generate_key(other.clone(), KeyType::Ed25519, vec![KeyUsage::Sign], false)
+            .unwrap();
+
+        let credential = VerifiableCredential::new(
+            "did:hairr:issuer".to_string(),
+            "did:hairr:user123".to_string(),
+            "AgeCredential",
+        );
+        assert!(keystore.issue_credential(credential, &other).is_err());
+    }
+
+    #[test]
+    fn test_timestamp_format() {
+        let credential = VerifiableCredential::new(
+            "did:hairr:issuer".to_string(),
+            "did:hairr:user123".to_string(),
+            "AgeCredential",
+        );
+        let issued = system_utils::time::parse_rfc3339(&credential.issuance_date).unwrap();
+        assert_eq!(credential.issuance_date, format_rfc3339(issued));
+        assert_eq!(issued % 1000, 0);
+    }
+}
@@ -0,0 +1,139 @@
+//! Key Agreement
+//!
+//! Finite-field Diffie-Hellman over the Mersenne prime 2^61 - 1. Keys with
+//! `KeyUsage::DeriveKey` can agree on shared secrets without their private
+//! half leaving the keystore. The group is sized for the simulation, not
+//! for real-world security.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use crate::{KeyId, KeyUsage, Keystore};
+
+/// Group modulus, 2^61 - 1
+pub const MODULUS: u64 = (1 << 61) - 1;
+
+/// Group generator
+pub const GENERATOR: u64 = 37;
+
+/// Encoded size of public keys and shared secrets
+pub const KEY_LEN: usize = 8;
+
+fn mul_mod(a: u64, b: u64) -> u64 {
+    ((a as u128 * b as u128) % MODULUS as u128) as u64
+}
+
+fn pow_mod(mut base: u64, mut exponent: u64) -> u64 {
+    let mut result = 1;
+    base %= MODULUS;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = mul_mod(result, base);
+        }
+        base = mul_mod(base, base);
+        exponent >>= 1;
+    }
+    result
+}
+
+/// Random bytes from the process entropy source
+pub fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(len + 8);
+    while bytes.len() < len {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_usize(bytes.len());
+        bytes.extend_from_slice(&hasher.finish().to_be_bytes());
+    }
+    bytes.truncate(len);
+    bytes
+}
+
+/// Private exponent derived from key material, in `[1, MODULUS - 2]`
+pub fn private_scalar(key_material: &[u8]) -> u64 {
+    let folded = key_material
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325u64, |acc, b| (acc ^ *b as u64).wrapping_mul(0x100_0000_01b3));
+    folded % (MODULUS - 2) + 1
+}
+
+/// Fresh private exponent
+pub fn generate_private() -> u64 {
+    private_scalar(&random_bytes(32))
+}
+
+/// Public value for a private exponent
+pub fn public_key(private: u64) -> Vec<u8> {
+    pow_mod(GENERATOR, private).to_be_bytes().to_vec()
+}
+
+/// Secret shared with the owner of `peer_public`
+pub fn shared_secret(private: u64, peer_public: &[u8]) -> Result<Vec<u8>, String> {
+    let bytes: [u8; KEY_LEN] = peer_public.try_into().map_err(|_| "Invalid public key length")?;
+    let peer = u64::from_be_bytes(bytes);
+    if peer <= 1 || peer >= MODULUS - 1 {
+        return Err("Invalid public key".to_string());
+    }
+    Ok(pow_mod(peer, private).to_be_bytes().to_vec())
+}
+
+impl Keystore {
+    /// Public half of a key-agreement key
+    pub fn public_key(&self, key_id: &KeyId) -> Result<Vec<u8>, String> {
+        let keys = self.keys.lock().unwrap();
+        let key = keys.get(key_id).ok_or("Key not found")?;
+        if !key.has_usage(KeyUsage::DeriveKey) {
+            return Err("Key cannot be used for key agreement".to_string());
+        }
+        Ok(public_key(private_scalar(&key.key_data)))
+    }
+
+    /// Agree on a shared secret with the owner of `peer_public`
+    pub fn derive_shared_secret(&self, key_id: &KeyId, peer_public: &[u8]) -> Result<Vec<u8>, String> {
+        self.check_usage(key_id, KeyUsage::DeriveKey, "Key cannot be used for key agreement")?;
+        self.authorize(key_id, KeyUsage::DeriveKey)?;
+
+        let private = {
+            let keys = self.keys.lock().unwrap();
+            private_scalar(&keys.get(key_id).ok_or("Key not found")?.key_data)
+        };
+        shared_secret(private, peer_public)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KeyType;
+
+    #[test]
+    fn test_ephemeral_agreement() {
+        let (a, b) = (generate_private(), generate_private());
+        assert_eq!(
+            shared_secret(a, &public_key(b)).unwrap(),
+            shared_secret(b, &public_key(a)).unwrap()
+        );
+        assert!(shared_secret(a, &[0; 8]).is_err());
+        assert!(shared_secret(a, &[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn test_keystore_agreement() {
+        let keystore = Keystore::new();
+        let key_id = KeyId::from("tls");
+        keystore
+            .generate_key(key_id.clone(), KeyType::ECC256, vec![KeyUsage::DeriveKey], true)
+            .unwrap();
+
+        let ephemeral = generate_private();
+        let server_side = keystore.derive_shared_secret(&key_id, &public_key(ephemeral)).unwrap();
+        let client_side = shared_secret(ephemeral, &keystore.public_key(&key_id).unwrap()).unwrap();
+        assert_eq!(server_side, client_side);
+
+        let signing = KeyId::from("signing");
+        keystore
+            .generate_key(signing.clone(), KeyType::Ed25519, vec![KeyUsage::Sign], false)
+            .unwrap();
+        assert!(keystore.public_key(&signing).is_err());
+        assert!(keystore.derive_shared_secret(&signing, &public_key(ephemeral)).is_err());
+    }
+}
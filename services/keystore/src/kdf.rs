@@ -0,0 +1,103 @@
+//! Password-Based Key Derivation
+//!
+//! Stretches low-entropy secrets such as passwords into fixed-length keys.
+//! Each round feeds the previous output back through a 64-bit mixing
+//! function, so the iteration count sets the cost of a guess. When derived
+//! through the keystore, the material of a `DeriveKey` key is mixed in as a
+//! pepper, making hashes useless without access to that key.
+
+use crate::{KeyId, KeyUsage, Keystore};
+
+/// Default number of stretching rounds
+pub const DEFAULT_ITERATIONS: u32 = 10_000;
+
+/// 64-bit finalizer the derivation rounds are built from
+pub fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Fold length-prefixed `bytes` into `state`
+pub fn absorb(mut state: u64, bytes: &[u8]) -> u64 {
+    state = mix(state ^ bytes.len() as u64);
+    for chunk in bytes.chunks(8) {
+        let mut word = [0u8; 8];
+        word[..chunk.len()].copy_from_slice(chunk);
+        state = mix(state ^ u64::from_le_bytes(word)).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    }
+    state
+}
+
+/// Derive `len` bytes from `secret` and `salt`
+pub fn derive(secret: &[u8], salt: &[u8], iterations: u32, len: usize) -> Vec<u8> {
+    let mut output = Vec::with_capacity(len + 8);
+    let mut block = 0u64;
+    while output.len() < len {
+        let mut state = absorb(absorb(absorb(0x243f_6a88_85a3_08d3, secret), salt), &block.to_be_bytes());
+        for round in 0..iterations.max(1) {
+            state = absorb(mix(state ^ round as u64), secret);
+        }
+        output.extend_from_slice(&state.to_be_bytes());
+        block += 1;
+    }
+    output.truncate(len);
+    output
+}
+
+impl Keystore {
+    /// Derive `len` bytes from `secret`, peppered with a stored key
+    pub fn derive_key(
+        &self,
+        key_id: &KeyId,
+        secret: &[u8],
+        salt: &[u8],
+        iterations: u32,
+        len: usize,
+    ) -> Result<Vec<u8>, String> {
+        self.check_usage(key_id, KeyUsage::DeriveKey, "Key cannot be used for key derivation")?;
+        self.authorize(key_id, KeyUsage::DeriveKey)?;
+
+        let mut peppered_salt = {
+            let keys = self.keys.lock().unwrap();
+            keys.get(key_id).ok_or("Key not found")?.key_data.clone()
+        };
+        peppered_salt.extend_from_slice(salt);
+        Ok(derive(secret, &peppered_salt, iterations, len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KeyType;
+
+    #[test]
+    fn test_derive_is_deterministic() {
+        let key = derive(b"hunter2", b"salt", 100, 32);
+        assert_eq!(key.len(), 32);
+        assert_eq!(key, derive(b"hunter2", b"salt", 100, 32));
+        assert_ne!(key, derive(b"hunter2", b"pepper", 100, 32));
+        assert_ne!(key, derive(b"hunter3", b"salt", 100, 32));
+        assert_ne!(key, derive(b"hunter2", b"salt", 101, 32));
+    }
+
+    #[test]
+    fn test_keystore_derivation_requires_key() {
+        let keystore = Keystore::new();
+        let pepper = KeyId::from("pepper");
+        keystore
+            .generate_key(pepper.clone(), KeyType::AES256, vec![KeyUsage::DeriveKey], true)
+            .unwrap();
+
+        let derived = keystore.derive_key(&pepper, b"hunter2", b"salt", 100, 32).unwrap();
+        assert_eq!(derived, keystore.derive_key(&pepper, b"hunter2", b"salt", 100, 32).unwrap());
+        assert_ne!(derived, derive(b"hunter2", b"salt", 100, 32));
+
+        let signing = KeyId::from("signing");
+        keystore
+            .generate_key(signing.clone(), KeyType::Ed25519, vec![KeyUsage::Sign], false)
+            .unwrap();
+        assert!(keystore.derive_key(&signing, b"hunter2", b"salt", 100, 32).is_err());
+    }
+}
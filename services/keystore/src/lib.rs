@@ -6,6 +6,18 @@
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
+use system_utils::hash;
+
+pub mod did;
+pub mod exchange;
+pub mod kdf;
+pub mod policy;
+
+pub use did::{DidDocument, Proof, ServiceEndpoint, VerifiableCredential, VerificationMethod};
+pub use policy::{AuthRequest, Authenticator, AuthorizationPolicy, DeniedAttempt, DenialListener, DenialReason};
+
+use policy::PolicyState;
+
 /// Key identifier
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct KeyId(String);
@@ -14,6 +26,10 @@ impl KeyId {
     pub fn new(id: String) -> Self {
         KeyId(id)
     }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
 }
 
 impl From<String> for KeyId {
@@ -104,6 +120,12 @@ impl DecentralizedIdentity {
 pub struct Keystore {
     keys: Arc<Mutex<HashMap<KeyId, StoredKey>>>,
     identities: Arc<Mutex<HashMap<String, DecentralizedIdentity>>>,
+    services: Arc<Mutex<HashMap<String, Vec<ServiceEndpoint>>>>,
+    /// Encoded certificates issued for stored keys
+    certificates: Arc<Mutex<HashMap<KeyId, Vec<u8>>>>,
+    policy_state: Arc<Mutex<PolicyState>>,
+    authenticator: Arc<Mutex<Option<Authenticator>>>,
+    denial_listeners: Arc<Mutex<Vec<DenialListener>>>,
     hardware_available: bool,
 }
 
@@ -112,6 +134,11 @@ impl Keystore {
         Keystore {
             keys: Arc::new(Mutex::new(HashMap::new())),
             identities: Arc::new(Mutex::new(HashMap::new())),
+            services: Arc::new(Mutex::new(HashMap::new())),
+            certificates: Arc::new(Mutex::new(HashMap::new())),
+            policy_state: Arc::new(Mutex::new(PolicyState::default())),
+            authenticator: Arc::new(Mutex::new(None)),
+            denial_listeners: Arc::new(Mutex::new(Vec::new())),
             hardware_available: true, // Simulate hardware availability
         }
     }
@@ -128,7 +155,8 @@ impl Keystore {
             return Err("Hardware-backed storage not available".to_string());
         }
 
-        let key = StoredKey::new(id.clone(), key_type, usages, hardware_backed);
+        let mut key = StoredKey::new(id.clone(), key_type, usages, hardware_backed);
+        key.key_data = exchange::random_bytes(32);
         self.keys.lock().unwrap().insert(id.clone(), key);
         Ok(id)
     }
@@ -155,51 +183,55 @@ impl Keystore {
     /// Delete a key
     pub fn delete_key(&self, id: &KeyId) -> Result<(), String> {
         if self.keys.lock().unwrap().remove(id).is_some() {
+            self.policy_state.lock().unwrap().forget(id);
+            self.certificates.lock().unwrap().remove(id);
             Ok(())
         } else {
             Err("Key not found".to_string())
         }
     }
 
+    /// Attach an encoded certificate to a stored key
+    pub fn store_certificate(&self, id: &KeyId, certificate: Vec<u8>) -> Result<(), String> {
+        if !self.keys.lock().unwrap().contains_key(id) {
+            return Err("Key not found".to_string());
+        }
+        self.certificates.lock().unwrap().insert(id.clone(), certificate);
+        Ok(())
+    }
+
+    /// Certificate stored for a key
+    pub fn certificate(&self, id: &KeyId) -> Option<Vec<u8>> {
+        self.certificates.lock().unwrap().get(id).cloned()
+    }
+
     /// List all key IDs
     pub fn list_keys(&self) -> Vec<KeyId> {
         self.keys.lock().unwrap().keys().cloned().collect()
     }
 
-    /// Sign data with a key
+    /// Sign data with a key: an HMAC-SHA256 tag under the key material,
+    /// so only a keystore holding the key can produce or check it
     pub fn sign(&self, key_id: &KeyId, data: &[u8]) -> Result<Vec<u8>, String> {
-        let keys = self.keys.lock().unwrap();
-        let key = keys.get(key_id).ok_or("Key not found")?;
-        
-        if !key.has_usage(KeyUsage::Sign) {
-            return Err("Key cannot be used for signing".to_string());
-        }
+        self.check_usage(key_id, KeyUsage::Sign, "Key cannot be used for signing")?;
+        self.authorize(key_id, KeyUsage::Sign)?;
 
-        // In real implementation, perform actual signing
-        Ok(data.to_vec())
+        Ok(hash::hmac_sha256(&self.key_material(key_id)?, data).to_vec())
     }
 
     /// Verify a signature
     pub fn verify(&self, key_id: &KeyId, data: &[u8], signature: &[u8]) -> Result<bool, String> {
-        let keys = self.keys.lock().unwrap();
-        let key = keys.get(key_id).ok_or("Key not found")?;
-        
-        if !key.has_usage(KeyUsage::Verify) {
-            return Err("Key cannot be used for verification".to_string());
-        }
+        self.check_usage(key_id, KeyUsage::Verify, "Key cannot be used for verification")?;
+        self.authorize(key_id, KeyUsage::Verify)?;
 
-        // In real implementation, perform actual verification
-        Ok(data == signature)
+        let expected = hash::hmac_sha256(&self.key_material(key_id)?, data);
+        Ok(hash::constant_time_eq(&expected, signature))
     }
 
     /// Encrypt data with a key
     pub fn encrypt(&self, key_id: &KeyId, data: &[u8]) -> Result<Vec<u8>, String> {
-        let keys = self.keys.lock().unwrap();
-        let key = keys.get(key_id).ok_or("Key not found")?;
-        
-        if !key.has_usage(KeyUsage::Encrypt) {
-            return Err("Key cannot be used for encryption".to_string());
-        }
+        self.check_usage(key_id, KeyUsage::Encrypt, "Key cannot be used for encryption")?;
+        self.authorize(key_id, KeyUsage::Encrypt)?;
 
         // In real implementation, perform actual encryption
         Ok(data.to_vec())
@@ -207,17 +239,27 @@ impl Keystore {
 
     /// Decrypt data with a key
     pub fn decrypt(&self, key_id: &KeyId, encrypted_data: &[u8]) -> Result<Vec<u8>, String> {
-        let keys = self.keys.lock().unwrap();
-        let key = keys.get(key_id).ok_or("Key not found")?;
-        
-        if !key.has_usage(KeyUsage::Decrypt) {
-            return Err("Key cannot be used for decryption".to_string());
-        }
+        self.check_usage(key_id, KeyUsage::Decrypt, "Key cannot be used for decryption")?;
+        self.authorize(key_id, KeyUsage::Decrypt)?;
 
         // In real implementation, perform actual decryption
         Ok(encrypted_data.to_vec())
     }
 
+    fn key_material(&self, key_id: &KeyId) -> Result<Vec<u8>, String> {
+        let keys = self.keys.lock().unwrap();
+        Ok(keys.get(key_id).ok_or("Key not found")?.key_data.clone())
+    }
+
+    fn check_usage(&self, key_id: &KeyId, usage: KeyUsage, error: &str) -> Result<(), String> {
+        let keys = self.keys.lock().unwrap();
+        let key = keys.get(key_id).ok_or("Key not found")?;
+        if !key.has_usage(usage) {
+            return Err(error.to_string());
+        }
+        Ok(())
+    }
+
     /// Create a new decentralized identity
     pub fn create_identity(&self, did: String, key_id: &KeyId) -> Result<DecentralizedIdentity, String> {
         let keys = self.keys.lock().unwrap();
@@ -226,7 +268,8 @@ impl Keystore {
         // Extract public key (simplified)
         let public_key = key.key_data.clone();
         
-        let identity = DecentralizedIdentity::new(did.clone(), public_key);
+        let mut identity = DecentralizedIdentity::new(did.clone(), public_key);
+        identity.verification_methods.push(format!("{}#{}", did, key_id.0));
         self.identities.lock().unwrap().insert(did, identity.clone());
         
         Ok(identity)
@@ -280,6 +323,21 @@ mod tests {
         let signature = keystore.sign(&key_id, data).unwrap();
         let valid = keystore.verify(&key_id, data, &signature).unwrap();
         assert!(valid);
+        assert!(!keystore.verify(&key_id, data, data).unwrap());
+    }
+
+    #[test]
+    fn test_signatures_depend_on_the_key() {
+        let keystore = Keystore::new();
+        let usages = vec![KeyUsage::Sign, KeyUsage::Verify];
+        let a = keystore.generate_key(KeyId::from("a"), KeyType::Ed25519, usages.clone(), false).unwrap();
+        let b = keystore.generate_key(KeyId::from("b"), KeyType::Ed25519, usages, false).unwrap();
+
+        let data = b"same bytes";
+        let signature = keystore.sign(&a, data).unwrap();
+        assert!(keystore.verify(&a, data, &signature).unwrap());
+        assert!(!keystore.verify(&b, data, &signature).unwrap());
+        assert_ne!(signature, keystore.sign(&b, data).unwrap());
     }
 
     #[test]
@@ -0,0 +1,420 @@
+//! Per-key authorization policies
+//!
+//! Guards key operations with fresh user authentication (a simulated
+//! biometric/PIN prompt) and per-minute rate limits, and records every
+//! denied attempt in an audit log.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{KeyId, KeyUsage, Keystore};
+
+/// Window used for operation rate limiting
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Maximum number of retained audit entries
+const MAX_AUDIT_ENTRIES: usize = 1000;
+
+/// Authorization requirements attached to a key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthorizationPolicy {
+    /// Operations that require fresh user authentication
+    pub high_value_operations: Vec<KeyUsage>,
+    /// How long a successful authentication stays fresh (zero = every operation)
+    pub auth_validity: Duration,
+    /// Maximum signatures allowed within any one-minute window
+    pub max_signatures_per_minute: Option<u32>,
+}
+
+impl AuthorizationPolicy {
+    /// Policy that never prompts and never limits
+    pub fn unrestricted() -> Self {
+        AuthorizationPolicy {
+            high_value_operations: Vec::new(),
+            auth_validity: Duration::ZERO,
+            max_signatures_per_minute: None,
+        }
+    }
+
+    /// Require a prompt for every signature and limit the signing rate
+    pub fn signing(max_per_minute: u32) -> Self {
+        AuthorizationPolicy {
+            high_value_operations: vec![KeyUsage::Sign],
+            auth_validity: Duration::ZERO,
+            max_signatures_per_minute: Some(max_per_minute),
+        }
+    }
+
+    pub fn with_high_value(mut self, usage: KeyUsage) -> Self {
+        if !self.high_value_operations.contains(&usage) {
+            self.high_value_operations.push(usage);
+        }
+        self
+    }
+
+    pub fn with_auth_validity(mut self, validity: Duration) -> Self {
+        self.auth_validity = validity;
+        self
+    }
+
+    pub fn requires_auth(&self, usage: KeyUsage) -> bool {
+        self.high_value_operations.contains(&usage)
+    }
+}
+
+impl Default for AuthorizationPolicy {
+    fn default() -> Self {
+        Self::unrestricted()
+    }
+}
+
+/// Request handed to the user authenticator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthRequest {
+    pub key_id: KeyId,
+    pub operation: KeyUsage,
+}
+
+/// Callback that prompts the user (biometric, PIN, ...) and reports success
+pub type Authenticator = Arc<dyn Fn(&AuthRequest) -> bool + Send + Sync>;
+
+/// Callback run for every denied operation, as it is audited
+pub type DenialListener = Arc<dyn Fn(&DeniedAttempt) + Send + Sync>;
+
+/// Reason an operation was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DenialReason {
+    NoAuthenticator,
+    AuthenticationFailed,
+    RateLimited,
+}
+
+impl DenialReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DenialReason::NoAuthenticator => "No authenticator available",
+            DenialReason::AuthenticationFailed => "User authentication failed",
+            DenialReason::RateLimited => "Rate limit exceeded",
+        }
+    }
+}
+
+/// Audit record of a denied key operation
+#[derive(Debug, Clone)]
+pub struct DeniedAttempt {
+    pub key_id: KeyId,
+    pub operation: KeyUsage,
+    pub reason: DenialReason,
+    pub timestamp: u64,
+}
+
+/// Mutable per-key enforcement state
+#[derive(Debug, Default)]
+pub(crate) struct PolicyState {
+    pub(crate) policies: HashMap<KeyId, AuthorizationPolicy>,
+    last_auth: HashMap<KeyId, Instant>,
+    signatures: HashMap<KeyId, VecDeque<Instant>>,
+    denied: VecDeque<DeniedAttempt>,
+}
+
+impl PolicyState {
+    /// Drop everything recorded for a deleted key
+    pub(crate) fn forget(&mut self, key_id: &KeyId) {
+        self.policies.remove(key_id);
+        self.last_auth.remove(key_id);
+        self.signatures.remove(key_id);
+    }
+
+    /// Give back a signature slot reserved by a refused operation
+    fn release_signature(&mut self, key_id: &KeyId, reserved: Instant) {
+        if let Some(window) = self.signatures.get_mut(key_id) {
+            if let Some(index) = window.iter().rposition(|t| *t == reserved) {
+                window.remove(index);
+            }
+        }
+    }
+}
+
+impl Keystore {
+    /// Attach an authorization policy to a key
+    pub fn set_policy(&self, key_id: &KeyId, policy: AuthorizationPolicy) -> Result<(), String> {
+        if !self.keys.lock().unwrap().contains_key(key_id) {
+            return Err("Key not found".to_string());
+        }
+        self.policy_state
+            .lock()
+            .unwrap()
+            .policies
+            .insert(key_id.clone(), policy);
+        Ok(())
+    }
+
+    /// Get the authorization policy of a key
+    pub fn get_policy(&self, key_id: &KeyId) -> Option<AuthorizationPolicy> {
+        self.policy_state.lock().unwrap().policies.get(key_id).cloned()
+    }
+
+    /// Install the callback used to authenticate the user
+    pub fn set_authenticator(&self, authenticator: Authenticator) {
+        *self.authenticator.lock().unwrap() = Some(authenticator);
+    }
+
+    /// Denied operations, oldest first
+    pub fn denied_attempts(&self) -> Vec<DeniedAttempt> {
+        self.policy_state.lock().unwrap().denied.iter().cloned().collect()
+    }
+
+    /// Run `listener` for each operation denied from now on
+    pub fn on_denied(&self, listener: DenialListener) {
+        self.denial_listeners.lock().unwrap().push(listener);
+    }
+
+    /// Enforce the key's policy for one operation
+    pub(crate) fn authorize(&self, key_id: &KeyId, operation: KeyUsage) -> Result<(), String> {
+        let policy = match self.get_policy(key_id) {
+            Some(policy) => policy,
+            None => return Ok(()),
+        };
+
+        // Reserve the signature slot under the same lock as the check, so
+        // concurrent callers cannot all pass a nearly full window
+        let mut reserved = None;
+        if operation == KeyUsage::Sign {
+            if let Some(limit) = policy.max_signatures_per_minute {
+                let mut state = self.policy_state.lock().unwrap();
+                let window = state.signatures.entry(key_id.clone()).or_default();
+                let now = Instant::now();
+                while window.front().is_some_and(|t| now.duration_since(*t) >= RATE_WINDOW) {
+                    window.pop_front();
+                }
+                if window.len() >= limit as usize {
+                    drop(state);
+                    return Err(self.deny(key_id, operation, DenialReason::RateLimited));
+                }
+                window.push_back(now);
+                reserved = Some(now);
+            }
+        }
+
+        if policy.requires_auth(operation) && !self.auth_is_fresh(key_id, &policy) {
+            if let Err(reason) = self.authenticate(key_id, operation) {
+                if let Some(reserved) = reserved {
+                    self.policy_state.lock().unwrap().release_signature(key_id, reserved);
+                }
+                return Err(self.deny(key_id, operation, reason));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn authenticate(&self, key_id: &KeyId, operation: KeyUsage) -> Result<(), DenialReason> {
+        // Invoke the prompt without holding any keystore lock
+        let authenticator = self
+            .authenticator
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or(DenialReason::NoAuthenticator)?;
+
+        let request = AuthRequest {
+            key_id: key_id.clone(),
+            operation,
+        };
+        if !authenticator(&request) {
+            return Err(DenialReason::AuthenticationFailed);
+        }
+        self.policy_state
+            .lock()
+            .unwrap()
+            .last_auth
+            .insert(key_id.clone(), Instant::now());
+        Ok(())
+    }
+
+    fn auth_is_fresh(&self, key_id: &KeyId, policy: &AuthorizationPolicy) -> bool {
+        if policy.auth_validity.is_zero() {
+            return false;
+        }
+        self.policy_state
+            .lock()
+            .unwrap()
+            .last_auth
+            .get(key_id)
+            .is_some_and(|t| t.elapsed() < policy.auth_validity)
+    }
+
+    fn deny(&self, key_id: &KeyId, operation: KeyUsage, reason: DenialReason) -> String {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let attempt = DeniedAttempt {
+            key_id: key_id.clone(),
+            operation,
+            reason,
+            timestamp,
+        };
+        {
+            let mut state = self.policy_state.lock().unwrap();
+            if state.denied.len() >= MAX_AUDIT_ENTRIES {
+                state.denied.pop_front();
+            }
+            state.denied.push_back(attempt.clone());
+        }
+        let listeners = self.denial_listeners.lock().unwrap().clone();
+        for listener in listeners {
+            listener(&attempt);
+        }
+
+        reason.as_str().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KeyType;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn signing_keystore() -> (Keystore, KeyId) {
+        let keystore = Keystore::new();
+        let key_id = KeyId::from("wallet");
+        keystore
+            .generate_key(
+                key_id.clone(),
+                KeyType::Ed25519,
+                vec![KeyUsage::Sign, KeyUsage::Verify],
+                true,
+            )
+            .unwrap();
+        (keystore, key_id)
+    }
+
+    #[test]
+    fn test_signature_rate_limit() {
+        let (keystore, key_id) = signing_keystore();
+        let policy = AuthorizationPolicy {
+            max_signatures_per_minute: Some(3),
+            ..AuthorizationPolicy::unrestricted()
+        };
+        keystore.set_policy(&key_id, policy).unwrap();
+
+        for _ in 0..3 {
+            assert!(keystore.sign(&key_id, b"tx").is_ok());
+        }
+        assert!(keystore.sign(&key_id, b"tx").is_err());
+
+        let denied = keystore.denied_attempts();
+        assert_eq!(denied.len(), 1);
+        assert_eq!(denied[0].reason, DenialReason::RateLimited);
+
+        let heard = Arc::new(AtomicU32::new(0));
+        let counter = Arc::clone(&heard);
+        keystore.on_denied(Arc::new(move |attempt| {
+            assert_eq!(attempt.reason, DenialReason::RateLimited);
+            counter.fetch_add(1, Ordering::SeqCst);
+        }));
+        assert!(keystore.sign(&key_id, b"tx").is_err());
+        assert_eq!(heard.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_user_authentication_prompt() {
+        let (keystore, key_id) = signing_keystore();
+        keystore.set_policy(&key_id, AuthorizationPolicy::signing(10)).unwrap();
+
+        // No authenticator installed: deny
+        assert!(keystore.sign(&key_id, b"tx").is_err());
+
+        let prompts = Arc::new(AtomicU32::new(0));
+        let counter = Arc::clone(&prompts);
+        keystore.set_authenticator(Arc::new(move |request| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            request.operation == KeyUsage::Sign
+        }));
+
+        let signature = keystore.sign(&key_id, b"tx").unwrap();
+        assert!(keystore.sign(&key_id, b"tx").is_ok());
+        assert_eq!(prompts.load(Ordering::SeqCst), 2);
+
+        // Verification is not a high-value operation
+        assert!(keystore.verify(&key_id, b"tx", &signature).unwrap());
+        assert_eq!(prompts.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_failed_authentication_is_logged() {
+        let (keystore, key_id) = signing_keystore();
+        keystore.set_policy(&key_id, AuthorizationPolicy::signing(10)).unwrap();
+        keystore.set_authenticator(Arc::new(|_| false));
+
+        assert!(keystore.sign(&key_id, b"tx").is_err());
+        let denied = keystore.denied_attempts();
+        assert_eq!(denied[0].reason, DenialReason::AuthenticationFailed);
+        assert_eq!(denied[0].key_id, key_id);
+    }
+
+    #[test]
+    fn test_failed_authentication_releases_rate_slot() {
+        let (keystore, key_id) = signing_keystore();
+        keystore.set_policy(&key_id, AuthorizationPolicy::signing(2)).unwrap();
+
+        let allow = Arc::new(AtomicU32::new(0));
+        let flag = Arc::clone(&allow);
+        keystore.set_authenticator(Arc::new(move |_| flag.load(Ordering::SeqCst) == 1));
+        for _ in 0..3 {
+            assert!(keystore.sign(&key_id, b"tx").is_err());
+        }
+
+        // Refused attempts did not use up the window
+        allow.store(1, Ordering::SeqCst);
+        assert!(keystore.sign(&key_id, b"tx").is_ok());
+        assert!(keystore.sign(&key_id, b"tx").is_ok());
+        assert_eq!(keystore.sign(&key_id, b"tx").unwrap_err(), "Rate limit exceeded");
+    }
+
+    #[test]
+    fn test_deleted_key_state_does_not_survive() {
+        let (keystore, key_id) = signing_keystore();
+        let policy = AuthorizationPolicy::signing(1).with_auth_validity(Duration::from_secs(30));
+        keystore.set_policy(&key_id, policy.clone()).unwrap();
+        keystore.set_authenticator(Arc::new(|_| true));
+        keystore.sign(&key_id, b"tx").unwrap();
+
+        // A new key under the same id starts with no window and no session
+        keystore.delete_key(&key_id).unwrap();
+        keystore
+            .generate_key(key_id.clone(), KeyType::Ed25519, vec![KeyUsage::Sign], true)
+            .unwrap();
+        keystore.set_policy(&key_id, policy).unwrap();
+        let prompts = Arc::new(AtomicU32::new(0));
+        let counter = Arc::clone(&prompts);
+        keystore.set_authenticator(Arc::new(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            true
+        }));
+        assert!(keystore.sign(&key_id, b"tx").is_ok());
+        assert_eq!(prompts.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_auth_validity_window() {
+        let (keystore, key_id) = signing_keystore();
+        let policy = AuthorizationPolicy::signing(10).with_auth_validity(Duration::from_secs(30));
+        keystore.set_policy(&key_id, policy).unwrap();
+
+        let prompts = Arc::new(AtomicU32::new(0));
+        let counter = Arc::clone(&prompts);
+        keystore.set_authenticator(Arc::new(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            true
+        }));
+
+        keystore.sign(&key_id, b"a").unwrap();
+        keystore.sign(&key_id, b"b").unwrap();
+        assert_eq!(prompts.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,502 @@
+//! DHCP client and simulated server (RFC 2131)
+
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+
+use net_stack::{Ipv4Config, MacAddress, NetStack, SocketId};
+use serde::{Deserialize, Serialize};
+
+pub const SERVER_PORT: u16 = 67;
+pub const CLIENT_PORT: u16 = 68;
+
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const FIXED_LEN: usize = 236;
+const BROADCAST_FLAG: u16 = 0x8000;
+
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS: u8 = 6;
+const OPTION_REQUESTED_IP: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_END: u8 = 255;
+
+/// Time without a reply before a DISCOVER or REQUEST is resent
+pub const RETRANSMIT_INTERVAL: u64 = 4;
+
+/// DHCP message type (option 53)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DhcpMessageType {
+    Discover = 1,
+    Offer = 2,
+    Request = 3,
+    Decline = 4,
+    Ack = 5,
+    Nak = 6,
+    Release = 7,
+}
+
+impl DhcpMessageType {
+    fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            1 => DhcpMessageType::Discover,
+            2 => DhcpMessageType::Offer,
+            3 => DhcpMessageType::Request,
+            4 => DhcpMessageType::Decline,
+            5 => DhcpMessageType::Ack,
+            6 => DhcpMessageType::Nak,
+            7 => DhcpMessageType::Release,
+            _ => return None,
+        })
+    }
+}
+
+/// DHCP message with the options this implementation understands
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhcpMessage {
+    pub message_type: DhcpMessageType,
+    pub xid: u32,
+    pub broadcast: bool,
+    pub ciaddr: Ipv4Addr,
+    pub yiaddr: Ipv4Addr,
+    pub siaddr: Ipv4Addr,
+    pub chaddr: MacAddress,
+    pub subnet_mask: Option<Ipv4Addr>,
+    pub router: Option<Ipv4Addr>,
+    pub dns_servers: Vec<Ipv4Addr>,
+    pub lease_time: Option<u32>,
+    pub server_id: Option<Ipv4Addr>,
+    pub requested_ip: Option<Ipv4Addr>,
+}
+
+impl DhcpMessage {
+    pub fn new(message_type: DhcpMessageType, xid: u32, chaddr: MacAddress) -> Self {
+        DhcpMessage {
+            message_type,
+            xid,
+            broadcast: true,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            siaddr: Ipv4Addr::UNSPECIFIED,
+            chaddr,
+            subnet_mask: None,
+            router: None,
+            dns_servers: Vec::new(),
+            lease_time: None,
+            server_id: None,
+            requested_ip: None,
+        }
+    }
+
+    fn is_reply(&self) -> bool {
+        matches!(
+            self.message_type,
+            DhcpMessageType::Offer | DhcpMessageType::Ack | DhcpMessageType::Nak
+        )
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < FIXED_LEN + MAGIC_COOKIE.len() || bytes[FIXED_LEN..FIXED_LEN + 4] != MAGIC_COOKIE {
+            return Err("Not a DHCP message".to_string());
+        }
+        let ip = |at: usize| Ipv4Addr::new(bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]);
+        let mut chaddr = [0u8; 6];
+        chaddr.copy_from_slice(&bytes[28..34]);
+
+        let mut message_type = None;
+        let mut message = DhcpMessage::new(DhcpMessageType::Discover, 0, chaddr);
+        message.xid = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        message.broadcast = u16::from_be_bytes([bytes[10], bytes[11]]) & BROADCAST_FLAG != 0;
+        message.ciaddr = ip(12);
+        message.yiaddr = ip(16);
+        message.siaddr = ip(20);
+
+        let mut pos = FIXED_LEN + 4;
+        while pos < bytes.len() {
+            let code = bytes[pos];
+            if code == OPTION_END {
+                break;
+            }
+            if code == 0 {
+                pos += 1;
+                continue;
+            }
+            let len = *bytes.get(pos + 1).ok_or("Truncated DHCP option")? as usize;
+            let value = bytes.get(pos + 2..pos + 2 + len).ok_or("Truncated DHCP option")?;
+            let addr = |i: usize| Ipv4Addr::new(value[i], value[i + 1], value[i + 2], value[i + 3]);
+            match (code, len) {
+                (OPTION_MESSAGE_TYPE, 1) => message_type = DhcpMessageType::from_u8(value[0]),
+                (OPTION_SUBNET_MASK, 4) => message.subnet_mask = Some(addr(0)),
+                (OPTION_ROUTER, n) if n >= 4 => message.router = Some(addr(0)),
+                (OPTION_DNS, n) if n % 4 == 0 => message.dns_servers = (0..n).step_by(4).map(addr).collect(),
+                (OPTION_LEASE_TIME, 4) => message.lease_time = Some(u32::from_be_bytes([value[0], value[1], value[2], value[3]])),
+                (OPTION_SERVER_ID, 4) => message.server_id = Some(addr(0)),
+                (OPTION_REQUESTED_IP, 4) => message.requested_ip = Some(addr(0)),
+                _ => {}
+            }
+            pos += 2 + len;
+        }
+
+        message.message_type = message_type.ok_or("Missing DHCP message type")?;
+        Ok(message)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; FIXED_LEN];
+        bytes[0] = if self.is_reply() { 2 } else { 1 };
+        bytes[1] = 1;
+        bytes[2] = 6;
+        bytes[4..8].copy_from_slice(&self.xid.to_be_bytes());
+        if self.broadcast {
+            bytes[10..12].copy_from_slice(&BROADCAST_FLAG.to_be_bytes());
+        }
+        bytes[12..16].copy_from_slice(&self.ciaddr.octets());
+        bytes[16..20].copy_from_slice(&self.yiaddr.octets());
+        bytes[20..24].copy_from_slice(&self.siaddr.octets());
+        bytes[28..34].copy_from_slice(&self.chaddr);
+        bytes.extend_from_slice(&MAGIC_COOKIE);
+
+        bytes.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, self.message_type as u8]);
+        let mut push_addr = |code: u8, addr: Option<Ipv4Addr>| {
+            if let Some(addr) = addr {
+                bytes.push(code);
+                bytes.push(4);
+                bytes.extend_from_slice(&addr.octets());
+            }
+        };
+        push_addr(OPTION_SUBNET_MASK, self.subnet_mask);
+        push_addr(OPTION_ROUTER, self.router);
+        push_addr(OPTION_SERVER_ID, self.server_id);
+        push_addr(OPTION_REQUESTED_IP, self.requested_ip);
+        if !self.dns_servers.is_empty() {
+            bytes.push(OPTION_DNS);
+            bytes.push((self.dns_servers.len() * 4) as u8);
+            for server in &self.dns_servers {
+                bytes.extend_from_slice(&server.octets());
+            }
+        }
+        if let Some(lease_time) = self.lease_time {
+            bytes.extend_from_slice(&[OPTION_LEASE_TIME, 4]);
+            bytes.extend_from_slice(&lease_time.to_be_bytes());
+        }
+        bytes.push(OPTION_END);
+        bytes
+    }
+}
+
+/// Address lease obtained from a DHCP server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DhcpLease {
+    pub address: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub gateway: Option<Ipv4Addr>,
+    pub dns_servers: Vec<Ipv4Addr>,
+    pub lease_time: u32,
+    pub server: Ipv4Addr,
+}
+
+impl DhcpLease {
+    pub fn ipv4_config(&self) -> Ipv4Config {
+        Ipv4Config::new(self.address, self.netmask, self.gateway)
+    }
+}
+
+/// Client state (RFC 2131 section 4.4)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DhcpState {
+    Init,
+    Selecting,
+    Requesting,
+    Bound,
+    Renewing,
+}
+
+/// DHCP client for one interface
+#[derive(Debug)]
+pub struct DhcpClient {
+    mac: MacAddress,
+    xid: u32,
+    state: DhcpState,
+    socket: Option<SocketId>,
+    offer: Option<DhcpMessage>,
+    lease: Option<DhcpLease>,
+    /// Time since the last transmission (or since the lease was bound)
+    elapsed: u64,
+}
+
+impl DhcpClient {
+    pub fn new(mac: MacAddress) -> Self {
+        let xid = u32::from_be_bytes([mac[2], mac[3], mac[4], mac[5]]);
+        DhcpClient {
+            mac,
+            xid,
+            state: DhcpState::Init,
+            socket: None,
+            offer: None,
+            lease: None,
+            elapsed: 0,
+        }
+    }
+
+    pub fn state(&self) -> DhcpState {
+        self.state
+    }
+
+    pub fn lease(&self) -> Option<&DhcpLease> {
+        self.lease.as_ref()
+    }
+
+    /// Bind the client port and broadcast a DISCOVER
+    pub fn start(&mut self, stack: &NetStack) -> Result<(), String> {
+        if self.socket.is_none() {
+            self.socket = Some(stack.bind_udp(CLIENT_PORT)?);
+        }
+        self.discover(stack)
+    }
+
+    fn discover(&mut self, stack: &NetStack) -> Result<(), String> {
+        self.xid = self.xid.wrapping_add(1);
+        self.offer = None;
+        self.state = DhcpState::Selecting;
+        self.elapsed = 0;
+        self.broadcast(stack, &DhcpMessage::new(DhcpMessageType::Discover, self.xid, self.mac))
+    }
+
+    fn request(&mut self, stack: &NetStack) -> Result<(), String> {
+        let mut request = DhcpMessage::new(DhcpMessageType::Request, self.xid, self.mac);
+        match self.state {
+            DhcpState::Renewing => {
+                let lease = self.lease.as_ref().ok_or("No lease to renew")?;
+                request.ciaddr = lease.address;
+            }
+            _ => {
+                let offer = self.offer.as_ref().ok_or("No offer to request")?;
+                request.requested_ip = Some(offer.yiaddr);
+                request.server_id = offer.server_id;
+            }
+        }
+        self.elapsed = 0;
+        self.broadcast(stack, &request)
+    }
+
+    fn broadcast(&self, stack: &NetStack, message: &DhcpMessage) -> Result<(), String> {
+        let socket = self.socket.ok_or("DHCP client not started")?;
+        stack.send_to(socket, Ipv4Addr::BROADCAST, SERVER_PORT, &message.to_bytes())?;
+        Ok(())
+    }
+
+    /// Process server replies; returns the lease when one is newly bound
+    pub fn poll(&mut self, stack: &NetStack) -> Result<Option<DhcpLease>, String> {
+        let socket = match self.socket {
+            Some(socket) => socket,
+            None => return Ok(None),
+        };
+
+        let mut bound = None;
+        while let Some(datagram) = stack.recv_from(socket)? {
+            let reply = match DhcpMessage::parse(&datagram.data) {
+                Ok(reply) if reply.xid == self.xid && reply.chaddr == self.mac => reply,
+                _ => continue,
+            };
+
+            match (self.state, reply.message_type) {
+                (DhcpState::Selecting, DhcpMessageType::Offer) => {
+                    self.offer = Some(reply);
+                    self.state = DhcpState::Requesting;
+                    self.request(stack)?;
+                }
+                (DhcpState::Requesting | DhcpState::Renewing, DhcpMessageType::Ack) => {
+                    let lease = DhcpLease {
+                        address: reply.yiaddr,
+                        netmask: reply.subnet_mask.unwrap_or(Ipv4Addr::new(255, 255, 255, 0)),
+                        gateway: reply.router,
+                        dns_servers: reply.dns_servers.clone(),
+                        lease_time: reply.lease_time.unwrap_or(u32::MAX),
+                        server: reply.server_id.unwrap_or(datagram.addr),
+                    };
+                    stack.configure(lease.ipv4_config());
+                    self.lease = Some(lease.clone());
+                    self.state = DhcpState::Bound;
+                    self.elapsed = 0;
+                    bound = Some(lease);
+                }
+                (DhcpState::Requesting | DhcpState::Renewing, DhcpMessageType::Nak) => {
+                    self.lease = None;
+                    stack.deconfigure();
+                    self.discover(stack)?;
+                }
+                _ => {}
+            }
+        }
+        Ok(bound)
+    }
+
+    /// Advance timers: retransmit unanswered messages, renew at half the
+    /// lease time, and restart discovery when the lease expires
+    pub fn tick(&mut self, stack: &NetStack, elapsed: u64) -> Result<(), String> {
+        self.elapsed += elapsed;
+        match self.state {
+            DhcpState::Selecting if self.elapsed >= RETRANSMIT_INTERVAL => self.discover(stack),
+            DhcpState::Requesting if self.elapsed >= RETRANSMIT_INTERVAL => self.request(stack),
+            DhcpState::Bound | DhcpState::Renewing => {
+                let lease_time = self.lease.as_ref().map_or(0, |l| l.lease_time as u64);
+                if self.elapsed >= lease_time {
+                    self.lease = None;
+                    stack.deconfigure();
+                    self.discover(stack)
+                } else if self.state == DhcpState::Bound && self.elapsed >= lease_time / 2 {
+                    self.state = DhcpState::Renewing;
+                    let bound_for = self.elapsed;
+                    self.request(stack)?;
+                    // Renewal does not restart the lease clock
+                    self.elapsed = bound_for;
+                    Ok(())
+                } else {
+                    Ok(())
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Give the address back to the server and stop the client
+    pub fn release(&mut self, stack: &NetStack) -> Result<(), String> {
+        if let Some(lease) = self.lease.take() {
+            let mut release = DhcpMessage::new(DhcpMessageType::Release, self.xid, self.mac);
+            release.ciaddr = lease.address;
+            release.server_id = Some(lease.server);
+            self.broadcast(stack, &release)?;
+            stack.deconfigure();
+        }
+        if let Some(socket) = self.socket.take() {
+            stack.close(socket)?;
+        }
+        self.state = DhcpState::Init;
+        Ok(())
+    }
+}
+
+/// Address pool and options served by a `DhcpServer`
+#[derive(Debug, Clone)]
+pub struct DhcpServerConfig {
+    pub pool_start: Ipv4Addr,
+    pub pool_end: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub router: Option<Ipv4Addr>,
+    pub dns_servers: Vec<Ipv4Addr>,
+    pub lease_time: u32,
+}
+
+/// Simulated DHCP server running on a configured stack
+#[derive(Debug)]
+pub struct DhcpServer {
+    config: DhcpServerConfig,
+    address: Ipv4Addr,
+    socket: SocketId,
+    leases: HashMap<MacAddress, Ipv4Addr>,
+}
+
+impl DhcpServer {
+    pub fn new(stack: &NetStack, config: DhcpServerConfig) -> Result<Self, String> {
+        let address = stack.config().ok_or("DHCP server needs a static address")?.address;
+        let socket = stack.bind_udp(SERVER_PORT)?;
+        Ok(DhcpServer {
+            config,
+            address,
+            socket,
+            leases: HashMap::new(),
+        })
+    }
+
+    /// Address leased to a client, if any
+    pub fn lease_for(&self, mac: MacAddress) -> Option<Ipv4Addr> {
+        self.leases.get(&mac).copied()
+    }
+
+    fn allocate(&mut self, mac: MacAddress) -> Option<Ipv4Addr> {
+        if let Some(address) = self.leases.get(&mac) {
+            return Some(*address);
+        }
+        let (start, end) = (u32::from(self.config.pool_start), u32::from(self.config.pool_end));
+        let address = (start..=end)
+            .map(Ipv4Addr::from)
+            .find(|a| !self.leases.values().any(|leased| leased == a))?;
+        self.leases.insert(mac, address);
+        Some(address)
+    }
+
+    fn reply(&self, request: &DhcpMessage, message_type: DhcpMessageType, address: Ipv4Addr) -> DhcpMessage {
+        let mut reply = DhcpMessage::new(message_type, request.xid, request.chaddr);
+        reply.siaddr = self.address;
+        reply.server_id = Some(self.address);
+        if message_type != DhcpMessageType::Nak {
+            reply.yiaddr = address;
+            reply.subnet_mask = Some(self.config.netmask);
+            reply.router = self.config.router;
+            reply.dns_servers = self.config.dns_servers.clone();
+            reply.lease_time = Some(self.config.lease_time);
+        }
+        reply
+    }
+
+    /// Answer pending client messages; returns the number handled
+    pub fn poll(&mut self, stack: &NetStack) -> Result<usize, String> {
+        let mut handled = 0;
+        while let Some(datagram) = stack.recv_from(self.socket)? {
+            let request = match DhcpMessage::parse(&datagram.data) {
+                Ok(request) => request,
+                Err(_) => continue,
+            };
+
+            let reply = match request.message_type {
+                DhcpMessageType::Discover => self
+                    .allocate(request.chaddr)
+                    .map(|address| self.reply(&request, DhcpMessageType::Offer, address)),
+                DhcpMessageType::Request => {
+                    let for_us = request.server_id.is_none_or(|id| id == self.address);
+                    let wanted = request.requested_ip.unwrap_or(request.ciaddr);
+                    if !for_us {
+                        None
+                    } else if self.leases.get(&request.chaddr) == Some(&wanted) {
+                        Some(self.reply(&request, DhcpMessageType::Ack, wanted))
+                    } else {
+                        Some(self.reply(&request, DhcpMessageType::Nak, wanted))
+                    }
+                }
+                DhcpMessageType::Release => {
+                    self.leases.remove(&request.chaddr);
+                    None
+                }
+                _ => None,
+            };
+
+            if let Some(reply) = reply {
+                stack.send_to(self.socket, Ipv4Addr::BROADCAST, CLIENT_PORT, &reply.to_bytes())?;
+            }
+            handled += 1;
+        }
+        Ok(handled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_round_trip() {
+        let mut offer = DhcpMessage::new(DhcpMessageType::Offer, 42, [2, 0, 0, 0, 0, 9]);
+        offer.yiaddr = Ipv4Addr::new(192, 168, 1, 100);
+        offer.subnet_mask = Some(Ipv4Addr::new(255, 255, 255, 0));
+        offer.router = Some(Ipv4Addr::new(192, 168, 1, 1));
+        offer.dns_servers = vec![Ipv4Addr::new(1, 1, 1, 1), Ipv4Addr::new(8, 8, 8, 8)];
+        offer.lease_time = Some(3600);
+        offer.server_id = Some(Ipv4Addr::new(192, 168, 1, 1));
+
+        let bytes = offer.to_bytes();
+        assert_eq!(bytes[0], 2);
+        assert_eq!(DhcpMessage::parse(&bytes).unwrap(), offer);
+        assert!(DhcpMessage::parse(&bytes[..100]).is_err());
+    }
+}
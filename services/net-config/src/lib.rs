@@ -0,0 +1,408 @@
+//! Network Configuration Service
+//!
+//! Configures network interfaces either statically (address, gateway, DNS)
+//! or through DHCP, persists each interface's configuration in the VFS,
+//! and exposes interface state over IPC.
+
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use filesystem::{OpenOptions, ProcessId, VirtualFileSystem};
+use ipc::JsonService;
+use net_stack::{Ipv4Config, MacAddress, NetStack};
+use serde::{Deserialize, Serialize};
+
+pub mod dhcp;
+
+pub use dhcp::{DhcpClient, DhcpLease, DhcpServer, DhcpServerConfig, DhcpState};
+
+/// Directory holding one JSON configuration file per interface
+pub const CONFIG_DIR: &str = "/etc/network";
+
+/// Static interface configuration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StaticConfig {
+    pub address: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub gateway: Option<Ipv4Addr>,
+    pub dns_servers: Vec<Ipv4Addr>,
+}
+
+/// How an interface obtains its address
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum AddressMode {
+    Static(StaticConfig),
+    Dhcp,
+}
+
+/// Link-layer configuration progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LinkState {
+    Unconfigured,
+    Acquiring,
+    Configured,
+}
+
+/// Interface state reported to clients
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InterfaceStatus {
+    pub name: String,
+    pub mac: MacAddress,
+    pub mode: Option<AddressMode>,
+    pub state: LinkState,
+    pub address: Option<Ipv4Addr>,
+    pub netmask: Option<Ipv4Addr>,
+    pub gateway: Option<Ipv4Addr>,
+    pub dns_servers: Vec<Ipv4Addr>,
+    pub dhcp_state: Option<DhcpState>,
+}
+
+struct Interface {
+    stack: Arc<NetStack>,
+    mode: Option<AddressMode>,
+    dhcp: Option<DhcpClient>,
+}
+
+impl Interface {
+    fn status(&self, name: &str) -> InterfaceStatus {
+        let config = self.stack.config();
+        let dns_servers = match (&self.mode, &self.dhcp) {
+            (Some(AddressMode::Static(config)), _) => config.dns_servers.clone(),
+            (_, Some(client)) => client.lease().map(|l| l.dns_servers.clone()).unwrap_or_default(),
+            _ => Vec::new(),
+        };
+        let state = match (&self.mode, config) {
+            (_, Some(_)) => LinkState::Configured,
+            (Some(AddressMode::Dhcp), None) => LinkState::Acquiring,
+            _ => LinkState::Unconfigured,
+        };
+        InterfaceStatus {
+            name: name.to_string(),
+            mac: self.stack.mac_address(),
+            mode: self.mode.clone(),
+            state,
+            address: config.map(|c| c.address),
+            netmask: config.map(|c| c.netmask),
+            gateway: config.and_then(|c| c.gateway),
+            dns_servers,
+            dhcp_state: self.dhcp.as_ref().map(|c| c.state()),
+        }
+    }
+}
+
+/// Network configuration service
+pub struct NetworkConfigService {
+    interfaces: Arc<Mutex<HashMap<String, Interface>>>,
+    vfs: Arc<VirtualFileSystem>,
+}
+
+impl NetworkConfigService {
+    pub fn new(vfs: Arc<VirtualFileSystem>) -> Self {
+        NetworkConfigService {
+            interfaces: Arc::new(Mutex::new(HashMap::new())),
+            vfs,
+        }
+    }
+
+    fn config_path(name: &str) -> PathBuf {
+        Path::new(CONFIG_DIR).join(format!("{}.json", name))
+    }
+
+    /// Manage an interface, applying its persisted configuration if any
+    pub fn add_interface(&self, name: &str, stack: Arc<NetStack>) -> Result<(), String> {
+        if self.interfaces.lock().unwrap().contains_key(name) {
+            return Err("Interface already exists".to_string());
+        }
+        self.interfaces.lock().unwrap().insert(
+            name.to_string(),
+            Interface {
+                stack,
+                mode: None,
+                dhcp: None,
+            },
+        );
+
+        if let Some(mode) = self.load_config(name)? {
+            self.apply(name, mode)?;
+        }
+        Ok(())
+    }
+
+    /// Stop managing an interface, releasing any DHCP lease
+    pub fn remove_interface(&self, name: &str) -> Result<(), String> {
+        let mut interface = self
+            .interfaces
+            .lock()
+            .unwrap()
+            .remove(name)
+            .ok_or("Interface not found")?;
+        if let Some(client) = interface.dhcp.as_mut() {
+            client.release(&interface.stack)?;
+        }
+        Ok(())
+    }
+
+    /// Apply and persist a static configuration
+    pub fn set_static(&self, name: &str, config: StaticConfig) -> Result<(), String> {
+        let mode = AddressMode::Static(config);
+        self.apply(name, mode.clone())?;
+        self.save_config(name, &mode)
+    }
+
+    /// Switch an interface to DHCP and persist the choice
+    pub fn enable_dhcp(&self, name: &str) -> Result<(), String> {
+        self.apply(name, AddressMode::Dhcp)?;
+        self.save_config(name, &AddressMode::Dhcp)
+    }
+
+    fn apply(&self, name: &str, mode: AddressMode) -> Result<(), String> {
+        let mut interfaces = self.interfaces.lock().unwrap();
+        let interface = interfaces.get_mut(name).ok_or("Interface not found")?;
+
+        if let Some(mut client) = interface.dhcp.take() {
+            client.release(&interface.stack)?;
+        }
+        match &mode {
+            AddressMode::Static(config) => {
+                interface
+                    .stack
+                    .configure(Ipv4Config::new(config.address, config.netmask, config.gateway));
+            }
+            AddressMode::Dhcp => {
+                interface.stack.deconfigure();
+                let mut client = DhcpClient::new(interface.stack.mac_address());
+                client.start(&interface.stack)?;
+                interface.dhcp = Some(client);
+            }
+        }
+        interface.mode = Some(mode);
+        Ok(())
+    }
+
+    /// Process network traffic on every interface, advancing DHCP clients
+    pub fn poll(&self) -> Result<(), String> {
+        let mut interfaces = self.interfaces.lock().unwrap();
+        for interface in interfaces.values_mut() {
+            interface.stack.poll();
+            if let Some(client) = interface.dhcp.as_mut() {
+                client.poll(&interface.stack)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Advance DHCP retransmission and lease timers
+    pub fn tick(&self, elapsed: u64) -> Result<(), String> {
+        let mut interfaces = self.interfaces.lock().unwrap();
+        for interface in interfaces.values_mut() {
+            if let Some(client) = interface.dhcp.as_mut() {
+                client.tick(&interface.stack, elapsed)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn status(&self, name: &str) -> Option<InterfaceStatus> {
+        self.interfaces.lock().unwrap().get(name).map(|i| i.status(name))
+    }
+
+    pub fn list_interfaces(&self) -> Vec<InterfaceStatus> {
+        let mut statuses: Vec<InterfaceStatus> = self
+            .interfaces
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, interface)| interface.status(name))
+            .collect();
+        statuses.sort_by(|a, b| a.name.cmp(&b.name));
+        statuses
+    }
+
+    fn save_config(&self, name: &str, mode: &AddressMode) -> Result<(), String> {
+        let data = serde_json::to_vec_pretty(mode).map_err(|e| e.to_string())?;
+        self.vfs.write_file(&Self::config_path(name), &data).map_err(String::from)
+    }
+
+    fn load_config(&self, name: &str) -> Result<Option<AddressMode>, String> {
+        let path = Self::config_path(name);
+        if !self.vfs.exists(&path) {
+            return Ok(None);
+        }
+        let size = self.vfs.metadata(&path)?.size as usize;
+        let handle = self.vfs.open(ProcessId::KERNEL, &path, OpenOptions::read_only())?;
+        let mut data = vec![0u8; size];
+        let read = self.vfs.read(ProcessId::KERNEL, handle, &mut data);
+        self.vfs.close(ProcessId::KERNEL, handle)?;
+        read?;
+        serde_json::from_slice(&data)
+            .map(Some)
+            .map_err(|e| format!("Invalid network configuration: {}", e))
+    }
+}
+
+impl JsonService for NetworkConfigService {
+    type Request = NetConfigRequest;
+    type Response = NetConfigResponse;
+    const KIND: &'static str = "network config";
+
+    fn handle_request(&self, request: NetConfigRequest) -> NetConfigResponse {
+        let result = match request {
+            NetConfigRequest::List => Ok(NetConfigResponse::Interfaces {
+                interfaces: self.list_interfaces(),
+            }),
+            NetConfigRequest::Status { name } => self
+                .status(&name)
+                .map(|status| NetConfigResponse::Interface { status })
+                .ok_or_else(|| "Interface not found".to_string()),
+            NetConfigRequest::SetStatic { name, config } => {
+                self.set_static(&name, config).map(|_| NetConfigResponse::Ok)
+            }
+            NetConfigRequest::EnableDhcp { name } => self.enable_dhcp(&name).map(|_| NetConfigResponse::Ok),
+        };
+        result.unwrap_or_else(|message| NetConfigResponse::Error { message })
+    }
+}
+
+/// Request accepted over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum NetConfigRequest {
+    List,
+    Status { name: String },
+    SetStatic { name: String, config: StaticConfig },
+    EnableDhcp { name: String },
+}
+
+/// Reply sent over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum NetConfigResponse {
+    Interfaces { interfaces: Vec<InterfaceStatus> },
+    Interface { status: InterfaceStatus },
+    Ok,
+    Error { message: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ipc::Message;
+    use net_stack::VirtualNic;
+
+    const CLIENT_MAC: MacAddress = [2, 0, 0, 0, 0, 0x10];
+
+    /// Client stack linked to a stack running a DHCP server on 192.168.1.1
+    fn dhcp_network() -> (Arc<NetStack>, NetStack, DhcpServer) {
+        let (client_nic, server_nic) = VirtualNic::pair(CLIENT_MAC, [2, 0, 0, 0, 0, 1]);
+        let server_stack = NetStack::new(Box::new(server_nic));
+        let netmask = Ipv4Addr::new(255, 255, 255, 0);
+        server_stack.configure(Ipv4Config::new(Ipv4Addr::new(192, 168, 1, 1), netmask, None));
+        let server = DhcpServer::new(
+            &server_stack,
+            DhcpServerConfig {
+                pool_start: Ipv4Addr::new(192, 168, 1, 100),
+                pool_end: Ipv4Addr::new(192, 168, 1, 199),
+                netmask,
+                router: Some(Ipv4Addr::new(192, 168, 1, 1)),
+                dns_servers: vec![Ipv4Addr::new(192, 168, 1, 1)],
+                lease_time: 600,
+            },
+        )
+        .unwrap();
+        (Arc::new(NetStack::new(Box::new(client_nic))), server_stack, server)
+    }
+
+    fn run(service: &NetworkConfigService, server_stack: &NetStack, server: &mut DhcpServer) {
+        for _ in 0..5 {
+            service.poll().unwrap();
+            server_stack.poll();
+            server.poll(server_stack).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_dhcp_acquisition() {
+        let (client_stack, server_stack, mut server) = dhcp_network();
+        let service = NetworkConfigService::new(Arc::new(VirtualFileSystem::new()));
+        service.add_interface("eth0", client_stack).unwrap();
+        service.enable_dhcp("eth0").unwrap();
+        assert_eq!(service.status("eth0").unwrap().state, LinkState::Acquiring);
+
+        run(&service, &server_stack, &mut server);
+
+        let status = service.status("eth0").unwrap();
+        assert_eq!(status.state, LinkState::Configured);
+        assert_eq!(status.address, Some(Ipv4Addr::new(192, 168, 1, 100)));
+        assert_eq!(status.gateway, Some(Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(status.dns_servers, vec![Ipv4Addr::new(192, 168, 1, 1)]);
+        assert_eq!(status.dhcp_state, Some(DhcpState::Bound));
+        assert_eq!(server.lease_for(CLIENT_MAC), Some(Ipv4Addr::new(192, 168, 1, 100)));
+    }
+
+    #[test]
+    fn test_lease_renewal() {
+        let (client_stack, server_stack, mut server) = dhcp_network();
+        let service = NetworkConfigService::new(Arc::new(VirtualFileSystem::new()));
+        service.add_interface("eth0", client_stack).unwrap();
+        service.enable_dhcp("eth0").unwrap();
+        run(&service, &server_stack, &mut server);
+
+        service.tick(300).unwrap();
+        assert_eq!(service.status("eth0").unwrap().dhcp_state, Some(DhcpState::Renewing));
+        run(&service, &server_stack, &mut server);
+        assert_eq!(service.status("eth0").unwrap().dhcp_state, Some(DhcpState::Bound));
+    }
+
+    #[test]
+    fn test_static_config_persists() {
+        let vfs = Arc::new(VirtualFileSystem::new());
+        let config = StaticConfig {
+            address: Ipv4Addr::new(10, 0, 0, 5),
+            netmask: Ipv4Addr::new(255, 0, 0, 0),
+            gateway: Some(Ipv4Addr::new(10, 0, 0, 1)),
+            dns_servers: vec![Ipv4Addr::new(9, 9, 9, 9)],
+        };
+
+        let service = NetworkConfigService::new(Arc::clone(&vfs));
+        let (nic, _peer) = VirtualNic::pair(CLIENT_MAC, [2, 0, 0, 0, 0, 1]);
+        service.add_interface("eth0", Arc::new(NetStack::new(Box::new(nic)))).unwrap();
+        service.set_static("eth0", config.clone()).unwrap();
+        assert!(vfs.exists(Path::new("/etc/network/eth0.json")));
+
+        // A fresh service instance restores the configuration from the VFS
+        let restarted = NetworkConfigService::new(vfs);
+        let (nic, _peer) = VirtualNic::pair(CLIENT_MAC, [2, 0, 0, 0, 0, 1]);
+        restarted.add_interface("eth0", Arc::new(NetStack::new(Box::new(nic)))).unwrap();
+        let status = restarted.status("eth0").unwrap();
+        assert_eq!(status.address, Some(config.address));
+        assert_eq!(status.dns_servers, config.dns_servers);
+        assert_eq!(status.mode, Some(AddressMode::Static(config)));
+    }
+
+    #[test]
+    fn test_ipc_requests() {
+        let service = NetworkConfigService::new(Arc::new(VirtualFileSystem::new()));
+        let (nic, _peer) = VirtualNic::pair(CLIENT_MAC, [2, 0, 0, 0, 0, 1]);
+        service.add_interface("eth0", Arc::new(NetStack::new(Box::new(nic)))).unwrap();
+
+        let request = NetConfigRequest::Status { name: "eth0".to_string() };
+        let message = Message::Request {
+            id: 7,
+            data: serde_json::to_vec(&request).unwrap(),
+        };
+        let Some(Message::Response { id, data }) = service.handle_message(&message) else {
+            panic!("expected a response");
+        };
+        assert_eq!(id, 7);
+        match serde_json::from_slice(&data).unwrap() {
+            NetConfigResponse::Interface { status } => assert_eq!(status.state, LinkState::Unconfigured),
+            other => panic!("unexpected response: {:?}", other),
+        }
+
+        let missing = service.handle_request(NetConfigRequest::EnableDhcp { name: "wlan0".to_string() });
+        assert!(matches!(missing, NetConfigResponse::Error { .. }));
+    }
+}
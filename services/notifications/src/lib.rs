@@ -0,0 +1,451 @@
+//! Notification Service
+//!
+//! Accepts notifications from applications over IPC and tracks them until
+//! they time out, are dismissed, or one of their actions is invoked. Each
+//! application is rate limited so a misbehaving app cannot flood the
+//! shell's notification list. Time advances through `tick`.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use ipc::JsonService;
+use serde::{Deserialize, Serialize};
+
+/// Lifetime of low and normal urgency notifications without an explicit timeout
+pub const DEFAULT_TIMEOUT_MS: u64 = 5_000;
+
+/// Notification identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct NotificationId(u64);
+
+impl NotificationId {
+    pub fn new(id: u64) -> Self {
+        NotificationId(id)
+    }
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+/// How urgently a notification needs the user's attention
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Urgency {
+    Low,
+    Normal,
+    /// Stays until dismissed unless a timeout is given
+    Critical,
+}
+
+/// Button offered with a notification
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NotificationAction {
+    pub id: String,
+    pub label: String,
+}
+
+/// Notification as submitted by an application
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NotificationRequest {
+    pub app_id: String,
+    pub title: String,
+    #[serde(default)]
+    pub body: String,
+    pub urgency: Urgency,
+    #[serde(default)]
+    pub actions: Vec<NotificationAction>,
+    /// Milliseconds before the notification expires
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+impl NotificationRequest {
+    pub fn new(app_id: &str, title: &str, body: &str, urgency: Urgency) -> Self {
+        NotificationRequest {
+            app_id: app_id.to_string(),
+            title: title.to_string(),
+            body: body.to_string(),
+            urgency,
+            actions: Vec::new(),
+            timeout_ms: None,
+        }
+    }
+
+    pub fn with_action(mut self, id: &str, label: &str) -> Self {
+        self.actions.push(NotificationAction {
+            id: id.to_string(),
+            label: label.to_string(),
+        });
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout_ms: u64) -> Self {
+        self.timeout_ms = Some(timeout_ms);
+        self
+    }
+}
+
+/// A notification currently shown to the user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Notification {
+    pub id: NotificationId,
+    pub app_id: String,
+    pub title: String,
+    pub body: String,
+    pub urgency: Urgency,
+    pub actions: Vec<NotificationAction>,
+    pub posted_at: u64,
+    /// Service time at which the notification expires
+    pub expires_at: Option<u64>,
+}
+
+/// Why a notification left the list
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum CloseReason {
+    Expired,
+    Dismissed,
+    ActionInvoked { action: String },
+}
+
+/// Lifecycle event delivered back to the posting application
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NotificationEvent {
+    pub id: NotificationId,
+    pub reason: CloseReason,
+}
+
+/// Callback run when a notification of an application closes
+pub type EventHandler = Arc<dyn Fn(&NotificationEvent) + Send + Sync>;
+
+/// Per-application posting limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub max_notifications: usize,
+    pub window_ms: u64,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            max_notifications: 5,
+            window_ms: 10_000,
+        }
+    }
+}
+
+#[derive(Default)]
+struct AppState {
+    limit: Option<RateLimit>,
+    /// Post times within the current rate window
+    recent: VecDeque<u64>,
+    events: Vec<NotificationEvent>,
+    handler: Option<EventHandler>,
+}
+
+/// Notification daemon
+pub struct NotificationService {
+    notifications: Arc<Mutex<HashMap<NotificationId, Notification>>>,
+    apps: Arc<Mutex<HashMap<String, AppState>>>,
+    clock: Arc<Mutex<u64>>,
+    next_id: Arc<Mutex<u64>>,
+}
+
+impl NotificationService {
+    pub fn new() -> Self {
+        NotificationService {
+            notifications: Arc::new(Mutex::new(HashMap::new())),
+            apps: Arc::new(Mutex::new(HashMap::new())),
+            clock: Arc::new(Mutex::new(0)),
+            next_id: Arc::new(Mutex::new(1)),
+        }
+    }
+
+    /// Override the default rate limit for an application
+    pub fn set_rate_limit(&self, app_id: &str, limit: RateLimit) {
+        self.apps.lock().unwrap().entry(app_id.to_string()).or_default().limit = Some(limit);
+    }
+
+    /// Run `handler` whenever one of the application's notifications closes
+    pub fn set_event_handler(&self, app_id: &str, handler: EventHandler) {
+        self.apps.lock().unwrap().entry(app_id.to_string()).or_default().handler = Some(handler);
+    }
+
+    /// Post a notification
+    pub fn notify(&self, request: NotificationRequest) -> Result<NotificationId, String> {
+        if request.app_id.is_empty() || request.title.is_empty() {
+            return Err("Notifications need an app id and a title".to_string());
+        }
+        let now = *self.clock.lock().unwrap();
+        {
+            let mut apps = self.apps.lock().unwrap();
+            let app = apps.entry(request.app_id.clone()).or_default();
+            let limit = app.limit.unwrap_or_default();
+            while app.recent.front().is_some_and(|t| now.saturating_sub(*t) >= limit.window_ms) {
+                app.recent.pop_front();
+            }
+            if app.recent.len() >= limit.max_notifications {
+                return Err(format!("Rate limit exceeded for {}", request.app_id));
+            }
+            app.recent.push_back(now);
+        }
+
+        let timeout = match (request.timeout_ms, request.urgency) {
+            (Some(timeout), _) => Some(timeout),
+            (None, Urgency::Critical) => None,
+            (None, _) => Some(DEFAULT_TIMEOUT_MS),
+        };
+        let mut next_id = self.next_id.lock().unwrap();
+        let id = NotificationId(*next_id);
+        *next_id += 1;
+        let notification = Notification {
+            id,
+            app_id: request.app_id,
+            title: request.title,
+            body: request.body,
+            urgency: request.urgency,
+            actions: request.actions,
+            posted_at: now,
+            expires_at: timeout.map(|t| now + t),
+        };
+        self.notifications.lock().unwrap().insert(id, notification);
+        Ok(id)
+    }
+
+    /// Dismiss a notification
+    pub fn dismiss(&self, id: NotificationId) -> Result<(), String> {
+        self.close(id, CloseReason::Dismissed)
+    }
+
+    /// Invoke one of a notification's actions, closing it
+    pub fn invoke_action(&self, id: NotificationId, action: &str) -> Result<(), String> {
+        let known = self
+            .notifications
+            .lock()
+            .unwrap()
+            .get(&id)
+            .ok_or("Notification not found")?
+            .actions
+            .iter()
+            .any(|a| a.id == action);
+        if !known {
+            return Err(format!("Unknown action {}", action));
+        }
+        self.close(id, CloseReason::ActionInvoked {
+            action: action.to_string(),
+        })
+    }
+
+    fn close(&self, id: NotificationId, reason: CloseReason) -> Result<(), String> {
+        let notification = self
+            .notifications
+            .lock()
+            .unwrap()
+            .remove(&id)
+            .ok_or("Notification not found")?;
+        let event = NotificationEvent { id, reason };
+        let handler = {
+            let mut apps = self.apps.lock().unwrap();
+            let app = apps.entry(notification.app_id).or_default();
+            app.events.push(event.clone());
+            app.handler.clone()
+        };
+        // Handlers may post follow-up notifications, so call them unlocked
+        if let Some(handler) = handler {
+            handler(&event);
+        }
+        Ok(())
+    }
+
+    /// Advance time, expiring notifications whose timeout has passed
+    pub fn tick(&self, elapsed_ms: u64) {
+        let now = {
+            let mut clock = self.clock.lock().unwrap();
+            *clock += elapsed_ms;
+            *clock
+        };
+        let expired: Vec<NotificationId> = self
+            .notifications
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|n| n.expires_at.is_some_and(|t| t <= now))
+            .map(|n| n.id)
+            .collect();
+        for id in expired {
+            let _ = self.close(id, CloseReason::Expired);
+        }
+    }
+
+    pub fn get(&self, id: NotificationId) -> Option<Notification> {
+        self.notifications.lock().unwrap().get(&id).cloned()
+    }
+
+    /// Active notifications, most urgent first and newest first within an urgency
+    pub fn active(&self) -> Vec<Notification> {
+        let mut active: Vec<Notification> = self.notifications.lock().unwrap().values().cloned().collect();
+        active.sort_by(|a, b| b.urgency.cmp(&a.urgency).then(b.id.cmp(&a.id)));
+        active
+    }
+
+    /// Drain the lifecycle events queued for an application
+    pub fn take_events(&self, app_id: &str) -> Vec<NotificationEvent> {
+        self.apps
+            .lock()
+            .unwrap()
+            .get_mut(app_id)
+            .map(|app| std::mem::take(&mut app.events))
+            .unwrap_or_default()
+    }
+}
+
+impl JsonService for NotificationService {
+    type Request = NotifyRequest;
+    type Response = NotifyResponse;
+    const KIND: &'static str = "notification";
+
+    fn handle_request(&self, request: NotifyRequest) -> NotifyResponse {
+        let result = match request {
+            NotifyRequest::Notify { notification } => {
+                self.notify(notification).map(|id| NotifyResponse::Posted { id })
+            }
+            NotifyRequest::Dismiss { id } => self.dismiss(id).map(|_| NotifyResponse::Ok),
+            NotifyRequest::InvokeAction { id, action } => {
+                self.invoke_action(id, &action).map(|_| NotifyResponse::Ok)
+            }
+            NotifyRequest::List => Ok(NotifyResponse::Notifications {
+                notifications: self.active(),
+            }),
+            NotifyRequest::Events { app_id } => Ok(NotifyResponse::Events {
+                events: self.take_events(&app_id),
+            }),
+        };
+        result.unwrap_or_else(|message| NotifyResponse::Error { message })
+    }
+}
+
+impl Default for NotificationService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Request accepted over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum NotifyRequest {
+    Notify { notification: NotificationRequest },
+    Dismiss { id: NotificationId },
+    InvokeAction { id: NotificationId, action: String },
+    List,
+    Events { app_id: String },
+}
+
+/// Reply sent over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum NotifyResponse {
+    Posted { id: NotificationId },
+    Notifications { notifications: Vec<Notification> },
+    Events { events: Vec<NotificationEvent> },
+    Ok,
+    Error { message: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ipc::{Message, ERROR_BAD_REQUEST};
+
+    #[test]
+    fn test_timeouts_and_ordering() {
+        let service = NotificationService::new();
+        let low = service.notify(NotificationRequest::new("mail", "New mail", "", Urgency::Low)).unwrap();
+        let critical = service
+            .notify(NotificationRequest::new("power", "Battery low", "5% remaining", Urgency::Critical))
+            .unwrap();
+        let short = service
+            .notify(NotificationRequest::new("mail", "Sent", "", Urgency::Normal).with_timeout(100))
+            .unwrap();
+        let order: Vec<NotificationId> = service.active().iter().map(|n| n.id).collect();
+        assert_eq!(order, vec![critical, short, low]);
+
+        service.tick(100);
+        assert!(service.get(short).is_none());
+        service.tick(DEFAULT_TIMEOUT_MS);
+        assert!(service.get(low).is_none());
+        assert!(service.get(critical).is_some());
+
+        let events = service.take_events("mail");
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|e| e.reason == CloseReason::Expired));
+        assert!(service.take_events("mail").is_empty());
+    }
+
+    #[test]
+    fn test_actions_and_dismissal() {
+        let service = NotificationService::new();
+        let invoked = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&invoked);
+        service.set_event_handler("updates", Arc::new(move |event| seen.lock().unwrap().push(event.clone())));
+
+        let id = service
+            .notify(
+                NotificationRequest::new("updates", "Update ready", "Restart to apply", Urgency::Normal)
+                    .with_action("restart", "Restart now"),
+            )
+            .unwrap();
+        assert!(service.invoke_action(id, "later").is_err());
+        service.invoke_action(id, "restart").unwrap();
+        assert_eq!(
+            invoked.lock().unwrap()[0].reason,
+            CloseReason::ActionInvoked {
+                action: "restart".to_string()
+            }
+        );
+        assert!(service.dismiss(id).is_err());
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let service = NotificationService::new();
+        service.set_rate_limit(
+            "chatty",
+            RateLimit {
+                max_notifications: 2,
+                window_ms: 1_000,
+            },
+        );
+        let post = || service.notify(NotificationRequest::new("chatty", "Hi", "", Urgency::Low));
+        assert!(post().is_ok());
+        assert!(post().is_ok());
+        assert!(post().is_err());
+        // Other applications are unaffected
+        assert!(service.notify(NotificationRequest::new("mail", "Hi", "", Urgency::Low)).is_ok());
+        service.tick(1_000);
+        assert!(post().is_ok());
+    }
+
+    #[test]
+    fn test_notify_over_ipc() {
+        let service = NotificationService::new();
+        let message = Message::Request {
+            id: 9,
+            data: br#"{"op":"notify","notification":{"app_id":"mail","title":"New mail","urgency":"normal"}}"#.to_vec(),
+        };
+        let Some(Message::Response { data, .. }) = service.handle_message(&message) else {
+            panic!("expected a response");
+        };
+        let NotifyResponse::Posted { id } = serde_json::from_slice(&data).unwrap() else {
+            panic!("expected a notification id");
+        };
+        assert_eq!(service.handle_request(NotifyRequest::Dismiss { id }), NotifyResponse::Ok);
+        match service.handle_request(NotifyRequest::Events { app_id: "mail".to_string() }) {
+            NotifyResponse::Events { events } => assert_eq!(events[0].reason, CloseReason::Dismissed),
+            other => panic!("unexpected response: {:?}", other),
+        }
+        let bad = Message::Request { id: 10, data: b"[]".to_vec() };
+        assert!(matches!(service.handle_message(&bad), Some(Message::Error { code: ERROR_BAD_REQUEST, .. })));
+    }
+}
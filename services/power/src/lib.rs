@@ -0,0 +1,540 @@
+//! Power Management Service
+//!
+//! Orchestrates system suspend and resume. Subscribed services are told
+//! before the system sleeps and may veto it, Chrysalis VMs are paused, and
+//! devices are suspended through the HAL in dependency order: a device is
+//! suspended before the devices it depends on and resumed after them. The
+//! system suspends on request or after an idle timeout, and only enabled
+//! wake sources bring it back. A shorter lock timeout tells the desktop to
+//! lock the screen while the system stays awake.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use compatibility::{Chrysalis, VmId, VmState};
+use hal::{PowerManagedDevice, PowerState};
+use ipc::JsonService;
+use serde::{Deserialize, Serialize};
+
+/// Wake source registered by default
+pub const POWER_BUTTON: &str = "power-button";
+
+/// Device shared between its driver and the power manager
+pub type SharedDevice = Arc<Mutex<dyn PowerManagedDevice>>;
+
+/// Subscriber callback; returning an error from `Suspending` vetoes the suspend
+pub type PowerListener = Arc<dyn Fn(PowerEvent) -> Result<(), String> + Send + Sync>;
+
+/// Callback run once the lock timeout passes without activity
+pub type IdleListener = Arc<dyn Fn() + Send + Sync>;
+
+/// Event delivered to subscribers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerEvent {
+    Suspending,
+    Resumed,
+}
+
+/// Overall system power state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SystemState {
+    Running,
+    Suspended,
+}
+
+/// Why the system went to sleep
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuspendReason {
+    Requested,
+    Idle,
+}
+
+/// Power status reported over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PowerStatus {
+    pub state: SystemState,
+    pub idle_ms: u64,
+    pub idle_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub lock_timeout_ms: Option<u64>,
+    pub last_suspend: Option<SuspendReason>,
+    pub last_wake_source: Option<String>,
+    pub suspend_count: u64,
+}
+
+struct ManagedDevice {
+    name: String,
+    device: SharedDevice,
+}
+
+/// System power manager
+pub struct PowerManager {
+    /// In registration order, which puts dependencies first
+    devices: Arc<Mutex<Vec<ManagedDevice>>>,
+    listeners: Arc<Mutex<Vec<(String, PowerListener)>>>,
+    wake_sources: Arc<Mutex<HashMap<String, bool>>>,
+    hypervisor: Arc<Mutex<Option<Arc<Chrysalis>>>>,
+    /// VMs paused by the last suspend
+    paused_vms: Arc<Mutex<Vec<VmId>>>,
+    status: Arc<Mutex<PowerStatus>>,
+    idle_listeners: Arc<Mutex<Vec<IdleListener>>>,
+    /// Idle listeners already ran for the current idle period
+    idle_reported: Arc<Mutex<bool>>,
+}
+
+impl PowerManager {
+    pub fn new() -> Self {
+        let mut wake_sources = HashMap::new();
+        wake_sources.insert(POWER_BUTTON.to_string(), true);
+        PowerManager {
+            devices: Arc::new(Mutex::new(Vec::new())),
+            listeners: Arc::new(Mutex::new(Vec::new())),
+            wake_sources: Arc::new(Mutex::new(wake_sources)),
+            hypervisor: Arc::new(Mutex::new(None)),
+            paused_vms: Arc::new(Mutex::new(Vec::new())),
+            status: Arc::new(Mutex::new(PowerStatus {
+                state: SystemState::Running,
+                idle_ms: 0,
+                idle_timeout_ms: None,
+                lock_timeout_ms: None,
+                last_suspend: None,
+                last_wake_source: None,
+                suspend_count: 0,
+            })),
+            idle_listeners: Arc::new(Mutex::new(Vec::new())),
+            idle_reported: Arc::new(Mutex::new(false)),
+        }
+    }
+
+    /// Manage a device; its dependencies must already be registered
+    pub fn register_device(&self, name: &str, device: SharedDevice, depends_on: &[&str]) -> Result<(), String> {
+        let mut devices = self.devices.lock().unwrap();
+        if devices.iter().any(|d| d.name == name) {
+            return Err(format!("Device {} already registered", name));
+        }
+        if let Some(missing) = depends_on.iter().find(|dep| !devices.iter().any(|d| d.name == **dep)) {
+            return Err(format!("Device {} depends on unregistered device {}", name, missing));
+        }
+        devices.push(ManagedDevice {
+            name: name.to_string(),
+            device,
+        });
+        Ok(())
+    }
+
+    pub fn unregister_device(&self, name: &str) {
+        self.devices.lock().unwrap().retain(|d| d.name != name);
+    }
+
+    /// Subscribe a service to suspend and resume events
+    pub fn subscribe(&self, service: &str, listener: PowerListener) {
+        self.listeners.lock().unwrap().push((service.to_string(), listener));
+    }
+
+    pub fn unsubscribe(&self, service: &str) {
+        self.listeners.lock().unwrap().retain(|(name, _)| name != service);
+    }
+
+    /// Pause running VMs of `hypervisor` while suspended
+    pub fn attach_hypervisor(&self, hypervisor: Arc<Chrysalis>) {
+        *self.hypervisor.lock().unwrap() = Some(hypervisor);
+    }
+
+    pub fn add_wake_source(&self, name: &str) {
+        self.wake_sources.lock().unwrap().insert(name.to_string(), true);
+    }
+
+    pub fn set_wake_source_enabled(&self, name: &str, enabled: bool) -> Result<(), String> {
+        let mut sources = self.wake_sources.lock().unwrap();
+        let source = sources.get_mut(name).ok_or_else(|| format!("Unknown wake source {}", name))?;
+        *source = enabled;
+        Ok(())
+    }
+
+    /// Suspend after `timeout_ms` without activity; `None` disables idle suspend
+    pub fn set_idle_timeout(&self, timeout_ms: Option<u64>) {
+        self.status.lock().unwrap().idle_timeout_ms = timeout_ms;
+    }
+
+    /// Run the idle listeners after `timeout_ms` without activity; `None` disables them
+    pub fn set_lock_timeout(&self, timeout_ms: Option<u64>) {
+        self.status.lock().unwrap().lock_timeout_ms = timeout_ms;
+    }
+
+    /// Run `listener` each time the lock timeout passes
+    pub fn on_idle(&self, listener: IdleListener) {
+        self.idle_listeners.lock().unwrap().push(listener);
+    }
+
+    /// Reset the idle timer
+    pub fn record_activity(&self) {
+        self.status.lock().unwrap().idle_ms = 0;
+        *self.idle_reported.lock().unwrap() = false;
+    }
+
+    pub fn status(&self) -> PowerStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Advance the idle timer, running the idle listeners once the lock
+    /// timeout passes and suspending once the idle timeout passes
+    pub fn tick(&self, elapsed_ms: u64) -> Result<(), String> {
+        let (lock, idle) = {
+            let mut status = self.status.lock().unwrap();
+            if status.state != SystemState::Running {
+                return Ok(());
+            }
+            status.idle_ms += elapsed_ms;
+            let lock = status.lock_timeout_ms.is_some_and(|t| status.idle_ms >= t)
+                && !std::mem::replace(&mut *self.idle_reported.lock().unwrap(), true);
+            (lock, status.idle_timeout_ms.is_some_and(|t| status.idle_ms >= t))
+        };
+        if lock {
+            let listeners = self.idle_listeners.lock().unwrap().clone();
+            for listener in listeners {
+                listener();
+            }
+        }
+        if idle {
+            self.suspend(SuspendReason::Idle)?;
+        }
+        Ok(())
+    }
+
+    /// Put the system to sleep
+    pub fn suspend(&self, reason: SuspendReason) -> Result<(), String> {
+        if self.status.lock().unwrap().state != SystemState::Running {
+            return Err("System already suspended".to_string());
+        }
+
+        // Listeners are called unlocked so they can query the power manager
+        let listeners = self.listeners.lock().unwrap().clone();
+        for (index, (service, listener)) in listeners.iter().enumerate() {
+            if let Err(e) = listener(PowerEvent::Suspending) {
+                self.notify(&listeners[..index], PowerEvent::Resumed);
+                return Err(format!("Suspend vetoed by {}: {}", service, e));
+            }
+        }
+
+        self.pause_vms();
+        if let Err(e) = self.suspend_devices() {
+            self.resume_vms();
+            self.notify(&listeners, PowerEvent::Resumed);
+            return Err(e);
+        }
+
+        let mut status = self.status.lock().unwrap();
+        status.state = SystemState::Suspended;
+        status.last_suspend = Some(reason);
+        status.suspend_count += 1;
+        Ok(())
+    }
+
+    /// Suspend devices dependents first, resuming them all again on failure
+    fn suspend_devices(&self) -> Result<(), String> {
+        let devices = self.devices.lock().unwrap();
+        for (index, managed) in devices.iter().enumerate().rev() {
+            if let Err(e) = managed.device.lock().unwrap().suspend() {
+                for resumed in &devices[index + 1..] {
+                    let _ = resumed.device.lock().unwrap().resume();
+                }
+                return Err(format!("Failed to suspend {}: {}", managed.name, e));
+            }
+        }
+        Ok(())
+    }
+
+    /// Wake the system from an enabled wake source
+    pub fn wake(&self, source: &str) -> Result<(), String> {
+        match self.wake_sources.lock().unwrap().get(source) {
+            Some(true) => {}
+            Some(false) => return Err(format!("Wake source {} is disabled", source)),
+            None => return Err(format!("Unknown wake source {}", source)),
+        }
+        if self.status.lock().unwrap().state != SystemState::Suspended {
+            return Err("System is not suspended".to_string());
+        }
+
+        let mut errors = Vec::new();
+        for managed in self.devices.lock().unwrap().iter() {
+            let mut device = managed.device.lock().unwrap();
+            if device.power_state() == PowerState::Suspended {
+                if let Err(e) = device.resume() {
+                    errors.push(format!("{}: {}", managed.name, e));
+                }
+            }
+        }
+        self.resume_vms();
+        {
+            let mut status = self.status.lock().unwrap();
+            status.state = SystemState::Running;
+            status.idle_ms = 0;
+            *self.idle_reported.lock().unwrap() = false;
+            status.last_wake_source = Some(source.to_string());
+        }
+        let listeners = self.listeners.lock().unwrap().clone();
+        self.notify(&listeners, PowerEvent::Resumed);
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Devices failed to resume: {}", errors.join(", ")))
+        }
+    }
+
+    fn notify(&self, listeners: &[(String, PowerListener)], event: PowerEvent) {
+        for (_, listener) in listeners {
+            let _ = listener(event);
+        }
+    }
+
+    fn pause_vms(&self) {
+        let Some(hypervisor) = self.hypervisor.lock().unwrap().clone() else {
+            return;
+        };
+        let paused: Vec<VmId> = hypervisor
+            .list_vms()
+            .iter()
+            .filter(|vm| vm.state == VmState::Running)
+            .filter(|vm| hypervisor.pause_vm(vm.id).is_ok())
+            .map(|vm| vm.id)
+            .collect();
+        *self.paused_vms.lock().unwrap() = paused;
+    }
+
+    fn resume_vms(&self) {
+        let paused = std::mem::take(&mut *self.paused_vms.lock().unwrap());
+        if let Some(hypervisor) = self.hypervisor.lock().unwrap().clone() {
+            for vm in paused {
+                let _ = hypervisor.resume_vm(vm);
+            }
+        }
+    }
+}
+
+impl JsonService for PowerManager {
+    type Request = PowerRequest;
+    type Response = PowerResponse;
+    const KIND: &'static str = "power";
+
+    fn handle_request(&self, request: PowerRequest) -> PowerResponse {
+        let result = match request {
+            PowerRequest::Status => Ok(PowerResponse::Status { status: self.status() }),
+            PowerRequest::Suspend => self.suspend(SuspendReason::Requested).map(|_| PowerResponse::Ok),
+            PowerRequest::Wake { source } => self.wake(&source).map(|_| PowerResponse::Ok),
+            PowerRequest::SetIdleTimeout { timeout_ms } => {
+                self.set_idle_timeout(timeout_ms);
+                Ok(PowerResponse::Ok)
+            }
+            PowerRequest::SetLockTimeout { timeout_ms } => {
+                self.set_lock_timeout(timeout_ms);
+                Ok(PowerResponse::Ok)
+            }
+            PowerRequest::Activity => {
+                self.record_activity();
+                Ok(PowerResponse::Ok)
+            }
+        };
+        result.unwrap_or_else(|message| PowerResponse::Error { message })
+    }
+}
+
+impl Default for PowerManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Request accepted over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum PowerRequest {
+    Status,
+    Suspend,
+    Wake { source: String },
+    SetIdleTimeout { timeout_ms: Option<u64> },
+    SetLockTimeout { timeout_ms: Option<u64> },
+    Activity,
+}
+
+/// Reply sent over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum PowerResponse {
+    Status { status: PowerStatus },
+    Ok,
+    Error { message: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use compatibility::{GuestOS, VmConfig};
+    use hal::{Device, DeviceInfo, DeviceType, ReferenceDevice};
+    use ipc::Message;
+
+    /// Records the order devices are suspended and resumed in
+    struct TracedDevice {
+        name: &'static str,
+        inner: ReferenceDevice,
+        log: Arc<Mutex<Vec<String>>>,
+        fail_suspend: bool,
+    }
+
+    impl Device for TracedDevice {
+        fn info(&self) -> DeviceInfo {
+            self.inner.info()
+        }
+        fn init(&mut self) -> Result<(), String> {
+            self.inner.init()
+        }
+        fn shutdown(&mut self) -> Result<(), String> {
+            self.inner.shutdown()
+        }
+        fn read(&self, offset: usize, buffer: &mut [u8]) -> Result<usize, String> {
+            self.inner.read(offset, buffer)
+        }
+        fn write(&mut self, offset: usize, data: &[u8]) -> Result<usize, String> {
+            self.inner.write(offset, data)
+        }
+    }
+
+    impl PowerManagedDevice for TracedDevice {
+        fn power_state(&self) -> PowerState {
+            self.inner.power_state()
+        }
+        fn suspend(&mut self) -> Result<(), String> {
+            if self.fail_suspend {
+                return Err("busy".to_string());
+            }
+            self.log.lock().unwrap().push(format!("suspend {}", self.name));
+            self.inner.suspend()
+        }
+        fn resume(&mut self) -> Result<(), String> {
+            self.log.lock().unwrap().push(format!("resume {}", self.name));
+            self.inner.resume()
+        }
+    }
+
+    fn device(name: &'static str, log: &Arc<Mutex<Vec<String>>>, fail_suspend: bool) -> SharedDevice {
+        let mut inner = ReferenceDevice::new(DeviceType::Storage);
+        inner.init().unwrap();
+        Arc::new(Mutex::new(TracedDevice {
+            name,
+            inner,
+            log: Arc::clone(log),
+            fail_suspend,
+        }))
+    }
+
+    #[test]
+    fn test_suspend_resume_order() {
+        let power = PowerManager::new();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        power.register_device("pci", device("pci", &log, false), &[]).unwrap();
+        power.register_device("nvme", device("nvme", &log, false), &["pci"]).unwrap();
+        assert!(power.register_device("gpu", device("gpu", &log, false), &["display"]).is_err());
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&events);
+        power.subscribe("net-config", Arc::new(move |event| {
+            seen.lock().unwrap().push(event);
+            Ok(())
+        }));
+
+        power.suspend(SuspendReason::Requested).unwrap();
+        assert_eq!(power.status().state, SystemState::Suspended);
+        assert!(power.wake("lid").is_err());
+        power.wake(POWER_BUTTON).unwrap();
+
+        assert_eq!(*log.lock().unwrap(), vec!["suspend nvme", "suspend pci", "resume pci", "resume nvme"]);
+        assert_eq!(*events.lock().unwrap(), vec![PowerEvent::Suspending, PowerEvent::Resumed]);
+        assert_eq!(power.status().last_wake_source, Some(POWER_BUTTON.to_string()));
+    }
+
+    #[test]
+    fn test_veto_and_device_failure_roll_back() {
+        let power = PowerManager::new();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        power.register_device("pci", device("pci", &log, true), &[]).unwrap();
+        power.register_device("nvme", device("nvme", &log, false), &["pci"]).unwrap();
+
+        let err = power.suspend(SuspendReason::Requested).unwrap_err();
+        assert!(err.contains("pci"));
+        assert_eq!(*log.lock().unwrap(), vec!["suspend nvme", "resume nvme"]);
+        assert_eq!(power.status().state, SystemState::Running);
+
+        power.subscribe("installer", Arc::new(|event| match event {
+            PowerEvent::Suspending => Err("update in progress".to_string()),
+            PowerEvent::Resumed => Ok(()),
+        }));
+        assert!(power.suspend(SuspendReason::Requested).unwrap_err().contains("installer"));
+    }
+
+    #[test]
+    fn test_idle_suspend_pauses_vms() {
+        let power = PowerManager::new();
+        let mut chrysalis = Chrysalis::new();
+        chrysalis.install().unwrap();
+        let vm = chrysalis.create_vm("dev".to_string(), GuestOS::Linux, VmConfig::default()).unwrap();
+        chrysalis.start_vm(vm).unwrap();
+        let chrysalis = Arc::new(chrysalis);
+        power.attach_hypervisor(Arc::clone(&chrysalis));
+
+        power.set_idle_timeout(Some(1_000));
+        power.tick(600).unwrap();
+        power.record_activity();
+        power.tick(600).unwrap();
+        assert_eq!(power.status().state, SystemState::Running);
+        power.tick(400).unwrap();
+        assert_eq!(power.status().last_suspend, Some(SuspendReason::Idle));
+        assert_eq!(chrysalis.get_vm(vm).unwrap().state, VmState::Paused);
+
+        power.add_wake_source("keyboard");
+        power.set_wake_source_enabled("keyboard", false).unwrap();
+        assert!(power.wake("keyboard").is_err());
+        power.set_wake_source_enabled("keyboard", true).unwrap();
+        power.wake("keyboard").unwrap();
+        assert_eq!(chrysalis.get_vm(vm).unwrap().state, VmState::Running);
+    }
+
+    #[test]
+    fn test_lock_timeout_runs_idle_listeners_once() {
+        let power = PowerManager::new();
+        let locks = Arc::new(Mutex::new(0));
+        let count = Arc::clone(&locks);
+        power.on_idle(Arc::new(move || *count.lock().unwrap() += 1));
+        power.handle_request(PowerRequest::SetLockTimeout { timeout_ms: Some(300) });
+        power.set_idle_timeout(Some(1_000));
+
+        power.tick(300).unwrap();
+        power.tick(300).unwrap();
+        assert_eq!(*locks.lock().unwrap(), 1);
+        assert_eq!(power.status().state, SystemState::Running);
+        power.record_activity();
+        power.tick(400).unwrap();
+        assert_eq!(*locks.lock().unwrap(), 2);
+        assert_eq!(power.status().lock_timeout_ms, Some(300));
+    }
+
+    #[test]
+    fn test_power_over_ipc() {
+        let power = PowerManager::new();
+        let message = Message::Request {
+            id: 5,
+            data: br#"{"op":"suspend"}"#.to_vec(),
+        };
+        let Some(Message::Response { data, .. }) = power.handle_message(&message) else {
+            panic!("expected a response");
+        };
+        assert_eq!(serde_json::from_slice::<PowerResponse>(&data).unwrap(), PowerResponse::Ok);
+        match power.handle_request(PowerRequest::Status) {
+            PowerResponse::Status { status } => assert_eq!(status.state, SystemState::Suspended),
+            other => panic!("unexpected response: {:?}", other),
+        }
+        assert!(matches!(power.handle_request(PowerRequest::Suspend), PowerResponse::Error { .. }));
+    }
+}
@@ -0,0 +1,654 @@
+//! Print Spooler
+//!
+//! Accepts print jobs over IPC, renders each document to a PDF spool file
+//! in the VFS and feeds the pages to the printer at its rated speed. Every
+//! printer has its own queue; jobs can be paused, cancelled and reordered,
+//! and whole printers paused. PDF printers have no device and complete
+//! jobs as soon as the spool file is written. Time advances through `tick`.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use filesystem::VirtualFileSystem;
+use hal::{PrinterDevice, PrinterState};
+use ipc::JsonService;
+use serde::{Deserialize, Serialize};
+
+pub mod pdf;
+
+/// Directory holding rendered spool files, one subdirectory per printer
+pub const SPOOL_DIR: &str = "/var/spool/print";
+
+/// Most copies a single job may ask for
+pub const MAX_COPIES: u32 = 99;
+
+/// Printer shared between the spooler and its driver
+pub type SharedPrinter = Arc<Mutex<dyn PrinterDevice>>;
+
+/// Print job identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct JobId(u64);
+
+impl JobId {
+    pub fn new(id: u64) -> Self {
+        JobId(id)
+    }
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Inclusive range of document pages, counted from 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageRange {
+    pub first: u32,
+    pub last: u32,
+}
+
+/// How a document should be printed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrintOptions {
+    #[serde(default = "default_copies")]
+    pub copies: u32,
+    #[serde(default)]
+    pub color: bool,
+    /// Print only these pages
+    #[serde(default)]
+    pub pages: Option<PageRange>,
+}
+
+fn default_copies() -> u32 {
+    1
+}
+
+impl Default for PrintOptions {
+    fn default() -> Self {
+        PrintOptions {
+            copies: 1,
+            color: false,
+            pages: None,
+        }
+    }
+}
+
+/// Where a job is in its lifecycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Printing,
+    Paused,
+    Completed,
+    Cancelled,
+}
+
+impl JobState {
+    fn is_finished(&self) -> bool {
+        matches!(self, JobState::Completed | JobState::Cancelled)
+    }
+}
+
+/// A submitted print job
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrintJob {
+    pub id: JobId,
+    pub printer: String,
+    pub owner: String,
+    pub title: String,
+    pub options: PrintOptions,
+    pub state: JobState,
+    /// Pages to print across all copies
+    pub total_pages: u32,
+    pub pages_printed: u32,
+    /// Rendered PDF in the VFS
+    pub spool_file: PathBuf,
+    pub submitted_at: u64,
+}
+
+/// Printer condition as reported to clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrinterCondition {
+    Ready,
+    Printing,
+    Paused,
+    OutOfPaper,
+    Offline,
+}
+
+/// Status of one printer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrinterStatus {
+    pub name: String,
+    pub condition: PrinterCondition,
+    pub color: bool,
+    /// Unfinished jobs in the queue
+    pub queued: usize,
+    pub current_job: Option<JobId>,
+    pub pages_printed: u64,
+    pub last_error: Option<String>,
+}
+
+struct Printer {
+    /// None for PDF printers
+    device: Option<SharedPrinter>,
+    paused: bool,
+    queue: Vec<JobId>,
+    /// Print time accumulated towards the next page
+    budget_ms: u64,
+    pages_printed: u64,
+    last_error: Option<String>,
+}
+
+struct SpooledJob {
+    job: PrintJob,
+    /// Pages in print order, copies included
+    pages: Vec<Vec<u8>>,
+}
+
+/// Print spooler daemon
+pub struct PrintSpooler {
+    vfs: Arc<VirtualFileSystem>,
+    printers: Arc<Mutex<HashMap<String, Printer>>>,
+    jobs: Arc<Mutex<HashMap<JobId, SpooledJob>>>,
+    clock: Arc<Mutex<u64>>,
+    next_id: Arc<Mutex<u64>>,
+}
+
+impl PrintSpooler {
+    pub fn new(vfs: Arc<VirtualFileSystem>) -> Self {
+        PrintSpooler {
+            vfs,
+            printers: Arc::new(Mutex::new(HashMap::new())),
+            jobs: Arc::new(Mutex::new(HashMap::new())),
+            clock: Arc::new(Mutex::new(0)),
+            next_id: Arc::new(Mutex::new(1)),
+        }
+    }
+
+    /// Register a printer backed by a device
+    pub fn add_printer(&self, name: &str, device: SharedPrinter) -> Result<(), String> {
+        self.insert_printer(name, Some(device))
+    }
+
+    /// Register a printer that only produces PDF files
+    pub fn add_pdf_printer(&self, name: &str) -> Result<(), String> {
+        self.insert_printer(name, None)
+    }
+
+    fn insert_printer(&self, name: &str, device: Option<SharedPrinter>) -> Result<(), String> {
+        if name.is_empty() || name.contains('/') {
+            return Err(format!("Invalid printer name {:?}", name));
+        }
+        let mut printers = self.printers.lock().unwrap();
+        if printers.contains_key(name) {
+            return Err(format!("Printer {} already exists", name));
+        }
+        printers.insert(
+            name.to_string(),
+            Printer {
+                device,
+                paused: false,
+                queue: Vec::new(),
+                budget_ms: 0,
+                pages_printed: 0,
+                last_error: None,
+            },
+        );
+        Ok(())
+    }
+
+    /// Queue a document, rendering it to a spool file
+    pub fn submit(
+        &self,
+        printer: &str,
+        owner: &str,
+        title: &str,
+        document: &[u8],
+        options: PrintOptions,
+    ) -> Result<JobId, String> {
+        let color = {
+            let printers = self.printers.lock().unwrap();
+            let entry = printers.get(printer).ok_or_else(|| format!("Printer {} not found", printer))?;
+            entry.device.as_ref().is_none_or(|device| device.lock().unwrap().supports_color())
+        };
+        if options.color && !color {
+            return Err(format!("Printer {} cannot print in color", printer));
+        }
+        if options.copies == 0 || options.copies > MAX_COPIES {
+            return Err(format!("Copies must be between 1 and {}", MAX_COPIES));
+        }
+
+        let mut pages = pdf::paginate(document);
+        if let Some(range) = options.pages {
+            if range.first == 0 || range.first > range.last || range.last as usize > pages.len() {
+                return Err(format!("Page range {}-{} is outside the document", range.first, range.last));
+            }
+            pages = pages[range.first as usize - 1..range.last as usize].to_vec();
+        }
+
+        let id = {
+            let mut next_id = self.next_id.lock().unwrap();
+            let id = JobId(*next_id);
+            *next_id += 1;
+            id
+        };
+        let spool_file = self.write_spool_file(printer, id, &pdf::render(title, &pages))?;
+        let single: Vec<Vec<u8>> = pages.iter().map(|lines| lines.join("\n").into_bytes()).collect();
+        let pages: Vec<Vec<u8>> = (0..options.copies).flat_map(|_| single.iter().cloned()).collect();
+        let job = PrintJob {
+            id,
+            printer: printer.to_string(),
+            owner: owner.to_string(),
+            title: title.to_string(),
+            options,
+            state: JobState::Queued,
+            total_pages: pages.len() as u32,
+            pages_printed: 0,
+            spool_file,
+            submitted_at: *self.clock.lock().unwrap(),
+        };
+
+        let mut printers = self.printers.lock().unwrap();
+        let entry = printers.get_mut(printer).ok_or_else(|| format!("Printer {} not found", printer))?;
+        entry.queue.push(id);
+        self.jobs.lock().unwrap().insert(id, SpooledJob { job, pages });
+        Ok(id)
+    }
+
+    fn write_spool_file(&self, printer: &str, id: JobId, data: &[u8]) -> Result<PathBuf, String> {
+        let path = Path::new(SPOOL_DIR).join(printer).join(format!("job-{}.pdf", id.0));
+        self.vfs.write_file(&path, data)?;
+        Ok(path)
+    }
+
+    fn update_job<T>(&self, id: JobId, f: impl FnOnce(&mut PrintJob) -> Result<T, String>) -> Result<T, String> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let spooled = jobs.get_mut(&id).ok_or("Job not found")?;
+        if spooled.job.state.is_finished() {
+            return Err(format!("Job {} has already finished", id.0));
+        }
+        f(&mut spooled.job)
+    }
+
+    /// Hold a job in the queue; later jobs print in the meantime
+    pub fn pause_job(&self, id: JobId) -> Result<(), String> {
+        self.update_job(id, |job| {
+            job.state = JobState::Paused;
+            Ok(())
+        })
+    }
+
+    pub fn resume_job(&self, id: JobId) -> Result<(), String> {
+        self.update_job(id, |job| {
+            if job.state == JobState::Paused {
+                job.state = JobState::Queued;
+            }
+            Ok(())
+        })
+    }
+
+    /// Cancel a job and remove its spool file
+    pub fn cancel(&self, id: JobId) -> Result<(), String> {
+        let mut printers = self.printers.lock().unwrap();
+        let (printer, spool_file) = self.update_job(id, |job| {
+            job.state = JobState::Cancelled;
+            Ok((job.printer.clone(), job.spool_file.clone()))
+        })?;
+        if let Some(entry) = printers.get_mut(&printer) {
+            entry.queue.retain(|queued| *queued != id);
+            entry.budget_ms = 0;
+        }
+        drop(printers);
+        if self.vfs.exists(&spool_file) {
+            self.vfs.delete(&spool_file)?;
+        }
+        Ok(())
+    }
+
+    /// Move a job to `position` in its printer's queue
+    pub fn move_job(&self, id: JobId, position: usize) -> Result<(), String> {
+        let mut printers = self.printers.lock().unwrap();
+        let printer = self.update_job(id, |job| Ok(job.printer.clone()))?;
+        let queue = &mut printers.get_mut(&printer).ok_or("Printer not found")?.queue;
+        let current = queue.iter().position(|queued| *queued == id).ok_or("Job not queued")?;
+        let id = queue.remove(current);
+        queue.insert(position.min(queue.len()), id);
+        Ok(())
+    }
+
+    /// Stop starting or continuing jobs on a printer
+    pub fn pause_printer(&self, name: &str) -> Result<(), String> {
+        self.set_printer_paused(name, true)
+    }
+
+    pub fn resume_printer(&self, name: &str) -> Result<(), String> {
+        self.set_printer_paused(name, false)
+    }
+
+    fn set_printer_paused(&self, name: &str, paused: bool) -> Result<(), String> {
+        let mut printers = self.printers.lock().unwrap();
+        let printer = printers.get_mut(name).ok_or_else(|| format!("Printer {} not found", name))?;
+        printer.paused = paused;
+        printer.budget_ms = 0;
+        Ok(())
+    }
+
+    /// Advance time, printing as many pages as each printer's speed allows
+    pub fn tick(&self, elapsed_ms: u64) {
+        *self.clock.lock().unwrap() += elapsed_ms;
+        let mut printers = self.printers.lock().unwrap();
+        let mut jobs = self.jobs.lock().unwrap();
+        for printer in printers.values_mut() {
+            if printer.paused {
+                continue;
+            }
+            printer.budget_ms += elapsed_ms;
+            while let Some(id) = printer
+                .queue
+                .iter()
+                .find(|id| jobs.get(id).is_some_and(|s| s.job.state != JobState::Paused))
+                .copied()
+            {
+                let spooled = jobs.get_mut(&id).unwrap();
+                spooled.job.state = JobState::Printing;
+                if !print_pages(printer, spooled) {
+                    break;
+                }
+                spooled.job.state = JobState::Completed;
+                printer.queue.retain(|queued| *queued != id);
+            }
+            if printer.queue.is_empty() {
+                printer.budget_ms = 0;
+            }
+        }
+    }
+
+    pub fn job(&self, id: JobId) -> Option<PrintJob> {
+        self.jobs.lock().unwrap().get(&id).map(|s| s.job.clone())
+    }
+
+    /// Jobs, optionally of one printer, unfinished ones in queue order first
+    pub fn jobs(&self, printer: Option<&str>) -> Vec<PrintJob> {
+        let printers = self.printers.lock().unwrap();
+        let jobs = self.jobs.lock().unwrap();
+        let position = |job: &PrintJob| {
+            printers
+                .get(&job.printer)
+                .and_then(|p| p.queue.iter().position(|id| *id == job.id))
+                .unwrap_or(usize::MAX)
+        };
+        let mut list: Vec<PrintJob> = jobs
+            .values()
+            .map(|s| s.job.clone())
+            .filter(|job| printer.is_none_or(|name| job.printer == name))
+            .collect();
+        list.sort_by_key(|job| (position(job), job.id));
+        list
+    }
+
+    pub fn printer_status(&self, name: &str) -> Result<PrinterStatus, String> {
+        let printers = self.printers.lock().unwrap();
+        let printer = printers.get(name).ok_or_else(|| format!("Printer {} not found", name))?;
+        let jobs = self.jobs.lock().unwrap();
+        let current_job = printer
+            .queue
+            .iter()
+            .find(|id| jobs.get(id).is_some_and(|s| s.job.state == JobState::Printing))
+            .copied();
+        let (device_state, color) = match &printer.device {
+            Some(device) => {
+                let device = device.lock().unwrap();
+                (device.printer_state(), device.supports_color())
+            }
+            None => (PrinterState::Ready, true),
+        };
+        let condition = match device_state {
+            PrinterState::Offline => PrinterCondition::Offline,
+            PrinterState::OutOfPaper => PrinterCondition::OutOfPaper,
+            PrinterState::Ready if printer.paused => PrinterCondition::Paused,
+            PrinterState::Ready if current_job.is_some() => PrinterCondition::Printing,
+            PrinterState::Ready => PrinterCondition::Ready,
+        };
+        Ok(PrinterStatus {
+            name: name.to_string(),
+            condition,
+            color,
+            queued: printer.queue.len(),
+            current_job,
+            pages_printed: printer.pages_printed,
+            last_error: printer.last_error.clone(),
+        })
+    }
+
+    /// Status of every printer, sorted by name
+    pub fn printers(&self) -> Vec<PrinterStatus> {
+        let mut names: Vec<String> = self.printers.lock().unwrap().keys().cloned().collect();
+        names.sort();
+        names.iter().filter_map(|name| self.printer_status(name).ok()).collect()
+    }
+}
+
+impl JsonService for PrintSpooler {
+    type Request = SpoolerRequest;
+    type Response = SpoolerResponse;
+    const KIND: &'static str = "print";
+
+    fn handle_request(&self, request: SpoolerRequest) -> SpoolerResponse {
+        let result = match request {
+            SpoolerRequest::Submit {
+                printer,
+                owner,
+                title,
+                document,
+                options,
+            } => self
+                .submit(&printer, &owner, &title, &document, options)
+                .map(|id| SpoolerResponse::Submitted { id }),
+            SpoolerRequest::Cancel { id } => self.cancel(id).map(|_| SpoolerResponse::Ok),
+            SpoolerRequest::PauseJob { id } => self.pause_job(id).map(|_| SpoolerResponse::Ok),
+            SpoolerRequest::ResumeJob { id } => self.resume_job(id).map(|_| SpoolerResponse::Ok),
+            SpoolerRequest::MoveJob { id, position } => self.move_job(id, position).map(|_| SpoolerResponse::Ok),
+            SpoolerRequest::PausePrinter { printer } => self.pause_printer(&printer).map(|_| SpoolerResponse::Ok),
+            SpoolerRequest::ResumePrinter { printer } => self.resume_printer(&printer).map(|_| SpoolerResponse::Ok),
+            SpoolerRequest::Jobs { printer } => Ok(SpoolerResponse::Jobs {
+                jobs: self.jobs(printer.as_deref()),
+            }),
+            SpoolerRequest::Status { printer } => {
+                self.printer_status(&printer).map(|status| SpoolerResponse::Status { status })
+            }
+            SpoolerRequest::Printers => Ok(SpoolerResponse::Printers {
+                printers: self.printers(),
+            }),
+        };
+        result.unwrap_or_else(|message| SpoolerResponse::Error { message })
+    }
+}
+
+/// Print what the budget allows of a job, returning whether it is done
+fn print_pages(printer: &mut Printer, spooled: &mut SpooledJob) -> bool {
+    let Some(device) = &printer.device else {
+        printer.pages_printed += (spooled.job.total_pages - spooled.job.pages_printed) as u64;
+        spooled.job.pages_printed = spooled.job.total_pages;
+        return true;
+    };
+    let mut device = device.lock().unwrap();
+    let page_ms = 60_000 / device.pages_per_minute().max(1) as u64;
+    while spooled.job.pages_printed < spooled.job.total_pages {
+        if printer.budget_ms < page_ms {
+            return false;
+        }
+        if let Err(e) = device.print_page(&spooled.pages[spooled.job.pages_printed as usize]) {
+            printer.last_error = Some(e);
+            printer.budget_ms = 0;
+            return false;
+        }
+        printer.budget_ms -= page_ms;
+        printer.pages_printed += 1;
+        printer.last_error = None;
+        spooled.job.pages_printed += 1;
+    }
+    true
+}
+
+/// Request accepted over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum SpoolerRequest {
+    Submit {
+        printer: String,
+        owner: String,
+        title: String,
+        document: Vec<u8>,
+        #[serde(default)]
+        options: PrintOptions,
+    },
+    Cancel { id: JobId },
+    PauseJob { id: JobId },
+    ResumeJob { id: JobId },
+    MoveJob { id: JobId, position: usize },
+    PausePrinter { printer: String },
+    ResumePrinter { printer: String },
+    Jobs {
+        #[serde(default)]
+        printer: Option<String>,
+    },
+    Status { printer: String },
+    Printers,
+}
+
+/// Reply sent over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum SpoolerResponse {
+    Submitted { id: JobId },
+    Jobs { jobs: Vec<PrintJob> },
+    Status { status: PrinterStatus },
+    Printers { printers: Vec<PrinterStatus> },
+    Ok,
+    Error { message: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hal::Device;
+    use ipc::{Message, ERROR_BAD_REQUEST};
+    use reference_driver::printer::ReferencePrinter;
+
+    fn setup(paper: u32) -> (Arc<VirtualFileSystem>, Arc<Mutex<ReferencePrinter>>, PrintSpooler) {
+        let vfs = Arc::new(VirtualFileSystem::new());
+        let mut device = ReferencePrinter::new("laser", 6, false);
+        device.init().unwrap();
+        device.load_paper(paper);
+        let device = Arc::new(Mutex::new(device));
+        let spooler = PrintSpooler::new(Arc::clone(&vfs));
+        spooler.add_printer("laser", device.clone()).unwrap();
+        spooler.add_pdf_printer("pdf").unwrap();
+        (vfs, device, spooler)
+    }
+
+    #[test]
+    fn test_print_at_rated_speed() {
+        let (vfs, device, spooler) = setup(10);
+        let options = PrintOptions {
+            copies: 2,
+            ..PrintOptions::default()
+        };
+        let id = spooler.submit("laser", "ana", "Notes", b"page one\x0cpage two", options).unwrap();
+        let job = spooler.job(id).unwrap();
+        assert_eq!(job.total_pages, 4);
+        assert!(vfs.read_file(&job.spool_file).unwrap().starts_with(b"%PDF-"));
+
+        // 6 pages per minute is one page every 10 seconds
+        spooler.tick(25_000);
+        assert_eq!(spooler.job(id).unwrap().pages_printed, 2);
+        assert_eq!(spooler.printer_status("laser").unwrap().condition, PrinterCondition::Printing);
+        spooler.tick(20_000);
+        assert_eq!(spooler.job(id).unwrap().state, JobState::Completed);
+        let printed = device.lock().unwrap().printed_pages().to_vec();
+        assert_eq!(printed, vec![b"page one".to_vec(), b"page two".to_vec(), b"page one".to_vec(), b"page two".to_vec()]);
+        assert_eq!(spooler.printer_status("laser").unwrap().condition, PrinterCondition::Ready);
+
+        assert!(spooler
+            .submit("laser", "ana", "Photo", b"x", PrintOptions { color: true, ..PrintOptions::default() })
+            .is_err());
+    }
+
+    #[test]
+    fn test_queue_control() {
+        let (vfs, _device, spooler) = setup(10);
+        let first = spooler.submit("laser", "ana", "First", b"1", PrintOptions::default()).unwrap();
+        let second = spooler.submit("laser", "ana", "Second", b"2", PrintOptions::default()).unwrap();
+        let third = spooler.submit("laser", "bo", "Third", b"3", PrintOptions::default()).unwrap();
+
+        spooler.move_job(third, 0).unwrap();
+        spooler.pause_job(third).unwrap();
+        let spool_file = spooler.job(second).unwrap().spool_file;
+        spooler.cancel(second).unwrap();
+        assert!(!vfs.exists(&spool_file));
+        assert!(spooler.cancel(second).is_err());
+
+        spooler.tick(10_000);
+        assert_eq!(spooler.job(first).unwrap().state, JobState::Completed);
+        assert_eq!(spooler.job(third).unwrap().state, JobState::Paused);
+
+        spooler.pause_printer("laser").unwrap();
+        spooler.resume_job(third).unwrap();
+        spooler.tick(10_000);
+        assert_eq!(spooler.printer_status("laser").unwrap().condition, PrinterCondition::Paused);
+        spooler.resume_printer("laser").unwrap();
+        spooler.tick(10_000);
+        assert_eq!(spooler.job(third).unwrap().state, JobState::Completed);
+        let order: Vec<JobId> = spooler.jobs(Some("laser")).iter().map(|job| job.id).collect();
+        assert_eq!(order, vec![first, second, third]);
+    }
+
+    #[test]
+    fn test_out_of_paper() {
+        let (_vfs, device, spooler) = setup(1);
+        let id = spooler.submit("laser", "ana", "Report", b"a\x0cb", PrintOptions::default()).unwrap();
+        spooler.tick(30_000);
+        let status = spooler.printer_status("laser").unwrap();
+        assert_eq!(status.condition, PrinterCondition::OutOfPaper);
+        assert_eq!(status.last_error.as_deref(), Some("Printer is out of paper"));
+        assert_eq!(spooler.job(id).unwrap().pages_printed, 1);
+
+        device.lock().unwrap().load_paper(5);
+        spooler.tick(10_000);
+        assert_eq!(spooler.job(id).unwrap().state, JobState::Completed);
+        assert_eq!(spooler.printer_status("laser").unwrap().pages_printed, 2);
+    }
+
+    #[test]
+    fn test_pdf_printer_over_ipc() {
+        let (vfs, _device, spooler) = setup(0);
+        let message = Message::Request {
+            id: 3,
+            data: br#"{"op":"submit","printer":"pdf","owner":"ana","title":"Memo","document":[104,105],"options":{"pages":{"first":1,"last":1}}}"#.to_vec(),
+        };
+        let Some(Message::Response { data, .. }) = spooler.handle_message(&message) else {
+            panic!("expected a response");
+        };
+        let SpoolerResponse::Submitted { id } = serde_json::from_slice(&data).unwrap() else {
+            panic!("expected a job id");
+        };
+        spooler.tick(1);
+        let job = spooler.job(id).unwrap();
+        assert_eq!(job.state, JobState::Completed);
+        assert_eq!(job.spool_file, PathBuf::from("/var/spool/print/pdf/job-1.pdf"));
+        assert!(vfs.exists(&job.spool_file));
+
+        match spooler.handle_request(SpoolerRequest::Printers) {
+            SpoolerResponse::Printers { printers } => {
+                assert_eq!(printers.len(), 2);
+                assert_eq!(printers[0].condition, PrinterCondition::OutOfPaper);
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+        let bad = Message::Request { id: 4, data: b"{}".to_vec() };
+        assert!(matches!(spooler.handle_message(&bad), Some(Message::Error { code: ERROR_BAD_REQUEST, .. })));
+    }
+}
@@ -0,0 +1,127 @@
+//! PDF rendering
+//!
+//! Lays text documents out on letter-sized pages and writes them as a
+//! minimal PDF 1.4 file set in the built-in Courier font.
+
+/// Lines printed on one page
+pub const LINES_PER_PAGE: usize = 60;
+
+/// Characters per line before wrapping
+pub const LINE_WIDTH: usize = 80;
+
+const FORM_FEED: char = '\x0c';
+
+/// Split a document into pages of lines; a form feed starts a new page
+pub fn paginate(document: &[u8]) -> Vec<Vec<String>> {
+    let text = String::from_utf8_lossy(document);
+    let mut pages = Vec::new();
+    for section in text.split(FORM_FEED) {
+        let mut lines = Vec::new();
+        for line in section.lines() {
+            let chars: Vec<char> = line.replace('\t', "    ").chars().collect();
+            if chars.is_empty() {
+                lines.push(String::new());
+            }
+            lines.extend(chars.chunks(LINE_WIDTH).map(|chunk| chunk.iter().collect::<String>()));
+        }
+        if lines.is_empty() {
+            pages.push(Vec::new());
+        }
+        pages.extend(lines.chunks(LINES_PER_PAGE).map(<[String]>::to_vec));
+    }
+    pages
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' | '(' | ')' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c if c.is_ascii() && !c.is_ascii_control() => escaped.push(c),
+            _ => escaped.push('?'),
+        }
+    }
+    escaped
+}
+
+/// Write `pages` as a PDF document
+pub fn render(title: &str, pages: &[Vec<String>]) -> Vec<u8> {
+    // Objects 1-4 are the catalog, page tree, font and info dictionary;
+    // each page then takes a page object followed by its content stream
+    let kids: Vec<String> = (0..pages.len()).map(|i| format!("{} 0 R", 5 + 2 * i)).collect();
+    let mut objects = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids.join(" "), pages.len()),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Courier >>".to_string(),
+        format!("<< /Title ({}) /Producer (hairr OS print spooler) >>", escape(title)),
+    ];
+    for (i, lines) in pages.iter().enumerate() {
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] \
+             /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+            6 + 2 * i
+        ));
+        let mut content = String::from("BT /F1 10 Tf 12 TL 36 756 Td\n");
+        for line in lines {
+            content.push_str(&format!("({}) Tj T*\n", escape(line)));
+        }
+        content.push_str("ET");
+        objects.push(format!("<< /Length {} >>\nstream\n{}\nendstream", content.len(), content));
+    }
+
+    let mut pdf = String::from("%PDF-1.4\n");
+    let mut offsets = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.push_str(&format!("{} 0 obj\n{}\nendobj\n", i + 1, object));
+    }
+    let xref = pdf.len();
+    pdf.push_str(&format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1));
+    for offset in offsets {
+        pdf.push_str(&format!("{:010} 00000 n \n", offset));
+    }
+    pdf.push_str(&format!(
+        "trailer\n<< /Size {} /Root 1 0 R /Info 4 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref
+    ));
+    pdf.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paginate() {
+        let long = "x".repeat(LINE_WIDTH + 5);
+        let pages = paginate(format!("one\n\n{}\x0ctwo", long).as_bytes());
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0], vec!["one".to_string(), String::new(), "x".repeat(LINE_WIDTH), "xxxxx".to_string()]);
+        assert_eq!(pages[1], vec!["two".to_string()]);
+
+        let lines: Vec<String> = (0..LINES_PER_PAGE + 1).map(|i| i.to_string()).collect();
+        assert_eq!(paginate(lines.join("\n").as_bytes()).len(), 2);
+        assert_eq!(paginate(b"").len(), 1);
+    }
+
+    #[test]
+    fn test_render_structure() {
+        let pdf = render("Report (draft)", &paginate(b"hello\x0cworld"));
+        let text = String::from_utf8(pdf).unwrap();
+        assert!(text.starts_with("%PDF-1.4\n"));
+        assert!(text.ends_with("%%EOF\n"));
+        assert!(text.contains("/Count 2"));
+        assert!(text.contains("/Title (Report \\(draft\\))"));
+        assert!(text.contains("(world) Tj"));
+
+        // The cross-reference table points at each object header
+        let xref = text[text.rfind("startxref\n").unwrap() + 10..].lines().next().unwrap();
+        let table = &text[xref.parse::<usize>().unwrap()..];
+        let third: usize = table.lines().nth(5).unwrap()[..10].parse().unwrap();
+        assert!(text[third..].starts_with("3 0 obj"));
+    }
+}
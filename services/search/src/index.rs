@@ -0,0 +1,183 @@
+//! Inverted index over file names and content
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// Weight of a match in the file name relative to one in the content
+const NAME_WEIGHT: f32 = 4.0;
+
+/// Split text into lowercase alphanumeric terms
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase())
+        .collect()
+}
+
+/// Occurrences of a term in one document
+#[derive(Debug, Clone, Copy, Default)]
+struct Posting {
+    in_name: bool,
+    count: u32,
+}
+
+/// A ranked query result
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub path: PathBuf,
+    pub score: f32,
+}
+
+/// Term to document postings, updated one document at a time
+#[derive(Default)]
+pub struct InvertedIndex {
+    postings: HashMap<String, HashMap<PathBuf, Posting>>,
+    /// Terms each document contributed, so it can be removed again
+    documents: HashMap<PathBuf, Vec<String>>,
+}
+
+impl InvertedIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Index `path`, replacing whatever was indexed for it before
+    pub fn insert(&mut self, path: &Path, content: Option<&str>) {
+        self.remove(path);
+        let mut terms: BTreeMap<String, Posting> = BTreeMap::new();
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        for term in tokenize(&name) {
+            terms.entry(term).or_default().in_name = true;
+        }
+        for term in content.map(tokenize).unwrap_or_default() {
+            terms.entry(term).or_default().count += 1;
+        }
+        for (term, posting) in &terms {
+            self.postings
+                .entry(term.clone())
+                .or_default()
+                .insert(path.to_path_buf(), *posting);
+        }
+        self.documents.insert(path.to_path_buf(), terms.into_keys().collect());
+    }
+
+    pub fn remove(&mut self, path: &Path) -> bool {
+        let Some(terms) = self.documents.remove(path) else {
+            return false;
+        };
+        for term in terms {
+            if let Some(docs) = self.postings.get_mut(&term) {
+                docs.remove(path);
+                if docs.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+        true
+    }
+
+    /// Remove `path` and everything below it
+    pub fn remove_tree(&mut self, path: &Path) -> usize {
+        let doomed: Vec<PathBuf> = self.documents.keys().filter(|p| p.starts_with(path)).cloned().collect();
+        for doc in &doomed {
+            self.remove(doc);
+        }
+        doomed.len()
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.documents.contains_key(path)
+    }
+
+    pub fn document_count(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn term_count(&self) -> usize {
+        self.postings.len()
+    }
+
+    /// Documents matching every query term, best first. The last term
+    /// also matches as a prefix so results update while typing.
+    pub fn query(&self, text: &str, limit: usize) -> Vec<SearchHit> {
+        let terms = tokenize(text);
+        let Some((last, rest)) = terms.split_last() else {
+            return Vec::new();
+        };
+        let total = self.documents.len() as f32;
+
+        let mut scores: Option<HashMap<PathBuf, f32>> = None;
+        let exact = rest.iter().map(|t| vec![t.as_str()]);
+        let prefixed = std::iter::once(
+            self.postings
+                .keys()
+                .filter(|t| t.starts_with(last.as_str()))
+                .map(String::as_str)
+                .collect::<Vec<_>>(),
+        );
+        for alternatives in exact.chain(prefixed) {
+            let mut term_scores: HashMap<PathBuf, f32> = HashMap::new();
+            for term in alternatives {
+                let Some(docs) = self.postings.get(term) else {
+                    continue;
+                };
+                let idf = (1.0 + total / docs.len() as f32).ln();
+                for (path, posting) in docs {
+                    let weight = if posting.in_name { NAME_WEIGHT } else { 0.0 } + (1.0 + posting.count as f32).ln();
+                    let score = term_scores.entry(path.clone()).or_default();
+                    *score = score.max(weight * idf);
+                }
+            }
+            scores = Some(match scores {
+                None => term_scores,
+                Some(previous) => previous
+                    .into_iter()
+                    .filter_map(|(path, score)| term_scores.get(&path).map(|s| (path, score + s)))
+                    .collect(),
+            });
+        }
+
+        let mut hits: Vec<SearchHit> = scores
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(path, score)| SearchHit { path, score })
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.path.cmp(&b.path)));
+        hits.truncate(limit);
+        hits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(tokenize("Quarterly_Report-2024.TXT"), vec!["quarterly", "report", "2024", "txt"]);
+        assert!(tokenize(" -- ").is_empty());
+    }
+
+    #[test]
+    fn test_ranking_and_incremental_updates() {
+        let mut index = InvertedIndex::new();
+        index.insert(Path::new("/docs/budget.txt"), Some("travel costs and hotel costs"));
+        index.insert(Path::new("/docs/notes.txt"), Some("remember the budget meeting"));
+        index.insert(Path::new("/photos/beach.png"), None);
+
+        let hits = index.query("budget", 10);
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].path, Path::new("/docs/budget.txt"));
+        assert_eq!(index.query("budget meet", 10)[0].path, Path::new("/docs/notes.txt"));
+        assert_eq!(index.query("bea", 10)[0].path, Path::new("/photos/beach.png"));
+        assert!(index.query("budget beach", 10).is_empty());
+
+        index.insert(Path::new("/docs/notes.txt"), Some("groceries"));
+        assert_eq!(index.query("budget", 10).len(), 1);
+        assert_eq!(index.remove_tree(Path::new("/docs")), 2);
+        assert!(index.query("groceries", 10).is_empty());
+        assert_eq!(index.document_count(), 1);
+    }
+}
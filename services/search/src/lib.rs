@@ -0,0 +1,232 @@
+//! Search Indexer Service
+//!
+//! Keeps an inverted index of file names and text content in sync with the
+//! VFS. The indexer watches for tree changes and queues them; `process`
+//! applies the queue so content is read outside the filesystem's own
+//! notification path. Ranked queries are served over IPC.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use filesystem::{VfsEvent, VirtualFileSystem};
+use ipc::JsonService;
+use serde::{Deserialize, Serialize};
+
+pub mod index;
+
+pub use index::{tokenize, InvertedIndex, SearchHit};
+
+/// Files larger than this are indexed by name only
+pub const MAX_CONTENT_BYTES: usize = 1024 * 1024;
+
+/// Results returned when a query gives no limit
+pub const DEFAULT_LIMIT: usize = 20;
+
+/// Index size reported over IPC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexStats {
+    pub documents: usize,
+    pub terms: usize,
+    pub pending: usize,
+}
+
+/// Indexer for one filesystem
+pub struct SearchIndexer {
+    vfs: Arc<VirtualFileSystem>,
+    index: Arc<Mutex<InvertedIndex>>,
+    pending: Arc<Mutex<VecDeque<VfsEvent>>>,
+}
+
+impl SearchIndexer {
+    /// Subscribe to `vfs`; call `rebuild` to index what already exists
+    pub fn new(vfs: Arc<VirtualFileSystem>) -> Self {
+        let pending = Arc::new(Mutex::new(VecDeque::new()));
+        let queue = Arc::clone(&pending);
+        vfs.watch(Arc::new(move |event: &VfsEvent| {
+            queue.lock().unwrap().push_back(event.clone());
+        }));
+        SearchIndexer {
+            vfs,
+            index: Arc::new(Mutex::new(InvertedIndex::new())),
+            pending,
+        }
+    }
+
+    /// Drop the index and walk the whole tree
+    pub fn rebuild(&self) -> Result<usize, String> {
+        self.pending.lock().unwrap().clear();
+        let mut index = InvertedIndex::new();
+        let mut directories = vec![PathBuf::from("/")];
+        while let Some(directory) = directories.pop() {
+            for path in self.vfs.list_directory(&directory)? {
+                if self.vfs.metadata(&path)?.is_directory() {
+                    directories.push(path.clone());
+                }
+                self.index_path(&mut index, &path);
+            }
+        }
+        let count = index.document_count();
+        *self.index.lock().unwrap() = index;
+        Ok(count)
+    }
+
+    /// Apply queued filesystem changes; returns how many were applied
+    pub fn process(&self) -> usize {
+        let events: Vec<VfsEvent> = self.pending.lock().unwrap().drain(..).collect();
+        let mut index = self.index.lock().unwrap();
+        for event in &events {
+            match event {
+                VfsEvent::Created { path, .. } | VfsEvent::Modified { path } => self.index_path(&mut index, path),
+                VfsEvent::Deleted { path } => {
+                    index.remove_tree(path);
+                }
+            }
+        }
+        events.len()
+    }
+
+    fn index_path(&self, index: &mut InvertedIndex, path: &Path) {
+        let Ok(metadata) = self.vfs.metadata(path) else {
+            // Already gone again; a later Deleted event will follow
+            return;
+        };
+        let content = if metadata.is_file() && metadata.size as usize <= MAX_CONTENT_BYTES {
+            self.vfs.read_file(path).ok().and_then(text_content)
+        } else {
+            None
+        };
+        index.insert(path, content.as_deref());
+    }
+
+    pub fn query(&self, text: &str, limit: usize) -> Vec<SearchHit> {
+        self.index.lock().unwrap().query(text, limit)
+    }
+
+    pub fn stats(&self) -> IndexStats {
+        let index = self.index.lock().unwrap();
+        IndexStats {
+            documents: index.document_count(),
+            terms: index.term_count(),
+            pending: self.pending.lock().unwrap().len(),
+        }
+    }
+}
+
+impl JsonService for SearchIndexer {
+    type Request = SearchRequest;
+    type Response = SearchResponse;
+    const KIND: &'static str = "search";
+
+    fn handle_request(&self, request: SearchRequest) -> SearchResponse {
+        let result = match request {
+            SearchRequest::Query { text, limit } => {
+                // Answer from an up-to-date index
+                self.process();
+                Ok(SearchResponse::Results {
+                    hits: self.query(&text, limit.unwrap_or(DEFAULT_LIMIT)),
+                })
+            }
+            SearchRequest::Stats => Ok(SearchResponse::Stats { stats: self.stats() }),
+            SearchRequest::Rebuild => self.rebuild().map(|_| SearchResponse::Ok),
+        };
+        result.unwrap_or_else(|message| SearchResponse::Error { message })
+    }
+}
+
+/// Content worth indexing: valid UTF-8 without NUL bytes
+fn text_content(bytes: Vec<u8>) -> Option<String> {
+    if bytes.contains(&0) {
+        return None;
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Requests accepted over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum SearchRequest {
+    Query { text: String, limit: Option<usize> },
+    Stats,
+    Rebuild,
+}
+
+/// Responses sent over IPC
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum SearchResponse {
+    Results { hits: Vec<SearchHit> },
+    Stats { stats: IndexStats },
+    Ok,
+    Error { message: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use filesystem::{OpenOptions, ProcessId};
+    use ipc::{Message, ERROR_BAD_REQUEST};
+
+    fn write(vfs: &VirtualFileSystem, path: &str, data: &[u8]) {
+        let handle = vfs.open(ProcessId::KERNEL, Path::new(path), OpenOptions::write_only()).unwrap();
+        vfs.write(ProcessId::KERNEL, handle, data).unwrap();
+        vfs.close(ProcessId::KERNEL, handle).unwrap();
+    }
+
+    #[test]
+    fn test_rebuild_and_incremental_updates() {
+        let vfs = Arc::new(VirtualFileSystem::new());
+        vfs.create_directory(Path::new("/home")).unwrap();
+        write(&vfs, "/home/recipe.txt", b"pancakes need flour and eggs");
+
+        let indexer = SearchIndexer::new(Arc::clone(&vfs));
+        assert!(indexer.query("flour", 10).is_empty());
+        assert_eq!(indexer.rebuild().unwrap(), 2);
+        assert_eq!(indexer.query("flour", 10)[0].path, Path::new("/home/recipe.txt"));
+
+        write(&vfs, "/home/shopping.txt", b"eggs milk");
+        write(&vfs, "/home/blob.bin", &[0, 159, 146, 150]);
+        assert_eq!(indexer.stats().pending, 4);
+        indexer.process();
+        assert_eq!(indexer.query("eggs", 10).len(), 2);
+        assert_eq!(indexer.query("blob", 10).len(), 1);
+
+        vfs.delete(Path::new("/home/recipe.txt")).unwrap();
+        indexer.process();
+        let hits = indexer.query("eggs", 10);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].path, Path::new("/home/shopping.txt"));
+    }
+
+    #[test]
+    fn test_ipc_query_processes_pending_changes() {
+        let vfs = Arc::new(VirtualFileSystem::new());
+        let indexer = SearchIndexer::new(Arc::clone(&vfs));
+        write(&vfs, "/holiday-plan.md", b"flights to lisbon");
+
+        let request = SearchRequest::Query {
+            text: "lisb".to_string(),
+            limit: None,
+        };
+        let reply = indexer
+            .handle_message(&Message::Request {
+                id: 5,
+                data: serde_json::to_vec(&request).unwrap(),
+            })
+            .unwrap();
+        match reply {
+            Message::Response { id: 5, data } => match serde_json::from_slice(&data).unwrap() {
+                SearchResponse::Results { hits } => assert_eq!(hits[0].path, Path::new("/holiday-plan.md")),
+                other => panic!("unexpected response: {:?}", other),
+            },
+            other => panic!("unexpected reply: {:?}", other),
+        }
+
+        match indexer.handle_request(SearchRequest::Stats) {
+            SearchResponse::Stats { stats } => assert_eq!((stats.documents, stats.pending), (1, 0)),
+            other => panic!("unexpected response: {:?}", other),
+        }
+        let bad = indexer.handle_message(&Message::Request { id: 6, data: b"7".to_vec() });
+        assert!(matches!(bad, Some(Message::Error { code: ERROR_BAD_REQUEST, .. })));
+    }
+}
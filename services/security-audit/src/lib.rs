@@ -0,0 +1,479 @@
+//! Security Audit Service
+//!
+//! Collects security events from across the system: failed capability
+//! checks, denied keystore operations, packets the firewall drops and
+//! failed logins. Events are grouped by the subject behind them (a user,
+//! process, key or remote host) and kept for a retention period, and
+//! detection rules run over each subject's recent events. When a rule
+//! fires, an alert is recorded and posted through the notification
+//! service. Time advances through `tick`.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+
+use capability::{CapabilityManager, CapabilityToken};
+use ipc::JsonService;
+use keystore::Keystore;
+use net_stack::firewall::Firewall;
+use notifications::{NotificationId, NotificationRequest, NotificationService};
+use serde::{Deserialize, Serialize};
+use users::UserService;
+
+pub mod rules;
+
+pub use rules::{default_rules, DetectionRule};
+
+/// Application id alerts are posted under
+pub const APP_ID: &str = "security";
+
+/// How long events are kept for correlation
+pub const RETENTION_MS: u64 = 3_600_000;
+
+/// Events kept per subject within the retention period
+pub const MAX_EVENTS_PER_SUBJECT: usize = 256;
+
+/// Kind of security event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// A capability check failed
+    PermissionDenied,
+    /// The keystore refused a key operation under its policy
+    KeyDenied,
+    /// The firewall dropped a packet
+    PacketDenied,
+    LoginFailed,
+    /// A sandboxed app asked for something outside its profile
+    SandboxViolation,
+}
+
+/// Who or what an event is attributed to
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Subject {
+    User(String),
+    Process(u64),
+    Key(String),
+    /// Remote host of inbound traffic no local process owns
+    Remote(Ipv4Addr),
+    /// Bearer capability presented without a user
+    Capability(CapabilityToken),
+}
+
+impl fmt::Display for Subject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Subject::User(name) => write!(f, "user {}", name),
+            Subject::Process(pid) => write!(f, "process {}", pid),
+            Subject::Key(key) => write!(f, "key {}", key),
+            Subject::Remote(address) => write!(f, "host {}", address),
+            Subject::Capability(token) => write!(f, "capability {:?}", token),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEvent {
+    /// Service time the event was recorded at
+    pub time_ms: u64,
+    pub kind: EventKind,
+    pub subject: Subject,
+    pub detail: String,
+}
+
+/// A detection rule that fired
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Alert {
+    pub rule: String,
+    pub subject: Subject,
+    /// Matching events within the rule's window
+    pub events: usize,
+    pub time_ms: u64,
+    /// `None` if the notification could not be posted
+    pub notification: Option<NotificationId>,
+}
+
+/// The security audit daemon
+#[derive(Clone)]
+pub struct SecurityAuditService {
+    notifications: Arc<NotificationService>,
+    rules: Arc<Mutex<Vec<DetectionRule>>>,
+    events: Arc<Mutex<HashMap<Subject, VecDeque<AuditEvent>>>>,
+    /// When each rule last fired for a subject
+    fired: Arc<Mutex<HashMap<(String, Subject), u64>>>,
+    alerts: Arc<Mutex<Vec<Alert>>>,
+    clock: Arc<Mutex<u64>>,
+}
+
+impl SecurityAuditService {
+    /// Create the service with the default rules
+    pub fn new(notifications: Arc<NotificationService>) -> Self {
+        SecurityAuditService {
+            notifications,
+            rules: Arc::new(Mutex::new(default_rules())),
+            events: Arc::new(Mutex::new(HashMap::new())),
+            fired: Arc::new(Mutex::new(HashMap::new())),
+            alerts: Arc::new(Mutex::new(Vec::new())),
+            clock: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    /// Audit failed capability checks, attributed to the user presenting
+    /// or owning the capability
+    pub fn attach_capabilities(&self, capabilities: &Arc<CapabilityManager>) {
+        let service = self.clone();
+        // The manager keeps the listener, so hold it weakly
+        let directory = Arc::downgrade(capabilities);
+        capabilities.on_denied(Arc::new(move |denial| {
+            let subject = match denial.uid {
+                Some(uid) => {
+                    let name = directory.upgrade().and_then(|c| c.user_name(uid));
+                    Subject::User(name.unwrap_or_else(|| uid.to_string()))
+                }
+                None => Subject::Capability(denial.token),
+            };
+            let detail = match &denial.resource {
+                Some(resource) => format!("{:?} access to {:?} denied", denial.required, resource),
+                None => "Invalid capability".to_string(),
+            };
+            service.record(EventKind::PermissionDenied, subject, detail);
+        }));
+    }
+
+    /// Audit key operations the keystore's policies deny
+    pub fn attach_keystore(&self, keystore: &Keystore) {
+        let service = self.clone();
+        keystore.on_denied(Arc::new(move |attempt| {
+            let detail = format!("{:?}: {}", attempt.operation, attempt.reason.as_str());
+            service.record(EventKind::KeyDenied, Subject::Key(attempt.key_id.as_str().to_string()), detail);
+        }));
+    }
+
+    /// Audit dropped packets, attributed to the owning process or else the
+    /// remote host
+    pub fn attach_firewall(&self, firewall: &Firewall) {
+        let service = self.clone();
+        firewall.on_denied(Arc::new(move |packet| {
+            let subject = match packet.owner {
+                Some(owner) => Subject::Process(owner.value()),
+                None => Subject::Remote(packet.remote),
+            };
+            let port = packet.local_port.map_or("-".to_string(), |port| port.to_string());
+            let detail = format!("{:?} {:?} packet on port {}", packet.direction, packet.protocol, port);
+            service.record(EventKind::PacketDenied, subject, detail);
+        }));
+    }
+
+    /// Audit failed logins, attributed to the account name tried
+    pub fn attach_users(&self, users: &UserService) {
+        let service = self.clone();
+        users.on_failed_login(Arc::new(move |failure| {
+            let detail = match failure.uid {
+                Some(_) => "Wrong password".to_string(),
+                None => "No such user".to_string(),
+            };
+            service.record(EventKind::LoginFailed, Subject::User(failure.name.clone()), detail);
+        }));
+    }
+
+    pub fn add_rule(&self, rule: DetectionRule) -> Result<(), String> {
+        let mut rules = self.rules.lock().unwrap();
+        if rules.iter().any(|r| r.name == rule.name) {
+            return Err(format!("Rule {} already exists", rule.name));
+        }
+        rules.push(rule);
+        Ok(())
+    }
+
+    pub fn remove_rule(&self, name: &str) -> Result<(), String> {
+        let mut rules = self.rules.lock().unwrap();
+        let index = rules.iter().position(|r| r.name == name).ok_or("Rule not found")?;
+        rules.remove(index);
+        Ok(())
+    }
+
+    pub fn rules(&self) -> Vec<DetectionRule> {
+        self.rules.lock().unwrap().clone()
+    }
+
+    /// Record an event and run the rules watching its kind
+    pub fn record(&self, kind: EventKind, subject: Subject, detail: String) {
+        let now = *self.clock.lock().unwrap();
+        let counts: Vec<(DetectionRule, usize)> = {
+            let mut events = self.events.lock().unwrap();
+            let history = events.entry(subject.clone()).or_default();
+            if history.len() >= MAX_EVENTS_PER_SUBJECT {
+                history.pop_front();
+            }
+            history.push_back(AuditEvent {
+                time_ms: now,
+                kind,
+                subject: subject.clone(),
+                detail,
+            });
+            self.rules
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|rule| rule.watches(kind))
+                .map(|rule| {
+                    let count = history
+                        .iter()
+                        .filter(|e| now - e.time_ms < rule.window_ms && rule.watches(e.kind))
+                        .count();
+                    (rule.clone(), count)
+                })
+                .collect()
+        };
+        for (rule, count) in counts {
+            if count >= rule.threshold && self.arm(&rule, &subject, now) {
+                self.raise(&rule, subject.clone(), count, now);
+            }
+        }
+    }
+
+    /// Whether `rule` may fire for `subject`, marking it fired if so
+    fn arm(&self, rule: &DetectionRule, subject: &Subject, now: u64) -> bool {
+        let mut fired = self.fired.lock().unwrap();
+        let key = (rule.name.clone(), subject.clone());
+        if fired.get(&key).is_some_and(|last| now - last < rule.window_ms) {
+            return false;
+        }
+        fired.insert(key, now);
+        true
+    }
+
+    fn raise(&self, rule: &DetectionRule, subject: Subject, events: usize, now: u64) {
+        let body = format!("{}: {} events in {} s", subject, events, rule.window_ms / 1000);
+        let request = NotificationRequest::new(APP_ID, &rule.description, &body, rule.urgency);
+        // A flood of alerts is throttled by the notification rate limit, but
+        // every alert is still recorded here
+        let notification = self.notifications.notify(request).ok();
+        self.alerts.lock().unwrap().push(Alert {
+            rule: rule.name.clone(),
+            subject,
+            events,
+            time_ms: now,
+            notification,
+        });
+    }
+
+    /// Advance time, dropping events past the retention period
+    pub fn tick(&self, elapsed_ms: u64) {
+        let now = {
+            let mut clock = self.clock.lock().unwrap();
+            *clock += elapsed_ms;
+            *clock
+        };
+        let cutoff = now.saturating_sub(RETENTION_MS);
+        let mut events = self.events.lock().unwrap();
+        for history in events.values_mut() {
+            while history.front().is_some_and(|e| e.time_ms < cutoff) {
+                history.pop_front();
+            }
+        }
+        events.retain(|_, history| !history.is_empty());
+    }
+
+    /// Retained events of one subject, oldest first
+    pub fn events_for(&self, subject: &Subject) -> Vec<AuditEvent> {
+        self.events
+            .lock()
+            .unwrap()
+            .get(subject)
+            .map(|history| history.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Subjects with retained events and how many, busiest first
+    pub fn subjects(&self) -> Vec<(Subject, usize)> {
+        let mut subjects: Vec<(Subject, usize)> = self
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(subject, history)| (subject.clone(), history.len()))
+            .collect();
+        subjects.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.to_string().cmp(&b.0.to_string())));
+        subjects
+    }
+
+    /// Alerts raised so far, oldest first
+    pub fn alerts(&self) -> Vec<Alert> {
+        self.alerts.lock().unwrap().clone()
+    }
+}
+
+impl JsonService for SecurityAuditService {
+    type Request = SecurityRequest;
+    type Response = SecurityResponse;
+    const KIND: &'static str = "security";
+
+    fn handle_request(&self, request: SecurityRequest) -> SecurityResponse {
+        match request {
+            SecurityRequest::Alerts => SecurityResponse::Alerts { alerts: self.alerts() },
+            SecurityRequest::Subjects => SecurityResponse::Subjects { subjects: self.subjects() },
+            SecurityRequest::Events { subject } => SecurityResponse::Events {
+                events: self.events_for(&subject),
+            },
+        }
+    }
+}
+
+/// Requests accepted over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum SecurityRequest {
+    Alerts,
+    Subjects,
+    Events { subject: Subject },
+}
+
+/// Replies to `SecurityRequest`s
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum SecurityResponse {
+    Alerts { alerts: Vec<Alert> },
+    Subjects { subjects: Vec<(Subject, usize)> },
+    Events { events: Vec<AuditEvent> },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use capability::{Permission, Resource};
+    use ipc::{Message, ERROR_BAD_REQUEST};
+    use keystore::{AuthorizationPolicy, KeyType, KeyUsage};
+    use net_stack::firewall::{Action, Direction, FirewallRule, PacketInfo, Protocol, FIREWALL_RESOURCE};
+    use notifications::Urgency;
+
+    fn service() -> (SecurityAuditService, Arc<NotificationService>) {
+        let notifications = Arc::new(NotificationService::new());
+        (SecurityAuditService::new(Arc::clone(&notifications)), notifications)
+    }
+
+    #[test]
+    fn test_repeated_permission_failures() {
+        let (audit, notifications) = service();
+        let capabilities = Arc::new(CapabilityManager::new());
+        audit.attach_capabilities(&capabilities);
+        let camera = capabilities.grant_to(1000, Resource::Device("camera".to_string()), Permission::Read);
+
+        for _ in 0..4 {
+            assert!(!capabilities.check_user_permission(camera, 1000, Permission::Write));
+        }
+        assert!(audit.alerts().is_empty());
+        // Checks that pass are not audited
+        assert!(capabilities.check_user_permission(camera, 1000, Permission::Read));
+        assert!(!capabilities.check_user_permission(camera, 1000, Permission::Write));
+
+        let alerts = audit.alerts();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!((alerts[0].rule.as_str(), &alerts[0].subject), ("repeated-permission-failures", &Subject::User("1000".to_string())));
+        let notification = notifications.get(alerts[0].notification.unwrap()).unwrap();
+        assert_eq!((notification.app_id.as_str(), notification.title.as_str()), (APP_ID, "Repeated permission failures"));
+        assert_eq!(notification.body, "user 1000: 5 events in 60 s");
+
+        // Once per window per subject
+        assert!(!capabilities.check_user_permission(camera, 1000, Permission::Write));
+        assert_eq!(audit.alerts().len(), 1);
+        audit.tick(60_000);
+        for _ in 0..4 {
+            assert!(!capabilities.check_user_permission(camera, 1000, Permission::Write));
+        }
+        assert_eq!(audit.alerts().len(), 1);
+        assert!(!capabilities.check_user_permission(camera, 1000, Permission::Write));
+        assert_eq!(audit.alerts().len(), 2);
+        assert_eq!(audit.events_for(&Subject::User("1000".to_string())).len(), 11);
+    }
+
+    #[test]
+    fn test_correlates_sources_by_subject() {
+        let (audit, notifications) = service();
+        let keystore = Arc::new(Keystore::new());
+        let capabilities = Arc::new(CapabilityManager::new());
+        let users = UserService::new(Arc::clone(&keystore), Arc::clone(&capabilities)).unwrap();
+        let firewall = Firewall::new(Arc::clone(&capabilities));
+        audit.attach_keystore(&keystore);
+        audit.attach_users(&users);
+        audit.attach_firewall(&firewall);
+        users.create_user("alice", "pw").unwrap();
+
+        for _ in 0..5 {
+            assert!(users.login("alice", "guess").is_err());
+        }
+        assert!(users.login("mallory", "guess").is_err());
+        assert!(users.login("alice", "pw").is_ok());
+
+        let wallet = keystore.generate_key("wallet".into(), KeyType::Ed25519, vec![KeyUsage::Sign], false).unwrap();
+        keystore.set_policy(&wallet, AuthorizationPolicy::signing(1)).unwrap();
+        keystore.set_authenticator(Arc::new(|_| false));
+        for _ in 0..3 {
+            assert!(keystore.sign(&wallet, b"tx").is_err());
+        }
+
+        let admin = capabilities.grant(Resource::Network(FIREWALL_RESOURCE.to_string()), Permission::Write);
+        firewall.set_default_action(admin, Action::Deny).unwrap();
+        firewall.add_rule(admin, FirewallRule::allow().with_direction(Direction::Outbound)).unwrap();
+        let packet = PacketInfo {
+            direction: Direction::Inbound,
+            interface: "eth0".to_string(),
+            protocol: Some(Protocol::Tcp),
+            remote: Ipv4Addr::new(203, 0, 113, 9),
+            local_port: Some(22),
+            remote_port: Some(40000),
+            owner: None,
+            len: 60,
+        };
+        for _ in 0..20 {
+            assert_eq!(firewall.check(&packet), Action::Deny);
+        }
+
+        let fired: Vec<(String, Subject)> = audit.alerts().into_iter().map(|a| (a.rule, a.subject)).collect();
+        assert_eq!(
+            fired,
+            vec![
+                ("password-guessing".to_string(), Subject::User("alice".to_string())),
+                ("key-misuse".to_string(), Subject::Key("wallet".to_string())),
+                ("blocked-traffic".to_string(), Subject::Remote(Ipv4Addr::new(203, 0, 113, 9))),
+            ]
+        );
+        assert_eq!(notifications.active()[0].urgency, Urgency::Critical);
+        assert_eq!(audit.subjects()[0], (Subject::Remote(Ipv4Addr::new(203, 0, 113, 9)), 20));
+        assert_eq!(audit.events_for(&Subject::User("mallory".to_string()))[0].detail, "No such user");
+
+        audit.tick(RETENTION_MS + 1);
+        assert!(audit.subjects().is_empty());
+    }
+
+    #[test]
+    fn test_custom_rules_and_requests() {
+        let (audit, _) = service();
+        assert!(audit.add_rule(DetectionRule::new("key-misuse", "", &[], 1, 0)).is_err());
+        audit
+            .add_rule(DetectionRule::new("any-denial", "Denied activity", &[EventKind::PermissionDenied, EventKind::KeyDenied], 2, 1_000))
+            .unwrap();
+        audit.record(EventKind::PermissionDenied, Subject::Process(7), "open /etc/shadow".to_string());
+        audit.tick(500);
+        audit.record(EventKind::KeyDenied, Subject::Process(7), "sign".to_string());
+        assert_eq!(audit.alerts()[0].rule, "any-denial");
+        audit.remove_rule("any-denial").unwrap();
+        assert!(audit.remove_rule("any-denial").is_err());
+
+        let request = Message::Request {
+            id: 3,
+            data: serde_json::to_vec(&SecurityRequest::Events { subject: Subject::Process(7) }).unwrap(),
+        };
+        let Some(Message::Response { id: 3, data }) = audit.handle_message(&request) else {
+            panic!("expected a response");
+        };
+        let SecurityResponse::Events { events } = serde_json::from_slice(&data).unwrap() else {
+            panic!("expected events");
+        };
+        assert_eq!(events.len(), 2);
+        let bad = Message::Request { id: 4, data: b"{}".to_vec() };
+        assert!(matches!(audit.handle_message(&bad), Some(Message::Error { code: ERROR_BAD_REQUEST, .. })));
+    }
+}
@@ -0,0 +1,80 @@
+//! Detection rules
+//!
+//! A rule fires when one subject produces `threshold` events of the kinds
+//! it watches within `window_ms`. It fires at most once per window for a
+//! subject, so a sustained attack raises one alert per window rather than
+//! one per event.
+
+use notifications::Urgency;
+use serde::{Deserialize, Serialize};
+
+use crate::EventKind;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DetectionRule {
+    pub name: String,
+    /// Shown as the alert's title
+    pub description: String,
+    pub kinds: Vec<EventKind>,
+    pub threshold: usize,
+    pub window_ms: u64,
+    pub urgency: Urgency,
+}
+
+impl DetectionRule {
+    pub fn new(name: &str, description: &str, kinds: &[EventKind], threshold: usize, window_ms: u64) -> Self {
+        DetectionRule {
+            name: name.to_string(),
+            description: description.to_string(),
+            kinds: kinds.to_vec(),
+            threshold: threshold.max(1),
+            window_ms,
+            urgency: Urgency::Normal,
+        }
+    }
+
+    pub fn with_urgency(mut self, urgency: Urgency) -> Self {
+        self.urgency = urgency;
+        self
+    }
+
+    pub fn watches(&self, kind: EventKind) -> bool {
+        self.kinds.contains(&kind)
+    }
+}
+
+/// Rules the service starts with
+pub fn default_rules() -> Vec<DetectionRule> {
+    vec![
+        DetectionRule::new(
+            "repeated-permission-failures",
+            "Repeated permission failures",
+            &[EventKind::PermissionDenied, EventKind::SandboxViolation],
+            5,
+            60_000,
+        ),
+        DetectionRule::new(
+            "password-guessing",
+            "Repeated failed logins",
+            &[EventKind::LoginFailed],
+            5,
+            300_000,
+        )
+        .with_urgency(Urgency::Critical),
+        DetectionRule::new(
+            "key-misuse",
+            "Repeated denied key operations",
+            &[EventKind::KeyDenied],
+            3,
+            60_000,
+        )
+        .with_urgency(Urgency::Critical),
+        DetectionRule::new(
+            "blocked-traffic",
+            "Sustained blocked network traffic",
+            &[EventKind::PacketDenied],
+            20,
+            10_000,
+        ),
+    ]
+}
@@ -0,0 +1,415 @@
+//! Session Manager
+//!
+//! Runs the login flow on top of the users service. A login opens a user
+//! session with its own environment and capability set, owned by the
+//! user's uid, then launches the user's shell and autostart apps as kernel
+//! processes. Locked sessions keep running but refuse new work until the
+//! password is entered again; logging out terminates every process the
+//! session started and revokes its capabilities. Apps installed from a
+//! package are launched in the sandbox profile generated at install time,
+//! holding only what it lists.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use capability::{CapabilityManager, CapabilityToken, Permission, Resource};
+use ipc::JsonService;
+use kernel::{Kernel, Priority, ProcessId, SandboxProfile};
+use serde::{Deserialize, Serialize};
+use users::{SessionToken, User, UserService};
+
+/// Shell launched when a profile names none
+pub const DEFAULT_SHELL: &str = "shell";
+
+/// Sandbox profile of an installed app, by app name; `None` for apps that
+/// were not installed from a package, and an error for an installed app
+/// whose sandbox cannot be set up
+pub type AppSandbox = Arc<dyn Fn(&str) -> Result<Option<SandboxProfile>, String> + Send + Sync>;
+
+/// What a user's session starts with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionProfile {
+    pub shell: String,
+    pub autostart: Vec<String>,
+    /// Granted on top of full access to the home directory
+    pub capabilities: Vec<(Resource, Permission)>,
+    /// Merged over the standard variables
+    pub environment: BTreeMap<String, String>,
+}
+
+impl Default for SessionProfile {
+    fn default() -> Self {
+        SessionProfile {
+            shell: DEFAULT_SHELL.to_string(),
+            autostart: Vec::new(),
+            capabilities: Vec::new(),
+            environment: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionState {
+    Active,
+    Locked,
+}
+
+/// A logged-in user session
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserSession {
+    pub token: SessionToken,
+    pub user: User,
+    pub state: SessionState,
+    pub environment: BTreeMap<String, String>,
+    pub capabilities: Vec<CapabilityToken>,
+    pub shell: ProcessId,
+    /// Every live process the session started, shell included
+    pub processes: Vec<ProcessId>,
+}
+
+/// Login, lock and logout for interactive users
+pub struct SessionManager {
+    users: Arc<UserService>,
+    kernel: Arc<Kernel>,
+    capabilities: Arc<CapabilityManager>,
+    sessions: Arc<Mutex<HashMap<SessionToken, UserSession>>>,
+    profiles: Arc<Mutex<HashMap<String, SessionProfile>>>,
+    default_profile: Arc<Mutex<SessionProfile>>,
+    app_sandbox: Arc<Mutex<Option<AppSandbox>>>,
+}
+
+impl SessionManager {
+    pub fn new(users: Arc<UserService>, kernel: Arc<Kernel>, capabilities: Arc<CapabilityManager>) -> Self {
+        let sessions: Arc<Mutex<HashMap<SessionToken, UserSession>>> = Arc::new(Mutex::new(HashMap::new()));
+        let tracked = Arc::clone(&sessions);
+        kernel.on_process_terminated(Arc::new(move |pid| {
+            for session in tracked.lock().unwrap().values_mut() {
+                session.processes.retain(|p| *p != pid);
+            }
+        }));
+        kernel.attach_capabilities(Arc::clone(&capabilities));
+        SessionManager {
+            users,
+            kernel,
+            capabilities,
+            sessions,
+            profiles: Arc::new(Mutex::new(HashMap::new())),
+            default_profile: Arc::new(Mutex::new(SessionProfile::default())),
+            app_sandbox: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Look up the sandbox installed apps are launched in
+    pub fn set_app_sandbox(&self, lookup: AppSandbox) {
+        *self.app_sandbox.lock().unwrap() = Some(lookup);
+    }
+
+    /// Profile for users without one of their own
+    pub fn set_default_profile(&self, profile: SessionProfile) {
+        *self.default_profile.lock().unwrap() = profile;
+    }
+
+    pub fn set_profile(&self, user: &str, profile: SessionProfile) -> Result<(), String> {
+        if self.users.user(user).is_none() {
+            return Err(format!("Unknown user: {}", user));
+        }
+        self.profiles.lock().unwrap().insert(user.to_string(), profile);
+        Ok(())
+    }
+
+    fn profile_for(&self, user: &str) -> SessionProfile {
+        self.profiles
+            .lock()
+            .unwrap()
+            .get(user)
+            .cloned()
+            .unwrap_or_else(|| self.default_profile.lock().unwrap().clone())
+    }
+
+    /// Authenticate and start a session
+    pub fn login(&self, name: &str, password: &str) -> Result<UserSession, String> {
+        let token = self.users.login(name, password)?;
+        let user = self.users.session_user(&token).ok_or("Session ended during login")?;
+        let profile = self.profile_for(name);
+
+        let mut environment: BTreeMap<String, String> = [
+            ("HOME", user.home.clone()),
+            ("USER", user.name.clone()),
+            ("LOGNAME", user.name.clone()),
+            ("SHELL", profile.shell.clone()),
+            ("PATH", "/bin:/usr/bin".to_string()),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v))
+        .collect();
+        environment.extend(profile.environment.clone());
+
+        let mut capabilities = vec![self
+            .capabilities
+            .grant_to(user.uid, Resource::File(user.home.clone()), Permission::Full)];
+        for (resource, permission) in &profile.capabilities {
+            capabilities.push(self.capabilities.grant_to(user.uid, resource.clone(), *permission));
+        }
+
+        let shell = self.kernel.create_process(profile.shell.clone(), Priority::Normal);
+        let mut processes = vec![shell];
+        for app in &profile.autostart {
+            processes.push(self.kernel.create_process(app.clone(), Priority::Normal));
+        }
+
+        let session = UserSession {
+            token: token.clone(),
+            user,
+            state: SessionState::Active,
+            environment,
+            capabilities,
+            shell,
+            processes,
+        };
+        self.sessions.lock().unwrap().insert(token, session.clone());
+        Ok(session)
+    }
+
+    pub fn session(&self, token: &SessionToken) -> Option<UserSession> {
+        self.sessions.lock().unwrap().get(token).cloned()
+    }
+
+    pub fn list_sessions(&self) -> Vec<UserSession> {
+        let mut sessions: Vec<UserSession> = self.sessions.lock().unwrap().values().cloned().collect();
+        sessions.sort_by_key(|s| s.user.uid);
+        sessions
+    }
+
+    pub fn lock(&self, token: &SessionToken) -> Result<(), String> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.get_mut(token).ok_or("Unknown session")?;
+        session.state = SessionState::Locked;
+        Ok(())
+    }
+
+    /// Unlock with the session user's password
+    pub fn unlock(&self, token: &SessionToken, password: &str) -> Result<(), String> {
+        let name = self.session(token).ok_or("Unknown session")?.user.name;
+        self.users.verify_password(&name, password)?;
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.get_mut(token).ok_or("Unknown session")?;
+        session.state = SessionState::Active;
+        Ok(())
+    }
+
+    /// Start another app in an unlocked session. A packaged app runs in
+    /// its sandbox and holds nothing it does not list.
+    pub fn launch(&self, token: &SessionToken, app: &str) -> Result<ProcessId, String> {
+        let sandbox = self.sandbox_for(app)?;
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.get_mut(token).ok_or("Unknown session")?;
+        if session.state == SessionState::Locked {
+            return Err("Session is locked".to_string());
+        }
+        let pid = match sandbox {
+            Some(profile) => self.kernel.spawn_sandboxed(app.to_string(), Priority::Normal, profile)?,
+            None => self.kernel.create_process(app.to_string(), Priority::Normal),
+        };
+        session.processes.push(pid);
+        Ok(pid)
+    }
+
+    /// Sandbox `app` is launched in, if it was installed from a package
+    pub fn sandbox_for(&self, app: &str) -> Result<Option<SandboxProfile>, String> {
+        match self.app_sandbox.lock().unwrap().clone() {
+            Some(lookup) => lookup(app),
+            None => Ok(None),
+        }
+    }
+
+    /// End a session, terminating its processes and revoking its capabilities
+    pub fn logout(&self, token: &SessionToken) -> Result<(), String> {
+        let session = self.sessions.lock().unwrap().remove(token).ok_or("Unknown session")?;
+        for pid in session.processes.iter().rev() {
+            // Already gone if it exited on its own
+            let _ = self.kernel.terminate_process(*pid);
+        }
+        for capability in session.capabilities {
+            self.capabilities.revoke(capability);
+        }
+        // The users service may have expired the login already
+        let _ = self.users.logout(token);
+        Ok(())
+    }
+}
+
+impl JsonService for SessionManager {
+    type Request = SessionRequest;
+    type Response = SessionResponse;
+    const KIND: &'static str = "session";
+
+    fn handle_request(&self, request: SessionRequest) -> SessionResponse {
+        let result = match request {
+            SessionRequest::Login { user, password } => {
+                self.login(&user, &password).map(|session| SessionResponse::Session { session })
+            }
+            SessionRequest::Info { token } => self
+                .session(&token)
+                .map(|session| SessionResponse::Session { session })
+                .ok_or_else(|| "Unknown session".to_string()),
+            SessionRequest::Launch { token, app } => self.launch(&token, &app).map(|pid| SessionResponse::Launched { pid }),
+            SessionRequest::Lock { token } => self.lock(&token).map(|_| SessionResponse::Ok),
+            SessionRequest::Unlock { token, password } => self.unlock(&token, &password).map(|_| SessionResponse::Ok),
+            SessionRequest::Logout { token } => self.logout(&token).map(|_| SessionResponse::Ok),
+        };
+        result.unwrap_or_else(|message| SessionResponse::Error { message })
+    }
+}
+
+/// Requests accepted over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum SessionRequest {
+    Login { user: String, password: String },
+    Info { token: SessionToken },
+    Launch { token: SessionToken, app: String },
+    Lock { token: SessionToken },
+    Unlock { token: SessionToken, password: String },
+    Logout { token: SessionToken },
+}
+
+/// Responses sent over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum SessionResponse {
+    Session { session: UserSession },
+    Launched { pid: ProcessId },
+    Ok,
+    Error { message: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ipc::{Message, ERROR_BAD_REQUEST};
+    use kernel::ProcessState;
+    use keystore::Keystore;
+
+    fn setup() -> (SessionManager, Arc<Kernel>, Arc<CapabilityManager>) {
+        let capabilities = Arc::new(CapabilityManager::new());
+        let users = Arc::new(UserService::new(Arc::new(Keystore::new()), Arc::clone(&capabilities)).unwrap());
+        users.create_user("alice", "hunter2").unwrap();
+        let kernel = Arc::new(Kernel::new());
+        let manager = SessionManager::new(users, Arc::clone(&kernel), Arc::clone(&capabilities));
+        (manager, kernel, capabilities)
+    }
+
+    #[test]
+    fn test_login_starts_shell_and_autostart() {
+        let (manager, kernel, capabilities) = setup();
+        let mut profile = SessionProfile {
+            autostart: vec!["notifications-tray".to_string(), "clipboard-agent".to_string()],
+            capabilities: vec![(Resource::Device("audio".to_string()), Permission::Write)],
+            ..SessionProfile::default()
+        };
+        profile.environment.insert("EDITOR".to_string(), "nano".to_string());
+        manager.set_profile("alice", profile).unwrap();
+        assert!(manager.set_profile("nobody", SessionProfile::default()).is_err());
+        assert!(manager.login("alice", "wrong").is_err());
+
+        let session = manager.login("alice", "hunter2").unwrap();
+        assert_eq!(kernel.get_process(session.shell).unwrap().name, DEFAULT_SHELL);
+        assert_eq!(session.processes.len(), 3);
+        assert_eq!(session.environment["HOME"], session.user.home);
+        assert_eq!(session.environment["EDITOR"], "nano");
+
+        let home = session.capabilities[0];
+        assert_eq!(capabilities.validate(home).unwrap().resource, Resource::File(session.user.home.clone()));
+        assert!(capabilities.check_user_permission(session.capabilities[1], session.user.uid, Permission::Write));
+        assert!(!capabilities.check_user_permission(home, 0, Permission::Read));
+    }
+
+    #[test]
+    fn test_lock_and_unlock() {
+        let (manager, _, _) = setup();
+        let session = manager.login("alice", "hunter2").unwrap();
+        manager.lock(&session.token).unwrap();
+        assert_eq!(manager.session(&session.token).unwrap().state, SessionState::Locked);
+        assert!(manager.launch(&session.token, "editor").is_err());
+
+        assert!(manager.unlock(&session.token, "guess").is_err());
+        manager.unlock(&session.token, "hunter2").unwrap();
+        assert!(manager.launch(&session.token, "editor").is_ok());
+    }
+
+    #[test]
+    fn test_logout_tears_down_processes() {
+        let (manager, kernel, capabilities) = setup();
+        let session = manager.login("alice", "hunter2").unwrap();
+        let editor = manager.launch(&session.token, "editor").unwrap();
+        kernel.terminate_process(editor).unwrap();
+        assert_eq!(manager.session(&session.token).unwrap().processes, vec![session.shell]);
+
+        manager.logout(&session.token).unwrap();
+        assert_eq!(kernel.get_process(session.shell).unwrap().state, ProcessState::Terminated);
+        assert!(capabilities.validate(session.capabilities[0]).is_none());
+        assert!(manager.session(&session.token).is_none());
+        assert!(manager.logout(&session.token).is_err());
+    }
+
+    #[test]
+    fn test_packaged_apps_run_sandboxed() {
+        let (manager, kernel, capabilities) = setup();
+        manager.set_app_sandbox(Arc::new(|app: &str| match app {
+            "viewer" => {
+                let mut profile = SandboxProfile::new("/apps/viewer".to_string());
+                profile.devices.push(("gpu".to_string(), Permission::Read));
+                Ok(Some(profile))
+            }
+            "broken" => Err("Invalid sandbox profiles".to_string()),
+            _ => Ok(None),
+        }));
+        let session = manager.login("alice", "hunter2").unwrap();
+        let viewer = manager.launch(&session.token, "viewer").unwrap();
+        let process = kernel.get_process(viewer).unwrap();
+        let tokens = process.capabilities;
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(capabilities.validate(tokens[1]).unwrap().resource, Resource::Device("gpu".to_string()));
+        assert_eq!(kernel.resolve_path(viewer, "/config").unwrap(), std::path::PathBuf::from("/apps/viewer/config"));
+        let editor = manager.launch(&session.token, "editor").unwrap();
+        assert!(kernel.get_process(editor).unwrap().capabilities.is_empty());
+        let before = kernel.process_count();
+        assert!(manager.launch(&session.token, "broken").is_err());
+        assert_eq!(kernel.process_count(), before);
+
+        manager.logout(&session.token).unwrap();
+        assert!(capabilities.validate(tokens[0]).is_none());
+    }
+
+    #[test]
+    fn test_login_over_ipc() {
+        let (manager, _, _) = setup();
+        let request = SessionRequest::Login {
+            user: "alice".to_string(),
+            password: "hunter2".to_string(),
+        };
+        let reply = manager
+            .handle_message(&Message::Request {
+                id: 4,
+                data: serde_json::to_vec(&request).unwrap(),
+            })
+            .unwrap();
+        let token = match reply {
+            Message::Response { id: 4, data } => match serde_json::from_slice(&data).unwrap() {
+                SessionResponse::Session { session } => session.token,
+                other => panic!("unexpected response: {:?}", other),
+            },
+            other => panic!("unexpected reply: {:?}", other),
+        };
+
+        assert_eq!(manager.handle_request(SessionRequest::Lock { token: token.clone() }), SessionResponse::Ok);
+        let launch = SessionRequest::Launch {
+            token,
+            app: "browser".to_string(),
+        };
+        assert!(matches!(manager.handle_request(launch), SessionResponse::Error { .. }));
+        let bad = manager.handle_message(&Message::Request { id: 5, data: b"{}".to_vec() });
+        assert!(matches!(bad, Some(Message::Error { code: ERROR_BAD_REQUEST, .. })));
+    }
+}
@@ -0,0 +1,272 @@
+//! Telemetry Service
+//!
+//! Aggregates the metrics registry shared by the kernel, scheduler, IPC
+//! and memory manager. Every aggregation interval the registry is sampled
+//! into per-metric ring buffers, from which recent history and summaries
+//! are served over IPC; the live values are exported in the Prometheus
+//! text format for external tooling.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use ipc::JsonService;
+use metrics::{MetricKind, MetricsRegistry};
+use serde::{Deserialize, Serialize};
+
+pub mod prometheus;
+
+/// Milliseconds between aggregations
+pub const DEFAULT_INTERVAL_MS: u64 = 10_000;
+
+/// Samples kept per metric
+pub const DEFAULT_RETENTION: usize = 360;
+
+/// One aggregated sample
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DataPoint {
+    /// Service uptime in milliseconds when sampled
+    pub time_ms: u64,
+    pub value: f64,
+}
+
+/// Statistics over the retained history of a metric
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SeriesSummary {
+    pub kind: MetricKind,
+    pub samples: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub last: f64,
+    /// Increase per second across the window; meaningful for counters
+    pub rate_per_sec: f64,
+}
+
+struct Series {
+    kind: MetricKind,
+    points: VecDeque<DataPoint>,
+}
+
+/// The metrics aggregation service
+pub struct TelemetryService {
+    registry: MetricsRegistry,
+    series: Arc<Mutex<BTreeMap<String, Series>>>,
+    interval_ms: u64,
+    retention: usize,
+    uptime_ms: Arc<Mutex<u64>>,
+    since_aggregate_ms: Arc<Mutex<u64>>,
+}
+
+impl TelemetryService {
+    pub fn new(registry: MetricsRegistry) -> Self {
+        TelemetryService {
+            registry,
+            series: Arc::new(Mutex::new(BTreeMap::new())),
+            interval_ms: DEFAULT_INTERVAL_MS,
+            retention: DEFAULT_RETENTION,
+            uptime_ms: Arc::new(Mutex::new(0)),
+            since_aggregate_ms: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    pub fn with_interval(mut self, interval_ms: u64) -> Self {
+        self.interval_ms = interval_ms.max(1);
+        self
+    }
+
+    pub fn with_retention(mut self, retention: usize) -> Self {
+        self.retention = retention.max(1);
+        self
+    }
+
+    /// Registry handed to instrumented components
+    pub fn registry(&self) -> MetricsRegistry {
+        self.registry.clone()
+    }
+
+    /// Let time pass, aggregating whenever an interval completes
+    pub fn tick(&self, elapsed_ms: u64) {
+        let due = {
+            let mut uptime = self.uptime_ms.lock().unwrap();
+            *uptime += elapsed_ms;
+            let mut since = self.since_aggregate_ms.lock().unwrap();
+            *since += elapsed_ms;
+            if *since >= self.interval_ms {
+                *since %= self.interval_ms;
+                true
+            } else {
+                false
+            }
+        };
+        if due {
+            self.aggregate();
+        }
+    }
+
+    /// Sample every metric into its ring buffer now
+    pub fn aggregate(&self) {
+        let time_ms = *self.uptime_ms.lock().unwrap();
+        let mut series = self.series.lock().unwrap();
+        for sample in self.registry.sample() {
+            let entry = series.entry(sample.name).or_insert_with(|| Series {
+                kind: sample.value.kind(),
+                points: VecDeque::new(),
+            });
+            if entry.points.len() >= self.retention {
+                entry.points.pop_front();
+            }
+            entry.points.push_back(DataPoint {
+                time_ms,
+                value: sample.value.scalar(),
+            });
+        }
+    }
+
+    pub fn metric_names(&self) -> Vec<String> {
+        self.series.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Retained history of a metric, oldest first
+    pub fn series(&self, name: &str) -> Result<Vec<DataPoint>, String> {
+        self.series
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|s| s.points.iter().copied().collect())
+            .ok_or_else(|| format!("No samples for metric: {}", name))
+    }
+
+    pub fn summary(&self, name: &str) -> Result<SeriesSummary, String> {
+        let series = self.series.lock().unwrap();
+        let entry = series.get(name).ok_or_else(|| format!("No samples for metric: {}", name))?;
+        let first = *entry.points.front().unwrap();
+        let last = *entry.points.back().unwrap();
+        let values = entry.points.iter().map(|p| p.value);
+        let span_ms = last.time_ms - first.time_ms;
+        Ok(SeriesSummary {
+            kind: entry.kind,
+            samples: entry.points.len(),
+            min: values.clone().fold(f64::INFINITY, f64::min),
+            max: values.clone().fold(f64::NEG_INFINITY, f64::max),
+            mean: values.sum::<f64>() / entry.points.len() as f64,
+            last: last.value,
+            rate_per_sec: if span_ms == 0 {
+                0.0
+            } else {
+                (last.value - first.value) * 1000.0 / span_ms as f64
+            },
+        })
+    }
+
+    /// Live values in the Prometheus text format
+    pub fn export_prometheus(&self) -> String {
+        prometheus::render(&self.registry.sample())
+    }
+}
+
+impl JsonService for TelemetryService {
+    type Request = TelemetryRequest;
+    type Response = TelemetryResponse;
+    const KIND: &'static str = "telemetry";
+
+    fn handle_request(&self, request: TelemetryRequest) -> TelemetryResponse {
+        let result = match request {
+            TelemetryRequest::List => Ok(TelemetryResponse::Metrics {
+                names: self.metric_names(),
+            }),
+            TelemetryRequest::Series { name } => self.series(&name).map(|points| TelemetryResponse::Series { points }),
+            TelemetryRequest::Summary { name } => {
+                self.summary(&name).map(|summary| TelemetryResponse::Summary { summary })
+            }
+            TelemetryRequest::Export => Ok(TelemetryResponse::Export {
+                body: self.export_prometheus(),
+            }),
+        };
+        result.unwrap_or_else(|message| TelemetryResponse::Error { message })
+    }
+}
+
+/// Requests accepted over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum TelemetryRequest {
+    List,
+    Series { name: String },
+    Summary { name: String },
+    Export,
+}
+
+/// Responses sent over IPC
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum TelemetryResponse {
+    Metrics { names: Vec<String> },
+    Series { points: Vec<DataPoint> },
+    Summary { summary: SeriesSummary },
+    Export { body: String },
+    Error { message: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ipc::{Message, ERROR_BAD_REQUEST};
+
+    #[test]
+    fn test_aggregation_and_retention() {
+        let registry = MetricsRegistry::new();
+        let service = TelemetryService::new(registry.clone()).with_interval(1000).with_retention(3);
+        let requests = registry.counter("requests_total", "Requests").unwrap();
+        let queue = registry.gauge("queue_depth", "Queue depth").unwrap();
+
+        for depth in [4.0, 2.0, 6.0, 8.0] {
+            requests.add(5);
+            queue.set(depth);
+            service.tick(400);
+            service.tick(600);
+        }
+
+        let points = service.series("requests_total").unwrap();
+        assert_eq!(points.len(), 3);
+        assert_eq!(points[0], DataPoint { time_ms: 2000, value: 10.0 });
+
+        let summary = service.summary("requests_total").unwrap();
+        assert_eq!(summary.rate_per_sec, 5.0);
+        let depth = service.summary("queue_depth").unwrap();
+        assert_eq!((depth.min, depth.max, depth.last), (2.0, 8.0, 8.0));
+        assert!(service.summary("missing").is_err());
+    }
+
+    #[test]
+    fn test_ipc_export() {
+        let registry = MetricsRegistry::new();
+        let service = TelemetryService::new(registry.clone());
+        let ipc_manager = ipc::IPCManager::new();
+        ipc_manager.attach_metrics(&registry).unwrap();
+        ipc_manager.create_channel();
+
+        let reply = service
+            .handle_message(&Message::Request {
+                id: 2,
+                data: serde_json::to_vec(&TelemetryRequest::Export).unwrap(),
+            })
+            .unwrap();
+        match reply {
+            Message::Response { id: 2, data } => match serde_json::from_slice(&data).unwrap() {
+                TelemetryResponse::Export { body } => {
+                    assert!(body.contains("# TYPE ipc_channels gauge\nipc_channels 1\n"));
+                }
+                other => panic!("unexpected response: {:?}", other),
+            },
+            other => panic!("unexpected reply: {:?}", other),
+        }
+
+        service.aggregate();
+        match service.handle_request(TelemetryRequest::List) {
+            TelemetryResponse::Metrics { names } => assert!(names.contains(&"ipc_messages_sent_total".to_string())),
+            other => panic!("unexpected response: {:?}", other),
+        }
+        let bad = service.handle_message(&Message::Request { id: 3, data: b"null".to_vec() });
+        assert!(matches!(bad, Some(Message::Error { code: ERROR_BAD_REQUEST, .. })));
+    }
+}
@@ -0,0 +1,76 @@
+//! Prometheus text exposition format
+
+use std::fmt::Write;
+
+use metrics::{MetricSample, MetricValue};
+
+fn escape_help(help: &str) -> String {
+    help.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+/// Render samples in the text format (version 0.0.4)
+pub fn render(samples: &[MetricSample]) -> String {
+    let mut out = String::new();
+    for sample in samples {
+        let kind = match sample.value {
+            MetricValue::Counter { .. } => "counter",
+            MetricValue::Gauge { .. } => "gauge",
+            MetricValue::Histogram { .. } => "histogram",
+        };
+        if !sample.help.is_empty() {
+            let _ = writeln!(out, "# HELP {} {}", sample.name, escape_help(&sample.help));
+        }
+        let _ = writeln!(out, "# TYPE {} {}", sample.name, kind);
+        match &sample.value {
+            MetricValue::Counter { value } => {
+                let _ = writeln!(out, "{} {}", sample.name, value);
+            }
+            MetricValue::Gauge { value } => {
+                let _ = writeln!(out, "{} {}", sample.name, value);
+            }
+            MetricValue::Histogram { histogram } => {
+                let mut cumulative = 0;
+                for (bound, count) in histogram.buckets.iter().zip(&histogram.counts) {
+                    cumulative += count;
+                    let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", sample.name, bound, cumulative);
+                }
+                let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", sample.name, histogram.count);
+                let _ = writeln!(out, "{}_sum {}", sample.name, histogram.sum);
+                let _ = writeln!(out, "{}_count {}", sample.name, histogram.count);
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metrics::MetricsRegistry;
+
+    #[test]
+    fn test_render() {
+        let registry = MetricsRegistry::new();
+        registry.counter("jobs_total", "Jobs\nrun").unwrap().add(3);
+        registry.gauge("load", "").unwrap().set(0.5);
+        let latency = registry.histogram("latency_ms", "Latency", &[1.0, 10.0]).unwrap();
+        latency.observe(0.5);
+        latency.observe(20.0);
+
+        let expected = "\
+# HELP jobs_total Jobs\\nrun
+# TYPE jobs_total counter
+jobs_total 3
+# HELP latency_ms Latency
+# TYPE latency_ms histogram
+latency_ms_bucket{le=\"1\"} 1
+latency_ms_bucket{le=\"10\"} 1
+latency_ms_bucket{le=\"+Inf\"} 2
+latency_ms_sum 20.5
+latency_ms_count 2
+# TYPE load gauge
+load 0.5
+";
+        assert_eq!(render(&registry.sample()), expected);
+    }
+}
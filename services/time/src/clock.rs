@@ -0,0 +1,113 @@
+//! System clock
+//!
+//! The monotonic clock counts time since boot and never jumps, so it is the
+//! one to measure intervals and deadlines with. The realtime clock is wall
+//! time since the Unix epoch and can be stepped by synchronisation or by an
+//! administrator; every step is announced to subscribers so schedulers
+//! keyed on wall time can re-arm their deadlines.
+
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+/// What changed the clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeReason {
+    Manual,
+    Sync,
+    /// Realtime unchanged but local time moved to another zone
+    Timezone,
+}
+
+/// A discontinuity in wall or local time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockChange {
+    pub previous_ms: u64,
+    pub current_ms: u64,
+    pub reason: ChangeReason,
+}
+
+/// Callback run after the clock changes
+pub type ClockListener = Arc<dyn Fn(&ClockChange) + Send + Sync>;
+
+struct ClockState {
+    monotonic_ms: u64,
+    /// Realtime at monotonic zero
+    epoch_offset_ms: u64,
+}
+
+/// Monotonic and realtime clocks shared by the whole system
+pub struct SystemClock {
+    state: Arc<Mutex<ClockState>>,
+    listeners: Arc<Mutex<Vec<ClockListener>>>,
+}
+
+impl SystemClock {
+    /// Clock starting at the host's current wall time
+    pub fn new() -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        Self::starting_at(now)
+    }
+
+    /// Clock starting at `realtime_ms`
+    pub fn starting_at(realtime_ms: u64) -> Self {
+        SystemClock {
+            state: Arc::new(Mutex::new(ClockState {
+                monotonic_ms: 0,
+                epoch_offset_ms: realtime_ms,
+            })),
+            listeners: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Milliseconds since boot
+    pub fn monotonic_ms(&self) -> u64 {
+        self.state.lock().unwrap().monotonic_ms
+    }
+
+    /// Milliseconds since the Unix epoch
+    pub fn realtime_ms(&self) -> u64 {
+        let state = self.state.lock().unwrap();
+        state.epoch_offset_ms + state.monotonic_ms
+    }
+
+    /// Let time pass
+    pub fn advance(&self, elapsed_ms: u64) {
+        self.state.lock().unwrap().monotonic_ms += elapsed_ms;
+    }
+
+    pub fn on_change(&self, listener: ClockListener) {
+        self.listeners.lock().unwrap().push(listener);
+    }
+
+    /// Step the realtime clock, leaving the monotonic clock alone
+    pub(crate) fn set_realtime(&self, realtime_ms: u64, reason: ChangeReason) -> ClockChange {
+        let change = {
+            let mut state = self.state.lock().unwrap();
+            let previous_ms = state.epoch_offset_ms + state.monotonic_ms;
+            state.epoch_offset_ms = realtime_ms.saturating_sub(state.monotonic_ms);
+            ClockChange {
+                previous_ms,
+                current_ms: state.epoch_offset_ms + state.monotonic_ms,
+                reason,
+            }
+        };
+        self.notify(&change);
+        change
+    }
+
+    pub(crate) fn notify(&self, change: &ClockChange) {
+        let listeners = self.listeners.lock().unwrap().clone();
+        for listener in listeners {
+            listener(change);
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,373 @@
+//! Time Service
+//!
+//! Owns the system clock. The realtime clock is disciplined by SNTP over
+//! the UDP stack or set by hand with a clock capability; local time is
+//! derived from the configured timezone. Other crates read the clock
+//! through `SystemClock` and subscribe to its change events.
+
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+
+use capability::{CapabilityManager, CapabilityToken, Permission, Resource};
+use ipc::JsonService;
+use net_stack::NetStack;
+use serde::{Deserialize, Serialize};
+
+pub mod clock;
+pub mod sntp;
+pub mod zone;
+
+pub use clock::{ChangeReason, ClockChange, ClockListener, SystemClock};
+pub use sntp::{SntpClient, SntpPacket, SntpServer, SyncSample, NTP_PORT};
+pub use zone::{LocalTime, TimeZone, TimeZoneDatabase, Transition};
+
+/// Device resource guarding manual clock changes
+pub const CLOCK_RESOURCE: &str = "clock";
+
+/// How often the clock is resynchronised
+pub const DEFAULT_SYNC_INTERVAL_MS: u64 = 3_600_000;
+
+struct SyncSource {
+    stack: Arc<NetStack>,
+    client: SntpClient,
+}
+
+/// The system time service
+pub struct TimeService {
+    clock: Arc<SystemClock>,
+    capabilities: Arc<CapabilityManager>,
+    zones: Arc<Mutex<TimeZoneDatabase>>,
+    timezone: Arc<Mutex<TimeZone>>,
+    sources: Arc<Mutex<Vec<SyncSource>>>,
+    last_sync: Arc<Mutex<Option<SyncSample>>>,
+    sync_interval_ms: u64,
+    since_sync_ms: Arc<Mutex<u64>>,
+}
+
+impl TimeService {
+    pub fn new(clock: Arc<SystemClock>, capabilities: Arc<CapabilityManager>) -> Self {
+        TimeService {
+            clock,
+            capabilities,
+            zones: Arc::new(Mutex::new(TimeZoneDatabase::builtin())),
+            timezone: Arc::new(Mutex::new(TimeZone::utc())),
+            sources: Arc::new(Mutex::new(Vec::new())),
+            last_sync: Arc::new(Mutex::new(None)),
+            sync_interval_ms: DEFAULT_SYNC_INTERVAL_MS,
+            since_sync_ms: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    pub fn with_sync_interval(mut self, interval_ms: u64) -> Self {
+        self.sync_interval_ms = interval_ms;
+        self
+    }
+
+    pub fn clock(&self) -> Arc<SystemClock> {
+        Arc::clone(&self.clock)
+    }
+
+    pub fn monotonic_ms(&self) -> u64 {
+        self.clock.monotonic_ms()
+    }
+
+    pub fn realtime_ms(&self) -> u64 {
+        self.clock.realtime_ms()
+    }
+
+    /// Set the wall clock; requires write access to the clock device
+    pub fn set_time(&self, token: CapabilityToken, realtime_ms: u64) -> Result<ClockChange, String> {
+        let authorized = match self.capabilities.validate(token) {
+            Some(cap) => {
+                cap.resource == Resource::Device(CLOCK_RESOURCE.to_string())
+                    && self.capabilities.check_permission(token, Permission::Write)
+            }
+            None => false,
+        };
+        if !authorized {
+            return Err("Permission denied: setting the clock requires the clock capability".to_string());
+        }
+        Ok(self.clock.set_realtime(realtime_ms, ChangeReason::Manual))
+    }
+
+    /// Add zones from JSON timezone data
+    pub fn load_timezones(&self, json: &str) -> Result<usize, String> {
+        self.zones.lock().unwrap().load_json(json)
+    }
+
+    pub fn timezones(&self) -> Vec<String> {
+        self.zones.lock().unwrap().names()
+    }
+
+    pub fn set_timezone(&self, name: &str) -> Result<(), String> {
+        let zone = self
+            .zones
+            .lock()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("Unknown timezone: {}", name))?;
+        *self.timezone.lock().unwrap() = zone;
+
+        let now = self.clock.realtime_ms();
+        self.clock.notify(&ClockChange {
+            previous_ms: now,
+            current_ms: now,
+            reason: ChangeReason::Timezone,
+        });
+        Ok(())
+    }
+
+    pub fn timezone(&self) -> TimeZone {
+        self.timezone.lock().unwrap().clone()
+    }
+
+    pub fn local_time(&self) -> LocalTime {
+        self.timezone().local_time(self.clock.realtime_ms())
+    }
+
+    /// Use an SNTP server reachable through `stack`
+    pub fn add_sync_server(&self, stack: Arc<NetStack>, server: Ipv4Addr) {
+        self.sources.lock().unwrap().push(SyncSource {
+            stack,
+            client: SntpClient::new(server),
+        });
+    }
+
+    /// Query every sync server
+    pub fn sync(&self) -> Result<(), String> {
+        let mut sources = self.sources.lock().unwrap();
+        if sources.is_empty() {
+            return Err("No time servers configured".to_string());
+        }
+        for source in sources.iter_mut() {
+            source.client.start(&source.stack, &self.clock)?;
+        }
+        *self.since_sync_ms.lock().unwrap() = 0;
+        Ok(())
+    }
+
+    /// Process server replies, stepping the clock with the best sample
+    pub fn poll(&self) -> Result<Option<SyncSample>, String> {
+        let mut best: Option<SyncSample> = None;
+        {
+            let mut sources = self.sources.lock().unwrap();
+            for source in sources.iter_mut() {
+                if let Some(sample) = source.client.poll(&source.stack, &self.clock)? {
+                    if best.is_none_or(|b| sample.delay_ms < b.delay_ms) {
+                        best = Some(sample);
+                    }
+                }
+            }
+        }
+        let Some(sample) = best else {
+            return Ok(None);
+        };
+        if sample.offset_ms != 0 {
+            let corrected = (self.clock.realtime_ms() as i64 + sample.offset_ms).max(0) as u64;
+            self.clock.set_realtime(corrected, ChangeReason::Sync);
+        }
+        *self.last_sync.lock().unwrap() = Some(sample);
+        Ok(Some(sample))
+    }
+
+    pub fn last_sync(&self) -> Option<SyncSample> {
+        *self.last_sync.lock().unwrap()
+    }
+
+    /// Advance the clock and resynchronise when the interval has passed
+    pub fn tick(&self, elapsed_ms: u64) -> Result<(), String> {
+        self.clock.advance(elapsed_ms);
+        let due = {
+            let mut since = self.since_sync_ms.lock().unwrap();
+            *since += elapsed_ms;
+            *since >= self.sync_interval_ms
+        };
+        if due && !self.sources.lock().unwrap().is_empty() {
+            self.sync()?;
+        }
+        Ok(())
+    }
+}
+
+impl JsonService for TimeService {
+    type Request = TimeRequest;
+    type Response = TimeResponse;
+    const KIND: &'static str = "time";
+
+    fn handle_request(&self, request: TimeRequest) -> TimeResponse {
+        let result = match request {
+            TimeRequest::Now => Ok(TimeResponse::Now {
+                monotonic_ms: self.monotonic_ms(),
+                realtime_ms: self.realtime_ms(),
+                local: self.local_time(),
+            }),
+            TimeRequest::SetTime { token, realtime_ms } => {
+                self.set_time(token, realtime_ms).map(|change| TimeResponse::Changed { change })
+            }
+            TimeRequest::SetTimezone { name } => self.set_timezone(&name).map(|_| TimeResponse::Ok),
+            TimeRequest::Timezones => Ok(TimeResponse::Timezones {
+                names: self.timezones(),
+            }),
+            TimeRequest::Sync => self.sync().map(|_| TimeResponse::Ok),
+        };
+        result.unwrap_or_else(|message| TimeResponse::Error { message })
+    }
+}
+
+/// Requests accepted over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum TimeRequest {
+    Now,
+    SetTime { token: CapabilityToken, realtime_ms: u64 },
+    SetTimezone { name: String },
+    Timezones,
+    Sync,
+}
+
+/// Responses sent over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum TimeResponse {
+    Now {
+        monotonic_ms: u64,
+        realtime_ms: u64,
+        local: LocalTime,
+    },
+    Changed {
+        change: ClockChange,
+    },
+    Timezones {
+        names: Vec<String>,
+    },
+    Ok,
+    Error {
+        message: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ipc::{Message, ERROR_BAD_REQUEST};
+    use net_stack::{Ipv4Config, VirtualNic};
+
+    const NOON_2024_06_01: u64 = 1_717_243_200_000;
+
+    fn networks() -> (Arc<NetStack>, NetStack) {
+        let (a, b) = VirtualNic::pair([2, 0, 0, 0, 0, 1], [2, 0, 0, 0, 0, 2]);
+        let netmask = Ipv4Addr::new(255, 255, 255, 0);
+        let client = NetStack::new(Box::new(a));
+        let server = NetStack::new(Box::new(b));
+        client.configure(Ipv4Config::new(Ipv4Addr::new(10, 0, 0, 1), netmask, None));
+        server.configure(Ipv4Config::new(Ipv4Addr::new(10, 0, 0, 2), netmask, None));
+        (Arc::new(client), server)
+    }
+
+    fn pump(a: &NetStack, b: &NetStack) {
+        while a.poll() + b.poll() > 0 {}
+    }
+
+    #[test]
+    fn test_sntp_sync_steps_clock() {
+        let (client_stack, server_stack) = networks();
+        let server_clock = SystemClock::starting_at(NOON_2024_06_01);
+        let server = SntpServer::new(&server_stack, 1).unwrap();
+
+        let clock = Arc::new(SystemClock::starting_at(NOON_2024_06_01 - 90_000));
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&changes);
+        clock.on_change(Arc::new(move |change| seen.lock().unwrap().push(*change)));
+
+        let time = TimeService::new(Arc::clone(&clock), Arc::new(CapabilityManager::new())).with_sync_interval(1000);
+        assert!(time.sync().is_err());
+        time.add_sync_server(Arc::clone(&client_stack), Ipv4Addr::new(10, 0, 0, 2));
+        time.sync().unwrap();
+        pump(&client_stack, &server_stack);
+        assert_eq!(server.poll(&server_stack, &server_clock).unwrap(), 1);
+        pump(&client_stack, &server_stack);
+
+        let sample = time.poll().unwrap().unwrap();
+        assert_eq!(sample.offset_ms, 90_000);
+        assert_eq!(time.realtime_ms(), NOON_2024_06_01);
+        assert_eq!(time.monotonic_ms(), 0);
+        assert_eq!(changes.lock().unwrap()[0].reason, ChangeReason::Sync);
+        assert!(time.poll().unwrap().is_none());
+
+        // The next interval triggers another exchange
+        time.tick(1000).unwrap();
+        server_clock.advance(1000);
+        pump(&client_stack, &server_stack);
+        server.poll(&server_stack, &server_clock).unwrap();
+        pump(&client_stack, &server_stack);
+        assert_eq!(time.poll().unwrap().unwrap().offset_ms, 0);
+        assert_eq!(changes.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_manual_set_requires_capability() {
+        let capabilities = Arc::new(CapabilityManager::new());
+        let clock = Arc::new(SystemClock::starting_at(1_000));
+        let time = TimeService::new(Arc::clone(&clock), Arc::clone(&capabilities));
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&changes);
+        clock.on_change(Arc::new(move |change| seen.lock().unwrap().push(*change)));
+
+        let read_only = capabilities.grant(Resource::Device(CLOCK_RESOURCE.to_string()), Permission::Read);
+        let other = capabilities.grant(Resource::Device("rtc0".to_string()), Permission::Full);
+        assert!(time.set_time(read_only, 5_000).is_err());
+        assert!(time.set_time(other, 5_000).is_err());
+
+        clock.advance(500);
+        let writer = capabilities.grant(Resource::Device(CLOCK_RESOURCE.to_string()), Permission::ReadWrite);
+        let change = time.set_time(writer, NOON_2024_06_01).unwrap();
+        assert_eq!((change.previous_ms, change.current_ms), (1_500, NOON_2024_06_01));
+        assert_eq!(*changes.lock().unwrap(), vec![change]);
+        assert_eq!(time.monotonic_ms(), 500);
+    }
+
+    #[test]
+    fn test_timezone_selection() {
+        let clock = Arc::new(SystemClock::starting_at(NOON_2024_06_01));
+        let time = TimeService::new(Arc::clone(&clock), Arc::new(CapabilityManager::new()));
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&changes);
+        clock.on_change(Arc::new(move |change| seen.lock().unwrap().push(change.reason)));
+
+        assert_eq!(time.local_time().hour, 12);
+        time.set_timezone("Europe/Berlin").unwrap();
+        let local = time.local_time();
+        assert_eq!((local.hour, local.dst, local.zone.as_str()), (14, true, "Europe/Berlin"));
+        assert!(time.set_timezone("Mars/Olympus").is_err());
+        assert_eq!(*changes.lock().unwrap(), vec![ChangeReason::Timezone]);
+    }
+
+    #[test]
+    fn test_ipc_requests() {
+        let capabilities = Arc::new(CapabilityManager::new());
+        let time = TimeService::new(Arc::new(SystemClock::starting_at(NOON_2024_06_01)), Arc::clone(&capabilities));
+        let token = capabilities.grant(Resource::Device(CLOCK_RESOURCE.to_string()), Permission::Write);
+
+        let request = TimeRequest::SetTime { token, realtime_ms: 42 };
+        let reply = time
+            .handle_message(&Message::Request {
+                id: 1,
+                data: serde_json::to_vec(&request).unwrap(),
+            })
+            .unwrap();
+        match reply {
+            Message::Response { id, data } => {
+                assert_eq!(id, 1);
+                let response: TimeResponse = serde_json::from_slice(&data).unwrap();
+                assert!(matches!(response, TimeResponse::Changed { change } if change.current_ms == 42));
+            }
+            other => panic!("unexpected reply: {:?}", other),
+        }
+
+        assert!(matches!(time.handle_request(TimeRequest::Now), TimeResponse::Now { realtime_ms: 42, .. }));
+        assert!(matches!(time.handle_request(TimeRequest::Sync), TimeResponse::Error { .. }));
+        let bad = time.handle_message(&Message::Request { id: 2, data: b"{}".to_vec() });
+        assert!(matches!(bad, Some(Message::Error { code: ERROR_BAD_REQUEST, .. })));
+    }
+}
@@ -0,0 +1,221 @@
+//! SNTP client and simulated server (RFC 4330)
+
+use std::net::Ipv4Addr;
+
+use net_stack::{NetStack, SocketId};
+
+use crate::clock::SystemClock;
+
+pub const NTP_PORT: u16 = 123;
+
+/// Seconds between the NTP era (1900) and the Unix epoch
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+const PACKET_LEN: usize = 48;
+const VERSION: u8 = 4;
+const MODE_CLIENT: u8 = 3;
+const MODE_SERVER: u8 = 4;
+
+/// Convert Unix milliseconds to a 64-bit NTP timestamp
+pub fn to_ntp(unix_ms: u64) -> u64 {
+    let seconds = unix_ms / 1000 + NTP_UNIX_OFFSET;
+    let fraction = ((unix_ms % 1000) << 32) / 1000;
+    (seconds << 32) | fraction
+}
+
+/// Convert a 64-bit NTP timestamp to Unix milliseconds
+pub fn from_ntp(timestamp: u64) -> u64 {
+    let seconds = (timestamp >> 32).saturating_sub(NTP_UNIX_OFFSET);
+    let fraction = timestamp & 0xffff_ffff;
+    seconds * 1000 + ((fraction * 1000 + (1 << 31)) >> 32)
+}
+
+/// The fields of an NTP packet SNTP uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SntpPacket {
+    pub mode: u8,
+    pub stratum: u8,
+    pub originate: u64,
+    pub receive: u64,
+    pub transmit: u64,
+}
+
+impl SntpPacket {
+    pub fn request(transmit: u64) -> Self {
+        SntpPacket {
+            mode: MODE_CLIENT,
+            stratum: 0,
+            originate: 0,
+            receive: 0,
+            transmit,
+        }
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < PACKET_LEN {
+            return Err("SNTP packet too short".to_string());
+        }
+        let timestamp = |at: usize| u64::from_be_bytes(bytes[at..at + 8].try_into().unwrap());
+        Ok(SntpPacket {
+            mode: bytes[0] & 0x07,
+            stratum: bytes[1],
+            originate: timestamp(24),
+            receive: timestamp(32),
+            transmit: timestamp(40),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; PACKET_LEN];
+        bytes[0] = (VERSION << 3) | self.mode;
+        bytes[1] = self.stratum;
+        bytes[24..32].copy_from_slice(&self.originate.to_be_bytes());
+        bytes[32..40].copy_from_slice(&self.receive.to_be_bytes());
+        bytes[40..48].copy_from_slice(&self.transmit.to_be_bytes());
+        bytes
+    }
+}
+
+/// Result of one request/response exchange
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncSample {
+    pub server: Ipv4Addr,
+    /// Amount to add to the local clock to match the server
+    pub offset_ms: i64,
+    /// Round-trip network delay
+    pub delay_ms: u64,
+}
+
+/// SNTP client for a single server
+pub struct SntpClient {
+    server: Ipv4Addr,
+    socket: Option<SocketId>,
+    /// Transmit timestamp of the outstanding request
+    outstanding: Option<u64>,
+}
+
+impl SntpClient {
+    pub fn new(server: Ipv4Addr) -> Self {
+        SntpClient {
+            server,
+            socket: None,
+            outstanding: None,
+        }
+    }
+
+    pub fn server(&self) -> Ipv4Addr {
+        self.server
+    }
+
+    pub fn is_waiting(&self) -> bool {
+        self.outstanding.is_some()
+    }
+
+    /// Send a request, replacing any outstanding one
+    pub fn start(&mut self, stack: &NetStack, clock: &SystemClock) -> Result<(), String> {
+        if self.socket.is_none() {
+            self.socket = Some(stack.bind_udp(0)?);
+        }
+        let transmit = to_ntp(clock.realtime_ms());
+        let socket = self.socket.unwrap();
+        stack.send_to(socket, self.server, NTP_PORT, &SntpPacket::request(transmit).to_bytes())?;
+        self.outstanding = Some(transmit);
+        Ok(())
+    }
+
+    /// Process replies; returns a sample once the server has answered
+    pub fn poll(&mut self, stack: &NetStack, clock: &SystemClock) -> Result<Option<SyncSample>, String> {
+        let Some(socket) = self.socket else {
+            return Ok(None);
+        };
+        while let Some(datagram) = stack.recv_from(socket)? {
+            let Some(sent) = self.outstanding else {
+                continue;
+            };
+            if datagram.addr != self.server || datagram.port != NTP_PORT {
+                continue;
+            }
+            let Ok(reply) = SntpPacket::parse(&datagram.data) else {
+                continue;
+            };
+            // Replies must echo our request, and stratum 0 is a kiss-of-death
+            if reply.mode != MODE_SERVER || reply.originate != sent || reply.stratum == 0 {
+                continue;
+            }
+            self.outstanding = None;
+
+            let t1 = from_ntp(sent) as i64;
+            let t2 = from_ntp(reply.receive) as i64;
+            let t3 = from_ntp(reply.transmit) as i64;
+            let t4 = clock.realtime_ms() as i64;
+            return Ok(Some(SyncSample {
+                server: self.server,
+                offset_ms: ((t2 - t1) + (t3 - t4)) / 2,
+                delay_ms: ((t4 - t1) - (t3 - t2)).max(0) as u64,
+            }));
+        }
+        Ok(None)
+    }
+
+    pub fn close(&mut self, stack: &NetStack) {
+        if let Some(socket) = self.socket.take() {
+            let _ = stack.close(socket);
+        }
+        self.outstanding = None;
+    }
+}
+
+/// Minimal SNTP server answering from its own clock
+pub struct SntpServer {
+    socket: SocketId,
+    stratum: u8,
+}
+
+impl SntpServer {
+    pub fn new(stack: &NetStack, stratum: u8) -> Result<Self, String> {
+        Ok(SntpServer {
+            socket: stack.bind_udp(NTP_PORT)?,
+            stratum,
+        })
+    }
+
+    /// Answer pending requests; returns how many were answered
+    pub fn poll(&self, stack: &NetStack, clock: &SystemClock) -> Result<usize, String> {
+        let mut answered = 0;
+        while let Some(datagram) = stack.recv_from(self.socket)? {
+            let Ok(request) = SntpPacket::parse(&datagram.data) else {
+                continue;
+            };
+            if request.mode != MODE_CLIENT {
+                continue;
+            }
+            let now = to_ntp(clock.realtime_ms());
+            let reply = SntpPacket {
+                mode: MODE_SERVER,
+                stratum: self.stratum,
+                originate: request.transmit,
+                receive: now,
+                transmit: now,
+            };
+            stack.send_to(self.socket, datagram.addr, datagram.port, &reply.to_bytes())?;
+            answered += 1;
+        }
+        Ok(answered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timestamp_conversion() {
+        for ms in [0, 1, 999, 1_700_000_000_123] {
+            assert_eq!(from_ntp(to_ntp(ms)), ms);
+        }
+        assert_eq!(to_ntp(0) >> 32, NTP_UNIX_OFFSET);
+
+        let packet = SntpPacket::request(to_ntp(42));
+        assert_eq!(SntpPacket::parse(&packet.to_bytes()).unwrap(), packet);
+        assert!(SntpPacket::parse(&[0; 10]).is_err());
+    }
+}
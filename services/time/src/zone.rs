@@ -0,0 +1,313 @@
+//! Timezone database and local time conversion
+//!
+//! Zones are a fixed standard offset plus an optional daylight saving rule
+//! in the POSIX TZ style ("second Sunday of March at 02:00"). Transition
+//! times are given in local standard time.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use system_utils::time::{civil_from_days, days_from_civil};
+
+const MS_PER_MINUTE: i64 = 60_000;
+const MS_PER_DAY: i64 = 86_400_000;
+
+/// Week value meaning the last occurrence of the weekday in the month
+pub const LAST_WEEK: u8 = 5;
+
+/// When a daylight saving period starts or ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transition {
+    /// 1-12
+    pub month: u8,
+    /// 1-4, or `LAST_WEEK`
+    pub week: u8,
+    /// 0 = Sunday
+    pub weekday: u8,
+    /// Minutes after local standard midnight
+    pub minute: u16,
+}
+
+impl Transition {
+    pub fn new(month: u8, week: u8, weekday: u8, minute: u16) -> Self {
+        Transition {
+            month,
+            week,
+            weekday,
+            minute,
+        }
+    }
+
+    /// Moment of the transition in a given year, as local standard ms
+    fn local_ms(&self, year: i64) -> i64 {
+        let first = days_from_civil(year, self.month as u32, 1);
+        let first_weekday = weekday(first) as i64;
+        let mut day = first + (self.weekday as i64 - first_weekday).rem_euclid(7);
+        if self.week >= LAST_WEEK {
+            let next_month = if self.month == 12 {
+                days_from_civil(year + 1, 1, 1)
+            } else {
+                days_from_civil(year, self.month as u32 + 1, 1)
+            };
+            while day + 7 < next_month {
+                day += 7;
+            }
+        } else {
+            day += 7 * (self.week.max(1) as i64 - 1);
+        }
+        day * MS_PER_DAY + self.minute as i64 * MS_PER_MINUTE
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if !(1..=12).contains(&self.month)
+            || !(1..=LAST_WEEK).contains(&self.week)
+            || self.weekday > 6
+            || self.minute >= 24 * 60
+        {
+            return Err(format!("Invalid transition: {:?}", self));
+        }
+        Ok(())
+    }
+}
+
+/// Daylight saving rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DstRule {
+    /// Added to the standard offset while in effect
+    pub save_minutes: i32,
+    pub start: Transition,
+    pub end: Transition,
+}
+
+/// A named timezone
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeZone {
+    pub name: String,
+    /// Standard offset from UTC
+    pub offset_minutes: i32,
+    #[serde(default)]
+    pub dst: Option<DstRule>,
+}
+
+impl TimeZone {
+    pub fn fixed(name: &str, offset_minutes: i32) -> Self {
+        TimeZone {
+            name: name.to_string(),
+            offset_minutes,
+            dst: None,
+        }
+    }
+
+    pub fn with_dst(mut self, save_minutes: i32, start: Transition, end: Transition) -> Self {
+        self.dst = Some(DstRule {
+            save_minutes,
+            start,
+            end,
+        });
+        self
+    }
+
+    pub fn utc() -> Self {
+        Self::fixed("UTC", 0)
+    }
+
+    /// Offset from UTC in effect at `utc_ms`, and whether it is daylight time
+    pub fn offset_at(&self, utc_ms: i64) -> (i32, bool) {
+        let Some(rule) = self.dst else {
+            return (self.offset_minutes, false);
+        };
+        let standard_ms = utc_ms + self.offset_minutes as i64 * MS_PER_MINUTE;
+        let (year, _, _) = civil_from_days(standard_ms.div_euclid(MS_PER_DAY));
+        let start = rule.start.local_ms(year);
+        let end = rule.end.local_ms(year);
+        // Southern hemisphere rules start later in the year than they end
+        let in_dst = if start < end {
+            standard_ms >= start && standard_ms < end
+        } else {
+            standard_ms >= start || standard_ms < end
+        };
+        if in_dst {
+            (self.offset_minutes + rule.save_minutes, true)
+        } else {
+            (self.offset_minutes, false)
+        }
+    }
+
+    /// Break `utc_ms` down into local calendar time
+    pub fn local_time(&self, utc_ms: u64) -> LocalTime {
+        let (offset_minutes, dst) = self.offset_at(utc_ms as i64);
+        let local_ms = utc_ms as i64 + offset_minutes as i64 * MS_PER_MINUTE;
+        let days = local_ms.div_euclid(MS_PER_DAY);
+        let ms_of_day = local_ms.rem_euclid(MS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+        LocalTime {
+            year,
+            month,
+            day,
+            hour: (ms_of_day / 3_600_000) as u32,
+            minute: (ms_of_day / MS_PER_MINUTE % 60) as u32,
+            second: (ms_of_day / 1000 % 60) as u32,
+            millisecond: (ms_of_day % 1000) as u32,
+            weekday: weekday(days),
+            offset_minutes,
+            dst,
+            zone: self.name.clone(),
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.name.is_empty() {
+            return Err("Timezone name cannot be empty".to_string());
+        }
+        if self.offset_minutes.abs() > 14 * 60 {
+            return Err(format!("Offset out of range for {}", self.name));
+        }
+        if let Some(rule) = &self.dst {
+            rule.start.validate()?;
+            rule.end.validate()?;
+        }
+        Ok(())
+    }
+}
+
+/// Calendar time in a particular zone
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LocalTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub millisecond: u32,
+    /// 0 = Sunday
+    pub weekday: u32,
+    pub offset_minutes: i32,
+    pub dst: bool,
+    pub zone: String,
+}
+
+/// Known timezones by name
+pub struct TimeZoneDatabase {
+    zones: BTreeMap<String, TimeZone>,
+}
+
+impl TimeZoneDatabase {
+    pub fn new() -> Self {
+        TimeZoneDatabase {
+            zones: BTreeMap::new(),
+        }
+    }
+
+    /// Database with a handful of common zones
+    pub fn builtin() -> Self {
+        let mut db = Self::new();
+        let eu_start = Transition::new(3, LAST_WEEK, 0, 60);
+        let eu_end = Transition::new(10, LAST_WEEK, 0, 60);
+        for zone in [
+            TimeZone::utc(),
+            TimeZone::fixed("Europe/London", 0).with_dst(60, eu_start, eu_end),
+            TimeZone::fixed("Europe/Berlin", 60).with_dst(
+                60,
+                Transition::new(3, LAST_WEEK, 0, 120),
+                Transition::new(10, LAST_WEEK, 0, 120),
+            ),
+            TimeZone::fixed("America/New_York", -300).with_dst(
+                60,
+                Transition::new(3, 2, 0, 120),
+                Transition::new(11, 1, 0, 60),
+            ),
+            TimeZone::fixed("Asia/Tokyo", 540),
+            TimeZone::fixed("Asia/Kolkata", 330),
+        ] {
+            db.zones.insert(zone.name.clone(), zone);
+        }
+        db
+    }
+
+    /// Add or replace zones from a JSON array of `TimeZone`
+    pub fn load_json(&mut self, json: &str) -> Result<usize, String> {
+        let zones: Vec<TimeZone> =
+            serde_json::from_str(json).map_err(|e| format!("Invalid timezone data: {}", e))?;
+        for zone in &zones {
+            zone.validate()?;
+        }
+        let count = zones.len();
+        for zone in zones {
+            self.zones.insert(zone.name.clone(), zone);
+        }
+        Ok(count)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&TimeZone> {
+        self.zones.get(name)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.zones.keys().cloned().collect()
+    }
+}
+
+impl Default for TimeZoneDatabase {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+fn weekday(days: i64) -> u32 {
+    // 1970-01-01 was a Thursday
+    (days + 4).rem_euclid(7) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc_ms(year: i64, month: u32, day: u32, hour: i64, minute: i64) -> u64 {
+        (days_from_civil(year, month, day) * MS_PER_DAY + hour * 3_600_000 + minute * MS_PER_MINUTE) as u64
+    }
+
+    #[test]
+    fn test_weekday() {
+        assert_eq!(weekday(0), 4);
+        assert_eq!(weekday(days_from_civil(2024, 1, 1)), 1);
+        assert_eq!(weekday(days_from_civil(1969, 12, 28)), 0);
+    }
+
+    #[test]
+    fn test_dst_transitions() {
+        let db = TimeZoneDatabase::builtin();
+        let london = db.get("Europe/London").unwrap();
+        // 2024-03-31 01:00 UTC is when the clocks go forward
+        assert!(!london.offset_at(utc_ms(2024, 3, 31, 0, 59) as i64).1);
+        let summer = london.local_time(utc_ms(2024, 3, 31, 1, 0));
+        assert_eq!((summer.hour, summer.offset_minutes, summer.dst), (2, 60, true));
+        assert!(!london.offset_at(utc_ms(2024, 10, 27, 1, 0) as i64).1);
+
+        let new_york = db.get("America/New_York").unwrap();
+        // Second Sunday of March 2024 is the 10th, 02:00 EST = 07:00 UTC
+        assert_eq!(new_york.offset_at(utc_ms(2024, 3, 10, 6, 59) as i64), (-300, false));
+        assert_eq!(new_york.offset_at(utc_ms(2024, 3, 10, 7, 0) as i64), (-240, true));
+        let evening = new_york.local_time(utc_ms(2024, 7, 4, 2, 30));
+        assert_eq!((evening.month, evening.day, evening.hour, evening.minute), (7, 3, 22, 30));
+        assert_eq!(evening.weekday, 3);
+
+        let kolkata = db.get("Asia/Kolkata").unwrap().local_time(utc_ms(2024, 1, 1, 0, 0));
+        assert_eq!((kolkata.hour, kolkata.minute), (5, 30));
+    }
+
+    #[test]
+    fn test_load_json() {
+        let mut db = TimeZoneDatabase::new();
+        let json = r#"[{"name": "Australia/Sydney", "offset_minutes": 600, "dst": {"save_minutes": 60,
+            "start": {"month": 10, "week": 1, "weekday": 0, "minute": 120},
+            "end": {"month": 4, "week": 1, "weekday": 0, "minute": 120}}}]"#;
+        assert_eq!(db.load_json(json).unwrap(), 1);
+        let sydney = db.get("Australia/Sydney").unwrap();
+        assert!(sydney.local_time(utc_ms(2024, 1, 15, 0, 0)).dst);
+        assert!(!sydney.local_time(utc_ms(2024, 6, 15, 0, 0)).dst);
+
+        assert!(db.load_json(r#"[{"name": "Bad", "offset_minutes": 2000}]"#).is_err());
+        assert!(db.load_json("not json").is_err());
+    }
+}
@@ -0,0 +1,65 @@
+//! System images
+//!
+//! An update is a raw system image plus a manifest naming its version,
+//! size and digest. The manifest is signed with a keystore key, so
+//! verifying the signature and then the digest of the written image proves
+//! the image came from the holder of that key.
+
+use keystore::{KeyId, Keystore};
+use serde::{Deserialize, Serialize};
+use system_utils::{encoding, hash};
+
+/// Length of image digests
+pub const DIGEST_LEN: usize = 32;
+
+/// SHA-256 of an image's contents
+pub fn image_digest(data: &[u8]) -> String {
+    encoding::hex_encode(&hash::sha256(data))
+}
+
+/// Description of a system image
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageManifest {
+    pub version: String,
+    pub size: u64,
+    /// Hex-encoded `image_digest` of the image
+    pub digest: String,
+}
+
+impl ImageManifest {
+    pub fn for_image(version: &str, image: &[u8]) -> Self {
+        ImageManifest {
+            version: version.to_string(),
+            size: image.len() as u64,
+            digest: image_digest(image),
+        }
+    }
+
+    fn signed_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+}
+
+/// Manifest with the publisher's signature
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedManifest {
+    pub manifest: ImageManifest,
+    pub signature: Vec<u8>,
+}
+
+impl SignedManifest {
+    /// Sign `manifest` with a keystore signing key
+    pub fn sign(keystore: &Keystore, key_id: &KeyId, manifest: ImageManifest) -> Result<Self, String> {
+        let signature = keystore.sign(key_id, &manifest.signed_bytes())?;
+        Ok(SignedManifest { manifest, signature })
+    }
+
+    /// Check the signature against a keystore verification key
+    pub fn verify(&self, keystore: &Keystore, key_id: &KeyId) -> Result<(), String> {
+        if keystore.verify(key_id, &self.manifest.signed_bytes(), &self.signature)? {
+            Ok(())
+        } else {
+            Err("Invalid image signature".to_string())
+        }
+    }
+}
@@ -0,0 +1,483 @@
+//! System Update Service
+//!
+//! Installs signed system images into the inactive A/B slot of the system
+//! storage device. An image is streamed into the slot in chunks, read back
+//! and checked against its signed manifest, and only then made the active
+//! slot. The new slot must confirm a successful boot within a few attempts
+//! or the previous slot is booted again.
+
+use std::sync::{Arc, Mutex};
+
+use hal::StorageDevice;
+use ipc::JsonService;
+use keystore::{KeyId, Keystore};
+use serde::{Deserialize, Serialize};
+
+pub mod image;
+pub mod slots;
+
+pub use image::{image_digest, ImageManifest, SignedManifest};
+pub use slots::{BootControl, BootDecision, Slot, SlotInfo, SlotLayout, BOOT_ATTEMPTS};
+
+/// Bytes written per chunk by `install`
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Storage device shared with its driver
+pub type SharedStorage = Arc<Mutex<dyn StorageDevice>>;
+
+/// Callback receiving update progress
+pub type ProgressListener = Arc<dyn Fn(&UpdateEvent) + Send + Sync>;
+
+/// Progress of an update
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum UpdateEvent {
+    /// Manifest signature checked; writing to `slot` starts
+    Started { version: String, slot: Slot },
+    Progress { written: u64, total: u64 },
+    /// Image verified and `slot` made active for the next boot
+    Installed { version: String, slot: Slot },
+    Failed { message: String },
+    RolledBack { from: Slot, to: Slot },
+}
+
+/// Update currently being written
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateProgress {
+    pub version: String,
+    pub slot: Slot,
+    pub written: u64,
+    pub total: u64,
+}
+
+/// Update status reported over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateStatus {
+    pub active: Slot,
+    pub slots: Vec<(Slot, SlotInfo)>,
+    pub in_progress: Option<UpdateProgress>,
+}
+
+struct PendingUpdate {
+    manifest: ImageManifest,
+    slot: Slot,
+    written: u64,
+}
+
+/// A/B system updater
+pub struct UpdateService {
+    storage: SharedStorage,
+    layout: SlotLayout,
+    keystore: Arc<Keystore>,
+    verification_key: KeyId,
+    control: Arc<Mutex<BootControl>>,
+    pending: Arc<Mutex<Option<PendingUpdate>>>,
+    listeners: Arc<Mutex<Vec<ProgressListener>>>,
+}
+
+impl UpdateService {
+    /// Open the system storage device, initialising boot control if absent.
+    /// Images must be signed by `verification_key`.
+    pub fn new(storage: SharedStorage, keystore: Arc<Keystore>, verification_key: KeyId) -> Result<Self, String> {
+        let (layout, control) = {
+            let mut device = storage.lock().unwrap();
+            let layout = SlotLayout::for_device(&*device)?;
+            let control = match BootControl::load(&*device)? {
+                Some(control) => control,
+                None => {
+                    let control = BootControl::factory();
+                    control.save(&mut *device)?;
+                    control
+                }
+            };
+            (layout, control)
+        };
+        Ok(UpdateService {
+            storage,
+            layout,
+            keystore,
+            verification_key,
+            control: Arc::new(Mutex::new(control)),
+            pending: Arc::new(Mutex::new(None)),
+            listeners: Arc::new(Mutex::new(Vec::new())),
+        })
+    }
+
+    pub fn subscribe(&self, listener: ProgressListener) {
+        self.listeners.lock().unwrap().push(listener);
+    }
+
+    fn emit(&self, event: UpdateEvent) {
+        let listeners = self.listeners.lock().unwrap().clone();
+        for listener in listeners {
+            listener(&event);
+        }
+    }
+
+    fn save_control(&self, control: &BootControl) -> Result<(), String> {
+        control.save(&mut *self.storage.lock().unwrap())
+    }
+
+    /// Verify a manifest and prepare the inactive slot for its image
+    pub fn begin(&self, signed: &SignedManifest) -> Result<Slot, String> {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.is_some() {
+            return Err("An update is already in progress".to_string());
+        }
+        signed.verify(&self.keystore, &self.verification_key)?;
+        let manifest = &signed.manifest;
+        if manifest.size > self.layout.slot_capacity() {
+            return Err(format!(
+                "Image of {} bytes exceeds slot capacity of {} bytes",
+                manifest.size,
+                self.layout.slot_capacity()
+            ));
+        }
+
+        // The slot is unbootable until the new image is verified. Until the
+        // active slot confirms a boot, the other slot is the known-good
+        // fallback and must not be overwritten.
+        let slot = {
+            let mut control = self.control.lock().unwrap();
+            if !control.slot(control.active).successful {
+                return Err("The active slot has not confirmed a successful boot".to_string());
+            }
+            let slot = control.active.other();
+            control.slot_mut(slot).bootable = false;
+            self.save_control(&control)?;
+            slot
+        };
+        *pending = Some(PendingUpdate {
+            manifest: manifest.clone(),
+            slot,
+            written: 0,
+        });
+        drop(pending);
+        self.emit(UpdateEvent::Started {
+            version: manifest.version.clone(),
+            slot,
+        });
+        Ok(slot)
+    }
+
+    /// Append image data to the slot being written
+    pub fn write_chunk(&self, data: &[u8]) -> Result<u64, String> {
+        let (written, total) = {
+            let mut guard = self.pending.lock().unwrap();
+            let pending = guard.as_mut().ok_or("No update in progress")?;
+            if pending.written + data.len() as u64 > pending.manifest.size {
+                return Err("Image data exceeds the manifest size".to_string());
+            }
+            let start = self.layout.slot_offset(pending.slot);
+            self.storage.lock().unwrap().write_bytes(start + pending.written, data)?;
+            pending.written += data.len() as u64;
+            (pending.written, pending.manifest.size)
+        };
+        self.emit(UpdateEvent::Progress { written, total });
+        Ok(written)
+    }
+
+    /// Verify the written image and make its slot active for the next boot
+    pub fn finish(&self) -> Result<Slot, String> {
+        let pending = self.pending.lock().unwrap().take().ok_or("No update in progress")?;
+        let result = self.verify_written(&pending).and_then(|_| {
+            let mut control = self.control.lock().unwrap();
+            control.install(pending.slot, &pending.manifest);
+            self.save_control(&control)
+        });
+        match result {
+            Ok(()) => {
+                self.emit(UpdateEvent::Installed {
+                    version: pending.manifest.version,
+                    slot: pending.slot,
+                });
+                Ok(pending.slot)
+            }
+            Err(message) => {
+                self.emit(UpdateEvent::Failed {
+                    message: message.clone(),
+                });
+                Err(message)
+            }
+        }
+    }
+
+    fn verify_written(&self, pending: &PendingUpdate) -> Result<(), String> {
+        if pending.written != pending.manifest.size {
+            return Err(format!(
+                "Image incomplete: {} of {} bytes written",
+                pending.written, pending.manifest.size
+            ));
+        }
+        let image = self
+            .storage
+            .lock()
+            .unwrap()
+            .read_bytes(self.layout.slot_offset(pending.slot), pending.manifest.size as usize)?;
+        if image_digest(&image) != pending.manifest.digest {
+            return Err("Image digest does not match the manifest".to_string());
+        }
+        Ok(())
+    }
+
+    /// Abandon the update in progress; its slot stays unbootable
+    pub fn abort(&self) -> Result<(), String> {
+        self.pending.lock().unwrap().take().ok_or("No update in progress")?;
+        self.emit(UpdateEvent::Failed {
+            message: "Update aborted".to_string(),
+        });
+        Ok(())
+    }
+
+    /// Install a complete image
+    pub fn install(&self, signed: &SignedManifest, image: &[u8]) -> Result<Slot, String> {
+        self.begin(signed)?;
+        for chunk in image.chunks(CHUNK_SIZE) {
+            if let Err(e) = self.write_chunk(chunk) {
+                self.pending.lock().unwrap().take();
+                self.emit(UpdateEvent::Failed { message: e.clone() });
+                return Err(e);
+            }
+        }
+        self.finish()
+    }
+
+    /// Pick the slot to boot; called once per boot, before anything else
+    pub fn boot(&self) -> Result<Slot, String> {
+        let decision = {
+            let mut control = self.control.lock().unwrap();
+            let decision = control.begin_boot()?;
+            self.save_control(&control)?;
+            decision
+        };
+        if let Some(from) = decision.rolled_back_from {
+            self.emit(UpdateEvent::RolledBack { from, to: decision.slot });
+        }
+        Ok(decision.slot)
+    }
+
+    /// Confirm the running system works, ending the rollback window
+    pub fn mark_boot_successful(&self) -> Result<(), String> {
+        let mut control = self.control.lock().unwrap();
+        control.mark_successful();
+        self.save_control(&control)
+    }
+
+    pub fn status(&self) -> UpdateStatus {
+        let control = self.control.lock().unwrap();
+        let in_progress = self.pending.lock().unwrap().as_ref().map(|p| UpdateProgress {
+            version: p.manifest.version.clone(),
+            slot: p.slot,
+            written: p.written,
+            total: p.manifest.size,
+        });
+        UpdateStatus {
+            active: control.active,
+            slots: [Slot::A, Slot::B]
+                .into_iter()
+                .map(|slot| (slot, control.slot(slot).clone()))
+                .collect(),
+            in_progress,
+        }
+    }
+}
+
+impl JsonService for UpdateService {
+    type Request = UpdateRequest;
+    type Response = UpdateResponse;
+    const KIND: &'static str = "update";
+
+    fn handle_request(&self, request: UpdateRequest) -> UpdateResponse {
+        let result = match request {
+            UpdateRequest::Status => Ok(UpdateResponse::Status { status: self.status() }),
+            UpdateRequest::Begin { manifest } => self.begin(&manifest).map(|slot| UpdateResponse::Slot { slot }),
+            UpdateRequest::Chunk { data } => self.write_chunk(&data).map(|written| UpdateResponse::Written { written }),
+            UpdateRequest::Finish => self.finish().map(|slot| UpdateResponse::Slot { slot }),
+            UpdateRequest::Abort => self.abort().map(|_| UpdateResponse::Ok),
+            UpdateRequest::MarkSuccessful => self.mark_boot_successful().map(|_| UpdateResponse::Ok),
+        };
+        result.unwrap_or_else(|message| UpdateResponse::Error { message })
+    }
+}
+
+/// Request accepted over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum UpdateRequest {
+    Status,
+    Begin { manifest: SignedManifest },
+    Chunk { data: Vec<u8> },
+    Finish,
+    Abort,
+    MarkSuccessful,
+}
+
+/// Reply sent over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum UpdateResponse {
+    Status { status: UpdateStatus },
+    Slot { slot: Slot },
+    Written { written: u64 },
+    Ok,
+    Error { message: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ipc::Message;
+    use keystore::{KeyType, KeyUsage};
+    use reference_driver::storage::ReferenceStorage;
+
+    fn setup() -> (UpdateService, SharedStorage, Arc<Keystore>, KeyId) {
+        let keystore = Arc::new(Keystore::new());
+        let key = KeyId::from("os-release");
+        keystore
+            .generate_key(key.clone(), KeyType::Ed25519, vec![KeyUsage::Sign, KeyUsage::Verify], true)
+            .unwrap();
+        let mut device = ReferenceStorage::new(1);
+        device.init().unwrap();
+        let storage: SharedStorage = Arc::new(Mutex::new(device));
+        let service = UpdateService::new(Arc::clone(&storage), Arc::clone(&keystore), key.clone()).unwrap();
+        (service, storage, keystore, key)
+    }
+
+    fn image(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7) as u8).collect()
+    }
+
+    #[test]
+    fn test_install_switches_slot_and_persists() {
+        let (service, storage, keystore, key) = setup();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&events);
+        service.subscribe(Arc::new(move |event| seen.lock().unwrap().push(event.clone())));
+
+        let data = image(200 * 1024);
+        let signed = SignedManifest::sign(&keystore, &key, ImageManifest::for_image("2.0", &data)).unwrap();
+        assert_eq!(service.install(&signed, &data).unwrap(), Slot::B);
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.first().unwrap(), &UpdateEvent::Started { version: "2.0".to_string(), slot: Slot::B });
+        assert!(events.contains(&UpdateEvent::Progress { written: 200 * 1024, total: 200 * 1024 }));
+        assert_eq!(events.last().unwrap(), &UpdateEvent::Installed { version: "2.0".to_string(), slot: Slot::B });
+
+        // Boot control survives a restart of the service
+        let reopened = UpdateService::new(storage, keystore, key).unwrap();
+        let status = reopened.status();
+        assert_eq!(status.active, Slot::B);
+        assert_eq!(status.slots[1].1.version, Some("2.0".to_string()));
+        assert_eq!(reopened.boot().unwrap(), Slot::B);
+        reopened.mark_boot_successful().unwrap();
+    }
+
+    #[test]
+    fn test_rejects_bad_signature_and_corrupt_image() {
+        let (service, _, keystore, key) = setup();
+        let data = image(4096);
+        let mut signed = SignedManifest::sign(&keystore, &key, ImageManifest::for_image("2.0", &data)).unwrap();
+        signed.manifest.version = "6.6.6".to_string();
+        assert!(service.install(&signed, &data).is_err());
+
+        let signed = SignedManifest::sign(&keystore, &key, ImageManifest::for_image("2.0", &data)).unwrap();
+        let mut corrupt = data.clone();
+        corrupt[100] ^= 0xff;
+        assert!(service.install(&signed, &corrupt).unwrap_err().contains("digest"));
+        assert_eq!(service.status().active, Slot::A);
+        assert!(!service.status().slots[1].1.bootable);
+
+        let huge = SignedManifest::sign(&keystore, &key, ImageManifest::for_image("3.0", &image(1024 * 1024))).unwrap();
+        assert!(service.begin(&huge).unwrap_err().contains("capacity"));
+    }
+
+    #[test]
+    fn test_rejects_manifest_signed_without_release_key() {
+        let (service, _, keystore, key) = setup();
+        let data = image(4096);
+        let manifest = ImageManifest::for_image("2.0", &data);
+
+        // A keystore holding a different key under the same name
+        let forger = Keystore::new();
+        forger
+            .generate_key(key.clone(), KeyType::Ed25519, vec![KeyUsage::Sign, KeyUsage::Verify], true)
+            .unwrap();
+        let forgeries = vec![
+            SignedManifest { manifest: manifest.clone(), signature: serde_json::to_vec(&manifest).unwrap() },
+            SignedManifest::sign(&forger, &key, manifest.clone()).unwrap(),
+        ];
+        for signed in forgeries {
+            assert_eq!(signed.verify(&keystore, &key), Err("Invalid image signature".to_string()));
+            assert_eq!(service.install(&signed, &data), Err("Invalid image signature".to_string()));
+        }
+        assert_eq!(service.status().active, Slot::A);
+        assert!(!service.status().slots[1].1.bootable);
+
+        let signed = SignedManifest::sign(&keystore, &key, manifest).unwrap();
+        assert_eq!(service.install(&signed, &data).unwrap(), Slot::B);
+    }
+
+    #[test]
+    fn test_no_update_before_new_slot_confirms() {
+        let (service, _, keystore, key) = setup();
+        let first = image(1024);
+        let signed = SignedManifest::sign(&keystore, &key, ImageManifest::for_image("2.0", &first)).unwrap();
+        assert_eq!(service.install(&signed, &first).unwrap(), Slot::B);
+
+        // Slot A still holds the running system B falls back to
+        let second = image(2048);
+        let next = SignedManifest::sign(&keystore, &key, ImageManifest::for_image("3.0", &second)).unwrap();
+        assert!(service.install(&next, &second).unwrap_err().contains("confirmed"));
+        assert!(service.status().slots[0].1.bootable);
+
+        assert_eq!(service.boot().unwrap(), Slot::B);
+        service.mark_boot_successful().unwrap();
+        assert_eq!(service.install(&next, &second).unwrap(), Slot::A);
+    }
+
+    #[test]
+    fn test_rollback_on_boot_failure() {
+        let (service, _, keystore, key) = setup();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&events);
+        service.subscribe(Arc::new(move |event| seen.lock().unwrap().push(event.clone())));
+
+        let data = image(1024);
+        let signed = SignedManifest::sign(&keystore, &key, ImageManifest::for_image("2.0", &data)).unwrap();
+        service.install(&signed, &data).unwrap();
+        // The new system never confirms its boot
+        for _ in 0..BOOT_ATTEMPTS {
+            assert_eq!(service.boot().unwrap(), Slot::B);
+        }
+        assert_eq!(service.boot().unwrap(), Slot::A);
+        assert_eq!(
+            events.lock().unwrap().last().unwrap(),
+            &UpdateEvent::RolledBack { from: Slot::B, to: Slot::A }
+        );
+    }
+
+    #[test]
+    fn test_streamed_update_over_ipc() {
+        let (service, _, keystore, key) = setup();
+        let data = image(3000);
+        let signed = SignedManifest::sign(&keystore, &key, ImageManifest::for_image("2.0", &data)).unwrap();
+
+        assert_eq!(
+            service.handle_request(UpdateRequest::Begin { manifest: signed }),
+            UpdateResponse::Slot { slot: Slot::B }
+        );
+        let message = Message::Request {
+            id: 1,
+            data: serde_json::to_vec(&UpdateRequest::Chunk { data: data[..1000].to_vec() }).unwrap(),
+        };
+        let Some(Message::Response { data: reply, .. }) = service.handle_message(&message) else {
+            panic!("expected a response");
+        };
+        assert_eq!(serde_json::from_slice::<UpdateResponse>(&reply).unwrap(), UpdateResponse::Written { written: 1000 });
+        assert_eq!(service.status().in_progress.unwrap().written, 1000);
+
+        service.handle_request(UpdateRequest::Chunk { data: data[1000..].to_vec() });
+        assert_eq!(service.handle_request(UpdateRequest::Finish), UpdateResponse::Slot { slot: Slot::B });
+        assert!(matches!(service.handle_request(UpdateRequest::Finish), UpdateResponse::Error { .. }));
+    }
+}
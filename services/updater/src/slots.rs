@@ -0,0 +1,231 @@
+//! A/B slot bookkeeping
+//!
+//! The storage device is split into a small boot-control region followed
+//! by two equally sized system slots. Boot control records which slot is
+//! active and, for a freshly installed slot, how many boot attempts remain
+//! before it is given up on and the previous slot is booted again.
+
+use hal::StorageDevice;
+use serde::{Deserialize, Serialize};
+
+use crate::image::ImageManifest;
+
+/// Boot attempts a new slot gets before rolling back
+pub const BOOT_ATTEMPTS: u8 = 3;
+
+/// Blocks reserved for boot control at the start of the device
+pub const METADATA_BLOCKS: u64 = 4;
+
+/// System slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Slot {
+    A,
+    B,
+}
+
+impl Slot {
+    pub fn other(self) -> Slot {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            Slot::A => 0,
+            Slot::B => 1,
+        }
+    }
+}
+
+/// What a slot holds and whether it may be booted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlotInfo {
+    pub version: Option<String>,
+    pub size: u64,
+    pub digest: Option<String>,
+    pub bootable: bool,
+    /// Set once the system has booted from the slot and confirmed it works
+    pub successful: bool,
+    pub tries_remaining: u8,
+}
+
+impl SlotInfo {
+    fn empty() -> Self {
+        SlotInfo {
+            version: None,
+            size: 0,
+            digest: None,
+            bootable: false,
+            successful: false,
+            tries_remaining: 0,
+        }
+    }
+}
+
+/// Persistent A/B state
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BootControl {
+    pub active: Slot,
+    slots: [SlotInfo; 2],
+}
+
+/// Outcome of choosing a slot at boot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootDecision {
+    pub slot: Slot,
+    /// Slot abandoned because it ran out of boot attempts
+    pub rolled_back_from: Option<Slot>,
+}
+
+impl BootControl {
+    /// State of a device as shipped: slot A holds a known-good system
+    pub fn factory() -> Self {
+        let mut a = SlotInfo::empty();
+        a.bootable = true;
+        a.successful = true;
+        BootControl {
+            active: Slot::A,
+            slots: [a, SlotInfo::empty()],
+        }
+    }
+
+    pub fn slot(&self, slot: Slot) -> &SlotInfo {
+        &self.slots[slot.index()]
+    }
+
+    pub(crate) fn slot_mut(&mut self, slot: Slot) -> &mut SlotInfo {
+        &mut self.slots[slot.index()]
+    }
+
+    /// Record a verified image in `slot` and boot it next
+    pub fn install(&mut self, slot: Slot, manifest: &ImageManifest) {
+        *self.slot_mut(slot) = SlotInfo {
+            version: Some(manifest.version.clone()),
+            size: manifest.size,
+            digest: Some(manifest.digest.clone()),
+            bootable: true,
+            successful: false,
+            tries_remaining: BOOT_ATTEMPTS,
+        };
+        self.active = slot;
+    }
+
+    /// Choose the slot to boot, consuming an attempt of an unconfirmed slot
+    pub fn begin_boot(&mut self) -> Result<BootDecision, String> {
+        let active = self.active;
+        let info = self.slot_mut(active);
+        if info.bootable && (info.successful || info.tries_remaining > 0) {
+            if !info.successful {
+                info.tries_remaining -= 1;
+            }
+            return Ok(BootDecision {
+                slot: active,
+                rolled_back_from: None,
+            });
+        }
+
+        info.bootable = false;
+        let fallback = active.other();
+        if !self.slot(fallback).bootable {
+            return Err("No bootable slot".to_string());
+        }
+        self.active = fallback;
+        Ok(BootDecision {
+            slot: fallback,
+            rolled_back_from: Some(active),
+        })
+    }
+
+    /// Confirm the active slot booted correctly
+    pub fn mark_successful(&mut self) {
+        let info = self.slot_mut(self.active);
+        info.successful = true;
+        info.tries_remaining = 0;
+    }
+
+    pub(crate) fn load(device: &dyn StorageDevice) -> Result<Option<Self>, String> {
+        let header = device.read_bytes(0, 4)?;
+        let len = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+        if len == 0 || len as u64 > METADATA_BLOCKS * device.block_size() as u64 - 4 {
+            return Ok(None);
+        }
+        let data = device.read_bytes(4, len)?;
+        Ok(serde_json::from_slice(&data).ok())
+    }
+
+    pub(crate) fn save(&self, device: &mut dyn StorageDevice) -> Result<(), String> {
+        let json = serde_json::to_vec(self).map_err(|e| e.to_string())?;
+        if json.len() as u64 > METADATA_BLOCKS * device.block_size() as u64 - 4 {
+            return Err("Boot control does not fit its region".to_string());
+        }
+        let mut data = (json.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(&json);
+        device.write_bytes(0, &data)
+    }
+}
+
+/// Where each slot lives on the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotLayout {
+    pub block_size: usize,
+    pub slot_blocks: u64,
+}
+
+impl SlotLayout {
+    pub fn for_device(device: &dyn StorageDevice) -> Result<Self, String> {
+        let block_size = device.block_size();
+        let blocks = device.capacity() / block_size as u64;
+        if blocks <= METADATA_BLOCKS + 1 {
+            return Err("Storage device too small for A/B slots".to_string());
+        }
+        Ok(SlotLayout {
+            block_size,
+            slot_blocks: (blocks - METADATA_BLOCKS) / 2,
+        })
+    }
+
+    /// First block of `slot`
+    pub fn first_block(&self, slot: Slot) -> u64 {
+        METADATA_BLOCKS + slot.index() as u64 * self.slot_blocks
+    }
+
+    /// Byte offset of `slot` on the device
+    pub fn slot_offset(&self, slot: Slot) -> u64 {
+        self.first_block(slot) * self.block_size as u64
+    }
+
+    /// Largest image a slot can hold
+    pub fn slot_capacity(&self) -> u64 {
+        self.slot_blocks * self.block_size as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rollback_after_failed_boots() {
+        let mut control = BootControl::factory();
+        control.install(Slot::B, &ImageManifest::for_image("2.0", b"system"));
+        assert_eq!(control.active, Slot::B);
+
+        for _ in 0..BOOT_ATTEMPTS {
+            assert_eq!(control.begin_boot().unwrap().slot, Slot::B);
+        }
+        let decision = control.begin_boot().unwrap();
+        assert_eq!(decision.slot, Slot::A);
+        assert_eq!(decision.rolled_back_from, Some(Slot::B));
+        assert!(!control.slot(Slot::B).bootable);
+
+        // A confirmed slot keeps booting
+        control.install(Slot::B, &ImageManifest::for_image("2.1", b"system"));
+        control.begin_boot().unwrap();
+        control.mark_successful();
+        for _ in 0..BOOT_ATTEMPTS + 1 {
+            assert_eq!(control.begin_boot().unwrap().slot, Slot::B);
+        }
+    }
+}
@@ -0,0 +1,587 @@
+//! User Accounts Service
+//!
+//! Keeps the user and group database behind the `owner_id`/`group_id`
+//! fields of the filesystem. Passwords are stored as salted hashes derived
+//! through the keystore, logins issue session tokens, and the service acts
+//! as the `UserDirectory` the VFS and capability manager resolve uids with.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use capability::{CapabilityManager, UserDirectory};
+use ipc::JsonService;
+use keystore::exchange::random_bytes;
+use keystore::kdf::DEFAULT_ITERATIONS;
+use keystore::{KeyId, KeyType, KeyUsage, Keystore};
+use serde::{Deserialize, Serialize};
+use system_utils::time::{system_clock, Clock};
+
+pub mod session;
+
+pub use session::{Session, SessionToken, SESSION_LIFETIME};
+
+/// Keystore key peppering every password hash
+pub const PEPPER_KEY: &str = "users/password-pepper";
+
+/// First uid and gid handed to regular accounts
+pub const FIRST_REGULAR_ID: u32 = 1000;
+
+const HASH_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+
+/// A password check that failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailedLogin {
+    pub name: String,
+    /// `None` if no account has that name
+    pub uid: Option<u32>,
+}
+
+/// Callback run for every failed password check
+pub type FailedLoginListener = Arc<dyn Fn(&FailedLogin) + Send + Sync>;
+
+/// A user account
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct User {
+    pub uid: u32,
+    pub name: String,
+    pub primary_group: u32,
+    pub home: String,
+}
+
+/// A group of users
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Group {
+    pub gid: u32,
+    pub name: String,
+    /// Users with this as a supplementary group
+    pub members: Vec<u32>,
+}
+
+struct Account {
+    user: User,
+    salt: Vec<u8>,
+    /// `None` while the account has no password and cannot log in
+    password_hash: Option<Vec<u8>>,
+}
+
+struct Database {
+    users: HashMap<u32, Account>,
+    groups: HashMap<u32, Group>,
+    next_uid: u32,
+    next_gid: u32,
+}
+
+impl Database {
+    fn uid_of(&self, name: &str) -> Option<u32> {
+        self.users.values().find(|a| a.user.name == name).map(|a| a.user.uid)
+    }
+
+    fn gid_of(&self, name: &str) -> Option<u32> {
+        self.groups.values().find(|g| g.name == name).map(|g| g.gid)
+    }
+}
+
+/// User and group database with login sessions
+pub struct UserService {
+    keystore: Arc<Keystore>,
+    capabilities: Arc<CapabilityManager>,
+    database: Arc<Mutex<Database>>,
+    sessions: Arc<Mutex<HashMap<SessionToken, Session>>>,
+    session_lifetime: Arc<Mutex<u64>>,
+    clock: Arc<Mutex<Arc<dyn Clock>>>,
+    failed_login_listeners: Arc<Mutex<Vec<FailedLoginListener>>>,
+    pepper: KeyId,
+}
+
+impl UserService {
+    /// Create the database with a `root` account that has no password yet
+    pub fn new(keystore: Arc<Keystore>, capabilities: Arc<CapabilityManager>) -> Result<Self, String> {
+        let pepper = KeyId::from(PEPPER_KEY);
+        if keystore.get_key(&pepper).is_none() {
+            keystore.generate_key(pepper.clone(), KeyType::AES256, vec![KeyUsage::DeriveKey], true)?;
+        }
+
+        let mut database = Database {
+            users: HashMap::new(),
+            groups: HashMap::new(),
+            next_uid: FIRST_REGULAR_ID,
+            next_gid: FIRST_REGULAR_ID,
+        };
+        database.groups.insert(
+            0,
+            Group {
+                gid: 0,
+                name: "root".to_string(),
+                members: Vec::new(),
+            },
+        );
+        database.users.insert(
+            0,
+            Account {
+                user: User {
+                    uid: 0,
+                    name: "root".to_string(),
+                    primary_group: 0,
+                    home: "/root".to_string(),
+                },
+                salt: random_bytes(SALT_LEN),
+                password_hash: None,
+            },
+        );
+
+        Ok(UserService {
+            keystore,
+            capabilities,
+            database: Arc::new(Mutex::new(database)),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            session_lifetime: Arc::new(Mutex::new(SESSION_LIFETIME)),
+            clock: Arc::new(Mutex::new(system_clock())),
+            failed_login_listeners: Arc::new(Mutex::new(Vec::new())),
+            pepper,
+        })
+    }
+
+    fn hash_password(&self, password: &str, salt: &[u8]) -> Result<Vec<u8>, String> {
+        self.keystore
+            .derive_key(&self.pepper, password.as_bytes(), salt, DEFAULT_ITERATIONS, HASH_LEN)
+    }
+
+    /// Create a group; returns its gid
+    pub fn create_group(&self, name: &str) -> Result<u32, String> {
+        let mut database = self.database.lock().unwrap();
+        if database.gid_of(name).is_some() {
+            return Err(format!("Group {} already exists", name));
+        }
+        let gid = database.next_gid;
+        database.next_gid += 1;
+        database.groups.insert(
+            gid,
+            Group {
+                gid,
+                name: name.to_string(),
+                members: Vec::new(),
+            },
+        );
+        Ok(gid)
+    }
+
+    /// Delete a group that is no user's primary group
+    pub fn delete_group(&self, name: &str) -> Result<(), String> {
+        let mut database = self.database.lock().unwrap();
+        let gid = database.gid_of(name).ok_or_else(|| format!("Unknown group {}", name))?;
+        if database.users.values().any(|a| a.user.primary_group == gid) {
+            return Err(format!("Group {} is a primary group", name));
+        }
+        database.groups.remove(&gid);
+        Ok(())
+    }
+
+    /// Create a user with a personal primary group; returns its uid
+    pub fn create_user(&self, name: &str, password: &str) -> Result<u32, String> {
+        if name.is_empty() || name.contains(['/', ':']) {
+            return Err(format!("Invalid user name {:?}", name));
+        }
+        let salt = random_bytes(SALT_LEN);
+        let password_hash = self.hash_password(password, &salt)?;
+
+        let mut database = self.database.lock().unwrap();
+        if database.uid_of(name).is_some() {
+            return Err(format!("User {} already exists", name));
+        }
+        let primary_group = match database.gid_of(name) {
+            Some(gid) => gid,
+            None => {
+                let gid = database.next_gid;
+                database.next_gid += 1;
+                database.groups.insert(
+                    gid,
+                    Group {
+                        gid,
+                        name: name.to_string(),
+                        members: Vec::new(),
+                    },
+                );
+                gid
+            }
+        };
+        let uid = database.next_uid;
+        database.next_uid += 1;
+        database.users.insert(
+            uid,
+            Account {
+                user: User {
+                    uid,
+                    name: name.to_string(),
+                    primary_group,
+                    home: format!("/home/{}", name),
+                },
+                salt,
+                password_hash: Some(password_hash),
+            },
+        );
+        Ok(uid)
+    }
+
+    /// Delete a user, ending its sessions and revoking its capabilities
+    pub fn delete_user(&self, name: &str) -> Result<(), String> {
+        let uid = {
+            let mut database = self.database.lock().unwrap();
+            let uid = database.uid_of(name).ok_or_else(|| format!("Unknown user {}", name))?;
+            if uid == 0 {
+                return Err("Cannot delete root".to_string());
+            }
+            let account = database.users.remove(&uid).unwrap();
+            for group in database.groups.values_mut() {
+                group.members.retain(|m| *m != uid);
+            }
+            // Drop the personal group if nobody else relies on it
+            let personal = account.user.primary_group;
+            let in_use = database.users.values().any(|a| a.user.primary_group == personal);
+            if !in_use && database.groups.get(&personal).is_some_and(|g| g.name == name) {
+                database.groups.remove(&personal);
+            }
+            uid
+        };
+        self.sessions.lock().unwrap().retain(|_, s| s.uid != uid);
+        self.capabilities.revoke_owned_by(uid);
+        Ok(())
+    }
+
+    /// Replace a user's password
+    pub fn set_password(&self, name: &str, password: &str) -> Result<(), String> {
+        let salt = random_bytes(SALT_LEN);
+        let password_hash = self.hash_password(password, &salt)?;
+        let mut database = self.database.lock().unwrap();
+        let uid = database.uid_of(name).ok_or_else(|| format!("Unknown user {}", name))?;
+        let account = database.users.get_mut(&uid).unwrap();
+        account.salt = salt;
+        account.password_hash = Some(password_hash);
+        Ok(())
+    }
+
+    pub fn add_to_group(&self, user: &str, group: &str) -> Result<(), String> {
+        let mut database = self.database.lock().unwrap();
+        let uid = database.uid_of(user).ok_or_else(|| format!("Unknown user {}", user))?;
+        let gid = database.gid_of(group).ok_or_else(|| format!("Unknown group {}", group))?;
+        let members = &mut database.groups.get_mut(&gid).unwrap().members;
+        if !members.contains(&uid) {
+            members.push(uid);
+        }
+        Ok(())
+    }
+
+    pub fn remove_from_group(&self, user: &str, group: &str) -> Result<(), String> {
+        let mut database = self.database.lock().unwrap();
+        let uid = database.uid_of(user).ok_or_else(|| format!("Unknown user {}", user))?;
+        let gid = database.gid_of(group).ok_or_else(|| format!("Unknown group {}", group))?;
+        database.groups.get_mut(&gid).unwrap().members.retain(|m| *m != uid);
+        Ok(())
+    }
+
+    pub fn user(&self, name: &str) -> Option<User> {
+        let database = self.database.lock().unwrap();
+        let uid = database.uid_of(name)?;
+        database.users.get(&uid).map(|a| a.user.clone())
+    }
+
+    pub fn user_by_uid(&self, uid: u32) -> Option<User> {
+        self.database.lock().unwrap().users.get(&uid).map(|a| a.user.clone())
+    }
+
+    pub fn group(&self, name: &str) -> Option<Group> {
+        let database = self.database.lock().unwrap();
+        let gid = database.gid_of(name)?;
+        database.groups.get(&gid).cloned()
+    }
+
+    pub fn list_users(&self) -> Vec<User> {
+        let mut users: Vec<User> = self.database.lock().unwrap().users.values().map(|a| a.user.clone()).collect();
+        users.sort_by_key(|u| u.uid);
+        users
+    }
+
+    /// Verify a password and open a session
+    pub fn login(&self, name: &str, password: &str) -> Result<SessionToken, String> {
+        let uid = self.verify_password(name, password)?;
+        let session = Session::new(uid, *self.session_lifetime.lock().unwrap(), self.now());
+        let token = session.token.clone();
+        self.sessions.lock().unwrap().insert(token.clone(), session);
+        Ok(token)
+    }
+
+    /// Check a password without opening a session; returns the uid
+    pub fn verify_password(&self, name: &str, password: &str) -> Result<u32, String> {
+        let (uid, salt, expected) = {
+            let database = self.database.lock().unwrap();
+            let Some(uid) = database.uid_of(name) else {
+                drop(database);
+                return Err(self.reject(name, None));
+            };
+            let account = &database.users[&uid];
+            let Some(expected) = account.password_hash.clone() else {
+                drop(database);
+                return Err(self.reject(name, Some(uid)));
+            };
+            (uid, account.salt.clone(), expected)
+        };
+        let actual = self.hash_password(password, &salt)?;
+        // Compare every byte so timing does not reveal the mismatch position
+        let difference = actual.iter().zip(&expected).fold(0u8, |acc, (a, b)| acc | (a ^ b));
+        if difference != 0 || actual.len() != expected.len() {
+            return Err(self.reject(name, Some(uid)));
+        }
+        Ok(uid)
+    }
+
+    fn reject(&self, name: &str, uid: Option<u32>) -> String {
+        let failure = FailedLogin {
+            name: name.to_string(),
+            uid,
+        };
+        let listeners = self.failed_login_listeners.lock().unwrap().clone();
+        for listener in listeners {
+            listener(&failure);
+        }
+        "Invalid user name or password".to_string()
+    }
+
+    /// Run `listener` for each failed password check from now on
+    pub fn on_failed_login(&self, listener: FailedLoginListener) {
+        self.failed_login_listeners.lock().unwrap().push(listener);
+    }
+
+    /// Look up a live session
+    pub fn session(&self, token: &SessionToken) -> Option<Session> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.get(token)?.clone();
+        if session.is_expired_at(self.now()) {
+            sessions.remove(token);
+            return None;
+        }
+        Some(session)
+    }
+
+    /// User a live session belongs to
+    pub fn session_user(&self, token: &SessionToken) -> Option<User> {
+        self.user_by_uid(self.session(token)?.uid)
+    }
+
+    pub fn logout(&self, token: &SessionToken) -> Result<(), String> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .remove(token)
+            .map(|_| ())
+            .ok_or_else(|| "Unknown session".to_string())
+    }
+
+    /// Lifetime of sessions opened from now on, in seconds
+    pub fn set_session_lifetime(&self, seconds: u64) {
+        *self.session_lifetime.lock().unwrap() = seconds;
+    }
+
+    /// Time sessions by `clock` instead of the host's clock
+    pub fn attach_clock(&self, clock: Arc<dyn Clock>) {
+        *self.clock.lock().unwrap() = clock;
+    }
+
+    /// Seconds since the Unix epoch
+    fn now(&self) -> u64 {
+        self.clock.lock().unwrap().now_ms() / 1000
+    }
+}
+
+impl JsonService for UserService {
+    type Request = UsersRequest;
+    type Response = UsersResponse;
+    const KIND: &'static str = "users";
+
+    fn handle_request(&self, request: UsersRequest) -> UsersResponse {
+        let result = match request {
+            UsersRequest::Login { name, password } => {
+                self.login(&name, &password).map(|token| UsersResponse::Session { token })
+            }
+            UsersRequest::Logout { token } => self.logout(&token).map(|_| UsersResponse::Ok),
+            UsersRequest::Whoami { token } => self
+                .session_user(&token)
+                .map(|user| UsersResponse::User { user })
+                .ok_or_else(|| "Unknown session".to_string()),
+            UsersRequest::Lookup { uid } => self
+                .user_by_uid(uid)
+                .map(|user| UsersResponse::User { user })
+                .ok_or_else(|| format!("Unknown uid {}", uid)),
+        };
+        result.unwrap_or_else(|message| UsersResponse::Error { message })
+    }
+}
+
+impl UserDirectory for UserService {
+    fn user_name(&self, uid: u32) -> Option<String> {
+        self.user_by_uid(uid).map(|u| u.name)
+    }
+
+    fn groups(&self, uid: u32) -> Vec<u32> {
+        let database = self.database.lock().unwrap();
+        let Some(account) = database.users.get(&uid) else {
+            return Vec::new();
+        };
+        let mut groups = vec![account.user.primary_group];
+        let mut supplementary: Vec<u32> = database
+            .groups
+            .values()
+            .filter(|g| g.members.contains(&uid) && g.gid != account.user.primary_group)
+            .map(|g| g.gid)
+            .collect();
+        supplementary.sort_unstable();
+        groups.extend(supplementary);
+        groups
+    }
+}
+
+/// Request accepted over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum UsersRequest {
+    Login { name: String, password: String },
+    Logout { token: SessionToken },
+    Whoami { token: SessionToken },
+    Lookup { uid: u32 },
+}
+
+/// Reply sent over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum UsersResponse {
+    Session { token: SessionToken },
+    User { user: User },
+    Ok,
+    Error { message: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use capability::{Permission, Resource};
+    use filesystem::{Access, FilePermissions, VirtualFileSystem};
+    use std::path::Path;
+    use std::time::Duration;
+    use ipc::{Message, ERROR_BAD_REQUEST};
+    use system_utils::time::SimulatedClock;
+
+    fn service() -> (Arc<UserService>, Arc<CapabilityManager>) {
+        let capabilities = Arc::new(CapabilityManager::new());
+        let users = UserService::new(Arc::new(Keystore::new()), Arc::clone(&capabilities)).unwrap();
+        (Arc::new(users), capabilities)
+    }
+
+    #[test]
+    fn test_users_and_groups() {
+        let (users, _) = service();
+        let alice = users.create_user("alice", "correct horse").unwrap();
+        assert_eq!(alice, FIRST_REGULAR_ID);
+        assert!(users.create_user("alice", "again").is_err());
+        assert!(users.create_user("a/b", "x").is_err());
+
+        let staff = users.create_group("staff").unwrap();
+        users.add_to_group("alice", "staff").unwrap();
+        let personal = users.user("alice").unwrap().primary_group;
+        assert_eq!(users.groups(alice), vec![personal, staff]);
+
+        assert!(users.delete_group("alice").is_err());
+        users.delete_user("alice").unwrap();
+        assert!(users.user("alice").is_none());
+        assert!(users.group("alice").is_none());
+        assert!(users.group("staff").unwrap().members.is_empty());
+        assert!(users.delete_user("root").is_err());
+    }
+
+    #[test]
+    fn test_login_sessions() {
+        let (users, _) = service();
+        let failures = Arc::new(Mutex::new(Vec::new()));
+        let heard = Arc::clone(&failures);
+        users.on_failed_login(Arc::new(move |failure: &FailedLogin| heard.lock().unwrap().push(failure.clone())));
+        users.create_user("alice", "correct horse").unwrap();
+        assert!(users.login("alice", "wrong").is_err());
+        assert!(users.login("mallory", "correct horse").is_err());
+        // root has no password until one is set
+        assert!(users.login("root", "").is_err());
+        let uids: Vec<Option<u32>> = failures.lock().unwrap().iter().map(|f| f.uid).collect();
+        assert_eq!(uids, vec![Some(FIRST_REGULAR_ID), None, Some(0)]);
+
+        let token = users.login("alice", "correct horse").unwrap();
+        assert_eq!(users.session_user(&token).unwrap().name, "alice");
+        users.logout(&token).unwrap();
+        assert!(users.session(&token).is_none());
+
+        users.set_password("alice", "battery staple").unwrap();
+        assert!(users.login("alice", "correct horse").is_err());
+        users.set_session_lifetime(0);
+        let expired = users.login("alice", "battery staple").unwrap();
+        assert!(users.session(&expired).is_none());
+    }
+
+    #[test]
+    fn test_session_expiry() {
+        let (users, _) = service();
+        let clock = Arc::new(SimulatedClock::new(1_709_164_800_000));
+        users.attach_clock(clock.clone());
+        users.create_user("alice", "pw").unwrap();
+        users.set_session_lifetime(60);
+
+        let token = users.login("alice", "pw").unwrap();
+        assert_eq!(users.session(&token).unwrap().expires_at, 1_709_164_860);
+        clock.advance(Duration::from_secs(59));
+        assert!(users.session(&token).is_some());
+        clock.advance(Duration::from_secs(1));
+        assert!(users.session(&token).is_none());
+    }
+
+    #[test]
+    fn test_directory_integration() {
+        let (users, capabilities) = service();
+        let alice = users.create_user("alice", "pw").unwrap();
+        let bob = users.create_user("bob", "pw").unwrap();
+        let staff = users.create_group("staff").unwrap();
+        users.add_to_group("bob", "staff").unwrap();
+
+        let vfs = VirtualFileSystem::new();
+        vfs.set_user_directory(users.clone());
+        let path = Path::new("/report.txt");
+        vfs.create_file(path).unwrap();
+        vfs.set_owner(path, alice, staff).unwrap();
+        vfs.set_permissions(path, FilePermissions::new(0o640)).unwrap();
+        assert!(vfs.check_access(path, bob, Access::Read).is_ok());
+        assert!(vfs.check_access(path, bob, Access::Write).is_err());
+
+        capabilities.set_user_directory(users.clone());
+        let token = capabilities.grant_to(bob, Resource::Network("listen:8080".to_string()), Permission::Write);
+        assert_eq!(capabilities.owner_name(token), Some("bob".to_string()));
+        assert!(capabilities.check_user_permission(token, bob, Permission::Write));
+        users.delete_user("bob").unwrap();
+        assert!(capabilities.validate(token).is_none());
+    }
+
+    #[test]
+    fn test_login_over_ipc() {
+        let (users, _) = service();
+        users.create_user("alice", "pw").unwrap();
+        let login = Message::Request {
+            id: 1,
+            data: br#"{"op":"login","name":"alice","password":"pw"}"#.to_vec(),
+        };
+        let Some(Message::Response { data, .. }) = users.handle_message(&login) else {
+            panic!("expected a response");
+        };
+        let UsersResponse::Session { token } = serde_json::from_slice(&data).unwrap() else {
+            panic!("expected a session");
+        };
+        match users.handle_request(UsersRequest::Whoami { token }) {
+            UsersResponse::User { user } => assert_eq!(user.name, "alice"),
+            other => panic!("unexpected response: {:?}", other),
+        }
+        let bad = Message::Request { id: 2, data: b"{}".to_vec() };
+        assert!(matches!(users.handle_message(&bad), Some(Message::Error { code: ERROR_BAD_REQUEST, .. })));
+    }
+}
@@ -0,0 +1,53 @@
+//! Login sessions
+//!
+//! A successful login issues an opaque session token that other services
+//! exchange for the user it belongs to. Sessions expire after a fixed
+//! lifetime and end when the user logs out or is deleted.
+
+use keystore::exchange::random_bytes;
+use serde::{Deserialize, Serialize};
+
+/// Default session lifetime, in seconds
+pub const SESSION_LIFETIME: u64 = 8 * 60 * 60;
+
+/// Opaque token identifying a login session
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SessionToken(String);
+
+impl SessionToken {
+    pub fn new(token: String) -> Self {
+        SessionToken(token)
+    }
+
+    pub(crate) fn generate() -> Self {
+        SessionToken(random_bytes(16).iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// An authenticated login
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub token: SessionToken,
+    pub uid: u32,
+    pub created_at: u64,
+    pub expires_at: u64,
+}
+
+impl Session {
+    pub(crate) fn new(uid: u32, lifetime: u64, created_at: u64) -> Self {
+        Session {
+            token: SessionToken::generate(),
+            uid,
+            created_at,
+            expires_at: created_at + lifetime,
+        }
+    }
+
+    pub fn is_expired_at(&self, time: u64) -> bool {
+        time >= self.expires_at
+    }
+}
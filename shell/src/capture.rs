@@ -0,0 +1,143 @@
+//! Screenshots and screen recording
+//!
+//! Capturing the screen or another application's window needs the screen
+//! capture capability. A recording captures its target at a fixed interval
+//! as the shell presents frames and queues the images until the recording
+//! application collects them; when it falls behind, the oldest frames are
+//! dropped. The capability a recording was started with is checked again
+//! for every frame, and only its holder may collect or stop it.
+
+use std::collections::VecDeque;
+
+use capability::CapabilityToken;
+use compositor::Image;
+
+use crate::output::OutputId;
+use crate::WindowId;
+
+/// Device resource a capability must name to capture the screen
+pub const CAPTURE_RESOURCE: &str = "screen-capture";
+
+/// Frames a recording holds before dropping the oldest
+pub const MAX_QUEUED_FRAMES: usize = 8;
+
+/// Recording identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RecordingId(u64);
+
+impl RecordingId {
+    pub fn new(id: u64) -> Self {
+        RecordingId(id)
+    }
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+/// What a capture shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureTarget {
+    /// Every output, laid out as on the desktop
+    Desktop,
+    Output(OutputId),
+    /// One window's contents, even where other windows cover it
+    Window(WindowId),
+}
+
+/// One recorded image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub sequence: u64,
+    /// Time since the recording started
+    pub time_ms: u64,
+    pub image: Image,
+}
+
+pub(crate) struct Recording {
+    /// Capability the recording was started with
+    pub(crate) token: CapabilityToken,
+    pub(crate) target: CaptureTarget,
+    interval_ms: u64,
+    elapsed_ms: u64,
+    /// `None` until the first frame, which is taken straight away
+    since_frame_ms: Option<u64>,
+    next_sequence: u64,
+    frames: VecDeque<Frame>,
+    /// The target went away; no more frames will come
+    pub(crate) ended: bool,
+}
+
+impl Recording {
+    pub(crate) fn new(token: CapabilityToken, target: CaptureTarget, interval_ms: u64) -> Self {
+        Recording {
+            token,
+            target,
+            interval_ms,
+            elapsed_ms: 0,
+            since_frame_ms: None,
+            next_sequence: 0,
+            frames: VecDeque::new(),
+            ended: false,
+        }
+    }
+
+    /// Advance time, returning whether a frame is due
+    pub(crate) fn advance(&mut self, elapsed_ms: u64) -> bool {
+        self.elapsed_ms += elapsed_ms;
+        if self.ended {
+            return false;
+        }
+        let Some(since) = self.since_frame_ms.as_mut() else {
+            self.since_frame_ms = Some(0);
+            return true;
+        };
+        *since += elapsed_ms;
+        if *since < self.interval_ms {
+            return false;
+        }
+        // Like vsync, missed frames are skipped rather than caught up
+        *since %= self.interval_ms;
+        true
+    }
+
+    pub(crate) fn push(&mut self, image: Image) {
+        if self.frames.len() == MAX_QUEUED_FRAMES {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(Frame {
+            sequence: self.next_sequence,
+            time_ms: self.elapsed_ms,
+            image,
+        });
+        self.next_sequence += 1;
+    }
+
+    pub(crate) fn take_frames(&mut self) -> Vec<Frame> {
+        self.frames.drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_pacing_and_queue() {
+        let mut recording = Recording::new(CapabilityToken::new(1), CaptureTarget::Desktop, 100);
+        assert!(recording.advance(0));
+        recording.push(Image::new(1, 1));
+        assert!(!recording.advance(60));
+        assert!(recording.advance(60));
+        recording.push(Image::new(1, 1));
+        assert!(recording.advance(350));
+        assert_eq!(recording.take_frames().iter().map(|f| (f.sequence, f.time_ms)).collect::<Vec<_>>(), vec![(0, 0), (1, 120)]);
+
+        for _ in 0..MAX_QUEUED_FRAMES + 2 {
+            recording.push(Image::new(1, 1));
+        }
+        let frames = recording.take_frames();
+        assert_eq!(frames.len(), MAX_QUEUED_FRAMES);
+        assert_eq!(frames[0].sequence, 4);
+    }
+}
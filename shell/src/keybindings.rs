@@ -0,0 +1,311 @@
+//! Keyboard shortcuts
+//!
+//! A binding maps a chord, one or more key combinations pressed in turn,
+//! to an action. Bindings are global or belong to one application; an
+//! application's bindings override global ones while one of its windows is
+//! focused. Key codes follow the HAL input events, which use PC virtual-key
+//! numbering.
+
+use std::fmt;
+
+pub const KEY_BACKSPACE: u32 = 0x08;
+pub const KEY_TAB: u32 = 0x09;
+pub const KEY_ENTER: u32 = 0x0d;
+pub const KEY_SHIFT: u32 = 0x10;
+pub const KEY_CTRL: u32 = 0x11;
+pub const KEY_ALT: u32 = 0x12;
+pub const KEY_ESCAPE: u32 = 0x1b;
+pub const KEY_SPACE: u32 = 0x20;
+pub const KEY_LEFT: u32 = 0x25;
+pub const KEY_UP: u32 = 0x26;
+pub const KEY_RIGHT: u32 = 0x27;
+pub const KEY_DOWN: u32 = 0x28;
+pub const KEY_DELETE: u32 = 0x2e;
+pub const KEY_SUPER: u32 = 0x5b;
+pub const KEY_F1: u32 = 0x70;
+
+const NAMED_KEYS: &[(&str, u32)] = &[
+    ("Backspace", KEY_BACKSPACE),
+    ("Tab", KEY_TAB),
+    ("Enter", KEY_ENTER),
+    ("Escape", KEY_ESCAPE),
+    ("Space", KEY_SPACE),
+    ("Left", KEY_LEFT),
+    ("Up", KEY_UP),
+    ("Right", KEY_RIGHT),
+    ("Down", KEY_DOWN),
+    ("Delete", KEY_DELETE),
+];
+
+/// Modifier keys held down
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub super_key: bool,
+}
+
+impl Modifiers {
+    /// Track a modifier key going down or up; false for other keys
+    pub fn update(&mut self, key: u32, pressed: bool) -> bool {
+        let flag = match key {
+            KEY_CTRL => &mut self.ctrl,
+            KEY_ALT => &mut self.alt,
+            KEY_SHIFT => &mut self.shift,
+            KEY_SUPER => &mut self.super_key,
+            _ => return false,
+        };
+        *flag = pressed;
+        true
+    }
+}
+
+/// A key pressed with a set of modifiers, such as `Ctrl+Shift+T`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyCombo {
+    pub modifiers: Modifiers,
+    pub key: u32,
+}
+
+impl KeyCombo {
+    pub fn new(modifiers: Modifiers, key: u32) -> Self {
+        KeyCombo { modifiers, key }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut modifiers = Modifiers::default();
+        let mut parts: Vec<&str> = text.split('+').collect();
+        let key_name = parts.pop().filter(|k| !k.is_empty()).ok_or_else(|| format!("Invalid key combination: {}", text))?;
+        for part in parts {
+            match part.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => modifiers.ctrl = true,
+                "alt" => modifiers.alt = true,
+                "shift" => modifiers.shift = true,
+                "super" | "win" | "meta" => modifiers.super_key = true,
+                _ => return Err(format!("Unknown modifier: {}", part)),
+            }
+        }
+        Ok(KeyCombo::new(modifiers, parse_key(key_name)?))
+    }
+}
+
+fn parse_key(name: &str) -> Result<u32, String> {
+    if let Some((_, code)) = NAMED_KEYS.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)) {
+        return Ok(*code);
+    }
+    if let [c] = name.as_bytes() {
+        if c.is_ascii_alphanumeric() {
+            return Ok(c.to_ascii_uppercase() as u32);
+        }
+    }
+    if let Some(n) = name.strip_prefix(['F', 'f']).and_then(|n| n.parse::<u32>().ok()) {
+        if (1..=12).contains(&n) {
+            return Ok(KEY_F1 + n - 1);
+        }
+    }
+    Err(format!("Unknown key: {}", name))
+}
+
+impl fmt::Display for KeyCombo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (held, name) in [
+            (self.modifiers.ctrl, "Ctrl"),
+            (self.modifiers.alt, "Alt"),
+            (self.modifiers.shift, "Shift"),
+            (self.modifiers.super_key, "Super"),
+        ] {
+            if held {
+                write!(f, "{}+", name)?;
+            }
+        }
+        match NAMED_KEYS.iter().find(|(_, code)| *code == self.key) {
+            Some((name, _)) => write!(f, "{}", name),
+            None if (KEY_F1..KEY_F1 + 12).contains(&self.key) => write!(f, "F{}", self.key - KEY_F1 + 1),
+            None => match char::from_u32(self.key).filter(|c| c.is_ascii_alphanumeric()) {
+                Some(c) => write!(f, "{}", c),
+                None => write!(f, "0x{:02x}", self.key),
+            },
+        }
+    }
+}
+
+/// Parse a space-separated chord such as `Ctrl+K Ctrl+C`
+pub fn parse_chord(text: &str) -> Result<Vec<KeyCombo>, String> {
+    let chord: Vec<KeyCombo> = text.split_whitespace().map(KeyCombo::parse).collect::<Result<_, _>>()?;
+    if chord.is_empty() {
+        return Err("Empty key chord".to_string());
+    }
+    Ok(chord)
+}
+
+/// Where a binding applies
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Scope {
+    Global,
+    /// Only while a window of this application is focused
+    App(String),
+}
+
+/// What a binding does
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    SpawnTerminal,
+    FocusNext,
+    FocusPrevious,
+    CloseWindow,
+    SwitchWorkspace(u64),
+    LockScreen,
+    /// Run a shell command line
+    Command(String),
+    /// Let the application have the keys instead of a global binding
+    PassThrough,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Binding {
+    pub scope: Scope,
+    pub chord: Vec<KeyCombo>,
+    pub action: Action,
+}
+
+/// Result of looking up the keys typed so far
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup {
+    Matched(Action),
+    /// The keys start a longer chord
+    Pending,
+    Unbound,
+}
+
+/// What the shell did with a key event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyOutcome {
+    /// A binding fired
+    Handled,
+    /// Waiting for the rest of a chord
+    Pending,
+    /// Keys for the focused application
+    Forwarded(Vec<KeyCombo>),
+    /// Not a key press the shell acts on
+    Ignored,
+}
+
+/// Keybinding registry
+#[derive(Debug, Clone, Default)]
+pub struct KeyBindings {
+    bindings: Vec<Binding>,
+}
+
+impl KeyBindings {
+    pub fn new() -> Self {
+        KeyBindings { bindings: Vec::new() }
+    }
+
+    /// Registry holding the desktop's standard shortcuts
+    pub fn with_defaults() -> Self {
+        let mut bindings = KeyBindings::new();
+        for (chord, action) in [
+            ("Super+Enter", Action::SpawnTerminal),
+            ("Alt+Tab", Action::FocusNext),
+            ("Alt+Shift+Tab", Action::FocusPrevious),
+            ("Super+Q", Action::CloseWindow),
+            ("Super+L", Action::LockScreen),
+            ("Super+1", Action::SwitchWorkspace(1)),
+            ("Super+2", Action::SwitchWorkspace(2)),
+            ("Super+3", Action::SwitchWorkspace(3)),
+            ("Super+4", Action::SwitchWorkspace(4)),
+        ] {
+            bindings.bind(Scope::Global, parse_chord(chord).unwrap(), action).unwrap();
+        }
+        bindings
+    }
+
+    /// Bindings in `scope` that would clash with `chord`: the same chord,
+    /// or one that is a prefix of the other and so could never both fire
+    pub fn conflicts(&self, scope: &Scope, chord: &[KeyCombo]) -> Vec<&Binding> {
+        self.bindings
+            .iter()
+            .filter(|b| &b.scope == scope && (b.chord.starts_with(chord) || chord.starts_with(&b.chord)))
+            .collect()
+    }
+
+    /// Add a binding, refusing ones that conflict
+    pub fn bind(&mut self, scope: Scope, chord: Vec<KeyCombo>, action: Action) -> Result<(), String> {
+        if chord.is_empty() {
+            return Err("Empty key chord".to_string());
+        }
+        if let Some(existing) = self.conflicts(&scope, &chord).first() {
+            return Err(format!("{} conflicts with {}", format_chord(&chord), format_chord(&existing.chord)));
+        }
+        self.bindings.push(Binding { scope, chord, action });
+        Ok(())
+    }
+
+    /// Remove a binding, returning its action
+    pub fn unbind(&mut self, scope: &Scope, chord: &[KeyCombo]) -> Option<Action> {
+        let index = self.bindings.iter().position(|b| &b.scope == scope && b.chord == chord)?;
+        Some(self.bindings.remove(index).action)
+    }
+
+    pub fn bindings(&self) -> &[Binding] {
+        &self.bindings
+    }
+
+    /// Resolve keys typed so far, preferring the focused application's bindings
+    pub fn lookup(&self, app: Option<&str>, keys: &[KeyCombo]) -> Lookup {
+        let scopes = app.map(|app| Scope::App(app.to_string())).into_iter().chain([Scope::Global]);
+        for scope in scopes {
+            let candidates = self.conflicts(&scope, keys);
+            if let Some(exact) = candidates.iter().find(|b| b.chord == keys) {
+                return Lookup::Matched(exact.action.clone());
+            }
+            if candidates.iter().any(|b| b.chord.starts_with(keys)) {
+                return Lookup::Pending;
+            }
+        }
+        Lookup::Unbound
+    }
+}
+
+pub fn format_chord(chord: &[KeyCombo]) -> String {
+    chord.iter().map(KeyCombo::to_string).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format() {
+        let combo = KeyCombo::parse("shift+ctrl+t").unwrap();
+        assert_eq!(combo.key, 'T' as u32);
+        assert_eq!(combo.to_string(), "Ctrl+Shift+T");
+        assert_eq!(KeyCombo::parse("Super+Enter").unwrap().to_string(), "Super+Enter");
+        assert_eq!(KeyCombo::parse("F11").unwrap().key, KEY_F1 + 10);
+        assert_eq!(format_chord(&parse_chord("Ctrl+K  Ctrl+C").unwrap()), "Ctrl+K Ctrl+C");
+        assert!(KeyCombo::parse("Hyper+A").is_err());
+        assert!(KeyCombo::parse("Ctrl+").is_err());
+        assert!(parse_chord(" ").is_err());
+    }
+
+    #[test]
+    fn test_chords_overrides_and_conflicts() {
+        let mut bindings = KeyBindings::with_defaults();
+        let chord = parse_chord("Ctrl+K Ctrl+C").unwrap();
+        bindings.bind(Scope::Global, chord.clone(), Action::Command("windows".to_string())).unwrap();
+        assert_eq!(bindings.lookup(None, &chord[..1]), Lookup::Pending);
+        assert_eq!(bindings.lookup(None, &chord), Lookup::Matched(Action::Command("windows".to_string())));
+
+        // A prefix of an existing chord can never fire, so it is refused
+        let prefix = parse_chord("Ctrl+K").unwrap();
+        assert_eq!(bindings.conflicts(&Scope::Global, &prefix).len(), 1);
+        assert!(bindings.bind(Scope::Global, prefix.clone(), Action::FocusNext).is_err());
+        assert!(bindings.bind(Scope::App("editor".to_string()), prefix.clone(), Action::PassThrough).is_ok());
+        assert_eq!(bindings.lookup(Some("editor"), &prefix), Lookup::Matched(Action::PassThrough));
+
+        let alt_tab = parse_chord("Alt+Tab").unwrap();
+        assert_eq!(bindings.unbind(&Scope::Global, &alt_tab), Some(Action::FocusNext));
+        assert_eq!(bindings.lookup(Some("editor"), &alt_tab), Lookup::Unbound);
+    }
+}
@@ -0,0 +1,51 @@
+//! Launching applications
+//!
+//! Applications run in the user's session, which spawns them with the
+//! capabilities their sandbox grants. The shell then opens the application's
+//! channels: a display channel to the shell for window traffic, and one
+//! channel for each service its sandbox lets it reach, waiting for the
+//! service to connect. Every channel is owned by the application, so they
+//! close when it exits.
+
+use std::collections::BTreeMap;
+
+use ipc::{ChannelId, IPCManager, OrphanPolicy};
+use kernel::{ProcessId, SandboxProfile};
+
+/// An application started from the shell
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LaunchedApp {
+    pub app_id: String,
+    pub process: ProcessId,
+    /// Connects the application to the shell's process
+    pub display: ChannelId,
+    /// By service name
+    pub services: BTreeMap<String, ChannelId>,
+}
+
+impl LaunchedApp {
+    /// Open the channels of `process`, started for `app_id` within the
+    /// session whose shell runs as `shell`
+    pub(crate) fn open(
+        ipc: &IPCManager,
+        app_id: &str,
+        process: ProcessId,
+        shell: ProcessId,
+        sandbox: Option<SandboxProfile>,
+    ) -> Result<Self, String> {
+        let display = ipc.create_owned_channel(process, OrphanPolicy::Close);
+        ipc.connect(display, shell).map_err(String::from)?;
+        let services = sandbox
+            .map(|profile| profile.services)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|service| (service, ipc.create_owned_channel(process, OrphanPolicy::Close)))
+            .collect();
+        Ok(LaunchedApp {
+            app_id: app_id.to_string(),
+            process,
+            display,
+            services,
+        })
+    }
+}
@@ -0,0 +1,97 @@
+//! Session layout
+//!
+//! When the shell exits it writes its workspaces and windows to a JSON file
+//! in the VFS. At the next login the shell reads the file back, relaunches
+//! each window's application and puts the window back where it was. Windows
+//! are stored bottom first, so restoring them in order rebuilds the
+//! stacking order too.
+
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{WindowId, WindowState};
+
+/// A window as it was when the layout was saved
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedWindow {
+    pub title: String,
+    /// Application to relaunch; windows without one cannot be restored
+    pub app_id: Option<String>,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub state: WindowState,
+    /// Position of the window's workspace in `SessionLayout::workspaces`
+    pub workspace: usize,
+    #[serde(default)]
+    pub always_on_top: bool,
+    /// Focused window of its workspace
+    #[serde(default)]
+    pub focused: bool,
+}
+
+/// Everything needed to rebuild the desktop
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionLayout {
+    /// Workspace names in switcher order
+    pub workspaces: Vec<String>,
+    pub active_workspace: usize,
+    /// Windows, bottom of the stack first
+    pub windows: Vec<SavedWindow>,
+}
+
+impl SessionLayout {
+    pub fn to_json(&self) -> Vec<u8> {
+        serde_json::to_vec_pretty(self).unwrap()
+    }
+
+    pub fn from_json(data: &[u8]) -> Result<Self, String> {
+        let layout: SessionLayout =
+            serde_json::from_slice(data).map_err(|e| format!("Invalid session layout: {}", e))?;
+        if layout.workspaces.is_empty() {
+            return Err("Invalid session layout: no workspaces".to_string());
+        }
+        if layout.active_workspace >= layout.workspaces.len()
+            || layout.windows.iter().any(|w| w.workspace >= layout.workspaces.len())
+        {
+            return Err("Invalid session layout: unknown workspace".to_string());
+        }
+        Ok(layout)
+    }
+}
+
+/// Starts an application to own a restored window, returning its process ID
+pub type Launcher = Arc<dyn Fn(&SavedWindow) -> Result<u64, String> + Send + Sync>;
+
+/// Outcome of restoring a layout
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RestoreReport {
+    pub restored: Vec<WindowId>,
+    /// Titles of the windows left out, with the reason
+    pub skipped: Vec<(String, String)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_validation() {
+        let layout = SessionLayout::from_json(
+            br#"{"workspaces":["Main"],"active_workspace":0,"windows":[{"title":"Editor","app_id":"editor",
+                "x":10,"y":20,"width":640,"height":480,"state":"maximized","workspace":0}]}"#,
+        )
+        .unwrap();
+        assert_eq!(layout.windows[0].state, WindowState::Maximized);
+        assert!(!layout.windows[0].focused);
+        assert_eq!(SessionLayout::from_json(&layout.to_json()).unwrap(), layout);
+
+        let mut broken = layout.clone();
+        broken.windows[0].workspace = 1;
+        assert!(SessionLayout::from_json(&broken.to_json()).is_err());
+        assert!(SessionLayout::from_json(&SessionLayout::default().to_json()).is_err());
+        assert!(SessionLayout::from_json(b"not json").is_err());
+    }
+}
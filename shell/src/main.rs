@@ -2,8 +2,60 @@
 //! 
 //! Provides the basic desktop environment and windowing system for hairr OS.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use accessibility::{AccessibilityService, Priority};
+use capability::{CapabilityManager, CapabilityToken, Permission, Resource};
+use compositor::{Compositor, Image, Layer, Rect, SharedDisplay, Surface, VSYNC_INTERVAL_MS};
+use filesystem::{VfsEvent, VirtualFileSystem};
+use hal::InputEvent;
+use i18n::{Locale, Localizer};
+use ipc::{ChannelId, IPCManager};
+use kernel::{Kernel, ProcessId, ProcessState};
+use notifications::{NotificationId, NotificationService, Urgency};
+use power::{PowerEvent, PowerManager};
+use serde::{Deserialize, Serialize};
+use session::SessionManager;
+use system_utils::cli::{Cli, CliError, Command};
+use terminal::{size_for_pixels, Terminal};
+use theme::{Appearance, Mode, ThemeManager};
+use time::TimeService;
+use users::SessionToken;
+
+mod capture;
+mod keybindings;
+mod launch;
+mod layout;
+mod output;
+mod panel;
+mod pointer;
+mod rules;
+mod workspace;
+
+use capture::{CaptureTarget, Frame, Recording, RecordingId, CAPTURE_RESOURCE};
+use keybindings::{format_chord, parse_chord, Action, KeyBindings, KeyCombo, KeyOutcome, Lookup, Modifiers, Scope};
+use launch::LaunchedApp;
+use layout::{Launcher, RestoreReport, SavedWindow, SessionLayout};
+use output::{carry, overlap, Output, OutputId};
+use panel::{PanelEntry, Tray};
+use pointer::{hit_test, Grab, GrabKind, Snap, BUTTON_LEFT};
+use rules::{RuleAction, RuleSet};
+use workspace::{Workspace, WorkspaceEvent, WorkspaceId, WorkspaceListener};
+
+/// Source title of terminal windows, translated when shown
+const TERMINAL_TITLE: &str = "Terminal";
+
+/// Height of the title bar drawn above window contents
+const TITLE_BAR_HEIGHT: u32 = 24;
+
+/// Screen size assumed while no output is connected
+const DEFAULT_SCREEN_SIZE: (u32, u32) = (1920, 1080);
+
+/// Process id of the windows the shell opens for itself, such as terminals
+pub const SHELL_PROCESS: u64 = 0;
 
 /// Window identifier
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -16,7 +68,8 @@ impl WindowId {
 }
 
 /// Window state
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum WindowState {
     Normal,
     Minimized,
@@ -35,10 +88,19 @@ pub struct Window {
     pub height: u32,
     pub state: WindowState,
     pub process_id: u64,
+    pub workspace: WorkspaceId,
+    /// Application the window belongs to, for per-application keybindings
+    pub app_id: Option<String>,
+    /// Stacked above every window without the flag
+    pub always_on_top: bool,
+    /// Set by window rules: never snapped to a screen edge
+    pub floating: bool,
+    /// Set by window rules: the pointer cannot resize the window
+    pub fixed_size: bool,
 }
 
 impl Window {
-    pub fn new(id: WindowId, title: String, process_id: u64) -> Self {
+    pub fn new(id: WindowId, title: String, process_id: u64, workspace: WorkspaceId) -> Self {
         Window {
             id,
             title,
@@ -48,43 +110,1061 @@ impl Window {
             height: 600,
             state: WindowState::Normal,
             process_id,
+            workspace,
+            app_id: None,
+            always_on_top: false,
+            floating: false,
+            fixed_size: false,
         }
     }
 }
 
+/// Geometry change reported to the application owning a window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowEvent {
+    Moved { x: i32, y: i32 },
+    Resized { width: u32, height: u32 },
+}
+
+/// Callback run for events of one window
+pub type WindowListener = Arc<dyn Fn(WindowId, &WindowEvent) + Send + Sync>;
+
 /// Desktop shell manager
 pub struct Shell {
     windows: HashMap<WindowId, Window>,
     next_window_id: u64,
-    focused_window: Option<WindowId>,
+    /// Stacking order, bottom first, before always-on-top windows are lifted
+    stack: Vec<WindowId>,
+    /// Virtual desktops in switcher order
+    workspaces: BTreeMap<WorkspaceId, Workspace>,
+    active_workspace: WorkspaceId,
+    next_workspace_id: u64,
+    workspace_listeners: Vec<WorkspaceListener>,
+    notifications: Option<Arc<NotificationService>>,
+    accessibility: Option<Arc<AccessibilityService>>,
+    /// Connected monitors
+    outputs: BTreeMap<OutputId, Output>,
+    next_output_id: u64,
+    /// Pixel buffers of the windows, while an output is connected
+    surfaces: HashMap<WindowId, Surface>,
+    /// Output whose compositor holds each window's surface
+    surface_outputs: HashMap<WindowId, OutputId>,
+    /// Geometry to restore when a window leaves fullscreen
+    saved_geometry: HashMap<WindowId, Rect>,
+    keybindings: KeyBindings,
+    modifiers: Modifiers,
+    /// Keys of a chord typed so far
+    pending_keys: Vec<KeyCombo>,
+    pointer: (i32, i32),
+    /// Window being moved or resized with the pointer
+    grab: Option<Grab>,
+    window_listeners: HashMap<WindowId, Vec<WindowListener>>,
+    /// Status icons shown in the panel
+    tray: Arc<Tray>,
+    time: Option<Arc<TimeService>>,
+    /// Start the applications of restored windows, by application ID
+    launchers: HashMap<String, Launcher>,
+    /// File the layout is saved to on exit and restored from on start
+    layout_store: Option<(Arc<VirtualFileSystem>, PathBuf)>,
+    rules: RuleSet,
+    /// File the window rules are read from
+    rules_store: Option<(Arc<VirtualFileSystem>, PathBuf)>,
+    /// Set when the rules file changes
+    rules_changed: Arc<Mutex<bool>>,
+    ipc: Arc<IPCManager>,
+    /// Applications started with `launch`, by process id
+    apps: HashMap<u64, LaunchedApp>,
+    /// Checks that windows belong to running processes
+    kernel: Option<Arc<Kernel>>,
+    /// Processes the kernel reported as terminated, whose windows are
+    /// still open
+    exited: Arc<Mutex<Vec<u64>>>,
+    /// Terminal sessions shown in windows
+    terminals: HashMap<WindowId, Terminal>,
+    i18n: Arc<Localizer>,
+    /// Set by the localizer when the user switches language
+    locale_changed: Arc<Mutex<bool>>,
+    theme: Arc<ThemeManager>,
+    /// Set when the theme or dark/light mode changes
+    theme_changed: Arc<Mutex<bool>>,
+    /// Session whose password unlocks the screen
+    session: Option<(Arc<SessionManager>, SessionToken)>,
+    power: Option<Arc<PowerManager>>,
+    /// Windows are hidden and input goes nowhere until the user unlocks
+    locked: bool,
+    /// Set by the power manager when the screen should lock
+    lock_requested: Arc<Mutex<bool>>,
+    /// Checks the capabilities presented for screen capture
+    capabilities: Option<Arc<CapabilityManager>>,
+    recordings: BTreeMap<RecordingId, Recording>,
+    next_recording_id: u64,
+    commands: Cli,
 }
 
 impl Shell {
     pub fn new() -> Self {
+        let first = WorkspaceId::new(1);
+        let mut workspaces = BTreeMap::new();
+        workspaces.insert(first, Workspace::new(first, "Workspace 1".to_string()));
         Shell {
             windows: HashMap::new(),
             next_window_id: 1,
-            focused_window: None,
+            stack: Vec::new(),
+            workspaces,
+            active_workspace: first,
+            next_workspace_id: 2,
+            workspace_listeners: Vec::new(),
+            notifications: None,
+            accessibility: None,
+            outputs: BTreeMap::new(),
+            next_output_id: 1,
+            surfaces: HashMap::new(),
+            surface_outputs: HashMap::new(),
+            saved_geometry: HashMap::new(),
+            keybindings: KeyBindings::with_defaults(),
+            modifiers: Modifiers::default(),
+            pending_keys: Vec::new(),
+            pointer: (0, 0),
+            grab: None,
+            window_listeners: HashMap::new(),
+            tray: Arc::new(Tray::new()),
+            time: None,
+            launchers: HashMap::new(),
+            layout_store: None,
+            rules: RuleSet::default(),
+            rules_store: None,
+            rules_changed: Arc::new(Mutex::new(false)),
+            ipc: Arc::new(IPCManager::new()),
+            apps: HashMap::new(),
+            kernel: None,
+            exited: Arc::new(Mutex::new(Vec::new())),
+            terminals: HashMap::new(),
+            i18n: Arc::new(Localizer::new()),
+            locale_changed: Arc::new(Mutex::new(false)),
+            theme: Arc::new(ThemeManager::new()),
+            theme_changed: Arc::new(Mutex::new(false)),
+            session: None,
+            power: None,
+            locked: false,
+            lock_requested: Arc::new(Mutex::new(false)),
+            capabilities: None,
+            recordings: BTreeMap::new(),
+            next_recording_id: 1,
+            commands: commands(),
+        }
+    }
+
+    /// Render user-facing text through `i18n`, following its locale changes
+    pub fn attach_localizer(&mut self, i18n: Arc<Localizer>) {
+        let changed = Arc::clone(&self.locale_changed);
+        i18n.on_change(Arc::new(move |_| *changed.lock().unwrap() = true));
+        self.i18n = i18n;
+        *self.locale_changed.lock().unwrap() = true;
+    }
+
+    /// Retranslate the titles the shell gave its own windows
+    fn apply_locale_change(&mut self) {
+        if !std::mem::take(&mut *self.locale_changed.lock().unwrap()) {
+            return;
+        }
+        let title = self.i18n.tr(TERMINAL_TITLE);
+        for id in self.terminals.keys() {
+            if let Some(window) = self.windows.get_mut(id) {
+                window.title = title.clone();
+            }
+        }
+    }
+
+    /// Draw with the user's theme, repainting whenever it changes
+    pub fn attach_theme(&mut self, theme: Arc<ThemeManager>) {
+        let changed = Arc::clone(&self.theme_changed);
+        theme.on_change(Arc::new(move |_| *changed.lock().unwrap() = true));
+        self.theme = theme;
+        *self.theme_changed.lock().unwrap() = true;
+    }
+
+    /// Repaint decorations, desktops and terminals in the current theme
+    fn apply_theme_change(&mut self) {
+        if !std::mem::take(&mut *self.theme_changed.lock().unwrap()) {
+            return;
+        }
+        let appearance = self.theme.appearance();
+        for output in self.outputs.values() {
+            output.compositor.set_background(appearance.palette().desktop.rgba());
+        }
+        for surface in self.surfaces.values() {
+            paint_decorations(surface, &appearance);
+        }
+        for terminal in self.terminals.values_mut() {
+            terminal.set_palette(*appearance.palette());
+        }
+    }
+
+    /// Open terminals and application channels on the system's IPC manager
+    pub fn attach_ipc(&mut self, ipc: Arc<IPCManager>) {
+        self.ipc = ipc;
+    }
+
+    /// Only open windows for running processes, and close a process's
+    /// windows once it terminates
+    pub fn attach_kernel(&mut self, kernel: Arc<Kernel>) {
+        let exited = Arc::clone(&self.exited);
+        kernel.on_process_terminated(Arc::new(move |pid| exited.lock().unwrap().push(pid.value())));
+        self.kernel = Some(kernel);
+    }
+
+    /// Close the windows of processes that terminated since the last call
+    fn close_exited_windows(&mut self) -> Result<(), String> {
+        let exited: Vec<u64> = std::mem::take(&mut *self.exited.lock().unwrap());
+        for pid in exited {
+            self.apps.remove(&pid);
+            let mut orphaned: Vec<WindowId> = self.windows.values().filter(|w| w.process_id == pid).map(|w| w.id).collect();
+            orphaned.sort_by_key(|id| id.0);
+            for id in orphaned {
+                self.close_window(id)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Lock and unlock through the user's session
+    pub fn attach_session(&mut self, manager: Arc<SessionManager>, token: SessionToken) {
+        self.session = Some((manager, token));
+    }
+
+    /// Report user activity to the power manager and lock when it says the
+    /// user has gone idle or the system is about to suspend
+    pub fn attach_power(&mut self, power: Arc<PowerManager>) {
+        let requested = Arc::clone(&self.lock_requested);
+        power.on_idle(Arc::new(move || *requested.lock().unwrap() = true));
+        let requested = Arc::clone(&self.lock_requested);
+        power.subscribe(
+            "shell",
+            Arc::new(move |event| {
+                if event == PowerEvent::Suspending {
+                    *requested.lock().unwrap() = true;
+                }
+                Ok(())
+            }),
+        );
+        self.power = Some(power);
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Hide every window and stop delivering input until the session password is entered
+    pub fn lock(&mut self) -> Result<(), String> {
+        if self.locked {
+            return Ok(());
+        }
+        let (manager, token) = self.session.as_ref().ok_or("No session to lock")?;
+        manager.lock(token)?;
+        self.locked = true;
+        self.grab = None;
+        self.pending_keys.clear();
+        if let Some(service) = &self.accessibility {
+            service.announce(&self.i18n.tr("Screen locked"), Priority::Assertive);
+        }
+        Ok(())
+    }
+
+    pub fn unlock(&mut self, password: &str) -> Result<(), String> {
+        if !self.locked {
+            return Err("Screen is not locked".to_string());
+        }
+        let (manager, token) = self.session.as_ref().ok_or("No session to unlock")?;
+        manager.unlock(token, password)?;
+        self.locked = false;
+        if let Some(power) = &self.power {
+            power.record_activity();
+        }
+        if let Some(service) = &self.accessibility {
+            service.announce(&self.i18n.tr("Screen unlocked"), Priority::Assertive);
+        }
+        Ok(())
+    }
+
+    fn apply_lock_request(&mut self) {
+        if std::mem::take(&mut *self.lock_requested.lock().unwrap()) {
+            // Without a session there is nothing to lock
+            let _ = self.lock();
+        }
+    }
+
+    /// Allow screen capture to holders of the screen capture capability
+    pub fn attach_capabilities(&mut self, capabilities: Arc<CapabilityManager>) {
+        self.capabilities = Some(capabilities);
+    }
+
+    fn authorize_capture(&self, token: CapabilityToken) -> Result<(), String> {
+        let authorized = self.capabilities.as_ref().is_some_and(|capabilities| match capabilities.validate(token) {
+            Some(cap) => {
+                cap.resource == Resource::Device(CAPTURE_RESOURCE.to_string())
+                    && capabilities.check_permission(token, Permission::Read)
+            }
+            None => false,
+        });
+        if !authorized {
+            return Err("Permission denied: capturing the screen requires the screen capture capability".to_string());
+        }
+        Ok(())
+    }
+
+    /// Screenshot of one window's contents
+    pub fn capture_window(&self, token: CapabilityToken, id: WindowId) -> Result<Image, String> {
+        self.authorize_capture(token)?;
+        self.capture(CaptureTarget::Window(id))
+    }
+
+    /// Screenshot of the whole desktop across every output
+    pub fn capture_screen(&self, token: CapabilityToken) -> Result<Image, String> {
+        self.authorize_capture(token)?;
+        self.capture(CaptureTarget::Desktop)
+    }
+
+    fn capture(&self, target: CaptureTarget) -> Result<Image, String> {
+        match target {
+            CaptureTarget::Window(id) => {
+                self.windows.get(&id).ok_or("Window not found")?;
+                if self.locked {
+                    return Err("Screen is locked".to_string());
+                }
+                let surface = self.surfaces.get(&id).ok_or("Window is not on a display")?;
+                Ok(surface.snapshot())
+            }
+            CaptureTarget::Output(id) => {
+                let output = self.outputs.get(&id).ok_or("Output not found")?;
+                Ok(output.compositor.capture_screen())
+            }
+            CaptureTarget::Desktop => {
+                if self.outputs.is_empty() {
+                    return Err("No display connected".to_string());
+                }
+                let bounds = self.desktop_bounds();
+                let mut image = Image::new(bounds.width, bounds.height);
+                for output in self.outputs.values() {
+                    image.blit(&output.compositor.capture_screen(), output.rect.x - bounds.x, output.rect.y - bounds.y);
+                }
+                Ok(image)
+            }
+        }
+    }
+
+    /// Capture `target` every `interval_ms` as frames are presented
+    pub fn start_recording(
+        &mut self,
+        token: CapabilityToken,
+        target: CaptureTarget,
+        interval_ms: u64,
+    ) -> Result<RecordingId, String> {
+        self.authorize_capture(token)?;
+        if interval_ms < VSYNC_INTERVAL_MS {
+            return Err(format!("Frames can be recorded at most every {} ms", VSYNC_INTERVAL_MS));
+        }
+        match target {
+            CaptureTarget::Window(id) if !self.windows.contains_key(&id) => return Err("Window not found".to_string()),
+            CaptureTarget::Output(id) if !self.outputs.contains_key(&id) => return Err("Output not found".to_string()),
+            _ => {}
+        }
+        let id = RecordingId::new(self.next_recording_id);
+        self.next_recording_id += 1;
+        self.recordings.insert(id, Recording::new(token, target, interval_ms));
+        Ok(id)
+    }
+
+    /// Recording started with `token`; others' recordings are not found
+    fn owned_recording(&self, token: CapabilityToken, id: RecordingId) -> Result<(), String> {
+        match self.recordings.get(&id) {
+            Some(recording) if recording.token == token => Ok(()),
+            _ => Err("Recording not found".to_string()),
+        }
+    }
+
+    /// Collect the frames recorded so far; once the target is gone and the
+    /// last frames are collected, the recording is over. A revoked
+    /// capability ends the recording and its frames go uncollected.
+    pub fn take_frames(&mut self, token: CapabilityToken, id: RecordingId) -> Result<Vec<Frame>, String> {
+        self.owned_recording(token, id)?;
+        if let Err(e) = self.authorize_capture(token) {
+            self.recordings.remove(&id);
+            return Err(e);
+        }
+        let recording = self.recordings.get_mut(&id).unwrap();
+        let frames = recording.take_frames();
+        if recording.ended {
+            self.recordings.remove(&id);
+        }
+        Ok(frames)
+    }
+
+    pub fn stop_recording(&mut self, token: CapabilityToken, id: RecordingId) -> Result<(), String> {
+        self.owned_recording(token, id)?;
+        self.recordings.remove(&id);
+        Ok(())
+    }
+
+    fn record_frames(&mut self, elapsed_ms: u64) {
+        let due: Vec<RecordingId> = self
+            .recordings
+            .iter_mut()
+            .filter_map(|(id, recording)| recording.advance(elapsed_ms).then_some(*id))
+            .collect();
+        for id in due {
+            let Recording { token, target, .. } = self.recordings[&id];
+            if self.authorize_capture(token).is_err() {
+                self.recordings.get_mut(&id).unwrap().ended = true;
+                continue;
+            }
+            let image = self.capture(target);
+            let recording = self.recordings.get_mut(&id).unwrap();
+            match image {
+                Ok(image) => recording.push(image),
+                // A locked screen pauses window recordings instead of ending them
+                Err(_) if self.locked => {}
+                Err(_) => recording.ended = true,
+            }
+        }
+    }
+
+    /// Show notifications from the notification service
+    pub fn attach_notifications(&mut self, service: Arc<NotificationService>) {
+        self.notifications = Some(service);
+    }
+
+    /// Show the time service's local time in the panel clock
+    pub fn attach_time(&mut self, service: Arc<TimeService>) {
+        self.time = Some(service);
+    }
+
+    /// Tray that services register their status icons with
+    pub fn tray(&self) -> Arc<Tray> {
+        Arc::clone(&self.tray)
+    }
+
+    /// Window list of the active workspace, in opening order
+    pub fn panel_entries(&self) -> Vec<PanelEntry> {
+        if self.locked {
+            return Vec::new();
+        }
+        let focused = self.get_focused_window();
+        self.windows_on(self.active_workspace)
+            .into_iter()
+            .map(|w| PanelEntry {
+                window: w.id,
+                title: w.title.clone(),
+                focused: Some(w.id) == focused,
+                minimized: w.state == WindowState::Minimized,
+            })
+            .collect()
+    }
+
+    /// Panel click on a window: restore and focus it, or minimize it if it is
+    /// already the focused window
+    pub fn panel_activate(&mut self, id: WindowId) -> Result<(), String> {
+        let window = self.windows.get(&id).ok_or("Window not found")?;
+        if window.state == WindowState::Minimized {
+            self.set_window_state(id, WindowState::Normal)?;
+        } else if self.get_focused_window() == Some(id) {
+            return self.set_window_state(id, WindowState::Minimized);
+        }
+        self.focus_window(id)
+    }
+
+    /// Panel clock text, once a time service is attached
+    pub fn panel_clock(&self) -> Option<String> {
+        let now = self.time.as_ref()?.local_time();
+        Some(format!(
+            "{} {}",
+            self.i18n.format_date(now.year, now.month, now.day),
+            self.i18n.format_time(now.hour, now.minute)
+        ))
+    }
+
+    /// One-line rendering of the panel: windows, tray icons and clock
+    pub fn render_panel(&self) -> String {
+        let mut parts: Vec<String> = self
+            .panel_entries()
+            .iter()
+            .map(|entry| {
+                let marker = if entry.focused {
+                    "*"
+                } else if entry.minimized {
+                    "_"
+                } else {
+                    ""
+                };
+                format!("[{}{}]", marker, entry.title)
+            })
+            .collect();
+        let icons: Vec<String> = self.tray.icons().into_iter().map(|(_, icon)| icon.icon).collect();
+        if !icons.is_empty() {
+            parts.push(format!("| {}", icons.join(" ")));
+        }
+        if let Some(clock) = self.panel_clock() {
+            parts.push(format!("| {}", clock));
+        }
+        parts.join(" ")
+    }
+
+    /// Announce window focus changes through the screen reader
+    pub fn attach_accessibility(&mut self, service: Arc<AccessibilityService>) {
+        self.accessibility = Some(service);
+    }
+
+    /// Save the layout to `path` on exit and restore it from there when the shell starts
+    pub fn attach_layout_store(&mut self, vfs: Arc<VirtualFileSystem>, path: PathBuf) {
+        self.layout_store = Some((vfs, path));
+    }
+
+    /// Relaunch `app_id` through `launcher` for each of its restored windows
+    pub fn register_launcher(&mut self, app_id: &str, launcher: Launcher) {
+        self.launchers.insert(app_id.to_string(), launcher);
+    }
+
+    /// Current workspaces and windows, ready to be saved
+    pub fn layout(&self) -> SessionLayout {
+        let workspaces: Vec<WorkspaceId> = self.workspaces.keys().copied().collect();
+        let index = |id: WorkspaceId| workspaces.iter().position(|w| *w == id).unwrap();
+        let windows = self
+            .stack
+            .iter()
+            .map(|id| {
+                let window = &self.windows[id];
+                // Fullscreen windows keep the geometry they return to
+                let frame = self
+                    .saved_geometry
+                    .get(id)
+                    .copied()
+                    .unwrap_or_else(|| Rect::new(window.x, window.y, window.width, window.height));
+                SavedWindow {
+                    title: window.title.clone(),
+                    app_id: window.app_id.clone(),
+                    x: frame.x,
+                    y: frame.y,
+                    width: frame.width,
+                    height: frame.height,
+                    state: window.state,
+                    workspace: index(window.workspace),
+                    always_on_top: window.always_on_top,
+                    focused: self.workspaces[&window.workspace].focused_window == Some(*id),
+                }
+            })
+            .collect();
+        SessionLayout {
+            workspaces: self.workspaces.values().map(|w| w.name.clone()).collect(),
+            active_workspace: index(self.active_workspace),
+            windows,
+        }
+    }
+
+    pub fn save_layout(&self, vfs: &VirtualFileSystem, path: &Path) -> Result<(), String> {
+        vfs.write_file(path, &self.layout().to_json()).map_err(String::from)
+    }
+
+    pub fn restore_layout(&mut self, vfs: &VirtualFileSystem, path: &Path) -> Result<RestoreReport, String> {
+        let layout = SessionLayout::from_json(&vfs.read_file(path)?)?;
+        Ok(self.apply_layout(&layout))
+    }
+
+    /// Relaunch a saved layout's windows on top of the current desktop,
+    /// reusing existing workspaces in order before creating new ones
+    pub fn apply_layout(&mut self, layout: &SessionLayout) -> RestoreReport {
+        let mut workspaces: Vec<WorkspaceId> = self.workspaces.keys().copied().collect();
+        workspaces.truncate(layout.workspaces.len());
+        for (i, name) in layout.workspaces.iter().enumerate() {
+            match workspaces.get(i) {
+                Some(id) => self.workspaces.get_mut(id).unwrap().name = name.clone(),
+                None => workspaces.push(self.create_workspace(name.clone())),
+            }
+        }
+
+        let mut report = RestoreReport::default();
+        let mut focused = Vec::new();
+        for saved in &layout.windows {
+            match self.relaunch(saved, workspaces[saved.workspace]) {
+                Ok(id) => {
+                    if saved.focused && saved.state != WindowState::Minimized {
+                        focused.push(id);
+                    }
+                    report.restored.push(id);
+                }
+                Err(e) => report.skipped.push((saved.title.clone(), e)),
+            }
+        }
+        for workspace in &workspaces {
+            self.refocus(*workspace);
+        }
+        for id in focused {
+            let workspace = self.windows[&id].workspace;
+            self.workspaces.get_mut(&workspace).unwrap().focused_window = Some(id);
+        }
+        // Cannot fail, every ID came from the shell's own workspaces
+        let _ = self.switch_workspace(workspaces[layout.active_workspace]);
+        report
+    }
+
+    /// Start a saved window's application and put the new window in place
+    fn relaunch(&mut self, saved: &SavedWindow, workspace: WorkspaceId) -> Result<WindowId, String> {
+        let id = match saved.app_id.as_deref() {
+            None => return Err("No application to relaunch".to_string()),
+            Some("terminal") => self.open_terminal()?,
+            Some(app) => {
+                let launcher = self.launchers.get(app).cloned().ok_or_else(|| format!("No launcher for {}", app))?;
+                let process_id = launcher(saved)?;
+                let id = self.create_window(saved.title.clone(), process_id)?;
+                self.set_window_app(id, app)?;
+                id
+            }
+        };
+        let window = self.windows.get_mut(&id).unwrap();
+        window.workspace = workspace;
+        window.always_on_top = saved.always_on_top;
+        self.move_window(id, saved.x, saved.y)?;
+        self.resize_window(id, saved.width, saved.height)?;
+        match self.output_of(&self.windows[&id]) {
+            Some(output) if saved.state == WindowState::Fullscreen => self.set_fullscreen(id, Some(output)),
+            _ => self.set_window_state(id, saved.state),
+        }?;
+        Ok(id)
+    }
+
+    /// Read window rules from `path`, and again whenever the file changes
+    pub fn attach_rules(&mut self, vfs: Arc<VirtualFileSystem>, path: PathBuf) -> Result<(), String> {
+        let changed = Arc::clone(&self.rules_changed);
+        let watched = path.clone();
+        vfs.watch(Arc::new(move |event: &VfsEvent| {
+            if event.path() == watched {
+                *changed.lock().unwrap() = true;
+            }
+        }));
+        self.rules_store = Some((vfs, path));
+        self.reload_rules()
+    }
+
+    /// Read the rules file again; a missing file means no rules, a broken
+    /// one keeps the rules already in force
+    pub fn reload_rules(&mut self) -> Result<(), String> {
+        let Some((vfs, path)) = self.rules_store.clone() else {
+            return Err("No rules file".to_string());
+        };
+        *self.rules_changed.lock().unwrap() = false;
+        let rules = if vfs.exists(&path) {
+            RuleSet::from_json(&vfs.read_file(&path)?)?
+        } else {
+            RuleSet::default()
+        };
+        self.set_rules(rules);
+        Ok(())
+    }
+
+    fn apply_rules_change(&mut self) -> Result<(), String> {
+        if !*self.rules_changed.lock().unwrap() {
+            return Ok(());
+        }
+        self.reload_rules()
+    }
+
+    pub fn rules(&self) -> &RuleSet {
+        &self.rules
+    }
+
+    /// Replace the window rules and apply them to every open window
+    pub fn set_rules(&mut self, rules: RuleSet) {
+        self.rules = rules;
+        let mut ids: Vec<WindowId> = self.windows.keys().copied().collect();
+        ids.sort_by_key(|id| id.0);
+        for id in ids {
+            // Cannot fail, the window is open
+            let _ = self.apply_rules(id);
+        }
+    }
+
+    /// Apply the actions of every rule matching a window. Removing a rule
+    /// lifts floating and fixed size but leaves the window where it is.
+    fn apply_rules(&mut self, id: WindowId) -> Result<(), String> {
+        let window = self.windows.get_mut(&id).ok_or("Window not found")?;
+        window.floating = false;
+        window.fixed_size = false;
+        for action in self.rules.actions_for(window) {
+            match action {
+                RuleAction::Maximized => self.set_window_state(id, WindowState::Maximized)?,
+                RuleAction::Workspace { number } => {
+                    while self.workspaces.len() < number {
+                        let name = format!("Workspace {}", self.workspaces.len() + 1);
+                        self.create_workspace(name);
+                    }
+                    let workspace = *self.workspaces.keys().nth(number - 1).unwrap();
+                    self.move_window_to_workspace(id, workspace)?;
+                }
+                RuleAction::Floating => self.windows.get_mut(&id).unwrap().floating = true,
+                RuleAction::Size { width, height } => {
+                    self.resize_window(id, width, height)?;
+                    self.windows.get_mut(&id).unwrap().fixed_size = true;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Draw windows through a compositor instead of only listing them
+    pub fn attach_compositor(&mut self, compositor: Arc<Compositor>) -> OutputId {
+        self.insert_output("default", compositor, 0, 0)
+    }
+
+    /// Connect a monitor with its top-left corner at `(x, y)` on the desktop
+    pub fn add_output(&mut self, name: &str, display: SharedDisplay, x: i32, y: i32) -> OutputId {
+        self.insert_output(name, Arc::new(Compositor::new(display)), x, y)
+    }
+
+    fn insert_output(&mut self, name: &str, compositor: Arc<Compositor>, x: i32, y: i32) -> OutputId {
+        let id = OutputId::new(self.next_output_id);
+        self.next_output_id += 1;
+        let (width, height) = compositor.resolution();
+        self.outputs.insert(
+            id,
+            Output {
+                id,
+                name: name.to_string(),
+                rect: Rect::new(x, y, width, height),
+                compositor,
+                fullscreen: None,
+            },
+        );
+        let ids: Vec<WindowId> = self.windows.keys().copied().collect();
+        for window in ids {
+            if !self.surfaces.contains_key(&window) {
+                self.create_surface(window);
+            }
+        }
+        id
+    }
+
+    /// Disconnect a monitor, carrying its windows to the first remaining one
+    pub fn remove_output(&mut self, id: OutputId) -> Result<(), String> {
+        if !self.outputs.contains_key(&id) {
+            return Err("Output not found".to_string());
+        }
+        let orphans: Vec<WindowId> = self
+            .windows
+            .values()
+            .filter(|w| self.output_of(w) == Some(id))
+            .map(|w| w.id)
+            .collect();
+        let removed = self.outputs.remove(&id).unwrap();
+        let Some(fallback) = self.outputs.keys().next().copied() else {
+            // Nothing left to draw on; surfaces come back with the next output
+            self.surfaces.clear();
+            self.surface_outputs.clear();
+            return Ok(());
+        };
+        for window in orphans {
+            self.carry_window(window, removed.rect, fallback)?;
+        }
+        Ok(())
+    }
+
+    pub fn outputs(&self) -> Vec<&Output> {
+        self.outputs.values().collect()
+    }
+
+    /// Area covered by all outputs together
+    pub fn desktop_bounds(&self) -> Rect {
+        let mut rects = self.outputs.values().map(|o| o.rect);
+        let first = rects
+            .next()
+            .unwrap_or(Rect::new(0, 0, DEFAULT_SCREEN_SIZE.0, DEFAULT_SCREEN_SIZE.1));
+        rects.fold(first, |acc, r| acc.union(&r))
+    }
+
+    /// Output showing a window: the one holding most of it
+    fn output_of(&self, window: &Window) -> Option<OutputId> {
+        let frame = Rect::new(window.x, window.y, window.width, window.height);
+        self.outputs
+            .values()
+            .max_by_key(|o| (overlap(&o.rect, &frame), std::cmp::Reverse(o.id)))
+            .map(|o| o.id)
+    }
+
+    /// Area of the output under a desktop point
+    fn output_area_at(&self, x: i32, y: i32) -> Rect {
+        self.outputs
+            .values()
+            .find(|o| o.rect.intersect(&Rect::new(x, y, 1, 1)).is_some())
+            .map(|o| o.rect)
+            .unwrap_or(Rect::new(0, 0, DEFAULT_SCREEN_SIZE.0, DEFAULT_SCREEN_SIZE.1))
+    }
+
+    /// Move a window onto another output at the same relative position
+    pub fn move_window_to_output(&mut self, id: WindowId, output: OutputId) -> Result<(), String> {
+        let window = self.windows.get(&id).ok_or("Window not found")?;
+        if !self.outputs.contains_key(&output) {
+            return Err("Output not found".to_string());
+        }
+        let from = self.output_of(window).map(|o| self.outputs[&o].rect).unwrap();
+        self.carry_window(id, from, output)
+    }
+
+    fn carry_window(&mut self, id: WindowId, from: Rect, to: OutputId) -> Result<(), String> {
+        let target = self.outputs[&to].rect;
+        let window = &self.windows[&id];
+        if window.state == WindowState::Fullscreen {
+            if let Some(saved) = self.saved_geometry.get_mut(&id) {
+                *saved = carry(*saved, from, target);
+            }
+            return self.set_fullscreen(id, Some(to));
+        }
+        let frame = carry(Rect::new(window.x, window.y, window.width, window.height), from, target);
+        self.move_window(id, frame.x, frame.y)?;
+        self.resize_window(id, frame.width, frame.height)
+    }
+
+    /// Cover an output with a window, or restore its old geometry with `None`
+    pub fn set_fullscreen(&mut self, id: WindowId, output: Option<OutputId>) -> Result<(), String> {
+        let window = self.windows.get(&id).ok_or("Window not found")?;
+        let frame = Rect::new(window.x, window.y, window.width, window.height);
+        let target = match output {
+            Some(output) => Some(self.outputs.get(&output).ok_or("Output not found")?),
+            None => None,
+        };
+        let previous = target.and_then(|o| o.fullscreen).filter(|w| *w != id);
+        for o in self.outputs.values_mut() {
+            if o.fullscreen == Some(id) {
+                o.fullscreen = None;
+            }
+        }
+        let Some(output) = output else {
+            if let Some(saved) = self.saved_geometry.remove(&id) {
+                self.set_window_state(id, WindowState::Normal)?;
+                self.move_window(id, saved.x, saved.y)?;
+                self.resize_window(id, saved.width, saved.height)?;
+            }
+            return Ok(());
+        };
+        // One fullscreen window per output
+        if let Some(previous) = previous {
+            self.set_fullscreen(previous, None)?;
+        }
+        self.saved_geometry.entry(id).or_insert(frame);
+        let output = self.outputs.get_mut(&output).unwrap();
+        output.fullscreen = Some(id);
+        let rect = output.rect;
+        self.set_window_state(id, WindowState::Fullscreen)?;
+        self.move_window(id, rect.x, rect.y)?;
+        self.resize_window(id, rect.width, rect.height)?;
+        self.focus_window(id)
+    }
+
+    fn create_surface(&mut self, id: WindowId) {
+        let Some(output) = self.output_of(&self.windows[&id]) else {
+            return;
+        };
+        let window = &self.windows[&id];
+        let surface = self.outputs[&output].compositor.create_surface(window.width, window.height);
+        paint_decorations(&surface, &self.theme.appearance());
+        self.surfaces.insert(id, surface);
+        self.surface_outputs.insert(id, output);
+    }
+
+    /// Surface an application draws its window contents into
+    pub fn window_surface(&self, id: WindowId) -> Option<Surface> {
+        self.surfaces.get(&id).cloned()
+    }
+
+    /// Hand the current stacking order to every output's compositor and let them run
+    pub fn present(&mut self, elapsed_ms: u64) -> Result<bool, String> {
+        self.close_exited_windows()?;
+        self.apply_rules_change()?;
+        self.apply_theme_change();
+        self.apply_lock_request();
+        if self.outputs.is_empty() {
+            return Ok(false);
+        }
+        // Surfaces follow their window to the output showing it
+        let ids: Vec<WindowId> = self.windows.keys().copied().collect();
+        for id in ids {
+            let target = self.output_of(&self.windows[&id]).unwrap();
+            match (self.surfaces.get(&id).cloned(), self.surface_outputs.get(&id).copied()) {
+                (Some(surface), Some(current)) if current != target => {
+                    if let Some(old) = self.outputs.get(&current) {
+                        old.compositor.destroy_surface(surface.id())?;
+                    }
+                    self.outputs[&target].compositor.adopt_surface(surface);
+                    self.surface_outputs.insert(id, target);
+                }
+                (None, _) => self.create_surface(id),
+                _ => {}
+            }
+        }
+        let mut presented = false;
+        for output in self.outputs.values() {
+            if self.locked {
+                output.compositor.set_scene(&[])?;
+                presented |= output.compositor.tick(elapsed_ms)?;
+                continue;
+            }
+            let layers: Vec<Layer> = self
+                .stacking_order()
+                .iter()
+                .filter(|w| self.surface_outputs.get(&w.id) == Some(&output.id))
+                .filter_map(|w| {
+                    let surface = self.surfaces.get(&w.id)?;
+                    Some(Layer::new(surface.id(), w.x - output.rect.x, w.y - output.rect.y))
+                })
+                .collect();
+            output.compositor.set_scene(&layers)?;
+            presented |= output.compositor.tick(elapsed_ms)?;
         }
+        self.record_frames(elapsed_ms);
+        Ok(presented)
+    }
+
+    /// Rendered notification list, one entry per active notification
+    pub fn render_notifications(&self) -> Vec<String> {
+        let Some(service) = &self.notifications else {
+            return Vec::new();
+        };
+        service
+            .active()
+            .iter()
+            .map(|n| {
+                let marker = match n.urgency {
+                    Urgency::Critical => "!",
+                    Urgency::Normal => "*",
+                    Urgency::Low => " ",
+                };
+                let mut line = format!("{}[{}] {}: {}", marker, n.id.value(), n.app_id, n.title);
+                if !n.body.is_empty() {
+                    line.push_str(&format!(" - {}", n.body));
+                }
+                if !n.actions.is_empty() {
+                    let actions: Vec<String> = n.actions.iter().map(|a| format!("[{}] {}", a.id, a.label)).collect();
+                    line.push_str(&format!("  {}", actions.join(" ")));
+                }
+                line
+            })
+            .collect()
     }
 
-    /// Create a new window
-    pub fn create_window(&mut self, title: String, process_id: u64) -> WindowId {
+    /// Create a new window for `process_id`, which must be running once a
+    /// kernel is attached; `SHELL_PROCESS` is the shell itself
+    pub fn create_window(&mut self, title: String, process_id: u64) -> Result<WindowId, String> {
+        if let Some(kernel) = self.kernel.as_ref().filter(|_| process_id != SHELL_PROCESS) {
+            let running = kernel
+                .get_process(ProcessId::new(process_id))
+                .is_some_and(|p| p.state != ProcessState::Terminated);
+            if !running {
+                return Err(format!("No running process {}", process_id));
+            }
+        }
         let window_id = WindowId(self.next_window_id);
         self.next_window_id += 1;
 
-        let window = Window::new(window_id, title, process_id);
+        let mut window = Window::new(window_id, title, process_id, self.active_workspace);
+        window.app_id = self.apps.get(&process_id).map(|app| app.app_id.clone());
         self.windows.insert(window_id, window);
-        self.focused_window = Some(window_id);
-        
-        window_id
+        self.stack.push(window_id);
+        self.active_mut().focused_window = Some(window_id);
+        self.create_surface(window_id);
+        // Cannot fail, the window was just created
+        let _ = self.apply_rules(window_id);
+
+        Ok(window_id)
+    }
+
+    /// Create a window asked for over `channel`, which must belong to the
+    /// process the window is for
+    pub fn request_window(&mut self, channel: ChannelId, title: String, process_id: u64) -> Result<WindowId, String> {
+        let caller = self.ipc.get_channel(channel).and_then(|c| c.owner());
+        if caller != Some(ProcessId::new(process_id)) {
+            return Err(format!("Permission denied: the caller does not own process {}", process_id));
+        }
+        self.create_window(title, process_id)
+    }
+
+    /// Start an application in the user's session and open its first
+    /// window; later windows of its process are tagged with it too
+    pub fn launch(&mut self, app_id: &str) -> Result<WindowId, String> {
+        if self.locked {
+            return Err("Screen is locked".to_string());
+        }
+        let (manager, token) = self.session.as_ref().ok_or("No session to launch applications in")?;
+        let shell = manager.session(token).ok_or("Unknown session")?.shell;
+        let process = manager.launch(token, app_id)?;
+        let app = LaunchedApp::open(&self.ipc, app_id, process, shell, manager.sandbox_for(app_id)?)?;
+        self.apps.insert(process.value(), app);
+        self.create_window(app_id.to_string(), process.value())
+    }
+
+    /// Application running as `process_id`, if the shell launched it
+    pub fn launched_app(&self, process_id: u64) -> Option<&LaunchedApp> {
+        self.apps.get(&process_id)
+    }
+
+    /// Open a window running a terminal session
+    pub fn open_terminal(&mut self) -> Result<WindowId, String> {
+        let window_id = self.create_window(self.i18n.tr(TERMINAL_TITLE), SHELL_PROCESS)?;
+        self.set_window_app(window_id, "terminal")?;
+        let window = &self.windows[&window_id];
+        match Terminal::new(&self.ipc, size_for_pixels(window.width, window.height)) {
+            Ok(mut terminal) => {
+                terminal.set_palette(*self.theme.appearance().palette());
+                self.terminals.insert(window_id, terminal);
+                Ok(window_id)
+            }
+            Err(e) => {
+                self.close_window(window_id)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Type into a terminal window; the window closes when its session ends
+    pub fn terminal_input(&mut self, id: WindowId, text: &str) -> Result<(), String> {
+        if self.locked {
+            return Err("Screen is locked".to_string());
+        }
+        let terminal = self.terminals.get_mut(&id).ok_or("Not a terminal window")?;
+        let result = terminal.type_text(text);
+        if terminal.has_exited() {
+            self.close_window(id)?;
+        }
+        result
+    }
+
+    /// Screen contents of a terminal window
+    pub fn render_terminal(&self, id: WindowId) -> Result<Vec<String>, String> {
+        self.terminals
+            .get(&id)
+            .map(Terminal::render)
+            .ok_or_else(|| "Not a terminal window".to_string())
     }
 
     /// Close a window
     pub fn close_window(&mut self, id: WindowId) -> Result<(), String> {
-        if self.windows.remove(&id).is_some() {
-            if self.focused_window == Some(id) {
-                self.focused_window = None;
+        if let Some(terminal) = self.terminals.remove(&id) {
+            terminal.close();
+        }
+        self.window_listeners.remove(&id);
+        if self.grab.is_some_and(|grab| grab.window == id) {
+            self.grab = None;
+        }
+        if let Some(surface) = self.surfaces.remove(&id) {
+            if let Some(output) = self.surface_outputs.remove(&id).and_then(|o| self.outputs.get(&o)) {
+                output.compositor.destroy_surface(surface.id())?;
+            }
+        }
+        self.saved_geometry.remove(&id);
+        for output in self.outputs.values_mut() {
+            if output.fullscreen == Some(id) {
+                output.fullscreen = None;
+            }
+        }
+        if let Some(window) = self.windows.remove(&id) {
+            self.stack.retain(|w| *w != id);
+            if self.workspaces[&window.workspace].focused_window == Some(id) {
+                self.refocus(window.workspace);
             }
             Ok(())
         } else {
@@ -99,49 +1179,414 @@ impl Shell {
 
     /// Set window state
     pub fn set_window_state(&mut self, id: WindowId, state: WindowState) -> Result<(), String> {
-        if let Some(window) = self.windows.get_mut(&id) {
-            window.state = state;
-            Ok(())
-        } else {
-            Err("Window not found".to_string())
+        let window = self.windows.get_mut(&id).ok_or("Window not found")?;
+        window.state = state;
+        let workspace = window.workspace;
+        if state == WindowState::Minimized && self.workspaces[&workspace].focused_window == Some(id) {
+            self.refocus(workspace);
+        }
+        Ok(())
+    }
+
+    /// Windows of the active workspace in painting order, bottom first
+    pub fn stacking_order(&self) -> Vec<&Window> {
+        self.stack_of(self.active_workspace)
+            .into_iter()
+            .map(|id| &self.windows[&id])
+            .collect()
+    }
+
+    /// Visible windows of a workspace, bottom first
+    fn stack_of(&self, workspace: WorkspaceId) -> Vec<WindowId> {
+        let mut stack: Vec<WindowId> = self
+            .stack
+            .iter()
+            .copied()
+            .filter(|id| {
+                let window = &self.windows[id];
+                window.workspace == workspace && window.state != WindowState::Minimized
+            })
+            .collect();
+        // Stable, so each layer keeps its own order
+        stack.sort_by_key(|id| self.windows[id].always_on_top);
+        stack
+    }
+
+    /// Give focus to the topmost visible window left on a workspace
+    fn refocus(&mut self, workspace: WorkspaceId) {
+        let top = self.stack_of(workspace).last().copied();
+        self.workspaces.get_mut(&workspace).unwrap().focused_window = top;
+    }
+
+    /// Bring a window to the top of its layer and focus it
+    pub fn raise_window(&mut self, id: WindowId) -> Result<(), String> {
+        self.focus_window(id)
+    }
+
+    /// Send a window to the bottom of its layer; focus passes to the new top
+    pub fn lower_window(&mut self, id: WindowId) -> Result<(), String> {
+        let workspace = self.windows.get(&id).ok_or("Window not found")?.workspace;
+        self.stack.retain(|w| *w != id);
+        self.stack.insert(0, id);
+        if self.workspaces[&workspace].focused_window == Some(id) {
+            self.refocus(workspace);
+        }
+        Ok(())
+    }
+
+    /// Keep a window above normal windows, or return it to their layer
+    pub fn set_always_on_top(&mut self, id: WindowId, on_top: bool) -> Result<(), String> {
+        let window = self.windows.get_mut(&id).ok_or("Window not found")?;
+        window.always_on_top = on_top;
+        Ok(())
+    }
+
+    /// Tag a window with the application that owns it
+    pub fn set_window_app(&mut self, id: WindowId, app_id: &str) -> Result<(), String> {
+        let window = self.windows.get_mut(&id).ok_or("Window not found")?;
+        window.app_id = Some(app_id.to_string());
+        self.apply_rules(id)
+    }
+
+    pub fn keybindings(&self) -> &KeyBindings {
+        &self.keybindings
+    }
+
+    pub fn keybindings_mut(&mut self) -> &mut KeyBindings {
+        &mut self.keybindings
+    }
+
+    /// Feed a HAL input event through the keybindings
+    pub fn handle_input(&mut self, event: &InputEvent) -> Result<KeyOutcome, String> {
+        self.close_exited_windows()?;
+        self.apply_lock_request();
+        if let Some(power) = &self.power {
+            power.record_activity();
+        }
+        if self.locked {
+            // Keep modifier state right for after the unlock, but deliver nothing
+            match event {
+                InputEvent::KeyPress(key) => self.modifiers.update(*key, true),
+                InputEvent::KeyRelease(key) => self.modifiers.update(*key, false),
+                _ => false,
+            };
+            return Ok(KeyOutcome::Ignored);
+        }
+        let key = match event {
+            InputEvent::KeyPress(key) => *key,
+            InputEvent::KeyRelease(key) => {
+                self.modifiers.update(*key, false);
+                return Ok(KeyOutcome::Ignored);
+            }
+            InputEvent::MouseMove { x, y } => return self.pointer_moved(*x, *y),
+            InputEvent::MouseButton { button, pressed } if *button == BUTTON_LEFT => {
+                return if *pressed { self.pointer_pressed() } else { self.pointer_released() };
+            }
+            _ => return Ok(KeyOutcome::Ignored),
+        };
+        if self.modifiers.update(key, true) {
+            return Ok(KeyOutcome::Ignored);
+        }
+        self.pending_keys.push(KeyCombo::new(self.modifiers, key));
+        let app = self
+            .get_focused_window()
+            .and_then(|id| self.windows[&id].app_id.clone());
+        match self.keybindings.lookup(app.as_deref(), &self.pending_keys) {
+            Lookup::Pending => Ok(KeyOutcome::Pending),
+            Lookup::Matched(Action::PassThrough) | Lookup::Unbound => {
+                Ok(KeyOutcome::Forwarded(std::mem::take(&mut self.pending_keys)))
+            }
+            Lookup::Matched(action) => {
+                self.pending_keys.clear();
+                self.run_action(action)?;
+                Ok(KeyOutcome::Handled)
+            }
+        }
+    }
+
+    /// Topmost window under the pointer on the active workspace
+    fn window_at(&self, x: i32, y: i32) -> Option<WindowId> {
+        self.stacking_order()
+            .iter()
+            .rev()
+            .find(|w| Rect::new(w.x, w.y, w.width, w.height).intersect(&Rect::new(x, y, 1, 1)).is_some())
+            .map(|w| w.id)
+    }
+
+    /// Click to focus; start a move or resize on the decorations
+    fn pointer_pressed(&mut self) -> Result<KeyOutcome, String> {
+        let (x, y) = self.pointer;
+        let Some(id) = self.window_at(x, y) else {
+            return Ok(KeyOutcome::Ignored);
+        };
+        self.focus_window(id)?;
+        let window = &self.windows[&id];
+        let frame = Rect::new(window.x, window.y, window.width, window.height);
+        let resizable = window.state == WindowState::Normal && !window.fixed_size;
+        match hit_test(frame, x, y, TITLE_BAR_HEIGHT) {
+            Some(GrabKind::Resize(_)) if !resizable => Ok(KeyOutcome::Ignored),
+            Some(kind) => {
+                self.grab = Some(Grab {
+                    window: id,
+                    kind,
+                    start: (x, y),
+                    origin: frame,
+                });
+                Ok(KeyOutcome::Handled)
+            }
+            None => Ok(KeyOutcome::Ignored),
+        }
+    }
+
+    fn pointer_moved(&mut self, x: i32, y: i32) -> Result<KeyOutcome, String> {
+        self.pointer = (x, y);
+        let Some(grab) = self.grab else {
+            return Ok(KeyOutcome::Ignored);
+        };
+        let (dx, dy) = (x - grab.start.0, y - grab.start.1);
+        match grab.kind {
+            GrabKind::Move => self.move_window(grab.window, grab.origin.x + dx, grab.origin.y + dy)?,
+            GrabKind::Resize(edges) => {
+                let frame = pointer::resize(grab.origin, edges, dx, dy);
+                self.move_window(grab.window, frame.x, frame.y)?;
+                self.resize_window(grab.window, frame.width, frame.height)?;
+            }
+        }
+        Ok(KeyOutcome::Handled)
+    }
+
+    /// End a grab, snapping a moved window dropped at a screen edge
+    fn pointer_released(&mut self) -> Result<KeyOutcome, String> {
+        let Some(grab) = self.grab.take() else {
+            return Ok(KeyOutcome::Ignored);
+        };
+        let window = &self.windows[&grab.window];
+        if grab.kind == GrabKind::Move && !window.floating && !window.fixed_size {
+            let area = self.output_area_at(self.pointer.0, self.pointer.1);
+            let size = (area.width, area.height);
+            if let Some(snap) = Snap::at(self.pointer.0 - area.x, self.pointer.1 - area.y, size) {
+                let frame = snap.geometry(size).offset(area.x, area.y);
+                self.set_window_state(grab.window, WindowState::Normal)?;
+                self.move_window(grab.window, frame.x, frame.y)?;
+                self.resize_window(grab.window, frame.width, frame.height)?;
+            }
+        }
+        Ok(KeyOutcome::Handled)
+    }
+
+    /// Tell the application owning `id` about its window's geometry changes
+    pub fn on_window_event(&mut self, id: WindowId, listener: WindowListener) -> Result<(), String> {
+        if !self.windows.contains_key(&id) {
+            return Err("Window not found".to_string());
+        }
+        self.window_listeners.entry(id).or_default().push(listener);
+        Ok(())
+    }
+
+    fn emit_window_event(&self, id: WindowId, event: WindowEvent) {
+        for listener in self.window_listeners.get(&id).into_iter().flatten() {
+            listener(id, &event);
+        }
+    }
+
+    fn run_action(&mut self, action: Action) -> Result<(), String> {
+        match action {
+            Action::SpawnTerminal => self.open_terminal().map(|_| ()),
+            Action::FocusNext => self.focus_next_window().map(|_| ()),
+            Action::FocusPrevious => self.focus_previous_window().map(|_| ()),
+            Action::CloseWindow => match self.get_focused_window() {
+                Some(id) => self.close_window(id),
+                None => Ok(()),
+            },
+            Action::SwitchWorkspace(id) => self.switch_workspace(WorkspaceId::new(id)),
+            Action::LockScreen => self.lock(),
+            Action::Command(line) => self.handle_command(&line).map(|_| ()),
+            Action::PassThrough => Ok(()),
         }
     }
 
     /// Move window
     pub fn move_window(&mut self, id: WindowId, x: i32, y: i32) -> Result<(), String> {
-        if let Some(window) = self.windows.get_mut(&id) {
+        let window = self.windows.get_mut(&id).ok_or("Window not found")?;
+        if (window.x, window.y) != (x, y) {
             window.x = x;
             window.y = y;
-            Ok(())
-        } else {
-            Err("Window not found".to_string())
+            self.emit_window_event(id, WindowEvent::Moved { x, y });
+        }
+        Ok(())
+    }
+
+    /// Resize window
+    pub fn resize_window(&mut self, id: WindowId, width: u32, height: u32) -> Result<(), String> {
+        let window = self.windows.get_mut(&id).ok_or("Window not found")?;
+        if (window.width, window.height) == (width, height) {
+            return Ok(());
+        }
+        window.width = width;
+        window.height = height;
+        if let Some(terminal) = self.terminals.get_mut(&id) {
+            terminal.resize(size_for_pixels(width, height));
+        }
+        if let Some(surface) = self.surfaces.get(&id) {
+            surface.resize(width, height);
+            paint_decorations(surface, &self.theme.appearance());
+        }
+        self.emit_window_event(id, WindowEvent::Resized { width, height });
+        Ok(())
+    }
+
+    /// Focus and raise a window, switching to its workspace
+    pub fn focus_window(&mut self, id: WindowId) -> Result<(), String> {
+        let workspace = self.windows.get(&id).ok_or("Window not found")?.workspace;
+        self.switch_workspace(workspace)?;
+        self.stack.retain(|w| *w != id);
+        self.stack.push(id);
+        self.active_mut().focused_window = Some(id);
+        let window = &self.windows[&id];
+        if let Some(service) = &self.accessibility {
+            service.announce(
+                &self.i18n.tr_args("{title}, window", &[("title", &window.title)]),
+                Priority::Assertive,
+            );
+        }
+        Ok(())
+    }
+
+    /// Move focus to the next window that is not minimized, wrapping around
+    pub fn focus_next_window(&mut self) -> Result<WindowId, String> {
+        self.cycle_focus(false)
+    }
+
+    /// Move focus to the previous window that is not minimized, wrapping around
+    pub fn focus_previous_window(&mut self) -> Result<WindowId, String> {
+        self.cycle_focus(true)
+    }
+
+    fn cycle_focus(&mut self, backwards: bool) -> Result<WindowId, String> {
+        let mut order: Vec<WindowId> = self
+            .windows
+            .values()
+            .filter(|w| w.workspace == self.active_workspace && w.state != WindowState::Minimized)
+            .map(|w| w.id)
+            .collect();
+        if order.is_empty() {
+            return Err("No windows to focus".to_string());
+        }
+        order.sort_by_key(|id| id.0);
+        if backwards {
+            order.reverse();
+        }
+        let next = match self.get_focused_window().and_then(|id| order.iter().position(|w| *w == id)) {
+            Some(i) => order[(i + 1) % order.len()],
+            None => order[0],
+        };
+        self.focus_window(next)?;
+        Ok(next)
+    }
+
+    /// Get the focused window of the active workspace
+    pub fn get_focused_window(&self) -> Option<WindowId> {
+        self.workspaces[&self.active_workspace].focused_window
+    }
+
+    fn active_mut(&mut self) -> &mut Workspace {
+        self.workspaces.get_mut(&self.active_workspace).unwrap()
+    }
+
+    fn emit(&self, event: WorkspaceEvent) {
+        for listener in &self.workspace_listeners {
+            listener(&event);
+        }
+    }
+
+    /// Run `listener` for every workspace change
+    pub fn on_workspace_event(&mut self, listener: WorkspaceListener) {
+        self.workspace_listeners.push(listener);
+    }
+
+    /// Add an empty workspace after the existing ones
+    pub fn create_workspace(&mut self, name: String) -> WorkspaceId {
+        let id = WorkspaceId::new(self.next_workspace_id);
+        self.next_workspace_id += 1;
+        self.workspaces.insert(id, Workspace::new(id, name));
+        self.emit(WorkspaceEvent::Created(id));
+        id
+    }
+
+    /// Workspaces in switcher order
+    pub fn workspaces(&self) -> Vec<&Workspace> {
+        self.workspaces.values().collect()
+    }
+
+    pub fn active_workspace(&self) -> WorkspaceId {
+        self.active_workspace
+    }
+
+    /// Show another workspace's windows
+    pub fn switch_workspace(&mut self, id: WorkspaceId) -> Result<(), String> {
+        if !self.workspaces.contains_key(&id) {
+            return Err("Workspace not found".to_string());
         }
+        if id != self.active_workspace {
+            let from = std::mem::replace(&mut self.active_workspace, id);
+            self.emit(WorkspaceEvent::Switched { from, to: id });
+        }
+        Ok(())
     }
 
-    /// Resize window
-    pub fn resize_window(&mut self, id: WindowId, width: u32, height: u32) -> Result<(), String> {
-        if let Some(window) = self.windows.get_mut(&id) {
-            window.width = width;
-            window.height = height;
-            Ok(())
-        } else {
-            Err("Window not found".to_string())
+    /// Remove a workspace, moving its windows to the one before it
+    pub fn destroy_workspace(&mut self, id: WorkspaceId) -> Result<(), String> {
+        if !self.workspaces.contains_key(&id) {
+            return Err("Workspace not found".to_string());
         }
+        if self.workspaces.len() == 1 {
+            return Err("Cannot destroy the last workspace".to_string());
+        }
+        let neighbour = self
+            .workspaces
+            .range(..id)
+            .next_back()
+            .or_else(|| self.workspaces.range(id..).nth(1))
+            .map(|(neighbour, _)| *neighbour)
+            .unwrap();
+        if self.active_workspace == id {
+            self.switch_workspace(neighbour)?;
+        }
+        let mut orphans: Vec<WindowId> = self.windows.values().filter(|w| w.workspace == id).map(|w| w.id).collect();
+        orphans.sort_by_key(|w| w.0);
+        for window in orphans {
+            self.move_window_to_workspace(window, neighbour)?;
+        }
+        self.workspaces.remove(&id);
+        self.emit(WorkspaceEvent::Destroyed(id));
+        Ok(())
     }
 
-    /// Focus a window
-    pub fn focus_window(&mut self, id: WindowId) -> Result<(), String> {
-        if self.windows.contains_key(&id) {
-            self.focused_window = Some(id);
-            Ok(())
-        } else {
-            Err("Window not found".to_string())
+    /// Send a window to another workspace, where it takes focus
+    pub fn move_window_to_workspace(&mut self, window: WindowId, to: WorkspaceId) -> Result<(), String> {
+        if !self.workspaces.contains_key(&to) {
+            return Err("Workspace not found".to_string());
+        }
+        let entry = self.windows.get_mut(&window).ok_or("Window not found")?;
+        let from = std::mem::replace(&mut entry.workspace, to);
+        if from == to {
+            return Ok(());
         }
+        self.stack.retain(|w| *w != window);
+        self.stack.push(window);
+        if self.workspaces[&from].focused_window == Some(window) {
+            self.refocus(from);
+        }
+        self.workspaces.get_mut(&to).unwrap().focused_window = Some(window);
+        self.emit(WorkspaceEvent::WindowMoved { window, from, to });
+        Ok(())
     }
 
-    /// Get focused window
-    pub fn get_focused_window(&self) -> Option<WindowId> {
-        self.focused_window
+    /// Windows on one workspace, ordered by ID
+    pub fn windows_on(&self, workspace: WorkspaceId) -> Vec<&Window> {
+        let mut windows: Vec<&Window> = self.windows.values().filter(|w| w.workspace == workspace).collect();
+        windows.sort_by_key(|w| w.id.0);
+        windows
     }
 
     /// List all windows
@@ -152,7 +1597,22 @@ impl Shell {
     /// Run the shell's main loop
     pub fn run(&mut self) {
         println!("hairr OS Desktop Shell v0.1.0");
-        println!("Type 'help' for available commands\n");
+        println!("{}\n", self.i18n.tr("Type 'help' for available commands"));
+        if let Some((vfs, path)) = self.layout_store.clone() {
+            if vfs.exists(&path) {
+                match self.restore_layout(&vfs, &path) {
+                    Ok(report) => {
+                        for (title, reason) in &report.skipped {
+                            println!(
+                                "{}",
+                                self.i18n.tr_args("Could not restore {title}: {reason}", &[("title", title), ("reason", reason)])
+                            );
+                        }
+                    }
+                    Err(e) => println!("{}", self.i18n.tr_args("Error: {error}", &[("error", &e)])),
+                }
+            }
+        }
 
         loop {
             print!("> ");
@@ -174,24 +1634,55 @@ impl Shell {
                         break;
                     }
                 }
-                Err(e) => println!("Error: {}", e),
+                Err(e) => println!("{}", self.i18n.tr_args("Error: {error}", &[("error", &e)])),
+            }
+            if let Err(e) = self.present(VSYNC_INTERVAL_MS) {
+                println!("{}", self.i18n.tr_args("Error: {error}", &[("error", &e)]));
             }
         }
     }
 
     fn handle_command(&mut self, input: &str) -> Result<bool, String> {
-        let parts: Vec<&str> = input.split_whitespace().collect();
-        if parts.is_empty() {
-            return Ok(false);
+        self.close_exited_windows()?;
+        self.apply_locale_change();
+        self.apply_lock_request();
+        if self.locked && !matches!(input.split_whitespace().next(), None | Some("help" | "lock" | "unlock")) {
+            return Err("Screen is locked".to_string());
         }
+        let parsed = match self.commands.parse(input) {
+            Ok(parsed) => parsed,
+            Err(CliError::Empty) => return Ok(false),
+            Err(CliError::Unknown(command)) => {
+                println!("{}", self.i18n.tr_args("Unknown command: {command}", &[("command", &command)]));
+                println!("{}", self.i18n.tr("Type 'help' for available commands"));
+                return Ok(false);
+            }
+            Err(e) => return Err(e.to_string()),
+        };
+        let arg = |name: &str| parsed.get(name).unwrap_or_default();
+        let window = |name: &str| parsed.parse::<u64>(name).map(WindowId);
 
-        match parts[0] {
+        match parsed.command() {
+            "lock" => {
+                self.lock()?;
+                Ok(false)
+            }
+            "unlock" => {
+                self.unlock(arg("password"))?;
+                Ok(false)
+            }
             "help" => {
                 self.show_help();
                 Ok(false)
             }
-            "exit" | "quit" => {
-                println!("Shutting down hairr OS...");
+            "exit" => {
+                if let Some((vfs, path)) = &self.layout_store {
+                    // Still exit; losing the layout is better than being stuck
+                    if let Err(e) = self.save_layout(vfs, path) {
+                        println!("{}", self.i18n.tr_args("Error: {error}", &[("error", &e)]));
+                    }
+                }
+                println!("{}", self.i18n.tr("Shutting down hairr OS..."));
                 Ok(true)
             }
             "version" => {
@@ -203,76 +1694,268 @@ impl Shell {
                 Ok(false)
             }
             "create" => {
-                if parts.len() < 2 {
-                    println!("Usage: create <window_title>");
-                } else {
-                    let title = parts[1..].join(" ");
-                    let window_id = self.create_window(title.clone(), 0);
-                    println!("Created window '{}' with ID {:?}", title, window_id);
-                }
+                let title = arg("title");
+                let window_id = self.create_window(title.to_string(), SHELL_PROCESS)?;
+                let id = window_id.0.to_string();
+                println!("{}", self.i18n.tr_args("Created window '{title}' with ID {id}", &[("title", title), ("id", &id)]));
                 Ok(false)
             }
             "close" => {
-                if parts.len() < 2 {
-                    println!("Usage: close <window_id>");
+                match self.close_window(window("window_id")?) {
+                    Ok(_) => println!("{}", self.i18n.tr("Window closed")),
+                    Err(e) => println!("{}", self.i18n.tr_args("Error: {error}", &[("error", &e)])),
+                }
+                Ok(false)
+            }
+            "focus" => {
+                match self.focus_window(window("window_id")?) {
+                    Ok(_) => println!("{}", self.i18n.tr("Window focused")),
+                    Err(e) => println!("{}", self.i18n.tr_args("Error: {error}", &[("error", &e)])),
+                }
+                Ok(false)
+            }
+            "next" | "prev" => {
+                let id = if parsed.command() == "next" {
+                    self.focus_next_window()?
                 } else {
-                    if let Ok(id) = parts[1].parse::<u64>() {
-                        match self.close_window(WindowId(id)) {
-                            Ok(_) => println!("Window closed"),
-                            Err(e) => println!("Error: {}", e),
+                    self.focus_previous_window()?
+                };
+                let title = self.windows[&id].title.clone();
+                println!("{}", self.i18n.tr_args("Focused '{title}'", &[("title", &title)]));
+                Ok(false)
+            }
+            "workspaces" => {
+                for workspace in self.workspaces() {
+                    let active = if workspace.id == self.active_workspace { "*" } else { " " };
+                    let count = self.windows_on(workspace.id).len() as u64;
+                    println!(
+                        "{}{:<9} {:<30} {}",
+                        active,
+                        workspace.id.value(),
+                        workspace.name,
+                        self.i18n.trn("{count} window", "{count} windows", count, &[])
+                    );
+                }
+                Ok(false)
+            }
+            "workspace" => {
+                self.switch_workspace(WorkspaceId::new(parsed.parse("id")?))?;
+                Ok(false)
+            }
+            "workspace new" => {
+                let name = match parsed.get("name") {
+                    Some(name) => name.to_string(),
+                    None => self.i18n.tr_args("Workspace {id}", &[("id", &self.next_workspace_id.to_string())]),
+                };
+                let id = self.create_workspace(name).value().to_string();
+                println!("{}", self.i18n.tr_args("Created workspace {id}", &[("id", &id)]));
+                Ok(false)
+            }
+            "workspace destroy" => {
+                self.destroy_workspace(WorkspaceId::new(parsed.parse("id")?))?;
+                Ok(false)
+            }
+            "send" => {
+                let workspace = WorkspaceId::new(parsed.parse("workspace_id")?);
+                self.move_window_to_workspace(window("window_id")?, workspace)?;
+                Ok(false)
+            }
+            "raise" | "lower" | "ontop" => {
+                let id = window("window_id")?;
+                match parsed.command() {
+                    "raise" => self.raise_window(id)?,
+                    "lower" => self.lower_window(id)?,
+                    _ => self.set_always_on_top(id, parsed.get("state") != Some("off"))?,
+                }
+                Ok(false)
+            }
+            "bind" => {
+                let chord = parse_chord(&arg("keys").replace(',', " "))?;
+                self.keybindings.bind(Scope::Global, chord, Action::Command(arg("command").to_string()))?;
+                Ok(false)
+            }
+            "unbind" => {
+                let chord = parse_chord(&arg("keys").replace(',', " "))?;
+                self.keybindings.unbind(&Scope::Global, &chord).ok_or("No such keybinding")?;
+                Ok(false)
+            }
+            "keys" => {
+                for binding in self.keybindings.bindings() {
+                    let scope = match &binding.scope {
+                        Scope::Global => String::new(),
+                        Scope::App(app) => format!(" [{}]", app),
+                    };
+                    println!("  {:<24} {:?}{}", format_chord(&binding.chord), binding.action, scope);
+                }
+                Ok(false)
+            }
+            "outputs" => {
+                for output in self.outputs() {
+                    let fullscreen = output.fullscreen.map(|w| format!(" [{}]", w.0)).unwrap_or_default();
+                    println!(
+                        "  {:<4} {:<16} {}x{} at ({}, {}){}",
+                        output.id.value(),
+                        output.name,
+                        output.rect.width,
+                        output.rect.height,
+                        output.rect.x,
+                        output.rect.y,
+                        fullscreen
+                    );
+                }
+                Ok(false)
+            }
+            "output" => {
+                self.move_window_to_output(window("window_id")?, OutputId::new(parsed.parse("output_id")?))?;
+                Ok(false)
+            }
+            "fullscreen" => {
+                let window = window("window_id")?;
+                match parsed.get("output") {
+                    Some("off") => self.set_fullscreen(window, None)?,
+                    Some(_) => self.set_fullscreen(window, Some(OutputId::new(parsed.parse("output")?)))?,
+                    None => {
+                        let current = self.windows.get(&window).ok_or("Window not found")?;
+                        let output = self.output_of(current).ok_or("No output connected")?;
+                        self.set_fullscreen(window, Some(output))?;
+                    }
+                }
+                Ok(false)
+            }
+            "panel" => {
+                println!("{}", self.render_panel());
+                Ok(false)
+            }
+            "activate" => {
+                self.panel_activate(window("window_id")?)?;
+                Ok(false)
+            }
+            "minimize" => {
+                self.set_window_state(window("window_id")?, WindowState::Minimized)?;
+                Ok(false)
+            }
+            "tray" => {
+                match (parsed.get("service"), parsed.get("icon")) {
+                    (Some(service), Some(icon)) => self.tray.activate(service, icon, parsed.get("item"))?,
+                    _ => {
+                        for (service, icon) in self.tray.icons() {
+                            let menu: Vec<&str> = icon.menu.iter().map(|m| m.id.as_str()).collect();
+                            println!("  {} {} ({}) {} [{}]", service, icon.id, icon.icon, icon.tooltip, menu.join(", "));
                         }
-                    } else {
-                        println!("Invalid window ID");
                     }
                 }
                 Ok(false)
             }
-            "focus" => {
-                if parts.len() < 2 {
-                    println!("Usage: focus <window_id>");
-                } else {
-                    if let Ok(id) = parts[1].parse::<u64>() {
-                        match self.focus_window(WindowId(id)) {
-                            Ok(_) => println!("Window focused"),
-                            Err(e) => println!("Error: {}", e),
+            "launch" => {
+                let app = arg("app");
+                let window_id = self.launch(app)?;
+                let id = window_id.0.to_string();
+                let pid = self.windows[&window_id].process_id.to_string();
+                println!("{}", self.i18n.tr_args("Launched {app} as process {pid} in window {id}", &[("app", app), ("pid", &pid), ("id", &id)]));
+                Ok(false)
+            }
+            "terminal" => {
+                let window_id = self.open_terminal()?;
+                let id = window_id.0.to_string();
+                println!("{}", self.i18n.tr_args("Opened terminal in window {id}", &[("id", &id)]));
+                Ok(false)
+            }
+            "type" | "view" => {
+                let id = window("window_id")?;
+                if parsed.command() == "type" {
+                    self.terminal_input(id, &format!("{}\n", arg("text")))?;
+                }
+                if self.windows.contains_key(&id) {
+                    for line in self.render_terminal(id)? {
+                        println!("{}", line);
+                    }
+                }
+                Ok(false)
+            }
+            "locale" => {
+                match parsed.get("name") {
+                    Some(name) => self.i18n.set_locale(Locale::parse(name)?),
+                    None => println!("{}", self.i18n.locale()),
+                }
+                Ok(false)
+            }
+            "theme" => {
+                match parsed.get("name") {
+                    Some(name) => self.theme.set_theme(name)?,
+                    None => {
+                        let appearance = self.theme.appearance();
+                        for name in self.theme.themes() {
+                            let marker = if name == appearance.theme.name { "*" } else { " " };
+                            println!("{}{}", marker, name);
+                        }
+                        if let Some(wallpaper) = &appearance.theme.wallpaper {
+                            println!("{}", self.i18n.tr_args("Wallpaper: {path}", &[("path", wallpaper)]));
                         }
-                    } else {
-                        println!("Invalid window ID");
                     }
                 }
                 Ok(false)
             }
-            _ => {
-                println!("Unknown command: {}", parts[0]);
-                println!("Type 'help' for available commands");
+            "rules" => {
+                if self.rules.rules.is_empty() {
+                    println!("{}", self.i18n.tr("No window rules"));
+                }
+                for rule in &self.rules.rules {
+                    println!("{}", serde_json::to_string(rule).unwrap());
+                }
+                Ok(false)
+            }
+            "rules reload" => {
+                self.reload_rules()?;
+                Ok(false)
+            }
+            "mode" => {
+                match parsed.get("mode") {
+                    Some(mode) => self.theme.set_mode(Mode::parse(mode)?),
+                    None => println!("{}", self.theme.mode()),
+                }
+                Ok(false)
+            }
+            "notifications" => {
+                let lines = self.render_notifications();
+                if lines.is_empty() {
+                    println!("{}", self.i18n.tr("No notifications"));
+                }
+                for line in lines {
+                    println!("{}", line);
+                }
+                Ok(false)
+            }
+            "dismiss" | "action" => {
+                let service = self.notifications.as_ref().ok_or("Notification service not attached")?;
+                let id = NotificationId::new(parsed.parse("id")?);
+                match parsed.get("action") {
+                    Some(action) => service.invoke_action(id, action)?,
+                    None => service.dismiss(id)?,
+                }
                 Ok(false)
             }
+            command => unreachable!("{} has no handler", command),
         }
     }
 
     fn show_help(&self) {
-        println!("Available commands:");
-        println!("  help                    - Show this help message");
-        println!("  version                 - Show version information");
-        println!("  windows                 - List all windows");
-        println!("  create <title>          - Create a new window");
-        println!("  close <window_id>       - Close a window");
-        println!("  focus <window_id>       - Focus a window");
-        println!("  exit/quit               - Exit the shell");
+        println!("{}", self.i18n.tr("Available commands:"));
+        print!("{}", self.commands.help(|about| self.i18n.tr(about)));
     }
 
     fn list_all_windows(&self) {
-        if self.windows.is_empty() {
-            println!("No windows open");
+        let windows = self.windows_on(self.active_workspace);
+        if windows.is_empty() {
+            println!("{}", self.i18n.tr("No windows open"));
             return;
         }
 
-        println!("\nOpen Windows:");
+        println!("\n{}", self.i18n.tr("Open Windows:"));
         println!("{:<10} {:<30} {:<12} {:<20}", "ID", "Title", "State", "Position/Size");
         println!("{:-<80}", "");
         
-        for window in self.windows.values() {
-            let focused = if Some(window.id) == self.focused_window { "*" } else { " " };
+        for window in windows {
+            let focused = if Some(window.id) == self.get_focused_window() { "*" } else { " " };
             println!(
                 "{}{:<9} {:<30} {:<12} {}x{} at ({}, {})",
                 focused,
@@ -289,6 +1972,75 @@ impl Shell {
     }
 }
 
+/// What the shell accepts at its prompt and in keybinding commands
+fn commands() -> Cli {
+    Cli::new()
+        .command(Command::new("help", "Show this help message"))
+        .command(Command::new("version", "Show version information"))
+        .command(Command::new("windows", "List all windows"))
+        .command(Command::new("create", "Create a new window").rest("title"))
+        .command(Command::new("close", "Close a window").arg("window_id"))
+        .command(Command::new("focus", "Focus a window").arg("window_id"))
+        .command(Command::new("next", "Focus the next window"))
+        .command(Command::new("prev", "Focus the previous window"))
+        .command(Command::new("raise", "Move a window to the top").arg("window_id"))
+        .command(Command::new("lower", "Move a window to the bottom").arg("window_id"))
+        .command(Command::new("ontop", "Keep a window above the others, or 'off' to stop").arg("window_id").optional("state"))
+        .command(Command::new("keys", "List keyboard shortcuts"))
+        .command(Command::new("bind", "Bind a keyboard shortcut, keys separated by commas, to a command").arg("keys").rest("command"))
+        .command(Command::new("unbind", "Remove a keyboard shortcut").arg("keys"))
+        .command(Command::new("outputs", "List connected displays"))
+        .command(Command::new("output", "Move a window to another display").arg("window_id").arg("output_id"))
+        .command(Command::new("fullscreen", "Make a window fullscreen, or 'off' to restore it").arg("window_id").optional("output"))
+        .command(Command::new("panel", "Show the panel"))
+        .command(Command::new("activate", "Restore or minimize a window from the panel").arg("window_id"))
+        .command(Command::new("minimize", "Minimize a window").arg("window_id"))
+        .command(
+            Command::new("tray", "List tray icons, or click one or its menu item")
+                .optional("service")
+                .optional("icon")
+                .optional("item"),
+        )
+        .command(Command::new("workspaces", "List workspaces"))
+        .command(
+            Command::new("workspace", "Switch to a workspace")
+                .arg("id")
+                .subcommand(Command::new("new", "Create a workspace").optional_rest("name"))
+                .subcommand(Command::new("destroy", "Destroy a workspace").arg("id")),
+        )
+        .command(Command::new("send", "Move a window to another workspace").arg("window_id").arg("workspace_id"))
+        .command(Command::new("launch", "Start an application").arg("app"))
+        .command(Command::new("terminal", "Open a terminal window"))
+        .command(Command::new("type", "Run a command in a terminal window").arg("window_id").optional_rest("text"))
+        .command(Command::new("view", "Show a terminal window").arg("window_id"))
+        .command(Command::new("locale", "Show or change the display language").optional("name"))
+        .command(Command::new("theme", "List themes or switch to one").optional_rest("name"))
+        .command(Command::new("mode", "Show or change the color mode").optional("mode"))
+        .command(Command::new("rules", "List window rules").subcommand(Command::new("reload", "Read the rules file again")))
+        .command(Command::new("notifications", "List notifications"))
+        .command(Command::new("dismiss", "Dismiss a notification").arg("id"))
+        .command(Command::new("action", "Invoke a notification action").arg("id").arg("action"))
+        .command(Command::new("lock", "Lock the screen"))
+        .command(Command::new("unlock", "Unlock the screen").arg("password"))
+        .command(Command::new("exit", "Exit the shell").alias("quit"))
+}
+
+/// Fill a window surface with its background, title bar and border
+fn paint_decorations(surface: &Surface, appearance: &Appearance) {
+    let palette = appearance.palette();
+    let (width, height) = surface.size();
+    surface.fill(Rect::new(0, 0, width, height), palette.window.rgba());
+    surface.fill(Rect::new(0, 0, width, TITLE_BAR_HEIGHT), palette.title_bar.rgba());
+    let border = appearance.theme.border_width.min(width / 2).min(height / 2);
+    if border > 0 {
+        let color = palette.border.rgba();
+        surface.fill(Rect::new(0, 0, width, border), color);
+        surface.fill(Rect::new(0, (height - border) as i32, width, border), color);
+        surface.fill(Rect::new(0, 0, border, height), color);
+        surface.fill(Rect::new((width - border) as i32, 0, border, height), color);
+    }
+}
+
 impl Default for Shell {
     fn default() -> Self {
         Self::new()
@@ -307,21 +2059,677 @@ mod tests {
     #[test]
     fn test_window_creation() {
         let mut shell = Shell::new();
-        let window_id = shell.create_window("Test Window".to_string(), 1);
+        let window_id = shell.create_window("Test Window".to_string(), 1).unwrap();
         assert!(shell.get_window(window_id).is_some());
     }
 
     #[test]
     fn test_window_focus() {
         let mut shell = Shell::new();
-        let window_id = shell.create_window("Test Window".to_string(), 1);
+        let window_id = shell.create_window("Test Window".to_string(), 1).unwrap();
         assert_eq!(shell.get_focused_window(), Some(window_id));
     }
 
+    #[test]
+    fn test_keyboard_window_cycling() {
+        let mut shell = Shell::new();
+        let service = Arc::new(AccessibilityService::new());
+        service
+            .set_settings(accessibility::AccessibilitySettings {
+                screen_reader: true,
+                ..Default::default()
+            })
+            .unwrap();
+        service.take_speech();
+        shell.attach_accessibility(Arc::clone(&service));
+
+        let mail = shell.create_window("Mail".to_string(), 1).unwrap();
+        let music = shell.create_window("Music".to_string(), 2).unwrap();
+        let files = shell.create_window("Files".to_string(), 3).unwrap();
+        shell.set_window_state(music, WindowState::Minimized).unwrap();
+
+        assert_eq!(shell.focus_next_window(), Ok(mail));
+        assert_eq!(shell.focus_next_window(), Ok(files));
+        assert_eq!(shell.focus_previous_window(), Ok(mail));
+        assert!(shell.handle_command("prev").is_ok());
+        assert_eq!(shell.get_focused_window(), Some(files));
+        let spoken: Vec<String> = service.take_speech().into_iter().map(|e| e.text).collect();
+        assert_eq!(spoken, vec!["Mail, window", "Files, window", "Mail, window", "Files, window"]);
+    }
+
+    #[test]
+    fn test_workspaces() {
+        let mut shell = Shell::new();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&events);
+        shell.on_workspace_event(Arc::new(move |event| log.lock().unwrap().push(event.clone())));
+        let first = shell.active_workspace();
+        let mail = shell.create_window("Mail".to_string(), 1).unwrap();
+
+        assert!(shell.handle_command("workspace new Code").is_ok());
+        let code = shell.workspaces()[1].id;
+        shell.switch_workspace(code).unwrap();
+        assert_eq!(shell.get_focused_window(), None);
+        let editor = shell.create_window("Editor".to_string(), 2).unwrap();
+        let build = shell.create_window("Build".to_string(), 3).unwrap();
+        assert_eq!(shell.windows_on(code).len(), 2);
+
+        // Each workspace keeps its own focus
+        shell.focus_window(editor).unwrap();
+        shell.switch_workspace(first).unwrap();
+        assert_eq!(shell.get_focused_window(), Some(mail));
+        assert_eq!(shell.focus_next_window(), Ok(mail));
+
+        // Focusing a window elsewhere switches to its workspace
+        shell.focus_window(build).unwrap();
+        assert_eq!(shell.active_workspace(), code);
+
+        assert!(shell.handle_command(&format!("send {} {}", build.0, first.value())).is_ok());
+        assert_eq!(shell.get_window(build).unwrap().workspace, first);
+        assert_eq!(shell.get_focused_window(), Some(editor));
+
+        assert!(shell.destroy_workspace(code).is_ok());
+        assert_eq!(shell.active_workspace(), first);
+        assert_eq!(shell.windows_on(first).len(), 3);
+        assert!(shell.destroy_workspace(first).is_err());
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                WorkspaceEvent::Created(code),
+                WorkspaceEvent::Switched { from: first, to: code },
+                WorkspaceEvent::Switched { from: code, to: first },
+                WorkspaceEvent::Switched { from: first, to: code },
+                WorkspaceEvent::WindowMoved { window: build, from: code, to: first },
+                WorkspaceEvent::Switched { from: code, to: first },
+                WorkspaceEvent::WindowMoved { window: editor, from: code, to: first },
+                WorkspaceEvent::Destroyed(code),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stacking_order() {
+        let mut shell = Shell::new();
+        let back = shell.create_window("Back".to_string(), 1).unwrap();
+        let middle = shell.create_window("Middle".to_string(), 2).unwrap();
+        let front = shell.create_window("Front".to_string(), 3).unwrap();
+        let order = |shell: &Shell| shell.stacking_order().iter().map(|w| w.id).collect::<Vec<_>>();
+        assert_eq!(order(&shell), vec![back, middle, front]);
+
+        shell.raise_window(back).unwrap();
+        assert_eq!(order(&shell), vec![middle, front, back]);
+        assert_eq!(shell.get_focused_window(), Some(back));
+
+        // Lowering the focused window hands focus to the new top
+        shell.lower_window(back).unwrap();
+        assert_eq!(order(&shell), vec![back, middle, front]);
+        assert_eq!(shell.get_focused_window(), Some(front));
+
+        assert!(shell.handle_command(&format!("ontop {}", middle.0)).is_ok());
+        shell.raise_window(back).unwrap();
+        assert_eq!(order(&shell), vec![front, back, middle]);
+
+        shell.set_window_state(back, WindowState::Minimized).unwrap();
+        assert_eq!(order(&shell), vec![front, middle]);
+        assert_eq!(shell.get_focused_window(), Some(middle));
+        shell.close_window(middle).unwrap();
+        assert_eq!(shell.get_focused_window(), Some(front));
+    }
+
+    #[test]
+    fn test_compositor_follows_windows() {
+        use reference_driver::display::ReferenceDisplay;
+
+        let mut display = ReferenceDisplay::new(320, 240);
+        display.init().unwrap();
+        let compositor = Arc::new(Compositor::new(Arc::new(Mutex::new(display))));
+        let palette = *theme::Theme::default().palette(Mode::Light);
+        let mut shell = Shell::new();
+        let early = shell.create_window("Early".to_string(), 1).unwrap();
+        shell.attach_compositor(Arc::clone(&compositor));
+        let late = shell.create_window("Late".to_string(), 2).unwrap();
+        for (id, x) in [(early, 0), (late, 100)] {
+            shell.resize_window(id, 150, 100).unwrap();
+            shell.move_window(id, x, 50).unwrap();
+        }
+        assert!(shell.present(VSYNC_INTERVAL_MS).unwrap());
+        assert_eq!(compositor.pixel(10, 60), Some(palette.title_bar.rgba()));
+        assert_eq!(compositor.pixel(120, 100), Some(palette.window.rgba()));
+        assert_eq!(compositor.pixel(10, 10), Some(compositor::DEFAULT_BACKGROUND));
+
+        // An application draws into its window
+        let surface = shell.window_surface(early).unwrap();
+        surface.fill(Rect::new(110, 40, 10, 10), [0, 128, 0, 255]);
+        assert!(shell.present(VSYNC_INTERVAL_MS).unwrap());
+        // Covered by the later window on top
+        assert_eq!(compositor.pixel(112, 92), Some(palette.window.rgba()));
+        shell.raise_window(early).unwrap();
+        assert!(shell.present(VSYNC_INTERVAL_MS).unwrap());
+        assert_eq!(compositor.pixel(112, 92), Some([0, 128, 0, 255]));
+
+        shell.set_window_state(early, WindowState::Minimized).unwrap();
+        shell.close_window(late).unwrap();
+        assert!(shell.present(VSYNC_INTERVAL_MS).unwrap());
+        assert_eq!(compositor.pixel(112, 92), Some(compositor::DEFAULT_BACKGROUND));
+        assert!(!shell.present(VSYNC_INTERVAL_MS).unwrap());
+    }
+
+    #[test]
+    fn test_theme_changes_repaint() {
+        use reference_driver::display::ReferenceDisplay;
+        use theme::Theme;
+
+        let mut display = ReferenceDisplay::new(320, 240);
+        display.init().unwrap();
+        let compositor = Arc::new(Compositor::new(Arc::new(Mutex::new(display))));
+        let themes = Arc::new(ThemeManager::new());
+        themes.add_theme(Theme {
+            name: "Wide".to_string(),
+            border_width: 3,
+            ..Theme::default()
+        });
+        let mut shell = Shell::new();
+        shell.attach_theme(Arc::clone(&themes));
+        shell.attach_compositor(Arc::clone(&compositor));
+        let window = shell.create_window("Notes".to_string(), 1).unwrap();
+        shell.resize_window(window, 150, 100).unwrap();
+        shell.move_window(window, 20, 20).unwrap();
+        let terminal = shell.open_terminal().unwrap();
+        shell.set_window_state(terminal, WindowState::Minimized).unwrap();
+
+        assert!(shell.handle_command("theme Wide").is_ok());
+        assert!(shell.handle_command("mode dark").is_ok());
+        assert!(shell.handle_command("mode dim").is_err());
+        assert!(shell.handle_command("theme Missing").is_err());
+        shell.present(VSYNC_INTERVAL_MS).unwrap();
+        let dark = *Theme::default().palette(Mode::Dark);
+        assert_eq!(compositor.pixel(5, 5), Some(dark.desktop.rgba()));
+        assert_eq!(compositor.pixel(22, 80), Some(dark.border.rgba()));
+        assert_eq!(compositor.pixel(60, 30), Some(dark.title_bar.rgba()));
+        assert_eq!(compositor.pixel(60, 80), Some(dark.window.rgba()));
+        assert_eq!(shell.terminals[&terminal].palette(), &dark);
+    }
+
+    #[test]
+    fn test_lock_screen() {
+        use capability::CapabilityManager;
+        use kernel::Kernel;
+        use keystore::Keystore;
+        use reference_driver::display::ReferenceDisplay;
+        use users::UserService;
+
+        let capabilities = Arc::new(CapabilityManager::new());
+        let users = Arc::new(UserService::new(Arc::new(Keystore::new()), Arc::clone(&capabilities)).unwrap());
+        users.create_user("alice", "hunter2").unwrap();
+        let sessions = Arc::new(SessionManager::new(users, Arc::new(Kernel::new()), capabilities));
+        let login = sessions.login("alice", "hunter2").unwrap();
+        let power = Arc::new(PowerManager::new());
+        power.set_lock_timeout(Some(5_000));
+
+        let mut display = ReferenceDisplay::new(320, 240);
+        display.init().unwrap();
+        let compositor = Arc::new(Compositor::new(Arc::new(Mutex::new(display))));
+        let mut shell = Shell::new();
+        assert!(shell.lock().is_err());
+        shell.attach_session(Arc::clone(&sessions), login.token.clone());
+        shell.attach_power(Arc::clone(&power));
+        shell.attach_compositor(Arc::clone(&compositor));
+        let window = shell.create_window("Mail".to_string(), 1).unwrap();
+        shell.move_window(window, 0, 0).unwrap();
+        shell.present(VSYNC_INTERVAL_MS).unwrap();
+        assert_ne!(compositor.pixel(50, 50), Some(compositor::DEFAULT_BACKGROUND));
+
+        // Typing keeps the screen unlocked, walking away does not
+        power.tick(4_000).unwrap();
+        shell.handle_input(&InputEvent::KeyPress('A' as u32)).unwrap();
+        power.tick(4_000).unwrap();
+        assert!(!shell.is_locked());
+        power.tick(1_000).unwrap();
+        shell.present(VSYNC_INTERVAL_MS).unwrap();
+        assert!(shell.is_locked());
+        assert_eq!(sessions.session(&login.token).unwrap().state, session::SessionState::Locked);
+        assert_eq!(compositor.pixel(50, 50), Some(compositor::DEFAULT_BACKGROUND));
+        assert!(shell.panel_entries().is_empty());
+        assert_eq!(shell.handle_input(&InputEvent::KeyPress('Q' as u32)), Ok(KeyOutcome::Ignored));
+        assert!(shell.handle_command("close 1").is_err());
+
+        assert!(shell.handle_command("unlock guess").is_err());
+        assert!(shell.handle_command("unlock hunter2").is_ok());
+        shell.present(VSYNC_INTERVAL_MS).unwrap();
+        assert_ne!(compositor.pixel(50, 50), Some(compositor::DEFAULT_BACKGROUND));
+
+        // Super+L locks straight away
+        shell.handle_input(&InputEvent::KeyPress(keybindings::KEY_SUPER)).unwrap();
+        shell.handle_input(&InputEvent::KeyPress('L' as u32)).unwrap();
+        assert!(shell.is_locked());
+    }
+
+    #[test]
+    fn test_launch_app() {
+        use kernel::{Kernel, SandboxProfile};
+        use keystore::Keystore;
+        use users::UserService;
+
+        let capabilities = Arc::new(CapabilityManager::new());
+        let kernel = Arc::new(Kernel::new());
+        let ipc = Arc::new(IPCManager::new());
+        ipc.attach_kernel(&kernel);
+        let users = Arc::new(UserService::new(Arc::new(Keystore::new()), Arc::clone(&capabilities)).unwrap());
+        users.create_user("alice", "hunter2").unwrap();
+        let sessions = Arc::new(SessionManager::new(users, Arc::clone(&kernel), Arc::clone(&capabilities)));
+        sessions.set_app_sandbox(Arc::new(|app| {
+            Ok((app == "notes").then(|| SandboxProfile {
+                services: vec!["clipboard".to_string()],
+                ..SandboxProfile::new("/apps/notes".to_string())
+            }))
+        }));
+        let login = sessions.login("alice", "hunter2").unwrap();
+
+        let mut shell = Shell::new();
+        assert!(shell.launch("notes").is_err());
+        shell.attach_ipc(Arc::clone(&ipc));
+        shell.attach_session(Arc::clone(&sessions), login.token.clone());
+
+        let window = shell.launch("notes").unwrap();
+        let pid = shell.get_window(window).unwrap().process_id;
+        let app = shell.launched_app(pid).unwrap().clone();
+        assert_eq!(app.process.value(), pid);
+        assert_eq!(shell.get_window(window).unwrap().app_id.as_deref(), Some("notes"));
+        let process = kernel.get_process(app.process).unwrap();
+        assert_eq!(process.sandbox.unwrap().root, "/apps/notes");
+        let root = capabilities.validate(process.capabilities[0]).unwrap();
+        assert_eq!(root.resource, Resource::File("/apps/notes".to_string()));
+        assert_eq!(ipc.get_channel(app.display).unwrap().peer(), Some(login.shell));
+        assert_eq!(app.services.keys().collect::<Vec<_>>(), vec!["clipboard"]);
+        assert_eq!(ipc.channels_of(app.process).len(), 2);
+
+        // Further windows of the process belong to the app
+        let second = shell.create_window("Note 2".to_string(), pid).unwrap();
+        assert_eq!(shell.get_window(second).unwrap().app_id.as_deref(), Some("notes"));
+        assert!(shell.handle_command("launch editor").is_ok());
+        let editor = shell.get_focused_window().unwrap();
+        let editor_pid = shell.get_window(editor).unwrap().process_id;
+        assert!(shell.launched_app(editor_pid).unwrap().services.is_empty());
+
+        kernel.terminate_process(app.process).unwrap();
+        assert!(ipc.get_channel(app.display).is_none());
+        shell.lock().unwrap();
+        assert!(shell.launch("notes").is_err());
+    }
+
+    #[test]
+    fn test_windows_follow_their_process() {
+        use ipc::OrphanPolicy;
+        use kernel::{Kernel, Priority};
+
+        let kernel = Arc::new(Kernel::new());
+        let ipc = Arc::new(IPCManager::new());
+        ipc.attach_kernel(&kernel);
+        let mut shell = Shell::new();
+        shell.attach_ipc(Arc::clone(&ipc));
+        shell.attach_kernel(Arc::clone(&kernel));
+
+        let editor = kernel.create_process("editor".to_string(), Priority::Normal);
+        let player = kernel.create_process("player".to_string(), Priority::Normal);
+        assert!(shell.create_window("Ghost".to_string(), 42).is_err());
+        let main = shell.create_window("Editor".to_string(), editor.value()).unwrap();
+        let terminal = shell.open_terminal().unwrap();
+
+        // A process can only ask for windows of its own
+        let editor_channel = ipc.create_owned_channel(editor, OrphanPolicy::Close);
+        let player_channel = ipc.create_owned_channel(player, OrphanPolicy::Close);
+        let dialog = shell.request_window(editor_channel, "Save".to_string(), editor.value()).unwrap();
+        assert!(shell.request_window(player_channel, "Spoof".to_string(), editor.value()).is_err());
+        let video = shell.request_window(player_channel, "Video".to_string(), player.value()).unwrap();
+
+        kernel.terminate_process(editor).unwrap();
+        assert!(shell.create_window("Late".to_string(), editor.value()).is_err());
+        assert!(shell.handle_command("windows").is_ok());
+        assert!(shell.get_window(main).is_none() && shell.get_window(dialog).is_none());
+        assert!(shell.get_window(terminal).is_some() && shell.get_window(video).is_some());
+        assert_eq!(shell.get_focused_window(), Some(video));
+    }
+
+    #[test]
+    fn test_screen_capture() {
+        use capture::CaptureTarget;
+        use reference_driver::display::ReferenceDisplay;
+
+        let display = |width, height| {
+            let mut display = ReferenceDisplay::new(width, height);
+            display.init().unwrap();
+            Arc::new(Mutex::new(display)) as SharedDisplay
+        };
+        let capabilities = Arc::new(CapabilityManager::new());
+        let allowed = capabilities.grant(Resource::Device(CAPTURE_RESOURCE.to_string()), Permission::Read);
+        let other = capabilities.grant(Resource::Device("camera".to_string()), Permission::Full);
+        let mut shell = Shell::new();
+        shell.attach_capabilities(Arc::clone(&capabilities));
+        shell.add_output("left", display(64, 48), 0, 0);
+        let right = shell.add_output("right", display(32, 32), 64, 0);
+        let window = shell.create_window("Paint".to_string(), 1).unwrap();
+        shell.resize_window(window, 40, 30).unwrap();
+        shell.move_window(window, 70, 0).unwrap();
+        shell.window_surface(window).unwrap().fill(Rect::new(0, 26, 40, 4), [0, 200, 0, 255]);
+        assert!(shell.capture_screen(other).is_err());
+        let recording = shell.start_recording(allowed, CaptureTarget::Output(right), 32).unwrap();
+        assert!(shell.start_recording(allowed, CaptureTarget::Desktop, 1).is_err());
+        let follow = shell.start_recording(allowed, CaptureTarget::Window(window), VSYNC_INTERVAL_MS).unwrap();
+        shell.present(VSYNC_INTERVAL_MS).unwrap();
+
+        let screen = shell.capture_screen(allowed).unwrap();
+        assert_eq!((screen.width, screen.height), (96, 48));
+        assert_eq!(screen.pixel(80, 28), Some([0, 200, 0, 255]));
+        assert_eq!(screen.pixel(80, 40), Some([0, 0, 0, 0]));
+        let contents = shell.capture_window(allowed, window).unwrap();
+        assert_eq!((contents.width, contents.height), (40, 30));
+        assert_eq!(contents.pixel(0, 29), Some([0, 200, 0, 255]));
+
+        for _ in 0..4 {
+            shell.present(VSYNC_INTERVAL_MS).unwrap();
+        }
+        assert!(shell.take_frames(other, recording).is_err());
+        let frames = shell.take_frames(allowed, recording).unwrap();
+        assert_eq!(frames.iter().map(|f| f.time_ms).collect::<Vec<_>>(), vec![16, 48, 80]);
+        assert_eq!(frames[0].image.pixel(16, 28), Some([0, 200, 0, 255]));
+
+        // Closing the window ends its recording once the last frames are taken
+        shell.close_window(window).unwrap();
+        shell.present(VSYNC_INTERVAL_MS).unwrap();
+        assert_eq!(shell.take_frames(allowed, follow).unwrap().len(), 5);
+        assert!(shell.take_frames(allowed, follow).is_err());
+        assert!(shell.stop_recording(other, recording).is_err());
+        shell.stop_recording(allowed, recording).unwrap();
+        assert!(shell.take_frames(allowed, recording).is_err());
+
+        // Revoking the capability stops a running recording
+        let revoked = capabilities.grant(Resource::Device(CAPTURE_RESOURCE.to_string()), Permission::Read);
+        let recording = shell.start_recording(revoked, CaptureTarget::Desktop, 32).unwrap();
+        shell.present(VSYNC_INTERVAL_MS).unwrap();
+        capabilities.revoke(revoked);
+        shell.present(VSYNC_INTERVAL_MS * 2).unwrap();
+        assert!(shell.recordings[&recording].ended);
+        assert!(shell.take_frames(revoked, recording).unwrap_err().starts_with("Permission denied"));
+        assert!(shell.recordings.is_empty());
+    }
+
+    #[test]
+    fn test_keybindings_from_input_events() {
+        use keybindings::{KEY_ALT, KEY_CTRL, KEY_ENTER, KEY_SUPER, KEY_TAB};
+
+        let mut shell = Shell::new();
+        let press = |shell: &mut Shell, keys: &[u32]| {
+            let outcomes: Vec<KeyOutcome> = keys
+                .iter()
+                .map(|key| shell.handle_input(&InputEvent::KeyPress(*key)).unwrap())
+                .collect();
+            for key in keys.iter().rev() {
+                shell.handle_input(&InputEvent::KeyRelease(*key)).unwrap();
+            }
+            outcomes.last().cloned().unwrap()
+        };
+
+        assert_eq!(press(&mut shell, &[KEY_SUPER, KEY_ENTER]), KeyOutcome::Handled);
+        let terminal = shell.get_focused_window().unwrap();
+        assert_eq!(shell.get_window(terminal).unwrap().app_id.as_deref(), Some("terminal"));
+        let editor = shell.create_window("Editor".to_string(), 7).unwrap();
+        assert_eq!(press(&mut shell, &[KEY_ALT, KEY_TAB]), KeyOutcome::Handled);
+        assert_eq!(shell.get_focused_window(), Some(terminal));
+
+        // Chords bound from the command line
+        assert!(shell.handle_command("bind Ctrl+K,Ctrl+W close 2").is_ok());
+        assert_eq!(press(&mut shell, &[KEY_CTRL, 'K' as u32]), KeyOutcome::Pending);
+        assert_eq!(press(&mut shell, &[KEY_CTRL, 'W' as u32]), KeyOutcome::Handled);
+        assert!(shell.get_window(editor).is_none());
+        assert!(shell.handle_command("bind Ctrl+K close 1").is_err());
+
+        // The terminal keeps Alt+Tab for itself
+        shell
+            .keybindings_mut()
+            .bind(Scope::App("terminal".to_string()), parse_chord("Alt+Tab").unwrap(), Action::PassThrough)
+            .unwrap();
+        let alt_tab = KeyCombo::parse("Alt+Tab").unwrap();
+        assert_eq!(press(&mut shell, &[KEY_ALT, KEY_TAB]), KeyOutcome::Forwarded(vec![alt_tab]));
+        assert_eq!(
+            press(&mut shell, &['X' as u32]),
+            KeyOutcome::Forwarded(vec![KeyCombo::new(Modifiers::default(), 'X' as u32)])
+        );
+    }
+
+    #[test]
+    fn test_pointer_drag_resize_and_snap() {
+        let mut shell = Shell::new();
+        let window = shell.create_window("Editor".to_string(), 4).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&events);
+        shell
+            .on_window_event(window, Arc::new(move |_, event| log.lock().unwrap().push(*event)))
+            .unwrap();
+        let pointer = |shell: &mut Shell, events: &[InputEvent]| {
+            events.iter().map(|e| shell.handle_input(e).unwrap()).last().unwrap()
+        };
+        let click = |pressed| InputEvent::MouseButton { button: BUTTON_LEFT, pressed };
+
+        // Drag by the title bar
+        pointer(&mut shell, &[InputEvent::MouseMove { x: 300, y: 110 }, click(true)]);
+        pointer(&mut shell, &[InputEvent::MouseMove { x: 350, y: 160 }, click(false)]);
+        let moved = shell.get_window(window).unwrap();
+        assert_eq!((moved.x, moved.y), (150, 150));
+
+        // Pull the bottom-right corner inwards past the minimum size
+        pointer(&mut shell, &[InputEvent::MouseMove { x: 949, y: 749 }, click(true)]);
+        pointer(&mut shell, &[InputEvent::MouseMove { x: 100, y: 100 }, click(false)]);
+        let resized = shell.get_window(window).unwrap();
+        assert_eq!((resized.width, resized.height), (pointer::MIN_WINDOW_WIDTH, pointer::MIN_WINDOW_HEIGHT));
+
+        // Drop at the left edge to fill the left half of the screen
+        pointer(&mut shell, &[InputEvent::MouseMove { x: 200, y: 160 }, click(true)]);
+        pointer(&mut shell, &[InputEvent::MouseMove { x: 2, y: 500 }, click(false)]);
+        let snapped = shell.get_window(window).unwrap();
+        assert_eq!((snapped.x, snapped.y, snapped.width, snapped.height), (0, 0, 960, 1080));
+
+        // Clicks on the window contents are left to the application
+        assert_eq!(pointer(&mut shell, &[InputEvent::MouseMove { x: 400, y: 400 }, click(true)]), KeyOutcome::Ignored);
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                WindowEvent::Moved { x: 150, y: 150 },
+                WindowEvent::Resized { width: 120, height: 80 },
+                WindowEvent::Moved { x: -48, y: 490 },
+                WindowEvent::Moved { x: 0, y: 0 },
+                WindowEvent::Resized { width: 960, height: 1080 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_multiple_outputs() {
+        use reference_driver::display::ReferenceDisplay;
+
+        let display = |width, height| {
+            let mut display = ReferenceDisplay::new(width, height);
+            display.init().unwrap();
+            Arc::new(Mutex::new(display)) as SharedDisplay
+        };
+        let palette = *theme::Theme::default().palette(Mode::Light);
+        let mut shell = Shell::new();
+        let left = shell.add_output("left", display(320, 240), 0, 0);
+        let right = shell.add_output("right", display(200, 150), 320, 0);
+        assert_eq!(shell.desktop_bounds(), Rect::new(0, 0, 520, 240));
+        let compositor = |shell: &Shell, id| Arc::clone(&shell.outputs.get(&id).unwrap().compositor);
+
+        let window = shell.create_window("Player".to_string(), 5).unwrap();
+        shell.resize_window(window, 100, 80).unwrap();
+        shell.move_window(window, 10, 60).unwrap();
+        shell.present(VSYNC_INTERVAL_MS).unwrap();
+        assert_eq!(compositor(&shell, left).pixel(20, 65), Some(palette.title_bar.rgba()));
+
+        assert!(shell.handle_command(&format!("output {} {}", window.0, right.value())).is_ok());
+        let moved = shell.get_window(window).unwrap();
+        assert_eq!((moved.x, moved.y), (330, 60));
+        shell.present(VSYNC_INTERVAL_MS).unwrap();
+        assert_eq!(compositor(&shell, right).pixel(20, 65), Some(palette.title_bar.rgba()));
+        assert_eq!(compositor(&shell, left).pixel(20, 65), Some(compositor::DEFAULT_BACKGROUND));
+
+        shell.set_fullscreen(window, Some(right)).unwrap();
+        let full = shell.get_window(window).unwrap();
+        assert_eq!((full.x, full.y, full.width, full.height), (320, 0, 200, 150));
+
+        // Unplugging the monitor carries the fullscreen window over
+        shell.remove_output(right).unwrap();
+        let full = shell.get_window(window).unwrap();
+        assert_eq!((full.x, full.y, full.width, full.height), (0, 0, 320, 240));
+        assert_eq!(shell.outputs()[0].fullscreen, Some(window));
+        shell.present(VSYNC_INTERVAL_MS).unwrap();
+        assert_eq!(compositor(&shell, left).pixel(300, 200), Some(palette.window.rgba()));
+
+        assert!(shell.handle_command(&format!("fullscreen {} off", window.0)).is_ok());
+        let restored = shell.get_window(window).unwrap();
+        assert_eq!((restored.x, restored.y, restored.width, restored.height, restored.state), (10, 60, 100, 80, WindowState::Normal));
+    }
+
+    #[test]
+    fn test_panel() {
+        use capability::CapabilityManager;
+        use panel::{TrayIcon, TrayMenuItem};
+        use time::SystemClock;
+
+        let mut shell = Shell::new();
+        // 2024-03-09 13:30 UTC
+        let clock = Arc::new(SystemClock::starting_at(1_709_991_000_000));
+        shell.attach_time(Arc::new(TimeService::new(clock, Arc::new(CapabilityManager::new()))));
+        let mail = shell.create_window("Mail".to_string(), 1).unwrap();
+        let music = shell.create_window("Music".to_string(), 2).unwrap();
+        shell
+            .tray()
+            .set_icon(
+                "net-config",
+                TrayIcon {
+                    id: "wifi".to_string(),
+                    icon: "wifi-strong".to_string(),
+                    tooltip: "home".to_string(),
+                    menu: vec![TrayMenuItem::new("disconnect", "Disconnect")],
+                },
+            )
+            .unwrap();
+        assert_eq!(shell.render_panel(), "[Mail] [*Music] | wifi-strong | 3/9/2024 1:30 PM");
+
+        // Clicking the focused window minimizes it, clicking again restores it
+        shell.panel_activate(music).unwrap();
+        assert_eq!(shell.get_focused_window(), Some(mail));
+        assert_eq!(shell.render_panel(), "[*Mail] [_Music] | wifi-strong | 3/9/2024 1:30 PM");
+        assert!(shell.handle_command(&format!("activate {}", music.0)).is_ok());
+        assert_eq!(shell.get_focused_window(), Some(music));
+
+        assert!(shell.handle_command("tray net-config wifi disconnect").is_ok());
+        assert_eq!(shell.tray().take_events("net-config").len(), 1);
+    }
+
+    #[test]
+    fn test_session_layout_restore() {
+        let vfs = Arc::new(VirtualFileSystem::new());
+        vfs.create_directory(Path::new("/home")).unwrap();
+        let path = PathBuf::from("/home/layout.json");
+
+        let mut shell = Shell::new();
+        shell.attach_layout_store(Arc::clone(&vfs), path.clone());
+        let terminal = shell.open_terminal().unwrap();
+        let editor = shell.create_window("Notes".to_string(), 7).unwrap();
+        shell.set_window_app(editor, "editor").unwrap();
+        shell.move_window(editor, 40, 60).unwrap();
+        shell.resize_window(editor, 640, 480).unwrap();
+        shell.set_always_on_top(editor, true).unwrap();
+        let untracked = shell.create_window("Scratch".to_string(), 8).unwrap();
+        let chat_space = shell.create_workspace("Chat".to_string());
+        let chat = shell.create_window("Chat".to_string(), 9).unwrap();
+        shell.set_window_app(chat, "chat").unwrap();
+        shell.move_window_to_workspace(chat, chat_space).unwrap();
+        shell.set_window_state(chat, WindowState::Maximized).unwrap();
+        shell.focus_window(terminal).unwrap();
+        shell.lower_window(untracked).unwrap();
+        assert_eq!(shell.handle_command("exit"), Ok(true));
+
+        let mut next = Shell::new();
+        let launched = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&launched);
+        next.register_launcher(
+            "editor",
+            Arc::new(move |saved: &SavedWindow| {
+                log.lock().unwrap().push(saved.title.clone());
+                Ok(70)
+            }),
+        );
+        let report = next.restore_layout(&vfs, &path).unwrap();
+        assert_eq!(*launched.lock().unwrap(), vec!["Notes"]);
+        assert_eq!(report.restored.len(), 2);
+        assert_eq!(
+            report.skipped,
+            vec![
+                ("Scratch".to_string(), "No application to relaunch".to_string()),
+                ("Chat".to_string(), "No launcher for chat".to_string()),
+            ]
+        );
+
+        let windows = next.stacking_order();
+        assert_eq!(windows.iter().map(|w| w.title.as_str()).collect::<Vec<_>>(), vec!["Terminal", "Notes"]);
+        let notes = windows[1];
+        assert_eq!((notes.x, notes.y, notes.width, notes.height, notes.process_id), (40, 60, 640, 480, 70));
+        assert_eq!(notes.app_id.as_deref(), Some("editor"));
+        assert!(next.render_terminal(windows[0].id).is_ok());
+        assert_eq!(next.get_focused_window(), Some(windows[0].id));
+        assert_eq!(
+            next.workspaces().iter().map(|w| w.name.as_str()).collect::<Vec<_>>(),
+            vec!["Workspace 1", "Chat"]
+        );
+    }
+
+    #[test]
+    fn test_window_rules() {
+        let vfs = Arc::new(VirtualFileSystem::new());
+        vfs.create_directory(Path::new("/home")).unwrap();
+        let path = PathBuf::from("/home/rules.json");
+        let write = |data: &[u8]| vfs.write_file(&path, data).unwrap();
+        write(
+            br#"{"rules":[{"app_id":"mpv","actions":[{"action":"floating"},{"action":"size","width":1280,"height":720}]},
+                {"title":"Slack","actions":[{"action":"workspace","number":2},{"action":"maximized"}]}]}"#,
+        );
+
+        let mut shell = Shell::new();
+        shell.attach_rules(Arc::clone(&vfs), path.clone()).unwrap();
+        let notes = shell.create_window("Notes".to_string(), 1).unwrap();
+        let video = shell.create_window("Video".to_string(), 2).unwrap();
+        shell.set_window_app(video, "mpv").unwrap();
+        let slack = shell.create_window("Slack - general".to_string(), 3).unwrap();
+
+        let player = shell.get_window(video).unwrap();
+        assert!(player.floating && player.fixed_size);
+        assert_eq!((player.width, player.height), (1280, 720));
+        let second = shell.workspaces()[1].id;
+        let chat = shell.get_window(slack).unwrap();
+        assert_eq!((chat.workspace, chat.state), (second, WindowState::Maximized));
+        assert_eq!(shell.active_workspace(), shell.workspaces()[0].id);
+        assert_eq!(shell.get_focused_window(), Some(video));
+        assert!(!shell.get_window(notes).unwrap().floating);
+
+        // Editing the file re-evaluates the open windows on the next frame
+        write(br#"{"rules":[{"title":"Notes","actions":[{"action":"floating"}]}]}"#);
+        shell.present(VSYNC_INTERVAL_MS).unwrap();
+        assert!(shell.get_window(notes).unwrap().floating);
+        assert!(!shell.get_window(video).unwrap().fixed_size);
+
+        write(b"{broken");
+        assert!(shell.present(VSYNC_INTERVAL_MS).is_err());
+        assert_eq!(shell.rules().rules.len(), 1);
+        assert!(shell.present(VSYNC_INTERVAL_MS).is_ok());
+        vfs.delete(&path).unwrap();
+        shell.present(VSYNC_INTERVAL_MS).unwrap();
+        assert!(!shell.get_window(notes).unwrap().floating);
+    }
+
     #[test]
     fn test_window_state_change() {
         let mut shell = Shell::new();
-        let window_id = shell.create_window("Test Window".to_string(), 1);
+        let window_id = shell.create_window("Test Window".to_string(), 1).unwrap();
         
         assert!(shell.set_window_state(window_id, WindowState::Maximized).is_ok());
         let window = shell.get_window(window_id).unwrap();
@@ -331,9 +2739,67 @@ mod tests {
     #[test]
     fn test_window_close() {
         let mut shell = Shell::new();
-        let window_id = shell.create_window("Test Window".to_string(), 1);
+        let window_id = shell.create_window("Test Window".to_string(), 1).unwrap();
         
         assert!(shell.close_window(window_id).is_ok());
         assert!(shell.get_window(window_id).is_none());
     }
+
+    #[test]
+    fn test_terminal_window() {
+        let mut shell = Shell::new();
+        let id = shell.open_terminal().unwrap();
+        assert_eq!(shell.get_window(id).unwrap().title, "Terminal");
+        assert!(shell.handle_command(&format!("type {} echo hello", id.0)).is_ok());
+        assert_eq!(shell.render_terminal(id).unwrap(), vec!["$ echo hello", "hello", "$ "]);
+
+        let editor = shell.create_window("Editor".to_string(), 7).unwrap();
+        assert!(shell.terminal_input(editor, "ls\n").is_err());
+        shell.terminal_input(id, "exit\n").unwrap();
+        assert!(shell.get_window(id).is_none());
+        assert!(shell.get_window(editor).is_some());
+    }
+
+    #[test]
+    fn test_locale_change_retitles_terminals() {
+        let i18n = Arc::new(Localizer::new());
+        let mut catalog = i18n::Catalog::new(Locale::parse("fi").unwrap());
+        catalog.insert("Terminal", i18n::Translation::Text("Pääte".to_string()));
+        i18n.add_catalog(catalog);
+
+        let mut shell = Shell::new();
+        shell.attach_localizer(Arc::clone(&i18n));
+        let terminal = shell.open_terminal().unwrap();
+        let editor = shell.create_window("Editor".to_string(), 7).unwrap();
+        // Another program switches the language
+        i18n.set_locale(Locale::parse("fi-FI").unwrap());
+        assert!(shell.handle_command("windows").is_ok());
+        assert_eq!(shell.get_window(terminal).unwrap().title, "Pääte");
+        assert_eq!(shell.get_window(editor).unwrap().title, "Editor");
+        assert!(shell.handle_command("locale xx-YYY").is_err());
+    }
+
+    #[test]
+    fn test_notification_list() {
+        use notifications::NotificationRequest;
+
+        let mut shell = Shell::new();
+        assert!(shell.render_notifications().is_empty());
+        let service = Arc::new(NotificationService::new());
+        shell.attach_notifications(Arc::clone(&service));
+
+        service.notify(NotificationRequest::new("mail", "New mail", "", Urgency::Low)).unwrap();
+        service
+            .notify(NotificationRequest::new("power", "Battery low", "5%", Urgency::Critical).with_action("saver", "Saver"))
+            .unwrap();
+        assert_eq!(
+            shell.render_notifications(),
+            vec!["![2] power: Battery low - 5%  [saver] Saver", " [1] mail: New mail"]
+        );
+
+        assert!(shell.handle_command("dismiss 1").is_ok());
+        assert!(shell.handle_command("action 2 saver").is_ok());
+        assert!(shell.render_notifications().is_empty());
+        assert!(shell.handle_command("dismiss 2").is_err());
+    }
 }
@@ -0,0 +1,69 @@
+//! Display outputs
+//!
+//! Each monitor is an output with its own compositor. Outputs are placed
+//! side by side in one virtual desktop coordinate space; a window is shown
+//! on the output holding most of it.
+
+use std::sync::Arc;
+
+use compositor::{Compositor, Rect};
+
+use crate::WindowId;
+
+/// Output identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct OutputId(u64);
+
+impl OutputId {
+    pub fn new(id: u64) -> Self {
+        OutputId(id)
+    }
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+/// A monitor placed in the virtual desktop
+#[derive(Clone)]
+pub struct Output {
+    pub id: OutputId,
+    pub name: String,
+    /// Position and size in desktop coordinates
+    pub rect: Rect,
+    pub compositor: Arc<Compositor>,
+    /// Window covering the whole output
+    pub fullscreen: Option<WindowId>,
+}
+
+/// Area of the overlap between two rectangles
+pub fn overlap(a: &Rect, b: &Rect) -> u64 {
+    a.intersect(b).map_or(0, |r| r.width as u64 * r.height as u64)
+}
+
+/// Carry `window` from output `from` to output `to`, keeping its offset
+/// from the output's corner and shrinking it if it would not fit
+pub fn carry(window: Rect, from: Rect, to: Rect) -> Rect {
+    let width = window.width.min(to.width);
+    let height = window.height.min(to.height);
+    let max_x = (to.width - width) as i32;
+    let max_y = (to.height - height) as i32;
+    let x = (window.x - from.x).clamp(0, max_x);
+    let y = (window.y - from.y).clamp(0, max_y);
+    Rect::new(to.x + x, to.y + y, width, height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_carry_between_outputs() {
+        let laptop = Rect::new(0, 0, 1920, 1080);
+        let monitor = Rect::new(1920, -200, 1280, 1024);
+        assert_eq!(carry(Rect::new(100, 50, 800, 600), laptop, monitor), Rect::new(2020, -150, 800, 600));
+        // Too far right and too tall for the smaller monitor
+        assert_eq!(carry(Rect::new(1500, 0, 400, 1080), laptop, monitor), Rect::new(2800, -200, 400, 1024));
+        assert_eq!(overlap(&Rect::new(1800, 0, 400, 100), &laptop), 12_000);
+    }
+}
@@ -0,0 +1,283 @@
+//! Panel and system tray
+//!
+//! The panel shows the active workspace's windows, the clock and the
+//! system tray. Services put status icons in the tray over IPC, each with
+//! an optional menu; clicks and menu choices are queued for the owning
+//! service and handed to its event handler.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use ipc::JsonService;
+use serde::{Deserialize, Serialize};
+
+use crate::WindowId;
+
+/// Most icons one service may keep in the tray
+pub const MAX_ICONS_PER_SERVICE: usize = 4;
+
+/// One window in the panel's window list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PanelEntry {
+    pub window: WindowId,
+    pub title: String,
+    pub focused: bool,
+    pub minimized: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrayMenuItem {
+    pub id: String,
+    pub label: String,
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl TrayMenuItem {
+    pub fn new(id: &str, label: &str) -> Self {
+        TrayMenuItem {
+            id: id.to_string(),
+            label: label.to_string(),
+            enabled: true,
+        }
+    }
+}
+
+/// Status icon registered by a service
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrayIcon {
+    pub id: String,
+    /// Icon name, such as `battery-low`
+    pub icon: String,
+    #[serde(default)]
+    pub tooltip: String,
+    #[serde(default)]
+    pub menu: Vec<TrayMenuItem>,
+}
+
+/// User interaction delivered back to the owning service
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TrayEvent {
+    Clicked { icon: String },
+    MenuItem { icon: String, item: String },
+}
+
+/// Callback run for every event of one service
+pub type TrayEventHandler = Arc<dyn Fn(&TrayEvent) + Send + Sync>;
+
+#[derive(Default)]
+struct ServiceState {
+    events: Vec<TrayEvent>,
+    handler: Option<TrayEventHandler>,
+}
+
+/// Registry of status icons
+pub struct Tray {
+    /// Icons keyed by service and icon ID
+    icons: Arc<Mutex<BTreeMap<(String, String), TrayIcon>>>,
+    services: Arc<Mutex<HashMap<String, ServiceState>>>,
+}
+
+impl Tray {
+    pub fn new() -> Self {
+        Tray {
+            icons: Arc::new(Mutex::new(BTreeMap::new())),
+            services: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Add an icon, or replace the service's icon with the same ID
+    pub fn set_icon(&self, service: &str, icon: TrayIcon) -> Result<(), String> {
+        if service.is_empty() || icon.id.is_empty() {
+            return Err("Service and icon ID are required".to_string());
+        }
+        let mut icons = self.icons.lock().unwrap();
+        let key = (service.to_string(), icon.id.clone());
+        let owned = icons.keys().filter(|(s, _)| s == service).count();
+        if !icons.contains_key(&key) && owned >= MAX_ICONS_PER_SERVICE {
+            return Err(format!("{} already has {} tray icons", service, MAX_ICONS_PER_SERVICE));
+        }
+        icons.insert(key, icon);
+        Ok(())
+    }
+
+    pub fn remove_icon(&self, service: &str, id: &str) -> Result<(), String> {
+        self.icons
+            .lock()
+            .unwrap()
+            .remove(&(service.to_string(), id.to_string()))
+            .map(|_| ())
+            .ok_or_else(|| "Tray icon not found".to_string())
+    }
+
+    /// Icons with their owning service, in display order
+    pub fn icons(&self) -> Vec<(String, TrayIcon)> {
+        self.icons
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|((service, _), icon)| (service.clone(), icon.clone()))
+            .collect()
+    }
+
+    /// Run `handler` for each of the service's tray events
+    pub fn set_event_handler(&self, service: &str, handler: TrayEventHandler) {
+        self.services.lock().unwrap().entry(service.to_string()).or_default().handler = Some(handler);
+    }
+
+    /// The user clicked an icon, or picked `item` from its menu
+    pub fn activate(&self, service: &str, id: &str, item: Option<&str>) -> Result<(), String> {
+        let event = {
+            let icons = self.icons.lock().unwrap();
+            let icon = icons
+                .get(&(service.to_string(), id.to_string()))
+                .ok_or("Tray icon not found")?;
+            match item {
+                None => TrayEvent::Clicked { icon: id.to_string() },
+                Some(item) => {
+                    let entry = icon.menu.iter().find(|m| m.id == item).ok_or("Menu item not found")?;
+                    if !entry.enabled {
+                        return Err(format!("{} is disabled", entry.label));
+                    }
+                    TrayEvent::MenuItem {
+                        icon: id.to_string(),
+                        item: item.to_string(),
+                    }
+                }
+            }
+        };
+        let handler = {
+            let mut services = self.services.lock().unwrap();
+            let state = services.entry(service.to_string()).or_default();
+            state.events.push(event.clone());
+            state.handler.clone()
+        };
+        if let Some(handler) = handler {
+            handler(&event);
+        }
+        Ok(())
+    }
+
+    /// Drain the events queued for a service
+    pub fn take_events(&self, service: &str) -> Vec<TrayEvent> {
+        self.services
+            .lock()
+            .unwrap()
+            .get_mut(service)
+            .map(|state| std::mem::take(&mut state.events))
+            .unwrap_or_default()
+    }
+}
+
+impl JsonService for Tray {
+    type Request = TrayRequest;
+    type Response = TrayResponse;
+    const KIND: &'static str = "tray";
+
+    fn handle_request(&self, request: TrayRequest) -> TrayResponse {
+        let result = match request {
+            TrayRequest::SetIcon { service, icon } => self.set_icon(&service, icon).map(|_| TrayResponse::Ok),
+            TrayRequest::RemoveIcon { service, id } => self.remove_icon(&service, &id).map(|_| TrayResponse::Ok),
+            TrayRequest::Events { service } => Ok(TrayResponse::Events {
+                events: self.take_events(&service),
+            }),
+        };
+        result.unwrap_or_else(|message| TrayResponse::Error { message })
+    }
+}
+
+impl Default for Tray {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Request accepted over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum TrayRequest {
+    SetIcon { service: String, icon: TrayIcon },
+    RemoveIcon { service: String, id: String },
+    Events { service: String },
+}
+
+/// Reply sent over IPC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum TrayResponse {
+    Events { events: Vec<TrayEvent> },
+    Ok,
+    Error { message: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ipc::{Message, ERROR_BAD_REQUEST};
+
+    #[test]
+    fn test_icons_over_ipc() {
+        let tray = Tray::new();
+        let register = Message::Request {
+            id: 1,
+            data: br#"{"op":"set_icon","service":"power","icon":{"id":"battery","icon":"battery-low",
+                "tooltip":"12%","menu":[{"id":"saver","label":"Battery saver"},
+                {"id":"calibrate","label":"Calibrate","enabled":false}]}}"#
+                .to_vec(),
+        };
+        let Some(Message::Response { data, .. }) = tray.handle_message(&register) else {
+            panic!("expected a response");
+        };
+        assert_eq!(serde_json::from_slice::<TrayResponse>(&data).unwrap(), TrayResponse::Ok);
+        let icons = tray.icons();
+        assert_eq!(icons[0].0, "power");
+        assert!(!icons[0].1.menu[1].enabled);
+
+        tray.activate("power", "battery", Some("saver")).unwrap();
+        tray.activate("power", "battery", None).unwrap();
+        assert!(tray.activate("power", "battery", Some("calibrate")).is_err());
+        assert!(tray.activate("power", "wifi", None).is_err());
+        assert_eq!(
+            tray.handle_request(TrayRequest::Events { service: "power".to_string() }),
+            TrayResponse::Events {
+                events: vec![
+                    TrayEvent::MenuItem { icon: "battery".to_string(), item: "saver".to_string() },
+                    TrayEvent::Clicked { icon: "battery".to_string() },
+                ]
+            }
+        );
+
+        let bad = Message::Request { id: 2, data: b"{\"op\":\"set_icon\"}".to_vec() };
+        assert!(matches!(tray.handle_message(&bad), Some(Message::Error { code: ERROR_BAD_REQUEST, .. })));
+    }
+
+    #[test]
+    fn test_icon_limit_and_handler() {
+        let tray = Tray::new();
+        let icon = |id: &str| TrayIcon {
+            id: id.to_string(),
+            icon: "generic".to_string(),
+            tooltip: String::new(),
+            menu: Vec::new(),
+        };
+        for i in 0..MAX_ICONS_PER_SERVICE {
+            tray.set_icon("spammy", icon(&i.to_string())).unwrap();
+        }
+        assert!(tray.set_icon("spammy", icon("extra")).is_err());
+        // Replacing an existing icon is always allowed
+        assert!(tray.set_icon("spammy", icon("0")).is_ok());
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        tray.set_event_handler("spammy", Arc::new(move |event| log.lock().unwrap().push(event.clone())));
+        tray.activate("spammy", "1", None).unwrap();
+        assert_eq!(*seen.lock().unwrap(), vec![TrayEvent::Clicked { icon: "1".to_string() }]);
+        tray.remove_icon("spammy", "1").unwrap();
+        assert!(tray.remove_icon("spammy", "1").is_err());
+    }
+}
@@ -0,0 +1,172 @@
+//! Pointer interactions
+//!
+//! Pressing the left button on a title bar starts a move, on a window
+//! border a resize. While the grab lasts, pointer motion updates the
+//! window's geometry; releasing a move near a screen edge or corner snaps
+//! the window to that half or quarter of the screen.
+
+use compositor::Rect;
+
+use crate::WindowId;
+
+pub const BUTTON_LEFT: u8 = 1;
+
+/// Width of the border that resizes instead of moving
+pub const RESIZE_BORDER: i32 = 4;
+
+/// Distance from the screen edge at which a dragged window snaps
+pub const SNAP_MARGIN: i32 = 8;
+
+pub const MIN_WINDOW_WIDTH: u32 = 120;
+pub const MIN_WINDOW_HEIGHT: u32 = 80;
+
+/// Window borders a resize moves
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Edges {
+    pub left: bool,
+    pub right: bool,
+    pub top: bool,
+    pub bottom: bool,
+}
+
+impl Edges {
+    pub fn any(&self) -> bool {
+        self.left || self.right || self.top || self.bottom
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrabKind {
+    Move,
+    Resize(Edges),
+}
+
+/// A window held by the pointer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Grab {
+    pub window: WindowId,
+    pub kind: GrabKind,
+    /// Pointer position when the button went down
+    pub start: (i32, i32),
+    /// Window geometry when the button went down
+    pub origin: Rect,
+}
+
+/// Part of the screen a window snaps to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Snap {
+    LeftHalf,
+    RightHalf,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Snap {
+    /// Snap zone under the pointer, if any
+    pub fn at(x: i32, y: i32, screen: (u32, u32)) -> Option<Snap> {
+        let (width, height) = (screen.0 as i32, screen.1 as i32);
+        let left = x < SNAP_MARGIN;
+        let right = x >= width - SNAP_MARGIN;
+        let top = y < SNAP_MARGIN;
+        let bottom = y >= height - SNAP_MARGIN;
+        match (left, right, top, bottom) {
+            (true, _, true, _) => Some(Snap::TopLeft),
+            (true, _, _, true) => Some(Snap::BottomLeft),
+            (_, true, true, _) => Some(Snap::TopRight),
+            (_, true, _, true) => Some(Snap::BottomRight),
+            (true, ..) => Some(Snap::LeftHalf),
+            (_, true, ..) => Some(Snap::RightHalf),
+            _ => None,
+        }
+    }
+
+    pub fn geometry(&self, screen: (u32, u32)) -> Rect {
+        let half_width = screen.0 / 2;
+        let half_height = screen.1 / 2;
+        let right = half_width as i32;
+        let lower = half_height as i32;
+        match self {
+            Snap::LeftHalf => Rect::new(0, 0, half_width, screen.1),
+            Snap::RightHalf => Rect::new(right, 0, screen.0 - half_width, screen.1),
+            Snap::TopLeft => Rect::new(0, 0, half_width, half_height),
+            Snap::TopRight => Rect::new(right, 0, screen.0 - half_width, half_height),
+            Snap::BottomLeft => Rect::new(0, lower, half_width, screen.1 - half_height),
+            Snap::BottomRight => Rect::new(right, lower, screen.0 - half_width, screen.1 - half_height),
+        }
+    }
+}
+
+/// What pressing at `(x, y)` inside `frame` grabs; `None` for the window contents
+pub fn hit_test(frame: Rect, x: i32, y: i32, title_bar_height: u32) -> Option<GrabKind> {
+    let edges = Edges {
+        left: x < frame.x + RESIZE_BORDER,
+        right: x >= frame.x + frame.width as i32 - RESIZE_BORDER,
+        top: y < frame.y + RESIZE_BORDER,
+        bottom: y >= frame.y + frame.height as i32 - RESIZE_BORDER,
+    };
+    if edges.any() {
+        Some(GrabKind::Resize(edges))
+    } else if y < frame.y + title_bar_height as i32 {
+        Some(GrabKind::Move)
+    } else {
+        None
+    }
+}
+
+/// Geometry after dragging `edges` by `(dx, dy)`, keeping the opposite edges
+/// in place and the window above its minimum size
+pub fn resize(origin: Rect, edges: Edges, dx: i32, dy: i32) -> Rect {
+    let span = |start: i32, size: u32, delta: i32, near: bool, far: bool, min: u32| {
+        let end = start + size as i32;
+        if near {
+            let start = (start + delta).min(end - min as i32);
+            (start, (end - start) as u32)
+        } else if far {
+            (start, (size as i32 + delta).max(min as i32) as u32)
+        } else {
+            (start, size)
+        }
+    };
+    let (x, width) = span(origin.x, origin.width, dx, edges.left, edges.right, MIN_WINDOW_WIDTH);
+    let (y, height) = span(origin.y, origin.height, dy, edges.top, edges.bottom, MIN_WINDOW_HEIGHT);
+    Rect::new(x, y, width, height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hit_test_and_resize() {
+        let frame = Rect::new(100, 100, 400, 300);
+        assert_eq!(hit_test(frame, 300, 110, 24), Some(GrabKind::Move));
+        assert_eq!(hit_test(frame, 300, 250, 24), None);
+        let corner = Edges {
+            left: true,
+            top: true,
+            ..Edges::default()
+        };
+        assert_eq!(hit_test(frame, 101, 102, 24), Some(GrabKind::Resize(corner)));
+
+        assert_eq!(resize(frame, corner, -20, 30), Rect::new(80, 130, 420, 270));
+        // The right and bottom edges stay put at the minimum size
+        assert_eq!(resize(frame, corner, 1000, 1000), Rect::new(380, 320, MIN_WINDOW_WIDTH, MIN_WINDOW_HEIGHT));
+        let right = Edges {
+            right: true,
+            ..Edges::default()
+        };
+        assert_eq!(resize(frame, right, -500, 40), Rect::new(100, 100, MIN_WINDOW_WIDTH, 300));
+    }
+
+    #[test]
+    fn test_snap_zones() {
+        let screen = (1920, 1080);
+        assert_eq!(Snap::at(0, 500, screen), Some(Snap::LeftHalf));
+        assert_eq!(Snap::at(1919, 1079, screen), Some(Snap::BottomRight));
+        assert_eq!(Snap::at(960, 0, screen), None);
+        assert_eq!(Snap::RightHalf.geometry(screen), Rect::new(960, 0, 960, 1080));
+        assert_eq!(Snap::BottomLeft.geometry((1001, 701)), Rect::new(0, 350, 500, 351));
+    }
+}
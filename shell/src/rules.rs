@@ -0,0 +1,113 @@
+//! Window rules
+//!
+//! Rules match windows by application ID and title and decide how they
+//! open: maximized, on a given workspace, floating or at a fixed size. The
+//! rules live in a JSON file in the VFS; when the file changes the shell
+//! reads it again and applies the new rules to every open window.
+//!
+//! ```json
+//! {"rules": [{"app_id": "mpv", "actions": [{"action": "floating"},
+//!             {"action": "size", "width": 1280, "height": 720}]},
+//!            {"title": "Slack", "actions": [{"action": "workspace", "number": 2}]}]}
+//! ```
+
+use serde::{Deserialize, Serialize};
+
+use crate::Window;
+
+/// What a rule does to the windows it matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum RuleAction {
+    Maximized,
+    /// Pin to the workspace at this position in the switcher, from 1
+    Workspace { number: usize },
+    /// Never snapped to a screen edge
+    Floating,
+    /// Open at this size and never resize with the pointer
+    Size { width: u32, height: u32 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowRule {
+    /// Exact application ID; any application when absent
+    #[serde(default)]
+    pub app_id: Option<String>,
+    /// Text the title must contain; any title when absent
+    #[serde(default)]
+    pub title: Option<String>,
+    pub actions: Vec<RuleAction>,
+}
+
+impl WindowRule {
+    pub fn matches(&self, window: &Window) -> bool {
+        let app = self.app_id.as_ref().is_none_or(|app| window.app_id.as_ref() == Some(app));
+        let title = self.title.as_ref().is_none_or(|title| window.title.contains(title.as_str()));
+        app && title
+    }
+}
+
+/// Rules in file order; later rules win where actions conflict
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuleSet {
+    pub rules: Vec<WindowRule>,
+}
+
+impl RuleSet {
+    pub fn from_json(data: &[u8]) -> Result<Self, String> {
+        let set: RuleSet = serde_json::from_slice(data).map_err(|e| format!("Invalid window rules: {}", e))?;
+        for action in set.rules.iter().flat_map(|r| &r.actions) {
+            match action {
+                RuleAction::Workspace { number: 0 } => {
+                    return Err("Invalid window rules: workspaces are numbered from 1".to_string())
+                }
+                RuleAction::Size { width, height } if *width == 0 || *height == 0 => {
+                    return Err("Invalid window rules: sizes must be positive".to_string())
+                }
+                _ => {}
+            }
+        }
+        Ok(set)
+    }
+
+    pub fn to_json(&self) -> Vec<u8> {
+        serde_json::to_vec_pretty(self).unwrap()
+    }
+
+    /// Actions of every rule matching `window`, in order
+    pub fn actions_for(&self, window: &Window) -> Vec<RuleAction> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.matches(window))
+            .flat_map(|rule| rule.actions.iter().copied())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workspace::WorkspaceId;
+    use crate::WindowId;
+
+    #[test]
+    fn test_rule_matching() {
+        let rules = RuleSet::from_json(
+            br#"{"rules":[{"app_id":"mpv","actions":[{"action":"floating"}]},
+                {"title":"Slack","actions":[{"action":"workspace","number":2}]},
+                {"app_id":"chat","title":"Slack","actions":[{"action":"maximized"}]}]}"#,
+        )
+        .unwrap();
+        let mut window = Window::new(WindowId::new(1), "Slack - general".to_string(), 1, WorkspaceId::new(1));
+        assert_eq!(rules.actions_for(&window), vec![RuleAction::Workspace { number: 2 }]);
+        window.app_id = Some("chat".to_string());
+        assert_eq!(rules.actions_for(&window), vec![RuleAction::Workspace { number: 2 }, RuleAction::Maximized]);
+        window.title = "Video".to_string();
+        assert!(rules.actions_for(&window).is_empty());
+        assert_eq!(RuleSet::from_json(&rules.to_json()).unwrap(), rules);
+
+        assert!(RuleSet::from_json(br#"{"rules":[{"actions":[{"action":"workspace","number":0}]}]}"#).is_err());
+        assert!(RuleSet::from_json(br#"{"rules":[{"actions":[{"action":"size","width":0,"height":10}]}]}"#).is_err());
+        assert!(RuleSet::from_json(br#"{"rules":[{"actions":[{"action":"shrink"}]}]}"#).is_err());
+    }
+}
@@ -0,0 +1,53 @@
+//! Virtual desktops
+//!
+//! Every window lives on exactly one workspace and only the active
+//! workspace's windows are shown. Each workspace remembers its own focused
+//! window so switching back restores it.
+
+use std::sync::Arc;
+
+use crate::WindowId;
+
+/// Workspace identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct WorkspaceId(u64);
+
+impl WorkspaceId {
+    pub fn new(id: u64) -> Self {
+        WorkspaceId(id)
+    }
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+/// A virtual desktop
+#[derive(Debug, Clone)]
+pub struct Workspace {
+    pub id: WorkspaceId,
+    pub name: String,
+    pub focused_window: Option<WindowId>,
+}
+
+impl Workspace {
+    pub fn new(id: WorkspaceId, name: String) -> Self {
+        Workspace {
+            id,
+            name,
+            focused_window: None,
+        }
+    }
+}
+
+/// Change a workspace switcher needs to redraw for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WorkspaceEvent {
+    Created(WorkspaceId),
+    Destroyed(WorkspaceId),
+    Switched { from: WorkspaceId, to: WorkspaceId },
+    WindowMoved { window: WindowId, from: WorkspaceId, to: WorkspaceId },
+}
+
+/// Callback run for every workspace event
+pub type WorkspaceListener = Arc<dyn Fn(&WorkspaceEvent) + Send + Sync>;
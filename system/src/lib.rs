@@ -0,0 +1,595 @@
+//! hairr OS system assembly
+//!
+//! Boots a complete system in one process: the kernel, memory manager,
+//! scheduler, VFS, IPC manager and device manager first, then the services on top of them,
+//! which init starts as supervised processes in dependency order. Components
+//! share each other through `Arc` handles; services that answer requests
+//! also get an IPC endpoint, a channel owned by their current process.
+//!
+//! Apps installed from a package run confined, every request they make
+//! checked by `sandbox`. With secure boot configured, the kernel and each service image are
+//! verified and measured before they start; see `secure_boot`.
+//!
+//! `HairrSystem` holds every handle, so integration tests can drive the
+//! whole system the way the shell and applications do.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use ai_scheduler::AIScheduler;
+use capability::CapabilityManager;
+use device_manager::DeviceManager;
+use filesystem::VirtualFileSystem;
+use init::{ServiceManager, ServiceUnit};
+use ipc::{ChannelId, IPCManager, Message, OrphanPolicy};
+use kernel::{Kernel, ProcessId, ProcessState};
+use keystore::Keystore;
+use memory_manager::MemoryManager;
+use metrics::MetricsRegistry;
+use notifications::NotificationService;
+use security_audit::SecurityAuditService;
+use session::SessionManager;
+use system_utils::sysinfo::{self, SystemMonitor};
+use system_utils::time::{system_clock, Clock, SimulatedClock};
+use telemetry::TelemetryService;
+use time::{SystemClock, TimeService};
+use users::UserService;
+
+pub mod sandbox;
+pub mod secure_boot;
+pub mod services;
+
+pub use sandbox::{Sandbox, Violation};
+pub use secure_boot::{AttestationLog, BootImage, BootMode, SecureBoot, TrustStore};
+pub use services::{Handler, BASE_DIRECTORIES};
+
+/// Physical memory a system boots with unless told otherwise
+pub const DEFAULT_MEMORY_MB: usize = 256;
+
+/// Configured hostname, one line
+pub const HOSTNAME_FILE: &str = "/etc/hostname";
+
+/// How a system boots
+#[derive(Clone)]
+pub struct BootOptions {
+    pub memory_mb: usize,
+    /// Virtual time starting at this many milliseconds since the Unix
+    /// epoch, advanced only by `HairrSystem::tick`; the host clock if `None`
+    pub simulated_start_ms: Option<u64>,
+    /// Verify images before starting them; nothing is checked if `None`
+    pub secure_boot: Option<SecureBoot>,
+    /// Let sandboxed apps past their profiles, logging what they do
+    pub developer_mode: bool,
+    /// Written to `HOSTNAME_FILE` at boot; the file is left alone if `None`
+    pub hostname: Option<String>,
+    /// Filesystem to boot from, with the packages installed on it; an
+    /// empty one if `None`
+    pub root: Option<Arc<VirtualFileSystem>>,
+}
+
+impl BootOptions {
+    pub fn with_memory(mut self, memory_mb: usize) -> Self {
+        self.memory_mb = memory_mb;
+        self
+    }
+
+    pub fn with_simulated_time(mut self, start_ms: u64) -> Self {
+        self.simulated_start_ms = Some(start_ms);
+        self
+    }
+
+    pub fn with_secure_boot(mut self, secure_boot: SecureBoot) -> Self {
+        self.secure_boot = Some(secure_boot);
+        self
+    }
+
+    pub fn with_developer_mode(mut self) -> Self {
+        self.developer_mode = true;
+        self
+    }
+
+    pub fn with_hostname(mut self, hostname: &str) -> Self {
+        self.hostname = Some(hostname.to_string());
+        self
+    }
+
+    pub fn with_root(mut self, root: Arc<VirtualFileSystem>) -> Self {
+        self.root = Some(root);
+        self
+    }
+}
+
+impl Default for BootOptions {
+    fn default() -> Self {
+        BootOptions {
+            memory_mb: DEFAULT_MEMORY_MB,
+            simulated_start_ms: None,
+            secure_boot: None,
+            developer_mode: false,
+            hostname: None,
+            root: None,
+        }
+    }
+}
+
+impl fmt::Debug for BootOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BootOptions")
+            .field("memory_mb", &self.memory_mb)
+            .field("simulated_start_ms", &self.simulated_start_ms)
+            .field("secure_boot", &self.secure_boot)
+            .field("developer_mode", &self.developer_mode)
+            .field("hostname", &self.hostname)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A booted system
+pub struct HairrSystem {
+    pub clock: Arc<dyn Clock>,
+    pub capabilities: Arc<CapabilityManager>,
+    pub kernel: Arc<Kernel>,
+    pub memory: Arc<MemoryManager>,
+    pub scheduler: Arc<AIScheduler>,
+    pub vfs: Arc<VirtualFileSystem>,
+    pub ipc: Arc<IPCManager>,
+    pub devices: Arc<DeviceManager>,
+    pub metrics: MetricsRegistry,
+    pub keystore: Arc<Keystore>,
+    pub users: Arc<UserService>,
+    pub sessions: Arc<SessionManager>,
+    pub time: Arc<TimeService>,
+    pub notifications: Arc<NotificationService>,
+    pub security: Arc<SecurityAuditService>,
+    pub sandbox: Arc<Sandbox>,
+    pub telemetry: Arc<TelemetryService>,
+    pub services: Arc<ServiceManager>,
+    /// System information as `uname` and `uptime` report it
+    pub monitor: SystemMonitor,
+    /// Images measured during boot; empty without secure boot
+    pub attestation: AttestationLog,
+    simulated: Option<Arc<SimulatedClock>>,
+    startup_order: Vec<String>,
+    handlers: HashMap<String, Handler>,
+    endpoints: Mutex<HashMap<String, ChannelId>>,
+}
+
+impl HairrSystem {
+    /// Bring up the core, then the services
+    pub fn boot(options: BootOptions) -> Result<Self, String> {
+        let simulated = options.simulated_start_ms.map(|start| Arc::new(SimulatedClock::new(start)));
+        let clock: Arc<dyn Clock> = match &simulated {
+            Some(clock) => clock.clone(),
+            None => system_clock(),
+        };
+
+        let vfs = options.root.clone().unwrap_or_else(|| Arc::new(VirtualFileSystem::new()));
+        let mut attestation = AttestationLog::new();
+        let verify = |component: &str, log: &mut AttestationLog| match &options.secure_boot {
+            Some(secure_boot) => secure_boot.check(&vfs, component, log),
+            None => Ok(()),
+        };
+        verify(secure_boot::KERNEL, &mut attestation)?;
+
+        // Core: processes own memory and channels, which go with them
+        let capabilities = Arc::new(CapabilityManager::new());
+        let kernel = Arc::new(Kernel::new());
+        kernel.attach_capabilities(Arc::clone(&capabilities));
+        let memory = Arc::new(MemoryManager::new(options.memory_mb));
+        let released = Arc::clone(&memory);
+        kernel.on_process_terminated(Arc::new(move |id| {
+            // Fails only when the process held no memory
+            let _ = released.free_all(memory_manager::ProcessId(id.value()));
+        }));
+        let scheduler = Arc::new(AIScheduler::new());
+        scheduler.attach_kernel(Arc::clone(&kernel));
+        let ipc = Arc::new(IPCManager::new());
+        ipc.attach_kernel(&kernel);
+        let devices = Arc::new(DeviceManager::new());
+
+        let metrics = MetricsRegistry::new();
+        kernel.attach_metrics(&metrics)?;
+        memory.attach_metrics(&metrics)?;
+        ipc.attach_metrics(&metrics)?;
+
+        for directory in BASE_DIRECTORIES.iter().map(Path::new) {
+            if !vfs.exists(directory) {
+                vfs.create_directory(directory)?;
+            }
+        }
+        procfs::mount_all(&vfs, Arc::clone(&kernel), Arc::clone(&memory), Arc::clone(&devices), Arc::clone(&ipc))?;
+        let exited = Arc::clone(&vfs);
+        kernel.on_process_terminated(Arc::new(move |id| {
+            exited.close_process(id);
+        }));
+
+        if let Some(hostname) = &options.hostname {
+            vfs.write_file(Path::new(HOSTNAME_FILE), format!("{}\n", hostname).as_bytes())?;
+        }
+        let monitor = SystemMonitor::new();
+        kernel.attach_sysinfo(&monitor);
+        memory.attach_sysinfo(&monitor);
+        scheduler.attach_sysinfo(&monitor);
+        let configured = Arc::clone(&vfs);
+        monitor.attach_hostname(move || {
+            let hostname = configured.read_file(Path::new(HOSTNAME_FILE)).unwrap_or_default();
+            match String::from_utf8_lossy(&hostname).trim() {
+                "" => sysinfo::DEFAULT_HOSTNAME.to_string(),
+                name => name.to_string(),
+            }
+        });
+
+        // Services, in the order they depend on each other
+        let keystore = Arc::new(Keystore::new());
+        let users = Arc::new(UserService::new(Arc::clone(&keystore), Arc::clone(&capabilities))?);
+        users.attach_clock(Arc::clone(&clock));
+        let sessions = Arc::new(SessionManager::new(Arc::clone(&users), Arc::clone(&kernel), Arc::clone(&capabilities)));
+        let time = Arc::new(TimeService::new(
+            Arc::new(SystemClock::starting_at(clock.now_ms())),
+            Arc::clone(&capabilities),
+        ));
+        let notifications = Arc::new(NotificationService::new());
+        let security = Arc::new(SecurityAuditService::new(Arc::clone(&notifications)));
+        security.attach_capabilities(&capabilities);
+        security.attach_keystore(&keystore);
+        security.attach_users(&users);
+        let sandbox = Arc::new(Sandbox::new(Arc::clone(&kernel), Arc::clone(&vfs), Arc::clone(&security)));
+        sandbox.set_developer_mode(options.developer_mode);
+        let (installed, launched) = (Arc::clone(&vfs), Arc::clone(&sandbox));
+        sessions.set_app_sandbox(Arc::new(move |app| {
+            let Some(profile) = sandbox::installed_profile(&installed, app)? else {
+                return Ok(None);
+            };
+            // Without its root the app has nowhere to run
+            launched.prepare(&profile).map_err(|e| format!("Cannot prepare sandbox for {}: {}", app, e))?;
+            Ok(Some(profile))
+        }));
+        let telemetry = Arc::new(TelemetryService::new(metrics.clone()));
+
+        let services = Arc::new(ServiceManager::new(Arc::clone(&kernel), Arc::clone(&capabilities)));
+        let units: HashMap<String, ServiceUnit> = services::units().into_iter().map(|u| (u.name.clone(), u)).collect();
+        for name in init::unit::startup_order(&units)? {
+            verify(&name, &mut attestation)?;
+        }
+        for unit in units.into_values() {
+            services.add_unit(unit)?;
+        }
+        let startup_order = services.start_all()?;
+
+        let system = HairrSystem {
+            handlers: services::handlers(&users, &sessions, &security, &telemetry),
+            clock,
+            capabilities,
+            kernel,
+            memory,
+            scheduler,
+            vfs,
+            ipc,
+            devices,
+            metrics,
+            keystore,
+            users,
+            sessions,
+            time,
+            notifications,
+            security,
+            sandbox,
+            telemetry,
+            services,
+            monitor,
+            attestation,
+            simulated,
+            startup_order,
+            endpoints: Mutex::new(HashMap::new()),
+        };
+        let mut served: Vec<&String> = system.handlers.keys().collect();
+        served.sort();
+        for name in served {
+            system.endpoint(name)?;
+        }
+        Ok(system)
+    }
+
+    /// Services in the order init started them
+    pub fn startup_order(&self) -> &[String] {
+        &self.startup_order
+    }
+
+    /// Channel a service currently receives requests on
+    pub fn endpoint(&self, service: &str) -> Result<ChannelId, String> {
+        let process = self
+            .services
+            .status(service)
+            .and_then(|status| status.process)
+            .filter(|pid| self.kernel.get_process(*pid).is_some_and(|p| p.state != ProcessState::Terminated))
+            .ok_or_else(|| format!("Service {} is not running", service))?;
+        let mut endpoints = self.endpoints.lock().unwrap();
+        if let Some(&channel) = endpoints.get(service) {
+            if self.ipc.get_channel(channel).is_some_and(|c| c.owner() == Some(process)) {
+                return Ok(channel);
+            }
+        }
+        // The previous process exited and took its channel with it
+        let channel = self.ipc.create_owned_channel(process, OrphanPolicy::Close);
+        endpoints.insert(service.to_string(), channel);
+        Ok(channel)
+    }
+
+    /// Services `process` can see and call
+    pub fn services_for(&self, process: ProcessId) -> Result<Vec<String>, String> {
+        let mut registry: Vec<String> = self.handlers.keys().cloned().collect();
+        registry.sort();
+        self.sandbox.visible_services(process, &registry)
+    }
+
+    /// Send a request to a service on behalf of `process`, if its sandbox
+    /// lets it reach the service
+    pub fn call_as(&self, process: ProcessId, service: &str, request: Message) -> Result<Message, String> {
+        self.sandbox.check_service(process, service)?;
+        self.call(service, request)
+    }
+
+    /// Send a request to a service over its endpoint and wait for the reply
+    pub fn call(&self, service: &str, request: Message) -> Result<Message, String> {
+        let handler = self
+            .handlers
+            .get(service)
+            .ok_or_else(|| format!("Service {} takes no requests", service))?;
+        let channel = self.endpoint(service)?;
+        self.ipc.send_message(channel, request).map_err(String::from)?;
+        let request = self
+            .ipc
+            .receive_message(channel)
+            .map_err(String::from)?
+            .ok_or("Request was lost in transit")?;
+        handler(&request).ok_or_else(|| format!("Service {} did not reply", service))
+    }
+
+    /// Let `elapsed_ms` pass: simulated time moves on, the scheduler and
+    /// services that keep time catch up, load is sampled, and init runs one
+    /// supervision tick
+    pub fn tick(&self, elapsed_ms: u64) -> Result<(), String> {
+        if let Some(clock) = &self.simulated {
+            clock.advance(std::time::Duration::from_millis(elapsed_ms));
+        }
+        self.scheduler.tick(elapsed_ms);
+        self.monitor.sample_load(self.clock.now_ms());
+        self.time.tick(elapsed_ms)?;
+        self.notifications.tick(elapsed_ms);
+        self.security.tick(elapsed_ms);
+        self.telemetry.tick(elapsed_ms);
+        self.services.tick(1);
+        Ok(())
+    }
+
+    /// Stop the services, dependents before what they depend on
+    pub fn shutdown(&self) -> Result<(), String> {
+        for name in self.startup_order.iter().rev() {
+            self.services.stop(name)?;
+        }
+        self.endpoints.lock().unwrap().clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use init::ServiceState;
+    use keystore::{KeyType, KeyUsage};
+    use users::{UsersRequest, UsersResponse};
+
+    fn request(id: u64, request: &UsersRequest) -> Message {
+        Message::Request {
+            id,
+            data: serde_json::to_vec(request).unwrap(),
+        }
+    }
+
+    fn login(system: &HairrSystem) -> Result<UsersResponse, String> {
+        let login = UsersRequest::Login {
+            name: "alice".to_string(),
+            password: "pw".to_string(),
+        };
+        match system.call("users", request(1, &login))? {
+            Message::Response { data, .. } => Ok(serde_json::from_slice(&data).unwrap()),
+            other => Err(format!("unexpected reply: {:?}", other)),
+        }
+    }
+
+    #[test]
+    fn test_boot_and_shutdown() {
+        let system = HairrSystem::boot(BootOptions::default().with_memory(16).with_simulated_time(1_709_164_800_000)).unwrap();
+        let order = system.startup_order();
+        let position = |name: &str| order.iter().position(|n| n == name).unwrap();
+        assert!(position("keystore") < position("users") && position("users") < position("session"));
+        assert_eq!(system.kernel.process_count(), order.len());
+        assert!(system.services.list().iter().all(|s| s.state == ServiceState::Running));
+
+        let channels = String::from_utf8(system.vfs.read_file(Path::new("/ipc/channels")).unwrap()).unwrap();
+        assert_eq!(channels.lines().count(), 1 + system.handlers.len());
+        assert!(system.vfs.metadata(Path::new("/tmp")).unwrap().is_directory());
+        assert_eq!(system.monitor.hostname(), "hairr-system");
+        assert_eq!(system.monitor.boot_time_ms(), system.kernel.boot_time_ms());
+        assert_eq!(system.monitor.memory().unwrap().total, 16 * 1024 * 1024);
+
+        system.users.create_user("alice", "pw").unwrap();
+        let Ok(UsersResponse::Session { token }) = login(&system) else {
+            panic!("expected a session");
+        };
+        assert!(system.users.session(&token).is_some());
+        assert!(system.call("time", Message::Text("now".to_string())).is_err());
+
+        // Password guessing reaches the user as a security alert
+        for _ in 0..5 {
+            assert!(system.users.login("alice", "guess").is_err());
+        }
+        assert_eq!(system.security.alerts()[0].rule, "password-guessing");
+        assert_eq!(system.notifications.active()[0].app_id, security_audit::APP_ID);
+
+        system.shutdown().unwrap();
+        assert!(system.services.list().iter().all(|s| s.state == ServiceState::Stopped));
+        assert!(system.ipc.list_channels().is_empty());
+        assert!(login(&system).is_err());
+    }
+
+    #[test]
+    fn test_load_follows_run_queue() {
+        let system = HairrSystem::boot(BootOptions::default().with_memory(16)).unwrap();
+        let worker = system.kernel.create_process("worker".to_string(), kernel::Priority::Normal);
+        system.scheduler.add_task(ai_scheduler::Task::new(worker, ai_scheduler::WorkloadType::Batch)).unwrap();
+        let [one, ..] = system.monitor.sample_load(system.clock.now_ms() + 60_000);
+        assert!(one > 0.5);
+
+        // Terminated processes leave the run queue
+        system.kernel.terminate_process(worker).unwrap();
+        assert_eq!(system.scheduler.queue_len(), 0);
+    }
+
+    #[test]
+    fn test_hostname_from_configuration() {
+        let system = HairrSystem::boot(BootOptions::default().with_memory(16).with_hostname("studio")).unwrap();
+        assert_eq!(system.monitor.hostname(), "studio");
+        assert_eq!(system.monitor.info().hostname, "studio");
+
+        // Renaming the machine takes effect without a reboot
+        system.vfs.write_file(Path::new(HOSTNAME_FILE), b"workshop\n").unwrap();
+        assert_eq!(system.monitor.hostname(), "workshop");
+    }
+
+    #[test]
+    fn test_secure_boot() {
+        let firmware = Arc::new(Keystore::new());
+        let vendor = firmware
+            .generate_key("vendor".into(), KeyType::Ed25519, vec![KeyUsage::Sign, KeyUsage::Verify], true)
+            .unwrap();
+        let mut trust = TrustStore::new(Arc::clone(&firmware));
+        trust.trust_key(vendor.clone());
+        let secure_boot = SecureBoot::new(BootMode::Enforcing, trust);
+        let installed = || {
+            let root = Arc::new(VirtualFileSystem::new());
+            for component in std::iter::once(secure_boot::KERNEL.to_string()).chain(services::units().into_iter().map(|u| u.name)) {
+                let image = BootImage::sign(&firmware, &vendor, &component, format!("{} 1.0", component).into_bytes()).unwrap();
+                image.install(&root).unwrap();
+            }
+            root
+        };
+        let options = BootOptions::default().with_memory(16).with_simulated_time(0);
+        assert_eq!(
+            HairrSystem::boot(options.clone().with_secure_boot(secure_boot.clone())).err().unwrap(),
+            "Secure boot refused kernel: No image"
+        );
+        let options = options.with_root(installed());
+
+        let system = HairrSystem::boot(options.with_secure_boot(secure_boot.clone())).unwrap();
+        let measured: Vec<&str> = system.attestation.measurements().iter().map(|m| m.component.as_str()).collect();
+        assert_eq!(measured[0], "kernel");
+        assert_eq!(&measured[1..], system.startup_order());
+        assert!(system.attestation.failures().is_empty());
+        assert_eq!(AttestationLog::replay(system.attestation.measurements()), system.attestation.value());
+
+        // A tampered service stops the boot in enforcing mode only
+        let tamper = || {
+            let root = installed();
+            root.write_file(Path::new("/apps/users/boot.img"), b"users 1.0 with a backdoor").unwrap();
+            root
+        };
+        let options = BootOptions::default().with_memory(16).with_simulated_time(0);
+        let error = HairrSystem::boot(options.clone().with_root(tamper()).with_secure_boot(secure_boot.clone())).err().unwrap();
+        assert_eq!(error, "Secure boot refused users: Invalid image signature");
+        let mut permissive = secure_boot;
+        permissive.mode = BootMode::Permissive;
+        let system = HairrSystem::boot(options.with_root(tamper()).with_secure_boot(permissive)).unwrap();
+        assert_eq!(system.attestation.failures()[0].component, "users");
+        assert!(system.services.list().iter().all(|s| s.state == ServiceState::Running));
+    }
+
+    #[test]
+    fn test_installed_apps_are_sandboxed() {
+        let boot = |options: BootOptions| {
+            let system = HairrSystem::boot(options.with_memory(16).with_simulated_time(0)).unwrap();
+            let profiles = r#"{"profiles": {"viewer": {"root": "/apps/viewer", "services": ["telemetry"], "network": "deny"}}}"#;
+            system.vfs.create_directory(Path::new("/var/lib")).unwrap();
+            system.vfs.create_directory(Path::new("/var/lib/pkg")).unwrap();
+            let handle = system.vfs.open(ProcessId::KERNEL, Path::new(sandbox::SANDBOX_FILE), filesystem::OpenOptions::write_only()).unwrap();
+            system.vfs.write(ProcessId::KERNEL, handle, profiles.as_bytes()).unwrap();
+            system.vfs.close(ProcessId::KERNEL, handle).unwrap();
+            system.users.create_user("alice", "pw").unwrap();
+            let session = system.sessions.login("alice", "pw").unwrap();
+            let viewer = system.sessions.launch(&session.token, "viewer").unwrap();
+            (system, session.shell, viewer)
+        };
+        let list = Message::Request {
+            id: 1,
+            data: br#"{"op": "list"}"#.to_vec(),
+        };
+
+        let (system, shell, viewer) = boot(BootOptions::default());
+        assert!(system.vfs.metadata(Path::new("/apps/viewer")).unwrap().is_directory());
+        assert_eq!(system.services_for(viewer).unwrap(), vec!["telemetry"]);
+        assert_eq!(system.services_for(shell).unwrap().len(), system.handlers.len());
+        assert!(system.call_as(viewer, "telemetry", list.clone()).is_ok());
+        assert_eq!(system.call_as(viewer, "users", list.clone()).unwrap_err(), "Sandbox denied viewer: request to users");
+        assert!(system.sandbox.read_file(viewer, "/var/lib/pkg/sandbox.json").is_err());
+
+        // Connections opened for the app go through its profile
+        let (nic, _) = net_stack::VirtualNic::pair([2, 0, 0, 0, 0, 1], [2, 0, 0, 0, 0, 2]);
+        let stack = net_stack::NetStack::new(Box::new(nic));
+        stack.configure(net_stack::Ipv4Config::new(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(255, 255, 255, 0), None));
+        system.sandbox.attach_network(&stack);
+        assert!(stack.connect_as(viewer, Ipv4Addr::new(10, 0, 0, 2), 443).is_err());
+        assert!(stack.connect_as(shell, Ipv4Addr::new(10, 0, 0, 2), 443).is_ok());
+        assert_eq!(system.sandbox.violations().len(), 2);
+
+        // Files the app left open are closed when it exits
+        system.sandbox.write_file(viewer, "/notes", b"hi").unwrap();
+        system.vfs.open(viewer, Path::new("/apps/viewer/notes"), filesystem::OpenOptions::read_only()).unwrap();
+        system.kernel.terminate_process(viewer).unwrap();
+        assert!(system.vfs.open_handles(viewer).is_empty());
+        system.tick(1).unwrap();
+        assert!(system.kernel.get_process(viewer).is_none());
+
+        let (system, _, viewer) = boot(BootOptions::default().with_developer_mode());
+        assert!(system.call_as(viewer, "users", list).is_ok());
+        assert!(!system.sandbox.violations()[0].rejected);
+
+        // An installed app whose profile is lost is not launched at all
+        system.vfs.delete(Path::new(sandbox::SANDBOX_FILE)).unwrap();
+        let session = system.sessions.login("alice", "pw").unwrap();
+        assert!(system.sessions.launch(&session.token, "viewer").is_err());
+        assert!(system.sessions.launch(&session.token, "calculator").is_ok());
+    }
+
+    #[test]
+    fn test_crashed_service_is_restarted() {
+        let system = HairrSystem::boot(BootOptions::default().with_memory(16).with_simulated_time(0)).unwrap();
+        system.users.create_user("alice", "pw").unwrap();
+        system.users.set_session_lifetime(60);
+        let Ok(UsersResponse::Session { token }) = login(&system) else {
+            panic!("expected a session");
+        };
+
+        let crashed = system.services.status("users").unwrap().process.unwrap();
+        let endpoint = system.endpoint("users").unwrap();
+        system.kernel.terminate_process(crashed).unwrap();
+        assert!(system.ipc.get_channel(endpoint).is_none());
+        assert!(login(&system).is_err());
+
+        // Reaped on the first tick, restarted after the backoff on the next
+        system.tick(30_000).unwrap();
+        assert!(system.kernel.get_process(crashed).is_none());
+        system.tick(29_000).unwrap();
+        let restarted = system.services.status("users").unwrap();
+        assert_eq!((restarted.state, restarted.restarts), (ServiceState::Running, 1));
+        assert_ne!(system.endpoint("users").unwrap(), endpoint);
+        assert!(login(&system).is_ok());
+
+        // Sessions run on the same simulated time
+        assert!(system.users.session(&token).is_some());
+        system.tick(1_000).unwrap();
+        assert!(system.users.session(&token).is_none());
+    }
+}
@@ -0,0 +1,50 @@
+//! hairr OS
+//!
+//! Boots the system, reports what came up and shuts it down again. Pass
+//! `--memory <MB>` to boot with a different amount of physical memory, and
+//! `--developer-mode` to let sandboxed apps past their profiles.
+
+use std::path::Path;
+
+use system::{BootOptions, HairrSystem};
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let mut options = BootOptions::default();
+    if let Some(index) = args.iter().position(|arg| arg == "--memory") {
+        match args.get(index + 1).and_then(|mb| mb.parse().ok()) {
+            Some(memory_mb) => options = options.with_memory(memory_mb),
+            None => {
+                eprintln!("Error: --memory needs a size in megabytes");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    if args.iter().any(|arg| arg == "--developer-mode") {
+        options = options.with_developer_mode();
+    }
+
+    let system = match HairrSystem::boot(options) {
+        Ok(system) => system,
+        Err(e) => {
+            eprintln!("Error: boot failed: {}", e);
+            std::process::exit(1);
+        }
+    };
+    for status in system.services.list() {
+        let pid = status.process.map_or("-".to_string(), |pid| pid.value().to_string());
+        println!("{:<16} {:?}\tpid {}", status.name, status.state, pid);
+    }
+    for path in ["/proc/meminfo", "/ipc/channels"] {
+        match system.vfs.read_file(Path::new(path)) {
+            Ok(content) => print!("\n{}:\n{}", path, String::from_utf8_lossy(&content)),
+            Err(e) => eprintln!("Error: {}: {}", path, e),
+        }
+    }
+
+    if let Err(e) = system.shutdown() {
+        eprintln!("Error: shutdown failed: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,332 @@
+//! Sandbox enforcement for installed apps
+//!
+//! Apps installed from a package are launched in the sandbox profile the
+//! package manager wrote for them, and every request such an app makes
+//! goes through here. Paths resolve in a private namespace: the app's
+//! install root is its `/`, and only the paths its profile lists are
+//! visible at their real location, with the permission listed. It sees
+//! only the services it lists and reaches only the hosts it lists, with no
+//! network at all by default. Processes without a profile are not
+//! confined.
+//!
+//! A request outside the profile is recorded as a violation and reported
+//! to the security audit service, then rejected. In developer mode it is
+//! recorded and reported the same way but allowed, so app authors can see
+//! everything their profile is missing in one run.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use capability::Permission;
+use filesystem::{OpenOptions, VirtualFileSystem};
+use kernel::{Kernel, ProcessId, SandboxProfile};
+use net_stack::NetStack;
+use security_audit::{EventKind, SecurityAuditService, Subject};
+use serde::Deserialize;
+
+/// Sandbox profiles of installed apps, as the package manager writes them
+pub const SANDBOX_FILE: &str = "/var/lib/pkg/sandbox.json";
+
+/// Directory packages are installed under
+pub const APPS_DIR: &str = "/apps";
+
+/// Violations kept for `violations`
+const MAX_VIOLATIONS: usize = 1000;
+
+#[derive(Deserialize)]
+struct SandboxDatabase {
+    profiles: std::collections::BTreeMap<String, SandboxProfile>,
+}
+
+/// Profile `app` was installed with; `None` if it was not installed from
+/// a package. An installed app whose profile cannot be read is an error,
+/// so it is never launched unconfined.
+pub fn installed_profile(vfs: &VirtualFileSystem, app: &str) -> Result<Option<SandboxProfile>, String> {
+    let installed = vfs.exists(&Path::new(APPS_DIR).join(app));
+    let database = vfs
+        .read_file(Path::new(SANDBOX_FILE))
+        .map_err(|e| format!("Cannot read sandbox profiles: {}", e))
+        .and_then(|data| {
+            serde_json::from_slice::<SandboxDatabase>(&data).map_err(|e| format!("Invalid sandbox profiles: {}", e))
+        });
+    match database {
+        Ok(mut database) => match database.profiles.remove(app) {
+            Some(profile) => Ok(Some(profile)),
+            None if installed => Err(format!("No sandbox profile for installed app {}", app)),
+            None => Ok(None),
+        },
+        Err(e) if installed => Err(e),
+        Err(_) => Ok(None),
+    }
+}
+
+/// A request a sandboxed app was not allowed to make
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub process: ProcessId,
+    pub app: String,
+    pub request: String,
+    /// False if developer mode let it through
+    pub rejected: bool,
+}
+
+/// Checks requests from sandboxed processes against their profiles
+#[derive(Clone)]
+pub struct Sandbox {
+    kernel: Arc<Kernel>,
+    vfs: Arc<VirtualFileSystem>,
+    security: Arc<SecurityAuditService>,
+    developer_mode: Arc<Mutex<bool>>,
+    violations: Arc<Mutex<VecDeque<Violation>>>,
+}
+
+impl Sandbox {
+    pub fn new(kernel: Arc<Kernel>, vfs: Arc<VirtualFileSystem>, security: Arc<SecurityAuditService>) -> Self {
+        Sandbox {
+            kernel,
+            vfs,
+            security,
+            developer_mode: Arc::new(Mutex::new(false)),
+            violations: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    /// Allow out-of-profile requests, still recording them
+    pub fn set_developer_mode(&self, enabled: bool) {
+        *self.developer_mode.lock().unwrap() = enabled;
+    }
+
+    pub fn developer_mode(&self) -> bool {
+        *self.developer_mode.lock().unwrap()
+    }
+
+    /// Violations so far, oldest first
+    pub fn violations(&self) -> Vec<Violation> {
+        self.violations.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Create the namespace root of an app about to be launched
+    pub fn prepare(&self, profile: &SandboxProfile) -> Result<(), String> {
+        create_directories(&self.vfs, Path::new(&profile.root))
+    }
+
+    /// Name and profile of `process`; `None` if it is not sandboxed
+    fn confinement(&self, process: ProcessId) -> Result<Option<(String, SandboxProfile)>, String> {
+        let process = self.kernel.get_process(process).ok_or("Process not found")?;
+        Ok(process.sandbox.map(|profile| (process.name, profile)))
+    }
+
+    /// Pass an allowed request; record a disallowed one and reject it
+    /// unless in developer mode
+    fn enforce(&self, process: ProcessId, app: &str, allowed: bool, request: String) -> Result<(), String> {
+        if allowed {
+            return Ok(());
+        }
+        let rejected = !self.developer_mode();
+        let error = self.record(process, app, request, rejected);
+        if rejected {
+            Err(error)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Log a violation and report it; returns the error rejecting it
+    fn record(&self, process: ProcessId, app: &str, request: String, rejected: bool) -> String {
+        self.security.record(EventKind::SandboxViolation, Subject::Process(process.value()), format!("{}: {}", app, request));
+        let error = format!("Sandbox denied {}: {}", app, request);
+        {
+            let mut violations = self.violations.lock().unwrap();
+            if violations.len() >= MAX_VIOLATIONS {
+                violations.pop_front();
+            }
+            violations.push_back(Violation {
+                process,
+                app: app.to_string(),
+                request,
+                rejected,
+            });
+        }
+        error
+    }
+
+    /// Where `path` in the process's namespace really is, once `required`
+    /// access to it has been allowed
+    pub fn resolve(&self, process: ProcessId, path: &str, required: Permission) -> Result<PathBuf, String> {
+        let Some((app, profile)) = self.confinement(process)? else {
+            return Ok(PathBuf::from(path));
+        };
+        let resolved = match profile.resolve(path) {
+            Ok(resolved) => resolved,
+            // Developer mode cannot let a path out of the namespace
+            Err(_) => return Err(self.record(process, &app, format!("{:?} access to {}", required, path), true)),
+        };
+        let granted = profile
+            .files
+            .iter()
+            .filter(|(listed, _)| resolved.starts_with(listed))
+            .max_by_key(|(listed, _)| listed.len())
+            .map_or(Permission::ReadWrite, |(_, permission)| *permission);
+        self.enforce(process, &app, granted.allows(required), format!("{:?} access to {}", required, path))?;
+        Ok(resolved)
+    }
+
+    pub fn read_file(&self, process: ProcessId, path: &str) -> Result<Vec<u8>, String> {
+        let resolved = self.resolve(process, path, Permission::Read)?;
+        self.vfs.read_file(&resolved).map_err(String::from)
+    }
+
+    /// Replace a file's contents, creating it if missing
+    pub fn write_file(&self, process: ProcessId, path: &str, data: &[u8]) -> Result<(), String> {
+        let resolved = self.resolve(process, path, Permission::Write)?;
+        if let Some(parent) = resolved.parent() {
+            create_directories(&self.vfs, parent)?;
+        }
+        let options = OpenOptions {
+            truncate: true,
+            ..OpenOptions::write_only()
+        };
+        let handle = self.vfs.open(process, &resolved, options)?;
+        let result = self.vfs.write(process, handle, data);
+        self.vfs.close(process, handle)?;
+        result.map(|_| ()).map_err(String::from)
+    }
+
+    /// Entries of a directory, named as the process sees them
+    pub fn list_directory(&self, process: ProcessId, path: &str) -> Result<Vec<PathBuf>, String> {
+        let resolved = self.resolve(process, path, Permission::Read)?;
+        let entries = self.vfs.list_directory(&resolved)?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| Path::new(path).join(entry.file_name().unwrap_or_default()))
+            .collect())
+    }
+
+    /// Services out of `registry` the process can see
+    pub fn visible_services(&self, process: ProcessId, registry: &[String]) -> Result<Vec<String>, String> {
+        Ok(match self.confinement(process)? {
+            Some((_, profile)) => registry.iter().filter(|s| profile.allows_service(s)).cloned().collect(),
+            None => registry.to_vec(),
+        })
+    }
+
+    pub fn check_service(&self, process: ProcessId, service: &str) -> Result<(), String> {
+        match self.confinement(process)? {
+            Some((app, profile)) => self.enforce(process, &app, profile.allows_service(service), format!("request to {}", service)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn check_device(&self, process: ProcessId, device: &str, required: Permission) -> Result<(), String> {
+        match self.confinement(process)? {
+            Some((app, profile)) => {
+                let allowed = profile.devices.iter().any(|(d, granted)| d == device && granted.allows(required));
+                self.enforce(process, &app, allowed, format!("{:?} access to device {}", required, device))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Check a connection to `host` before it is opened
+    pub fn check_connect(&self, process: ProcessId, host: &str) -> Result<(), String> {
+        match self.confinement(process)? {
+            Some((app, profile)) => self.enforce(process, &app, profile.allows_host(host), format!("connection to {}", host)),
+            None => Ok(()),
+        }
+    }
+
+    /// Check every connection `stack` opens for a process. The stack has
+    /// no resolver, so profiles reach hosts by address.
+    pub fn attach_network(&self, stack: &NetStack) {
+        let sandbox = self.clone();
+        stack.attach_connect_check(Arc::new(move |process, addr| sandbox.check_connect(process, &addr.to_string())));
+    }
+}
+
+fn create_directories(vfs: &VirtualFileSystem, path: &Path) -> Result<(), String> {
+    for ancestor in path.ancestors().collect::<Vec<_>>().into_iter().rev() {
+        if !vfs.exists(ancestor) {
+            vfs.create_directory(ancestor)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kernel::{NetworkPolicy, Priority};
+    use notifications::NotificationService;
+
+    fn setup() -> (Sandbox, Arc<Kernel>, Arc<SecurityAuditService>, ProcessId) {
+        let kernel = Arc::new(Kernel::new());
+        kernel.attach_capabilities(Arc::new(capability::CapabilityManager::new()));
+        let vfs = Arc::new(VirtualFileSystem::new());
+        create_directories(&vfs, Path::new("/home/media")).unwrap();
+        vfs.create_directory(Path::new("/etc")).unwrap();
+        let security = Arc::new(SecurityAuditService::new(Arc::new(NotificationService::new())));
+        let sandbox = Sandbox::new(Arc::clone(&kernel), vfs, Arc::clone(&security));
+
+        let mut profile = SandboxProfile::new("/apps/viewer".to_string());
+        profile.files.push(("/home/media".to_string(), Permission::Read));
+        profile.devices.push(("gpu".to_string(), Permission::Read));
+        profile.services.push("notifications".to_string());
+        profile.network = NetworkPolicy::Hosts(vec!["api.example.org".to_string()]);
+        sandbox.prepare(&profile).unwrap();
+        let viewer = kernel.spawn_sandboxed("viewer".to_string(), Priority::Normal, profile).unwrap();
+        (sandbox, kernel, security, viewer)
+    }
+
+    #[test]
+    fn test_private_namespace() {
+        let (sandbox, kernel, _, viewer) = setup();
+        sandbox.write_file(viewer, "/data/state.json", b"{}").unwrap();
+        assert_eq!(sandbox.read_file(viewer, "/data/state.json").unwrap(), b"{}");
+        assert_eq!(sandbox.list_directory(viewer, "/").unwrap(), vec![PathBuf::from("/data")]);
+        assert!(sandbox.vfs.exists(Path::new("/apps/viewer/data/state.json")));
+
+        // Listed paths keep their location and permission; nothing else is visible
+        assert!(sandbox.list_directory(viewer, "/home/media").is_ok());
+        assert_eq!(
+            sandbox.write_file(viewer, "/home/media/cover.png", b"x").unwrap_err(),
+            "Sandbox denied viewer: Write access to /home/media/cover.png"
+        );
+        assert!(sandbox.list_directory(viewer, "/etc").is_err());
+        assert!(sandbox.read_file(viewer, "/data/../../../etc/passwd").is_err());
+
+        // Unconfined processes see the real tree
+        let shell = kernel.create_process("shell".to_string(), Priority::Normal);
+        assert_eq!(sandbox.list_directory(shell, "/").unwrap().len(), 3);
+        assert!(sandbox.check_connect(shell, "tracker.example.com").is_ok());
+    }
+
+    #[test]
+    fn test_violations_are_logged_and_rejected() {
+        let (sandbox, _, security, viewer) = setup();
+        let registry: Vec<String> = ["notifications", "telemetry", "users"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(sandbox.visible_services(viewer, &registry).unwrap(), vec!["notifications"]);
+        assert!(sandbox.check_service(viewer, "notifications").is_ok());
+        assert!(sandbox.check_service(viewer, "users").is_err());
+        assert!(sandbox.check_connect(viewer, "api.example.org").is_ok());
+        assert!(sandbox.check_connect(viewer, "tracker.example.com").is_err());
+        assert!(sandbox.check_device(viewer, "gpu", Permission::Read).is_ok());
+        assert!(sandbox.check_device(viewer, "gpu", Permission::Write).is_err());
+        assert!(sandbox.check_device(viewer, "camera", Permission::Read).is_err());
+
+        let requests: Vec<String> = sandbox.violations().into_iter().map(|v| v.request).collect();
+        assert_eq!(requests, vec!["request to users", "connection to tracker.example.com", "Write access to device gpu", "Read access to device camera"]);
+        let subject = Subject::Process(viewer.value());
+        assert_eq!(security.events_for(&subject).len(), 4);
+
+        // Developer mode lets the same requests through but still logs them
+        sandbox.set_developer_mode(true);
+        assert!(sandbox.check_service(viewer, "users").is_ok());
+        assert!(sandbox.write_file(viewer, "/home/media/cover.png", b"x").is_ok());
+        assert!(sandbox.read_file(viewer, "/../etc/passwd").is_err());
+        let violations = sandbox.violations();
+        assert_eq!(violations.len(), 7);
+        assert!(!violations[4].rejected && !violations[5].rejected);
+        assert_eq!(security.alerts()[0].subject, subject);
+    }
+}
@@ -0,0 +1,422 @@
+//! Secure and measured boot
+//!
+//! Before anything starts, the kernel and every core service are checked
+//! against the trust store. Each component boots from the image its package
+//! installed under `/apps/<component>`: the payload in `IMAGE_FILE` and, if
+//! the publisher signed it, signer and signature in `SIGNATURE_FILE`. An
+//! image passes if a trusted key signed its name and digest, or if its
+//! digest is on the allowlist for that component. Every image is measured into the attestation log whether it
+//! passes or not, each measurement extending a running digest the way a
+//! TPM extends a PCR, so a verifier that replays the log and gets the same
+//! value knows nothing was left out or reordered. In enforcing mode the
+//! system refuses to boot past a component that fails; in permissive mode
+//! the failure is only recorded.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use filesystem::VirtualFileSystem;
+use keystore::{KeyId, Keystore};
+use serde::{Deserialize, Serialize};
+use system_utils::{encoding, hash};
+
+use crate::sandbox::APPS_DIR;
+
+/// Component name of the kernel image
+pub const KERNEL: &str = "kernel";
+
+/// Payload a component boots from, in its install root
+pub const IMAGE_FILE: &str = "boot.img";
+
+/// Publisher's signature over `IMAGE_FILE`, in its install root
+pub const SIGNATURE_FILE: &str = "boot.sig";
+
+/// What happens to a component that fails verification
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootMode {
+    /// Refuse to boot
+    Enforcing,
+    /// Boot anyway, with the failure in the attestation log
+    Permissive,
+}
+
+/// Hex-encoded SHA-256 of an image's payload
+pub fn image_digest(payload: &[u8]) -> String {
+    encoding::hex_encode(&hash::sha256(payload))
+}
+
+fn signed_bytes(component: &str, digest: &str) -> Vec<u8> {
+    format!("{}\n{}", component, digest).into_bytes()
+}
+
+fn install_root(component: &str) -> PathBuf {
+    Path::new(APPS_DIR).join(component)
+}
+
+/// Contents of `SIGNATURE_FILE`
+#[derive(Serialize, Deserialize)]
+struct SignatureFile {
+    signer: String,
+    /// Hex-encoded
+    signature: String,
+}
+
+/// The payload a component boots from, as its package ships it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootImage {
+    pub component: String,
+    pub payload: Vec<u8>,
+    /// Key the publisher signed with, if signed
+    pub signer: Option<KeyId>,
+    pub signature: Vec<u8>,
+}
+
+impl BootImage {
+    pub fn unsigned(component: &str, payload: Vec<u8>) -> Self {
+        BootImage {
+            component: component.to_string(),
+            payload,
+            signer: None,
+            signature: Vec::new(),
+        }
+    }
+
+    /// Sign the component name and payload digest with a keystore key
+    pub fn sign(keystore: &Keystore, key_id: &KeyId, component: &str, payload: Vec<u8>) -> Result<Self, String> {
+        let signature = keystore.sign(key_id, &signed_bytes(component, &image_digest(&payload)))?;
+        Ok(BootImage {
+            component: component.to_string(),
+            payload,
+            signer: Some(key_id.clone()),
+            signature,
+        })
+    }
+
+    /// The image installed for `component`
+    pub fn load(vfs: &VirtualFileSystem, component: &str) -> Result<Self, String> {
+        let root = install_root(component);
+        let payload = vfs.read_file(&root.join(IMAGE_FILE)).map_err(|_| "No image".to_string())?;
+        let signature_path = root.join(SIGNATURE_FILE);
+        if !vfs.exists(&signature_path) {
+            return Ok(BootImage::unsigned(component, payload));
+        }
+        let file: SignatureFile = serde_json::from_slice(&vfs.read_file(&signature_path)?)
+            .map_err(|e| format!("Invalid signature file: {}", e))?;
+        Ok(BootImage {
+            component: component.to_string(),
+            payload,
+            signer: Some(KeyId::new(file.signer)),
+            signature: encoding::hex_decode(&file.signature)?,
+        })
+    }
+
+    /// Write this image into its component's install root, as its package
+    /// installs it
+    pub fn install(&self, vfs: &VirtualFileSystem) -> Result<(), String> {
+        let root = install_root(&self.component);
+        vfs.write_file(&root.join(IMAGE_FILE), &self.payload)?;
+        let signature_path = root.join(SIGNATURE_FILE);
+        match &self.signer {
+            Some(signer) => {
+                let file = SignatureFile {
+                    signer: signer.as_str().to_string(),
+                    signature: encoding::hex_encode(&self.signature),
+                };
+                let json = serde_json::to_vec(&file).map_err(|e| e.to_string())?;
+                vfs.write_file(&signature_path, &json).map_err(String::from)
+            }
+            None if vfs.exists(&signature_path) => vfs.delete(&signature_path).map_err(String::from),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Keys and digests boot images are checked against
+#[derive(Clone)]
+pub struct TrustStore {
+    keystore: Arc<Keystore>,
+    keys: HashSet<KeyId>,
+    /// Digests allowed without a signature, by component
+    digests: HashMap<String, HashSet<String>>,
+}
+
+impl TrustStore {
+    pub fn new(keystore: Arc<Keystore>) -> Self {
+        TrustStore {
+            keystore,
+            keys: HashSet::new(),
+            digests: HashMap::new(),
+        }
+    }
+
+    /// Trust images signed with keystore key `key_id`
+    pub fn trust_key(&mut self, key_id: KeyId) {
+        self.keys.insert(key_id);
+    }
+
+    pub fn revoke_key(&mut self, key_id: &KeyId) {
+        self.keys.remove(key_id);
+    }
+
+    /// Trust this exact payload digest for `component`, signed or not
+    pub fn allow_digest(&mut self, component: &str, digest: &str) {
+        self.digests.entry(component.to_string()).or_default().insert(digest.to_string());
+    }
+
+    /// Check `image`, returning how it was trusted
+    pub fn verify(&self, image: &BootImage) -> Result<Trust, String> {
+        let digest = image_digest(&image.payload);
+        if self.digests.get(&image.component).is_some_and(|allowed| allowed.contains(&digest)) {
+            return Ok(Trust::Allowlisted);
+        }
+        let signer = image.signer.as_ref().ok_or("Image is unsigned and its digest is not allowed")?;
+        if !self.keys.contains(signer) {
+            return Err(format!("Untrusted signer: {}", signer.as_str()));
+        }
+        if !self.keystore.verify(signer, &signed_bytes(&image.component, &digest), &image.signature)? {
+            return Err("Invalid image signature".to_string());
+        }
+        Ok(Trust::Signed(signer.clone()))
+    }
+}
+
+impl fmt::Debug for TrustStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TrustStore")
+            .field("keys", &self.keys)
+            .field("digests", &self.digests)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Why an image was trusted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trust {
+    Signed(KeyId),
+    Allowlisted,
+}
+
+/// One image measured at boot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Measurement {
+    pub component: String,
+    /// `image_digest` of the payload; empty if there was no image
+    pub digest: String,
+    pub verdict: Result<Trust, String>,
+}
+
+/// Everything measured during boot, in order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttestationLog {
+    measurements: Vec<Measurement>,
+    value: [u8; 32],
+}
+
+impl AttestationLog {
+    pub fn new() -> Self {
+        AttestationLog {
+            measurements: Vec::new(),
+            value: [0; 32],
+        }
+    }
+
+    fn extend(value: &[u8; 32], measurement: &Measurement) -> [u8; 32] {
+        let mut data = value.to_vec();
+        data.extend_from_slice(&signed_bytes(&measurement.component, &measurement.digest));
+        hash::sha256(&data)
+    }
+
+    pub fn record(&mut self, measurement: Measurement) {
+        self.value = Self::extend(&self.value, &measurement);
+        self.measurements.push(measurement);
+    }
+
+    pub fn measurements(&self) -> &[Measurement] {
+        &self.measurements
+    }
+
+    /// Running digest over every measurement, hex-encoded
+    pub fn value(&self) -> String {
+        encoding::hex_encode(&self.value)
+    }
+
+    /// The value `measurements` should produce, for checking a log
+    /// received from elsewhere against its reported value
+    pub fn replay(measurements: &[Measurement]) -> String {
+        encoding::hex_encode(&measurements.iter().fold([0; 32], |value, m| Self::extend(&value, m)))
+    }
+
+    /// Components that failed verification
+    pub fn failures(&self) -> Vec<&Measurement> {
+        self.measurements.iter().filter(|m| m.verdict.is_err()).collect()
+    }
+}
+
+impl Default for AttestationLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Trust store and mode for a secure boot
+#[derive(Debug, Clone)]
+pub struct SecureBoot {
+    pub mode: BootMode,
+    pub trust: TrustStore,
+}
+
+impl SecureBoot {
+    pub fn new(mode: BootMode, trust: TrustStore) -> Self {
+        SecureBoot { mode, trust }
+    }
+
+    /// Measure the image installed in `vfs` for `component` into `log`
+    /// and verify it; an error means the boot must stop there
+    pub fn check(&self, vfs: &VirtualFileSystem, component: &str, log: &mut AttestationLog) -> Result<(), String> {
+        let (digest, verdict) = match BootImage::load(vfs, component) {
+            Ok(image) => (image_digest(&image.payload), self.trust.verify(&image)),
+            Err(reason) => (String::new(), Err(reason)),
+        };
+        let failure = verdict.as_ref().err().cloned();
+        log.record(Measurement {
+            component: component.to_string(),
+            digest,
+            verdict,
+        });
+        match failure {
+            Some(reason) if self.mode == BootMode::Enforcing => {
+                Err(format!("Secure boot refused {}: {}", component, reason))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keystore::{KeyType, KeyUsage};
+
+    fn vendor() -> (Arc<Keystore>, KeyId) {
+        let keystore = Arc::new(Keystore::new());
+        let key = keystore
+            .generate_key("vendor".into(), KeyType::Ed25519, vec![KeyUsage::Sign, KeyUsage::Verify], true)
+            .unwrap();
+        (keystore, key)
+    }
+
+    #[test]
+    fn test_trust_store() {
+        let (keystore, key) = vendor();
+        let mut trust = TrustStore::new(Arc::clone(&keystore));
+        let signed = BootImage::sign(&keystore, &key, KERNEL, b"kernel v1".to_vec()).unwrap();
+        assert_eq!(trust.verify(&signed).unwrap_err(), "Untrusted signer: vendor");
+        trust.trust_key(key.clone());
+        assert_eq!(trust.verify(&signed), Ok(Trust::Signed(key.clone())));
+
+        // The signature covers the payload and the component it is for
+        let mut patched = signed.clone();
+        patched.payload.push(0x90);
+        assert!(trust.verify(&patched).is_err());
+        let mut renamed = signed.clone();
+        renamed.component = "keystore".to_string();
+        assert!(trust.verify(&renamed).is_err());
+
+        let unsigned = BootImage::unsigned("time", b"time v1".to_vec());
+        assert!(trust.verify(&unsigned).is_err());
+        trust.allow_digest("time", &image_digest(b"time v1"));
+        assert_eq!(trust.verify(&unsigned), Ok(Trust::Allowlisted));
+        assert!(trust.verify(&BootImage::unsigned("users", b"time v1".to_vec())).is_err());
+
+        trust.revoke_key(&key);
+        assert!(trust.verify(&signed).is_err());
+    }
+
+    #[test]
+    fn test_attestation_log() {
+        let (keystore, key) = vendor();
+        let mut trust = TrustStore::new(Arc::clone(&keystore));
+        trust.trust_key(key.clone());
+        let secure_boot = SecureBoot::new(BootMode::Permissive, trust);
+        let vfs = VirtualFileSystem::new();
+        BootImage::sign(&keystore, &key, KERNEL, b"kernel".to_vec()).unwrap().install(&vfs).unwrap();
+        BootImage::unsigned("users", b"users".to_vec()).install(&vfs).unwrap();
+
+        let mut log = AttestationLog::new();
+        let empty = log.value();
+        for component in [KERNEL, "users", "time"] {
+            secure_boot.check(&vfs, component, &mut log).unwrap();
+        }
+        assert_ne!(log.value(), empty);
+        assert_eq!(AttestationLog::replay(log.measurements()), log.value());
+        let failed: Vec<&str> = log.failures().iter().map(|m| m.component.as_str()).collect();
+        assert_eq!(failed, vec!["users", "time"]);
+
+        // Dropping or reordering measurements changes the value
+        let mut reordered = log.measurements().to_vec();
+        reordered.swap(0, 1);
+        assert_ne!(AttestationLog::replay(&reordered), log.value());
+        assert_ne!(AttestationLog::replay(&log.measurements()[1..]), log.value());
+
+        let enforcing = SecureBoot { mode: BootMode::Enforcing, ..secure_boot };
+        assert!(enforcing.check(&vfs, KERNEL, &mut log).is_ok());
+        assert_eq!(enforcing.check(&vfs, "time", &mut log).unwrap_err(), "Secure boot refused time: No image");
+        assert_eq!(log.measurements().len(), 5);
+    }
+
+    #[test]
+    fn test_images_load_from_install_root() {
+        let (keystore, key) = vendor();
+        let mut trust = TrustStore::new(Arc::clone(&keystore));
+        trust.trust_key(key.clone());
+        let secure_boot = SecureBoot::new(BootMode::Enforcing, trust);
+        let vfs = VirtualFileSystem::new();
+        let image = BootImage::sign(&keystore, &key, KERNEL, b"kernel v1".to_vec()).unwrap();
+        image.install(&vfs).unwrap();
+        assert_eq!(BootImage::load(&vfs, KERNEL), Ok(image));
+        assert!(vfs.exists(Path::new("/apps/kernel/boot.sig")));
+
+        // What is verified is what is installed, not what was signed
+        let mut log = AttestationLog::new();
+        secure_boot.check(&vfs, KERNEL, &mut log).unwrap();
+        vfs.write_file(Path::new("/apps/kernel/boot.img"), b"kernel v1 patched").unwrap();
+        let error = secure_boot.check(&vfs, KERNEL, &mut log).unwrap_err();
+        assert_eq!(error, "Secure boot refused kernel: Invalid image signature");
+        assert_eq!(log.measurements()[1].digest, image_digest(b"kernel v1 patched"));
+    }
+
+    #[test]
+    fn test_image_signed_without_trusted_key() {
+        let (keystore, key) = vendor();
+        let mut trust = TrustStore::new(Arc::clone(&keystore));
+        trust.trust_key(key.clone());
+        let payload = b"kernel v2".to_vec();
+
+        // Neither the bare signed bytes nor another key's signature stand in for the vendor's
+        let (forger, forged_key) = vendor();
+        let forgeries = vec![
+            signed_bytes(KERNEL, &image_digest(&payload)),
+            BootImage::sign(&forger, &forged_key, KERNEL, payload.clone()).unwrap().signature,
+        ];
+        let secure_boot = SecureBoot::new(BootMode::Enforcing, trust);
+        for signature in forgeries {
+            let image = BootImage {
+                component: KERNEL.to_string(),
+                payload: payload.clone(),
+                signer: Some(key.clone()),
+                signature,
+            };
+            assert_eq!(secure_boot.trust.verify(&image), Err("Invalid image signature".to_string()));
+
+            let vfs = VirtualFileSystem::new();
+            image.install(&vfs).unwrap();
+            let error = secure_boot.check(&vfs, KERNEL, &mut AttestationLog::new()).unwrap_err();
+            assert_eq!(error, "Secure boot refused kernel: Invalid image signature");
+        }
+
+        let image = BootImage::sign(&keystore, &key, KERNEL, payload).unwrap();
+        assert_eq!(secure_boot.trust.verify(&image), Ok(Trust::Signed(key)));
+    }
+}
@@ -0,0 +1,55 @@
+//! The services a system runs
+//!
+//! Each service has a unit telling init what it depends on, and services
+//! that answer requests over IPC have a handler for their endpoint.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use init::ServiceUnit;
+use ipc::{JsonService, Message};
+use kernel::Priority;
+use security_audit::SecurityAuditService;
+use session::SessionManager;
+use telemetry::TelemetryService;
+use users::UserService;
+
+/// Handles a request that arrived on a service's endpoint
+pub type Handler = Arc<dyn Fn(&Message) -> Option<Message> + Send + Sync>;
+
+/// Directories every system starts with
+pub const BASE_DIRECTORIES: &[&str] = &["/etc", "/home", "/root", "/tmp", "/var"];
+
+/// Units for the services `HairrSystem` starts
+pub fn units() -> Vec<ServiceUnit> {
+    let mut keystore = ServiceUnit::new("keystore");
+    keystore.priority = Priority::High;
+    vec![
+        keystore,
+        ServiceUnit::new("users").with_dependency("keystore"),
+        ServiceUnit::new("session").with_dependency("users"),
+        ServiceUnit::new("time"),
+        ServiceUnit::new("notifications"),
+        ServiceUnit::new("security").with_dependency("notifications"),
+        ServiceUnit::new("telemetry"),
+    ]
+}
+
+/// Request handlers, by service name
+pub(crate) fn handlers(
+    users: &Arc<UserService>,
+    sessions: &Arc<SessionManager>,
+    security: &Arc<SecurityAuditService>,
+    telemetry: &Arc<TelemetryService>,
+) -> HashMap<String, Handler> {
+    let mut handlers: HashMap<String, Handler> = HashMap::new();
+    let service = Arc::clone(users);
+    handlers.insert("users".to_string(), Arc::new(move |message| service.handle_message(message)));
+    let service = Arc::clone(sessions);
+    handlers.insert("session".to_string(), Arc::new(move |message| service.handle_message(message)));
+    let service = Arc::clone(security);
+    handlers.insert("security".to_string(), Arc::new(move |message| service.handle_message(message)));
+    let service = Arc::clone(telemetry);
+    handlers.insert("telemetry".to_string(), Arc::new(move |message| service.handle_message(message)));
+    handlers
+}